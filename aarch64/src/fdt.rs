@@ -14,17 +14,21 @@ use arch::fdt::create_memory_node;
 use arch::fdt::create_reserved_memory_node;
 use arch::fdt::ReservedMemoryRegion;
 use arch::serial::SerialDeviceInfo;
+use arch::CpuCacheType;
 use arch::CpuSet;
 use arch::DtbOverlay;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 use arch::PlatformBusResources;
+use arch::VcpuCache;
 use base::open_file_or_duplicate;
 use cros_fdt::Error;
 use cros_fdt::Fdt;
+use cros_fdt::FdtNode;
 use cros_fdt::Result;
 // This is a Battery related constant
 use devices::bat::GOLDFISHBAT_MMIO_LEN;
 use devices::pl030::PL030_AMBA_ID;
+use devices::smmuv3::SMMUV3_MMIO_LEN;
 use devices::IommuDevType;
 use devices::PciAddress;
 use devices::PciInterruptPin;
@@ -69,6 +73,11 @@ const PHANDLE_OPP_DOMAIN_BASE: u32 = 0x1000;
 // pKVM pvIOMMUs are assigned phandles starting with this number.
 const PHANDLE_PKVM_PVIOMMU: u32 = 0x2000;
 
+const PHANDLE_SMMUV3: u32 = 0x3000;
+
+// Caches mirrored from the host are assigned phandles starting with this number.
+const PHANDLE_CACHE_BASE: u32 = 0x4000;
+
 // These are specified by the Linux GIC bindings
 const GIC_FDT_IRQ_NUM_CELLS: u32 = 3;
 const GIC_FDT_IRQ_TYPE_SPI: u32 = 0;
@@ -79,6 +88,56 @@ const IRQ_TYPE_EDGE_RISING: u32 = 0x00000001;
 const IRQ_TYPE_LEVEL_HIGH: u32 = 0x00000004;
 const IRQ_TYPE_LEVEL_LOW: u32 = 0x00000008;
 
+// Sets the properties describing `cache`, which are prefixed by the kind of data it holds unless
+// it is unified.
+fn set_cache_props(node: &mut FdtNode, cache: &VcpuCache) -> Result<()> {
+    let prefix = match cache.cache_type {
+        CpuCacheType::Data => "d-",
+        CpuCacheType::Instruction => "i-",
+        CpuCacheType::Unified => {
+            node.set_prop("cache-unified", ())?;
+            ""
+        }
+    };
+    if let Some(size) = cache.size {
+        node.set_prop(&format!("{}cache-size", prefix), size)?;
+    }
+    if let Some(line_size) = cache.line_size {
+        node.set_prop(&format!("{}cache-line-size", prefix), line_size)?;
+    }
+    if let Some(sets) = cache.sets {
+        node.set_prop(&format!("{}cache-sets", prefix), sets)?;
+    }
+    Ok(())
+}
+
+// Returns the caches beyond L1, which get nodes shared by the vCPUs running on the host CPUs that
+// share them.
+fn shared_caches(vcpu_caches: &[Vec<VcpuCache>]) -> Vec<&VcpuCache> {
+    let mut shared_caches: Vec<&VcpuCache> = Vec::new();
+    for cache in vcpu_caches.iter().flatten().filter(|cache| cache.level > 1) {
+        if !shared_caches.contains(&cache) {
+            shared_caches.push(cache);
+        }
+    }
+    shared_caches
+}
+
+// Returns the phandle of the unified cache of `level` of `vcpu`, if it has one.
+fn cache_phandle(
+    shared_caches: &[&VcpuCache],
+    vcpu_caches: &[Vec<VcpuCache>],
+    vcpu: usize,
+    level: u32,
+) -> Option<u32> {
+    let cache = vcpu_caches
+        .get(vcpu)?
+        .iter()
+        .find(|cache| cache.level == level && cache.cache_type == CpuCacheType::Unified)?;
+    let index = shared_caches.iter().position(|c| *c == cache)?;
+    Some(PHANDLE_CACHE_BASE + index as u32)
+}
+
 fn create_cpu_nodes(
     fdt: &mut Fdt,
     num_cpus: u32,
@@ -87,12 +146,14 @@ fn create_cpu_nodes(
     cpu_capacity: BTreeMap<usize, u32>,
     dynamic_power_coefficient: BTreeMap<usize, u32>,
     cpu_frequencies: BTreeMap<usize, Vec<u32>>,
+    vcpu_caches: &[Vec<VcpuCache>],
 ) -> Result<()> {
     let root_node = fdt.root_mut();
     let cpus_node = root_node.subnode_mut("cpus")?;
     cpus_node.set_prop("#address-cells", 0x1u32)?;
     cpus_node.set_prop("#size-cells", 0x0u32)?;
 
+    let shared_caches = shared_caches(vcpu_caches);
     for cpu_id in 0..num_cpus {
         let reg = u32::try_from(
             cpu_mpidr_generator(cpu_id.try_into().unwrap()).ok_or(Error::PropertyValueInvalid)?,
@@ -114,6 +175,14 @@ fn create_cpu_nodes(
         if let Some(capacity) = cpu_capacity.get(&(cpu_id as usize)) {
             cpu_node.set_prop("capacity-dmips-mhz", *capacity)?;
         }
+        if let Some(caches) = vcpu_caches.get(cpu_id as usize) {
+            for cache in caches.iter().filter(|cache| cache.level == 1) {
+                set_cache_props(cpu_node, cache)?;
+            }
+            if let Some(next) = cache_phandle(&shared_caches, vcpu_caches, cpu_id as usize, 2) {
+                cpu_node.set_prop("next-level-cache", next)?;
+            }
+        }
         // Placed inside cpu nodes for ease of parsing for some secure firmwares(PvmFw).
         if let Some(frequencies) = cpu_frequencies.get(&(cpu_id as usize)) {
             cpu_node.set_prop("operating-points-v2", PHANDLE_OPP_DOMAIN_BASE + cpu_id)?;
@@ -128,6 +197,24 @@ fn create_cpu_nodes(
         }
     }
 
+    // The caches beyond L1, which the cpu nodes and the caches of the previous level point to.
+    for (index, cache) in shared_caches.iter().enumerate() {
+        let cache_node = cpus_node.subnode_mut(&format!("l{}-cache{}", cache.level, index))?;
+        cache_node.set_prop("compatible", "cache")?;
+        cache_node.set_prop("cache-level", cache.level)?;
+        set_cache_props(cache_node, cache)?;
+        // The next level is the same for all the vCPUs sharing the cache.
+        if let Some(next) = cache
+            .shared_vcpus
+            .iter()
+            .next()
+            .and_then(|vcpu| cache_phandle(&shared_caches, vcpu_caches, *vcpu, cache.level + 1))
+        {
+            cache_node.set_prop("next-level-cache", next)?;
+        }
+        cache_node.set_prop("phandle", PHANDLE_CACHE_BASE + index as u32)?;
+    }
+
     if !cpu_clusters.is_empty() {
         let cpu_map_node = cpus_node.subnode_mut("cpu-map")?;
         for (cluster_idx, cpus) in cpu_clusters.iter().enumerate() {
@@ -410,6 +497,19 @@ pub struct VmWdtConfig {
     pub timeout_sec: u32,
 }
 
+/// Location and endpoints of the emulated SMMUv3
+#[derive(Clone)]
+pub struct SmmuV3Config {
+    /// Physical address of the base of the SMMU registers.
+    pub base: u64,
+    /// Interrupt signaling new entries in the event queue.
+    pub eventq_irq: u32,
+    /// Interrupt signaling global errors.
+    pub gerror_irq: u32,
+    /// Stream IDs of the PCI endpoints translated by the SMMU, matching their requester IDs.
+    pub stream_ids: Vec<u32>,
+}
+
 fn create_pci_nodes(
     fdt: &mut Fdt,
     pci_irqs: Vec<(PciAddress, u32, PciInterruptPin)>,
@@ -517,6 +617,35 @@ fn create_rtc_node(fdt: &mut Fdt) -> Result<()> {
     Ok(())
 }
 
+/// Create the flattened device tree nodes of virtio-mmio devices.
+///
+/// # Arguments
+///
+/// * `fdt` - An Fdt in which the nodes are created
+/// * `resources` - The (start address, size, IRQ number) of the devices
+/// * `dma_pool` - The phandle of the restricted DMA pool of the devices, if any
+fn create_virtio_mmio_nodes(
+    fdt: &mut Fdt,
+    resources: &[(u64, u64, u32)],
+    dma_pool: Option<u32>,
+) -> Result<()> {
+    for &(mmio_base, mmio_size, irq) in resources {
+        let reg = [mmio_base, mmio_size];
+        let irqs = [GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_EDGE_RISING];
+        let virtio_mmio_node = fdt
+            .root_mut()
+            .subnode_mut(&format!("virtio_mmio@{:x}", mmio_base))?;
+        virtio_mmio_node.set_prop("compatible", "virtio,mmio")?;
+        virtio_mmio_node.set_prop("reg", &reg)?;
+        virtio_mmio_node.set_prop("interrupts", &irqs)?;
+        virtio_mmio_node.set_prop("dma-coherent", ())?;
+        if let Some(dma_pool) = dma_pool {
+            virtio_mmio_node.set_prop("memory-region", dma_pool)?;
+        }
+    }
+    Ok(())
+}
+
 /// Create a flattened device tree node for Goldfish Battery device.
 ///
 /// # Arguments
@@ -534,6 +663,40 @@ fn create_battery_node(fdt: &mut Fdt, mmio_base: u64, irq: u32) -> Result<()> {
     Ok(())
 }
 
+/// Create a flattened device tree node for the emulated SMMUv3, and map the requester IDs of its
+/// endpoints to it in the PCI host controller node.
+fn create_smmuv3_node(fdt: &mut Fdt, cfg: &SmmuV3Config) -> Result<()> {
+    let reg = [cfg.base, SMMUV3_MMIO_LEN];
+    let irqs = [
+        GIC_FDT_IRQ_TYPE_SPI,
+        cfg.eventq_irq,
+        IRQ_TYPE_EDGE_RISING,
+        GIC_FDT_IRQ_TYPE_SPI,
+        cfg.gerror_irq,
+        IRQ_TYPE_EDGE_RISING,
+    ];
+    let smmu_node = fdt
+        .root_mut()
+        .subnode_mut(&format!("iommu@{:x}", cfg.base))?;
+    smmu_node.set_prop("compatible", "arm,smmu-v3")?;
+    smmu_node.set_prop("reg", &reg)?;
+    smmu_node.set_prop("interrupts", &irqs)?;
+    smmu_node.set_prop("interrupt-names", &["eventq", "gerror"])?;
+    smmu_node.set_prop("#iommu-cells", 1u32)?;
+    smmu_node.set_prop("dma-coherent", ())?;
+    smmu_node.set_prop("phandle", PHANDLE_SMMUV3)?;
+
+    // Only the endpoints are behind the SMMU, the DMA of other devices isn't translated.
+    let iommu_map: Vec<u32> = cfg
+        .stream_ids
+        .iter()
+        .flat_map(|&sid| [sid, PHANDLE_SMMUV3, sid, 1])
+        .collect();
+    let pci_node = fdt.root_mut().subnode_mut("pci")?;
+    pci_node.set_prop("iommu-map", iommu_map)?;
+    Ok(())
+}
+
 fn create_vmwdt_node(fdt: &mut Fdt, vmwdt_cfg: VmWdtConfig, num_cpus: u32) -> Result<()> {
     let vmwdt_name = format!("vmwdt@{:x}", vmwdt_cfg.base);
     let reg = [vmwdt_cfg.base, vmwdt_cfg.size];
@@ -580,6 +743,7 @@ fn add_symbols_entry(fdt: &mut Fdt, symbol: &str, path: &str) -> Result<()> {
 /// * `pci_irqs` - List of PCI device address to PCI interrupt number and pin mappings
 /// * `pci_cfg` - Location of the memory-mapped PCI configuration space.
 /// * `pci_ranges` - Memory ranges accessible via the PCI host controller.
+/// * `virtio_mmio_resources` - The (start address, size, IRQ number) of virtio-mmio devices
 /// * `num_cpus` - Number of virtual CPUs the guest will have
 /// * `fdt_address` - The offset into physical memory for the device tree
 /// * `cmdline` - The kernel commandline
@@ -589,6 +753,7 @@ fn add_symbols_entry(fdt: &mut Fdt, symbol: &str, path: &str) -> Result<()> {
 /// * `psci_version` - the current PSCI version
 /// * `swiotlb` - Reserve a memory pool for DMA. Tuple of base address and size.
 /// * `bat_mmio_base_and_irq` - The battery base address and irq number
+/// * `smmuv3_cfg` - The emulated SMMUv3 configuration
 /// * `vmwdt_cfg` - The virtual watchdog configuration
 /// * `dump_device_tree_blob` - Option path to write DTB to
 /// * `vm_generator` - Callback to add additional nodes to DTB. create_vm uses Aarch64Vm::create_fdt
@@ -601,6 +766,7 @@ pub fn create_fdt(
     #[cfg(any(target_os = "android", target_os = "linux"))] platform_dev_resources: Vec<
         PlatformBusResources,
     >,
+    virtio_mmio_resources: &[(u64, u64, u32)],
     num_cpus: u32,
     cpu_mpidr_generator: &impl Fn(usize) -> Option<u64>,
     cpu_clusters: Vec<CpuSet>,
@@ -616,6 +782,7 @@ pub fn create_fdt(
     psci_version: PsciVersion,
     swiotlb: Option<(Option<GuestAddress>, u64)>,
     bat_mmio_base_and_irq: Option<(u64, u32)>,
+    smmuv3_cfg: Option<SmmuV3Config>,
     vmwdt_cfg: VmWdtConfig,
     dump_device_tree_blob: Option<PathBuf>,
     vm_generator: &impl Fn(&mut Fdt, &BTreeMap<&str, u32>) -> cros_fdt::Result<()>,
@@ -623,6 +790,7 @@ pub fn create_fdt(
     device_tree_overlays: Vec<DtbOverlay>,
     serial_devices: &[SerialDeviceInfo],
     virt_cpufreq_v2: bool,
+    vcpu_caches: &[Vec<VcpuCache>],
 ) -> Result<()> {
     let mut fdt = Fdt::new(&[]);
     let mut phandles_key_cache = Vec::new();
@@ -674,6 +842,7 @@ pub fn create_fdt(
         cpu_capacity,
         dynamic_power_coefficient,
         cpu_frequencies.clone(),
+        vcpu_caches,
     )?;
     create_gic_node(&mut fdt, is_gicv3, num_cpus as u64)?;
     create_timer_node(&mut fdt, num_cpus)?;
@@ -683,10 +852,15 @@ pub fn create_fdt(
     create_serial_nodes(&mut fdt, serial_devices)?;
     create_psci_node(&mut fdt, &psci_version)?;
     create_pci_nodes(&mut fdt, pci_irqs, pci_cfg, pci_ranges, dma_pool_phandle)?;
+    create_virtio_mmio_nodes(&mut fdt, virtio_mmio_resources, dma_pool_phandle)?;
     create_rtc_node(&mut fdt)?;
     if let Some((bat_mmio_base, bat_irq)) = bat_mmio_base_and_irq {
         create_battery_node(&mut fdt, bat_mmio_base, bat_irq)?;
     }
+    if let Some(smmuv3_cfg) = &smmuv3_cfg {
+        create_smmuv3_node(&mut fdt, smmuv3_cfg)?;
+        phandles.insert("smmuv3", PHANDLE_SMMUV3);
+    }
     create_vmwdt_node(&mut fdt, vmwdt_cfg, num_cpus)?;
     create_kvm_cpufreq_node(&mut fdt)?;
     vm_generator(&mut fdt, &phandles)?;
@@ -797,6 +971,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn smmuv3_iommu_map() {
+        let mut fdt = Fdt::new(&[]);
+        let cfg = SmmuV3Config {
+            base: 0x9000_0000,
+            eventq_irq: 40,
+            gerror_irq: 41,
+            stream_ids: vec![0x8, 0x10],
+        };
+        create_smmuv3_node(&mut fdt, &cfg).unwrap();
+
+        let smmu = fdt.get_node("/iommu@90000000").unwrap();
+        assert_eq!(smmu.get_prop::<u32>("phandle"), Some(PHANDLE_SMMUV3));
+        let pci = fdt.get_node("/pci").unwrap();
+        assert_eq!(
+            pci.get_prop::<Vec<u32>>("iommu-map").unwrap(),
+            vec![0x8, PHANDLE_SMMUV3, 0x8, 1, 0x10, PHANDLE_SMMUV3, 0x10, 1]
+        );
+    }
+
+    #[test]
+    fn mirrored_caches() {
+        let cache = |level, cache_type, size, shared_vcpus: &[usize]| VcpuCache {
+            level,
+            cache_type,
+            size: Some(size),
+            line_size: Some(64),
+            sets: None,
+            shared_vcpus: CpuSet::new(shared_vcpus.iter().copied()),
+        };
+        let vcpu_caches: Vec<Vec<VcpuCache>> = (0..2)
+            .map(|vcpu| {
+                vec![
+                    cache(1, CpuCacheType::Data, 0x10000, &[vcpu]),
+                    cache(1, CpuCacheType::Instruction, 0x10000, &[vcpu]),
+                    cache(2, CpuCacheType::Unified, 0x100000, &[vcpu]),
+                    cache(3, CpuCacheType::Unified, 0x1000000, &[0, 1]),
+                ]
+            })
+            .collect();
+
+        let mut fdt = Fdt::new(&[]);
+        create_cpu_nodes(
+            &mut fdt,
+            2,
+            &|vcpu| Some(vcpu as u64),
+            Vec::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            &vcpu_caches,
+        )
+        .unwrap();
+
+        let cpu1 = fdt.get_node("/cpus/cpu@1").unwrap();
+        assert_eq!(cpu1.get_prop::<u32>("d-cache-size"), Some(0x10000));
+        assert_eq!(cpu1.get_prop::<u32>("i-cache-line-size"), Some(64));
+        assert_eq!(
+            cpu1.get_prop::<u32>("next-level-cache"),
+            Some(PHANDLE_CACHE_BASE + 2)
+        );
+
+        // vCPU 0 lists its L2 and the shared L3 before the L2 of vCPU 1.
+        let l2 = fdt.get_node("/cpus/l2-cache2").unwrap();
+        assert_eq!(l2.get_prop::<u32>("cache-level"), Some(2));
+        assert_eq!(l2.get_prop::<u32>("cache-size"), Some(0x100000));
+        assert_eq!(
+            l2.get_prop::<u32>("next-level-cache"),
+            Some(PHANDLE_CACHE_BASE + 1)
+        );
+        let l3 = fdt.get_node("/cpus/l3-cache1").unwrap();
+        assert_eq!(l3.get_prop::<u32>("phandle"), Some(PHANDLE_CACHE_BASE + 1));
+        assert_eq!(l3.get_prop::<u32>("next-level-cache"), None);
+        assert!(fdt.get_node("/cpus/l3-cache3").is_none());
+    }
+
     #[test]
     fn symbols_entries() {
         const TEST_SYMBOL: &str = "dev";
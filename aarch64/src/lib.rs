@@ -59,6 +59,8 @@ use gdbstub_arch::aarch64::reg::id::AArch64RegId;
 use gdbstub_arch::aarch64::AArch64 as GdbArch;
 use hypervisor::CpuConfigAArch64;
 use hypervisor::DeviceKind;
+#[cfg(feature = "gdb")]
+use hypervisor::HwWatchpoint;
 use hypervisor::Hypervisor;
 use hypervisor::HypervisorCap;
 use hypervisor::MemCacheType;
@@ -130,6 +132,11 @@ const PSR_I_BIT: u64 = 0x00000080;
 const PSR_A_BIT: u64 = 0x00000100;
 const PSR_D_BIT: u64 = 0x00000200;
 
+// SCTLR_EL1 (System Control Register) bits
+const SCTLR_EL1_M_BIT: u64 = 1 << 0;
+const SCTLR_EL1_C_BIT: u64 = 1 << 2;
+const SCTLR_EL1_I_BIT: u64 = 1 << 12;
+
 // This was the speed kvmtool used, not sure if it matters.
 const AARCH64_SERIAL_SPEED: u32 = 1843200;
 // The serial device gets the first interrupt line
@@ -273,12 +280,16 @@ pub enum Error {
     CreatePlatformBus(arch::DeviceRegistrationError),
     #[error("unable to create serial devices: {0}")]
     CreateSerialDevices(arch::DeviceRegistrationError),
+    #[error("unable to create SMMUv3: {0}")]
+    CreateSmmuV3(arch::DeviceRegistrationError),
     #[error("failed to create socket: {0}")]
     CreateSocket(io::Error),
     #[error("failed to create tube: {0}")]
     CreateTube(base::TubeError),
     #[error("failed to create VCPU: {0}")]
     CreateVcpu(base::Error),
+    #[error("failed to create virtio-mmio bus: {0}")]
+    CreateVirtioMmioBus(arch::DeviceRegistrationError),
     #[error("unable to create vm watchdog timer device: {0}")]
     CreateVmwdtDevice(anyhow::Error),
     #[error("custom pVM firmware could not be loaded: {0}")]
@@ -291,6 +302,8 @@ pub enum Error {
     FinalizeIrqChip(base::Error),
     #[error("failed to get HW breakpoint count: {0}")]
     GetMaxHwBreakPoint(base::Error),
+    #[error("failed to get HW watchpoint count: {0}")]
+    GetMaxHwWatchPoint(base::Error),
     #[error("failed to get PSCI version: {0}")]
     GetPsciVersion(base::Error),
     #[error("failed to get serial cmdline: {0}")]
@@ -743,6 +756,7 @@ impl arch::LinuxArch for AArch64 {
                 &mut vm,
                 (devices::AARCH64_GIC_NR_SPIS - AARCH64_IRQ_BASE) as usize,
                 None,
+                0,
                 #[cfg(feature = "swap")]
                 swap_controller,
             )
@@ -750,7 +764,7 @@ impl arch::LinuxArch for AArch64 {
 
         let pci_root = Arc::new(Mutex::new(pci));
         let pci_bus = Arc::new(Mutex::new(PciConfigMmio::new(pci_root.clone(), 8)));
-        let (platform_devices, _others): (Vec<_>, Vec<_>) = others
+        let (platform_devices, others): (Vec<_>, Vec<_>) = others
             .into_iter()
             .partition(|(dev, _)| dev.as_platform_device().is_some());
 
@@ -772,6 +786,27 @@ impl arch::LinuxArch for AArch64 {
             .map_err(Error::CreatePlatformBus)?;
         pid_debug_label_map.append(&mut platform_pid_debug_label_map);
 
+        let (virtio_mmio_devices, _others): (Vec<_>, Vec<_>) = others
+            .into_iter()
+            .partition(|(dev, _)| dev.as_virtio_mmio_device().is_some());
+
+        let virtio_mmio_devices = virtio_mmio_devices
+            .into_iter()
+            .map(|(dev, jail_orig)| (*(dev.into_virtio_mmio_device().unwrap()), jail_orig))
+            .collect();
+        let (mut virtio_mmio_pid_debug_label_map, virtio_mmio_resources) =
+            arch::sys::linux::generate_virtio_mmio_bus(
+                virtio_mmio_devices,
+                irq_chip.as_irq_chip_mut(),
+                &mmio_bus,
+                system_allocator,
+                &mut vm,
+                #[cfg(feature = "swap")]
+                swap_controller,
+            )
+            .map_err(Error::CreateVirtioMmioBus)?;
+        pid_debug_label_map.append(&mut virtio_mmio_pid_debug_label_map);
+
         let (vmwdt_host_tube, vmwdt_control_tube) = Tube::pair().map_err(Error::CreateTube)?;
         Self::add_arch_devs(
             irq_chip.as_irq_chip_mut(),
@@ -891,6 +926,13 @@ impl arch::LinuxArch for AArch64 {
         let mut cmdline = Self::get_base_linux_cmdline();
         get_serial_cmdline(&mut cmdline, serial_parameters, "mmio", &serial_devices)
             .map_err(Error::GetSerialCmdline)?;
+        if components.boot_vcpu_count < vcpu_count {
+            // The parked vCPUs are listed in the device tree but left offline by the guest, which
+            // brings them up with PSCI CPU_ON once they are onlined.
+            cmdline
+                .insert("maxcpus", components.boot_vcpu_count.to_string().as_str())
+                .map_err(Error::Cmdline)?;
+        }
         for param in components.extra_kernel_params {
             cmdline.insert_str(&param).map_err(Error::Cmdline)?;
         }
@@ -940,6 +982,7 @@ impl arch::LinuxArch for AArch64 {
                 let (control_tube, mmio_base) = arch::sys::linux::add_goldfish_battery(
                     &mut amls,
                     bat_jail,
+                    components.host_battery,
                     &mmio_bus,
                     irq_chip.as_irq_chip_mut(),
                     bat_irq,
@@ -959,6 +1002,33 @@ impl arch::LinuxArch for AArch64 {
             None => (None, None),
         };
 
+        let smmuv3_cfg = match components.smmuv3.take() {
+            Some((smmu, smmu_jail)) => {
+                let stream_ids = smmu.stream_ids();
+                let eventq_irq = system_allocator.allocate_irq().ok_or(Error::AllocateIrq)?;
+                let gerror_irq = system_allocator.allocate_irq().ok_or(Error::AllocateIrq)?;
+                let base = arch::sys::linux::add_smmuv3(
+                    smmu,
+                    smmu_jail,
+                    &mmio_bus,
+                    irq_chip.as_irq_chip_mut(),
+                    eventq_irq,
+                    gerror_irq,
+                    system_allocator,
+                    #[cfg(feature = "swap")]
+                    swap_controller,
+                )
+                .map_err(Error::CreateSmmuV3)?;
+                Some(fdt::SmmuV3Config {
+                    base,
+                    eventq_irq,
+                    gerror_irq,
+                    stream_ids,
+                })
+            }
+            None => None,
+        };
+
         let vmwdt_cfg = fdt::VmWdtConfig {
             base: AARCH64_VMWDT_ADDR,
             size: AARCH64_VMWDT_SIZE,
@@ -973,6 +1043,7 @@ impl arch::LinuxArch for AArch64 {
             pci_cfg,
             &pci_ranges,
             dev_resources,
+            &virtio_mmio_resources,
             vcpu_count as u32,
             &|n| get_vcpu_mpidr_aff(&vcpus, n),
             components.cpu_clusters,
@@ -995,6 +1066,7 @@ impl arch::LinuxArch for AArch64 {
                 )
             }),
             bat_mmio_base_and_irq,
+            smmuv3_cfg,
             vmwdt_cfg,
             dump_device_tree_blob,
             &|writer, phandles| vm.create_fdt(writer, phandles),
@@ -1002,6 +1074,7 @@ impl arch::LinuxArch for AArch64 {
             device_tree_overlays,
             &serial_devices,
             components.virt_cpufreq_v2,
+            &components.vcpu_caches,
         )
         .map_err(Error::CreateFdt)?;
 
@@ -1307,7 +1380,7 @@ impl<T: VcpuAArch64> arch::GdbOps<T> for AArch64 {
 
     fn enable_singlestep(vcpu: &T) -> Result<()> {
         const SINGLE_STEP: bool = true;
-        vcpu.set_guest_debug(&[], SINGLE_STEP)
+        vcpu.set_guest_debug(&[], &[], SINGLE_STEP)
             .map_err(Error::EnableSinglestep)
     }
 
@@ -1315,9 +1388,17 @@ impl<T: VcpuAArch64> arch::GdbOps<T> for AArch64 {
         vcpu.get_max_hw_bps().map_err(Error::GetMaxHwBreakPoint)
     }
 
-    fn set_hw_breakpoints(vcpu: &T, breakpoints: &[GuestAddress]) -> Result<()> {
+    fn get_max_hw_watchpoints(vcpu: &T) -> Result<usize> {
+        vcpu.get_max_hw_wps().map_err(Error::GetMaxHwWatchPoint)
+    }
+
+    fn set_hw_breakpoints(
+        vcpu: &T,
+        breakpoints: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+    ) -> Result<()> {
         const SINGLE_STEP: bool = false;
-        vcpu.set_guest_debug(breakpoints, SINGLE_STEP)
+        vcpu.set_guest_debug(breakpoints, watchpoints, SINGLE_STEP)
             .map_err(Error::SetHwBreakpoint)
     }
 }
@@ -1467,6 +1548,43 @@ impl AArch64 {
     }
 }
 
+/// Returns the registers of a vCPU resuming from PSCI SYSTEM_SUSPEND, given the value of its
+/// SCTLR_EL1 when it suspended.
+///
+/// Like after CPU_ON, the vCPU starts at `entry` in EL1 with `context_id` in X0, and with the MMU,
+/// caches and interrupts disabled.
+fn system_resume_regs(
+    entry: u64,
+    context_id: u64,
+    sctlr_el1: u64,
+) -> BTreeMap<VcpuRegAArch64, u64> {
+    BTreeMap::from([
+        (
+            VcpuRegAArch64::Pstate,
+            PSR_D_BIT | PSR_A_BIT | PSR_I_BIT | PSR_F_BIT | PSR_MODE_EL1H,
+        ),
+        (VcpuRegAArch64::Pc, entry),
+        (VcpuRegAArch64::X(0), context_id),
+        (
+            VcpuRegAArch64::System(aarch64_sys_reg::SCTLR_EL1),
+            sctlr_el1 & !(SCTLR_EL1_M_BIT | SCTLR_EL1_C_BIT | SCTLR_EL1_I_BIT),
+        ),
+    ])
+}
+
+/// Sends a vCPU that called PSCI SYSTEM_SUSPEND to the `entry` point it passed, with `context_id`.
+pub fn resume_from_system_suspend(
+    vcpu: &dyn VcpuAArch64,
+    entry: u64,
+    context_id: u64,
+) -> std::result::Result<(), base::Error> {
+    let sctlr_el1 = vcpu.get_one_reg(VcpuRegAArch64::System(aarch64_sys_reg::SCTLR_EL1))?;
+    for (reg, value) in system_resume_regs(entry, context_id, sctlr_el1) {
+        vcpu.set_one_reg(reg, value)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1559,4 +1677,17 @@ mod tests {
         // X2: image size
         assert_eq!(vcpu_init.regs.get(&VcpuRegAArch64::X(2)), Some(&0x1000));
     }
+
+    #[test]
+    fn system_resume() {
+        let regs = system_resume_regs(0x8000_1000, 0x42, 0x30d0_199d);
+        assert_eq!(regs[&VcpuRegAArch64::Pc], 0x8000_1000);
+        assert_eq!(regs[&VcpuRegAArch64::X(0)], 0x42);
+        assert_eq!(regs[&VcpuRegAArch64::Pstate], 0x3c5);
+        // MMU, data and instruction caches disabled.
+        assert_eq!(
+            regs[&VcpuRegAArch64::System(aarch64_sys_reg::SCTLR_EL1)],
+            0x30d0_0998
+        );
+    }
 }
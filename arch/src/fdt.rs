@@ -112,6 +112,141 @@ fn update_device_nodes(
     Ok(())
 }
 
+// Properties of a host device tree node that are not copied to the generated guest node, because
+// they are set from the platform resources of the device or reference other host nodes.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const HOST_DT_SKIPPED_PROPS: &[&str] = &[
+    "assigned-clock-parents",
+    "assigned-clock-rates",
+    "assigned-clocks",
+    "clocks",
+    "dma-names",
+    "dmas",
+    "interrupt-parent",
+    "interrupts",
+    "interrupts-extended",
+    "iommu-map",
+    "iommus",
+    "linux,phandle",
+    "msi-parent",
+    "name",
+    "nvmem-cell-names",
+    "nvmem-cells",
+    "operating-points-v2",
+    "phandle",
+    "power-domain-names",
+    "power-domains",
+    "reg",
+    "reset-names",
+    "resets",
+    "status",
+];
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn is_host_dt_prop_copied(name: &str) -> bool {
+    !HOST_DT_SKIPPED_PROPS.contains(&name)
+        && !name.starts_with("pinctrl-")
+        && !name.ends_with("-supply")
+        && !name.ends_with("gpios")
+        && !name.ends_with("-gpio")
+}
+
+// Read the properties of a node exported by the host kernel in sysfs, where each property is a
+// file holding its raw value.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn read_host_dt_props(host_node: &std::path::Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut props = Vec::new();
+    for entry in std::fs::read_dir(host_node)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        props.push((name, std::fs::read(entry.path())?));
+    }
+    props.sort();
+    Ok(props)
+}
+
+// Generate the node of a platform device at the root of the FDT from its node in the host device
+// tree, and label it with the DT symbol of the device. Properties referencing other host nodes
+// are dropped, except for the clocks, which are replaced by fixed-clock stubs that overlays can
+// amend through the `<symbol>_clk_<clock name>` labels.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn create_host_device_node(
+    fdt: &mut Fdt,
+    host_node: &std::path::Path,
+    resources: &PlatformBusResources,
+    phandles: &BTreeMap<&str, u32>,
+    next_phandle: &mut u32,
+) -> Result<()> {
+    let host_name = host_node
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::InvalidPath(format!("invalid host node {}", host_node.display())))?;
+    // Replace the unit address of the host node with the guest address of the device.
+    let base_name = host_name.split('@').next().unwrap_or(host_name);
+    let node_name = match resources.regions.first() {
+        Some((addr, _)) => format!("{base_name}@{addr:x}"),
+        None => base_name.to_owned(),
+    };
+    let node_path = format!("/{node_name}");
+    if fdt.get_node(node_path.as_str()).is_some() {
+        return Err(Error::DuplicateNode(node_name));
+    }
+
+    let props = read_host_dt_props(host_node)?;
+    let clock_names: Vec<String> = props
+        .iter()
+        .find(|(name, _)| name == "clock-names")
+        .map(|(_, val)| {
+            val.split(|b| *b == 0)
+                .filter(|s| !s.is_empty())
+                .map(|s| String::from_utf8_lossy(s).into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut clocks = Vec::new();
+    for clock in &clock_names {
+        let phandle = *next_phandle;
+        *next_phandle += 1;
+        let clk_node = fdt
+            .root_mut()
+            .subnode_mut(&format!("{}-clk-{}", resources.dt_symbol, clock))?;
+        clk_node.set_prop("compatible", "fixed-clock")?;
+        clk_node.set_prop("#clock-cells", 0u32)?;
+        clk_node.set_prop("clock-frequency", 0u32)?;
+        clk_node.set_prop("clock-output-names", clock.as_str())?;
+        clk_node.set_prop("phandle", phandle)?;
+        clocks.push(phandle);
+        let label = format!("{}_clk_{}", resources.dt_symbol, clock).replace('-', "_");
+        fdt.root_mut()
+            .subnode_mut("__symbols__")?
+            .set_prop(&label, format!("/{}-clk-{}", resources.dt_symbol, clock))?;
+    }
+
+    let phandle = *next_phandle;
+    *next_phandle += 1;
+    let node = fdt.root_mut().subnode_mut(&node_name)?;
+    for (name, val) in props {
+        if is_host_dt_prop_copied(&name) {
+            node.set_prop(&name, val)?;
+        }
+    }
+    if !clocks.is_empty() {
+        node.set_prop("clocks", clocks)?;
+    }
+    node.set_prop("phandle", phandle)?;
+    fdt.root_mut()
+        .subnode_mut("__symbols__")?
+        .set_prop(&resources.dt_symbol, node_path.as_str())?;
+
+    update_device_nodes(node_path.parse()?, fdt, resources, phandles)
+}
+
 /// Apply multiple device tree overlays to the base FDT.
 ///
 /// # Arguments
@@ -119,13 +254,25 @@ fn update_device_nodes(
 /// * `fdt` - The base FDT
 /// * `overlays` - A vector of overlay files to apply
 /// * `devices` - A vector of device resource descriptors to amend the overlay nodes with
+///
+/// The nodes of the devices with a host device tree node are generated in the base FDT before the
+/// overlays are applied, so that the overlays can amend them through their labels.
 #[cfg(any(target_os = "android", target_os = "linux"))]
 pub fn apply_device_tree_overlays(
     fdt: &mut Fdt,
     overlays: Vec<DtbOverlay>,
-    mut devices: Vec<PlatformBusResources>,
+    devices: Vec<PlatformBusResources>,
     phandles: &BTreeMap<&str, u32>,
 ) -> Result<()> {
+    let (host_devices, mut devices): (Vec<_>, Vec<_>) =
+        devices.into_iter().partition(|r| r.host_dt_node.is_some());
+    let mut next_phandle = fdt.max_phandle() + 1;
+    for res in &host_devices {
+        if let Some(host_node) = &res.host_dt_node {
+            create_host_device_node(fdt, host_node, res, phandles, &mut next_phandle)?;
+        }
+    }
+
     for mut dtbo in overlays {
         let mut buffer = Vec::new();
         dtbo.file
@@ -252,3 +399,70 @@ pub fn create_reserved_memory_node(
 
     Ok(())
 }
+
+#[cfg(test)]
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_device_node() {
+        let host_dt = tempfile::tempdir().unwrap();
+        let host_node = host_dt.path().join("serial@fe001000");
+        std::fs::create_dir(&host_node).unwrap();
+        std::fs::write(host_node.join("name"), b"serial\0").unwrap();
+        std::fs::write(host_node.join("compatible"), b"vendor,uart\0").unwrap();
+        std::fs::write(host_node.join("reg"), [0u8; 16]).unwrap();
+        std::fs::write(host_node.join("clocks"), [0u8; 8]).unwrap();
+        std::fs::write(host_node.join("clock-names"), b"baud\0apb_pclk\0").unwrap();
+        std::fs::write(host_node.join("pinctrl-0"), [0u8; 4]).unwrap();
+        std::fs::write(host_node.join("vdd-supply"), [0u8; 4]).unwrap();
+        std::fs::create_dir(host_node.join("child")).unwrap();
+
+        let mut fdt = Fdt::new(&[]);
+        fdt.root_mut()
+            .subnode_mut("intc")
+            .unwrap()
+            .set_prop("phandle", 1u32)
+            .unwrap();
+        let res = PlatformBusResources {
+            dt_symbol: "uart".to_owned(),
+            regions: vec![(0x9000000, 0x1000)],
+            irqs: vec![(32, 4)],
+            iommus: vec![],
+            host_dt_node: Some(host_node),
+        };
+        apply_device_tree_overlays(&mut fdt, vec![], vec![res], &BTreeMap::new()).unwrap();
+
+        let node = fdt.get_node("/serial@9000000").unwrap();
+        assert_eq!(
+            node.get_prop::<String>("compatible").as_deref(),
+            Some("vendor,uart")
+        );
+        assert_eq!(
+            node.get_prop::<Vec<u64>>("reg"),
+            Some(vec![0x9000000, 0x1000])
+        );
+        assert_eq!(
+            node.get_prop::<Vec<u32>>("interrupts"),
+            Some(vec![0, 32, 4])
+        );
+        assert_eq!(node.get_prop::<Vec<u32>>("clocks"), Some(vec![2, 3]));
+        assert_eq!(node.get_prop::<u32>("phandle"), Some(4));
+        assert!(node.get_prop::<Vec<u8>>("pinctrl-0").is_none());
+        assert!(node.get_prop::<Vec<u8>>("vdd-supply").is_none());
+        assert!(node.get_prop::<Vec<u8>>("name").is_none());
+        assert!(node.subnode("child").is_none());
+
+        let clk = fdt.get_node("/uart-clk-apb_pclk").unwrap();
+        assert_eq!(clk.get_prop::<u32>("phandle"), Some(3));
+        assert_eq!(
+            fdt.symbol_to_path("uart_clk_apb_pclk").unwrap(),
+            "/uart-clk-apb_pclk".parse::<Path>().unwrap()
+        );
+        assert_eq!(
+            fdt.symbol_to_path("uart").unwrap(),
+            "/serial@9000000".parse::<Path>().unwrap()
+        );
+    }
+}
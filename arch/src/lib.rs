@@ -58,6 +58,8 @@ pub use fdt::apply_device_tree_overlays;
 pub use fdt::DtbOverlay;
 #[cfg(feature = "gdb")]
 use gdbstub::arch::Arch;
+#[cfg(feature = "gdb")]
+use hypervisor::HwWatchpoint;
 use hypervisor::MemCacheType;
 use hypervisor::Vm;
 #[cfg(windows)]
@@ -79,6 +81,8 @@ pub use serial::SERIAL_ADDR;
 use sync::Condvar;
 use sync::Mutex;
 #[cfg(any(target_os = "android", target_os = "linux"))]
+pub use sys::linux::host_vcpu_caches;
+#[cfg(any(target_os = "android", target_os = "linux"))]
 pub use sys::linux::PlatformBusResources;
 use thiserror::Error;
 use uuid::Uuid;
@@ -132,6 +136,10 @@ pub enum VmImage {
 pub struct Pstore {
     pub path: PathBuf,
     pub size: u32,
+    /// Number of copies of the buffer left by previous boots to keep, as `<path>.1` for the most
+    /// recent one up to `<path>.<rotate>`.
+    #[serde(default)]
+    pub rotate: u32,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, FromKeyValues)]
@@ -330,6 +338,27 @@ impl Serialize for CpuSet {
     }
 }
 
+/// The kind of data held by a CPU cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuCacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// A cache of the host CPU a vCPU is pinned to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcpuCache {
+    pub level: u32,
+    pub cache_type: CpuCacheType,
+    /// Size in bytes, if the host reports it.
+    pub size: Option<u32>,
+    pub line_size: Option<u32>,
+    pub sets: Option<u32>,
+    /// The vCPUs pinned to the host CPUs sharing the cache, including this one.
+    pub shared_vcpus: CpuSet,
+}
+
 /// Mapping of guest VCPU threads to host CPU cores.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub enum VcpuAffinity {
@@ -360,6 +389,9 @@ pub struct PciConfig {
     pub ecam: Option<MemoryRegionConfig>,
     /// region for non-prefetchable PCI device memory below 4G
     pub mem: Option<MemoryRegionConfig>,
+    /// number of PCI segments, each with its own host bridge and bus number space
+    #[cfg(target_arch = "x86_64")]
+    pub segments: Option<u16>,
 }
 
 /// Holds the pieces needed to build a VM. Passed to `build_vm` in the `LinuxArch` trait below to
@@ -371,6 +403,10 @@ pub struct VmComponents {
     pub acpi_sdts: Vec<SDT>,
     pub android_fstab: Option<File>,
     pub boot_cpu: usize,
+    /// Number of vCPUs brought up by the guest at boot, the others being parked until onlined at
+    /// runtime.
+    #[cfg(target_arch = "aarch64")]
+    pub boot_vcpu_count: usize,
     pub bootorder_fw_cfg_blob: Vec<u8>,
     #[cfg(target_arch = "x86_64")]
     pub break_linux_pci_config_io: bool,
@@ -387,7 +423,13 @@ pub struct VmComponents {
     #[cfg(target_arch = "x86_64")]
     pub force_s2idle: bool,
     pub fw_cfg_enable: bool,
+    /// A kernel for the firmware to load from fw_cfg, along with `initrd_image` and
+    /// `extra_kernel_params`.
+    #[cfg(target_arch = "x86_64")]
+    pub fw_cfg_kernel: Option<File>,
     pub fw_cfg_parameters: Vec<FwCfgParameters>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub host_battery: bool,
     pub host_cpu_topology: bool,
     pub hugepages: bool,
     pub hv_cfg: hypervisor::Config,
@@ -409,13 +451,33 @@ pub struct VmComponents {
     /// A file to load as pVM firmware. Must be `Some` iff
     /// `hv_cfg.protection_type == ProtectionType::UnprotectedWithFirmware`.
     pub pvm_fw: Option<File>,
+    #[cfg(target_arch = "x86_64")]
+    pub rtc_offset: devices::cmos::RtcOffset,
     pub rt_cpus: CpuSet,
     #[cfg(target_arch = "x86_64")]
+    pub s3: bool,
+    #[cfg(target_arch = "x86_64")]
     pub smbios: SmbiosOptions,
+    /// An emulated SMMUv3 translating the DMA of the IOMMU endpoints, and its jail.
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    pub smmuv3: Option<(devices::SmmuV3, Option<Minijail>)>,
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     pub sve_config: SveConfig,
     pub swiotlb: Option<u64>,
+    /// A TPM backend exposed to the guest through a CRB interface, and its jail.
+    #[cfg(all(
+        target_arch = "x86_64",
+        any(target_os = "android", target_os = "linux"),
+        feature = "vtpm"
+    ))]
+    pub tpm_crb: Option<(Box<dyn devices::virtio::TpmBackend>, Option<Minijail>)>,
     pub vcpu_affinity: Option<VcpuAffinity>,
+    /// Caches of the host CPU of each vCPU to describe to the guest, if any.
+    #[cfg(target_arch = "aarch64")]
+    pub vcpu_caches: Vec<Vec<VcpuCache>>,
     pub vcpu_count: usize,
     #[cfg(all(
         any(target_arch = "arm", target_arch = "aarch64"),
@@ -606,6 +668,11 @@ pub trait LinuxArch {
 pub trait GdbOps<T: VcpuArch> {
     type Error: StdError;
 
+    /// Prepares the vCPU to be debugged, before its first run.
+    fn init_debug(_vcpu: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Reads vCPU's registers.
     fn read_registers(vcpu: &T) -> Result<<GdbArch as Arch>::Registers, Self::Error>;
 
@@ -646,8 +713,15 @@ pub trait GdbOps<T: VcpuArch> {
     /// Get maximum number of hardware breakpoints.
     fn get_max_hw_breakpoints(vcpu: &T) -> Result<usize, Self::Error>;
 
-    /// Set hardware breakpoints at the given addresses.
-    fn set_hw_breakpoints(vcpu: &T, breakpoints: &[GuestAddress]) -> Result<(), Self::Error>;
+    /// Get maximum number of hardware watchpoints.
+    fn get_max_hw_watchpoints(vcpu: &T) -> Result<usize, Self::Error>;
+
+    /// Set hardware breakpoints at the given addresses and the given hardware watchpoints.
+    fn set_hw_breakpoints(
+        vcpu: &T,
+        breakpoints: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+    ) -> Result<(), Self::Error>;
 }
 
 /// Errors for device manager.
@@ -978,6 +1052,7 @@ pub fn generate_pci_root(
     vm: &mut impl Vm,
     max_irqs: usize,
     vcfg_base: Option<u64>,
+    segment: u16,
     #[cfg(feature = "swap")] swap_controller: &mut Option<swap::SwapController>,
 ) -> Result<
     (
@@ -1018,6 +1093,7 @@ pub fn generate_pci_root(
         mmio_register_bit_num,
         Arc::downgrade(&io_bus),
         root_bus,
+        segment,
     )
     .map_err(DeviceRegistrationError::CreateRoot)?;
     #[cfg_attr(windows, allow(unused_mut))]
@@ -1396,9 +1472,13 @@ mod tests {
             Pstore {
                 path: "/some/path".into(),
                 size: 16384,
+                rotate: 0,
             }
         );
 
+        let res: Pstore = from_key_values("path=/some/path,size=16384,rotate=4").unwrap();
+        assert_eq!(res.rotate, 4);
+
         let res = from_key_values::<Pstore>("path=/some/path");
         assert!(res.is_err());
 
@@ -2,7 +2,11 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::ffi::OsString;
+use std::fs;
 use std::fs::OpenOptions;
+use std::path::Path;
+use std::path::PathBuf;
 
 use anyhow::bail;
 use anyhow::Context;
@@ -11,17 +15,161 @@ use base::MemoryMappingBuilder;
 use hypervisor::MemCacheType;
 use hypervisor::Vm;
 use resources::AddressRange;
+use vm_control::PstoreRecord;
+use vm_control::PstoreRecordKind;
 use vm_memory::GuestAddress;
 
 use crate::Pstore;
 
 mod sys;
 
+// Header of each persistent_ram_zone of ramoops: signature, write position and used size.
+const PERSISTENT_RAM_SIG: u32 = 0x43474244; // DBGC
+const PERSISTENT_RAM_HEADER_SIZE: usize = 12;
+
 pub struct RamoopsRegion {
     pub address: u64,
     pub size: u32,
 }
 
+/// Sizes of the ramoops zones. The dmesg zone takes the rest of the region and is split in
+/// records of `zone_size` bytes.
+struct RamoopsLayout {
+    size: u32,
+    zone_size: u32,
+}
+
+impl RamoopsLayout {
+    fn new(size: u32) -> RamoopsLayout {
+        // The kernel rounds the zone sizes down to a power of two, do it here so that the host and
+        // the guest agree on the layout.
+        let quarter = size / 4;
+        let zone_size = if quarter == 0 {
+            0
+        } else {
+            1 << (u32::BITS - 1 - quarter.leading_zeros())
+        };
+        RamoopsLayout { size, zone_size }
+    }
+
+    fn dmesg_size(&self) -> u32 {
+        self.size - 3 * self.zone_size
+    }
+
+    /// Returns the offset and size of each zone of the region.
+    fn zones(&self) -> Vec<(PstoreRecordKind, usize, usize)> {
+        let zone_size = self.zone_size as usize;
+        if zone_size == 0 {
+            return Vec::new();
+        }
+        let dmesg_size = self.dmesg_size() as usize;
+        let mut zones: Vec<_> = (0..dmesg_size / zone_size)
+            .map(|i| (PstoreRecordKind::Dmesg, i * zone_size, zone_size))
+            .collect();
+        zones.push((PstoreRecordKind::Console, dmesg_size, zone_size));
+        zones.push((PstoreRecordKind::Ftrace, dmesg_size + zone_size, zone_size));
+        zones.push((
+            PstoreRecordKind::Pmsg,
+            dmesg_size + 2 * zone_size,
+            zone_size,
+        ));
+        zones
+    }
+}
+
+/// Returns the path of the copy of the pstore buffer taken `boot` boots ago.
+fn rotated_path(path: &Path, boot: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", boot));
+    PathBuf::from(name)
+}
+
+/// Keeps a copy of the records left in the pstore buffer by the previous boot before the guest
+/// gets to overwrite them, shifting the older copies up to `pstore.rotate`.
+fn rotate_files(pstore: &Pstore) -> Result<()> {
+    let data = match fs::read(&pstore.path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context("failed to read pstore"),
+    };
+    if parse_records(&data, pstore.size, 0).is_empty() {
+        return Ok(());
+    }
+    for boot in (1..pstore.rotate).rev() {
+        let from = rotated_path(&pstore.path, boot);
+        if from.exists() {
+            fs::rename(&from, rotated_path(&pstore.path, boot + 1))
+                .with_context(|| format!("failed to rotate {}", from.display()))?;
+        }
+    }
+    fs::write(rotated_path(&pstore.path, 1), data).context("failed to copy pstore")
+}
+
+/// Reads the content of the ramoops zone at `offset`, oldest data first.
+fn read_zone(data: &[u8], offset: usize, zone_size: usize) -> Option<Vec<u8>> {
+    let zone = data.get(offset..offset + zone_size)?;
+    let word = |i: usize| u32::from_le_bytes(zone[i * 4..i * 4 + 4].try_into().unwrap());
+    if zone_size < PERSISTENT_RAM_HEADER_SIZE || word(0) != PERSISTENT_RAM_SIG {
+        return None;
+    }
+    let buffer = &zone[PERSISTENT_RAM_HEADER_SIZE..];
+    let start = word(1) as usize;
+    let size = word(2) as usize;
+    if size == 0 || start > size || size > buffer.len() {
+        return None;
+    }
+    // Once the buffer has wrapped around, the oldest data follows the write position.
+    let mut content = buffer[start..size].to_vec();
+    content.extend_from_slice(&buffer[..start]);
+    Some(content)
+}
+
+// Returns the timestamp in the `====<seconds>.<nanoseconds>-` header of a dmesg record.
+fn dmesg_timestamp(data: &[u8]) -> Option<(u64, u64)> {
+    let header =
+        std::str::from_utf8(data.strip_prefix(b"====")?.split(|&b| b == b'-').next()?).ok()?;
+    let (seconds, nanoseconds) = header.split_once('.')?;
+    Some((seconds.parse().ok()?, nanoseconds.parse().ok()?))
+}
+
+/// Parses the records of a pstore buffer of `size` bytes taken `boot` boots ago. Dmesg records
+/// come most recent first.
+fn parse_records(data: &[u8], size: u32, boot: u32) -> Vec<PstoreRecord> {
+    let mut records: Vec<PstoreRecord> = RamoopsLayout::new(size)
+        .zones()
+        .into_iter()
+        .filter_map(|(kind, offset, zone_size)| {
+            read_zone(data, offset, zone_size).map(|data| PstoreRecord { boot, kind, data })
+        })
+        .collect();
+    // The dmesg zone is used as a ring of records, sort them by time. The sort is stable and
+    // dmesg records are first, so the other zones keep their order.
+    records.sort_by_key(|r| match r.kind {
+        PstoreRecordKind::Dmesg => (0, std::cmp::Reverse(dmesg_timestamp(&r.data))),
+        _ => (1, std::cmp::Reverse(None)),
+    });
+    records
+}
+
+/// Reads the records of the pstore buffer of the running VM and of the copies kept from previous
+/// boots, most recent boot first.
+pub fn read_records(pstore: &Pstore) -> Result<Vec<PstoreRecord>> {
+    let mut records = Vec::new();
+    for boot in 0..=pstore.rotate {
+        let path = match boot {
+            0 => pstore.path.clone(),
+            _ => rotated_path(&pstore.path, boot),
+        };
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        records.extend(parse_records(&data, pstore.size, boot));
+    }
+    Ok(records)
+}
+
 /// Creates a mmio memory region for pstore.
 pub fn create_memory_region(
     vm: &mut impl Vm,
@@ -33,6 +181,10 @@ pub fn create_memory_region(
         bail!("insufficient space for pstore {} {}", region, pstore.size);
     }
 
+    if pstore.rotate > 0 {
+        rotate_files(pstore)?;
+    }
+
     let mut open_opts = OpenOptions::new();
     open_opts.read(true).write(true).create(true);
     sys::set_extra_open_opts(&mut open_opts);
@@ -71,12 +223,132 @@ pub fn add_ramoops_kernel_cmdline(
     // more memory. It means that one crash can only 4096 byte.
     // Set record_size and console_size to 1/4 of allocated memory size.
     // This configulation is same as the host.
+    // The ftrace and pmsg zones get the same size, so that the host can find the records in
+    // the buffer.
+    let zone_size = RamoopsLayout::new(ramoops_region.size).zone_size as u64;
     let ramoops_opts = [
         ("mem_address", ramoops_region.address),
         ("mem_size", ramoops_region.size as u64),
+        ("record_size", zone_size),
+        ("console_size", zone_size),
+        ("ftrace_size", zone_size),
+        ("pmsg_size", zone_size),
     ];
     for (name, val) in &ramoops_opts {
         cmdline.insert_str(format!("ramoops.{}={:#x}", name, val))?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const SIZE: u32 = 0x4000;
+    const ZONE_SIZE: usize = 0x1000;
+
+    fn write_zone(data: &mut [u8], zone: usize, start: u32, content: &[u8]) {
+        let offset = zone * ZONE_SIZE;
+        data[offset..offset + 4].copy_from_slice(&PERSISTENT_RAM_SIG.to_le_bytes());
+        data[offset + 4..offset + 8].copy_from_slice(&start.to_le_bytes());
+        data[offset + 8..offset + 12].copy_from_slice(&(content.len() as u32).to_le_bytes());
+        let buffer = offset + PERSISTENT_RAM_HEADER_SIZE;
+        data[buffer..buffer + content.len()].copy_from_slice(content);
+    }
+
+    #[test]
+    fn layout() {
+        let layout = RamoopsLayout::new(0x100000);
+        assert_eq!(layout.zone_size, 0x40000);
+        assert_eq!(layout.zones().len(), 4);
+
+        // Zones are rounded down to a power of two, the dmesg zone gets the rest.
+        let layout = RamoopsLayout::new(0x30000);
+        assert_eq!(layout.zone_size, 0x8000);
+        assert_eq!(layout.dmesg_size(), 0x18000);
+        let zones = layout.zones();
+        assert_eq!(zones.len(), 6);
+        assert_eq!(zones[3], (PstoreRecordKind::Console, 0x18000, 0x8000));
+        assert_eq!(zones[5], (PstoreRecordKind::Pmsg, 0x28000, 0x8000));
+    }
+
+    #[test]
+    fn parse_zones() {
+        let dmesg = b"====10.000000001-D\npanic\n";
+        let mut data = vec![0u8; SIZE as usize];
+        write_zone(&mut data, 0, dmesg.len() as u32, dmesg);
+        // A console zone that wrapped around: "second" was written after "first".
+        write_zone(&mut data, 1, 6, b"secondfirst ");
+
+        let records = parse_records(&data, SIZE, 2);
+        assert_eq!(
+            records,
+            vec![
+                PstoreRecord {
+                    boot: 2,
+                    kind: PstoreRecordKind::Dmesg,
+                    data: dmesg.to_vec(),
+                },
+                PstoreRecord {
+                    boot: 2,
+                    kind: PstoreRecordKind::Console,
+                    data: b"first second".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dmesg_most_recent_first() {
+        let size = 0x7000;
+        let mut data = vec![0u8; size as usize];
+        // With a zone size of 0x1000 there are 4 dmesg records.
+        write_zone(&mut data, 0, 0, b"====20.0-D\nnew\n");
+        write_zone(&mut data, 1, 0, b"====10.0-D\nold\n");
+        write_zone(&mut data, 2, 0, b"====30.0-D\nnewest\n");
+        let records = parse_records(&data, size, 0);
+        assert_eq!(dmesg_timestamp(&records[0].data), Some((30, 0)));
+        assert_eq!(dmesg_timestamp(&records[1].data), Some((20, 0)));
+        assert_eq!(dmesg_timestamp(&records[2].data), Some((10, 0)));
+    }
+
+    #[test]
+    fn rotate() {
+        let dir = tempdir().unwrap();
+        let pstore = Pstore {
+            path: dir.path().join("pstore"),
+            size: SIZE,
+            rotate: 2,
+        };
+
+        // Nothing to keep from a missing or empty buffer.
+        rotate_files(&pstore).unwrap();
+        fs::write(&pstore.path, vec![0u8; SIZE as usize]).unwrap();
+        rotate_files(&pstore).unwrap();
+        assert!(!rotated_path(&pstore.path, 1).exists());
+
+        for boot in 0..3u8 {
+            let mut data = vec![0u8; SIZE as usize];
+            write_zone(&mut data, 1, 1, &[b'0' + boot]);
+            fs::write(&pstore.path, &data).unwrap();
+            rotate_files(&pstore).unwrap();
+        }
+        assert!(!rotated_path(&pstore.path, 3).exists());
+
+        let records = read_records(&pstore).unwrap();
+        let records: Vec<_> = records
+            .iter()
+            .map(|r| (r.boot, r.kind, r.data.clone()))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (0, PstoreRecordKind::Console, b"2".to_vec()),
+                (1, PstoreRecordKind::Console, b"2".to_vec()),
+                (2, PstoreRecordKind::Console, b"1".to_vec()),
+            ]
+        );
+    }
+}
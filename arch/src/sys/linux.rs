@@ -3,10 +3,15 @@
 // found in the LICENSE file.
 
 use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use acpi_tables::aml::Aml;
 use base::syslog;
+use base::AsRawDescriptor;
 use base::AsRawDescriptors;
 use base::Tube;
 use devices::Bus;
@@ -15,7 +20,10 @@ use devices::IommuDevType;
 use devices::IrqChip;
 use devices::IrqEventSource;
 use devices::ProxyDevice;
+use devices::SmmuV3;
 use devices::VfioPlatformDevice;
+use devices::VirtioMmioDevice;
+use hypervisor::IoEventAddress;
 use hypervisor::ProtectionType;
 use hypervisor::Vm;
 use minijail::Minijail;
@@ -23,7 +31,10 @@ use resources::AllocOptions;
 use resources::SystemAllocator;
 use sync::Mutex;
 
+use crate::CpuCacheType;
+use crate::CpuSet;
 use crate::DeviceRegistrationError;
+use crate::VcpuCache;
 
 /// Adds goldfish battery and returns the platform needed resources including
 /// its AML data and mmio base address
@@ -32,6 +43,7 @@ use crate::DeviceRegistrationError;
 ///
 /// * `amls` - the vector to put the goldfish battery AML
 /// * `battery_jail` - used when sandbox is enabled
+/// * `host_battery` - mirror the host's power supplies instead of the default power monitor
 /// * `mmio_bus` - bus to add the devices to
 /// * `irq_chip` - the IrqChip object for registering irq events
 /// * `irq_num` - assigned interrupt to use
@@ -39,6 +51,7 @@ use crate::DeviceRegistrationError;
 pub fn add_goldfish_battery(
     amls: &mut Vec<u8>,
     battery_jail: Option<Minijail>,
+    host_battery: bool,
     mmio_bus: &Bus,
     irq_chip: &mut dyn IrqChip,
     irq_num: u32,
@@ -71,6 +84,17 @@ pub fn add_goldfish_battery(
     #[cfg(not(feature = "power-monitor-powerd"))]
     let (create_monitor, create_client) = (None, None);
 
+    let (create_monitor, create_client) = if host_battery {
+        (
+            Some(Box::new(power_monitor::sysfs::SysfsMonitor::connect)
+                as Box<dyn power_monitor::CreatePowerMonitorFn>),
+            Some(Box::new(power_monitor::sysfs::SysfsClient::connect)
+                as Box<dyn power_monitor::CreatePowerClientFn>),
+        )
+    } else {
+        (create_monitor, create_client)
+    };
+
     let irq_evt = devices::IrqLevelEvent::new().map_err(DeviceRegistrationError::EventCreate)?;
 
     let goldfish_bat = devices::GoldfishBattery::new(
@@ -134,23 +158,94 @@ pub fn add_goldfish_battery(
     Ok((control_tube, mmio_base))
 }
 
+/// Adds an emulated SMMUv3 and returns its mmio base address
+///
+/// # Arguments
+///
+/// * `smmu` - the SMMU translating the DMA of its endpoints
+/// * `smmu_jail` - used when sandbox is enabled
+/// * `mmio_bus` - bus to add the device to
+/// * `irq_chip` - the IrqChip object for registering irq events
+/// * `eventq_irq` - assigned interrupt signaling new events in the event queue
+/// * `gerror_irq` - assigned interrupt signaling global errors
+/// * `resources` - the SystemAllocator to allocate MMIO
+pub fn add_smmuv3(
+    mut smmu: SmmuV3,
+    smmu_jail: Option<Minijail>,
+    mmio_bus: &Bus,
+    irq_chip: &mut dyn IrqChip,
+    eventq_irq: u32,
+    gerror_irq: u32,
+    resources: &mut SystemAllocator,
+    #[cfg(feature = "swap")] swap_controller: &mut Option<swap::SwapController>,
+) -> Result<u64, DeviceRegistrationError> {
+    let alloc = resources.get_anon_alloc();
+    let mmio_base = resources
+        .allocate_mmio(
+            devices::smmuv3::SMMUV3_MMIO_LEN,
+            alloc,
+            "SmmuV3".to_string(),
+            AllocOptions::new().align(devices::smmuv3::SMMUV3_MMIO_LEN),
+        )
+        .map_err(DeviceRegistrationError::AllocateIoResource)?;
+
+    let eventq_evt = devices::IrqEdgeEvent::new().map_err(DeviceRegistrationError::EventCreate)?;
+    let gerror_evt = devices::IrqEdgeEvent::new().map_err(DeviceRegistrationError::EventCreate)?;
+    for (irq_num, irq_evt) in [(eventq_irq, &eventq_evt), (gerror_irq, &gerror_evt)] {
+        irq_chip
+            .register_edge_irq_event(irq_num, irq_evt, IrqEventSource::from_device(&smmu))
+            .map_err(DeviceRegistrationError::RegisterIrqfd)?;
+    }
+    smmu.set_irqs(eventq_evt, gerror_evt);
+
+    let device: Arc<Mutex<dyn BusDevice>> = match smmu_jail {
+        Some(jail) => {
+            let mut keep_rds = smmu.keep_rds();
+            syslog::push_descriptors(&mut keep_rds);
+            cros_tracing::push_descriptors!(&mut keep_rds);
+            metrics::push_descriptors(&mut keep_rds);
+            Arc::new(Mutex::new(
+                ProxyDevice::new(
+                    smmu,
+                    jail,
+                    keep_rds,
+                    #[cfg(feature = "swap")]
+                    swap_controller,
+                )
+                .map_err(DeviceRegistrationError::ProxyDeviceCreation)?,
+            ))
+        }
+        None => {
+            smmu.on_sandboxed();
+            Arc::new(Mutex::new(smmu))
+        }
+    };
+    mmio_bus
+        .insert(device, mmio_base, devices::smmuv3::SMMUV3_MMIO_LEN)
+        .map_err(DeviceRegistrationError::MmioInsert)?;
+
+    Ok(mmio_base)
+}
+
 pub struct PlatformBusResources {
     pub dt_symbol: String,        // DT symbol (label) assigned to the device
     pub regions: Vec<(u64, u64)>, // (start address, size)
     pub irqs: Vec<(u32, u32)>,    // (IRQ number, flags)
     pub iommus: Vec<(IommuDevType, Option<u32>, Vec<u32>)>, // (IOMMU type, IOMMU identifier, IDs)
+    pub host_dt_node: Option<PathBuf>, // Host DT node to generate the guest node from
 }
 
 impl PlatformBusResources {
     const IRQ_TRIGGER_EDGE: u32 = 1;
     const IRQ_TRIGGER_LEVEL: u32 = 4;
 
-    fn new(symbol: String) -> Self {
+    fn new(symbol: String, host_dt_node: Option<PathBuf>) -> Self {
         Self {
             dt_symbol: symbol,
             regions: vec![],
             irqs: vec![],
             iommus: vec![],
+            host_dt_node,
         }
     }
 }
@@ -183,7 +278,8 @@ pub fn generate_platform_bus(
             .dt_symbol()
             .ok_or(DeviceRegistrationError::MissingDeviceTreeSymbol)?
             .to_owned();
-        let mut device_resources = PlatformBusResources::new(dt_symbol);
+        let mut device_resources =
+            PlatformBusResources::new(dt_symbol, device.host_dt_node().map(|p| p.to_path_buf()));
         let ranges = device
             .allocate_regions(resources)
             .map_err(DeviceRegistrationError::AllocateIoResource)?;
@@ -276,3 +372,184 @@ pub fn generate_platform_bus(
     }
     Ok((platform_devices, pid_labels, bus_dev_resources))
 }
+
+/// Adds virtio-mmio devices to the mmio bus, and returns the pid labels of their processes and
+/// the (start address, size, IRQ number) of each device.
+///
+/// The queue notifications of the devices are registered as ioeventfds, so that they are handled
+/// by the hypervisor without exiting to userspace, like the ones of virtio-pci devices.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn generate_virtio_mmio_bus(
+    devices: Vec<(VirtioMmioDevice, Option<Minijail>)>,
+    irq_chip: &mut dyn IrqChip,
+    mmio_bus: &Bus,
+    resources: &mut SystemAllocator,
+    vm: &mut impl Vm,
+    #[cfg(feature = "swap")] swap_controller: &mut Option<swap::SwapController>,
+) -> Result<(BTreeMap<u32, String>, Vec<(u64, u64, u32)>), DeviceRegistrationError> {
+    let mut pid_labels = BTreeMap::new();
+    let mut bus_dev_resources = Vec::new();
+
+    for (mut device, jail) in devices.into_iter() {
+        let ranges = device
+            .allocate_regions(resources)
+            .map_err(DeviceRegistrationError::AllocateIoResource)?;
+
+        let mut keep_rds = device.keep_rds();
+        syslog::push_descriptors(&mut keep_rds);
+        cros_tracing::push_descriptors!(&mut keep_rds);
+        metrics::push_descriptors(&mut keep_rds);
+
+        let irq_num = resources
+            .allocate_irq()
+            .ok_or(DeviceRegistrationError::AllocateIrq)?;
+        let irq_evt = devices::IrqEdgeEvent::new().map_err(DeviceRegistrationError::EventCreate)?;
+        irq_chip
+            .register_edge_irq_event(irq_num, &irq_evt, IrqEventSource::from_device(&device))
+            .map_err(DeviceRegistrationError::RegisterIrqfd)?;
+        device.assign_irq(&irq_evt, irq_num);
+        keep_rds.extend(irq_evt.as_raw_descriptors());
+
+        for (event, addr, datamatch) in device.ioevents() {
+            vm.register_ioevent(event, IoEventAddress::Mmio(addr), datamatch)
+                .map_err(DeviceRegistrationError::RegisterIoevent)?;
+            keep_rds.push(event.as_raw_descriptor());
+        }
+
+        let arced_dev: Arc<Mutex<dyn BusDevice>> = if let Some(jail) = jail {
+            let proxy = ProxyDevice::new(
+                device,
+                jail,
+                keep_rds,
+                #[cfg(feature = "swap")]
+                swap_controller,
+            )
+            .map_err(DeviceRegistrationError::ProxyDeviceCreation)?;
+            pid_labels.insert(proxy.pid() as u32, proxy.debug_label());
+            Arc::new(Mutex::new(proxy))
+        } else {
+            device.on_sandboxed();
+            Arc::new(Mutex::new(device))
+        };
+        for range in &ranges {
+            mmio_bus
+                .insert(arced_dev.clone(), range.0, range.1)
+                .map_err(DeviceRegistrationError::MmioInsert)?;
+            bus_dev_resources.push((range.0, range.1, irq_num));
+        }
+    }
+    Ok((pid_labels, bus_dev_resources))
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+// Parses a cache size of sysfs, e.g. "32K".
+fn parse_cache_size(s: &str) -> Option<u32> {
+    let (number, shift) = if let Some(kib) = s.strip_suffix('K') {
+        (kib, 10)
+    } else if let Some(mib) = s.strip_suffix('M') {
+        (mib, 20)
+    } else {
+        (s, 0)
+    };
+    number.parse::<u32>().ok()?.checked_mul(1 << shift)
+}
+
+fn read_cache(dir: &Path, host_cpus: &[usize]) -> io::Result<VcpuCache> {
+    let read = |name: &str| -> io::Result<String> {
+        Ok(fs::read_to_string(dir.join(name))?.trim().to_string())
+    };
+    let cache_type = match read("type")?.as_str() {
+        "Data" => CpuCacheType::Data,
+        "Instruction" => CpuCacheType::Instruction,
+        "Unified" => CpuCacheType::Unified,
+        other => return Err(invalid_data(format!("unknown cache type {}", other))),
+    };
+    let shared_cpus: CpuSet = read("shared_cpu_list")?.parse().map_err(invalid_data)?;
+
+    Ok(VcpuCache {
+        level: read("level")?.parse().map_err(invalid_data)?,
+        cache_type,
+        // Firmwares don't always describe the geometry of caches.
+        size: read("size").ok().and_then(|size| parse_cache_size(&size)),
+        line_size: read("coherency_line_size")
+            .ok()
+            .and_then(|line_size| line_size.parse().ok()),
+        sets: read("number_of_sets")
+            .ok()
+            .and_then(|sets| sets.parse().ok()),
+        shared_vcpus: host_cpus
+            .iter()
+            .enumerate()
+            .filter(|(_, host_cpu)| shared_cpus.iter().any(|cpu| cpu == *host_cpu))
+            .map(|(vcpu, _)| vcpu)
+            .collect(),
+    })
+}
+
+/// Returns the caches of the host CPU each vCPU is pinned to, `host_cpus[vcpu]` being the host CPU
+/// of `vcpu`. The caches of a vCPU are in the order the host enumerates them, which on x86 is the
+/// order of the subleaves of CPUID leaf 4 (0x8000001D on AMD).
+pub fn host_vcpu_caches(host_cpus: &[usize]) -> io::Result<Vec<Vec<VcpuCache>>> {
+    host_cpus
+        .iter()
+        .map(|host_cpu| {
+            let mut caches = Vec::new();
+            for index in 0.. {
+                let dir = PathBuf::from(format!(
+                    "/sys/devices/system/cpu/cpu{}/cache/index{}",
+                    host_cpu, index
+                ));
+                if !dir.exists() {
+                    break;
+                }
+                caches.push(read_cache(&dir, host_cpus)?);
+            }
+            Ok(caches)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_size() {
+        assert_eq!(parse_cache_size("48K"), Some(48 << 10));
+        assert_eq!(parse_cache_size("2M"), Some(2 << 20));
+        assert_eq!(parse_cache_size("512"), Some(512));
+        assert_eq!(parse_cache_size("8192M"), None);
+        assert_eq!(parse_cache_size("K"), None);
+    }
+
+    #[test]
+    fn shared_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in [
+            ("type", "Unified\n"),
+            ("level", "2\n"),
+            ("size", "1024K\n"),
+            ("coherency_line_size", "64\n"),
+            ("shared_cpu_list", "2-3,6\n"),
+        ] {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+
+        // vCPUs 1, 2 and 3 run on host CPUs sharing the cache.
+        let cache = read_cache(dir.path(), &[0, 3, 2, 6]).unwrap();
+        assert_eq!(
+            cache,
+            VcpuCache {
+                level: 2,
+                cache_type: CpuCacheType::Unified,
+                size: Some(1 << 20),
+                line_size: Some(64),
+                sets: None,
+                shared_vcpus: CpuSet::new([1, 2, 3]),
+            }
+        );
+    }
+}
@@ -180,6 +180,7 @@ pub use log::error;
 pub use log::info;
 pub use log::trace;
 pub use log::warn;
+pub use mmap::NumaPolicy;
 pub use mmap::Protection;
 pub use platform::get_cpu_affinity;
 pub use platform::getpid;
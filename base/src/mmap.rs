@@ -164,6 +164,22 @@ impl Protection {
     }
 }
 
+/// Host NUMA memory policy of a range of a mapping. See `set_mempolicy(2)` for the semantics of
+/// each mode.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum NumaPolicy {
+    /// Allocate according to the policy of the allocating thread.
+    #[default]
+    Default,
+    /// Allocate only from the given nodes.
+    Bind,
+    /// Interleave page allocations across the given nodes.
+    Interleave,
+    /// Allocate from the given node first and fall back to the others.
+    Preferred,
+}
+
 /// See [MemoryMapping](crate::platform::MemoryMapping) for struct- and method-level
 /// documentation.
 #[derive(Debug)]
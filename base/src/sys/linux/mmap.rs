@@ -8,6 +8,8 @@
 use std::ptr::null_mut;
 
 use libc::c_int;
+use libc::c_uint;
+use libc::c_ulong;
 use libc::PROT_READ;
 use libc::PROT_WRITE;
 use log::warn;
@@ -21,6 +23,7 @@ use crate::MemoryMapping as CrateMemoryMapping;
 use crate::MemoryMappingBuilder;
 use crate::MmapError as Error;
 use crate::MmapResult as Result;
+use crate::NumaPolicy;
 use crate::Protection;
 use crate::RawDescriptor;
 use crate::SafeDescriptor;
@@ -410,6 +413,27 @@ impl MemoryMapping {
         }
     }
 
+    /// Madvise the kernel to merge identical pages of this mapping with KSM.
+    ///
+    /// The kernel only merges anonymous pages, so this has no effect on pages of shared mappings.
+    pub fn use_mergeable(&self) -> Result<()> {
+        // SAFETY:
+        // This is safe because we call madvise with a valid address and size, and we check the
+        // return value.
+        let ret = unsafe {
+            libc::madvise(
+                self.as_ptr() as *mut libc::c_void,
+                self.size(),
+                libc::MADV_MERGEABLE,
+            )
+        };
+        if ret == -1 {
+            Err(Error::SystemCallFailed(ErrnoError::last()))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Madvise the kernel to use Huge Pages for this mapping.
     pub fn use_hugepages(&self) -> Result<()> {
         const SZ_2M: usize = 2 * 1024 * 1024;
@@ -478,6 +502,47 @@ impl MemoryMapping {
         }
     }
 
+    /// Replaces the specified range with a private copy-on-write mapping of `fd` at `fd_offset`.
+    /// Reads return the content of `fd` and writes allocate anonymous pages, so the pages nobody
+    /// writes are shared with every other private mapping of the file.
+    pub fn remap_private(
+        &self,
+        mem_offset: usize,
+        count: usize,
+        fd: &dyn AsRawDescriptor,
+        fd_offset: u64,
+    ) -> Result<()> {
+        self.range_end(mem_offset, count)
+            .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
+        if mem_offset % pagesize() != 0 {
+            return Err(Error::NotPageAligned);
+        }
+        if fd_offset > libc::off64_t::MAX as u64 {
+            return Err(Error::InvalidOffset);
+        }
+        let addr = (self.addr as usize + mem_offset) as *mut libc::c_void;
+        // SAFETY:
+        // Safe because the range is within this mapping, MAP_FIXED only replaces pages of this
+        // mapping, and the return value is checked. As with `remove_range`, the content of the
+        // range changing is the same as the guest changing it.
+        let ret = unsafe {
+            libc::mmap64(
+                addr,
+                count,
+                PROT_READ | PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_FIXED | libc::MAP_NORESERVE,
+                fd.as_raw_descriptor(),
+                fd_offset as libc::off64_t,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(Error::SystemCallFailed(ErrnoError::last()));
+        }
+        // SAFETY: Safe because madvise only changes how the range is dumped.
+        let _ = unsafe { libc::madvise(addr, count, libc::MADV_DONTDUMP) };
+        Ok(())
+    }
+
     /// Uses madvise to tell the kernel to remove the specified range. Works even on locked ranges.
     pub fn dontneed_locked_range(&self, mem_offset: usize, count: usize) -> Result<()> {
         self.range_end(mem_offset, count)
@@ -624,6 +689,73 @@ impl MemoryMapping {
         }
     }
 
+    /// Sets the host NUMA memory policy of a range of the mapping with `mbind(2)` and migrates the
+    /// pages already allocated in the range to comply with it.
+    ///
+    /// `nodes` must be empty for `NumaPolicy::Default` and non-empty otherwise. Only the first of
+    /// `nodes` is used for `NumaPolicy::Preferred`.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_offset` - The offset into the memory mapping. It must be page aligned.
+    /// * `count` - The size in bytes of the range.
+    /// * `policy` - The memory policy to apply.
+    /// * `nodes` - The host NUMA nodes the policy refers to.
+    pub fn set_numa_policy(
+        &self,
+        mem_offset: usize,
+        count: usize,
+        policy: NumaPolicy,
+        nodes: &[u32],
+    ) -> Result<()> {
+        // from <linux/mempolicy.h>
+        const MPOL_DEFAULT: c_int = 0;
+        const MPOL_PREFERRED: c_int = 1;
+        const MPOL_BIND: c_int = 2;
+        const MPOL_INTERLEAVE: c_int = 3;
+        const MPOL_MF_MOVE: c_uint = 1 << 1;
+        const NODES_PER_WORD: usize = c_ulong::BITS as usize;
+
+        self.range_end(mem_offset, count)
+            .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
+        if (policy == NumaPolicy::Default) != nodes.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        let (mode, nodes) = match policy {
+            NumaPolicy::Default => (MPOL_DEFAULT, nodes),
+            NumaPolicy::Bind => (MPOL_BIND, nodes),
+            NumaPolicy::Interleave => (MPOL_INTERLEAVE, nodes),
+            NumaPolicy::Preferred => (MPOL_PREFERRED, &nodes[..1]),
+        };
+        let node_count = nodes.iter().max().map_or(0, |&node| node as usize + 1);
+        let mut node_mask = vec![0 as c_ulong; node_count.div_ceil(NODES_PER_WORD)];
+        for &node in nodes {
+            node_mask[node as usize / NODES_PER_WORD] |= 1 << (node as usize % NODES_PER_WORD);
+        }
+        // The kernel ignores the last bit of `maxnode`.
+        let max_node = node_mask.len() * NODES_PER_WORD + 1;
+        // SAFETY:
+        // Safe because mbind only changes the host placement of the pages of the range, which has
+        // no impact on rust semantics, `node_mask` outlives the call and `max_node` is within its
+        // bounds.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                self.addr as usize + mem_offset,
+                count,
+                mode,
+                node_mask.as_ptr(),
+                max_node,
+                MPOL_MF_MOVE,
+            )
+        };
+        if ret < 0 {
+            Err(Error::SystemCallFailed(super::Error::last()))
+        } else {
+            Ok(())
+        }
+    }
+
     // Check that offset+count is valid and return the sum.
     pub(crate) fn range_end(&self, offset: usize, count: usize) -> Result<usize> {
         let mem_end = offset.checked_add(count).ok_or(Error::InvalidAddress)?;
@@ -872,6 +1004,10 @@ impl CrateMemoryMapping {
         self.mapping.use_hugepages()
     }
 
+    pub fn use_mergeable(&self) -> Result<()> {
+        self.mapping.use_mergeable()
+    }
+
     pub fn from_raw_ptr(addr: RawDescriptor, size: usize) -> Result<CrateMemoryMapping> {
         MemoryMapping::from_fd_offset(&Descriptor(addr), size, 0).map(|mapping| {
             CrateMemoryMapping {
@@ -887,6 +1023,14 @@ pub trait MemoryMappingUnix {
     fn remove_range(&self, mem_offset: usize, count: usize) -> Result<()>;
     /// Remove the specified range from the mapping. Works even on locked ranges.
     fn dontneed_locked_range(&self, mem_offset: usize, count: usize) -> Result<()>;
+    /// Replace the specified range with a private copy-on-write mapping of a file.
+    fn remap_private(
+        &self,
+        mem_offset: usize,
+        count: usize,
+        fd: &dyn AsRawDescriptor,
+        fd_offset: u64,
+    ) -> Result<()>;
     /// Tell the kernel to readahead the range.
     fn async_prefetch(&self, mem_offset: usize, count: usize) -> Result<()>;
     /// Tell the kernel to drop the page cache.
@@ -897,6 +1041,14 @@ pub trait MemoryMappingUnix {
     fn unlock(&self, mem_offset: usize, count: usize) -> Result<()>;
     /// Disable host swap for this mapping.
     fn lock_all(&self) -> Result<()>;
+    /// Set the host NUMA memory policy of the range.
+    fn set_numa_policy(
+        &self,
+        mem_offset: usize,
+        count: usize,
+        policy: NumaPolicy,
+        nodes: &[u32],
+    ) -> Result<()>;
 }
 
 impl MemoryMappingUnix for CrateMemoryMapping {
@@ -906,6 +1058,15 @@ impl MemoryMappingUnix for CrateMemoryMapping {
     fn dontneed_locked_range(&self, mem_offset: usize, count: usize) -> Result<()> {
         self.mapping.dontneed_locked_range(mem_offset, count)
     }
+    fn remap_private(
+        &self,
+        mem_offset: usize,
+        count: usize,
+        fd: &dyn AsRawDescriptor,
+        fd_offset: u64,
+    ) -> Result<()> {
+        self.mapping.remap_private(mem_offset, count, fd, fd_offset)
+    }
     fn async_prefetch(&self, mem_offset: usize, count: usize) -> Result<()> {
         self.mapping.async_prefetch(mem_offset, count)
     }
@@ -921,6 +1082,16 @@ impl MemoryMappingUnix for CrateMemoryMapping {
     fn lock_all(&self) -> Result<()> {
         self.mapping.lock_on_fault(0, self.mapping.size())
     }
+    fn set_numa_policy(
+        &self,
+        mem_offset: usize,
+        count: usize,
+        policy: NumaPolicy,
+        nodes: &[u32],
+    ) -> Result<()> {
+        self.mapping
+            .set_numa_policy(mem_offset, count, policy, nodes)
+    }
 }
 
 pub trait MemoryMappingBuilderUnix<'a> {
@@ -1216,4 +1387,55 @@ mod tests {
             e => panic!("unexpected error: {}", e),
         }
     }
+
+    #[test]
+    fn remap_private() {
+        use std::io::Write;
+
+        let ps = pagesize();
+        let mut file = tempfile().unwrap();
+        file.write_all(&vec![0xaa; ps]).unwrap();
+        let m = MemoryMappingBuilder::new(3 * ps).build().unwrap();
+        m.remap_private(ps, ps, &file, 0).unwrap();
+        assert_eq!(m.read_obj::<u8>(0).unwrap(), 0);
+        assert_eq!(m.read_obj::<u8>(ps).unwrap(), 0xaa);
+        assert_eq!(m.read_obj::<u8>(2 * ps).unwrap(), 0);
+
+        // Writes must not reach the file.
+        m.write_obj(0x55u8, ps).unwrap();
+        assert_eq!(m.read_obj::<u8>(ps).unwrap(), 0x55);
+        let other = MemoryMappingBuilder::new(ps)
+            .from_file(&file)
+            .build()
+            .unwrap();
+        assert_eq!(other.read_obj::<u8>(0).unwrap(), 0xaa);
+
+        assert!(m.remap_private(2 * ps, 2 * ps, &file, 0).is_err());
+    }
+
+    #[test]
+    fn set_numa_policy() {
+        let ps = pagesize();
+        let m = MemoryMappingBuilder::new(2 * ps).build().unwrap();
+        m.write_obj(0x55u8, 0).unwrap();
+        match m.set_numa_policy(0, 2 * ps, NumaPolicy::Bind, &[0]) {
+            // The host kernel was built without NUMA support.
+            Err(Error::SystemCallFailed(e)) if e.errno() == libc::ENOSYS => return,
+            r => r.unwrap(),
+        }
+        assert_eq!(m.read_obj::<u8>(0).unwrap(), 0x55);
+        m.set_numa_policy(ps, ps, NumaPolicy::Default, &[]).unwrap();
+
+        assert!(matches!(
+            m.set_numa_policy(0, ps, NumaPolicy::Default, &[0]),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(
+            m.set_numa_policy(0, ps, NumaPolicy::Interleave, &[]),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(m
+            .set_numa_policy(ps, 2 * ps, NumaPolicy::Bind, &[0])
+            .is_err());
+    }
 }
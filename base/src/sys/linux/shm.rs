@@ -18,6 +18,7 @@ use libc::ftruncate64;
 use libc::off64_t;
 use libc::syscall;
 use libc::SYS_memfd_create;
+use libc::EINVAL;
 use libc::F_ADD_SEALS;
 use libc::F_GET_SEALS;
 use libc::F_SEAL_FUTURE_WRITE;
@@ -31,6 +32,7 @@ use crate::errno_result;
 use crate::shm::PlatformSharedMemory;
 use crate::trace;
 use crate::AsRawDescriptor;
+use crate::Error;
 use crate::FromRawDescriptor;
 use crate::Result;
 use crate::SafeDescriptor;
@@ -38,7 +40,9 @@ use crate::SharedMemory;
 
 // from <sys/memfd.h>
 const MFD_CLOEXEC: c_uint = 0x0001;
+const MFD_HUGETLB: c_uint = 0x0004;
 const MFD_NOEXEC_SEAL: c_uint = 0x0008;
+const MFD_HUGE_SHIFT: c_uint = 26;
 
 // SAFETY: It is caller's responsibility to ensure the args are valid and check the
 // return value of the function.
@@ -149,6 +153,30 @@ static MFD_NOEXEC_SEAL_SUPPORTED: LazyLock<bool> = LazyLock::new(|| {
     }
 });
 
+/// Creates a memfd named `debug_name` with the given `flags` and truncates it to `size` bytes.
+fn create_memfd(debug_name: &CStr, flags: c_uint, size: u64) -> Result<SharedMemory> {
+    let shm_name = debug_name.as_ptr() as *const c_char;
+    // SAFETY:
+    // The following are safe because we give a valid C string and check the
+    // results of the memfd_create call.
+    let fd = unsafe { memfd_create(shm_name, flags) };
+    if fd < 0 {
+        return errno_result();
+    }
+    // SAFETY: Safe because fd is valid.
+    let descriptor = unsafe { SafeDescriptor::from_raw_descriptor(fd) };
+
+    // Set the size of the memfd.
+    // SAFETY: Safe because we check the return value to ftruncate64 and all the args to the
+    // function are valid.
+    let ret = unsafe { ftruncate64(descriptor.as_raw_descriptor(), size as off64_t) };
+    if ret < 0 {
+        return errno_result();
+    }
+
+    Ok(SharedMemory { descriptor, size })
+}
+
 impl PlatformSharedMemory for SharedMemory {
     /// Creates a new shared memory file descriptor with the specified `size` in bytes.
     ///
@@ -165,27 +193,7 @@ impl PlatformSharedMemory for SharedMemory {
         if *MFD_NOEXEC_SEAL_SUPPORTED {
             flags |= MFD_NOEXEC_SEAL;
         }
-
-        let shm_name = debug_name.as_ptr() as *const c_char;
-        // SAFETY:
-        // The following are safe because we give a valid C string and check the
-        // results of the memfd_create call.
-        let fd = unsafe { memfd_create(shm_name, flags) };
-        if fd < 0 {
-            return errno_result();
-        }
-        // SAFETY: Safe because fd is valid.
-        let descriptor = unsafe { SafeDescriptor::from_raw_descriptor(fd) };
-
-        // Set the size of the memfd.
-        // SAFETY: Safe because we check the return value to ftruncate64 and all the args to the
-        // function are valid.
-        let ret = unsafe { ftruncate64(descriptor.as_raw_descriptor(), size as off64_t) };
-        if ret < 0 {
-            return errno_result();
-        }
-
-        Ok(SharedMemory { descriptor, size })
+        create_memfd(debug_name, flags, size)
     }
 
     /// Creates a SharedMemory instance from a SafeDescriptor owning a reference to a
@@ -203,6 +211,13 @@ pub trait SharedMemoryLinux {
     /// file's size can not be determined this way, this will return an error.
     fn from_file(file: File) -> Result<SharedMemory>;
 
+    /// Creates a new shared memory file descriptor of `size` bytes backed by huge pages.
+    ///
+    /// The memfd is created with `MFD_HUGETLB` and allows sealing. `page_size` selects the huge
+    /// page size in bytes and must be a power of two; if it is `None`, the system's default huge
+    /// page size is used. `size` must be a multiple of the huge page size.
+    fn new_hugetlb(debug_name: &CStr, size: u64, page_size: Option<u64>) -> Result<SharedMemory>;
+
     /// Gets the memfd seals that have already been added to this.
     ///
     /// This may fail if this instance was not constructed from a memfd.
@@ -224,6 +239,17 @@ impl SharedMemoryLinux for SharedMemory {
         })
     }
 
+    fn new_hugetlb(debug_name: &CStr, size: u64, page_size: Option<u64>) -> Result<SharedMemory> {
+        let mut flags = MFD_CLOEXEC | MFD_ALLOW_SEALING | MFD_HUGETLB;
+        if let Some(page_size) = page_size {
+            if !page_size.is_power_of_two() {
+                return Err(Error::new(EINVAL));
+            }
+            flags |= page_size.trailing_zeros() << MFD_HUGE_SHIFT;
+        }
+        create_memfd(debug_name, flags, size)
+    }
+
     fn get_seals(&self) -> Result<MemfdSeals> {
         // SAFETY: Safe because we check the return value to fcntl and all the args to the
         // function are valid.
@@ -305,6 +331,13 @@ mod tests {
         shm.add_seals(seals).unwrap_err();
     }
 
+    #[test]
+    fn new_hugetlb_invalid_page_size() {
+        let err = SharedMemory::new_hugetlb(c"test", 0, Some(3 << 20))
+            .expect_err("non-power-of-two huge page size accepted");
+        assert_eq!(err, Error::new(EINVAL));
+    }
+
     #[test]
     fn mmap_page() {
         let shm = SharedMemory::new("test", 4096).expect("failed to create shared memory");
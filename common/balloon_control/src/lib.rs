@@ -31,6 +31,11 @@ pub enum BalloonTubeCommand {
         refresh_threshold: u32,
         report_threshold: u32,
     },
+    // Set whether the ws reports the guest sends on its own are forwarded as
+    // BalloonTubeResult::WorkingSetPushed messages.
+    WorkingSetForward {
+        enabled: bool,
+    },
 }
 
 // BalloonStats holds stats returned from the stats_queue.
@@ -87,5 +92,16 @@ pub enum BalloonTubeResult {
         ws: BalloonWS,
         /// size of the balloon in bytes.
         balloon_actual: u64,
+        /// time the report was received, in milliseconds since the UNIX epoch.
+        timestamp_ms: u64,
+    },
+    // A ws report the guest sent without being asked, forwarded as requested by
+    // BalloonTubeCommand::WorkingSetForward.
+    WorkingSetPushed {
+        ws: BalloonWS,
+        /// size of the balloon in bytes.
+        balloon_actual: u64,
+        /// time the report was received, in milliseconds since the UNIX epoch.
+        timestamp_ms: u64,
     },
 }
@@ -57,7 +57,13 @@ static DEFAULT_EXECUTOR_KIND: OnceLock<ExecutorKind> = OnceLock::new();
 impl Default for ExecutorKind {
     fn default() -> Self {
         #[cfg(any(target_os = "android", target_os = "linux"))]
-        let default_fn = || ExecutorKindSys::Fd.into();
+        let default_fn = || {
+            if linux::uring_executor::use_uring_by_default() {
+                ExecutorKindSys::Uring.into()
+            } else {
+                ExecutorKindSys::Fd.into()
+            }
+        };
         #[cfg(windows)]
         let default_fn = || ExecutorKindSys::Handle.into();
         *DEFAULT_EXECUTOR_KIND.get_or_init(default_fn)
@@ -374,6 +380,16 @@ impl Executor {
         })
     }
 
+    /// Create a new `Executor` that spawns its tasks and drives its IO sources on an existing
+    /// Tokio runtime, e.g. to embed device backends into a Tokio-based service.
+    ///
+    /// The runtime should be a multi-threaded one, and `run_until` must not be called from within
+    /// it.
+    #[cfg(feature = "tokio")]
+    pub fn from_tokio_handle(handle: tokio::runtime::Handle) -> Self {
+        Executor::Tokio(TokioExecutor::from_handle(handle))
+    }
+
     /// Set the default ExecutorKind for [`Self::new()`]. This call is effective only once.
     pub fn set_default_executor_kind(
         executor_kind: ExecutorKind,
@@ -106,9 +106,13 @@ pub use mem::VecIoWrapper;
 use remain::sorted;
 pub use select::SelectResult;
 #[cfg(any(target_os = "android", target_os = "linux"))]
+pub use sys::linux::uring_executor::is_uring_operation_supported;
+#[cfg(any(target_os = "android", target_os = "linux"))]
 pub use sys::linux::uring_executor::is_uring_stable;
 use thiserror::Error as ThisError;
 pub use timer::TimerAsync;
+#[cfg(feature = "tokio")]
+pub use tokio_executor::in_tokio_context;
 
 #[sorted]
 #[derive(ThisError, Debug)]
@@ -71,6 +71,7 @@ use base::RawDescriptor;
 use io_uring::URingAllowlist;
 use io_uring::URingContext;
 use io_uring::URingOperation;
+use io_uring::URingProbe;
 use remain::sorted;
 use slab::Slab;
 use sync::Mutex;
@@ -106,6 +107,12 @@ pub enum Error {
     /// The Executor is gone.
     #[error("The executor is gone")]
     ExecutorGone,
+    /// An error occurred when executing fallocate synchronously.
+    #[error("An error occurred when executing fallocate synchronously: {0}")]
+    Fallocate(base::Error),
+    /// An error occurred when executing fsync synchronously.
+    #[error("An error occurred when executing fsync synchronously: {0}")]
+    Fsync(base::Error),
     /// Invalid offset or length given for an iovec in backing memory.
     #[error("Invalid offset/len for getting an iovec")]
     InvalidOffset,
@@ -115,6 +122,9 @@ pub enum Error {
     /// Error doing the IO.
     #[error("Error during IO: {0}")]
     Io(io::Error),
+    /// The kernel doesn't support an operation required by the executor.
+    #[error("io_uring operation {0:?} is not supported by the kernel")]
+    OperationUnsupported(URingOperation),
     /// Registering operation restrictions to a uring failed.
     #[error("Error registering restrictions to the URing context: {0}")]
     RegisteringURingRestriction(io_uring::Error),
@@ -140,9 +150,14 @@ impl From<Error> for io::Error {
             Discard(e) => e.into(),
             DuplicatingFd(e) => e.into(),
             ExecutorGone => io::Error::new(io::ErrorKind::Other, ExecutorGone),
+            Fallocate(e) => e.into(),
+            Fsync(e) => e.into(),
             InvalidOffset => io::Error::new(io::ErrorKind::InvalidInput, InvalidOffset),
             InvalidSource => io::Error::new(io::ErrorKind::InvalidData, InvalidSource),
             Io(e) => e,
+            OperationUnsupported(op) => {
+                io::Error::new(io::ErrorKind::Unsupported, OperationUnsupported(op))
+            }
             CreatingContext(e) => e.into(),
             RemovingWaker(e) => e.into(),
             SubmittingOp(e) => e.into(),
@@ -200,13 +215,61 @@ pub fn is_uring_stable() -> bool {
     *IS_URING_STABLE
 }
 
-// Checks the uring availability by checking if the uring creation succeeds.
-// If uring creation succeeds, it returns `Ok(())`. It returns an `URingContextError` otherwise.
-// It fails if the kernel does not support io_uring, but note that the cause is not limited to it.
+// Operations the `UringReactor` can't work without.
+const REQUIRED_OPERATIONS: [URingOperation; 6] = [
+    URingOperation::Readv,
+    URingOperation::Writev,
+    URingOperation::Nop,
+    URingOperation::PollAdd,
+    URingOperation::PollRemove,
+    URingOperation::AsyncCancel,
+];
+
+// The operations supported by the kernel, probed once. `None` if io_uring is unavailable or
+// the kernel is too old to be probed.
+static URING_PROBE: LazyLock<Option<URingProbe>> = LazyLock::new(|| {
+    let ctx = URingContext::new(8, None).ok()?;
+    match ctx.probe() {
+        Ok(probe) => Some(probe),
+        Err(e) => {
+            warn!("Failed to probe io_uring operations: {}", e);
+            None
+        }
+    }
+});
+
+/// Returns true if the kernel supports submitting `operation` to an io_uring.
+///
+/// `UringSource` performs the operations that are not supported synchronously instead, the same
+/// way the epoll executor does.
+pub fn is_uring_operation_supported(operation: URingOperation) -> bool {
+    URING_PROBE
+        .as_ref()
+        .is_some_and(|probe| probe.is_supported(operation))
+}
+
+// Checks the uring availability by checking if the uring creation succeeds and the kernel
+// supports all the operations in `REQUIRED_OPERATIONS`.
+// If so, it returns `Ok(())`. It returns an `URingContextError` or an `OperationUnsupported`
+// otherwise. It fails if the kernel does not support io_uring, but note that the cause is not
+// limited to it.
 pub(crate) fn check_uring_availability() -> Result<()> {
     URingContext::new(8, None)
         .map(drop)
-        .map_err(Error::URingContextError)
+        .map_err(Error::URingContextError)?;
+    match REQUIRED_OPERATIONS
+        .into_iter()
+        .find(|&op| !is_uring_operation_supported(op))
+    {
+        Some(op) => Err(Error::OperationUnsupported(op)),
+        None => Ok(()),
+    }
+}
+
+/// Returns true if the uring executor should be the default: io_uring is stable on this kernel and
+/// supports every operation the executor requires.
+pub(crate) fn use_uring_by_default() -> bool {
+    is_uring_stable() && check_uring_availability().is_ok()
 }
 
 pub struct RegisteredSource {
@@ -920,6 +983,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn default_to_uring_when_supported() {
+        if !is_uring_stable() {
+            return;
+        }
+
+        // Kernels where io_uring is stable support every operation the executor submits.
+        for op in REQUIRED_OPERATIONS {
+            assert!(is_uring_operation_supported(op), "{:?}", op);
+        }
+        assert!(is_uring_operation_supported(URingOperation::Fsync));
+        assert!(is_uring_operation_supported(URingOperation::Fallocate));
+        assert!(use_uring_by_default());
+    }
+
     #[test]
     fn dont_drop_backing_mem_read() {
         if !is_uring_stable() {
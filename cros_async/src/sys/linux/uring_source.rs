@@ -6,9 +6,13 @@ use std::ops::Deref;
 use std::ops::DerefMut;
 use std::sync::Arc;
 
+use base::sys::fallocate;
 use base::sys::FallocateMode;
 use base::AsRawDescriptor;
+use io_uring::URingOperation;
 
+use super::uring_executor::is_uring_operation_supported;
+use super::uring_executor::Error;
 use super::uring_executor::RegisteredSource;
 use super::uring_executor::Result;
 use super::uring_executor::UringReactor;
@@ -122,6 +126,12 @@ impl<F: AsRawDescriptor> UringSource<F> {
 
     /// Deallocates the given range of a file.
     pub async fn punch_hole(&self, file_offset: u64, len: u64) -> AsyncResult<()> {
+        if !is_uring_operation_supported(URingOperation::Fallocate) {
+            return Ok(
+                fallocate(&self.source, FallocateMode::PunchHole, file_offset, len)
+                    .map_err(Error::Fallocate)?,
+            );
+        }
         let op = self.registered_source.start_fallocate(
             file_offset,
             len,
@@ -133,6 +143,12 @@ impl<F: AsRawDescriptor> UringSource<F> {
 
     /// Fills the given range with zeroes.
     pub async fn write_zeroes_at(&self, file_offset: u64, len: u64) -> AsyncResult<()> {
+        if !is_uring_operation_supported(URingOperation::Fallocate) {
+            return Ok(
+                fallocate(&self.source, FallocateMode::ZeroRange, file_offset, len)
+                    .map_err(Error::Fallocate)?,
+            );
+        }
         let op = self.registered_source.start_fallocate(
             file_offset,
             len,
@@ -144,6 +160,15 @@ impl<F: AsRawDescriptor> UringSource<F> {
 
     /// Sync all completed write operations to the backing storage.
     pub async fn fsync(&self) -> AsyncResult<()> {
+        if !is_uring_operation_supported(URingOperation::Fsync) {
+            // SAFETY: the source's descriptor is valid and return value is checked.
+            let ret = unsafe { libc::fsync(self.source.as_raw_descriptor()) };
+            return if ret == 0 {
+                Ok(())
+            } else {
+                Err(Error::Fsync(base::Error::last()).into())
+            };
+        }
         let op = self.registered_source.start_fsync()?;
         let _ = op.await?;
         Ok(())
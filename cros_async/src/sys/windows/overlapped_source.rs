@@ -7,18 +7,22 @@
 use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::mem;
 use std::mem::ManuallyDrop;
+use std::ptr;
 use std::sync::Arc;
 
 use base::error;
 use base::AsRawDescriptor;
 use base::Descriptor;
 use base::FromRawDescriptor;
-use base::PunchHole;
 use base::RawDescriptor;
-use base::WriteZeroesAt;
 use thiserror::Error as ThisError;
+use winapi::ctypes::c_void;
+use winapi::shared::winerror::ERROR_IO_PENDING;
+use winapi::um::ioapiset::DeviceIoControl;
 use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::winioctl::FSCTL_SET_ZERO_DATA;
 
 use crate::common_executor::RawExecutor;
 use crate::mem::BackingMemory;
@@ -109,6 +113,13 @@ impl<F: AsRawDescriptor> OverlappedSource<F> {
     }
 }
 
+// The input of FSCTL_SET_ZERO_DATA, FILE_ZERO_DATA_INFORMATION, which winapi doesn't define.
+#[repr(C)]
+struct FileZeroDataInformation {
+    file_offset: i64,
+    beyond_final_zero: i64,
+}
+
 /// SAFETY:
 /// Safety requirements:
 ///     Same as base::windows::read_file.
@@ -310,9 +321,6 @@ impl<F: AsRawDescriptor> OverlappedSource<F> {
     }
 
     /// Deallocates the given range of a file.
-    ///
-    /// TODO(nkgold): currently this is sync on the executor, which is bad / very hacky. With a
-    /// little wrapper work, we can make overlapped DeviceIoControl calls instead.
     pub async fn punch_hole(&self, file_offset: u64, len: u64) -> AsyncResult<()> {
         if self.seek_forbidden {
             return Err(Error::IoSeekError(io::Error::new(
@@ -321,20 +329,13 @@ impl<F: AsRawDescriptor> OverlappedSource<F> {
             ))
             .into());
         }
-        // SAFETY:
-        // Safe because self.source lives as long as file.
-        let file = ManuallyDrop::new(unsafe {
-            File::from_raw_descriptor(self.source.as_raw_descriptor())
-        });
-        file.punch_hole(file_offset, len)
+        self.set_zero_data(file_offset, len)
+            .await
             .map_err(Error::IoPunchHoleError)?;
         Ok(())
     }
 
     /// Fills the given range with zeroes.
-    ///
-    /// TODO(nkgold): currently this is sync on the executor, which is bad / very hacky. With a
-    /// little wrapper work, we can make overlapped DeviceIoControl calls instead.
     pub async fn write_zeroes_at(&self, file_offset: u64, len: u64) -> AsyncResult<()> {
         if self.seek_forbidden {
             return Err(Error::IoSeekError(io::Error::new(
@@ -343,18 +344,61 @@ impl<F: AsRawDescriptor> OverlappedSource<F> {
             ))
             .into());
         }
-        // SAFETY:
-        // Safe because self.source lives as long as file.
-        let file = ManuallyDrop::new(unsafe {
-            File::from_raw_descriptor(self.source.as_raw_descriptor())
-        });
-        // ZeroRange calls `punch_hole` which doesn't extend the File size if it needs to.
-        // Will fix if it becomes a problem.
-        file.write_zeroes_at(file_offset, len as usize)
+        // Like `WriteZeroesAt` for files, this doesn't extend the file if the range is past its
+        // end.
+        self.set_zero_data(file_offset, len)
+            .await
             .map_err(Error::IoWriteZeroesError)?;
         Ok(())
     }
 
+    /// Zeroes the given range with an overlapped FSCTL_SET_ZERO_DATA, which also deallocates it
+    /// if the file is sparse.
+    async fn set_zero_data(&self, file_offset: u64, len: u64) -> io::Result<()> {
+        let end = file_offset
+            .checked_add(len)
+            .filter(|&end| end <= i64::MAX as u64)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        // Boxed so that it stays at the same address until the operation completes.
+        let zero_data = Box::new(FileZeroDataInformation {
+            file_offset: file_offset as i64,
+            beyond_final_zero: end as i64,
+        });
+
+        let mut overlapped_op = self
+            .reg_source
+            .register_overlapped_operation(None)
+            .map_err(io::Error::from)?;
+        // SAFETY:
+        // Safe because `zero_data` is only freed after the operation completes, the output buffer
+        // is empty and the return value is checked.
+        let ret = unsafe {
+            DeviceIoControl(
+                self.source.as_raw_descriptor(),
+                FSCTL_SET_ZERO_DATA,
+                &*zero_data as *const FileZeroDataInformation as *mut c_void,
+                mem::size_of::<FileZeroDataInformation>() as u32,
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                overlapped_op.get_overlapped(),
+            )
+        };
+        if ret == 0 {
+            let e = io::Error::last_os_error();
+            // The operation completes through the IO completion port.
+            if e.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+                return Err(e);
+            }
+        }
+        overlapped_op
+            .await
+            .map_err(io::Error::from)?
+            .result
+            .map_err(io::Error::from)?;
+        Ok(())
+    }
+
     /// Sync all completed write operations to the backing storage.
     pub async fn fsync(&self) -> AsyncResult<()> {
         // SAFETY:
@@ -604,6 +648,26 @@ mod tests {
         assert!(f.read_exact(&mut buf).is_err());
     }
 
+    #[cfg_attr(all(target_os = "windows", target_env = "gnu"), ignore)]
+    #[test]
+    fn test_write_zeroes_in_bounds() {
+        let (file_path, _tmpdir) = tempfile_path();
+        std::fs::write(&file_path, "abcdefghijk").unwrap();
+
+        async fn write_zeroes(src: &OverlappedSource<File>) {
+            src.write_zeroes_at(2, 3).await.unwrap();
+        }
+
+        let ex = RawExecutor::<HandleReactor>::new().unwrap();
+        let f = open_overlapped(&file_path);
+        let src = OverlappedSource::new(f, &ex, false).unwrap();
+        ex.run_until(write_zeroes(&src)).unwrap();
+        drop(src);
+
+        let buf = std::fs::read(&file_path).unwrap();
+        assert_eq!(buf, b"ab\0\0\0fghijk");
+    }
+
     // TODO(b/194338842): "ZeroRange" is supposed to allocate more memory if it goes out of the
     // bounds of the file. Determine if we need to support this, since Windows doesn't do this yet.
     // use tempfile::NamedTempFile;
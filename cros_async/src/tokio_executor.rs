@@ -9,6 +9,7 @@ use std::sync::OnceLock;
 
 use base::AsRawDescriptors;
 use base::RawDescriptor;
+use tokio::runtime::Handle;
 use tokio::runtime::Runtime;
 use tokio::task::LocalSet;
 
@@ -65,34 +66,65 @@ mod send_wrapper {
 
 #[derive(Clone)]
 pub struct TokioExecutor {
-    runtime: Arc<Runtime>,
+    handle: Handle,
+    // The runtime `handle` refers to, if the executor created it.
+    runtime: Option<Arc<Runtime>>,
     local_set: Arc<OnceLock<send_wrapper::SendWrapper<LocalSet>>>,
 }
 
 impl TokioExecutor {
     pub fn new() -> AsyncResult<Self> {
+        let runtime = Runtime::new().map_err(AsyncError::Io)?;
         Ok(TokioExecutor {
-            runtime: Arc::new(Runtime::new().map_err(AsyncError::Io)?),
+            handle: runtime.handle().clone(),
+            runtime: Some(Arc::new(runtime)),
             local_set: Arc::new(OnceLock::new()),
         })
     }
+
+    /// Creates an executor that spawns its tasks and drives its IO sources on the existing runtime
+    /// referred to by `handle`.
+    ///
+    /// The runtime should be a multi-threaded one: `run_until` can't drive the IO and timer
+    /// drivers of a current-thread runtime, and like `Handle::block_on`, it panics if called from
+    /// within the runtime.
+    pub fn from_handle(handle: Handle) -> Self {
+        TokioExecutor {
+            handle,
+            runtime: None,
+            local_set: Arc::new(OnceLock::new()),
+        }
+    }
+}
+
+/// Polls `f` within the context of the Tokio runtime referred to by `handle`.
+///
+/// This allows futures that need a Tokio runtime, such as those of Tokio's IO types and timers, to
+/// be awaited on any executor, not just a Tokio one. The runtime must keep driving its IO and
+/// timers in the meantime, so it should be a multi-threaded one.
+pub async fn in_tokio_context<F: Future>(handle: &Handle, f: F) -> F::Output {
+    let mut f = std::pin::pin!(f);
+    std::future::poll_fn(|cx| {
+        let _guard = handle.enter();
+        f.as_mut().poll(cx)
+    })
+    .await
 }
 
 impl ExecutorTrait for TokioExecutor {
     fn async_from<'a, F: IntoAsync + 'a>(&self, f: F) -> AsyncResult<IoSource<F>> {
-        Ok(IoSource::Tokio(TokioSource::new(
-            f,
-            self.runtime.handle().clone(),
-        )?))
+        Ok(IoSource::Tokio(TokioSource::new(f, self.handle.clone())?))
     }
 
     fn run_until<F: Future>(&self, f: F) -> AsyncResult<F::Output> {
         let local_set = self
             .local_set
             .get_or_init(|| send_wrapper::SendWrapper::new(LocalSet::new()));
-        Ok(self
-            .runtime
-            .block_on(async { local_set.run_until(f).await }))
+        let f = async { local_set.run_until(f).await };
+        Ok(match &self.runtime {
+            Some(runtime) => runtime.block_on(f),
+            None => self.handle.block_on(f),
+        })
     }
 
     fn spawn<F>(&self, f: F) -> TaskHandle<F::Output>
@@ -101,7 +133,7 @@ impl ExecutorTrait for TokioExecutor {
         F::Output: Send + 'static,
     {
         TaskHandle::Tokio(TokioTaskHandle {
-            join_handle: Some(self.runtime.spawn(f)),
+            join_handle: Some(self.handle.spawn(f)),
         })
     }
 
@@ -111,7 +143,7 @@ impl ExecutorTrait for TokioExecutor {
         R: Send + 'static,
     {
         TaskHandle::Tokio(TokioTaskHandle {
-            join_handle: Some(self.runtime.spawn_blocking(f)),
+            join_handle: Some(self.handle.spawn_blocking(f)),
         })
     }
 
@@ -168,3 +200,44 @@ impl<T> std::ops::Drop for TokioTaskHandle<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::sys::ExecutorKindSys;
+    use crate::Executor;
+
+    #[test]
+    fn from_handle() {
+        let runtime = Runtime::new().unwrap();
+        let ex = Executor::from_tokio_handle(runtime.handle().clone());
+
+        let task = ex.spawn(async {
+            // Tokio timers need to be polled within the runtime.
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            42
+        });
+
+        // The task runs on the runtime's own threads, so it can be awaited from the runtime.
+        assert_eq!(runtime.block_on(task), 42);
+    }
+
+    #[test]
+    fn tokio_future_on_other_executor() {
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.handle().clone();
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let ex = Executor::with_executor_kind(ExecutorKindSys::Fd.into()).unwrap();
+        #[cfg(windows)]
+        let ex = Executor::with_executor_kind(ExecutorKindSys::Handle.into()).unwrap();
+
+        ex.run_until(in_tokio_context(
+            &handle,
+            tokio::time::sleep(Duration::from_millis(1)),
+        ))
+        .unwrap();
+    }
+}
@@ -783,6 +783,11 @@ impl Fdt {
         Some(result_node)
     }
 
+    /// Return the largest phandle value used in the FDT, or 0 if no node has a phandle.
+    pub fn max_phandle(&self) -> u32 {
+        crate::overlay::get_max_phandle(&self.root)
+    }
+
     /// Find a device tree path to the symbol exported by the FDT. The symbol must be a node label.
     ///
     /// # Arguments
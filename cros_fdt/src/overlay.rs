@@ -174,7 +174,7 @@ fn get_node_phandle(node: &FdtNode) -> Option<u32> {
 }
 
 // Return the largest phandle value in a node tree.
-fn get_max_phandle(root_node: &FdtNode) -> u32 {
+pub(crate) fn get_max_phandle(root_node: &FdtNode) -> u32 {
     let mut max_phandle = 0u32;
     let mut nodes_to_visit = VecDeque::new();
     nodes_to_visit.push_back(root_node);
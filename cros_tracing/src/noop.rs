@@ -36,3 +36,11 @@ macro_rules! push_descriptors {
 }
 
 pub fn init() {}
+
+pub fn categories() -> Vec<(&'static str, bool)> {
+    Vec::new()
+}
+
+pub fn set_category_enabled(name: &str, _enabled: bool) -> anyhow::Result<()> {
+    anyhow::bail!("unknown tracing category {}: tracing is not built in", name)
+}
@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use anyhow::bail;
+use base::error;
 pub use cros_tracing_types::static_strings::StaticString;
 pub use perfetto::*;
 
@@ -27,6 +29,9 @@ setup_perfetto!(
     perfetto_tags!("devices"),
     future,
     "Async trace points",
+    perfetto_tags!(),
+    vcpu,
+    "vCPU exit trace points",
     perfetto_tags!()
 );
 
@@ -34,7 +39,36 @@ setup_perfetto!(
 // See go/bstar-perfetto
 pub const HOST_GUEST_CLOCK_ID_OFFSET: u32 = 32;
 
+/// Returns the name of every tracing category and whether it is enabled.
+pub fn categories() -> Vec<(&'static str, bool)> {
+    PERFETTO_CATEGORY_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, PERFETTO_CATEGORY_MASK.is_enabled(i)))
+        .collect()
+}
+
+/// Enables or disables the tracing category called `name`. A disabled category is not traced even
+/// if a trace session selects it.
+pub fn set_category_enabled(name: &str, enabled: bool) -> anyhow::Result<()> {
+    let Some(index) = PERFETTO_CATEGORY_NAMES.iter().position(|c| *c == name) else {
+        bail!("unknown tracing category {}", name);
+    };
+    PERFETTO_CATEGORY_MASK.set_enabled(index, enabled);
+    Ok(())
+}
+
+fn share_categories() {
+    if let Err(e) = PERFETTO_CATEGORY_MASK.share() {
+        error!(
+            "{:#}. Tracing categories can only be toggled in this process.",
+            e
+        );
+    }
+}
+
 pub fn init() {
+    share_categories();
     register_categories();
     // This tracing crate only supports system backend for now. If we want crosvm to start/end
     // a trace then we'd want to add some functions in this crate for that.
@@ -42,6 +76,7 @@ pub fn init() {
 }
 
 pub fn init_in_process() {
+    share_categories();
     register_categories();
     perfetto::init_tracing(perfetto::BackendType::InProcess);
 }
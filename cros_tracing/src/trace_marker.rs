@@ -10,8 +10,10 @@ use std::io::Write;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
+use anyhow::bail;
 use base::error;
 use base::RawDescriptor;
+pub use cros_tracing_types::category_mask::CategoryMask;
 use sync::Mutex;
 
 static TRACE_MARKER_FILE: Mutex<Option<File>> = Mutex::new(None);
@@ -52,7 +54,7 @@ macro_rules! push_descriptors {
 /// The tagged variant lets us enable or disable individual categories.
 macro_rules! trace_simple_print {
     ($category: ident, $($t:tt)+) => {{
-        if($crate::ENABLED_CATEGORIES[$crate::TracedCategories::$category as usize].load(std::sync::atomic::Ordering::Relaxed)) {
+        if($crate::ENABLED_CATEGORIES.is_enabled($crate::TracedCategories::$category as usize)) {
             $crate::trace_simple_print!($($t)*);
         }
     }};
@@ -94,7 +96,8 @@ pub fn push_descriptors_internal(keep_rds: &mut Vec<RawDescriptor>) {
 ///
 /// Categories that are enabled will have their events traced at runtime via
 /// `trace_event_begin!()`, `trace_event_end!()`, or `trace_event!()` scoped tracing.
-/// The categories that are marked as false will have their events skipped.
+/// The categories that are marked as false will have their events skipped until they
+/// are enabled with `set_category_enabled()`.
 macro_rules! setup_trace_marker {
  ($(($cat:ident, $enabled:literal)),+) => {
      #[allow(non_camel_case_types, missing_docs)]
@@ -114,11 +117,14 @@ macro_rules! setup_trace_marker {
          )+
      ];
 
-     /// Vector used to test if a category is enabled or not for tracing.
-     pub static ENABLED_CATEGORIES: [std::sync::atomic::AtomicBool; TracedCategories::CATEGORY_COUNT as usize] = [
-         $(
-             std::sync::atomic::AtomicBool::new($enabled),
-         )+
+     /// Mask used to test if a category is enabled or not for tracing.
+     pub static ENABLED_CATEGORIES: $crate::CategoryMask = $crate::CategoryMask::new(
+         0 $(| (($enabled as u64) << (TracedCategories::$cat as u64)))+
+     );
+
+     /// Names of the tracing categories, indexed by `TracedCategories`.
+     pub const CATEGORY_NAMES: [&str; TracedCategories::CATEGORY_COUNT as usize] = [
+         $(std::stringify!($cat),)+
      ];
 
      /// Sequential identifier for scoped trace events. This unique identifier is incremented
@@ -159,7 +165,7 @@ macro_rules! setup_trace_marker {
 /// where `$uid` will be the same unique value across those two events.
 macro_rules! trace_event {
     ($category:ident, $name:literal, $($arg:expr),+) => {{
-        if($crate::ENABLED_CATEGORIES[$crate::TracedCategories::$category as usize].load(std::sync::atomic::Ordering::Relaxed)) {
+        if($crate::ENABLED_CATEGORIES.is_enabled($crate::TracedCategories::$category as usize)) {
             $crate::trace_event_begin!($category);
             let index = $crate::EVENT_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
             $crate::trace_simple_print!($category,
@@ -176,7 +182,7 @@ macro_rules! trace_event {
         }
     }};
     ($category:ident, $name:expr) => {{
-        if($crate::ENABLED_CATEGORIES[$crate::TracedCategories::$category as usize].load(std::sync::atomic::Ordering::Relaxed)) {
+        if($crate::ENABLED_CATEGORIES.is_enabled($crate::TracedCategories::$category as usize)) {
             $crate::trace_event_begin!($category);
             let index = $crate::EVENT_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
             $crate::trace_simple_print!($category,
@@ -211,17 +217,13 @@ macro_rules! trace_event_begin {
 /// * `category` - Identifier name of the category.
 macro_rules! trace_event_end {
     ($category:ident) => {
-        if ($crate::ENABLED_CATEGORIES[$crate::TracedCategories::$category as usize]
-            .load(std::sync::atomic::Ordering::Relaxed))
-        {
+        if ($crate::ENABLED_CATEGORIES.is_enabled($crate::TracedCategories::$category as usize)) {
             $crate::CATEGORY_COUNTER[$crate::TracedCategories::$category as usize]
                 .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
         }
     };
     ($category_id:expr) => {
-        if ($crate::ENABLED_CATEGORIES[$category_id as usize]
-            .load(std::sync::atomic::Ordering::Relaxed))
-        {
+        if ($crate::ENABLED_CATEGORIES.is_enabled($category_id as usize)) {
             $crate::CATEGORY_COUNTER[$category_id as usize]
                 .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
         }
@@ -236,9 +238,31 @@ setup_trace_marker!(
     (USB, true),
     (gpu_display, true),
     (VirtioBlk, true),
-    (VirtioScsi, true)
+    (VirtioScsi, true),
+    (virtqueue, true),
+    (vcpu, true),
+    (gpu, true)
 );
 
+/// Returns the name of every tracing category and whether it is enabled.
+pub fn categories() -> Vec<(&'static str, bool)> {
+    CATEGORY_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, ENABLED_CATEGORIES.is_enabled(i)))
+        .collect()
+}
+
+/// Enables or disables the tracing category called `name` in this process and in the
+/// processes it forked after `init()`.
+pub fn set_category_enabled(name: &str, enabled: bool) -> anyhow::Result<()> {
+    let Some(index) = CATEGORY_NAMES.iter().position(|c| *c == name) else {
+        bail!("unknown tracing category {}", name);
+    };
+    ENABLED_CATEGORIES.set_enabled(index, enabled);
+    Ok(())
+}
+
 /// Platform-specific implementation of the `trace_simple_print!` macro. If tracing
 /// is enabled on the system, it writes the given message to the `trace_marker` file.
 ///
@@ -269,6 +293,13 @@ pub fn init() {
         return;
     }
 
+    if let Err(e) = ENABLED_CATEGORIES.share() {
+        error!(
+            "{:#}. Tracing categories can only be toggled in this process.",
+            e
+        );
+    }
+
     let path = Path::new("/sys/kernel/tracing/trace_marker");
     let file = match OpenOptions::new().read(false).write(true).open(path) {
         Ok(f) => f,
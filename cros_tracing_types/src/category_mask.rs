@@ -0,0 +1,134 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Runtime switches for trace categories.
+//!
+//! Tracing backends check the mask before emitting an event, which lets a category be turned off
+//! at runtime without rebuilding crosvm. Once shared, the mask lives in memory inherited by the
+//! processes forked afterwards, so that toggling a category in the main process also affects the
+//! sandboxed devices.
+
+use std::ptr::null_mut;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// The maximum number of categories a `CategoryMask` can hold.
+pub const MAX_CATEGORIES: usize = u64::BITS as usize;
+
+/// A set of enabled trace categories, indexed by category number.
+pub struct CategoryMask {
+    /// The mask used until `share` is called.
+    local: AtomicU64,
+    /// The mask in shared memory, or null if the mask is not shared.
+    shared: AtomicPtr<AtomicU64>,
+}
+
+impl CategoryMask {
+    /// Creates a mask with the categories set in `bits` enabled.
+    pub const fn new(bits: u64) -> Self {
+        CategoryMask {
+            local: AtomicU64::new(bits),
+            shared: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    fn mask(&self) -> &AtomicU64 {
+        let shared = self.shared.load(Ordering::Acquire);
+        if shared.is_null() {
+            &self.local
+        } else {
+            // SAFETY: `shared` points to an initialized mapping which is never unmapped.
+            unsafe { &*shared }
+        }
+    }
+
+    /// Returns whether the category at `index` is enabled.
+    #[inline]
+    pub fn is_enabled(&self, index: usize) -> bool {
+        index < MAX_CATEGORIES && self.mask().load(Ordering::Relaxed) & (1 << index) != 0
+    }
+
+    /// Enables or disables the category at `index`.
+    pub fn set_enabled(&self, index: usize, enabled: bool) {
+        if index >= MAX_CATEGORIES {
+            return;
+        }
+        if enabled {
+            self.mask().fetch_or(1 << index, Ordering::Relaxed);
+        } else {
+            self.mask().fetch_and(!(1 << index), Ordering::Relaxed);
+        }
+    }
+
+    /// Moves the mask to shared memory, so that changes made after forking are seen by both the
+    /// parent and the child processes.
+    ///
+    /// This should be called once at process startup, before any child is forked.
+    #[cfg(unix)]
+    pub fn share(&self) -> anyhow::Result<()> {
+        if !self.shared.load(Ordering::Acquire).is_null() {
+            return Ok(());
+        }
+        // SAFETY: A new anonymous mapping is created, no existing memory is affected.
+        let addr = unsafe {
+            libc::mmap(
+                null_mut(),
+                std::mem::size_of::<AtomicU64>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            anyhow::bail!(
+                "failed to map shared category mask: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let shared = addr as *mut AtomicU64;
+        // SAFETY: The mapping is page aligned, large enough for an `AtomicU64` and never
+        // unmapped.
+        unsafe { shared.write(AtomicU64::new(self.local.load(Ordering::Relaxed))) };
+        self.shared.store(shared, Ordering::Release);
+        Ok(())
+    }
+
+    /// Devices are not forked on this platform, so the mask is only used by this process.
+    #[cfg(not(unix))]
+    pub fn share(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_categories() {
+        let mask = CategoryMask::new(0b101);
+        assert!(mask.is_enabled(0));
+        assert!(!mask.is_enabled(1));
+        assert!(mask.is_enabled(2));
+        assert!(!mask.is_enabled(MAX_CATEGORIES));
+
+        mask.set_enabled(0, false);
+        mask.set_enabled(1, true);
+        assert!(!mask.is_enabled(0));
+        assert!(mask.is_enabled(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_mask_keeps_state() {
+        let mask = CategoryMask::new(0b10);
+        mask.share().unwrap();
+        assert!(mask.is_enabled(1));
+        mask.set_enabled(3, true);
+        assert!(mask.is_enabled(3));
+        assert!(!mask.is_enabled(0));
+    }
+}
@@ -6,6 +6,7 @@ use std::time::Duration;
 
 use anyhow::bail;
 
+pub mod category_mask;
 pub mod static_strings;
 
 /// Sets the duration for a trace.
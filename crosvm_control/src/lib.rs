@@ -1132,6 +1132,8 @@ pub struct RegisteredEventFfi(u32);
 pub const REGISTERED_EVENT_VIRTIO_BALLOON_WS_REPORT: RegisteredEventFfi = RegisteredEventFfi(0);
 pub const REGISTERED_EVENT_VIRTIO_BALLOON_RESIZE: RegisteredEventFfi = RegisteredEventFfi(1);
 pub const REGISTERED_EVENT_VIRTIO_BALLOON_OOM_DEFLATION: RegisteredEventFfi = RegisteredEventFfi(2);
+pub const REGISTERED_EVENT_GUEST_PANIC: RegisteredEventFfi = RegisteredEventFfi(3);
+pub const REGISTERED_EVENT_GPU_MEMORY_PRESSURE: RegisteredEventFfi = RegisteredEventFfi(4);
 
 impl TryFrom<RegisteredEventFfi> for RegisteredEvent {
     type Error = &'static str;
@@ -1141,6 +1143,8 @@ impl TryFrom<RegisteredEventFfi> for RegisteredEvent {
             0 => Ok(RegisteredEvent::VirtioBalloonWsReport),
             1 => Ok(RegisteredEvent::VirtioBalloonResize),
             2 => Ok(RegisteredEvent::VirtioBalloonOOMDeflation),
+            3 => Ok(RegisteredEvent::GuestPanic),
+            4 => Ok(RegisteredEvent::GpuMemoryPressure),
             _ => Err("RegisteredEventFFi outside of known RegisteredEvent enum range"),
         }
     }
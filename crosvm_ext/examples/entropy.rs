@@ -0,0 +1,110 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! An entropy device provided to crosvm by an extension.
+//!
+//! Run with the path of the control socket, then start crosvm with `--extension PATH`.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod entropy {
+    use std::fs::File;
+    use std::io::Read;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::thread::JoinHandle;
+
+    use anyhow::anyhow;
+    use anyhow::Context;
+    use crosvm_ext::DeviceQueue;
+    use crosvm_ext::Extension;
+    use crosvm_ext::ExtensionDevice;
+
+    const VIRTIO_ID_RNG: u32 = 4;
+
+    /// Waits for either descriptor to become readable. Returns true if `stop` did.
+    fn wait(queue: &DeviceQueue, stop: &UnixStream) -> anyhow::Result<bool> {
+        let mut fds = [
+            libc::pollfd {
+                fd: queue.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: stop.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // SAFETY: `fds` is a valid array of `pollfd` of the given length.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("poll failed");
+        }
+        Ok(fds[1].revents != 0)
+    }
+
+    fn run_queue(mut queue: DeviceQueue, stop: UnixStream) -> anyhow::Result<DeviceQueue> {
+        let mut urandom = File::open("/dev/urandom")?;
+        while !wait(&queue, &stop)? {
+            queue.wait_kick()?;
+            while let Some(mut request) = queue.pop() {
+                let mut buf = vec![0u8; request.writable_bytes()];
+                urandom.read_exact(&mut buf)?;
+                request.write_all(&buf)?;
+                queue.complete(request);
+            }
+            queue.notify();
+        }
+        Ok(queue)
+    }
+
+    #[derive(Default)]
+    struct Entropy {
+        // The queue worker and the socket whose closing stops it.
+        worker: Option<(JoinHandle<anyhow::Result<DeviceQueue>>, UnixStream)>,
+    }
+
+    impl ExtensionDevice for Entropy {
+        fn device_type(&self) -> u32 {
+            VIRTIO_ID_RNG
+        }
+
+        fn num_queues(&self) -> usize {
+            1
+        }
+
+        fn start_queue(&mut self, _index: usize, queue: DeviceQueue) -> anyhow::Result<()> {
+            let (stop_tx, stop_rx) = UnixStream::pair()?;
+            let handle = thread::spawn(move || run_queue(queue, stop_rx));
+            self.worker = Some((handle, stop_tx));
+            Ok(())
+        }
+
+        fn stop_queue(&mut self, _index: usize) -> anyhow::Result<DeviceQueue> {
+            let (handle, stop_tx) = self.worker.take().context("queue not started")?;
+            drop(stop_tx);
+            handle
+                .join()
+                .map_err(|_| anyhow!("queue worker panicked"))?
+        }
+    }
+
+    pub fn main() -> anyhow::Result<()> {
+        let path = std::env::args()
+            .nth(1)
+            .context("usage: entropy CONTROL_SOCKET")?;
+        let mut extension = Extension::new();
+        extension.add_device(Entropy::default());
+        extension.run(path)
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> anyhow::Result<()> {
+    entropy::main()
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() {}
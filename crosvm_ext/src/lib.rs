@@ -0,0 +1,506 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Helper library for implementing crosvm devices in a separate process.
+//!
+//! An extension is a process providing one or more virtio devices to a crosvm VM. crosvm connects
+//! to the extension's control socket, given with `--extension`, asks for its devices and adds a
+//! vhost-user frontend for each of them. The extension process implements [ExtensionDevice] for
+//! each device and hands them to [Extension::run], which takes care of the control channel and of
+//! the vhost-user protocol. Devices only deal with:
+//!
+//! - [DeviceQueue]s, whose descriptor can be polled for guest notifications (the ioeventfd), which
+//!   yield [Request]s and which notify the guest when requests are completed (the irqfd).
+//! - [GuestMemory], a view of the guest memory shared by crosvm, for devices whose requests refer
+//!   to other guest buffers.
+//! - [VmEvent]s, which notify devices of changes in the VM state such as suspension.
+//!
+//! See `docs/book/src/devices/extensions.md` for the protocol, which can also be implemented
+//! without this library.
+
+#![cfg(any(target_os = "android", target_os = "linux"))]
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::AsRawDescriptor;
+use base::Tube;
+use base::TubeError;
+use base::UnixSeqpacketListener;
+use cros_async::Executor;
+use devices::virtio;
+use devices::virtio::vhost::user::VhostUserConnectionTrait;
+use devices::virtio::vhost::user::VhostUserDevice;
+use devices::virtio::vhost::user::VhostUserStream;
+use devices::virtio::DescriptorChain;
+use devices::virtio::Queue;
+use hypervisor::ProtectionType;
+use snapshot::AnySnapshot;
+use sync::Mutex;
+use vm_control::extension::ExtensionDeviceInfo;
+use vm_control::extension::ExtensionRequest;
+use vm_control::extension::ExtensionResponse;
+pub use vm_control::extension::VmEvent;
+use vm_control::extension::EXTENSION_PROTOCOL_VERSION;
+use vm_memory::GuestAddress;
+use vmm_vhost::message::VhostUserProtocolFeatures;
+use vmm_vhost::VHOST_USER_F_PROTOCOL_FEATURES;
+
+/// A virtio device implemented by an extension.
+///
+/// Queue processing happens on threads or tasks owned by the device: `start_queue` hands over a
+/// queue, which the device gives back in `stop_queue`.
+pub trait ExtensionDevice: Send {
+    /// Virtio device ID, e.g. 4 for an entropy device.
+    fn device_type(&self) -> u32;
+
+    /// Number of queues of the device.
+    fn num_queues(&self) -> usize;
+
+    /// Maximum number of entries in each queue.
+    fn max_queue_size(&self) -> u16 {
+        Queue::MAX_SIZE
+    }
+
+    /// Device-specific feature bits offered to the guest.
+    fn features(&self) -> u64 {
+        0
+    }
+
+    /// Acknowledges the features negotiated with the guest, including the generic ones.
+    fn ack_features(&mut self, _features: u64) {}
+
+    /// Reads the device configuration space at `offset`.
+    fn read_config(&self, _offset: u64, _data: &mut [u8]) {}
+
+    /// Writes `data` to the device configuration space at `offset`.
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {}
+
+    /// Starts processing the requests of queue `index`. Must not block.
+    fn start_queue(&mut self, index: usize, queue: DeviceQueue) -> anyhow::Result<()>;
+
+    /// Stops processing queue `index` and returns the queue given to `start_queue`.
+    fn stop_queue(&mut self, index: usize) -> anyhow::Result<DeviceQueue>;
+
+    /// Resets the device to its initial state. All queues are stopped beforehand.
+    fn reset(&mut self) {}
+
+    /// Notifies the device of a change in the state of the VM.
+    fn vm_event(&mut self, _event: VmEvent) {}
+}
+
+/// The guest memory, as shared by crosvm. All accesses are checked against its regions.
+#[derive(Clone)]
+pub struct GuestMemory(vm_memory::GuestMemory);
+
+impl GuestMemory {
+    /// Reads `buf.len()` bytes at guest physical address `addr`.
+    pub fn read_at(&self, addr: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.0
+            .read_exact_at_addr(buf, GuestAddress(addr))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Writes `buf` at guest physical address `addr`.
+    pub fn write_at(&self, addr: u64, buf: &[u8]) -> io::Result<()> {
+        self.0
+            .write_all_at_addr(buf, GuestAddress(addr))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+/// A request taken from a queue: a descriptor chain with a device-readable part followed by a
+/// device-writable part.
+///
+/// Reading consumes the readable part and writing fills the writable part, in order.
+pub struct Request(DescriptorChain);
+
+impl Request {
+    /// Bytes of the readable part not read yet.
+    pub fn readable_bytes(&self) -> usize {
+        self.0.reader.available_bytes()
+    }
+
+    /// Bytes of the writable part not written yet.
+    pub fn writable_bytes(&self) -> usize {
+        self.0.writer.available_bytes()
+    }
+
+    /// Bytes written so far, reported to the guest on completion.
+    pub fn bytes_written(&self) -> usize {
+        self.0.writer.bytes_written()
+    }
+}
+
+impl Read for Request {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.reader.read(buf)
+    }
+}
+
+impl Write for Request {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A started virtqueue.
+///
+/// The descriptor returned by `as_raw_fd` becomes readable when the guest adds requests to the
+/// queue, and must then be cleared with `wait_kick`.
+pub struct DeviceQueue {
+    queue: Queue,
+    mem: GuestMemory,
+}
+
+impl DeviceQueue {
+    /// Waits for the guest to notify the queue, and clears the notification.
+    pub fn wait_kick(&self) -> io::Result<()> {
+        self.queue.event().wait().map_err(io::Error::from)
+    }
+
+    /// Takes the next request the guest made available, if any.
+    pub fn pop(&mut self) -> Option<Request> {
+        self.queue.pop().map(Request)
+    }
+
+    /// Returns `request` to the guest, with the number of bytes written to it. The guest is only
+    /// interrupted once `notify` is called, so several requests can be completed at once.
+    pub fn complete(&mut self, request: Request) {
+        let written = request.bytes_written() as u32;
+        self.queue.add_used(request.0, written);
+    }
+
+    /// Interrupts the guest if it needs to be told about completed requests.
+    pub fn notify(&mut self) {
+        self.queue.trigger_interrupt();
+    }
+
+    /// The guest memory the queue lives in.
+    pub fn memory(&self) -> &GuestMemory {
+        &self.mem
+    }
+}
+
+impl AsRawFd for DeviceQueue {
+    fn as_raw_fd(&self) -> RawFd {
+        self.queue.event().as_raw_descriptor()
+    }
+}
+
+/// Adapts an `ExtensionDevice` to crosvm's vhost-user backend framework.
+struct VhostUserAdapter {
+    device: Arc<Mutex<dyn ExtensionDevice>>,
+    // Queried once, as it cannot change while the device is connected.
+    num_queues: usize,
+}
+
+impl VhostUserDevice for VhostUserAdapter {
+    fn max_queue_num(&self) -> usize {
+        self.num_queues
+    }
+
+    fn features(&self) -> u64 {
+        virtio::base_features(ProtectionType::Unprotected)
+            | self.device.lock().features()
+            | 1 << VHOST_USER_F_PROTOCOL_FEATURES
+    }
+
+    fn ack_features(&mut self, value: u64) -> anyhow::Result<()> {
+        self.device.lock().ack_features(value);
+        Ok(())
+    }
+
+    fn protocol_features(&self) -> VhostUserProtocolFeatures {
+        VhostUserProtocolFeatures::CONFIG | VhostUserProtocolFeatures::MQ
+    }
+
+    fn read_config(&self, offset: u64, dst: &mut [u8]) {
+        self.device.lock().read_config(offset, dst)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) {
+        self.device.lock().write_config(offset, data)
+    }
+
+    fn start_queue(
+        &mut self,
+        idx: usize,
+        queue: Queue,
+        mem: vm_memory::GuestMemory,
+    ) -> anyhow::Result<()> {
+        let queue = DeviceQueue {
+            queue,
+            mem: GuestMemory(mem),
+        };
+        self.device.lock().start_queue(idx, queue)
+    }
+
+    fn stop_queue(&mut self, idx: usize) -> anyhow::Result<Queue> {
+        Ok(self.device.lock().stop_queue(idx)?.queue)
+    }
+
+    fn reset(&mut self) {
+        self.device.lock().reset()
+    }
+
+    fn enter_suspended_state(&mut self) -> anyhow::Result<()> {
+        // Devices do not process any queue once they were all stopped.
+        Ok(())
+    }
+
+    fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        bail!("extension devices do not support snapshots")
+    }
+
+    fn restore(&mut self, _data: AnySnapshot) -> anyhow::Result<()> {
+        bail!("extension devices do not support snapshots")
+    }
+}
+
+/// Serves the vhost-user protocol for `device` on `socket` until crosvm disconnects.
+fn run_vhost_user(
+    device: Arc<Mutex<dyn ExtensionDevice>>,
+    socket: UnixStream,
+) -> anyhow::Result<()> {
+    let num_queues = device.lock().num_queues();
+    let backend = VhostUserAdapter { device, num_queues };
+    let ex = Executor::new().context("failed to create executor")?;
+    ex.run_until(VhostUserStream::from(socket).run_backend(backend, &ex))?
+}
+
+/// A set of devices served to crosvm over a control socket.
+#[derive(Default)]
+pub struct Extension {
+    devices: Vec<Arc<Mutex<dyn ExtensionDevice>>>,
+}
+
+impl Extension {
+    /// Creates an extension without devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `device` to the devices provided to crosvm.
+    pub fn add_device(&mut self, device: impl ExtensionDevice + 'static) -> &mut Self {
+        self.devices.push(Arc::new(Mutex::new(device)));
+        self
+    }
+
+    /// Listens on `control_socket` for crosvm, provides the devices to it and serves them until
+    /// the VM exits.
+    pub fn run<P: AsRef<Path>>(self, control_socket: P) -> anyhow::Result<()> {
+        let listener = UnixSeqpacketListener::bind(control_socket.as_ref()).with_context(|| {
+            format!(
+                "failed to bind control socket {}",
+                control_socket.as_ref().display()
+            )
+        })?;
+        let tube = Tube::try_from(listener.accept().context("failed to accept crosvm")?)
+            .context("failed to create control tube")?;
+        self.serve(tube)
+    }
+
+    /// Provides the devices to crosvm over `tube` and serves them until the VM exits.
+    pub fn serve(self, tube: Tube) -> anyhow::Result<()> {
+        match tube
+            .recv::<ExtensionRequest>()
+            .context("failed to receive hello")?
+        {
+            ExtensionRequest::Hello { version } if version == EXTENSION_PROTOCOL_VERSION => {}
+            ExtensionRequest::Hello { version } => {
+                let msg = format!("unsupported extension protocol version {}", version);
+                tube.send(&ExtensionResponse::Err(msg.clone()))?;
+                bail!(msg);
+            }
+            request => bail!("unexpected request {:?}", request),
+        }
+
+        let mut infos = Vec::new();
+        for device in &self.devices {
+            let (backend_socket, frontend_socket) =
+                UnixStream::pair().context("failed to create vhost-user socket")?;
+            let (virtio_type, max_queue_size) = {
+                let device = device.lock();
+                (device.device_type(), device.max_queue_size())
+            };
+            infos.push(ExtensionDeviceInfo {
+                virtio_type,
+                max_queue_size: Some(max_queue_size),
+                socket: frontend_socket,
+            });
+            let device = device.clone();
+            thread::Builder::new()
+                .name(format!("ext_dev_{}", virtio_type))
+                .spawn(move || {
+                    if let Err(e) = run_vhost_user(device, backend_socket) {
+                        error!("extension device failed: {:#}", e);
+                    }
+                })
+                .context("failed to spawn device thread")?;
+        }
+        tube.send(&ExtensionResponse::Devices(infos))
+            .context("failed to send devices")?;
+
+        loop {
+            let event = match tube.recv::<ExtensionRequest>() {
+                Ok(ExtensionRequest::Event(event)) => event,
+                Ok(request) => bail!("unexpected request {:?}", request),
+                Err(TubeError::Disconnected) => return Ok(()),
+                Err(e) => return Err(e).context("failed to receive event"),
+            };
+            for device in &self.devices {
+                device.lock().vm_event(event);
+            }
+            if event == VmEvent::Exiting {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::JoinHandle;
+
+    use vmm_vhost::BackendClient;
+    use vmm_vhost::Connection;
+
+    use super::*;
+
+    const TEST_FEATURES: u64 = 1 << 3;
+
+    struct TestDevice {
+        events: Arc<Mutex<Vec<VmEvent>>>,
+    }
+
+    impl ExtensionDevice for TestDevice {
+        fn device_type(&self) -> u32 {
+            4
+        }
+
+        fn num_queues(&self) -> usize {
+            1
+        }
+
+        fn max_queue_size(&self) -> u16 {
+            64
+        }
+
+        fn features(&self) -> u64 {
+            TEST_FEATURES
+        }
+
+        fn start_queue(&mut self, _index: usize, _queue: DeviceQueue) -> anyhow::Result<()> {
+            bail!("unexpected start_queue")
+        }
+
+        fn stop_queue(&mut self, _index: usize) -> anyhow::Result<DeviceQueue> {
+            bail!("unexpected stop_queue")
+        }
+
+        fn vm_event(&mut self, event: VmEvent) {
+            self.events.lock().push(event);
+        }
+    }
+
+    /// Serves a `TestDevice` on a new tube, returns crosvm's end of the tube and the events
+    /// received by the device.
+    fn serve_test_device() -> (
+        Tube,
+        Arc<Mutex<Vec<VmEvent>>>,
+        JoinHandle<anyhow::Result<()>>,
+    ) {
+        let (crosvm, extension) = Tube::pair().unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut ext = Extension::new();
+        ext.add_device(TestDevice {
+            events: events.clone(),
+        });
+        let handle = thread::spawn(move || ext.serve(extension));
+        (crosvm, events, handle)
+    }
+
+    fn hello(crosvm: &Tube, version: u32) -> ExtensionResponse {
+        crosvm.send(&ExtensionRequest::Hello { version }).unwrap();
+        crosvm.recv().unwrap()
+    }
+
+    #[test]
+    fn serve_devices_and_events() {
+        let (crosvm, events, handle) = serve_test_device();
+
+        let mut devices = match hello(&crosvm, EXTENSION_PROTOCOL_VERSION) {
+            ExtensionResponse::Devices(devices) => devices,
+            response => panic!("unexpected response {:?}", response),
+        };
+        assert_eq!(devices.len(), 1);
+        let info = devices.remove(0);
+        assert_eq!(info.virtio_type, 4);
+        assert_eq!(info.max_queue_size, Some(64));
+
+        // The device is served as a vhost-user backend on the socket.
+        let mut backend = BackendClient::new(Connection::try_from(info.socket).unwrap());
+        let features = backend.get_features().unwrap();
+        assert_eq!(features & TEST_FEATURES, TEST_FEATURES);
+        assert_ne!(features & (1 << VHOST_USER_F_PROTOCOL_FEATURES), 0);
+
+        crosvm
+            .send(&ExtensionRequest::Event(VmEvent::Suspended))
+            .unwrap();
+        crosvm
+            .send(&ExtensionRequest::Event(VmEvent::Exiting))
+            .unwrap();
+        handle.join().unwrap().unwrap();
+        assert_eq!(*events.lock(), vec![VmEvent::Suspended, VmEvent::Exiting]);
+    }
+
+    #[test]
+    fn serve_version_mismatch() {
+        let (crosvm, _events, handle) = serve_test_device();
+
+        assert!(matches!(
+            hello(&crosvm, EXTENSION_PROTOCOL_VERSION + 1),
+            ExtensionResponse::Err(_)
+        ));
+        assert!(handle.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn serve_unexpected_request() {
+        let (crosvm, _events, handle) = serve_test_device();
+
+        crosvm
+            .send(&ExtensionRequest::Event(VmEvent::Running))
+            .unwrap();
+        assert!(handle.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn serve_crosvm_disconnect() {
+        let (crosvm, events, handle) = serve_test_device();
+
+        assert!(matches!(
+            hello(&crosvm, EXTENSION_PROTOCOL_VERSION),
+            ExtensionResponse::Devices(_)
+        ));
+        drop(crosvm);
+        handle.join().unwrap().unwrap();
+        assert!(events.lock().is_empty());
+    }
+}
@@ -133,22 +133,33 @@ pub struct ACPIPMResource {
     pci: Arc<Mutex<PciResource>>,
     #[serde(skip_serializing)]
     acdc: Option<Arc<Mutex<AcAdapter>>>,
+    // Whether S3 (suspend-to-RAM) is advertised to the guest.
+    #[serde(skip_serializing)]
+    s3_enabled: bool,
+    // Set when the guest entered S3, until its vCPUs are sent back to the waking vector.
+    s3_suspended: bool,
 }
 
 #[derive(Deserialize)]
 struct ACPIPMResrourceSerializable {
     pm1: Pm1ResourceSerializable,
     gpe0: GpeResourceSerializable,
+    #[serde(default)]
+    s3_suspended: bool,
 }
 
 impl ACPIPMResource {
     /// Constructs ACPI Power Management Resouce.
+    ///
+    /// When `s3` is set, the `\_S3` sleep state is advertised and entering it is reported through
+    /// `PmResource::is_s3_suspended`.
     #[allow(dead_code)]
     pub fn new(
         sci_evt: IrqLevelEvent,
         suspend_tube: Arc<Mutex<SendTube>>,
         exit_evt_wrtube: SendTube,
         acdc: Option<Arc<Mutex<AcAdapter>>>,
+        s3: bool,
     ) -> ACPIPMResource {
         let pm1 = Pm1Resource {
             status: 0,
@@ -177,6 +188,8 @@ impl ACPIPMResource {
             gpe0: Arc::new(Mutex::new(gpe0)),
             pci: Arc::new(Mutex::new(pci)),
             acdc,
+            s3_enabled: s3,
+            s3_suspended: false,
         }
     }
 
@@ -219,6 +232,7 @@ impl Suspendable for ACPIPMResource {
             gpe0.status = acpi_snapshot.gpe0.status;
             gpe0.enable = acpi_snapshot.gpe0.enable;
         }
+        self.s3_suspended = acpi_snapshot.s3_suspended;
         Ok(())
     }
 
@@ -409,6 +423,7 @@ const BITMASK_PM1CNT_WAKE_STATUS: u16 = 0x8000;
 
 const BITMASK_PM1CNT_SLEEP_TYPE: u16 = 0x1C00;
 const SLEEP_TYPE_S1: u16 = 1 << 10;
+const SLEEP_TYPE_S3: u16 = 3 << 10;
 const SLEEP_TYPE_S5: u16 = 0 << 10;
 
 impl ACPIPMFixedEvent {
@@ -506,6 +521,14 @@ impl PmResource for ACPIPMResource {
         }
     }
 
+    fn is_s3_suspended(&self) -> bool {
+        self.s3_suspended
+    }
+
+    fn s3_resume(&mut self) {
+        self.s3_suspended = false;
+    }
+
     fn register_pme_notify_dev(&mut self, bus: u8, notify_dev: Arc<Mutex<dyn PmeNotify>>) {
         let mut pci = self.pci.lock();
         match pci.pme_notify.get_mut(&bus) {
@@ -663,6 +686,14 @@ impl BusDevice for ACPIPMResource {
                                 error!("ACPIPM: failed to trigger suspend event: {}", e);
                             }
                         }
+                        SLEEP_TYPE_S3 if self.s3_enabled => {
+                            // The vCPUs are parked like for S1, but will resume at the waking
+                            // vector instead of after this write.
+                            self.s3_suspended = true;
+                            if let Err(e) = self.suspend_tube.lock().send(&true) {
+                                error!("ACPIPM: failed to trigger suspend event: {}", e);
+                            }
+                        }
                         SLEEP_TYPE_S5 => {
                             if let Err(e) =
                                 self.exit_evt_wrtube.send::<VmEventType>(&VmEventType::Exit)
@@ -732,6 +763,16 @@ impl Aml for ACPIPMResource {
         )
         .to_aml_bytes(bytes);
 
+        // S3
+        if self.s3_enabled {
+            let slp_typ = (SLEEP_TYPE_S3 >> 10) as u8;
+            aml::Name::new(
+                "_S3_".into(),
+                &aml::Package::new(vec![&slp_typ, &slp_typ, &aml::ZERO, &aml::ZERO]),
+            )
+            .to_aml_bytes(bytes);
+        }
+
         // S5
         aml::Name::new(
             "_S5_".into(),
@@ -810,7 +851,37 @@ mod tests {
             Arc::new(Mutex::new(get_send_tube())),
             get_send_tube(),
             None,
+            false,
         ),
         modify_device
     );
+
+    #[test]
+    fn s3_sleep_request() {
+        let (suspend_send, suspend_recv) = Tube::directional_pair().unwrap();
+        let mut acpi = ACPIPMResource::new(
+            get_irq_evt(),
+            Arc::new(Mutex::new(suspend_send)),
+            get_send_tube(),
+            None,
+            true,
+        );
+
+        let mut aml = Vec::new();
+        acpi.to_aml_bytes(&mut aml);
+        assert!(aml.windows(4).any(|w| w == b"_S3_"));
+
+        let info = BusAccessInfo {
+            offset: PM1_CONTROL.into(),
+            address: 0,
+            id: 0,
+        };
+        let val = SLEEP_TYPE_S3 | BITMASK_PM1CNT_SLEEP_ENABLE;
+        acpi.write(info, &val.to_ne_bytes());
+
+        assert!(acpi.is_s3_suspended());
+        assert!(suspend_recv.recv::<bool>().unwrap());
+        acpi.s3_resume();
+        assert!(!acpi.is_s3_suspended());
+    }
 }
@@ -20,12 +20,17 @@ use base::debug;
 use base::error;
 use base::Event;
 use base::SharedMemory;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
 use remain::sorted;
 use serde::Deserialize;
 use serde::Serialize;
 use snapshot::AnySnapshot;
 use sync::Mutex;
 use thiserror::Error;
+use vm_control::DeviceDetails;
+use vm_control::DeviceInfo;
+use vm_control::DeviceRange;
 
 #[cfg(feature = "stats")]
 use crate::bus_stats::BusOperation;
@@ -37,6 +42,7 @@ use crate::PciDevice;
 use crate::Suspendable;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 use crate::VfioPlatformDevice;
+use crate::VirtioMmioDevice;
 
 /// Information about how a device was accessed.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -93,6 +99,11 @@ pub trait BusDevice: Send + Suspendable {
     fn debug_label(&self) -> String;
     /// Returns a unique id per device type suitable for metrics gathering.
     fn device_id(&self) -> DeviceId;
+    /// Returns the id of the emulated device type. This differs from `device_id` for devices
+    /// wrapping another device, such as `ProxyDevice`.
+    fn device_type(&self) -> DeviceId {
+        self.device_id()
+    }
     /// Reads at `offset` from this device
     fn read(&mut self, offset: BusAccessInfo, data: &mut [u8]) {}
     /// Writes at `offset` into this device
@@ -143,6 +154,17 @@ pub trait BusDevice: Send + Suspendable {
     /// Invoked when the device is sandboxed.
     fn on_sandboxed(&mut self) {}
 
+    /// Returns the host paths the device needs to open once it runs in a sandboxed process.
+    ///
+    /// When this returns a list, the device process is confined with Landlock to exactly those
+    /// paths before the device is notified that it is sandboxed. Descriptors the device already
+    /// holds keep working. `None`, the default, leaves the filesystem access of the process to the
+    /// jail alone.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        None
+    }
+
     /// Gets a list of all ranges registered by this BusDevice.
     fn get_ranges(&self) -> Vec<(BusRange, BusType)> {
         Vec::new()
@@ -155,6 +177,11 @@ pub trait BusDevice: Send + Suspendable {
     fn is_bridge(&self) -> Option<u8> {
         None
     }
+
+    /// Returns details about the device for introspection.
+    fn device_details(&self) -> DeviceDetails {
+        DeviceDetails::default()
+    }
 }
 
 pub trait BusDeviceSync: BusDevice + Sync {
@@ -269,6 +296,15 @@ pub trait BusDeviceObj {
     fn into_platform_device(self: Box<Self>) -> Option<Box<VfioPlatformDevice>> {
         None
     }
+    fn as_virtio_mmio_device(&self) -> Option<&VirtioMmioDevice> {
+        None
+    }
+    fn as_virtio_mmio_device_mut(&mut self) -> Option<&mut VirtioMmioDevice> {
+        None
+    }
+    fn into_virtio_mmio_device(self: Box<Self>) -> Option<Box<VirtioMmioDevice>> {
+        None
+    }
 }
 
 #[sorted]
@@ -769,6 +805,58 @@ impl Bus {
     }
 }
 
+/// Describes the devices attached to `buses`.
+///
+/// A device occupying several ranges, possibly on different buses, is reported once with all of
+/// its ranges.
+pub fn query_devices(buses: &[&Bus]) -> Vec<DeviceInfo> {
+    let mut infos: Vec<DeviceInfo> = Vec::new();
+    let mut indices = BTreeMap::new();
+    for bus in buses {
+        let bus_name = match bus.bus_type {
+            BusType::Mmio => "mmio",
+            BusType::Io => "io",
+        };
+        // Clone the entries so that the bus is not locked while querying proxied devices.
+        let entries: Vec<(BusRange, BusDeviceEntry)> = bus
+            .devices
+            .lock()
+            .iter()
+            .map(|(range, entry)| (*range, entry.device.clone()))
+            .collect();
+        for (range, device) in entries {
+            let ptr = match &device {
+                BusDeviceEntry::OuterSync(dev) => Arc::as_ptr(dev) as *const u8,
+                BusDeviceEntry::InnerSync(dev) => Arc::as_ptr(dev) as *const u8,
+            };
+            let index = *indices.entry(ptr).or_insert_with(|| {
+                let (label, device_type, details) = match &device {
+                    BusDeviceEntry::OuterSync(dev) => {
+                        let dev = dev.lock();
+                        (dev.debug_label(), dev.device_type(), dev.device_details())
+                    }
+                    BusDeviceEntry::InnerSync(dev) => {
+                        (dev.debug_label(), dev.device_type(), dev.device_details())
+                    }
+                };
+                infos.push(DeviceInfo {
+                    label,
+                    device_type: device_type.to_string(),
+                    ranges: Vec::new(),
+                    details,
+                });
+                infos.len() - 1
+            });
+            infos[index].ranges.push(DeviceRange {
+                bus: bus_name.to_owned(),
+                base: range.base,
+                len: range.len,
+            });
+        }
+    }
+    infos
+}
+
 impl Default for Bus {
     fn default() -> Self {
         Self::new(BusType::Io)
@@ -892,6 +980,55 @@ mod tests {
         assert!(bus.insert(dummy, 0x0, 0x10).is_ok());
     }
 
+    #[test]
+    fn bus_query_devices() {
+        let io_bus = Bus::new(BusType::Io);
+        let mmio_bus = Bus::new(BusType::Mmio);
+        let dummy = Arc::new(Mutex::new(DummyDevice));
+        let constant = Arc::new(Mutex::new(ConstantDevice {
+            uses_full_addr: false,
+        }));
+        mmio_bus.insert(dummy.clone(), 0x1000, 0x10).unwrap();
+        io_bus.insert(dummy.clone(), 0x10, 0x10).unwrap();
+        io_bus.insert(dummy, 0x30, 0x8).unwrap();
+        io_bus.insert(constant, 0x20, 0x10).unwrap();
+
+        let devices = query_devices(&[&mmio_bus, &io_bus]);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].label, "dummy device");
+        assert_eq!(devices[0].device_type, "Cmos");
+        assert_eq!(
+            devices[0].ranges,
+            vec![
+                DeviceRange {
+                    bus: "mmio".to_owned(),
+                    base: 0x1000,
+                    len: 0x10,
+                },
+                DeviceRange {
+                    bus: "io".to_owned(),
+                    base: 0x10,
+                    len: 0x10,
+                },
+                DeviceRange {
+                    bus: "io".to_owned(),
+                    base: 0x30,
+                    len: 0x8,
+                },
+            ]
+        );
+        assert_eq!(devices[0].details, DeviceDetails::default());
+        assert_eq!(devices[1].label, "constant device");
+        assert_eq!(
+            devices[1].ranges,
+            vec![DeviceRange {
+                bus: "io".to_owned(),
+                base: 0x20,
+                len: 0x10,
+            }]
+        );
+    }
+
     #[test]
     fn bus_insert_full_addr() {
         let bus = Bus::new(BusType::Io);
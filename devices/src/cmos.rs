@@ -3,6 +3,8 @@
 // found in the LICENSE file.
 
 use std::cmp::min;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
@@ -22,6 +24,7 @@ use base::WaitContext;
 use base::WorkerThread;
 use chrono::DateTime;
 use chrono::Datelike;
+use chrono::TimeDelta;
 use chrono::TimeZone;
 use chrono::Timelike;
 use chrono::Utc;
@@ -29,6 +32,7 @@ use metrics::log_metric;
 use metrics::MetricEventType;
 use serde::Deserialize;
 use serde::Serialize;
+use serde::Serializer;
 use snapshot::AnySnapshot;
 use sync::Mutex;
 use vm_control::VmResponse;
@@ -75,6 +79,38 @@ const RTC_REG_D_VRT: u8 = 0x80; // RAM and time valid
 
 pub type CmosNowFn = fn() -> DateTime<Utc>;
 
+/// Offset in seconds of the time reported by the RTC from the host time. It is shared between the
+/// CMOS device and the VM control loop, which can read and change it at runtime.
+#[derive(Clone, Debug, Default)]
+pub struct RtcOffset(Arc<AtomicI64>);
+
+impl RtcOffset {
+    pub fn new(secs: i64) -> RtcOffset {
+        RtcOffset(Arc::new(AtomicI64::new(secs)))
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, secs: i64) {
+        self.0.store(secs, Ordering::Relaxed)
+    }
+}
+
+impl Serialize for RtcOffset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.get())
+    }
+}
+
+/// Returns the RTC offset that makes the RTC start at `base`, an RFC 3339 date such as
+/// `2000-01-01T00:00:00Z`.
+pub fn rtc_offset_from_base(base: &str) -> anyhow::Result<i64> {
+    let base = DateTime::parse_from_rfc3339(base).context("invalid RTC base time")?;
+    Ok(base.timestamp() - Utc::now().timestamp())
+}
+
 // Alarm state shared between Cmos and the alarm worker thread.
 struct AlarmState {
     alarm: Timer,
@@ -121,6 +157,9 @@ pub struct Cmos {
     data: [u8; DATA_LEN],
     #[serde(skip_serializing)] // skip serializing time function.
     now_fn: CmosNowFn,
+    // The offset is part of the snapshot so that the guest doesn't see its clock jump when it is
+    // restored.
+    offset: RtcOffset,
     // alarm_time is re-loaded from data on deserialization, so there's
     // no need to explicitly serialize it.
     #[serde(skip_serializing)]
@@ -138,10 +177,12 @@ impl Cmos {
     /// `mem_below_4g` is the size of memory in bytes below the 32-bit gap.
     /// `mem_above_4g` is the size of memory in bytes above the 32-bit gap.
     /// `now_fn` is a function that returns the current date and time.
+    /// `offset` is added to the time returned by `now_fn` to get the time reported to the guest.
     pub fn new(
         mem_below_4g: u64,
         mem_above_4g: u64,
         now_fn: CmosNowFn,
+        offset: RtcOffset,
         vm_control: Tube,
         irq: IrqEdgeEvent,
     ) -> anyhow::Result<Cmos> {
@@ -167,6 +208,7 @@ impl Cmos {
             index: 0,
             data,
             now_fn,
+            offset,
             alarm_time: None,
             alarm_state: Arc::new(Mutex::new(AlarmState {
                 alarm: Timer::new().context("cmos timer")?,
@@ -180,6 +222,11 @@ impl Cmos {
         })
     }
 
+    /// Returns the current date and time as seen by the guest.
+    fn now(&self) -> DateTime<Utc> {
+        (self.now_fn)() + TimeDelta::seconds(self.offset.get())
+    }
+
     fn spawn_worker(&mut self, alarm_state: Arc<Mutex<AlarmState>>) {
         self.worker = Some(WorkerThread::start("CMOS_alarm", move |kill_evt| {
             if let Err(e) = run_cmos_worker(alarm_state, kill_evt) {
@@ -191,7 +238,7 @@ impl Cmos {
     fn set_alarm(&mut self) {
         let mut state = self.alarm_state.lock();
         if self.data[RTC_REG_B as usize] & RTC_REG_B_ALARM_ENABLE != 0 {
-            let now = (self.now_fn)();
+            let now = self.now();
             let target = alarm_from_registers(now.year(), &self.data).and_then(|this_year| {
                 // There is no year register for the alarm. If the alarm target has
                 // already passed this year, then the next time it will occur is next
@@ -361,7 +408,7 @@ impl BusDevice for Cmos {
         data[0] = match info.offset {
             INDEX_OFFSET => self.index,
             DATA_OFFSET => {
-                let now = (self.now_fn)();
+                let now = self.now();
                 let seconds = now.second(); // 0..=59
                 let minutes = now.minute(); // 0..=59
                 let hours = now.hour(); // 0..=23 (24-hour mode only)
@@ -411,11 +458,15 @@ impl Suspendable for Cmos {
             index: u8,
             #[serde(deserialize_with = "deserialize_seq_to_arr")]
             data: [u8; DATA_LEN],
+            // Snapshots taken before the offset was configurable don't have it.
+            #[serde(default)]
+            offset: i64,
         }
 
         let deser: CmosIndex = AnySnapshot::from_any(data).context("failed to deserialize Cmos")?;
         self.index = deser.index;
         self.data = deser.data;
+        self.offset.set(deser.offset);
         self.set_alarm();
 
         Ok(())
@@ -514,7 +565,15 @@ mod tests {
 
     fn new_cmos_for_test(now_fn: CmosNowFn) -> Cmos {
         let irq = IrqEdgeEvent::new().unwrap();
-        Cmos::new(1024, 0, now_fn, Tube::pair().unwrap().0, irq).unwrap()
+        Cmos::new(
+            1024,
+            0,
+            now_fn,
+            RtcOffset::default(),
+            Tube::pair().unwrap().0,
+            irq,
+        )
+        .unwrap()
     }
 
     #[test]
@@ -711,12 +770,57 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn cmos_restore_reads_host_time() -> anyhow::Result<()> {
+        let mut cmos = new_cmos_for_test(test_now_party_like_its_1999);
+        let snap = cmos.snapshot().context("failed to snapshot Cmos")?;
+
+        // The time is not part of the snapshot, so the restored RTC is current.
+        let mut restored = new_cmos_for_test(test_now_y2k_compliant);
+        restored.restore(snap).context("failed to restore Cmos")?;
+        assert_eq!(read_reg(&mut restored, 0x00), 0x00); // seconds
+        assert_eq!(read_reg(&mut restored, 0x09), 0x00); // year
+        assert_eq!(read_reg(&mut restored, 0x32), 0x20); // century
+        Ok(())
+    }
+
+    #[test]
+    fn cmos_offset() {
+        let mut cmos = new_cmos_for_test(test_now_party_like_its_1999);
+        // One second later is the year 2000.
+        cmos.offset.set(1);
+        assert_eq!(read_reg(&mut cmos, 0x00), 0x00); // seconds
+        assert_eq!(read_reg(&mut cmos, 0x09), 0x00); // year
+        assert_eq!(read_reg(&mut cmos, 0x32), 0x20); // century
+    }
+
+    #[test]
+    fn cmos_restore_keeps_offset() -> anyhow::Result<()> {
+        let mut cmos = new_cmos_for_test(test_now_party_like_its_1999);
+        cmos.offset.set(-86400);
+        let snap = cmos.snapshot().context("failed to snapshot Cmos")?;
+
+        let mut restored = new_cmos_for_test(test_now_party_like_its_1999);
+        restored.restore(snap).context("failed to restore Cmos")?;
+        assert_eq!(restored.offset.get(), -86400);
+        assert_eq!(read_reg(&mut restored, 0x07), 0x30); // day of month
+        Ok(())
+    }
+
     #[test]
     fn cmos_sleep_wake() {
         // 2000-01-02T03:04:05+00:00
         let irq = IrqEdgeEvent::new().unwrap();
         let now_fn = || timestamp_to_datetime(946782245);
-        let mut cmos = Cmos::new(1024, 0, now_fn, Tube::pair().unwrap().0, irq).unwrap();
+        let mut cmos = Cmos::new(
+            1024,
+            0,
+            now_fn,
+            RtcOffset::default(),
+            Tube::pair().unwrap().0,
+            irq,
+        )
+        .unwrap();
 
         // A date later this year
         write_reg(&mut cmos, 0x01, 0x06); // seconds
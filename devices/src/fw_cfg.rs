@@ -41,6 +41,19 @@ const FW_CFG_REVISION: [u8; 4] = [0, 0, 0, 1];
 const FW_CFG_SIGNATURE_SELECTOR: u16 = 0x0000;
 const FW_CFG_REVISION_SELECTOR: u16 = 0x0001;
 const FW_CFG_FILE_DIR_SELECTOR: u16 = 0x0019;
+// Well-known items from which firmware (e.g. OVMF's QemuKernelLoaderFsDxe) loads a kernel.
+const FW_CFG_KERNEL_SIZE_SELECTOR: u16 = 0x0008;
+const FW_CFG_INITRD_SIZE_SELECTOR: u16 = 0x000b;
+const FW_CFG_KERNEL_DATA_SELECTOR: u16 = 0x0011;
+const FW_CFG_INITRD_DATA_SELECTOR: u16 = 0x0012;
+const FW_CFG_CMDLINE_SIZE_SELECTOR: u16 = 0x0014;
+const FW_CFG_CMDLINE_DATA_SELECTOR: u16 = 0x0015;
+const FW_CFG_SETUP_SIZE_SELECTOR: u16 = 0x0017;
+const FW_CFG_SETUP_DATA_SELECTOR: u16 = 0x0018;
+// Offsets in the header of an x86 bzImage.
+const BZIMAGE_SETUP_SECTS_OFFSET: usize = 0x1f1;
+const BZIMAGE_HEADER_MAGIC_OFFSET: usize = 0x202;
+const BZIMAGE_HEADER_MAGIC: &[u8; 4] = b"HdrS";
 // Code that uses fw_cfg expects to read a char[56] for filenames
 const FW_CFG_FILENAME_SIZE: usize = 56;
 
@@ -70,6 +83,14 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// A kernel for the firmware to boot directly instead of loading a boot loader from a disk.
+#[derive(Clone, Debug, Default)]
+pub struct FwCfgKernel {
+    pub kernel: Vec<u8>,
+    pub initrd: Vec<u8>,
+    pub cmdline: String,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, FromKeyValues, PartialEq, Eq)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct FwCfgParameters {
@@ -233,6 +254,68 @@ impl FwCfgDevice {
         Ok(())
     }
 
+    /// Exposes a kernel, initrd and command line through the well-known fw_cfg items firmware
+    /// reads them from.
+    ///
+    /// The real-mode setup code of an x86 bzImage is exposed separately from the protected-mode
+    /// kernel, as QEMU does.
+    pub fn add_kernel(&mut self, kernel: FwCfgKernel) -> Result<()> {
+        let FwCfgKernel {
+            mut kernel,
+            initrd,
+            cmdline,
+        } = kernel;
+
+        let setup = match bzimage_setup_size(&kernel) {
+            Some(setup_size) => {
+                let protected_mode = kernel.split_off(setup_size);
+                std::mem::replace(&mut kernel, protected_mode)
+            }
+            None => Vec::new(),
+        };
+
+        // The command line is a NUL-terminated string.
+        let mut cmdline = cmdline.into_bytes();
+        cmdline.push(0);
+
+        for (size_selector, data_selector, data) in [
+            (
+                FW_CFG_SETUP_SIZE_SELECTOR,
+                FW_CFG_SETUP_DATA_SELECTOR,
+                setup,
+            ),
+            (
+                FW_CFG_KERNEL_SIZE_SELECTOR,
+                FW_CFG_KERNEL_DATA_SELECTOR,
+                kernel,
+            ),
+            (
+                FW_CFG_INITRD_SIZE_SELECTOR,
+                FW_CFG_INITRD_DATA_SELECTOR,
+                initrd,
+            ),
+            (
+                FW_CFG_CMDLINE_SIZE_SELECTOR,
+                FW_CFG_CMDLINE_DATA_SELECTOR,
+                cmdline,
+            ),
+        ] {
+            let size: u32 = data.len().try_into().map_err(|_| Error::SizeOverflow)?;
+            // Unlike the file directory, these items are little-endian.
+            self.set_item(size_selector, size.to_le_bytes().to_vec());
+            self.set_item(data_selector, data);
+        }
+
+        Ok(())
+    }
+
+    fn set_item(&mut self, selector: u16, data: Vec<u8>) {
+        self.entries[FwCfgItemType::GenericItem.value()][selector as usize] = FwCfgEntry {
+            allow_write: false,
+            data,
+        };
+    }
+
     fn add_bytes(&mut self, data: Vec<u8>, item_type: FwCfgItemType) {
         // Add a FwCfgEntry to FwCfgDevice's entries array
 
@@ -280,6 +363,22 @@ impl FwCfgDevice {
     }
 }
 
+// Returns the size of the real-mode setup code at the start of `kernel` if it is an x86 bzImage.
+fn bzimage_setup_size(kernel: &[u8]) -> Option<usize> {
+    let magic = kernel.get(BZIMAGE_HEADER_MAGIC_OFFSET..BZIMAGE_HEADER_MAGIC_OFFSET + 4)?;
+    if magic != BZIMAGE_HEADER_MAGIC {
+        return None;
+    }
+    // A setup_sects of 0 means 4 for compatibility with old kernels. The boot sector comes on top
+    // of the setup sectors.
+    let setup_sects = match *kernel.get(BZIMAGE_SETUP_SECTS_OFFSET)? {
+        0 => 4,
+        n => n as usize,
+    };
+    let setup_size = (setup_sects + 1) * 512;
+    (setup_size <= kernel.len()).then_some(setup_size)
+}
+
 // We implement two 8-bit registers: a Selector(Control) Register and a Data Register
 impl BusDevice for FwCfgDevice {
     fn device_id(&self) -> DeviceId {
@@ -816,4 +915,81 @@ mod tests {
 
         assert_read_entries(&FILENAMES, &mut device, bai);
     }
+
+    #[test]
+    // Read a kernel, initrd and command line from the well-known items.
+    fn read_kernel() {
+        let mut device = FwCfgDevice::new(1, default_params()).unwrap();
+        device
+            .add_kernel(FwCfgKernel {
+                kernel: vec![MAGIC_BYTE; 4],
+                initrd: vec![MAGIC_BYTE_ALT; 2],
+                cmdline: "console=ttyS0".to_owned(),
+            })
+            .unwrap();
+        let bai = BusAccessInfo {
+            offset: FW_CFG_SELECTOR_PORT_OFFSET,
+            address: FW_CFG_BASE_PORT,
+            id: 0,
+        };
+
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_SETUP_SIZE_SELECTOR),
+            0u32.to_le_bytes()
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_KERNEL_SIZE_SELECTOR),
+            4u32.to_le_bytes()
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_KERNEL_DATA_SELECTOR),
+            vec![MAGIC_BYTE; 4]
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_INITRD_SIZE_SELECTOR),
+            2u32.to_le_bytes()
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 2, FW_CFG_INITRD_DATA_SELECTOR),
+            vec![MAGIC_BYTE_ALT; 2]
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_CMDLINE_SIZE_SELECTOR),
+            14u32.to_le_bytes()
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 14, FW_CFG_CMDLINE_DATA_SELECTOR),
+            b"console=ttyS0\0"
+        );
+    }
+
+    #[test]
+    // The setup code of a bzImage is split from the rest of the kernel.
+    fn read_bzimage_kernel() {
+        let mut kernel = vec![MAGIC_BYTE; 8 * 512];
+        kernel[BZIMAGE_SETUP_SECTS_OFFSET] = 2;
+        kernel[BZIMAGE_HEADER_MAGIC_OFFSET..BZIMAGE_HEADER_MAGIC_OFFSET + 4]
+            .copy_from_slice(BZIMAGE_HEADER_MAGIC);
+        let mut device = FwCfgDevice::new(1, default_params()).unwrap();
+        device
+            .add_kernel(FwCfgKernel {
+                kernel,
+                ..Default::default()
+            })
+            .unwrap();
+        let bai = BusAccessInfo {
+            offset: FW_CFG_SELECTOR_PORT_OFFSET,
+            address: FW_CFG_BASE_PORT,
+            id: 0,
+        };
+
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_SETUP_SIZE_SELECTOR),
+            (3u32 * 512).to_le_bytes()
+        );
+        assert_eq!(
+            get_entry(&mut device, bai, 4, FW_CFG_KERNEL_SIZE_SELECTOR),
+            (5u32 * 512).to_le_bytes()
+        );
+    }
 }
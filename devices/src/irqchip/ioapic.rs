@@ -280,6 +280,16 @@ impl Ioapic {
         self.resample_events = resample_events;
     }
 
+    /// Returns the level-triggered pins using `vector`, whose interrupts an EOI of `vector` ends.
+    pub fn level_triggered_pins(&self, vector: u8) -> Vec<usize> {
+        (0..self.num_pins)
+            .filter(|&i| {
+                self.redirect_table[i].get_vector() == vector
+                    && self.redirect_table[i].get_trigger_mode() == TriggerMode::Level
+            })
+            .collect()
+    }
+
     // The ioapic must be informed about EOIs in order to avoid sending multiple interrupts of the
     // same type at the same time.
     pub fn end_of_interrupt(&mut self, vector: u8) {
@@ -411,6 +421,7 @@ impl Ioapic {
                 let entry = &self.redirect_table[index];
                 address.set_destination_mode(entry.get_dest_mode());
                 address.set_destination_id(entry.get_dest_id());
+                address.set_ext_destination_id(entry.get_ext_dest_id());
                 address.set_always_0xfee(0xfee);
                 data.set_vector(entry.get_vector());
                 data.set_delivery_mode(entry.get_delivery_mode());
@@ -438,6 +449,18 @@ impl Ioapic {
             return Ok(());
         }
 
+        // Writes which don't change the route of the pin, e.g. masking or unmasking it, don't need
+        // to update the routes of the hypervisor, which is expensive.
+        if let Some(OutEvent {
+            snapshot: Some(snapshot),
+            ..
+        }) = &self.out_events[index]
+        {
+            if snapshot.msi_address == msi_address && snapshot.msi_data == msi_data {
+                return Ok(());
+            }
+        }
+
         // Allocate a GSI and event for the outgoing route, if we haven't already done it.
         // The event will be used on the "outgoing" end of the ioapic to send an interrupt to the
         // apics: when an incoming ioapic irq line gets signalled, the ioapic writes to the
@@ -1252,6 +1275,46 @@ mod tests {
         t.send(&VmIrqResponse::Ok).unwrap();
     }
 
+    #[test]
+    fn unchanged_msi_route_not_updated() {
+        let (irqchip_tube, ioapic_irq_tube) = Tube::pair().unwrap();
+        let gsi_num = NUM_IOAPIC_PINS as u32;
+        let irq = 10;
+
+        // Create a fake irqchip which records the routes set up until the ioapic is dropped.
+        let irqchip_fake = thread::spawn(move || {
+            let mut routes = Vec::new();
+            while let Ok(request) = irqchip_tube.recv::<VmIrqRequest>() {
+                match request {
+                    VmIrqRequest::AllocateOneMsi { .. } => irqchip_tube
+                        .send(&VmIrqResponse::AllocateOneMsi { gsi: gsi_num })
+                        .unwrap(),
+                    VmIrqRequest::AddMsiRoute { gsi, msi_data, .. } => {
+                        routes.push((gsi, msi_data));
+                        send_ok(&irqchip_tube);
+                    }
+                    msg => panic!("unexpected irqchip message: {:?}", msg),
+                }
+            }
+            routes
+        });
+
+        let mut ioapic = Ioapic::new(ioapic_irq_tube, NUM_IOAPIC_PINS).unwrap();
+        set_up_redirection_table_entry(&mut ioapic, irq, TriggerMode::Edge);
+        set_mask(&mut ioapic, irq, true);
+        set_mask(&mut ioapic, irq, false);
+        let mut entry = read_entry(&mut ioapic, irq);
+        entry.set_vector(DEFAULT_VECTOR);
+        write_entry(&mut ioapic, irq, entry);
+        drop(ioapic);
+
+        let routes = irqchip_fake.join().unwrap();
+        assert_eq!(routes.len(), 2);
+        assert!(routes.iter().all(|&(gsi, _)| gsi == gsi_num));
+        assert_eq!(routes[0].1 & 0xff, DEFAULT_DESTINATION_ID as u32);
+        assert_eq!(routes[1].1 & 0xff, DEFAULT_VECTOR as u32);
+    }
+
     /// Simulates restoring the ioapic as if the VM had never booted a guest.
     /// This is called the "cold" restore case since all the devices are
     /// expected to be essentially blank / unconfigured.
@@ -26,6 +26,7 @@ use snapshot::AnySnapshot;
 use sync::Mutex;
 
 use crate::icc_regs;
+use crate::irqchip::kvm::IrqLatencyProxy;
 use crate::IrqChip;
 use crate::IrqChipAArch64;
 
@@ -49,6 +50,7 @@ pub struct KvmKernelIrqChip {
     vgic: SafeDescriptor,
     device_kind: DeviceKind,
     pub(super) routes: Arc<Mutex<Vec<IrqRoute>>>,
+    pub(super) irq_latency: Option<Arc<IrqLatencyProxy>>,
 }
 
 // These constants indicate the address space used by the ARM vGIC.
@@ -149,6 +151,7 @@ impl KvmKernelIrqChip {
             vgic,
             device_kind,
             routes: Arc::new(Mutex::new(kvm_default_irq_routing_table())),
+            irq_latency: None,
         })
     }
 
@@ -160,6 +163,7 @@ impl KvmKernelIrqChip {
             vgic: self.vgic.try_clone()?,
             device_kind: self.device_kind,
             routes: self.routes.clone(),
+            irq_latency: self.irq_latency.clone(),
         })
     }
 }
@@ -2,6 +2,9 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use base::error;
 use base::Error;
 use base::Event;
@@ -14,10 +17,13 @@ use hypervisor::MPState;
 use hypervisor::Vcpu;
 use kvm_sys::kvm_mp_state;
 use resources::SystemAllocator;
+use sync::Mutex;
+use vm_control::IrqLatencyHistogram;
 
 use crate::Bus;
 use crate::IrqEdgeEvent;
 use crate::IrqEventSource;
+use crate::IrqLatencyTracker;
 use crate::IrqLevelEvent;
 
 #[cfg(target_arch = "x86_64")]
@@ -40,6 +46,109 @@ use crate::IrqChipCap;
 use crate::IrqEventIndex;
 use crate::VcpuRunState;
 
+/// Level-triggered irq events proxied through the IRQ handler thread to time their interrupts,
+/// see `KvmKernelIrqChip::enable_irq_latency_stats`.
+///
+/// KVM gets irqfds of crosvm in place of those of the devices. The signals of the devices are
+/// forwarded to KVM, and the resamples of KVM, which happen on EOI, to the devices.
+struct IrqLatencyProxy {
+    tracker: IrqLatencyTracker,
+    /// Each proxied event has two event indices: the even one for the signal of the device, and
+    /// the odd one for the resample of KVM.
+    events: Mutex<Vec<Option<ProxiedIrqEvent>>>,
+}
+
+struct ProxiedIrqEvent {
+    gsi: u32,
+    device_event: IrqLevelEvent,
+    kvm_event: IrqLevelEvent,
+    source: IrqEventSource,
+}
+
+impl IrqLatencyProxy {
+    fn register(
+        &self,
+        gsi: u32,
+        device_event: IrqLevelEvent,
+        kvm_event: IrqLevelEvent,
+        source: IrqEventSource,
+    ) -> IrqEventIndex {
+        let mut events = self.events.lock();
+        let index = events.len() * 2;
+        events.push(Some(ProxiedIrqEvent {
+            gsi,
+            device_event,
+            kvm_event,
+            source,
+        }));
+        index
+    }
+
+    fn unregister(&self, gsi: u32, device_event: &IrqLevelEvent) -> Option<ProxiedIrqEvent> {
+        self.events
+            .lock()
+            .iter_mut()
+            .find(|evt| {
+                evt.as_ref().is_some_and(|evt| {
+                    evt.gsi == gsi
+                        && evt
+                            .device_event
+                            .get_trigger()
+                            .eq(device_event.get_trigger())
+                })
+            })
+            .and_then(Option::take)
+    }
+
+    fn event_tokens(&self) -> Result<Vec<(IrqEventIndex, IrqEventSource, Event)>> {
+        let mut tokens = Vec::new();
+        for (index, evt) in self.events.lock().iter().enumerate() {
+            if let Some(evt) = evt {
+                tokens.push((
+                    index * 2,
+                    evt.source.clone(),
+                    evt.device_event.get_trigger().try_clone()?,
+                ));
+                tokens.push((
+                    index * 2 + 1,
+                    evt.source.clone(),
+                    evt.kvm_event.get_resample().try_clone()?,
+                ));
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn service(&self, event_index: IrqEventIndex) -> Result<()> {
+        if let Some(Some(evt)) = self.events.lock().get(event_index / 2) {
+            if event_index % 2 == 0 {
+                evt.device_event.get_trigger().wait()?;
+                self.tracker.signaled(evt.gsi);
+                evt.kvm_event.trigger()?;
+            } else {
+                evt.kvm_event.get_resample().wait()?;
+                self.tracker.end_of_interrupt(evt.gsi);
+                evt.device_event.trigger_resample()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KvmKernelIrqChip {
+    /// Times level-triggered interrupts from the signal of their irq event to their EOI, by
+    /// proxying their irq events through the IRQ handler thread. This adds a hop through crosvm to
+    /// the delivery of these interrupts, as with the split irqchip. Edge-triggered interrupts and
+    /// MSIs don't report their EOI to crosvm, so they aren't timed. Must be called before any irq
+    /// event is registered and before the chip is cloned.
+    pub fn enable_irq_latency_stats(&mut self) {
+        self.irq_latency = Some(Arc::new(IrqLatencyProxy {
+            tracker: IrqLatencyTracker::new(),
+            events: Mutex::new(Vec::new()),
+        }));
+    }
+}
+
 /// This IrqChip only works with Kvm so we only implement it for KvmVcpu.
 impl IrqChip for KvmKernelIrqChip {
     /// Add a vcpu to the irq chip.
@@ -74,8 +183,20 @@ impl IrqChip for KvmKernelIrqChip {
         &mut self,
         irq: u32,
         irq_event: &IrqLevelEvent,
-        _source: IrqEventSource,
+        source: IrqEventSource,
     ) -> Result<Option<IrqEventIndex>> {
+        if let Some(irq_latency) = &self.irq_latency {
+            let device_event = irq_event.try_clone()?;
+            let kvm_event = IrqLevelEvent::new()?;
+            self.vm
+                .register_irqfd(irq, kvm_event.get_trigger(), Some(kvm_event.get_resample()))?;
+            return Ok(Some(irq_latency.register(
+                irq,
+                device_event,
+                kvm_event,
+                source,
+            )));
+        }
         self.vm
             .register_irqfd(irq, irq_event.get_trigger(), Some(irq_event.get_resample()))?;
         Ok(None)
@@ -83,16 +204,44 @@ impl IrqChip for KvmKernelIrqChip {
 
     /// Unregister an event with level-trigger semantic for a particular GSI.
     fn unregister_level_irq_event(&mut self, irq: u32, irq_event: &IrqLevelEvent) -> Result<()> {
+        if let Some(proxied) = self
+            .irq_latency
+            .as_ref()
+            .and_then(|irq_latency| irq_latency.unregister(irq, irq_event))
+        {
+            return self
+                .vm
+                .unregister_irqfd(irq, proxied.kvm_event.get_trigger());
+        }
         self.vm.unregister_irqfd(irq, irq_event.get_trigger())
     }
 
     /// Route an IRQ line to an interrupt controller, or to a particular MSI vector.
     fn route_irq(&mut self, route: IrqRoute) -> Result<()> {
-        let mut routes = self.routes.lock();
-        routes.retain(|r| r.gsi != route.gsi);
+        self.route_irqs(&[route])
+    }
 
-        routes.push(route);
+    /// Route several IRQ lines with a single update of the routing table of KVM, which is skipped
+    /// if none of the routes changed.
+    fn route_irqs(&mut self, new_routes: &[IrqRoute]) -> Result<()> {
+        let mut routes = self.routes.lock();
+        let mut changed = false;
+        for route in new_routes {
+            if routes
+                .iter()
+                .filter(|r| r.gsi == route.gsi)
+                .eq(std::iter::once(route))
+            {
+                continue;
+            }
+            routes.retain(|r| r.gsi != route.gsi);
+            routes.push(*route);
+            changed = true;
+        }
 
+        if !changed {
+            return Ok(());
+        }
         self.vm.set_gsi_routing(&routes)
     }
 
@@ -107,9 +256,12 @@ impl IrqChip for KvmKernelIrqChip {
     /// Return a vector of all registered irq numbers and their associated events and event
     /// indices. These should be used by the main thread to wait for irq events.
     /// For the KvmKernelIrqChip, the kernel handles listening to irq events being triggered by
-    /// devices, so this function always returns an empty Vec.
+    /// devices, so this function only returns the events proxied to time their interrupts.
     fn irq_event_tokens(&self) -> Result<Vec<(IrqEventIndex, IrqEventSource, Event)>> {
-        Ok(Vec::new())
+        match &self.irq_latency {
+            Some(irq_latency) => irq_latency.event_tokens(),
+            None => Ok(Vec::new()),
+        }
     }
 
     /// Either assert or deassert an IRQ line.  Sends to either an interrupt controller, or does
@@ -123,10 +275,16 @@ impl IrqChip for KvmKernelIrqChip {
     /// that triggered the irq event will be read from. If the irq is associated with a resample
     /// Event, then the deassert will only happen after an EOI is broadcast for a vector
     /// associated with the irq line.
-    /// This function should never be called on KvmKernelIrqChip.
-    fn service_irq_event(&mut self, _event_index: IrqEventIndex) -> Result<()> {
-        error!("service_irq_event should never be called for KvmKernelIrqChip");
-        Ok(())
+    /// This function is only called on KvmKernelIrqChip for the events proxied to time their
+    /// interrupts, which it forwards.
+    fn service_irq_event(&mut self, event_index: IrqEventIndex) -> Result<()> {
+        match &self.irq_latency {
+            Some(irq_latency) => irq_latency.service(event_index),
+            None => {
+                error!("service_irq_event should never be called for KvmKernelIrqChip");
+                Ok(())
+            }
+        }
     }
 
     /// Broadcast an end of interrupt.
@@ -212,6 +370,18 @@ impl IrqChip for KvmKernelIrqChip {
             #[cfg(target_arch = "x86_64")]
             IrqChipCap::X2Apic => true,
             IrqChipCap::MpStateGetSet => true,
+            #[cfg(target_arch = "x86_64")]
+            IrqChipCap::AsyncPf => true,
+            // The in-kernel IOAPIC can only target 8-bit APIC IDs, so VMs with more vCPUs need the
+            // split irqchip.
+            #[cfg(target_arch = "x86_64")]
+            IrqChipCap::MsiExtDestId => false,
         }
     }
+
+    fn irq_latency_stats(&self) -> Option<BTreeMap<u32, IrqLatencyHistogram>> {
+        self.irq_latency
+            .as_ref()
+            .map(|irq_latency| irq_latency.tracker.histograms())
+    }
 }
@@ -19,6 +19,7 @@ use hypervisor::Vm;
 use kvm_sys::*;
 use sync::Mutex;
 
+use crate::irqchip::kvm::IrqLatencyProxy;
 use crate::IrqChip;
 use crate::IrqChipRiscv64;
 
@@ -214,6 +215,7 @@ pub struct KvmKernelIrqChip {
     aia: AiaDescriptor,
     device_kind: DeviceKind,
     pub(super) routes: Arc<Mutex<Vec<IrqRoute>>>,
+    pub(super) irq_latency: Option<Arc<IrqLatencyProxy>>,
 }
 
 impl KvmKernelIrqChip {
@@ -250,6 +252,7 @@ impl KvmKernelIrqChip {
             routes: Arc::new(Mutex::new(kvm_default_irq_routing_table(
                 NUM_SOURCES as usize,
             ))),
+            irq_latency: None,
         })
     }
 
@@ -265,6 +268,7 @@ impl KvmKernelIrqChip {
             aia: self.aia.try_clone()?,
             device_kind: self.device_kind,
             routes: self.routes.clone(),
+            irq_latency: self.irq_latency.clone(),
         })
     }
 }
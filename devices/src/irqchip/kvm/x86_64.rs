@@ -2,6 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use anyhow::anyhow;
@@ -36,7 +37,9 @@ use serde::Deserialize;
 use serde::Serialize;
 use snapshot::AnySnapshot;
 use sync::Mutex;
+use vm_control::IrqLatencyHistogram;
 
+use crate::irqchip::kvm::IrqLatencyProxy;
 use crate::irqchip::Ioapic;
 use crate::irqchip::IrqEvent;
 use crate::irqchip::IrqEventIndex;
@@ -50,6 +53,7 @@ use crate::IrqChipCap;
 use crate::IrqChipX86_64;
 use crate::IrqEdgeEvent;
 use crate::IrqEventSource;
+use crate::IrqLatencyTracker;
 use crate::IrqLevelEvent;
 use crate::Pit;
 use crate::PitError;
@@ -84,6 +88,7 @@ pub struct KvmKernelIrqChip {
     pub(super) vm: KvmVm,
     pub(super) vcpus: Arc<Mutex<Vec<Option<KvmVcpu>>>>,
     pub(super) routes: Arc<Mutex<Vec<IrqRoute>>>,
+    pub(super) irq_latency: Option<Arc<IrqLatencyProxy>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -107,6 +112,7 @@ impl KvmKernelIrqChip {
             vm,
             vcpus: Arc::new(Mutex::new((0..num_vcpus).map(|_| None).collect())),
             routes: Arc::new(Mutex::new(kvm_default_irq_routing_table(ioapic_pins))),
+            irq_latency: None,
         })
     }
     /// Attempt to create a shallow clone of this x86_64 KvmKernelIrqChip instance.
@@ -115,6 +121,7 @@ impl KvmKernelIrqChip {
             vm: self.vm.try_clone()?,
             vcpus: self.vcpus.clone(),
             routes: self.routes.clone(),
+            irq_latency: self.irq_latency.clone(),
         })
     }
 }
@@ -237,6 +244,9 @@ pub struct KvmSplitIrqChip {
     vm: KvmVm,
     vcpus: Arc<Mutex<Vec<Option<KvmVcpu>>>>,
     routes: Arc<Mutex<Vec<IrqRoute>>>,
+    /// Serializes the updates of the MSI routes of KVM. `routes` isn't held during the update,
+    /// which can take milliseconds, since every irq event serviced by the chip locks it.
+    gsi_routing: Arc<Mutex<()>>,
     pit: Arc<Mutex<Pit>>,
     pic: Arc<Mutex<Pic>>,
     ioapic: Arc<Mutex<Ioapic>>,
@@ -250,6 +260,8 @@ pub struct KvmSplitIrqChip {
     delayed_ioapic_irq_trigger: Event,
     /// Array of Events that devices will use to assert ioapic pins.
     irq_events: Arc<Mutex<Vec<Option<IrqEvent>>>>,
+    /// Times level-triggered ioapic interrupts, see `enable_irq_latency_stats`.
+    irq_latency: Option<IrqLatencyTracker>,
 }
 
 fn kvm_dummy_msi_routes(ioapic_pins: usize) -> Vec<IrqRoute> {
@@ -297,6 +309,7 @@ impl KvmSplitIrqChip {
             vm,
             vcpus: Arc::new(Mutex::new((0..num_vcpus).map(|_| None).collect())),
             routes: Arc::new(Mutex::new(Vec::new())),
+            gsi_routing: Arc::new(Mutex::new(())),
             pit: Arc::new(Mutex::new(pit)),
             pic: Arc::new(Mutex::new(Pic::new())),
             ioapic: Arc::new(Mutex::new(Ioapic::new(irq_tube, ioapic_pins)?)),
@@ -304,6 +317,7 @@ impl KvmSplitIrqChip {
             delayed_ioapic_irq_events: Arc::new(Mutex::new(Vec::new())),
             delayed_ioapic_irq_trigger: Event::new()?,
             irq_events: Arc::new(Mutex::new(Default::default())),
+            irq_latency: None,
         };
 
         // Setup standard x86 irq routes
@@ -346,6 +360,13 @@ impl KvmSplitIrqChip {
         chips
     }
 
+    /// Times level-triggered interrupts routed to the ioapic, from the signal of their irq event to
+    /// their EOI. Edge-triggered interrupts don't exit on EOI, so they aren't timed. Must be called
+    /// before the chip is cloned.
+    pub fn enable_irq_latency_stats(&mut self) {
+        self.irq_latency = Some(IrqLatencyTracker::new());
+    }
+
     /// Return true if there is a pending interrupt for the specified vcpu. For KvmSplitIrqChip
     /// this calls interrupt_requested on the pic.
     pub fn interrupt_requested(&self, vcpu_id: usize) -> bool {
@@ -416,6 +437,15 @@ impl KvmSplitIrqChip {
     }
 }
 
+/// Returns the MSI routes of `routes`, the only ones KVM gets with a split irqchip.
+fn msi_routes(routes: &[IrqRoute]) -> Vec<IrqRoute> {
+    routes
+        .iter()
+        .filter(|r| matches!(r.source, IrqSource::Msi { .. }))
+        .copied()
+        .collect()
+}
+
 /// Convenience function for determining whether or not two irq routes conflict.
 /// Returns true if they conflict.
 fn routes_conflict(route: &IrqRoute, other: &IrqRoute) -> bool {
@@ -491,28 +521,48 @@ impl IrqChip for KvmSplitIrqChip {
 
     /// Route an IRQ line to an interrupt controller, or to a particular MSI vector.
     fn route_irq(&mut self, route: IrqRoute) -> Result<()> {
-        let mut routes = self.routes.lock();
-        routes.retain(|r| !routes_conflict(r, &route));
-
-        routes.push(route);
+        self.route_irqs(&[route])
+    }
+
+    /// Route several IRQ lines with a single update of the MSI routes of KVM, which is skipped if
+    /// none of them changed.
+    fn route_irqs(&mut self, new_routes: &[IrqRoute]) -> Result<()> {
+        let _gsi_routing = self.gsi_routing.lock();
+        let msi_routes = {
+            let mut routes = self.routes.lock();
+            let mut msi_changed = false;
+            for route in new_routes {
+                if routes
+                    .iter()
+                    .filter(|r| routes_conflict(r, route))
+                    .eq(std::iter::once(route))
+                {
+                    continue;
+                }
+                routes.retain(|r| {
+                    let conflict = routes_conflict(r, route);
+                    msi_changed |= conflict && matches!(r.source, IrqSource::Msi { .. });
+                    !conflict
+                });
+                msi_changed |= matches!(route.source, IrqSource::Msi { .. });
+                routes.push(*route);
+            }
 
-        // We only call set_gsi_routing with the msi routes
-        let mut msi_routes = routes.clone();
-        msi_routes.retain(|r| matches!(r.source, IrqSource::Msi { .. }));
+            if !msi_changed {
+                return Ok(());
+            }
+            msi_routes(&routes)
+        };
 
         self.vm.set_gsi_routing(&msi_routes)
     }
 
     /// Replace all irq routes with the supplied routes
     fn set_irq_routes(&mut self, routes: &[IrqRoute]) -> Result<()> {
-        let mut current_routes = self.routes.lock();
-        *current_routes = routes.to_vec();
+        let _gsi_routing = self.gsi_routing.lock();
+        *self.routes.lock() = routes.to_vec();
 
-        // We only call set_gsi_routing with the msi routes
-        let mut msi_routes = routes.to_vec();
-        msi_routes.retain(|r| matches!(r.source, IrqSource::Msi { .. }));
-
-        self.vm.set_gsi_routing(&msi_routes)
+        self.vm.set_gsi_routing(&msi_routes(routes))
     }
 
     /// Return a vector of all registered irq numbers and their associated events and event
@@ -556,6 +606,9 @@ impl IrqChip for KvmSplitIrqChip {
     fn service_irq_event(&mut self, event_index: IrqEventIndex) -> Result<()> {
         if let Some(evt) = &self.irq_events.lock()[event_index] {
             evt.event.wait()?;
+            if let (Some(irq_latency), Some(_)) = (&self.irq_latency, &evt.resample_event) {
+                irq_latency.signaled(evt.gsi);
+            }
             let chips = self.routes_to_chips(evt.gsi);
 
             for (chip, pin) in chips {
@@ -588,7 +641,22 @@ impl IrqChip for KvmSplitIrqChip {
 
     /// Broadcast an end of interrupt. For KvmSplitIrqChip this sends the EOI to the ioapic
     fn broadcast_eoi(&self, vector: u8) -> Result<()> {
-        self.ioapic.lock().end_of_interrupt(vector);
+        let mut ioapic = self.ioapic.lock();
+        if let Some(irq_latency) = &self.irq_latency {
+            let pins = ioapic.level_triggered_pins(vector);
+            for route in self.routes.lock().iter() {
+                if let IrqSource::Irqchip {
+                    chip: IrqSourceChip::Ioapic,
+                    pin,
+                } = route.source
+                {
+                    if pins.contains(&(pin as usize)) {
+                        irq_latency.end_of_interrupt(route.gsi);
+                    }
+                }
+            }
+        }
+        ioapic.end_of_interrupt(vector);
         Ok(())
     }
 
@@ -655,6 +723,7 @@ impl IrqChip for KvmSplitIrqChip {
             vm: self.vm.try_clone()?,
             vcpus: self.vcpus.clone(),
             routes: self.routes.clone(),
+            gsi_routing: self.gsi_routing.clone(),
             pit: self.pit.clone(),
             pic: self.pic.clone(),
             ioapic: self.ioapic.clone(),
@@ -662,6 +731,7 @@ impl IrqChip for KvmSplitIrqChip {
             delayed_ioapic_irq_events: self.delayed_ioapic_irq_events.clone(),
             delayed_ioapic_irq_trigger: Event::new()?,
             irq_events: self.irq_events.clone(),
+            irq_latency: self.irq_latency.clone(),
         })
     }
 
@@ -769,8 +839,16 @@ impl IrqChip for KvmSplitIrqChip {
             IrqChipCap::TscDeadlineTimer => self.vm.check_raw_capability(KvmCap::TscDeadlineTimer),
             IrqChipCap::X2Apic => true,
             IrqChipCap::MpStateGetSet => true,
+            IrqChipCap::AsyncPf => true,
+            IrqChipCap::MsiExtDestId => self.vm.x2apic_ids(),
         }
     }
+
+    fn irq_latency_stats(&self) -> Option<BTreeMap<u32, IrqLatencyHistogram>> {
+        self.irq_latency
+            .as_ref()
+            .map(|irq_latency| irq_latency.histograms())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,87 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use sync::Mutex;
+use vm_control::IrqLatencyHistogram;
+
+/// Records the time between the signal of an interrupt and its EOI by the guest, in a histogram
+/// per GSI.
+///
+/// A GSI signaled again before the EOI of its pending interrupt is timed from the first signal,
+/// since the guest only sees one interrupt.
+#[derive(Clone, Default)]
+pub struct IrqLatencyTracker {
+    state: Arc<Mutex<IrqLatencyState>>,
+}
+
+#[derive(Default)]
+struct IrqLatencyState {
+    signaled: BTreeMap<u32, Instant>,
+    histograms: BTreeMap<u32, IrqLatencyHistogram>,
+}
+
+impl IrqLatencyTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records the signal of an interrupt for `gsi`.
+    pub fn signaled(&self, gsi: u32) {
+        self.state
+            .lock()
+            .signaled
+            .entry(gsi)
+            .or_insert_with(Instant::now);
+    }
+
+    /// Records the EOI of the pending interrupt of `gsi`, if any.
+    pub fn end_of_interrupt(&self, gsi: u32) {
+        let mut state = self.state.lock();
+        if let Some(signaled) = state.signaled.remove(&gsi) {
+            state
+                .histograms
+                .entry(gsi)
+                .or_default()
+                .record(signaled.elapsed());
+        }
+    }
+
+    /// Returns the histograms of the GSIs which had at least one interrupt ended.
+    pub fn histograms(&self) -> BTreeMap<u32, IrqLatencyHistogram> {
+        self.state.lock().histograms.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_then_eoi() {
+        let tracker = IrqLatencyTracker::new();
+        tracker.signaled(5);
+        // Signals coalesced with the pending interrupt aren't timed on their own.
+        tracker.signaled(5);
+        tracker.end_of_interrupt(5);
+        // EOIs without a pending interrupt, e.g. of an edge-triggered GSI sharing the vector.
+        tracker.end_of_interrupt(5);
+        tracker.end_of_interrupt(6);
+
+        let histograms = tracker.histograms();
+        assert_eq!(histograms.len(), 1);
+        assert_eq!(histograms[&5].count, 1);
+    }
+
+    #[test]
+    fn clones_share_histograms() {
+        let tracker = IrqLatencyTracker::new();
+        tracker.clone().signaled(9);
+        tracker.clone().end_of_interrupt(9);
+        assert_eq!(tracker.histograms()[&9].count, 1);
+    }
+}
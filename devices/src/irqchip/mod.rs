@@ -2,6 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::BTreeMap;
 use std::marker::Send;
 use std::marker::Sized;
 
@@ -13,6 +14,7 @@ use hypervisor::Vcpu;
 use resources::SystemAllocator;
 use serde::Deserialize;
 use serde::Serialize;
+use vm_control::IrqLatencyHistogram;
 
 use crate::pci::CrosvmDeviceId;
 use crate::pci::PciId;
@@ -71,6 +73,9 @@ cfg_if::cfg_if! {
 
 }
 
+mod latency;
+pub use self::latency::IrqLatencyTracker;
+
 #[cfg(all(target_arch = "aarch64", feature = "geniezone"))]
 mod geniezone;
 #[cfg(all(target_arch = "aarch64", feature = "geniezone"))]
@@ -131,6 +136,15 @@ impl From<DeviceId> for u32 {
     }
 }
 
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceId::PciDeviceId(pci_id) => write!(f, "pci {}", pci_id),
+            DeviceId::PlatformDeviceId(id) => write!(f, "{:?}", id),
+        }
+    }
+}
+
 /// Identification information about the source of an IrqEvent
 #[derive(Clone, Serialize, Deserialize)]
 pub struct IrqEventSource {
@@ -188,6 +202,15 @@ pub trait IrqChip: Send {
     /// Route an IRQ line to an interrupt controller, or to a particular MSI vector.
     fn route_irq(&mut self, route: IrqRoute) -> Result<()>;
 
+    /// Route several IRQ lines as if by `route_irq`, updating the routing table of the hypervisor
+    /// once for all of them where the IrqChip can.
+    fn route_irqs(&mut self, routes: &[IrqRoute]) -> Result<()> {
+        for route in routes {
+            self.route_irq(*route)?;
+        }
+        Ok(())
+    }
+
     /// Replace all irq routes with the supplied routes
     fn set_irq_routes(&mut self, routes: &[IrqRoute]) -> Result<()>;
 
@@ -256,6 +279,11 @@ pub trait IrqChip: Send {
 
     /// Checks if a particular `IrqChipCap` is available.
     fn check_capability(&self, c: IrqChipCap) -> bool;
+
+    /// Returns the latency histogram of each GSI, or None if the IrqChip doesn't time interrupts.
+    fn irq_latency_stats(&self) -> Option<BTreeMap<u32, IrqLatencyHistogram>> {
+        None
+    }
 }
 
 /// A capability the `IrqChip` can possibly expose.
@@ -270,6 +298,14 @@ pub enum IrqChipCap {
     /// Irqchip exposes mp_state_get/set methods. Calling these methods on chips
     /// without this capability will result in undefined behavior.
     MpStateGetSet,
+    /// The hypervisor emulates the APICs and can notify the guest of asynchronous page faults
+    /// through them.
+    #[cfg(target_arch = "x86_64")]
+    AsyncPf,
+    /// MSIs can address APIC IDs above 255 with the extended destination ID in bits 11:5 of their
+    /// address.
+    #[cfg(target_arch = "x86_64")]
+    MsiExtDestId,
 }
 
 /// A capability the `IrqChip` can possibly expose.
@@ -827,6 +827,9 @@ impl<V: VcpuX86_64 + 'static> IrqChip for UserspaceIrqChip<V> {
             IrqChipCap::TscDeadlineTimer => false,
             IrqChipCap::X2Apic => false,
             IrqChipCap::MpStateGetSet => true,
+            // KVM requires its in-kernel APIC to deliver asynchronous page faults.
+            IrqChipCap::AsyncPf => false,
+            IrqChipCap::MsiExtDestId => false,
         }
     }
 }
@@ -544,6 +544,8 @@ impl IrqChip for WhpxSplitIrqChip {
             // TODO(b/180966070): Figure out how to query x2apic support.
             IrqChipCap::X2Apic => false,
             IrqChipCap::MpStateGetSet => false,
+            IrqChipCap::AsyncPf => false,
+            IrqChipCap::MsiExtDestId => false,
         }
     }
 }
@@ -25,6 +25,8 @@ pub mod pl030;
 pub mod pmc_virt;
 mod serial;
 pub mod serial_device;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub mod smmuv3;
 mod suspendable;
 mod sys;
 #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -64,6 +66,7 @@ pub use self::acpi::ACPIPMFixedEvent;
 pub use self::acpi::ACPIPMResource;
 pub use self::bat::BatteryError;
 pub use self::bat::GoldfishBattery;
+pub use self::bus::query_devices;
 pub use self::bus::Bus;
 pub use self::bus::BusAccessInfo;
 pub use self::bus::BusDevice;
@@ -82,6 +85,7 @@ pub use self::debugcon::Debugcon;
 pub use self::fw_cfg::Error as FwCfgError;
 pub use self::fw_cfg::FwCfgDevice;
 pub use self::fw_cfg::FwCfgItemType;
+pub use self::fw_cfg::FwCfgKernel;
 pub use self::fw_cfg::FwCfgParameters;
 pub use self::fw_cfg::FW_CFG_BASE_PORT;
 pub use self::fw_cfg::FW_CFG_MAX_FILE_SLOTS;
@@ -91,7 +95,11 @@ pub use self::irq_event::IrqEdgeEvent;
 pub use self::irq_event::IrqLevelEvent;
 pub use self::irqchip::*;
 pub use self::pci::BarRange;
+#[cfg(feature = "pci-hotplug")]
+pub use self::pci::BlockResourceCarrier;
 pub use self::pci::CrosvmDeviceId;
+#[cfg(feature = "pci-hotplug")]
+pub use self::pci::EvdevResourceCarrier;
 pub use self::pci::GpeScope;
 #[cfg(feature = "pci-hotplug")]
 pub use self::pci::HotPluggable;
@@ -121,6 +129,7 @@ pub use self::pci::StubPciDevice;
 pub use self::pci::StubPciParameters;
 pub use self::pflash::Pflash;
 pub use self::pflash::PflashParameters;
+pub use self::pflash::SecureBootParameters;
 pub use self::pl030::Pl030;
 pub use self::pmc_virt::VirtualPmc;
 pub use self::serial::Serial;
@@ -129,6 +138,8 @@ pub use self::serial_device::SerialDevice;
 pub use self::serial_device::SerialHardware;
 pub use self::serial_device::SerialParameters;
 pub use self::serial_device::SerialType;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use self::smmuv3::SmmuV3;
 pub use self::suspendable::DeviceState;
 pub use self::suspendable::Suspendable;
 #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -144,6 +155,10 @@ cfg_if::cfg_if! {
     if #[cfg(any(target_os = "android", target_os = "linux"))] {
         mod platform;
         mod proxy;
+        #[cfg(feature = "vtpm")]
+        mod swtpm;
+        #[cfg(feature = "vtpm")]
+        pub mod tpm_crb;
         pub mod vmwdt;
         pub mod vfio;
         #[cfg(feature = "usb")]
@@ -155,15 +170,23 @@ cfg_if::cfg_if! {
         mod utils;
 
         pub use self::pci::{
-            CoIommuDev, CoIommuParameters, CoIommuUnpinPolicy, PciBridge, PcieDownstreamPort,
-            PcieHostPort, PcieRootPort, PcieUpstreamPort, PvPanicCode, PvPanicPciDevice,
-            VfioPciDevice,
+            CoIommuDev, CoIommuParameters, CoIommuUnpinPolicy, IvshmemParameters, IvshmemPciDevice,
+            PciBridge, PcieDownstreamPort, PcieHostPort, PcieRootPort, PcieUpstreamPort,
+            PvPanicCode, PvPanicPciDevice, VfioPciDevice,
         };
         pub use self::platform::VfioPlatformDevice;
         pub use self::ac_adapter::AcAdapter;
         pub use self::proxy::ChildProcIntf;
         pub use self::proxy::Error as ProxyError;
         pub use self::proxy::ProxyDevice;
+        #[cfg(feature = "vtpm")]
+        pub use self::swtpm::Swtpm;
+        #[cfg(feature = "vtpm")]
+        pub use self::swtpm::SwtpmParameters;
+        #[cfg(feature = "vtpm")]
+        pub use self::swtpm::TpmInterface;
+        #[cfg(feature = "vtpm")]
+        pub use self::tpm_crb::TpmCrb;
         #[cfg(feature = "usb")]
         pub use self::usb::backend::device_provider::DeviceProvider;
         #[cfg(feature = "usb")]
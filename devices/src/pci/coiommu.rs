@@ -1272,6 +1272,7 @@ impl CoIommuDev {
             .allocate_mmio(
                 size,
                 Alloc::PciBar {
+                    domain: address.domain,
                     bus: address.bus,
                     dev: address.dev,
                     func: address.func,
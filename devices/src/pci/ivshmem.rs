@@ -0,0 +1,536 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Inter-VM shared memory device compatible with QEMU's ivshmem.
+//! <https://www.qemu.org/docs/master/specs/ivshmem-spec.html>
+//!
+//! BAR 2 exposes a host file, such as one under `/dev/shm`, so VMs mapping the same file share its
+//! contents. The file is mapped into the guest by the main process when the device is created, so
+//! guest accesses to it never reach the device.
+//!
+//! BAR 0 holds the registers. Instead of the ivshmem-server protocol, doorbells are exchanged
+//! between two crosvm instances over a pair of Unix datagram sockets, and raise INTx# on the peer.
+
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::warn;
+use base::AsRawDescriptor;
+use base::Event;
+use base::EventToken;
+use base::RawDescriptor;
+use base::SharedMemory;
+use base::WaitContext;
+use base::WorkerThread;
+use resources::Alloc;
+use resources::AllocOptions;
+use resources::SystemAllocator;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_keyvalue::FromKeyValues;
+use snapshot::AnySnapshot;
+use sync::Mutex;
+
+use crate::pci::pci_configuration::PciBarConfiguration;
+use crate::pci::pci_configuration::PciBarPrefetchable;
+use crate::pci::pci_configuration::PciBarRegionType;
+use crate::pci::pci_configuration::PciClassCode;
+use crate::pci::pci_configuration::PciConfiguration;
+use crate::pci::pci_configuration::PciHeaderType;
+use crate::pci::pci_configuration::PciMemoryControllerSubclass;
+use crate::pci::pci_device;
+use crate::pci::pci_device::BarRange;
+use crate::pci::pci_device::PciDevice;
+use crate::pci::pci_device::Result;
+use crate::pci::PciAddress;
+use crate::pci::PciBarIndex;
+use crate::pci::PciDeviceError;
+use crate::pci::PciInterruptPin;
+use crate::IrqLevelEvent;
+use crate::Suspendable;
+
+const PCI_VENDOR_ID_IVSHMEM: u16 = 0x1af4;
+const PCI_DEVICE_ID_IVSHMEM: u16 = 0x1110;
+const PCI_IVSHMEM_REVISION_ID: u8 = 1;
+
+const IVSHMEM_REG_BAR_INDEX: PciBarIndex = 0;
+const IVSHMEM_REG_SIZE: u64 = 0x100;
+const IVSHMEM_SHM_BAR_INDEX: PciBarIndex = 2;
+
+const INTR_MASK_REG: u64 = 0x00;
+const INTR_STATUS_REG: u64 = 0x04;
+const IV_POSITION_REG: u64 = 0x08;
+const DOORBELL_REG: u64 = 0x0c;
+
+/// Parameters for an ivshmem device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct IvshmemParameters {
+    /// File backing the shared memory, created if it does not exist.
+    pub path: PathBuf,
+    /// Size of the shared memory in bytes. Must be a power of two.
+    pub size: u64,
+    /// Path prefix of the doorbell sockets. Peer `N` receives doorbells on `<doorbell>.N`.
+    pub doorbell: Option<PathBuf>,
+    /// Peer ID of this VM, 0 or 1. Reported to the guest in the IVPosition register.
+    #[serde(default)]
+    pub id: u16,
+}
+
+impl IvshmemParameters {
+    fn socket_path(&self, id: u16) -> Option<PathBuf> {
+        self.doorbell.as_ref().map(|prefix| {
+            let mut path = prefix.clone().into_os_string();
+            path.push(format!(".{}", id));
+            PathBuf::from(path)
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct InterruptRegs {
+    intr_mask: u32,
+    intr_status: u32,
+}
+
+impl InterruptRegs {
+    fn pending(&self) -> bool {
+        self.intr_mask & self.intr_status != 0
+    }
+}
+
+/// Interrupt state shared with the doorbell worker.
+struct Interrupt {
+    regs: Mutex<InterruptRegs>,
+    irq_evt: Option<IrqLevelEvent>,
+}
+
+impl Interrupt {
+    fn trigger_if_pending(&self) {
+        if !self.regs.lock().pending() {
+            return;
+        }
+        if let Some(irq_evt) = &self.irq_evt {
+            if let Err(e) = irq_evt.trigger() {
+                error!("ivshmem: failed to trigger interrupt: {}", e);
+            }
+        }
+    }
+}
+
+struct Doorbell {
+    socket: UnixDatagram,
+    peer_id: u16,
+    peer_path: PathBuf,
+}
+
+fn run_doorbell_worker(
+    socket: UnixDatagram,
+    interrupt: Arc<Interrupt>,
+    kill_evt: Event,
+) -> anyhow::Result<()> {
+    #[derive(EventToken)]
+    enum Token {
+        Doorbell,
+        Resample,
+        Kill,
+    }
+
+    let wait_ctx = WaitContext::build_with(&[(&socket, Token::Doorbell), (&kill_evt, Token::Kill)])
+        .context("failed creating WaitContext")?;
+    if let Some(irq_evt) = &interrupt.irq_evt {
+        wait_ctx
+            .add(irq_evt.get_resample(), Token::Resample)
+            .context("failed adding resample event")?;
+    }
+
+    loop {
+        let events = wait_ctx.wait().context("failed polling for events")?;
+        for event in events.iter().filter(|e| e.is_readable) {
+            match event.token {
+                Token::Doorbell => {
+                    let mut buf = [0u8; 2];
+                    match socket.recv(&mut buf) {
+                        Ok(_) => {
+                            // With INTx#, every vector sets the same status bit.
+                            interrupt.regs.lock().intr_status |= 1;
+                            interrupt.trigger_if_pending();
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e).context("failed to receive doorbell"),
+                    }
+                }
+                Token::Resample => {
+                    if let Some(irq_evt) = &interrupt.irq_evt {
+                        irq_evt
+                            .get_resample()
+                            .wait()
+                            .context("failed reading resample event")?;
+                    }
+                    interrupt.trigger_if_pending();
+                }
+                Token::Kill => return Ok(()),
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IvshmemSnapshot {
+    config_regs: AnySnapshot,
+    interrupt_regs: InterruptRegs,
+}
+
+/// ivshmem PCI device exposing a host file as shared memory.
+pub struct IvshmemPciDevice {
+    pci_address: Option<PciAddress>,
+    config_regs: PciConfiguration,
+    shm_address: u64,
+    shm_size: u64,
+    id: u16,
+    interrupt: Arc<Interrupt>,
+    doorbell: Option<Doorbell>,
+    worker_thread: Option<WorkerThread<()>>,
+}
+
+impl IvshmemPciDevice {
+    /// Creates an ivshmem device whose shared memory was mapped into the guest at `shm_address`.
+    /// This binds the doorbell socket if `params` has one.
+    pub fn new(params: &IvshmemParameters, shm_address: u64) -> anyhow::Result<Self> {
+        if params.id > 1 {
+            bail!("ivshmem peer id must be 0 or 1, got {}", params.id);
+        }
+        let doorbell = match params.socket_path(params.id) {
+            Some(path) => {
+                // Remove the socket left behind by a previous run.
+                if let Err(e) = fs::remove_file(&path) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e).with_context(|| {
+                            format!("failed to remove stale socket {}", path.display())
+                        });
+                    }
+                }
+                let socket = UnixDatagram::bind(&path)
+                    .with_context(|| format!("failed to bind {}", path.display()))?;
+                let peer_id = 1 - params.id;
+                Some(Doorbell {
+                    socket,
+                    peer_id,
+                    peer_path: params.socket_path(peer_id).unwrap(),
+                })
+            }
+            None => None,
+        };
+
+        let config_regs = PciConfiguration::new(
+            PCI_VENDOR_ID_IVSHMEM,
+            PCI_DEVICE_ID_IVSHMEM,
+            PciClassCode::MemoryController,
+            &PciMemoryControllerSubclass::RamController,
+            None,
+            PciHeaderType::Device,
+            PCI_VENDOR_ID_IVSHMEM,
+            PCI_DEVICE_ID_IVSHMEM,
+            PCI_IVSHMEM_REVISION_ID,
+        );
+
+        Ok(IvshmemPciDevice {
+            pci_address: None,
+            config_regs,
+            shm_address,
+            shm_size: params.size,
+            id: params.id,
+            interrupt: Arc::new(Interrupt {
+                regs: Mutex::new(InterruptRegs::default()),
+                irq_evt: None,
+            }),
+            doorbell,
+            worker_thread: None,
+        })
+    }
+
+    fn start_worker(&mut self) {
+        let Some(doorbell) = &self.doorbell else {
+            return;
+        };
+        let socket = match doorbell.socket.try_clone() {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("ivshmem: failed to clone doorbell socket: {}", e);
+                return;
+            }
+        };
+        let interrupt = self.interrupt.clone();
+        self.worker_thread = Some(WorkerThread::start("ivshmem", move |kill_evt| {
+            if let Err(e) = run_doorbell_worker(socket, interrupt, kill_evt) {
+                error!("ivshmem doorbell worker failed: {:#}", e);
+            }
+        }));
+    }
+
+    fn ring_doorbell(&self, value: u32) {
+        let Some(doorbell) = &self.doorbell else {
+            return;
+        };
+        let peer_id = (value >> 16) as u16;
+        let vector = value as u16;
+        if peer_id != doorbell.peer_id {
+            warn!("ivshmem: doorbell for unknown peer {}", peer_id);
+            return;
+        }
+        // The peer may not be running, in which case the doorbell is dropped.
+        if let Err(e) = doorbell
+            .socket
+            .send_to(&vector.to_le_bytes(), &doorbell.peer_path)
+        {
+            warn!("ivshmem: failed to ring peer {}: {}", peer_id, e);
+        }
+    }
+}
+
+impl PciDevice for IvshmemPciDevice {
+    fn debug_label(&self) -> String {
+        "ivshmem".to_owned()
+    }
+
+    fn allocate_address(&mut self, resources: &mut SystemAllocator) -> Result<PciAddress> {
+        if self.pci_address.is_none() {
+            self.pci_address = resources.allocate_pci(0, self.debug_label());
+        }
+        self.pci_address.ok_or(PciDeviceError::PciAllocationFailed)
+    }
+
+    fn keep_rds(&self) -> Vec<RawDescriptor> {
+        let mut rds = Vec::new();
+        if let Some(doorbell) = &self.doorbell {
+            rds.push(doorbell.socket.as_raw_descriptor());
+        }
+        if let Some(irq_evt) = &self.interrupt.irq_evt {
+            rds.push(irq_evt.get_trigger().as_raw_descriptor());
+            rds.push(irq_evt.get_resample().as_raw_descriptor());
+        }
+        rds
+    }
+
+    fn assign_irq(&mut self, irq_evt: IrqLevelEvent, pin: PciInterruptPin, irq_num: u32) {
+        self.config_regs.set_irq(irq_num as u8, pin);
+        // The worker is not running yet, so the state is not shared at this point.
+        match Arc::get_mut(&mut self.interrupt) {
+            Some(interrupt) => interrupt.irq_evt = Some(irq_evt),
+            None => error!("ivshmem: irq assigned after the device started"),
+        }
+    }
+
+    fn allocate_io_bars(&mut self, resources: &mut SystemAllocator) -> Result<Vec<BarRange>> {
+        let address = self
+            .pci_address
+            .expect("allocate_address must be called prior to allocate_io_bars");
+        let reg_addr = resources
+            .allocate_mmio(
+                IVSHMEM_REG_SIZE,
+                Alloc::PciBar {
+                    domain: address.domain,
+                    bus: address.bus,
+                    dev: address.dev,
+                    func: address.func,
+                    bar: IVSHMEM_REG_BAR_INDEX as u8,
+                },
+                "ivshmem_reg".to_string(),
+                AllocOptions::new()
+                    .max_address(u32::MAX.into())
+                    .align(IVSHMEM_REG_SIZE),
+            )
+            .map_err(|e| pci_device::Error::IoAllocationFailed(IVSHMEM_REG_SIZE, e))?;
+        let reg_config = PciBarConfiguration::new(
+            IVSHMEM_REG_BAR_INDEX,
+            IVSHMEM_REG_SIZE,
+            PciBarRegionType::Memory32BitRegion,
+            PciBarPrefetchable::NotPrefetchable,
+        )
+        .set_address(reg_addr);
+        self.config_regs
+            .add_pci_bar(reg_config)
+            .map_err(|e| pci_device::Error::IoRegistrationFailed(reg_addr, e))?;
+
+        Ok(vec![BarRange {
+            addr: reg_addr,
+            size: IVSHMEM_REG_SIZE,
+            prefetchable: false,
+        }])
+    }
+
+    fn allocate_device_bars(&mut self, _resources: &mut SystemAllocator) -> Result<Vec<BarRange>> {
+        // The shared memory was allocated and mapped when the device was created. It is backed by
+        // a memory slot, so no range is returned for the device to trap.
+        let shm_config = PciBarConfiguration::new(
+            IVSHMEM_SHM_BAR_INDEX,
+            self.shm_size,
+            PciBarRegionType::Memory64BitRegion,
+            PciBarPrefetchable::Prefetchable,
+        )
+        .set_address(self.shm_address);
+        self.config_regs
+            .add_pci_bar(shm_config)
+            .map_err(|e| pci_device::Error::IoRegistrationFailed(self.shm_address, e))?;
+        Ok(Vec::new())
+    }
+
+    fn get_bar_configuration(&self, bar_num: usize) -> Option<PciBarConfiguration> {
+        self.config_regs.get_bar_configuration(bar_num)
+    }
+
+    fn read_config_register(&self, reg_idx: usize) -> u32 {
+        self.config_regs.read_reg(reg_idx)
+    }
+
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        self.config_regs.write_reg(reg_idx, offset, data);
+    }
+
+    fn setup_pci_config_mapping(
+        &mut self,
+        shmem: &SharedMemory,
+        base: usize,
+        len: usize,
+    ) -> Result<bool> {
+        self.config_regs
+            .setup_mapping(shmem, base, len)
+            .map(|_| true)
+            .map_err(PciDeviceError::MmioSetup)
+    }
+
+    fn read_bar(&mut self, bar_index: PciBarIndex, offset: u64, data: &mut [u8]) {
+        data.fill(0);
+        if bar_index != IVSHMEM_REG_BAR_INDEX || data.len() != 4 {
+            return;
+        }
+        let value = match offset {
+            INTR_MASK_REG => self.interrupt.regs.lock().intr_mask,
+            // Reading the status clears it.
+            INTR_STATUS_REG => std::mem::take(&mut self.interrupt.regs.lock().intr_status),
+            IV_POSITION_REG => self.id.into(),
+            _ => 0,
+        };
+        data.copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_bar(&mut self, bar_index: PciBarIndex, offset: u64, data: &[u8]) {
+        if bar_index != IVSHMEM_REG_BAR_INDEX || data.len() != 4 {
+            return;
+        }
+        let value = u32::from_le_bytes(data.try_into().unwrap());
+        match offset {
+            INTR_MASK_REG => {
+                self.interrupt.regs.lock().intr_mask = value;
+                self.interrupt.trigger_if_pending();
+            }
+            DOORBELL_REG => self.ring_doorbell(value),
+            _ => {}
+        }
+    }
+
+    fn on_device_sandboxed(&mut self) {
+        self.start_worker();
+    }
+}
+
+impl Suspendable for IvshmemPciDevice {
+    fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        let regs = self.interrupt.regs.lock();
+        AnySnapshot::to_any(IvshmemSnapshot {
+            config_regs: self
+                .config_regs
+                .snapshot()
+                .context("failed to serialize ivshmem config")?,
+            interrupt_regs: InterruptRegs {
+                intr_mask: regs.intr_mask,
+                intr_status: regs.intr_status,
+            },
+        })
+        .context("failed to serialize IvshmemPciDevice")
+    }
+
+    fn restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        let snapshot: IvshmemSnapshot =
+            AnySnapshot::from_any(data).context("failed to deserialize IvshmemPciDevice")?;
+        self.config_regs.restore(snapshot.config_regs)?;
+        *self.interrupt.regs.lock() = snapshot.interrupt_regs;
+        Ok(())
+    }
+
+    fn sleep(&mut self) -> anyhow::Result<()> {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            worker_thread.stop();
+        }
+        Ok(())
+    }
+
+    fn wake(&mut self) -> anyhow::Result<()> {
+        self.start_worker();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_keyvalue::from_key_values;
+
+    use super::*;
+
+    #[test]
+    fn params_from_key_values() {
+        let params: IvshmemParameters =
+            from_key_values("path=/dev/shm/ivshmem,size=0x100000").unwrap();
+        assert_eq!(
+            params,
+            IvshmemParameters {
+                path: PathBuf::from("/dev/shm/ivshmem"),
+                size: 0x100000,
+                doorbell: None,
+                id: 0,
+            }
+        );
+        assert_eq!(params.socket_path(0), None);
+
+        let params: IvshmemParameters =
+            from_key_values("path=/dev/shm/ivshmem,size=4096,doorbell=/run/ivshmem,id=1").unwrap();
+        assert_eq!(params.id, 1);
+        assert_eq!(params.socket_path(0), Some(PathBuf::from("/run/ivshmem.0")));
+
+        assert!(from_key_values::<IvshmemParameters>("path=/dev/shm/ivshmem").is_err());
+    }
+
+    #[test]
+    fn interrupt_status() {
+        let mut device = IvshmemPciDevice::new(
+            &IvshmemParameters {
+                path: PathBuf::from("/dev/shm/ivshmem"),
+                size: 4096,
+                doorbell: None,
+                id: 1,
+            },
+            0x1_0000_0000,
+        )
+        .unwrap();
+        let mut data = [0u8; 4];
+        device.read_bar(IVSHMEM_REG_BAR_INDEX, IV_POSITION_REG, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 1);
+
+        device.interrupt.regs.lock().intr_status = 1;
+        device.read_bar(IVSHMEM_REG_BAR_INDEX, INTR_STATUS_REG, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 1);
+        device.read_bar(IVSHMEM_REG_BAR_INDEX, INTR_STATUS_REG, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0);
+
+        device.write_bar(IVSHMEM_REG_BAR_INDEX, INTR_MASK_REG, &1u32.to_le_bytes());
+        device.read_bar(IVSHMEM_REG_BAR_INDEX, INTR_MASK_REG, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 1);
+    }
+}
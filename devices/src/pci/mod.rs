@@ -7,6 +7,8 @@
 mod acpi;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 mod coiommu;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod ivshmem;
 mod msi;
 mod msix;
 mod pci_configuration;
@@ -35,6 +37,10 @@ pub use self::coiommu::CoIommuDev;
 pub use self::coiommu::CoIommuParameters;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 pub use self::coiommu::CoIommuUnpinPolicy;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use self::ivshmem::IvshmemParameters;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use self::ivshmem::IvshmemPciDevice;
 pub use self::msi::MsiConfig;
 pub use self::msix::MsixCap;
 pub use self::msix::MsixConfig;
@@ -52,8 +58,10 @@ pub use self::pci_configuration::PciDisplaySubclass;
 pub use self::pci_configuration::PciHeaderType;
 pub use self::pci_configuration::PciInputDeviceSubclass;
 pub use self::pci_configuration::PciMassStorageSubclass;
+pub use self::pci_configuration::PciMemoryControllerSubclass;
 pub use self::pci_configuration::PciMultimediaSubclass;
 pub use self::pci_configuration::PciNetworkControllerSubclass;
+pub use self::pci_configuration::PciOtherSubclass;
 pub use self::pci_configuration::PciProgrammingInterface;
 pub use self::pci_configuration::PciSerialBusSubClass;
 pub use self::pci_configuration::PciSimpleCommunicationControllerSubclass;
@@ -65,6 +73,10 @@ pub use self::pci_device::PciBus;
 pub use self::pci_device::PciDevice;
 pub use self::pci_device::PreferredIrq;
 #[cfg(feature = "pci-hotplug")]
+pub use self::pci_hotplug::BlockResourceCarrier;
+#[cfg(feature = "pci-hotplug")]
+pub use self::pci_hotplug::EvdevResourceCarrier;
+#[cfg(feature = "pci-hotplug")]
 pub use self::pci_hotplug::HotPluggable;
 #[cfg(feature = "pci-hotplug")]
 pub use self::pci_hotplug::IntxParameter;
@@ -144,6 +156,8 @@ pub enum CrosvmDeviceId {
     VirtualPmc = 21,
     VirtCpufreq = 22,
     FwCfg = 23,
+    SmmuV3 = 24,
+    TpmCrb = 25,
 }
 
 impl TryFrom<u16> for CrosvmDeviceId {
@@ -172,6 +186,8 @@ impl TryFrom<u16> for CrosvmDeviceId {
             19 => Ok(CrosvmDeviceId::VirtioMmio),
             20 => Ok(CrosvmDeviceId::AcAdapter),
             21 => Ok(CrosvmDeviceId::VirtualPmc),
+            24 => Ok(CrosvmDeviceId::SmmuV3),
+            25 => Ok(CrosvmDeviceId::TpmCrb),
             _ => Err(base::Error::new(EINVAL)),
         }
     }
@@ -193,6 +209,12 @@ impl PciId {
     }
 }
 
+impl std::fmt::Display for PciId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor_id, self.device_id)
+    }
+}
+
 impl From<PciId> for u32 {
     fn from(pci_id: PciId) -> Self {
         // vendor ID is the lower 16 bits and device id is the upper 16 bits
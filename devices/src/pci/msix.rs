@@ -19,6 +19,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use snapshot::AnySnapshot;
 use thiserror::Error;
+use vm_control::MsiRoute;
 use vm_control::VmIrqRequest;
 use vm_control::VmIrqResponse;
 use zerocopy::FromBytes;
@@ -209,7 +210,9 @@ impl MsixConfig {
             self.masked = (reg & FUNCTION_MASK_BIT) == FUNCTION_MASK_BIT;
             self.enabled = (reg & MSIX_ENABLE_BIT) == MSIX_ENABLE_BIT;
 
-            if !old_enabled && self.enabled {
+            // Vectors unmasked while the function was masked are only allocated once the function
+            // is unmasked.
+            if self.enabled && (!old_enabled || (old_masked && !self.masked)) {
                 if let Err(e) = self.msix_enable_all() {
                     error!("failed to enable MSI-X: {}", e);
                     self.enabled = false;
@@ -350,7 +353,8 @@ impl MsixConfig {
         Ok(())
     }
 
-    fn add_msi_route(&mut self, index: u16, gsi: u32) -> MsixResult<()> {
+    // Returns the msi route of the vector `index` to `gsi`, or None if its address isn't set up.
+    fn msi_route(&self, index: u16, gsi: u32) -> Option<MsiRoute> {
         let mut data: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
         self.read_msix_table((index * 16).into(), data.as_mut());
         let msi_address: u64 = u64::from_le_bytes(data);
@@ -359,14 +363,26 @@ impl MsixConfig {
         let msi_data: u32 = u32::from_le_bytes(data);
 
         if msi_address == 0 {
-            return Ok(());
+            return None;
         }
 
+        Some(MsiRoute {
+            gsi,
+            msi_address,
+            msi_data,
+        })
+    }
+
+    fn add_msi_route(&mut self, index: u16, gsi: u32) -> MsixResult<()> {
+        let Some(route) = self.msi_route(index, gsi) else {
+            return Ok(());
+        };
+
         self.msi_device_socket
             .send(&VmIrqRequest::AddMsiRoute {
-                gsi,
-                msi_address,
-                msi_data,
+                gsi: route.gsi,
+                msi_address: route.msi_address,
+                msi_data: route.msi_data,
             })
             .map_err(MsixError::AddMsiRouteSend)?;
         if let VmIrqResponse::Err(e) = self
@@ -379,23 +395,53 @@ impl MsixConfig {
         Ok(())
     }
 
+    // Add the routes with a single update of the routing table of the irq chip.
+    fn add_msi_routes(&mut self, routes: Vec<MsiRoute>) -> MsixResult<()> {
+        if routes.is_empty() {
+            return Ok(());
+        }
+
+        self.msi_device_socket
+            .send(&VmIrqRequest::AddMsiRoutes { routes })
+            .map_err(MsixError::AddMsiRouteSend)?;
+        if let VmIrqResponse::Err(e) = self
+            .msi_device_socket
+            .recv()
+            .map_err(MsixError::AddMsiRouteRecv)?
+        {
+            return Err(MsixError::AddMsiRoute(e));
+        }
+        Ok(())
+    }
+
     // Enable MSI-X
     fn msix_enable_all(&mut self) -> MsixResult<()> {
+        let mut routes = Vec::new();
         for index in 0..self.irq_vec.len() {
-            self.msix_enable_one(index)?;
+            if let Some(gsi) = self.msix_allocate_one(index)? {
+                routes.extend(self.msi_route(index as u16, gsi));
+            }
         }
-        Ok(())
+        self.add_msi_routes(routes)
     }
 
     // Use a new MSI-X vector
-    // Create a new eventfd and bind them to a new msi
     fn msix_enable_one(&mut self, index: usize) -> MsixResult<()> {
+        if let Some(gsi) = self.msix_allocate_one(index)? {
+            self.add_msi_route(index as u16, gsi)?;
+        }
+        Ok(())
+    }
+
+    // Create a new eventfd and bind it to a new msi, returning its gsi, unless the vector is
+    // already allocated or can't be used yet.
+    fn msix_allocate_one(&mut self, index: usize) -> MsixResult<Option<u32>> {
         if self.irq_vec[index].is_some()
             || !self.enabled()
             || self.masked()
             || self.table_masked(index)
         {
-            return Ok(());
+            return Ok(None);
         }
         let irqfd = Event::new().map_err(MsixError::AllocateOneMsi)?;
         let request = VmIrqRequest::AllocateOneMsi {
@@ -423,9 +469,7 @@ impl MsixConfig {
             },
             gsi: irq_num,
         });
-
-        self.add_msi_route(index as u16, irq_num)?;
-        Ok(())
+        Ok(Some(irq_num))
     }
 
     /// Read MSI-X table
@@ -890,6 +934,61 @@ mod tests {
         t.send(&VmIrqResponse::Ok).unwrap();
     }
 
+    #[test]
+    fn enable_batches_msi_routes() {
+        let (irqchip_tube, msix_config_tube) = Tube::pair().unwrap();
+
+        let mut cfg = MsixConfig::new(3, msix_config_tube, 0, "test_device".to_owned());
+
+        // Unmask vectors 0 and 1 while MSI-X is disabled; vector 2 stays masked.
+        for (index, entry) in cfg.table_entries.iter_mut().take(2).enumerate() {
+            entry.msg_data = 0xd0 + index as u32;
+            entry.msg_addr_lo = 0xa0 + index as u32;
+            entry.msg_addr_hi = 0;
+            entry.vector_ctl = 0;
+        }
+
+        // Create a fake irqchip to respond to our requests
+        let irqchip_fake = thread::spawn(move || {
+            for gsi in [10, 20] {
+                match irqchip_tube.recv::<VmIrqRequest>().unwrap() {
+                    VmIrqRequest::AllocateOneMsi { .. } => irqchip_tube
+                        .send(&VmIrqResponse::AllocateOneMsi { gsi })
+                        .unwrap(),
+                    msg => panic!("unexpected irqchip message: {:?}", msg),
+                }
+            }
+            match irqchip_tube.recv::<VmIrqRequest>().unwrap() {
+                VmIrqRequest::AddMsiRoutes { routes } => assert_eq!(
+                    routes,
+                    vec![
+                        MsiRoute {
+                            gsi: 10,
+                            msi_address: 0xa0,
+                            msi_data: 0xd0,
+                        },
+                        MsiRoute {
+                            gsi: 20,
+                            msi_address: 0xa1,
+                            msi_data: 0xd1,
+                        },
+                    ]
+                ),
+                msg => panic!("unexpected irqchip message: {:?}", msg),
+            }
+            send_ok(&irqchip_tube);
+            irqchip_tube
+        });
+
+        cfg.write_msix_capability(2, &MSIX_ENABLE_BIT.to_le_bytes());
+        irqchip_fake.join().unwrap();
+
+        assert!(cfg.enabled());
+        assert_eq!(cfg.irq_vec[0].as_ref().unwrap().gsi, 10);
+        assert_eq!(cfg.irq_vec[1].as_ref().unwrap().gsi, 20);
+        assert!(cfg.irq_vec[2].is_none());
+    }
+
     /// Tests a cold restore where there are no existing vectors at the time
     /// restore is called.
     #[test]
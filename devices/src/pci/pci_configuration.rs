@@ -185,6 +185,21 @@ impl PciSubclass for PciMultimediaSubclass {
     }
 }
 
+/// Subclasses of the MemoryController class.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum PciMemoryControllerSubclass {
+    RamController = 0x00,
+    FlashController = 0x01,
+    Other = 0x80,
+}
+
+impl PciSubclass for PciMemoryControllerSubclass {
+    fn get_register_value(&self) -> u8 {
+        *self as u8
+    }
+}
+
 /// Subclasses of the BridgeDevice
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
@@ -15,6 +15,8 @@ use base::warn;
 use base::MemoryMapping;
 use base::RawDescriptor;
 use base::SharedMemory;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
 use remain::sorted;
 use resources::Error as SystemAllocatorFaliure;
 use resources::SystemAllocator;
@@ -22,6 +24,7 @@ use snapshot::AnySnapshot;
 use sync::Mutex;
 use thiserror::Error;
 use vm_control::api::VmMemoryClient;
+use vm_control::DeviceDetails;
 
 use super::PciId;
 use crate::bus::BusDeviceObj;
@@ -439,6 +442,12 @@ pub trait PciDevice: Send + Suspendable {
     /// Invoked when the device is sandboxed.
     fn on_device_sandboxed(&mut self) {}
 
+    /// Returns the paths the device opens once sandboxed. See `BusDevice::landlock_rules`.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        None
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn generate_acpi(&mut self, sdts: &mut Vec<SDT>) -> anyhow::Result<()> {
         let _ = sdts;
@@ -468,6 +477,11 @@ pub trait PciDevice: Send + Suspendable {
         None
     }
 
+    /// Returns details about the device for introspection.
+    fn device_details(&self) -> DeviceDetails {
+        DeviceDetails::default()
+    }
+
     /// if device is a pci brdige, configure pci bridge window
     fn configure_bridge_window(
         &mut self,
@@ -705,6 +719,11 @@ impl<T: PciDevice> BusDevice for T {
         self.on_device_sandboxed();
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        PciDevice::landlock_rules(self)
+    }
+
     fn get_ranges(&self) -> Vec<(BusRange, BusType)> {
         let mut ranges = Vec::new();
         for bar_num in 0..NUM_BAR_REGS {
@@ -734,6 +753,10 @@ impl<T: PciDevice> BusDevice for T {
     fn is_bridge(&self) -> Option<u8> {
         self.get_new_pci_bus().map(|bus| bus.lock().get_bus_num())
     }
+
+    fn device_details(&self) -> DeviceDetails {
+        PciDevice::device_details(self)
+    }
 }
 
 impl<T: PciDevice + ?Sized> PciDevice for Box<T> {
@@ -801,6 +824,10 @@ impl<T: PciDevice + ?Sized> PciDevice for Box<T> {
     fn on_device_sandboxed(&mut self) {
         (**self).on_device_sandboxed()
     }
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        (**self).landlock_rules()
+    }
 
     #[cfg(target_arch = "x86_64")]
     fn generate_acpi(&mut self, sdts: &mut Vec<SDT>) -> anyhow::Result<()> {
@@ -824,6 +851,9 @@ impl<T: PciDevice + ?Sized> PciDevice for Box<T> {
     fn get_removed_children_devices(&self) -> Vec<PciAddress> {
         (**self).get_removed_children_devices()
     }
+    fn device_details(&self) -> DeviceDetails {
+        (**self).device_details()
+    }
 
     fn configure_bridge_window(
         &mut self,
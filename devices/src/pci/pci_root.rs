@@ -62,6 +62,7 @@ impl PciDevice for PciRootConfiguration {
     fn allocate_address(&mut self, _resources: &mut SystemAllocator) -> Result<PciAddress, Error> {
         // PCI root fixed address.
         Ok(PciAddress {
+            domain: 0,
             bus: 0,
             dev: 0,
             func: 0,
@@ -160,6 +161,8 @@ pub struct PciRoot {
     /// pcie enhanced configuration access mmio base
     pcie_cfg_mmio: Option<u64>,
     pci_mmio_state: PciRootMmioState,
+    /// PCI segment (domain) of the devices attached to this bridge.
+    segment: u16,
 }
 
 const PCI_DEVICE_ID_INTEL_82441: u16 = 0x1237;
@@ -181,15 +184,17 @@ impl PciRoot {
         mmio_register_bit_num: usize,
         io_bus: Weak<Bus>,
         root_bus: Arc<Mutex<PciBus>>,
+        segment: u16,
     ) -> anyhow::Result<Self> {
         // mmio_mappings's implementation assumes each device's mmio registers
         // can fit on a single page. Always true given existing specs.
         assert!(base::pagesize() >= (1 << mmio_register_bit_num));
         let mut root =
             Self::create_for_test(mmio_bus, mmio_base, mmio_register_bit_num, io_bus, root_bus);
+        root.segment = segment;
         root.pci_mmio_state
             .setup_mapping(
-                &PciAddress::new(0, 0, 0, 0).unwrap(),
+                &PciAddress::new(segment.into(), 0, 0, 0).unwrap(),
                 &mut root.root_configuration,
                 vm,
             )
@@ -228,6 +233,7 @@ impl PciRoot {
                 base: mmio_base,
                 register_bit_num: mmio_register_bit_num,
             },
+            segment: 0,
         }
     }
 
@@ -236,6 +242,20 @@ impl PciRoot {
         self.root_bus.clone()
     }
 
+    /// Get the PCI segment (domain) of this root bridge
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
+
+    /// Get the ACPI name of this root bridge
+    pub fn acpi_name(&self) -> String {
+        if self.segment == 0 {
+            "PC00".to_owned()
+        } else {
+            format!("PS{:02X}", self.segment)
+        }
+    }
+
     /// Get the ACPI path to a PCI device
     pub fn acpi_path(&self, address: &PciAddress) -> Option<String> {
         if let Some(device) = self.devices.get(address) {
@@ -245,8 +265,8 @@ impl PciRoot {
             } else {
                 Some(format!(
                     "_SB_.{}.{}",
-                    path.iter()
-                        .map(|x| format!("PC{:02X}", x))
+                    std::iter::once(self.acpi_name())
+                        .chain(path.iter().skip(1).map(|x| format!("PC{:02X}", x)))
                         .collect::<Vec<String>>()
                         .join("."),
                     match device.lock().is_bridge() {
@@ -351,6 +371,11 @@ impl PciRoot {
     }
 
     pub fn config_space_read(&self, address: PciAddress, register: usize) -> u32 {
+        // Configuration accesses do not carry the segment, which is implied by the root bridge.
+        let address = PciAddress {
+            domain: self.segment,
+            ..address
+        };
         if address.is_root() {
             if register == PCIE_XBAR_BASE_ADDR && self.pcie_cfg_mmio.is_some() {
                 let pcie_mmio = self.pcie_cfg_mmio.unwrap() as u32;
@@ -388,6 +413,10 @@ impl PciRoot {
         if offset as usize + data.len() > 4 {
             return;
         }
+        let address = PciAddress {
+            domain: self.segment,
+            ..address
+        };
         if address.is_root() {
             self.root_configuration
                 .config_register_write(register, offset, data);
@@ -427,6 +456,10 @@ impl PciRoot {
     }
 
     pub fn virtual_config_space_read(&self, address: PciAddress, register: usize) -> u32 {
+        let address = PciAddress {
+            domain: self.segment,
+            ..address
+        };
         if address.is_root() {
             0u32
         } else {
@@ -437,6 +470,10 @@ impl PciRoot {
     }
 
     pub fn virtual_config_space_write(&mut self, address: PciAddress, register: usize, value: u32) {
+        let address = PciAddress {
+            domain: self.segment,
+            ..address
+        };
         if !address.is_root() {
             if let Some(d) = self.devices.get(&address) {
                 d.lock().virtual_config_register_write(register, value);
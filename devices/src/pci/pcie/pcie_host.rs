@@ -143,6 +143,7 @@ fn visit_children(dir: &Path, children: &mut Vec<HotPlugDeviceInfo>) -> Result<(
                 device_type: HotPlugDeviceType::EndPoint,
                 path: device.to_path_buf(),
                 hp_interrupt,
+                p2p: false,
             });
             // No need to look further
             return Ok(());
@@ -170,6 +171,7 @@ fn visit_children(dir: &Path, children: &mut Vec<HotPlugDeviceInfo>) -> Result<(
                         device_type: HotPlugDeviceType::UpstreamPort,
                         path: device.to_path_buf(),
                         hp_interrupt,
+                        p2p: false,
                     })
                 }
                 x if x == PcieDevicePortType::DownstreamPort as u16 => {
@@ -177,6 +179,7 @@ fn visit_children(dir: &Path, children: &mut Vec<HotPlugDeviceInfo>) -> Result<(
                         device_type: HotPlugDeviceType::DownstreamPort,
                         path: device.to_path_buf(),
                         hp_interrupt,
+                        p2p: false,
                     })
                 }
                 _ => (),
@@ -132,6 +132,7 @@ impl PciDevice for PvPanicPciDevice {
             .allocate_mmio(
                 PVPANIC_REG_SIZE,
                 Alloc::PciBar {
+                    domain: address.domain,
                     bus: address.bus,
                     dev: address.dev,
                     func: address.func,
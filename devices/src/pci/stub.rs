@@ -226,6 +226,7 @@ mod test {
 
     const CONFIG: StubPciParameters = StubPciParameters {
         address: PciAddress {
+            domain: 0,
             bus: 0x0a,
             dev: 0x0b,
             func: 0x1,
@@ -304,6 +305,7 @@ mod test {
         assert_eq!(
             params.address,
             PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 1,
                 func: 2
@@ -318,6 +320,7 @@ mod test {
         assert_eq!(
             params.address,
             PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 1,
                 func: 2
@@ -352,6 +355,7 @@ mod test {
         assert_eq!(
             params.address,
             PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 1,
                 func: 2
@@ -407,6 +411,7 @@ mod test {
         */
         device.restore(snapshot_init.clone())?;
         device.requested_address = PciAddress {
+            domain: 0,
             bus: 0x0d,
             dev: 0x0e,
             func: 0x4,
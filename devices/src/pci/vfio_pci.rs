@@ -13,6 +13,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use acpi_tables::aml::Aml;
+use anyhow::Context;
 use base::debug;
 use base::error;
 use base::pagesize;
@@ -33,10 +34,14 @@ use resources::Alloc;
 use resources::AllocOptions;
 use resources::MmioType;
 use resources::SystemAllocator;
+use serde::Deserialize;
+use serde::Serialize;
+use snapshot::AnySnapshot;
 use sync::Mutex;
 use vfio_sys::vfio::VFIO_PCI_ACPI_NTFY_IRQ_INDEX;
 use vfio_sys::*;
 use vm_control::api::VmMemoryClient;
+use vm_control::DeviceDetails;
 use vm_control::HotPlugDeviceInfo;
 use vm_control::HotPlugDeviceType;
 use vm_control::VmMemoryDestination;
@@ -84,6 +89,8 @@ use crate::vfio::VfioDevice;
 use crate::vfio::VfioError;
 use crate::vfio::VfioIrqType;
 use crate::vfio::VfioPciConfig;
+#[cfg(feature = "pci-hotplug")]
+use crate::HotPluggable;
 use crate::IrqLevelEvent;
 use crate::Suspendable;
 
@@ -91,6 +98,8 @@ const PCI_VENDOR_ID: u32 = 0x0;
 const PCI_DEVICE_ID: u32 = 0x2;
 const PCI_COMMAND: u32 = 0x4;
 const PCI_COMMAND_MEMORY: u8 = 0x2;
+const PCI_BASE_ADDRESS_0: u32 = 0x10;
+const PCI_BAR_COUNT: u32 = 6;
 const PCI_BASE_CLASS_CODE: u32 = 0x0B;
 const PCI_INTERRUPT_NUM: u32 = 0x3C;
 const PCI_INTERRUPT_PIN: u32 = 0x3D;
@@ -570,6 +579,7 @@ impl VfioPciWorker {
                             device_type: HotPlugDeviceType::EndPoint,
                             path: self.sysfs_path.clone(),
                             hp_interrupt: false,
+                            p2p: false,
                         };
 
                         let request = VmRequest::HotPlugVfioCommand { device, add: false };
@@ -687,6 +697,21 @@ pub struct VfioPciDevice {
     acpi_notifier_val: Arc<Mutex<Vec<u32>>>,
     gpe: Option<u32>,
     base_class_code: PciClassCode,
+    // Whether the device state can be saved with the VFIO migration protocol.
+    migration: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VfioPciDeviceSnapshot {
+    activated: bool,
+    command: u16,
+    // BAR registers as programmed by the guest.
+    bar_registers: Vec<u32>,
+    mmio_bar_addresses: Vec<(PciBarIndex, u64)>,
+    msix_config: Option<AnySnapshot>,
+    msix_enabled: bool,
+    // Internal state of the device, opaque to crosvm.
+    device_state: Vec<u8>,
 }
 
 impl VfioPciDevice {
@@ -705,6 +730,7 @@ impl VfioPciDevice {
         let preferred_address = if let Some(bus_num) = hotplug_bus_number {
             debug!("hotplug bus {}", bus_num);
             PciAddress {
+                domain: 0,
                 // Caller specify pcie bus number for hotplug device
                 bus: bus_num,
                 // devfn should be 0, otherwise pcie root port couldn't detect it
@@ -826,6 +852,7 @@ impl VfioPciDevice {
             None
         };
 
+        let migration = dev.supports_migration();
         Ok(VfioPciDevice {
             device: dev,
             config,
@@ -855,6 +882,7 @@ impl VfioPciDevice {
             acpi_notifier_val: Arc::new(Mutex::new(Vec::new())),
             gpe: None,
             base_class_code,
+            migration,
         })
     }
 
@@ -1433,6 +1461,7 @@ impl VfioPciDevice {
                     .allocate_mmio(
                         bar_size,
                         Alloc::PciBar {
+                            domain: address.domain,
                             bus: address.bus,
                             dev: address.dev,
                             func: address.func,
@@ -1617,6 +1646,14 @@ impl PciDevice for VfioPciDevice {
         format!("vfio {} device", self.device.device_name())
     }
 
+    fn device_details(&self) -> DeviceDetails {
+        DeviceDetails {
+            pci_address: self.pci_address,
+            resources: vec![self.sysfs_path.display().to_string()],
+            ..Default::default()
+        }
+    }
+
     fn preferred_address(&self) -> Option<PciAddress> {
         Some(self.preferred_address)
     }
@@ -1740,6 +1777,7 @@ impl PciDevice for VfioPciDevice {
                 .allocate_mmio(
                     size,
                     Alloc::PciBar {
+                        domain: address.domain,
                         bus: address.bus,
                         dev: address.dev,
                         func: address.func,
@@ -2104,6 +2142,32 @@ impl PciDevice for VfioPciDevice {
     }
 }
 
+#[cfg(feature = "pci-hotplug")]
+impl HotPluggable for VfioPciDevice {
+    /// Sets PciAddress to pci_addr
+    fn set_pci_address(&mut self, pci_addr: PciAddress) -> Result<(), PciDeviceError> {
+        self.pci_address = Some(pci_addr);
+        Ok(())
+    }
+
+    /// Configures IO BAR layout without memory alloc.
+    fn configure_io_bars(&mut self) -> Result<(), PciDeviceError> {
+        // The guest OS assigns the BARs from the bridge window of the hotplug port, and the
+        // device maps them when they are programmed.
+        for mem_bar in self.collect_bars() {
+            self.configure_barmem(&mem_bar, 0);
+        }
+        Ok(())
+    }
+
+    /// Configure device BAR layout without memory alloc.
+    fn configure_device_bars(&mut self) -> Result<(), PciDeviceError> {
+        // The only device BAR is the OpRegion of Intel graphics, which needs a fixed guest
+        // address and is not exposed for hotplugged devices.
+        Ok(())
+    }
+}
+
 impl Suspendable for VfioPciDevice {
     fn sleep(&mut self) -> anyhow::Result<()> {
         if let Some(worker_thread) = self.worker_thread.take() {
@@ -2114,15 +2178,105 @@ impl Suspendable for VfioPciDevice {
             self.msix_cap = res.msix_cap;
             self.vm_socket_vm = Some(res.vm_socket);
         }
+        if self.migration {
+            self.device
+                .migration_set_running(false)
+                .with_context(|| format!("failed to stop {}", self.debug_label()))?;
+        }
         Ok(())
     }
 
     fn wake(&mut self) -> anyhow::Result<()> {
+        if self.migration {
+            self.device
+                .migration_set_running(true)
+                .with_context(|| format!("failed to resume {}", self.debug_label()))?;
+        }
         if self.activated {
             self.start_work_thread();
         }
         Ok(())
     }
+
+    fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        anyhow::ensure!(
+            self.migration,
+            "{} doesn't support VFIO migration",
+            self.debug_label()
+        );
+        anyhow::ensure!(
+            !matches!(self.irq_type, Some(VfioIrqType::Msi)),
+            "snapshotting {} with MSI enabled isn't supported",
+            self.debug_label()
+        );
+
+        AnySnapshot::to_any(VfioPciDeviceSnapshot {
+            activated: self.activated,
+            command: self.config.read_config(PCI_COMMAND),
+            bar_registers: (0..PCI_BAR_COUNT)
+                .map(|bar| self.config.read_config(PCI_BASE_ADDRESS_0 + bar * 4))
+                .collect(),
+            mmio_bar_addresses: self
+                .mmio_regions
+                .iter()
+                .map(|region| (region.bar_index(), region.address()))
+                .collect(),
+            msix_config: match &self.msix_cap {
+                Some(msix_cap) => Some(msix_cap.lock().config.snapshot()?),
+                None => None,
+            },
+            msix_enabled: matches!(self.irq_type, Some(VfioIrqType::Msix)),
+            device_state: self
+                .device
+                .migration_save()
+                .with_context(|| format!("failed to save the state of {}", self.debug_label()))?,
+        })
+        .context("failed to serialize VfioPciDeviceSnapshot")
+    }
+
+    fn restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.migration,
+            "{} doesn't support VFIO migration",
+            self.debug_label()
+        );
+        let deser: VfioPciDeviceSnapshot =
+            AnySnapshot::from_any(data).context("failed to deserialize VfioPciDeviceSnapshot")?;
+
+        // The device starts from its reset state, so the configuration the guest programmed is
+        // written again before its internal state is loaded.
+        for (bar, register) in (0..PCI_BAR_COUNT).zip(deser.bar_registers) {
+            self.config
+                .write_config(register, PCI_BASE_ADDRESS_0 + bar * 4);
+        }
+        for region in self.mmio_regions.iter_mut() {
+            if let Some((_, address)) = deser
+                .mmio_bar_addresses
+                .iter()
+                .find(|(bar_index, _)| *bar_index == region.bar_index())
+            {
+                *region = region.set_address(*address);
+            }
+        }
+        self.config.write_config(deser.command, PCI_COMMAND);
+        if deser.command & PCI_COMMAND_MEMORY as u16 != 0 {
+            self.commit_bars_mmap();
+        }
+
+        if let (Some(msix_cap), Some(msix_config)) = (&self.msix_cap, deser.msix_config) {
+            msix_cap.lock().config.restore(msix_config)?;
+        }
+        if deser.msix_enabled {
+            self.enable_msix();
+        }
+
+        self.device
+            .migration_load(&deser.device_state)
+            .with_context(|| format!("failed to load the state of {}", self.debug_label()))?;
+        // The worker is started when the device wakes up.
+        self.activated = deser.activated;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -21,9 +21,11 @@
 //!
 //! [QEMU's pflash implementation]: https://github.com/qemu/qemu/blob/master/hw/block/pflash_cfi01.c
 
+use std::fs;
 use std::path::PathBuf;
 
 use anyhow::bail;
+use anyhow::Context;
 use base::error;
 use base::VolatileSlice;
 use disk::DiskFile;
@@ -37,6 +39,10 @@ use crate::BusDevice;
 use crate::DeviceId;
 use crate::Suspendable;
 
+mod secure_boot;
+
+pub use secure_boot::SecureBootParameters;
+
 const COMMAND_WRITE_BYTE: u8 = 0x10;
 const COMMAND_BLOCK_ERASE: u8 = 0x20;
 const COMMAND_CLEAR_STATUS: u8 = 0x50;
@@ -56,6 +62,38 @@ pub struct PflashParameters {
     pub path: PathBuf,
     #[serde(default = "pflash_parameters_default_block_size")]
     pub block_size: u32,
+    /// Pristine image (e.g. OVMF_VARS.fd) that `path` is created from if it doesn't exist yet.
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+}
+
+impl PflashParameters {
+    /// Creates the image at `path` by copying `template` if there is no image yet, so that the
+    /// variables the firmware stores persist across VM restarts.
+    pub fn create_from_template(&self) -> anyhow::Result<()> {
+        let Some(template) = &self.template else {
+            return Ok(());
+        };
+        if self.path.exists() {
+            return Ok(());
+        }
+        fs::copy(template, &self.path).with_context(|| {
+            format!(
+                "failed to create pflash image {} from {}",
+                self.path.display(),
+                template.display()
+            )
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PflashSnapshot {
+    status: u8,
+    state: State,
+    // The image holds the UEFI variables, which must match the firmware state in guest memory.
+    image: Vec<u8>,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
@@ -234,13 +272,31 @@ impl BusDevice for Pflash {
 
 impl Suspendable for Pflash {
     fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
-        AnySnapshot::to_any((self.status, self.state))
+        let mut image = vec![0u8; self.image_size.try_into()?];
+        self.image
+            .read_exact_at_volatile(VolatileSlice::new(&mut image), 0)
+            .context("failed to read pflash image")?;
+        AnySnapshot::to_any(PflashSnapshot {
+            status: self.status,
+            state: self.state,
+            image,
+        })
     }
 
     fn restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
-        let (status, state) = AnySnapshot::from_any(data)?;
-        self.status = status;
-        self.state = state;
+        let mut snapshot: PflashSnapshot = AnySnapshot::from_any(data)?;
+        if snapshot.image.len() as u64 != self.image_size {
+            bail!(
+                "pflash image size {} doesn't match the snapshot image size {}",
+                self.image_size,
+                snapshot.image.len()
+            );
+        }
+        self.image
+            .write_all_at_volatile(VolatileSlice::new(&mut snapshot.image), 0)
+            .context("failed to restore pflash image")?;
+        self.status = snapshot.status;
+        self.state = snapshot.state;
         Ok(())
     }
 
@@ -415,4 +471,45 @@ mod tests {
         let new_size = pflash.image.get_len().unwrap();
         assert_eq!(new_size, IMAGE_SIZE as u64);
     }
+
+    #[test]
+    fn snapshot_restore() {
+        let want = [0xdeu8];
+        let offset = 0x1000;
+
+        let mut pflash = new(empty_image());
+        pflash.write(off(offset), &[COMMAND_WRITE_BYTE]);
+        pflash.write(off(offset), &want);
+        let snapshot = pflash.snapshot().unwrap();
+
+        // The variables written after the snapshot must be rolled back on restore.
+        pflash.write(off(offset), &[COMMAND_BLOCK_ERASE]);
+        pflash.write(off(offset), &[COMMAND_BLOCK_ERASE_CONFIRM]);
+
+        let mut restored = new(empty_image());
+        restored.restore(snapshot).unwrap();
+        let mut got = [0u8; 1];
+        restored.read(off(offset), &mut got);
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn create_from_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("OVMF_VARS.fd");
+        fs::write(&template, b"vars").unwrap();
+        let params = PflashParameters {
+            path: dir.path().join("vars.fd"),
+            block_size: BLOCK_SIZE,
+            template: Some(template),
+        };
+
+        params.create_from_template().unwrap();
+        assert_eq!(fs::read(&params.path).unwrap(), b"vars");
+
+        // An existing image is kept.
+        fs::write(&params.path, b"saved").unwrap();
+        params.create_from_template().unwrap();
+        assert_eq!(fs::read(&params.path).unwrap(), b"saved");
+    }
 }
@@ -0,0 +1,402 @@
+// Copyright 2026 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Provisioning of the UEFI Secure Boot keys in an OVMF variable store.
+//!
+//! The variables are written directly into the authenticated variable store of the pflash image
+//! before the VM starts, so no signed `SetVariable()` payloads are needed. Once PK is enrolled,
+//! OVMF leaves setup mode and enforces Secure Boot.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::Datelike;
+use chrono::Timelike;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+
+const FV_SIGNATURE_OFFSET: usize = 0x28;
+const FV_SIGNATURE: &[u8; 4] = b"_FVH";
+const FV_HEADER_LENGTH_OFFSET: usize = 0x30;
+
+// VARIABLE_STORE_HEADER
+const STORE_HEADER_SIZE: usize = 28;
+const STORE_SIZE_OFFSET: usize = 16;
+
+// AUTHENTICATED_VARIABLE_HEADER
+const VARIABLE_HEADER_SIZE: usize = 60;
+const VARIABLE_START_ID: u16 = 0x55aa;
+const VARIABLE_ALIGNMENT: usize = 4;
+const VAR_ADDED: u8 = 0x3f;
+const VAR_IN_DELETED_TRANSITION: u8 = 0xfe;
+
+const EFI_VARIABLE_NON_VOLATILE: u32 = 0x1;
+const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+const EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS: u32 = 0x20;
+
+const EFI_AUTHENTICATED_VARIABLE_GUID: [u8; 16] = guid(
+    0xaaf32c78,
+    0x947b,
+    0x439a,
+    [0xa1, 0x80, 0x2e, 0x14, 0x4e, 0xc3, 0x77, 0x92],
+);
+const EFI_GLOBAL_VARIABLE_GUID: [u8; 16] = guid(
+    0x8be4df61,
+    0x93ca,
+    0x11d2,
+    [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+);
+const EFI_IMAGE_SECURITY_DATABASE_GUID: [u8; 16] = guid(
+    0xd719b2cb,
+    0x3d3a,
+    0x4596,
+    [0xa3, 0xbc, 0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f],
+);
+const EFI_SECURE_BOOT_ENABLE_DISABLE_GUID: [u8; 16] = guid(
+    0xf0a30bc7,
+    0xaf08,
+    0x4556,
+    [0x99, 0xc4, 0x00, 0x10, 0x09, 0xc9, 0x3a, 0x44],
+);
+const EFI_CERT_X509_GUID: [u8; 16] = guid(
+    0xa5c059a1,
+    0x94e4,
+    0x4aa7,
+    [0x87, 0xb5, 0xab, 0x15, 0x5c, 0x2b, 0xf0, 0x72],
+);
+// Owner of the signatures enrolled by crosvm.
+const CROSVM_SIGNATURE_OWNER_GUID: [u8; 16] = guid(
+    0x2a3a4f1c,
+    0x6e0b,
+    0x4c55,
+    [0x9a, 0x3d, 0x7b, 0x1e, 0x52, 0x0c, 0x8f, 0x61],
+);
+
+/// Encodes a GUID the way UEFI stores it in memory.
+const fn guid(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> [u8; 16] {
+    let d1 = d1.to_le_bytes();
+    let d2 = d2.to_le_bytes();
+    let d3 = d3.to_le_bytes();
+    [
+        d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1], d4[0], d4[1], d4[2], d4[3], d4[4],
+        d4[5], d4[6], d4[7],
+    ]
+}
+
+/// DER-encoded X.509 certificates to enroll as the Secure Boot keys of an OVMF variable store.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecureBootParameters {
+    /// Platform key.
+    pub pk: PathBuf,
+    /// Key exchange keys.
+    #[serde(default)]
+    pub kek: Vec<PathBuf>,
+    /// Keys of the authorized signature database.
+    #[serde(default)]
+    pub db: Vec<PathBuf>,
+}
+
+impl SecureBootParameters {
+    /// Enrolls the keys in the variable store at `varstore` unless a platform key is already
+    /// enrolled, which happens on the first boot of the VM. Returns whether the keys were
+    /// enrolled.
+    pub fn provision(&self, varstore: &Path) -> anyhow::Result<bool> {
+        let mut image = fs::read(varstore)
+            .with_context(|| format!("failed to read varstore {}", varstore.display()))?;
+        if !self.provision_image(&mut image)? {
+            return Ok(false);
+        }
+        fs::write(varstore, image)
+            .with_context(|| format!("failed to write varstore {}", varstore.display()))?;
+        Ok(true)
+    }
+
+    fn provision_image(&self, image: &mut [u8]) -> anyhow::Result<bool> {
+        let mut store = VariableStore::new(image)?;
+        if store.contains(&EFI_GLOBAL_VARIABLE_GUID, "PK") {
+            return Ok(false);
+        }
+
+        let read_certs = |paths: &[PathBuf]| -> anyhow::Result<Vec<u8>> {
+            let mut lists = Vec::new();
+            for path in paths {
+                let cert = fs::read(path)
+                    .with_context(|| format!("failed to read certificate {}", path.display()))?;
+                lists.extend(x509_signature_list(&cert)?);
+            }
+            Ok(lists)
+        };
+
+        let authenticated = EFI_VARIABLE_NON_VOLATILE
+            | EFI_VARIABLE_BOOTSERVICE_ACCESS
+            | EFI_VARIABLE_RUNTIME_ACCESS
+            | EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS;
+        // PK goes last so that OVMF doesn't see a half-provisioned store in user mode.
+        if !self.db.is_empty() {
+            store.append(
+                &EFI_IMAGE_SECURITY_DATABASE_GUID,
+                "db",
+                authenticated,
+                &read_certs(&self.db)?,
+            )?;
+        }
+        if !self.kek.is_empty() {
+            store.append(
+                &EFI_GLOBAL_VARIABLE_GUID,
+                "KEK",
+                authenticated,
+                &read_certs(&self.kek)?,
+            )?;
+        }
+        store.append(
+            &EFI_SECURE_BOOT_ENABLE_DISABLE_GUID,
+            "SecureBootEnable",
+            EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS,
+            &[1],
+        )?;
+        store.append(
+            &EFI_GLOBAL_VARIABLE_GUID,
+            "PK",
+            authenticated,
+            &read_certs(std::slice::from_ref(&self.pk))?,
+        )?;
+        Ok(true)
+    }
+}
+
+/// Wraps a DER-encoded X.509 certificate in an EFI_SIGNATURE_LIST.
+fn x509_signature_list(cert: &[u8]) -> anyhow::Result<Vec<u8>> {
+    // Every DER certificate starts with a SEQUENCE tag; this catches PEM files.
+    if cert.first() != Some(&0x30) {
+        bail!("certificate is not DER-encoded");
+    }
+    let signature_size = CROSVM_SIGNATURE_OWNER_GUID.len() + cert.len();
+    let list_size = 16 + 3 * 4 + signature_size;
+
+    let mut list = Vec::with_capacity(list_size);
+    list.extend_from_slice(&EFI_CERT_X509_GUID);
+    list.extend_from_slice(&u32::try_from(list_size)?.to_le_bytes());
+    // SignatureHeaderSize
+    list.extend_from_slice(&0u32.to_le_bytes());
+    list.extend_from_slice(&u32::try_from(signature_size)?.to_le_bytes());
+    list.extend_from_slice(&CROSVM_SIGNATURE_OWNER_GUID);
+    list.extend_from_slice(cert);
+    Ok(list)
+}
+
+/// Returns the current time as an EFI_TIME.
+fn efi_time_now() -> [u8; 16] {
+    let now = Utc::now();
+    let mut time = [0u8; 16];
+    time[0..2].copy_from_slice(&(now.year() as u16).to_le_bytes());
+    time[2] = now.month() as u8;
+    time[3] = now.day() as u8;
+    time[4] = now.hour() as u8;
+    time[5] = now.minute() as u8;
+    time[6] = now.second() as u8;
+    time
+}
+
+/// The authenticated variable store of an OVMF firmware volume.
+struct VariableStore<'a> {
+    image: &'a mut [u8],
+    // Offset of the first byte after the last variable.
+    end: usize,
+    // Offset of the first byte after the store.
+    limit: usize,
+    variables: Vec<(usize, usize)>,
+}
+
+impl<'a> VariableStore<'a> {
+    fn new(image: &'a mut [u8]) -> anyhow::Result<Self> {
+        if image.get(FV_SIGNATURE_OFFSET..FV_SIGNATURE_OFFSET + 4) != Some(&FV_SIGNATURE[..]) {
+            bail!("varstore is not a firmware volume");
+        }
+        let store = image
+            .get(FV_HEADER_LENGTH_OFFSET..FV_HEADER_LENGTH_OFFSET + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .context("varstore header is truncated")?;
+        let header = image
+            .get(store..store + STORE_HEADER_SIZE)
+            .context("varstore header is truncated")?;
+        if header[..16] != EFI_AUTHENTICATED_VARIABLE_GUID {
+            bail!("varstore doesn't hold authenticated variables");
+        }
+        let size = u32::from_le_bytes(
+            header[STORE_SIZE_OFFSET..STORE_SIZE_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let limit = (store + size).min(image.len());
+
+        let mut variables = Vec::new();
+        let mut offset = store + STORE_HEADER_SIZE;
+        while offset + VARIABLE_HEADER_SIZE <= limit {
+            let variable = &image[offset..offset + VARIABLE_HEADER_SIZE];
+            if u16::from_le_bytes([variable[0], variable[1]]) != VARIABLE_START_ID {
+                break;
+            }
+            let name_size = u32::from_le_bytes(variable[40..44].try_into().unwrap()) as usize;
+            let data_size = u32::from_le_bytes(variable[44..48].try_into().unwrap()) as usize;
+            let state = variable[2];
+            if state == VAR_ADDED || state == VAR_ADDED & VAR_IN_DELETED_TRANSITION {
+                variables.push((offset, name_size));
+            }
+            offset = (offset + VARIABLE_HEADER_SIZE + name_size + data_size)
+                .next_multiple_of(VARIABLE_ALIGNMENT);
+        }
+
+        Ok(VariableStore {
+            image,
+            end: offset,
+            limit,
+            variables,
+        })
+    }
+
+    fn contains(&self, vendor: &[u8; 16], name: &str) -> bool {
+        let name = utf16_name(name);
+        self.variables.iter().any(|&(offset, name_size)| {
+            let header = &self.image[offset..offset + VARIABLE_HEADER_SIZE];
+            header[44..60] == vendor[..]
+                && self.image[offset + VARIABLE_HEADER_SIZE..][..name_size] == name[..]
+        })
+    }
+
+    fn append(
+        &mut self,
+        vendor: &[u8; 16],
+        name: &str,
+        attributes: u32,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let name = utf16_name(name);
+        let size = VARIABLE_HEADER_SIZE + name.len() + data.len();
+        if self.end + size > self.limit {
+            bail!("varstore is full");
+        }
+
+        let mut variable = Vec::with_capacity(size);
+        variable.extend_from_slice(&VARIABLE_START_ID.to_le_bytes());
+        variable.push(VAR_ADDED);
+        // Reserved
+        variable.push(0);
+        variable.extend_from_slice(&attributes.to_le_bytes());
+        // MonotonicCount
+        variable.extend_from_slice(&0u64.to_le_bytes());
+        if attributes & EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0 {
+            variable.extend_from_slice(&efi_time_now());
+        } else {
+            variable.extend_from_slice(&[0; 16]);
+        }
+        // PubKeyIndex
+        variable.extend_from_slice(&0u32.to_le_bytes());
+        variable.extend_from_slice(&u32::try_from(name.len())?.to_le_bytes());
+        variable.extend_from_slice(&u32::try_from(data.len())?.to_le_bytes());
+        variable.extend_from_slice(vendor);
+        variable.extend_from_slice(&name);
+        variable.extend_from_slice(data);
+
+        self.image[self.end..self.end + size].copy_from_slice(&variable);
+        self.variables.push((self.end, name.len()));
+        self.end = (self.end + size).next_multiple_of(VARIABLE_ALIGNMENT);
+        Ok(())
+    }
+}
+
+/// Encodes a variable name as a NUL-terminated UTF-16 string.
+fn utf16_name(name: &str) -> Vec<u8> {
+    name.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORE_OFFSET: usize = 0x48;
+    const IMAGE_SIZE: usize = 0x1000;
+
+    fn empty_varstore() -> Vec<u8> {
+        let mut image = vec![0xff; IMAGE_SIZE];
+        image[FV_SIGNATURE_OFFSET..FV_SIGNATURE_OFFSET + 4].copy_from_slice(FV_SIGNATURE);
+        image[FV_HEADER_LENGTH_OFFSET..FV_HEADER_LENGTH_OFFSET + 2]
+            .copy_from_slice(&(STORE_OFFSET as u16).to_le_bytes());
+        image[STORE_OFFSET..STORE_OFFSET + 16].copy_from_slice(&EFI_AUTHENTICATED_VARIABLE_GUID);
+        image[STORE_OFFSET + STORE_SIZE_OFFSET..STORE_OFFSET + STORE_SIZE_OFFSET + 4]
+            .copy_from_slice(&((IMAGE_SIZE - STORE_OFFSET) as u32).to_le_bytes());
+        image
+    }
+
+    fn params(dir: &Path) -> SecureBootParameters {
+        let cert = |name: &str| {
+            let path = dir.join(name);
+            fs::write(&path, [0x30, 0x03, 0x02, 0x01, 0x01]).unwrap();
+            path
+        };
+        SecureBootParameters {
+            pk: cert("pk.der"),
+            kek: vec![cert("kek.der")],
+            db: vec![cert("db1.der"), cert("db2.der")],
+        }
+    }
+
+    #[test]
+    fn provision_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = params(dir.path());
+        let mut image = empty_varstore();
+
+        assert!(params.provision_image(&mut image).unwrap());
+        let store = VariableStore::new(&mut image).unwrap();
+        assert!(store.contains(&EFI_GLOBAL_VARIABLE_GUID, "PK"));
+        assert!(store.contains(&EFI_GLOBAL_VARIABLE_GUID, "KEK"));
+        assert!(store.contains(&EFI_IMAGE_SECURITY_DATABASE_GUID, "db"));
+        assert!(store.contains(&EFI_SECURE_BOOT_ENABLE_DISABLE_GUID, "SecureBootEnable"));
+        assert!(!store.contains(&EFI_GLOBAL_VARIABLE_GUID, "db"));
+        assert_eq!(store.variables.len(), 4);
+
+        // The keys are only enrolled on the first boot.
+        let provisioned = image.clone();
+        assert!(!params.provision_image(&mut image).unwrap());
+        assert_eq!(image, provisioned);
+    }
+
+    #[test]
+    fn signature_list() {
+        let cert = [0x30, 0x00];
+        let list = x509_signature_list(&cert).unwrap();
+        assert_eq!(list.len(), 46);
+        assert_eq!(list[..16], EFI_CERT_X509_GUID);
+        assert_eq!(list[16..20], 46u32.to_le_bytes());
+        assert_eq!(list[24..28], 18u32.to_le_bytes());
+        assert_eq!(list[44..], cert);
+
+        assert!(x509_signature_list(b"-----BEGIN CERTIFICATE-----").is_err());
+    }
+
+    #[test]
+    fn full_varstore() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = params(dir.path());
+        let mut image = empty_varstore();
+        image.truncate(STORE_OFFSET + STORE_HEADER_SIZE + VARIABLE_HEADER_SIZE);
+        assert!(params.provision_image(&mut image).is_err());
+    }
+
+    #[test]
+    fn not_a_varstore() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = params(dir.path());
+        assert!(params.provision_image(&mut [0xff; IMAGE_SIZE]).is_err());
+    }
+}
@@ -3,6 +3,8 @@
 // found in the LICENSE file.
 
 use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::bail;
@@ -54,6 +56,8 @@ pub struct VfioPlatformDevice {
     vm_memory_client: VmMemoryClient,
     // scratch MemoryMapping to avoid unmap beform vm exit
     mem: Vec<MemoryMapping>,
+    // node of the device in the host device tree, to generate the guest node from
+    host_dt_node: Option<PathBuf>,
 }
 
 impl BusDevice for VfioPlatformDevice {
@@ -90,7 +94,14 @@ impl BusDeviceObj for VfioPlatformDevice {
 
 impl VfioPlatformDevice {
     /// Constructs a new Vfio Platform device for the given Vfio device
-    pub fn new(device: VfioDevice, vm_memory_client: VmMemoryClient) -> Self {
+    ///
+    /// `host_dt_node` is the sysfs directory of the device node in the host device tree, if the
+    /// guest device tree node should be generated from it.
+    pub fn new(
+        device: VfioDevice,
+        vm_memory_client: VmMemoryClient,
+        host_dt_node: Option<PathBuf>,
+    ) -> Self {
         let dev = Arc::new(device);
         VfioPlatformDevice {
             device: dev,
@@ -99,6 +110,7 @@ impl VfioPlatformDevice {
             mmio_regions: Vec::new(),
             vm_memory_client,
             mem: Vec::new(),
+            host_dt_node,
         }
     }
 
@@ -364,6 +376,12 @@ impl VfioPlatformDevice {
         self.device.dt_symbol()
     }
 
+    /// Returns the node of the device in the host device tree, if the guest node should be
+    /// generated from it.
+    pub fn host_dt_node(&self) -> Option<&Path> {
+        self.host_dt_node.as_deref()
+    }
+
     /// Returns the type and indentifier (if applicable) of the IOMMU used by this VFIO device and
     /// its master IDs.
     pub fn iommu(&self) -> Option<(IommuDevType, Option<u32>, &[u32])> {
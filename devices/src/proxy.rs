@@ -23,6 +23,7 @@ use base::SharedMemory;
 use base::Tube;
 use base::TubeError;
 use jail::fork::fork_process;
+use jail::landlock::LandlockRuleset;
 use libc::pid_t;
 use minijail::Minijail;
 use remain::sorted;
@@ -31,6 +32,7 @@ use serde::Serialize;
 use snapshot::AnySnapshot;
 use tempfile::tempfile;
 use thiserror::Error;
+use vm_control::DeviceDetails;
 
 use crate::bus::ConfigWriteResult;
 use crate::pci::CrosvmDeviceId;
@@ -50,6 +52,8 @@ pub enum Error {
     ActivatingProxyDevice,
     #[error("Failed to fork jail process: {0}")]
     ForkingJail(#[from] minijail::Error),
+    #[error("Failed to build the Landlock ruleset: {0}")]
+    Landlock(std::io::Error),
     #[error("Failed to configure swap: {0}")]
     Swap(anyhow::Error),
     #[error("Failed to configure tube: {0}")]
@@ -144,6 +148,7 @@ enum Command {
     DestroyDevice,
     Shutdown,
     GetRanges,
+    GetDeviceDetails,
     Snapshot {
         // NOTE: the SnapshotFile is created by the parent and sent to the child proxied device
         // as the jailed child may not have permission to create a temp file.
@@ -171,6 +176,7 @@ enum CommandResult {
     InitPciConfigMappingResult(bool),
     ReadVirtualConfigResult(u32),
     GetRangesResult(Vec<(BusRange, BusType)>),
+    GetDeviceDetailsResult(DeviceDetails),
     SnapshotResult(std::result::Result<SnapshotFile, String>),
     RestoreResult(std::result::Result<(), String>),
     SleepResult(std::result::Result<(), String>),
@@ -282,6 +288,10 @@ fn child_proc<D: BusDevice>(tube: Tube, mut device: D) {
                 let ranges = device.get_ranges();
                 tube.send(&CommandResult::GetRangesResult(ranges))
             }
+            Command::GetDeviceDetails => {
+                let details = device.device_details();
+                tube.send(&CommandResult::GetDeviceDetailsResult(details))
+            }
             Command::Snapshot { mut snapshot } => {
                 let res = device.snapshot().and_then(|data| {
                     snapshot.write(data)?;
@@ -326,6 +336,7 @@ pub struct ChildProcIntf {
     tube: Tube,
     pid: pid_t,
     debug_label: String,
+    device_type: DeviceId,
 }
 
 impl ChildProcIntf {
@@ -347,6 +358,7 @@ impl ChildProcIntf {
         #[cfg(feature = "swap")] swap_prepare_fork: &mut Option<P>,
     ) -> Result<ChildProcIntf> {
         let debug_label = device.debug_label();
+        let device_type = device.device_type();
         let (child_tube, parent_tube) = Tube::pair()?;
 
         keep_rds.push(child_tube.as_raw_descriptor());
@@ -372,6 +384,21 @@ impl ChildProcIntf {
             }
         }
 
+        // The ruleset is built from host paths here, before the child enters its jail.
+        let landlock_ruleset = match device.landlock_rules() {
+            Some(rules) => {
+                let ruleset = LandlockRuleset::from_rules(&rules).map_err(Error::Landlock)?;
+                if ruleset.is_none() {
+                    info!("Landlock is not supported, {} is not confined", debug_label);
+                }
+                ruleset
+            }
+            None => None,
+        };
+        if let Some(ruleset) = &landlock_ruleset {
+            keep_rds.push(ruleset.as_raw_descriptor());
+        }
+
         let child_process = fork_process(jail, keep_rds, Some(debug_label.clone()), || {
             #[cfg(feature = "swap")]
             if let Some(swap_device_uffd_sender) = swap_device_uffd_sender {
@@ -383,6 +410,17 @@ impl ChildProcIntf {
                 }
             }
 
+            // Confine the process before any device thread is started, so that they all inherit
+            // the restriction.
+            if let Some(ruleset) = landlock_ruleset {
+                if let Err(e) = ruleset.restrict_self() {
+                    error!("failed to enforce the Landlock ruleset: {}", e);
+                    // SAFETY:
+                    // exit() is trivially safe.
+                    unsafe { libc::exit(1) };
+                }
+            }
+
             device.on_sandboxed();
             child_proc(child_tube, device);
 
@@ -407,6 +445,7 @@ impl ChildProcIntf {
             tube: parent_tube,
             pid,
             debug_label,
+            device_type,
         })
     }
 }
@@ -495,6 +534,10 @@ impl BusDevice for ProxyDevice {
         CrosvmDeviceId::ProxyDevice.into()
     }
 
+    fn device_type(&self) -> DeviceId {
+        self.child_proc_intf.device_type
+    }
+
     fn debug_label(&self) -> String {
         self.child_proc_intf.debug_label.clone()
     }
@@ -595,6 +638,16 @@ impl BusDevice for ProxyDevice {
         }
     }
 
+    fn device_details(&self) -> DeviceDetails {
+        if let Some(CommandResult::GetDeviceDetailsResult(details)) =
+            self.sync_send(&Command::GetDeviceDetails)
+        {
+            details
+        } else {
+            Default::default()
+        }
+    }
+
     fn destroy_device(&mut self) {
         self.send_no_result(&Command::DestroyDevice);
     }
@@ -94,9 +94,9 @@ impl RegisterValue for u16 {}
 impl RegisterValue for u32 {}
 impl RegisterValue for u64 {}
 
-// Helper function to read a register. If the read range overlaps with value's range, it will load
-// corresponding bytes into data.
-fn read_reg_helper<T: RegisterValue>(
+/// Helper function to read a register. If the read range overlaps with value's range, it will load
+/// corresponding bytes into data.
+pub fn read_reg_helper<T: RegisterValue>(
     val: T,
     val_range: RegisterRange,
     addr: RegisterOffset,
@@ -37,6 +37,8 @@ use crate::PciAddress;
 #[sorted]
 #[derive(ThisError, Debug)]
 pub enum Error {
+    #[error("Serial device type tcp requires an addr")]
+    AddrRequired,
     #[error("Unable to clone an Event: {0}")]
     CloneEvent(base::Error),
     #[error("Unable to clone a Unix Stream: {0}")]
@@ -47,6 +49,8 @@ pub enum Error {
     FileCreate(std::io::Error, PathBuf),
     #[error("Unable to open file '{1}': {0}")]
     FileOpen(std::io::Error, PathBuf),
+    #[error("Invalid TCP address '{1}': {0}")]
+    InvalidAddr(std::io::Error, String),
     #[error("Invalid serial config specified: {0}")]
     InvalidConfig(String),
     #[error("Serial device path '{0} is invalid")]
@@ -61,8 +65,12 @@ pub enum Error {
     SocketConnect(std::io::Error),
     #[error("Failed to create unbound socket: {0}")]
     SocketCreate(std::io::Error),
+    #[error("Failed to spawn thread: {0}")]
+    SpawnThread(std::io::Error),
     #[error("Unable to open system type serial: {0}")]
     SystemTypeError(std::io::Error),
+    #[error("Failed to listen on TCP address: {0}")]
+    TcpListen(std::io::Error),
     #[error("Serial device type {0} not implemented")]
     Unimplemented(SerialType),
 }
@@ -89,6 +97,12 @@ pub enum SerialType {
     // Use the same Unix domain socket for input and output.
     #[cfg(unix)]
     UnixStream,
+    // Relay input and output over a TCP connection, optionally using the telnet protocol.
+    #[cfg(unix)]
+    Tcp,
+    // Write timestamped lines to a file that is rotated by size.
+    #[cfg(unix)]
+    LogFile,
 }
 
 impl Default for SerialType {
@@ -107,6 +121,10 @@ impl Display for SerialType {
             SerialType::SystemSerialType => SYSTEM_SERIAL_TYPE_NAME.to_string(),
             #[cfg(unix)]
             SerialType::UnixStream => "UnixStream".to_string(),
+            #[cfg(unix)]
+            SerialType::Tcp => "Tcp".to_string(),
+            #[cfg(unix)]
+            SerialType::LogFile => "LogFile".to_string(),
         };
 
         write!(f, "{}", s)
@@ -168,6 +186,22 @@ pub struct SerialParameters {
     /// This flag can be used only when `type_` is `UnixStream`.
     #[cfg(unix)]
     pub input_unix_stream: bool,
+    /// Address to listen on or connect to, as `host:port`.
+    /// Only valid when `type_` is `Tcp`.
+    #[cfg(unix)]
+    pub addr: Option<String>,
+    /// Listen on `addr` for a client instead of connecting to it.
+    #[cfg(unix)]
+    pub listen: bool,
+    /// Speak the telnet protocol on the TCP connection.
+    #[cfg(unix)]
+    pub telnet: bool,
+    /// Rotate the log once it reaches this many bytes. Only valid when `type_` is `LogFile`.
+    #[cfg(unix)]
+    pub rotate_size: Option<u64>,
+    /// Number of rotated logs to keep. Only valid when `type_` is `LogFile`.
+    #[cfg(unix)]
+    pub rotate_count: Option<u32>,
     #[serde(default = "serial_parameters_default_num")]
     pub num: u8,
     pub console: bool,
@@ -182,6 +216,9 @@ pub struct SerialParameters {
     pub debugcon_port: u16,
     pub pci_address: Option<PciAddress>,
     pub max_queue_sizes: Option<Vec<u16>>,
+    /// Enable multiport with room for this many ports, so that ports can be added at runtime.
+    /// Only valid for `hardware=virtio-console`.
+    pub max_ports: Option<u32>,
 }
 
 /// Temporary structure containing the parameters of a serial port for easy passing to
@@ -193,6 +230,7 @@ pub struct SerialOptions {
     pub console: bool,
     pub pci_address: Option<PciAddress>,
     pub max_queue_sizes: Option<Vec<u16>>,
+    pub max_ports: Option<u32>,
 }
 
 impl SerialParameters {
@@ -290,6 +328,21 @@ impl SerialParameters {
                 keep_rds.push(output.as_raw_descriptor());
                 (Some(Box::new(output)), None)
             }
+            #[cfg(unix)]
+            SerialType::Tcp => {
+                if input.is_some() {
+                    return Err(Error::InvalidConfig(
+                        "type=tcp can't be used with input or stdin".to_string(),
+                    ));
+                }
+                return create_tcp_serial_device(self, protection_type, evt, keep_rds);
+            }
+            #[cfg(unix)]
+            SerialType::LogFile => {
+                let output = create_log_file_output(self)?;
+                keep_rds.push(output.as_raw_descriptor());
+                (Some(Box::new(output)), None)
+            }
         };
         Ok(T::new(
             protection_type,
@@ -303,6 +356,7 @@ impl SerialParameters {
                 console: self.console,
                 pci_address: self.pci_address,
                 max_queue_sizes: self.max_queue_sizes.clone(),
+                max_ports: self.max_ports,
             },
             keep_rds.to_vec(),
         ))
@@ -333,6 +387,16 @@ mod tests {
                 input: None,
                 #[cfg(unix)]
                 input_unix_stream: false,
+                #[cfg(unix)]
+                addr: None,
+                #[cfg(unix)]
+                listen: false,
+                #[cfg(unix)]
+                telnet: false,
+                #[cfg(unix)]
+                rotate_size: None,
+                #[cfg(unix)]
+                rotate_count: None,
                 num: 1,
                 console: false,
                 earlycon: false,
@@ -341,6 +405,7 @@ mod tests {
                 debugcon_port: 0x402,
                 pci_address: None,
                 max_queue_sizes: None,
+                max_ports: None,
             }
         );
 
@@ -363,6 +428,10 @@ mod tests {
         {
             let params = from_serial_arg("type=unix-stream").unwrap();
             assert_eq!(params.type_, SerialType::UnixStream);
+            let params = from_serial_arg("type=tcp").unwrap();
+            assert_eq!(params.type_, SerialType::Tcp);
+            let params = from_serial_arg("type=log-file").unwrap();
+            assert_eq!(params.type_, SerialType::LogFile);
         }
         let params = from_serial_arg("type=foobar");
         assert!(params.is_err());
@@ -402,6 +471,28 @@ mod tests {
             assert!(params.is_err());
         }
 
+        #[cfg(unix)]
+        {
+            // addr, listen and telnet parameters
+            let params = from_serial_arg("type=tcp,addr=127.0.0.1:4555,listen,telnet").unwrap();
+            assert_eq!(params.addr.as_deref(), Some("127.0.0.1:4555"));
+            assert!(params.listen);
+            assert!(params.telnet);
+            let params = from_serial_arg("type=tcp,addr=localhost:4555").unwrap();
+            assert_eq!(params.addr.as_deref(), Some("localhost:4555"));
+            assert!(!params.listen);
+            assert!(!params.telnet);
+
+            // rotate-size and rotate-count parameters
+            let params =
+                from_serial_arg("type=log-file,path=/tmp/log,rotate-size=1048576,rotate-count=3")
+                    .unwrap();
+            assert_eq!(params.rotate_size, Some(1048576));
+            assert_eq!(params.rotate_count, Some(3));
+            let params = from_serial_arg("rotate-size=foobar");
+            assert!(params.is_err());
+        }
+
         // console parameter
         let params = from_serial_arg("console").unwrap();
         assert!(params.console);
@@ -452,8 +543,14 @@ mod tests {
         let params = from_serial_arg("debugcon_port=1026").unwrap();
         assert_eq!(params.debugcon_port, 1026);
 
+        // max-ports parameter
+        let params = from_serial_arg("max-ports=4").unwrap();
+        assert_eq!(params.max_ports, Some(4));
+        let params = from_serial_arg("max-ports=foobar");
+        assert!(params.is_err());
+
         // all together
-        let params = from_serial_arg("type=stdout,path=/some/path,hardware=virtio-console,num=5,earlycon,console,stdin,input=/some/input,out_timestamp,debugcon_port=12,pci-address=00:0e.0,max-queue-sizes=[1,2]").unwrap();
+        let params = from_serial_arg("type=stdout,path=/some/path,hardware=virtio-console,num=5,earlycon,console,stdin,input=/some/input,out_timestamp,debugcon_port=12,pci-address=00:0e.0,max-queue-sizes=[1,2],max-ports=2").unwrap();
         assert_eq!(
             params,
             SerialParameters {
@@ -464,6 +561,16 @@ mod tests {
                 input: Some("/some/input".into()),
                 #[cfg(unix)]
                 input_unix_stream: false,
+                #[cfg(unix)]
+                addr: None,
+                #[cfg(unix)]
+                listen: false,
+                #[cfg(unix)]
+                telnet: false,
+                #[cfg(unix)]
+                rotate_size: None,
+                #[cfg(unix)]
+                rotate_count: None,
                 num: 5,
                 console: true,
                 earlycon: true,
@@ -471,11 +578,13 @@ mod tests {
                 out_timestamp: true,
                 debugcon_port: 12,
                 pci_address: Some(PciAddress {
+                    domain: 0,
                     bus: 0,
                     dev: 14,
                     func: 0
                 }),
                 max_queue_sizes: Some(vec![1, 2]),
+                max_ports: Some(2),
             }
         );
 
@@ -0,0 +1,1137 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Emulated Arm SMMUv3, for guests whose kernels expect a standard SMMU rather than virtio-iommu.
+//!
+//! Only stage-1 translation with AArch64 translation tables is supported. The SMMU translates the
+//! DMA of the same endpoints as virtio-iommu: devices which access guest memory through an
+//! `IpcMemoryMapper`, backed by a `MemoryMapperTrait` per endpoint. When an endpoint exports a
+//! region, the SMMU walks the guest's stream table, context descriptor and translation tables, and
+//! caches the resulting translations in the endpoint's mapper. Cached translations are dropped
+//! when the guest invalidates them through the command queue, which faults any endpoint still
+//! using them. Translation faults are reported to the guest through the event queue.
+//!
+//! Unlike virtio-iommu, the SMMU is not backed by the host IOMMU and doesn't translate the DMA of
+//! VFIO devices: the guest doesn't notify the SMMU when it maps memory, so the host IOMMU can't be
+//! programmed before the device's DMA.
+
+mod page_table;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::warn;
+use base::AsRawDescriptor;
+use base::Event;
+use base::Protection;
+use base::RawDescriptor;
+use base::Tube;
+use base::TubeError;
+use base::WorkerThread;
+use cros_async::AsyncTube;
+use cros_async::EventAsync;
+use cros_async::Executor;
+use futures::select;
+use futures::FutureExt;
+use sync::Mutex;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+
+use self::page_table::Granule;
+use self::page_table::Translation;
+use self::page_table::TranslationConfig;
+use self::page_table::WalkFault;
+use crate::pci::CrosvmDeviceId;
+use crate::virtio::async_utils;
+use crate::virtio::ipc_memory_mapper::IommuRequest;
+use crate::virtio::ipc_memory_mapper::IommuResponse;
+use crate::virtio::memory_mapper::AddMapResult;
+use crate::virtio::memory_mapper::MappingInfo;
+use crate::virtio::memory_mapper::MemRegion;
+use crate::virtio::memory_mapper::MemoryMapperTrait;
+use crate::virtio::memory_mapper::RemoveMapResult;
+use crate::BusAccessInfo;
+use crate::BusDevice;
+use crate::DeviceId;
+use crate::IrqEdgeEvent;
+use crate::Suspendable;
+
+/// Size of the MMIO region of the SMMU: register pages 0 and 1.
+pub const SMMUV3_MMIO_LEN: u64 = 0x20000;
+
+// Register offsets.
+const SMMU_IDR0: u64 = 0x0;
+const SMMU_IDR1: u64 = 0x4;
+const SMMU_IDR2: u64 = 0x8;
+const SMMU_IDR3: u64 = 0xc;
+const SMMU_IDR4: u64 = 0x10;
+const SMMU_IDR5: u64 = 0x14;
+const SMMU_IIDR: u64 = 0x18;
+const SMMU_AIDR: u64 = 0x1c;
+const SMMU_CR0: u64 = 0x20;
+const SMMU_CR0ACK: u64 = 0x24;
+const SMMU_CR1: u64 = 0x28;
+const SMMU_CR2: u64 = 0x2c;
+const SMMU_STATUSR: u64 = 0x40;
+const SMMU_GBPA: u64 = 0x44;
+const SMMU_IRQ_CTRL: u64 = 0x50;
+const SMMU_IRQ_CTRLACK: u64 = 0x54;
+const SMMU_GERROR: u64 = 0x60;
+const SMMU_GERRORN: u64 = 0x64;
+const SMMU_STRTAB_BASE: u64 = 0x80;
+const SMMU_STRTAB_BASE_HI: u64 = 0x84;
+const SMMU_STRTAB_BASE_CFG: u64 = 0x88;
+const SMMU_CMDQ_BASE: u64 = 0x90;
+const SMMU_CMDQ_BASE_HI: u64 = 0x94;
+const SMMU_CMDQ_PROD: u64 = 0x98;
+const SMMU_CMDQ_CONS: u64 = 0x9c;
+const SMMU_EVENTQ_BASE: u64 = 0xa0;
+const SMMU_EVENTQ_BASE_HI: u64 = 0xa4;
+const SMMU_EVENTQ_PROD: u64 = 0xa8;
+const SMMU_EVENTQ_CONS: u64 = 0xac;
+// Register page 1 aliases the event queue pointers.
+const SMMU_PAGE1_EVENTQ_PROD: u64 = 0x100a8;
+const SMMU_PAGE1_EVENTQ_CONS: u64 = 0x100ac;
+
+// SMMU_IDR0: linear and 2-level stream tables, terminate model, no stalls, little-endian
+// AArch64 translation tables, 16-bit ASIDs, coherent accesses and stage 1 only.
+const IDR0_VALUE: u32 = 1 << 27 | 1 << 26 | 1 << 24 | 2 << 21 | 1 << 12 | 1 << 4 | 2 << 2 | 1 << 1;
+const CMDQ_MAX_LOG2SIZE: u32 = 8;
+const EVENTQ_MAX_LOG2SIZE: u32 = 7;
+const SID_BITS: u32 = 16;
+const IDR1_VALUE: u32 = CMDQ_MAX_LOG2SIZE << 21 | EVENTQ_MAX_LOG2SIZE << 16 | SID_BITS;
+// SMMU_IDR5: 4KiB, 16KiB and 64KiB granules, 48-bit output addresses.
+const IDR5_VALUE: u32 = 1 << 6 | 1 << 5 | 1 << 4 | 5;
+
+const CR0_SMMUEN: u32 = 1 << 0;
+const CR0_EVENTQEN: u32 = 1 << 2;
+const CR0_CMDQEN: u32 = 1 << 3;
+const GBPA_UPDATE: u32 = 1 << 31;
+const GBPA_ABORT: u32 = 1 << 20;
+const IRQ_CTRL_GERROR_IRQEN: u32 = 1 << 0;
+const IRQ_CTRL_EVENTQ_IRQEN: u32 = 1 << 2;
+const GERROR_CMDQ_ERR: u32 = 1 << 0;
+const QUEUE_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_ffe0;
+const CMDQ_CONS_ERR_SHIFT: u32 = 24;
+const CERROR_ILL: u32 = 1;
+const CERROR_ABT: u32 = 2;
+const EVENTQ_PROD_OVFLG: u32 = 1 << 31;
+const EVENTQ_CONS_OVACKFLG: u32 = 1 << 31;
+
+const STRTAB_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_ffc0;
+const STRTAB_FMT_2LVL: u32 = 1;
+const STE_SIZE: u64 = 64;
+const STE_VALID: u64 = 1 << 0;
+const STE_CONFIG_ABORT: u64 = 0b000;
+const STE_CONFIG_BYPASS: u64 = 0b100;
+const STE_CONFIG_S1_TRANS: u64 = 0b101;
+const STE_CTX_PTR_MASK: u64 = 0x000f_ffff_ffff_ffc0;
+const CD_EPD0: u64 = 1 << 14;
+const CD_VALID: u64 = 1 << 31;
+const CD_AA64: u64 = 1 << 41;
+const CD_TTB_MASK: u64 = 0x000f_ffff_ffff_fff0;
+
+const CMD_SIZE: u64 = 16;
+const CMD_PREFETCH_CONFIG: u8 = 0x01;
+const CMD_PREFETCH_ADDR: u8 = 0x02;
+const CMD_CFGI_STE: u8 = 0x03;
+const CMD_CFGI_STE_RANGE: u8 = 0x04;
+const CMD_CFGI_CD: u8 = 0x05;
+const CMD_CFGI_CD_ALL: u8 = 0x06;
+const CMD_TLBI_NH_ALL: u8 = 0x10;
+const CMD_TLBI_NH_ASID: u8 = 0x11;
+const CMD_TLBI_NH_VA: u8 = 0x12;
+const CMD_TLBI_NH_VAA: u8 = 0x13;
+const CMD_TLBI_S12_VMALL: u8 = 0x28;
+const CMD_TLBI_NSNH_ALL: u8 = 0x30;
+const CMD_SYNC: u8 = 0x46;
+const CMD_TLBI_ADDR_MASK: u64 = !0xfff;
+
+const EVENT_SIZE: u64 = 32;
+const EVT_C_BAD_STREAMID: u8 = 0x02;
+const EVT_F_STE_FETCH: u8 = 0x03;
+const EVT_C_BAD_STE: u8 = 0x04;
+const EVT_F_CD_FETCH: u8 = 0x09;
+const EVT_C_BAD_CD: u8 = 0x0a;
+const EVT_F_WALK_EABT: u8 = 0x0b;
+const EVT_F_TRANSLATION: u8 = 0x10;
+const EVT_F_ADDR_SIZE: u8 = 0x11;
+const EVT_F_ACCESS: u8 = 0x12;
+
+/// Page size of the translations created for streams in bypass.
+const BYPASS_PAGE_SIZE: u64 = 0x1000;
+
+/// A circular queue in guest memory, described by a `*_BASE` register.
+#[derive(Clone, Copy)]
+struct Queue {
+    addr: u64,
+    log2size: u32,
+    entry_size: u64,
+}
+
+impl Queue {
+    fn new(base: u64, max_log2size: u32, entry_size: u64) -> Queue {
+        Queue {
+            addr: base & QUEUE_BASE_ADDR_MASK,
+            log2size: ((base & 0x1f) as u32).min(max_log2size),
+            entry_size,
+        }
+    }
+
+    /// Masks the index and wrap bit out of a `*_PROD` or `*_CONS` register.
+    fn ptr(&self, reg: u32) -> u32 {
+        reg & ((2 << self.log2size) - 1)
+    }
+
+    fn is_empty(&self, prod: u32, cons: u32) -> bool {
+        self.ptr(prod) == self.ptr(cons)
+    }
+
+    fn is_full(&self, prod: u32, cons: u32) -> bool {
+        self.ptr(prod) ^ self.ptr(cons) == 1 << self.log2size
+    }
+
+    fn next(&self, ptr: u32) -> u32 {
+        self.ptr(ptr.wrapping_add(1))
+    }
+
+    fn entry(&self, ptr: u32) -> GuestAddress {
+        let index = ptr & ((1 << self.log2size) - 1);
+        GuestAddress(self.addr + u64::from(index) * self.entry_size)
+    }
+}
+
+/// A fault recorded in the event queue.
+#[derive(Debug)]
+struct FaultEvent {
+    id: u8,
+    sid: u32,
+    iova: u64,
+}
+
+impl FaultEvent {
+    fn new(id: u8, sid: u32) -> FaultEvent {
+        FaultEvent { id, sid, iova: 0 }
+    }
+
+    fn from_walk(fault: WalkFault, sid: u32, iova: u64) -> FaultEvent {
+        let id = match fault {
+            WalkFault::Translation => EVT_F_TRANSLATION,
+            WalkFault::AddressSize => EVT_F_ADDR_SIZE,
+            WalkFault::Access => EVT_F_ACCESS,
+            WalkFault::External => EVT_F_WALK_EABT,
+        };
+        FaultEvent { id, sid, iova }
+    }
+
+    fn to_dwords(&self) -> [u64; 4] {
+        [
+            u64::from(self.id) | u64::from(self.sid) << 32,
+            0,
+            self.iova,
+            0,
+        ]
+    }
+}
+
+/// How a stream's transactions are handled, from its STE and CD.
+enum StreamConfig {
+    Abort,
+    Bypass,
+    Translate {
+        asid: u16,
+        /// `None` if walks of TTB0 are disabled, in which case every access faults.
+        table: Option<TranslationConfig>,
+    },
+}
+
+struct Stream {
+    mapper: Arc<Mutex<Box<dyn MemoryMapperTrait>>>,
+    /// ASID of the translations cached in the mapper, if they were made by a stage-1 walk.
+    asid: Option<u16>,
+    /// Translations cached in the mapper, as IOVA to size.
+    cached: BTreeMap<u64, u64>,
+}
+
+impl Stream {
+    /// Drops all the cached translations. Returns an event to wait on if exported memory was
+    /// affected.
+    fn flush(&mut self) -> Option<EventAsync> {
+        if self.cached.is_empty() {
+            return None;
+        }
+        self.cached.clear();
+        self.asid = None;
+        self.mapper.lock().reset_domain()
+    }
+
+    /// Drops the cached translation of `iova`, if any.
+    fn invalidate(&mut self, iova: u64) -> anyhow::Result<Option<EventAsync>> {
+        let Some((&start, &size)) = self.cached.range(..=iova).next_back() else {
+            return Ok(None);
+        };
+        if start + size <= iova {
+            return Ok(None);
+        }
+        self.cached.remove(&start);
+        match self.mapper.lock().remove_map(start, size)? {
+            RemoveMapResult::Success(fault) => Ok(fault),
+            RemoveMapResult::OverlapFailure => bail!("cached translation {:#x} not mapped", start),
+        }
+    }
+}
+
+fn set_low(reg: &mut u64, value: u32) {
+    *reg = (*reg & !0xffff_ffff) | u64::from(value);
+}
+
+fn set_high(reg: &mut u64, value: u32) {
+    *reg = (*reg & 0xffff_ffff) | u64::from(value) << 32;
+}
+
+#[derive(Default)]
+struct Registers {
+    cr0: u32,
+    cr1: u32,
+    cr2: u32,
+    gbpa: u32,
+    irq_ctrl: u32,
+    gerror: u32,
+    gerrorn: u32,
+    strtab_base: u64,
+    strtab_base_cfg: u32,
+    cmdq_base: u64,
+    cmdq_prod: u32,
+    cmdq_cons: u32,
+    eventq_base: u64,
+    eventq_prod: u32,
+    eventq_cons: u32,
+}
+
+/// State shared by the MMIO handler and the worker thread.
+struct State {
+    mem: GuestMemory,
+    regs: Registers,
+    streams: BTreeMap<u32, Stream>,
+    eventq_irq: Option<IrqEdgeEvent>,
+    gerror_irq: Option<IrqEdgeEvent>,
+    /// Set when a global setting affecting all translations changed, so that the worker drops
+    /// every cached translation before processing further commands.
+    flush_pending: bool,
+}
+
+impl State {
+    fn cmdq(&self) -> Queue {
+        Queue::new(self.regs.cmdq_base, CMDQ_MAX_LOG2SIZE, CMD_SIZE)
+    }
+
+    fn eventq(&self) -> Queue {
+        Queue::new(self.regs.eventq_base, EVENTQ_MAX_LOG2SIZE, EVENT_SIZE)
+    }
+
+    fn gerror_active(&self, bit: u32) -> bool {
+        (self.regs.gerror ^ self.regs.gerrorn) & bit != 0
+    }
+
+    fn raise_gerror(&mut self, bit: u32) {
+        if self.gerror_active(bit) {
+            return;
+        }
+        self.regs.gerror ^= bit;
+        if self.regs.irq_ctrl & IRQ_CTRL_GERROR_IRQEN != 0 {
+            if let Some(irq) = &self.gerror_irq {
+                if let Err(e) = irq.trigger() {
+                    error!("smmuv3: failed to trigger gerror irq: {}", e);
+                }
+            }
+        }
+    }
+
+    fn record_event(&mut self, event: FaultEvent) {
+        warn!("smmuv3: fault {:#x?}", event);
+        if self.regs.cr0 & CR0_EVENTQEN == 0 {
+            return;
+        }
+        let queue = self.eventq();
+        if queue.is_full(self.regs.eventq_prod, self.regs.eventq_cons) {
+            // Signal the overflow if the guest acknowledged the previous one.
+            if (self.regs.eventq_prod & EVENTQ_PROD_OVFLG != 0)
+                == (self.regs.eventq_cons & EVENTQ_CONS_OVACKFLG != 0)
+            {
+                self.regs.eventq_prod ^= EVENTQ_PROD_OVFLG;
+            }
+            return;
+        }
+        if let Err(e) = self
+            .mem
+            .write_obj_at_addr(event.to_dwords(), queue.entry(self.regs.eventq_prod))
+        {
+            error!("smmuv3: failed to write event: {}", e);
+            return;
+        }
+        self.regs.eventq_prod =
+            (self.regs.eventq_prod & EVENTQ_PROD_OVFLG) | queue.next(self.regs.eventq_prod);
+        if self.regs.irq_ctrl & IRQ_CTRL_EVENTQ_IRQEN != 0 {
+            if let Some(irq) = &self.eventq_irq {
+                if let Err(e) = irq.trigger() {
+                    error!("smmuv3: failed to trigger eventq irq: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Reads the STE of `sid` from the stream table.
+    fn fetch_ste(&self, sid: u32) -> Result<[u64; 8], FaultEvent> {
+        let cfg = self.regs.strtab_base_cfg;
+        let log2size = cfg & 0x3f;
+        if log2size < 32 && sid >> log2size != 0 {
+            return Err(FaultEvent::new(EVT_C_BAD_STREAMID, sid));
+        }
+        let base = self.regs.strtab_base & STRTAB_BASE_ADDR_MASK;
+        let addr = if (cfg >> 16) & 0x3 == STRTAB_FMT_2LVL {
+            let split = (cfg >> 6) & 0x1f;
+            let l1_addr = GuestAddress(base + u64::from(sid >> split) * 8);
+            let l1_desc: u64 = self
+                .mem
+                .read_obj_from_addr(l1_addr)
+                .map_err(|_| FaultEvent::new(EVT_F_STE_FETCH, sid))?;
+            let span = (l1_desc & 0x1f) as u32;
+            let index = sid & ((1 << split) - 1);
+            if span == 0 || index >> (span - 1) != 0 {
+                return Err(FaultEvent::new(EVT_C_BAD_STREAMID, sid));
+            }
+            (l1_desc & STRTAB_BASE_ADDR_MASK) + u64::from(index) * STE_SIZE
+        } else {
+            base + u64::from(sid) * STE_SIZE
+        };
+        self.mem
+            .read_obj_from_addr(GuestAddress(addr))
+            .map_err(|_| FaultEvent::new(EVT_F_STE_FETCH, sid))
+    }
+
+    /// Decodes the configuration of `sid` from its STE and CD.
+    fn stream_config(&self, sid: u32) -> Result<StreamConfig, FaultEvent> {
+        if self.regs.cr0 & CR0_SMMUEN == 0 {
+            return Ok(if self.regs.gbpa & GBPA_ABORT != 0 {
+                StreamConfig::Abort
+            } else {
+                StreamConfig::Bypass
+            });
+        }
+        let ste = self.fetch_ste(sid)?;
+        if ste[0] & STE_VALID == 0 {
+            return Err(FaultEvent::new(EVT_C_BAD_STE, sid));
+        }
+        match (ste[0] >> 1) & 0x7 {
+            STE_CONFIG_ABORT => return Ok(StreamConfig::Abort),
+            STE_CONFIG_BYPASS => return Ok(StreamConfig::Bypass),
+            STE_CONFIG_S1_TRANS => {}
+            _ => return Err(FaultEvent::new(EVT_C_BAD_STE, sid)),
+        }
+
+        let cd: [u64; 8] = self
+            .mem
+            .read_obj_from_addr(GuestAddress(ste[0] & STE_CTX_PTR_MASK))
+            .map_err(|_| FaultEvent::new(EVT_F_CD_FETCH, sid))?;
+        if cd[0] & CD_VALID == 0 || cd[0] & CD_AA64 == 0 {
+            return Err(FaultEvent::new(EVT_C_BAD_CD, sid));
+        }
+        let asid = (cd[0] >> 48) as u16;
+        if cd[0] & CD_EPD0 != 0 {
+            return Ok(StreamConfig::Translate { asid, table: None });
+        }
+        let granule =
+            Granule::from_tg0((cd[0] >> 6) & 0x3).ok_or(FaultEvent::new(EVT_C_BAD_CD, sid))?;
+        Ok(StreamConfig::Translate {
+            asid,
+            table: Some(TranslationConfig {
+                ttb: cd[1] & CD_TTB_MASK,
+                tsz: (cd[0] & 0x3f) as u32,
+                granule,
+            }),
+        })
+    }
+
+    /// Translates `iova` for `sid`, with the guest's configuration, recording faults.
+    fn translate(&mut self, sid: u32, iova: u64, end: u64) -> anyhow::Result<Translation> {
+        let config = match self.stream_config(sid) {
+            Ok(config) => config,
+            Err(event) => {
+                self.record_event(event);
+                bail!("invalid configuration for stream {:#x}", sid);
+            }
+        };
+        let stream = self.streams.get_mut(&sid).context("unknown stream")?;
+        match config {
+            StreamConfig::Abort => bail!("stream {:#x} aborts transactions", sid),
+            StreamConfig::Bypass => {
+                let start = iova & !(BYPASS_PAGE_SIZE - 1);
+                let next_cached = stream
+                    .cached
+                    .range(iova..)
+                    .next()
+                    .map_or(u64::MAX, |(start, _)| *start);
+                let end = end.next_multiple_of(BYPASS_PAGE_SIZE).min(next_cached);
+                Ok(Translation {
+                    iova: start,
+                    gpa: GuestAddress(start),
+                    size: end - start,
+                    prot: Protection::read_write(),
+                })
+            }
+            StreamConfig::Translate { asid, table } => {
+                let walk = table
+                    .ok_or(WalkFault::Translation)
+                    .and_then(|table| table.walk(&self.mem, iova));
+                match walk {
+                    Ok(translation) => {
+                        stream.asid = Some(asid);
+                        Ok(translation)
+                    }
+                    Err(fault) => {
+                        self.record_event(FaultEvent::from_walk(fault, sid, iova));
+                        bail!("translation fault for stream {:#x} at {:#x}", sid, iova);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Translates the region and exports it from the mapper of `sid`.
+    fn export(&mut self, sid: u32, iova: u64, size: u64) -> anyhow::Result<Vec<MemRegion>> {
+        let end = iova.checked_add(size).context("iova overflow")?;
+        let mut addr = iova;
+        while addr < end {
+            let stream = self.streams.get(&sid).context("unknown stream")?;
+            if let Some((&start, &size)) = stream.cached.range(..=addr).next_back() {
+                if start + size > addr {
+                    addr = start + size;
+                    continue;
+                }
+            }
+            let translation = self.translate(sid, addr, end)?;
+            let stream = self.streams.get_mut(&sid).context("unknown stream")?;
+            let result = stream.mapper.lock().add_map(MappingInfo {
+                iova: translation.iova,
+                gpa: translation.gpa,
+                size: translation.size,
+                prot: translation.prot,
+            })?;
+            if result != AddMapResult::Ok {
+                bail!(
+                    "translation at {:#x} overlaps a cached one",
+                    translation.iova
+                );
+            }
+            stream.cached.insert(translation.iova, translation.size);
+            addr = translation.iova + translation.size;
+        }
+        let stream = self.streams.get(&sid).context("unknown stream")?;
+        stream.mapper.lock().export(iova, size)
+    }
+
+    fn flush_streams(
+        &mut self,
+        filter: impl Fn(u32, &Stream) -> bool,
+        faults: &mut Vec<EventAsync>,
+    ) {
+        for (sid, stream) in self.streams.iter_mut() {
+            if filter(*sid, stream) {
+                faults.extend(stream.flush());
+            }
+        }
+    }
+
+    fn invalidate_va(
+        &mut self,
+        asid: Option<u16>,
+        iova: u64,
+        faults: &mut Vec<EventAsync>,
+    ) -> anyhow::Result<()> {
+        for stream in self.streams.values_mut() {
+            if stream.asid.is_some() && (asid.is_none() || stream.asid == asid) {
+                faults.extend(stream.invalidate(iova)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a command other than CMD_SYNC. Returns the error code to report in CMDQ_CONS if
+    /// the command is invalid.
+    fn execute(&mut self, cmd: [u64; 2], faults: &mut Vec<EventAsync>) -> Result<(), u32> {
+        let sid = (cmd[0] >> 32) as u32;
+        let asid = (cmd[0] >> 48) as u16;
+        match cmd[0] as u8 {
+            CMD_PREFETCH_CONFIG | CMD_PREFETCH_ADDR => {}
+            CMD_CFGI_STE | CMD_CFGI_CD | CMD_CFGI_CD_ALL => {
+                self.flush_streams(|s, _| s == sid, faults);
+            }
+            CMD_CFGI_STE_RANGE => {
+                let range = (cmd[1] & 0x1f) as u32;
+                let mask = ((2u64 << range) - 1) as u32;
+                self.flush_streams(|s, _| s & !mask == sid & !mask, faults);
+            }
+            CMD_TLBI_NH_ALL => self.flush_streams(|_, stream| stream.asid.is_some(), faults),
+            CMD_TLBI_NH_ASID => {
+                self.flush_streams(|_, stream| stream.asid == Some(asid), faults);
+            }
+            CMD_TLBI_NH_VA | CMD_TLBI_NH_VAA => {
+                let asid = (cmd[0] as u8 == CMD_TLBI_NH_VA).then_some(asid);
+                if let Err(e) = self.invalidate_va(asid, cmd[1] & CMD_TLBI_ADDR_MASK, faults) {
+                    error!("smmuv3: failed to invalidate translation: {:#}", e);
+                    return Err(CERROR_ABT);
+                }
+            }
+            CMD_TLBI_S12_VMALL | CMD_TLBI_NSNH_ALL => self.flush_streams(|_, _| true, faults),
+            opcode => {
+                warn!("smmuv3: unsupported command {:#x}", opcode);
+                return Err(CERROR_ILL);
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the commands in the command queue. Returns true if a CMD_SYNC must wait for the
+    /// events in `faults` to be signaled before completing.
+    fn process_commands(&mut self, faults: &mut Vec<EventAsync>) -> bool {
+        if self.flush_pending {
+            self.flush_pending = false;
+            self.flush_streams(|_, _| true, faults);
+        }
+        if self.regs.cr0 & CR0_CMDQEN == 0 || self.gerror_active(GERROR_CMDQ_ERR) {
+            return false;
+        }
+        let queue = self.cmdq();
+        while !queue.is_empty(self.regs.cmdq_prod, self.regs.cmdq_cons) {
+            let cmd: [u64; 2] = match self
+                .mem
+                .read_obj_from_addr(queue.entry(self.regs.cmdq_cons))
+            {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    error!("smmuv3: failed to read command: {}", e);
+                    self.command_error(CERROR_ABT);
+                    return false;
+                }
+            };
+            if cmd[0] as u8 == CMD_SYNC {
+                if !faults.is_empty() {
+                    return true;
+                }
+            } else if let Err(code) = self.execute(cmd, faults) {
+                self.command_error(code);
+                return false;
+            }
+            self.regs.cmdq_cons = queue.next(self.regs.cmdq_cons);
+        }
+        false
+    }
+
+    /// Stops the command queue on the current command, until the guest acknowledges the error.
+    fn command_error(&mut self, code: u32) {
+        let queue = self.cmdq();
+        self.regs.cmdq_cons = queue.ptr(self.regs.cmdq_cons) | code << CMDQ_CONS_ERR_SHIFT;
+        self.raise_gerror(GERROR_CMDQ_ERR);
+    }
+
+    fn read_reg(&self, offset: u64) -> Option<u32> {
+        let regs = &self.regs;
+        let value = match offset {
+            SMMU_IDR0 => IDR0_VALUE,
+            SMMU_IDR1 => IDR1_VALUE,
+            SMMU_IDR2 | SMMU_IDR3 | SMMU_IDR4 | SMMU_IIDR | SMMU_AIDR | SMMU_STATUSR => 0,
+            SMMU_IDR5 => IDR5_VALUE,
+            SMMU_CR0 | SMMU_CR0ACK => regs.cr0,
+            SMMU_CR1 => regs.cr1,
+            SMMU_CR2 => regs.cr2,
+            SMMU_GBPA => regs.gbpa,
+            SMMU_IRQ_CTRL | SMMU_IRQ_CTRLACK => regs.irq_ctrl,
+            SMMU_GERROR => regs.gerror,
+            SMMU_GERRORN => regs.gerrorn,
+            SMMU_STRTAB_BASE => regs.strtab_base as u32,
+            SMMU_STRTAB_BASE_HI => (regs.strtab_base >> 32) as u32,
+            SMMU_STRTAB_BASE_CFG => regs.strtab_base_cfg,
+            SMMU_CMDQ_BASE => regs.cmdq_base as u32,
+            SMMU_CMDQ_BASE_HI => (regs.cmdq_base >> 32) as u32,
+            SMMU_CMDQ_PROD => regs.cmdq_prod,
+            SMMU_CMDQ_CONS => regs.cmdq_cons,
+            SMMU_EVENTQ_BASE => regs.eventq_base as u32,
+            SMMU_EVENTQ_BASE_HI => (regs.eventq_base >> 32) as u32,
+            SMMU_EVENTQ_PROD | SMMU_PAGE1_EVENTQ_PROD => regs.eventq_prod,
+            SMMU_EVENTQ_CONS | SMMU_PAGE1_EVENTQ_CONS => regs.eventq_cons,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Writes a register. Returns true if the command queue must be looked at again.
+    fn write_reg(&mut self, offset: u64, value: u32) -> bool {
+        let regs = &mut self.regs;
+        match offset {
+            SMMU_CR0 => {
+                if (regs.cr0 ^ value) & CR0_SMMUEN != 0 {
+                    self.flush_pending = true;
+                }
+                regs.cr0 = value;
+                return true;
+            }
+            SMMU_CR1 => regs.cr1 = value,
+            SMMU_CR2 => regs.cr2 = value,
+            SMMU_GBPA => {
+                if value & GBPA_UPDATE != 0 {
+                    regs.gbpa = value & !GBPA_UPDATE;
+                    self.flush_pending = true;
+                    return true;
+                }
+            }
+            SMMU_IRQ_CTRL => regs.irq_ctrl = value,
+            SMMU_GERRORN => {
+                regs.gerrorn = value;
+                return true;
+            }
+            SMMU_STRTAB_BASE => set_low(&mut regs.strtab_base, value),
+            SMMU_STRTAB_BASE_HI => set_high(&mut regs.strtab_base, value),
+            SMMU_STRTAB_BASE_CFG => regs.strtab_base_cfg = value,
+            SMMU_CMDQ_BASE => set_low(&mut regs.cmdq_base, value),
+            SMMU_CMDQ_BASE_HI => set_high(&mut regs.cmdq_base, value),
+            SMMU_CMDQ_PROD => {
+                regs.cmdq_prod = value;
+                return true;
+            }
+            SMMU_CMDQ_CONS => {
+                if regs.cr0 & CR0_CMDQEN == 0 {
+                    regs.cmdq_cons = value;
+                }
+            }
+            SMMU_EVENTQ_BASE => set_low(&mut regs.eventq_base, value),
+            SMMU_EVENTQ_BASE_HI => set_high(&mut regs.eventq_base, value),
+            SMMU_EVENTQ_PROD | SMMU_PAGE1_EVENTQ_PROD => {
+                if regs.cr0 & CR0_EVENTQEN == 0 {
+                    regs.eventq_prod = value;
+                }
+            }
+            SMMU_EVENTQ_CONS | SMMU_PAGE1_EVENTQ_CONS => regs.eventq_cons = value,
+            _ => warn!("smmuv3: write to unsupported register {:#x}", offset),
+        }
+        false
+    }
+}
+
+async fn handle_command_queue(state: &Mutex<State>, cmdq_evt: EventAsync) -> anyhow::Result<()> {
+    let mut faults = Vec::new();
+    loop {
+        if !state.lock().process_commands(&mut faults) {
+            cmdq_evt
+                .next_val()
+                .await
+                .context("failed to read cmdq event")?;
+            continue;
+        }
+        // A CMD_SYNC completes once the endpoints have released the invalidated memory.
+        for fault in faults.drain(..) {
+            fault
+                .next_val()
+                .await
+                .context("failed to wait for fault resolution")?;
+        }
+    }
+}
+
+async fn handle_translate_request(
+    ex: &Executor,
+    state: &Mutex<State>,
+    request_tube: &AsyncTube,
+    response_tubes: &BTreeMap<u32, AsyncTube>,
+) -> anyhow::Result<()> {
+    loop {
+        let req: IommuRequest = match request_tube.next().await {
+            Ok(req) => req,
+            // The endpoint process went away, there's nothing left to translate.
+            Err(TubeError::Disconnected) => return Ok(()),
+            Err(e) => return Err(e).context("failed to receive translate request"),
+        };
+        let sid = req.get_endpoint_id();
+        let Some(response_tube) = response_tubes.get(&sid) else {
+            error!("smmuv3: endpoint {:#x} not found", sid);
+            continue;
+        };
+        let resp = {
+            let mut state = state.lock();
+            match req {
+                IommuRequest::Export { iova, size, .. } => {
+                    state.export(sid, iova, size).map(IommuResponse::Export)
+                }
+                IommuRequest::Release { iova, size, .. } => state
+                    .streams
+                    .get(&sid)
+                    .context("unknown stream")
+                    .and_then(|stream| stream.mapper.lock().release(iova, size))
+                    .map(|_| IommuResponse::Release),
+                IommuRequest::StartExportSession { .. } => state
+                    .streams
+                    .get(&sid)
+                    .context("unknown stream")
+                    .and_then(|stream| stream.mapper.lock().start_export_session(ex))
+                    .map(IommuResponse::StartExportSession),
+            }
+        };
+        let resp = resp.unwrap_or_else(|e| IommuResponse::Err(format!("{:#}", e)));
+        response_tube
+            .send(resp)
+            .await
+            .context("failed to send translate response")?;
+    }
+}
+
+/// Tubes through which the endpoints request translations.
+struct TranslateTubes {
+    request_rx: Tube,
+    response_senders: BTreeMap<u32, Tube>,
+}
+
+/// Runs the worker until `kill_evt` is signaled, then gives back the translation tubes.
+fn run_worker(
+    state: &Mutex<State>,
+    cmdq_evt: Event,
+    tubes: Option<TranslateTubes>,
+    kill_evt: Event,
+) -> anyhow::Result<Option<TranslateTubes>> {
+    let ex = Executor::new().context("failed to create executor")?;
+    let cmdq_evt = EventAsync::new(cmdq_evt, &ex).context("failed to create async event")?;
+    let tubes = tubes
+        .map(|tubes| -> anyhow::Result<_> {
+            let request_tube = AsyncTube::new(&ex, tubes.request_rx)?;
+            let response_tubes = tubes
+                .response_senders
+                .into_iter()
+                .map(|(sid, tube)| Ok((sid, AsyncTube::new(&ex, tube)?)))
+                .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+            Ok((request_tube, response_tubes))
+        })
+        .transpose()
+        .context("failed to create async tubes")?;
+
+    let f_kill = async_utils::await_and_exit(&ex, kill_evt);
+    let f_cmdq = handle_command_queue(state, cmdq_evt);
+    let f_translate = async {
+        match &tubes {
+            Some((request_tube, response_tubes)) => {
+                handle_translate_request(&ex, state, request_tube, response_tubes).await
+            }
+            None => futures::future::pending().await,
+        }
+    };
+    let done = async {
+        select! {
+            res = f_kill.fuse() => res.context("error in await_and_exit"),
+            res = f_cmdq.fuse() => res.context("error in handling command queue"),
+            res = f_translate.fuse() => res.context("error in handling translate requests"),
+        }
+    };
+    ex.run_until(done).context("failed to run executor")??;
+
+    Ok(tubes.map(|(request_tube, response_tubes)| TranslateTubes {
+        request_rx: request_tube.into(),
+        response_senders: response_tubes
+            .into_iter()
+            .map(|(sid, tube)| (sid, tube.into()))
+            .collect(),
+    }))
+}
+
+/// Emulated SMMUv3 translating the DMA of the endpoints attached to it.
+pub struct SmmuV3 {
+    state: Arc<Mutex<State>>,
+    cmdq_evt: Event,
+    /// The translation tubes, while the worker isn't running.
+    tubes: Option<TranslateTubes>,
+    worker_thread: Option<WorkerThread<Option<TranslateTubes>>>,
+}
+
+impl SmmuV3 {
+    /// Creates an SMMU translating for `endpoints`, keyed by stream ID.
+    ///
+    /// Endpoints access memory through an `IpcMemoryMapper` sending its requests to
+    /// `translate_request_rx`, with the responses sent back through `translate_response_senders`.
+    pub fn new(
+        mem: GuestMemory,
+        endpoints: BTreeMap<u32, Arc<Mutex<Box<dyn MemoryMapperTrait>>>>,
+        translate_response_senders: Option<BTreeMap<u32, Tube>>,
+        translate_request_rx: Option<Tube>,
+    ) -> anyhow::Result<SmmuV3> {
+        let streams = endpoints
+            .into_iter()
+            .map(|(sid, mapper)| {
+                (
+                    sid,
+                    Stream {
+                        mapper,
+                        asid: None,
+                        cached: BTreeMap::new(),
+                    },
+                )
+            })
+            .collect();
+        let tubes = match (translate_request_rx, translate_response_senders) {
+            (Some(request_rx), Some(response_senders)) => Some(TranslateTubes {
+                request_rx,
+                response_senders,
+            }),
+            _ => None,
+        };
+        Ok(SmmuV3 {
+            state: Arc::new(Mutex::new(State {
+                mem,
+                regs: Registers::default(),
+                streams,
+                eventq_irq: None,
+                gerror_irq: None,
+                flush_pending: false,
+            })),
+            cmdq_evt: Event::new().context("failed to create cmdq event")?,
+            tubes,
+            worker_thread: None,
+        })
+    }
+
+    /// Sets the interrupts raised for new events and global errors.
+    pub fn set_irqs(&mut self, eventq_irq: IrqEdgeEvent, gerror_irq: IrqEdgeEvent) {
+        let mut state = self.state.lock();
+        state.eventq_irq = Some(eventq_irq);
+        state.gerror_irq = Some(gerror_irq);
+    }
+
+    /// Returns the stream IDs of the endpoints translated by the SMMU.
+    pub fn stream_ids(&self) -> Vec<u32> {
+        self.state.lock().streams.keys().copied().collect()
+    }
+
+    pub fn keep_rds(&self) -> Vec<RawDescriptor> {
+        let state = self.state.lock();
+        let mut rds = vec![self.cmdq_evt.as_raw_descriptor()];
+        for stream in state.streams.values() {
+            rds.append(&mut stream.mapper.lock().as_raw_descriptors());
+        }
+        for irq in [&state.eventq_irq, &state.gerror_irq].into_iter().flatten() {
+            rds.push(irq.get_trigger().as_raw_descriptor());
+        }
+        if let Some(tubes) = &self.tubes {
+            rds.push(tubes.request_rx.as_raw_descriptor());
+            rds.extend(
+                tubes
+                    .response_senders
+                    .values()
+                    .map(|tube| tube.as_raw_descriptor()),
+            );
+        }
+        rds
+    }
+
+    fn start_worker(&mut self) {
+        if self.worker_thread.is_some() {
+            return;
+        }
+        let cmdq_evt = match self.cmdq_evt.try_clone() {
+            Ok(evt) => evt,
+            Err(e) => {
+                error!("smmuv3: failed to clone cmdq event: {}", e);
+                return;
+            }
+        };
+        let state = self.state.clone();
+        let tubes = self.tubes.take();
+        self.worker_thread = Some(WorkerThread::start(
+            "smmuv3",
+            move |kill_evt| match run_worker(&state, cmdq_evt, tubes, kill_evt) {
+                Ok(tubes) => tubes,
+                Err(e) => {
+                    error!("smmuv3 worker thread exited with error: {:#}", e);
+                    None
+                }
+            },
+        ));
+    }
+
+    fn read_reg(&self, offset: u64) -> Option<u32> {
+        self.state.lock().read_reg(offset)
+    }
+
+    fn write_reg(&self, offset: u64, value: u32) {
+        if self.state.lock().write_reg(offset, value) {
+            if let Err(e) = self.cmdq_evt.signal() {
+                error!("smmuv3: failed to signal cmdq event: {}", e);
+            }
+        }
+    }
+}
+
+impl BusDevice for SmmuV3 {
+    fn device_id(&self) -> DeviceId {
+        CrosvmDeviceId::SmmuV3.into()
+    }
+
+    fn debug_label(&self) -> String {
+        "SmmuV3".to_owned()
+    }
+
+    fn read(&mut self, info: BusAccessInfo, data: &mut [u8]) {
+        let value = match data.len() {
+            4 => self.read_reg(info.offset).map(u64::from),
+            8 => self
+                .read_reg(info.offset)
+                .zip(self.read_reg(info.offset + 4))
+                .map(|(lo, hi)| u64::from(lo) | u64::from(hi) << 32),
+            _ => None,
+        };
+        let Some(value) = value else {
+            warn!("smmuv3: unsupported read {}", info);
+            data.fill(0);
+            return;
+        };
+        data.copy_from_slice(&value.to_le_bytes()[..data.len()]);
+    }
+
+    fn write(&mut self, info: BusAccessInfo, data: &[u8]) {
+        match data.len() {
+            4 => self.write_reg(info.offset, u32::from_le_bytes(data.try_into().unwrap())),
+            8 => {
+                let value = u64::from_le_bytes(data.try_into().unwrap());
+                self.write_reg(info.offset, value as u32);
+                self.write_reg(info.offset + 4, (value >> 32) as u32);
+            }
+            _ => warn!("smmuv3: unsupported write {}", info),
+        }
+    }
+
+    fn on_sandboxed(&mut self) {
+        self.start_worker();
+    }
+}
+
+impl Suspendable for SmmuV3 {
+    fn sleep(&mut self) -> anyhow::Result<()> {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            self.tubes = worker_thread.stop();
+        }
+        Ok(())
+    }
+
+    fn wake(&mut self) -> anyhow::Result<()> {
+        self.start_worker();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::memory_mapper::BasicMemoryMapper;
+
+    const STRTAB: u64 = 0x10000;
+    const CMDQ: u64 = 0x20000;
+    const EVENTQ: u64 = 0x30000;
+    const CD: u64 = 0x40000;
+    const TTB: u64 = 0x50000;
+
+    fn new_smmu(mem: &GuestMemory, sid: u32) -> SmmuV3 {
+        let mut endpoints: BTreeMap<u32, Arc<Mutex<Box<dyn MemoryMapperTrait>>>> = BTreeMap::new();
+        endpoints.insert(
+            sid,
+            Arc::new(Mutex::new(Box::new(BasicMemoryMapper::new(u64::MAX)))),
+        );
+        let smmu = SmmuV3::new(mem.clone(), endpoints, None, None).unwrap();
+        smmu.write_reg(SMMU_STRTAB_BASE, STRTAB as u32);
+        smmu.write_reg(SMMU_STRTAB_BASE_CFG, SID_BITS);
+        smmu.write_reg(SMMU_CMDQ_BASE, CMDQ as u32 | 4);
+        smmu.write_reg(SMMU_EVENTQ_BASE, EVENTQ as u32 | 4);
+        smmu.write_reg(SMMU_CR0, CR0_SMMUEN | CR0_EVENTQEN | CR0_CMDQEN);
+        smmu
+    }
+
+    fn push_command(smmu: &SmmuV3, mem: &GuestMemory, cmd: [u64; 2]) {
+        let prod = smmu.read_reg(SMMU_CMDQ_PROD).unwrap();
+        mem.write_obj_at_addr(cmd, GuestAddress(CMDQ + u64::from(prod & 0xf) * CMD_SIZE))
+            .unwrap();
+        smmu.write_reg(SMMU_CMDQ_PROD, (prod + 1) & 0x1f);
+    }
+
+    #[test]
+    fn translate_and_invalidate() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x100000)]).unwrap();
+        let sid = 0x8;
+        let smmu = new_smmu(&mem, sid);
+
+        // S1 translation with a 4KiB granule and 39-bit input addresses, starting at level 1.
+        let ste = [
+            CD | STE_CONFIG_S1_TRANS << 1 | STE_VALID,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        mem.write_obj_at_addr(ste, GuestAddress(STRTAB + u64::from(sid) * STE_SIZE))
+            .unwrap();
+        let cd = [25 | CD_VALID | CD_AA64 | 7 << 48, TTB, 0, 0, 0, 0, 0, 0];
+        mem.write_obj_at_addr(cd, GuestAddress(CD)).unwrap();
+        // iova 0x1000 -> gpa 0x80000.
+        mem.write_obj_at_addr((TTB + 0x1000) | 3, GuestAddress(TTB))
+            .unwrap();
+        mem.write_obj_at_addr((TTB + 0x2000) | 3, GuestAddress(TTB + 0x1000))
+            .unwrap();
+        mem.write_obj_at_addr(0x80000u64 | 1 << 10 | 3, GuestAddress(TTB + 0x2008))
+            .unwrap();
+
+        let mut state = smmu.state.lock();
+        let ex = Executor::new().unwrap();
+        let mapper = state.streams[&sid].mapper.clone();
+        mapper.lock().start_export_session(&ex).unwrap();
+        assert_eq!(
+            state.export(sid, 0x1010, 0x10).unwrap(),
+            vec![MemRegion {
+                gpa: GuestAddress(0x80010),
+                len: 0x10,
+                prot: Protection::read_write(),
+            }]
+        );
+        mapper.lock().release(0x1010, 0x10).unwrap();
+
+        // Unmapped iova: a translation fault is recorded in the event queue.
+        assert!(state.export(sid, 0x2000, 0x10).is_err());
+        assert_eq!(state.regs.eventq_prod, 1);
+        let event: [u64; 4] = mem.read_obj_from_addr(GuestAddress(EVENTQ)).unwrap();
+        assert_eq!(
+            event[0],
+            u64::from(EVT_F_TRANSLATION) | u64::from(sid) << 32
+        );
+        assert_eq!(event[2], 0x2000);
+
+        // Invalidating the page drops the cached translation.
+        drop(state);
+        push_command(&smmu, &mem, [u64::from(CMD_TLBI_NH_VA) | 7 << 48, 0x1000]);
+        push_command(&smmu, &mem, [u64::from(CMD_SYNC), 0]);
+        let mut state = smmu.state.lock();
+        let mut faults = Vec::new();
+        assert!(!state.process_commands(&mut faults));
+        assert!(faults.is_empty());
+        assert_eq!(state.regs.cmdq_cons, 2);
+        assert!(state.streams[&sid].cached.is_empty());
+    }
+
+    #[test]
+    fn illegal_command() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x100000)]).unwrap();
+        let smmu = new_smmu(&mem, 0);
+        push_command(&smmu, &mem, [0xff, 0]);
+        let mut state = smmu.state.lock();
+        assert!(!state.process_commands(&mut Vec::new()));
+        assert_eq!(state.regs.cmdq_cons >> CMDQ_CONS_ERR_SHIFT, CERROR_ILL);
+        assert!(state.gerror_active(GERROR_CMDQ_ERR));
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Walker for the AArch64 stage-1 translation tables used by SMMUv3 context descriptors.
+
+use base::Protection;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+
+/// Bits of the output address held by a descriptor. Only 48-bit output addresses are supported.
+const OA_MASK: u64 = 0x0000_ffff_ffff_ffff;
+/// Output address bits beyond the supported output size.
+const OA_HIGH_MASK: u64 = 0x000f_0000_0000_0000;
+
+const DESC_VALID: u64 = 1 << 0;
+const DESC_TABLE: u64 = 1 << 1;
+/// AP[2]: the mapping is read-only.
+const DESC_AP_RDONLY: u64 = 1 << 7;
+/// Access flag.
+const DESC_AF: u64 = 1 << 10;
+/// APTable[1]: the mappings reached through the table are read-only.
+const DESC_APTABLE_RDONLY: u64 = 1 << 62;
+
+/// Translation granule, selected by the TG0 field of a context descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granule {
+    Size4K,
+    Size16K,
+    Size64K,
+}
+
+impl Granule {
+    /// Decodes the TG0 field of a context descriptor.
+    pub fn from_tg0(tg0: u64) -> Option<Granule> {
+        match tg0 {
+            0 => Some(Granule::Size4K),
+            1 => Some(Granule::Size64K),
+            2 => Some(Granule::Size16K),
+            _ => None,
+        }
+    }
+
+    fn shift(self) -> u32 {
+        match self {
+            Granule::Size4K => 12,
+            Granule::Size16K => 14,
+            Granule::Size64K => 16,
+        }
+    }
+
+    /// Whether a block descriptor is allowed at `level` with 48-bit output addresses.
+    fn allows_block(self, level: u32) -> bool {
+        match self {
+            Granule::Size4K => level == 1 || level == 2,
+            Granule::Size16K | Granule::Size64K => level == 2,
+        }
+    }
+}
+
+/// Stage-1 translation regime of a context descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TranslationConfig {
+    /// Guest physical address of the translation table, from TTB0.
+    pub ttb: u64,
+    /// Size offset of the input address space, from T0SZ.
+    pub tsz: u32,
+    pub granule: Granule,
+}
+
+/// A leaf entry of the translation table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Translation {
+    /// IO virtual address of the start of the block or page.
+    pub iova: u64,
+    pub gpa: GuestAddress,
+    pub size: u64,
+    pub prot: Protection,
+}
+
+/// Reasons a walk may fail, reported to the guest as the matching SMMU fault event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkFault {
+    /// The input address is out of range or the walk reached an invalid descriptor.
+    Translation,
+    /// The output address of a descriptor is larger than the supported output size.
+    AddressSize,
+    /// The access flag of the leaf descriptor is not set.
+    Access,
+    /// A descriptor could not be read from guest memory.
+    External,
+}
+
+impl TranslationConfig {
+    fn input_bits(&self) -> u32 {
+        64 - self.tsz
+    }
+
+    /// Looks up the leaf descriptor translating `iova`.
+    pub fn walk(&self, mem: &GuestMemory, iova: u64) -> Result<Translation, WalkFault> {
+        let granule_shift = self.granule.shift();
+        let stride = granule_shift - 3;
+        let input_bits = self.input_bits();
+        if !(granule_shift + 1..=48).contains(&input_bits) {
+            return Err(WalkFault::Translation);
+        }
+        if iova >> input_bits != 0 {
+            return Err(WalkFault::Translation);
+        }
+
+        let levels = (input_bits - granule_shift).div_ceil(stride);
+        let mut level = 4 - levels;
+        let mut table = self.ttb & OA_MASK & !0x7;
+        let mut read_only = false;
+        loop {
+            let shift = granule_shift + (3 - level) * stride;
+            let index_bits = (input_bits - shift).min(stride);
+            let index = (iova >> shift) & ((1 << index_bits) - 1);
+            let desc: u64 = mem
+                .read_obj_from_addr(GuestAddress(table + index * 8))
+                .map_err(|_| WalkFault::External)?;
+            if desc & DESC_VALID == 0 {
+                return Err(WalkFault::Translation);
+            }
+            if desc & OA_HIGH_MASK != 0 {
+                return Err(WalkFault::AddressSize);
+            }
+            let is_table = desc & DESC_TABLE != 0;
+            if is_table && level < 3 {
+                read_only |= desc & DESC_APTABLE_RDONLY != 0;
+                table = desc & OA_MASK & !((1 << granule_shift) - 1);
+                level += 1;
+                continue;
+            }
+            // A block, or a page at the last level.
+            if !is_table && !self.granule.allows_block(level) {
+                return Err(WalkFault::Translation);
+            }
+            if desc & DESC_AF == 0 {
+                return Err(WalkFault::Access);
+            }
+            read_only |= desc & DESC_AP_RDONLY != 0;
+            let size = 1u64 << shift;
+            let prot = if read_only {
+                Protection::read()
+            } else {
+                Protection::read_write()
+            };
+            return Ok(Translation {
+                iova: iova & !(size - 1),
+                gpa: GuestAddress(desc & OA_MASK & !(size - 1)),
+                size,
+                prot,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE_FLAGS: u64 = DESC_VALID | DESC_TABLE;
+    const PAGE_FLAGS: u64 = DESC_VALID | DESC_TABLE | DESC_AF;
+    const BLOCK_FLAGS: u64 = DESC_VALID | DESC_AF;
+
+    fn write_desc(mem: &GuestMemory, table: u64, index: u64, desc: u64) {
+        mem.write_obj_at_addr(desc, GuestAddress(table + index * 8))
+            .unwrap();
+    }
+
+    #[test]
+    fn walk_4k_pages_and_blocks() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x100000)]).unwrap();
+        let config = TranslationConfig {
+            ttb: 0x1000,
+            tsz: 16,
+            granule: Granule::Size4K,
+        };
+        // iova 0x4020_3000: L0 index 0, L1 index 1, L2 index 1, L3 index 3.
+        write_desc(&mem, 0x1000, 0, 0x2000 | TABLE_FLAGS);
+        write_desc(&mem, 0x2000, 1, 0x3000 | TABLE_FLAGS);
+        write_desc(&mem, 0x3000, 1, 0x4000 | TABLE_FLAGS | DESC_APTABLE_RDONLY);
+        write_desc(&mem, 0x4000, 3, 0x8_0000 | PAGE_FLAGS);
+        // iova 0x4040_0000: a 2MiB block at L2 index 2.
+        write_desc(&mem, 0x3000, 2, 0x20_0000 | BLOCK_FLAGS);
+
+        assert_eq!(
+            config.walk(&mem, 0x4020_3456),
+            Ok(Translation {
+                iova: 0x4020_3000,
+                gpa: GuestAddress(0x8_0000),
+                size: 0x1000,
+                prot: Protection::read(),
+            })
+        );
+        assert_eq!(
+            config.walk(&mem, 0x4045_6789),
+            Ok(Translation {
+                iova: 0x4040_0000,
+                gpa: GuestAddress(0x20_0000),
+                size: 0x20_0000,
+                prot: Protection::read_write(),
+            })
+        );
+        assert_eq!(config.walk(&mem, 0x4020_4000), Err(WalkFault::Translation));
+        assert_eq!(config.walk(&mem, 1 << 48), Err(WalkFault::Translation));
+    }
+
+    #[test]
+    fn walk_64k_two_levels() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x100000)]).unwrap();
+        // 42-bit input addresses with a 64KiB granule only use levels 2 and 3.
+        let config = TranslationConfig {
+            ttb: 0x10000,
+            tsz: 22,
+            granule: Granule::Size64K,
+        };
+        write_desc(&mem, 0x10000, 1, 0x20000 | TABLE_FLAGS);
+        write_desc(&mem, 0x20000, 2, 0x50000 | PAGE_FLAGS);
+        write_desc(&mem, 0x20000, 3, 0x60000 | DESC_VALID | DESC_TABLE);
+
+        assert_eq!(
+            config.walk(&mem, 0x2002_1234),
+            Ok(Translation {
+                iova: 0x2002_0000,
+                gpa: GuestAddress(0x50000),
+                size: 0x10000,
+                prot: Protection::read_write(),
+            })
+        );
+        assert_eq!(config.walk(&mem, 0x2003_0000), Err(WalkFault::Access));
+    }
+}
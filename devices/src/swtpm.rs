@@ -0,0 +1,389 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! vTPM backend that forwards TPM 2.0 commands to an external [swtpm] process.
+//!
+//! swtpm is expected to be started with a UnixIO data socket and, optionally, a UnixIO control
+//! socket, e.g.
+//!
+//! ```text
+//! swtpm socket --tpm2 --tpmstate dir=/var/lib/vtpm \
+//!     --server type=unixio,path=/run/vtpm/data.sock \
+//!     --ctrl type=unixio,path=/run/vtpm/ctrl.sock
+//! ```
+//!
+//! The control socket is used to initialize the TPM and to move its NVRAM and volatile state in
+//! and out of VM snapshots. Without it the TPM still works, but the VM cannot be snapshotted.
+//!
+//! The TPM is exposed to the guest as a virtio-tpm device or, on x86_64, through the CRB interface
+//! of [`crate::tpm_crb`].
+//!
+//! [swtpm]: https://github.com/stefanberger/swtpm
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::AsRawDescriptor;
+use base::RawDescriptor;
+use remain::sorted;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_keyvalue::FromKeyValues;
+use snapshot::AnySnapshot;
+use thiserror::Error;
+
+use super::virtio::TpmBackend;
+
+// Control channel commands, from swtpm's `tpm_ioctl.h`.
+const CMD_INIT: u32 = 2;
+const CMD_GET_STATEBLOB: u32 = 12;
+const CMD_SET_STATEBLOB: u32 = 13;
+const CMD_STOP: u32 = 14;
+
+// State blob types.
+const PTM_BLOB_TYPE_PERMANENT: u32 = 1;
+const PTM_BLOB_TYPE_VOLATILE: u32 = 2;
+
+// Size of the TPM 2.0 response header: tag (u16) followed by the total response size (u32).
+const TPM_HEADER_SIZE: usize = 6;
+
+// Upper bound on a state blob, to avoid allocating unbounded memory on a misbehaving peer.
+const MAX_STATE_BLOB_SIZE: usize = 1 << 20;
+
+// The response of TPM_RC_FAILURE
+const TPM_RC_FAILURE_RESPONSE: &[u8] = &[
+    0x80, 0x01, // TPM_ST_NO_SESSIONS
+    0x00, 0x00, 0x00, 0x0A, // Header Size = 10
+    0x00, 0x00, 0x01, 0x01, // TPM_RC_FAILURE
+];
+
+/// Parameters for connecting to an swtpm instance.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SwtpmParameters {
+    /// Path to the swtpm data channel socket.
+    pub socket: PathBuf,
+    /// Path to the swtpm control channel socket.
+    #[serde(default)]
+    pub ctrl: Option<PathBuf>,
+    /// Interface the TPM is exposed to the guest with.
+    #[serde(default)]
+    pub interface: TpmInterface,
+}
+
+/// Interface a TPM is exposed to the guest with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TpmInterface {
+    /// A virtio-tpm device.
+    #[default]
+    Virtio,
+    /// A TPM 2.0 CRB interface, described to the guest through ACPI. Only available on x86_64.
+    Crb,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SwtpmSnapshot {
+    permanent: Vec<u8>,
+    volatile: Vec<u8>,
+}
+
+/// A TPM backend connected to swtpm over Unix domain sockets.
+pub struct Swtpm {
+    data: UnixStream,
+    ctrl: Option<UnixStream>,
+    buf: Vec<u8>,
+}
+
+impl Swtpm {
+    /// Connects to swtpm and, if a control channel is available, initializes the TPM.
+    pub fn new(params: &SwtpmParameters) -> anyhow::Result<Self> {
+        let data = UnixStream::connect(&params.socket).with_context(|| {
+            format!(
+                "failed to connect to swtpm data socket {}",
+                params.socket.display()
+            )
+        })?;
+        let ctrl = match &params.ctrl {
+            Some(path) => Some(UnixStream::connect(path).with_context(|| {
+                format!("failed to connect to swtpm ctrl socket {}", path.display())
+            })?),
+            None => None,
+        };
+
+        let mut swtpm = Swtpm {
+            data,
+            ctrl,
+            buf: Vec::new(),
+        };
+        if swtpm.ctrl.is_some() {
+            swtpm.init().context("failed to initialize swtpm")?;
+        }
+        Ok(swtpm)
+    }
+
+    fn ctrl(&mut self) -> Result<&mut UnixStream> {
+        self.ctrl.as_mut().ok_or(Error::NoControlChannel)
+    }
+
+    fn ctrl_command(&mut self, cmd: u32, payload: &[u8]) -> Result<()> {
+        let ctrl = self.ctrl()?;
+        let mut req = Vec::with_capacity(4 + payload.len());
+        req.extend_from_slice(&cmd.to_be_bytes());
+        req.extend_from_slice(payload);
+        ctrl.write_all(&req).map_err(Error::CtrlIo)?;
+        let result = read_be_u32(ctrl).map_err(Error::CtrlIo)?;
+        if result != 0 {
+            return Err(Error::CommandFailed { cmd, result });
+        }
+        Ok(())
+    }
+
+    fn init(&mut self) -> Result<()> {
+        // init_flags = 0: keep any volatile state swtpm may have saved.
+        self.ctrl_command(CMD_INIT, &0u32.to_be_bytes())
+    }
+
+    fn get_state_blob(&mut self, blob_type: u32) -> Result<Vec<u8>> {
+        let ctrl = self.ctrl()?;
+        let mut req = Vec::with_capacity(16);
+        req.extend_from_slice(&CMD_GET_STATEBLOB.to_be_bytes());
+        req.extend_from_slice(&0u32.to_be_bytes()); // state_flags
+        req.extend_from_slice(&blob_type.to_be_bytes());
+        req.extend_from_slice(&0u32.to_be_bytes()); // offset
+        ctrl.write_all(&req).map_err(Error::CtrlIo)?;
+
+        let result = read_be_u32(ctrl).map_err(Error::CtrlIo)?;
+        let _state_flags = read_be_u32(ctrl).map_err(Error::CtrlIo)?;
+        let total_length = read_be_u32(ctrl).map_err(Error::CtrlIo)? as usize;
+        let _length = read_be_u32(ctrl).map_err(Error::CtrlIo)?;
+        if result != 0 {
+            return Err(Error::CommandFailed {
+                cmd: CMD_GET_STATEBLOB,
+                result,
+            });
+        }
+        if total_length > MAX_STATE_BLOB_SIZE {
+            return Err(Error::StateBlobTooLarge(total_length));
+        }
+
+        // On the socket interface swtpm sends the whole blob following the header, regardless of
+        // how much of it fits in the first response.
+        let mut blob = vec![0u8; total_length];
+        ctrl.read_exact(&mut blob).map_err(Error::CtrlIo)?;
+        Ok(blob)
+    }
+
+    fn set_state_blob(&mut self, blob_type: u32, blob: &[u8]) -> Result<()> {
+        let mut payload = Vec::with_capacity(12 + blob.len());
+        payload.extend_from_slice(&0u32.to_be_bytes()); // state_flags
+        payload.extend_from_slice(&blob_type.to_be_bytes());
+        payload.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+        payload.extend_from_slice(blob);
+        self.ctrl_command(CMD_SET_STATEBLOB, &payload)
+    }
+
+    fn try_execute_command(&mut self, command: &[u8]) -> Result<()> {
+        self.data.write_all(command).map_err(Error::DataIo)?;
+
+        let mut header = [0u8; TPM_HEADER_SIZE];
+        self.data.read_exact(&mut header).map_err(Error::DataIo)?;
+        let size = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        if size < TPM_HEADER_SIZE {
+            return Err(Error::InvalidResponseSize(size));
+        }
+
+        self.buf.clear();
+        self.buf.extend_from_slice(&header);
+        self.buf.resize(size, 0);
+        self.data
+            .read_exact(&mut self.buf[TPM_HEADER_SIZE..])
+            .map_err(Error::DataIo)?;
+        Ok(())
+    }
+}
+
+impl TpmBackend for Swtpm {
+    fn execute_command<'a>(&'a mut self, command: &[u8]) -> &'a [u8] {
+        match self.try_execute_command(command) {
+            Ok(()) => &self.buf,
+            Err(e) => {
+                error!("{:#}", e);
+                TPM_RC_FAILURE_RESPONSE
+            }
+        }
+    }
+
+    fn keep_rds(&self) -> Vec<RawDescriptor> {
+        let mut rds = vec![self.data.as_raw_descriptor()];
+        if let Some(ctrl) = &self.ctrl {
+            rds.push(ctrl.as_raw_descriptor());
+        }
+        rds
+    }
+
+    fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        let permanent = self
+            .get_state_blob(PTM_BLOB_TYPE_PERMANENT)
+            .context("failed to get permanent state")?;
+        let volatile = self
+            .get_state_blob(PTM_BLOB_TYPE_VOLATILE)
+            .context("failed to get volatile state")?;
+        AnySnapshot::to_any(SwtpmSnapshot {
+            permanent,
+            volatile,
+        })
+    }
+
+    fn restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        let snapshot: SwtpmSnapshot = AnySnapshot::from_any(data)?;
+        if self.ctrl.is_none() {
+            bail!("swtpm restore requires a control channel");
+        }
+        // swtpm only accepts new state while the TPM is stopped.
+        self.ctrl_command(CMD_STOP, &[])
+            .context("failed to stop swtpm")?;
+        self.set_state_blob(PTM_BLOB_TYPE_PERMANENT, &snapshot.permanent)
+            .context("failed to set permanent state")?;
+        self.set_state_blob(PTM_BLOB_TYPE_VOLATILE, &snapshot.volatile)
+            .context("failed to set volatile state")?;
+        self.init().context("failed to restart swtpm")?;
+        Ok(())
+    }
+}
+
+fn read_be_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[sorted]
+#[derive(Error, Debug)]
+enum Error {
+    #[error("swtpm control command {cmd} failed with result {result:#x}")]
+    CommandFailed { cmd: u32, result: u32 },
+    #[error("swtpm control channel I/O failed: {0}")]
+    CtrlIo(io::Error),
+    #[error("swtpm data channel I/O failed: {0}")]
+    DataIo(io::Error),
+    #[error("swtpm returned a response with an invalid size: {0}")]
+    InvalidResponseSize(usize),
+    #[error("swtpm control channel is not configured")]
+    NoControlChannel,
+    #[error("swtpm state blob is too large: {0} bytes")]
+    StateBlobTooLarge(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use serde_keyvalue::from_key_values;
+
+    use super::*;
+
+    #[test]
+    fn params_from_key_values() {
+        assert_eq!(
+            from_key_values::<SwtpmParameters>("socket=/run/data.sock").unwrap(),
+            SwtpmParameters {
+                socket: "/run/data.sock".into(),
+                ctrl: None,
+                interface: TpmInterface::Virtio,
+            }
+        );
+        assert_eq!(
+            from_key_values::<SwtpmParameters>("socket=/run/data.sock,ctrl=/run/ctrl.sock")
+                .unwrap(),
+            SwtpmParameters {
+                socket: "/run/data.sock".into(),
+                ctrl: Some("/run/ctrl.sock".into()),
+                interface: TpmInterface::Virtio,
+            }
+        );
+        assert_eq!(
+            from_key_values::<SwtpmParameters>("socket=/run/data.sock,interface=crb")
+                .unwrap()
+                .interface,
+            TpmInterface::Crb
+        );
+        assert!(from_key_values::<SwtpmParameters>("ctrl=/run/ctrl.sock").is_err());
+        assert!(from_key_values::<SwtpmParameters>("socket=/run/data.sock,interface=tis").is_err());
+    }
+
+    #[test]
+    fn execute_command_reads_full_response() {
+        let (data, mut peer) = UnixStream::pair().unwrap();
+        let mut swtpm = Swtpm {
+            data,
+            ctrl: None,
+            buf: Vec::new(),
+        };
+
+        // A TPM2_GetRandom response with 4 random bytes, distinct from the failure response
+        // returned when the read fails.
+        const RESPONSE: &[u8] = &[
+            0x80, 0x01, // TPM_ST_NO_SESSIONS
+            0x00, 0x00, 0x00, 0x10, // Response size = 16
+            0x00, 0x00, 0x00, 0x00, // TPM_RC_SUCCESS
+            0x00, 0x04, // Random bytes size = 4
+            0xde, 0xad, 0xbe, 0xef,
+        ];
+        let responder = thread::spawn(move || {
+            let mut command = [0u8; 4];
+            peer.read_exact(&mut command).unwrap();
+            assert_eq!(command, [1, 2, 3, 4]);
+            // Split the response to check that it is read until its end.
+            peer.write_all(&RESPONSE[..8]).unwrap();
+            peer.write_all(&RESPONSE[8..]).unwrap();
+        });
+
+        assert_eq!(swtpm.execute_command(&[1, 2, 3, 4]), RESPONSE);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn execute_command_fails_on_short_response() {
+        let (data, mut peer) = UnixStream::pair().unwrap();
+        let mut swtpm = Swtpm {
+            data,
+            ctrl: None,
+            buf: Vec::new(),
+        };
+
+        let responder = thread::spawn(move || {
+            let mut command = [0u8; 4];
+            peer.read_exact(&mut command).unwrap();
+            // Announce 16 bytes, then hang up after the header.
+            peer.write_all(&[0x80, 0x01, 0x00, 0x00, 0x00, 0x10])
+                .unwrap();
+        });
+
+        assert_eq!(
+            swtpm.execute_command(&[1, 2, 3, 4]),
+            TPM_RC_FAILURE_RESPONSE
+        );
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn snapshot_without_ctrl_fails() {
+        let (data, _peer) = UnixStream::pair().unwrap();
+        let mut swtpm = Swtpm {
+            data,
+            ctrl: None,
+            buf: Vec::new(),
+        };
+        assert!(swtpm.snapshot().is_err());
+    }
+}
@@ -29,6 +29,12 @@ use crate::serial_device::SerialInput;
 use crate::serial_device::SerialOptions;
 use crate::serial_device::SerialParameters;
 
+mod log_file;
+mod tcp;
+
+pub(crate) use log_file::create_log_file_output;
+pub(crate) use tcp::create_tcp_serial_device;
+
 pub const SYSTEM_SERIAL_TYPE_NAME: &str = "UnixSocket";
 
 // This wrapper is used in place of the libstd native version because we don't want
@@ -258,6 +264,7 @@ pub(crate) fn create_unix_stream_serial_device<T: SerialDevice>(
             console: param.console,
             pci_address: param.pci_address,
             max_queue_sizes: param.max_queue_sizes.clone(),
+            max_ports: param.max_ports,
         },
         keep_rds.to_vec(),
     ))
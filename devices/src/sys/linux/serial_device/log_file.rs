@@ -0,0 +1,215 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Serial backend that writes timestamped lines to a log file and rotates it by size.
+//!
+//! Rotation needs to create and rename files next to the log, which a sandboxed device cannot do,
+//! so the device writes to one end of a Unix stream socket pair and a thread in the process that
+//! created the device does the file handling.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Instant;
+
+use base::error;
+
+use crate::serial_device::Error;
+use crate::serial_device::SerialParameters;
+
+// Each line starts with the wall-clock time followed by the seconds since the log was opened.
+const WALL_CLOCK_FMT: &str = "%F %T%.9f";
+
+// Number of rotated files kept when `rotate-count` is not given.
+const DEFAULT_ROTATE_COUNT: u32 = 5;
+
+const BUF_SIZE: usize = 4096;
+
+/// Appends timestamped lines to a file, rotating it once it reaches a size limit.
+struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotate_size: Option<u64>,
+    rotate_count: u32,
+    start: Instant,
+    at_line_start: bool,
+}
+
+impl RotatingLog {
+    fn open(path: &Path, rotate_size: Option<u64>, rotate_count: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLog {
+            path: path.to_owned(),
+            file,
+            size,
+            rotate_size,
+            rotate_count,
+            start: Instant::now(),
+            at_line_start: true,
+        })
+    }
+
+    // Returns the path of the `n`th rotated file, e.g. `console.log.1`.
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    // Shifts the rotated files up by one, dropping the oldest, and starts a new log. With a
+    // rotate count of zero the log is simply truncated.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.rotate_count == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.rotate_count).rev() {
+                match std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_raw(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Writes `buf`, prefixing each new line with a timestamp. Rotation only happens between
+    /// lines, so a line is never split across files.
+    fn write_output(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            if self.at_line_start {
+                if self.rotate_size.is_some_and(|max| self.size >= max) {
+                    self.rotate()?;
+                }
+                let prefix = format!(
+                    "[ {} {:12.6} ]: ",
+                    chrono::Utc::now().format(WALL_CLOCK_FMT),
+                    self.start.elapsed().as_secs_f64()
+                );
+                self.write_raw(prefix.as_bytes())?;
+            }
+            let (line, rest) = match buf.iter().position(|&b| b == b'\n') {
+                Some(pos) => buf.split_at(pos + 1),
+                None => (buf, &[][..]),
+            };
+            self.write_raw(line)?;
+            self.at_line_start = line.ends_with(b"\n");
+            buf = rest;
+        }
+        Ok(())
+    }
+
+    fn run(mut self, mut input: UnixStream) {
+        let mut buf = [0u8; BUF_SIZE];
+        loop {
+            let len = match input.read(&mut buf) {
+                Ok(0) => return,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("failed to read serial output: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = self.write_output(&buf[..len]) {
+                error!("failed to write serial log {}: {}", self.path.display(), e);
+                return;
+            }
+        }
+    }
+}
+
+/// Opens the log file at `param.path` and returns the stream the device should write its output
+/// to.
+pub(crate) fn create_log_file_output(param: &SerialParameters) -> Result<UnixStream, Error> {
+    let path = param.path.as_ref().ok_or(Error::PathRequired)?;
+    let log = RotatingLog::open(
+        path,
+        param.rotate_size,
+        param.rotate_count.unwrap_or(DEFAULT_ROTATE_COUNT),
+    )
+    .map_err(|e| Error::FileCreate(e, path.clone()))?;
+
+    let (output, log_end) = UnixStream::pair().map_err(Error::SocketCreate)?;
+    thread::Builder::new()
+        .name("serial_log".to_string())
+        .spawn(move || log.run(log_end))
+        .map_err(Error::SpawnThread)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn read_lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| line.split_once("]: ").unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn timestamps_each_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        let mut log = RotatingLog::open(&path, None, 0).unwrap();
+        log.write_output(b"hello\nwor").unwrap();
+        log.write_output(b"ld\n\nbye\n").unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 4);
+        assert!(contents.lines().all(|line| line.starts_with("[ ")));
+        assert_eq!(read_lines(&path), ["hello", "world", "", "bye"]);
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        let mut log = RotatingLog::open(&path, Some(1), 2).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            log.write_output(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read_lines(&path), ["four"]);
+        assert_eq!(read_lines(&dir.path().join("console.log.1")), ["three"]);
+        assert_eq!(read_lines(&dir.path().join("console.log.2")), ["two"]);
+        assert!(!dir.path().join("console.log.3").exists());
+    }
+
+    #[test]
+    fn rotate_count_zero_truncates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        let mut log = RotatingLog::open(&path, Some(1), 0).unwrap();
+        log.write_output(b"one\ntwo\n").unwrap();
+
+        assert_eq!(read_lines(&path), ["two"]);
+        assert!(!dir.path().join("console.log.1").exists());
+    }
+}
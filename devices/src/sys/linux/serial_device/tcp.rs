@@ -0,0 +1,424 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Serial backend that relays the port over TCP, in the style of QEMU's `-serial tcp:` and
+//! `-serial telnet:`.
+//!
+//! The device reads and writes one end of a Unix stream socket pair and never sees the TCP
+//! connection. A relay thread in the process that created the device owns the other end. It
+//! listens for or connects to the peer, reconnects whenever the connection drops, and discards
+//! output while no peer is connected so that the guest never stalls on the serial port.
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use base::error;
+use base::info;
+use base::AsRawDescriptor;
+use base::Event;
+use base::EventToken;
+use base::RawDescriptor;
+use base::WaitContext;
+use hypervisor::ProtectionType;
+
+use super::SerialDevice;
+use crate::serial_device::Error;
+use crate::serial_device::SerialOptions;
+use crate::serial_device::SerialParameters;
+
+// How long to wait between attempts to connect to the peer.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+const BUF_SIZE: usize = 4096;
+
+// Telnet commands and options, from RFC 854, 856, 857 and 858.
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+const OPT_BINARY: u8 = 0;
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+// Sent by the server side on each new connection to switch the client to character-at-a-time
+// binary mode without local echo, as QEMU does.
+const TELNET_SERVER_INIT: &[u8] = &[
+    IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA, IAC, WILL, OPT_BINARY, IAC, DO, OPT_BINARY,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum TelnetState {
+    #[default]
+    Data,
+    // The previous data byte was a carriage return.
+    Cr,
+    Iac,
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Strips telnet commands out of the stream received from the peer.
+#[derive(Default)]
+struct TelnetParser {
+    state: TelnetState,
+}
+
+impl TelnetParser {
+    /// Parses `input`, appending the data bytes to `data` and any replies to the peer's option
+    /// negotiation to `replies`. Commands may be split across calls.
+    fn process(&mut self, input: &[u8], data: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &b in input {
+            self.state = match (self.state, b) {
+                (TelnetState::Data | TelnetState::Cr, IAC) => TelnetState::Iac,
+                // Clients send a bare carriage return as CR NUL.
+                (TelnetState::Cr, 0) => TelnetState::Data,
+                (TelnetState::Data | TelnetState::Cr, b) => {
+                    data.push(b);
+                    if b == b'\r' {
+                        TelnetState::Cr
+                    } else {
+                        TelnetState::Data
+                    }
+                }
+                (TelnetState::Iac, IAC) => {
+                    data.push(IAC);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, WILL | WONT | DO | DONT) => TelnetState::Negotiate(b),
+                (TelnetState::Iac, SB) => TelnetState::Subnegotiation,
+                // Other commands (NOP, break, etc.) carry no data.
+                (TelnetState::Iac, _) => TelnetState::Data,
+                (TelnetState::Negotiate(cmd), opt) => {
+                    negotiation_reply(cmd, opt, replies);
+                    TelnetState::Data
+                }
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationIac,
+                (TelnetState::Subnegotiation, _) => TelnetState::Subnegotiation,
+                (TelnetState::SubnegotiationIac, SE) => TelnetState::Data,
+                (TelnetState::SubnegotiationIac, _) => TelnetState::Subnegotiation,
+            };
+        }
+    }
+}
+
+// Refuses every option except the ones offered in `TELNET_SERVER_INIT`. Acknowledgements of those
+// and refusals from the peer need no reply.
+fn negotiation_reply(cmd: u8, opt: u8, replies: &mut Vec<u8>) {
+    let reply = match cmd {
+        DO if !matches!(opt, OPT_BINARY | OPT_ECHO | OPT_SGA) => WONT,
+        WILL if !matches!(opt, OPT_BINARY | OPT_SGA) => DONT,
+        _ => return,
+    };
+    replies.extend_from_slice(&[IAC, reply, opt]);
+}
+
+// Doubles any IAC bytes in `data` so the peer does not interpret them as commands.
+fn escape_iac(data: &[u8], out: &mut Vec<u8>) {
+    for &b in data {
+        out.push(b);
+        if b == IAC {
+            out.push(IAC);
+        }
+    }
+}
+
+enum Endpoint {
+    Listen(TcpListener),
+    Connect(Vec<SocketAddr>),
+}
+
+#[derive(EventToken)]
+enum Token {
+    Local,
+    Listener,
+    Remote,
+}
+
+/// Relays between the device end of the socket pair and the TCP peer.
+struct TcpRelay {
+    endpoint: Endpoint,
+    telnet: bool,
+    local: UnixStream,
+}
+
+impl TcpRelay {
+    fn run(mut self) {
+        loop {
+            let stream = match self.wait_for_peer() {
+                Ok(Some(stream)) => stream,
+                // The device went away.
+                Ok(None) => return,
+                Err(e) => {
+                    error!("serial tcp relay failed: {:#}", e);
+                    return;
+                }
+            };
+            match self.relay(stream) {
+                Ok(true) => info!("serial tcp peer disconnected"),
+                Ok(false) => return,
+                Err(e) => {
+                    error!("serial tcp relay failed: {:#}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Reads and drops whatever the device wrote. Returns false once the device end is closed.
+    fn discard_local(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; BUF_SIZE];
+        Ok(self.local.read(&mut buf)? != 0)
+    }
+
+    fn try_connect(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        for addr in addrs {
+            match TcpStream::connect_timeout(addr, RECONNECT_DELAY) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Waits until a peer is connected, discarding device output meanwhile. Returns `None` if the
+    /// device end is closed first.
+    fn wait_for_peer(&mut self) -> anyhow::Result<Option<TcpStream>> {
+        let wait_ctx = WaitContext::build_with(&[(&self.local, Token::Local)])
+            .context("failed to create wait context")?;
+        if let Endpoint::Listen(listener) = &self.endpoint {
+            wait_ctx
+                .add(listener, Token::Listener)
+                .context("failed to add listener to wait context")?;
+        }
+
+        let mut logged_failure = false;
+        // Device output wakes the wait below early, so only retry once the delay has passed since
+        // the last attempt instead of on every wakeup.
+        let mut next_attempt = Instant::now();
+        loop {
+            let events = match &self.endpoint {
+                Endpoint::Listen(_) => wait_ctx.wait(),
+                Endpoint::Connect(addrs) => {
+                    if Instant::now() >= next_attempt {
+                        match Self::try_connect(addrs) {
+                            Ok(stream) => return Ok(Some(stream)),
+                            Err(e) => {
+                                if !logged_failure {
+                                    info!("serial tcp connect failed, will retry: {}", e);
+                                    logged_failure = true;
+                                }
+                            }
+                        }
+                        next_attempt = Instant::now() + RECONNECT_DELAY;
+                    }
+                    wait_ctx.wait_timeout(next_attempt.saturating_duration_since(Instant::now()))
+                }
+            }
+            .context("failed to wait for events")?;
+
+            for event in events.iter().filter(|e| e.is_readable) {
+                match event.token {
+                    Token::Local => {
+                        if !self.discard_local().context("failed to read from device")? {
+                            return Ok(None);
+                        }
+                    }
+                    Token::Listener => {
+                        if let Endpoint::Listen(listener) = &self.endpoint {
+                            let (stream, addr) =
+                                listener.accept().context("failed to accept connection")?;
+                            info!("serial tcp peer connected from {}", addr);
+                            return Ok(Some(stream));
+                        }
+                    }
+                    Token::Remote => {}
+                }
+            }
+        }
+    }
+
+    /// Relays data until either side hangs up. Returns true if the peer disconnected and false if
+    /// the device end was closed.
+    fn relay(&mut self, mut stream: TcpStream) -> anyhow::Result<bool> {
+        if self.telnet && matches!(self.endpoint, Endpoint::Listen(_)) {
+            if let Err(e) = stream.write_all(TELNET_SERVER_INIT) {
+                info!("failed to send telnet negotiation: {}", e);
+                return Ok(true);
+            }
+        }
+
+        let wait_ctx =
+            WaitContext::build_with(&[(&self.local, Token::Local), (&stream, Token::Remote)])
+                .context("failed to create wait context")?;
+
+        let mut parser = TelnetParser::default();
+        let mut buf = [0u8; BUF_SIZE];
+        let mut data = Vec::new();
+        let mut replies = Vec::new();
+        loop {
+            let events = wait_ctx.wait().context("failed to wait for events")?;
+            for event in events.iter() {
+                match event.token {
+                    Token::Local => {
+                        let len = self
+                            .local
+                            .read(&mut buf)
+                            .context("failed to read from device")?;
+                        if len == 0 {
+                            return Ok(false);
+                        }
+                        let out = if self.telnet {
+                            data.clear();
+                            escape_iac(&buf[..len], &mut data);
+                            &data[..]
+                        } else {
+                            &buf[..len]
+                        };
+                        if stream.write_all(out).is_err() {
+                            return Ok(true);
+                        }
+                    }
+                    Token::Remote => {
+                        let len = match stream.read(&mut buf) {
+                            Ok(0) | Err(_) => return Ok(true),
+                            Ok(len) => len,
+                        };
+                        let input = if self.telnet {
+                            data.clear();
+                            replies.clear();
+                            parser.process(&buf[..len], &mut data, &mut replies);
+                            if stream.write_all(&replies).is_err() {
+                                return Ok(true);
+                            }
+                            &data[..]
+                        } else {
+                            &buf[..len]
+                        };
+                        self.local
+                            .write_all(input)
+                            .context("failed to write to device")?;
+                    }
+                    Token::Listener => {}
+                }
+            }
+        }
+    }
+}
+
+/// Creates a serial device whose input and output are relayed over TCP to `param.addr`.
+pub(crate) fn create_tcp_serial_device<T: SerialDevice>(
+    param: &SerialParameters,
+    protection_type: ProtectionType,
+    evt: Event,
+    keep_rds: &mut Vec<RawDescriptor>,
+) -> std::result::Result<T, Error> {
+    let addr = param.addr.as_ref().ok_or(Error::AddrRequired)?;
+    let addrs: Vec<SocketAddr> = addr
+        .to_socket_addrs()
+        .map_err(|e| Error::InvalidAddr(e, addr.clone()))?
+        .collect();
+    // Bind now so that a bad or busy address is reported at startup.
+    let endpoint = if param.listen {
+        Endpoint::Listen(TcpListener::bind(&addrs[..]).map_err(Error::TcpListen)?)
+    } else {
+        Endpoint::Connect(addrs)
+    };
+
+    let (input, relay_end) = UnixStream::pair().map_err(Error::SocketCreate)?;
+    let relay = TcpRelay {
+        endpoint,
+        telnet: param.telnet,
+        local: relay_end,
+    };
+    thread::Builder::new()
+        .name("serial_tcp".to_string())
+        .spawn(move || relay.run())
+        .map_err(Error::SpawnThread)?;
+
+    let output = input.try_clone().map_err(Error::CloneUnixStream)?;
+    keep_rds.push(input.as_raw_descriptor());
+    keep_rds.push(output.as_raw_descriptor());
+
+    Ok(T::new(
+        protection_type,
+        evt,
+        Some(Box::new(input)),
+        Some(Box::new(output)),
+        None,
+        SerialOptions {
+            name: param.name.clone(),
+            out_timestamp: param.out_timestamp,
+            console: param.console,
+            pci_address: param.pci_address,
+            max_queue_sizes: param.max_queue_sizes.clone(),
+            max_ports: param.max_ports,
+        },
+        keep_rds.to_vec(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[&[u8]]) -> (Vec<u8>, Vec<u8>) {
+        let mut parser = TelnetParser::default();
+        let mut data = Vec::new();
+        let mut replies = Vec::new();
+        for chunk in input {
+            parser.process(chunk, &mut data, &mut replies);
+        }
+        (data, replies)
+    }
+
+    #[test]
+    fn telnet_strips_commands() {
+        let (data, replies) = parse(&[b"ab\xff\xfd\x03c\xff\xf1d\xff\xfa\x18\x01\xff\xf0e"]);
+        assert_eq!(data, b"abcde");
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn telnet_split_across_reads() {
+        let (data, replies) = parse(&[b"a\xff", b"\xff", b"b\xff", b"\xfb", b"\x18c"]);
+        assert_eq!(data, b"a\xffbc");
+        // WILL TERMINAL-TYPE is refused.
+        assert_eq!(replies, [IAC, DONT, 0x18]);
+    }
+
+    #[test]
+    fn telnet_refuses_unknown_options() {
+        let (data, replies) = parse(&[&[IAC, DO, OPT_ECHO, IAC, DO, 0x1f, IAC, WILL, OPT_SGA]]);
+        assert!(data.is_empty());
+        assert_eq!(replies, [IAC, WONT, 0x1f]);
+    }
+
+    #[test]
+    fn telnet_cr_nul() {
+        let (data, _) = parse(&[b"a\r\0b\r\n"]);
+        assert_eq!(data, b"a\rb\r\n");
+    }
+
+    #[test]
+    fn escape_doubles_iac() {
+        let mut out = Vec::new();
+        escape_iac(b"a\xffb", &mut out);
+        assert_eq!(out, b"a\xff\xffb");
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! TPM 2.0 Command Response Buffer (CRB) interface, as defined by the TCG PC Client Platform TPM
+//! Profile (PTP) specification.
+//!
+//! Unlike virtio-tpm, the CRB interface is discovered through the ACPI `TPM2` table and the
+//! `MSFT0101` ACPI device, and is supported by firmware and by guests without virtio drivers, e.g.
+//! for measured boot or BitLocker.
+//!
+//! Only locality 0 is implemented, and commands are executed synchronously when the guest sets
+//! `CRB_CTRL_START`, so the guest never observes a command in progress and interrupts are not
+//! needed.
+
+use acpi_tables::aml;
+use acpi_tables::aml::Aml;
+use acpi_tables::sdt::SDT;
+use anyhow::Context;
+use base::warn;
+use base::RawDescriptor;
+use serde::Deserialize;
+use serde::Serialize;
+use snapshot::AnySnapshot;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+use crate::pci::CrosvmDeviceId;
+use crate::virtio::TpmBackend;
+use crate::BusAccessInfo;
+use crate::BusDevice;
+use crate::DeviceId;
+use crate::Suspendable;
+
+/// Size of the MMIO region of the CRB interface, holding the registers and the data buffer of
+/// locality 0.
+pub const TPM_CRB_MMIO_SIZE: u64 = 0x1000;
+
+// Register offsets.
+const CRB_LOC_STATE: u64 = 0x00;
+const CRB_LOC_CTRL: u64 = 0x08;
+const CRB_LOC_STS: u64 = 0x0c;
+const CRB_INTF_ID: u64 = 0x30;
+const CRB_CTRL_REQ: u64 = 0x40;
+const CRB_CTRL_STS: u64 = 0x44;
+const CRB_CTRL_CANCEL: u64 = 0x48;
+const CRB_CTRL_START: u64 = 0x4c;
+const CRB_INT_ENABLE: u64 = 0x50;
+const CRB_INT_STS: u64 = 0x54;
+const CRB_CTRL_CMD_SIZE: u64 = 0x58;
+const CRB_CTRL_CMD_LADDR: u64 = 0x5c;
+const CRB_CTRL_CMD_HADDR: u64 = 0x60;
+const CRB_CTRL_RSP_SIZE: u64 = 0x64;
+const CRB_CTRL_RSP_ADDR: u64 = 0x68;
+const CRB_CTRL_RSP_ADDR_HI: u64 = 0x6c;
+const CRB_DATA_BUFFER: u64 = 0x80;
+
+// The command and response share the data buffer, which fills the rest of the region.
+const CRB_BUFFER_SIZE: usize = (TPM_CRB_MMIO_SIZE - CRB_DATA_BUFFER) as usize;
+
+// CRB_LOC_STATE bits.
+const LOC_STATE_TPM_ESTABLISHED: u32 = 1 << 0;
+const LOC_STATE_LOC_ASSIGNED: u32 = 1 << 1;
+const LOC_STATE_REG_VALID_STS: u32 = 1 << 7;
+
+// CRB_LOC_CTRL bits.
+const LOC_CTRL_REQUEST_ACCESS: u32 = 1 << 0;
+const LOC_CTRL_RELINQUISH: u32 = 1 << 1;
+
+// CRB_LOC_STS bits.
+const LOC_STS_GRANTED: u32 = 1 << 0;
+
+// CRB_INTF_ID: CRB interface type and version, CRB capability, 64 byte data transfers and the CRB
+// interface selected.
+const INTF_ID: u32 = 0x1 | (0x1 << 4) | (0x3 << 11) | (1 << 14) | (0x1 << 17);
+
+// CRB_CTRL_REQ bits.
+const CTRL_REQ_CMD_READY: u32 = 1 << 0;
+const CTRL_REQ_GO_IDLE: u32 = 1 << 1;
+
+// CRB_CTRL_STS bits.
+const CTRL_STS_TPM_IDLE: u32 = 1 << 1;
+
+// CRB_CTRL_START bits.
+const CTRL_START: u32 = 1 << 0;
+
+// Size of the TPM 2.0 command header: tag (u16) followed by the total command size (u32).
+const TPM_HEADER_SIZE: usize = 6;
+
+// TPM2 table values.
+const TPM2_REVISION: u8 = 4;
+const OEM_REVISION: u32 = 1;
+const TPM2_PLATFORM_CLASS_CLIENT: u16 = 0;
+const TPM2_START_METHOD_CRB: u32 = 7;
+
+/// The fixed part of the ACPI `TPM2` table, following the table header.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
+struct Tpm2Table {
+    platform_class: u16,
+    reserved: u16,
+    control_area: u64,
+    start_method: u32,
+    start_method_params: [u8; 12],
+}
+
+#[derive(Serialize, Deserialize)]
+struct TpmCrbSnapshot {
+    loc_assigned: bool,
+    idle: bool,
+    int_enable: u32,
+    buffer: Vec<u8>,
+    backend: AnySnapshot,
+}
+
+/// A TPM 2.0 CRB interface forwarding the guest's commands to a `TpmBackend`.
+pub struct TpmCrb {
+    backend: Box<dyn TpmBackend>,
+    mmio_base: u64,
+    loc_assigned: bool,
+    idle: bool,
+    int_enable: u32,
+    buffer: Vec<u8>,
+}
+
+impl TpmCrb {
+    /// Creates a CRB interface whose registers are mapped at `mmio_base`.
+    pub fn new(backend: Box<dyn TpmBackend>, mmio_base: u64) -> TpmCrb {
+        TpmCrb {
+            backend,
+            mmio_base,
+            loc_assigned: false,
+            idle: true,
+            int_enable: 0,
+            buffer: vec![0; CRB_BUFFER_SIZE],
+        }
+    }
+
+    /// Descriptors that must stay open when the device is sandboxed.
+    pub fn keep_rds(&self) -> Vec<RawDescriptor> {
+        self.backend.keep_rds()
+    }
+
+    fn read_reg(&self, offset: u64) -> u32 {
+        let buffer_addr = self.mmio_base + CRB_DATA_BUFFER;
+        match offset {
+            CRB_LOC_STATE => {
+                let mut state = LOC_STATE_TPM_ESTABLISHED | LOC_STATE_REG_VALID_STS;
+                if self.loc_assigned {
+                    state |= LOC_STATE_LOC_ASSIGNED;
+                }
+                state
+            }
+            CRB_LOC_STS => {
+                if self.loc_assigned {
+                    LOC_STS_GRANTED
+                } else {
+                    0
+                }
+            }
+            CRB_INTF_ID => INTF_ID,
+            CRB_CTRL_STS => {
+                if self.idle {
+                    CTRL_STS_TPM_IDLE
+                } else {
+                    0
+                }
+            }
+            CRB_INT_ENABLE => self.int_enable,
+            CRB_CTRL_CMD_SIZE | CRB_CTRL_RSP_SIZE => CRB_BUFFER_SIZE as u32,
+            CRB_CTRL_CMD_LADDR | CRB_CTRL_RSP_ADDR => buffer_addr as u32,
+            CRB_CTRL_CMD_HADDR | CRB_CTRL_RSP_ADDR_HI => (buffer_addr >> 32) as u32,
+            // Requests and commands complete before the write returns, so the bits the guest
+            // polls for completion, e.g. in CRB_CTRL_REQ and CRB_CTRL_START, always read as 0.
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, offset: u64, value: u32) {
+        match offset {
+            CRB_LOC_CTRL => {
+                if value & LOC_CTRL_REQUEST_ACCESS != 0 {
+                    self.loc_assigned = true;
+                }
+                if value & LOC_CTRL_RELINQUISH != 0 {
+                    self.loc_assigned = false;
+                }
+            }
+            CRB_CTRL_REQ => {
+                if value & CTRL_REQ_CMD_READY != 0 {
+                    self.idle = false;
+                }
+                if value & CTRL_REQ_GO_IDLE != 0 {
+                    self.idle = true;
+                }
+            }
+            CRB_CTRL_START => {
+                if value & CTRL_START != 0 {
+                    self.execute_command();
+                }
+            }
+            CRB_INT_ENABLE => self.int_enable = value,
+            // Nothing to cancel or acknowledge, commands complete synchronously.
+            CRB_CTRL_CANCEL | CRB_INT_STS => {}
+            _ => warn!("tpm_crb: write to read-only register {:#x}", offset),
+        }
+    }
+
+    fn execute_command(&mut self) {
+        let size = u32::from_be_bytes(self.buffer[2..TPM_HEADER_SIZE].try_into().unwrap()) as usize;
+        if !(TPM_HEADER_SIZE..=CRB_BUFFER_SIZE).contains(&size) {
+            warn!("tpm_crb: invalid command size {}", size);
+            return;
+        }
+        let response = self.backend.execute_command(&self.buffer[..size]);
+        if response.len() > CRB_BUFFER_SIZE {
+            warn!(
+                "tpm_crb: truncating a {} bytes response to {} bytes",
+                response.len(),
+                CRB_BUFFER_SIZE
+            );
+        }
+        let len = response.len().min(CRB_BUFFER_SIZE);
+        self.buffer[..len].copy_from_slice(&response[..len]);
+    }
+}
+
+/// Returns the ACPI `TPM2` table describing a CRB interface mapped at `mmio_base`.
+pub fn create_tpm2_table(mmio_base: u64) -> SDT {
+    let mut tpm2 = SDT::new(
+        *b"TPM2",
+        acpi_tables::HEADER_LEN,
+        TPM2_REVISION,
+        *b"CROSVM",
+        *b"CROSVMDT",
+        OEM_REVISION,
+    );
+    tpm2.append(Tpm2Table {
+        platform_class: TPM2_PLATFORM_CLASS_CLIENT,
+        control_area: mmio_base + CRB_CTRL_REQ,
+        start_method: TPM2_START_METHOD_CRB,
+        ..Default::default()
+    });
+    tpm2
+}
+
+/// Appends the AML of the `MSFT0101` device of a CRB interface mapped at `mmio_base` to `amls`.
+pub fn generate_aml(mmio_base: u64, amls: &mut Vec<u8>) {
+    aml::Device::new(
+        "_SB_.TPM0".into(),
+        vec![
+            &aml::Name::new("_HID".into(), &"MSFT0101"),
+            &aml::Name::new("_STA".into(), &0xfu8),
+            &aml::Name::new(
+                "_CRS".into(),
+                &aml::ResourceTemplate::new(vec![&aml::Memory32Fixed::new(
+                    true,
+                    mmio_base as u32,
+                    TPM_CRB_MMIO_SIZE as u32,
+                )]),
+            ),
+        ],
+    )
+    .to_aml_bytes(amls);
+}
+
+impl BusDevice for TpmCrb {
+    fn device_id(&self) -> DeviceId {
+        CrosvmDeviceId::TpmCrb.into()
+    }
+
+    fn debug_label(&self) -> String {
+        "TpmCrb".to_owned()
+    }
+
+    fn read(&mut self, info: BusAccessInfo, data: &mut [u8]) {
+        if info.offset >= CRB_DATA_BUFFER {
+            let start = (info.offset - CRB_DATA_BUFFER) as usize;
+            match self.buffer.get(start..start + data.len()) {
+                Some(buffer) => data.copy_from_slice(buffer),
+                None => data.fill(0),
+            }
+            return;
+        }
+        let value = match data.len() {
+            4 => u64::from(self.read_reg(info.offset)),
+            8 => {
+                u64::from(self.read_reg(info.offset))
+                    | u64::from(self.read_reg(info.offset + 4)) << 32
+            }
+            _ => {
+                warn!("tpm_crb: unsupported read {}", info);
+                data.fill(0);
+                return;
+            }
+        };
+        data.copy_from_slice(&value.to_le_bytes()[..data.len()]);
+    }
+
+    fn write(&mut self, info: BusAccessInfo, data: &[u8]) {
+        if info.offset >= CRB_DATA_BUFFER {
+            let start = (info.offset - CRB_DATA_BUFFER) as usize;
+            match self.buffer.get_mut(start..start + data.len()) {
+                Some(buffer) => buffer.copy_from_slice(data),
+                None => warn!("tpm_crb: write past the data buffer {}", info),
+            }
+            return;
+        }
+        match data.len() {
+            4 => self.write_reg(info.offset, u32::from_le_bytes(data.try_into().unwrap())),
+            8 => {
+                let value = u64::from_le_bytes(data.try_into().unwrap());
+                self.write_reg(info.offset, value as u32);
+                self.write_reg(info.offset + 4, (value >> 32) as u32);
+            }
+            _ => warn!("tpm_crb: unsupported write {}", info),
+        }
+    }
+}
+
+impl Suspendable for TpmCrb {
+    // Commands complete within a bus access, so there is nothing to quiesce.
+    fn sleep(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn wake(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        AnySnapshot::to_any(TpmCrbSnapshot {
+            loc_assigned: self.loc_assigned,
+            idle: self.idle,
+            int_enable: self.int_enable,
+            buffer: self.buffer.clone(),
+            backend: self
+                .backend
+                .snapshot()
+                .context("failed to snapshot tpm backend")?,
+        })
+        .context("failed to serialize tpm_crb snapshot")
+    }
+
+    fn restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        let snapshot: TpmCrbSnapshot =
+            AnySnapshot::from_any(data).context("failed to deserialize tpm_crb snapshot")?;
+        anyhow::ensure!(
+            snapshot.buffer.len() == CRB_BUFFER_SIZE,
+            "tpm_crb snapshot has a {} bytes buffer",
+            snapshot.buffer.len()
+        );
+        self.backend
+            .restore(snapshot.backend)
+            .context("failed to restore tpm backend")?;
+        self.loc_assigned = snapshot.loc_assigned;
+        self.idle = snapshot.idle;
+        self.int_enable = snapshot.int_enable;
+        self.buffer = snapshot.buffer;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MMIO_BASE: u64 = 0xfed4_0000;
+
+    // Echoes the command back with the tag of a response.
+    struct EchoBackend {
+        response: Vec<u8>,
+    }
+
+    impl TpmBackend for EchoBackend {
+        fn execute_command<'a>(&'a mut self, command: &[u8]) -> &'a [u8] {
+            self.response = command.to_vec();
+            self.response[..2].copy_from_slice(&[0x80, 0x01]);
+            &self.response
+        }
+    }
+
+    fn new_crb() -> TpmCrb {
+        TpmCrb::new(
+            Box::new(EchoBackend {
+                response: Vec::new(),
+            }),
+            MMIO_BASE,
+        )
+    }
+
+    fn access(offset: u64) -> BusAccessInfo {
+        BusAccessInfo {
+            offset,
+            address: MMIO_BASE + offset,
+            id: 0,
+        }
+    }
+
+    fn read32(crb: &mut TpmCrb, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        crb.read(access(offset), &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write32(crb: &mut TpmCrb, offset: u64, value: u32) {
+        crb.write(access(offset), &value.to_le_bytes());
+    }
+
+    #[test]
+    fn locality_request_and_relinquish() {
+        let mut crb = new_crb();
+        assert_eq!(read32(&mut crb, CRB_LOC_STATE) & LOC_STATE_LOC_ASSIGNED, 0);
+
+        write32(&mut crb, CRB_LOC_CTRL, LOC_CTRL_REQUEST_ACCESS);
+        let state = read32(&mut crb, CRB_LOC_STATE);
+        assert_ne!(state & LOC_STATE_LOC_ASSIGNED, 0);
+        assert_ne!(state & LOC_STATE_REG_VALID_STS, 0);
+        assert_eq!(read32(&mut crb, CRB_LOC_STS), LOC_STS_GRANTED);
+
+        write32(&mut crb, CRB_LOC_CTRL, LOC_CTRL_RELINQUISH);
+        assert_eq!(read32(&mut crb, CRB_LOC_STATE) & LOC_STATE_LOC_ASSIGNED, 0);
+        assert_eq!(read32(&mut crb, CRB_LOC_STS), 0);
+    }
+
+    #[test]
+    fn cmd_ready_and_go_idle() {
+        let mut crb = new_crb();
+        assert_eq!(read32(&mut crb, CRB_CTRL_STS), CTRL_STS_TPM_IDLE);
+        write32(&mut crb, CRB_CTRL_REQ, CTRL_REQ_CMD_READY);
+        assert_eq!(read32(&mut crb, CRB_CTRL_REQ), 0);
+        assert_eq!(read32(&mut crb, CRB_CTRL_STS), 0);
+        write32(&mut crb, CRB_CTRL_REQ, CTRL_REQ_GO_IDLE);
+        assert_eq!(read32(&mut crb, CRB_CTRL_STS), CTRL_STS_TPM_IDLE);
+    }
+
+    #[test]
+    fn buffer_addresses() {
+        let mut crb = new_crb();
+        let buffer_addr = (MMIO_BASE + CRB_DATA_BUFFER) as u32;
+        assert_eq!(read32(&mut crb, CRB_CTRL_CMD_LADDR), buffer_addr);
+        assert_eq!(read32(&mut crb, CRB_CTRL_CMD_HADDR), 0);
+        assert_eq!(read32(&mut crb, CRB_CTRL_CMD_SIZE), CRB_BUFFER_SIZE as u32);
+        let mut rsp_addr = [0u8; 8];
+        crb.read(access(CRB_CTRL_RSP_ADDR), &mut rsp_addr);
+        assert_eq!(u64::from_le_bytes(rsp_addr), u64::from(buffer_addr));
+    }
+
+    #[test]
+    fn start_executes_command() {
+        let mut crb = new_crb();
+        write32(&mut crb, CRB_LOC_CTRL, LOC_CTRL_REQUEST_ACCESS);
+        write32(&mut crb, CRB_CTRL_REQ, CTRL_REQ_CMD_READY);
+
+        // TPM2_GetRandom of 8 bytes.
+        let command = [
+            0x80, 0x01, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x01, 0x7b, 0x00, 0x08,
+        ];
+        crb.write(access(CRB_DATA_BUFFER), &command);
+        write32(&mut crb, CRB_CTRL_START, CTRL_START);
+        assert_eq!(read32(&mut crb, CRB_CTRL_START), 0);
+
+        let mut response = [0u8; 12];
+        crb.read(access(CRB_DATA_BUFFER), &mut response);
+        assert_eq!(response, command);
+    }
+
+    #[test]
+    fn start_ignores_invalid_size() {
+        let mut crb = new_crb();
+        let command = [0x80, 0x01, 0xff, 0xff, 0xff, 0xff];
+        crb.write(access(CRB_DATA_BUFFER), &command);
+        write32(&mut crb, CRB_CTRL_START, CTRL_START);
+
+        let mut response = [0u8; 6];
+        crb.read(access(CRB_DATA_BUFFER), &mut response);
+        assert_eq!(response, command);
+    }
+
+    #[test]
+    fn tpm2_table() {
+        let tpm2 = create_tpm2_table(MMIO_BASE);
+        assert!(tpm2.is_signature(b"TPM2"));
+        assert_eq!(tpm2.len(), acpi_tables::HEADER_LEN as usize + 28);
+        assert_eq!(
+            tpm2.read::<u64>(acpi_tables::HEADER_LEN as usize + 4),
+            MMIO_BASE + CRB_CTRL_REQ
+        );
+        assert_eq!(
+            tpm2.read::<u32>(acpi_tables::HEADER_LEN as usize + 12),
+            TPM2_START_METHOD_CRB
+        );
+    }
+}
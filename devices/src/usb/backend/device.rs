@@ -30,12 +30,16 @@ use crate::usb::backend::endpoint::UsbEndpoint;
 use crate::usb::backend::error::Error;
 use crate::usb::backend::error::Result;
 use crate::usb::backend::fido_backend::fido_passthrough::FidoPassthroughDevice;
-use crate::usb::backend::fido_backend::transfer::FidoTransfer;
+use crate::usb::backend::gadget::transfer::GadgetTransfer;
+use crate::usb::backend::hid_gadget::descriptors;
+use crate::usb::backend::hid_gadget::hid_gadget_device::HidGadgetDevice;
 use crate::usb::backend::host_backend::host_device::HostDevice;
 use crate::usb::backend::transfer::BackendTransfer;
 use crate::usb::backend::transfer::BackendTransferHandle;
 use crate::usb::backend::transfer::BackendTransferType;
 use crate::usb::backend::transfer::ControlTransferState;
+use crate::usb::backend::usbip_backend::transfer::UsbipTransfer;
+use crate::usb::backend::usbip_backend::usbip_device::UsbipDevice;
 use crate::usb::backend::utils::multi_dispatch;
 use crate::usb::backend::utils::update_transfer_state;
 use crate::usb::xhci::scatter_gather_buffer::ScatterGatherBuffer;
@@ -57,11 +61,20 @@ pub enum BackendDeviceType {
     HostDevice(HostDevice),
     // Virtual security key implementation
     FidoDevice(FidoPassthroughDevice),
+    // Device exported by a remote usbip server
+    UsbipDevice(UsbipDevice),
+    // Emulated HID keyboard, mouse or tablet fed by the host
+    HidGadget(HidGadgetDevice),
 }
 
 impl AsRawDescriptor for BackendDeviceType {
     fn as_raw_descriptor(&self) -> RawDescriptor {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, as_raw_descriptor)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            as_raw_descriptor
+        )
     }
 }
 
@@ -73,7 +86,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             submit_backend_transfer,
             transfer
         )
@@ -83,7 +96,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             detach_event_handler,
             event_loop
         )
@@ -93,7 +106,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             request_transfer_buffer,
             size
         )
@@ -108,7 +121,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             build_bulk_transfer,
             ep_addr,
             transfer_buffer,
@@ -124,31 +137,53 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             build_interrupt_transfer,
             ep_addr,
             transfer_buffer
         )
     }
 
+    fn build_isochronous_transfer(
+        &mut self,
+        ep_addr: u8,
+        transfer_buffer: TransferBuffer,
+        packet_lengths: &[u32],
+    ) -> Result<BackendTransferType> {
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            build_isochronous_transfer,
+            ep_addr,
+            transfer_buffer,
+            packet_lengths
+        )
+    }
+
     fn get_control_transfer_state(&mut self) -> Arc<RwLock<ControlTransferState>> {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             get_control_transfer_state
         )
     }
 
     fn get_device_state(&mut self) -> Arc<RwLock<DeviceState>> {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, get_device_state)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            get_device_state
+        )
     }
 
     fn get_active_config_descriptor(&mut self) -> Result<ConfigDescriptorTree> {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             get_active_config_descriptor
         )
     }
@@ -157,7 +192,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             get_config_descriptor,
             config
         )
@@ -167,7 +202,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             get_config_descriptor_by_index,
             config_index
         )
@@ -177,7 +212,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             get_device_descriptor_tree
         )
     }
@@ -186,7 +221,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             get_active_configuration
         )
     }
@@ -195,7 +230,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             set_active_configuration,
             config
         )
@@ -205,7 +240,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             clear_feature,
             value,
             index
@@ -216,7 +251,7 @@ impl BackendDevice for BackendDeviceType {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             create_endpoints,
             config_descriptor
         )
@@ -225,34 +260,60 @@ impl BackendDevice for BackendDeviceType {
 
 impl XhciBackendDevice for BackendDeviceType {
     fn get_backend_type(&self) -> BackendType {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, get_backend_type)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            get_backend_type
+        )
     }
 
     fn get_vid(&self) -> u16 {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, get_vid)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            get_vid
+        )
     }
 
     fn get_pid(&self) -> u16 {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, get_pid)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            get_pid
+        )
     }
 
     fn set_address(&mut self, address: UsbDeviceAddress) {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, set_address, address)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            set_address,
+            address
+        )
     }
 
     fn reset(&mut self) -> Result<()> {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, reset)
+        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice UsbipDevice HidGadget, reset)
     }
 
     fn get_speed(&self) -> Option<DeviceSpeed> {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, get_speed)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            get_speed
+        )
     }
 
     fn alloc_streams(&self, ep: u8, num_streams: u16) -> Result<()> {
         multi_dispatch!(
             self,
             BackendDeviceType,
-            HostDevice FidoDevice,
+            HostDevice FidoDevice UsbipDevice HidGadget,
             alloc_streams,
             ep,
             num_streams
@@ -260,11 +321,17 @@ impl XhciBackendDevice for BackendDeviceType {
     }
 
     fn free_streams(&self, ep: u8) -> Result<()> {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, free_streams, ep)
+        multi_dispatch!(
+            self,
+            BackendDeviceType,
+            HostDevice FidoDevice UsbipDevice HidGadget,
+            free_streams,
+            ep
+        )
     }
 
     fn stop(&mut self) {
-        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice, stop)
+        multi_dispatch!(self, BackendDeviceType, HostDevice FidoDevice UsbipDevice HidGadget, stop)
     }
 }
 
@@ -308,6 +375,19 @@ impl BackendDeviceType {
             return Ok(false);
         };
 
+        // The usbip server applies these requests to the exported device itself. They are passed
+        // through and the device tracks them once the server completed them.
+        if let BackendDeviceType::UsbipDevice(_) = self {
+            if matches!(
+                standard_request,
+                StandardControlRequest::SetConfiguration
+                    | StandardControlRequest::SetInterface
+                    | StandardControlRequest::ClearFeature
+            ) {
+                return Ok(false);
+            }
+        }
+
         let (status, bytes_transferred) = match (standard_request, recipient, direction) {
             (
                 StandardControlRequest::SetAddress,
@@ -352,7 +432,7 @@ impl BackendDeviceType {
                         }
                     },
                     _ => {
-                        // Nothing to do for non-host devices
+                        // Nothing to do for virtual devices
                         (TransferStatus::Completed, 0)
                     }
                 }
@@ -418,6 +498,13 @@ impl BackendDeviceType {
                                 }
                             }
                         }
+                        // The remote device answers with its own descriptors.
+                        BackendDeviceType::UsbipDevice(_) => return Ok(false),
+                        BackendDeviceType::HidGadget(hid_gadget) => {
+                            let data = descriptors::config_descriptor(hid_gadget.kind());
+                            let bytes = buffer.write(&data).map_err(Error::WriteBuffer)?;
+                            (TransferStatus::Completed, bytes as u32)
+                        }
                     }
                 } else {
                     return Ok(false);
@@ -476,10 +563,15 @@ impl BackendDeviceType {
                 Transfer::new_control(TransferBuffer::Vector(control_buffer))
                     .map_err(Error::CreateTransfer)?,
             ),
-            BackendDeviceType::FidoDevice(_) => BackendTransferType::FidoDevice(FidoTransfer::new(
-                0,
-                TransferBuffer::Vector(control_buffer),
-            )),
+            BackendDeviceType::FidoDevice(_) | BackendDeviceType::HidGadget(_) => {
+                BackendTransferType::Gadget(GadgetTransfer::new(
+                    0,
+                    TransferBuffer::Vector(control_buffer),
+                ))
+            }
+            BackendDeviceType::UsbipDevice(_) => BackendTransferType::UsbipDevice(
+                UsbipTransfer::new(0, TransferBuffer::Vector(control_buffer), 0, Vec::new()),
+            ),
         };
 
         let tmp_transfer = xhci_transfer.clone();
@@ -784,6 +876,14 @@ pub trait BackendDevice: Sync + Send {
         ep_addr: u8,
         transfer_buffer: TransferBuffer,
     ) -> Result<BackendTransferType>;
+    /// Requests the backend to build a backend-specific isochronous transfer request with one
+    /// packet of each length in `packet_lengths`
+    fn build_isochronous_transfer(
+        &mut self,
+        ep_addr: u8,
+        transfer_buffer: TransferBuffer,
+        packet_lengths: &[u32],
+    ) -> Result<BackendTransferType>;
 
     /// Returns the `ControlTransferState` for the given backend device.
     fn get_control_transfer_state(&mut self) -> Arc<RwLock<ControlTransferState>>;
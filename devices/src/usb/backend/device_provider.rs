@@ -5,6 +5,8 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::mem;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -13,19 +15,24 @@ use base::error;
 use base::AsRawDescriptor;
 use base::EventType;
 use base::RawDescriptor;
+use base::SafeDescriptor;
 use base::Tube;
 use sync::Mutex;
 use vm_control::UsbControlAttachedDevice;
 use vm_control::UsbControlCommand;
 use vm_control::UsbControlResult;
+use vm_control::UsbHidGadgetKind;
 use vm_control::USB_CONTROL_MAX_PORTS;
 
 use crate::usb::backend::device::BackendDevice;
+use crate::usb::backend::device::BackendDeviceType;
 use crate::usb::backend::device::DeviceState;
 use crate::usb::backend::error::Error;
 use crate::usb::backend::error::Result;
 use crate::usb::backend::fido_backend::fido_provider::attach_security_key;
+use crate::usb::backend::hid_gadget::hid_gadget_provider::attach_hid_gadget;
 use crate::usb::backend::host_backend::host_backend_device_provider::attach_host_backend_device;
+use crate::usb::backend::usbip_backend::usbip_provider::attach_usbip_device;
 use crate::usb::xhci::usb_hub::UsbHub;
 use crate::usb::xhci::xhci_backend_device::XhciBackendDevice;
 use crate::usb::xhci::xhci_backend_device_provider::XhciBackendDeviceProvider;
@@ -282,6 +289,131 @@ impl ProviderInner {
         }
     }
 
+    fn handle_attach_usbip_device(&self, socket: SafeDescriptor, busid: &str) -> UsbControlResult {
+        let (usbip_device, event_handler) = match attach_usbip_device(
+            TcpStream::from(socket),
+            busid,
+            DeviceState::new(self.fail_handle.clone(), self.job_queue.clone()),
+        ) {
+            Ok((usbip_device, event_handler)) => (usbip_device, event_handler),
+            Err(e) => {
+                error!("could not import usbip device {}: {}", busid, e);
+                return UsbControlResult::NoSuchDevice;
+            }
+        };
+
+        if let Err(e) = self.event_loop.add_event(
+            &*usbip_device.lock(),
+            EventType::Read,
+            Arc::downgrade(&event_handler),
+        ) {
+            error!("failed to add usbip device to event handler: {}", e);
+            return UsbControlResult::FailedToOpenDevice;
+        }
+
+        let device_ctx = DeviceContext {
+            event_handler,
+            device: usbip_device.clone(),
+        };
+
+        let port = self.usb_hub.connect_backend(usbip_device);
+        match port {
+            Ok(port) => {
+                self.devices.lock().insert(port, device_ctx);
+                UsbControlResult::Ok { port }
+            }
+            Err(e) => {
+                error!("failed to connect device to hub: {}", e);
+                UsbControlResult::NoAvailablePort
+            }
+        }
+    }
+
+    fn handle_attach_hid_gadget(
+        &self,
+        kind: UsbHidGadgetKind,
+        report_socket: Option<UnixStream>,
+    ) -> UsbControlResult {
+        let (hid_gadget, event_handler) = match attach_hid_gadget(
+            kind,
+            report_socket,
+            DeviceState::new(self.fail_handle.clone(), self.job_queue.clone()),
+        ) {
+            Ok((hid_gadget, event_handler)) => (hid_gadget, event_handler),
+            Err(e) => {
+                error!("could not create hid gadget: {}", e);
+                return UsbControlResult::FailedToOpenDevice;
+            }
+        };
+
+        if let Err(e) = self.event_loop.add_event(
+            &*hid_gadget.lock(),
+            EventType::Read,
+            Arc::downgrade(&event_handler),
+        ) {
+            error!("failed to add hid gadget to event handler: {}", e);
+            return UsbControlResult::FailedToOpenDevice;
+        }
+
+        if let BackendDeviceType::HidGadget(device) = &*hid_gadget.lock() {
+            if let Some(socket) = device.report_socket() {
+                if let Err(e) = self.event_loop.add_event(
+                    socket,
+                    EventType::Read,
+                    Arc::downgrade(&event_handler),
+                ) {
+                    error!("failed to add hid report socket to event handler: {}", e);
+                    let _ = self.event_loop.remove_event_for_descriptor(device);
+                    return UsbControlResult::FailedToOpenDevice;
+                }
+            }
+        }
+
+        let device_ctx = DeviceContext {
+            event_handler,
+            device: hid_gadget.clone(),
+        };
+
+        let port = self.usb_hub.connect_backend(hid_gadget);
+        match port {
+            Ok(port) => {
+                self.devices.lock().insert(port, device_ctx);
+                UsbControlResult::Ok { port }
+            }
+            Err(e) => {
+                error!("failed to connect device to hub: {}", e);
+                UsbControlResult::NoAvailablePort
+            }
+        }
+    }
+
+    fn handle_send_hid_report(&self, port: u8, report: Vec<u8>) -> UsbControlResult {
+        let device = match self
+            .usb_hub
+            .get_port(port)
+            .and_then(|p| p.backend_device().clone())
+        {
+            Some(device) => device,
+            None => return UsbControlResult::NoSuchDevice,
+        };
+        let mut device = device.lock();
+        let hid_gadget = match &mut *device {
+            BackendDeviceType::HidGadget(hid_gadget) => hid_gadget,
+            _ => return UsbControlResult::NoSuchDevice,
+        };
+        match hid_gadget.queue_report(report) {
+            Ok(()) => UsbControlResult::Ok { port },
+            Err(Error::InvalidHidReport(len)) => {
+                error!("hid report of {} bytes does not match the gadget", len);
+                UsbControlResult::InvalidReport
+            }
+            Err(e) => {
+                error!("failed to queue hid report: {}", e);
+                UsbControlResult::FailedToOpenDevice
+            }
+        }
+    }
+
     fn handle_list_devices(&self, ports: [u8; USB_CONTROL_MAX_PORTS]) -> UsbControlResult {
         let mut devices: [UsbControlAttachedDevice; USB_CONTROL_MAX_PORTS] = Default::default();
         for (result_index, &port_id) in ports.iter().enumerate() {
@@ -310,6 +442,18 @@ impl ProviderInner {
         let result = match cmd {
             UsbControlCommand::AttachDevice { file } => self.handle_attach_device(file),
             UsbControlCommand::AttachSecurityKey { file } => self.handle_attach_security_key(file),
+            UsbControlCommand::AttachUsbipDevice { socket, busid } => {
+                self.handle_attach_usbip_device(socket, &busid)
+            }
+            UsbControlCommand::AttachHidGadget { kind } => {
+                self.handle_attach_hid_gadget(kind, None)
+            }
+            UsbControlCommand::AttachHidGadgetWithSocket { kind, socket } => {
+                self.handle_attach_hid_gadget(kind, Some(UnixStream::from(socket)))
+            }
+            UsbControlCommand::SendHidReport { port, report } => {
+                self.handle_send_hid_report(port, report)
+            }
             UsbControlCommand::DetachDevice { port } => self.handle_detach_device(port),
             UsbControlCommand::ListDevice { ports } => self.handle_list_devices(ports),
         };
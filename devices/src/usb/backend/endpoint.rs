@@ -89,7 +89,9 @@ impl UsbEndpoint {
             .get_transfer_type()
             .map_err(Error::GetXhciTransferType)?
         {
-            XhciTransferType::Normal => transfer.create_buffer().map_err(Error::CreateBuffer)?,
+            XhciTransferType::Normal | XhciTransferType::Isochronous => {
+                transfer.create_buffer().map_err(Error::CreateBuffer)?
+            }
             XhciTransferType::Noop => {
                 return transfer
                     .on_transfer_complete(&TransferStatus::Completed, 0)
@@ -110,6 +112,9 @@ impl UsbEndpoint {
             EndpointType::Interrupt => {
                 self.handle_interrupt_transfer(device, transfer, buffer)?;
             }
+            EndpointType::Isochronous => {
+                self.handle_isochronous_transfer(device, transfer, buffer)?;
+            }
             _ => {
                 return transfer
                     .on_transfer_complete(&TransferStatus::Error, 0)
@@ -172,6 +177,21 @@ impl UsbEndpoint {
         self.do_handle_transfer(device, xhci_transfer, usb_transfer, buffer)
     }
 
+    fn handle_isochronous_transfer(
+        &self,
+        device: &mut BackendDeviceType,
+        xhci_transfer: XhciTransfer,
+        buffer: ScatterGatherBuffer,
+    ) -> Result<()> {
+        // Each isochronous TD carries the data of one service interval, which the host controller
+        // moves as a single isochronous packet.
+        let len = buffer.len().map_err(Error::BufferLen)?;
+        let transfer_buffer = self.get_transfer_buffer(&buffer, device)?;
+        let usb_transfer =
+            device.build_isochronous_transfer(self.ep_addr(), transfer_buffer, &[len as u32])?;
+        self.do_handle_transfer(device, xhci_transfer, usb_transfer, buffer)
+    }
+
     fn do_handle_transfer(
         &self,
         device: &mut BackendDeviceType,
@@ -8,6 +8,7 @@ use thiserror::Error;
 use usb_util::Error as UsbUtilError;
 
 use crate::usb::backend::fido_backend::error::Error as FidoError;
+use crate::usb::backend::usbip_backend::error::Error as UsbipError;
 use crate::usb::xhci::scatter_gather_buffer::Error as BufferError;
 use crate::usb::xhci::xhci_transfer::Error as XhciTransferError;
 use crate::utils::Error as UtilsError;
@@ -33,6 +34,8 @@ pub enum Error {
     CreateControlTube(TubeError),
     #[error("failed to create fido backend device: {0}")]
     CreateFidoBackendDevice(FidoError),
+    #[error("failed to create hid gadget: {0}")]
+    CreateHidGadget(base::Error),
     #[error("failed to create host backend usb device: {0}")]
     CreateHostUsbDevice(UsbUtilError),
     #[error("failed to create libusb context: {0}")]
@@ -41,6 +44,8 @@ pub enum Error {
     CreateTransfer(UsbUtilError),
     #[error("failed to create USB request setup: {0}")]
     CreateUsbRequestSetup(XhciTransferError),
+    #[error("failed to create usbip backend device: {0}")]
+    CreateUsbipBackendDevice(UsbipError),
     #[error("failed to free streams: {0}")]
     FreeStreams(UsbUtilError),
     #[error("failed to get active config: {0}")]
@@ -60,6 +65,8 @@ pub enum Error {
     GetInterfaceDescriptor(u8, u8),
     #[error("failed to get xhci transfer type: {0}")]
     GetXhciTransferType(XhciTransferError),
+    #[error("hid report has the wrong length: {0}")]
+    InvalidHidReport(usize),
     #[error("the backend received the wrong transfer request")]
     MalformedBackendTransfer,
     #[error("request missing required data buffer")]
@@ -82,8 +89,14 @@ pub enum Error {
     SetInterfaceAltSetting(UsbUtilError),
     #[error("failed to setup control tube: {0}")]
     SetupControlTube(TubeError),
+    #[error("failed to signal hid gadget event: {0}")]
+    SignalHidGadget(base::Error),
     #[error("failed to start async job queue: {0}")]
     StartAsyncJobQueue(UtilsError),
+    #[error("the backend device does not support bulk streams")]
+    StreamsNotSupported,
+    #[error("failed to submit usbip transfer: {0}")]
+    SubmitUsbipTransfer(UsbipError),
     #[error("xhci transfer completed: {0}")]
     TransferComplete(XhciTransferError),
     #[error("failed to cancel transfer: {0}")]
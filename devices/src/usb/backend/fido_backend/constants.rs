@@ -46,11 +46,6 @@ pub const U2FHID_CONTROL_ENDPOINT: u8 = 0x00;
 pub const U2FHID_IN_ENDPOINT: u8 = 0x81;
 pub const U2FHID_OUT_ENDPOINT: u8 = 0x01;
 
-// Generic HID commands
-pub const HID_GET_IDLE: u8 = 0x02;
-pub const HID_SET_IDLE: u8 = 0x0A;
-pub const HID_GET_REPORT_DESC: u8 = 0x22;
-
 pub const HID_MAX_DESCRIPTOR_SIZE: usize = 4096;
 
 // Descriptor data taken from: https://github.com/gl-sergei/u2f-token/blob/master/src/usb-hid.c
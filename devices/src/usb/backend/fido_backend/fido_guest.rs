@@ -6,12 +6,13 @@ use std::collections::VecDeque;
 
 use base::error;
 use usb_util::TransferBuffer;
+use usb_util::TransferStatus;
 
 use crate::usb::backend::fido_backend::constants;
 use crate::usb::backend::fido_backend::error::Error;
 use crate::usb::backend::fido_backend::error::Result;
 use crate::usb::backend::fido_backend::poll_thread::PollTimer;
-use crate::usb::backend::fido_backend::transfer::FidoTransfer;
+use crate::usb::backend::gadget::transfer::GadgetTransfer;
 
 /// `FidoGuestKey` is the struct representation of a virtual fido device as seen by the guest VM.
 /// It takes care of bubbling up transactions from the host into the guest and show a
@@ -51,8 +52,8 @@ impl FidoGuestKey {
     /// same transfer back to the caller, unmodified.
     pub fn return_data_to_guest(
         &mut self,
-        transfer_opt: Option<FidoTransfer>,
-    ) -> Result<Option<FidoTransfer>> {
+        transfer_opt: Option<GadgetTransfer>,
+    ) -> Result<Option<GadgetTransfer>> {
         // If this happens, it means we passed around an empty reference to a
         // non existing transfer that was already cancelled and removed.
         let mut transfer = transfer_opt.ok_or(Error::FidoTransferLost)?;
@@ -60,7 +61,7 @@ impl FidoGuestKey {
             Some(packet) => {
                 transfer.buffer = TransferBuffer::Vector(packet.to_vec());
                 transfer.actual_length = packet.len();
-                transfer.complete_transfer();
+                transfer.complete_transfer(TransferStatus::Completed);
                 Ok(None)
             }
             None => {
@@ -83,7 +84,7 @@ mod tests {
 
     use crate::usb::backend::fido_backend::constants::U2FHID_PACKET_SIZE;
     use crate::usb::backend::fido_backend::fido_guest::FidoGuestKey;
-    use crate::usb::backend::fido_backend::transfer::FidoTransfer;
+    use crate::usb::backend::gadget::transfer::GadgetTransfer;
     use crate::usb::backend::transfer::BackendTransfer;
     use crate::usb::backend::transfer::BackendTransferType;
 
@@ -102,7 +103,7 @@ mod tests {
     fn test_return_data_to_guest_no_packet_retry() {
         let mut fido_key = FidoGuestKey::new().unwrap();
         let transfer_buffer = TransferBuffer::Vector(vec![0u8; U2FHID_PACKET_SIZE]);
-        let fake_transfer = FidoTransfer::new(1, transfer_buffer);
+        let fake_transfer = GadgetTransfer::new(1, transfer_buffer);
 
         let returned_transfer = fido_key.return_data_to_guest(Some(fake_transfer)).unwrap();
         assert!(returned_transfer.is_some());
@@ -113,7 +114,7 @@ mod tests {
         let mut fido_key = FidoGuestKey::new().unwrap();
         let fake_packet = [5; U2FHID_PACKET_SIZE];
         let transfer_buffer = TransferBuffer::Vector(vec![0u8; U2FHID_PACKET_SIZE]);
-        let mut fake_transfer = FidoTransfer::new(1, transfer_buffer);
+        let mut fake_transfer = GadgetTransfer::new(1, transfer_buffer);
 
         let callback_outer = Arc::new(Mutex::new(false));
         let callback_inner = callback_outer.clone();
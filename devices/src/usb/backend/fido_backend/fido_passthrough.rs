@@ -44,12 +44,12 @@ use crate::usb::backend::fido_backend::error::Error;
 use crate::usb::backend::fido_backend::error::Result;
 use crate::usb::backend::fido_backend::fido_device::FidoDevice;
 use crate::usb::backend::fido_backend::poll_thread::poll_for_pending_packets;
-use crate::usb::backend::fido_backend::transfer::FidoTransfer;
-use crate::usb::backend::fido_backend::transfer::FidoTransferHandle;
+use crate::usb::backend::gadget::hid;
+use crate::usb::backend::gadget::transfer::GadgetTransfer;
+use crate::usb::backend::gadget::transfer::GadgetTransferHandle;
 use crate::usb::backend::transfer::BackendTransferHandle;
 use crate::usb::backend::transfer::BackendTransferType;
 use crate::usb::backend::transfer::ControlTransferState;
-use crate::usb::backend::transfer::GenericTransferHandle;
 use crate::usb::xhci::xhci_backend_device::BackendType;
 use crate::usb::xhci::xhci_backend_device::UsbDeviceAddress;
 use crate::usb::xhci::xhci_backend_device::XhciBackendDevice;
@@ -69,7 +69,7 @@ pub struct FidoPassthroughDevice {
     kill_evt: Event,
     worker_thread: Option<WorkerThread<()>>,
     pending_in_transfers:
-        Arc<Mutex<VecDeque<(FidoTransferHandle, Arc<Mutex<Option<FidoTransfer>>>)>>>,
+        Arc<Mutex<VecDeque<(GadgetTransferHandle, Arc<Mutex<Option<GadgetTransfer>>>)>>>,
 }
 
 impl FidoPassthroughDevice {
@@ -143,7 +143,7 @@ impl FidoPassthroughDevice {
     /// This function is called by a queued job to handle all communication related to USB control
     /// transfer packets between the guest and the virtual security key.
     pub fn handle_control(
-        transfer: &mut FidoTransfer,
+        transfer: &mut GadgetTransfer,
         device: &Arc<Mutex<FidoDevice>>,
     ) -> Result<()> {
         transfer.actual_length = 0;
@@ -179,7 +179,7 @@ impl FidoPassthroughDevice {
 
         if request_setup.get_recipient() == ControlRequestRecipient::Interface {
             // It's a request for the HID report descriptor
-            if is_device_to_host && descriptor_type == constants::HID_GET_REPORT_DESC {
+            if is_device_to_host && descriptor_type == hid::HID_REPORT_DESCRIPTOR_TYPE {
                 let mut buffer: Vec<u8> = constants::HID_REPORT_DESC.to_vec();
                 transfer.actual_length = buffer.len();
                 request_setup_out.append(&mut buffer);
@@ -188,13 +188,13 @@ impl FidoPassthroughDevice {
 
         if request_setup.get_type() == ControlRequestType::Class {
             match request_setup.request {
-                constants::HID_GET_IDLE => {
+                hid::HID_GET_IDLE => {
                     let mut buffer: Vec<u8> = vec![0u8, 1];
                     buffer[0] = device.lock().guest_key.lock().idle;
                     transfer.actual_length = 1;
                     request_setup_out.append(&mut buffer);
                 }
-                constants::HID_SET_IDLE => {
+                hid::HID_SET_IDLE => {
                     device.lock().guest_key.lock().idle = (request_setup.value >> 8) as u8;
                 }
                 _ => {
@@ -212,9 +212,9 @@ impl FidoPassthroughDevice {
     }
 
     /// This function is called by a queued job to handle all USB OUT requests from the guest down
-    /// to the host by writing the given `FidoTransfer` data into the hidraw file.
+    /// to the host by writing the given `GadgetTransfer` data into the hidraw file.
     pub fn handle_interrupt_out(
-        transfer: &mut FidoTransfer,
+        transfer: &mut GadgetTransfer,
         device: &Arc<Mutex<FidoDevice>>,
     ) -> Result<()> {
         let mut packet = [0u8; constants::U2FHID_PACKET_SIZE];
@@ -262,15 +262,12 @@ impl BackendDevice for FidoPassthroughDevice {
         transfer: BackendTransferType,
     ) -> BackendResult<BackendTransferHandle> {
         let transfer = match transfer {
-            BackendTransferType::FidoDevice(transfer) => transfer,
+            BackendTransferType::Gadget(transfer) => transfer,
             _ => return Err(BackendError::MalformedBackendTransfer),
         };
 
         let endpoint = transfer.endpoint;
         let arc_transfer = Arc::new(Mutex::new(Some(transfer)));
-        let cancel_handle = FidoTransferHandle {
-            weak_transfer: Arc::downgrade(&arc_transfer),
-        };
 
         match endpoint {
             constants::U2FHID_CONTROL_ENDPOINT => {
@@ -281,23 +278,21 @@ impl BackendDevice for FidoPassthroughDevice {
                         let mut lock = arc_transfer_local.lock();
                         match lock.take() {
                             Some(mut transfer) => {
-                                if let Err(e) = FidoPassthroughDevice::handle_control(
+                                let status = match FidoPassthroughDevice::handle_control(
                                     &mut transfer,
                                     &fido_device,
                                 ) {
-                                    error!(
-                                        "Fido device handle control failed, cancelling transfer:\
-                                        {e:#}"
-                                    );
-                                    drop(lock);
-                                    if let Err(e) = cancel_handle.cancel() {
+                                    Ok(()) => TransferStatus::Completed,
+                                    Err(e) => {
                                         error!(
-                                            "Failed to cancel transfer, dropping request: {e:#}"
+                                            "Fido device handle control failed, cancelling \
+                                            transfer: {e:#}"
                                         );
-                                        return;
+                                        TransferStatus::Cancelled
                                     }
-                                }
-                                transfer.complete_transfer();
+                                };
+                                drop(lock);
+                                transfer.complete_transfer(status);
                             }
                             None => {
                                 error!(
@@ -316,23 +311,21 @@ impl BackendDevice for FidoPassthroughDevice {
                         let mut lock = arc_transfer_local.lock();
                         match lock.take() {
                             Some(mut transfer) => {
-                                if let Err(e) = FidoPassthroughDevice::handle_interrupt_out(
+                                let status = match FidoPassthroughDevice::handle_interrupt_out(
                                     &mut transfer,
                                     &fido_device,
                                 ) {
-                                    error!(
-                                        "Fido device handle interrupt out failed,\
-                                        cancelling transfer: {e:#}"
-                                    );
-                                    drop(lock);
-                                    if let Err(e) = cancel_handle.cancel() {
+                                    Ok(()) => TransferStatus::Completed,
+                                    Err(e) => {
                                         error!(
-                                            "Failed to cancel transfer, dropping request: {e:#}"
+                                            "Fido device handle interrupt out failed, \
+                                            cancelling transfer: {e:#}"
                                         );
-                                        return;
+                                        TransferStatus::Cancelled
                                     }
-                                }
-                                transfer.complete_transfer();
+                                };
+                                drop(lock);
+                                transfer.complete_transfer(status);
                             }
                             None => {
                                 error!("Interrupt out transfer disappeared. Dropping request.");
@@ -342,8 +335,9 @@ impl BackendDevice for FidoPassthroughDevice {
                     .map_err(BackendError::QueueAsyncJob)?;
             }
             constants::U2FHID_IN_ENDPOINT => {
-                let handle = FidoTransferHandle {
-                    weak_transfer: Arc::downgrade(&arc_transfer.clone()),
+                let handle = GadgetTransferHandle {
+                    weak_transfer: Arc::downgrade(&arc_transfer),
+                    job_queue: self.transfer_job_queue.clone(),
                 };
                 self.pending_in_transfers
                     .lock()
@@ -379,8 +373,9 @@ impl BackendDevice for FidoPassthroughDevice {
             }));
         }
 
-        let cancel_handle = FidoTransferHandle {
+        let cancel_handle = GadgetTransferHandle {
             weak_transfer: Arc::downgrade(&arc_transfer),
+            job_queue: self.transfer_job_queue.clone(),
         };
         Ok(BackendTransferHandle::new(cancel_handle))
     }
@@ -409,12 +404,22 @@ impl BackendDevice for FidoPassthroughDevice {
         ep_addr: u8,
         transfer_buffer: TransferBuffer,
     ) -> BackendResult<BackendTransferType> {
-        Ok(BackendTransferType::FidoDevice(FidoTransfer::new(
+        Ok(BackendTransferType::Gadget(GadgetTransfer::new(
             ep_addr,
             transfer_buffer,
         )))
     }
 
+    fn build_isochronous_transfer(
+        &mut self,
+        _ep_addr: u8,
+        _transfer_buffer: TransferBuffer,
+        _packet_lengths: &[u32],
+    ) -> BackendResult<BackendTransferType> {
+        // Fido devices don't support isochronous transfer requests
+        Err(BackendError::MalformedBackendTransfer)
+    }
+
     fn get_control_transfer_state(&mut self) -> Arc<RwLock<ControlTransferState>> {
         self.control_transfer_state.clone()
     }
@@ -11,4 +11,3 @@ pub mod fido_provider;
 pub mod fido_transaction;
 pub mod hid_utils;
 pub mod poll_thread;
-pub mod transfer;
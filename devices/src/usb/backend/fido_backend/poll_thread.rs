@@ -11,7 +11,6 @@ use std::time::Duration;
 
 use anyhow::Context;
 use base::debug;
-use base::error;
 use base::AsRawDescriptor;
 use base::Event;
 use base::EventToken;
@@ -22,13 +21,12 @@ use base::WaitContext;
 use sync::Mutex;
 use usb_util::TransferStatus;
 
+use crate::usb::backend::fido_backend::constants::USB_TRANSFER_TIMEOUT_MILLIS;
 use crate::usb::backend::fido_backend::error::Error;
 use crate::usb::backend::fido_backend::error::Result;
 use crate::usb::backend::fido_backend::fido_device::FidoDevice;
-use crate::usb::backend::fido_backend::transfer::FidoTransfer;
-use crate::usb::backend::fido_backend::transfer::FidoTransferHandle;
-use crate::usb::backend::transfer::BackendTransfer;
-use crate::usb::backend::transfer::GenericTransferHandle;
+use crate::usb::backend::gadget::transfer::GadgetTransfer;
+use crate::usb::backend::gadget::transfer::GadgetTransferHandle;
 
 #[derive(EventToken)]
 enum Token {
@@ -92,7 +90,7 @@ impl AsRawDescriptor for PollTimer {
 pub fn poll_for_pending_packets(
     device: Arc<Mutex<FidoDevice>>,
     pending_in_transfers: Arc<
-        Mutex<VecDeque<(FidoTransferHandle, Arc<Mutex<Option<FidoTransfer>>>)>>,
+        Mutex<VecDeque<(GadgetTransferHandle, Arc<Mutex<Option<GadgetTransfer>>>)>>,
     >,
     kill_evt: Event,
 ) -> Result<()> {
@@ -189,7 +187,7 @@ pub fn poll_for_pending_packets(
 fn handle_packet_poll(
     device: &Arc<Mutex<FidoDevice>>,
     pending_in_transfers: &Arc<
-        Mutex<VecDeque<(FidoTransferHandle, Arc<Mutex<Option<FidoTransfer>>>)>>,
+        Mutex<VecDeque<(GadgetTransferHandle, Arc<Mutex<Option<GadgetTransfer>>>)>>,
     >,
 ) -> Result<()> {
     if device.lock().is_device_lost {
@@ -208,8 +206,8 @@ fn handle_packet_poll(
         return Ok(());
     }
 
-    // Fetch first available transfer from the pending list and its fail handle.
-    let (_, transfer_opt) = match transfers_lock.pop_front() {
+    // Fetch first available transfer from the pending list and its cancel handle.
+    let (cancel_handle, transfer_opt) = match transfers_lock.pop_front() {
         Some(tuple) => tuple,
         None => {
             // No pending transfers waiting for data, so we do nothing.
@@ -237,9 +235,6 @@ fn handle_packet_poll(
             // guest.
             *transfer_lock = transfer;
             drop(transfer_lock);
-            let cancel_handle = FidoTransferHandle {
-                weak_transfer: Arc::downgrade(&transfer_opt),
-            };
 
             // Put the transfer back into the pending queue, we can try again later.
             pending_in_transfers
@@ -254,30 +249,20 @@ fn handle_packet_poll(
 /// USB transfer waiting in the pending queue. Returns true if the given transfer is still valid,
 /// otherwise false.
 fn process_pending_transfer(
-    transfer_handle_pair: &(FidoTransferHandle, Arc<Mutex<Option<FidoTransfer>>>),
+    transfer_handle_pair: &(GadgetTransferHandle, Arc<Mutex<Option<GadgetTransfer>>>),
 ) -> bool {
     let mut lock = transfer_handle_pair.1.lock();
     let transfer = match lock.take() {
         Some(t) => {
-            // The transfer has already been cancelled. We report back to the xhci level and remove
-            // it.
-            if t.status() == TransferStatus::Cancelled {
-                t.complete_transfer();
-                return false;
-            }
             // The transfer has expired, we cancel it and report back to the xhci level.
-            if t.timeout_expired() {
-                if let Err(e) = transfer_handle_pair.0.cancel() {
-                    error!("Failed to properly cancel IN transfer, dropping the request: {e:#}");
-                    return false;
-                }
-                t.complete_transfer();
+            if t.timeout_expired(Duration::from_millis(USB_TRANSFER_TIMEOUT_MILLIS)) {
+                t.complete_transfer(TransferStatus::Cancelled);
                 return false;
             }
             Some(t)
         }
         None => {
-            // Transfer has already been removed so we can skip it.
+            // Transfer has already been completed or cancelled so we can skip it.
             return false;
         }
     };
@@ -288,10 +273,9 @@ fn process_pending_transfer(
 
 /// Signals to the current transfer that the underlying device has been lost and the xhci layer
 /// should recover by detaching the FIDO backend.
-fn signal_device_lost(transfer_opt: Option<FidoTransfer>) {
-    if let Some(mut transfer) = transfer_opt {
-        transfer.signal_device_lost();
-        transfer.complete_transfer();
+fn signal_device_lost(transfer_opt: Option<GadgetTransfer>) {
+    if let Some(transfer) = transfer_opt {
+        transfer.complete_transfer(TransferStatus::NoDevice);
     }
 }
 
@@ -299,7 +283,7 @@ fn signal_device_lost(transfer_opt: Option<FidoTransfer>) {
 fn packet_timer_needs_rearm(
     device: &Arc<Mutex<FidoDevice>>,
     pending_in_transfers: &Arc<
-        Mutex<VecDeque<(FidoTransferHandle, Arc<Mutex<Option<FidoTransfer>>>)>>,
+        Mutex<VecDeque<(GadgetTransferHandle, Arc<Mutex<Option<GadgetTransfer>>>)>>,
     >,
 ) -> bool {
     let transfers_lock = pending_in_transfers.lock();
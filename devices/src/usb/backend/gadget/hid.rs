@@ -0,0 +1,17 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Constants of the USB HID class shared by the emulated HID gadgets.
+
+// HID class descriptor types.
+pub const HID_DESCRIPTOR_TYPE: u8 = 0x21;
+pub const HID_REPORT_DESCRIPTOR_TYPE: u8 = 0x22;
+
+// HID class requests.
+pub const HID_GET_REPORT: u8 = 0x01;
+pub const HID_GET_IDLE: u8 = 0x02;
+pub const HID_GET_PROTOCOL: u8 = 0x03;
+pub const HID_SET_REPORT: u8 = 0x09;
+pub const HID_SET_IDLE: u8 = 0x0A;
+pub const HID_SET_PROTOCOL: u8 = 0x0B;
@@ -0,0 +1,9 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Building blocks shared by the emulated USB devices (gadgets), such as the FIDO security key
+//! and the HID gadgets, whose transfers are handled by crosvm rather than by a host device.
+
+pub mod hid;
+pub mod transfer;
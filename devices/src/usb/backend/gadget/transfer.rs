@@ -0,0 +1,114 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::sync::Arc;
+use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
+
+use base::Clock;
+use sync::Mutex;
+use usb_util::TransferBuffer;
+use usb_util::TransferStatus;
+
+use crate::usb::backend::error::Error as BackendError;
+use crate::usb::backend::error::Result as BackendResult;
+use crate::usb::backend::transfer::BackendTransfer;
+use crate::usb::backend::transfer::BackendTransferType;
+use crate::usb::backend::transfer::GenericTransferHandle;
+use crate::utils::AsyncJobQueue;
+
+/// Implementation of a generic USB transfer for emulated gadgets. It implements common USB
+/// transfer functionality since it cannot rely on the transfer structures provided by the
+/// usb_utils crate as gadgets do not use usbdevfs to communicate with the host, the gadget fills
+/// in the buffer and completes the transfer itself.
+pub struct GadgetTransfer {
+    /// TransferBuffer structure with either a request or response data from the guest/host.
+    /// Control transfers carry the setup packet in the first 8 bytes.
+    pub buffer: TransferBuffer,
+    /// Status of the transfer, used by the xhci layer for a successful completion.
+    status: TransferStatus,
+    /// Actual length of the transfer, excluding the setup packet of control transfers.
+    pub actual_length: usize,
+    /// USB endpoint associated with this transfer.
+    pub endpoint: u8,
+    /// Timestamp of the transfer submission time.
+    submission_time: Instant,
+    /// Callback to be executed once the transfer has completed, to signal the xhci layer.
+    pub callback: Option<Box<dyn Fn(GadgetTransfer) + Send + Sync>>,
+}
+
+impl GadgetTransfer {
+    pub fn new(endpoint: u8, buffer: TransferBuffer) -> GadgetTransfer {
+        let clock = Clock::new();
+        GadgetTransfer {
+            buffer,
+            status: TransferStatus::Error, // Default to error
+            actual_length: 0,
+            endpoint,
+            submission_time: clock.now(),
+            callback: None,
+        }
+    }
+
+    /// Checks if the transfer has been pending for longer than `timeout`.
+    pub fn timeout_expired(&self, timeout: Duration) -> bool {
+        self.submission_time.elapsed() >= timeout
+    }
+
+    /// Finalizes the transfer with `status` and calls the callback to signal the xhci layer.
+    pub fn complete_transfer(mut self, status: TransferStatus) {
+        self.status = status;
+        if let Some(cb) = self.callback.take() {
+            cb(self);
+        }
+    }
+}
+
+impl BackendTransfer for GadgetTransfer {
+    fn status(&self) -> TransferStatus {
+        self.status
+    }
+
+    fn actual_length(&self) -> usize {
+        self.actual_length
+    }
+
+    fn buffer(&self) -> &TransferBuffer {
+        &self.buffer
+    }
+
+    fn set_callback<C: 'static + Fn(BackendTransferType) + Send + Sync>(&mut self, cb: C) {
+        let callback = move |t: GadgetTransfer| cb(BackendTransferType::Gadget(t));
+        self.callback = Some(Box::new(callback));
+    }
+}
+
+/// Implementation of a cancel handler for a `GadgetTransfer` that the gadget keeps until it
+/// completes it.
+pub struct GadgetTransferHandle {
+    pub weak_transfer: Weak<Mutex<Option<GadgetTransfer>>>,
+    pub job_queue: Arc<AsyncJobQueue>,
+}
+
+impl GenericTransferHandle for GadgetTransferHandle {
+    fn cancel(&self) -> BackendResult<()> {
+        let rc_transfer = self
+            .weak_transfer
+            .upgrade()
+            .ok_or(BackendError::TransferHandleAlreadyComplete)?;
+        if rc_transfer.lock().is_none() {
+            return Err(BackendError::TransferHandleAlreadyComplete);
+        }
+        // The xhci transfer state is locked while cancelling, complete the transfer later unless
+        // the gadget completes it first.
+        self.job_queue
+            .queue_job(move || {
+                if let Some(transfer) = rc_transfer.lock().take() {
+                    transfer.complete_transfer(TransferStatus::Cancelled);
+                }
+            })
+            .map_err(BackendError::QueueAsyncJob)
+    }
+}
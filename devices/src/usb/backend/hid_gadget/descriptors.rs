@@ -0,0 +1,302 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use usb_util::DescriptorType;
+use vm_control::UsbHidGadgetKind;
+
+use crate::usb::backend::gadget::hid::HID_DESCRIPTOR_TYPE;
+use crate::usb::backend::gadget::hid::HID_REPORT_DESCRIPTOR_TYPE;
+
+// Google Vendor ID
+pub const HID_GADGET_VENDOR_ID: u16 = 0x18d1;
+
+pub const HID_GADGET_IN_ENDPOINT: u8 = 0x81;
+pub const HID_GADGET_CONFIGURATION_VALUE: u8 = 1;
+const HID_GADGET_MAX_PACKET_SIZE: u16 = 8;
+
+pub const STRING_DESCRIPTOR_TYPE: u8 = 0x03;
+
+const HID_DESCRIPTOR_SIZE: usize = 9;
+const CONFIG_DESCRIPTOR_SIZE: usize = 9;
+const INTERFACE_DESCRIPTOR_SIZE: usize = 9;
+const ENDPOINT_DESCRIPTOR_SIZE: usize = 7;
+
+const MANUFACTURER_STRING: &str = "crosvm";
+
+const KEYBOARD_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, /* USAGE_PAGE (Generic Desktop) */
+    0x09, 0x06, /* USAGE (Keyboard) */
+    0xa1, 0x01, /* COLLECTION (Application) */
+    0x05, 0x07, /* USAGE_PAGE (Keyboard) */
+    0x19, 0xe0, /* USAGE_MINIMUM (Keyboard LeftControl) */
+    0x29, 0xe7, /* USAGE_MAXIMUM (Keyboard Right GUI) */
+    0x15, 0x00, /* LOGICAL_MINIMUM (0) */
+    0x25, 0x01, /* LOGICAL_MAXIMUM (1) */
+    0x75, 0x01, /* REPORT_SIZE (1) */
+    0x95, 0x08, /* REPORT_COUNT (8) */
+    0x81, 0x02, /* INPUT (Data,Var,Abs); Modifier byte */
+    0x95, 0x01, /* REPORT_COUNT (1) */
+    0x75, 0x08, /* REPORT_SIZE (8) */
+    0x81, 0x01, /* INPUT (Cnst); Reserved byte */
+    0x95, 0x05, /* REPORT_COUNT (5) */
+    0x75, 0x01, /* REPORT_SIZE (1) */
+    0x05, 0x08, /* USAGE_PAGE (LEDs) */
+    0x19, 0x01, /* USAGE_MINIMUM (Num Lock) */
+    0x29, 0x05, /* USAGE_MAXIMUM (Kana) */
+    0x91, 0x02, /* OUTPUT (Data,Var,Abs); LED report */
+    0x95, 0x01, /* REPORT_COUNT (1) */
+    0x75, 0x03, /* REPORT_SIZE (3) */
+    0x91, 0x01, /* OUTPUT (Cnst); LED report padding */
+    0x95, 0x06, /* REPORT_COUNT (6) */
+    0x75, 0x08, /* REPORT_SIZE (8) */
+    0x15, 0x00, /* LOGICAL_MINIMUM (0) */
+    0x25, 0x65, /* LOGICAL_MAXIMUM (101) */
+    0x05, 0x07, /* USAGE_PAGE (Keyboard) */
+    0x19, 0x00, /* USAGE_MINIMUM (Reserved) */
+    0x29, 0x65, /* USAGE_MAXIMUM (Keyboard Application) */
+    0x81, 0x00, /* INPUT (Data,Ary,Abs); Key arrays (6 bytes) */
+    0xc0, /* END_COLLECTION */
+];
+
+const MOUSE_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, /* USAGE_PAGE (Generic Desktop) */
+    0x09, 0x02, /* USAGE (Mouse) */
+    0xa1, 0x01, /* COLLECTION (Application) */
+    0x09, 0x01, /* USAGE (Pointer) */
+    0xa1, 0x00, /* COLLECTION (Physical) */
+    0x05, 0x09, /* USAGE_PAGE (Button) */
+    0x19, 0x01, /* USAGE_MINIMUM (Button 1) */
+    0x29, 0x03, /* USAGE_MAXIMUM (Button 3) */
+    0x15, 0x00, /* LOGICAL_MINIMUM (0) */
+    0x25, 0x01, /* LOGICAL_MAXIMUM (1) */
+    0x95, 0x03, /* REPORT_COUNT (3) */
+    0x75, 0x01, /* REPORT_SIZE (1) */
+    0x81, 0x02, /* INPUT (Data,Var,Abs); Buttons */
+    0x95, 0x01, /* REPORT_COUNT (1) */
+    0x75, 0x05, /* REPORT_SIZE (5) */
+    0x81, 0x01, /* INPUT (Cnst); Button padding */
+    0x05, 0x01, /* USAGE_PAGE (Generic Desktop) */
+    0x09, 0x30, /* USAGE (X) */
+    0x09, 0x31, /* USAGE (Y) */
+    0x09, 0x38, /* USAGE (Wheel) */
+    0x15, 0x81, /* LOGICAL_MINIMUM (-127) */
+    0x25, 0x7f, /* LOGICAL_MAXIMUM (127) */
+    0x75, 0x08, /* REPORT_SIZE (8) */
+    0x95, 0x03, /* REPORT_COUNT (3) */
+    0x81, 0x06, /* INPUT (Data,Var,Rel) */
+    0xc0, /* END_COLLECTION */
+    0xc0, /* END_COLLECTION */
+];
+
+const TABLET_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, /* USAGE_PAGE (Generic Desktop) */
+    0x09, 0x02, /* USAGE (Mouse) */
+    0xa1, 0x01, /* COLLECTION (Application) */
+    0x09, 0x01, /* USAGE (Pointer) */
+    0xa1, 0x00, /* COLLECTION (Physical) */
+    0x05, 0x09, /* USAGE_PAGE (Button) */
+    0x19, 0x01, /* USAGE_MINIMUM (Button 1) */
+    0x29, 0x03, /* USAGE_MAXIMUM (Button 3) */
+    0x15, 0x00, /* LOGICAL_MINIMUM (0) */
+    0x25, 0x01, /* LOGICAL_MAXIMUM (1) */
+    0x95, 0x03, /* REPORT_COUNT (3) */
+    0x75, 0x01, /* REPORT_SIZE (1) */
+    0x81, 0x02, /* INPUT (Data,Var,Abs); Buttons */
+    0x95, 0x01, /* REPORT_COUNT (1) */
+    0x75, 0x05, /* REPORT_SIZE (5) */
+    0x81, 0x01, /* INPUT (Cnst); Button padding */
+    0x05, 0x01, /* USAGE_PAGE (Generic Desktop) */
+    0x09, 0x30, /* USAGE (X) */
+    0x09, 0x31, /* USAGE (Y) */
+    0x15, 0x00, /* LOGICAL_MINIMUM (0) */
+    0x26, 0xff, 0x7f, /* LOGICAL_MAXIMUM (32767) */
+    0x75, 0x10, /* REPORT_SIZE (16) */
+    0x95, 0x02, /* REPORT_COUNT (2) */
+    0x81, 0x02, /* INPUT (Data,Var,Abs) */
+    0x09, 0x38, /* USAGE (Wheel) */
+    0x15, 0x81, /* LOGICAL_MINIMUM (-127) */
+    0x25, 0x7f, /* LOGICAL_MAXIMUM (127) */
+    0x75, 0x08, /* REPORT_SIZE (8) */
+    0x95, 0x01, /* REPORT_COUNT (1) */
+    0x81, 0x06, /* INPUT (Data,Var,Rel) */
+    0xc0, /* END_COLLECTION */
+    0xc0, /* END_COLLECTION */
+];
+
+/// Returns the HID report descriptor of the gadget.
+pub fn report_descriptor(kind: UsbHidGadgetKind) -> &'static [u8] {
+    match kind {
+        UsbHidGadgetKind::Keyboard => KEYBOARD_REPORT_DESC,
+        UsbHidGadgetKind::Mouse => MOUSE_REPORT_DESC,
+        UsbHidGadgetKind::Tablet => TABLET_REPORT_DESC,
+    }
+}
+
+/// Returns the size of the input reports described by `report_descriptor()`.
+pub fn report_size(kind: UsbHidGadgetKind) -> usize {
+    match kind {
+        UsbHidGadgetKind::Keyboard => 8,
+        UsbHidGadgetKind::Mouse => 4,
+        UsbHidGadgetKind::Tablet => 6,
+    }
+}
+
+pub fn product_id(kind: UsbHidGadgetKind) -> u16 {
+    // Unique Product IDs
+    match kind {
+        UsbHidGadgetKind::Keyboard => 0x5f01,
+        UsbHidGadgetKind::Mouse => 0x5f02,
+        UsbHidGadgetKind::Tablet => 0x5f03,
+    }
+}
+
+fn product_string(kind: UsbHidGadgetKind) -> &'static str {
+    match kind {
+        UsbHidGadgetKind::Keyboard => "crosvm virtual keyboard",
+        UsbHidGadgetKind::Mouse => "crosvm virtual mouse",
+        UsbHidGadgetKind::Tablet => "crosvm virtual tablet",
+    }
+}
+
+pub fn device_descriptor(kind: UsbHidGadgetKind) -> Vec<u8> {
+    let vid = HID_GADGET_VENDOR_ID.to_le_bytes();
+    let pid = product_id(kind).to_le_bytes();
+    vec![
+        18,
+        DescriptorType::Device as u8,
+        0x00,
+        0x02, /* bcdUSB 2.0 */
+        0x00, /* bDeviceClass: defined by the interface */
+        0x00, /* bDeviceSubClass */
+        0x00, /* bDeviceProtocol */
+        0x40, /* bMaxPacketSize0 */
+        vid[0],
+        vid[1],
+        pid[0],
+        pid[1],
+        0x00,
+        0x01, /* bcdDevice */
+        1,    /* iManufacturer */
+        2,    /* iProduct */
+        0,    /* iSerialNumber */
+        1,    /* bNumConfigurations */
+    ]
+}
+
+/// Returns the configuration descriptor followed by the interface, HID and endpoint descriptors.
+pub fn config_descriptor(kind: UsbHidGadgetKind) -> Vec<u8> {
+    let total_length = (CONFIG_DESCRIPTOR_SIZE
+        + INTERFACE_DESCRIPTOR_SIZE
+        + HID_DESCRIPTOR_SIZE
+        + ENDPOINT_DESCRIPTOR_SIZE) as u16;
+    // Keyboards and mice implement the boot protocol so that firmware can use them.
+    let (subclass, protocol) = match kind {
+        UsbHidGadgetKind::Keyboard => (1, 1),
+        UsbHidGadgetKind::Mouse => (1, 2),
+        UsbHidGadgetKind::Tablet => (0, 0),
+    };
+    let mut desc = vec![
+        /* Configuration Descriptor. */
+        CONFIG_DESCRIPTOR_SIZE as u8,
+        DescriptorType::Configuration as u8,
+        total_length as u8,
+        (total_length >> 8) as u8, /* wTotalLength. */
+        0x01,                      /* bNumInterfaces. */
+        HID_GADGET_CONFIGURATION_VALUE,
+        0,    /* iConfiguration. */
+        0xa0, /* bmAttributes: bus powered, remote wakeup. */
+        50,   /* bMaxPower (100mA). */
+        /* Interface Descriptor. */
+        INTERFACE_DESCRIPTOR_SIZE as u8,
+        DescriptorType::Interface as u8,
+        0,    /* bInterfaceNumber */
+        0x00, /* bAlternateSetting */
+        0x01, /* bNumEndpoints */
+        0x03, /* bInterfaceClass: HID */
+        subclass,
+        protocol,
+        0x00, /* iInterface */
+    ];
+    desc.extend_from_slice(&hid_descriptor(kind));
+    let max_packet_size = HID_GADGET_MAX_PACKET_SIZE.to_le_bytes();
+    desc.extend_from_slice(&[
+        /* Endpoint IN1 Descriptor */
+        ENDPOINT_DESCRIPTOR_SIZE as u8,
+        DescriptorType::Endpoint as u8,
+        HID_GADGET_IN_ENDPOINT,
+        0x03, /* bmAttributes: Interrupt */
+        max_packet_size[0],
+        max_packet_size[1],
+        0x04, /* bInterval (4ms) */
+    ]);
+    desc
+}
+
+pub fn hid_descriptor(kind: UsbHidGadgetKind) -> [u8; HID_DESCRIPTOR_SIZE] {
+    let report_length = (report_descriptor(kind).len() as u16).to_le_bytes();
+    [
+        HID_DESCRIPTOR_SIZE as u8,
+        HID_DESCRIPTOR_TYPE,
+        0x11,
+        0x01, /* bcdHID 1.11 */
+        0x00, /* bCountryCode */
+        0x01, /* bNumDescriptors */
+        HID_REPORT_DESCRIPTOR_TYPE,
+        report_length[0],
+        report_length[1],
+    ]
+}
+
+/// Returns the string descriptor `index`, or None if the gadget has no such string.
+pub fn string_descriptor(kind: UsbHidGadgetKind, index: u8) -> Option<Vec<u8>> {
+    let string = match index {
+        // Supported languages: US English.
+        0 => return Some(vec![4, STRING_DESCRIPTOR_TYPE, 0x09, 0x04]),
+        1 => MANUFACTURER_STRING,
+        2 => product_string(kind),
+        _ => return None,
+    };
+    let mut desc = vec![0, STRING_DESCRIPTOR_TYPE];
+    for c in string.encode_utf16() {
+        desc.extend_from_slice(&c.to_le_bytes());
+    }
+    desc[0] = desc.len() as u8;
+    Some(desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use usb_util::parse_usbfs_descriptors;
+
+    use super::*;
+
+    #[test]
+    fn descriptors_parse() {
+        for kind in [
+            UsbHidGadgetKind::Keyboard,
+            UsbHidGadgetKind::Mouse,
+            UsbHidGadgetKind::Tablet,
+        ] {
+            let mut raw = device_descriptor(kind);
+            raw.extend_from_slice(&config_descriptor(kind));
+            let tree = parse_usbfs_descriptors(&raw).unwrap();
+            let config = tree
+                .get_config_descriptor(HID_GADGET_CONFIGURATION_VALUE)
+                .unwrap();
+            let interface = config.get_interface_descriptor(0, 0).unwrap();
+            assert_eq!(interface.bInterfaceClass, 0x03);
+            let endpoint = interface.get_endpoint_descriptor(0).unwrap();
+            assert_eq!(endpoint.bEndpointAddress, HID_GADGET_IN_ENDPOINT);
+        }
+    }
+
+    #[test]
+    fn string_descriptors() {
+        assert_eq!(
+            string_descriptor(UsbHidGadgetKind::Mouse, 1).unwrap(),
+            vec![14, 3, b'c', 0, b'r', 0, b'o', 0, b's', 0, b'v', 0, b'm', 0]
+        );
+        assert!(string_descriptor(UsbHidGadgetKind::Mouse, 3).is_none());
+    }
+}
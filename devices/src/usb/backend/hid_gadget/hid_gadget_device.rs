@@ -0,0 +1,676 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use base::debug;
+use base::error;
+use base::AsRawDescriptor;
+use base::Event;
+use base::RawDescriptor;
+use sync::Mutex;
+use usb_util::parse_usbfs_descriptors;
+use usb_util::ConfigDescriptorTree;
+use usb_util::ControlRequestDataPhaseTransferDirection;
+use usb_util::ControlRequestRecipient;
+use usb_util::ControlRequestType;
+use usb_util::DescriptorType;
+use usb_util::DeviceDescriptorTree;
+use usb_util::DeviceSpeed;
+use usb_util::EndpointDirection;
+use usb_util::EndpointType;
+use usb_util::Error as UsbUtilError;
+use usb_util::StandardControlRequest;
+use usb_util::TransferBuffer;
+use usb_util::TransferStatus;
+use usb_util::UsbRequestSetup;
+use vm_control::UsbHidGadgetKind;
+use zerocopy::FromBytes;
+
+use crate::usb::backend::device::BackendDevice;
+use crate::usb::backend::device::DeviceState;
+use crate::usb::backend::endpoint::ControlEndpointState;
+use crate::usb::backend::endpoint::UsbEndpoint;
+use crate::usb::backend::error::Error;
+use crate::usb::backend::error::Result;
+use crate::usb::backend::gadget::hid;
+use crate::usb::backend::gadget::transfer::GadgetTransfer;
+use crate::usb::backend::gadget::transfer::GadgetTransferHandle;
+use crate::usb::backend::hid_gadget::descriptors;
+use crate::usb::backend::transfer::BackendTransferHandle;
+use crate::usb::backend::transfer::BackendTransferType;
+use crate::usb::backend::transfer::ControlTransferState;
+use crate::usb::xhci::xhci_backend_device::BackendType;
+use crate::usb::xhci::xhci_backend_device::UsbDeviceAddress;
+use crate::usb::xhci::xhci_backend_device::XhciBackendDevice;
+use crate::utils::AsyncJobQueue;
+use crate::utils::EventLoop;
+
+// Reports the guest has not read yet are dropped, oldest first, beyond this limit.
+const MAX_QUEUED_REPORTS: usize = 64;
+
+const SETUP_SIZE: usize = mem::size_of::<UsbRequestSetup>();
+
+/// Emulated USB HID device (keyboard, mouse or tablet) whose input reports are provided by the
+/// host through the backend device provider rather than by real hardware. Reports are queued
+/// through vm_control or read from an optional report socket, on which the host writes them back
+/// to back, each exactly the size of a report of the gadget kind.
+pub struct HidGadgetDevice {
+    kind: UsbHidGadgetKind,
+    /// The state of the device as seen by the backend provider.
+    state: Arc<RwLock<DeviceState>>,
+    /// The state of the control transfer exchange with the xhci layer.
+    control_transfer_state: Arc<RwLock<ControlTransferState>>,
+    job_queue: Arc<AsyncJobQueue>,
+    /// Signaled when a report is queued or an IN transfer is submitted, so that the event loop
+    /// matches reports with transfers.
+    report_evt: Event,
+    /// Non-blocking socket the host writes reports to, closed once the host closes its end.
+    report_socket: Option<UnixStream>,
+    /// Bytes read from the report socket that do not make up a full report yet.
+    partial_report: Vec<u8>,
+    /// Reports queued by the host that the guest has not read yet.
+    queued_reports: VecDeque<Vec<u8>>,
+    /// Interrupt IN transfers waiting for a report.
+    pending_in_transfers: VecDeque<Arc<Mutex<Option<GadgetTransfer>>>>,
+    /// Last report sent to the guest, returned by GET_REPORT.
+    last_report: Vec<u8>,
+    active_config: u8,
+    idle: u8,
+    protocol: u8,
+}
+
+impl HidGadgetDevice {
+    pub fn new(
+        kind: UsbHidGadgetKind,
+        state: DeviceState,
+        report_socket: Option<UnixStream>,
+    ) -> Result<HidGadgetDevice> {
+        if let Some(socket) = &report_socket {
+            socket
+                .set_nonblocking(true)
+                .map_err(|e| Error::CreateHidGadget(e.into()))?;
+        }
+        let control_transfer_state = ControlTransferState {
+            ctl_ep_state: ControlEndpointState::SetupStage,
+            control_request_setup: UsbRequestSetup::new(0, 0, 0, 0, 0),
+            executed: false,
+        };
+        Ok(HidGadgetDevice {
+            kind,
+            job_queue: state.job_queue.clone(),
+            state: Arc::new(RwLock::new(state)),
+            control_transfer_state: Arc::new(RwLock::new(control_transfer_state)),
+            report_evt: Event::new().map_err(Error::CreateHidGadget)?,
+            report_socket,
+            partial_report: Vec::new(),
+            queued_reports: VecDeque::new(),
+            pending_in_transfers: VecDeque::new(),
+            last_report: vec![0u8; descriptors::report_size(kind)],
+            active_config: 0,
+            idle: 0,
+            // The report protocol is the default after reset.
+            protocol: 1,
+        })
+    }
+
+    pub fn kind(&self) -> UsbHidGadgetKind {
+        self.kind
+    }
+
+    /// The socket the host writes reports to, which must be polled along with the device.
+    pub fn report_socket(&self) -> Option<&UnixStream> {
+        self.report_socket.as_ref()
+    }
+
+    /// Queues an input report for the guest. The report must match the layout described by
+    /// `UsbHidGadgetKind`.
+    pub fn queue_report(&mut self, report: Vec<u8>) -> Result<()> {
+        if report.len() != descriptors::report_size(self.kind) {
+            return Err(Error::InvalidHidReport(report.len()));
+        }
+        self.push_report(report);
+        self.report_evt.signal().map_err(Error::SignalHidGadget)
+    }
+
+    fn push_report(&mut self, report: Vec<u8>) {
+        if self.queued_reports.len() == MAX_QUEUED_REPORTS {
+            debug!("hid gadget report queue is full, dropping the oldest report");
+            self.queued_reports.pop_front();
+        }
+        self.queued_reports.push_back(report);
+    }
+
+    // Queues the full reports available on the report socket. The socket is dropped, which also
+    // removes it from the event loop, once the host closes its end or reading it fails.
+    fn read_report_socket(&mut self) {
+        let socket = match &mut self.report_socket {
+            Some(socket) => socket,
+            None => return,
+        };
+        let mut buf = [0u8; 64];
+        let closed = loop {
+            match socket.read(&mut buf) {
+                Ok(0) => {
+                    debug!("hid gadget report socket closed by the host");
+                    break true;
+                }
+                Ok(n) => self.partial_report.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break false,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("failed to read hid gadget report socket: {}", e);
+                    break true;
+                }
+            }
+        };
+        if closed {
+            self.report_socket = None;
+        }
+        let report_size = descriptors::report_size(self.kind);
+        while self.partial_report.len() >= report_size {
+            let report = self.partial_report.drain(..report_size).collect();
+            self.push_report(report);
+        }
+    }
+
+    /// Called from the event handler, when reports are queued, an IN transfer is submitted or the
+    /// report socket is readable, to hand queued reports to pending IN transfers.
+    pub fn deliver_reports(&mut self) -> Result<()> {
+        self.report_evt.reset().map_err(Error::SignalHidGadget)?;
+        self.read_report_socket();
+        while !self.queued_reports.is_empty() {
+            let mut transfer = match self.pending_in_transfers.pop_front() {
+                // Skip transfers that have been cancelled.
+                Some(rc_transfer) => match rc_transfer.lock().take() {
+                    Some(transfer) => transfer,
+                    None => continue,
+                },
+                None => break,
+            };
+            let report = self.queued_reports.pop_front().unwrap();
+            let status = match &mut transfer.buffer {
+                TransferBuffer::Vector(v) => {
+                    let len = v.len().min(report.len());
+                    v[..len].copy_from_slice(&report[..len]);
+                    transfer.actual_length = len;
+                    TransferStatus::Completed
+                }
+                TransferBuffer::Dma(_) => TransferStatus::Error,
+            };
+            self.last_report = report;
+            transfer.complete_transfer(status);
+        }
+        Ok(())
+    }
+
+    // Handles a control request that the generic backend layer did not intercept. Returns the
+    // data of the data stage for device-to-host requests, or None if the request is unsupported.
+    fn handle_control(&mut self, setup: &UsbRequestSetup) -> Option<Vec<u8>> {
+        let descriptor_type = (setup.value >> 8) as u8;
+        let descriptor_index = setup.value as u8;
+        let is_device_to_host =
+            setup.get_direction() == ControlRequestDataPhaseTransferDirection::DeviceToHost;
+
+        match (setup.get_type(), setup.get_recipient(), is_device_to_host) {
+            (ControlRequestType::Standard, _, true)
+                if setup.get_standard_request() == Some(StandardControlRequest::GetStatus) =>
+            {
+                Some(vec![0, 0])
+            }
+            (ControlRequestType::Standard, ControlRequestRecipient::Device, true) => {
+                match setup.get_standard_request()? {
+                    StandardControlRequest::GetDescriptor
+                        if descriptor_type == DescriptorType::Device as u8 =>
+                    {
+                        Some(descriptors::device_descriptor(self.kind))
+                    }
+                    StandardControlRequest::GetDescriptor
+                        if descriptor_type == descriptors::STRING_DESCRIPTOR_TYPE =>
+                    {
+                        descriptors::string_descriptor(self.kind, descriptor_index)
+                    }
+                    StandardControlRequest::GetConfiguration => Some(vec![self.active_config]),
+                    _ => None,
+                }
+            }
+            (ControlRequestType::Standard, ControlRequestRecipient::Interface, true) => {
+                match setup.get_standard_request()? {
+                    StandardControlRequest::GetDescriptor
+                        if descriptor_type == hid::HID_REPORT_DESCRIPTOR_TYPE =>
+                    {
+                        Some(descriptors::report_descriptor(self.kind).to_vec())
+                    }
+                    StandardControlRequest::GetDescriptor
+                        if descriptor_type == hid::HID_DESCRIPTOR_TYPE =>
+                    {
+                        Some(descriptors::hid_descriptor(self.kind).to_vec())
+                    }
+                    StandardControlRequest::GetInterface => Some(vec![0]),
+                    _ => None,
+                }
+            }
+            (ControlRequestType::Standard, _, false) => match setup.get_standard_request()? {
+                // Remote wakeup and endpoint halt features have no effect on the gadget.
+                StandardControlRequest::SetFeature | StandardControlRequest::ClearFeature => {
+                    Some(Vec::new())
+                }
+                _ => None,
+            },
+            (ControlRequestType::Class, ControlRequestRecipient::Interface, _) => {
+                match setup.request {
+                    hid::HID_GET_REPORT => Some(self.last_report.clone()),
+                    hid::HID_GET_IDLE => Some(vec![self.idle]),
+                    hid::HID_GET_PROTOCOL => Some(vec![self.protocol]),
+                    hid::HID_SET_IDLE => {
+                        self.idle = (setup.value >> 8) as u8;
+                        Some(Vec::new())
+                    }
+                    hid::HID_SET_PROTOCOL => {
+                        self.protocol = setup.value as u8;
+                        Some(Vec::new())
+                    }
+                    // Keyboard LED state, there is nothing to light up.
+                    hid::HID_SET_REPORT => Some(Vec::new()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Completes `transfer` from the job queue, as the xhci transfer state is locked while the
+    // transfer is being submitted.
+    fn complete_later(
+        &self,
+        transfer: GadgetTransfer,
+        status: TransferStatus,
+    ) -> Result<BackendTransferHandle> {
+        let rc_transfer = Arc::new(Mutex::new(Some(transfer)));
+        let handle = GadgetTransferHandle {
+            weak_transfer: Arc::downgrade(&rc_transfer),
+            job_queue: self.job_queue.clone(),
+        };
+        self.job_queue
+            .queue_job(move || {
+                if let Some(transfer) = rc_transfer.lock().take() {
+                    transfer.complete_transfer(status);
+                }
+            })
+            .map_err(Error::QueueAsyncJob)?;
+        Ok(BackendTransferHandle::new(handle))
+    }
+}
+
+impl AsRawDescriptor for HidGadgetDevice {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.report_evt.as_raw_descriptor()
+    }
+}
+
+impl BackendDevice for HidGadgetDevice {
+    fn submit_backend_transfer(
+        &mut self,
+        transfer: BackendTransferType,
+    ) -> Result<BackendTransferHandle> {
+        let mut transfer = match transfer {
+            BackendTransferType::Gadget(transfer) => transfer,
+            _ => return Err(Error::MalformedBackendTransfer),
+        };
+
+        match transfer.endpoint {
+            0 => {
+                let setup = match &transfer.buffer {
+                    TransferBuffer::Vector(v) => {
+                        UsbRequestSetup::read_from_prefix(v)
+                            .map_err(|_| Error::MalformedBackendTransfer)?
+                            .0
+                    }
+                    TransferBuffer::Dma(_) => return Err(Error::MalformedBackendTransfer),
+                };
+                let status = match self.handle_control(&setup) {
+                    Some(mut data) => {
+                        data.truncate(setup.length as usize);
+                        transfer.actual_length = data.len();
+                        if let TransferBuffer::Vector(v) = &mut transfer.buffer {
+                            if setup.get_direction()
+                                == ControlRequestDataPhaseTransferDirection::DeviceToHost
+                            {
+                                v.truncate(SETUP_SIZE);
+                                v.extend_from_slice(&data);
+                            }
+                        }
+                        TransferStatus::Completed
+                    }
+                    None => {
+                        debug!("hid gadget stalls unsupported control request {:?}", setup);
+                        TransferStatus::Stalled
+                    }
+                };
+                self.complete_later(transfer, status)
+            }
+            descriptors::HID_GADGET_IN_ENDPOINT => {
+                let rc_transfer = Arc::new(Mutex::new(Some(transfer)));
+                let handle = GadgetTransferHandle {
+                    weak_transfer: Arc::downgrade(&rc_transfer),
+                    job_queue: self.job_queue.clone(),
+                };
+                self.pending_in_transfers.push_back(rc_transfer);
+                // Let the event loop hand it a queued report, if any.
+                if !self.queued_reports.is_empty() {
+                    self.report_evt.signal().map_err(Error::SignalHidGadget)?;
+                }
+                Ok(BackendTransferHandle::new(handle))
+            }
+            endpoint => {
+                error!("Wrong hid gadget endpoint requested: {endpoint}");
+                Err(Error::MalformedBackendTransfer)
+            }
+        }
+    }
+
+    fn detach_event_handler(&self, event_loop: &Arc<EventLoop>) -> Result<()> {
+        if let Some(socket) = &self.report_socket {
+            event_loop
+                .remove_event_for_descriptor(socket)
+                .map_err(Error::RemoveFromEventLoop)?;
+        }
+        event_loop
+            .remove_event_for_descriptor(self)
+            .map_err(Error::RemoveFromEventLoop)
+    }
+
+    fn request_transfer_buffer(&mut self, size: usize) -> TransferBuffer {
+        TransferBuffer::Vector(vec![0u8; size])
+    }
+
+    fn build_bulk_transfer(
+        &mut self,
+        _ep_addr: u8,
+        _transfer_buffer: TransferBuffer,
+        _stream_id: Option<u16>,
+    ) -> Result<BackendTransferType> {
+        // HID gadgets don't support bulk transfer requests
+        Err(Error::MalformedBackendTransfer)
+    }
+
+    fn build_interrupt_transfer(
+        &mut self,
+        ep_addr: u8,
+        transfer_buffer: TransferBuffer,
+    ) -> Result<BackendTransferType> {
+        Ok(BackendTransferType::Gadget(GadgetTransfer::new(
+            ep_addr,
+            transfer_buffer,
+        )))
+    }
+
+    fn build_isochronous_transfer(
+        &mut self,
+        _ep_addr: u8,
+        _transfer_buffer: TransferBuffer,
+        _packet_lengths: &[u32],
+    ) -> Result<BackendTransferType> {
+        // HID gadgets don't support isochronous transfer requests
+        Err(Error::MalformedBackendTransfer)
+    }
+
+    fn get_control_transfer_state(&mut self) -> Arc<RwLock<ControlTransferState>> {
+        self.control_transfer_state.clone()
+    }
+
+    fn get_device_state(&mut self) -> Arc<RwLock<DeviceState>> {
+        self.state.clone()
+    }
+
+    fn get_active_config_descriptor(&mut self) -> Result<ConfigDescriptorTree> {
+        self.get_config_descriptor(descriptors::HID_GADGET_CONFIGURATION_VALUE)
+    }
+
+    fn get_config_descriptor(&mut self, config: u8) -> Result<ConfigDescriptorTree> {
+        let device_descriptor = self.get_device_descriptor_tree()?;
+        device_descriptor
+            .get_config_descriptor(config)
+            .cloned()
+            .ok_or(Error::GetConfigDescriptor(UsbUtilError::NoSuchDescriptor))
+    }
+
+    fn get_config_descriptor_by_index(&mut self, config_index: u8) -> Result<ConfigDescriptorTree> {
+        let device_descriptor = self.get_device_descriptor_tree()?;
+        device_descriptor
+            .get_config_descriptor_by_index(config_index)
+            .cloned()
+            .ok_or(Error::GetConfigDescriptor(UsbUtilError::NoSuchDescriptor))
+    }
+
+    fn get_device_descriptor_tree(&mut self) -> Result<DeviceDescriptorTree> {
+        let mut descbuf = descriptors::device_descriptor(self.kind);
+        descbuf.extend_from_slice(&descriptors::config_descriptor(self.kind));
+        parse_usbfs_descriptors(&descbuf).map_err(Error::GetDeviceDescriptor)
+    }
+
+    fn get_active_configuration(&mut self) -> Result<u8> {
+        Ok(self.active_config)
+    }
+
+    fn set_active_configuration(&mut self, config: u8) -> Result<()> {
+        if config != 0 && config != descriptors::HID_GADGET_CONFIGURATION_VALUE {
+            error!("Requested to set hid gadget active configuration of {config}");
+            return Err(Error::SetActiveConfig(UsbUtilError::NoSuchDescriptor));
+        }
+        self.active_config = config;
+        Ok(())
+    }
+
+    fn clear_feature(&mut self, _value: u16, _index: u16) -> Result<TransferStatus> {
+        // Nothing to do here, the interrupt endpoint never halts.
+        Ok(TransferStatus::Completed)
+    }
+
+    fn create_endpoints(&mut self, _config_descriptor: &ConfigDescriptorTree) -> Result<()> {
+        let device_state = self.get_device_state();
+        let endpoint = UsbEndpoint::new(
+            device_state.read().unwrap().fail_handle.clone(),
+            device_state.read().unwrap().job_queue.clone(),
+            descriptors::HID_GADGET_IN_ENDPOINT & 0x0f,
+            EndpointDirection::DeviceToHost,
+            EndpointType::Interrupt,
+        );
+        device_state.write().unwrap().endpoints = vec![endpoint];
+        Ok(())
+    }
+}
+
+impl XhciBackendDevice for HidGadgetDevice {
+    fn get_backend_type(&self) -> BackendType {
+        BackendType::Usb2
+    }
+
+    fn get_vid(&self) -> u16 {
+        descriptors::HID_GADGET_VENDOR_ID
+    }
+
+    fn get_pid(&self) -> u16 {
+        descriptors::product_id(self.kind)
+    }
+
+    fn set_address(&mut self, _address: UsbDeviceAddress) {
+        // Nothing to do here
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // Reports queued before the guest configured the device are stale.
+        self.queued_reports.clear();
+        for rc_transfer in self.pending_in_transfers.drain(..) {
+            self.job_queue
+                .queue_job(move || {
+                    if let Some(transfer) = rc_transfer.lock().take() {
+                        transfer.complete_transfer(TransferStatus::Cancelled);
+                    }
+                })
+                .map_err(Error::QueueAsyncJob)?;
+        }
+        self.idle = 0;
+        self.protocol = 1;
+        Ok(())
+    }
+
+    fn get_speed(&self) -> Option<DeviceSpeed> {
+        Some(DeviceSpeed::Full)
+    }
+
+    fn alloc_streams(&self, _ep: u8, _num_streams: u16) -> Result<()> {
+        // HID gadgets don't support bulk/streams so we ignore this request.
+        Ok(())
+    }
+
+    fn free_streams(&self, _ep: u8) -> Result<()> {
+        // HID gadgets don't support bulk/streams so we ignore this request.
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Err(e) = self.reset() {
+            error!("failed to reset hid gadget on stop: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::mpsc::channel;
+    use std::sync::mpsc::Receiver;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::usb::backend::transfer::BackendTransfer;
+    use crate::utils::FailHandle;
+
+    struct TestDevice {
+        device: HidGadgetDevice,
+        event_loop: Arc<EventLoop>,
+        event_loop_thread: Option<JoinHandle<()>>,
+    }
+
+    impl Drop for TestDevice {
+        fn drop(&mut self) {
+            self.event_loop.stop();
+            if let Some(thread) = self.event_loop_thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    fn new_mouse(report_socket: Option<UnixStream>) -> TestDevice {
+        let (event_loop, event_loop_thread) = EventLoop::start("test".to_string(), None).unwrap();
+        let job_queue = AsyncJobQueue::init(&event_loop).unwrap();
+        let fail_handle: Arc<dyn FailHandle> = Arc::new(None::<Arc<dyn FailHandle>>);
+        let state = DeviceState::new(fail_handle, job_queue);
+        TestDevice {
+            device: HidGadgetDevice::new(UsbHidGadgetKind::Mouse, state, report_socket).unwrap(),
+            event_loop: Arc::new(event_loop),
+            event_loop_thread: Some(event_loop_thread),
+        }
+    }
+
+    fn submit_in_transfer(
+        device: &mut HidGadgetDevice,
+    ) -> (BackendTransferHandle, Receiver<(TransferStatus, Vec<u8>)>) {
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let mut transfer = device
+            .build_interrupt_transfer(
+                descriptors::HID_GADGET_IN_ENDPOINT,
+                TransferBuffer::Vector(vec![0u8; 8]),
+            )
+            .unwrap();
+        transfer.set_callback(move |t: BackendTransferType| {
+            let data = match t.buffer() {
+                TransferBuffer::Vector(v) => v[..t.actual_length()].to_vec(),
+                TransferBuffer::Dma(_) => Vec::new(),
+            };
+            tx.lock().send((t.status(), data)).unwrap();
+        });
+        let handle = device.submit_backend_transfer(transfer).unwrap();
+        (handle, rx)
+    }
+
+    #[test]
+    fn queue_report_drops_oldest_and_rejects_invalid_size() {
+        let mut t = new_mouse(None);
+        for i in 0..=MAX_QUEUED_REPORTS {
+            t.device.queue_report(vec![i as u8, 0, 0, 0]).unwrap();
+        }
+        assert_eq!(t.device.queued_reports.len(), MAX_QUEUED_REPORTS);
+        assert_eq!(t.device.queued_reports.front().unwrap()[0], 1);
+
+        assert!(matches!(
+            t.device.queue_report(vec![0u8; 3]),
+            Err(Error::InvalidHidReport(3))
+        ));
+        assert_eq!(t.device.queued_reports.len(), MAX_QUEUED_REPORTS);
+    }
+
+    #[test]
+    fn report_completes_pending_in_transfer() {
+        let mut t = new_mouse(None);
+        let (_handle, rx) = submit_in_transfer(&mut t.device);
+
+        t.device.queue_report(vec![1, 2, 3, 4]).unwrap();
+        t.device.deliver_reports().unwrap();
+
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (TransferStatus::Completed, vec![1, 2, 3, 4])
+        );
+        assert!(t.device.queued_reports.is_empty());
+        assert_eq!(t.device.last_report, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn cancelled_in_transfer_does_not_consume_report() {
+        let mut t = new_mouse(None);
+        let (handle, rx) = submit_in_transfer(&mut t.device);
+
+        handle.cancel().unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (TransferStatus::Cancelled, Vec::new())
+        );
+
+        t.device.queue_report(vec![1, 2, 3, 4]).unwrap();
+        t.device.deliver_reports().unwrap();
+        assert_eq!(t.device.queued_reports.len(), 1);
+        assert!(t.device.pending_in_transfers.is_empty());
+    }
+
+    #[test]
+    fn reports_are_read_from_socket() {
+        let (mut host, gadget) = UnixStream::pair().unwrap();
+        let mut t = new_mouse(Some(gadget));
+
+        // One and a half reports.
+        host.write_all(&[1, 2, 3, 4, 5, 6]).unwrap();
+        t.device.deliver_reports().unwrap();
+        assert_eq!(t.device.queued_reports, [vec![1, 2, 3, 4]]);
+        assert!(t.device.report_socket().is_some());
+
+        host.write_all(&[7, 8]).unwrap();
+        drop(host);
+        t.device.deliver_reports().unwrap();
+        assert_eq!(
+            t.device.queued_reports,
+            [vec![1, 2, 3, 4], vec![5, 6, 7, 8]]
+        );
+        assert!(t.device.report_socket().is_none());
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use sync::Mutex;
+use vm_control::UsbHidGadgetKind;
+
+use crate::usb::backend::device::BackendDeviceType;
+use crate::usb::backend::device::DeviceState;
+use crate::usb::backend::error::Result;
+use crate::usb::backend::hid_gadget::hid_gadget_device::HidGadgetDevice;
+use crate::usb::backend::utils::UsbUtilEventHandler;
+use crate::utils::EventHandler;
+
+/// Utility function to attach an emulated HID gadget of the given `kind` to the backend provider,
+/// optionally reading its reports from `report_socket`.
+/// It returns the `HidGadgetDevice` with its `EventHandler` to the backend.
+pub fn attach_hid_gadget(
+    kind: UsbHidGadgetKind,
+    report_socket: Option<UnixStream>,
+    device_state: DeviceState,
+) -> Result<(Arc<Mutex<BackendDeviceType>>, Arc<dyn EventHandler>)> {
+    let device = HidGadgetDevice::new(kind, device_state, report_socket)?;
+    let arc_mutex_device = Arc::new(Mutex::new(BackendDeviceType::HidGadget(device)));
+
+    let event_handler: Arc<dyn EventHandler> = Arc::new(UsbUtilEventHandler {
+        device: arc_mutex_device.clone(),
+    });
+
+    Ok((arc_mutex_device, event_handler))
+}
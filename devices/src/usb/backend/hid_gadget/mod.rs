@@ -0,0 +1,7 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+pub mod descriptors;
+pub mod hid_gadget_device;
+pub mod hid_gadget_provider;
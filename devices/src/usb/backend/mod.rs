@@ -7,6 +7,9 @@ pub mod device_provider;
 pub mod endpoint;
 pub mod error;
 pub mod fido_backend;
+pub mod gadget;
+pub mod hid_gadget;
 pub mod host_backend;
 pub mod transfer;
+pub mod usbip_backend;
 pub mod utils;
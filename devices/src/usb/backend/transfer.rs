@@ -9,7 +9,8 @@ use usb_util::UsbRequestSetup;
 
 use crate::usb::backend::endpoint::ControlEndpointState;
 use crate::usb::backend::error::Result;
-use crate::usb::backend::fido_backend::transfer::FidoTransfer;
+use crate::usb::backend::gadget::transfer::GadgetTransfer;
+use crate::usb::backend::usbip_backend::transfer::UsbipTransfer;
 
 /// BackendTransferHandle is a wrapper structure around a generic transfer handle whose
 /// implementation depends on the backend type that is being used.
@@ -31,7 +32,8 @@ impl BackendTransferHandle {
 
 pub enum BackendTransferType {
     HostDevice(Transfer),
-    FidoDevice(FidoTransfer),
+    Gadget(GadgetTransfer),
+    UsbipDevice(UsbipTransfer),
 }
 
 /// The backend transfer trait implemention is the interface of a generic transfer structure that
@@ -53,21 +55,24 @@ impl BackendTransfer for BackendTransferType {
     fn status(&self) -> TransferStatus {
         match self {
             BackendTransferType::HostDevice(transfer) => BackendTransfer::status(transfer),
-            BackendTransferType::FidoDevice(transfer) => BackendTransfer::status(transfer),
+            BackendTransferType::Gadget(transfer) => BackendTransfer::status(transfer),
+            BackendTransferType::UsbipDevice(transfer) => BackendTransfer::status(transfer),
         }
     }
 
     fn actual_length(&self) -> usize {
         match self {
             BackendTransferType::HostDevice(transfer) => BackendTransfer::actual_length(transfer),
-            BackendTransferType::FidoDevice(transfer) => BackendTransfer::actual_length(transfer),
+            BackendTransferType::Gadget(transfer) => BackendTransfer::actual_length(transfer),
+            BackendTransferType::UsbipDevice(transfer) => BackendTransfer::actual_length(transfer),
         }
     }
 
     fn buffer(&self) -> &TransferBuffer {
         match self {
             BackendTransferType::HostDevice(transfer) => BackendTransfer::buffer(transfer),
-            BackendTransferType::FidoDevice(transfer) => BackendTransfer::buffer(transfer),
+            BackendTransferType::Gadget(transfer) => BackendTransfer::buffer(transfer),
+            BackendTransferType::UsbipDevice(transfer) => BackendTransfer::buffer(transfer),
         }
     }
 
@@ -76,7 +81,8 @@ impl BackendTransfer for BackendTransferType {
             BackendTransferType::HostDevice(transfer) => {
                 BackendTransfer::set_callback(transfer, cb)
             }
-            BackendTransferType::FidoDevice(transfer) => {
+            BackendTransferType::Gadget(transfer) => BackendTransfer::set_callback(transfer, cb),
+            BackendTransferType::UsbipDevice(transfer) => {
                 BackendTransfer::set_callback(transfer, cb)
             }
         }
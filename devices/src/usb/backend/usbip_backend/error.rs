@@ -0,0 +1,34 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::io::Error as IOError;
+
+use remain::sorted;
+use thiserror::Error;
+use usb_util::Error as UsbUtilError;
+
+#[sorted]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("usbip server failed the control request with status {0}")]
+    ControlRequestFailed(i32),
+    #[error("usbip server rejected the import request with status {0}")]
+    ImportRejected(u32),
+    #[error("invalid usbip bus id: {0:?}")]
+    InvalidBusId(String),
+    #[error("failed to parse descriptors of the remote device: {0}")]
+    ParseDescriptors(UsbUtilError),
+    #[error("failed to read from usbip socket: {0}")]
+    ReadSocket(IOError),
+    #[error("failed to set usbip socket option: {0}")]
+    SetSocketOption(IOError),
+    #[error("unexpected usbip reply {0:#x}")]
+    UnexpectedReply(u32),
+    #[error("Unsupported TransferBuffer type")]
+    UnsupportedTransferBufferType,
+    #[error("failed to write to usbip socket: {0}")]
+    WriteSocket(IOError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
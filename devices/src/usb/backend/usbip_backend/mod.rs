@@ -0,0 +1,9 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+pub mod error;
+pub mod protocol;
+pub mod transfer;
+pub mod usbip_device;
+pub mod usbip_provider;
@@ -0,0 +1,367 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Wire format of the USB/IP protocol as described in the Linux kernel documentation
+//! (Documentation/usb/usbip_protocol.rst). All fields are transmitted in network byte order,
+//! except the setup packet which is copied verbatim from the USB request.
+
+use std::io::Read;
+use std::io::Write;
+
+use usb_util::DeviceSpeed;
+
+use crate::usb::backend::usbip_backend::error::Error;
+use crate::usb::backend::usbip_backend::error::Result;
+
+/// TCP port usbipd listens on by default.
+pub const USBIP_DEFAULT_PORT: u16 = 3240;
+
+const USBIP_VERSION: u16 = 0x0111;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+pub const USBIP_CMD_SUBMIT: u32 = 0x0001;
+pub const USBIP_CMD_UNLINK: u32 = 0x0002;
+pub const USBIP_RET_SUBMIT: u32 = 0x0003;
+pub const USBIP_RET_UNLINK: u32 = 0x0004;
+
+pub const USBIP_DIR_OUT: u32 = 0;
+pub const USBIP_DIR_IN: u32 = 1;
+
+/// Asks the server to schedule isochronous packets as soon as possible.
+pub const URB_ISO_ASAP: u32 = 0x0002;
+
+pub const BUSID_SIZE: usize = 32;
+const PATH_SIZE: usize = 256;
+/// Every command and reply header is padded to the same size.
+pub const HEADER_SIZE: usize = 48;
+pub const ISO_PACKET_DESCRIPTOR_SIZE: usize = 16;
+const OP_HEADER_SIZE: usize = 8;
+const IMPORTED_DEVICE_SIZE: usize = PATH_SIZE + BUSID_SIZE + 24;
+
+/// Status values reported by the server are negated Linux errno values.
+pub const ENOENT: i32 = 2;
+pub const ENODEV: i32 = 19;
+pub const EPIPE: i32 = 32;
+pub const ESHUTDOWN: i32 = 108;
+pub const ECONNRESET: i32 = 104;
+
+/// Description of the remote device returned by a successful import.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportedDevice {
+    pub busnum: u32,
+    pub devnum: u32,
+    pub speed: u32,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub configuration_value: u8,
+    pub num_configurations: u8,
+}
+
+impl ImportedDevice {
+    /// Device id used by the server to route submitted URBs.
+    pub fn devid(&self) -> u32 {
+        (self.busnum << 16) | (self.devnum & 0xffff)
+    }
+
+    /// Converts the kernel `usb_device_speed` value into a `DeviceSpeed`.
+    pub fn device_speed(&self) -> Option<DeviceSpeed> {
+        match self.speed {
+            1 => Some(DeviceSpeed::Low),
+            2 => Some(DeviceSpeed::Full),
+            3 => Some(DeviceSpeed::High),
+            5 => Some(DeviceSpeed::Super),
+            6 => Some(DeviceSpeed::SuperPlus),
+            _ => None,
+        }
+    }
+}
+
+/// Issues OP_REQ_IMPORT for `busid` and waits for the server to hand over the device.
+pub fn import_device<S: Read + Write>(stream: &mut S, busid: &str) -> Result<ImportedDevice> {
+    if busid.is_empty() || busid.len() >= BUSID_SIZE {
+        return Err(Error::InvalidBusId(busid.to_string()));
+    }
+
+    let mut request = [0u8; OP_HEADER_SIZE + BUSID_SIZE];
+    request[0..2].copy_from_slice(&USBIP_VERSION.to_be_bytes());
+    request[2..4].copy_from_slice(&OP_REQ_IMPORT.to_be_bytes());
+    request[OP_HEADER_SIZE..OP_HEADER_SIZE + busid.len()].copy_from_slice(busid.as_bytes());
+    stream.write_all(&request).map_err(Error::WriteSocket)?;
+
+    let mut reply = [0u8; OP_HEADER_SIZE];
+    stream.read_exact(&mut reply).map_err(Error::ReadSocket)?;
+    let code = u16::from_be_bytes([reply[2], reply[3]]);
+    let status = be_u32(&reply, 4);
+    if code != OP_REP_IMPORT {
+        return Err(Error::UnexpectedReply(code as u32));
+    }
+    if status != 0 {
+        return Err(Error::ImportRejected(status));
+    }
+
+    let mut device = [0u8; IMPORTED_DEVICE_SIZE];
+    stream.read_exact(&mut device).map_err(Error::ReadSocket)?;
+    let d = &device[PATH_SIZE + BUSID_SIZE..];
+    Ok(ImportedDevice {
+        busnum: be_u32(d, 0),
+        devnum: be_u32(d, 4),
+        speed: be_u32(d, 8),
+        vendor_id: u16::from_be_bytes([d[12], d[13]]),
+        product_id: u16::from_be_bytes([d[14], d[15]]),
+        configuration_value: d[21],
+        num_configurations: d[22],
+    })
+}
+
+/// USBIP_CMD_SUBMIT header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CmdSubmit {
+    pub seqnum: u32,
+    pub devid: u32,
+    pub direction: u32,
+    pub ep: u32,
+    pub transfer_flags: u32,
+    pub transfer_buffer_length: u32,
+    /// Number of isochronous packets, or 0 for other transfer types.
+    pub number_of_packets: u32,
+    pub interval: u32,
+    pub setup: [u8; 8],
+}
+
+impl CmdSubmit {
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut b = [0u8; HEADER_SIZE];
+        put_basic_header(
+            &mut b,
+            USBIP_CMD_SUBMIT,
+            self.seqnum,
+            self.devid,
+            self.direction,
+            self.ep,
+        );
+        b[20..24].copy_from_slice(&self.transfer_flags.to_be_bytes());
+        b[24..28].copy_from_slice(&self.transfer_buffer_length.to_be_bytes());
+        // start_frame is left at 0, the server picks it for URB_ISO_ASAP.
+        b[32..36].copy_from_slice(&self.number_of_packets.to_be_bytes());
+        b[36..40].copy_from_slice(&self.interval.to_be_bytes());
+        b[40..48].copy_from_slice(&self.setup);
+        b
+    }
+}
+
+/// USBIP_CMD_UNLINK header, asking the server to cancel the URB `unlink_seqnum`.
+pub fn cmd_unlink_bytes(seqnum: u32, devid: u32, unlink_seqnum: u32) -> [u8; HEADER_SIZE] {
+    let mut b = [0u8; HEADER_SIZE];
+    put_basic_header(&mut b, USBIP_CMD_UNLINK, seqnum, devid, USBIP_DIR_OUT, 0);
+    b[20..24].copy_from_slice(&unlink_seqnum.to_be_bytes());
+    b
+}
+
+/// Replies sent by the server once the URB of a command has been handled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    Submit {
+        seqnum: u32,
+        status: i32,
+        actual_length: u32,
+        number_of_packets: u32,
+    },
+    Unlink {
+        seqnum: u32,
+        status: i32,
+    },
+}
+
+impl Reply {
+    pub fn from_bytes(b: &[u8; HEADER_SIZE]) -> Result<Reply> {
+        let command = be_u32(b, 0);
+        let seqnum = be_u32(b, 4);
+        match command {
+            USBIP_RET_SUBMIT => Ok(Reply::Submit {
+                seqnum,
+                status: be_u32(b, 20) as i32,
+                actual_length: be_u32(b, 24),
+                number_of_packets: be_u32(b, 32),
+            }),
+            USBIP_RET_UNLINK => Ok(Reply::Unlink {
+                seqnum,
+                status: be_u32(b, 20) as i32,
+            }),
+            _ => Err(Error::UnexpectedReply(command)),
+        }
+    }
+}
+
+/// Per-packet descriptor following the data of isochronous transfers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IsoPacketDescriptor {
+    pub offset: u32,
+    pub length: u32,
+    pub actual_length: u32,
+    pub status: i32,
+}
+
+impl IsoPacketDescriptor {
+    pub fn to_bytes(&self) -> [u8; ISO_PACKET_DESCRIPTOR_SIZE] {
+        let mut b = [0u8; ISO_PACKET_DESCRIPTOR_SIZE];
+        b[0..4].copy_from_slice(&self.offset.to_be_bytes());
+        b[4..8].copy_from_slice(&self.length.to_be_bytes());
+        b[8..12].copy_from_slice(&self.actual_length.to_be_bytes());
+        b[12..16].copy_from_slice(&self.status.to_be_bytes());
+        b
+    }
+
+    pub fn from_bytes(b: &[u8]) -> IsoPacketDescriptor {
+        IsoPacketDescriptor {
+            offset: be_u32(b, 0),
+            length: be_u32(b, 4),
+            actual_length: be_u32(b, 8),
+            status: be_u32(b, 12) as i32,
+        }
+    }
+}
+
+fn put_basic_header(b: &mut [u8], command: u32, seqnum: u32, devid: u32, direction: u32, ep: u32) {
+    b[0..4].copy_from_slice(&command.to_be_bytes());
+    b[4..8].copy_from_slice(&seqnum.to_be_bytes());
+    b[8..12].copy_from_slice(&devid.to_be_bytes());
+    b[12..16].copy_from_slice(&direction.to_be_bytes());
+    b[16..20].copy_from_slice(&ep.to_be_bytes());
+}
+
+fn be_u32(b: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([b[offset], b[offset + 1], b[offset + 2], b[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    struct FakeServer {
+        reply: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reply.read(buf)
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn import_device_roundtrip() {
+        let mut reply = vec![0x01, 0x11, 0x00, 0x03, 0, 0, 0, 0];
+        let mut device = vec![0u8; IMPORTED_DEVICE_SIZE];
+        let d = &mut device[PATH_SIZE + BUSID_SIZE..];
+        d[0..4].copy_from_slice(&3u32.to_be_bytes());
+        d[4..8].copy_from_slice(&7u32.to_be_bytes());
+        d[8..12].copy_from_slice(&3u32.to_be_bytes());
+        d[12..14].copy_from_slice(&0x1234u16.to_be_bytes());
+        d[14..16].copy_from_slice(&0x5678u16.to_be_bytes());
+        d[21] = 1;
+        d[22] = 2;
+        reply.extend_from_slice(&device);
+        let mut server = FakeServer {
+            reply: Cursor::new(reply),
+            sent: Vec::new(),
+        };
+
+        let imported = import_device(&mut server, "1-1.2").unwrap();
+        assert_eq!(
+            imported,
+            ImportedDevice {
+                busnum: 3,
+                devnum: 7,
+                speed: 3,
+                vendor_id: 0x1234,
+                product_id: 0x5678,
+                configuration_value: 1,
+                num_configurations: 2,
+            }
+        );
+        assert_eq!(imported.devid(), 0x0003_0007);
+        assert_eq!(server.sent.len(), OP_HEADER_SIZE + BUSID_SIZE);
+        assert_eq!(&server.sent[0..4], &[0x01, 0x11, 0x80, 0x03]);
+        assert_eq!(&server.sent[8..13], b"1-1.2");
+        assert_eq!(server.sent[13], 0);
+    }
+
+    #[test]
+    fn import_device_rejected() {
+        let mut server = FakeServer {
+            reply: Cursor::new(vec![0x01, 0x11, 0x00, 0x03, 0, 0, 0, 1]),
+            sent: Vec::new(),
+        };
+        assert!(matches!(
+            import_device(&mut server, "1-1"),
+            Err(Error::ImportRejected(1))
+        ));
+    }
+
+    #[test]
+    fn cmd_submit_layout() {
+        let cmd = CmdSubmit {
+            seqnum: 5,
+            devid: 0x0001_0002,
+            direction: USBIP_DIR_IN,
+            ep: 0x81,
+            transfer_flags: URB_ISO_ASAP,
+            transfer_buffer_length: 512,
+            number_of_packets: 4,
+            interval: 8,
+            setup: [1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let b = cmd.to_bytes();
+        assert_eq!(be_u32(&b, 0), USBIP_CMD_SUBMIT);
+        assert_eq!(be_u32(&b, 4), 5);
+        assert_eq!(be_u32(&b, 8), 0x0001_0002);
+        assert_eq!(be_u32(&b, 12), USBIP_DIR_IN);
+        assert_eq!(be_u32(&b, 16), 0x81);
+        assert_eq!(be_u32(&b, 20), URB_ISO_ASAP);
+        assert_eq!(be_u32(&b, 24), 512);
+        assert_eq!(be_u32(&b, 28), 0);
+        assert_eq!(be_u32(&b, 32), 4);
+        assert_eq!(be_u32(&b, 36), 8);
+        assert_eq!(&b[40..48], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn parse_replies() {
+        let mut b = [0u8; HEADER_SIZE];
+        b[0..4].copy_from_slice(&USBIP_RET_SUBMIT.to_be_bytes());
+        b[4..8].copy_from_slice(&9u32.to_be_bytes());
+        b[20..24].copy_from_slice(&(-EPIPE).to_be_bytes());
+        b[24..28].copy_from_slice(&13u32.to_be_bytes());
+        assert_eq!(
+            Reply::from_bytes(&b).unwrap(),
+            Reply::Submit {
+                seqnum: 9,
+                status: -EPIPE,
+                actual_length: 13,
+                number_of_packets: 0,
+            }
+        );
+
+        let b = cmd_unlink_bytes(10, 0x0001_0002, 9);
+        assert_eq!(be_u32(&b, 20), 9);
+        assert!(matches!(
+            Reply::from_bytes(&b),
+            Err(Error::UnexpectedReply(USBIP_CMD_UNLINK))
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::sync::Weak;
+
+use usb_util::TransferBuffer;
+use usb_util::TransferStatus;
+
+use crate::usb::backend::error::Error as BackendError;
+use crate::usb::backend::error::Result as BackendResult;
+use crate::usb::backend::transfer::BackendTransfer;
+use crate::usb::backend::transfer::BackendTransferType;
+use crate::usb::backend::transfer::GenericTransferHandle;
+use crate::usb::backend::usbip_backend::protocol::ECONNRESET;
+use crate::usb::backend::usbip_backend::protocol::ENODEV;
+use crate::usb::backend::usbip_backend::protocol::ENOENT;
+use crate::usb::backend::usbip_backend::protocol::EPIPE;
+use crate::usb::backend::usbip_backend::protocol::ESHUTDOWN;
+use crate::usb::backend::usbip_backend::usbip_device::UsbipConnection;
+
+/// A USB transfer forwarded to a remote usbip server as a single URB.
+pub struct UsbipTransfer {
+    /// Data of the transfer. Control transfers carry the setup packet in the first 8 bytes.
+    pub buffer: TransferBuffer,
+    /// Status of the transfer, used by the xhci layer for a successful completion.
+    status: TransferStatus,
+    /// Actual length of the transfer, excluding the setup packet of control transfers.
+    pub actual_length: usize,
+    /// Endpoint address, including the direction bit.
+    pub endpoint: u8,
+    /// Polling interval of the URB, in (micro)frames.
+    pub interval: u32,
+    /// Length of each isochronous packet, empty for other transfer types.
+    pub packet_lengths: Vec<u32>,
+    /// Callback to be executed once the transfer has completed, to signal the xhci layer.
+    pub callback: Option<Box<dyn Fn(UsbipTransfer) + Send + Sync>>,
+}
+
+impl UsbipTransfer {
+    pub fn new(
+        endpoint: u8,
+        buffer: TransferBuffer,
+        interval: u32,
+        packet_lengths: Vec<u32>,
+    ) -> UsbipTransfer {
+        UsbipTransfer {
+            buffer,
+            status: TransferStatus::Error,
+            actual_length: 0,
+            endpoint,
+            interval,
+            packet_lengths,
+            callback: None,
+        }
+    }
+
+    /// Finalizes the transfer with the URB status reported by the server, or `status` for
+    /// transfers that never reached it, and signals the xhci layer.
+    pub fn complete_transfer(mut self, status: TransferStatus) {
+        self.status = status;
+        if let Some(cb) = self.callback.take() {
+            cb(self);
+        }
+    }
+}
+
+/// Converts the negated errno reported in a RET_SUBMIT into a `TransferStatus`.
+pub fn status_from_urb(status: i32) -> TransferStatus {
+    match -status {
+        0 => TransferStatus::Completed,
+        EPIPE => TransferStatus::Stalled,
+        ENODEV | ESHUTDOWN => TransferStatus::NoDevice,
+        ECONNRESET | ENOENT => TransferStatus::Cancelled,
+        _ => TransferStatus::Error,
+    }
+}
+
+impl BackendTransfer for UsbipTransfer {
+    fn status(&self) -> TransferStatus {
+        self.status
+    }
+
+    fn actual_length(&self) -> usize {
+        self.actual_length
+    }
+
+    fn buffer(&self) -> &TransferBuffer {
+        &self.buffer
+    }
+
+    fn set_callback<C: 'static + Fn(BackendTransferType) + Send + Sync>(&mut self, cb: C) {
+        let callback = move |t: UsbipTransfer| cb(BackendTransferType::UsbipDevice(t));
+        self.callback = Some(Box::new(callback));
+    }
+}
+
+/// Cancels an in-flight `UsbipTransfer` by asking the server to unlink its URB.
+pub struct UsbipTransferHandle {
+    pub connection: Weak<UsbipConnection>,
+    pub seqnum: u32,
+}
+
+impl GenericTransferHandle for UsbipTransferHandle {
+    fn cancel(&self) -> BackendResult<()> {
+        match self.connection.upgrade() {
+            Some(connection) => connection.unlink(self.seqnum),
+            None => Err(BackendError::TransferHandleAlreadyComplete),
+        }
+    }
+}
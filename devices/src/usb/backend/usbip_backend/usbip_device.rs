@@ -0,0 +1,987 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use base::debug;
+use base::error;
+use base::AsRawDescriptor;
+use base::RawDescriptor;
+use sync::Mutex;
+use usb_util::control_request_type;
+use usb_util::parse_usbfs_descriptors;
+use usb_util::ConfigDescriptorTree;
+use usb_util::ControlRequestDataPhaseTransferDirection;
+use usb_util::ControlRequestRecipient;
+use usb_util::ControlRequestType;
+use usb_util::DescriptorType;
+use usb_util::DeviceDescriptorTree;
+use usb_util::DeviceSpeed;
+use usb_util::EndpointType;
+use usb_util::Error as UsbUtilError;
+use usb_util::StandardControlRequest;
+use usb_util::TransferBuffer;
+use usb_util::TransferStatus;
+use usb_util::UsbRequestSetup;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+use crate::usb::backend::device::BackendDevice;
+use crate::usb::backend::device::DeviceState;
+use crate::usb::backend::endpoint::ControlEndpointState;
+use crate::usb::backend::endpoint::UsbEndpoint;
+use crate::usb::backend::error::Error as BackendError;
+use crate::usb::backend::error::Result as BackendResult;
+use crate::usb::backend::transfer::BackendTransfer;
+use crate::usb::backend::transfer::BackendTransferHandle;
+use crate::usb::backend::transfer::BackendTransferType;
+use crate::usb::backend::transfer::ControlTransferState;
+use crate::usb::backend::usbip_backend::error::Error;
+use crate::usb::backend::usbip_backend::error::Result;
+use crate::usb::backend::usbip_backend::protocol::cmd_unlink_bytes;
+use crate::usb::backend::usbip_backend::protocol::import_device;
+use crate::usb::backend::usbip_backend::protocol::CmdSubmit;
+use crate::usb::backend::usbip_backend::protocol::ImportedDevice;
+use crate::usb::backend::usbip_backend::protocol::IsoPacketDescriptor;
+use crate::usb::backend::usbip_backend::protocol::Reply;
+use crate::usb::backend::usbip_backend::protocol::HEADER_SIZE;
+use crate::usb::backend::usbip_backend::protocol::ISO_PACKET_DESCRIPTOR_SIZE;
+use crate::usb::backend::usbip_backend::protocol::URB_ISO_ASAP;
+use crate::usb::backend::usbip_backend::protocol::USBIP_DIR_IN;
+use crate::usb::backend::usbip_backend::protocol::USBIP_DIR_OUT;
+use crate::usb::backend::usbip_backend::transfer::status_from_urb;
+use crate::usb::backend::usbip_backend::transfer::UsbipTransfer;
+use crate::usb::backend::usbip_backend::transfer::UsbipTransferHandle;
+use crate::usb::xhci::xhci_backend_device::BackendType;
+use crate::usb::xhci::xhci_backend_device::UsbDeviceAddress;
+use crate::usb::xhci::xhci_backend_device::XhciBackendDevice;
+use crate::utils::EventLoop;
+
+// How long to wait for the server while importing the device and reading its descriptors.
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SETUP_SIZE: usize = mem::size_of::<UsbRequestSetup>();
+// How much to read from the socket at once.
+const RECV_CHUNK_SIZE: usize = 16 * 1024;
+const DEVICE_DESCRIPTOR_SIZE: u16 = 18;
+const CONFIG_DESCRIPTOR_SIZE: u16 = 9;
+
+// Hub class request the usbip server turns into a reset of the exported device.
+const HUB_REQ_SET_FEATURE: u8 = 3;
+const HUB_PORT_FEAT_RESET: u16 = 4;
+
+/// Connection to the usbip server shared by a `UsbipDevice` and its transfer handles.
+pub struct UsbipConnection {
+    writer: Mutex<TcpStream>,
+    devid: u32,
+    next_seqnum: AtomicU32,
+    inflight: Mutex<InflightRequests>,
+}
+
+#[derive(Default)]
+struct InflightRequests {
+    // URBs waiting for a RET_SUBMIT, keyed by their seqnum.
+    transfers: HashMap<u32, UsbipTransfer>,
+    // Seqnum of each CMD_UNLINK waiting for a RET_UNLINK, mapped to the URB it cancels.
+    unlinks: HashMap<u32, u32>,
+}
+
+impl UsbipConnection {
+    fn alloc_seqnum(&self) -> u32 {
+        loop {
+            // Seqnum 0 is reserved by the protocol.
+            let seqnum = self.next_seqnum.fetch_add(1, Ordering::Relaxed);
+            if seqnum != 0 {
+                return seqnum;
+            }
+        }
+    }
+
+    /// Sends `transfer` to the server as a CMD_SUBMIT and returns its seqnum.
+    fn submit(&self, transfer: UsbipTransfer) -> Result<u32> {
+        let seqnum = self.alloc_seqnum();
+        let data = match &transfer.buffer {
+            TransferBuffer::Vector(v) => v.as_slice(),
+            TransferBuffer::Dma(_) => return Err(Error::UnsupportedTransferBufferType),
+        };
+
+        let ep = transfer.endpoint & 0x0f;
+        let mut setup = [0u8; SETUP_SIZE];
+        let (direction, payload) = if ep == 0 {
+            // Control transfers carry the setup packet at the start of the buffer.
+            setup.copy_from_slice(&data[..SETUP_SIZE]);
+            (direction_of(setup[0]), &data[SETUP_SIZE..])
+        } else {
+            (direction_of(transfer.endpoint), data)
+        };
+
+        let mut cmd = CmdSubmit {
+            seqnum,
+            devid: self.devid,
+            direction,
+            ep: ep as u32,
+            transfer_buffer_length: payload.len() as u32,
+            interval: transfer.interval,
+            setup,
+            ..Default::default()
+        };
+        if !transfer.packet_lengths.is_empty() {
+            cmd.transfer_flags = URB_ISO_ASAP;
+            cmd.number_of_packets = transfer.packet_lengths.len() as u32;
+        }
+
+        let mut message = cmd.to_bytes().to_vec();
+        if direction == USBIP_DIR_OUT {
+            message.extend_from_slice(payload);
+        }
+        let mut offset = 0;
+        for &length in &transfer.packet_lengths {
+            let descriptor = IsoPacketDescriptor {
+                offset,
+                length,
+                ..Default::default()
+            };
+            message.extend_from_slice(&descriptor.to_bytes());
+            offset += length;
+        }
+
+        // Track the transfer before sending it, the reply may arrive before write_all returns.
+        self.inflight.lock().transfers.insert(seqnum, transfer);
+        if let Err(e) = self.writer.lock().write_all(&message) {
+            self.inflight.lock().transfers.remove(&seqnum);
+            return Err(Error::WriteSocket(e));
+        }
+        Ok(seqnum)
+    }
+
+    /// Asks the server to cancel the URB `seqnum`. The transfer completes as cancelled once the
+    /// server confirms it, or normally if the URB finished first.
+    pub fn unlink(&self, seqnum: u32) -> BackendResult<()> {
+        let unlink_seqnum = self.alloc_seqnum();
+        {
+            let mut inflight = self.inflight.lock();
+            if !inflight.transfers.contains_key(&seqnum) {
+                return Err(BackendError::TransferHandleAlreadyComplete);
+            }
+            inflight.unlinks.insert(unlink_seqnum, seqnum);
+        }
+        self.writer
+            .lock()
+            .write_all(&cmd_unlink_bytes(unlink_seqnum, self.devid, seqnum))
+            .map_err(|e| BackendError::SubmitUsbipTransfer(Error::WriteSocket(e)))
+    }
+}
+
+fn direction_of(address_or_request_type: u8) -> u32 {
+    if address_or_request_type & 0x80 != 0 {
+        USBIP_DIR_IN
+    } else {
+        USBIP_DIR_OUT
+    }
+}
+
+// Converts the bInterval of an endpoint into the URB interval the server expects, in frames for
+// low and full speed interrupt endpoints and in (micro)frames as a power of two otherwise.
+fn urb_interval(speed: Option<DeviceSpeed>, ty: EndpointType, b_interval: u8) -> u32 {
+    let exponential = ty == EndpointType::Isochronous
+        || matches!(
+            speed,
+            Some(DeviceSpeed::High) | Some(DeviceSpeed::Super) | Some(DeviceSpeed::SuperPlus)
+        );
+    if exponential {
+        1 << (b_interval.clamp(1, 16) - 1)
+    } else {
+        b_interval.max(1) as u32
+    }
+}
+
+// Runs a device-to-host control request synchronously. This is only used before the device is
+// registered with the event loop, while nothing else reads from the socket.
+fn control_in_sync(
+    stream: &mut TcpStream,
+    devid: u32,
+    seqnum: u32,
+    setup: UsbRequestSetup,
+) -> Result<Vec<u8>> {
+    let mut cmd = CmdSubmit {
+        seqnum,
+        devid,
+        direction: USBIP_DIR_IN,
+        transfer_buffer_length: setup.length as u32,
+        ..Default::default()
+    };
+    cmd.setup.copy_from_slice(setup.as_bytes());
+    stream
+        .write_all(&cmd.to_bytes())
+        .map_err(Error::WriteSocket)?;
+
+    let mut header = [0u8; HEADER_SIZE];
+    stream.read_exact(&mut header).map_err(Error::ReadSocket)?;
+    match Reply::from_bytes(&header)? {
+        Reply::Submit {
+            seqnum: reply_seqnum,
+            status,
+            actual_length,
+            ..
+        } if reply_seqnum == seqnum && actual_length <= cmd.transfer_buffer_length => {
+            let mut data = vec![0u8; actual_length as usize];
+            stream.read_exact(&mut data).map_err(Error::ReadSocket)?;
+            if status != 0 {
+                return Err(Error::ControlRequestFailed(status));
+            }
+            Ok(data)
+        }
+        _ => Err(Error::UnexpectedReply(u32::from_be_bytes([
+            header[0], header[1], header[2], header[3],
+        ]))),
+    }
+}
+
+// Reads the device descriptor followed by every configuration descriptor, in the layout
+// `parse_usbfs_descriptors` expects.
+fn read_descriptors(stream: &mut TcpStream, devid: u32) -> Result<DeviceDescriptorTree> {
+    let get_descriptor = |ty: DescriptorType, index: u8, length: u16| {
+        UsbRequestSetup::new(
+            control_request_type(
+                ControlRequestType::Standard,
+                ControlRequestDataPhaseTransferDirection::DeviceToHost,
+                ControlRequestRecipient::Device,
+            ),
+            StandardControlRequest::GetDescriptor as u8,
+            ((ty as u16) << 8) | index as u16,
+            0,
+            length,
+        )
+    };
+
+    let mut seqnum = 1;
+    let mut raw = control_in_sync(
+        stream,
+        devid,
+        seqnum,
+        get_descriptor(DescriptorType::Device, 0, DEVICE_DESCRIPTOR_SIZE),
+    )?;
+    if raw.len() != DEVICE_DESCRIPTOR_SIZE as usize {
+        return Err(Error::ParseDescriptors(UsbUtilError::DescriptorParse));
+    }
+    let num_configurations = raw[DEVICE_DESCRIPTOR_SIZE as usize - 1];
+    for index in 0..num_configurations {
+        seqnum += 1;
+        let header = control_in_sync(
+            stream,
+            devid,
+            seqnum,
+            get_descriptor(DescriptorType::Configuration, index, CONFIG_DESCRIPTOR_SIZE),
+        )?;
+        if header.len() < 4 {
+            return Err(Error::ParseDescriptors(UsbUtilError::DescriptorParse));
+        }
+        let total_length = u16::from_le_bytes([header[2], header[3]]);
+        seqnum += 1;
+        let config = control_in_sync(
+            stream,
+            devid,
+            seqnum,
+            get_descriptor(DescriptorType::Configuration, index, total_length),
+        )?;
+        raw.extend_from_slice(&config);
+    }
+    parse_usbfs_descriptors(&raw).map_err(Error::ParseDescriptors)
+}
+
+/// A device exported by a remote usbip server, e.g. with `usbip bind` on a lab machine. USB
+/// requests from the guest are forwarded as URBs over the usbip connection.
+pub struct UsbipDevice {
+    reader: TcpStream,
+    replies: ReplyBuffer,
+    connection: Arc<UsbipConnection>,
+    info: ImportedDevice,
+    descriptors: DeviceDescriptorTree,
+    active_config: u8,
+    alt_settings: HashMap<u8, u8>,
+    // URB interval of interrupt and isochronous endpoints, keyed by endpoint address.
+    intervals: HashMap<u8, u32>,
+    disconnected: bool,
+    state: Arc<RwLock<DeviceState>>,
+    control_transfer_state: Arc<RwLock<ControlTransferState>>,
+}
+
+impl UsbipDevice {
+    /// Imports `busid` over `stream`, a connection to the usbip server, and reads the descriptors
+    /// of the device.
+    pub fn new(mut stream: TcpStream, busid: &str, state: DeviceState) -> Result<UsbipDevice> {
+        stream
+            .set_read_timeout(Some(ATTACH_TIMEOUT))
+            .map_err(Error::SetSocketOption)?;
+        let info = import_device(&mut stream, busid)?;
+        let descriptors = read_descriptors(&mut stream, info.devid())?;
+        stream
+            .set_read_timeout(None)
+            .map_err(Error::SetSocketOption)?;
+        // URBs are small and latency sensitive.
+        stream.set_nodelay(true).map_err(Error::SetSocketOption)?;
+        let writer = stream.try_clone().map_err(Error::SetSocketOption)?;
+
+        let control_transfer_state = ControlTransferState {
+            ctl_ep_state: ControlEndpointState::SetupStage,
+            control_request_setup: UsbRequestSetup::new(0, 0, 0, 0, 0),
+            executed: false,
+        };
+        Ok(UsbipDevice {
+            reader: stream,
+            replies: ReplyBuffer::default(),
+            connection: Arc::new(UsbipConnection {
+                writer: Mutex::new(writer),
+                devid: info.devid(),
+                // Seqnums below this were used to read the descriptors.
+                next_seqnum: AtomicU32::new(descriptors.bNumConfigurations as u32 * 2 + 2),
+                inflight: Mutex::new(InflightRequests::default()),
+            }),
+            active_config: info.configuration_value,
+            info,
+            descriptors,
+            alt_settings: HashMap::new(),
+            intervals: HashMap::new(),
+            disconnected: false,
+            state: Arc::new(RwLock::new(state)),
+            control_transfer_state: Arc::new(RwLock::new(control_transfer_state)),
+        })
+    }
+
+    /// Called from the event handler when the socket is readable. Completes the transfers whose
+    /// reply was fully received. If the connection is lost, all pending transfers complete with
+    /// `TransferStatus::NoDevice` and an error is returned so that the socket stops being polled.
+    pub fn read_reply(&mut self) -> Result<()> {
+        let mut completed = Vec::new();
+        let result = self.recv_available().and_then(|()| {
+            self.replies
+                .take_completed(&self.connection.inflight, &mut completed)
+        });
+        for (transfer, status) in completed {
+            if status == TransferStatus::Completed {
+                if let Err(e) = self.control_request_completed(&transfer) {
+                    error!("failed to track usbip control request: {}", e);
+                }
+            }
+            transfer.complete_transfer(status);
+        }
+        if let Err(e) = result {
+            self.disconnected = true;
+            let transfers: Vec<UsbipTransfer> = {
+                let mut inflight = self.connection.inflight.lock();
+                inflight.unlinks.clear();
+                inflight.transfers.drain().map(|(_, t)| t).collect()
+            };
+            for transfer in transfers {
+                transfer.complete_transfer(TransferStatus::NoDevice);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Reads what the server sent so far. The socket is shared with the writer, which must block,
+    // so it is read with MSG_DONTWAIT rather than made non-blocking.
+    fn recv_available(&mut self) -> Result<()> {
+        let mut buf = [0u8; RECV_CHUNK_SIZE];
+        loop {
+            // SAFETY:
+            // Safe because `buf` is valid for writes of its length and the return value is
+            // checked.
+            let ret = unsafe {
+                libc::recv(
+                    self.reader.as_raw_descriptor(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(Error::ReadSocket(e)),
+                }
+            }
+            if ret == 0 {
+                return Err(Error::ReadSocket(io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.replies.data.extend_from_slice(&buf[..ret as usize]);
+            if (ret as usize) < buf.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Tracks the effect of a standard request the remote device completed. The server applies
+    // set configuration and set interface requests to the device itself.
+    fn control_request_completed(&mut self, transfer: &UsbipTransfer) -> BackendResult<()> {
+        if transfer.endpoint & 0x0f != 0 {
+            return Ok(());
+        }
+        let setup = match &transfer.buffer {
+            TransferBuffer::Vector(v) => match UsbRequestSetup::read_from_prefix(v) {
+                Ok((setup, _)) => setup,
+                Err(_) => return Ok(()),
+            },
+            TransferBuffer::Dma(_) => return Ok(()),
+        };
+        match (setup.get_standard_request(), setup.get_recipient()) {
+            (Some(StandardControlRequest::SetConfiguration), ControlRequestRecipient::Device) => {
+                let config = setup.value as u8;
+                self.set_active_configuration(config)?;
+                if config == 0 {
+                    self.state.write().unwrap().endpoints.clear();
+                    return Ok(());
+                }
+                let config_descriptor = self.get_active_config_descriptor()?;
+                self.create_endpoints(&config_descriptor)
+            }
+            (Some(StandardControlRequest::SetInterface), ControlRequestRecipient::Interface) => {
+                self.alt_settings
+                    .insert(setup.index as u8, setup.value as u8);
+                let config_descriptor = self.get_active_config_descriptor()?;
+                self.create_endpoints(&config_descriptor)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Replies received from the server, buffered until they are complete.
+#[derive(Default)]
+struct ReplyBuffer {
+    data: Vec<u8>,
+}
+
+impl ReplyBuffer {
+    /// Consumes the complete replies, adding the transfers they complete, taken from `inflight`,
+    /// to `completed`.
+    fn take_completed(
+        &mut self,
+        inflight: &Mutex<InflightRequests>,
+        completed: &mut Vec<(UsbipTransfer, TransferStatus)>,
+    ) -> Result<()> {
+        let mut consumed = 0;
+        let result = loop {
+            let rest = &self.data[consumed..];
+            let Some(header) = rest.first_chunk::<HEADER_SIZE>() else {
+                break Ok(());
+            };
+            let reply = match Reply::from_bytes(header) {
+                Ok(reply) => reply,
+                Err(e) => break Err(e),
+            };
+            match reply {
+                Reply::Submit {
+                    seqnum,
+                    status,
+                    actual_length,
+                    number_of_packets,
+                } => {
+                    let mut inflight = inflight.lock();
+                    let payload_len = match inflight.transfers.get(&seqnum) {
+                        Some(transfer) => match reply_payload_len(transfer, actual_length) {
+                            Ok(len) => len,
+                            Err(e) => break Err(e),
+                        },
+                        None => break Err(Error::UnexpectedReply(seqnum)),
+                    };
+                    let Some(payload) = rest[HEADER_SIZE..].get(..payload_len) else {
+                        break Ok(());
+                    };
+                    let mut transfer = inflight.transfers.remove(&seqnum).unwrap();
+                    drop(inflight);
+                    match receive_data(
+                        &mut transfer,
+                        status,
+                        actual_length,
+                        number_of_packets,
+                        payload,
+                    ) {
+                        Ok(status) => completed.push((transfer, status)),
+                        Err(e) => {
+                            completed.push((transfer, TransferStatus::Error));
+                            break Err(e);
+                        }
+                    }
+                    consumed += HEADER_SIZE + payload_len;
+                }
+                Reply::Unlink { seqnum, status } => {
+                    consumed += HEADER_SIZE;
+                    let mut inflight = inflight.lock();
+                    let Some(target) = inflight.unlinks.remove(&seqnum) else {
+                        continue;
+                    };
+                    // A zero status means the URB had already completed, its RET_SUBMIT has been
+                    // handled or is on its way.
+                    if status == 0 {
+                        continue;
+                    }
+                    if let Some(transfer) = inflight.transfers.remove(&target) {
+                        completed.push((transfer, TransferStatus::Cancelled));
+                    }
+                }
+            }
+        };
+        self.data.drain(..consumed);
+        result
+    }
+}
+
+// Returns the direction of `transfer` and the part of its buffer the data stage uses.
+fn transfer_data(transfer: &mut UsbipTransfer) -> Result<(u32, &mut [u8])> {
+    let ep = transfer.endpoint;
+    let buffer = match &mut transfer.buffer {
+        TransferBuffer::Vector(v) => v,
+        TransferBuffer::Dma(_) => return Err(Error::UnsupportedTransferBufferType),
+    };
+    if ep & 0x0f == 0 {
+        Ok((direction_of(buffer[0]), &mut buffer[SETUP_SIZE..]))
+    } else {
+        Ok((direction_of(ep), &mut buffer[..]))
+    }
+}
+
+// Returns the size of the payload following the RET_SUBMIT header of `transfer`: the received data
+// for IN transfers, then the packet descriptors for isochronous transfers.
+fn reply_payload_len(transfer: &UsbipTransfer, actual_length: u32) -> Result<usize> {
+    let data_len = match &transfer.buffer {
+        TransferBuffer::Vector(v) if transfer.endpoint & 0x0f == 0 => v.len() - SETUP_SIZE,
+        TransferBuffer::Vector(v) => v.len(),
+        TransferBuffer::Dma(_) => return Err(Error::UnsupportedTransferBufferType),
+    };
+    if actual_length as usize > data_len {
+        return Err(Error::UnexpectedReply(actual_length));
+    }
+    let direction = match &transfer.buffer {
+        TransferBuffer::Vector(v) if transfer.endpoint & 0x0f == 0 => direction_of(v[0]),
+        _ => direction_of(transfer.endpoint),
+    };
+    let received = if direction == USBIP_DIR_IN {
+        actual_length as usize
+    } else {
+        0
+    };
+    Ok(received + transfer.packet_lengths.len() * ISO_PACKET_DESCRIPTOR_SIZE)
+}
+
+// Copies the payload of a RET_SUBMIT into the buffer of `transfer` and returns its status.
+fn receive_data(
+    transfer: &mut UsbipTransfer,
+    urb_status: i32,
+    actual_length: u32,
+    number_of_packets: u32,
+    payload: &[u8],
+) -> Result<TransferStatus> {
+    let num_packets = transfer.packet_lengths.len();
+    let (direction, data) = transfer_data(transfer)?;
+    let packed = if direction == USBIP_DIR_IN {
+        &payload[..actual_length as usize]
+    } else {
+        &[]
+    };
+
+    let mut status = status_from_urb(urb_status);
+    if num_packets == 0 {
+        data[..packed.len()].copy_from_slice(packed);
+    } else {
+        if number_of_packets as usize != num_packets {
+            return Err(Error::UnexpectedReply(number_of_packets));
+        }
+        let raw = &payload[packed.len()..];
+        // The server only sends the bytes each packet actually received, back to back.
+        let mut consumed = 0;
+        for raw_descriptor in raw.chunks_exact(ISO_PACKET_DESCRIPTOR_SIZE) {
+            let descriptor = IsoPacketDescriptor::from_bytes(raw_descriptor);
+            if descriptor.status != 0 && status == TransferStatus::Completed {
+                status = status_from_urb(descriptor.status);
+            }
+            if direction != USBIP_DIR_IN {
+                continue;
+            }
+            let offset = descriptor.offset as usize;
+            let length = descriptor.actual_length as usize;
+            if offset + length > data.len() || consumed + length > packed.len() {
+                return Err(Error::UnexpectedReply(descriptor.offset));
+            }
+            data[offset..offset + length].copy_from_slice(&packed[consumed..consumed + length]);
+            consumed += length;
+        }
+    }
+    transfer.actual_length = actual_length as usize;
+    Ok(status)
+}
+
+impl AsRawDescriptor for UsbipDevice {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.reader.as_raw_descriptor()
+    }
+}
+
+impl BackendDevice for UsbipDevice {
+    fn submit_backend_transfer(
+        &mut self,
+        transfer: BackendTransferType,
+    ) -> BackendResult<BackendTransferHandle> {
+        let transfer = match transfer {
+            BackendTransferType::UsbipDevice(transfer) => transfer,
+            _ => return Err(BackendError::MalformedBackendTransfer),
+        };
+        if self.disconnected {
+            return Err(BackendError::SubmitUsbipTransfer(Error::WriteSocket(
+                std::io::ErrorKind::NotConnected.into(),
+            )));
+        }
+        let seqnum = self
+            .connection
+            .submit(transfer)
+            .map_err(BackendError::SubmitUsbipTransfer)?;
+        Ok(BackendTransferHandle::new(UsbipTransferHandle {
+            connection: Arc::downgrade(&self.connection),
+            seqnum,
+        }))
+    }
+
+    fn detach_event_handler(&self, event_loop: &Arc<EventLoop>) -> BackendResult<()> {
+        // The event loop already dropped the socket if the connection was lost.
+        if self.disconnected {
+            return Ok(());
+        }
+        event_loop
+            .remove_event_for_descriptor(self)
+            .map_err(BackendError::RemoveFromEventLoop)
+    }
+
+    fn request_transfer_buffer(&mut self, size: usize) -> TransferBuffer {
+        TransferBuffer::Vector(vec![0u8; size])
+    }
+
+    fn build_bulk_transfer(
+        &mut self,
+        ep_addr: u8,
+        transfer_buffer: TransferBuffer,
+        stream_id: Option<u16>,
+    ) -> BackendResult<BackendTransferType> {
+        if stream_id.is_some() {
+            return Err(BackendError::StreamsNotSupported);
+        }
+        Ok(BackendTransferType::UsbipDevice(UsbipTransfer::new(
+            ep_addr,
+            transfer_buffer,
+            0,
+            Vec::new(),
+        )))
+    }
+
+    fn build_interrupt_transfer(
+        &mut self,
+        ep_addr: u8,
+        transfer_buffer: TransferBuffer,
+    ) -> BackendResult<BackendTransferType> {
+        let interval = self.intervals.get(&ep_addr).copied().unwrap_or(1);
+        Ok(BackendTransferType::UsbipDevice(UsbipTransfer::new(
+            ep_addr,
+            transfer_buffer,
+            interval,
+            Vec::new(),
+        )))
+    }
+
+    fn build_isochronous_transfer(
+        &mut self,
+        ep_addr: u8,
+        transfer_buffer: TransferBuffer,
+        packet_lengths: &[u32],
+    ) -> BackendResult<BackendTransferType> {
+        let interval = self.intervals.get(&ep_addr).copied().unwrap_or(1);
+        Ok(BackendTransferType::UsbipDevice(UsbipTransfer::new(
+            ep_addr,
+            transfer_buffer,
+            interval,
+            packet_lengths.to_vec(),
+        )))
+    }
+
+    fn get_control_transfer_state(&mut self) -> Arc<RwLock<ControlTransferState>> {
+        self.control_transfer_state.clone()
+    }
+
+    fn get_device_state(&mut self) -> Arc<RwLock<DeviceState>> {
+        self.state.clone()
+    }
+
+    fn get_active_config_descriptor(&mut self) -> BackendResult<ConfigDescriptorTree> {
+        self.get_config_descriptor(self.active_config)
+    }
+
+    fn get_config_descriptor(&mut self, config: u8) -> BackendResult<ConfigDescriptorTree> {
+        self.descriptors
+            .get_config_descriptor(config)
+            .cloned()
+            .ok_or(BackendError::GetConfigDescriptor(
+                UsbUtilError::NoSuchDescriptor,
+            ))
+    }
+
+    fn get_config_descriptor_by_index(
+        &mut self,
+        config_index: u8,
+    ) -> BackendResult<ConfigDescriptorTree> {
+        self.descriptors
+            .get_config_descriptor_by_index(config_index)
+            .cloned()
+            .ok_or(BackendError::GetConfigDescriptor(
+                UsbUtilError::NoSuchDescriptor,
+            ))
+    }
+
+    fn get_device_descriptor_tree(&mut self) -> BackendResult<DeviceDescriptorTree> {
+        Ok(self.descriptors.clone())
+    }
+
+    fn get_active_configuration(&mut self) -> BackendResult<u8> {
+        Ok(self.active_config)
+    }
+
+    fn set_active_configuration(&mut self, config: u8) -> BackendResult<()> {
+        // Set configuration requests of the guest are forwarded to the server, which reconfigures
+        // the device. This only tracks the configuration once the server completed the request.
+        self.active_config = config;
+        self.alt_settings.clear();
+        Ok(())
+    }
+
+    fn clear_feature(&mut self, _value: u16, _index: u16) -> BackendResult<TransferStatus> {
+        // Clear feature requests of the guest are forwarded to the server, which clears endpoint
+        // halts on its side.
+        Ok(TransferStatus::Completed)
+    }
+
+    fn create_endpoints(&mut self, config_descriptor: &ConfigDescriptorTree) -> BackendResult<()> {
+        let mut endpoints = Vec::new();
+        let mut intervals = HashMap::new();
+        let device_state = self.get_device_state();
+        for i in 0..config_descriptor.num_interfaces() {
+            let alt_setting = self.alt_settings.get(&i).unwrap_or(&0);
+            let interface = config_descriptor
+                .get_interface_descriptor(i, *alt_setting)
+                .ok_or(BackendError::GetInterfaceDescriptor(i, *alt_setting))?;
+            for ep_idx in 0..interface.bNumEndpoints {
+                let ep_dp = interface
+                    .get_endpoint_descriptor(ep_idx)
+                    .ok_or(BackendError::GetEndpointDescriptor(ep_idx))?;
+                let ep_num = ep_dp.get_endpoint_number();
+                if ep_num == 0 {
+                    continue;
+                }
+                let direction = ep_dp.get_direction();
+                let ty = ep_dp
+                    .get_endpoint_type()
+                    .ok_or(BackendError::GetEndpointType)?;
+                if ty == EndpointType::Interrupt || ty == EndpointType::Isochronous {
+                    intervals.insert(
+                        ep_dp.bEndpointAddress,
+                        urb_interval(self.info.device_speed(), ty, ep_dp.bInterval),
+                    );
+                }
+                endpoints.push(UsbEndpoint::new(
+                    device_state.read().unwrap().fail_handle.clone(),
+                    device_state.read().unwrap().job_queue.clone(),
+                    ep_num,
+                    direction,
+                    ty,
+                ));
+            }
+        }
+        self.intervals = intervals;
+        device_state.write().unwrap().endpoints = endpoints;
+        Ok(())
+    }
+}
+
+impl XhciBackendDevice for UsbipDevice {
+    fn get_backend_type(&self) -> BackendType {
+        match self.info.device_speed() {
+            Some(DeviceSpeed::Super) | Some(DeviceSpeed::SuperPlus) => BackendType::Usb3,
+            _ => BackendType::Usb2,
+        }
+    }
+
+    fn get_vid(&self) -> u16 {
+        self.info.vendor_id
+    }
+
+    fn get_pid(&self) -> u16 {
+        self.info.product_id
+    }
+
+    fn set_address(&mut self, address: UsbDeviceAddress) {
+        // The remote device keeps the address assigned by the server's host controller.
+        debug!(
+            "usbip set address control transfer is received with address: {}",
+            address
+        );
+    }
+
+    fn reset(&mut self) -> BackendResult<()> {
+        // The server resets the exported device when it receives a port reset hub request, before
+        // it handles any later request. Whether the reset worked is only known from its reply.
+        let setup = UsbRequestSetup::new(
+            control_request_type(
+                ControlRequestType::Class,
+                ControlRequestDataPhaseTransferDirection::HostToDevice,
+                ControlRequestRecipient::Other,
+            ),
+            HUB_REQ_SET_FEATURE,
+            HUB_PORT_FEAT_RESET,
+            0,
+            0,
+        );
+        let mut transfer = UsbipTransfer::new(
+            0,
+            TransferBuffer::Vector(setup.as_bytes().to_vec()),
+            0,
+            Vec::new(),
+        );
+        transfer.callback = Some(Box::new(|t: UsbipTransfer| {
+            if t.status() != TransferStatus::Completed {
+                error!("usbip server failed to reset the device");
+            }
+        }));
+        self.connection
+            .submit(transfer)
+            .map(|_| ())
+            .map_err(BackendError::SubmitUsbipTransfer)
+    }
+
+    fn get_speed(&self) -> Option<DeviceSpeed> {
+        self.info.device_speed()
+    }
+
+    fn alloc_streams(&self, _ep: u8, _num_streams: u16) -> BackendResult<()> {
+        // The usbip protocol has no notion of bulk streams.
+        Err(BackendError::StreamsNotSupported)
+    }
+
+    fn free_streams(&self, _ep: u8) -> BackendResult<()> {
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // NOOP, nothing to do
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupt_and_isoch_intervals() {
+        assert_eq!(
+            urb_interval(Some(DeviceSpeed::Full), EndpointType::Interrupt, 10),
+            10
+        );
+        assert_eq!(
+            urb_interval(Some(DeviceSpeed::Full), EndpointType::Isochronous, 1),
+            1
+        );
+        assert_eq!(
+            urb_interval(Some(DeviceSpeed::High), EndpointType::Interrupt, 4),
+            8
+        );
+        assert_eq!(
+            urb_interval(Some(DeviceSpeed::Super), EndpointType::Isochronous, 0),
+            1
+        );
+    }
+
+    fn ret_submit(seqnum: u32, actual_length: u32) -> Vec<u8> {
+        let mut b = vec![0u8; HEADER_SIZE];
+        b[0..4].copy_from_slice(&3u32.to_be_bytes());
+        b[4..8].copy_from_slice(&seqnum.to_be_bytes());
+        b[24..28].copy_from_slice(&actual_length.to_be_bytes());
+        b
+    }
+
+    #[test]
+    fn partial_reply_is_buffered() {
+        let inflight = Mutex::new(InflightRequests::default());
+        inflight.lock().transfers.insert(
+            7,
+            UsbipTransfer::new(0x81, TransferBuffer::Vector(vec![0u8; 8]), 0, Vec::new()),
+        );
+        let mut reply = ret_submit(7, 4);
+        reply.extend_from_slice(&[1, 2, 3, 4]);
+
+        let mut replies = ReplyBuffer::default();
+        let mut completed = Vec::new();
+        replies.data.extend_from_slice(&reply[..HEADER_SIZE + 2]);
+        replies.take_completed(&inflight, &mut completed).unwrap();
+        assert!(completed.is_empty());
+        assert!(inflight.lock().transfers.contains_key(&7));
+
+        replies.data.extend_from_slice(&reply[HEADER_SIZE + 2..]);
+        replies.take_completed(&inflight, &mut completed).unwrap();
+        assert!(replies.data.is_empty());
+        assert_eq!(completed.len(), 1);
+        let (transfer, status) = completed.pop().unwrap();
+        assert!(status == TransferStatus::Completed);
+        assert_eq!(transfer.actual_length, 4);
+        match transfer.buffer {
+            TransferBuffer::Vector(v) => assert_eq!(v[..4], [1, 2, 3, 4]),
+            TransferBuffer::Dma(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn control_out_reply_has_no_payload() {
+        let inflight = Mutex::new(InflightRequests::default());
+        let setup = UsbRequestSetup::new(0x00, 0x09, 1, 0, 0);
+        inflight.lock().transfers.insert(
+            1,
+            UsbipTransfer::new(
+                0,
+                TransferBuffer::Vector(setup.as_bytes().to_vec()),
+                0,
+                Vec::new(),
+            ),
+        );
+        // A reply to another request follows in the same read.
+        let mut data = ret_submit(1, 0);
+        data.extend_from_slice(&ret_submit(2, 0)[..10]);
+
+        let mut replies = ReplyBuffer::default();
+        let mut completed = Vec::new();
+        replies.data = data;
+        replies.take_completed(&inflight, &mut completed).unwrap();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(replies.data.len(), 10);
+    }
+
+    #[test]
+    fn oversized_reply_is_rejected() {
+        let inflight = Mutex::new(InflightRequests::default());
+        inflight.lock().transfers.insert(
+            7,
+            UsbipTransfer::new(0x81, TransferBuffer::Vector(vec![0u8; 8]), 0, Vec::new()),
+        );
+        let mut replies = ReplyBuffer::default();
+        let mut completed = Vec::new();
+        replies.data = ret_submit(7, 9);
+        assert!(replies.take_completed(&inflight, &mut completed).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use sync::Mutex;
+
+use crate::usb::backend::device::BackendDeviceType;
+use crate::usb::backend::device::DeviceState;
+use crate::usb::backend::error::Error;
+use crate::usb::backend::error::Result;
+use crate::usb::backend::usbip_backend::usbip_device::UsbipDevice;
+use crate::usb::backend::utils::UsbUtilEventHandler;
+use crate::utils::EventHandler;
+
+/// Utility function to attach a device exported by a usbip server to the backend provider.
+/// `stream` is a connection to the server, over which `busid` gets imported. It returns the
+/// `UsbipDevice` with its `EventHandler` to the backend.
+pub fn attach_usbip_device(
+    stream: TcpStream,
+    busid: &str,
+    device_state: DeviceState,
+) -> Result<(Arc<Mutex<BackendDeviceType>>, Arc<dyn EventHandler>)> {
+    let device =
+        UsbipDevice::new(stream, busid, device_state).map_err(Error::CreateUsbipBackendDevice)?;
+    let arc_mutex_device = Arc::new(Mutex::new(BackendDeviceType::UsbipDevice(device)));
+
+    let event_handler: Arc<dyn EventHandler> = Arc::new(UsbUtilEventHandler {
+        device: arc_mutex_device.clone(),
+    });
+
+    Ok((arc_mutex_device, event_handler))
+}
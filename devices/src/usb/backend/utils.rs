@@ -75,6 +75,12 @@ impl EventHandler for UsbUtilEventHandler {
             BackendDeviceType::FidoDevice(fido_device) => fido_device
                 .read_hidraw_file()
                 .context("FidoDeviceEventHandler failed to read hidraw device"),
+            BackendDeviceType::UsbipDevice(usbip_device) => usbip_device
+                .read_reply()
+                .context("UsbipDeviceEventHandler failed to read usbip reply"),
+            BackendDeviceType::HidGadget(hid_gadget) => hid_gadget
+                .deliver_reports()
+                .context("HidGadgetEventHandler failed to deliver reports"),
         }
     }
 }
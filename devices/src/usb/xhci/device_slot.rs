@@ -34,12 +34,13 @@ use super::xhci_abi::EndpointState;
 use super::xhci_abi::EvaluateContextCommandTrb;
 use super::xhci_abi::InputControlContext;
 use super::xhci_abi::SlotContext;
-use super::xhci_abi::StreamContextArray;
+use super::xhci_abi::StreamContext;
 use super::xhci_abi::TrbCompletionCode;
 use super::xhci_abi::DEVICE_CONTEXT_ENTRY_SIZE;
 use super::xhci_backend_device::XhciBackendDevice;
 use super::xhci_regs::valid_max_pstreams;
 use super::xhci_regs::valid_slot_id;
+use super::xhci_regs::MicroframeIndex;
 use super::xhci_regs::MAX_PORTS;
 use super::xhci_regs::MAX_SLOTS;
 use crate::register_space::Register;
@@ -122,6 +123,7 @@ impl DeviceSlots {
         dcbaap: Register<u64>,
         hub: Arc<UsbHub>,
         interrupter: Arc<Mutex<Interrupter>>,
+        mfindex: MicroframeIndex,
         event_loop: Arc<EventLoop>,
         mem: GuestMemory,
     ) -> DeviceSlots {
@@ -132,6 +134,7 @@ impl DeviceSlots {
                 dcbaap.clone(),
                 hub.clone(),
                 interrupter.clone(),
+                mfindex.clone(),
                 event_loop.clone(),
                 mem.clone(),
             )));
@@ -286,6 +289,7 @@ pub struct DeviceSlot {
     dcbaap: Register<u64>,
     hub: Arc<UsbHub>,
     interrupter: Arc<Mutex<Interrupter>>,
+    mfindex: MicroframeIndex,
     event_loop: Arc<EventLoop>,
     mem: GuestMemory,
     enabled: AtomicBool,
@@ -299,6 +303,7 @@ impl DeviceSlot {
         dcbaap: Register<u64>,
         hub: Arc<UsbHub>,
         interrupter: Arc<Mutex<Interrupter>>,
+        mfindex: MicroframeIndex,
         event_loop: Arc<EventLoop>,
         mem: GuestMemory,
     ) -> Self {
@@ -310,6 +315,7 @@ impl DeviceSlot {
             dcbaap,
             hub,
             interrupter,
+            mfindex,
             event_loop,
             mem,
             enabled: AtomicBool::new(false),
@@ -492,6 +498,8 @@ impl DeviceSlot {
             self.hub.get_port(port_id).ok_or(Error::GetPort(port_id))?,
             self.event_loop.clone(),
             self.interrupter.clone(),
+            self.mfindex.clone(),
+            1,
             self.slot_id,
             1,
             Arc::downgrade(self),
@@ -709,27 +717,16 @@ impl DeviceSlot {
             }
             Some(TransferRingControllers::Stream(trcs)) => {
                 let stream_context_array_addr = endpoint_context.get_tr_dequeue_pointer().get_gpa();
-                let mut stream_context_array: StreamContextArray = self
-                    .mem
-                    .read_obj_from_addr(stream_context_array_addr)
-                    .map_err(Error::ReadGuestMemory)?;
                 let auto_cb = RingBufferStopCallback::new(fallible_closure(
                     fail_handle,
                     move || -> Result<()> {
                         cb(TrbCompletionCode::Success).map_err(|_| Error::CallbackFailed)
                     },
                 ));
-                for (i, trc) in trcs.iter().enumerate() {
-                    let dequeue_pointer = trc.get_dequeue_pointer();
-                    let dcs = trc.get_consumer_cycle_state();
+                for trc in &trcs {
                     trc.stop(auto_cb.clone());
-                    stream_context_array.stream_contexts[i + 1]
-                        .set_tr_dequeue_pointer(DequeuePtr::new(dequeue_pointer));
-                    stream_context_array.stream_contexts[i + 1].set_dequeue_cycle_state(dcs);
                 }
-                self.mem
-                    .write_obj_at_addr(stream_context_array, stream_context_array_addr)
-                    .map_err(Error::WriteGuestMemory)?;
+                self.save_stream_dequeue_pointers(stream_context_array_addr, &trcs)?;
             }
             None => {
                 error!("endpoint at index {} is not started", index);
@@ -781,27 +778,16 @@ impl DeviceSlot {
             }
             Some(TransferRingControllers::Stream(trcs)) => {
                 let stream_context_array_addr = endpoint_context.get_tr_dequeue_pointer().get_gpa();
-                let mut stream_context_array: StreamContextArray = self
-                    .mem
-                    .read_obj_from_addr(stream_context_array_addr)
-                    .map_err(Error::ReadGuestMemory)?;
                 let auto_cb = RingBufferStopCallback::new(fallible_closure(
                     fail_handle,
                     move || -> Result<()> {
                         cb(TrbCompletionCode::Success).map_err(|_| Error::CallbackFailed)
                     },
                 ));
-                for (i, trc) in trcs.iter().enumerate() {
-                    let dequeue_pointer = trc.get_dequeue_pointer();
-                    let dcs = trc.get_consumer_cycle_state();
+                for trc in &trcs {
                     trc.stop(auto_cb.clone());
-                    stream_context_array.stream_contexts[i + 1]
-                        .set_tr_dequeue_pointer(DequeuePtr::new(dequeue_pointer));
-                    stream_context_array.stream_contexts[i + 1].set_dequeue_cycle_state(dcs);
                 }
-                self.mem
-                    .write_obj_at_addr(stream_context_array, stream_context_array_addr)
-                    .map_err(Error::WriteGuestMemory)?;
+                self.save_stream_dequeue_pointers(stream_context_array_addr, &trcs)?;
             }
             None => {
                 error!("endpoint at index {} is not started", index);
@@ -853,6 +839,50 @@ impl DeviceSlot {
         self.port_id.reset();
     }
 
+    fn stream_context_addr(
+        stream_context_array_addr: GuestAddress,
+        stream_id: usize,
+    ) -> GuestAddress {
+        stream_context_array_addr.unchecked_add((stream_id * size_of::<StreamContext>()) as u64)
+    }
+
+    fn read_stream_context(
+        &self,
+        stream_context_array_addr: GuestAddress,
+        stream_id: usize,
+    ) -> Result<StreamContext> {
+        self.mem
+            .read_obj_from_addr(Self::stream_context_addr(
+                stream_context_array_addr,
+                stream_id,
+            ))
+            .map_err(Error::ReadGuestMemory)
+    }
+
+    // Saves the dequeue state of the stream rings in their stream contexts. The stream context
+    // array is as large as the guest allocated it, so each context is accessed on its own.
+    fn save_stream_dequeue_pointers(
+        &self,
+        stream_context_array_addr: GuestAddress,
+        trcs: &[Arc<TransferRingController>],
+    ) -> Result<()> {
+        for (i, trc) in trcs.iter().enumerate() {
+            // Stream ID 0 is reserved, so the first ring belongs to stream 1.
+            let stream_id = i + 1;
+            let mut stream_context =
+                self.read_stream_context(stream_context_array_addr, stream_id)?;
+            stream_context.set_tr_dequeue_pointer(DequeuePtr::new(trc.get_dequeue_pointer()));
+            stream_context.set_dequeue_cycle_state(trc.get_consumer_cycle_state());
+            self.mem
+                .write_obj_at_addr(
+                    stream_context,
+                    Self::stream_context_addr(stream_context_array_addr, stream_id),
+                )
+                .map_err(Error::WriteGuestMemory)?;
+        }
+        Ok(())
+    }
+
     fn create_stream_trcs(
         self: &Arc<Self>,
         stream_context_array_addr: GuestAddress,
@@ -860,15 +890,11 @@ impl DeviceSlot {
         device_context_index: u8,
     ) -> Result<TransferRingControllers> {
         let pstreams = 1usize << (max_pstreams + 1);
-        let stream_context_array: StreamContextArray = self
-            .mem
-            .read_obj_from_addr(stream_context_array_addr)
-            .map_err(Error::ReadGuestMemory)?;
         let mut trcs = Vec::new();
 
         // Stream ID 0 is reserved (xHCI spec Section 4.12.2)
         for i in 1..pstreams {
-            let stream_context = &stream_context_array.stream_contexts[i];
+            let stream_context = self.read_stream_context(stream_context_array_addr, i)?;
             let context_type = stream_context.get_stream_context_type();
             if context_type != 1 {
                 // We only support Linear Stream Context Array for now
@@ -881,6 +907,8 @@ impl DeviceSlot {
                     .ok_or(Error::GetPort(self.port_id.get()?))?,
                 self.event_loop.clone(),
                 self.interrupter.clone(),
+                self.mfindex.clone(),
+                1,
                 self.slot_id,
                 device_context_index,
                 Arc::downgrade(self),
@@ -941,6 +969,14 @@ impl DeviceSlot {
             }
             trcs
         } else {
+            let endpoint_type = endpoint_context.get_endpoint_type();
+            // Queue as many bulk TDs as the device can burst packets, so that the host controller
+            // does not wait for the guest between TDs.
+            let max_in_flight = if endpoint_type == 2 || endpoint_type == 6 {
+                endpoint_context.get_max_burst_size() as usize + 1
+            } else {
+                1
+            };
             let trc = TransferRingController::new(
                 self.mem.clone(),
                 self.hub
@@ -948,6 +984,8 @@ impl DeviceSlot {
                     .ok_or(Error::GetPort(self.port_id.get()?))?,
                 self.event_loop.clone(),
                 self.interrupter.clone(),
+                self.mfindex.clone(),
+                max_in_flight,
                 self.slot_id,
                 device_context_index,
                 Arc::downgrade(self),
@@ -1062,19 +1100,7 @@ impl DeviceSlot {
                 TransferRingControllers::Stream(trcs) => {
                     let stream_context_array_addr =
                         endpoint_context.get_tr_dequeue_pointer().get_gpa();
-                    let mut stream_context_array: StreamContextArray = self
-                        .mem
-                        .read_obj_from_addr(stream_context_array_addr)
-                        .map_err(Error::ReadGuestMemory)?;
-                    for (i, trc) in trcs.iter().enumerate() {
-                        stream_context_array.stream_contexts[i + 1]
-                            .set_tr_dequeue_pointer(DequeuePtr::new(trc.get_dequeue_pointer()));
-                        stream_context_array.stream_contexts[i + 1]
-                            .set_dequeue_cycle_state(trc.get_consumer_cycle_state());
-                    }
-                    self.mem
-                        .write_obj_at_addr(stream_context_array, stream_context_array_addr)
-                        .map_err(Error::WriteGuestMemory)?;
+                    self.save_stream_dequeue_pointers(stream_context_array_addr, &trcs)?;
                 }
             },
             None => {
@@ -141,6 +141,7 @@ impl Xhci {
             regs.dcbaap.clone(),
             hub,
             interrupter.clone(),
+            regs.mfindex.clone(),
             event_loop.clone(),
             mem.clone(),
         );
@@ -266,8 +267,10 @@ impl Xhci {
         if (value & USB_CMD_RUNSTOP) > 0 {
             debug!("xhci_controller: clear halt bits");
             self.regs.usbsts.clear_bits(USB_STS_HALTED);
+            self.regs.mfindex.start();
         } else {
             debug!("xhci_controller: halt device");
+            self.regs.mfindex.stop();
             self.halt();
             self.regs.crcr.clear_bits(CRCR_COMMAND_RING_RUNNING);
         }
@@ -303,13 +306,38 @@ impl Xhci {
         let _trace = cros_tracing::trace_event!(USB, "portsc_callback", index, value);
         let mut value = value;
         let port_id = (index + 1) as u8;
-        // xHCI spec 4.19.5. Note: we might want to change this logic if we support USB 3.0.
+        let old_link_state =
+            self.regs.portsc[index as usize].get_value() & PORTSC_PORT_LINK_STATE_MASK;
+        // The port link state only changes when written together with the strobe, see xHCI spec
+        // 5.4.8.
+        if (value & PORTSC_LINK_STATE_WRITE_STROBE) > 0 {
+            value &= !PORTSC_LINK_STATE_WRITE_STROBE;
+            let link_state = (value & PORTSC_PORT_LINK_STATE_MASK) >> PORTSC_PORT_LINK_STATE_SHIFT;
+            let resume = link_state == PORT_LINK_STATE_U0 || link_state == PORT_LINK_STATE_RESUME;
+            if resume && old_link_state == PORT_LINK_STATE_U3 << PORTSC_PORT_LINK_STATE_SHIFT {
+                // There is no link to train, so a suspended port is back in U0 right away.
+                value &= !PORTSC_PORT_LINK_STATE_MASK;
+                value |= PORT_LINK_STATE_U0 << PORTSC_PORT_LINK_STATE_SHIFT;
+                value |= PORTSC_PORT_LINK_STATE_CHANGE;
+                self.interrupter
+                    .lock()
+                    .send_port_status_change_trb(port_id)
+                    .map_err(Error::SendInterrupt)?;
+            }
+        } else {
+            value = (value & !PORTSC_PORT_LINK_STATE_MASK) | old_link_state;
+        }
+        // xHCI spec 4.19.5.
         if (value & PORTSC_PORT_RESET) > 0 || (value & PORTSC_WARM_PORT_RESET) > 0 {
             self.device_slots
                 .reset_port(port_id)
                 .map_err(|_| Error::ResetPort)?;
             value &= !PORTSC_PORT_LINK_STATE_MASK;
-            value &= !PORTSC_PORT_RESET;
+            if (value & PORTSC_WARM_PORT_RESET) > 0 {
+                // Warm resets only exist on USB3 ports.
+                value |= PORTSC_WARM_PORT_RESET_CHANGE;
+            }
+            value &= !(PORTSC_PORT_RESET | PORTSC_WARM_PORT_RESET);
             value |= PORTSC_PORT_ENABLED;
             value |= PORTSC_PORT_RESET_CHANGE;
             self.interrupter
@@ -401,6 +429,7 @@ impl Xhci {
 
     fn reset(&self) {
         self.regs.usbsts.set_bits(USB_STS_CONTROLLER_NOT_READY);
+        self.regs.mfindex.reset();
         let usbsts = self.regs.usbsts.clone();
         self.device_slots.stop_all_and_reset(move || {
             usbsts.clear_bits(USB_STS_CONTROLLER_NOT_READY);
@@ -14,6 +14,7 @@ use super::device_slot::DeviceSlot;
 use super::interrupter::Interrupter;
 use super::usb_hub::UsbPort;
 use super::xhci_abi::TransferDescriptor;
+use super::xhci_regs::MicroframeIndex;
 use super::xhci_transfer::XhciTransferManager;
 use crate::usb::xhci::ring_buffer_controller::Error as RingBufferControllerError;
 use crate::usb::xhci::ring_buffer_controller::RingBufferController;
@@ -80,6 +81,8 @@ impl TransferRingController {
         port: Arc<UsbPort>,
         event_loop: Arc<EventLoop>,
         interrupter: Arc<Mutex<Interrupter>>,
+        mfindex: MicroframeIndex,
+        max_in_flight: usize,
         slot_id: u8,
         endpoint_id: u8,
         device_slot: Weak<DeviceSlot>,
@@ -95,7 +98,7 @@ impl TransferRingController {
                 interrupter,
                 slot_id,
                 endpoint_id,
-                transfer_manager: XhciTransferManager::new(device_slot),
+                transfer_manager: XhciTransferManager::new(device_slot, mfindex, max_in_flight),
                 stream_id,
             },
         )
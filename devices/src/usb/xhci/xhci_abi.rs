@@ -74,6 +74,7 @@ pub enum TrbCompletionCode {
     SlotNotEnabledError = 11,
     ShortPacket = 13,
     ContextStateError = 19,
+    MissedServiceError = 23,
 }
 
 /// State of device slot.
@@ -872,12 +873,6 @@ pub struct StreamContext {
     reserved2: B32,
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug, FromBytes, Immutable, IntoBytes, KnownLayout)]
-pub struct StreamContextArray {
-    pub stream_contexts: [StreamContext; 16],
-}
-
 /// Device context.
 #[repr(C)]
 #[derive(Clone, Copy, Debug, FromBytes, Immutable, IntoBytes, KnownLayout)]
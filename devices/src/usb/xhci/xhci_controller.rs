@@ -240,6 +240,7 @@ impl PciDevice for XhciController {
             .allocate_mmio(
                 XHCI_BAR0_SIZE,
                 Alloc::PciBar {
+                    domain: address.domain,
                     bus: address.bus,
                     dev: address.dev,
                     func: address.func,
@@ -2,7 +2,17 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use sync::Mutex;
+
+use crate::register_space::read_reg_helper;
 use crate::register_space::Register;
+use crate::register_space::RegisterInterface;
+use crate::register_space::RegisterOffset;
+use crate::register_space::RegisterRange;
 use crate::register_space::RegisterSpace;
 
 /// Max interrupter number.
@@ -67,6 +77,8 @@ pub const PORTSC_PORT_ENABLED: u32 = 1u32 << 1;
 pub const PORTSC_PORT_RESET: u32 = 1u32 << 4;
 /// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_PORT_LINK_STATE_MASK: u32 = 0x000001E0;
+/// Offset of the port link state in portsc register, see spec 5.4.8.
+pub const PORTSC_PORT_LINK_STATE_SHIFT: u32 = 5;
 /// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_PORT_POWER: u32 = 1u32 << 9;
 /// Bitmask for portsc register, see spec 5.4.8.
@@ -74,16 +86,29 @@ pub const PORTSC_PORT_SPEED_MASK: u32 = 0x00003C00;
 /// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_PORT_SPEED_SHIFT: u32 = 10;
 /// Bitmask for portsc register, see spec 5.4.8.
+pub const PORTSC_LINK_STATE_WRITE_STROBE: u32 = 1u32 << 16;
+/// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_CONNECT_STATUS_CHANGE: u32 = 1u32 << 17;
 /// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_PORT_ENABLED_DISABLED_CHANGE: u32 = 1u32 << 18;
 /// Bitmask for portsc register, see spec 5.4.8.
+pub const PORTSC_WARM_PORT_RESET_CHANGE: u32 = 1u32 << 19;
+/// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_PORT_RESET_CHANGE: u32 = 1u32 << 21;
 /// Bitmask for portsc register, see spec 5.4.8.
+pub const PORTSC_PORT_LINK_STATE_CHANGE: u32 = 1u32 << 22;
+/// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_WARM_PORT_RESET: u32 = 1u32 << 31;
 /// Bitmask for portsc register, see spec 5.4.8.
 pub const PORTSC_SET_TO_CLEAR_MASK: u32 = 0x00FE0002;
 
+/// Port link state U0, the normal operational state. See spec table 5-27.
+pub const PORT_LINK_STATE_U0: u32 = 0;
+/// Port link state U3, suspended.
+pub const PORT_LINK_STATE_U3: u32 = 3;
+/// Port link state written by the guest to resume a suspended USB2 port.
+pub const PORT_LINK_STATE_RESUME: u32 = 15;
+
 /// Bitmask for iman registers, see spec 5.5.2.1.
 pub const IMAN_INTERRUPT_PENDING: u32 = 1u32 << 0;
 /// Bitmask for iman registers, see spec 5.5.2.1.
@@ -126,10 +151,17 @@ pub const SPCAP_PORT_COUNT_MASK: u32 = 0xFF00;
 /// Offset of port count.
 pub const SPCAP_PORT_COUNT_OFFSET: u32 = 8;
 
+/// Bitmask for mfindex register, see spec 5.5.1.
+pub const MFINDEX_MICROFRAME_INDEX_MASK: u32 = 0x3FFF;
+/// Duration of a microframe.
+pub const MICROFRAME_DURATION: Duration = Duration::from_micros(125);
+/// Number of bits of the microframe index that are below the frame index.
+pub const MICROFRAMES_PER_FRAME_SHIFT: u32 = 3;
+
 /// Bitmask for hccparams1 register, see spec 5.3.6.
 pub const HCCPARAMS1_MAX_PSA_SIZE_OFFSET: u32 = 12;
-/// Maximum primary stream array size, support up to 16 (2^(MAX_PSA_SIZE+1)) streams
-pub const MAX_PSA_SIZE: u32 = 3;
+/// Maximum primary stream array size, support up to 64 (2^(MAX_PSA_SIZE+1)) streams
+pub const MAX_PSA_SIZE: u32 = 5;
 
 /// Helper function for validating slot_id.
 pub fn valid_slot_id(slot_id: u8) -> bool {
@@ -148,6 +180,95 @@ pub fn valid_stream_id(stream_id: u16) -> bool {
     stream_id > 0 && stream_id < (1 << (MAX_PSA_SIZE + 1))
 }
 
+struct MicroframeIndexState {
+    /// Microframes counted before the controller was last started.
+    base: u64,
+    /// When the controller was last started, or `None` while it is halted.
+    running_since: Option<Instant>,
+}
+
+/// The microframe index register. It counts the 125us microframes elapsed while the controller
+/// is running, which guest drivers use to schedule isochronous transfers. See spec 5.5.1.
+#[derive(Clone)]
+pub struct MicroframeIndex {
+    offset: RegisterOffset,
+    state: Arc<Mutex<MicroframeIndexState>>,
+}
+
+impl MicroframeIndex {
+    fn new(offset: RegisterOffset) -> Self {
+        MicroframeIndex {
+            offset,
+            state: Arc::new(Mutex::new(MicroframeIndexState {
+                base: 0,
+                running_since: None,
+            })),
+        }
+    }
+
+    /// Starts counting microframes, when the controller is set to run.
+    pub fn start(&self) {
+        let mut state = self.state.lock();
+        if state.running_since.is_none() {
+            state.running_since = Some(Instant::now());
+        }
+    }
+
+    /// Stops counting microframes, when the controller is halted.
+    pub fn stop(&self) {
+        let mut state = self.state.lock();
+        if let Some(since) = state.running_since.take() {
+            state.base += Self::elapsed_microframes(since);
+        }
+    }
+
+    /// Stops counting and resets the index to 0, when the controller is reset.
+    pub fn reset(&self) {
+        let mut state = self.state.lock();
+        state.base = 0;
+        state.running_since = None;
+    }
+
+    fn elapsed_microframes(since: Instant) -> u64 {
+        (since.elapsed().as_micros() / MICROFRAME_DURATION.as_micros()) as u64
+    }
+
+    /// Current value of the register.
+    pub fn microframe(&self) -> u32 {
+        let state = self.state.lock();
+        let microframes = state.base + state.running_since.map_or(0, Self::elapsed_microframes);
+        microframes as u32 & MFINDEX_MICROFRAME_INDEX_MASK
+    }
+
+    /// Index of the current 1ms frame, as used by the Frame ID field of isoch TRBs.
+    pub fn frame(&self) -> u16 {
+        (self.microframe() >> MICROFRAMES_PER_FRAME_SHIFT) as u16
+    }
+}
+
+impl Default for MicroframeIndex {
+    fn default() -> Self {
+        Self::new(XHCI_RTSOFF as RegisterOffset)
+    }
+}
+
+impl RegisterInterface for MicroframeIndex {
+    fn range(&self) -> RegisterRange {
+        RegisterRange {
+            from: self.offset,
+            to: self.offset + 3,
+        }
+    }
+
+    fn read(&self, addr: RegisterOffset, data: &mut [u8]) {
+        read_reg_helper(self.microframe(), self.range(), addr, data);
+    }
+
+    fn reset(&self) {
+        MicroframeIndex::reset(self);
+    }
+}
+
 /// XhciRegs hold all xhci registers.
 pub struct XhciRegs {
     pub usbcmd: Register<u32>,
@@ -158,6 +279,7 @@ pub struct XhciRegs {
     pub config: Register<u64>,
     pub portsc: Vec<Register<u32>>,
     pub doorbells: Vec<Register<u32>>,
+    pub mfindex: MicroframeIndex,
     pub iman: Register<u32>,
     pub imod: Register<u32>,
     pub erstsz: Register<u32>,
@@ -227,7 +349,7 @@ pub fn init_xhci_mmio_space_and_regs() -> (RegisterSpace, XhciRegs) {
         ty: u32,
         offset: 0x10,
         // Supports 64 bit addressing
-        // Max primary stream array size = 3 (support up to 16 streams).
+        // Max primary stream array size = 5 (support up to 64 streams).
         // Extended capabilities pointer = 0xC000 offset from base.
         value: 0x30000501 | (MAX_PSA_SIZE << HCCPARAMS1_MAX_PSA_SIZE_OFFSET),
         ),
@@ -387,14 +509,8 @@ pub fn init_xhci_mmio_space_and_regs() -> (RegisterSpace, XhciRegs) {
 
     /* Runtime Registers */
 
-    mmio.add_register(
-        // mfindex
-        static_register!(
-        ty: u32,
-        offset: 0x3000,
-        value: 0, // 4 ports starting at port 5
-        ),
-    );
+    let mfindex = MicroframeIndex::new(XHCI_RTSOFF as RegisterOffset);
+    mmio.add_register(mfindex.clone());
 
     /* Reg Array for interrupters */
     // Although the following should be register arrays, we only have one interrupter.
@@ -454,6 +570,7 @@ pub fn init_xhci_mmio_space_and_regs() -> (RegisterSpace, XhciRegs) {
         config,
         portsc,
         doorbells,
+        mfindex,
         iman,
         imod,
         erstsz,
@@ -515,8 +632,8 @@ pub fn init_xhci_mmio_space_and_regs() -> (RegisterSpace, XhciRegs) {
         offset: 0xc100,
         // "Supported Protocol" capability.
         // Not next capability.
-        // USB 3.0. Revision 2.0.
-        value: 0x03000002,
+        // USB 3.1, so that the default speed IDs include SuperSpeedPlus. Revision 2.0.
+        value: 0x03100002,
         ),
     );
     mmio.add_register(
@@ -551,3 +668,36 @@ pub fn init_xhci_mmio_space_and_regs() -> (RegisterSpace, XhciRegs) {
 
     (mmio, xhci_regs)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    #[test]
+    fn mfindex_counts_while_running() {
+        let mfindex = MicroframeIndex::new(0x3000);
+        assert_eq!(mfindex.microframe(), 0);
+
+        mfindex.start();
+        sleep(Duration::from_millis(2));
+        mfindex.stop();
+        let stopped = mfindex.microframe();
+        assert!(stopped >= 16);
+        assert_eq!(mfindex.frame() as u32, stopped >> 3);
+
+        // The index does not move while the controller is halted.
+        sleep(Duration::from_millis(1));
+        let mut data = [0u8; 4];
+        mfindex.read(0x3000, &mut data);
+        assert_eq!(u32::from_le_bytes(data), stopped);
+
+        mfindex.start();
+        sleep(Duration::from_millis(1));
+        assert!(mfindex.microframe() >= stopped + 8);
+
+        mfindex.reset();
+        assert_eq!(mfindex.microframe(), 0);
+    }
+}
@@ -34,11 +34,13 @@ use super::usb_hub::UsbPort;
 use super::xhci_abi::AddressedTrb;
 use super::xhci_abi::Error as TrbError;
 use super::xhci_abi::EventDataTrb;
+use super::xhci_abi::IsochTrb;
 use super::xhci_abi::SetupStageTrb;
 use super::xhci_abi::TransferDescriptor;
 use super::xhci_abi::TrbCast;
 use super::xhci_abi::TrbCompletionCode;
 use super::xhci_abi::TrbType;
+use super::xhci_regs::MicroframeIndex;
 use super::xhci_regs::MAX_INTERRUPTER;
 
 #[sorted]
@@ -72,6 +74,16 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Mask of the Frame ID field of isoch TRBs, see spec 6.4.1.3.
+const FRAME_ID_MASK: u16 = 0x7FF;
+/// How many frames ahead of the current frame an isoch TD may be scheduled.
+const ISOCH_MAX_FRAMES_AHEAD: u16 = 895;
+
+/// Returns true if an isoch TD scheduled for `frame_id` can still be serviced in `current_frame`.
+fn isoch_frame_in_window(frame_id: u16, current_frame: u16) -> bool {
+    (frame_id.wrapping_sub(current_frame) & FRAME_ID_MASK) <= ISOCH_MAX_FRAMES_AHEAD
+}
+
 /// Type of usb endpoints.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum TransferDirection {
@@ -148,17 +160,31 @@ impl Display for XhciTransferType {
 pub struct XhciTransferManager {
     transfers: Arc<Mutex<Vec<Weak<Mutex<XhciTransferState>>>>>,
     device_slot: Weak<DeviceSlot>,
+    mfindex: MicroframeIndex,
+    // How many non-isochronous transfers may be in flight on the ring at the same time.
+    max_in_flight: usize,
 }
 
 impl XhciTransferManager {
     /// Create a new manager.
-    pub fn new(device_slot: Weak<DeviceSlot>) -> XhciTransferManager {
+    pub fn new(
+        device_slot: Weak<DeviceSlot>,
+        mfindex: MicroframeIndex,
+        max_in_flight: usize,
+    ) -> XhciTransferManager {
         XhciTransferManager {
             transfers: Arc::new(Mutex::new(Vec::new())),
             device_slot,
+            mfindex,
+            max_in_flight,
         }
     }
 
+    // Returns true if another transfer may be started before the ones in flight complete.
+    fn can_pipeline(&self) -> bool {
+        self.transfers.lock().len() < self.max_in_flight
+    }
+
     /// Build a new XhciTransfer. Endpoint id is the id in xHCI device slot.
     pub fn create_transfer(
         &self,
@@ -181,6 +207,7 @@ impl XhciTransferManager {
                 TransferDirection::In
             }
         };
+        let isochronous = matches!(transfer_trbs[0].trb.get_trb_type(), Ok(TrbType::Isoch));
         let t = XhciTransfer {
             manager: self.clone(),
             state: Arc::new(Mutex::new(XhciTransferState::Created)),
@@ -194,6 +221,9 @@ impl XhciTransferManager {
             transfer_trbs,
             device_slot: self.device_slot.clone(),
             stream_id,
+            isochronous,
+            ring_released: false,
+            mfindex: self.mfindex.clone(),
         };
         self.transfers.lock().push(Arc::downgrade(&t.state));
         t
@@ -229,7 +259,7 @@ impl XhciTransferManager {
 
 impl Default for XhciTransferManager {
     fn default() -> Self {
-        Self::new(Weak::new())
+        Self::new(Weak::new(), MicroframeIndex::default(), 1)
     }
 }
 
@@ -249,6 +279,10 @@ pub struct XhciTransfer {
     transfer_completion_event: Event,
     device_slot: Weak<DeviceSlot>,
     stream_id: Option<u16>,
+    isochronous: bool,
+    // Whether the transfer ring was allowed to move on before this transfer completed.
+    ring_released: bool,
+    mfindex: MicroframeIndex,
 }
 
 impl Drop for XhciTransfer {
@@ -351,15 +385,10 @@ impl XhciTransfer {
             TransferStatus::Cancelled => {
                 // TODO(jkwang) According to the spec, we should send a stopped event here. But
                 // kernel driver does not do anything meaningful when it sees a stopped event.
-                return self
-                    .transfer_completion_event
-                    .signal()
-                    .map_err(Error::WriteCompletionEvent);
+                return self.signal_completion_event();
             }
             TransferStatus::Completed => {
-                self.transfer_completion_event
-                    .signal()
-                    .map_err(Error::WriteCompletionEvent)?;
+                self.signal_completion_event()?;
             }
             TransferStatus::Stalled => {
                 warn!("xhci: endpoint is stalled. set state to Halted");
@@ -368,17 +397,13 @@ impl XhciTransfer {
                         .halt_endpoint(self.endpoint_id)
                         .map_err(|_| Error::HaltEndpoint(self.endpoint_id))?;
                 }
-                self.transfer_completion_event
-                    .signal()
-                    .map_err(Error::WriteCompletionEvent)?;
+                self.signal_completion_event()?;
             }
             _ => {
                 // Transfer failed, we are not handling this correctly yet. Guest kernel might see
                 // short packets for in transfer and might think control transfer is successful. It
                 // will eventually find out device is in a wrong state.
-                self.transfer_completion_event
-                    .signal()
-                    .map_err(Error::WriteCompletionEvent)?;
+                self.signal_completion_event()?;
             }
         }
 
@@ -461,9 +486,72 @@ impl XhciTransfer {
         Ok(())
     }
 
+    // Lets the transfer ring move on to the next TD.
+    fn signal_completion_event(&self) -> Result<()> {
+        if self.ring_released {
+            // Already signaled when the transfer was sent to the backend.
+            return Ok(());
+        }
+        self.transfer_completion_event
+            .signal()
+            .map_err(Error::WriteCompletionEvent)
+    }
+
+    // Returns true if this is an isoch TD scheduled for a frame that can no longer be serviced.
+    fn isoch_frame_missed(&self) -> Result<bool> {
+        if !self.isochronous {
+            return Ok(false);
+        }
+        let trb = self.transfer_trbs[0]
+            .trb
+            .checked_cast::<IsochTrb>()
+            .map_err(Error::CastTrb)?;
+        // Start Isoch ASAP TDs are serviced in the next available frame.
+        if trb.get_sia() == 1 {
+            return Ok(false);
+        }
+        Ok(!isoch_frame_in_window(
+            trb.get_frame_id(),
+            self.mfindex.frame(),
+        ))
+    }
+
+    // Reports that the TD was skipped because its frame has passed.
+    fn send_missed_service_event(&self) -> Result<()> {
+        let mut transfer_length = 0;
+        for atrb in &self.transfer_trbs {
+            transfer_length += atrb.trb.transfer_length().map_err(Error::TransferLength)?;
+        }
+        self.interrupter
+            .lock()
+            .send_transfer_event_trb(
+                TrbCompletionCode::MissedServiceError,
+                self.transfer_trbs[0].gpa,
+                transfer_length,
+                false,
+                self.slot_id,
+                self.endpoint_id,
+            )
+            .map_err(Error::SendInterrupt)
+    }
+
     /// Send this transfer to backend if it's a valid transfer.
-    pub fn send_to_backend_if_valid(self) -> Result<()> {
+    pub fn send_to_backend_if_valid(mut self) -> Result<()> {
+        // Isoch TDs are queued ahead of the frames they are scheduled for, so the next TD is
+        // fetched right away. The host controller paces the transfers, one per service interval
+        // of the endpoint. Endpoints that burst also get several TDs queued, so that the host
+        // controller can keep the bus busy between TDs.
+        if self.isochronous || self.manager.can_pipeline() {
+            self.ring_released = true;
+            self.transfer_completion_event
+                .signal()
+                .map_err(Error::WriteCompletionEvent)?;
+        }
         if self.validate_transfer()? {
+            if self.isoch_frame_missed()? {
+                debug!("xhci: isoch td missed its frame");
+                return self.send_missed_service_event();
+            }
             // Backend should invoke on transfer complete when transfer is completed.
             let port = self.port.clone();
             let mut backend = port.backend_device();
@@ -474,16 +562,12 @@ impl XhciTransfer {
                     .map_err(|_| Error::SubmitTransfer)?,
                 None => {
                     error!("backend is already disconnected");
-                    self.transfer_completion_event
-                        .signal()
-                        .map_err(Error::WriteCompletionEvent)?;
+                    self.signal_completion_event()?;
                 }
             }
         } else {
             error!("invalid td on transfer ring");
-            self.transfer_completion_event
-                .signal()
-                .map_err(Error::WriteCompletionEvent)?;
+            self.signal_completion_event()?;
         }
         Ok(())
     }
@@ -522,3 +606,20 @@ fn trb_is_valid(atrb: &AddressedTrb) -> bool {
     };
     can_be_in_transfer_ring && (atrb.trb.interrupter_target() < MAX_INTERRUPTER)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isoch_frame_window() {
+        assert!(isoch_frame_in_window(10, 10));
+        assert!(isoch_frame_in_window(10 + ISOCH_MAX_FRAMES_AHEAD, 10));
+        assert!(!isoch_frame_in_window(11 + ISOCH_MAX_FRAMES_AHEAD, 10));
+        // Frames that have already passed.
+        assert!(!isoch_frame_in_window(9, 10));
+        // The frame index wraps around.
+        assert!(isoch_frame_in_window(2, FRAME_ID_MASK - 1));
+        assert!(!isoch_frame_in_window(FRAME_ID_MASK, 0));
+    }
+}
@@ -7,6 +7,8 @@ use std::ffi::CString;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
+use std::io::Read;
+use std::io::Write;
 use std::mem;
 use std::os::raw::c_ulong;
 use std::os::unix::prelude::FileExt;
@@ -46,7 +48,17 @@ use resources::Error as ResourcesError;
 use sync::Mutex;
 use thiserror::Error;
 use vfio_sys::vfio::vfio_acpi_dsm;
+use vfio_sys::vfio::vfio_device_feature_mig_state;
+use vfio_sys::vfio::vfio_device_mig_state;
+use vfio_sys::vfio::vfio_device_mig_state_VFIO_DEVICE_STATE_RESUMING;
+use vfio_sys::vfio::vfio_device_mig_state_VFIO_DEVICE_STATE_RUNNING;
+use vfio_sys::vfio::vfio_device_mig_state_VFIO_DEVICE_STATE_STOP;
+use vfio_sys::vfio::vfio_device_mig_state_VFIO_DEVICE_STATE_STOP_COPY;
+use vfio_sys::vfio::VFIO_DEVICE_FEATURE_GET;
+use vfio_sys::vfio::VFIO_DEVICE_FEATURE_MIGRATION;
+use vfio_sys::vfio::VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE;
 use vfio_sys::vfio::VFIO_IRQ_SET_DATA_BOOL;
+use vfio_sys::vfio::VFIO_MIGRATION_STOP_COPY;
 use vfio_sys::*;
 use zerocopy::FromBytes;
 use zerocopy::Immutable;
@@ -127,6 +139,10 @@ pub enum VfioError {
     VfioIrqMask(Error),
     #[error("failed to unmask vfio deviece's irq: {0}")]
     VfioIrqUnmask(Error),
+    #[error("failed to transfer vfio device's migration data: {0}")]
+    VfioMigrationData(io::Error),
+    #[error("failed to set vfio device's migration state: {0}")]
+    VfioMigrationState(Error),
     #[error("failed to enter vfio deviece's low power state: {0}")]
     VfioPmLowPowerEnter(Error),
     #[error("failed to exit vfio deviece's low power state: {0}")]
@@ -470,6 +486,32 @@ impl VfioContainer {
         Ok(())
     }
 
+    /// Remove all the maps of the vfio container iommu table
+    pub fn vfio_dma_unmap_all(&self) -> Result<()> {
+        match self
+            .iommu_type
+            .expect("vfio_dma_unmap_all called before configuring IOMMU")
+        {
+            IommuType::Type1V2 | IommuType::Type1ChromeOS => {
+                let mut dma_unmap = vfio_iommu_type1_dma_unmap {
+                    argsz: mem::size_of::<vfio_iommu_type1_dma_unmap>() as u32,
+                    flags: VFIO_DMA_UNMAP_FLAG_ALL,
+                    ..Default::default()
+                };
+
+                // SAFETY:
+                // Safe as file is vfio container, dma_unmap is constructed by us, and
+                // we check the return value
+                let ret = unsafe { ioctl_with_mut_ref(self, VFIO_IOMMU_UNMAP_DMA, &mut dma_unmap) };
+                if ret != 0 {
+                    return Err(VfioError::IommuDmaUnmap(get_error()));
+                }
+                Ok(())
+            }
+            IommuType::PkvmPviommu => Err(VfioError::InvalidOperation),
+        }
+    }
+
     pub fn vfio_get_iommu_page_size_mask(&self) -> Result<u64> {
         match self
             .iommu_type
@@ -713,6 +755,11 @@ impl VfioContainer {
     pub fn group_ids(&self) -> Vec<&u32> {
         self.groups.keys().collect()
     }
+
+    // Gets the number of devices opened from the groups in the container.
+    pub fn device_count(&self) -> u32 {
+        self.groups.values().map(|g| g.lock().device_num()).sum()
+    }
 }
 
 impl AsRawDescriptor for VfioContainer {
@@ -1216,6 +1263,117 @@ impl VfioDevice {
         }
     }
 
+    /// Returns whether the device can save and load its internal state with the VFIO migration
+    /// protocol.
+    pub fn supports_migration(&self) -> bool {
+        let payload_size = mem::size_of::<u64>();
+        let mut device_feature = vec_with_array_field::<vfio_device_feature, u8>(payload_size);
+        device_feature[0].argsz = (mem::size_of::<vfio_device_feature>() + payload_size) as u32;
+        device_feature[0].flags = VFIO_DEVICE_FEATURE_GET | VFIO_DEVICE_FEATURE_MIGRATION;
+        // SAFETY:
+        // Safe as enough space is reserved for the migration flags written by the kernel
+        let ret =
+            unsafe { ioctl_with_mut_ref(&self.dev, VFIO_DEVICE_FEATURE, &mut device_feature[0]) };
+        if ret < 0 {
+            return false;
+        }
+        // SAFETY:
+        // Safe as the payload was reserved above
+        let flags = unsafe { device_feature[0].data.as_slice(payload_size) };
+        let flags = u64::from_ne_bytes(flags.try_into().unwrap());
+        flags & VFIO_MIGRATION_STOP_COPY as u64 != 0
+    }
+
+    // Moves the device to the migration `state`, returning the file its state is transferred
+    // through if the new state has one.
+    fn set_migration_state(&self, state: vfio_device_mig_state) -> Result<Option<File>> {
+        let payload = vfio_device_feature_mig_state {
+            device_state: state,
+            data_fd: -1,
+        };
+        let payload_size = mem::size_of::<vfio_device_feature_mig_state>();
+        let mut device_feature = vec_with_array_field::<vfio_device_feature, u8>(payload_size);
+        device_feature[0].argsz = (mem::size_of::<vfio_device_feature>() + payload_size) as u32;
+        device_feature[0].flags = VFIO_DEVICE_FEATURE_SET | VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE;
+        // SAFETY:
+        // Safe as we know vfio_device_feature_mig_state has two 32-bit int fields
+        unsafe {
+            device_feature[0]
+                .data
+                .as_mut_slice(payload_size)
+                .copy_from_slice(
+                    mem::transmute::<vfio_device_feature_mig_state, [u8; 8]>(payload).as_slice(),
+                );
+        }
+        // SAFETY:
+        // Safe as we are the owner of self and device_feature which are valid value
+        let ret =
+            unsafe { ioctl_with_mut_ref(&self.dev, VFIO_DEVICE_FEATURE, &mut device_feature[0]) };
+        if ret < 0 {
+            return Err(VfioError::VfioMigrationState(get_error()));
+        }
+
+        // SAFETY:
+        // Safe as the kernel wrote the new state and the data fd back into the payload
+        let payload = unsafe {
+            mem::transmute::<[u8; 8], vfio_device_feature_mig_state>(
+                device_feature[0]
+                    .data
+                    .as_slice(payload_size)
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+        if payload.data_fd < 0 {
+            return Ok(None);
+        }
+        // SAFETY:
+        // Safe as the kernel just created the fd, which nothing else owns
+        Ok(Some(File::from(unsafe {
+            SafeDescriptor::from_raw_descriptor(payload.data_fd)
+        })))
+    }
+
+    /// Stops the device, or lets it run again after it was stopped, with the VFIO migration
+    /// protocol. A stopped device neither does DMA nor raises interrupts.
+    pub fn migration_set_running(&self, running: bool) -> Result<()> {
+        let state = if running {
+            vfio_device_mig_state_VFIO_DEVICE_STATE_RUNNING
+        } else {
+            vfio_device_mig_state_VFIO_DEVICE_STATE_STOP
+        };
+        self.set_migration_state(state)?;
+        Ok(())
+    }
+
+    /// Returns the internal state of the stopped device.
+    pub fn migration_save(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if let Some(mut data_file) =
+            self.set_migration_state(vfio_device_mig_state_VFIO_DEVICE_STATE_STOP_COPY)?
+        {
+            data_file
+                .read_to_end(&mut data)
+                .map_err(VfioError::VfioMigrationData)?;
+        }
+        self.set_migration_state(vfio_device_mig_state_VFIO_DEVICE_STATE_STOP)?;
+        Ok(data)
+    }
+
+    /// Loads the internal state returned by `migration_save` into the stopped device.
+    pub fn migration_load(&self, data: &[u8]) -> Result<()> {
+        if let Some(mut data_file) =
+            self.set_migration_state(vfio_device_mig_state_VFIO_DEVICE_STATE_RESUMING)?
+        {
+            data_file
+                .write_all(data)
+                .map_err(VfioError::VfioMigrationData)?;
+        }
+        // The device loads the state when it leaves the resuming state.
+        self.set_migration_state(vfio_device_mig_state_VFIO_DEVICE_STATE_STOP)?;
+        Ok(())
+    }
+
     /// call _DSM from the device's ACPI table
     pub fn acpi_dsm(&self, args: &[u8]) -> Result<Vec<u8>> {
         let count = args.len();
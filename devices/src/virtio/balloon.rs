@@ -6,6 +6,8 @@ use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::io::Write;
 use std::sync::Arc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::anyhow;
 use anyhow::Context;
@@ -147,6 +149,10 @@ struct BalloonState {
     num_pages: u32,
     actual_pages: u32,
     expecting_ws: bool,
+    // Whether unrequested ws reports are forwarded on the command tube. This is set by the host
+    // side of the tube, which is not part of snapshots, so it is not saved.
+    #[serde(skip)]
+    forward_ws: bool,
     // Flag indicating that the balloon is in the process of a failable update. This
     // is set by an Adjust command that has allow_failure set, and is cleared when the
     // Adjusted success/failure response is sent.
@@ -645,6 +651,10 @@ async fn handle_ws_data_queue(
         };
 
         let ws = parse_balloon_ws(&mut avail_desc.reader);
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
 
         let mut state = state.lock().await;
 
@@ -652,7 +662,11 @@ async fn handle_ws_data_queue(
         let balloon_actual = (state.actual_pages as u64) << VIRTIO_BALLOON_PFN_SHIFT;
 
         if state.expecting_ws {
-            let result = BalloonTubeResult::WorkingSet { ws, balloon_actual };
+            let result = BalloonTubeResult::WorkingSet {
+                ws,
+                balloon_actual,
+                timestamp_ms,
+            };
             let send_result = command_tube.send(result).await;
             if let Err(e) = send_result {
                 error!("failed to send ws result: {}", e);
@@ -660,6 +674,16 @@ async fn handle_ws_data_queue(
 
             state.expecting_ws = false;
         } else {
+            if state.forward_ws {
+                let result = BalloonTubeResult::WorkingSetPushed {
+                    ws: ws.clone(),
+                    balloon_actual,
+                    timestamp_ms,
+                };
+                if let Err(e) = command_tube.send(result).await {
+                    error!("failed to forward ws report: {}", e);
+                }
+            }
             #[cfg(feature = "registered_events")]
             if let Some(registered_evt_q) = registered_evt_q {
                 if let Err(e) = registered_evt_q
@@ -736,6 +760,9 @@ async fn handle_command_tube(
                         error!("failed to send report request to ws handler: {}", e);
                     }
                 }
+                BalloonTubeCommand::WorkingSetForward { enabled } => {
+                    state.lock().await.forward_ws = enabled;
+                }
             },
             #[cfg(windows)]
             Err(base::TubeError::Recv(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
@@ -1231,6 +1258,7 @@ impl Balloon {
                 failable_update: false,
                 pending_adjusted_responses: VecDeque::new(),
                 expecting_ws: false,
+                forward_ws: false,
             })),
             worker_thread: None,
             features,
@@ -1404,6 +1432,10 @@ impl VirtioDevice for Balloon {
         rds
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Balloon
     }
@@ -1523,7 +1555,9 @@ impl VirtioDevice for Balloon {
             .lock()
             .now_or_never()
             .context("failed to acquire balloon lock")?;
+        let forward_ws = state.forward_ws;
         *state = snap.state;
+        state.forward_ws = forward_ws;
         self.ws_num_bins = snap.ws_num_bins;
         self.acked_features = snap.acked_features;
         Ok(())
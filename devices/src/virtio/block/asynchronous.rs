@@ -5,6 +5,8 @@
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::fs::File;
 use std::io;
 use std::io::Write;
 use std::mem::size_of;
@@ -16,6 +18,7 @@ use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Context;
 use base::debug;
@@ -49,6 +52,10 @@ use futures::pin_mut;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
 use futures::FutureExt;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
+use metrics::log_metric;
+use metrics::MetricEventType;
 use remain::sorted;
 use snapshot::AnySnapshot;
 use thiserror::Error as ThisError;
@@ -226,6 +233,9 @@ struct DiskState {
 /// Disk state which can be modified by other worker threads
 struct WorkerSharedState {
     disk_size: Arc<AtomicU64>,
+    /// Whether write requests are rejected. Starts as the `read_only` option of the disk and can
+    /// be changed at runtime with `DiskControlCommand::SetReadOnly`.
+    read_only: bool,
 }
 
 async fn process_one_request(
@@ -361,6 +371,9 @@ async fn handle_command_tube(
             Ok(command) => {
                 let resp = match command {
                     DiskControlCommand::Resize { new_size } => resize(&disk_state, new_size).await,
+                    DiskControlCommand::SetReadOnly { read_only } => {
+                        set_read_only(&disk_state, read_only).await
+                    }
                 };
 
                 let resp_clone = resp.clone();
@@ -368,7 +381,11 @@ async fn handle_command_tube(
                     .send(resp_clone)
                     .await
                     .map_err(ExecuteError::SendingResponse)?;
-                if let DiskControlResult::Ok = resp {
+                // The read-only state is negotiated as a feature bit, which cannot change once the
+                // driver is running, so only a resize is announced to the guest.
+                if resp == DiskControlResult::Ok
+                    && matches!(command, DiskControlCommand::Resize { .. })
+                {
                     if let Some(interrupt) = &*interrupt.borrow() {
                         interrupt.signal_config_changed();
                     }
@@ -387,7 +404,7 @@ async fn resize(disk_state: &AsyncRwLock<DiskState>, new_size: u64) -> DiskContr
     let worker_shared_state = Arc::clone(&disk_state.worker_shared_state);
     let worker_shared_state = worker_shared_state.lock().await;
 
-    if disk_state.read_only {
+    if worker_shared_state.read_only {
         error!("Attempted to resize read-only block device");
         return DiskControlResult::Err(SysError::new(libc::EROFS));
     }
@@ -415,6 +432,28 @@ async fn resize(disk_state: &AsyncRwLock<DiskState>, new_size: u64) -> DiskContr
     DiskControlResult::Ok
 }
 
+/// Starts or stops rejecting write requests. A disk opened read-only cannot be made writable.
+async fn set_read_only(disk_state: &AsyncRwLock<DiskState>, read_only: bool) -> DiskControlResult {
+    let disk_state = disk_state.lock().await;
+    // Wait for the requests in flight on all workers to complete.
+    let mut worker_shared_state = disk_state.worker_shared_state.lock().await;
+
+    if !read_only && disk_state.read_only {
+        error!("Attempted to make a block device opened read-only writable");
+        return DiskControlResult::Err(SysError::new(libc::EROFS));
+    }
+    if read_only && !worker_shared_state.read_only {
+        if let Err(e) = disk_state.disk_image.flush().await {
+            error!("Flushing disk before making it read-only failed! {:#}", e);
+            return DiskControlResult::Err(SysError::new(libc::EIO));
+        }
+    }
+
+    info!("Setting block device read-only: {}", read_only);
+    worker_shared_state.read_only = read_only;
+    DiskControlResult::Ok
+}
+
 /// Periodically flushes the disk when the given timer fires.
 async fn flush_disk(
     disk_state: Rc<AsyncRwLock<DiskState>>,
@@ -634,6 +673,11 @@ pub struct BlockAsync {
     #[cfg(windows)]
     pub(super) io_concurrency: u32,
     pci_address: Option<PciAddress>,
+    // Host CPUs the worker threads are pinned to.
+    worker_affinity: Option<Vec<usize>>,
+    // Cgroup file the worker threads write their tid into when they start.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    worker_cgroup: Option<File>,
 }
 
 impl BlockAsync {
@@ -695,9 +739,23 @@ impl BlockAsync {
 
         let seg_max = get_seg_max(q_size);
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let worker_cgroup = match &disk_option.cgroup {
+            Some(cgroup_path) => Some(open_cgroup_threads_file(cgroup_path).map_err(|e| {
+                error!(
+                    "failed to open cgroup {} for block workers: {}",
+                    cgroup_path.display(),
+                    e
+                );
+                SysError::from(e)
+            })?),
+            None => None,
+        };
+
         let disk_size = Arc::new(AtomicU64::new(disk_size));
         let shared_state = Arc::new(AsyncRwLock::new(WorkerSharedState {
             disk_size: disk_size.clone(),
+            read_only,
         }));
 
         Ok(BlockAsync {
@@ -720,6 +778,9 @@ impl BlockAsync {
             #[cfg(windows)]
             io_concurrency,
             pci_address: disk_option.pci_address,
+            worker_affinity: disk_option.affinity.clone(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            worker_cgroup,
         })
     }
 
@@ -773,7 +834,10 @@ impl BlockAsync {
         let req_type = req_header.req_type.to_native();
         let sector = req_header.sector.to_native();
 
-        if disk_state.read_only && req_type != VIRTIO_BLK_T_IN && req_type != VIRTIO_BLK_T_GET_ID {
+        if worker_shared_state.read_only
+            && req_type != VIRTIO_BLK_T_IN
+            && req_type != VIRTIO_BLK_T_GET_ID
+        {
             return Err(ExecuteError::ReadOnly {
                 request_type: req_type,
             });
@@ -808,6 +872,7 @@ impl BlockAsync {
                     .ok_or(ExecuteError::OutOfRange)?;
                 check_range(offset, data_len as u64, disk_size)?;
                 let disk_image = &disk_state.disk_image;
+                let start = Instant::now();
                 writer
                     .write_all_from_at_fut(&**disk_image, data_len, offset)
                     .await
@@ -816,6 +881,10 @@ impl BlockAsync {
                         sector,
                         desc_error,
                     })?;
+                log_metric(
+                    MetricEventType::BlockRead,
+                    start.elapsed().as_micros() as i64,
+                );
             }
             VIRTIO_BLK_T_OUT => {
                 let data_len = reader.available_bytes();
@@ -827,6 +896,7 @@ impl BlockAsync {
                     .ok_or(ExecuteError::OutOfRange)?;
                 check_range(offset, data_len as u64, disk_size)?;
                 let disk_image = &disk_state.disk_image;
+                let start = Instant::now();
                 reader
                     .read_exact_to_at_fut(&**disk_image, data_len, offset)
                     .await
@@ -835,6 +905,10 @@ impl BlockAsync {
                         sector,
                         desc_error,
                     })?;
+                log_metric(
+                    MetricEventType::BlockWrite,
+                    start.elapsed().as_micros() as i64,
+                );
 
                 if !*flush_timer_armed.borrow() {
                     *flush_timer_armed.borrow_mut() = true;
@@ -980,9 +1054,31 @@ impl BlockAsync {
         let sparse = self.sparse;
         let id = self.id;
         let worker_shared_state = self.shared_state.clone();
+        let worker_affinity = self.worker_affinity.clone();
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let worker_cgroup = self
+            .worker_cgroup
+            .as_ref()
+            .map(File::try_clone)
+            .transpose()
+            .context("Failed to clone the worker cgroup file")?;
 
         let (worker_tx, worker_rx) = mpsc::unbounded();
         let worker_thread = WorkerThread::start("virtio_blk", move |kill_evt| {
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            if let Some(mut cgroup) = worker_cgroup {
+                if let Err(e) = cgroup.write_all(base::gettid().to_string().as_bytes()) {
+                    error!("failed to move block worker into cgroup: {}", e);
+                }
+            }
+
+            // The affinity is set after joining the cgroup, which would otherwise override it.
+            if let Some(cpus) = worker_affinity {
+                if let Err(e) = base::set_cpu_affinity(cpus) {
+                    error!("failed to set block worker CPU affinity: {}", e);
+                }
+            }
+
             let async_control =
                 control_tube.map(|c| AsyncTube::new(&ex, c).expect("failed to create async tube"));
 
@@ -1072,13 +1168,28 @@ impl VirtioDevice for BlockAsync {
             keep_rds.push(control_tube.as_raw_descriptor());
         }
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if let Some(worker_cgroup) = &self.worker_cgroup {
+            keep_rds.push(worker_cgroup.as_raw_descriptor());
+        }
+
         keep_rds
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        // The disk image and cgroup are opened before the device is sandboxed.
+        Some(Vec::new())
+    }
+
     fn features(&self) -> u64 {
         self.avail_features
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Block
     }
@@ -1284,6 +1395,7 @@ mod tests {
         let features = base_features(ProtectionType::Unprotected);
         let disk_option = DiskOption {
             pci_address: Some(PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 1,
                 func: 1,
@@ -1379,6 +1491,7 @@ mod tests {
             id: None,
             worker_shared_state: Arc::new(AsyncRwLock::new(WorkerSharedState {
                 disk_size: Arc::new(AtomicU64::new(disk_size)),
+                read_only: false,
             })),
         }));
 
@@ -1447,6 +1560,7 @@ mod tests {
             id: None,
             worker_shared_state: Arc::new(AsyncRwLock::new(WorkerSharedState {
                 disk_size: Arc::new(AtomicU64::new(disk_size)),
+                read_only: false,
             })),
         }));
 
@@ -1793,6 +1907,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_read_only_command() {
+        let f = tempfile().unwrap();
+        f.set_len(0x1000).unwrap();
+        let disk_image: Box<dyn DiskFile> = Box::new(f);
+        let mem = GuestMemory::new(&[(GuestAddress(0u64), 4 * 1024 * 1024)])
+            .expect("Creating guest memory failed.");
+        let (control_tube, control_tube_device) = Tube::pair().unwrap();
+
+        let features = base_features(ProtectionType::Unprotected);
+        let mut b = BlockAsync::new(
+            features,
+            disk_image,
+            &DiskOption::default(),
+            Some(control_tube_device),
+            None,
+            None,
+        )
+        .unwrap();
+
+        let interrupt = Interrupt::new_for_test();
+        let mut q0 = QueueConfig::new(DEFAULT_QUEUE_SIZE, 0);
+        q0.set_ready(true);
+        let q0 = q0
+            .activate(&mem, Event::new().unwrap(), interrupt.clone())
+            .expect("QueueConfig::activate");
+        b.activate(mem, interrupt, BTreeMap::from([(0, q0)]))
+            .expect("activate should succeed");
+
+        let mut send = |command| {
+            control_tube.send(&command).unwrap();
+            control_tube.recv::<DiskControlResult>().unwrap()
+        };
+        assert_eq!(
+            send(DiskControlCommand::SetReadOnly { read_only: true }),
+            DiskControlResult::Ok
+        );
+        assert_eq!(
+            send(DiskControlCommand::Resize { new_size: 0x2000 }),
+            DiskControlResult::Err(SysError::new(libc::EROFS)),
+            "a read-only disk cannot be resized"
+        );
+        assert_eq!(
+            send(DiskControlCommand::SetReadOnly { read_only: false }),
+            DiskControlResult::Ok
+        );
+        assert_eq!(
+            send(DiskControlCommand::Resize { new_size: 0x2000 }),
+            DiskControlResult::Ok
+        );
+    }
+
     #[test]
     fn run_worker_threads() {
         // Create an empty duplicable disk image
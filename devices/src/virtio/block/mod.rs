@@ -81,6 +81,89 @@ fn deserialize_disk_id<'de, D: Deserializer<'de>>(
     }
 }
 
+fn parse_cpu_range(s: &str, cpus: &mut Vec<usize>) -> Result<(), String> {
+    let parse_cpu = |cpu: &str| {
+        cpu.parse::<usize>()
+            .map_err(|_| format!("invalid CPU index {}", cpu))
+    };
+
+    match s.split_once('-') {
+        Some((first, last)) => {
+            let (first, last) = (parse_cpu(first)?, parse_cpu(last)?);
+            if last < first {
+                return Err(format!(
+                    "invalid CPU range {} - ranges must be from low to high",
+                    s
+                ));
+            }
+            cpus.extend(first..=last);
+        }
+        None => cpus.push(parse_cpu(s)?),
+    }
+    Ok(())
+}
+
+/// Deserializes a list of host CPUs given either as a single CPU or range (e.g. `2` or `2-3`), or
+/// as a sequence of them (e.g. `[0,2-3]`).
+fn deserialize_cpu_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<usize>>, D::Error> {
+    struct CpuListVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for CpuListVisitor {
+        type Value = Vec<usize>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a CPU index, a CPU range or a sequence of them")
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(vec![v as usize])
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            usize::try_from(v)
+                .map(|cpu| vec![cpu])
+                .map_err(|_| E::custom(format!("invalid CPU index {}", v)))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            let mut cpus = Vec::new();
+            parse_cpu_range(v, &mut cpus).map_err(E::custom)?;
+            Ok(cpus)
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum CpuListValue {
+                Single(usize),
+                Range(String),
+            }
+
+            let mut cpus = Vec::new();
+            while let Some(value) = seq.next_element::<CpuListValue>()? {
+                match value {
+                    CpuListValue::Single(cpu) => cpus.push(cpu),
+                    CpuListValue::Range(range) => {
+                        parse_cpu_range(&range, &mut cpus).map_err(serde::de::Error::custom)?
+                    }
+                }
+            }
+            Ok(cpus)
+        }
+    }
+
+    let cpus = deserializer.deserialize_any(CpuListVisitor)?;
+    if cpus.is_empty() {
+        return Err(serde::de::Error::custom("CPU list cannot be empty"));
+    }
+    Ok(Some(cpus))
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, serde_keyvalue::FromKeyValues)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct DiskOption {
@@ -137,6 +220,15 @@ pub struct DiskOption {
 
     /// Specify PCI address will be used to attach this device
     pub pci_address: Option<PciAddress>,
+
+    /// Host CPUs the worker threads of this device are allowed to run on.
+    #[serde(default, deserialize_with = "deserialize_cpu_list")]
+    pub affinity: Option<Vec<usize>>,
+
+    /// Host cgroup the worker threads of this device are moved into when they start.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[serde(default)]
+    pub cgroup: Option<PathBuf>,
 }
 
 impl Default for DiskOption {
@@ -157,6 +249,9 @@ impl Default for DiskOption {
             packed_queue: false,
             bootindex: None,
             pci_address: None,
+            affinity: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            cgroup: None,
         }
     }
 }
@@ -217,6 +312,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -240,6 +338,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: Some(5),
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -263,6 +364,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -286,6 +390,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -309,6 +416,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -332,6 +442,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
         let params = from_block_arg("/some/path.img,sparse=false").unwrap();
@@ -353,6 +466,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -376,6 +492,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -399,6 +518,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -422,6 +544,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -445,6 +570,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -469,6 +597,9 @@ mod tests {
                     packed_queue: false,
                     bootindex: None,
                     pci_address: None,
+                    affinity: None,
+                    #[cfg(any(target_os = "android", target_os = "linux"))]
+                    cgroup: None,
                 }
             );
             let params = from_block_arg("/some/path.img,async-executor=overlapped").unwrap();
@@ -489,6 +620,9 @@ mod tests {
                     packed_queue: false,
                     bootindex: None,
                     pci_address: None,
+                    affinity: None,
+                    #[cfg(any(target_os = "android", target_os = "linux"))]
+                    cgroup: None,
                 }
             );
             let params =
@@ -516,6 +650,9 @@ mod tests {
                     packed_queue: false,
                     bootindex: None,
                     pci_address: None,
+                    affinity: None,
+                    #[cfg(any(target_os = "android", target_os = "linux"))]
+                    cgroup: None,
                 }
             );
         }
@@ -540,6 +677,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
         let err = from_block_arg("/some/path.img,id=DISK_ID_IS_WAY_TOO_LONG").unwrap_err();
@@ -576,6 +716,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -599,6 +742,9 @@ mod tests {
                 packed_queue: true,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -622,10 +768,14 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: Some(PciAddress {
+                    domain: 0,
                     bus: 0,
                     dev: 1,
                     func: 1,
                 }),
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -649,6 +799,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
         // lock=false
@@ -671,6 +824,9 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: None,
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
 
@@ -698,14 +854,50 @@ mod tests {
                 packed_queue: false,
                 bootindex: None,
                 pci_address: Some(PciAddress {
+                    domain: 0,
                     bus: 0,
                     dev: 1,
                     func: 1,
                 }),
+                affinity: None,
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                cgroup: None,
             }
         );
     }
 
+    #[test]
+    fn params_affinity_and_cgroup() {
+        let params = from_block_arg("/path/to/disk.img,affinity=3").unwrap();
+        assert_eq!(params.affinity, Some(vec![3]));
+
+        let params = from_block_arg("/path/to/disk.img,affinity=2-3").unwrap();
+        assert_eq!(params.affinity, Some(vec![2, 3]));
+
+        let params = from_block_arg("/path/to/disk.img,affinity=[0,2-4],ro").unwrap();
+        assert_eq!(params.affinity, Some(vec![0, 2, 3, 4]));
+        assert!(params.read_only);
+
+        assert!(from_block_arg("/path/to/disk.img,affinity=3-2").is_err());
+        assert!(from_block_arg("/path/to/disk.img,affinity=[]").is_err());
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            let params =
+                from_block_arg("/path/to/disk.img,affinity=2-3,cgroup=/sys/fs/cgroup/vm-io")
+                    .unwrap();
+            assert_eq!(
+                params,
+                DiskOption {
+                    path: "/path/to/disk.img".into(),
+                    affinity: Some(vec![2, 3]),
+                    cgroup: Some("/sys/fs/cgroup/vm-io".into()),
+                    ..DiskOption::default()
+                }
+            );
+        }
+    }
+
     #[test]
     fn diskoption_serialize_deserialize() {
         // With id == None
@@ -725,6 +917,9 @@ mod tests {
             packed_queue: false,
             bootindex: None,
             pci_address: None,
+            affinity: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            cgroup: None,
         };
         let json = serde_json::to_string(&original).unwrap();
         let deserialized = serde_json::from_str(&json).unwrap();
@@ -747,6 +942,9 @@ mod tests {
             packed_queue: false,
             bootindex: None,
             pci_address: None,
+            affinity: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            cgroup: None,
         };
         let json = serde_json::to_string(&original).unwrap();
         let deserialized = serde_json::from_str(&json).unwrap();
@@ -769,6 +967,9 @@ mod tests {
             packed_queue: false,
             bootindex: None,
             pci_address: None,
+            affinity: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            cgroup: None,
         };
         let json = serde_json::to_string(&original).unwrap();
         let deserialized = serde_json::from_str(&json).unwrap();
@@ -4,6 +4,10 @@
 
 use std::cmp::max;
 use std::cmp::min;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
 
 use anyhow::Context;
 use base::unix::iov_max;
@@ -38,6 +42,22 @@ impl DiskOption {
     }
 }
 
+/// Opens the file used to move threads into the cgroup at `cgroup_path`: `cgroup.threads` for
+/// cgroup v2, falling back to `tasks` for cgroup v1 hierarchies.
+///
+/// The file is opened up front so that worker threads can still join the cgroup once the device
+/// is sandboxed.
+pub fn open_cgroup_threads_file(cgroup_path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .open(cgroup_path.join("cgroup.threads"))
+        .or_else(|_| {
+            OpenOptions::new()
+                .write(true)
+                .open(cgroup_path.join("tasks"))
+        })
+}
+
 impl BlockAsync {
     pub fn create_executor(&self) -> Executor {
         Executor::with_executor_kind(self.executor_kind).expect("Failed to create an executor")
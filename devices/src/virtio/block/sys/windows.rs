@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::File;
+
 use anyhow::Context;
 use base::warn;
 use cros_async::sys::windows::ExecutorKindSys;
@@ -17,9 +19,8 @@ pub fn get_seg_max(_queue_size: u16) -> u32 {
 }
 
 impl DiskOption {
-    /// Open the specified disk file.
-    pub fn open(&self) -> anyhow::Result<Box<dyn disk::DiskFile>> {
-        Ok(disk::open_disk_file(disk::DiskFileParams {
+    fn disk_file_params(&self) -> disk::DiskFileParams {
+        disk::DiskFileParams {
             path: self.path.clone(),
             is_read_only: self.read_only,
             is_sparse_file: self.sparse,
@@ -30,7 +31,26 @@ impl DiskOption {
             is_direct: self.direct,
             lock: self.lock,
             depth: 0,
-        })?)
+        }
+    }
+
+    /// Open the specified disk file.
+    pub fn open(&self) -> anyhow::Result<Box<dyn disk::DiskFile>> {
+        Ok(disk::open_disk_file(self.disk_file_params())?)
+    }
+
+    /// Open the image file of the disk without inspecting it, so that a sandboxed device process
+    /// can be handed the file with `open_from_raw`.
+    pub fn open_raw(&self) -> anyhow::Result<File> {
+        Ok(disk::open_raw_disk_file(&self.disk_file_params())?)
+    }
+
+    /// Open the specified disk from its already opened image file.
+    pub fn open_from_raw(&self, raw_image: File) -> anyhow::Result<Box<dyn disk::DiskFile>> {
+        Ok(disk::open_disk_file_from_raw(
+            raw_image,
+            self.disk_file_params(),
+        )?)
     }
 }
 
@@ -0,0 +1,856 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Virtio CAN device bridged to a host SocketCAN interface.
+//!
+//! Frames the guest transmits are written to a raw CAN socket bound to the host interface, and
+//! frames received on that socket are passed to the guest. Receive filters are installed on the
+//! socket, so the host kernel drops frames the guest is not interested in.
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::mem::size_of;
+use std::mem::size_of_val;
+use std::os::raw::c_int;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::warn;
+use base::AsRawDescriptor;
+use base::Event;
+use base::EventToken;
+use base::EventType;
+use base::RawDescriptor;
+use base::WaitContext;
+use base::WorkerThread;
+use data_model::Le16;
+use data_model::Le32;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_keyvalue::FromKeyValues;
+use snapshot::AnySnapshot;
+use vm_memory::GuestMemory;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+use super::copy_config;
+use super::DeviceType;
+use super::Interrupt;
+use super::Queue;
+use super::Reader;
+use super::VirtioDevice;
+
+const QUEUE_SIZE: u16 = 64;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; 3];
+const TX_QUEUE: usize = 0;
+const RX_QUEUE: usize = 1;
+const CTRL_QUEUE: usize = 2;
+
+// Feature bits, from the virtio specification.
+const VIRTIO_CAN_F_CAN_CLASSIC: u32 = 0;
+const VIRTIO_CAN_F_CAN_FD: u32 = 1;
+const VIRTIO_CAN_F_RTR_FRAMES: u32 = 3;
+
+const VIRTIO_CAN_TX: u16 = 0x0001;
+const VIRTIO_CAN_RX: u16 = 0x0101;
+const VIRTIO_CAN_SET_CTRL_MODE_START: u16 = 0x0201;
+const VIRTIO_CAN_SET_CTRL_MODE_STOP: u16 = 0x0202;
+
+const VIRTIO_CAN_RESULT_OK: u8 = 0;
+const VIRTIO_CAN_RESULT_NOT_OK: u8 = 1;
+
+const VIRTIO_CAN_FLAGS_EXTENDED: u32 = 1 << 1;
+const VIRTIO_CAN_FLAGS_FD: u32 = 1 << 2;
+const VIRTIO_CAN_FLAGS_RTR: u32 = 1 << 3;
+const VIRTIO_CAN_FLAGS_KNOWN: u32 =
+    VIRTIO_CAN_FLAGS_EXTENDED | VIRTIO_CAN_FLAGS_FD | VIRTIO_CAN_FLAGS_RTR;
+
+// From linux/can.h and linux/can/raw.h.
+const CAN_RAW: c_int = 1;
+const SOL_CAN_RAW: c_int = 101;
+const CAN_RAW_FILTER: c_int = 1;
+const CAN_RAW_FD_FRAMES: c_int = 5;
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_SFF_MASK: u32 = 0x0000_07ff;
+const CAN_EFF_MASK: u32 = 0x1fff_ffff;
+const CANFD_FDF: u8 = 0x04;
+const CAN_MTU: usize = 16;
+const CANFD_MTU: usize = 72;
+const CAN_MAX_DLEN: usize = 8;
+const CANFD_MAX_DLEN: usize = 64;
+
+// Payload lengths a CAN FD frame can have.
+const CANFD_LENGTHS: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// A SocketCAN receive filter. A frame is accepted if `received_id & mask == id & mask`, with the
+/// identifiers using the SocketCAN encoding (`CAN_EFF_FLAG` set for extended frames).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanFilter {
+    pub id: u32,
+    pub mask: u32,
+}
+
+/// Parameters for a virtio-can device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CanParameters {
+    /// Name of the host SocketCAN interface, e.g. `can0` or `vcan0`.
+    pub iface: String,
+    /// Offer CAN FD frames to the guest.
+    #[serde(default)]
+    pub fd: bool,
+    /// Only pass frames matching one of these filters to the guest. All frames are passed if
+    /// empty.
+    #[serde(default)]
+    pub filters: Vec<CanFilter>,
+}
+
+#[derive(Copy, Clone, Debug, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct virtio_can_config {
+    status: Le16,
+}
+
+// Header shared by `virtio_can_tx_out` and `virtio_can_rx`; the payload follows it.
+#[derive(Copy, Clone, Debug, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct virtio_can_frame_hdr {
+    msg_type: Le16,
+    length: Le16,
+    reserved_classic_dlc: u8,
+    padding: u8,
+    reserved_xl_priority: Le16,
+    flags: Le32,
+    can_id: Le32,
+}
+
+#[derive(Copy, Clone, Debug, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct virtio_can_control_out {
+    msg_type: Le16,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct sockaddr_can {
+    can_family: libc::sa_family_t,
+    can_ifindex: c_int,
+    // Transport protocol addresses, unused by raw sockets.
+    can_addr: [u64; 2],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct can_filter {
+    can_id: u32,
+    can_mask: u32,
+}
+
+/// A CAN frame, with the identifier and flags in their virtio encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct CanFrame {
+    can_id: u32,
+    flags: u32,
+    // For remote frames this is the requested length and `data` is empty.
+    length: usize,
+    data: Vec<u8>,
+}
+
+impl CanFrame {
+    /// Reads the frame a guest transmits, checking it against the negotiated features.
+    fn from_guest(reader: &mut Reader, acked_features: u64) -> anyhow::Result<Self> {
+        let hdr: virtio_can_frame_hdr = reader.read_obj().context("failed to read tx header")?;
+        if hdr.msg_type.to_native() != VIRTIO_CAN_TX {
+            bail!("unexpected tx message type {:#x}", hdr.msg_type.to_native());
+        }
+        let flags = hdr.flags.to_native();
+        let can_id = hdr.can_id.to_native();
+        let length = hdr.length.to_native() as usize;
+
+        if flags & !VIRTIO_CAN_FLAGS_KNOWN != 0 {
+            bail!("unknown frame flags {:#x}", flags);
+        }
+        let fd = flags & VIRTIO_CAN_FLAGS_FD != 0;
+        let rtr = flags & VIRTIO_CAN_FLAGS_RTR != 0;
+        if fd && acked_features & (1 << VIRTIO_CAN_F_CAN_FD) == 0 {
+            bail!("CAN FD frame without VIRTIO_CAN_F_CAN_FD");
+        }
+        if !fd && acked_features & (1 << VIRTIO_CAN_F_CAN_CLASSIC) == 0 {
+            bail!("classic CAN frame without VIRTIO_CAN_F_CAN_CLASSIC");
+        }
+        if rtr && (fd || acked_features & (1 << VIRTIO_CAN_F_RTR_FRAMES) == 0) {
+            bail!("unsupported remote frame");
+        }
+        let id_mask = if flags & VIRTIO_CAN_FLAGS_EXTENDED != 0 {
+            CAN_EFF_MASK
+        } else {
+            CAN_SFF_MASK
+        };
+        if can_id & !id_mask != 0 {
+            bail!("invalid CAN identifier {:#x}", can_id);
+        }
+        if (fd && !CANFD_LENGTHS.contains(&length)) || (!fd && length > CAN_MAX_DLEN) {
+            bail!("invalid frame length {}", length);
+        }
+
+        let mut data = Vec::new();
+        if !rtr {
+            data.resize(length, 0);
+            reader
+                .read_exact(&mut data)
+                .context("failed to read frame payload")?;
+        }
+        Ok(CanFrame {
+            can_id,
+            flags,
+            length,
+            data,
+        })
+    }
+
+    fn write_to_guest(&self, writer: &mut impl Write) -> io::Result<()> {
+        let hdr = virtio_can_frame_hdr {
+            msg_type: VIRTIO_CAN_RX.into(),
+            length: (self.length as u16).into(),
+            flags: self.flags.into(),
+            can_id: self.can_id.into(),
+            ..Default::default()
+        };
+        writer.write_all(hdr.as_bytes())?;
+        writer.write_all(&self.data)
+    }
+
+    /// Decodes a `struct can_frame` or `struct canfd_frame` read from a raw CAN socket. Returns
+    /// `None` for error frames.
+    fn from_socketcan(buf: &[u8]) -> io::Result<Option<Self>> {
+        let fd = match buf.len() {
+            CAN_MTU => false,
+            CANFD_MTU => true,
+            n => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected CAN frame size {}", n),
+                ))
+            }
+        };
+        let raw_id = u32::from_ne_bytes(buf[0..4].try_into().unwrap());
+        if raw_id & CAN_ERR_FLAG != 0 {
+            return Ok(None);
+        }
+
+        let mut flags = 0;
+        let can_id = if raw_id & CAN_EFF_FLAG != 0 {
+            flags |= VIRTIO_CAN_FLAGS_EXTENDED;
+            raw_id & CAN_EFF_MASK
+        } else {
+            raw_id & CAN_SFF_MASK
+        };
+        let max_len = if fd { CANFD_MAX_DLEN } else { CAN_MAX_DLEN };
+        let length = (buf[4] as usize).min(max_len);
+        let data = if fd {
+            flags |= VIRTIO_CAN_FLAGS_FD;
+            buf[8..8 + length].to_vec()
+        } else if raw_id & CAN_RTR_FLAG != 0 {
+            flags |= VIRTIO_CAN_FLAGS_RTR;
+            Vec::new()
+        } else {
+            buf[8..8 + length].to_vec()
+        };
+        Ok(Some(CanFrame {
+            can_id,
+            flags,
+            length,
+            data,
+        }))
+    }
+
+    /// Encodes the frame as a `struct can_frame` or `struct canfd_frame`.
+    fn to_socketcan(&self) -> Vec<u8> {
+        let fd = self.flags & VIRTIO_CAN_FLAGS_FD != 0;
+        let mut raw_id = self.can_id;
+        if self.flags & VIRTIO_CAN_FLAGS_EXTENDED != 0 {
+            raw_id |= CAN_EFF_FLAG;
+        }
+        if self.flags & VIRTIO_CAN_FLAGS_RTR != 0 {
+            raw_id |= CAN_RTR_FLAG;
+        }
+        let mut buf = vec![0u8; if fd { CANFD_MTU } else { CAN_MTU }];
+        buf[0..4].copy_from_slice(&raw_id.to_ne_bytes());
+        buf[4] = self.length as u8;
+        if fd {
+            buf[5] = CANFD_FDF;
+        }
+        buf[8..8 + self.data.len()].copy_from_slice(&self.data);
+        buf
+    }
+
+    /// Returns whether the guest negotiated the features needed to receive this frame.
+    fn supported_by(&self, acked_features: u64) -> bool {
+        let required = if self.flags & VIRTIO_CAN_FLAGS_FD != 0 {
+            VIRTIO_CAN_F_CAN_FD
+        } else if self.flags & VIRTIO_CAN_FLAGS_RTR != 0 {
+            VIRTIO_CAN_F_RTR_FRAMES
+        } else {
+            VIRTIO_CAN_F_CAN_CLASSIC
+        };
+        acked_features & (1 << required) != 0
+    }
+}
+
+/// A raw CAN socket bound to a host interface.
+struct CanSocket {
+    sock: File,
+}
+
+impl CanSocket {
+    fn open(params: &CanParameters) -> io::Result<Self> {
+        let iface = CString::new(params.iface.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY:
+        // Safe because `iface` is a valid NUL-terminated string.
+        let ifindex = unsafe { libc::if_nametoindex(iface.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY:
+        // Safe because we check the return value and take ownership of the new descriptor.
+        let sock = unsafe {
+            let fd = libc::socket(libc::AF_CAN, libc::SOCK_RAW | libc::SOCK_CLOEXEC, CAN_RAW);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            File::from_raw_fd(fd)
+        };
+
+        if params.fd {
+            let enable: c_int = 1;
+            setsockopt(&sock, CAN_RAW_FD_FRAMES, &enable)?;
+        }
+        if !params.filters.is_empty() {
+            let filters: Vec<can_filter> = params
+                .filters
+                .iter()
+                .map(|f| can_filter {
+                    can_id: f.id,
+                    can_mask: f.mask,
+                })
+                .collect();
+            setsockopt(&sock, CAN_RAW_FILTER, filters.as_slice())?;
+        }
+
+        let addr = sockaddr_can {
+            can_family: libc::AF_CAN as libc::sa_family_t,
+            can_ifindex: ifindex as c_int,
+            can_addr: [0; 2],
+        };
+        // SAFETY:
+        // Safe because we pass a descriptor that we own and a valid pointer/size for the address.
+        let ret = unsafe {
+            libc::bind(
+                sock.as_raw_fd(),
+                &addr as *const sockaddr_can as *const libc::sockaddr,
+                size_of::<sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(CanSocket { sock })
+    }
+
+    /// Receives a frame, returning `None` for frames that are not passed to the guest.
+    fn recv(&mut self) -> io::Result<Option<CanFrame>> {
+        let mut buf = [0u8; CANFD_MTU];
+        let len = self.sock.read(&mut buf)?;
+        CanFrame::from_socketcan(&buf[..len])
+    }
+
+    fn send(&mut self, frame: &CanFrame) -> io::Result<()> {
+        let buf = frame.to_socketcan();
+        let len = self.sock.write(&buf)?;
+        if len != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "short write to CAN socket",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AsRawDescriptor for CanSocket {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.sock.as_raw_descriptor()
+    }
+}
+
+fn setsockopt<T: ?Sized>(sock: &File, name: c_int, value: &T) -> io::Result<()> {
+    // SAFETY:
+    // Safe because the kernel only reads `size_of_val(value)` bytes from `value` and we check the
+    // return value.
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            SOL_CAN_RAW,
+            name,
+            value as *const T as *const libc::c_void,
+            size_of_val(value) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+struct Worker {
+    tx_queue: Queue,
+    rx_queue: Queue,
+    ctrl_queue: Queue,
+    socket: CanSocket,
+    acked_features: u64,
+    started: bool,
+    // A received frame waiting for the guest to provide a buffer.
+    pending_rx: Option<CanFrame>,
+}
+
+impl Worker {
+    fn process_tx_queue(&mut self) -> bool {
+        let mut needs_interrupt = false;
+        while let Some(mut avail_desc) = self.tx_queue.pop() {
+            let result = match self.transmit(&mut avail_desc.reader) {
+                Ok(()) => VIRTIO_CAN_RESULT_OK,
+                Err(e) => {
+                    warn!("virtio-can: failed to transmit frame: {:#}", e);
+                    VIRTIO_CAN_RESULT_NOT_OK
+                }
+            };
+            if let Err(e) = avail_desc.writer.write_obj(result) {
+                error!("virtio-can: failed to write tx result: {}", e);
+            }
+            let len = avail_desc.writer.bytes_written() as u32;
+            self.tx_queue.add_used(avail_desc, len);
+            needs_interrupt = true;
+        }
+        needs_interrupt
+    }
+
+    fn transmit(&mut self, reader: &mut Reader) -> anyhow::Result<()> {
+        let frame = CanFrame::from_guest(reader, self.acked_features)?;
+        if !self.started {
+            bail!("controller is stopped");
+        }
+        self.socket
+            .send(&frame)
+            .context("failed to write to CAN socket")
+    }
+
+    fn process_ctrl_queue(&mut self) -> bool {
+        let mut needs_interrupt = false;
+        while let Some(mut avail_desc) = self.ctrl_queue.pop() {
+            let result = match avail_desc.reader.read_obj::<virtio_can_control_out>() {
+                Ok(req) => match req.msg_type.to_native() {
+                    VIRTIO_CAN_SET_CTRL_MODE_START => {
+                        self.started = true;
+                        VIRTIO_CAN_RESULT_OK
+                    }
+                    VIRTIO_CAN_SET_CTRL_MODE_STOP => {
+                        self.started = false;
+                        self.pending_rx = None;
+                        VIRTIO_CAN_RESULT_OK
+                    }
+                    msg_type => {
+                        warn!("virtio-can: unknown control message {:#x}", msg_type);
+                        VIRTIO_CAN_RESULT_NOT_OK
+                    }
+                },
+                Err(e) => {
+                    warn!("virtio-can: failed to read control message: {}", e);
+                    VIRTIO_CAN_RESULT_NOT_OK
+                }
+            };
+            if let Err(e) = avail_desc.writer.write_obj(result) {
+                error!("virtio-can: failed to write control result: {}", e);
+            }
+            let len = avail_desc.writer.bytes_written() as u32;
+            self.ctrl_queue.add_used(avail_desc, len);
+            needs_interrupt = true;
+        }
+        needs_interrupt
+    }
+
+    fn receive(&mut self) -> anyhow::Result<()> {
+        let frame = self
+            .socket
+            .recv()
+            .context("failed to read from CAN socket")?;
+        // Frames are dropped while the controller is stopped, as they would be on the bus.
+        if let Some(frame) = frame {
+            if self.started && frame.supported_by(self.acked_features) {
+                self.pending_rx = Some(frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn deliver_rx(&mut self) -> bool {
+        let Some(frame) = self.pending_rx.take() else {
+            return false;
+        };
+        let Some(mut avail_desc) = self.rx_queue.pop() else {
+            self.pending_rx = Some(frame);
+            return false;
+        };
+        if let Err(e) = frame.write_to_guest(&mut avail_desc.writer) {
+            error!("virtio-can: failed to write received frame: {}", e);
+        }
+        let len = avail_desc.writer.bytes_written() as u32;
+        self.rx_queue.add_used(avail_desc, len);
+        true
+    }
+
+    fn run(&mut self, kill_evt: Event) -> anyhow::Result<()> {
+        #[derive(EventToken)]
+        enum Token {
+            TxQueue,
+            RxQueue,
+            CtrlQueue,
+            Socket,
+            Kill,
+        }
+
+        let wait_ctx = WaitContext::build_with(&[
+            (self.tx_queue.event(), Token::TxQueue),
+            (self.rx_queue.event(), Token::RxQueue),
+            (self.ctrl_queue.event(), Token::CtrlQueue),
+            (&self.socket, Token::Socket),
+            (&kill_evt, Token::Kill),
+        ])
+        .context("failed creating WaitContext")?;
+        let mut socket_enabled = true;
+
+        let mut exiting = false;
+        while !exiting {
+            let events = wait_ctx.wait().context("failed polling for events")?;
+            let mut tx_interrupt = false;
+            let mut ctrl_interrupt = false;
+            for event in events.iter().filter(|e| e.is_readable) {
+                match event.token {
+                    Token::TxQueue => {
+                        self.tx_queue
+                            .event()
+                            .wait()
+                            .context("failed reading tx queue Event")?;
+                        tx_interrupt |= self.process_tx_queue();
+                    }
+                    Token::RxQueue => {
+                        self.rx_queue
+                            .event()
+                            .wait()
+                            .context("failed reading rx queue Event")?;
+                    }
+                    Token::CtrlQueue => {
+                        self.ctrl_queue
+                            .event()
+                            .wait()
+                            .context("failed reading control queue Event")?;
+                        ctrl_interrupt |= self.process_ctrl_queue();
+                    }
+                    Token::Socket => self.receive()?,
+                    Token::Kill => exiting = true,
+                }
+            }
+
+            let rx_interrupt = self.deliver_rx();
+
+            // Stop reading from the socket until the guest makes room for the pending frame.
+            let want_socket = self.pending_rx.is_none();
+            if want_socket != socket_enabled {
+                let event_type = if want_socket {
+                    EventType::Read
+                } else {
+                    EventType::None
+                };
+                wait_ctx
+                    .modify(&self.socket, event_type, Token::Socket)
+                    .context("failed to update CAN socket polling")?;
+                socket_enabled = want_socket;
+            }
+
+            if tx_interrupt {
+                self.tx_queue.trigger_interrupt();
+            }
+            if rx_interrupt {
+                self.rx_queue.trigger_interrupt();
+            }
+            if ctrl_interrupt {
+                self.ctrl_queue.trigger_interrupt();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CanSnapshot {
+    acked_features: u64,
+    started: bool,
+}
+
+/// Virtio device bridging the guest to a host CAN interface.
+pub struct Can {
+    socket: Option<CanSocket>,
+    virtio_features: u64,
+    acked_features: u64,
+    started: bool,
+    worker_thread: Option<WorkerThread<Worker>>,
+}
+
+impl Can {
+    /// Creates a virtio-can device bound to the host interface given in `params`.
+    pub fn new(params: &CanParameters, base_features: u64) -> anyhow::Result<Can> {
+        let socket = CanSocket::open(params)
+            .with_context(|| format!("failed to open CAN interface {}", params.iface))?;
+        let mut virtio_features =
+            base_features | 1 << VIRTIO_CAN_F_CAN_CLASSIC | 1 << VIRTIO_CAN_F_RTR_FRAMES;
+        if params.fd {
+            virtio_features |= 1 << VIRTIO_CAN_F_CAN_FD;
+        }
+        Ok(Can {
+            socket: Some(socket),
+            virtio_features,
+            acked_features: 0,
+            started: false,
+            worker_thread: None,
+        })
+    }
+
+    fn stop_worker(&mut self) -> Option<Worker> {
+        let worker = self.worker_thread.take()?.stop();
+        self.started = worker.started;
+        Some(worker)
+    }
+}
+
+impl VirtioDevice for Can {
+    fn keep_rds(&self) -> Vec<RawDescriptor> {
+        self.socket
+            .iter()
+            .map(|socket| socket.as_raw_descriptor())
+            .collect()
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Can
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        QUEUE_SIZES
+    }
+
+    fn features(&self) -> u64 {
+        self.virtio_features
+    }
+
+    fn ack_features(&mut self, mut value: u64) {
+        if value & !self.virtio_features != 0 {
+            warn!("virtio-can got unknown feature ack {:x}", value);
+            value &= self.virtio_features;
+        }
+        self.acked_features |= value;
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        // The bus-off state is not reported by the host interface, so it is never set.
+        let config = virtio_can_config::default();
+        copy_config(data, 0, config.as_bytes(), offset);
+    }
+
+    fn activate(
+        &mut self,
+        _mem: GuestMemory,
+        _interrupt: Interrupt,
+        mut queues: BTreeMap<usize, Queue>,
+    ) -> anyhow::Result<()> {
+        if queues.len() != QUEUE_SIZES.len() {
+            return Err(anyhow!(
+                "expected {} queues, got {}",
+                QUEUE_SIZES.len(),
+                queues.len()
+            ));
+        }
+        let socket = self
+            .socket
+            .take()
+            .context("virtio-can activated without a socket")?;
+
+        let mut worker = Worker {
+            tx_queue: queues.remove(&TX_QUEUE).unwrap(),
+            rx_queue: queues.remove(&RX_QUEUE).unwrap(),
+            ctrl_queue: queues.remove(&CTRL_QUEUE).unwrap(),
+            socket,
+            acked_features: self.acked_features,
+            started: self.started,
+            pending_rx: None,
+        };
+        self.worker_thread = Some(WorkerThread::start("v_can", move |kill_evt| {
+            if let Err(e) = worker.run(kill_evt) {
+                error!("virtio-can worker thread failed: {:#}", e);
+            }
+            worker
+        }));
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        if let Some(worker) = self.stop_worker() {
+            self.socket = Some(worker.socket);
+        }
+        self.acked_features = 0;
+        self.started = false;
+        Ok(())
+    }
+
+    fn virtio_sleep(&mut self) -> anyhow::Result<Option<BTreeMap<usize, Queue>>> {
+        if let Some(worker) = self.stop_worker() {
+            self.socket = Some(worker.socket);
+            return Ok(Some(BTreeMap::from([
+                (TX_QUEUE, worker.tx_queue),
+                (RX_QUEUE, worker.rx_queue),
+                (CTRL_QUEUE, worker.ctrl_queue),
+            ])));
+        }
+        Ok(None)
+    }
+
+    fn virtio_wake(
+        &mut self,
+        queues_state: Option<(GuestMemory, Interrupt, BTreeMap<usize, Queue>)>,
+    ) -> anyhow::Result<()> {
+        if let Some((mem, interrupt, queues)) = queues_state {
+            self.activate(mem, interrupt, queues)?;
+        }
+        Ok(())
+    }
+
+    fn virtio_snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        // A frame pending delivery when the device went to sleep is dropped, as it would be if the
+        // guest had not provided a buffer in time.
+        AnySnapshot::to_any(CanSnapshot {
+            acked_features: self.acked_features,
+            started: self.started,
+        })
+    }
+
+    fn virtio_restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        let snapshot: CanSnapshot = AnySnapshot::from_any(data)?;
+        self.acked_features = snapshot.acked_features;
+        self.started = snapshot.started;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_keyvalue::from_key_values;
+
+    use super::*;
+
+    #[test]
+    fn params_from_key_values() {
+        assert_eq!(
+            from_key_values::<CanParameters>("iface=vcan0").unwrap(),
+            CanParameters {
+                iface: "vcan0".to_string(),
+                fd: false,
+                filters: Vec::new(),
+            }
+        );
+        assert_eq!(
+            from_key_values::<CanParameters>(
+                "iface=can0,fd=true,filters=[[id=0x100,mask=0x700],[id=0x80000000,mask=0x80000000]]"
+            )
+            .unwrap(),
+            CanParameters {
+                iface: "can0".to_string(),
+                fd: true,
+                filters: vec![
+                    CanFilter {
+                        id: 0x100,
+                        mask: 0x700,
+                    },
+                    CanFilter {
+                        id: CAN_EFF_FLAG,
+                        mask: CAN_EFF_FLAG,
+                    },
+                ],
+            }
+        );
+        assert!(from_key_values::<CanParameters>("fd=true").is_err());
+    }
+
+    #[test]
+    fn socketcan_round_trip() {
+        let frames = [
+            CanFrame {
+                can_id: 0x123,
+                flags: 0,
+                length: 3,
+                data: vec![1, 2, 3],
+            },
+            CanFrame {
+                can_id: 0x1234567,
+                flags: VIRTIO_CAN_FLAGS_EXTENDED | VIRTIO_CAN_FLAGS_FD,
+                length: 12,
+                data: (0..12).collect(),
+            },
+            CanFrame {
+                can_id: 0x7ff,
+                flags: VIRTIO_CAN_FLAGS_RTR,
+                length: 8,
+                data: Vec::new(),
+            },
+        ];
+        for frame in frames {
+            let buf = frame.to_socketcan();
+            assert_eq!(CanFrame::from_socketcan(&buf).unwrap(), Some(frame));
+        }
+
+        let mut error_frame = [0u8; CAN_MTU];
+        error_frame[0..4].copy_from_slice(&CAN_ERR_FLAG.to_ne_bytes());
+        assert_eq!(CanFrame::from_socketcan(&error_frame).unwrap(), None);
+        assert!(CanFrame::from_socketcan(&[0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn supported_by_features() {
+        let fd_frame = CanFrame {
+            can_id: 1,
+            flags: VIRTIO_CAN_FLAGS_FD,
+            length: 0,
+            data: Vec::new(),
+        };
+        assert!(!fd_frame.supported_by(1 << VIRTIO_CAN_F_CAN_CLASSIC));
+        assert!(fd_frame.supported_by(1 << VIRTIO_CAN_F_CAN_FD));
+    }
+}
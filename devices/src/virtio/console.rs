@@ -17,6 +17,7 @@ use std::collections::BTreeMap;
 
 use anyhow::Context;
 use base::RawDescriptor;
+use base::Tube;
 use hypervisor::ProtectionType;
 use snapshot::AnySnapshot;
 use vm_memory::GuestMemory;
@@ -25,6 +26,7 @@ use crate::serial::sys::InStreamType;
 use crate::virtio::console::device::ConsoleDevice;
 use crate::virtio::console::device::ConsoleSnapshot;
 use crate::virtio::console::port::ConsolePort;
+use crate::virtio::console::port::ConsolePortInfo;
 use crate::virtio::DeviceType;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
@@ -48,9 +50,27 @@ impl Console {
         keep_rds: Vec<RawDescriptor>,
         pci_address: Option<PciAddress>,
         max_queue_sizes: Option<Vec<u16>>,
+        max_ports: Option<u32>,
     ) -> Console {
-        let port = ConsolePort::new(input, output, None, keep_rds);
-        let console = ConsoleDevice::new_single_port(protection_type, port);
+        let console = match max_ports {
+            Some(max_ports) => {
+                // With multiport enabled, port 0 is only a console if the driver is told so.
+                let info = ConsolePortInfo {
+                    console: true,
+                    name: None,
+                };
+                let port = ConsolePort::new(input, output, Some(info), keep_rds);
+                ConsoleDevice::new_multi_port_with_max_ports(
+                    protection_type,
+                    vec![port],
+                    max_ports as usize,
+                )
+            }
+            None => {
+                let port = ConsolePort::new(input, output, None, keep_rds);
+                ConsoleDevice::new_single_port(protection_type, port)
+            }
+        };
         let max_queue_sizes =
             max_queue_sizes.unwrap_or_else(|| vec![QUEUE_SIZE; console.max_queues()]);
 
@@ -67,6 +87,12 @@ impl Console {
             pci_address,
         }
     }
+
+    /// Allow ports to be added and removed at runtime through `ConsolePortRequest`s received on
+    /// `control_tube`. The console must have been created with `max_ports`.
+    pub fn enable_port_hotplug(&mut self, control_tube: Tube) {
+        self.console.enable_port_hotplug(control_tube);
+    }
 }
 
 impl VirtioDevice for Console {
@@ -78,6 +104,10 @@ impl VirtioDevice for Console {
         self.console.features()
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Console
     }
@@ -92,6 +122,7 @@ impl VirtioDevice for Console {
 
     fn on_device_sandboxed(&mut self) {
         self.console.start_input_threads();
+        self.console.start_port_hotplug();
     }
 
     fn activate(
@@ -189,6 +220,7 @@ mod tests {
             Vec::new(),
             None,
             None,
+            None,
         );
 
         let context = ConsoleContext {};
@@ -214,6 +246,7 @@ mod tests {
             Vec::new(),
             None,
             None,
+            None,
         );
 
         let context = ConsoleContext { input_pipe_client };
@@ -223,6 +256,12 @@ mod tests {
 
     suspendable_virtio_tests!(console, create_device, 2, modify_device);
 
+    #[test]
+    fn supports_child_process() {
+        let (_ctx, device) = create_device();
+        assert!(device.supports_child_process());
+    }
+
     #[test]
     fn test_inactive_sleep_resume() {
         let (_ctx, mut device) = create_device();
@@ -249,4 +288,70 @@ mod tests {
         // Wake up the device, which should start the input thread again.
         device.virtio_wake(None).expect("failed to wake");
     }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_port_hotplug() {
+        use std::os::fd::OwnedFd;
+        use std::os::unix::net::UnixStream;
+
+        use base::SafeDescriptor;
+        use vm_control::ConsoleControlResult;
+        use vm_control::ConsolePortRequest;
+
+        let (host_tube, device_tube) = Tube::pair().unwrap();
+        let mut device = Console::new(
+            hypervisor::ProtectionType::Unprotected,
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+            Some(2),
+        );
+        device.enable_port_hotplug(device_tube);
+        // Port 0 and port 1 queues, plus the control queues.
+        assert_eq!(device.queue_max_sizes().len(), 6);
+        device.on_device_sandboxed();
+
+        let request = |request: ConsolePortRequest| {
+            host_tube.send(&request).unwrap();
+            host_tube.recv::<ConsoleControlResult>().unwrap()
+        };
+        let add_port = |name: &str| {
+            let (stream, _peer) = UnixStream::pair().unwrap();
+            request(ConsolePortRequest::Add {
+                name: name.to_string(),
+                stream: SafeDescriptor::from(OwnedFd::from(stream)),
+            })
+        };
+        let remove_port = |name: &str| {
+            request(ConsolePortRequest::Remove {
+                name: name.to_string(),
+            })
+        };
+
+        assert_eq!(add_port("agent"), ConsoleControlResult::Ok);
+        assert_eq!(
+            add_port("agent"),
+            ConsoleControlResult::Err(base::Error::new(libc::EEXIST))
+        );
+        assert_eq!(
+            add_port("log"),
+            ConsoleControlResult::Err(base::Error::new(libc::ENOSPC))
+        );
+
+        // Hotplugged ports are not restorable, so snapshotting is refused while one is attached.
+        assert!(device.virtio_snapshot().is_err());
+
+        assert_eq!(remove_port("agent"), ConsoleControlResult::Ok);
+        assert_eq!(
+            remove_port("agent"),
+            ConsoleControlResult::Err(base::Error::new(libc::ENOENT))
+        );
+        assert_eq!(add_port("log"), ConsoleControlResult::Ok);
+        assert_eq!(remove_port("log"), ConsoleControlResult::Ok);
+
+        device.virtio_snapshot().expect("failed to snapshot");
+    }
 }
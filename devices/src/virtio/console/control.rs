@@ -4,6 +4,7 @@
 
 //! Virtio console device control queue handling.
 
+use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::io::Write;
 
@@ -26,7 +27,7 @@ use crate::virtio::Reader;
 
 pub type ControlMsgBytes = Box<[u8]>;
 
-fn control_msg(id: u32, event: u16, value: u16, extra_bytes: &[u8]) -> ControlMsgBytes {
+pub fn control_msg(id: u32, event: u16, value: u16, extra_bytes: &[u8]) -> ControlMsgBytes {
     virtio_console_control {
         id: id.into(),
         event: event.into(),
@@ -41,7 +42,8 @@ fn control_msg(id: u32, event: u16, value: u16, extra_bytes: &[u8]) -> ControlMs
 
 fn process_control_msg(
     reader: &mut Reader,
-    ports: &[WorkerPort],
+    ports: &BTreeMap<u32, WorkerPort>,
+    device_ready: &mut bool,
     pending_receive_control_msgs: &mut VecDeque<ControlMsgBytes>,
 ) -> anyhow::Result<()> {
     let ctrl_msg: virtio_console_control =
@@ -57,8 +59,9 @@ fn process_control_msg(
                 return Err(anyhow!("console device ready failure ({value})"));
             }
 
-            for (index, port) in ports.iter().enumerate() {
-                let port_id = index as u32;
+            *device_ready = true;
+
+            for (&port_id, port) in ports.iter() {
                 // TODO(dverkamp): cap the size of `pending_receive_control_msgs` somehow
                 pending_receive_control_msgs.push_back(control_msg(
                     port_id,
@@ -85,7 +88,7 @@ fn process_control_msg(
             }
 
             let port = ports
-                .get(id as usize)
+                .get(&id)
                 .with_context(|| format!("invalid port id {id}"))?;
 
             pending_receive_control_msgs.push_back(control_msg(
@@ -121,15 +124,19 @@ fn process_control_msg(
 
 pub fn process_control_transmit_queue(
     queue: &mut Queue,
-    ports: &[WorkerPort],
+    ports: &BTreeMap<u32, WorkerPort>,
+    device_ready: &mut bool,
     pending_receive_control_msgs: &mut VecDeque<ControlMsgBytes>,
 ) {
     let mut needs_interrupt = false;
 
     while let Some(mut avail_desc) = queue.pop() {
-        if let Err(e) =
-            process_control_msg(&mut avail_desc.reader, ports, pending_receive_control_msgs)
-        {
+        if let Err(e) = process_control_msg(
+            &mut avail_desc.reader,
+            ports,
+            device_ready,
+            pending_receive_control_msgs,
+        ) {
             error!("failed to handle control msg: {:#}", e);
         }
 
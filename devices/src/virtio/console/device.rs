@@ -4,7 +4,11 @@
 
 //! virtio-console and vhost-user-console device shared backend implementation
 
+use std::collections::BTreeMap;
+
+use base::AsRawDescriptor;
 use base::RawDescriptor;
+use base::Tube;
 use data_model::Le32;
 use hypervisor::ProtectionType;
 use serde::Deserialize;
@@ -14,8 +18,10 @@ use zerocopy::IntoBytes;
 use crate::virtio::base_features;
 use crate::virtio::console::port::ConsolePort;
 use crate::virtio::console::port::ConsolePortSnapshot;
+use crate::virtio::console::worker::PortHotplug;
 use crate::virtio::console::worker::WorkerHandle;
 use crate::virtio::console::worker::WorkerPort;
+use crate::virtio::console::worker::WorkerSnapshot;
 use crate::virtio::copy_config;
 use crate::virtio::device_constants::console::virtio_console_config;
 use crate::virtio::device_constants::console::VIRTIO_CONSOLE_F_MULTIPORT;
@@ -24,6 +30,10 @@ use crate::virtio::Queue;
 pub struct ConsoleDevice {
     avail_features: u64,
     pub(crate) ports: Vec<ConsolePort>,
+    max_ports: usize,
+    // Set if ports can be added at runtime. Moved into the worker while it is running.
+    hotplug: Option<PortHotplug>,
+    hotplug_enabled: bool,
     worker: Option<WorkerHandle>,
 }
 
@@ -31,6 +41,8 @@ pub struct ConsoleDevice {
 pub struct ConsoleSnapshot {
     avail_features: u64,
     pub(super) ports: Vec<ConsolePortSnapshot>,
+    #[serde(default)]
+    worker: Option<WorkerSnapshot>,
 }
 
 impl ConsoleDevice {
@@ -39,6 +51,9 @@ impl ConsoleDevice {
         ConsoleDevice {
             avail_features: base_features(protection_type),
             ports: vec![port],
+            max_ports: 1,
+            hotplug: None,
+            hotplug_enabled: false,
             worker: None,
         }
     }
@@ -55,24 +70,54 @@ impl ConsoleDevice {
 
         ConsoleDevice {
             avail_features,
+            max_ports: ports.len(),
             ports,
+            hotplug: None,
+            hotplug_enabled: false,
             worker: None,
         }
     }
 
+    /// Create a console device with the multiport feature enabled and room for up to `max_ports`
+    /// ports, of which only `ports` exist initially.
+    pub fn new_multi_port_with_max_ports(
+        protection_type: ProtectionType,
+        ports: Vec<ConsolePort>,
+        max_ports: usize,
+    ) -> ConsoleDevice {
+        assert!(max_ports >= ports.len());
+
+        let mut console = ConsoleDevice::new_multi_port(protection_type, ports);
+        console.max_ports = max_ports;
+        console
+    }
+
+    /// Allow ports to be added and removed at runtime through `ConsolePortRequest`s received on
+    /// `control_tube`. Hotplugged ports use the port IDs not taken by the initial ports, up to
+    /// `max_ports()`.
+    pub fn enable_port_hotplug(&mut self, control_tube: Tube) {
+        assert!(self.avail_features & (1 << VIRTIO_CONSOLE_F_MULTIPORT) != 0);
+
+        self.hotplug = Some(PortHotplug {
+            control_tube,
+            ports: BTreeMap::new(),
+        });
+        self.hotplug_enabled = true;
+    }
+
     pub fn features(&self) -> u64 {
         self.avail_features
     }
 
     pub fn max_ports(&self) -> usize {
-        self.ports.len()
+        self.max_ports
     }
 
     /// Returns the maximum number of queues supported by this device.
     pub fn max_queues(&self) -> usize {
         // The port 0 receive and transmit queues always exist;
         // other queues only exist if VIRTIO_CONSOLE_F_MULTIPORT is set.
-        let num_queues = self.ports.len().max(1);
+        let num_queues = self.max_ports.max(1);
         if self.avail_features & (1 << VIRTIO_CONSOLE_F_MULTIPORT) != 0 {
             // Each port has two queues (tx & rx), plus 2 for control receiveq and transmitq.
             num_queues * 2 + 2
@@ -92,26 +137,53 @@ impl ConsoleDevice {
     }
 
     pub fn keep_rds(&self) -> Vec<RawDescriptor> {
-        self.ports.iter().flat_map(ConsolePort::keep_rds).collect()
+        let mut rds: Vec<RawDescriptor> =
+            self.ports.iter().flat_map(ConsolePort::keep_rds).collect();
+        if let Some(hotplug) = &self.hotplug {
+            rds.push(hotplug.control_tube.as_raw_descriptor());
+        }
+        rds
     }
 
     fn ensure_worker_started(&mut self) -> &mut WorkerHandle {
         self.worker.get_or_insert_with(|| {
+            let mut hotplug = self.hotplug.take();
+            let hotplug_ports = hotplug.iter_mut().flat_map(|h| h.ports.iter_mut());
             let ports = self
                 .ports
                 .iter_mut()
-                .map(WorkerPort::from_console_port)
+                .enumerate()
+                .map(|(id, port)| (id as u32, port))
+                .chain(hotplug_ports.map(|(&id, port)| (id, port)))
+                .map(|(id, port)| (id, WorkerPort::from_console_port(port)))
                 .collect();
-            WorkerHandle::new(ports).expect("failed to create console worker")
+            WorkerHandle::new(ports, self.max_ports as u32, hotplug)
+                .expect("failed to create console worker")
         })
     }
 
     fn ensure_worker_stopped(&mut self) {
         if let Some(worker) = self.worker.take() {
-            let ports = worker.stop();
-            for (worker_port, port) in ports.into_iter().zip(self.ports.iter_mut()) {
+            let (ports, mut hotplug) = worker.stop();
+            for (id, worker_port) in ports {
+                let port = match self.ports.get_mut(id as usize) {
+                    Some(port) => port,
+                    None => match hotplug.as_mut().and_then(|h| h.ports.get_mut(&id)) {
+                        Some(port) => port,
+                        None => continue,
+                    },
+                };
                 worker_port.into_console_port(port);
             }
+            self.hotplug = hotplug;
+        }
+    }
+
+    /// Starts servicing port hotplug requests, if enabled. Must only be called after the device
+    /// has been sandboxed.
+    pub fn start_port_hotplug(&mut self) {
+        if self.hotplug_enabled {
+            self.ensure_worker_started();
         }
     }
 
@@ -132,6 +204,8 @@ impl ConsoleDevice {
             let _ = self.stop_queue(idx);
         }
         self.ensure_worker_stopped();
+        // Keep listening for hotplug requests while the driver is not active.
+        self.start_port_hotplug();
         Ok(())
     }
 
@@ -148,6 +222,13 @@ impl ConsoleDevice {
     }
 
     pub fn snapshot(&mut self) -> anyhow::Result<ConsoleSnapshot> {
+        // Hotplugged ports are not part of the device configuration, so the worker refuses to
+        // snapshot while any are attached.
+        let worker = match (self.hotplug_enabled, self.worker.as_mut()) {
+            (true, Some(worker)) => Some(worker.snapshot()?),
+            _ => None,
+        };
+
         let mut ports = Vec::new();
         for port in &mut self.ports {
             ports.push(port.snapshot());
@@ -156,6 +237,7 @@ impl ConsoleDevice {
         Ok(ConsoleSnapshot {
             avail_features: self.avail_features,
             ports,
+            worker,
         })
     }
 
@@ -171,6 +253,10 @@ impl ConsoleDevice {
             port.restore(port_snap);
         }
 
+        if let (Some(worker), Some(worker_snap)) = (self.worker.as_mut(), &snap.worker) {
+            worker.restore(worker_snap.clone())?;
+        }
+
         Ok(())
     }
 }
@@ -3,6 +3,7 @@
 // found in the LICENSE file.
 
 use std::collections::VecDeque;
+use std::fs::File;
 use std::io;
 use std::sync::Arc;
 use std::time::Duration;
@@ -14,6 +15,7 @@ use base::Event;
 use base::EventToken;
 use base::FileSync;
 use base::RawDescriptor;
+use base::SafeDescriptor;
 use base::WaitContext;
 use base::WorkerThread;
 use sync::Mutex;
@@ -46,6 +48,7 @@ impl SerialDevice for Console {
             keep_rds,
             options.pci_address,
             options.max_queue_sizes,
+            options.max_ports,
         )
     }
 }
@@ -88,6 +91,28 @@ impl SerialDevice for ConsolePort {
     }
 }
 
+/// Creates a hotplugged port named `name` that reads from and writes to `stream`.
+pub(in crate::virtio::console) fn create_hotplug_port(
+    name: String,
+    stream: SafeDescriptor,
+) -> anyhow::Result<ConsolePort> {
+    let input = File::from(stream);
+    let output = input
+        .try_clone()
+        .context("failed to clone console port stream")?;
+    let info = ConsolePortInfo {
+        name: Some(name),
+        console: false,
+    };
+    // The device is already sandboxed, so there are no descriptors left to keep.
+    Ok(ConsolePort::new(
+        Some(Box::new(input)),
+        Some(Box::new(output)),
+        Some(info),
+        Vec::new(),
+    ))
+}
+
 /// Starts a thread that reads input and sends the input back via the provided buffer.
 ///
 /// The caller should listen on `in_avail_evt` for events. When `in_avail_evt` signals that data
@@ -13,11 +13,13 @@ use base::named_pipes;
 use base::Event;
 use base::FileSync;
 use base::RawDescriptor;
+use base::SafeDescriptor;
 use base::WorkerThread;
 use sync::Mutex;
 
 use crate::serial_device::SerialInput;
 use crate::serial_device::SerialOptions;
+use crate::virtio::console::port::ConsolePort;
 use crate::virtio::console::Console;
 use crate::virtio::ProtectionType;
 use crate::SerialDevice;
@@ -40,6 +42,7 @@ impl SerialDevice for Console {
             keep_rds,
             options.pci_address,
             options.max_queue_sizes,
+            options.max_ports,
         )
     }
 
@@ -59,10 +62,19 @@ impl SerialDevice for Console {
             keep_rds,
             options.pci_address,
             options.max_queue_sizes,
+            options.max_ports,
         )
     }
 }
 
+/// Console port hotplug is not supported on Windows.
+pub(in crate::virtio::console) fn create_hotplug_port(
+    _name: String,
+    _stream: SafeDescriptor,
+) -> anyhow::Result<ConsolePort> {
+    anyhow::bail!("console port hotplug is not supported on Windows")
+}
+
 /// Platform-specific function to add a delay for reading rx.
 ///
 /// We can't issue blocking reads here and overlapped I/O is
@@ -12,12 +12,26 @@ use std::sync::Arc;
 use anyhow::anyhow;
 use anyhow::Context;
 use base::error;
+use base::Error as SysError;
 use base::Event;
 use base::EventToken;
+use base::ReadNotifier;
+use base::SafeDescriptor;
+use base::Tube;
+use base::TubeError;
 use base::WaitContext;
 use base::WorkerThread;
+use libc::EEXIST;
+use libc::EIO;
+use libc::ENOENT;
+use libc::ENOSPC;
+use serde::Deserialize;
+use serde::Serialize;
 use sync::Mutex;
+use vm_control::ConsoleControlResult;
+use vm_control::ConsolePortRequest;
 
+use crate::virtio::console::control::control_msg;
 use crate::virtio::console::control::process_control_receive_queue;
 use crate::virtio::console::control::process_control_transmit_queue;
 use crate::virtio::console::control::ControlMsgBytes;
@@ -25,6 +39,10 @@ use crate::virtio::console::input::process_receive_queue;
 use crate::virtio::console::output::process_transmit_queue;
 use crate::virtio::console::port::ConsolePort;
 use crate::virtio::console::port::ConsolePortInfo;
+use crate::virtio::console::sys::create_hotplug_port;
+use crate::virtio::device_constants::console::VIRTIO_CONSOLE_DEVICE_ADD;
+use crate::virtio::device_constants::console::VIRTIO_CONSOLE_DEVICE_REMOVE;
+use crate::virtio::device_constants::console::VIRTIO_CONSOLE_PORT_NAME;
 use crate::virtio::Queue;
 
 const PORT0_RECEIVEQ_IDX: usize = 0;
@@ -82,10 +100,26 @@ enum Token {
     InputAvailable(u32),
     ControlReceiveQueueAvailable,
     ControlTransmitQueueAvailable,
+    PortControlTube,
     WorkerRequest,
     Kill,
 }
 
+/// State for adding and removing ports at runtime. It is handed to the worker while it is running
+/// and returned to the device when the worker stops.
+pub struct PortHotplug {
+    /// Receives `ConsolePortRequest`s and replies with `ConsoleControlResult`s.
+    pub control_tube: Tube,
+    /// Ports that were added through `control_tube`, indexed by port ID.
+    pub ports: BTreeMap<u32, ConsolePort>,
+}
+
+/// Worker state that needs to be preserved across snapshot and restore.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorkerSnapshot {
+    device_ready: bool,
+}
+
 pub enum WorkerRequest {
     StartQueue {
         idx: usize,
@@ -96,6 +130,13 @@ pub enum WorkerRequest {
         idx: usize,
         response_sender: mpsc::SyncSender<Option<Queue>>,
     },
+    Snapshot {
+        response_sender: mpsc::SyncSender<anyhow::Result<WorkerSnapshot>>,
+    },
+    Restore {
+        snapshot: WorkerSnapshot,
+        response_sender: mpsc::SyncSender<()>,
+    },
 }
 
 pub struct Worker {
@@ -106,7 +147,16 @@ pub struct Worker {
 
     // Console ports indexed by port ID. At least port 0 will exist, and other ports may be
     // available if `VIRTIO_CONSOLE_F_MULTIPORT` is enabled.
-    ports: Vec<WorkerPort>,
+    ports: BTreeMap<u32, WorkerPort>,
+
+    // Upper bound on the port IDs that may be used by hotplugged ports.
+    max_ports: u32,
+
+    hotplug: Option<PortHotplug>,
+
+    // Whether the driver has sent VIRTIO_CONSOLE_DEVICE_READY, after which ports that are added
+    // or removed must be announced on the control receiveq.
+    device_ready: bool,
 
     // Device-to-driver messages to be received by the driver via the control receiveq.
     pending_receive_control_msgs: VecDeque<ControlMsgBytes>,
@@ -117,7 +167,9 @@ pub struct Worker {
 
 impl Worker {
     pub fn new(
-        ports: Vec<WorkerPort>,
+        ports: BTreeMap<u32, WorkerPort>,
+        max_ports: u32,
+        hotplug: Option<PortHotplug>,
         worker_receiver: mpsc::Receiver<WorkerRequest>,
         worker_event: Event,
     ) -> anyhow::Result<Self> {
@@ -125,15 +177,24 @@ impl Worker {
 
         wait_ctx.add(&worker_event, Token::WorkerRequest)?;
 
-        for (index, port) in ports.iter().enumerate() {
-            let port_id = index as u32;
+        for (&port_id, port) in ports.iter() {
             wait_ctx.add(&port.in_avail_evt, Token::InputAvailable(port_id))?;
         }
 
+        if let Some(hotplug) = &hotplug {
+            wait_ctx.add(
+                hotplug.control_tube.get_read_notifier(),
+                Token::PortControlTube,
+            )?;
+        }
+
         Ok(Worker {
             wait_ctx,
             queues: BTreeMap::new(),
             ports,
+            max_ports,
+            hotplug,
+            device_ready: false,
             pending_receive_control_msgs: VecDeque::new(),
             worker_receiver,
             worker_event,
@@ -155,19 +216,23 @@ impl Worker {
             for event in events.iter().filter(|e| e.is_readable) {
                 match event.token {
                     Token::TransmitQueueAvailable(port_id) => {
-                        if let (Some(port), Some(transmitq)) = (
-                            self.ports.get_mut(port_id as usize),
-                            transmitq_idx(port_id).and_then(|idx| self.queues.get_mut(&idx)),
-                        ) {
+                        if let Some(transmitq) =
+                            transmitq_idx(port_id).and_then(|idx| self.queues.get_mut(&idx))
+                        {
                             transmitq
                                 .event()
                                 .wait()
                                 .context("failed reading transmit queue Event")?;
-                            process_transmit_queue(transmitq, &mut port.output);
+                            // Output written to a port that is not currently present (e.g. one
+                            // that was just removed) is discarded.
+                            match self.ports.get_mut(&port_id) {
+                                Some(port) => process_transmit_queue(transmitq, &mut port.output),
+                                None => process_transmit_queue(transmitq, &mut std::io::sink()),
+                            }
                         }
                     }
                     Token::ReceiveQueueAvailable(port_id) | Token::InputAvailable(port_id) => {
-                        let port = self.ports.get_mut(port_id as usize);
+                        let port = self.ports.get_mut(&port_id);
                         let receiveq =
                             receiveq_idx(port_id).and_then(|idx| self.queues.get_mut(&idx));
 
@@ -206,18 +271,15 @@ impl Worker {
                             process_control_transmit_queue(
                                 ctrl_transmitq,
                                 &self.ports,
+                                &mut self.device_ready,
                                 &mut self.pending_receive_control_msgs,
                             );
                         }
 
                         // Attempt to send any new replies if there is space in the receiveq.
-                        if let Some(ctrl_receiveq) = self.queues.get_mut(&CONTROL_RECEIVEQ_IDX) {
-                            process_control_receive_queue(
-                                ctrl_receiveq,
-                                &mut self.pending_receive_control_msgs,
-                            )
-                        }
+                        self.send_pending_control_msgs();
                     }
+                    Token::PortControlTube => self.process_port_control_tube()?,
                     Token::WorkerRequest => {
                         self.worker_event.wait()?;
                         self.process_worker_requests();
@@ -247,10 +309,155 @@ impl Worker {
                     let res = self.stop_queue(idx);
                     let _ = response_sender.send(res);
                 }
+                WorkerRequest::Snapshot { response_sender } => {
+                    let res = self.snapshot();
+                    let _ = response_sender.send(res);
+                }
+                WorkerRequest::Restore {
+                    snapshot,
+                    response_sender,
+                } => {
+                    self.device_ready = snapshot.device_ready;
+                    let _ = response_sender.send(());
+                }
             }
         }
     }
 
+    fn snapshot(&self) -> anyhow::Result<WorkerSnapshot> {
+        if let Some(hotplug) = &self.hotplug {
+            anyhow::ensure!(
+                hotplug.ports.is_empty(),
+                "cannot snapshot virtio console with {} hotplugged ports",
+                hotplug.ports.len()
+            );
+        }
+        Ok(WorkerSnapshot {
+            device_ready: self.device_ready,
+        })
+    }
+
+    fn send_pending_control_msgs(&mut self) {
+        if let Some(ctrl_receiveq) = self.queues.get_mut(&CONTROL_RECEIVEQ_IDX) {
+            process_control_receive_queue(ctrl_receiveq, &mut self.pending_receive_control_msgs)
+        }
+    }
+
+    fn process_port_control_tube(&mut self) -> anyhow::Result<()> {
+        let Some(hotplug) = &self.hotplug else {
+            return Ok(());
+        };
+        let request = match hotplug.control_tube.recv::<ConsolePortRequest>() {
+            Ok(request) => request,
+            Err(TubeError::Disconnected) => {
+                self.wait_ctx
+                    .delete(hotplug.control_tube.get_read_notifier())
+                    .context("failed to remove port control tube")?;
+                return Ok(());
+            }
+            Err(e) => return Err(e).context("failed to receive console port request"),
+        };
+
+        let res = match request {
+            ConsolePortRequest::Add { name, stream } => self.add_port(name, stream),
+            ConsolePortRequest::Remove { name } => self.remove_port(&name),
+        };
+        let response = match res {
+            Ok(()) => ConsoleControlResult::Ok,
+            Err(e) => ConsoleControlResult::Err(e),
+        };
+
+        if let Some(hotplug) = &self.hotplug {
+            hotplug
+                .control_tube
+                .send(&response)
+                .context("failed to send console port response")?;
+        }
+        Ok(())
+    }
+
+    fn add_port(&mut self, name: String, stream: SafeDescriptor) -> base::Result<()> {
+        if self
+            .ports
+            .values()
+            .any(|port| port.name() == Some(name.as_str()))
+        {
+            error!("console port {name} already exists");
+            return Err(SysError::new(EEXIST));
+        }
+        let port_id = (0..self.max_ports)
+            .find(|id| !self.ports.contains_key(id))
+            .ok_or_else(|| {
+                error!("no free console port for {name}");
+                SysError::new(ENOSPC)
+            })?;
+
+        let mut port = create_hotplug_port(name.clone(), stream).map_err(|e| {
+            error!("failed to create console port {name}: {:#}", e);
+            SysError::new(EIO)
+        })?;
+        port.start_input_thread();
+        let worker_port = WorkerPort::from_console_port(&mut port);
+        self.wait_ctx
+            .add(&worker_port.in_avail_evt, Token::InputAvailable(port_id))?;
+        self.ports.insert(port_id, worker_port);
+        if let Some(hotplug) = self.hotplug.as_mut() {
+            hotplug.ports.insert(port_id, port);
+        }
+
+        if self.device_ready {
+            self.pending_receive_control_msgs.push_back(control_msg(
+                port_id,
+                VIRTIO_CONSOLE_DEVICE_ADD,
+                0,
+                &[],
+            ));
+            self.pending_receive_control_msgs.push_back(control_msg(
+                port_id,
+                VIRTIO_CONSOLE_PORT_NAME,
+                0,
+                name.as_bytes(),
+            ));
+            self.send_pending_control_msgs();
+        }
+        Ok(())
+    }
+
+    fn remove_port(&mut self, name: &str) -> base::Result<()> {
+        // Only hotplugged ports may be removed; ports created with the device are permanent.
+        let hotplug = self.hotplug.as_mut().ok_or_else(|| SysError::new(ENOENT))?;
+        let port_id = hotplug
+            .ports
+            .iter()
+            .find(|(_, port)| port.port_info().and_then(ConsolePortInfo::name) == Some(name))
+            .map(|(&id, _)| id)
+            .ok_or_else(|| {
+                error!("no hotplugged console port named {name}");
+                SysError::new(ENOENT)
+            })?;
+
+        let mut port = hotplug
+            .ports
+            .remove(&port_id)
+            .expect("missing hotplugged port");
+        if let Some(worker_port) = self.ports.remove(&port_id) {
+            let _ = self.wait_ctx.delete(&worker_port.in_avail_evt);
+            worker_port.into_console_port(&mut port);
+        }
+        port.stop_input_thread();
+
+        if self.device_ready {
+            self.pending_receive_control_msgs.push_back(control_msg(
+                port_id,
+                VIRTIO_CONSOLE_DEVICE_REMOVE,
+                0,
+                &[],
+            ));
+            self.send_pending_control_msgs();
+        }
+        Ok(())
+    }
+
     fn start_queue(&mut self, idx: usize, queue: Queue) -> anyhow::Result<()> {
         if let Some(port_id) = receiveq_port_id(idx) {
             self.wait_ctx
@@ -284,23 +491,33 @@ impl Worker {
 }
 
 pub struct WorkerHandle {
-    worker_thread: WorkerThread<Vec<WorkerPort>>,
+    worker_thread: WorkerThread<(BTreeMap<u32, WorkerPort>, Option<PortHotplug>)>,
     worker_sender: mpsc::Sender<WorkerRequest>,
     worker_event: Event,
 }
 
 impl WorkerHandle {
-    pub fn new(ports: Vec<WorkerPort>) -> anyhow::Result<Self> {
+    pub fn new(
+        ports: BTreeMap<u32, WorkerPort>,
+        max_ports: u32,
+        hotplug: Option<PortHotplug>,
+    ) -> anyhow::Result<Self> {
         let worker_event = Event::new().context("Event::new")?;
         let worker_event_clone = worker_event.try_clone().context("Event::try_clone")?;
         let (worker_sender, worker_receiver) = mpsc::channel();
         let worker_thread = WorkerThread::start("v_console", move |kill_evt| {
-            let mut worker = Worker::new(ports, worker_receiver, worker_event_clone)
-                .expect("console Worker::new() failed");
+            let mut worker = Worker::new(
+                ports,
+                max_ports,
+                hotplug,
+                worker_receiver,
+                worker_event_clone,
+            )
+            .expect("console Worker::new() failed");
             if let Err(e) = worker.run(&kill_evt) {
                 error!("console worker failed: {:#}", e);
             }
-            worker.ports
+            (worker.ports, worker.hotplug)
         });
         Ok(WorkerHandle {
             worker_thread,
@@ -334,7 +551,28 @@ impl WorkerHandle {
         response_receiver.recv().context("mpsc::Receiver::recv")
     }
 
-    pub fn stop(self) -> Vec<WorkerPort> {
+    pub fn snapshot(&mut self) -> anyhow::Result<WorkerSnapshot> {
+        let (response_sender, response_receiver) = mpsc::sync_channel(0);
+        self.worker_sender
+            .send(WorkerRequest::Snapshot { response_sender })
+            .context("mpsc::Sender::send")?;
+        self.worker_event.signal().context("Event::signal")?;
+        response_receiver.recv().context("mpsc::Receiver::recv")?
+    }
+
+    pub fn restore(&mut self, snapshot: WorkerSnapshot) -> anyhow::Result<()> {
+        let (response_sender, response_receiver) = mpsc::sync_channel(0);
+        self.worker_sender
+            .send(WorkerRequest::Restore {
+                snapshot,
+                response_sender,
+            })
+            .context("mpsc::Sender::send")?;
+        self.worker_event.signal().context("Event::signal")?;
+        response_receiver.recv().context("mpsc::Receiver::recv")
+    }
+
+    pub fn stop(self) -> (BTreeMap<u32, WorkerPort>, Option<PortHotplug>) {
         self.worker_thread.stop()
     }
 }
@@ -4,6 +4,8 @@
 
 use std::collections::BTreeMap;
 use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use anyhow::anyhow;
@@ -11,8 +13,13 @@ use base::error;
 use base::warn;
 use base::AsRawDescriptor;
 use base::Error as SysError;
+use base::Event;
+use base::EventToken;
 use base::RawDescriptor;
+use base::ReadNotifier;
 use base::Tube;
+use base::TubeError;
+use base::WaitContext;
 use base::WorkerThread;
 use data_model::Le32;
 use remain::sorted;
@@ -21,6 +28,8 @@ use sync::Mutex;
 use thiserror::Error;
 use virtio_sys::virtio_fs::virtio_fs_config;
 use virtio_sys::virtio_fs::VIRTIO_FS_SHMCAP_ID_CACHE;
+use vm_control::FsControlCommand;
+use vm_control::FsControlResult;
 use vm_control::FsMappingRequest;
 use vm_control::VmResponse;
 use vm_memory::GuestMemory;
@@ -126,6 +135,9 @@ pub struct Fs {
     pci_bar: Option<Alloc>,
     tube: Option<Tube>,
     workers: Vec<WorkerThread<Result<()>>>,
+    read_only: Arc<AtomicBool>,
+    control_tube: Option<Tube>,
+    control_worker: Option<WorkerThread<()>>,
 }
 
 impl Fs {
@@ -155,6 +167,7 @@ impl Fs {
 
         // TODO(b/176129399): Remove cfg! once DAX is supported on ARM.
         let use_dax = cfg!(target_arch = "x86_64") && fs.cfg().use_dax;
+        let read_only = fs.read_only();
 
         Ok(Fs {
             cfg,
@@ -167,8 +180,67 @@ impl Fs {
             pci_bar: None,
             tube: Some(tube),
             workers: Vec::with_capacity(num_workers + 1),
+            read_only,
+            control_tube: None,
+            control_worker: None,
         })
     }
+
+    /// Sets the tube receiving `FsControlCommand`s, which are handled once the device is active.
+    pub fn set_control_tube(&mut self, control_tube: Tube) {
+        self.control_tube = Some(control_tube);
+    }
+}
+
+#[derive(EventToken)]
+enum ControlToken {
+    Command,
+    Kill,
+}
+
+/// Handles the `FsControlCommand`s received on `control_tube` until `kill_evt` is signaled.
+fn run_control(control_tube: Tube, kill_evt: Event, read_only: Arc<AtomicBool>) {
+    let wait_ctx: WaitContext<ControlToken> = match WaitContext::build_with(&[
+        (control_tube.get_read_notifier(), ControlToken::Command),
+        (&kill_evt, ControlToken::Kill),
+    ]) {
+        Ok(wait_ctx) => wait_ctx,
+        Err(e) => {
+            error!("virtio-fs: failed to create control wait context: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        let events = match wait_ctx.wait() {
+            Ok(events) => events,
+            Err(e) => {
+                error!("virtio-fs: failed to wait for control events: {}", e);
+                return;
+            }
+        };
+        for event in events.iter().filter(|e| e.is_readable) {
+            match event.token {
+                ControlToken::Command => {
+                    let result = match control_tube.recv::<FsControlCommand>() {
+                        Ok(FsControlCommand::SetReadOnly { read_only: value }) => {
+                            read_only.store(value, Ordering::Release);
+                            FsControlResult::Ok
+                        }
+                        Err(TubeError::Disconnected) => return,
+                        Err(e) => {
+                            error!("virtio-fs: failed to receive control command: {}", e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = control_tube.send(&result) {
+                        error!("virtio-fs: failed to send control result: {}", e);
+                    }
+                }
+                ControlToken::Kill => return,
+            }
+        }
+    }
 }
 
 impl VirtioDevice for Fs {
@@ -181,10 +253,17 @@ impl VirtioDevice for Fs {
         if let Some(rd) = self.tube.as_ref().map(|s| s.as_raw_descriptor()) {
             fds.push(rd);
         }
+        if let Some(rd) = self.control_tube.as_ref().map(|s| s.as_raw_descriptor()) {
+            fds.push(rd);
+        }
 
         fds
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Fs
     }
@@ -264,6 +343,14 @@ impl VirtioDevice for Fs {
                 })
             })
             .collect();
+
+        if let Some(control_tube) = self.control_tube.take() {
+            let read_only = self.read_only.clone();
+            self.control_worker = Some(WorkerThread::start(
+                format!("v_fs_ctl:{}", self.tag),
+                move |kill_evt| run_control(control_tube, kill_evt, read_only),
+            ));
+        }
         Ok(())
     }
 
@@ -273,6 +360,7 @@ impl VirtioDevice for Fs {
         }
 
         self.pci_bar = Some(Alloc::PciBar {
+            domain: address.domain,
             bus: address.bus,
             dev: address.dev,
             func: address.func,
@@ -710,6 +710,10 @@ pub struct PassthroughFs {
     // Whether zero message opendir is supported by the kernel driver.
     zero_message_opendir: AtomicBool,
 
+    // Whether requests modifying the file system are rejected with EROFS. Shared with the device
+    // so that the share can be made read-only, or writable again, while the guest is running.
+    read_only: Arc<AtomicBool>,
+
     // Used to communicate with other processes using D-Bus.
     #[cfg(feature = "arc_quota")]
     dbus_connection: Option<Mutex<dbus::blocking::Connection>>,
@@ -755,6 +759,7 @@ impl std::fmt::Debug for PassthroughFs {
             .field("writeback", &self.writeback)
             .field("zero_message_open", &self.zero_message_open)
             .field("zero_message_opendir", &self.zero_message_opendir)
+            .field("read_only", &self.read_only)
             .field("cfg", &self.cfg)
             .finish()
     }
@@ -812,6 +817,8 @@ impl PassthroughFs {
             zero_message_open: AtomicBool::new(false),
             zero_message_opendir: AtomicBool::new(false),
 
+            read_only: Arc::new(AtomicBool::new(false)),
+
             #[cfg(feature = "arc_quota")]
             dbus_connection,
             #[cfg(feature = "arc_quota")]
@@ -866,6 +873,29 @@ impl PassthroughFs {
         &self.cfg
     }
 
+    /// Returns the flag that makes the file system reject modifications when set.
+    pub fn read_only(&self) -> Arc<AtomicBool> {
+        self.read_only.clone()
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only.load(Ordering::Acquire) {
+            Err(io::Error::from_raw_os_error(libc::EROFS))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Rejects opening a file for modification while the file system is read-only.
+    fn check_open_flags(&self, flags: u32) -> io::Result<()> {
+        let flags = flags as i32;
+        if flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0 {
+            self.check_writable()
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn keep_rds(&self) -> Vec<RawDescriptor> {
         #[cfg_attr(not(feature = "arc_quota"), allow(unused_mut))]
         let mut keep_rds = vec![self.proc.as_raw_descriptor()];
@@ -1174,6 +1204,7 @@ impl PassthroughFs {
     }
 
     fn do_open(&self, inode: Inode, flags: u32) -> io::Result<(Option<Handle>, OpenOptions)> {
+        self.check_open_flags(flags)?;
         let inode_data = self.find_inode(inode)?;
 
         let file = Mutex::new(self.open_inode(&inode_data, flags as i32)?);
@@ -1195,6 +1226,7 @@ impl PassthroughFs {
         inode: Inode,
         flags: u32,
     ) -> io::Result<(Option<Handle>, OpenOptions)> {
+        self.check_open_flags(flags)?;
         let open_flags = self.update_open_flags(flags as i32);
 
         let fd_open = syscall!(
@@ -2322,6 +2354,7 @@ impl FileSystem for PassthroughFs {
         security_ctx: Option<&CStr>,
     ) -> io::Result<Entry> {
         let _trace = fs_trace!(self.tag, "mkdir", parent, name, mode, umask, security_ctx);
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
 
         let _ctx = security_ctx
@@ -2353,6 +2386,7 @@ impl FileSystem for PassthroughFs {
 
     fn rmdir(&self, _ctx: Context, parent: Inode, name: &CStr) -> io::Result<()> {
         let _trace = fs_trace!(self.tag, "rmdir", parent, name);
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
         let casefold_cache = self.lock_casefold_lookup_caches();
         // TODO(b/278691962): If ascii_casefold is enabled, we need to call
@@ -2437,6 +2471,7 @@ impl FileSystem for PassthroughFs {
             umask,
             security_ctx
         );
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
 
         let _ctx = security_ctx
@@ -2504,6 +2539,7 @@ impl FileSystem for PassthroughFs {
             umask,
             security_ctx
         );
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
 
         let _ctx = security_ctx
@@ -2573,6 +2609,7 @@ impl FileSystem for PassthroughFs {
 
     fn unlink(&self, _ctx: Context, parent: Inode, name: &CStr) -> io::Result<()> {
         let _trace = fs_trace!(self.tag, "unlink", parent, name);
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
         let casefold_cache = self.lock_casefold_lookup_caches();
         // TODO(b/278691962): If ascii_casefold is enabled, we need to call
@@ -2636,6 +2673,7 @@ impl FileSystem for PassthroughFs {
         _delayed_write: bool,
         flags: u32,
     ) -> io::Result<usize> {
+        self.check_writable()?;
         // When the WRITE_KILL_PRIV flag is set, drop CAP_FSETID so that the kernel will
         // automatically clear the setuid and setgid bits for us.
         let _fsetid = if flags & WRITE_KILL_PRIV != 0 {
@@ -2703,6 +2741,7 @@ impl FileSystem for PassthroughFs {
         valid: SetattrValid,
     ) -> io::Result<(libc::stat64, Duration)> {
         let _trace = fs_trace!(self.tag, "setattr", inode, handle);
+        self.check_writable()?;
         let inode_data = self.find_inode(inode)?;
 
         enum Data<'a> {
@@ -2824,6 +2863,7 @@ impl FileSystem for PassthroughFs {
         flags: u32,
     ) -> io::Result<()> {
         let _trace = fs_trace!(self.tag, "rename", olddir, oldname, newdir, newname, flags);
+        self.check_writable()?;
 
         let old_inode = self.find_inode(olddir)?;
         let new_inode = self.find_inode(newdir)?;
@@ -2872,6 +2912,7 @@ impl FileSystem for PassthroughFs {
             umask,
             security_ctx
         );
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
 
         let _ctx = security_ctx
@@ -2917,6 +2958,7 @@ impl FileSystem for PassthroughFs {
         newname: &CStr,
     ) -> io::Result<Entry> {
         let _trace = fs_trace!(self.tag, "link", inode, newparent, newname);
+        self.check_writable()?;
         let data = self.find_inode(inode)?;
         let new_inode = self.find_inode(newparent)?;
 
@@ -2952,6 +2994,7 @@ impl FileSystem for PassthroughFs {
         security_ctx: Option<&CStr>,
     ) -> io::Result<Entry> {
         let _trace = fs_trace!(self.tag, "symlink", parent, linkname, name, security_ctx);
+        self.check_writable()?;
         let data = self.find_inode(parent)?;
 
         let _ctx = security_ctx
@@ -3133,6 +3176,7 @@ impl FileSystem for PassthroughFs {
         flags: u32,
     ) -> io::Result<()> {
         let _trace = fs_trace!(self.tag, "setxattr", inode, name, flags);
+        self.check_writable()?;
         // We can't allow the VM to set this xattr because an unprivileged process may use it to set
         // a privileged xattr.
         if self.cfg.rewrite_security_xattrs && name.to_bytes().starts_with(USER_VIRTIOFS_XATTR) {
@@ -3273,6 +3317,7 @@ impl FileSystem for PassthroughFs {
 
     fn removexattr(&self, _ctx: Context, inode: Inode, name: &CStr) -> io::Result<()> {
         let _trace = fs_trace!(self.tag, "removexattr", inode, name);
+        self.check_writable()?;
         // We don't allow the VM to set this xattr so we also pretend there is no value associated
         // with it.
         if self.cfg.rewrite_security_xattrs && name.to_bytes().starts_with(USER_VIRTIOFS_XATTR) {
@@ -3315,6 +3360,7 @@ impl FileSystem for PassthroughFs {
         length: u64,
     ) -> io::Result<()> {
         let _trace = fs_trace!(self.tag, "fallocate", inode, handle, mode, offset, length);
+        self.check_writable()?;
 
         let data: Arc<dyn AsRawDescriptor> = if self.zero_message_open.load(Ordering::Relaxed) {
             let data = self.find_inode(inode)?;
@@ -3463,6 +3509,7 @@ impl FileSystem for PassthroughFs {
             length,
             flags
         );
+        self.check_writable()?;
         // We need to change credentials during a write so that the kernel will remove setuid or
         // setgid bits from the file if it was written to by someone other than the owner.
         let (_uid, _gid) = set_creds(ctx.uid, ctx.gid)?;
@@ -3522,6 +3569,9 @@ impl FileSystem for PassthroughFs {
 
         let read = prot & libc::PROT_READ as u32 != 0;
         let write = prot & libc::PROT_WRITE as u32 != 0;
+        if write {
+            self.check_writable()?;
+        }
         let (mmap_flags, prot) = match (read, write) {
             (true, true) => (libc::O_RDWR, Protection::read_write()),
             (true, false) => (libc::O_RDONLY, Protection::read()),
@@ -3924,6 +3974,47 @@ mod tests {
         assert_eq!(&actual[..], b"security.sehash");
     }
 
+    #[test]
+    fn read_only() {
+        // Since PassthroughFs may executes process-wide operations such as `fchdir`, acquire
+        // `NamedLock` before starting each unit test creating a `PassthroughFs` instance.
+        let lock = NamedLock::create(UNITTEST_LOCK_NAME).expect("create named lock");
+        let _guard = lock.lock().expect("acquire named lock");
+
+        let temp_dir = TempDir::new().unwrap();
+        create_test_data(&temp_dir, &[], &["a.txt"]);
+
+        let fs = PassthroughFs::new("tag", Default::default()).unwrap();
+        fs.init(FsOptions::empty()).unwrap();
+
+        let read_only = fs.read_only();
+        read_only.store(true, Ordering::Release);
+        assert_eq!(
+            create(&fs, &temp_dir.path().join("b.txt"))
+                .expect_err("file must not be created")
+                .raw_os_error(),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            unlink(&fs, &temp_dir.path().join("a.txt"))
+                .expect_err("file must not be removed")
+                .raw_os_error(),
+            Some(libc::EROFS)
+        );
+        let inode = lookup(&fs, &temp_dir.path().join("a.txt")).unwrap();
+        assert!(fs.open(get_context(), inode, libc::O_RDONLY as u32).is_ok());
+        assert_eq!(
+            fs.open(get_context(), inode, libc::O_RDWR as u32)
+                .expect_err("file must not be opened for writing")
+                .raw_os_error(),
+            Some(libc::EROFS)
+        );
+
+        read_only.store(false, Ordering::Release);
+        assert!(create(&fs, &temp_dir.path().join("b.txt")).is_ok());
+        assert!(unlink(&fs, &temp_dir.path().join("a.txt")).is_ok());
+    }
+
     #[test]
     fn lookup_files() {
         // Since PassthroughFs may executes process-wide operations such as `fchdir`, acquire
@@ -13,6 +13,9 @@ use super::protocol::VirtioGpuResult;
 use crate::virtio::gpu::GpuDisplayParameters;
 
 const EDID_DATA_LENGTH: usize = 128;
+// Size of the EDID field of the response to VIRTIO_GPU_CMD_GET_EDID.
+const MAX_EDID_LENGTH: usize = 1024;
+const EDID_HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
 const DEFAULT_HORIZONTAL_BLANKING: u16 = 560;
 const DEFAULT_VERTICAL_BLANKING: u16 = 50;
 const DEFAULT_HORIZONTAL_FRONT_PORCH: u16 = 64;
@@ -33,7 +36,7 @@ const MILLIMETERS_PER_INCH: f32 = 25.4;
 /// information and no other form of timing information.
 #[repr(C)]
 pub struct EdidBytes {
-    bytes: [u8; EDID_DATA_LENGTH],
+    bytes: Vec<u8>,
 }
 
 impl EdidBytes {
@@ -55,7 +58,16 @@ impl EdidBytes {
 
         calculate_checksum(&mut edid);
 
-        Ok(OkEdid(Box::new(Self { bytes: edid })))
+        Ok(OkEdid(Box::new(Self {
+            bytes: edid.to_vec(),
+        })))
+    }
+
+    /// Wraps an EDID provided by the host, which must have been checked by `check_edid_blob()`.
+    pub fn from_blob(blob: &[u8]) -> VirtioGpuResult {
+        Ok(OkEdid(Box::new(Self {
+            bytes: blob.to_vec(),
+        })))
     }
 
     pub fn len(&self) -> usize {
@@ -67,6 +79,36 @@ impl EdidBytes {
     }
 }
 
+/// Checks that `blob` is a base EDID block followed by the extension blocks it announces, each with
+/// a valid checksum, and that it fits in the response to VIRTIO_GPU_CMD_GET_EDID.
+pub fn check_edid_blob(blob: &[u8]) -> Result<(), String> {
+    if blob.is_empty() || blob.len() % EDID_DATA_LENGTH != 0 || blob.len() > MAX_EDID_LENGTH {
+        return Err(format!(
+            "EDID of {} bytes isn't made of 1 to {} blocks of {} bytes",
+            blob.len(),
+            MAX_EDID_LENGTH / EDID_DATA_LENGTH,
+            EDID_DATA_LENGTH
+        ));
+    }
+    if blob[..EDID_HEADER.len()] != EDID_HEADER {
+        return Err("EDID doesn't start with the EDID header".to_string());
+    }
+    let extensions = blob[126] as usize;
+    if extensions + 1 != blob.len() / EDID_DATA_LENGTH {
+        return Err(format!(
+            "EDID announces {} extension blocks but has {}",
+            extensions,
+            blob.len() / EDID_DATA_LENGTH - 1
+        ));
+    }
+    for (i, block) in blob.chunks_exact(EDID_DATA_LENGTH).enumerate() {
+        if block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(format!("invalid checksum of EDID block {}", i));
+        }
+    }
+    Ok(())
+}
+
 impl Debug for EdidBytes {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.bytes[..].fmt(f)
@@ -359,3 +401,37 @@ fn calculate_checksum(edid: &mut [u8]) {
 
     edid[127] = checksum;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generated_edid() -> Vec<u8> {
+        match EdidBytes::new(&DisplayInfo::new(&Default::default())) {
+            Ok(OkEdid(edid)) => edid.as_bytes().to_vec(),
+            _ => panic!("failed to generate the EDID"),
+        }
+    }
+
+    #[test]
+    fn check_generated_edid() {
+        let mut edid = generated_edid();
+        assert!(check_edid_blob(&edid).is_ok());
+
+        edid[20] = edid[20].wrapping_add(1);
+        assert!(check_edid_blob(&edid).is_err());
+    }
+
+    #[test]
+    fn check_edid_extensions() {
+        let mut edid = generated_edid();
+        // Announce an extension block whose checksum is valid since it is all zeroes.
+        edid[126] = 1;
+        edid[127] = edid[127].wrapping_sub(1);
+        assert!(check_edid_blob(&edid).is_err());
+
+        edid.extend_from_slice(&[0; EDID_DATA_LENGTH]);
+        assert!(check_edid_blob(&edid).is_ok());
+        assert!(check_edid_blob(&edid[..100]).is_err());
+    }
+}
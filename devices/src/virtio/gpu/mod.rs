@@ -10,6 +10,7 @@ mod virtio_gpu;
 
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -17,6 +18,7 @@ use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use ::snapshot::AnySnapshot;
 use anyhow::anyhow;
@@ -39,6 +41,8 @@ use base::RecvTube;
 use base::Result;
 use base::SafeDescriptor;
 use base::SendTube;
+use base::Timer;
+use base::TimerTrait;
 use base::Tube;
 use base::VmEventType;
 use base::WaitContext;
@@ -48,6 +52,7 @@ pub use gpu_display::EventDevice;
 use gpu_display::*;
 use hypervisor::MemCacheType;
 pub use parameters::AudioDeviceMode;
+pub use parameters::GpuBlobFile;
 pub use parameters::GpuParameters;
 use rutabaga_gfx::*;
 use serde::Deserialize;
@@ -55,6 +60,7 @@ use serde::Serialize;
 use sync::Mutex;
 pub use vm_control::gpu::DisplayMode as GpuDisplayMode;
 pub use vm_control::gpu::DisplayParameters as GpuDisplayParameters;
+pub use vm_control::gpu::DisplayRotation as GpuDisplayRotation;
 use vm_control::gpu::GpuControlCommand;
 use vm_control::gpu::GpuControlResult;
 pub use vm_control::gpu::MouseMode as GpuMouseMode;
@@ -63,6 +69,8 @@ pub use vm_control::gpu::DEFAULT_DISPLAY_WIDTH;
 pub use vm_control::gpu::DEFAULT_REFRESH_RATE;
 #[cfg(windows)]
 use vm_control::ModifyWaitContext;
+#[cfg(feature = "registered_events")]
+use vm_control::RegisteredEventWithData;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use zerocopy::IntoBytes;
@@ -79,6 +87,7 @@ pub use self::protocol::VIRTIO_GPU_MAX_SCANOUTS;
 pub use self::protocol::VIRTIO_GPU_SHM_ID_HOST_VISIBLE;
 use self::protocol::*;
 use self::virtio_gpu::to_rutabaga_descriptor;
+use self::virtio_gpu::MemoryPressure;
 pub use self::virtio_gpu::ProcessDisplayResult;
 use self::virtio_gpu::VirtioGpu;
 use self::virtio_gpu::VirtioGpuSnapshot;
@@ -260,6 +269,8 @@ fn build(
     udmabuf: bool,
     #[cfg(windows)] gpu_display_wait_descriptor_ctrl_wr: SendTube,
     snapshot_scratch_directory: Option<PathBuf>,
+    memory_budget: Option<u64>,
+    memory_event: Arc<AtomicBool>,
 ) -> Option<VirtioGpu> {
     let mut display_opt = None;
     for display_backend in display_backends {
@@ -297,6 +308,8 @@ fn build(
         fixed_blob_mapping,
         udmabuf,
         snapshot_scratch_directory,
+        memory_budget,
+        memory_event,
     )
 }
 
@@ -309,6 +322,26 @@ where
     pub ctrl_queue: Q,
 }
 
+/// Opens a host file the guest can create blob resources from, returning a descriptor of it along
+/// with the clone handed to rutabaga.
+fn open_blob_file(blob_file: &GpuBlobFile) -> anyhow::Result<(SafeDescriptor, RutabagaBlobFile)> {
+    let file = File::open(&blob_file.path).context("failed to open file")?;
+    let size = file.metadata().context("failed to get file size")?.len();
+    let descriptor = SafeDescriptor::from(file);
+    let clone = descriptor
+        .try_clone()
+        .context("failed to clone file descriptor")?;
+    let rutabaga_blob_file = RutabagaBlobFile {
+        blob_id: blob_file.id,
+        handle: Arc::new(RutabagaHandle {
+            os_handle: to_rutabaga_descriptor(clone),
+            handle_type: RUTABAGA_HANDLE_TYPE_MEM_FILE,
+        }),
+        size,
+    };
+    Ok((descriptor, rutabaga_blob_file))
+}
+
 /// Create a handler that writes into the completed fence queue
 pub fn create_fence_handler<Q>(
     fence_handler_resources: Arc<Mutex<Option<FenceHandlerActivationResources<Q>>>>,
@@ -318,8 +351,16 @@ where
     Q: QueueReader + Send + Clone + 'static,
 {
     RutabagaFenceHandler::new(move |completed_fence: RutabagaFence| {
+        let _trace = cros_tracing::trace_event!(gpu, "fence_completed");
         let mut signal = false;
 
+        if completed_fence.flags & RUTABAGA_FLAG_FENCE_ERROR != 0 {
+            warn!(
+                "fence {} signaled for killed context {}",
+                completed_fence.fence_id, completed_fence.ctx_id
+            );
+        }
+
         if let Some(ref fence_handler_resources) = *fence_handler_resources.lock() {
             // Limits the lifetime of `fence_state`:
             {
@@ -406,8 +447,12 @@ impl Frontend {
 
     /// Processes the GPU control command and returns the result with a bool indicating if the
     /// GPU device's config needs to be updated.
-    pub fn process_gpu_control_command(&mut self, cmd: GpuControlCommand) -> GpuControlResult {
-        self.virtio_gpu.process_gpu_control_command(cmd)
+    pub fn process_gpu_control_command(
+        &mut self,
+        mem: &GuestMemory,
+        cmd: GpuControlCommand,
+    ) -> GpuControlResult {
+        self.virtio_gpu.process_gpu_control_command(mem, cmd)
     }
 
     fn process_gpu_command(
@@ -511,7 +556,9 @@ impl Frontend {
                 .virtio_gpu
                 .get_capset(info.capset_id.to_native(), info.capset_version.to_native()),
             GpuCommand::CtxCreate(info) => {
-                let context_name: Option<String> = String::from_utf8(info.debug_name.to_vec()).ok();
+                let nlen = (info.nlen.to_native() as usize).min(info.debug_name.len());
+                let context_name: Option<String> =
+                    String::from_utf8(info.debug_name[..nlen].to_vec()).ok();
                 self.virtio_gpu.create_context(
                     info.hdr.ctx_id.to_native(),
                     info.context_init.to_native(),
@@ -840,6 +887,7 @@ enum WorkerToken {
         index: usize,
     },
     VirtioGpuPoll,
+    FenceWatchdog,
     #[cfg(windows)]
     DisplayDescriptorRequest,
 }
@@ -934,6 +982,9 @@ struct Worker {
     #[cfg(windows)]
     gpu_display_wait_descriptor_ctrl_rd: RecvTube,
     activation_resources: Option<GpuActivationResources>,
+    fence_watchdog: Option<Duration>,
+    #[cfg(feature = "registered_events")]
+    registered_evt_q: Option<SendTube>,
 }
 
 #[derive(Copy, Clone)]
@@ -971,6 +1022,10 @@ impl Worker {
         #[cfg(windows)] gpu_display_wait_descriptor_ctrl_rd: RecvTube,
         #[cfg(windows)] gpu_display_wait_descriptor_ctrl_wr: SendTube,
         snapshot_scratch_directory: Option<PathBuf>,
+        fence_watchdog: Option<Duration>,
+        memory_budget: Option<u64>,
+        memory_event: Arc<AtomicBool>,
+        #[cfg(feature = "registered_events")] registered_evt_q: Option<SendTube>,
     ) -> anyhow::Result<Worker> {
         let fence_state = Arc::new(Mutex::new(Default::default()));
         let fence_handler_resources = Arc::new(Mutex::new(None));
@@ -991,6 +1046,8 @@ impl Worker {
             #[cfg(windows)]
             gpu_display_wait_descriptor_ctrl_wr,
             snapshot_scratch_directory,
+            memory_budget,
+            memory_event,
         )
         .ok_or_else(|| anyhow!("failed to build virtio gpu"))?;
 
@@ -1015,6 +1072,9 @@ impl Worker {
             #[cfg(windows)]
             gpu_display_wait_descriptor_ctrl_rd,
             activation_resources: None,
+            fence_watchdog,
+            #[cfg(feature = "registered_events")]
+            registered_evt_q,
         })
     }
 
@@ -1184,6 +1244,21 @@ impl Worker {
                 .context("failed adding poll event to WaitContext")?;
         }
 
+        let mut fence_watchdog_timer = None;
+        let fence_watchdog_desc: SafeDescriptor;
+        if let Some(period) = self.fence_watchdog {
+            let mut timer = Timer::new().context("failed to create fence watchdog timer")?;
+            timer
+                .reset_repeating(period)
+                .context("failed to arm fence watchdog timer")?;
+            fence_watchdog_desc = SafeDescriptor::try_from(&timer as &dyn AsRawDescriptor)
+                .context("failed getting fence watchdog timer descriptor")?;
+            event_manager
+                .add(&fence_watchdog_desc, WorkerToken::FenceWatchdog)
+                .context("failed adding fence watchdog timer to WaitContext")?;
+            fence_watchdog_timer = Some(timer);
+        }
+
         self.resource_bridges
             .add_to_wait_context(&mut event_manager.wait_ctx);
 
@@ -1267,9 +1342,14 @@ impl Worker {
                             .gpu_control_tube
                             .recv()
                             .context("failed to recv from gpu control socket")?;
-                        let resp = self.state.process_gpu_control_command(req);
+                        let resp = self
+                            .state
+                            .process_gpu_control_command(&activation_resources.mem, req);
 
-                        if let GpuControlResult::DisplaysUpdated = resp {
+                        if let GpuControlResult::DisplaysUpdated
+                        | GpuControlResult::DisplayConfigured
+                        | GpuControlResult::DisplayEdidSet = resp
+                        {
                             needs_config_interrupt = true;
                         }
 
@@ -1283,6 +1363,15 @@ impl Worker {
                     WorkerToken::VirtioGpuPoll => {
                         self.state.event_poll();
                     }
+                    WorkerToken::FenceWatchdog => {
+                        if let Some(timer) = fence_watchdog_timer.as_mut() {
+                            let _ = timer.mark_waited();
+                        }
+                        // Newly stuck fences are logged by rutabaga.
+                        if let Some(period) = self.fence_watchdog {
+                            self.state.virtio_gpu.check_stuck_fences(period);
+                        }
+                    }
                     WorkerToken::Sleep => {
                         return Ok(WorkerStopReason::Sleep);
                     }
@@ -1322,6 +1411,11 @@ impl Worker {
             self.resource_bridges
                 .process_resource_bridges(&mut self.state, &mut event_manager.wait_ctx);
 
+            if let Some(pressure) = self.state.virtio_gpu.take_memory_pressure() {
+                self.report_memory_pressure(pressure);
+                needs_config_interrupt = true;
+            }
+
             if signal_used_ctrl {
                 activation_resources.ctrl_queue.signal_used();
             }
@@ -1335,6 +1429,26 @@ impl Worker {
             }
         }
     }
+
+    // Forwards memory pressure to the listeners of registered events, such as the policy engine
+    // resizing the balloon.  The guest is notified through the config space.
+    fn report_memory_pressure(&self, pressure: MemoryPressure) {
+        warn!(
+            "gpu memory pressure: {} bytes used, budget {:?}, allocation failed: {}",
+            pressure.usage, pressure.budget, pressure.allocation_failed
+        );
+
+        #[cfg(feature = "registered_events")]
+        if let Some(registered_evt_q) = &self.registered_evt_q {
+            if let Err(e) = registered_evt_q.send(&RegisteredEventWithData::GpuMemoryPressure {
+                usage: pressure.usage,
+                budget: pressure.budget,
+                allocation_failed: pressure.allocation_failed,
+            }) {
+                error!("failed to send gpu memory pressure event: {}", e);
+            }
+        }
+    }
 }
 
 /// Indicates a backend that should be tried for the gpu to use for display.
@@ -1431,6 +1545,13 @@ pub struct Gpu {
     #[cfg(any(target_os = "android", target_os = "linux"))]
     gpu_cgroup_path: Option<PathBuf>,
     snapshot_scratch_directory: Option<PathBuf>,
+    fence_watchdog: Option<Duration>,
+    // Kept open for `keep_rds()`, the builder holds clones of them.
+    blob_file_descriptors: Vec<SafeDescriptor>,
+    memory_budget: Option<u64>,
+    memory_event: Arc<AtomicBool>,
+    #[cfg(feature = "registered_events")]
+    registered_evt_q: Option<SendTube>,
 }
 
 impl Gpu {
@@ -1446,6 +1567,7 @@ impl Gpu {
         channels: &BTreeMap<String, PathBuf>,
         #[cfg(windows)] wndproc_thread: WindowProcedureThread,
         #[cfg(any(target_os = "android", target_os = "linux"))] gpu_cgroup_path: Option<&PathBuf>,
+        #[cfg(feature = "registered_events")] registered_evt_q: Option<SendTube>,
     ) -> Gpu {
         let mut display_params = gpu_parameters.display_params.clone();
         if display_params.is_empty() {
@@ -1459,15 +1581,32 @@ impl Gpu {
                 "" => rutabaga_channels.push(RutabagaChannel {
                     base_channel: path.clone(),
                     channel_type: RUTABAGA_CHANNEL_TYPE_WAYLAND,
+                    name: None,
                 }),
                 "mojo" => rutabaga_channels.push(RutabagaChannel {
                     base_channel: path.clone(),
                     channel_type: RUTABAGA_CHANNEL_TYPE_CAMERA,
+                    name: None,
+                }),
+                // Other Wayland sockets, such as the compositors of other seats, are used by the
+                // cross-domain contexts of the same name.
+                _ => rutabaga_channels.push(RutabagaChannel {
+                    base_channel: path.clone(),
+                    channel_type: RUTABAGA_CHANNEL_TYPE_WAYLAND,
+                    name: Some(channel_name.clone()),
                 }),
-                _ => error!("unknown rutabaga channel"),
             }
         }
 
+        #[cfg(windows)]
+        if let Some(compositor_pipe) = &gpu_parameters.compositor_pipe {
+            rutabaga_channels.push(RutabagaChannel {
+                base_channel: compositor_pipe.clone(),
+                channel_type: RUTABAGA_CHANNEL_TYPE_WAYLAND,
+                name: None,
+            });
+        }
+
         let rutabaga_channels_opt = Some(rutabaga_channels);
         let component = match gpu_parameters.mode {
             GpuMode::Mode2D => RutabagaComponentType::Rutabaga2D,
@@ -1488,6 +1627,23 @@ impl Gpu {
             _ => RutabagaWsi::Surfaceless,
         };
 
+        let mut blob_file_descriptors = Vec::new();
+        let mut rutabaga_blob_files = Vec::new();
+        for blob_file in &gpu_parameters.blob_files {
+            match open_blob_file(blob_file) {
+                Ok((descriptor, rutabaga_blob_file)) => {
+                    blob_file_descriptors.push(descriptor);
+                    rutabaga_blob_files.push(rutabaga_blob_file);
+                }
+                // The guest fails to create blobs from the file instead.
+                Err(e) => error!(
+                    "failed to open GPU blob file {}: {:#}",
+                    blob_file.path.display(),
+                    e
+                ),
+            }
+        }
+
         let rutabaga_builder = RutabagaBuilder::new(component, gpu_parameters.capset_mask)
             .set_display_width(display_width)
             .set_display_height(display_height)
@@ -1501,7 +1657,8 @@ impl Gpu {
             .set_use_external_blob(gpu_parameters.external_blob)
             .set_use_system_blob(gpu_parameters.system_blob)
             .set_use_render_server(use_render_server)
-            .set_renderer_features(gpu_parameters.renderer_features.clone());
+            .set_renderer_features(gpu_parameters.renderer_features.clone())
+            .set_blob_files(rutabaga_blob_files);
 
         #[cfg(windows)]
         let (gpu_display_wait_descriptor_ctrl_wr, gpu_display_wait_descriptor_ctrl_rd) =
@@ -1540,6 +1697,14 @@ impl Gpu {
             #[cfg(any(target_os = "android", target_os = "linux"))]
             gpu_cgroup_path: gpu_cgroup_path.cloned(),
             snapshot_scratch_directory: gpu_parameters.snapshot_scratch_path.clone(),
+            fence_watchdog: gpu_parameters.fence_watchdog_ms.map(Duration::from_millis),
+            blob_file_descriptors,
+            memory_budget: gpu_parameters
+                .memory_budget_mib
+                .map(|mib| mib.saturating_mul(1 << 20)),
+            memory_event: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "registered_events")]
+            registered_evt_q,
         }
     }
 
@@ -1578,6 +1743,8 @@ impl Gpu {
                 .try_clone()
                 .expect("failed to clone wait context control channel"),
             self.snapshot_scratch_directory.clone(),
+            self.memory_budget,
+            self.memory_event.clone(),
         )?;
 
         for event_device in self.event_devices.take().expect("missing event_devices") {
@@ -1624,6 +1791,11 @@ impl Gpu {
         let fixed_blob_mapping = self.fixed_blob_mapping;
         let udmabuf = self.udmabuf;
         let snapshot_scratch_directory = self.snapshot_scratch_directory.clone();
+        let fence_watchdog = self.fence_watchdog;
+        let memory_budget = self.memory_budget;
+        let memory_event = self.memory_event.clone();
+        #[cfg(feature = "registered_events")]
+        let registered_evt_q = self.registered_evt_q.take();
 
         #[cfg(windows)]
         let mut wndproc_thread = self.wndproc_thread.take();
@@ -1687,6 +1859,11 @@ impl Gpu {
                 #[cfg(windows)]
                 gpu_display_wait_descriptor_ctrl_wr,
                 snapshot_scratch_directory,
+                fence_watchdog,
+                memory_budget,
+                memory_event,
+                #[cfg(feature = "registered_events")]
+                registered_evt_q,
             )
             .expect("Failed to create virtio gpu worker thread");
 
@@ -1724,6 +1901,10 @@ impl Gpu {
             events_read |= VIRTIO_GPU_EVENT_DISPLAY;
         }
 
+        if self.memory_event.load(Ordering::Relaxed) {
+            events_read |= VIRTIO_GPU_EVENT_MEMORY_PRESSURE;
+        }
+
         let num_capsets = match self.capset_mask {
             0 => {
                 match self.rutabaga_component {
@@ -1806,6 +1987,15 @@ impl VirtioDevice for Gpu {
             keep_rds.push(event_device.as_raw_descriptor());
         }
 
+        for descriptor in &self.blob_file_descriptors {
+            keep_rds.push(descriptor.as_raw_descriptor());
+        }
+
+        #[cfg(feature = "registered_events")]
+        if let Some(registered_evt_q) = &self.registered_evt_q {
+            keep_rds.push(registered_evt_q.as_raw_descriptor());
+        }
+
         keep_rds
     }
 
@@ -1855,6 +2045,9 @@ impl VirtioDevice for Gpu {
         if (cfg.events_clear.to_native() & VIRTIO_GPU_EVENT_DISPLAY) != 0 {
             self.display_event.store(false, Ordering::Relaxed);
         }
+        if (cfg.events_clear.to_native() & VIRTIO_GPU_EVENT_MEMORY_PRESSURE) != 0 {
+            self.memory_event.store(false, Ordering::Relaxed);
+        }
     }
 
     fn on_device_sandboxed(&mut self) {
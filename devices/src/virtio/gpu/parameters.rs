@@ -46,6 +46,15 @@ pub enum AudioDeviceMode {
     OneGlobal,
 }
 
+/// Host file the guest can create read-only blob resources from, by passing `id` as the blob id of
+/// a `VIRTIO_GPU_BLOB_MEM_HOST3D` blob.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GpuBlobFile {
+    pub id: u64,
+    pub path: PathBuf,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, FromKeyValues)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct GpuParameters {
@@ -93,6 +102,19 @@ pub struct GpuParameters {
     // When running with device sandboxing, the path of a directory available for
     // scratch space.
     pub snapshot_scratch_path: Option<PathBuf>,
+    // Period in milliseconds of the check for fences left unsignaled for longer than that, which
+    // are logged to diagnose GPU hangs.
+    pub fence_watchdog_ms: Option<u64>,
+    // Host files, such as large texture packs, that the guest maps as read-only blob resources
+    // instead of copying them into guest memory.
+    pub blob_files: Vec<GpuBlobFile>,
+    // Estimated host memory in MiB that the resources of the guest may use before GPU memory
+    // pressure is reported to the guest and to the listeners of registered events.
+    pub memory_budget_mib: Option<u64>,
+    // Named pipe of the host compositor that cross-domain contexts forward the Wayland protocol
+    // to, the Windows equivalent of the unnamed Wayland socket.
+    #[cfg(windows)]
+    pub compositor_pipe: Option<PathBuf>,
 }
 
 impl Default for GpuParameters {
@@ -125,6 +147,11 @@ impl Default for GpuParameters {
             allow_implicit_render_server_exec: false,
             renderer_features: None,
             snapshot_scratch_path: None,
+            fence_watchdog_ms: None,
+            blob_files: vec![],
+            memory_budget_mib: None,
+            #[cfg(windows)]
+            compositor_pipe: None,
         }
     }
 }
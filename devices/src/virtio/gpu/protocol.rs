@@ -493,6 +493,9 @@ pub struct virtio_gpu_resp_resource_plane_info {
 pub const PLANE_INFO_MAX_COUNT: usize = 4;
 
 pub const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
+/// Not in the virtio spec: the host is short of memory for the resources of the guest, which
+/// should release the ones it can spare.
+pub const VIRTIO_GPU_EVENT_MEMORY_PRESSURE: u32 = 1 << 1;
 
 #[derive(Copy, Clone, Debug, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
 #[repr(C)]
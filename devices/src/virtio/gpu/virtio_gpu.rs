@@ -13,6 +13,7 @@ use std::result::Result;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
 use base::error;
@@ -28,13 +29,15 @@ use rutabaga_gfx::ResourceCreate3D;
 use rutabaga_gfx::ResourceCreateBlob;
 use rutabaga_gfx::Rutabaga;
 use rutabaga_gfx::RutabagaDescriptor;
-#[cfg(windows)]
+use rutabaga_gfx::RutabagaError;
 use rutabaga_gfx::RutabagaErrorKind;
 use rutabaga_gfx::RutabagaFence;
+use rutabaga_gfx::RutabagaFenceRing;
 use rutabaga_gfx::RutabagaFromRawDescriptor;
 use rutabaga_gfx::RutabagaHandle;
 use rutabaga_gfx::RutabagaIntoRawDescriptor;
 use rutabaga_gfx::RutabagaIovec;
+use rutabaga_gfx::RutabagaStuckFence;
 use rutabaga_gfx::Transfer3D;
 use rutabaga_gfx::RUTABAGA_HANDLE_TYPE_MEM_DMABUF;
 use rutabaga_gfx::RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_FD;
@@ -47,11 +50,17 @@ use rutabaga_gfx::RUTABAGA_MAP_CACHE_MASK;
 use serde::Deserialize;
 use serde::Serialize;
 use sync::Mutex;
+use vm_control::gpu::DisplayConfig;
 use vm_control::gpu::DisplayMode;
 use vm_control::gpu::DisplayParameters;
+use vm_control::gpu::DisplayRotation;
 use vm_control::gpu::GpuControlCommand;
 use vm_control::gpu::GpuControlResult;
+use vm_control::gpu::GpuFenceRingInfo;
+use vm_control::gpu::GpuResourceInfo;
+use vm_control::gpu::GpuStuckFenceInfo;
 use vm_control::gpu::MouseMode;
+use vm_control::gpu::DEFAULT_DPI;
 use vm_control::VmMemorySource;
 use vm_memory::udmabuf::UdmabufDriver;
 use vm_memory::udmabuf::UdmabufDriverTrait;
@@ -66,6 +75,7 @@ use super::protocol::VirtioGpuResult;
 use super::protocol::VIRTIO_GPU_BLOB_FLAG_CREATE_GUEST_HANDLE;
 use super::protocol::VIRTIO_GPU_BLOB_MEM_HOST3D;
 use super::VirtioScanoutBlobData;
+use crate::virtio::gpu::edid::check_edid_blob;
 use crate::virtio::gpu::edid::DisplayInfo;
 use crate::virtio::gpu::edid::EdidBytes;
 use crate::virtio::gpu::snapshot::pack_directory_to_snapshot;
@@ -96,6 +106,8 @@ struct VirtioGpuResource {
     width: u32,
     height: u32,
     size: u64,
+    // Estimated host memory used by the resource, counted against the memory budget.
+    host_size: u64,
     shmem_offset: Option<u64>,
     scanout_data: Option<VirtioScanoutBlobData>,
     display_import: Option<u32>,
@@ -112,6 +124,8 @@ struct VirtioGpuResourceSnapshot {
     width: u32,
     height: u32,
     size: u64,
+    #[serde(default)]
+    host_size: u64,
 
     backing_iovecs: Option<Vec<(GuestAddress, usize)>>,
     shmem_offset: Option<u64>,
@@ -126,6 +140,7 @@ impl VirtioGpuResource {
             width,
             height,
             size,
+            host_size: 0,
             shmem_offset: None,
             scanout_data: None,
             display_import: None,
@@ -144,6 +159,7 @@ impl VirtioGpuResource {
             width: self.width,
             height: self.height,
             size: self.size,
+            host_size: self.host_size,
             backing_iovecs: self.backing_iovecs.clone(),
             shmem_offset: self.shmem_offset,
         }
@@ -151,6 +167,7 @@ impl VirtioGpuResource {
 
     fn restore(s: VirtioGpuResourceSnapshot) -> Self {
         let mut resource = VirtioGpuResource::new(s.resource_id, s.width, s.height, s.size);
+        resource.host_size = s.host_size;
         resource.backing_iovecs = s.backing_iovecs;
         resource
     }
@@ -164,6 +181,13 @@ struct VirtioGpuScanout {
     scanout_id: Option<u32>,
     // If this scanout is a primary scanout, the display properties.
     display_params: Option<GpuDisplayParameters>,
+    // If this scanout is a primary scanout, its rotation and its DPI at a 100% scale before
+    // rotation, the reference for `DisplayConfig` changes.
+    rotation: DisplayRotation,
+    unscaled_dpi: (u32, u32),
+    // If this scanout is a primary scanout, the EDID set by the host in place of the one generated
+    // from the display properties.
+    edid: Option<Vec<u8>>,
     // If this scanout is a cursor scanout, the scanout that this is cursor is overlayed onto.
     parent_surface_id: Option<u32>,
 
@@ -181,6 +205,8 @@ struct VirtioGpuScanoutSnapshot {
     scanout_type: SurfaceType,
     scanout_id: Option<u32>,
     display_params: Option<GpuDisplayParameters>,
+    #[serde(default)]
+    edid: Option<Vec<u8>>,
 
     // The surface IDs aren't guest visible. Instead of storing them and then having to fix up
     // `gpu_display` internals, we'll allocate new ones on restore. So, we just need to store
@@ -195,12 +221,16 @@ struct VirtioGpuScanoutSnapshot {
 impl VirtioGpuScanout {
     fn new_primary(scanout_id: u32, params: GpuDisplayParameters) -> VirtioGpuScanout {
         let (width, height) = params.get_virtual_display_size();
+        let unscaled_dpi = params.dpi.unwrap_or((DEFAULT_DPI, DEFAULT_DPI));
         VirtioGpuScanout {
             width,
             height,
             scanout_type: SurfaceType::Scanout,
             scanout_id: Some(scanout_id),
             display_params: Some(params),
+            rotation: DisplayRotation::Rotate0,
+            unscaled_dpi,
+            edid: None,
             parent_surface_id: None,
             surface_id: None,
             parent_scanout_id: None,
@@ -218,6 +248,9 @@ impl VirtioGpuScanout {
             scanout_type: SurfaceType::Cursor,
             scanout_id: None,
             display_params: None,
+            rotation: DisplayRotation::Rotate0,
+            unscaled_dpi: (0, 0),
+            edid: None,
             parent_surface_id: None,
             surface_id: None,
             parent_scanout_id: None,
@@ -235,6 +268,7 @@ impl VirtioGpuScanout {
             scanout_type: self.scanout_type,
             scanout_id: self.scanout_id,
             display_params: self.display_params.clone(),
+            edid: self.edid.clone(),
             parent_scanout_id: self.parent_scanout_id,
             position: self.position,
         }
@@ -247,14 +281,16 @@ impl VirtioGpuScanout {
         display: &Rc<RefCell<GpuDisplay>>,
     ) -> VirtioGpuResult {
         // Scanouts are mainly controlled by the host, we just need to make sure it looks same,
-        // restore the resource_id association, and create a surface in the display.
+        // restore the resource_id association, and create a surface in the display. Only the size
+        // may differ, since the guest can change it when setting the scanout.
 
-        assert_eq!(self.width, snapshot.width);
-        assert_eq!(self.height, snapshot.height);
         assert_eq!(self.scanout_type, snapshot.scanout_type);
         assert_eq!(self.scanout_id, snapshot.scanout_id);
         assert_eq!(self.display_params, snapshot.display_params);
 
+        self.width = snapshot.width;
+        self.height = snapshot.height;
+        self.edid = snapshot.edid;
         self.resource_id = snapshot.resource_id;
         if snapshot.has_surface {
             self.create_surface(display, parent_surface_id, None)?;
@@ -356,6 +392,65 @@ impl VirtioGpuScanout {
         Ok(OkNoData)
     }
 
+    /// Applies `config` to the display parameters, which are the source of the display info and
+    /// EDID given to the guest, and resizes the host surface.
+    fn configure(
+        &mut self,
+        display: &Rc<RefCell<GpuDisplay>>,
+        config: &DisplayConfig,
+    ) -> VirtioGpuResult {
+        let params = self.display_params.as_mut().ok_or(ErrInvalidScanoutId)?;
+
+        let (width, height) = match config.size {
+            Some(size) => size,
+            None => {
+                let (width, height) = params.get_virtual_display_size();
+                if self.rotation.swaps_axes() {
+                    (height, width)
+                } else {
+                    (width, height)
+                }
+            }
+        };
+        let (horizontal_dpi, vertical_dpi) = match config.scale_percent {
+            Some(0) => return Err(ErrInvalidParameter),
+            Some(scale_percent) => (
+                self.unscaled_dpi.0.saturating_mul(scale_percent) / 100,
+                self.unscaled_dpi.1.saturating_mul(scale_percent) / 100,
+            ),
+            None => {
+                let (horizontal_dpi, vertical_dpi) = params.dpi.unwrap_or(self.unscaled_dpi);
+                if self.rotation.swaps_axes() {
+                    (vertical_dpi, horizontal_dpi)
+                } else {
+                    (horizontal_dpi, vertical_dpi)
+                }
+            }
+        };
+        if let Some(rotation) = config.rotation {
+            self.rotation = rotation;
+        }
+
+        let (width, height, dpi) = if self.rotation.swaps_axes() {
+            (height, width, (vertical_dpi, horizontal_dpi))
+        } else {
+            (width, height, (horizontal_dpi, vertical_dpi))
+        };
+        params.mode = DisplayMode::Windowed(width, height);
+        params.dpi = Some(dpi);
+        self.width = width;
+        self.height = height;
+
+        // The guest sets the scanout again once it has read the new display info, until then the
+        // current resource is shown on a surface of the new size.
+        if self.surface_id.is_some() {
+            self.release_surface(display);
+            let parent_surface_id = self.parent_surface_id;
+            self.create_surface(display, parent_surface_id, None)?;
+        }
+        Ok(OkNoData)
+    }
+
     fn set_position(
         &mut self,
         display: &Rc<RefCell<GpuDisplay>>,
@@ -489,6 +584,14 @@ pub struct VirtioGpu {
     udmabuf_driver: Option<UdmabufDriver>,
     snapshot_scratch_directory: Option<PathBuf>,
     deferred_snapshot_load: Option<VirtioGpuSnapshot>,
+    // Contexts destroyed by the host that the guest has not destroyed yet.
+    lost_contexts: Set<u32>,
+    // Estimated host memory used by the resources, see `VirtioGpuResource::host_size`.
+    memory_usage: u64,
+    memory_budget: Option<u64>,
+    // Set until the guest clears the memory pressure event.
+    memory_event: Arc<AtomicBool>,
+    memory_pressure: Option<MemoryPressure>,
 }
 
 // Only the 2D mode is supported. Notes on `VirtioGpu` fields:
@@ -502,6 +605,8 @@ pub struct VirtioGpu {
 //   * resources: snapshot'd
 //   * external_blob: not needed for 2d mode
 //   * udmabuf_driver: not needed for 2d mode
+//   * memory_usage: recomputed from the resource snapshots
+//   * memory_event, memory_pressure: not snapshot'd, the guest is not notified again
 #[derive(Serialize, Deserialize)]
 pub struct VirtioGpuSnapshot {
     scanouts: Map<u32, VirtioGpuScanoutSnapshot>,
@@ -546,6 +651,39 @@ fn sglist_to_rutabaga_iovecs(
     Ok(rutabaga_iovecs)
 }
 
+/// Host memory used by the GPU resources running short.
+#[derive(Clone, Debug)]
+pub struct MemoryPressure {
+    /// Estimated host memory used by the resources, in bytes.
+    pub usage: u64,
+    /// The memory budget of the resources, if any, in bytes.
+    pub budget: Option<u64>,
+    /// Whether the renderer failed to allocate a resource, as opposed to the budget being
+    /// exceeded.
+    pub allocation_failed: bool,
+}
+
+// Estimates the host memory used by a 3D resource, assuming 4 bytes per texel and ignoring the
+// mipmap levels.
+fn estimate_host_size(resource_create_3d: &ResourceCreate3D) -> u64 {
+    [
+        resource_create_3d.width,
+        resource_create_3d.height,
+        resource_create_3d.depth,
+        resource_create_3d.array_size,
+    ]
+    .iter()
+    .fold(4u64, |size, &dim| size.saturating_mul(dim.max(1).into()))
+}
+
+// Whether the renderer failed to create a resource for lack of memory.
+fn is_out_of_memory(e: &RutabagaError) -> bool {
+    match e.kind() {
+        RutabagaErrorKind::ComponentError(ret) => ret.unsigned_abs() == libc::ENOMEM as u32,
+        _ => false,
+    }
+}
+
 pub enum ProcessDisplayResult {
     Success,
     CloseRequested,
@@ -564,6 +702,8 @@ impl VirtioGpu {
         fixed_blob_mapping: bool,
         udmabuf: bool,
         snapshot_scratch_directory: Option<PathBuf>,
+        memory_budget: Option<u64>,
+        memory_event: Arc<AtomicBool>,
     ) -> Option<VirtioGpu> {
         let mut udmabuf_driver = None;
         if udmabuf {
@@ -599,6 +739,11 @@ impl VirtioGpu {
             udmabuf_driver,
             deferred_snapshot_load: None,
             snapshot_scratch_directory,
+            lost_contexts: Default::default(),
+            memory_usage: 0,
+            memory_budget,
+            memory_event,
+            memory_pressure: None,
         })
     }
 
@@ -707,8 +852,155 @@ impl VirtioGpu {
         }
     }
 
+    /// Changes the resolution, scale or rotation of a display and notifies the guest.
+    fn configure_display(&mut self, display_id: u32, config: DisplayConfig) -> GpuControlResult {
+        let Some(scanout) = self.scanouts.get_mut(&display_id) else {
+            return GpuControlResult::NoSuchDisplay { display_id };
+        };
+        if let Err(e) = scanout.configure(&self.display, &config) {
+            return GpuControlResult::ErrString(e.to_string());
+        }
+
+        self.scanouts_updated.store(true, Ordering::Relaxed);
+        GpuControlResult::DisplayConfigured
+    }
+
+    /// Replaces the EDID of a display, or reverts to the generated one if `edid` is None, and
+    /// notifies the guest so that it probes the modes again.
+    fn set_display_edid(&mut self, display_id: u32, edid: Option<Vec<u8>>) -> GpuControlResult {
+        let Some(scanout) = self.scanouts.get_mut(&display_id) else {
+            return GpuControlResult::NoSuchDisplay { display_id };
+        };
+        if let Some(edid) = &edid {
+            if let Err(e) = check_edid_blob(edid) {
+                return GpuControlResult::ErrString(e);
+            }
+        }
+
+        scanout.edid = edid;
+        self.scanouts_updated.store(true, Ordering::Relaxed);
+        GpuControlResult::DisplayEdidSet
+    }
+
+    /// Destroys a guest context that hung the GPU, without resetting the device.
+    fn kill_context(&mut self, ctx_id: u32) -> GpuControlResult {
+        if let Err(e) = self.rutabaga.destroy_context_for_misbehavior(ctx_id) {
+            return GpuControlResult::ErrString(e.to_string());
+        }
+
+        self.lost_contexts.insert(ctx_id);
+        GpuControlResult::ContextKilled
+    }
+
+    /// Lists the live rutabaga resources, to debug resources leaked by the guest.
+    fn dump_resources(&self) -> GpuControlResult {
+        let resources = self
+            .rutabaga
+            .debug_dump()
+            .into_iter()
+            .map(|info| GpuResourceInfo {
+                resource_id: info.resource_id,
+                size: info.size,
+                blob: info.blob,
+                blob_mem: info.blob_mem,
+                blob_flags: info.blob_flags,
+                components: info
+                    .components
+                    .iter()
+                    .map(|c| c.as_str().to_string())
+                    .collect(),
+                has_backing: info.has_backing,
+                last_access_fence: info.last_access_fence,
+            })
+            .collect();
+        GpuControlResult::ResourceList { resources }
+    }
+
+    /// Lists the fence counters of every ring and the fences left unsignaled for longer than
+    /// `stuck_threshold`, to diagnose GPU hangs.
+    fn fence_stats(&self, stuck_threshold: Duration) -> GpuControlResult {
+        let ring_ids = |ring| match ring {
+            RutabagaFenceRing::Global => (None, None),
+            RutabagaFenceRing::Context { ctx_id, ring_idx } => (Some(ctx_id), Some(ring_idx)),
+        };
+
+        let rings = self
+            .rutabaga
+            .fence_stats()
+            .into_iter()
+            .map(|stats| {
+                let (ctx_id, ring_idx) = ring_ids(stats.ring);
+                GpuFenceRingInfo {
+                    ctx_id,
+                    ring_idx,
+                    created: stats.created,
+                    signaled: stats.signaled,
+                    pending: stats.pending,
+                }
+            })
+            .collect();
+        let stuck = self
+            .check_stuck_fences(stuck_threshold)
+            .into_iter()
+            .map(|fence| GpuStuckFenceInfo {
+                fence_id: fence.fence_id,
+                ctx_id: fence.ctx_id,
+                ring_idx: ring_ids(fence.ring).1,
+                pending_ms: fence.pending_for.as_millis(),
+                last_command_size: fence.last_command_size,
+            })
+            .collect();
+        GpuControlResult::FenceStats { rings, stuck }
+    }
+
+    /// Detaches the backing of the resources attached to guest memory overlapping one of the
+    /// `(address, size)` ranges, so that no component accesses that memory once it is removed from
+    /// the guest. The guest must attach backing to these resources again before using them.
+    fn invalidate_backing(
+        &mut self,
+        mem: &GuestMemory,
+        ranges: Vec<(GuestAddress, u64)>,
+    ) -> GpuControlResult {
+        // Rutabaga compares host addresses: translate the part of each range in every region.
+        let mut host_ranges = Vec::new();
+        for (addr, size) in ranges {
+            let end = addr.offset().saturating_add(size);
+            for region in mem.regions() {
+                let region_start = region.guest_addr.offset();
+                let region_end = region_start + region.size as u64;
+                let start = addr.offset().max(region_start);
+                let overlap_end = end.min(region_end);
+                if start < overlap_end {
+                    let host_start = region.host_addr as u64 + (start - region_start);
+                    host_ranges.push(host_start..host_start + (overlap_end - start));
+                }
+            }
+        }
+
+        let resource_ids = match self.rutabaga.invalidate_backing(&host_ranges) {
+            Ok(resource_ids) => resource_ids,
+            Err(e) => return GpuControlResult::ErrString(e.to_string()),
+        };
+        for resource_id in &resource_ids {
+            if let Some(resource) = self.resources.get_mut(resource_id) {
+                resource.backing_iovecs = None;
+            }
+        }
+        GpuControlResult::BackingInvalidated { resource_ids }
+    }
+
+    /// Returns the fences left unsignaled for longer than `threshold`, logging the ones that were
+    /// not returned before.
+    pub fn check_stuck_fences(&self, threshold: Duration) -> Vec<RutabagaStuckFence> {
+        self.rutabaga.check_stuck_fences(threshold)
+    }
+
     /// Performs the given command to interact with or modify the device.
-    pub fn process_gpu_control_command(&mut self, cmd: GpuControlCommand) -> GpuControlResult {
+    pub fn process_gpu_control_command(
+        &mut self,
+        mem: &GuestMemory,
+        cmd: GpuControlCommand,
+    ) -> GpuControlResult {
         match cmd {
             GpuControlCommand::AddDisplays { displays } => self.add_displays(displays),
             GpuControlCommand::ListDisplays => self.list_displays(),
@@ -717,6 +1009,16 @@ impl VirtioGpu {
                 display_id,
                 mouse_mode,
             } => self.set_display_mouse_mode(display_id, mouse_mode),
+            GpuControlCommand::ConfigureDisplay { display_id, config } => {
+                self.configure_display(display_id, config)
+            }
+            GpuControlCommand::SetDisplayEdid { display_id, edid } => {
+                self.set_display_edid(display_id, edid)
+            }
+            GpuControlCommand::DumpResources => self.dump_resources(),
+            GpuControlCommand::FenceStats { stuck_threshold } => self.fence_stats(stuck_threshold),
+            GpuControlCommand::KillContext { ctx_id } => self.kill_context(ctx_id),
+            GpuControlCommand::InvalidateBacking { ranges } => self.invalidate_backing(mem, ranges),
         }
     }
 
@@ -923,6 +1225,7 @@ impl VirtioGpu {
     /// Creates a fence with the RutabagaFence that can be used to determine when the previous
     /// command completed.
     pub fn create_fence(&mut self, rutabaga_fence: RutabagaFence) -> VirtioGpuResult {
+        let _trace = cros_tracing::trace_event!(gpu, "create_fence");
         self.rutabaga.create_fence(rutabaga_fence)?;
         Ok(OkNoData)
     }
@@ -932,6 +1235,45 @@ impl VirtioGpu {
         self.rutabaga.event_poll();
     }
 
+    /// Takes the memory pressure reported since the last call, if any.
+    pub fn take_memory_pressure(&mut self) -> Option<MemoryPressure> {
+        self.memory_pressure.take()
+    }
+
+    fn report_memory_pressure(&mut self, allocation_failed: bool) {
+        self.memory_event.store(true, Ordering::Relaxed);
+        self.memory_pressure = Some(MemoryPressure {
+            usage: self.memory_usage,
+            budget: self.memory_budget,
+            allocation_failed,
+        });
+    }
+
+    fn over_memory_budget(&self) -> bool {
+        self.memory_budget
+            .is_some_and(|budget| self.memory_usage > budget)
+    }
+
+    // Counts the host memory of a new resource, reporting pressure when the budget gets exceeded.
+    fn charge_memory(&mut self, host_size: u64) {
+        let was_over_budget = self.over_memory_budget();
+        self.memory_usage = self.memory_usage.saturating_add(host_size);
+        if !was_over_budget && self.over_memory_budget() {
+            self.report_memory_pressure(false);
+        }
+    }
+
+    // Converts an error of the renderer creating a resource, reporting pressure if it ran out of
+    // memory.
+    fn resource_creation_error(&mut self, e: RutabagaError) -> GpuResponse {
+        if is_out_of_memory(&e) {
+            error!("renderer failed to allocate a resource: {}", e);
+            self.report_memory_pressure(true);
+            return ErrOutOfMemory;
+        }
+        e.into()
+    }
+
     /// Gets a pollable eventfd that signals the device to wakeup and poll the
     /// Rutabaga backend.
     pub fn poll_descriptor(&self) -> Option<SafeDescriptor> {
@@ -945,14 +1287,17 @@ impl VirtioGpu {
         resource_create_3d: ResourceCreate3D,
     ) -> VirtioGpuResult {
         self.rutabaga
-            .resource_create_3d(resource_id, resource_create_3d)?;
+            .resource_create_3d(resource_id, resource_create_3d)
+            .map_err(|e| self.resource_creation_error(e))?;
 
-        let resource = VirtioGpuResource::new(
+        let mut resource = VirtioGpuResource::new(
             resource_id,
             resource_create_3d.width,
             resource_create_3d.height,
             0,
         );
+        resource.host_size = estimate_host_size(&resource_create_3d);
+        self.charge_memory(resource.host_size);
 
         // Rely on rutabaga to check for duplicate resource ids.
         self.resources.insert(resource_id, resource);
@@ -1003,10 +1348,15 @@ impl VirtioGpu {
         }
 
         self.rutabaga.unref_resource(resource_id)?;
+        self.memory_usage = self.memory_usage.saturating_sub(resource.host_size);
         Ok(OkNoData)
     }
 
     /// Copies data to host resource from the attached iovecs. Can also be used to flush caches.
+    ///
+    /// Large copies may continue in the background so that the control queue keeps being
+    /// processed. The fence of the command, if any, and the later commands using the resource wait
+    /// for the copy.
     pub fn transfer_write(
         &mut self,
         ctx_id: u32,
@@ -1014,7 +1364,7 @@ impl VirtioGpu {
         transfer: Transfer3D,
     ) -> VirtioGpuResult {
         self.rutabaga
-            .transfer_write(ctx_id, resource_id, transfer)?;
+            .transfer_write_async(ctx_id, resource_id, transfer)?;
         Ok(OkNoData)
     }
 
@@ -1063,18 +1413,25 @@ impl VirtioGpu {
                 Some(sglist_to_rutabaga_iovecs(&vecs[..], mem).map_err(|_| ErrUnspec)?);
         }
 
-        self.rutabaga.resource_create_blob(
-            ctx_id,
-            resource_id,
-            resource_create_blob,
-            rutabaga_iovecs,
-            descriptor.map(|descriptor| RutabagaHandle {
-                os_handle: to_rutabaga_descriptor(descriptor),
-                handle_type: RUTABAGA_HANDLE_TYPE_MEM_DMABUF,
-            }),
-        )?;
+        self.rutabaga
+            .resource_create_blob(
+                ctx_id,
+                resource_id,
+                resource_create_blob,
+                rutabaga_iovecs,
+                descriptor.map(|descriptor| RutabagaHandle {
+                    os_handle: to_rutabaga_descriptor(descriptor),
+                    handle_type: RUTABAGA_HANDLE_TYPE_MEM_DMABUF,
+                }),
+            )
+            .map_err(|e| self.resource_creation_error(e))?;
 
-        let resource = VirtioGpuResource::new(resource_id, 0, 0, resource_create_blob.size);
+        let mut resource = VirtioGpuResource::new(resource_id, 0, 0, resource_create_blob.size);
+        // Only host3d blobs are allocated by the host, the others are backed by guest memory.
+        if resource_create_blob.blob_mem == VIRTIO_GPU_BLOB_MEM_HOST3D {
+            resource.host_size = resource_create_blob.size;
+            self.charge_memory(resource.host_size);
+        }
 
         // Rely on rutabaga to check for duplicate resource ids.
         self.resources.insert(resource_id, resource);
@@ -1211,6 +1568,9 @@ impl VirtioGpu {
     /// the EDID of a default display.
     pub fn get_edid(&self, scanout_id: u32) -> VirtioGpuResult {
         let display_info = match self.scanouts.get(&scanout_id) {
+            Some(VirtioGpuScanout {
+                edid: Some(edid), ..
+            }) => return EdidBytes::from_blob(edid),
             Some(scanout) => {
                 // Primary scanouts should always have display params.
                 let params = scanout.display_params.as_ref().unwrap();
@@ -1235,6 +1595,12 @@ impl VirtioGpu {
 
     /// Destroys a rutabaga context.
     pub fn destroy_context(&mut self, ctx_id: u32) -> VirtioGpuResult {
+        // The guest learns that a killed context is lost when its commands fail, and then
+        // destroys it.
+        if self.lost_contexts.remove(&ctx_id) {
+            return Ok(OkNoData);
+        }
+
         self.rutabaga.destroy_context(ctx_id)?;
         Ok(OkNoData)
     }
@@ -1467,6 +1833,7 @@ impl VirtioGpu {
             for (id, s) in snapshot.resources.into_iter() {
                 let backing_iovecs = s.backing_iovecs.clone();
                 let shmem_offset = s.shmem_offset;
+                self.memory_usage = self.memory_usage.saturating_add(s.host_size);
                 self.resources.insert(id, VirtioGpuResource::restore(s));
                 if let Some(backing_iovecs) = backing_iovecs {
                     self.attach_backing(id, mem, backing_iovecs)
@@ -1477,6 +1844,20 @@ impl VirtioGpu {
                         .context("failed to restore resource mapping")?;
                 }
             }
+
+            // Show the restored contents instead of blank surfaces until the guest flushes again.
+            let scanout_resource_ids: Set<u32> = self
+                .scanouts
+                .values()
+                .chain(std::iter::once(&self.cursor_scanout))
+                .filter_map(|scanout| scanout.resource_id)
+                .map(NonZeroU32::get)
+                .collect();
+            for resource_id in scanout_resource_ids {
+                if let Err(e) = self.flush_resource(resource_id) {
+                    error!("failed to flush restored resource {}: {}", resource_id, e);
+                }
+            }
         }
 
         self.rutabaga.resume().context("failed to resume rutabaga")
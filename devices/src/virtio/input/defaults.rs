@@ -126,23 +126,47 @@ pub fn new_single_touch_config(
 }
 
 /// Instantiates a VirtioInputConfig object with the default configuration for a multitouch
-/// touchscreen.
+/// touchscreen. `slots` is the number of simultaneous contacts the guest may track; when it is not
+/// given the device reports slots 0 to 10.
 pub fn new_multi_touch_config(
     idx: u32,
     width: u32,
     height: u32,
+    slots: Option<u32>,
     name: Option<&str>,
 ) -> VirtioInputConfig {
     let name = name
         .map(str::to_owned)
         .unwrap_or(format!("Crosvm Virtio Multitouch Touchscreen {idx}"));
+    let max_slot = slots.map_or(10, |slots| slots.saturating_sub(1));
     VirtioInputConfig::new(
         virtio_input_device_ids::new(0, 0, 0, 0),
         name,
         format!("virtio-touchscreen-{idx}"),
         virtio_input_bitmap::from_bits(&[INPUT_PROP_DIRECT]),
         default_multitouchscreen_events(),
-        default_multitouchscreen_absinfo(width, height, 10, 10),
+        default_multitouchscreen_absinfo(width, height, max_slot, max_slot.max(10)),
+    )
+}
+
+/// Instantiates a VirtioInputConfig object with the default configuration for a tablet, an
+/// absolute pointing device with left, right and middle buttons and a wheel.
+pub fn new_tablet_config(
+    idx: u32,
+    width: u32,
+    height: u32,
+    name: Option<&str>,
+) -> VirtioInputConfig {
+    let name = name
+        .map(str::to_owned)
+        .unwrap_or(format!("Crosvm Virtio Tablet {idx}"));
+    VirtioInputConfig::new(
+        virtio_input_device_ids::new(0, 0, 0, 0),
+        name,
+        format!("virtio-tablet-{idx}"),
+        virtio_input_bitmap::new([0u8; 128]),
+        default_tablet_events(),
+        default_tablet_absinfo(width, height),
     )
 }
 
@@ -321,6 +345,24 @@ fn default_mouse_events() -> BTreeMap<u16, virtio_input_bitmap> {
     supported_events
 }
 
+fn default_tablet_absinfo(width: u32, height: u32) -> BTreeMap<u16, virtio_input_absinfo> {
+    let mut absinfo: BTreeMap<u16, virtio_input_absinfo> = BTreeMap::new();
+    absinfo.insert(ABS_X, virtio_input_absinfo::new(0, width, 0, 0));
+    absinfo.insert(ABS_Y, virtio_input_absinfo::new(0, height, 0, 0));
+    absinfo
+}
+
+fn default_tablet_events() -> BTreeMap<u16, virtio_input_bitmap> {
+    let mut supported_events: BTreeMap<u16, virtio_input_bitmap> = BTreeMap::new();
+    supported_events.insert(
+        EV_KEY,
+        virtio_input_bitmap::from_bits(&[BTN_LEFT, BTN_RIGHT, BTN_MIDDLE]),
+    );
+    supported_events.insert(EV_ABS, virtio_input_bitmap::from_bits(&[ABS_X, ABS_Y]));
+    supported_events.insert(EV_REL, virtio_input_bitmap::from_bits(&[REL_WHEEL]));
+    supported_events
+}
+
 fn default_keyboard_events() -> BTreeMap<u16, virtio_input_bitmap> {
     let mut supported_events: BTreeMap<u16, virtio_input_bitmap> = BTreeMap::new();
     supported_events.insert(
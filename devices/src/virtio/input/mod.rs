@@ -374,6 +374,7 @@ struct Worker<T: EventSource> {
     event_queue: Queue,
     status_queue: Queue,
     name: String,
+    wakeup_evt: Option<Event>,
 }
 
 impl<T: EventSource> Worker<T> {
@@ -518,7 +519,12 @@ impl<T: EventSource> Worker<T> {
                     }
                     Token::InputEventsAvailable => match self.event_source.receive_events() {
                         Err(e) => error!("error receiving events: {}", e),
-                        Ok(_cnt) => eventq_needs_interrupt |= self.send_events(),
+                        Ok(cnt) => {
+                            if cnt > 0 {
+                                signal_wakeup(self.wakeup_evt.as_ref());
+                            }
+                            eventq_needs_interrupt |= self.send_events();
+                        }
                     },
                     Token::Kill => {
                         let _ = kill_evt.wait();
@@ -549,15 +555,67 @@ impl<T: EventSource> Worker<T> {
     }
 }
 
+fn signal_wakeup(wakeup_evt: Option<&Event>) {
+    if let Some(wakeup_evt) = wakeup_evt {
+        if let Err(e) = wakeup_evt.signal() {
+            error!("failed to signal input wakeup event: {}", e);
+        }
+    }
+}
+
+/// Watches `event_source` while the device is reset, and signals `wakeup_evt` once input events
+/// are available. The events are left in the source for the guest driver.
+fn watch_for_wakeup<T: EventSource>(event_source: T, wakeup_evt: Event, kill_evt: Event) -> T {
+    #[derive(EventToken)]
+    enum Token {
+        InputEventsAvailable,
+        Kill,
+    }
+    let wait_ctx: WaitContext<Token> = match WaitContext::build_with(&[
+        (&event_source, Token::InputEventsAvailable),
+        (&kill_evt, Token::Kill),
+    ]) {
+        Ok(wait_ctx) => wait_ctx,
+        Err(e) => {
+            error!("failed creating WaitContext: {}", e);
+            return event_source;
+        }
+    };
+
+    loop {
+        let wait_events = match wait_ctx.wait() {
+            Ok(wait_events) => wait_events,
+            Err(e) => {
+                error!("failed polling for events: {}", e);
+                return event_source;
+            }
+        };
+        for wait_event in wait_events.iter() {
+            match wait_event.token {
+                Token::InputEventsAvailable => {
+                    signal_wakeup(Some(&wakeup_evt));
+                    let _ = wait_ctx.delete(&event_source);
+                }
+                Token::Kill => {
+                    let _ = kill_evt.wait();
+                    return event_source;
+                }
+            }
+        }
+    }
+}
+
 /// Virtio input device
 pub struct Input<T: EventSource + Send + 'static> {
     worker_thread: Option<WorkerThread<Worker<T>>>,
+    wakeup_thread: Option<WorkerThread<T>>,
     config: VirtioInputConfig,
     config_select: u8,
     config_subsel: u8,
     config_data: virtio_input_config,
     source: Option<T>,
     virtio_features: u64,
+    wakeup_evt: Option<Event>,
 }
 
 /// Snapshot of [Input]'s state.
@@ -573,10 +631,18 @@ where
     T: 'static + EventSource + Send,
 {
     fn keep_rds(&self) -> Vec<RawDescriptor> {
+        let mut rds = Vec::new();
         if let Some(source) = &self.source {
-            return vec![source.as_raw_descriptor()];
+            rds.push(source.as_raw_descriptor());
         }
-        Vec::new()
+        if let Some(wakeup_evt) = &self.wakeup_evt {
+            rds.push(wakeup_evt.as_raw_descriptor());
+        }
+        rds
+    }
+
+    fn supports_child_process(&self) -> bool {
+        true
     }
 
     fn device_type(&self) -> DeviceType {
@@ -623,17 +689,25 @@ where
         let event_queue = queues.remove(&0).unwrap();
         let status_queue = queues.remove(&1).unwrap();
 
+        self.stop_wakeup_thread();
         let name = self.config.name.clone();
         let source = self
             .source
             .take()
             .context("tried to activate device without a source for events")?;
+        let wakeup_evt = self
+            .wakeup_evt
+            .as_ref()
+            .map(Event::try_clone)
+            .transpose()
+            .context("failed to clone wakeup event")?;
         self.worker_thread = Some(WorkerThread::start("v_input", move |kill_evt| {
             let mut worker = Worker {
                 event_source: source,
                 event_queue,
                 status_queue,
                 name,
+                wakeup_evt,
             };
             worker.run(kill_evt);
             worker
@@ -647,10 +721,12 @@ where
             let worker = worker_thread.stop();
             self.source = Some(worker.event_source);
         }
-        Ok(())
+        // The guest resets the device when it suspends, keep watching for input to wake it up.
+        self.start_wakeup_thread()
     }
 
     fn virtio_sleep(&mut self) -> anyhow::Result<Option<BTreeMap<usize, Queue>>> {
+        self.stop_wakeup_thread();
         if let Some(worker_thread) = self.worker_thread.take() {
             let worker = worker_thread.stop();
             self.source = Some(worker.event_source);
@@ -666,9 +742,10 @@ where
         queues_state: Option<(GuestMemory, Interrupt, BTreeMap<usize, Queue>)>,
     ) -> anyhow::Result<()> {
         if let Some((mem, interrupt, queues)) = queues_state {
-            self.activate(mem, interrupt, queues)?;
+            self.activate(mem, interrupt, queues)
+        } else {
+            self.start_wakeup_thread()
         }
-        Ok(())
     }
 
     fn virtio_snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
@@ -705,12 +782,40 @@ where
         let config_data = config.build_config_memory(config_select, config_subsel);
         Input {
             worker_thread: None,
+            wakeup_thread: None,
             config,
             config_select,
             config_subsel,
             config_data,
             source,
             virtio_features,
+            wakeup_evt: None,
+        }
+    }
+
+    /// Sets an event to signal on input activity, including while the guest driver has reset the
+    /// device. Used to wake up a suspended guest.
+    pub fn set_wakeup_event(&mut self, wakeup_evt: Option<Event>) {
+        self.wakeup_evt = wakeup_evt;
+    }
+
+    fn start_wakeup_thread(&mut self) -> anyhow::Result<()> {
+        let (Some(wakeup_evt), Some(_)) = (&self.wakeup_evt, &self.source) else {
+            return Ok(());
+        };
+        let wakeup_evt = wakeup_evt
+            .try_clone()
+            .context("failed to clone wakeup event")?;
+        let source = self.source.take().unwrap();
+        self.wakeup_thread = Some(WorkerThread::start("v_input_wakeup", move |kill_evt| {
+            watch_for_wakeup(source, wakeup_evt, kill_evt)
+        }));
+        Ok(())
+    }
+
+    fn stop_wakeup_thread(&mut self) {
+        if let Some(wakeup_thread) = self.wakeup_thread.take() {
+            self.source = Some(wakeup_thread.stop());
         }
     }
 }
@@ -748,6 +853,27 @@ where
 
 /// Creates a new virtio touch device which supports multi touch.
 pub fn new_multi_touch<T>(
+    idx: u32,
+    source: T,
+    width: u32,
+    height: u32,
+    slots: Option<u32>,
+    name: Option<&str>,
+    virtio_features: u64,
+) -> Result<Input<SocketEventSource<T>>>
+where
+    T: Read + Write + AsRawDescriptor + Send + 'static,
+{
+    Ok(Input::new(
+        defaults::new_multi_touch_config(idx, width, height, slots, name),
+        Some(SocketEventSource::new(source)),
+        virtio_features,
+    ))
+}
+
+/// Creates a new virtio tablet, an absolute pointing device with primary, secondary and middle
+/// buttons and a wheel.
+pub fn new_tablet<T>(
     idx: u32,
     source: T,
     width: u32,
@@ -759,7 +885,7 @@ where
     T: Read + Write + AsRawDescriptor + Send + 'static,
 {
     Ok(Input::new(
-        defaults::new_multi_touch_config(idx, width, height, name),
+        defaults::new_tablet_config(idx, width, height, name),
         Some(SocketEventSource::new(source)),
         virtio_features,
     ))
@@ -1292,7 +1418,7 @@ mod tests {
             include_str!("../../../tests/data/input/example_custom_multitouchscreen_config.json");
         fs::write(&path, test_json).expect("Unable to write test file");
 
-        let default_config = new_multi_touch_config(0, 720, 1280, None);
+        let default_config = new_multi_touch_config(0, 720, 1280, None, None);
         let custom_config = parse_input_config_file(&path, 0).expect("Failed to parse JSON file");
 
         assert_eq!(
@@ -1302,4 +1428,39 @@ mod tests {
         assert_eq!(default_config.properties, custom_config.properties);
         assert_eq!(default_config.axis_info, custom_config.axis_info);
     }
+
+    #[test]
+    fn multi_touch_config_slots() {
+        let config = new_multi_touch_config(0, 720, 1280, Some(4), None);
+        assert_eq!(
+            config.axis_info.get(&ABS_MT_SLOT),
+            Some(&virtio_input_absinfo::new(0, 3, 0, 0))
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn reset_device_signals_wakeup() {
+        use std::os::unix::net::UnixStream;
+        use std::time::Duration;
+
+        use base::EventWaitResult;
+
+        let (mut host, guest) = UnixStream::pair().unwrap();
+        let mut input = new_keyboard(0, guest, 0).unwrap();
+        let wakeup_evt = Event::new().unwrap();
+        input.set_wakeup_event(Some(wakeup_evt.try_clone().unwrap()));
+
+        // The guest driver resets the device when it suspends.
+        input.reset().unwrap();
+        host.write_all(&[0u8; virtio_input_event::SIZE]).unwrap();
+        assert_eq!(
+            wakeup_evt.wait_timeout(Duration::from_secs(5)).unwrap(),
+            EventWaitResult::Signaled
+        );
+
+        // The event source is handed back for the next activation.
+        input.stop_wakeup_thread();
+        assert!(input.source.is_some());
+    }
 }
@@ -7,6 +7,7 @@ pub mod memory_mapper;
 pub mod protocol;
 pub(crate) mod sys;
 
+use std::any::TypeId;
 use std::cell::RefCell;
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
@@ -159,9 +160,28 @@ pub enum IommuError {
     WriteBufferTooSmall,
 }
 
+type Mapper = Arc<Mutex<Box<dyn MemoryMapperTrait>>>;
+
+// Identifies the mapper instance of an endpoint. Endpoints of the same VFIO group share a mapper
+// instance.
+fn mapper_key(mapper: &Mapper) -> (TypeId, u32) {
+    let m = mapper.lock();
+    ((**m).type_id(), m.id())
+}
+
+// An IOMMU domain, which can be shared by endpoints with different mappers, e.g. by several
+// passthrough devices assigned to the same VFIO container in the guest.
+struct Domain {
+    // Mappers of the endpoints attached to the domain, with the number of endpoints using each of
+    // them. All the mappers hold the mappings of the domain.
+    mappers: Vec<(u32, Mapper)>,
+    // Mappings of the domain, replayed into the mappers of the endpoints attached later.
+    // key: IOVA
+    maps: BTreeMap<u64, MappingInfo>,
+}
+
 // key: domain ID
-// value: reference counter and MemoryMapperTrait
-type DomainMap = BTreeMap<u32, (u32, Arc<Mutex<Box<dyn MemoryMapperTrait>>>)>;
+type DomainMap = BTreeMap<u32, Domain>;
 
 struct DmabufRegionEntry {
     mmap: MemoryMapping,
@@ -186,7 +206,7 @@ struct State {
     // Contains all pass-through endpoints that attach to this IOMMU device
     // key: endpoint PCI address
     // value: reference counter and MemoryMapperTrait
-    endpoints: BTreeMap<u32, Arc<Mutex<Box<dyn MemoryMapperTrait>>>>,
+    endpoints: BTreeMap<u32, Mapper>,
     // Contains dmabuf regions
     // key: guest physical address
     dmabuf_mem: BTreeMap<u64, DmabufRegionEntry>,
@@ -200,30 +220,43 @@ impl State {
     // The device MUST ensure that after being detached from a domain, the endpoint
     // cannot access any mapping from that domain.
     //
-    // Currently, we only support detaching an endpoint if it is the only endpoint attached
-    // to its domain.
+    // Currently, we only support detaching an endpoint if no other endpoint attached to
+    // its domain uses the same mapper.
     fn detach_endpoint(
         endpoint_map: &mut BTreeMap<u32, u32>,
         domain_map: &mut DomainMap,
         endpoint: u32,
+        mapper: &Mapper,
     ) -> (bool, Option<EventAsync>) {
         let mut evt = None;
         // The endpoint has attached to an IOMMU domain
         if let Some(attached_domain) = endpoint_map.get(&endpoint) {
-            // Remove the entry or update the domain reference count
-            if let Entry::Occupied(o) = domain_map.entry(*attached_domain) {
-                let (refs, mapper) = o.get();
+            // Remove the mapper from the domain or update its reference count
+            if let Entry::Occupied(mut o) = domain_map.entry(*attached_domain) {
                 if !mapper.lock().supports_detach() {
                     return (false, None);
                 }
 
-                match refs {
-                    0 => unreachable!(),
-                    1 => {
-                        evt = mapper.lock().reset_domain();
-                        o.remove();
+                let key = mapper_key(mapper);
+                let domain = o.get_mut();
+                if let Some(index) = domain
+                    .mappers
+                    .iter()
+                    .position(|(_, m)| mapper_key(m) == key)
+                {
+                    match domain.mappers[index].0 {
+                        0 => unreachable!(),
+                        1 => {
+                            // The mapper is only used by this domain, so resetting it removes
+                            // all the mappings of the domain from it.
+                            evt = mapper.lock().reset_domain();
+                            domain.mappers.remove(index);
+                        }
+                        _ => return (false, None),
                     }
-                    _ => return (false, None),
+                }
+                if domain.mappers.is_empty() {
+                    o.remove();
                 }
             }
         }
@@ -232,6 +265,37 @@ impl State {
         (true, evt)
     }
 
+    // Maps `map` into `mapper`, resolving the guest physical addresses of dmabufs to the host
+    // addresses of their mappings.
+    fn add_map(&self, mapper: &Mapper, map: &MappingInfo) -> Result<AddMapResult> {
+        let gpa = map.gpa.offset();
+        let dmabuf_map =
+            self.dmabuf_mem
+                .range(..=gpa)
+                .next_back()
+                .and_then(|(base_gpa, region)| {
+                    if gpa + map.size <= base_gpa + region.size {
+                        let offset = gpa - base_gpa;
+                        Some(region.mmap.as_ptr() as u64 + offset)
+                    } else {
+                        None
+                    }
+                });
+
+        match dmabuf_map {
+            // SAFETY:
+            // Safe because [dmabuf_map, dmabuf_map + size) refers to an external mmap'ed
+            // region.
+            Some(dmabuf_map) => unsafe {
+                mapper
+                    .lock()
+                    .vfio_dma_map(map.iova, dmabuf_map, map.size, map.prot)
+            },
+            None => mapper.lock().add_map(*map),
+        }
+        .map_err(IommuError::MemoryMapper)
+    }
+
     // Processes an attach request. This may require detaching the endpoint from
     // its current endpoint before attaching it to a new endpoint. If that happens
     // while the endpoint has exported memory, this function returns an event that
@@ -254,47 +318,39 @@ impl State {
         &mut self,
         reader: &mut Reader,
         tail: &mut virtio_iommu_req_tail,
-    ) -> Result<(usize, Option<EventAsync>)> {
+    ) -> Result<(usize, Vec<EventAsync>)> {
         let req: virtio_iommu_req_attach =
             reader.read_obj().map_err(IommuError::GuestMemoryRead)?;
-        let mut fault_resolved_event = None;
+        let mut fault_resolved_events = Vec::new();
 
         // If the reserved field of an ATTACH request is not zero,
         // the device MUST reject the request and set status to
         // VIRTIO_IOMMU_S_INVAL.
         if req.reserved.iter().any(|&x| x != 0) {
             tail.status = VIRTIO_IOMMU_S_INVAL;
-            return Ok((0, None));
+            return Ok((0, fault_resolved_events));
         }
 
         let domain: u32 = req.domain.into();
         let endpoint: u32 = req.endpoint.into();
 
-        if let Some(mapper) = self.endpoints.get(&endpoint) {
+        if let Some(mapper) = self.endpoints.get(&endpoint).cloned() {
             // The same mapper can't be used for two domains at the same time,
             // since that would result in conflicts/permission leaks between
             // the two domains.
-            let mapper_id = {
-                let m = mapper.lock();
-                ((**m).type_id(), m.id())
-            };
+            let mapper_id = mapper_key(&mapper);
             for (other_endpoint, other_mapper) in self.endpoints.iter() {
                 if *other_endpoint == endpoint {
                     continue;
                 }
-                let other_id = {
-                    let m = other_mapper.lock();
-                    ((**m).type_id(), m.id())
-                };
-                if mapper_id == other_id {
-                    if !self
+                if mapper_id == mapper_key(other_mapper)
+                    && !self
                         .endpoint_map
                         .get(other_endpoint)
                         .map_or(true, |d| d == &domain)
-                    {
-                        tail.status = VIRTIO_IOMMU_S_UNSUPP;
-                        return Ok((0, None));
-                    }
+                {
+                    tail.status = VIRTIO_IOMMU_S_UNSUPP;
+                    return Ok((0, fault_resolved_events));
                 }
             }
 
@@ -306,22 +362,53 @@ impl State {
                 // a DETACH request with this endpoint, followed by the ATTACH
                 // request. If the device cannot do so, it MUST reject the request
                 // and set status to VIRTIO_IOMMU_S_UNSUPP.
-                let (detached, evt) =
-                    Self::detach_endpoint(&mut self.endpoint_map, &mut self.domain_map, endpoint);
+                let (detached, evt) = Self::detach_endpoint(
+                    &mut self.endpoint_map,
+                    &mut self.domain_map,
+                    endpoint,
+                    &mapper,
+                );
                 if !detached {
                     tail.status = VIRTIO_IOMMU_S_UNSUPP;
-                    return Ok((0, None));
+                    return Ok((0, fault_resolved_events));
                 }
-                fault_resolved_event = evt;
+                fault_resolved_events.extend(evt);
             }
 
-            let new_ref = match self.domain_map.get(&domain) {
-                None => 1,
-                Some(val) => val.0 + 1,
-            };
-
+            let attached = self.domain_map.get_mut(&domain).and_then(|d| {
+                d.mappers
+                    .iter_mut()
+                    .find(|(_, m)| mapper_key(m) == mapper_id)
+            });
+            if let Some((refs, _)) = attached {
+                *refs += 1;
+            } else {
+                // The endpoint joins a domain which may already hold mappings for the other
+                // endpoints, so its mapper receives all of them.
+                let maps: Vec<MappingInfo> = self
+                    .domain_map
+                    .get(&domain)
+                    .map(|d| d.maps.values().copied().collect())
+                    .unwrap_or_default();
+                for map in &maps {
+                    if self.add_map(&mapper, map)? != AddMapResult::Ok {
+                        return Err(IommuError::MemoryMapper(anyhow!(
+                            "failed to replay mapping of domain {} at iova {:#x}",
+                            domain,
+                            map.iova
+                        )));
+                    }
+                }
+                self.domain_map
+                    .entry(domain)
+                    .or_insert_with(|| Domain {
+                        mappers: Vec::new(),
+                        maps: BTreeMap::new(),
+                    })
+                    .mappers
+                    .push((1, mapper));
+            }
             self.endpoint_map.insert(endpoint, domain);
-            self.domain_map.insert(domain, (new_ref, mapper.clone()));
         } else {
             // If the endpoint identified by endpoint doesn’t exist,
             // the device MUST reject the request and set status to
@@ -329,14 +416,14 @@ impl State {
             tail.status = VIRTIO_IOMMU_S_NOENT;
         }
 
-        Ok((0, fault_resolved_event))
+        Ok((0, fault_resolved_events))
     }
 
     fn process_detach_request(
         &mut self,
         reader: &mut Reader,
         tail: &mut virtio_iommu_req_tail,
-    ) -> Result<(usize, Option<EventAsync>)> {
+    ) -> Result<(usize, Vec<EventAsync>)> {
         let req: virtio_iommu_req_detach =
             reader.read_obj().map_err(IommuError::GuestMemoryRead)?;
 
@@ -344,17 +431,21 @@ impl State {
         // the device MUST reject the request and set status to
         // VIRTIO_IOMMU_S_NOENT.
         let endpoint: u32 = req.endpoint.into();
-        if !self.endpoints.contains_key(&endpoint) {
+        let Some(mapper) = self.endpoints.get(&endpoint) else {
             tail.status = VIRTIO_IOMMU_S_NOENT;
-            return Ok((0, None));
-        }
+            return Ok((0, Vec::new()));
+        };
 
-        let (detached, evt) =
-            Self::detach_endpoint(&mut self.endpoint_map, &mut self.domain_map, endpoint);
+        let (detached, evt) = Self::detach_endpoint(
+            &mut self.endpoint_map,
+            &mut self.domain_map,
+            endpoint,
+            mapper,
+        );
         if !detached {
             tail.status = VIRTIO_IOMMU_S_UNSUPP;
         }
-        Ok((0, evt))
+        Ok((0, evt.into_iter().collect()))
     }
 
     fn process_dma_map_request(
@@ -393,70 +484,62 @@ impl State {
         }
 
         let domain: u32 = req.domain.into();
-        if !self.domain_map.contains_key(&domain) {
+        let Some(mappers) = self
+            .domain_map
+            .get(&domain)
+            .map(|d| d.mappers.iter().map(|(_, m)| m.clone()).collect::<Vec<_>>())
+        else {
             // If domain does not exist, the device SHOULD reject
             // the request and set status to VIRTIO_IOMMU_S_NOENT.
             tail.status = VIRTIO_IOMMU_S_NOENT;
             return Ok(0);
-        }
+        };
 
         // The device MUST NOT allow writes to a range mapped
         // without the VIRTIO_IOMMU_MAP_F_WRITE flag.
         let write_en = u32::from(req.flags) & VIRTIO_IOMMU_MAP_F_WRITE != 0;
 
-        if let Some(mapper) = self.domain_map.get(&domain) {
-            let gpa = phys_start;
-            let iova = virt_start;
-            let Some(size) = u64::checked_add(virt_end - virt_start, 1) else {
-                // implementation doesn't support unlikely request for size == U64::MAX+1
-                tail.status = VIRTIO_IOMMU_S_DEVERR;
-                return Ok(0);
-            };
-
-            let dmabuf_map =
-                self.dmabuf_mem
-                    .range(..=gpa)
-                    .next_back()
-                    .and_then(|(base_gpa, region)| {
-                        if gpa + size <= base_gpa + region.size {
-                            let offset = gpa - base_gpa;
-                            Some(region.mmap.as_ptr() as u64 + offset)
-                        } else {
-                            None
-                        }
-                    });
-
-            let prot = match write_en {
-                true => Protection::read_write(),
-                false => Protection::read(),
-            };
+        let Some(size) = u64::checked_add(virt_end - virt_start, 1) else {
+            // implementation doesn't support unlikely request for size == U64::MAX+1
+            tail.status = VIRTIO_IOMMU_S_DEVERR;
+            return Ok(0);
+        };
 
-            let vfio_map_result = match dmabuf_map {
-                // SAFETY:
-                // Safe because [dmabuf_map, dmabuf_map + size) refers to an external mmap'ed
-                // region.
-                Some(dmabuf_map) => unsafe {
-                    mapper.1.lock().vfio_dma_map(iova, dmabuf_map, size, prot)
-                },
-                None => mapper.1.lock().add_map(MappingInfo {
-                    iova,
-                    gpa: GuestAddress(gpa),
-                    size,
-                    prot,
-                }),
-            };
+        let prot = match write_en {
+            true => Protection::read_write(),
+            false => Protection::read(),
+        };
 
-            match vfio_map_result {
-                Ok(AddMapResult::Ok) => (),
-                Ok(AddMapResult::OverlapFailure) => {
-                    // If a mapping already exists in the requested range,
-                    // the device SHOULD reject the request and set status
-                    // to VIRTIO_IOMMU_S_INVAL.
+        let map = MappingInfo {
+            iova: virt_start,
+            gpa: GuestAddress(phys_start),
+            size,
+            prot,
+        };
+        for (i, mapper) in mappers.iter().enumerate() {
+            match self.add_map(mapper, &map)? {
+                AddMapResult::Ok => (),
+                // If a mapping already exists in the requested range,
+                // the device SHOULD reject the request and set status
+                // to VIRTIO_IOMMU_S_INVAL.
+                AddMapResult::OverlapFailure if i == 0 => {
                     tail.status = VIRTIO_IOMMU_S_INVAL;
+                    return Ok(0);
+                }
+                // All the mappers hold the same mappings, so only the first one can report an
+                // overlap.
+                AddMapResult::OverlapFailure => {
+                    return Err(IommuError::MemoryMapper(anyhow!(
+                        "mappers of domain {} are out of sync at iova {:#x}",
+                        domain,
+                        virt_start
+                    )));
                 }
-                Err(e) => return Err(IommuError::MemoryMapper(e)),
             }
         }
+        if let Some(d) = self.domain_map.get_mut(&domain) {
+            d.maps.insert(virt_start, map);
+        }
 
         Ok(0)
     }
@@ -465,36 +548,68 @@ impl State {
         &mut self,
         reader: &mut Reader,
         tail: &mut virtio_iommu_req_tail,
-    ) -> Result<(usize, Option<EventAsync>)> {
+    ) -> Result<(usize, Vec<EventAsync>)> {
         let req: virtio_iommu_req_unmap = reader.read_obj().map_err(IommuError::GuestMemoryRead)?;
 
         let domain: u32 = req.domain.into();
-        let fault_resolved_event = if let Some(mapper) = self.domain_map.get(&domain) {
-            let size = u64::from(req.virt_end) - u64::from(req.virt_start) + 1;
-            let res = mapper
-                .1
-                .lock()
-                .remove_map(u64::from(req.virt_start), size)
-                .map_err(IommuError::MemoryMapper)?;
-            match res {
-                RemoveMapResult::Success(evt) => evt,
-                RemoveMapResult::OverlapFailure => {
-                    // If a mapping affected by the range is not covered in its entirety by the
-                    // range (the UNMAP request would split the mapping), then the device SHOULD
-                    // set the request `status` to VIRTIO_IOMMU_S_RANGE, and SHOULD NOT remove
-                    // any mapping.
-                    tail.status = VIRTIO_IOMMU_S_RANGE;
-                    None
-                }
-            }
-        } else {
+        let mut fault_resolved_events = Vec::new();
+        let Some(d) = self.domain_map.get_mut(&domain) else {
             // If domain does not exist, the device SHOULD set the
             // request status to VIRTIO_IOMMU_S_NOENT
             tail.status = VIRTIO_IOMMU_S_NOENT;
-            None
+            return Ok((0, fault_resolved_events));
         };
 
-        Ok((0, fault_resolved_event))
+        let virt_start = u64::from(req.virt_start);
+        let virt_end = u64::from(req.virt_end);
+        // The mapping starting before the range may overlap it.
+        let first = d
+            .maps
+            .range(..virt_start)
+            .next_back()
+            .filter(|(_, m)| m.iova + m.size > virt_start)
+            .map_or(virt_start, |(iova, _)| *iova);
+        let iovas: Vec<u64> = d
+            .maps
+            .range(first..=virt_end)
+            .map(|(iova, _)| *iova)
+            .collect();
+
+        // If a mapping affected by the range is not covered in its entirety by the
+        // range (the UNMAP request would split the mapping), then the device SHOULD
+        // set the request `status` to VIRTIO_IOMMU_S_RANGE, and SHOULD NOT remove
+        // any mapping.
+        if iovas
+            .iter()
+            .any(|iova| *iova < virt_start || iova + (d.maps[iova].size - 1) > virt_end)
+        {
+            tail.status = VIRTIO_IOMMU_S_RANGE;
+            return Ok((0, fault_resolved_events));
+        }
+
+        for iova in iovas {
+            let Some(map) = d.maps.remove(&iova) else {
+                continue;
+            };
+            for (_, mapper) in &d.mappers {
+                let res = mapper
+                    .lock()
+                    .remove_map(map.iova, map.size)
+                    .map_err(IommuError::MemoryMapper)?;
+                match res {
+                    RemoveMapResult::Success(evt) => fault_resolved_events.extend(evt),
+                    RemoveMapResult::OverlapFailure => {
+                        return Err(IommuError::MemoryMapper(anyhow!(
+                            "mappers of domain {} are out of sync at iova {:#x}",
+                            domain,
+                            map.iova
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok((0, fault_resolved_events))
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -562,7 +677,7 @@ impl State {
     fn execute_request(
         &mut self,
         avail_desc: &mut DescriptorChain,
-    ) -> Result<(usize, Option<EventAsync>)> {
+    ) -> Result<(usize, Vec<EventAsync>)> {
         let reader = &mut avail_desc.reader;
         let writer = &mut avail_desc.writer;
 
@@ -579,13 +694,16 @@ impl State {
             ..Default::default()
         };
 
-        let (reply_len, fault_resolved_event) = match req_head.type_ {
+        let (reply_len, fault_resolved_events) = match req_head.type_ {
             VIRTIO_IOMMU_T_ATTACH => self.process_attach_request(reader, &mut tail)?,
             VIRTIO_IOMMU_T_DETACH => self.process_detach_request(reader, &mut tail)?,
-            VIRTIO_IOMMU_T_MAP => (self.process_dma_map_request(reader, &mut tail)?, None),
+            VIRTIO_IOMMU_T_MAP => (self.process_dma_map_request(reader, &mut tail)?, Vec::new()),
             VIRTIO_IOMMU_T_UNMAP => self.process_dma_unmap_request(reader, &mut tail)?,
             #[cfg(target_arch = "x86_64")]
-            VIRTIO_IOMMU_T_PROBE => (self.process_probe_request(reader, writer, &mut tail)?, None),
+            VIRTIO_IOMMU_T_PROBE => (
+                self.process_probe_request(reader, writer, &mut tail)?,
+                Vec::new(),
+            ),
             _ => return Err(IommuError::UnexpectedDescriptor),
         };
 
@@ -594,7 +712,7 @@ impl State {
             .map_err(IommuError::GuestMemoryWrite)?;
         Ok((
             reply_len + size_of::<virtio_iommu_req_tail>(),
-            fault_resolved_event,
+            fault_resolved_events,
         ))
     }
 }
@@ -610,7 +728,7 @@ async fn request_queue(
             .await
             .map_err(IommuError::ReadAsyncDesc)?;
 
-        let (len, fault_resolved_event) = match state.borrow_mut().execute_request(&mut avail_desc)
+        let (len, fault_resolved_events) = match state.borrow_mut().execute_request(&mut avail_desc)
         {
             Ok(res) => res,
             Err(e) => {
@@ -618,11 +736,11 @@ async fn request_queue(
 
                 // If a request type is not recognized, the device SHOULD NOT write
                 // the buffer and SHOULD set the used length to zero
-                (0, None)
+                (0, Vec::new())
             }
         };
 
-        if let Some(fault_resolved_event) = fault_resolved_event {
+        for fault_resolved_event in fault_resolved_events {
             debug!("waiting for iommu fault resolution");
             fault_resolved_event
                 .next_val()
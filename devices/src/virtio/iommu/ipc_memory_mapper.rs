@@ -31,7 +31,7 @@ use zerocopy::IntoBytes;
 use crate::virtio::memory_mapper::MemRegion;
 
 #[derive(Serialize, Deserialize)]
-pub(super) enum IommuRequest {
+pub(crate) enum IommuRequest {
     Export {
         endpoint_id: u32,
         iova: u64,
@@ -48,7 +48,7 @@ pub(super) enum IommuRequest {
 }
 
 #[derive(Serialize, Deserialize)]
-pub(super) enum IommuResponse {
+pub(crate) enum IommuResponse {
     Export(Vec<MemRegion>),
     Release,
     StartExportSession(Event),
@@ -56,7 +56,7 @@ pub(super) enum IommuResponse {
 }
 
 impl IommuRequest {
-    pub(super) fn get_endpoint_id(&self) -> u32 {
+    pub(crate) fn get_endpoint_id(&self) -> u32 {
         match self {
             Self::Export { endpoint_id, .. } => *endpoint_id,
             Self::Release { endpoint_id, .. } => *endpoint_id,
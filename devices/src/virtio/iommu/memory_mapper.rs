@@ -35,7 +35,7 @@ pub struct MemRegion {
 }
 
 /// Manages the mapping from a guest IO virtual address space to the guest physical address space
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct MappingInfo {
     pub iova: u64,
     pub gpa: GuestAddress,
@@ -5,6 +5,7 @@
 pub mod vfio_wrapper;
 
 use std::cell::RefCell;
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::rc::Rc;
@@ -25,6 +26,7 @@ use vm_control::VmMemoryRegionId;
 use self::vfio_wrapper::VfioWrapper;
 use crate::virtio::iommu::ipc_memory_mapper::IommuRequest;
 use crate::virtio::iommu::ipc_memory_mapper::IommuResponse;
+use crate::virtio::iommu::mapper_key;
 use crate::virtio::iommu::DmabufRegionEntry;
 use crate::virtio::iommu::Result;
 use crate::virtio::iommu::State;
@@ -59,12 +61,25 @@ impl State {
         &mut self,
         pci_address: u32,
     ) -> VirtioIOMMUVfioResult {
-        if self.endpoints.remove(&pci_address).is_none() {
+        let Some(mapper) = self.endpoints.remove(&pci_address) else {
             error!("There is no vfio container of {}", pci_address);
             return VirtioIOMMUVfioResult::NoSuchDevice;
-        }
+        };
         if let Some(domain) = self.endpoint_map.remove(&pci_address) {
-            self.domain_map.remove(&domain);
+            // The domain may still be used by the other endpoints.
+            if let Entry::Occupied(mut o) = self.domain_map.entry(domain) {
+                let key = mapper_key(&mapper);
+                let mappers = &mut o.get_mut().mappers;
+                if let Some(index) = mappers.iter().position(|(_, m)| mapper_key(m) == key) {
+                    mappers[index].0 -= 1;
+                    if mappers[index].0 == 0 {
+                        mappers.remove(index);
+                    }
+                }
+                if mappers.is_empty() {
+                    o.remove();
+                }
+            }
         }
         VirtioIOMMUVfioResult::Ok
     }
@@ -75,6 +90,7 @@ impl State {
         gpa: u64,
         size: u64,
         dma_buf: File,
+        offset: u64,
     ) -> VirtioIOMMUVfioResult {
         if gpa & self.page_mask != 0 {
             error!("cannot map dmabuf to non-page-aligned guest physical address");
@@ -82,6 +98,7 @@ impl State {
         }
         let mmap = match MemoryMappingBuilder::new(size as usize)
             .from_file(&dma_buf)
+            .offset(offset)
             .build()
         {
             Ok(v) => v,
@@ -146,7 +163,8 @@ impl State {
                 gpa,
                 size,
                 dma_buf,
-            } => self.handle_map_dmabuf(region_id, gpa, size, File::from(dma_buf)),
+                offset,
+            } => self.handle_map_dmabuf(region_id, gpa, size, File::from(dma_buf), offset),
             VfioDmabufUnmap(region_id) => self.handle_unmap_dmabuf(region_id),
         };
         VirtioIOMMUResponse::VfioResponse(vfio_result)
@@ -7,10 +7,12 @@
 use std::sync::Arc;
 
 use anyhow::Context;
+use base::error;
 use base::AsRawDescriptor;
 use base::AsRawDescriptors;
 use base::Protection;
 use base::RawDescriptor;
+use cros_async::EventAsync;
 use sync::Mutex;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
@@ -116,19 +118,30 @@ impl MemoryMapper for VfioWrapper {
     }
 
     fn supports_detach(&self) -> bool {
-        // A few reasons why we don't support detach:
+        // The container only holds the mappings of the domain the group is attached to, so
+        // detaching the group is done by removing all of them. This allows the guest to move a
+        // passthrough device between domains, e.g. when it is assigned to guest userspace with
+        // VFIO.
         //
-        // 1. Seems it's not possible to dynamically attach and detach a IOMMU domain if the virtio
-        //    IOMMU device is running on top of VFIO
-        // 2. Even if VIRTIO_IOMMU_T_DETACH is implemented in front-end driver, it could violate the
-        //    following virtio IOMMU spec: Detach an endpoint from a domain. when this request
-        //    completes, the endpoint cannot access any mapping from that domain anymore.
+        // However, this could violate the following virtio IOMMU spec if the group has several
+        // devices: Detach an endpoint from a domain. when this request completes, the endpoint
+        // cannot access any mapping from that domain anymore.
         //
-        //    This is because VFIO doesn't support detaching a single device. When the virtio-iommu
-        //    device receives a VIRTIO_IOMMU_T_DETACH request, it can either to:
-        //    - detach a group: any other endpoints in the group lose access to the domain.
-        //    - do not detach the group at all: this breaks the above mentioned spec.
-        false
+        // This is because VFIO doesn't support detaching a single device. When the virtio-iommu
+        // device receives a VIRTIO_IOMMU_T_DETACH request, it can either to:
+        // - detach a group: any other endpoints in the group lose access to the domain.
+        // - do not detach the group at all: this breaks the above mentioned spec.
+        //
+        // Containers of hotplugged devices are passed without their groups, so the number of
+        // devices is unknown and they are never detached.
+        self.container.lock().device_count() == 1
+    }
+
+    fn reset_domain(&mut self) -> Option<EventAsync> {
+        if let Err(e) = self.container.lock().vfio_dma_unmap_all() {
+            error!("failed to reset vfio container: {}", e);
+        }
+        None
     }
 
     fn id(&self) -> u32 {
@@ -4,7 +4,7 @@
 
 //! Implements virtio devices, queues, and transport mechanisms.
 
-mod async_utils;
+pub(crate) mod async_utils;
 #[cfg(feature = "balloon")]
 mod balloon;
 mod descriptor_chain;
@@ -27,6 +27,8 @@ mod virtio_device;
 mod virtio_mmio_device;
 mod virtio_pci_common_config;
 mod virtio_pci_device;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 pub mod block;
 pub mod console;
@@ -63,6 +65,8 @@ pub use self::gpu::GpuDisplayMode;
 #[cfg(feature = "gpu")]
 pub use self::gpu::GpuDisplayParameters;
 #[cfg(feature = "gpu")]
+pub use self::gpu::GpuDisplayRotation;
+#[cfg(feature = "gpu")]
 pub use self::gpu::GpuMode;
 #[cfg(feature = "gpu")]
 pub use self::gpu::GpuMouseMode;
@@ -86,6 +90,7 @@ pub use self::net::NetParameters;
 pub use self::net::NetParametersMode;
 pub use self::queue::split_descriptor_chain::Desc;
 pub use self::queue::split_descriptor_chain::SplitDescriptorChain;
+pub use self::queue::InflightQueue;
 pub use self::queue::PeekedDescriptorChain;
 pub use self::queue::Queue;
 pub use self::queue::QueueConfig;
@@ -108,17 +113,26 @@ pub use self::virtio_pci_device::PciCapabilityType;
 pub use self::virtio_pci_device::VirtioPciCap;
 pub use self::virtio_pci_device::VirtioPciDevice;
 pub use self::virtio_pci_device::VirtioPciShmCap;
+#[cfg(feature = "wasm")]
+pub use self::wasm::WasmDevice;
+#[cfg(feature = "wasm")]
+pub use self::wasm::WasmDeviceParameters;
 #[cfg(feature = "pvclock")]
 pub use self::DeviceType::Pvclock;
 
 cfg_if::cfg_if! {
     if #[cfg(any(target_os = "android", target_os = "linux"))] {
+        mod can;
         mod p9;
         mod pmem;
+        mod spi;
 
         pub mod wl;
         pub mod fs;
 
+        pub use self::can::Can;
+        pub use self::can::CanFilter;
+        pub use self::can::CanParameters;
         pub use self::iommu::sys::linux::vfio_wrapper;
         #[cfg(feature = "net")]
         pub use self::net::VhostNetParameters;
@@ -128,6 +142,8 @@ cfg_if::cfg_if! {
         pub use self::pmem::Pmem;
         pub use self::pmem::PmemConfig;
         pub use self::pmem::MemSlotConfig;
+        pub use self::spi::Spi;
+        pub use self::spi::SpiParameters;
         #[cfg(feature = "audio")]
         pub use self::snd::new_sound;
         pub use self::wl::Wl;
@@ -158,37 +174,109 @@ const INTERRUPT_STATUS_CONFIG_CHANGED: u32 = 0x2;
 
 const VIRTIO_MSI_NO_VECTOR: u16 = 0xffff;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "kebab-case")]
-#[repr(u32)]
+/// Largest virtio device ID that can be exposed as a virtio-pci device, whose PCI device ID is
+/// 0x1040 plus the virtio device ID, within 0x1040-0x107f.
+const VIRTIO_ID_MAX: u32 = 0x3f;
+
+/// A virtio device type.
+///
+/// Device types that crosvm does not implement natively (e.g. ones only provided by an external
+/// vhost-user backend) are represented by `Custom` with their raw virtio device ID, which must be
+/// accepted by `from_virtio_id`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum DeviceType {
-    Net = virtio_ids::VIRTIO_ID_NET,
-    Block = virtio_ids::VIRTIO_ID_BLOCK,
-    Console = virtio_ids::VIRTIO_ID_CONSOLE,
-    Rng = virtio_ids::VIRTIO_ID_RNG,
-    Balloon = virtio_ids::VIRTIO_ID_BALLOON,
-    Scsi = virtio_ids::VIRTIO_ID_SCSI,
-    #[serde(rename = "9p")]
-    P9 = virtio_ids::VIRTIO_ID_9P,
-    Gpu = virtio_ids::VIRTIO_ID_GPU,
-    Input = virtio_ids::VIRTIO_ID_INPUT,
-    Vsock = virtio_ids::VIRTIO_ID_VSOCK,
-    Iommu = virtio_ids::VIRTIO_ID_IOMMU,
-    Sound = virtio_ids::VIRTIO_ID_SOUND,
-    Fs = virtio_ids::VIRTIO_ID_FS,
-    Pmem = virtio_ids::VIRTIO_ID_PMEM,
-    #[serde(rename = "mac80211-hwsim")]
-    Mac80211HwSim = virtio_ids::VIRTIO_ID_MAC80211_HWSIM,
-    VideoEncoder = virtio_ids::VIRTIO_ID_VIDEO_ENCODER,
-    VideoDecoder = virtio_ids::VIRTIO_ID_VIDEO_DECODER,
-    Scmi = virtio_ids::VIRTIO_ID_SCMI,
-    Wl = virtio_ids::VIRTIO_ID_WL,
-    Tpm = virtio_ids::VIRTIO_ID_TPM,
-    Pvclock = virtio_ids::VIRTIO_ID_PVCLOCK,
-    Media = virtio_ids::VIRTIO_ID_MEDIA,
+    Net,
+    Block,
+    Console,
+    Rng,
+    Balloon,
+    Scsi,
+    P9,
+    Gpu,
+    Input,
+    Vsock,
+    Iommu,
+    Sound,
+    Fs,
+    Pmem,
+    Mac80211HwSim,
+    VideoEncoder,
+    VideoDecoder,
+    Scmi,
+    Wl,
+    Tpm,
+    Pvclock,
+    Media,
+    Can,
+    Spi,
+    Custom(u32),
 }
 
 impl DeviceType {
+    /// Returns the device type corresponding to the virtio device ID `id`, or `None` if `id` is
+    /// the reserved ID 0 or is too large for a virtio-pci device.
+    pub fn from_virtio_id(id: u32) -> Option<DeviceType> {
+        Some(match id {
+            virtio_ids::VIRTIO_ID_NET => DeviceType::Net,
+            virtio_ids::VIRTIO_ID_BLOCK => DeviceType::Block,
+            virtio_ids::VIRTIO_ID_CONSOLE => DeviceType::Console,
+            virtio_ids::VIRTIO_ID_RNG => DeviceType::Rng,
+            virtio_ids::VIRTIO_ID_BALLOON => DeviceType::Balloon,
+            virtio_ids::VIRTIO_ID_SCSI => DeviceType::Scsi,
+            virtio_ids::VIRTIO_ID_9P => DeviceType::P9,
+            virtio_ids::VIRTIO_ID_GPU => DeviceType::Gpu,
+            virtio_ids::VIRTIO_ID_INPUT => DeviceType::Input,
+            virtio_ids::VIRTIO_ID_VSOCK => DeviceType::Vsock,
+            virtio_ids::VIRTIO_ID_IOMMU => DeviceType::Iommu,
+            virtio_ids::VIRTIO_ID_SOUND => DeviceType::Sound,
+            virtio_ids::VIRTIO_ID_FS => DeviceType::Fs,
+            virtio_ids::VIRTIO_ID_PMEM => DeviceType::Pmem,
+            virtio_ids::VIRTIO_ID_MAC80211_HWSIM => DeviceType::Mac80211HwSim,
+            virtio_ids::VIRTIO_ID_VIDEO_ENCODER => DeviceType::VideoEncoder,
+            virtio_ids::VIRTIO_ID_VIDEO_DECODER => DeviceType::VideoDecoder,
+            virtio_ids::VIRTIO_ID_SCMI => DeviceType::Scmi,
+            virtio_ids::VIRTIO_ID_WL => DeviceType::Wl,
+            virtio_ids::VIRTIO_ID_TPM => DeviceType::Tpm,
+            virtio_ids::VIRTIO_ID_PVCLOCK => DeviceType::Pvclock,
+            virtio_ids::VIRTIO_ID_MEDIA => DeviceType::Media,
+            virtio_ids::VIRTIO_ID_CAN => DeviceType::Can,
+            virtio_ids::VIRTIO_ID_SPI => DeviceType::Spi,
+            id if id == 0 || id > VIRTIO_ID_MAX => return None,
+            id => DeviceType::Custom(id),
+        })
+    }
+
+    /// Returns the virtio device ID of this device type.
+    pub fn virtio_id(&self) -> u32 {
+        match self {
+            DeviceType::Net => virtio_ids::VIRTIO_ID_NET,
+            DeviceType::Block => virtio_ids::VIRTIO_ID_BLOCK,
+            DeviceType::Console => virtio_ids::VIRTIO_ID_CONSOLE,
+            DeviceType::Rng => virtio_ids::VIRTIO_ID_RNG,
+            DeviceType::Balloon => virtio_ids::VIRTIO_ID_BALLOON,
+            DeviceType::Scsi => virtio_ids::VIRTIO_ID_SCSI,
+            DeviceType::P9 => virtio_ids::VIRTIO_ID_9P,
+            DeviceType::Gpu => virtio_ids::VIRTIO_ID_GPU,
+            DeviceType::Input => virtio_ids::VIRTIO_ID_INPUT,
+            DeviceType::Vsock => virtio_ids::VIRTIO_ID_VSOCK,
+            DeviceType::Iommu => virtio_ids::VIRTIO_ID_IOMMU,
+            DeviceType::Sound => virtio_ids::VIRTIO_ID_SOUND,
+            DeviceType::Fs => virtio_ids::VIRTIO_ID_FS,
+            DeviceType::Pmem => virtio_ids::VIRTIO_ID_PMEM,
+            DeviceType::Mac80211HwSim => virtio_ids::VIRTIO_ID_MAC80211_HWSIM,
+            DeviceType::VideoEncoder => virtio_ids::VIRTIO_ID_VIDEO_ENCODER,
+            DeviceType::VideoDecoder => virtio_ids::VIRTIO_ID_VIDEO_DECODER,
+            DeviceType::Scmi => virtio_ids::VIRTIO_ID_SCMI,
+            DeviceType::Wl => virtio_ids::VIRTIO_ID_WL,
+            DeviceType::Tpm => virtio_ids::VIRTIO_ID_TPM,
+            DeviceType::Pvclock => virtio_ids::VIRTIO_ID_PVCLOCK,
+            DeviceType::Media => virtio_ids::VIRTIO_ID_MEDIA,
+            DeviceType::Can => virtio_ids::VIRTIO_ID_CAN,
+            DeviceType::Spi => virtio_ids::VIRTIO_ID_SPI,
+            DeviceType::Custom(id) => *id,
+        }
+    }
+
     /// Returns the minimum number of queues that a device of the corresponding type must support.
     ///
     /// Note that this does not mean a driver must activate these queues, only that they must be
@@ -217,6 +305,9 @@ impl DeviceType {
             DeviceType::Tpm => 1,           // request queue
             DeviceType::Pvclock => 1,       // request queue
             DeviceType::Media => 2,         // commandq, eventq
+            DeviceType::Can => 3,           // txq, rxq, controlq
+            DeviceType::Spi => 1,           // requestq
+            DeviceType::Custom(_) => 1,     // unknown; at least one queue
         }
     }
 }
@@ -247,10 +338,103 @@ impl std::fmt::Display for DeviceType {
             DeviceType::Mac80211HwSim => write!(f, "mac80211-hwsim"),
             DeviceType::Scmi => write!(f, "scmi"),
             DeviceType::Media => write!(f, "media"),
+            DeviceType::Can => write!(f, "can"),
+            DeviceType::Spi => write!(f, "spi"),
+            DeviceType::Custom(id) => write!(f, "{}", id),
         }
     }
 }
 
+impl std::str::FromStr for DeviceType {
+    type Err = String;
+
+    /// Parses either the name of a device type (as printed by `Display`) or a numeric virtio
+    /// device ID.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "net" => DeviceType::Net,
+            "block" => DeviceType::Block,
+            "console" => DeviceType::Console,
+            "rng" => DeviceType::Rng,
+            "balloon" => DeviceType::Balloon,
+            "scsi" => DeviceType::Scsi,
+            "9p" => DeviceType::P9,
+            "gpu" => DeviceType::Gpu,
+            "input" => DeviceType::Input,
+            "vsock" => DeviceType::Vsock,
+            "iommu" => DeviceType::Iommu,
+            "sound" => DeviceType::Sound,
+            "fs" => DeviceType::Fs,
+            "pmem" => DeviceType::Pmem,
+            "mac80211-hwsim" => DeviceType::Mac80211HwSim,
+            "video-encoder" => DeviceType::VideoEncoder,
+            "video-decoder" => DeviceType::VideoDecoder,
+            "scmi" => DeviceType::Scmi,
+            "wl" => DeviceType::Wl,
+            "tpm" => DeviceType::Tpm,
+            "pvclock" => DeviceType::Pvclock,
+            "media" => DeviceType::Media,
+            "can" => DeviceType::Can,
+            "spi" => DeviceType::Spi,
+            s => {
+                let id: u32 = s
+                    .parse()
+                    .map_err(|_| format!("unknown virtio device type \"{}\"", s))?;
+                DeviceType::from_virtio_id(id)
+                    .ok_or_else(|| format!("invalid virtio device ID {}", id))?
+            }
+        })
+    }
+}
+
+impl Serialize for DeviceType {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            DeviceType::Custom(id) => serializer.serialize_u32(*id),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceType {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct DeviceTypeVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for DeviceTypeVisitor {
+            type Value = DeviceType;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a virtio device type name or numeric virtio device ID")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> std::result::Result<DeviceType, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> std::result::Result<DeviceType, E> {
+                u32::try_from(v)
+                    .ok()
+                    .and_then(DeviceType::from_virtio_id)
+                    .ok_or_else(|| E::custom(format!("invalid virtio device ID {}", v)))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> std::result::Result<DeviceType, E> {
+                u32::try_from(v)
+                    .ok()
+                    .and_then(DeviceType::from_virtio_id)
+                    .ok_or_else(|| E::custom(format!("invalid virtio device ID {}", v)))
+            }
+        }
+
+        deserializer.deserialize_any(DeviceTypeVisitor)
+    }
+}
+
 /// Copy virtio device configuration data from a subslice of `src` to a subslice of `dst`.
 /// Unlike std::slice::copy_from_slice(), this function copies as much as possible within
 /// the common subset of the two slices, truncating the requested range instead of
@@ -19,11 +19,13 @@ use base::error;
 #[cfg(windows)]
 use base::named_pipes::OverlappedWrapper;
 use base::warn;
+use base::AsRawDescriptor;
 use base::Error as SysError;
 use base::Event;
 use base::EventToken;
 use base::RawDescriptor;
 use base::ReadNotifier;
+use base::Tube;
 use base::WaitContext;
 use base::WorkerThread;
 use data_model::Le16;
@@ -44,6 +46,9 @@ use virtio_sys::virtio_net::VIRTIO_NET_CTRL_MQ;
 use virtio_sys::virtio_net::VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET;
 use virtio_sys::virtio_net::VIRTIO_NET_ERR;
 use virtio_sys::virtio_net::VIRTIO_NET_OK;
+use vm_control::NetConfigCommand;
+use vm_control::NetConfigResult;
+use vm_control::NetGuestOffloads;
 use vm_memory::GuestMemory;
 use zerocopy::FromBytes;
 use zerocopy::Immutable;
@@ -462,6 +467,8 @@ pub struct Net<T: TapT + ReadNotifier + 'static> {
     guest_mac: Option<[u8; 6]>,
     queue_sizes: Box<[u16]>,
     worker_threads: Vec<WorkerThread<Worker<T>>>,
+    control_tube: Option<Tube>,
+    control_worker: Option<WorkerThread<Tube>>,
     taps: Vec<T>,
     avail_features: u64,
     acked_features: u64,
@@ -554,6 +561,8 @@ where
             guest_mac: mac_addr.map(|mac| mac.octets()),
             queue_sizes: vec![QUEUE_SIZE; taps.len() * 2 + 1].into_boxed_slice(),
             worker_threads: Vec::new(),
+            control_tube: None,
+            control_worker: None,
             taps,
             avail_features,
             acked_features: 0u64,
@@ -566,11 +575,125 @@ where
         Ok(net)
     }
 
+    /// Sets the tube receiving `NetConfigCommand`s, which are handled while the device is active.
+    pub fn set_control_tube(&mut self, control_tube: Tube) {
+        self.control_tube = Some(control_tube);
+    }
+
     /// Returns the maximum number of receive/transmit queue pairs for this device.
     /// Only relevant when multi-queue support is negotiated.
     fn max_virtqueue_pairs(&self) -> usize {
         self.taps.len()
     }
+
+    fn start_control_worker(&mut self) {
+        let Some(control_tube) = self.control_tube.take() else {
+            return;
+        };
+        // Offloads are set for the whole tap device, through any of its queues.
+        let tap = match self.taps.first().map(|tap| tap.try_clone()) {
+            Some(Ok(tap)) => tap,
+            Some(Err(e)) => {
+                error!("net: failed to clone tap for the control worker: {}", e);
+                self.control_tube = Some(control_tube);
+                return;
+            }
+            None => {
+                self.control_tube = Some(control_tube);
+                return;
+            }
+        };
+        let acked_features = self.acked_features;
+        self.control_worker = Some(WorkerThread::start("v_net_ctl", move |kill_evt| {
+            if let Err(e) = run_control(&control_tube, &kill_evt, &tap, acked_features) {
+                error!("net control worker exited with error: {}", e);
+            }
+            control_tube
+        }));
+    }
+
+    fn stop_control_worker(&mut self) {
+        if let Some(control_worker) = self.control_worker.take() {
+            self.control_tube = Some(control_worker.stop());
+        }
+    }
+}
+
+#[derive(EventToken)]
+enum ControlToken {
+    Command,
+    Kill,
+}
+
+/// Converts `offloads` to the `VIRTIO_NET_F_GUEST_*` feature bits, the layout used by
+/// `VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET`.
+fn guest_offloads_to_features(offloads: NetGuestOffloads) -> u64 {
+    let mut features = 0;
+    if offloads.csum {
+        features |= 1 << virtio_net::VIRTIO_NET_F_GUEST_CSUM;
+    }
+    if offloads.tso4 {
+        features |= 1 << virtio_net::VIRTIO_NET_F_GUEST_TSO4;
+    }
+    if offloads.tso6 {
+        features |= 1 << virtio_net::VIRTIO_NET_F_GUEST_TSO6;
+    }
+    if offloads.ecn {
+        features |= 1 << virtio_net::VIRTIO_NET_F_GUEST_ECN;
+    }
+    if offloads.ufo {
+        features |= 1 << virtio_net::VIRTIO_NET_F_GUEST_UFO;
+    }
+    features
+}
+
+/// Handles the `NetConfigCommand`s received on `control_tube` until `kill_evt` is signaled.
+fn run_control<T: TapT>(
+    control_tube: &Tube,
+    kill_evt: &Event,
+    tap: &T,
+    acked_features: u64,
+) -> Result<(), NetError> {
+    let wait_ctx: WaitContext<ControlToken> = WaitContext::build_with(&[
+        (control_tube.get_read_notifier(), ControlToken::Command),
+        (kill_evt, ControlToken::Kill),
+    ])
+    .map_err(NetError::CreateWaitContext)?;
+
+    loop {
+        let events = wait_ctx.wait().map_err(NetError::WaitError)?;
+        for event in events.iter().filter(|e| e.is_readable) {
+            match event.token {
+                ControlToken::Command => {
+                    let command = match control_tube.recv::<NetConfigCommand>() {
+                        Ok(command) => command,
+                        Err(base::TubeError::Disconnected) => return Ok(()),
+                        Err(e) => {
+                            error!("net: failed to receive control command: {}", e);
+                            continue;
+                        }
+                    };
+                    let result = match command {
+                        NetConfigCommand::SetGuestOffloads { offloads } => {
+                            // The guest can only receive the offloads it negotiated.
+                            let features = guest_offloads_to_features(offloads) & acked_features;
+                            match tap.set_offload(virtio_features_to_tap_offload(features)) {
+                                Ok(()) => NetConfigResult::Ok,
+                                Err(e) => {
+                                    error!("net: failed to set tap offloads: {}", e);
+                                    NetConfigResult::Err(e.sys_error())
+                                }
+                            }
+                        }
+                    };
+                    if let Err(e) = control_tube.send(&result) {
+                        error!("net: failed to send control result: {}", e);
+                    }
+                }
+                ControlToken::Kill => return Ok(()),
+            }
+        }
+    }
 }
 
 impl<T> Drop for Net<T>
@@ -598,9 +721,17 @@ where
             keep_rds.push(tap.as_raw_descriptor());
         }
 
+        if let Some(control_tube) = &self.control_tube {
+            keep_rds.push(control_tube.as_raw_descriptor());
+        }
+
         keep_rds
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Net
     }
@@ -679,6 +810,7 @@ where
             ));
         }
 
+        self.start_control_worker();
         for i in 0..vq_pairs {
             let tap = self.taps.remove(0);
             let acked_features = self.acked_features;
@@ -732,6 +864,7 @@ where
         if self.worker_threads.is_empty() {
             return Ok(None);
         }
+        self.stop_control_worker();
         let mut queues = BTreeMap::new();
         let mut queue_index = 0;
         let mut ctrl_queue = None;
@@ -789,6 +922,7 @@ where
     }
 
     fn reset(&mut self) -> anyhow::Result<()> {
+        self.stop_control_worker();
         for worker_thread in self.worker_threads.drain(..) {
             let worker = worker_thread.stop();
             self.taps.push(worker.tap);
@@ -927,6 +1061,7 @@ mod tests {
                 },
                 packed_queue: false,
                 pci_address: Some(PciAddress {
+                    domain: 0,
                     bus: 0,
                     dev: 1,
                     func: 1,
@@ -1061,6 +1196,7 @@ mod tests {
                 },
                 packed_queue: false,
                 pci_address: Some(PciAddress {
+                    domain: 0,
                     bus: 0,
                     dev: 1,
                     func: 1,
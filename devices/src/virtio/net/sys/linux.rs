@@ -10,6 +10,8 @@ use base::warn;
 use base::EventType;
 use base::ReadNotifier;
 use base::WaitContext;
+use metrics::log_metric;
+use metrics::MetricEventType;
 use net_util::TapT;
 use virtio_sys::virtio_net;
 use virtio_sys::virtio_net::virtio_net_hdr_v1;
@@ -83,6 +85,7 @@ pub fn virtio_features_to_tap_offload(features: u64) -> u32 {
 pub fn process_rx<T: TapT>(rx_queue: &mut Queue, mut tap: &mut T) -> result::Result<(), NetError> {
     let mut needs_interrupt = false;
     let mut exhausted_queue = false;
+    let mut packets = 0;
 
     // Read as many frames as possible.
     loop {
@@ -119,11 +122,13 @@ pub fn process_rx<T: TapT>(rx_queue: &mut Queue, mut tap: &mut T) -> result::Res
             let desc_chain = desc_chain.pop();
             rx_queue.add_used(desc_chain, bytes_written);
             needs_interrupt = true;
+            packets += 1;
         }
     }
 
     if needs_interrupt {
         rx_queue.trigger_interrupt();
+        log_metric(MetricEventType::NetworkRxPackets, packets);
     }
 
     if exhausted_queue {
@@ -134,28 +139,41 @@ pub fn process_rx<T: TapT>(rx_queue: &mut Queue, mut tap: &mut T) -> result::Res
 }
 
 pub fn process_tx<T: TapT>(tx_queue: &mut Queue, mut tap: &mut T) {
-    while let Some(mut desc_chain) = tx_queue.pop() {
-        let reader = &mut desc_chain.reader;
-        let expected_count = reader.available_bytes();
-        match reader.read_to(&mut tap, expected_count) {
-            Ok(count) => {
-                // Tap writes must be done in one call. If the entire frame was not
-                // written, it's an error.
-                if count != expected_count {
-                    error!(
-                        "net: tx: wrote only {} bytes of {} byte frame",
-                        count, expected_count
-                    );
+    let mut packets = 0;
+    // Packets queued while the queue is drained don't need a notification of their own.
+    tx_queue.disable_notification();
+    loop {
+        while let Some(mut desc_chain) = tx_queue.pop() {
+            let reader = &mut desc_chain.reader;
+            let expected_count = reader.available_bytes();
+            match reader.read_to(&mut tap, expected_count) {
+                Ok(count) => {
+                    // Tap writes must be done in one call. If the entire frame was not
+                    // written, it's an error.
+                    if count != expected_count {
+                        error!(
+                            "net: tx: wrote only {} bytes of {} byte frame",
+                            count, expected_count
+                        );
+                    }
+                    cros_tracing::trace_simple_print!("{count} bytes write to tap");
+                    packets += 1;
                 }
-                cros_tracing::trace_simple_print!("{count} bytes write to tap");
+                Err(e) => error!("net: tx: failed to write frame to tap: {}", e),
             }
-            Err(e) => error!("net: tx: failed to write frame to tap: {}", e),
-        }
 
-        tx_queue.add_used(desc_chain, 0);
+            tx_queue.add_used(desc_chain, 0);
+        }
+        if !tx_queue.enable_notification() {
+            break;
+        }
+        tx_queue.disable_notification();
     }
 
     tx_queue.trigger_interrupt();
+    if packets > 0 {
+        log_metric(MetricEventType::NetworkTxPackets, packets);
+    }
 }
 
 impl<T> Worker<T>
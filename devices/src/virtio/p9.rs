@@ -161,6 +161,10 @@ impl VirtioDevice for P9 {
             .unwrap_or_default()
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::P9
     }
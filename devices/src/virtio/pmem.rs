@@ -441,6 +441,10 @@ impl VirtioDevice for Pmem {
         keep_rds
     }
 
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Pmem
     }
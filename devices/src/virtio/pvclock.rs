@@ -309,6 +309,9 @@ struct PvClockState {
     total_suspend_ns: Arc<AtomicU64>,
     features: u64,
     acked_features: u64,
+    /// The moment the snapshot was taken. Only set in snapshots.
+    #[serde(default)]
+    snapshot_time: Option<PvclockInstant>,
 }
 
 /// An enum to keep dynamic state of pvclock workers in a type safe manner.
@@ -328,6 +331,9 @@ enum PvClockWorkerState {
 pub struct PvClock {
     state: PvClockState,
     worker_state: PvClockWorkerState,
+    /// Set when a suspend was pending in the restored snapshot. The vCPUs run right after a
+    /// restore, so the suspend is completed as soon as the device wakes.
+    resume_on_wake: bool,
 }
 
 impl PvClock {
@@ -341,10 +347,12 @@ impl PvClock {
                 | 1 << VIRTIO_PVCLOCK_F_INJECT_SLEEP
                 | 1 << VIRTIO_PVCLOCK_F_CLOCKSOURCE_RATING,
             acked_features: 0,
+            snapshot_time: None,
         };
         PvClock {
             state,
             worker_state: PvClockWorkerState::Idle(suspend_tube),
+            resume_on_wake: false,
         }
     }
 
@@ -453,6 +461,26 @@ struct PvclockInstant {
     tsc_value: u64,
 }
 
+impl PvclockInstant {
+    fn now() -> Self {
+        PvclockInstant {
+            time: Utc::now(),
+            tsc_value: read_clock_counter(),
+        }
+    }
+
+    /// Moves this instant, which precedes `snapshot_time`, forward by the time passed since the
+    /// snapshot was taken. The guest counter is frozen while the VM is in a snapshot and the
+    /// hypervisor clock accounts for the time spent there, so it must not count as suspended.
+    fn skip_time_in_snapshot(&mut self, snapshot_time: &PvclockInstant) {
+        let now = PvclockInstant::now();
+        self.time = now.time - snapshot_time.time.signed_duration_since(self.time);
+        self.tsc_value = now
+            .tsc_value
+            .wrapping_sub(snapshot_time.tsc_value.wrapping_sub(self.tsc_value));
+    }
+}
+
 /// The unique data retained by [PvClockWorker] which can be used to re-create
 /// an identical worker.
 #[derive(Serialize, Deserialize, Clone)]
@@ -550,10 +578,7 @@ impl PvClockWorker {
             warn!("Suspend time already set, ignoring new suspend time");
             return;
         }
-        self.suspend_time = Some(PvclockInstant {
-            time: Utc::now(),
-            tsc_value: read_clock_counter(),
-        });
+        self.suspend_time = Some(PvclockInstant::now());
     }
 
     pub fn resume(&mut self) -> Result<u64> {
@@ -988,12 +1013,18 @@ impl VirtioDevice for PvClock {
                 .paused_main_worker
                 .take()
                 .ok_or(anyhow!("a sleeping pvclock must have a paused worker"))?;
-            let worker = PvClockWorker::from_snapshot(
+            let mut worker = PvClockWorker::from_snapshot(
                 self.state.tsc_frequency,
                 self.state.total_suspend_ns.clone(),
                 worker_snap,
                 mem,
             );
+            if std::mem::take(&mut self.resume_on_wake) {
+                match worker.resume() {
+                    Ok(_) => interrupt.signal_config_changed(),
+                    Err(e) => error!("Failed to resume pvclock after restore: {:#}", e),
+                }
+            }
             // Use unchecked as no worker is running at this point
             self.start_main_worker(interrupt, worker, queues)?;
         } else {
@@ -1005,7 +1036,10 @@ impl VirtioDevice for PvClock {
     }
 
     fn virtio_snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
-        AnySnapshot::to_any(&self.state).context("failed to serialize PvClockState")
+        self.state.snapshot_time = Some(PvclockInstant::now());
+        let snapshot = AnySnapshot::to_any(&self.state).context("failed to serialize PvClockState");
+        self.state.snapshot_time = None;
+        snapshot
     }
 
     fn virtio_restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
@@ -1022,6 +1056,17 @@ impl VirtioDevice for PvClock {
         // this is a reasonable assumption. We don't verify the frequency
         // because TSC calibration noisy.
         self.state = state;
+        if let Some(snapshot_time) = self.state.snapshot_time.take() {
+            if let Some(suspend_time) = self
+                .state
+                .paused_main_worker
+                .as_mut()
+                .and_then(|worker| worker.suspend_time.as_mut())
+            {
+                suspend_time.skip_time_in_snapshot(&snapshot_time);
+                self.resume_on_wake = true;
+            }
+        }
         Ok(())
     }
 
@@ -1149,6 +1194,28 @@ mod tests {
         assert_wake_successful(&mut pvclock_device, &mem);
     }
 
+    #[test]
+    fn test_skip_time_in_snapshot() {
+        let now = PvclockInstant::now();
+        let snapshot_time = PvclockInstant {
+            time: now.time - chrono::Duration::hours(1),
+            tsc_value: now.tsc_value.wrapping_sub(3_600_000_000_000),
+        };
+        let mut suspend_time = PvclockInstant {
+            time: snapshot_time.time - chrono::Duration::seconds(10),
+            tsc_value: snapshot_time.tsc_value.wrapping_sub(10_000),
+        };
+        suspend_time.skip_time_in_snapshot(&snapshot_time);
+
+        // Only the time suspended before the snapshot remains.
+        let suspended = PvClockWorker::get_suspended_duration(&suspend_time);
+        assert!(suspended >= Duration::from_secs(10));
+        assert!(suspended < Duration::from_secs(11));
+        let suspended_ticks = read_clock_counter().wrapping_sub(suspend_time.tsc_value);
+        assert!(suspended_ticks >= 10_000);
+        assert!(suspended_ticks < 3_600_000_000_000);
+    }
+
     /// A simplified clone of `pvclock_scale_delta` from Linux kernel to emulate
     /// what the kernel does when converting TSC to ktime.
     fn pvclock_scale_tsc(mult: u32, shift: i8, tsc: u64) -> u64 {
@@ -9,6 +9,7 @@
 use std::ops::Deref;
 use std::ops::DerefMut;
 
+mod inflight;
 pub mod packed_descriptor_chain;
 mod packed_queue;
 pub mod split_descriptor_chain;
@@ -26,6 +27,7 @@ use cros_async::EventAsync;
 use futures::channel::oneshot;
 use futures::select_biased;
 use futures::FutureExt;
+pub use inflight::InflightQueue;
 use packed_queue::PackedQueue;
 use serde::Deserialize;
 use serde::Serialize;
@@ -83,6 +85,17 @@ pub struct QueueConfig {
 
     /// Initial used ring index when the queue is activated.
     next_used: Wrapping<u16>,
+
+    /// Initial wrap counter of `next_avail` when a packed queue is activated.
+    next_avail_wrap_counter: bool,
+
+    /// Initial wrap counter of `next_used` when a packed queue is activated.
+    next_used_wrap_counter: bool,
+}
+
+fn queue_config_wrap_counter_default() -> bool {
+    // The driver and device ring wrap counters of a packed queue start at 1.
+    true
 }
 
 #[derive(Serialize, Deserialize)]
@@ -99,6 +112,10 @@ struct QueueConfigSnapshot {
     used_ring: GuestAddress,
     next_avail: Wrapping<u16>,
     next_used: Wrapping<u16>,
+    #[serde(default = "queue_config_wrap_counter_default")]
+    next_avail_wrap_counter: bool,
+    #[serde(default = "queue_config_wrap_counter_default")]
+    next_used_wrap_counter: bool,
 }
 
 impl QueueConfig {
@@ -119,6 +136,8 @@ impl QueueConfig {
             acked_features: 0,
             next_used: Wrapping(0),
             next_avail: Wrapping(0),
+            next_avail_wrap_counter: queue_config_wrap_counter_default(),
+            next_used_wrap_counter: queue_config_wrap_counter_default(),
         }
     }
 
@@ -240,6 +259,31 @@ impl QueueConfig {
         self.next_used = val;
     }
 
+    /// Getter for the wrap counter of the next_avail index of a packed queue
+    pub fn next_avail_wrap_counter(&self) -> bool {
+        self.next_avail_wrap_counter
+    }
+
+    /// Getter for the wrap counter of the next_used index of a packed queue
+    pub fn next_used_wrap_counter(&self) -> bool {
+        self.next_used_wrap_counter
+    }
+
+    /// Sets the initial indices of a packed queue from a vhost-user vring base, which holds the
+    /// next_avail index in bits 0-14 and its wrap counter in bit 15, and the next_used index and
+    /// its wrap counter in bits 16-31 the same way.
+    pub fn set_packed_vring_base(&mut self, base: u32) {
+        if self.ready {
+            warn!("ignoring write to packed vring base on ready queue");
+            return;
+        }
+
+        self.next_avail = Wrapping(base as u16 & 0x7fff);
+        self.next_avail_wrap_counter = base & (1 << 15) != 0;
+        self.next_used = Wrapping((base >> 16) as u16 & 0x7fff);
+        self.next_used_wrap_counter = base & (1 << 31) != 0;
+    }
+
     /// Returns the features that have been acknowledged by the driver.
     pub fn acked_features(&self) -> u64 {
         self.acked_features
@@ -301,6 +345,8 @@ impl QueueConfig {
         self.used_ring = GuestAddress(0);
         self.next_avail = Wrapping(0);
         self.next_used = Wrapping(0);
+        self.next_avail_wrap_counter = queue_config_wrap_counter_default();
+        self.next_used_wrap_counter = queue_config_wrap_counter_default();
         self.acked_features = 0;
     }
 
@@ -319,6 +365,8 @@ impl QueueConfig {
             used_ring: self.used_ring,
             next_avail: self.next_avail,
             next_used: self.next_used,
+            next_avail_wrap_counter: self.next_avail_wrap_counter,
+            next_used_wrap_counter: self.next_used_wrap_counter,
         })
         .context("error serializing")
     }
@@ -339,6 +387,8 @@ impl QueueConfig {
         self.used_ring = snap.used_ring;
         self.next_avail = snap.next_avail;
         self.next_used = snap.next_used;
+        self.next_avail_wrap_counter = snap.next_avail_wrap_counter;
+        self.next_used_wrap_counter = snap.next_used_wrap_counter;
         Ok(())
     }
 }
@@ -421,6 +471,7 @@ impl Queue {
 
     /// If a new DescriptorChain is available, returns one and removes it from the queue.
     pub fn pop(&mut self) -> Option<DescriptorChain> {
+        let _trace = cros_tracing::trace_event!(virtqueue, "pop");
         self.peek().map(PeekedDescriptorChain::pop)
     }
 
@@ -443,6 +494,7 @@ impl Queue {
     /// return true: interrupt is injected into guest for this queue
     ///        false: interrupt isn't injected
     pub fn trigger_interrupt(&mut self) -> bool {
+        let _trace = cros_tracing::trace_event!(virtqueue, "trigger_interrupt");
         match self {
             Queue::SplitVirtQueue(sq) => sq.trigger_interrupt(),
             Queue::PackedVirtQueue(pq) => pq.trigger_interrupt(),
@@ -476,6 +528,18 @@ impl Queue {
         }
     }
 
+    /// Tracks in-flight descriptor chains in memory shared with a vhost-user frontend, so that
+    /// requests a previous backend left unfinished are handed out again. Only split queues are
+    /// supported.
+    pub fn set_inflight(&mut self, inflight: InflightQueue) -> Result<()> {
+        match self {
+            Queue::SplitVirtQueue(q) => q.set_inflight(inflight),
+            Queue::PackedVirtQueue(_) => {
+                bail!("inflight tracking is not supported for packed queues")
+            }
+        }
+    }
+
     /// Getter for the next index of the available ring that device will process.
     ///
     /// Not to be confused with the available ring's index field, which is the next index for the
@@ -487,6 +551,18 @@ impl Queue {
         }
     }
 
+    /// Returns the state to report in the reply to VHOST_USER_GET_VRING_BASE.
+    ///
+    /// For split queues this is the next available index to process. For packed queues it also
+    /// holds the next used index and the wrap counters, as set by
+    /// [`QueueConfig::set_packed_vring_base`].
+    pub fn vhost_user_vring_base(&self) -> u32 {
+        match self {
+            Queue::SplitVirtQueue(q) => q.next_avail_to_process().into(),
+            Queue::PackedVirtQueue(q) => q.vhost_user_vring_base(),
+        }
+    }
+
     define_queue_method!(
         /// Getter for vector field
         vector,
@@ -544,6 +620,25 @@ impl Queue {
         snapshot,
         Result<AnySnapshot>,
     );
+
+    define_queue_method!(
+        /// Asks the driver not to notify the device of new available descriptor chains, e.g.
+        /// while the device is already processing the queue.
+        disable_notification,
+        (),
+        mut,
+    );
+
+    define_queue_method!(
+        /// Asks the driver to notify the device of new available descriptor chains again.
+        ///
+        /// Returns whether descriptor chains were made available while notifications were
+        /// disabled, in which case the device must process them without waiting for a
+        /// notification.
+        enable_notification,
+        bool,
+        mut,
+    );
 }
 
 /// A `DescriptorChain` that has been peeked from a `Queue` but not popped yet.
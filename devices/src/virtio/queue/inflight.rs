@@ -0,0 +1,201 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Tracking of in-flight descriptor chains in memory shared with a vhost-user frontend
+//! (`VHOST_USER_PROTOCOL_F_INFLIGHT_SHMFD`).
+//!
+//! The frontend keeps the memory alive across backend restarts, so a restarted backend can find
+//! out which requests its predecessor had popped from a split queue but never completed, and
+//! resubmit them. The layout of each queue's slice follows libvhost-user:
+//!
+//! ```text
+//! features: u64, version: u16, desc_num: u16, last_batch_head: u16, used_idx: u16,
+//! desc: [{ inflight: u8, padding: [u8; 5], next: u16, counter: u64 }; desc_num]
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::atomic::fence;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use base::MemoryMapping;
+
+const INFLIGHT_ALIGNMENT: usize = 64;
+const INFLIGHT_VERSION: u16 = 1;
+
+const VERSION_OFFSET: usize = 8;
+const DESC_NUM_OFFSET: usize = 10;
+const LAST_BATCH_HEAD_OFFSET: usize = 12;
+const USED_IDX_OFFSET: usize = 14;
+const HEADER_SIZE: usize = 16;
+
+const DESC_INFLIGHT_OFFSET: usize = 0;
+const DESC_COUNTER_OFFSET: usize = 8;
+const DESC_STATE_SIZE: usize = 16;
+
+/// One split queue's slice of a shared inflight region.
+pub struct InflightQueue {
+    mmap: Arc<MemoryMapping>,
+    offset: usize,
+    desc_num: u16,
+    next_counter: u64,
+    resubmit: VecDeque<u16>,
+}
+
+impl std::fmt::Debug for InflightQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InflightQueue")
+            .field("offset", &self.offset)
+            .field("desc_num", &self.desc_num)
+            .field("resubmit", &self.resubmit)
+            .finish()
+    }
+}
+
+impl InflightQueue {
+    /// Size in bytes of the slice of the inflight region used by each queue of up to `queue_size`
+    /// entries.
+    pub fn region_size(queue_size: u16) -> usize {
+        let size = HEADER_SIZE + DESC_STATE_SIZE * queue_size as usize;
+        size.div_ceil(INFLIGHT_ALIGNMENT) * INFLIGHT_ALIGNMENT
+    }
+
+    /// Uses the slice for queue `index` of `mmap`, which holds one slice per queue of up to
+    /// `queue_size` entries.
+    pub fn new(mmap: Arc<MemoryMapping>, index: usize, queue_size: u16) -> Result<Self> {
+        let region_size = Self::region_size(queue_size);
+        let offset = index * region_size;
+        if offset + region_size > mmap.size() {
+            bail!(
+                "inflight region of {} bytes is too small for queue {}",
+                mmap.size(),
+                index
+            );
+        }
+        Ok(InflightQueue {
+            mmap,
+            offset,
+            desc_num: queue_size,
+            next_counter: 1,
+            resubmit: VecDeque::new(),
+        })
+    }
+
+    /// Reconciles the slice with the used index the guest currently sees, initializing it if it
+    /// is fresh. Returns the number of descriptor chains that were in flight; these are returned
+    /// by `next_resubmit` before any new ones are taken from the available ring.
+    pub(super) fn recover(&mut self, used_idx: u16) -> Result<u16> {
+        let version: u16 = self.read(VERSION_OFFSET)?;
+        if version == 0 {
+            self.write(self.desc_num, DESC_NUM_OFFSET)?;
+            self.write(used_idx, USED_IDX_OFFSET)?;
+            fence(Ordering::SeqCst);
+            self.write(INFLIGHT_VERSION, VERSION_OFFSET)?;
+            return Ok(0);
+        }
+        if version != INFLIGHT_VERSION {
+            bail!("unsupported inflight region version {version}");
+        }
+        let desc_num: u16 = self.read(DESC_NUM_OFFSET)?;
+        if desc_num != self.desc_num {
+            bail!(
+                "inflight region tracks {} descriptors, expected {}",
+                desc_num,
+                self.desc_num
+            );
+        }
+
+        // The previous backend may have died after publishing the used index but before clearing
+        // the in-flight flag of the last chain it completed.
+        let recorded_used_idx: u16 = self.read(USED_IDX_OFFSET)?;
+        if recorded_used_idx != used_idx {
+            let last_batch_head: u16 = self.read(LAST_BATCH_HEAD_OFFSET)?;
+            self.set_desc_inflight(last_batch_head, false)?;
+            fence(Ordering::SeqCst);
+            self.write(used_idx, USED_IDX_OFFSET)?;
+        }
+
+        let mut inflight = Vec::new();
+        for head in 0..self.desc_num {
+            let desc = self.desc_offset(head)?;
+            let flag: u8 = self.read(desc + DESC_INFLIGHT_OFFSET)?;
+            if flag != 0 {
+                let counter: u64 = self.read(desc + DESC_COUNTER_OFFSET)?;
+                inflight.push((counter, head));
+            }
+        }
+        // Resubmit in the order the chains were originally popped.
+        inflight.sort_unstable();
+        self.next_counter = inflight.last().map_or(1, |&(counter, _)| counter + 1);
+        self.resubmit = inflight.into_iter().map(|(_, head)| head).collect();
+        Ok(self.resubmit.len() as u16)
+    }
+
+    /// Returns the head of the next chain to resubmit, if any.
+    pub(super) fn next_resubmit(&self) -> Option<u16> {
+        self.resubmit.front().copied()
+    }
+
+    /// Records that the chain with head `head` was popped. Returns whether it was a resubmitted
+    /// chain rather than a new one from the available ring.
+    pub(super) fn pop(&mut self, head: u16) -> Result<bool> {
+        if self.resubmit.front() == Some(&head) {
+            self.resubmit.pop_front();
+            return Ok(true);
+        }
+        self.write(
+            self.next_counter,
+            self.desc_offset(head)? + DESC_COUNTER_OFFSET,
+        )?;
+        self.next_counter += 1;
+        self.set_desc_inflight(head, true)?;
+        Ok(false)
+    }
+
+    /// Must be called before the chain with head `head` is written to the used ring.
+    pub(super) fn pre_put(&mut self, head: u16) -> Result<()> {
+        self.write(head, LAST_BATCH_HEAD_OFFSET)
+    }
+
+    /// Must be called after the chain with head `head` was written to the used ring and the used
+    /// index was updated to `used_idx`.
+    pub(super) fn post_put(&mut self, head: u16, used_idx: u16) -> Result<()> {
+        self.set_desc_inflight(head, false)?;
+        fence(Ordering::SeqCst);
+        self.write(used_idx, USED_IDX_OFFSET)
+    }
+
+    fn set_desc_inflight(&self, head: u16, inflight: bool) -> Result<()> {
+        self.write(
+            inflight as u8,
+            self.desc_offset(head)? + DESC_INFLIGHT_OFFSET,
+        )
+    }
+
+    fn desc_offset(&self, head: u16) -> Result<usize> {
+        if head >= self.desc_num {
+            bail!("descriptor {head} is out of range of the inflight region");
+        }
+        Ok(HEADER_SIZE + head as usize * DESC_STATE_SIZE)
+    }
+
+    fn read<T: zerocopy::FromBytes>(&self, offset: usize) -> Result<T> {
+        self.mmap
+            .read_obj_volatile(self.offset + offset)
+            .context("failed to read inflight region")
+    }
+
+    fn write<T: zerocopy::IntoBytes + zerocopy::Immutable>(
+        &self,
+        val: T,
+        offset: usize,
+    ) -> Result<()> {
+        self.mmap
+            .write_obj_volatile(val, self.offset + offset)
+            .context("failed to write inflight region")
+    }
+}
@@ -9,6 +9,7 @@ use std::sync::atomic::fence;
 use std::sync::atomic::Ordering;
 
 use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use base::error;
 use base::warn;
@@ -29,6 +30,8 @@ use crate::virtio::queue::packed_descriptor_chain::PackedDescEvent;
 use crate::virtio::queue::packed_descriptor_chain::PackedDescriptorChain;
 use crate::virtio::queue::packed_descriptor_chain::PackedNotificationType;
 use crate::virtio::queue::packed_descriptor_chain::RING_EVENT_FLAGS_DESC;
+use crate::virtio::queue::packed_descriptor_chain::RING_EVENT_FLAGS_DISABLE;
+use crate::virtio::queue::packed_descriptor_chain::RING_EVENT_FLAGS_ENABLE;
 use crate::virtio::Interrupt;
 use crate::virtio::QueueConfig;
 
@@ -52,14 +55,16 @@ impl PackedQueueIndex {
         Self::new(wrap_counter, index)
     }
 
+    /// Encodes the index with its wrap counter in bit 15, as in event suppression structures and
+    /// vhost-user vring bases.
+    pub fn to_u16(self) -> u16 {
+        self.index.0 | (self.wrap_counter as u16) << 15
+    }
+
     pub fn to_desc(self) -> PackedDescEvent {
         let flag = RING_EVENT_FLAGS_DESC;
-        let mut desc = self.index.0;
-        if self.wrap_counter {
-            desc |= 1 << 15;
-        }
         PackedDescEvent {
-            desc: desc.into(),
+            desc: self.to_u16().into(),
             flag: flag.into(),
         }
     }
@@ -102,6 +107,9 @@ pub struct PackedQueue {
     // Device feature bits accepted by the driver
     features: u64,
 
+    // Whether the driver was asked not to notify the device of new available descriptors.
+    notification_disabled: bool,
+
     // Guest physical address of the descriptor table
     desc_table: GuestAddress,
 
@@ -158,6 +166,19 @@ impl PackedQueue {
             }
         }
 
+        let avail_index =
+            PackedQueueIndex::new(config.next_avail_wrap_counter(), config.next_avail().0);
+        let use_index =
+            PackedQueueIndex::new(config.next_used_wrap_counter(), config.next_used().0);
+        if avail_index.index.0 >= size || use_index.index.0 >= size {
+            bail!(
+                "packed virtqueue indices out of bounds: avail:{} used:{} size:{}",
+                avail_index.index,
+                use_index.index,
+                size,
+            );
+        }
+
         Ok(PackedQueue {
             mem: mem.clone(),
             event,
@@ -168,9 +189,10 @@ impl PackedQueue {
             driver_event_suppression: config.avail_ring(),
             device_event_suppression: config.used_ring(),
             features: config.acked_features(),
-            avail_index: PackedQueueIndex::default(),
-            use_index: PackedQueueIndex::default(),
-            signalled_used_index: PackedQueueIndex::default(),
+            notification_disabled: false,
+            avail_index,
+            use_index,
+            signalled_used_index: use_index,
         })
     }
 
@@ -183,6 +205,12 @@ impl PackedQueue {
         self.avail_index.index.0
     }
 
+    /// Returns the next available and used indices with their wrap counters, encoded as a
+    /// vhost-user vring base.
+    pub fn vhost_user_vring_base(&self) -> u32 {
+        u32::from(self.avail_index.to_u16()) | u32::from(self.use_index.to_u16()) << 16
+    }
+
     /// Return the actual size of the queue, as the driver may not set up a
     /// queue as big as the device allows.
     pub fn size(&self) -> u16 {
@@ -304,11 +332,46 @@ impl PackedQueue {
     pub(super) fn pop_peeked(&mut self, descriptor_chain: &DescriptorChain) {
         self.avail_index
             .add_index(descriptor_chain.count, self.size());
-        if self.features & ((1u64) << VIRTIO_RING_F_EVENT_IDX) != 0 {
+        if self.features & ((1u64) << VIRTIO_RING_F_EVENT_IDX) != 0 && !self.notification_disabled {
             self.set_avail_event(self.avail_index.to_desc());
         }
     }
 
+    /// Asks the driver not to notify the device of new available descriptors, e.g. while the
+    /// device is already processing the queue.
+    pub fn disable_notification(&mut self) {
+        self.notification_disabled = true;
+        self.set_avail_event(PackedDescEvent {
+            desc: 0.into(),
+            flag: RING_EVENT_FLAGS_DISABLE.into(),
+        });
+    }
+
+    /// Asks the driver to notify the device of new available descriptors again.
+    ///
+    /// Returns whether descriptors were made available while notifications were disabled, in
+    /// which case the driver may not notify the device about them.
+    pub fn enable_notification(&mut self) -> bool {
+        self.notification_disabled = false;
+        let event = if self.features & ((1u64) << VIRTIO_RING_F_EVENT_IDX) != 0 {
+            self.avail_index.to_desc()
+        } else {
+            PackedDescEvent {
+                desc: 0.into(),
+                flag: RING_EVENT_FLAGS_ENABLE.into(),
+            }
+        };
+        self.set_avail_event(event);
+
+        let desc_addr = self
+            .desc_table
+            .checked_add((self.avail_index.index.0 as u64) * 16)
+            .expect("peeked address will not overflow");
+        self.mem
+            .read_obj_from_addr_volatile::<PackedDesc>(desc_addr)
+            .is_ok_and(|desc| desc.is_available(self.avail_index.wrap_counter as u16))
+    }
+
     /// Write to first descriptor in descriptor chain to mark descriptor chain as used
     pub fn add_used(&mut self, desc_chain: DescriptorChain, len: u32) {
         let desc_index = desc_chain.index();
@@ -389,20 +452,19 @@ impl PackedQueue {
                 // Get desc_event_off and desc_event_wrap from driver event suppress area
                 let event_index: PackedQueueIndex = PackedQueueIndex::new_from_desc(desc);
 
-                let event_idx = event_index.index;
+                let mut event_idx = event_index.index;
                 let old_idx = old.index;
                 let new_idx = self.use_index.index;
 
-                // In qemu's implementation, there's an additional calculation,
-                // need to verify its correctness.
-                // if event_index.wrap_counter != self.use_index.wrap_counter {
-                //     event_idx -= self.size() as u16;
-                // }
+                // An event index from the previous lap of the ring is behind the used index by
+                // a full ring.
+                if event_index.wrap_counter != self.use_index.wrap_counter {
+                    event_idx -= Wrapping(self.size);
+                }
 
                 (new_idx - event_idx - Wrapping(1)) < (new_idx - old_idx)
             }
-        };
-        true
+        }
     }
 
     /// inject interrupt into guest on this queue
@@ -422,20 +484,213 @@ impl PackedQueue {
         self.features |= features;
     }
 
-    /// TODO: b/290307056 - Implement snapshot for packed virtqueue,
-    /// add tests to validate.
+    /// Take snapshot of queue's current status
     pub fn snapshot(&self) -> Result<AnySnapshot> {
-        bail!("Snapshot for packed virtqueue not implemented.");
+        AnySnapshot::to_any(PackedQueueSnapshot {
+            size: self.size,
+            vector: self.vector,
+            avail_index: self.avail_index,
+            use_index: self.use_index,
+            signalled_used_index: self.signalled_used_index,
+            features: self.features,
+            desc_table: self.desc_table,
+            device_event_suppression: self.device_event_suppression,
+            driver_event_suppression: self.driver_event_suppression,
+        })
+        .context("error serializing")
     }
 
-    /// TODO: b/290307056 - Implement restore for packed virtqueue,
-    /// add tests to validate.
+    /// Restore queue's status from snapshot
     pub fn restore(
-        _queue_value: AnySnapshot,
-        _mem: &GuestMemory,
-        _event: Event,
-        _interrupt: Interrupt,
+        queue_value: AnySnapshot,
+        mem: &GuestMemory,
+        event: Event,
+        interrupt: Interrupt,
     ) -> Result<PackedQueue> {
-        bail!("Restore for packed virtqueue not implemented.");
+        let s: PackedQueueSnapshot =
+            AnySnapshot::from_any(queue_value).context("error deserializing")?;
+        Ok(PackedQueue {
+            mem: mem.clone(),
+            event,
+            interrupt,
+            size: s.size,
+            vector: s.vector,
+            avail_index: s.avail_index,
+            use_index: s.use_index,
+            signalled_used_index: s.signalled_used_index,
+            features: s.features,
+            notification_disabled: false,
+            desc_table: s.desc_table,
+            device_event_suppression: s.device_event_suppression,
+            driver_event_suppression: s.driver_event_suppression,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_model::Le16;
+    use data_model::Le32;
+    use data_model::Le64;
+    use virtio_sys::virtio_config::VIRTIO_F_RING_PACKED;
+
+    use super::*;
+    use crate::virtio::Queue;
+
+    const GUEST_MEMORY_SIZE: u64 = 0x10000;
+    const DESC_OFFSET: u64 = 0;
+    const DRIVER_EVENT_OFFSET: u64 = 0x1000;
+    const DEVICE_EVENT_OFFSET: u64 = 0x2000;
+    const BUFFER_OFFSET: u64 = 0x8000;
+    const QUEUE_SIZE: u16 = 16;
+
+    fn setup_vq(mem: &GuestMemory, features: u64, vring_base: Option<u32>) -> Queue {
+        let features = features | 1 << VIRTIO_F_RING_PACKED;
+        let mut config = QueueConfig::new(QUEUE_SIZE, features);
+        config.set_desc_table(GuestAddress(DESC_OFFSET));
+        config.set_avail_ring(GuestAddress(DRIVER_EVENT_OFFSET));
+        config.set_used_ring(GuestAddress(DEVICE_EVENT_OFFSET));
+        if let Some(base) = vring_base {
+            config.set_packed_vring_base(base);
+        }
+        config.ack_features(features);
+        config.set_ready(true);
+        config
+            .activate(mem, Event::new().unwrap(), Interrupt::new_for_test())
+            .expect("QueueConfig::activate failed")
+    }
+
+    fn add_avail_desc(mem: &GuestMemory, index: u16, id: u16) {
+        let desc = PackedDesc {
+            addr: Le64::from(BUFFER_OFFSET),
+            len: Le32::from(0x100),
+            id: Le16::from(id),
+            flags: Le16::from(VIRTQ_DESC_F_AVAIL),
+        };
+        mem.write_obj_at_addr(desc, GuestAddress(DESC_OFFSET + index as u64 * 16))
+            .unwrap();
+    }
+
+    fn set_driver_event(mem: &GuestMemory, desc: u16, flag: u16) {
+        let event = PackedDescEvent {
+            desc: desc.into(),
+            flag: flag.into(),
+        };
+        mem.write_obj_at_addr(event, GuestAddress(DRIVER_EVENT_OFFSET))
+            .unwrap();
+    }
+
+    #[test]
+    fn used_descriptor() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = setup_vq(&mem, 0, None);
+
+        add_avail_desc(&mem, 0, 7);
+        let chain = queue.pop().expect("no available descriptor");
+        assert!(queue.pop().is_none());
+        queue.add_used(chain, 0x20);
+
+        let desc: PackedDesc = mem.read_obj_from_addr(GuestAddress(DESC_OFFSET)).unwrap();
+        assert_eq!(desc.id(), 7);
+        assert_eq!(desc.len(), 0x20);
+        assert_eq!(
+            desc.flags(),
+            VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_USED | VIRTQ_DESC_F_WRITE
+        );
+    }
+
+    #[test]
+    fn interrupt_suppression() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = setup_vq(&mem, 1 << VIRTIO_RING_F_EVENT_IDX, None);
+
+        // Ask for an interrupt once the first descriptor is used.
+        set_driver_event(&mem, 1 << 15, RING_EVENT_FLAGS_DESC);
+        add_avail_desc(&mem, 0, 0);
+        add_avail_desc(&mem, 1, 1);
+
+        let chain = queue.pop().unwrap();
+        queue.add_used(chain, 0);
+        assert!(queue.trigger_interrupt());
+        // Nothing was used since the last interrupt.
+        assert!(!queue.trigger_interrupt());
+
+        // The driver didn't move its event index, so no interrupt for the second descriptor.
+        let chain = queue.pop().unwrap();
+        queue.add_used(chain, 0);
+        assert!(!queue.trigger_interrupt());
+
+        set_driver_event(&mem, 0, RING_EVENT_FLAGS_DISABLE);
+        assert!(!queue.trigger_interrupt());
+        set_driver_event(&mem, 0, RING_EVENT_FLAGS_ENABLE);
+        assert!(queue.trigger_interrupt());
+    }
+
+    #[test]
+    fn notification_suppression() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = setup_vq(&mem, 1 << VIRTIO_RING_F_EVENT_IDX, None);
+        let device_event = || {
+            mem.read_obj_from_addr::<PackedDescEvent>(GuestAddress(DEVICE_EVENT_OFFSET))
+                .unwrap()
+        };
+
+        queue.disable_notification();
+        assert_eq!(u16::from(device_event().flag), RING_EVENT_FLAGS_DISABLE);
+        add_avail_desc(&mem, 0, 0);
+        let chain = queue.pop().unwrap();
+        queue.add_used(chain, 0);
+        assert_eq!(u16::from(device_event().flag), RING_EVENT_FLAGS_DISABLE);
+
+        // A descriptor made available before notifications are enabled again must not be missed.
+        add_avail_desc(&mem, 1, 1);
+        assert!(queue.enable_notification());
+        let event = device_event();
+        assert_eq!(u16::from(event.flag), RING_EVENT_FLAGS_DESC);
+        assert_eq!(u16::from(event.desc), 1 | 1 << 15);
+        assert!(queue.pop().is_some());
+        assert!(!queue.enable_notification());
+    }
+
+    #[test]
+    fn vhost_user_vring_base() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let queue = setup_vq(&mem, 0, None);
+        assert_eq!(queue.vhost_user_vring_base(), 0x8000_8000);
+
+        // The device is on its second lap of the available descriptors, so those made available
+        // on the first lap are not available anymore.
+        let mut queue = setup_vq(&mem, 0, Some(0x8003_0003));
+        assert_eq!(queue.vhost_user_vring_base(), 0x8003_0003);
+        add_avail_desc(&mem, 3, 3);
+        assert!(queue.pop().is_none());
+
+        let mut queue = setup_vq(&mem, 0, Some(0x8003_8003));
+        let chain = queue.pop().unwrap();
+        queue.add_used(chain, 0);
+        assert_eq!(queue.vhost_user_vring_base(), 0x8004_8004);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = setup_vq(&mem, 0, None);
+        add_avail_desc(&mem, 0, 0);
+        let chain = queue.pop().unwrap();
+        queue.add_used(chain, 0);
+
+        let snapshot = queue.snapshot().unwrap();
+        let restored = PackedQueue::restore(
+            snapshot,
+            &mem,
+            Event::new().unwrap(),
+            Interrupt::new_for_test(),
+        )
+        .unwrap();
+        assert_eq!(restored.size(), QUEUE_SIZE);
+        assert_eq!(
+            restored.vhost_user_vring_base(),
+            queue.vhost_user_vring_base()
+        );
     }
 }
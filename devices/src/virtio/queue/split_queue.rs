@@ -23,12 +23,12 @@ use zerocopy::Immutable;
 use zerocopy::IntoBytes;
 use zerocopy::KnownLayout;
 
+use super::inflight::InflightQueue;
 use crate::virtio::DescriptorChain;
 use crate::virtio::Interrupt;
 use crate::virtio::QueueConfig;
 use crate::virtio::SplitDescriptorChain;
 
-#[allow(dead_code)]
 const VIRTQ_USED_F_NO_NOTIFY: u16 = 0x1;
 #[allow(dead_code)]
 const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 0x1;
@@ -63,6 +63,13 @@ pub struct SplitQueue {
     // Device feature bits accepted by the driver
     features: u64,
     last_used: Wrapping<u16>,
+
+    // Whether the driver was asked not to notify the device of new available descriptor chains.
+    notification_disabled: bool,
+
+    // Shared record of in-flight descriptor chains, when running in a vhost-user backend that
+    // negotiated VHOST_USER_PROTOCOL_F_INFLIGHT_SHMFD.
+    inflight: Option<InflightQueue>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -139,6 +146,8 @@ impl SplitQueue {
             // snapshot system since it is much simpler to just use the zero
             // value and send a potentially spurious interrupt on restore).
             last_used: Wrapping(0),
+            notification_disabled: false,
+            inflight: None,
         })
     }
 
@@ -186,6 +195,23 @@ impl SplitQueue {
         self.next_avail.0
     }
 
+    /// Tracks the queue's in-flight descriptor chains in `inflight`. Chains that a previous
+    /// backend popped but never completed are returned again before any new ones.
+    pub fn set_inflight(&mut self, mut inflight: InflightQueue) -> Result<()> {
+        let used_index_addr = self.used_ring.unchecked_add(2);
+        let used_index: u16 = self
+            .mem
+            .read_obj_from_addr_volatile(used_index_addr)
+            .context("failed to read used index")?;
+        let resubmit = inflight.recover(used_index)?;
+
+        // Every chain popped from the available ring has either been used or is in flight.
+        self.next_used = Wrapping(used_index);
+        self.next_avail = Wrapping(used_index) + Wrapping(resubmit);
+        self.inflight = Some(inflight);
+        Ok(())
+    }
+
     /// Return the actual size of the queue, as the driver may not set up a
     /// queue as big as the device allows.
     pub fn size(&self) -> u16 {
@@ -276,6 +302,15 @@ impl SplitQueue {
             .unwrap();
     }
 
+    // Set the `flags` field in the used ring.
+    fn set_used_flags(&mut self, flags: u16) {
+        fence(Ordering::SeqCst);
+
+        self.mem
+            .write_obj_at_addr_volatile(flags, self.used_ring)
+            .unwrap();
+    }
+
     // Query the value of a single-bit flag in the available ring.
     //
     // Returns `true` if `flag` is currently set (by the driver) in the available ring flags.
@@ -325,6 +360,14 @@ impl SplitQueue {
     /// Get the first available descriptor chain without removing it from the queue.
     /// Call `pop_peeked` to remove the returned descriptor chain from the queue.
     pub fn peek(&mut self) -> Option<DescriptorChain> {
+        if let Some(head) = self
+            .inflight
+            .as_ref()
+            .and_then(InflightQueue::next_resubmit)
+        {
+            return self.descriptor_chain(head);
+        }
+
         let avail_index = self.get_avail_index();
         if self.next_avail == avail_index {
             return None;
@@ -341,6 +384,10 @@ impl SplitQueue {
         // This index is checked below in checked_new.
         let descriptor_index: u16 = self.mem.read_obj_from_addr_volatile(desc_idx_addr).unwrap();
 
+        self.descriptor_chain(descriptor_index)
+    }
+
+    fn descriptor_chain(&self, descriptor_index: u16) -> Option<DescriptorChain> {
         let chain =
             SplitDescriptorChain::new(&self.mem, self.desc_table, self.size, descriptor_index);
         DescriptorChain::new(chain, &self.mem, descriptor_index)
@@ -354,11 +401,46 @@ impl SplitQueue {
     /// Remove the first available descriptor chain from the queue.
     /// This function should only be called immediately following `peek` and must be passed a
     /// reference to the same `DescriptorChain` returned by the most recent `peek`.
-    pub(super) fn pop_peeked(&mut self, _descriptor_chain: &DescriptorChain) {
+    pub(super) fn pop_peeked(&mut self, descriptor_chain: &DescriptorChain) {
+        if let Some(inflight) = &mut self.inflight {
+            match inflight.pop(descriptor_chain.index()) {
+                // Resubmitted chains are already accounted for in `next_avail`.
+                Ok(true) => return,
+                Ok(false) => (),
+                Err(e) => error!("{:#}", e),
+            }
+        }
+
         self.next_avail += Wrapping(1);
+        if self.features & ((1u64) << VIRTIO_RING_F_EVENT_IDX) != 0 && !self.notification_disabled {
+            self.set_avail_event(self.next_avail);
+        }
+    }
+
+    /// Asks the driver not to notify the device of new available descriptor chains, e.g. while
+    /// the device is already processing the queue.
+    ///
+    /// With `VIRTIO_RING_F_EVENT_IDX`, `avail_event` is simply no longer moved forward, so the
+    /// driver notifies at most once more.
+    pub fn disable_notification(&mut self) {
+        self.notification_disabled = true;
+        if self.features & ((1u64) << VIRTIO_RING_F_EVENT_IDX) == 0 {
+            self.set_used_flags(VIRTQ_USED_F_NO_NOTIFY);
+        }
+    }
+
+    /// Asks the driver to notify the device of new available descriptor chains again.
+    ///
+    /// Returns whether descriptor chains were made available while notifications were disabled,
+    /// in which case the driver may not notify the device about them.
+    pub fn enable_notification(&mut self) -> bool {
+        self.notification_disabled = false;
         if self.features & ((1u64) << VIRTIO_RING_F_EVENT_IDX) != 0 {
             self.set_avail_event(self.next_avail);
+        } else {
+            self.set_used_flags(0);
         }
+        self.get_avail_index() != self.next_avail
     }
 
     /// Puts an available descriptor head into the used ring for use by the guest.
@@ -366,6 +448,12 @@ impl SplitQueue {
         let desc_index = desc_chain.index();
         debug_assert!(desc_index < self.size);
 
+        if let Some(inflight) = &mut self.inflight {
+            if let Err(e) = inflight.pre_put(desc_index) {
+                error!("{:#}", e);
+            }
+        }
+
         let used_ring = self.used_ring;
         let next_used = self.wrap_queue_index(self.next_used) as usize;
         let used_elem = used_ring.unchecked_add((4 + next_used * 8) as u64);
@@ -382,6 +470,12 @@ impl SplitQueue {
 
         self.next_used += Wrapping(1);
         self.set_used_index(self.next_used);
+
+        if let Some(inflight) = &mut self.inflight {
+            if let Err(e) = inflight.post_put(desc_index, self.next_used.0) {
+                error!("{:#}", e);
+            }
+        }
     }
 
     /// Returns if the queue should have an interrupt sent based on its state.
@@ -560,6 +654,8 @@ impl SplitQueue {
             next_used: s.next_used,
             features: s.features,
             last_used: s.last_used,
+            notification_disabled: false,
+            inflight: None,
         };
         Ok(queue)
     }
@@ -569,7 +665,9 @@ impl SplitQueue {
 mod tests {
     use std::convert::TryInto;
     use std::mem::offset_of;
+    use std::sync::Arc;
 
+    use base::MemoryMappingBuilder;
     use data_model::Le16;
     use data_model::Le32;
     use data_model::Le64;
@@ -837,4 +935,107 @@ mod tests {
         // should inject interrupt again.
         assert_eq!(queue.trigger_interrupt(), true);
     }
+
+    #[test]
+    fn notification_suppression_event_idx() {
+        let mut queue =
+            QueueConfig::new(QUEUE_SIZE.try_into().unwrap(), 1 << VIRTIO_RING_F_EVENT_IDX);
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = setup_vq(&mut queue, &mem);
+
+        let avail_idx_address = GuestAddress(AVAIL_OFFSET + offset_of!(Avail, idx) as u64);
+        let avail_event_address = GuestAddress(USED_OFFSET + offset_of!(Used, avail_event) as u64);
+        let avail_event = || mem.read_obj_from_addr::<u16>(avail_event_address).unwrap();
+
+        queue.disable_notification();
+        mem.write_obj_at_addr(Le16::from(2u16), avail_idx_address)
+            .unwrap();
+        assert!(queue.pop().is_some());
+        assert!(queue.pop().is_some());
+        assert!(queue.pop().is_none());
+        // The driver isn't asked to notify the device while the queue is processed.
+        assert_eq!(avail_event(), 0);
+
+        // A descriptor chain made available before notifications are enabled again must not be
+        // missed.
+        mem.write_obj_at_addr(Le16::from(3u16), avail_idx_address)
+            .unwrap();
+        assert!(queue.enable_notification());
+        assert_eq!(avail_event(), 2);
+        assert!(queue.pop().is_some());
+        assert_eq!(avail_event(), 3);
+        assert!(!queue.enable_notification());
+    }
+
+    #[test]
+    fn notification_suppression_no_event_idx() {
+        let mut queue = QueueConfig::new(QUEUE_SIZE.try_into().unwrap(), 0);
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = setup_vq(&mut queue, &mem);
+
+        let used_flags = || {
+            mem.read_obj_from_addr::<u16>(GuestAddress(USED_OFFSET))
+                .unwrap()
+        };
+
+        queue.disable_notification();
+        assert_eq!(used_flags(), VIRTQ_USED_F_NO_NOTIFY);
+        assert!(!queue.enable_notification());
+        assert_eq!(used_flags(), 0);
+    }
+
+    #[test]
+    fn inflight_resubmit_after_restart() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut config = QueueConfig::new(QUEUE_SIZE.try_into().unwrap(), 0);
+        let mut queue = setup_vq(&mut config, &mem);
+
+        // Make descriptors 0 and 1 available.
+        let desc = Desc {
+            addr: Le64::from(BUFFER_OFFSET),
+            len: Le32::from(BUFFER_LEN),
+            flags: Le16::from(0u16),
+            next: Le16::from(0u16),
+        };
+        mem.write_obj_at_addr(desc, GuestAddress(DESC_OFFSET + 16))
+            .unwrap();
+        let mut avail = Avail::default();
+        avail.ring[1] = Le16::from(1u16);
+        avail.idx = Le16::from(2u16);
+        mem.write_obj_at_addr(avail, GuestAddress(AVAIL_OFFSET))
+            .unwrap();
+
+        let region_size = InflightQueue::region_size(QUEUE_SIZE.try_into().unwrap());
+        let mmap = Arc::new(MemoryMappingBuilder::new(region_size).build().unwrap());
+        queue
+            .set_inflight(
+                InflightQueue::new(mmap.clone(), 0, QUEUE_SIZE.try_into().unwrap()).unwrap(),
+            )
+            .unwrap();
+
+        // Complete the second request before the first and "crash".
+        let first = queue.pop().unwrap();
+        let second = queue.pop().unwrap();
+        assert_eq!(first.index(), 0);
+        assert_eq!(second.index(), 1);
+        queue.add_used(second, BUFFER_LEN);
+        drop(queue);
+
+        let mut config = QueueConfig::new(QUEUE_SIZE.try_into().unwrap(), 0);
+        config.set_desc_table(GuestAddress(DESC_OFFSET));
+        config.set_avail_ring(GuestAddress(AVAIL_OFFSET));
+        config.set_used_ring(GuestAddress(USED_OFFSET));
+        config.set_ready(true);
+        let mut queue = config
+            .activate(&mem, Event::new().unwrap(), Interrupt::new_for_test())
+            .unwrap();
+        queue
+            .set_inflight(InflightQueue::new(mmap, 0, QUEUE_SIZE.try_into().unwrap()).unwrap())
+            .unwrap();
+
+        // Only the unfinished request is handed out again.
+        assert_eq!(queue.next_avail_to_process(), 2);
+        assert_eq!(queue.pop().unwrap().index(), 0);
+        assert!(queue.pop().is_none());
+    }
 }
@@ -14,6 +14,8 @@ use base::EventToken;
 use base::RawDescriptor;
 use base::WaitContext;
 use base::WorkerThread;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
 use rand::rngs::OsRng;
 use rand::RngCore;
 use snapshot::AnySnapshot;
@@ -116,6 +118,15 @@ impl VirtioDevice for Rng {
         Vec::new()
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        Some(Vec::new())
+    }
+
+    fn supports_child_process(&self) -> bool {
+        true
+    }
+
     fn device_type(&self) -> DeviceType {
         DeviceType::Rng
     }
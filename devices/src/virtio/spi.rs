@@ -0,0 +1,529 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Virtio SPI controller proxying transfers to host spidev devices.
+//!
+//! Each host spidev node drives a single chip select, so the device exposes one guest chip select
+//! per spidev node it is given, in order.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::io::Write;
+use std::os::raw::c_uint;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use base::error;
+use base::ioctl_iow_nr;
+use base::ioctl_with_ref;
+use base::warn;
+use base::AsRawDescriptor;
+use base::Event;
+use base::EventToken;
+use base::RawDescriptor;
+use base::WaitContext;
+use base::WorkerThread;
+use data_model::Le32;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_keyvalue::FromKeyValues;
+use snapshot::AnySnapshot;
+use vm_memory::GuestMemory;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+use super::copy_config;
+use super::DeviceType;
+use super::Interrupt;
+use super::Queue;
+use super::Reader;
+use super::VirtioDevice;
+use super::Writer;
+
+const QUEUE_SIZE: u16 = 256;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+
+const VIRTIO_SPI_TRANS_OK: u8 = 0;
+const VIRTIO_SPI_PARAM_ERR: u8 = 1;
+const VIRTIO_SPI_TRANS_ERR: u8 = 2;
+
+const VIRTIO_SPI_CPHA: u32 = 1 << 0;
+const VIRTIO_SPI_CPOL: u32 = 1 << 1;
+const VIRTIO_SPI_CS_HIGH: u32 = 1 << 2;
+const VIRTIO_SPI_MODE_LSB_FIRST: u32 = 1 << 3;
+const VIRTIO_SPI_MODE_LOOP: u32 = 1 << 4;
+
+// Both clock phases and polarities, active high chip select, LSB first and loopback. Whether the
+// host controller supports a mode is only known when it is set, so everything is advertised.
+const MODE_FUNC_SUPPORTED: u32 = 0x7f;
+
+// spidev only has microsecond delays, and no chip select setup or inactive delay.
+const MAX_WORD_DELAY_NS: u32 = u8::MAX as u32 * 1000;
+const MAX_CS_HOLD_NS: u32 = u16::MAX as u32 * 1000;
+
+// From linux/spi/spi.h.
+const SPI_CPHA: u32 = 0x01;
+const SPI_CPOL: u32 = 0x02;
+const SPI_CS_HIGH: u32 = 0x04;
+const SPI_LSB_FIRST: u32 = 0x08;
+const SPI_LOOP: u32 = 0x20;
+
+const SPI_IOC_MAGIC: c_uint = b'k' as c_uint;
+
+/// Parameters for a virtio-spi device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpiParameters {
+    /// Host spidev nodes, one per chip select.
+    pub devices: Vec<PathBuf>,
+    /// Maximum clock frequency reported to the guest, 0 meaning no limit.
+    #[serde(default)]
+    pub max_freq_hz: u32,
+}
+
+#[derive(Copy, Clone, Debug, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct virtio_spi_config {
+    cs_max_number: u8,
+    cs_change_supported: u8,
+    tx_nbits_supported: u8,
+    rx_nbits_supported: u8,
+    bits_per_word_mask: Le32,
+    mode_func_supported: Le32,
+    max_freq_hz: Le32,
+    max_word_delay_ns: Le32,
+    max_cs_setup_ns: Le32,
+    max_cs_hold_ns: Le32,
+    max_cs_inactive_ns: Le32,
+}
+
+#[derive(Copy, Clone, Debug, Default, FromBytes, Immutable, IntoBytes, KnownLayout)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct spi_transfer_head {
+    chip_select_id: u8,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    reserved: [u8; 3],
+    mode: Le32,
+    freq: Le32,
+    word_delay_ns: Le32,
+    cs_setup_ns: Le32,
+    cs_delay_hold_ns: Le32,
+    cs_change_delay_inactive_ns: Le32,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct spi_ioc_transfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+// SPI_IOC_MESSAGE(1), whose size is that of a single transfer.
+ioctl_iow_nr!(SPI_IOC_MESSAGE_1, SPI_IOC_MAGIC, 0, spi_ioc_transfer);
+ioctl_iow_nr!(SPI_IOC_WR_MODE32, SPI_IOC_MAGIC, 5, u32);
+
+/// Converts virtio SPI mode bits to spidev mode bits.
+fn spidev_mode(mode: u32) -> Option<u32> {
+    const BITS: &[(u32, u32)] = &[
+        (VIRTIO_SPI_CPHA, SPI_CPHA),
+        (VIRTIO_SPI_CPOL, SPI_CPOL),
+        (VIRTIO_SPI_CS_HIGH, SPI_CS_HIGH),
+        (VIRTIO_SPI_MODE_LSB_FIRST, SPI_LSB_FIRST),
+        (VIRTIO_SPI_MODE_LOOP, SPI_LOOP),
+    ];
+    let known = BITS.iter().fold(0, |acc, (bit, _)| acc | bit);
+    if mode & !known != 0 {
+        return None;
+    }
+    Some(
+        BITS.iter()
+            .filter(|(bit, _)| mode & bit != 0)
+            .fold(0, |acc, (_, spidev_bit)| acc | spidev_bit),
+    )
+}
+
+/// A host spidev node along with the mode it was last configured with.
+struct SpiDev {
+    file: File,
+    mode: Option<u32>,
+}
+
+impl SpiDev {
+    fn set_mode(&mut self, mode: u32) -> base::Result<()> {
+        if self.mode == Some(mode) {
+            return Ok(());
+        }
+        // SAFETY:
+        // Safe because the kernel only reads a u32 from `mode` and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.file, SPI_IOC_WR_MODE32, &mode) };
+        if ret < 0 {
+            self.mode = None;
+            return Err(base::Error::last());
+        }
+        self.mode = Some(mode);
+        Ok(())
+    }
+
+    fn transfer(&self, xfer: &spi_ioc_transfer) -> base::Result<()> {
+        // SAFETY:
+        // Safe because `xfer` points to buffers of at least `xfer.len` bytes that outlive the call,
+        // and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.file, SPI_IOC_MESSAGE_1, xfer) };
+        if ret < 0 {
+            return Err(base::Error::last());
+        }
+        Ok(())
+    }
+}
+
+struct Worker {
+    queue: Queue,
+    devices: Vec<SpiDev>,
+}
+
+impl Worker {
+    /// Performs the transfer described by a request and returns its result code.
+    fn process_request(&mut self, reader: &mut Reader, writer: &mut Writer) -> u8 {
+        let head: spi_transfer_head = match reader.read_obj() {
+            Ok(head) => head,
+            Err(e) => {
+                warn!("virtio-spi: failed to read transfer head: {}", e);
+                return VIRTIO_SPI_PARAM_ERR;
+            }
+        };
+        let Some(dev) = self.devices.get_mut(head.chip_select_id as usize) else {
+            warn!("virtio-spi: invalid chip select {}", head.chip_select_id);
+            return VIRTIO_SPI_PARAM_ERR;
+        };
+        let Some(mode) = spidev_mode(head.mode.to_native()) else {
+            warn!("virtio-spi: unsupported mode {:#x}", head.mode.to_native());
+            return VIRTIO_SPI_PARAM_ERR;
+        };
+        let word_delay_ns = head.word_delay_ns.to_native();
+        let cs_delay_hold_ns = head.cs_delay_hold_ns.to_native();
+        if head.tx_nbits > 1
+            || head.rx_nbits > 1
+            || word_delay_ns > MAX_WORD_DELAY_NS
+            || cs_delay_hold_ns > MAX_CS_HOLD_NS
+            || head.cs_setup_ns.to_native() != 0
+            || head.cs_change_delay_inactive_ns.to_native() != 0
+        {
+            warn!("virtio-spi: unsupported transfer parameters {:?}", head);
+            return VIRTIO_SPI_PARAM_ERR;
+        }
+
+        // The rx buffer, if any, comes before the result byte.
+        let tx_len = reader.available_bytes();
+        let rx_len = writer.available_bytes().saturating_sub(1);
+        if tx_len != 0 && rx_len != 0 && tx_len != rx_len {
+            warn!(
+                "virtio-spi: tx length {} does not match rx length {}",
+                tx_len, rx_len
+            );
+            return VIRTIO_SPI_PARAM_ERR;
+        }
+        let len = tx_len.max(rx_len);
+        let Ok(xfer_len) = u32::try_from(len) else {
+            return VIRTIO_SPI_PARAM_ERR;
+        };
+
+        let mut tx_buf = vec![0u8; tx_len];
+        if let Err(e) = reader.read_exact(&mut tx_buf) {
+            warn!("virtio-spi: failed to read tx buffer: {}", e);
+            return VIRTIO_SPI_PARAM_ERR;
+        }
+        let mut rx_buf = vec![0u8; rx_len];
+
+        if let Err(e) = dev.set_mode(mode) {
+            warn!("virtio-spi: failed to set mode {:#x}: {}", mode, e);
+            return VIRTIO_SPI_PARAM_ERR;
+        }
+        let xfer = spi_ioc_transfer {
+            tx_buf: if tx_len == 0 {
+                0
+            } else {
+                tx_buf.as_ptr() as u64
+            },
+            rx_buf: if rx_len == 0 {
+                0
+            } else {
+                rx_buf.as_mut_ptr() as u64
+            },
+            len: xfer_len,
+            speed_hz: head.freq.to_native(),
+            delay_usecs: (cs_delay_hold_ns / 1000) as u16,
+            bits_per_word: head.bits_per_word,
+            cs_change: head.cs_change,
+            word_delay_usecs: (word_delay_ns / 1000) as u8,
+            ..Default::default()
+        };
+        if let Err(e) = dev.transfer(&xfer) {
+            warn!("virtio-spi: transfer failed: {}", e);
+            return VIRTIO_SPI_TRANS_ERR;
+        }
+
+        if let Err(e) = writer.write_all(&rx_buf) {
+            error!("virtio-spi: failed to write rx buffer: {}", e);
+            return VIRTIO_SPI_TRANS_ERR;
+        }
+        VIRTIO_SPI_TRANS_OK
+    }
+
+    fn process_queue(&mut self) -> bool {
+        let mut needs_interrupt = false;
+        while let Some(mut avail_desc) = self.queue.pop() {
+            let result = self.process_request(&mut avail_desc.reader, &mut avail_desc.writer);
+            // The result is always the last byte of the request, whatever was written before it.
+            let offset = avail_desc.writer.available_bytes().saturating_sub(1);
+            let mut result_writer = avail_desc.writer.split_at(offset);
+            if let Err(e) = result_writer.write_obj(result) {
+                error!("virtio-spi: failed to write transfer result: {}", e);
+            }
+            let len = (avail_desc.writer.bytes_written() + result_writer.bytes_written()) as u32;
+            self.queue.add_used(avail_desc, len);
+            needs_interrupt = true;
+        }
+        needs_interrupt
+    }
+
+    fn run(&mut self, kill_evt: Event) -> anyhow::Result<()> {
+        #[derive(EventToken)]
+        enum Token {
+            QueueAvailable,
+            Kill,
+        }
+
+        let wait_ctx = WaitContext::build_with(&[
+            (self.queue.event(), Token::QueueAvailable),
+            (&kill_evt, Token::Kill),
+        ])
+        .context("failed creating WaitContext")?;
+
+        let mut exiting = false;
+        while !exiting {
+            let events = wait_ctx.wait().context("failed polling for events")?;
+            let mut needs_interrupt = false;
+            for event in events.iter().filter(|e| e.is_readable) {
+                match event.token {
+                    Token::QueueAvailable => {
+                        self.queue
+                            .event()
+                            .wait()
+                            .context("failed reading queue Event")?;
+                        needs_interrupt |= self.process_queue();
+                    }
+                    Token::Kill => exiting = true,
+                }
+            }
+            if needs_interrupt {
+                self.queue.trigger_interrupt();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Virtio device for a SPI controller backed by host spidev nodes.
+pub struct Spi {
+    devices: Option<Vec<SpiDev>>,
+    config: virtio_spi_config,
+    virtio_features: u64,
+    worker_thread: Option<WorkerThread<Worker>>,
+}
+
+impl Spi {
+    /// Creates a virtio-spi device with one chip select per spidev node in `params`.
+    pub fn new(params: &SpiParameters, base_features: u64) -> anyhow::Result<Spi> {
+        if params.devices.is_empty() {
+            return Err(anyhow!("virtio-spi needs at least one spidev device"));
+        }
+        let cs_max_number = u8::try_from(params.devices.len())
+            .map_err(|_| anyhow!("too many spidev devices: {}", params.devices.len()))?;
+        let devices = params
+            .devices
+            .iter()
+            .map(|path| {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                Ok(SpiDev { file, mode: None })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Spi {
+            devices: Some(devices),
+            config: virtio_spi_config {
+                cs_max_number,
+                cs_change_supported: 1,
+                mode_func_supported: MODE_FUNC_SUPPORTED.into(),
+                max_freq_hz: params.max_freq_hz.into(),
+                max_word_delay_ns: MAX_WORD_DELAY_NS.into(),
+                max_cs_hold_ns: MAX_CS_HOLD_NS.into(),
+                ..Default::default()
+            },
+            virtio_features: base_features,
+            worker_thread: None,
+        })
+    }
+}
+
+impl VirtioDevice for Spi {
+    fn keep_rds(&self) -> Vec<RawDescriptor> {
+        self.devices
+            .iter()
+            .flatten()
+            .map(|dev| dev.file.as_raw_descriptor())
+            .collect()
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Spi
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        QUEUE_SIZES
+    }
+
+    fn features(&self) -> u64 {
+        self.virtio_features
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        copy_config(data, 0, self.config.as_bytes(), offset);
+    }
+
+    fn activate(
+        &mut self,
+        _mem: GuestMemory,
+        _interrupt: Interrupt,
+        mut queues: BTreeMap<usize, Queue>,
+    ) -> anyhow::Result<()> {
+        if queues.len() != 1 {
+            return Err(anyhow!("expected 1 queue, got {}", queues.len()));
+        }
+        let devices = self
+            .devices
+            .take()
+            .context("virtio-spi activated without spidev devices")?;
+
+        let mut worker = Worker {
+            queue: queues.remove(&0).unwrap(),
+            devices,
+        };
+        self.worker_thread = Some(WorkerThread::start("v_spi", move |kill_evt| {
+            if let Err(e) = worker.run(kill_evt) {
+                error!("virtio-spi worker thread failed: {:#}", e);
+            }
+            worker
+        }));
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            let worker = worker_thread.stop();
+            self.devices = Some(worker.devices);
+        }
+        Ok(())
+    }
+
+    fn virtio_sleep(&mut self) -> anyhow::Result<Option<BTreeMap<usize, Queue>>> {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            let worker = worker_thread.stop();
+            self.devices = Some(worker.devices);
+            return Ok(Some(BTreeMap::from([(0, worker.queue)])));
+        }
+        Ok(None)
+    }
+
+    fn virtio_wake(
+        &mut self,
+        queues_state: Option<(GuestMemory, Interrupt, BTreeMap<usize, Queue>)>,
+    ) -> anyhow::Result<()> {
+        if let Some((mem, interrupt, queues)) = queues_state {
+            self.activate(mem, interrupt, queues)?;
+        }
+        Ok(())
+    }
+
+    fn virtio_snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        // Requests are completed synchronously, so there is no state to save.
+        AnySnapshot::to_any(())
+    }
+
+    fn virtio_restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        let () = AnySnapshot::from_any(data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_keyvalue::from_key_values;
+
+    use super::*;
+
+    #[test]
+    fn params_from_key_values() {
+        assert_eq!(
+            from_key_values::<SpiParameters>("devices=[/dev/spidev0.0,/dev/spidev0.1]").unwrap(),
+            SpiParameters {
+                devices: vec![
+                    PathBuf::from("/dev/spidev0.0"),
+                    PathBuf::from("/dev/spidev0.1"),
+                ],
+                max_freq_hz: 0,
+            }
+        );
+        assert_eq!(
+            from_key_values::<SpiParameters>("devices=[/dev/spidev1.0],max-freq-hz=1000000")
+                .unwrap()
+                .max_freq_hz,
+            1_000_000
+        );
+        assert!(from_key_values::<SpiParameters>("max-freq-hz=1000000").is_err());
+    }
+
+    #[test]
+    fn mode_translation() {
+        assert_eq!(spidev_mode(0), Some(0));
+        assert_eq!(
+            spidev_mode(VIRTIO_SPI_CPHA | VIRTIO_SPI_CPOL),
+            Some(SPI_CPHA | SPI_CPOL)
+        );
+        assert_eq!(spidev_mode(VIRTIO_SPI_MODE_LOOP), Some(SPI_LOOP));
+        assert_eq!(spidev_mode(1 << 5), None);
+    }
+
+    #[test]
+    fn config_layout() {
+        assert_eq!(std::mem::size_of::<virtio_spi_config>(), 32);
+        assert_eq!(std::mem::size_of::<spi_transfer_head>(), 32);
+        assert_eq!(std::mem::size_of::<spi_ioc_transfer>(), 32);
+    }
+}
@@ -17,6 +17,7 @@ use base::RawDescriptor;
 use base::WaitContext;
 use base::WorkerThread;
 use remain::sorted;
+use snapshot::AnySnapshot;
 use thiserror::Error;
 use vm_memory::GuestMemory;
 
@@ -44,6 +45,22 @@ struct Worker {
 
 pub trait TpmBackend: Send {
     fn execute_command<'a>(&'a mut self, command: &[u8]) -> &'a [u8];
+
+    /// Descriptors that must stay open when the device is sandboxed.
+    fn keep_rds(&self) -> Vec<RawDescriptor> {
+        Vec::new()
+    }
+
+    /// Captures the backend's NVRAM and volatile state so that it can be included in a VM
+    /// snapshot. Only called while the device is asleep.
+    fn snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        Err(anyhow!("snapshot is not supported by this vtpm backend"))
+    }
+
+    /// Restores state previously returned by `snapshot`.
+    fn restore(&mut self, _data: AnySnapshot) -> anyhow::Result<()> {
+        Err(anyhow!("restore is not supported by this vtpm backend"))
+    }
 }
 
 impl Worker {
@@ -97,7 +114,7 @@ impl Worker {
         needs_interrupt
     }
 
-    fn run(mut self, kill_evt: Event) -> anyhow::Result<()> {
+    fn run(&mut self, kill_evt: Event) -> anyhow::Result<()> {
         #[derive(EventToken, Debug)]
         enum Token {
             // A request is ready on the queue.
@@ -134,7 +151,7 @@ impl Worker {
 /// Virtio vTPM device.
 pub struct Tpm {
     backend: Option<Box<dyn TpmBackend>>,
-    worker_thread: Option<WorkerThread<()>>,
+    worker_thread: Option<WorkerThread<Worker>>,
     features: u64,
 }
 
@@ -150,7 +167,10 @@ impl Tpm {
 
 impl VirtioDevice for Tpm {
     fn keep_rds(&self) -> Vec<RawDescriptor> {
-        Vec::new()
+        self.backend
+            .as_ref()
+            .map(|backend| backend.keep_rds())
+            .unwrap_or_default()
     }
 
     fn device_type(&self) -> DeviceType {
@@ -178,16 +198,66 @@ impl VirtioDevice for Tpm {
 
         let backend = self.backend.take().context("no backend in vtpm")?;
 
-        let worker = Worker { queue, backend };
+        let mut worker = Worker { queue, backend };
 
-        self.worker_thread = Some(WorkerThread::start("v_tpm", |kill_evt| {
+        self.worker_thread = Some(WorkerThread::start("v_tpm", move |kill_evt| {
             if let Err(e) = worker.run(kill_evt) {
                 error!("virtio-tpm worker failed: {:#}", e);
             }
+            worker
         }));
 
         Ok(())
     }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            let worker = worker_thread.stop();
+            self.backend = Some(worker.backend);
+        }
+        Ok(())
+    }
+
+    fn virtio_sleep(&mut self) -> anyhow::Result<Option<BTreeMap<usize, Queue>>> {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            let worker = worker_thread.stop();
+            self.backend = Some(worker.backend);
+            return Ok(Some(BTreeMap::from([(0, worker.queue)])));
+        }
+        Ok(None)
+    }
+
+    fn virtio_wake(
+        &mut self,
+        queues_state: Option<(GuestMemory, Interrupt, BTreeMap<usize, Queue>)>,
+    ) -> anyhow::Result<()> {
+        if let Some((mem, interrupt, queues)) = queues_state {
+            self.activate(mem, interrupt, queues)?;
+        }
+        Ok(())
+    }
+
+    fn virtio_snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        // `virtio_sleep` hands the backend back to us, so the TPM cannot be processing a command
+        // while its state is captured.
+        let backend = self
+            .backend
+            .as_mut()
+            .context("vtpm backend is busy, device must be asleep")?;
+        backend
+            .snapshot()
+            .context("failed to snapshot vtpm backend")
+    }
+
+    fn virtio_restore(&mut self, data: AnySnapshot) -> anyhow::Result<()> {
+        let backend = self
+            .backend
+            .as_mut()
+            .context("vtpm backend is busy, device must be asleep")?;
+        backend
+            .restore(data)
+            .context("failed to restore vtpm backend")
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -62,6 +62,7 @@ impl VhostUserDevice for BlockBackend {
             | VhostUserProtocolFeatures::MQ
             | VhostUserProtocolFeatures::BACKEND_REQ
             | VhostUserProtocolFeatures::DEVICE_STATE
+            | VhostUserProtocolFeatures::INFLIGHT_SHMFD
     }
 
     fn read_config(&self, offset: u64, data: &mut [u8]) {
@@ -2,6 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use anyhow::bail;
 use anyhow::Context;
 use argh::FromArgs;
 use base::RawDescriptor;
@@ -11,6 +12,7 @@ use hypervisor::ProtectionType;
 use crate::virtio::base_features;
 use crate::virtio::block::DiskOption;
 use crate::virtio::vhost::user::device::BackendConnection;
+use crate::virtio::vhost::user::VhostUserDeviceBuilder;
 use crate::virtio::BlockAsync;
 
 #[derive(FromArgs)]
@@ -32,6 +34,11 @@ pub struct Options {
     #[argh(option, arg_name = "PATH<:read-only>")]
     /// path and options of the disk file.
     file: String,
+
+    #[argh(switch)]
+    /// serve any number of frontends at once, each with its own view of the disk.
+    /// Requires a read-only disk and --socket-path.
+    multi_client: bool,
 }
 
 /// Starts a vhost-user block device.
@@ -49,17 +56,33 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
         ..DiskOption::default()
     };
 
-    let block = Box::new(BlockAsync::new(
+    let conn =
+        BackendConnection::from_opts(opts.socket.as_deref(), opts.socket_path.as_deref(), opts.fd)?;
+
+    if opts.multi_client {
+        if !disk.read_only {
+            bail!("--multi-client requires a read-only disk");
+        }
+        let BackendConnection::Listener(listener) = conn else {
+            bail!("--multi-client requires --socket-path");
+        };
+        return listener.run_devices(ex, move || {
+            let block: Box<dyn VhostUserDeviceBuilder> = Box::new(new_block(&disk)?);
+            Ok(block)
+        });
+    }
+
+    conn.run_device(ex, Box::new(new_block(&disk)?))
+}
+
+fn new_block(disk: &DiskOption) -> anyhow::Result<BlockAsync> {
+    BlockAsync::new(
         base_features(ProtectionType::Unprotected),
         disk.open()?,
-        &disk,
+        disk,
         None,
         None,
         None,
-    )?);
-
-    let conn =
-        BackendConnection::from_opts(opts.socket.as_deref(), opts.socket_path.as_deref(), opts.fd)?;
-
-    conn.run_device(ex, block)
+    )
+    .context("failed to create block device")
 }
@@ -12,6 +12,7 @@ use argh::FromArgs;
 use base::enable_high_res_timers;
 use base::info;
 use base::Event;
+use base::FileSerdeWrapper;
 use base::RawDescriptor;
 use broker_ipc::common_child_setup;
 use broker_ipc::CommonChildStartupArgs;
@@ -58,6 +59,8 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
     let _child_cleanup = common_child_setup(startup_args)?;
 
     let disk_option: DiskOption = bootstrap_tube.recv::<DiskOption>()?;
+    // Set by the broker when this process cannot open the disk image itself.
+    let raw_image = bootstrap_tube.recv::<Option<FileSerdeWrapper>>()?;
     let exit_event = bootstrap_tube.recv::<Event>()?;
 
     // TODO(b/213146388): Replace below with `broker_ipc::common_child_setup`
@@ -72,9 +75,11 @@ pub fn start_device(opts: Options) -> anyhow::Result<()> {
 
     let block = Box::new(BlockAsync::new(
         base_features(ProtectionType::Unprotected),
-        disk_option
-            .open()
-            .exit_context(Exit::OpenDiskImage, "failed to open disk image")?,
+        match raw_image {
+            Some(FileSerdeWrapper(raw_image)) => disk_option.open_from_raw(raw_image),
+            None => disk_option.open(),
+        }
+        .exit_context(Exit::OpenDiskImage, "failed to open disk image")?,
         &disk_option,
         None,
         None,
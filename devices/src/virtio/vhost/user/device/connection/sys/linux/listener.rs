@@ -5,6 +5,7 @@
 use std::pin::Pin;
 
 use anyhow::Context;
+use base::error;
 use base::AsRawDescriptor;
 use base::RawDescriptor;
 use cros_async::AsyncWrapper;
@@ -17,6 +18,7 @@ use vmm_vhost::BackendServer;
 
 use crate::virtio::vhost::user::device::connection::VhostUserConnectionTrait;
 use crate::virtio::vhost::user::device::handler::sys::linux::run_handler;
+use crate::virtio::vhost::user::VhostUserDeviceBuilder;
 
 /// On Unix we can listen to a socket.
 pub struct VhostUserListener(SocketListener);
@@ -28,6 +30,16 @@ impl VhostUserListener {
 
         Ok(VhostUserListener(listener))
     }
+
+    /// Serves every incoming connection with its own device from `new_device`, so that any number
+    /// of frontends can use the backend at the same time. Only returns on error.
+    pub fn run_devices(
+        self,
+        ex: Executor,
+        new_device: impl FnMut() -> anyhow::Result<Box<dyn VhostUserDeviceBuilder>>,
+    ) -> anyhow::Result<()> {
+        ex.run_until(run_with_devices(self.0, new_device, &ex))?
+    }
 }
 
 impl AsRawDescriptor for VhostUserListener {
@@ -71,6 +83,42 @@ async fn run_with_handler(
     }
 }
 
+/// Like `run_with_handler`, but keeps accepting connections and serves each of them concurrently
+/// with a new device from `new_device`.
+async fn run_with_devices(
+    mut listener: SocketListener,
+    mut new_device: impl FnMut() -> anyhow::Result<Box<dyn VhostUserDeviceBuilder>>,
+    ex: &Executor,
+) -> anyhow::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    loop {
+        match listener
+            .accept()
+            .context("failed to accept an incoming connection")?
+        {
+            Some(connection) => {
+                let handler = new_device()?.build(ex)?;
+                let req_handler = BackendServer::new(connection, handler);
+                let handler_ex = ex.clone();
+                ex.spawn_local(async move {
+                    if let Err(e) = run_handler(req_handler, &handler_ex).await {
+                        error!("vhost-user connection failed: {:#}", e);
+                    }
+                })
+                .detach();
+            }
+            None => {
+                let async_waiter = ex
+                    .async_from(AsyncWrapper::new(listener))
+                    .context("failed to create async waiter")?;
+                async_waiter.wait_readable().await?;
+                listener = async_waiter.into_source().into_inner();
+            }
+        }
+    }
+}
+
 impl VhostUserConnectionTrait for VhostUserListener {
     fn run_req_handler<'e>(
         self,
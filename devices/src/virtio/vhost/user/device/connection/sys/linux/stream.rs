@@ -54,6 +54,12 @@ impl VhostUserStream {
     }
 }
 
+impl From<UnixStream> for VhostUserStream {
+    fn from(stream: UnixStream) -> Self {
+        VhostUserStream(stream)
+    }
+}
+
 impl VhostUserConnectionTrait for VhostUserStream {
     fn run_req_handler<'e>(
         self,
@@ -197,7 +197,7 @@ impl VhostUserDevice for GpuBackend {
                 };
 
                 // Start handling platform-specific workers.
-                self.start_platform_workers(doorbell)?;
+                self.start_platform_workers(doorbell, mem.clone())?;
 
                 // Start handling the control queue.
                 self.ex
@@ -22,6 +22,7 @@ use cros_async::Executor;
 use cros_async::IoSource;
 use hypervisor::ProtectionType;
 use sync::Mutex;
+use vm_memory::GuestMemory;
 
 use crate::virtio;
 use crate::virtio::gpu;
@@ -76,7 +77,11 @@ async fn run_resource_bridge(tube: IoSource<Tube>, state: Rc<RefCell<gpu::Fronte
 }
 
 impl GpuBackend {
-    pub fn start_platform_workers(&mut self, _interrupt: Interrupt) -> anyhow::Result<()> {
+    pub fn start_platform_workers(
+        &mut self,
+        _interrupt: Interrupt,
+        _mem: GuestMemory,
+    ) -> anyhow::Result<()> {
         let state = self
             .state
             .as_ref()
@@ -250,6 +255,9 @@ pub fn run_gpu_device(opts: Options) -> anyhow::Result<()> {
         &channels,
         /* gpu_cgroup_path */
         None,
+        #[cfg(feature = "registered_events")]
+        /* registered_evt_q */
+        None,
     )));
 
     let (platform_worker_tx, platform_worker_rx) = futures::channel::mpsc::unbounded();
@@ -36,6 +36,7 @@ use sync::Mutex;
 use tube_transporter::TubeToken;
 use vm_control::gpu::GpuControlCommand;
 use vm_control::gpu::GpuControlResult;
+use vm_memory::GuestMemory;
 
 use crate::virtio;
 use crate::virtio::gpu;
@@ -87,6 +88,7 @@ async fn run_gpu_control_command_handler(
     mut gpu_control_tube: AsyncTube,
     state: Rc<RefCell<gpu::Frontend>>,
     interrupt: Interrupt,
+    mem: GuestMemory,
 ) {
     'wait: loop {
         let req = match gpu_control_tube.next::<GpuControlCommand>().await {
@@ -97,7 +99,7 @@ async fn run_gpu_control_command_handler(
             }
         };
 
-        let resp = state.borrow_mut().process_gpu_control_command(req);
+        let resp = state.borrow_mut().process_gpu_control_command(&mem, req);
 
         if let GpuControlResult::DisplaysUpdated = resp {
             info!("Signaling display config change");
@@ -112,7 +114,11 @@ async fn run_gpu_control_command_handler(
 }
 
 impl GpuBackend {
-    pub fn start_platform_workers(&mut self, interrupt: Interrupt) -> anyhow::Result<()> {
+    pub fn start_platform_workers(
+        &mut self,
+        interrupt: Interrupt,
+        mem: GuestMemory,
+    ) -> anyhow::Result<()> {
         let state = self
             .state
             .as_ref()
@@ -146,6 +152,7 @@ impl GpuBackend {
             .expect("gpu control tube creation"),
             state,
             interrupt,
+            mem,
         ));
         self.platform_worker_tx
             .unbounded_send(task)
@@ -310,6 +317,9 @@ pub fn run_gpu_device_worker(
         base_features,
         /* channels= */ &Default::default(),
         wndproc_thread,
+        #[cfg(feature = "registered_events")]
+        /* registered_evt_q= */
+        None,
     )));
 
     let ex = Executor::new().context("failed to create executor")?;
@@ -63,6 +63,8 @@ use base::error;
 use base::trace;
 use base::warn;
 use base::Event;
+use base::MemoryMapping;
+use base::MemoryMappingBuilder;
 use base::Protection;
 use base::SafeDescriptor;
 use base::SharedMemory;
@@ -74,6 +76,7 @@ use serde::Serialize;
 use snapshot::AnySnapshot;
 use sync::Mutex;
 use thiserror::Error as ThisError;
+use virtio_sys::virtio_config::VIRTIO_F_RING_PACKED;
 use vm_control::VmMemorySource;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
@@ -101,6 +104,11 @@ use vmm_vhost::FrontendClient;
 use vmm_vhost::Result as VhostResult;
 use vmm_vhost::VHOST_USER_F_PROTOCOL_FEATURES;
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use self::sys::linux::Postcopy;
+#[cfg(windows)]
+use self::sys::windows::Postcopy;
+use crate::virtio::InflightQueue;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
 use crate::virtio::QueueConfig;
@@ -174,6 +182,13 @@ pub trait VhostUserDevice {
         None
     }
 
+    /// Returns all of the device's shared memory regions, each with a unique id.
+    ///
+    /// Devices with a single region only need to implement `get_shared_memory_region`.
+    fn get_shared_memory_regions(&self) -> Vec<SharedMemoryRegion> {
+        self.get_shared_memory_region().into_iter().collect()
+    }
+
     /// Accepts `VhostBackendReqConnection` to conduct Vhost backend to frontend message
     /// handling.
     ///
@@ -292,6 +307,18 @@ pub struct DeviceRequestHandler<T: VhostUserDevice> {
     backend_req_connection: Arc<Mutex<VhostBackendReqConnectionState>>,
     // Thread processing active device state FD.
     device_state_thread: Option<DeviceStateThread>,
+    // userfaultfd opened by POSTCOPY_ADVISE and closed by POSTCOPY_END.
+    postcopy: Option<Postcopy>,
+    // Whether guest memory is registered with `postcopy`.
+    postcopy_listening: bool,
+    // Region shared with the frontend by VHOST_USER_{GET,SET}_INFLIGHT_FD.
+    inflight: Option<InflightRegion>,
+}
+
+struct InflightRegion {
+    mmap: Arc<MemoryMapping>,
+    // Number of descriptors each queue's slice of `mmap` can track.
+    queue_size: u16,
 }
 
 enum DeviceStateThread {
@@ -332,6 +359,9 @@ impl<T: VhostUserDevice> DeviceRequestHandler<T> {
                 VhostBackendReqConnectionState::NoConnection,
             )),
             device_state_thread: None,
+            postcopy: None,
+            postcopy_listening: false,
+            inflight: None,
         }
     }
 
@@ -435,6 +465,11 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
         files: Vec<File>,
     ) -> VhostResult<()> {
         let (guest_mem, vmm_maps) = VhostUserRegularOps::set_mem_table(contexts, files)?;
+        if self.postcopy_listening {
+            if let Some(postcopy) = &self.postcopy {
+                postcopy.register(&guest_mem)?;
+            }
+        }
         self.mem = Some(guest_mem);
         self.vmm_maps = Some(vmm_maps);
         Ok(())
@@ -493,9 +528,14 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
             ));
         }
 
+        let packed = self.acked_features & 1 << VIRTIO_F_RING_PACKED != 0;
         let vring = &mut self.vrings[index as usize];
-        vring.queue.set_next_avail(Wrapping(base as u16));
-        vring.queue.set_next_used(Wrapping(base as u16));
+        if packed {
+            vring.queue.set_packed_vring_base(base);
+        } else {
+            vring.queue.set_next_avail(Wrapping(base as u16));
+            vring.queue.set_next_used(Wrapping(base as u16));
+        }
 
         Ok(())
     }
@@ -531,12 +571,12 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
                     .map_err(VhostError::EnterSuspendedState)?;
             }
 
-            queue.next_avail_to_process()
+            queue.vhost_user_vring_base()
         } else {
             0
         };
 
-        Ok(VhostUserVringState::new(index, vring_base.into()))
+        Ok(VhostUserVringState::new(index, vring_base))
     }
 
     fn set_vring_kick(&mut self, index: u8, file: Option<File>) -> VhostResult<()> {
@@ -579,7 +619,7 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
 
         let doorbell = vring.doorbell.clone().ok_or(VhostError::InvalidOperation)?;
 
-        let queue = match vring.queue.activate(&mem, kick_evt, doorbell) {
+        let mut queue = match vring.queue.activate(&mem, kick_evt, doorbell) {
             Ok(queue) => queue,
             Err(e) => {
                 error!("failed to activate vring: {:#}", e);
@@ -587,6 +627,19 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
             }
         };
 
+        if let Some(inflight) = &self.inflight {
+            if let Err(e) =
+                InflightQueue::new(inflight.mmap.clone(), index as usize, inflight.queue_size)
+                    .and_then(|inflight| queue.set_inflight(inflight))
+            {
+                error!(
+                    "failed to track inflight descriptors of vring {}: {:#}",
+                    index, e
+                );
+                return Err(VhostError::BackendInternalError);
+            }
+        }
+
         if let Err(e) = self.backend.start_queue(index as usize, queue, mem) {
             error!("Failed to start queue {}: {}", index, e);
             return Err(VhostError::BackendInternalError);
@@ -673,7 +726,11 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
     fn set_backend_req_fd(&mut self, ep: Connection<BackendReq>) {
         let conn = Arc::new(VhostBackendReqConnection::new(
             FrontendClient::new(ep),
-            self.backend.get_shared_memory_region().map(|r| r.id),
+            self.backend
+                .get_shared_memory_regions()
+                .iter()
+                .map(|r| r.id)
+                .collect(),
         ));
 
         {
@@ -689,13 +746,57 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
 
     fn get_inflight_fd(
         &mut self,
-        _inflight: &VhostUserInflight,
+        inflight: &VhostUserInflight,
     ) -> VhostResult<(VhostUserInflight, File)> {
-        unimplemented!("get_inflight_fd");
+        let mmap_size =
+            InflightQueue::region_size(inflight.queue_size) * inflight.num_queues as usize;
+        let shm = SharedMemory::new("vhost-user inflight", mmap_size as u64).map_err(|e| {
+            error!("failed to create inflight region: {}", e);
+            VhostError::BackendInternalError
+        })?;
+        let mmap = MemoryMappingBuilder::new(mmap_size)
+            .from_shared_memory(&shm)
+            .build()
+            .map_err(|e| {
+                error!("failed to map inflight region: {}", e);
+                VhostError::BackendInternalError
+            })?;
+        self.inflight = Some(InflightRegion {
+            mmap: Arc::new(mmap),
+            queue_size: inflight.queue_size,
+        });
+
+        let reply = VhostUserInflight::new(
+            mmap_size as u64,
+            0,
+            inflight.num_queues,
+            inflight.queue_size,
+        );
+        Ok((reply, File::from(SafeDescriptor::from(shm))))
     }
 
-    fn set_inflight_fd(&mut self, _inflight: &VhostUserInflight, _file: File) -> VhostResult<()> {
-        unimplemented!("set_inflight_fd");
+    fn set_inflight_fd(&mut self, inflight: &VhostUserInflight, file: File) -> VhostResult<()> {
+        let mmap_size = inflight.mmap_size as usize;
+        if InflightQueue::region_size(inflight.queue_size) * inflight.num_queues as usize
+            > mmap_size
+        {
+            return Err(VhostError::InvalidParam(
+                "set_inflight_fd: region too small for queues",
+            ));
+        }
+        let mmap = MemoryMappingBuilder::new(mmap_size)
+            .from_file(&file)
+            .offset(inflight.mmap_offset)
+            .build()
+            .map_err(|e| {
+                error!("failed to map inflight region: {}", e);
+                VhostError::BackendInternalError
+            })?;
+        self.inflight = Some(InflightRegion {
+            mmap: Arc::new(mmap),
+            queue_size: inflight.queue_size,
+        });
+        Ok(())
     }
 
     fn get_max_mem_slots(&mut self) -> VhostResult<u64> {
@@ -807,11 +908,55 @@ impl<T: VhostUserDevice> vmm_vhost::Backend for DeviceRequestHandler<T> {
     }
 
     fn get_shared_memory_regions(&mut self) -> VhostResult<Vec<VhostSharedMemoryRegion>> {
-        Ok(if let Some(r) = self.backend.get_shared_memory_region() {
-            vec![VhostSharedMemoryRegion::new(r.id, r.length)]
-        } else {
-            Vec::new()
-        })
+        Ok(self
+            .backend
+            .get_shared_memory_regions()
+            .iter()
+            .map(|r| VhostSharedMemoryRegion::new(r.id, r.length))
+            .collect())
+    }
+
+    // The postcopy requests are only accepted if the device advertises
+    // `VhostUserProtocolFeatures::PAGEFAULT`, which none does by default: no frontend drives a
+    // postcopy migration yet and the device seccomp policies don't allow `userfaultfd`.
+    fn postcopy_advise(&mut self) -> VhostResult<File> {
+        let postcopy = Postcopy::new()?;
+        let uffd = postcopy.uffd()?;
+        self.postcopy = Some(postcopy);
+        Ok(uffd)
+    }
+
+    fn postcopy_listen(&mut self) -> VhostResult<()> {
+        let Some(postcopy) = &self.postcopy else {
+            error!("postcopy_listen: POSTCOPY_ADVISE was not sent");
+            return Err(VhostError::InvalidOperation);
+        };
+        if let Some(mem) = &self.mem {
+            postcopy.register(mem)?;
+        }
+        self.postcopy_listening = true;
+        Ok(())
+    }
+
+    fn postcopy_end(&mut self) -> VhostResult<()> {
+        self.postcopy_listening = false;
+        // Closing the userfaultfd unregisters guest memory from it.
+        self.postcopy = None;
+        Ok(())
+    }
+
+    fn postcopy_region_addrs(&mut self) -> VhostResult<Vec<u64>> {
+        let (Some(mem), Some(vmm_maps)) = (&self.mem, &self.vmm_maps) else {
+            return Err(VhostError::InvalidOperation);
+        };
+        vmm_maps
+            .iter()
+            .map(|map| {
+                mem.get_host_address(GuestAddress(map.guest_phys))
+                    .map(|addr| addr as u64)
+                    .map_err(|_| VhostError::InvalidOperation)
+            })
+            .collect()
     }
 }
 
@@ -826,7 +971,7 @@ pub enum VhostBackendReqConnectionState {
 /// Keeps track of Vhost user backend request connection.
 pub struct VhostBackendReqConnection {
     conn: Arc<Mutex<FrontendClient>>,
-    shmem_info: Mutex<Option<ShmemInfo>>,
+    shmem_info: Mutex<BTreeMap<u8, ShmemInfo>>,
 }
 
 #[derive(Clone)]
@@ -836,11 +981,21 @@ struct ShmemInfo {
 }
 
 impl VhostBackendReqConnection {
-    pub fn new(conn: FrontendClient, shmid: Option<u8>) -> Self {
-        let shmem_info = Mutex::new(shmid.map(|shmid| ShmemInfo {
-            shmid,
-            mapped_regions: BTreeMap::new(),
-        }));
+    pub fn new(conn: FrontendClient, shmids: Vec<u8>) -> Self {
+        let shmem_info = Mutex::new(
+            shmids
+                .into_iter()
+                .map(|shmid| {
+                    (
+                        shmid,
+                        ShmemInfo {
+                            shmid,
+                            mapped_regions: BTreeMap::new(),
+                        },
+                    )
+                })
+                .collect(),
+        );
         Self {
             conn: Arc::new(Mutex::new(conn)),
             shmem_info,
@@ -856,13 +1011,26 @@ impl VhostBackendReqConnection {
         Ok(())
     }
 
-    /// Create a SharedMemoryMapper trait object from the ShmemInfo.
+    /// Create a SharedMemoryMapper trait object for the device's first shared memory region.
     pub fn take_shmem_mapper(&self) -> anyhow::Result<Box<dyn SharedMemoryMapper>> {
-        let shmem_info = self
+        let shmid = *self
             .shmem_info
             .lock()
-            .take()
+            .keys()
+            .next()
             .context("could not take shared memory mapper information")?;
+        self.take_shmem_mapper_for_region(shmid)
+    }
+
+    /// Create a SharedMemoryMapper trait object for the shared memory region with id `shmid`.
+    pub fn take_shmem_mapper_for_region(
+        &self,
+        shmid: u8,
+    ) -> anyhow::Result<Box<dyn SharedMemoryMapper>> {
+        let shmem_info =
+            self.shmem_info.lock().remove(&shmid).with_context(|| {
+                format!("could not take shared memory mapper for shmid {}", shmid)
+            })?;
 
         Ok(Box::new(VhostShmemMapper {
             conn: self.conn.clone(),
@@ -1140,7 +1308,7 @@ mod tests {
             ready_rx.recv().unwrap(); // Ensure the device is ready.
 
             let mut vmm_device =
-                VhostUserFrontend::new(DeviceType::Console, 0, client_connection, None, None)
+                VhostUserFrontend::new(DeviceType::Console, 0, client_connection, None, None, None)
                     .unwrap();
 
             println!("read_config");
@@ -1320,6 +1488,152 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(not(windows))] // Windows requries more complex connection setup.
+    fn test_vhost_user_reconnect() {
+        const QUEUES_NUM: usize = 2;
+        // Used ring indices written by the "guest" before the first backend goes away.
+        const USED_IDX: [u16; QUEUES_NUM] = [5, 3];
+
+        let (client_connection, server_connection) =
+            vmm_vhost::Connection::<FrontendReq>::pair().unwrap();
+        let (new_client_connection, new_server_connection) =
+            vmm_vhost::Connection::<FrontendReq>::pair().unwrap();
+
+        let vmm_bar = Arc::new(Barrier::new(2));
+        let dev_bar = vmm_bar.clone();
+
+        let (ready_tx, ready_rx) = channel();
+        let (activated_tx, activated_rx) = channel();
+        let (shutdown_tx, shutdown_rx) = channel();
+
+        std::thread::spawn(move || {
+            // VMM side
+            ready_rx.recv().unwrap(); // Ensure the device is ready.
+
+            let mut vmm_device =
+                VhostUserFrontend::new(DeviceType::Console, 0, client_connection, None, None, None)
+                    .unwrap();
+            // The restarted backend is reachable through the second connection, only once.
+            let new_client_connection = Mutex::new(Some(new_client_connection));
+            vmm_device
+                .set_reconnect(Arc::new(move || {
+                    new_client_connection
+                        .lock()
+                        .take()
+                        .context("backend already reconnected")
+                }))
+                .unwrap();
+
+            let mem = GuestMemory::new(&[(GuestAddress(0x0), 0x10000)]).unwrap();
+            let interrupt = Interrupt::new_for_test_with_msix();
+            let mut queues = BTreeMap::new();
+            for idx in 0..QUEUES_NUM {
+                let base = GuestAddress(0x1000 * (idx as u64 + 1));
+                let mut queue = QueueConfig::new(0x10, 0);
+                queue.set_desc_table(base);
+                queue.set_avail_ring(base.unchecked_add(0x400));
+                queue.set_used_ring(base.unchecked_add(0x800));
+                queue.set_ready(true);
+                let queue = queue
+                    .activate(&mem, Event::new().unwrap(), interrupt.clone())
+                    .expect("QueueConfig::activate");
+                queues.insert(idx, queue);
+            }
+
+            println!("activate");
+            vmm_device.activate(mem.clone(), interrupt, queues).unwrap();
+
+            // Pretend the first backend completed some requests. The used index follows the
+            // 16-bit flags field of the used ring.
+            for (idx, used_idx) in USED_IDX.iter().enumerate() {
+                let used_ring = GuestAddress(0x1000 * (idx as u64 + 1) + 0x800);
+                mem.write_obj_at_addr(*used_idx, used_ring.unchecked_add(2))
+                    .unwrap();
+            }
+            activated_tx.send(()).unwrap();
+
+            println!("wait for shutdown signal");
+            shutdown_rx.recv().unwrap();
+
+            // The VMM side is supposed to stop before the device side.
+            println!("drop");
+            drop(vmm_device);
+
+            vmm_bar.wait();
+        });
+
+        // Device side
+        ready_tx.send(()).unwrap();
+
+        let mut req_handler = BackendServer::new(
+            server_connection,
+            DeviceRequestHandler::new(FakeBackend::new()),
+        );
+
+        // VhostUserFrontend::new()
+        handle_request(&mut req_handler, FrontendReq::SET_OWNER).unwrap();
+        handle_request(&mut req_handler, FrontendReq::GET_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::SET_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::GET_PROTOCOL_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::SET_PROTOCOL_FEATURES).unwrap();
+
+        // VhostUserFrontend::activate()
+        handle_request(&mut req_handler, FrontendReq::SET_MEM_TABLE).unwrap();
+        for _ in 0..QUEUES_NUM {
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_NUM).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_ADDR).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_BASE).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_CALL).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_KICK).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_ENABLE).unwrap();
+        }
+
+        // The backend crashes: the frontend worker sees the socket hang up and connects again.
+        activated_rx.recv().unwrap();
+        drop(req_handler);
+
+        let mut req_handler = BackendServer::new(
+            new_server_connection,
+            DeviceRequestHandler::new(FakeBackend::new()),
+        );
+
+        // Worker::reconnect() replays the negotiated state and the vrings.
+        handle_request(&mut req_handler, FrontendReq::SET_OWNER).unwrap();
+        handle_request(&mut req_handler, FrontendReq::GET_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::SET_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::GET_PROTOCOL_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::SET_PROTOCOL_FEATURES).unwrap();
+        handle_request(&mut req_handler, FrontendReq::SET_MEM_TABLE).unwrap();
+        for _ in 0..QUEUES_NUM {
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_NUM).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_ADDR).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_BASE).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_CALL).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_KICK).unwrap();
+            handle_request(&mut req_handler, FrontendReq::SET_VRING_ENABLE).unwrap();
+        }
+
+        // Each vring resumes from the used index in guest memory.
+        let backend = req_handler.as_ref().as_ref();
+        for (idx, used_idx) in USED_IDX.iter().enumerate() {
+            let queue = backend.active_queues[idx]
+                .as_ref()
+                .expect("queue not restarted");
+            assert_eq!(queue.next_avail_to_process(), *used_idx);
+        }
+
+        // Ask the client to shutdown, then wait to it to finish.
+        shutdown_tx.send(()).unwrap();
+        dev_bar.wait();
+
+        // Verify recv_header fails with `ClientExit` after the client has disconnected.
+        match req_handler.recv_header() {
+            Err(VhostError::ClientExit) => (),
+            r => panic!("expected Err(ClientExit) but got {:?}", r),
+        }
+    }
+
     fn handle_request<S: vmm_vhost::Backend>(
         handler: &mut BackendServer<S>,
         expected_message_type: FrontendReq,
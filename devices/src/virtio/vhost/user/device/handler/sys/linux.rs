@@ -2,15 +2,25 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
 use anyhow::Context;
 use anyhow::Result;
+use base::clone_descriptor;
+use base::error;
 use base::info;
 use base::AsRawDescriptor;
+use base::Descriptor;
 use base::SafeDescriptor;
 use cros_async::AsyncWrapper;
 use cros_async::Executor;
+use userfaultfd::Uffd;
+use userfaultfd::UffdBuilder;
+use vm_memory::GuestMemory;
 use vmm_vhost::BackendServer;
 use vmm_vhost::Error as VhostError;
+use vmm_vhost::Result as VhostResult;
 
 /// Performs the run loop for an already-constructor request handler.
 pub async fn run_handler<S>(mut backend_server: BackendServer<S>, ex: &Executor) -> Result<()>
@@ -50,3 +60,49 @@ where
         backend_server.process_message(hdr, files)?;
     }
 }
+
+/// The userfaultfd through which guest memory is demand-faulted from the frontend during a
+/// postcopy migration.
+pub struct Postcopy {
+    uffd: Uffd,
+}
+
+impl Postcopy {
+    pub fn new() -> VhostResult<Self> {
+        let uffd = UffdBuilder::new()
+            .close_on_exec(true)
+            .non_blocking(true)
+            // The backend's own syscalls (e.g. reads from a disk image into guest memory) must
+            // fault on missing pages too.
+            .user_mode_only(false)
+            .create()
+            .map_err(|e| {
+                error!("failed to create userfaultfd: {}", e);
+                VhostError::InvalidOperation
+            })?;
+        Ok(Postcopy { uffd })
+    }
+
+    /// Returns a copy of the userfaultfd to hand to the frontend.
+    pub fn uffd(&self) -> VhostResult<File> {
+        clone_descriptor(&Descriptor(self.uffd.as_raw_fd()))
+            .map(File::from)
+            .map_err(|e| VhostError::ReqHandlerError(e.into()))
+    }
+
+    /// Registers all of `mem` for missing-page faults.
+    pub fn register(&self, mem: &GuestMemory) -> VhostResult<()> {
+        for region in mem.regions() {
+            self.uffd
+                .register(region.host_addr as *mut libc::c_void, region.size)
+                .map_err(|e| {
+                    error!(
+                        "failed to register guest memory at {} with userfaultfd: {}",
+                        region.guest_addr, e
+                    );
+                    VhostError::InvalidOperation
+                })?;
+        }
+        Ok(())
+    }
+}
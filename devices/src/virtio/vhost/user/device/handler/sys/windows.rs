@@ -22,10 +22,13 @@ use futures::select;
 use futures::FutureExt;
 use tube_transporter::TubeTransferDataList;
 use tube_transporter::TubeTransporterReader;
+use vm_memory::GuestMemory;
 use vmm_vhost::message::FrontendReq;
 use vmm_vhost::message::VhostUserMsgHeader;
 use vmm_vhost::BackendServer;
 use vmm_vhost::Connection;
+use vmm_vhost::Error as VhostError;
+use vmm_vhost::Result as VhostResult;
 
 pub fn read_from_tube_transporter(
     raw_transport_tube: RawDescriptor,
@@ -117,3 +120,20 @@ pub async fn run_handler(
         }
     }
 }
+
+/// Postcopy migration is not supported on Windows.
+pub enum Postcopy {}
+
+impl Postcopy {
+    pub fn new() -> VhostResult<Self> {
+        Err(VhostError::InvalidOperation)
+    }
+
+    pub fn uffd(&self) -> VhostResult<std::fs::File> {
+        match *self {}
+    }
+
+    pub fn register(&self, _mem: &GuestMemory) -> VhostResult<()> {
+        match *self {}
+    }
+}
@@ -442,6 +442,22 @@ impl vmm_vhost::Backend for VsockBackend {
     fn get_shared_memory_regions(&mut self) -> Result<Vec<VhostSharedMemoryRegion>> {
         Ok(vec![])
     }
+
+    fn postcopy_advise(&mut self) -> Result<File> {
+        Err(Error::InvalidOperation)
+    }
+
+    fn postcopy_listen(&mut self) -> Result<()> {
+        Err(Error::InvalidOperation)
+    }
+
+    fn postcopy_end(&mut self) -> Result<()> {
+        Err(Error::InvalidOperation)
+    }
+
+    fn postcopy_region_addrs(&mut self) -> Result<Vec<u64>> {
+        Err(Error::InvalidOperation)
+    }
 }
 
 #[derive(FromArgs)]
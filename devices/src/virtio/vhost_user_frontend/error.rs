@@ -11,6 +11,9 @@ use vmm_vhost::Error as VhostError;
 #[sorted]
 #[derive(ThisError, Debug)]
 pub enum Error {
+    /// Failed to clone `base::Event`.
+    #[error("failed to clone Event: {0}")]
+    CloneEvent(base::Error),
     /// Failed to copy config to a buffer.
     #[error("failed to copy config to a buffer: {0}")]
     CopyConfig(std::io::Error),
@@ -29,6 +32,9 @@ pub enum Error {
     /// Failed to get host address.
     #[error("failed to get host address: {0}")]
     GetHostAddress(GuestMemoryError),
+    /// Failed to get the inflight I/O tracking region.
+    #[error("failed to get inflight fd: {0}")]
+    GetInflightFd(VhostError),
     /// Failed to get protocol features.
     #[error("failed to get protocol features: {0}")]
     GetProtocolFeatures(VhostError),
@@ -44,8 +50,23 @@ pub enum Error {
     /// MSI-X irqfd is unavailable.
     #[error("MSI-X irqfd is unavailable")]
     MsixIrqfdUnavailable,
+    /// Failed to start a postcopy migration.
+    #[error("failed to advise postcopy: {0}")]
+    PostcopyAdvise(VhostError),
+    /// Failed to end a postcopy migration.
+    #[error("failed to end postcopy: {0}")]
+    PostcopyEnd(VhostError),
+    /// Failed to switch the backend to postcopy mode.
+    #[error("failed to listen for postcopy: {0}")]
+    PostcopyListen(VhostError),
     #[error("protocol feature is not negotiated: {0:?}")]
     ProtocolFeatureNotNegoiated(VhostUserProtocolFeatures),
+    /// Failed to read the index of a used ring.
+    #[error("failed to read used ring index: {0}")]
+    ReadUsedIndex(GuestMemoryError),
+    /// Reconnecting to the backend is not supported with the given protocol feature.
+    #[error("reconnecting is not supported with protocol feature {0:?}")]
+    ReconnectUnsupported(VhostUserProtocolFeatures),
     /// Failed to reset owner.
     #[error("failed to reset owner: {0}")]
     ResetOwner(VhostError),
@@ -64,6 +85,9 @@ pub enum Error {
     /// Failed to set features.
     #[error("failed to set features: {0}")]
     SetFeatures(VhostError),
+    /// Failed to set the inflight I/O tracking region.
+    #[error("failed to set inflight fd: {0}")]
+    SetInflightFd(VhostError),
     /// Failed to set memory map regions.
     #[error("failed to set memory map regions: {0}")]
     SetMemTable(VhostError),
@@ -112,9 +136,6 @@ pub enum Error {
     /// The tag for the Fs device was too long to fit in the config space.
     #[error("tag is too long: {len} > {max}")]
     TagTooLong { len: usize, max: usize },
-    /// Too many shmem regions.
-    #[error("too many shmem regions: {0} > 1")]
-    TooManyShmemRegions(usize),
     /// vring base from vhost-user backend is too big.
     #[error("vring base returned by vhost-user backend is too big: {0}")]
     VringBaseTooBig(u32),
@@ -50,6 +50,7 @@ impl VhostUserFrontend {
             base_features,
             cfg.as_deref(),
             None, // pci_address
+            None, // allow_protocol_features
         )
     }
 }
@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::BTreeMap;
+
 use base::error;
 use base::info;
 use base::AsRawDescriptor;
@@ -22,21 +24,17 @@ use crate::virtio::SharedMemoryMapper;
 
 pub(crate) type BackendReqHandler = FrontendServer<BackendReqHandlerImpl>;
 
-struct SharedMapperState {
-    mapper: Box<dyn SharedMemoryMapper>,
-    shmid: u8,
-}
-
 pub struct BackendReqHandlerImpl {
     interrupt: Option<Interrupt>,
-    shared_mapper_state: Option<SharedMapperState>,
+    /// Mappers of the device's shared memory regions, keyed by shmid.
+    shared_mappers: BTreeMap<u8, Box<dyn SharedMemoryMapper>>,
 }
 
 impl BackendReqHandlerImpl {
     pub(crate) fn new() -> Self {
         BackendReqHandlerImpl {
             interrupt: None,
-            shared_mapper_state: None,
+            shared_mappers: BTreeMap::new(),
         }
     }
 
@@ -44,12 +42,18 @@ impl BackendReqHandlerImpl {
         self.interrupt = Some(interrupt);
     }
 
-    pub(crate) fn set_shared_mapper_state(
+    pub(crate) fn set_shared_mappers(
         &mut self,
-        mapper: Box<dyn SharedMemoryMapper>,
-        shmid: u8,
+        mappers: BTreeMap<u8, Box<dyn SharedMemoryMapper>>,
     ) {
-        self.shared_mapper_state = Some(SharedMapperState { mapper, shmid });
+        self.shared_mappers = mappers;
+    }
+
+    fn shared_mapper(&mut self, shmid: u8) -> HandlerResult<&mut Box<dyn SharedMemoryMapper>> {
+        self.shared_mappers.get_mut(&shmid).ok_or_else(|| {
+            error!("bad shmid {}", shmid);
+            std::io::Error::from_raw_os_error(libc::EINVAL)
+        })
     }
 }
 
@@ -59,18 +63,8 @@ impl Frontend for BackendReqHandlerImpl {
         req: &VhostUserShmemMapMsg,
         fd: &dyn AsRawDescriptor,
     ) -> HandlerResult<u64> {
-        let shared_mapper_state = self
-            .shared_mapper_state
-            .as_mut()
-            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))?;
-        if req.shmid != shared_mapper_state.shmid {
-            error!(
-                "bad shmid {}, expected {}",
-                req.shmid, shared_mapper_state.shmid
-            );
-            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
-        }
-        match shared_mapper_state.mapper.add_mapping(
+        let mapper = self.shared_mapper(req.shmid)?;
+        match mapper.add_mapping(
             VmMemorySource::Descriptor {
                 descriptor: SafeDescriptor::try_from(fd)
                     .map_err(|_| std::io::Error::from_raw_os_error(libc::EIO))?,
@@ -90,18 +84,8 @@ impl Frontend for BackendReqHandlerImpl {
     }
 
     fn shmem_unmap(&mut self, req: &VhostUserShmemUnmapMsg) -> HandlerResult<u64> {
-        let shared_mapper_state = self
-            .shared_mapper_state
-            .as_mut()
-            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))?;
-        if req.shmid != shared_mapper_state.shmid {
-            error!(
-                "bad shmid {}, expected {}",
-                req.shmid, shared_mapper_state.shmid
-            );
-            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
-        }
-        match shared_mapper_state.mapper.remove_mapping(req.shm_offset) {
+        let mapper = self.shared_mapper(req.shmid)?;
+        match mapper.remove_mapping(req.shm_offset) {
             Ok(()) => Ok(0),
             Err(e) => {
                 error!("failed to remove mapping {:?}", e);
@@ -117,18 +101,8 @@ impl Frontend for BackendReqHandlerImpl {
         req: &VhostUserGpuMapMsg,
         descriptor: &dyn AsRawDescriptor,
     ) -> HandlerResult<u64> {
-        let shared_mapper_state = self
-            .shared_mapper_state
-            .as_mut()
-            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))?;
-        if req.shmid != shared_mapper_state.shmid {
-            error!(
-                "bad shmid {}, expected {}",
-                req.shmid, shared_mapper_state.shmid
-            );
-            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
-        }
-        match shared_mapper_state.mapper.add_mapping(
+        let mapper = self.shared_mapper(req.shmid)?;
+        match mapper.add_mapping(
             VmMemorySource::Vulkan {
                 descriptor: SafeDescriptor::try_from(descriptor)
                     .map_err(|_| std::io::Error::from_raw_os_error(libc::EIO))?,
@@ -153,18 +127,8 @@ impl Frontend for BackendReqHandlerImpl {
     }
 
     fn external_map(&mut self, req: &VhostUserExternalMapMsg) -> HandlerResult<u64> {
-        let shared_mapper_state = self
-            .shared_mapper_state
-            .as_mut()
-            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))?;
-        if req.shmid != shared_mapper_state.shmid {
-            error!(
-                "bad shmid {}, expected {}",
-                req.shmid, shared_mapper_state.shmid
-            );
-            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
-        }
-        match shared_mapper_state.mapper.add_mapping(
+        let mapper = self.shared_mapper(req.shmid)?;
+        match mapper.add_mapping(
             VmMemorySource::ExternalMapping {
                 ptr: req.ptr,
                 size: req.len,
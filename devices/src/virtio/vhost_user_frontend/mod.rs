@@ -12,6 +12,7 @@ mod worker;
 
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::sync::Arc;
@@ -26,8 +27,10 @@ use base::RawDescriptor;
 use base::WorkerThread;
 use snapshot::AnySnapshot;
 use sync::Mutex;
+use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use vmm_vhost::message::VhostUserConfigFlags;
+use vmm_vhost::message::VhostUserInflight;
 use vmm_vhost::message::VhostUserMigrationPhase;
 use vmm_vhost::message::VhostUserProtocolFeatures;
 use vmm_vhost::message::VhostUserTransferDirection;
@@ -65,7 +68,7 @@ pub struct VhostUserFrontend {
     // return ownershp of the handler when stopped.
     backend_req_handler: Option<BackendReqHandler>,
     // Shared memory region info. IPC result from backend is saved with outer Option.
-    shmem_region: RefCell<Option<Option<SharedMemoryRegion>>>,
+    shmem_regions: RefCell<Option<Vec<SharedMemoryRegion>>>,
 
     queue_sizes: Vec<u16>,
     cfg: Option<Vec<u8>>,
@@ -76,6 +79,48 @@ pub struct VhostUserFrontend {
     // for use in `virtio_sleep`. Since the backend is managing them, the local state of the queue
     // is likely stale.
     sent_queues: Option<BTreeMap<usize, Queue>>,
+
+    // Used by the worker to open a new connection if the backend goes away while the device is
+    // active. `None` if reconnecting is not enabled.
+    reconnect: Option<ReconnectFn>,
+
+    // Region in which the backend tracks in-flight requests, if INFLIGHT_SHMFD was negotiated.
+    // Obtained on the first activation and handed to every backend until the device is reset, so
+    // that a restarted backend can resubmit requests its predecessor did not complete.
+    inflight: Option<InflightFd>,
+}
+
+/// Opens a new connection to a vhost-user backend.
+pub type ReconnectFn =
+    Arc<dyn Fn() -> anyhow::Result<vmm_vhost::Connection<vmm_vhost::FrontendReq>> + Send + Sync>;
+
+/// Everything the worker needs to bring a freshly restarted backend up to the state the previous
+/// one was in.
+pub(crate) struct ReconnectState {
+    pub connect: ReconnectFn,
+    pub mem: GuestMemory,
+    pub acked_features: u64,
+    pub protocol_features: VhostUserProtocolFeatures,
+    pub inflight: Option<InflightFd>,
+    pub vrings: Vec<VringState>,
+}
+
+/// The inflight I/O tracking region returned by VHOST_USER_GET_INFLIGHT_FD.
+#[derive(Clone)]
+pub(crate) struct InflightFd {
+    pub info: VhostUserInflight,
+    pub file: Arc<File>,
+}
+
+/// A vring as it was handed to the backend.
+pub(crate) struct VringState {
+    pub queue_index: usize,
+    pub size: u16,
+    pub desc_table: GuestAddress,
+    pub avail_ring: GuestAddress,
+    pub used_ring: GuestAddress,
+    pub call_evt: Event,
+    pub kick_evt: Event,
 }
 
 // Returns the largest power of two that is less than or equal to `val`.
@@ -99,12 +144,16 @@ impl VhostUserFrontend {
     /// - `base_features`: base virtio device features (e.g. `VIRTIO_F_VERSION_1`)
     /// - `connection`: connection to the device backend
     /// - `max_queue_size`: maximum number of entries in each queue (default: [`Queue::MAX_SIZE`])
+    /// - `pci_address`: preferred PCI address of the device
+    /// - `allow_protocol_features`: mask of vhost-user protocol features that may be negotiated
+    ///   with the backend (default: the features supported for `device_type`)
     pub fn new(
         device_type: DeviceType,
         base_features: u64,
         connection: vmm_vhost::Connection<vmm_vhost::FrontendReq>,
         max_queue_size: Option<u16>,
         pci_address: Option<PciAddress>,
+        allow_protocol_features: Option<u64>,
     ) -> Result<VhostUserFrontend> {
         VhostUserFrontend::new_internal(
             connection,
@@ -113,6 +162,7 @@ impl VhostUserFrontend {
             base_features,
             None, // cfg
             pci_address,
+            allow_protocol_features,
         )
     }
 
@@ -126,6 +176,9 @@ impl VhostUserFrontend {
     /// - `base_features`: base virtio device features (e.g. `VIRTIO_F_VERSION_1`)
     /// - `cfg`: bytes to return for the virtio configuration space (queried from device if not
     ///   specified)
+    /// - `pci_address`: preferred PCI address of the device
+    /// - `allow_protocol_features`: mask of vhost-user protocol features that may be negotiated
+    ///   with the backend (default: the features supported for `device_type`)
     pub(crate) fn new_internal(
         connection: vmm_vhost::Connection<vmm_vhost::FrontendReq>,
         device_type: DeviceType,
@@ -133,6 +186,7 @@ impl VhostUserFrontend {
         mut base_features: u64,
         cfg: Option<&[u8]>,
         pci_address: Option<PciAddress>,
+        allow_protocol_features_mask: Option<u64>,
     ) -> Result<VhostUserFrontend> {
         // Don't allow packed queues even if requested. We don't handle them properly yet at the
         // protocol layer.
@@ -162,7 +216,9 @@ impl VhostUserFrontend {
         let mut allow_protocol_features = VhostUserProtocolFeatures::CONFIG
             | VhostUserProtocolFeatures::MQ
             | VhostUserProtocolFeatures::BACKEND_REQ
-            | VhostUserProtocolFeatures::DEVICE_STATE;
+            | VhostUserProtocolFeatures::DEVICE_STATE
+            | VhostUserProtocolFeatures::INFLIGHT_SHMFD
+            | VhostUserProtocolFeatures::PAGEFAULT;
 
         // HACK: the crosvm vhost-user GPU backend supports the non-standard
         // VHOST_USER_PROTOCOL_FEATURE_SHARED_MEMORY_REGIONS. This should either be standardized
@@ -174,6 +230,21 @@ impl VhostUserFrontend {
             false
         };
 
+        // Let the user restrict (or, for device types crosvm doesn't know about, extend) the set of
+        // protocol features offered to the backend. Features the frontend does not implement are
+        // never offered.
+        if let Some(mask) = allow_protocol_features_mask {
+            let supported = VhostUserProtocolFeatures::CONFIG
+                | VhostUserProtocolFeatures::MQ
+                | VhostUserProtocolFeatures::BACKEND_REQ
+                | VhostUserProtocolFeatures::DEVICE_STATE
+                | VhostUserProtocolFeatures::INFLIGHT_SHMFD
+                | VhostUserProtocolFeatures::PAGEFAULT
+                | VhostUserProtocolFeatures::SHARED_MEMORY_REGIONS;
+            allow_protocol_features =
+                VhostUserProtocolFeatures::from_bits_truncate(mask) & supported;
+        }
+
         let mut protocol_features = VhostUserProtocolFeatures::empty();
         if avail_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES != 0 {
             // The vhost-user backend supports VHOST_USER_F_PROTOCOL_FEATURES; enable it.
@@ -245,33 +316,118 @@ impl VhostUserFrontend {
             acked_features,
             protocol_features,
             backend_req_handler,
-            shmem_region: RefCell::new(None),
+            shmem_regions: RefCell::new(None),
             queue_sizes,
             cfg: cfg.map(|cfg| cfg.to_vec()),
             expose_shmem_descriptors_with_viommu,
             pci_address,
             sent_queues: None,
+            reconnect: None,
+            inflight: None,
         })
     }
 
+    /// Makes the frontend wait for the backend to come back, using `connect` to open a new
+    /// connection, if the backend disconnects while the device is active. The device state is
+    /// replayed to the new backend and the guest only observes a stall.
+    ///
+    /// Not supported for backends that negotiated shared memory regions, since their mappings
+    /// cannot be recovered.
+    pub fn set_reconnect(&mut self, connect: ReconnectFn) -> Result<()> {
+        if self
+            .protocol_features
+            .contains(VhostUserProtocolFeatures::SHARED_MEMORY_REGIONS)
+        {
+            return Err(Error::ReconnectUnsupported(
+                VhostUserProtocolFeatures::SHARED_MEMORY_REGIONS,
+            ));
+        }
+        self.reconnect = Some(connect);
+        Ok(())
+    }
+
     fn set_mem_table(&mut self, mem: &GuestMemory) -> Result<()> {
-        let regions: Vec<_> = mem
-            .regions()
-            .map(|region| VhostUserMemoryRegionInfo {
-                guest_phys_addr: region.guest_addr.0,
-                memory_size: region.size as u64,
-                userspace_addr: region.host_addr as u64,
-                mmap_offset: region.shm_offset,
-                mmap_handle: region.shm.as_raw_descriptor(),
-            })
-            .collect();
+        set_mem_table(&self.backend_client.lock(), mem)
+    }
 
+    /// Hands the inflight I/O tracking region to the backend, first asking the backend to create
+    /// one if this is the first activation since the device was reset.
+    fn set_inflight_fd(&mut self) -> Result<Option<InflightFd>> {
+        if !self
+            .protocol_features
+            .contains(VhostUserProtocolFeatures::INFLIGHT_SHMFD)
+        {
+            return Ok(None);
+        }
+
+        let backend_client = self.backend_client.lock();
+        let inflight = match &self.inflight {
+            Some(inflight) => inflight.clone(),
+            None => {
+                let queue_size = self.queue_sizes.iter().copied().max().unwrap_or(0);
+                let (info, file) = backend_client
+                    .get_inflight_fd(&VhostUserInflight::new(
+                        0,
+                        0,
+                        self.queue_sizes.len() as u16,
+                        queue_size,
+                    ))
+                    .map_err(Error::GetInflightFd)?;
+                let inflight = InflightFd {
+                    info,
+                    file: Arc::new(file),
+                };
+                self.inflight = Some(inflight.clone());
+                inflight
+            }
+        };
+        backend_client
+            .set_inflight_fd(&inflight.info, inflight.file.as_raw_descriptor())
+            .map_err(Error::SetInflightFd)?;
+        Ok(Some(inflight))
+    }
+
+    /// Tells the backend that a postcopy migration is about to start. Returns the userfaultfd on
+    /// which the caller must resolve the backend's faults on guest memory until `postcopy_end`.
+    pub fn postcopy_advise(&self) -> Result<File> {
+        self.require_protocol_feature(VhostUserProtocolFeatures::PAGEFAULT)?;
         self.backend_client
             .lock()
-            .set_mem_table(regions.as_slice())
-            .map_err(Error::SetMemTable)?;
+            .postcopy_advise()
+            .map_err(Error::PostcopyAdvise)
+    }
 
-        Ok(())
+    /// Switches the backend to postcopy mode, in which accesses to guest memory that has not
+    /// arrived yet fault on the userfaultfd from `postcopy_advise`.
+    ///
+    /// Returns the address at which the backend mapped each region of `mem`, in the order of
+    /// `GuestMemory::regions`. Faults are reported at, and must be resolved for, those addresses.
+    pub fn postcopy_listen(&self, mem: &GuestMemory) -> Result<Vec<u64>> {
+        self.require_protocol_feature(VhostUserProtocolFeatures::PAGEFAULT)?;
+        let backend_client = self.backend_client.lock();
+        backend_client
+            .postcopy_listen()
+            .map_err(Error::PostcopyListen)?;
+        backend_client
+            .set_mem_table_postcopy(&mem_table_regions(mem))
+            .map_err(Error::SetMemTable)
+    }
+
+    /// Ends the postcopy migration once all of guest memory has arrived.
+    pub fn postcopy_end(&self) -> Result<()> {
+        self.require_protocol_feature(VhostUserProtocolFeatures::PAGEFAULT)?;
+        self.backend_client
+            .lock()
+            .postcopy_end()
+            .map_err(Error::PostcopyEnd)
+    }
+
+    fn require_protocol_feature(&self, feature: VhostUserProtocolFeatures) -> Result<()> {
+        if self.protocol_features.contains(feature) {
+            Ok(())
+        } else {
+            Err(Error::ProtocolFeatureNotNegoiated(feature))
+        }
     }
 
     /// Activates a vring for the given `queue`.
@@ -281,50 +437,24 @@ impl VhostUserFrontend {
         queue_index: usize,
         queue: &Queue,
         irqfd: &Event,
-    ) -> Result<()> {
-        let backend_client = self.backend_client.lock();
-        backend_client
-            .set_vring_num(queue_index, queue.size())
-            .map_err(Error::SetVringNum)?;
-
-        let config_data = VringConfigData {
-            queue_size: queue.size(),
-            flags: 0u32,
-            desc_table_addr: mem
-                .get_host_address(queue.desc_table())
-                .map_err(Error::GetHostAddress)? as u64,
-            used_ring_addr: mem
-                .get_host_address(queue.used_ring())
-                .map_err(Error::GetHostAddress)? as u64,
-            avail_ring_addr: mem
-                .get_host_address(queue.avail_ring())
-                .map_err(Error::GetHostAddress)? as u64,
-            log_addr: None,
+    ) -> Result<VringState> {
+        let vring = VringState {
+            queue_index,
+            size: queue.size(),
+            desc_table: queue.desc_table(),
+            avail_ring: queue.avail_ring(),
+            used_ring: queue.used_ring(),
+            call_evt: irqfd.try_clone().map_err(Error::CloneEvent)?,
+            kick_evt: queue.event().try_clone().map_err(Error::CloneEvent)?,
         };
-        backend_client
-            .set_vring_addr(queue_index, &config_data)
-            .map_err(Error::SetVringAddr)?;
-
-        backend_client
-            .set_vring_base(queue_index, queue.next_avail_to_process())
-            .map_err(Error::SetVringBase)?;
-
-        backend_client
-            .set_vring_call(queue_index, irqfd)
-            .map_err(Error::SetVringCall)?;
-        backend_client
-            .set_vring_kick(queue_index, queue.event())
-            .map_err(Error::SetVringKick)?;
-
-        // Per protocol documentation, `VHOST_USER_SET_VRING_ENABLE` should be sent only when
-        // `VHOST_USER_F_PROTOCOL_FEATURES` has been negotiated.
-        if self.acked_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES != 0 {
-            backend_client
-                .set_vring_enable(queue_index, true)
-                .map_err(Error::SetVringEnable)?;
-        }
-
-        Ok(())
+        configure_vring(
+            &self.backend_client.lock(),
+            self.acked_features,
+            mem,
+            &vring,
+            queue.next_avail_to_process(),
+        )?;
+        Ok(vring)
     }
 
     /// Stops the vring for the given `queue`, returning its base index.
@@ -348,7 +478,12 @@ impl VhostUserFrontend {
 
     /// Helper to start up the worker thread that will be used with handling interrupts and requests
     /// from the device process.
-    fn start_worker(&mut self, interrupt: Interrupt, non_msix_evt: Event) {
+    fn start_worker(
+        &mut self,
+        interrupt: Interrupt,
+        non_msix_evt: Event,
+        reconnect: Option<ReconnectState>,
+    ) {
         assert!(
             self.worker_thread.is_none(),
             "BUG: attempted to start worker twice"
@@ -370,6 +505,7 @@ impl VhostUserFrontend {
                 non_msix_evt,
                 backend_req_handler,
                 backend_client,
+                reconnect,
             };
             worker
                 .run(interrupt)
@@ -470,6 +606,7 @@ impl VirtioDevice for VhostUserFrontend {
         queues: BTreeMap<usize, Queue>,
     ) -> anyhow::Result<()> {
         self.set_mem_table(&mem)?;
+        let inflight = self.set_inflight_fd()?;
 
         let msix_config_opt = interrupt
             .get_msix_config()
@@ -478,18 +615,27 @@ impl VirtioDevice for VhostUserFrontend {
         let msix_config = msix_config_opt.lock();
 
         let non_msix_evt = Event::new().map_err(Error::CreateEvent)?;
+        let mut vrings = Vec::with_capacity(queues.len());
         for (&queue_index, queue) in queues.iter() {
             let irqfd = msix_config
                 .get_irqfd(queue.vector() as usize)
                 .unwrap_or(&non_msix_evt);
-            self.activate_vring(&mem, queue_index, queue, irqfd)?;
+            vrings.push(self.activate_vring(&mem, queue_index, queue, irqfd)?);
         }
 
         self.sent_queues = Some(queues);
 
         drop(msix_config);
 
-        self.start_worker(interrupt, non_msix_evt);
+        let reconnect = self.reconnect.clone().map(|connect| ReconnectState {
+            connect,
+            mem,
+            acked_features: self.acked_features,
+            protocol_features: self.protocol_features,
+            inflight,
+            vrings,
+        });
+        self.start_worker(interrupt, non_msix_evt, reconnect);
         Ok(())
     }
 
@@ -506,6 +652,9 @@ impl VirtioDevice for VhostUserFrontend {
             self.backend_req_handler = w.stop();
         }
 
+        // The guest starts over with empty rings, so nothing is in flight anymore.
+        self.inflight = None;
+
         Ok(())
     }
 
@@ -513,14 +662,14 @@ impl VirtioDevice for VhostUserFrontend {
         self.pci_address
     }
 
-    fn get_shared_memory_region(&self) -> Option<SharedMemoryRegion> {
+    fn get_shared_memory_regions(&self) -> Vec<SharedMemoryRegion> {
         if !self
             .protocol_features
             .contains(VhostUserProtocolFeatures::SHARED_MEMORY_REGIONS)
         {
-            return None;
+            return Vec::new();
         }
-        if let Some(r) = self.shmem_region.borrow().as_ref() {
+        if let Some(r) = self.shmem_regions.borrow().as_ref() {
             return r.clone();
         }
         let regions = match self
@@ -532,29 +681,22 @@ impl VirtioDevice for VhostUserFrontend {
             Ok(x) => x,
             Err(e) => {
                 error!("Failed to get shared memory regions {}", e);
-                return None;
-            }
-        };
-        let region = match regions.len() {
-            0 => None,
-            1 => Some(SharedMemoryRegion {
-                id: regions[0].id,
-                length: regions[0].length,
-            }),
-            n => {
-                error!(
-                    "Failed to get shared memory regions {}",
-                    Error::TooManyShmemRegions(n)
-                );
-                return None;
+                return Vec::new();
             }
         };
+        let regions: Vec<SharedMemoryRegion> = regions
+            .iter()
+            .map(|r| SharedMemoryRegion {
+                id: r.id,
+                length: r.length,
+            })
+            .collect();
 
-        *self.shmem_region.borrow_mut() = Some(region.clone());
-        region
+        *self.shmem_regions.borrow_mut() = Some(regions.clone());
+        regions
     }
 
-    fn set_shared_memory_mapper(&mut self, mapper: Box<dyn SharedMemoryMapper>) {
+    fn set_shared_memory_mappers(&mut self, mappers: BTreeMap<u8, Box<dyn SharedMemoryMapper>>) {
         // Return error if backend request handler is not available. This indicates
         // that `VhostUserProtocolFeatures::BACKEND_REQ` is not negotiated.
         let Some(backend_req_handler) = self.backend_req_handler.as_mut() else {
@@ -565,18 +707,9 @@ impl VirtioDevice for VhostUserFrontend {
             return;
         };
 
-        // The virtio framework will only call this if get_shared_memory_region returned a region
-        let shmid = self
-            .shmem_region
-            .borrow()
-            .clone()
-            .flatten()
-            .expect("missing shmid")
-            .id;
-
         backend_req_handler
             .frontend_mut()
-            .set_shared_mapper_state(mapper, shmid);
+            .set_shared_mappers(mappers);
     }
 
     fn expose_shmem_descriptors_with_viommu(&self) -> bool {
@@ -692,6 +825,77 @@ impl VirtioDevice for VhostUserFrontend {
     }
 }
 
+fn mem_table_regions(mem: &GuestMemory) -> Vec<VhostUserMemoryRegionInfo> {
+    mem.regions()
+        .map(|region| VhostUserMemoryRegionInfo {
+            guest_phys_addr: region.guest_addr.0,
+            memory_size: region.size as u64,
+            userspace_addr: region.host_addr as u64,
+            mmap_offset: region.shm_offset,
+            mmap_handle: region.shm.as_raw_descriptor(),
+        })
+        .collect()
+}
+
+pub(crate) fn set_mem_table(backend_client: &BackendClient, mem: &GuestMemory) -> Result<()> {
+    backend_client
+        .set_mem_table(&mem_table_regions(mem))
+        .map_err(Error::SetMemTable)
+}
+
+/// Hands `vring` to the backend, starting at avail ring index `base`.
+pub(crate) fn configure_vring(
+    backend_client: &BackendClient,
+    acked_features: u64,
+    mem: &GuestMemory,
+    vring: &VringState,
+    base: u16,
+) -> Result<()> {
+    let queue_index = vring.queue_index;
+    backend_client
+        .set_vring_num(queue_index, vring.size)
+        .map_err(Error::SetVringNum)?;
+
+    let config_data = VringConfigData {
+        queue_size: vring.size,
+        flags: 0u32,
+        desc_table_addr: mem
+            .get_host_address(vring.desc_table)
+            .map_err(Error::GetHostAddress)? as u64,
+        used_ring_addr: mem
+            .get_host_address(vring.used_ring)
+            .map_err(Error::GetHostAddress)? as u64,
+        avail_ring_addr: mem
+            .get_host_address(vring.avail_ring)
+            .map_err(Error::GetHostAddress)? as u64,
+        log_addr: None,
+    };
+    backend_client
+        .set_vring_addr(queue_index, &config_data)
+        .map_err(Error::SetVringAddr)?;
+
+    backend_client
+        .set_vring_base(queue_index, base)
+        .map_err(Error::SetVringBase)?;
+
+    backend_client
+        .set_vring_call(queue_index, &vring.call_evt)
+        .map_err(Error::SetVringCall)?;
+    backend_client
+        .set_vring_kick(queue_index, &vring.kick_evt)
+        .map_err(Error::SetVringKick)?;
+
+    // Per protocol documentation, `VHOST_USER_SET_VRING_ENABLE` should be sent only when
+    // `VHOST_USER_F_PROTOCOL_FEATURES` has been negotiated.
+    if acked_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES != 0 {
+        backend_client
+            .set_vring_enable(queue_index, true)
+            .map_err(Error::SetVringEnable)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(unix)]
 fn new_pipe_pair() -> anyhow::Result<(impl AsRawDescriptor + Read, impl AsRawDescriptor + Write)> {
     base::pipe().context("failed to create pipe")
@@ -3,83 +3,69 @@
 // found in the LICENSE file.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::bail;
 use anyhow::Context;
+use base::debug;
 use base::info;
 use base::warn;
+use base::AsRawDescriptor;
 #[cfg(windows)]
 use base::CloseNotifier;
 use base::Event;
 use base::EventToken;
 use base::EventType;
+use base::EventWaitResult;
 use base::ReadNotifier;
 use base::WaitContext;
 use sync::Mutex;
+use vmm_vhost::message::VhostUserProtocolFeatures;
 use vmm_vhost::BackendClient;
 use vmm_vhost::Error as VhostError;
+use vmm_vhost::VHOST_USER_F_PROTOCOL_FEATURES;
 
+use crate::virtio::vhost_user_frontend::configure_vring;
+use crate::virtio::vhost_user_frontend::error::Error;
 use crate::virtio::vhost_user_frontend::handler::BackendReqHandler;
+use crate::virtio::vhost_user_frontend::handler::BackendReqHandlerImpl;
+use crate::virtio::vhost_user_frontend::set_mem_table;
+use crate::virtio::vhost_user_frontend::sys::create_backend_req_handler;
+use crate::virtio::vhost_user_frontend::ReconnectState;
 use crate::virtio::Interrupt;
 use crate::virtio::VIRTIO_MSI_NO_VECTOR;
 
+// How long to wait between attempts to reach a backend that went away.
+const RECONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(EventToken)]
+enum Token {
+    Kill,
+    NonMsixEvt,
+    ReqHandlerRead,
+    #[cfg(target_os = "windows")]
+    ReqHandlerClose,
+    // monitor whether backend_client_fd is broken
+    BackendCloseNotify,
+}
+
 pub struct Worker {
     pub kill_evt: Event,
     pub non_msix_evt: Event,
     pub backend_req_handler: Option<BackendReqHandler>,
     pub backend_client: Arc<Mutex<BackendClient>>,
+    pub reconnect: Option<ReconnectState>,
 }
 
 impl Worker {
     pub fn run(&mut self, interrupt: Interrupt) -> anyhow::Result<()> {
-        #[derive(EventToken)]
-        enum Token {
-            Kill,
-            NonMsixEvt,
-            ReqHandlerRead,
-            #[cfg(target_os = "windows")]
-            ReqHandlerClose,
-            // monitor whether backend_client_fd is broken
-            BackendCloseNotify,
-        }
         let wait_ctx = WaitContext::build_with(&[
             (&self.non_msix_evt, Token::NonMsixEvt),
             (&self.kill_evt, Token::Kill),
         ])
         .context("failed to build WaitContext")?;
 
-        if let Some(backend_req_handler) = self.backend_req_handler.as_mut() {
-            wait_ctx
-                .add(
-                    backend_req_handler.get_read_notifier(),
-                    Token::ReqHandlerRead,
-                )
-                .context("failed to add backend req handler to WaitContext")?;
-
-            #[cfg(target_os = "windows")]
-            wait_ctx
-                .add(
-                    backend_req_handler.get_close_notifier(),
-                    Token::ReqHandlerClose,
-                )
-                .context("failed to add backend req handler close notifier to WaitContext")?;
-        }
-
-        #[cfg(any(target_os = "android", target_os = "linux"))]
-        wait_ctx
-            .add_for_event(
-                self.backend_client.lock().get_read_notifier(),
-                EventType::None,
-                Token::BackendCloseNotify,
-            )
-            .context("failed to add backend client close notifier to WaitContext")?;
-        #[cfg(target_os = "windows")]
-        wait_ctx
-            .add(
-                self.backend_client.lock().get_close_notifier(),
-                Token::BackendCloseNotify,
-            )
-            .context("failed to add backend client close notifier to WaitContext")?;
+        self.watch_backend(&wait_ctx)?;
 
         'wait: loop {
             let events = wait_ctx.wait().context("WaitContext::wait() failed")?;
@@ -137,7 +123,17 @@ impl Worker {
                             warn!("event besides hungup should not be notified");
                             continue;
                         }
-                        bail!("Backend device disconnected early");
+                        if self.reconnect.is_none() {
+                            bail!("Backend device disconnected early");
+                        }
+
+                        warn!("vhost-user backend disconnected, waiting for it to come back");
+                        self.unwatch_backend(&wait_ctx);
+                        if !self.reconnect(&interrupt)? {
+                            break 'wait;
+                        }
+                        self.watch_backend(&wait_ctx)?;
+                        info!("vhost-user backend reconnected");
                     }
                 }
             }
@@ -145,4 +141,160 @@ impl Worker {
 
         Ok(())
     }
+
+    /// Adds the backend connection and request handler to `wait_ctx`.
+    fn watch_backend(&self, wait_ctx: &WaitContext<Token>) -> anyhow::Result<()> {
+        if let Some(backend_req_handler) = self.backend_req_handler.as_ref() {
+            wait_ctx
+                .add(
+                    backend_req_handler.get_read_notifier(),
+                    Token::ReqHandlerRead,
+                )
+                .context("failed to add backend req handler to WaitContext")?;
+
+            #[cfg(target_os = "windows")]
+            wait_ctx
+                .add(
+                    backend_req_handler.get_close_notifier(),
+                    Token::ReqHandlerClose,
+                )
+                .context("failed to add backend req handler close notifier to WaitContext")?;
+        }
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        wait_ctx
+            .add_for_event(
+                self.backend_client.lock().get_read_notifier(),
+                EventType::None,
+                Token::BackendCloseNotify,
+            )
+            .context("failed to add backend client close notifier to WaitContext")?;
+        #[cfg(target_os = "windows")]
+        wait_ctx
+            .add(
+                self.backend_client.lock().get_close_notifier(),
+                Token::BackendCloseNotify,
+            )
+            .context("failed to add backend client close notifier to WaitContext")?;
+
+        Ok(())
+    }
+
+    /// Removes the (dead) backend connection and request handler from `wait_ctx`.
+    fn unwatch_backend(&mut self, wait_ctx: &WaitContext<Token>) {
+        if let Some(backend_req_handler) = self.backend_req_handler.take() {
+            let _ = wait_ctx.delete(backend_req_handler.get_read_notifier());
+            #[cfg(target_os = "windows")]
+            let _ = wait_ctx.delete(backend_req_handler.get_close_notifier());
+        }
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let _ = wait_ctx.delete(self.backend_client.lock().get_read_notifier());
+        #[cfg(target_os = "windows")]
+        let _ = wait_ctx.delete(self.backend_client.lock().get_close_notifier());
+    }
+
+    /// Waits until a new backend can be reached and brings it to the state of the previous one.
+    ///
+    /// Descriptors the old backend had taken from the avail ring but not yet returned are handed
+    /// out again, since the new backend resumes from the used ring index in guest memory.
+    ///
+    /// Returns `false` if the worker was asked to stop before a backend came back.
+    fn reconnect(&mut self, interrupt: &Interrupt) -> anyhow::Result<bool> {
+        let state = self
+            .reconnect
+            .as_ref()
+            .expect("BUG: reconnect called without reconnect state");
+
+        let connection = loop {
+            match (state.connect)() {
+                Ok(connection) => break connection,
+                Err(e) => debug!("vhost-user backend not available yet: {:#}", e),
+            }
+            match self
+                .kill_evt
+                .wait_timeout(RECONNECT_RETRY_INTERVAL)
+                .context("failed to wait on kill event")?
+            {
+                EventWaitResult::Signaled => return Ok(false),
+                EventWaitResult::TimedOut => (),
+            }
+        };
+
+        #[cfg(windows)]
+        let backend_pid = connection.target_pid();
+
+        let mut backend_client = BackendClient::new(connection);
+        backend_client.set_owner().map_err(Error::SetOwner)?;
+
+        let avail_features = backend_client.get_features().map_err(Error::GetFeatures)?;
+        if avail_features & state.acked_features != state.acked_features {
+            bail!(
+                "restarted backend lacks features: 0x{:x}",
+                state.acked_features & !avail_features
+            );
+        }
+        backend_client
+            .set_features(state.acked_features)
+            .map_err(Error::SetFeatures)?;
+
+        if state.acked_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES != 0 {
+            let avail_protocol_features = backend_client
+                .get_protocol_features()
+                .map_err(Error::GetProtocolFeatures)?;
+            if !avail_protocol_features.contains(state.protocol_features) {
+                bail!(
+                    "restarted backend lacks protocol features: {:?}",
+                    state.protocol_features - avail_protocol_features
+                );
+            }
+            backend_client
+                .set_protocol_features(state.protocol_features)
+                .map_err(Error::SetProtocolFeatures)?;
+        }
+
+        if state
+            .protocol_features
+            .contains(VhostUserProtocolFeatures::BACKEND_REQ)
+        {
+            let mut handler_impl = BackendReqHandlerImpl::new();
+            handler_impl.set_interrupt(interrupt.clone());
+            let (handler, tx_fd) = create_backend_req_handler(
+                handler_impl,
+                #[cfg(windows)]
+                backend_pid,
+            )?;
+            backend_client
+                .set_backend_req_fd(&tx_fd)
+                .map_err(Error::SetDeviceRequestChannel)?;
+            self.backend_req_handler = Some(handler);
+        }
+
+        set_mem_table(&backend_client, &state.mem)?;
+
+        if let Some(inflight) = &state.inflight {
+            backend_client
+                .set_inflight_fd(&inflight.info, inflight.file.as_raw_descriptor())
+                .map_err(Error::SetInflightFd)?;
+        }
+
+        for vring in &state.vrings {
+            // The used index lives right after the 16-bit flags field of the used ring.
+            let used_idx: u16 = state
+                .mem
+                .read_obj_from_addr_volatile(vring.used_ring.unchecked_add(2))
+                .map_err(Error::ReadUsedIndex)?;
+            configure_vring(
+                &backend_client,
+                state.acked_features,
+                &state.mem,
+                vring,
+                used_idx,
+            )?;
+        }
+
+        *self.backend_client.lock() = backend_client;
+
+        Ok(true)
+    }
 }
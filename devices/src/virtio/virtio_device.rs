@@ -11,6 +11,8 @@ use anyhow::Result;
 use base::Protection;
 use base::RawDescriptor;
 use hypervisor::MemCacheType;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
 use resources::AddressRange;
 use snapshot::AnySnapshot;
 use vm_control::VmMemorySource;
@@ -158,6 +160,20 @@ pub trait VirtioDevice: Send {
     /// Invoked when the device is sandboxed.
     fn on_device_sandboxed(&mut self) {}
 
+    /// Returns the paths the device opens once sandboxed, used to confine the device process with
+    /// Landlock. `None` leaves the process unconfined, while an empty list denies opening anything.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        None
+    }
+
+    /// Returns whether the device can run in a child process of its own even when it is not
+    /// sandboxed. Only devices that are sandboxed in a child process by default, and so only use
+    /// the descriptors listed by `keep_rds`, should return true.
+    fn supports_child_process(&self) -> bool {
+        false
+    }
+
     fn control_notify(&self, _behavior: MsixStatus) {}
 
     #[cfg(target_arch = "x86_64")]
@@ -182,6 +198,13 @@ pub trait VirtioDevice: Send {
         None
     }
 
+    /// Returns all of the device's shared memory regions, each with a unique id.
+    ///
+    /// Devices with a single region only need to implement `get_shared_memory_region`.
+    fn get_shared_memory_regions(&self) -> Vec<SharedMemoryRegion> {
+        self.get_shared_memory_region().into_iter().collect()
+    }
+
     /// If true, VFIO passthrough devices can access descriptors mapped into
     /// this region by mapping the corresponding addresses from this device's
     /// PCI bar into their IO address space with virtio-iommu.
@@ -199,12 +222,32 @@ pub trait VirtioDevice: Send {
     /// before `activate`.
     fn set_shared_memory_mapper(&mut self, _mapper: Box<dyn SharedMemoryMapper>) {}
 
+    /// Provides one mapper per region returned by `get_shared_memory_regions`, keyed by region
+    /// id. Offsets given to each mapper are relative to the start of its region.
+    ///
+    /// By default, the mapper of the first region is passed to `set_shared_memory_mapper`.
+    fn set_shared_memory_mappers(&mut self, mappers: BTreeMap<u8, Box<dyn SharedMemoryMapper>>) {
+        if let Some(mapper) = mappers.into_values().next() {
+            self.set_shared_memory_mapper(mapper);
+        }
+    }
+
     /// Provides the guest address range of the shared memory region, if one is present. Will
     /// be called before `activate`.
     fn set_shared_memory_region(&mut self, shmem_region: AddressRange) {
         let _ = shmem_region;
     }
 
+    /// Provides the guest address range of each region returned by `get_shared_memory_regions`,
+    /// keyed by region id. Will be called before `activate`.
+    ///
+    /// By default, the range of the first region is passed to `set_shared_memory_region`.
+    fn set_shared_memory_regions(&mut self, shmem_regions: BTreeMap<u8, AddressRange>) {
+        if let Some(range) = shmem_regions.into_values().next() {
+            self.set_shared_memory_region(range);
+        }
+    }
+
     /// Queries the implementation whether a single prepared hypervisor memory mapping with explicit
     /// caching type should be setup lazily on first mapping request, or whether to dynamically
     /// setup a hypervisor mapping with every request's caching type.
@@ -11,11 +11,14 @@ use anyhow::Context;
 use base::error;
 use base::pagesize;
 use base::warn;
+use base::AsRawDescriptor;
 use base::AsRawDescriptors;
 use base::Event;
 use base::RawDescriptor;
 use base::Result;
 use hypervisor::Datamatch;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
 use resources::AllocOptions;
 use resources::SystemAllocator;
 use virtio_sys::virtio_config::VIRTIO_CONFIG_S_ACKNOWLEDGE;
@@ -98,6 +101,10 @@ impl VirtioMmioDevice {
             config_generation: 0,
         })
     }
+
+    /// Returns the events to register as ioeventfds with the VM, one per queue matching the
+    /// writes of its index to the notification register, so that queue notifications don't exit
+    /// to userspace. `allocate_regions` has to be called first.
     pub fn ioevents(&self) -> Vec<(&Event, u64, Datamatch)> {
         self.queue_evts
             .iter()
@@ -126,7 +133,7 @@ impl VirtioMmioDevice {
     }
 
     fn device_type(&self) -> u32 {
-        self.device.device_type() as u32
+        self.device.device_type().virtio_id()
     }
 
     /// Activates the underlying `VirtioDevice`. `assign_irq` has to be called first.
@@ -388,6 +395,7 @@ impl VirtioMmioDevice {
         if let Some(interrupt_evt) = &self.interrupt_evt {
             rds.extend(interrupt_evt.as_raw_descriptors());
         }
+        rds.extend(self.queue_evts.iter().map(|evt| evt.as_raw_descriptor()));
         rds
     }
 
@@ -420,7 +428,17 @@ impl Aml for VirtioMmioDevice {
     }
 }
 
-impl BusDeviceObj for VirtioMmioDevice {}
+impl BusDeviceObj for VirtioMmioDevice {
+    fn as_virtio_mmio_device(&self) -> Option<&VirtioMmioDevice> {
+        Some(self)
+    }
+    fn as_virtio_mmio_device_mut(&mut self) -> Option<&mut VirtioMmioDevice> {
+        Some(self)
+    }
+    fn into_virtio_mmio_device(self: Box<Self>) -> Option<Box<VirtioMmioDevice>> {
+        Some(self)
+    }
+}
 
 impl BusDevice for VirtioMmioDevice {
     fn debug_label(&self) -> String {
@@ -442,6 +460,11 @@ impl BusDevice for VirtioMmioDevice {
     fn on_sandboxed(&mut self) {
         self.on_device_sandboxed();
     }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        self.device.landlock_rules()
+    }
 }
 
 // TODO: Mimic the Suspendable impl in ViritoPciDevice when/if someone wants it.
@@ -7,10 +7,17 @@ use std::convert::TryInto;
 use base::warn;
 use serde::Deserialize;
 use serde::Serialize;
+use virtio_sys::virtio_config::VIRTIO_F_NOTIFICATION_DATA;
 use vm_memory::GuestAddress;
 
 use super::*;
 
+/// Features implemented by the PCI transport for every device, and hidden from the devices.
+///
+/// Each queue has its own notification address and ioevent, so the notification data the driver
+/// writes with `VIRTIO_F_NOTIFICATION_DATA` doesn't need to be decoded.
+const TRANSPORT_FEATURES: u64 = 1 << VIRTIO_F_NOTIFICATION_DATA;
+
 /// Contains the data for reading and writing the common configuration structure of a virtio PCI
 /// device.
 ///
@@ -156,7 +163,8 @@ impl VirtioPciCommonConfig {
                 // Only 64 bits of features (2 pages) are defined for now, so limit
                 // device_feature_select to avoid shifting by 64 or more bits.
                 if self.device_feature_select < 2 {
-                    (device.features() >> (self.device_feature_select * 32)) as u32
+                    ((device.features() | TRANSPORT_FEATURES) >> (self.device_feature_select * 32))
+                        as u32
                 } else {
                     0
                 }
@@ -190,6 +198,7 @@ impl VirtioPciCommonConfig {
             0x0c => {
                 if self.driver_feature_select < 2 {
                     let features: u64 = (value as u64) << (self.driver_feature_select * 32);
+                    let features = features & !TRANSPORT_FEATURES;
                     device.ack_features(features);
                     for queue in queues.iter_mut() {
                         queue.ack_features(features);
@@ -327,4 +336,41 @@ mod tests {
         assert_eq!(read_back[0], 0xaa);
         assert_eq!(read_back[1], 0x55);
     }
+
+    #[test]
+    fn notification_data_feature() {
+        let mut regs = VirtioPciCommonConfig {
+            driver_status: 0,
+            config_generation: 0,
+            device_feature_select: 1,
+            driver_feature_select: 1,
+            queue_select: 0,
+            msix_config: 0,
+        };
+
+        let dev = &mut DummyDevice(DeviceType::Net) as &mut dyn VirtioDevice;
+        let mut queues = vec![QueueConfig::new(QUEUE_SIZE, u64::MAX)];
+
+        // The transport offers VIRTIO_F_NOTIFICATION_DATA on top of the device features.
+        let mut read_back = [0u8; 4];
+        regs.read(0x04, &mut read_back, &mut queues, dev);
+        assert_eq!(
+            u32::from_le_bytes(read_back),
+            1 << (VIRTIO_F_NOTIFICATION_DATA - 32)
+        );
+
+        // ...and doesn't pass it down once the driver accepts it.
+        regs.write(
+            0x0c,
+            &(1u32 << (VIRTIO_F_NOTIFICATION_DATA - 32)).to_le_bytes(),
+            &mut queues,
+            dev,
+        );
+        assert_eq!(queues[0].acked_features(), 0);
+    }
+
+    #[test]
+    fn child_process_is_opt_in() {
+        assert!(!DummyDevice(DeviceType::Net).supports_child_process());
+    }
 }
@@ -24,6 +24,8 @@ use base::WorkerThread;
 use data_model::Le32;
 use hypervisor::Datamatch;
 use hypervisor::MemCacheType;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
 use libc::ERANGE;
 #[cfg(target_arch = "x86_64")]
 use metrics::MetricEventType;
@@ -43,6 +45,7 @@ use virtio_sys::virtio_config::VIRTIO_CONFIG_S_FEATURES_OK;
 use virtio_sys::virtio_config::VIRTIO_CONFIG_S_NEEDS_RESET;
 use virtio_sys::virtio_config::VIRTIO_CONFIG_S_SUSPEND;
 use vm_control::api::VmMemoryClient;
+use vm_control::DeviceDetails;
 use vm_control::VmMemoryDestination;
 use vm_control::VmMemoryRegionId;
 use vm_control::VmMemorySource;
@@ -85,6 +88,7 @@ use crate::pci::PciInterruptPin;
 use crate::pci::PciMassStorageSubclass;
 use crate::pci::PciMultimediaSubclass;
 use crate::pci::PciNetworkControllerSubclass;
+use crate::pci::PciOtherSubclass;
 use crate::pci::PciSimpleCommunicationControllerSubclass;
 use crate::pci::PciSubclass;
 use crate::pci::PciWirelessControllerSubclass;
@@ -362,7 +366,7 @@ impl VirtioPciDevice {
     ) -> Result<Self> {
         // shared_memory_vm_memory_client is required if there are shared memory regions.
         assert_eq!(
-            device.get_shared_memory_region().is_none(),
+            device.get_shared_memory_regions().is_empty(),
             shared_memory_vm_memory_client.is_none()
         );
 
@@ -379,7 +383,7 @@ impl VirtioPciDevice {
             .map(|&s| QueueConfig::new(s, device.features()))
             .collect();
 
-        let pci_device_id = VIRTIO_PCI_DEVICE_ID_BASE + device.device_type() as u16;
+        let pci_device_id = VIRTIO_PCI_DEVICE_ID_BASE + device.device_type().virtio_id() as u16;
 
         let (pci_device_class, pci_device_subclass) = match device.device_type() {
             DeviceType::Net => (
@@ -470,6 +474,18 @@ impl VirtioPciDevice {
                 PciClassCode::BaseSystemPeripheral,
                 &PciBaseSystemPeripheralSubclass::Other as &dyn PciSubclass,
             ),
+            DeviceType::Can => (
+                PciClassCode::NetworkController,
+                &PciNetworkControllerSubclass::Other as &dyn PciSubclass,
+            ),
+            DeviceType::Spi => (
+                PciClassCode::BaseSystemPeripheral,
+                &PciBaseSystemPeripheralSubclass::Other as &dyn PciSubclass,
+            ),
+            DeviceType::Custom(_) => (
+                PciClassCode::Other,
+                &PciOtherSubclass::Other as &dyn PciSubclass,
+            ),
         };
 
         let num_interrupts = device.num_interrupts();
@@ -631,7 +647,7 @@ impl VirtioPciDevice {
             Some((
                 PmWakeupEvent::new(self.vm_control_tube.clone(), self.pm_config.clone()),
                 MetricEventType::VirtioWakeup {
-                    virtio_id: self.device.device_type() as u32,
+                    virtio_id: self.device.device_type().virtio_id(),
                 },
             )),
         );
@@ -722,6 +738,17 @@ impl PciDevice for VirtioPciDevice {
         format!("pci{}", self.device.debug_label())
     }
 
+    fn device_details(&self) -> DeviceDetails {
+        DeviceDetails {
+            pci_address: self.pci_address,
+            queue_sizes: self.queues.iter().map(|queue| queue.size()).collect(),
+            resources: Vec::new(),
+            // One irqfd per MSI-X vector and one ioeventfd per queue notification.
+            irqfds: self.msix_config.lock().num_vectors() as usize,
+            ioeventfds: self.queue_evts.len(),
+        }
+    }
+
     fn preferred_address(&self) -> Option<PciAddress> {
         self.preferred_address
     }
@@ -822,11 +849,13 @@ impl PciDevice for VirtioPciDevice {
 
     fn register_device_capabilities(&mut self) -> std::result::Result<(), PciDeviceError> {
         let mut caps = self.device.get_device_caps();
-        if let Some(region) = self.device.get_shared_memory_region() {
+        let regions = self.device.get_shared_memory_regions();
+        let (offsets, _) = shared_memory_layout(&regions);
+        for (region, offset) in regions.iter().zip(offsets) {
             caps.push(Box::new(VirtioPciShmCap::new(
                 PciCapabilityType::SharedMemoryConfig,
                 SHMEM_BAR_NUM as u8,
-                0,
+                offset,
                 region.length,
                 region.id,
             )));
@@ -997,6 +1026,11 @@ impl PciDevice for VirtioPciDevice {
         self.device.on_device_sandboxed();
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        self.device.landlock_rules()
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn generate_acpi(&mut self, sdts: &mut Vec<SDT>) -> anyhow::Result<()> {
         self.device.generate_acpi(
@@ -1024,6 +1058,7 @@ where
     let settings_config_addr = alloc_fn(
         CAPABILITY_BAR_SIZE,
         Alloc::PciBar {
+            domain: address.domain,
             bus: address.bus,
             dev: address.dev,
             func: address.func,
@@ -1070,21 +1105,20 @@ where
     let configs = if !configs.is_empty() {
         configs
     } else {
-        let region = match virtio_pci_device.device.get_shared_memory_region() {
-            None => return Ok(Vec::new()),
-            Some(r) => r,
-        };
+        let regions = virtio_pci_device.device.get_shared_memory_regions();
+        if regions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (offsets, bar_size) = shared_memory_layout(&regions);
         let config = PciBarConfiguration::new(
             SHMEM_BAR_NUM,
-            region
-                .length
-                .checked_next_power_of_two()
-                .expect("bar too large"),
+            bar_size,
             PciBarRegionType::Memory64BitRegion,
             PciBarPrefetchable::Prefetchable,
         );
 
         let alloc = Alloc::PciBar {
+            domain: address.domain,
             bus: address.bus,
             dev: address.dev,
             func: address.func,
@@ -1106,10 +1140,25 @@ where
             SharedMemoryPrepareType::DynamicPerMapping
         };
 
-        let vm_requester = Box::new(VmRequester::new(vm_memory_client, alloc, prepare_type));
-        virtio_pci_device
-            .device
-            .set_shared_memory_mapper(vm_requester);
+        // All regions share the BAR, so they also share the requester that maps into it.
+        let vm_requester = Arc::new(Mutex::new(VmRequester::new(
+            vm_memory_client,
+            alloc,
+            prepare_type,
+        )));
+        let mappers = regions
+            .iter()
+            .zip(&offsets)
+            .map(|(region, &offset)| {
+                let mapper: Box<dyn SharedMemoryMapper> = Box::new(SharedMemoryRegionMapper {
+                    vm_requester: vm_requester.clone(),
+                    offset,
+                    length: region.length,
+                });
+                (region.id, mapper)
+            })
+            .collect();
+        virtio_pci_device.device.set_shared_memory_mappers(mappers);
 
         vec![config]
     };
@@ -1118,6 +1167,7 @@ where
         let device_addr = alloc_fn(
             config.size(),
             Alloc::PciBar {
+                domain: address.domain,
                 bus: address.bus,
                 dev: address.dev,
                 func: address.func,
@@ -1139,21 +1189,56 @@ where
         });
     }
 
-    if virtio_pci_device
-        .device
-        .get_shared_memory_region()
-        .is_some()
-    {
-        let shmem_region = AddressRange::from_start_and_size(ranges[0].addr, ranges[0].size)
-            .expect("invalid shmem region");
+    let regions = virtio_pci_device.device.get_shared_memory_regions();
+    if !regions.is_empty() {
+        let (offsets, _) = shared_memory_layout(&regions);
+        let shmem_regions = if let [region] = &regions[..] {
+            // A single region is given the whole BAR, including the padding at its end.
+            let range = AddressRange::from_start_and_size(ranges[0].addr, ranges[0].size)
+                .expect("invalid shmem region");
+            BTreeMap::from([(region.id, range)])
+        } else {
+            regions
+                .iter()
+                .zip(offsets)
+                .map(|(region, offset)| {
+                    let range =
+                        AddressRange::from_start_and_size(ranges[0].addr + offset, region.length)
+                            .expect("invalid shmem region");
+                    (region.id, range)
+                })
+                .collect()
+        };
         virtio_pci_device
             .device
-            .set_shared_memory_region(shmem_region);
+            .set_shared_memory_regions(shmem_regions);
     }
 
     Ok(ranges)
 }
 
+/// Lays out `regions` consecutively in the shared memory BAR.
+///
+/// Each region is aligned to its size rounded up to a power of two, so that large regions can be
+/// backed by large pages. Returns the offset of each region in the BAR and the size of the BAR.
+fn shared_memory_layout(regions: &[SharedMemoryRegion]) -> (Vec<u64>, u64) {
+    let mut offsets = Vec::with_capacity(regions.len());
+    let mut end = 0u64;
+    for region in regions {
+        let align = region
+            .length
+            .checked_next_power_of_two()
+            .expect("bar too large");
+        let offset = end.checked_next_multiple_of(align).expect("bar too large");
+        offsets.push(offset);
+        end = offset.checked_add(region.length).expect("bar too large");
+    }
+    (
+        offsets,
+        end.checked_next_power_of_two().expect("bar too large"),
+    )
+}
+
 #[cfg(feature = "pci-hotplug")]
 impl HotPluggable for VirtioPciDevice {
     /// Sets PciAddress to pci_addr
@@ -1340,7 +1425,7 @@ impl Suspendable for VirtioPciDevice {
                 Some((
                     PmWakeupEvent::new(self.vm_control_tube.clone(), self.pm_config.clone()),
                     MetricEventType::VirtioWakeup {
-                        virtio_id: self.device.device_type() as u32,
+                        virtio_id: self.device.device_type().virtio_id(),
                     },
                 )),
             );
@@ -1445,6 +1530,51 @@ impl Suspendable for VirtioPciDevice {
     }
 }
 
+/// Maps into a single shared memory region of the shared memory BAR, translating region offsets
+/// into BAR offsets.
+struct SharedMemoryRegionMapper {
+    vm_requester: Arc<Mutex<VmRequester>>,
+    offset: u64,
+    length: u64,
+}
+
+impl SharedMemoryRegionMapper {
+    fn bar_offset(&self, offset: u64) -> anyhow::Result<u64> {
+        if offset >= self.length {
+            return Err(anyhow!(
+                "offset {:#x} outside of shared memory region of size {:#x}",
+                offset,
+                self.length
+            ));
+        }
+        Ok(self.offset + offset)
+    }
+}
+
+impl SharedMemoryMapper for SharedMemoryRegionMapper {
+    fn add_mapping(
+        &mut self,
+        source: VmMemorySource,
+        offset: u64,
+        prot: Protection,
+        cache: MemCacheType,
+    ) -> anyhow::Result<()> {
+        let offset = self.bar_offset(offset)?;
+        self.vm_requester
+            .lock()
+            .add_mapping(source, offset, prot, cache)
+    }
+
+    fn remove_mapping(&mut self, offset: u64) -> anyhow::Result<()> {
+        let offset = self.bar_offset(offset)?;
+        self.vm_requester.lock().remove_mapping(offset)
+    }
+
+    fn as_raw_descriptor(&self) -> Option<RawDescriptor> {
+        self.vm_requester.lock().as_raw_descriptor()
+    }
+}
+
 struct VmRequester {
     vm_memory_client: VmMemoryClient,
     alloc: Alloc,
@@ -1549,4 +1679,21 @@ mod tests {
         // 0x108 => start at 0x180. Interval end at 0x1b0.
         assert_eq!(simple_allocator.alloc(0x30, 0x80).unwrap(), 0x180);
     }
+
+    #[test]
+    fn shared_memory_layout() {
+        use crate::virtio::SharedMemoryRegion;
+
+        let region = |id, length| SharedMemoryRegion { id, length };
+
+        assert_eq!(
+            super::shared_memory_layout(&[region(0, 0x3000)]),
+            (vec![0], 0x4000)
+        );
+        // Each region is aligned to its size.
+        assert_eq!(
+            super::shared_memory_layout(&[region(0, 0x1000), region(1, 0x4000), region(2, 0x1000)]),
+            (vec![0, 0x4000, 0x8000], 0x10000)
+        );
+    }
 }
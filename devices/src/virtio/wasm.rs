@@ -0,0 +1,917 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Virtio devices whose dataplane is implemented by a WebAssembly module.
+//!
+//! The module is run by an interpreter and can only reach the guest through the hostcalls imported
+//! from the `crosvm` module: it pops descriptor chains from its queues, reads and writes them, and
+//! pushes them back once they are processed. Every access to the module's linear memory and to the
+//! guest memory is bounds checked, and each call into the module runs with a bounded amount of
+//! fuel, so a faulty module can neither reach host memory nor stall the device forever. When a call
+//! traps or runs out of fuel, the failure is logged, the chains held by the module are returned to
+//! the guest without data and the module is instantiated again from scratch.
+//!
+//! A module must export its `memory` and the following functions:
+//!
+//! - `device_type() -> i32`: the virtio device ID.
+//! - `num_queues() -> i32` and `queue_size(queue: i32) -> i32`: the queues and their maximum sizes.
+//! - `process_queue(queue: i32)`: called when the guest notifies `queue`.
+//!
+//! The optional exports are `features() -> i64` (device-specific feature bits), `config_ptr() ->
+//! i32` and `config_len() -> i32` (location of the configuration space in the linear memory, read
+//! once at load time), and `reset()`.
+//!
+//! The hostcalls return a negative `WASM_ERR_*` value on failure:
+//!
+//! - `queue_pop(queue: i32) -> i32`: returns a handle to the next available descriptor chain.
+//! - `chain_readable_bytes(chain: i32) -> i32` and `chain_writable_bytes(chain: i32) -> i32`.
+//! - `chain_read(chain: i32, buf: i32, len: i32) -> i32`: reads the device-readable part of the
+//!   chain into `buf` and returns the number of bytes read.
+//! - `chain_write(chain: i32, buf: i32, len: i32) -> i32`: writes `buf` to the device-writable part
+//!   of the chain and returns the number of bytes written.
+//! - `queue_push(chain: i32) -> i32`: returns the chain to the guest, which is notified once the
+//!   current call into the module returns.
+//! - `guest_read(addr: i64, buf: i32, len: i32) -> i32` and `guest_write(addr: i64, buf: i32, len:
+//!   i32) -> i32`: access guest memory directly, for devices whose requests refer to other buffers.
+//! - `log(buf: i32, len: i32)`: logs a message from the module.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::info;
+use base::Event;
+use base::EventToken;
+use base::RawDescriptor;
+use base::WaitContext;
+use base::WorkerThread;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use jail::landlock::LandlockRule;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_keyvalue::FromKeyValues;
+use snapshot::AnySnapshot;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+use wasmi::Caller;
+use wasmi::Engine;
+use wasmi::Extern;
+use wasmi::Instance;
+use wasmi::Linker;
+use wasmi::Module;
+use wasmi::Store;
+use wasmi::StoreLimits;
+use wasmi::StoreLimitsBuilder;
+use wasmi::TypedFunc;
+use wasmi::WasmParams;
+use wasmi::WasmResults;
+
+use super::copy_config;
+use super::DescriptorChain;
+use super::DeviceType;
+use super::Interrupt;
+use super::Queue;
+use super::VirtioDevice;
+
+/// Invalid queue or chain handle, or buffer outside of the module's linear memory.
+pub const WASM_ERR_INVALID: i32 = -1;
+/// The guest memory access failed.
+pub const WASM_ERR_FAULT: i32 = -2;
+/// No descriptor chain is available.
+pub const WASM_ERR_EMPTY: i32 = -3;
+
+// Instructions a single call into the module may execute before it is aborted.
+const FUEL_PER_CALL: u64 = 1 << 30;
+const MAX_MEMORY_BYTES: usize = 256 << 20;
+const MAX_QUEUES: usize = 64;
+
+/// Parameters for a WebAssembly device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WasmDeviceParameters {
+    /// Path to the WebAssembly module implementing the device.
+    pub path: PathBuf,
+}
+
+/// State reachable from the hostcalls.
+struct HostState {
+    limits: StoreLimits,
+    mem: Option<GuestMemory>,
+    queues: BTreeMap<usize, Queue>,
+    // Chains popped by the module and not pushed back yet, with the index of their queue.
+    chains: BTreeMap<i32, (usize, DescriptorChain)>,
+    next_chain: i32,
+    needs_interrupt: BTreeSet<usize>,
+}
+
+impl HostState {
+    fn new() -> HostState {
+        HostState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MAX_MEMORY_BYTES)
+                .build(),
+            mem: None,
+            queues: BTreeMap::new(),
+            chains: BTreeMap::new(),
+            next_chain: 0,
+            needs_interrupt: BTreeSet::new(),
+        }
+    }
+
+    fn queue_pop(&mut self, queue: i32) -> i32 {
+        let Some(q) = usize::try_from(queue)
+            .ok()
+            .and_then(|i| self.queues.get_mut(&i))
+        else {
+            return WASM_ERR_INVALID;
+        };
+        let Some(chain) = q.pop() else {
+            return WASM_ERR_EMPTY;
+        };
+        // Handles wrap around, skip the ones of chains the module still holds. There are fewer
+        // chains than handles as they are bounded by the queue sizes.
+        let mut handle = self.next_chain;
+        while self.chains.contains_key(&handle) {
+            handle = handle.checked_add(1).unwrap_or(0);
+        }
+        self.next_chain = handle.checked_add(1).unwrap_or(0);
+        self.chains.insert(handle, (queue as usize, chain));
+        handle
+    }
+
+    fn queue_push(&mut self, chain: i32) -> i32 {
+        let Some((queue, chain)) = self.chains.remove(&chain) else {
+            return WASM_ERR_INVALID;
+        };
+        let Some(q) = self.queues.get_mut(&queue) else {
+            return WASM_ERR_INVALID;
+        };
+        let written = chain.writer.bytes_written() as u32;
+        q.add_used(chain, written);
+        self.needs_interrupt.insert(queue);
+        0
+    }
+
+    fn chain(&mut self, chain: i32) -> Option<&mut DescriptorChain> {
+        self.chains.get_mut(&chain).map(|(_, chain)| chain)
+    }
+}
+
+/// Returns the part of the linear memory `memory` designated by the module as `buf` and `len`.
+fn module_buffer(memory: &mut [u8], buf: i32, len: i32) -> Option<&mut [u8]> {
+    let start = usize::try_from(buf).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    memory.get_mut(start..end)
+}
+
+/// Runs `f` with the linear memory of the calling module and the host state.
+fn with_memory(
+    caller: &mut Caller<'_, HostState>,
+    f: impl FnOnce(&mut [u8], &mut HostState) -> i32,
+) -> i32 {
+    match caller.get_export("memory").and_then(Extern::into_memory) {
+        Some(memory) => {
+            let (memory, state) = memory.data_and_store_mut(caller);
+            f(memory, state)
+        }
+        None => WASM_ERR_INVALID,
+    }
+}
+
+fn link_hostcalls(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
+    linker.func_wrap(
+        "crosvm",
+        "queue_pop",
+        |mut caller: Caller<'_, HostState>, queue: i32| caller.data_mut().queue_pop(queue),
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "queue_push",
+        |mut caller: Caller<'_, HostState>, chain: i32| caller.data_mut().queue_push(chain),
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "chain_readable_bytes",
+        |mut caller: Caller<'_, HostState>, chain: i32| match caller.data_mut().chain(chain) {
+            Some(chain) => chain.reader.available_bytes().min(i32::MAX as usize) as i32,
+            None => WASM_ERR_INVALID,
+        },
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "chain_writable_bytes",
+        |mut caller: Caller<'_, HostState>, chain: i32| match caller.data_mut().chain(chain) {
+            Some(chain) => chain.writer.available_bytes().min(i32::MAX as usize) as i32,
+            None => WASM_ERR_INVALID,
+        },
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "chain_read",
+        |mut caller: Caller<'_, HostState>, chain: i32, buf: i32, len: i32| {
+            with_memory(&mut caller, |memory, state| {
+                let (Some(chain), Some(buf)) =
+                    (state.chain(chain), module_buffer(memory, buf, len))
+                else {
+                    return WASM_ERR_INVALID;
+                };
+                match chain.reader.read(buf) {
+                    Ok(n) => n as i32,
+                    Err(_) => WASM_ERR_FAULT,
+                }
+            })
+        },
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "chain_write",
+        |mut caller: Caller<'_, HostState>, chain: i32, buf: i32, len: i32| {
+            with_memory(&mut caller, |memory, state| {
+                let (Some(chain), Some(buf)) =
+                    (state.chain(chain), module_buffer(memory, buf, len))
+                else {
+                    return WASM_ERR_INVALID;
+                };
+                match chain.writer.write(buf) {
+                    Ok(n) => n as i32,
+                    Err(_) => WASM_ERR_FAULT,
+                }
+            })
+        },
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "guest_read",
+        |mut caller: Caller<'_, HostState>, addr: i64, buf: i32, len: i32| {
+            with_memory(&mut caller, |memory, state| {
+                let (Some(mem), Some(buf)) = (state.mem.as_ref(), module_buffer(memory, buf, len))
+                else {
+                    return WASM_ERR_INVALID;
+                };
+                match mem.read_exact_at_addr(buf, GuestAddress(addr as u64)) {
+                    Ok(()) => len,
+                    Err(_) => WASM_ERR_FAULT,
+                }
+            })
+        },
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "guest_write",
+        |mut caller: Caller<'_, HostState>, addr: i64, buf: i32, len: i32| {
+            with_memory(&mut caller, |memory, state| {
+                let (Some(mem), Some(buf)) = (state.mem.as_ref(), module_buffer(memory, buf, len))
+                else {
+                    return WASM_ERR_INVALID;
+                };
+                match mem.write_all_at_addr(buf, GuestAddress(addr as u64)) {
+                    Ok(()) => len,
+                    Err(_) => WASM_ERR_FAULT,
+                }
+            })
+        },
+    )?;
+    linker.func_wrap(
+        "crosvm",
+        "log",
+        |mut caller: Caller<'_, HostState>, buf: i32, len: i32| {
+            with_memory(&mut caller, |memory, _| {
+                if let Some(buf) = module_buffer(memory, buf, len) {
+                    info!("wasm device: {}", String::from_utf8_lossy(buf));
+                }
+                0
+            });
+        },
+    )?;
+    Ok(())
+}
+
+/// Calls `func` with a fresh fuel budget.
+fn call<P: WasmParams, R: WasmResults>(
+    store: &mut Store<HostState>,
+    func: &TypedFunc<P, R>,
+    params: P,
+) -> anyhow::Result<R> {
+    store.set_fuel(FUEL_PER_CALL)?;
+    Ok(func.call(store, params)?)
+}
+
+/// Instantiates `module` in a new store holding `state`.
+fn instantiate(
+    linker: &Linker<HostState>,
+    module: &Module,
+    state: HostState,
+) -> anyhow::Result<(Store<HostState>, Instance)> {
+    let mut store = Store::new(linker.engine(), state);
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(FUEL_PER_CALL)?;
+    let instance = linker
+        .instantiate(&mut store, module)
+        .and_then(|pre| pre.start(&mut store))
+        .context("failed to instantiate module")?;
+    Ok((store, instance))
+}
+
+/// An instantiated module.
+struct Plugin {
+    module: Module,
+    linker: Linker<HostState>,
+    store: Store<HostState>,
+    process_queue: TypedFunc<i32, ()>,
+    reset: Option<TypedFunc<(), ()>>,
+}
+
+impl Plugin {
+    fn new(
+        module: Module,
+        linker: Linker<HostState>,
+        state: HostState,
+    ) -> anyhow::Result<(Plugin, Instance)> {
+        let (store, instance) = instantiate(&linker, &module, state)?;
+        let process_queue = instance.get_typed_func(&store, "process_queue")?;
+        let reset = instance.get_typed_func(&store, "reset").ok();
+        let plugin = Plugin {
+            module,
+            linker,
+            store,
+            process_queue,
+            reset,
+        };
+        Ok((plugin, instance))
+    }
+
+    /// Replaces the instance of the module by a fresh one, as the state of an instance whose call
+    /// failed can't be trusted. The chains it held are returned to the guest without data.
+    fn restart(&mut self) -> anyhow::Result<()> {
+        let old = self.store.data_mut();
+        let mut state = HostState::new();
+        state.mem = old.mem.take();
+        state.queues = std::mem::take(&mut old.queues);
+        state.needs_interrupt = std::mem::take(&mut old.needs_interrupt);
+        for (queue, chain) in std::mem::take(&mut old.chains).into_values() {
+            if let Some(q) = state.queues.get_mut(&queue) {
+                q.add_used(chain, 0);
+                state.needs_interrupt.insert(queue);
+            }
+        }
+
+        let (store, instance) = instantiate(&self.linker, &self.module, state)?;
+        self.process_queue = instance.get_typed_func(&store, "process_queue")?;
+        self.reset = instance.get_typed_func(&store, "reset").ok();
+        self.store = store;
+        Ok(())
+    }
+
+    fn process_queue(&mut self, queue: usize) -> anyhow::Result<()> {
+        if let Err(e) = call(&mut self.store, &self.process_queue, queue as i32) {
+            error!(
+                "wasm device failed to process queue {}, restarting it: {:#}",
+                queue, e
+            );
+            self.restart().context("failed to restart wasm device")?;
+        }
+        let needs_interrupt = std::mem::take(&mut self.store.data_mut().needs_interrupt);
+        for queue in needs_interrupt {
+            if let Some(q) = self.store.data_mut().queues.get_mut(&queue) {
+                q.trigger_interrupt();
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, kill_evt: Event) -> anyhow::Result<()> {
+        #[derive(EventToken)]
+        enum Token {
+            Queue(usize),
+            Kill,
+        }
+
+        let wait_ctx = WaitContext::build_with(&[(&kill_evt, Token::Kill)])
+            .context("failed creating WaitContext")?;
+        for (index, queue) in self.store.data().queues.iter() {
+            wait_ctx
+                .add(queue.event(), Token::Queue(*index))
+                .context("failed adding queue to WaitContext")?;
+        }
+
+        loop {
+            let events = wait_ctx.wait().context("failed polling for events")?;
+            for event in events.iter().filter(|e| e.is_readable) {
+                match event.token {
+                    Token::Queue(index) => {
+                        self.store.data().queues[&index]
+                            .event()
+                            .wait()
+                            .context("failed reading queue Event")?;
+                        self.process_queue(index)?;
+                    }
+                    Token::Kill => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Virtio device implemented by a WebAssembly module.
+pub struct WasmDevice {
+    device_type: DeviceType,
+    queue_sizes: Vec<u16>,
+    virtio_features: u64,
+    config: Vec<u8>,
+    plugin: Option<Plugin>,
+    worker_thread: Option<WorkerThread<Plugin>>,
+}
+
+impl WasmDevice {
+    /// Loads the module at `params.path` and queries the device it implements.
+    pub fn new(params: &WasmDeviceParameters, base_features: u64) -> anyhow::Result<WasmDevice> {
+        let wasm = fs::read(&params.path)
+            .with_context(|| format!("failed to read {}", params.path.display()))?;
+        Self::from_module(&wasm, base_features)
+            .with_context(|| format!("failed to load {}", params.path.display()))
+    }
+
+    fn from_module(wasm: &[u8], base_features: u64) -> anyhow::Result<WasmDevice> {
+        let mut engine_config = wasmi::Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+        let module = Module::new(&engine, wasm).context("invalid module")?;
+
+        let mut linker = Linker::new(&engine);
+        link_hostcalls(&mut linker)?;
+        let (mut plugin, instance) = Plugin::new(module, linker, HostState::new())?;
+        let store = &mut plugin.store;
+        let memory = instance
+            .get_memory(&*store, "memory")
+            .context("module does not export its memory")?;
+
+        let device_type = instance.get_typed_func::<(), i32>(&*store, "device_type")?;
+        let device_type = call(store, &device_type, ())?;
+        let device_type = u32::try_from(device_type)
+            .ok()
+            .and_then(DeviceType::from_virtio_id)
+            .with_context(|| format!("invalid device type {}", device_type))?;
+
+        let num_queues = instance.get_typed_func::<(), i32>(&*store, "num_queues")?;
+        let num_queues = call(store, &num_queues, ())?;
+        if num_queues <= 0 || num_queues as usize > MAX_QUEUES {
+            bail!("invalid number of queues {}", num_queues);
+        }
+        let queue_size = instance.get_typed_func::<i32, i32>(&*store, "queue_size")?;
+        let queue_sizes = (0..num_queues)
+            .map(|queue| {
+                let size = call(store, &queue_size, queue)?;
+                match u16::try_from(size) {
+                    Ok(size) if size > 0 && size <= Queue::MAX_SIZE => Ok(size),
+                    _ => Err(anyhow!("invalid size {} for queue {}", size, queue)),
+                }
+            })
+            .collect::<anyhow::Result<Vec<u16>>>()?;
+
+        let device_features = match instance.get_typed_func::<(), i64>(&*store, "features") {
+            Ok(features) => call(store, &features, ())? as u64,
+            Err(_) => 0,
+        };
+
+        let config = match (
+            instance.get_typed_func::<(), i32>(&*store, "config_ptr"),
+            instance.get_typed_func::<(), i32>(&*store, "config_len"),
+        ) {
+            (Ok(config_ptr), Ok(config_len)) => {
+                let ptr = call(store, &config_ptr, ())?;
+                let len = call(store, &config_len, ())?;
+                module_buffer(memory.data_mut(&mut *store), ptr, len)
+                    .context("configuration space outside of the module memory")?
+                    .to_vec()
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(WasmDevice {
+            device_type,
+            queue_sizes,
+            virtio_features: base_features | device_features,
+            config,
+            plugin: Some(plugin),
+            worker_thread: None,
+        })
+    }
+
+    fn stop_worker(&mut self) {
+        if let Some(worker_thread) = self.worker_thread.take() {
+            self.plugin = Some(worker_thread.stop());
+        }
+    }
+}
+
+impl VirtioDevice for WasmDevice {
+    fn keep_rds(&self) -> Vec<RawDescriptor> {
+        Vec::new()
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn landlock_rules(&self) -> Option<Vec<LandlockRule>> {
+        // The module is loaded before the device is sandboxed.
+        Some(Vec::new())
+    }
+
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.virtio_features
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        copy_config(data, 0, &self.config, offset);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemory,
+        _interrupt: Interrupt,
+        queues: BTreeMap<usize, Queue>,
+    ) -> anyhow::Result<()> {
+        let mut plugin = self
+            .plugin
+            .take()
+            .context("wasm device is already active")?;
+        let state = plugin.store.data_mut();
+        state.mem = Some(mem);
+        state.queues = queues;
+
+        self.worker_thread = Some(WorkerThread::start("v_wasm", move |kill_evt| {
+            if let Err(e) = plugin.run(kill_evt) {
+                error!("wasm device worker thread failed: {:#}", e);
+            }
+            plugin
+        }));
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        self.stop_worker();
+        if let Some(plugin) = self.plugin.as_mut() {
+            let state = plugin.store.data_mut();
+            state.mem = None;
+            state.queues.clear();
+            state.chains.clear();
+            state.needs_interrupt.clear();
+            if let Some(reset) = plugin.reset.as_ref() {
+                call(&mut plugin.store, reset, ()).context("failed to reset wasm device")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn virtio_sleep(&mut self) -> anyhow::Result<Option<BTreeMap<usize, Queue>>> {
+        if self.worker_thread.is_none() {
+            return Ok(None);
+        }
+        self.stop_worker();
+        let state = self
+            .plugin
+            .as_mut()
+            .context("wasm device worker is missing")?
+            .store
+            .data_mut();
+        // Chains held by the module stay valid, as the queues are handed back on wake.
+        Ok(Some(std::mem::take(&mut state.queues)))
+    }
+
+    fn virtio_wake(
+        &mut self,
+        queues_state: Option<(GuestMemory, Interrupt, BTreeMap<usize, Queue>)>,
+    ) -> anyhow::Result<()> {
+        if let Some((mem, interrupt, queues)) = queues_state {
+            self.activate(mem, interrupt, queues)?;
+        }
+        Ok(())
+    }
+
+    fn virtio_snapshot(&mut self) -> anyhow::Result<AnySnapshot> {
+        // The state of the module lives in its linear memory and globals, which are not captured.
+        bail!("snapshots are not supported by wasm devices")
+    }
+
+    fn virtio_restore(&mut self, _data: AnySnapshot) -> anyhow::Result<()> {
+        bail!("snapshots are not supported by wasm devices")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_keyvalue::from_key_values;
+
+    use super::*;
+    use crate::virtio::create_descriptor_chain;
+    use crate::virtio::DescriptorType;
+    use crate::virtio::QueueConfig;
+
+    const QUEUE_SIZE: u16 = 16;
+    const DESC_TABLE: u64 = 0x0;
+    const AVAIL_RING: u64 = 0x1000;
+    const USED_RING: u64 = 0x2000;
+    const BUFFERS: u64 = 0x3000;
+    const MEM_SIZE: u64 = 0x10000;
+
+    // Exports shared by the devices driven through their queue, which define `process_queue`.
+    const DEVICE_EXPORTS: &str = r#"
+          (import "crosvm" "queue_pop" (func $queue_pop (param i32) (result i32)))
+          (import "crosvm" "queue_push" (func $queue_push (param i32) (result i32)))
+          (import "crosvm" "chain_read" (func $chain_read (param i32 i32 i32) (result i32)))
+          (import "crosvm" "chain_write" (func $chain_write (param i32 i32 i32) (result i32)))
+          (import "crosvm" "guest_read" (func $guest_read (param i64 i32 i32) (result i32)))
+          (import "crosvm" "guest_write" (func $guest_write (param i64 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "device_type") (result i32) (i32.const 4))
+          (func (export "num_queues") (result i32) (i32.const 1))
+          (func (export "queue_size") (param i32) (result i32) (i32.const 16))
+    "#;
+
+    // Writes back the request it reads from each chain.
+    const ECHO_DEVICE: &str = r#"
+          (func (export "process_queue") (param i32)
+            (local $chain i32)
+            (block $done
+              (loop $next
+                (local.set $chain (call $queue_pop (local.get 0)))
+                (br_if $done (i32.lt_s (local.get $chain) (i32.const 0)))
+                (drop (call $chain_write
+                  (local.get $chain)
+                  (i32.const 0x100)
+                  (call $chain_read (local.get $chain) (i32.const 0x100) (i32.const 64))))
+                (drop (call $queue_push (local.get $chain)))
+                (br $next))))
+    "#;
+
+    // Reads 4 bytes of guest memory at the address in the request and writes them 4 bytes further.
+    // The result of `guest_read` is written to the chain.
+    const COPY_DEVICE: &str = r#"
+          (func (export "process_queue") (param i32)
+            (local $chain i32)
+            (local $addr i64)
+            (local.set $chain (call $queue_pop (local.get 0)))
+            (drop (call $chain_read (local.get $chain) (i32.const 0x100) (i32.const 8)))
+            (local.set $addr (i64.load (i32.const 0x100)))
+            (i32.store (i32.const 0x108)
+              (call $guest_read (local.get $addr) (i32.const 0x200) (i32.const 4)))
+            (if (i32.ge_s (i32.load (i32.const 0x108)) (i32.const 0))
+              (then
+                (drop (call $guest_write
+                  (i64.add (local.get $addr) (i64.const 4))
+                  (i32.const 0x200)
+                  (i32.const 4)))))
+            (drop (call $chain_write (local.get $chain) (i32.const 0x108) (i32.const 4)))
+            (drop (call $queue_push (local.get $chain))))
+    "#;
+
+    // Pops a chain and traps while holding it.
+    const TRAPPING_DEVICE: &str = r#"
+          (func (export "process_queue") (param i32)
+            (drop (call $queue_pop (local.get 0)))
+            (unreachable))
+    "#;
+
+    fn setup_queue(mem: &GuestMemory) -> Queue {
+        let mut queue = QueueConfig::new(QUEUE_SIZE, 0);
+        queue.set_desc_table(GuestAddress(DESC_TABLE));
+        queue.set_avail_ring(GuestAddress(AVAIL_RING));
+        queue.set_used_ring(GuestAddress(USED_RING));
+        queue.set_ready(true);
+        queue
+            .activate(mem, Event::new().unwrap(), Interrupt::new_for_test())
+            .expect("QueueConfig::activate failed")
+    }
+
+    /// Writes a chain with a readable descriptor holding `request` and a writable descriptor of
+    /// `writable` bytes, then makes it available `count` more times.
+    fn add_chains(mem: &GuestMemory, request: &[u8], writable: u32, count: u16) {
+        create_descriptor_chain(
+            mem,
+            GuestAddress(DESC_TABLE),
+            GuestAddress(BUFFERS),
+            vec![
+                (DescriptorType::Readable, request.len() as u32),
+                (DescriptorType::Writable, writable),
+            ],
+            0,
+        )
+        .unwrap();
+        mem.write_all_at_addr(request, GuestAddress(BUFFERS))
+            .unwrap();
+        let avail_idx: u16 = mem
+            .read_obj_from_addr(GuestAddress(AVAIL_RING + 2))
+            .unwrap();
+        for i in 0..count {
+            let slot = avail_idx.wrapping_add(i) % QUEUE_SIZE;
+            mem.write_obj_at_addr(0u16, GuestAddress(AVAIL_RING + 4 + 2 * slot as u64))
+                .unwrap();
+        }
+        mem.write_obj_at_addr(avail_idx.wrapping_add(count), GuestAddress(AVAIL_RING + 2))
+            .unwrap();
+    }
+
+    /// Returns the used ring index and the length of the used element at `slot`.
+    fn used(mem: &GuestMemory, slot: u64) -> (u16, u32) {
+        let idx = mem.read_obj_from_addr(GuestAddress(USED_RING + 2)).unwrap();
+        let len = mem
+            .read_obj_from_addr(GuestAddress(USED_RING + 4 + 8 * slot + 4))
+            .unwrap();
+        (idx, len)
+    }
+
+    /// Loads a device made of `DEVICE_EXPORTS` and `process_queue`, and activates its queue.
+    fn activate_plugin(process_queue: &str, mem: &GuestMemory) -> Plugin {
+        let wasm =
+            wat::parse_str(format!("(module {} {})", DEVICE_EXPORTS, process_queue)).unwrap();
+        let mut plugin = WasmDevice::from_module(&wasm, 0).unwrap().plugin.unwrap();
+        let state = plugin.store.data_mut();
+        state.mem = Some(mem.clone());
+        state.queues.insert(0, setup_queue(mem));
+        plugin
+    }
+
+    fn guest_memory() -> GuestMemory {
+        GuestMemory::new(&[(GuestAddress(0), MEM_SIZE)]).unwrap()
+    }
+
+    const ENTROPY_DEVICE: &str = r#"
+        (module
+          (import "crosvm" "queue_pop" (func $queue_pop (param i32) (result i32)))
+          (import "crosvm" "queue_push" (func $queue_push (param i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 16) "\2a\00\00\00")
+          (func (export "device_type") (result i32) (i32.const 4))
+          (func (export "num_queues") (result i32) (i32.const 1))
+          (func (export "queue_size") (param i32) (result i32) (i32.const 256))
+          (func (export "features") (result i64) (i64.const 0x100000000))
+          (func (export "config_ptr") (result i32) (i32.const 16))
+          (func (export "config_len") (result i32) (i32.const 4))
+          (func (export "process_queue") (param i32)
+            (local $chain i32)
+            (block $done
+              (loop $next
+                (local.set $chain (call $queue_pop (local.get 0)))
+                (br_if $done (i32.lt_s (local.get $chain) (i32.const 0)))
+                (drop (call $queue_push (local.get $chain)))
+                (br $next)))))
+    "#;
+
+    #[test]
+    fn params_from_key_values() {
+        assert_eq!(
+            from_key_values::<WasmDeviceParameters>("path=/usr/lib/dev.wasm").unwrap(),
+            WasmDeviceParameters {
+                path: PathBuf::from("/usr/lib/dev.wasm"),
+            }
+        );
+    }
+
+    #[test]
+    fn load_module() {
+        let wasm = wat::parse_str(ENTROPY_DEVICE).unwrap();
+        let dev = WasmDevice::from_module(&wasm, 1).unwrap();
+        assert_eq!(dev.device_type(), DeviceType::Rng);
+        assert_eq!(dev.queue_max_sizes(), &[256]);
+        assert_eq!(dev.features(), 0x100000001);
+        let mut config = [0u8; 4];
+        dev.read_config(0, &mut config);
+        assert_eq!(config, [0x2a, 0, 0, 0]);
+    }
+
+    #[test]
+    fn missing_export() {
+        let wasm = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert!(WasmDevice::from_module(&wasm, 0).is_err());
+    }
+
+    #[test]
+    fn unknown_import() {
+        let wasm = wat::parse_str(r#"(module (import "env" "open" (func (param i32))))"#).unwrap();
+        assert!(WasmDevice::from_module(&wasm, 0).is_err());
+    }
+
+    #[test]
+    fn runaway_module() {
+        let wasm = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "process_queue") (param i32))
+                 (func (export "device_type") (result i32) (loop (br 0)) (i32.const 4)))"#,
+        )
+        .unwrap();
+        assert!(WasmDevice::from_module(&wasm, 0).is_err());
+    }
+
+    #[test]
+    fn process_queue_echo() {
+        let mem = guest_memory();
+        let mut plugin = activate_plugin(ECHO_DEVICE, &mem);
+        add_chains(&mem, b"hello", 16, 2);
+        plugin.process_queue(0).unwrap();
+
+        assert_eq!(used(&mem, 0), (2, 5));
+        assert_eq!(used(&mem, 1), (2, 5));
+        let mut reply = [0u8; 5];
+        mem.read_exact_at_addr(&mut reply, GuestAddress(BUFFERS + 5))
+            .unwrap();
+        assert_eq!(&reply, b"hello");
+        assert!(plugin.store.data().chains.is_empty());
+    }
+
+    #[test]
+    fn process_queue_guest_memory() {
+        let mem = guest_memory();
+        let mut plugin = activate_plugin(COPY_DEVICE, &mem);
+        let src = BUFFERS + 0x100;
+        mem.write_all_at_addr(&[1, 2, 3, 4], GuestAddress(src))
+            .unwrap();
+        add_chains(&mem, &src.to_le_bytes(), 4, 1);
+        plugin.process_queue(0).unwrap();
+
+        assert_eq!(used(&mem, 0), (1, 4));
+        let result: i32 = mem.read_obj_from_addr(GuestAddress(BUFFERS + 8)).unwrap();
+        assert_eq!(result, 4);
+        let mut copy = [0u8; 4];
+        mem.read_exact_at_addr(&mut copy, GuestAddress(src + 4))
+            .unwrap();
+        assert_eq!(copy, [1, 2, 3, 4]);
+
+        // The read straddles the end of guest memory.
+        add_chains(&mem, &(MEM_SIZE - 2).to_le_bytes(), 4, 1);
+        plugin.process_queue(0).unwrap();
+
+        assert_eq!(used(&mem, 1), (2, 4));
+        let result: i32 = mem.read_obj_from_addr(GuestAddress(BUFFERS + 8)).unwrap();
+        assert_eq!(result, WASM_ERR_FAULT);
+    }
+
+    #[test]
+    fn process_queue_trap_restarts_module() {
+        let mem = guest_memory();
+        let mut plugin = activate_plugin(TRAPPING_DEVICE, &mem);
+        add_chains(&mem, b"req", 4, 1);
+        plugin.process_queue(0).unwrap();
+
+        // The chain held by the module is returned without data and the device keeps working.
+        assert_eq!(used(&mem, 0), (1, 0));
+        assert!(plugin.store.data().chains.is_empty());
+        add_chains(&mem, b"req", 4, 1);
+        plugin.process_queue(0).unwrap();
+        assert_eq!(used(&mem, 1), (2, 0));
+    }
+
+    #[test]
+    fn process_queue_out_of_fuel() {
+        let mem = guest_memory();
+        let mut plugin = activate_plugin(
+            r#"(func (export "process_queue") (param i32)
+                 (drop (call $queue_pop (local.get 0)))
+                 (loop (br 0)))"#,
+            &mem,
+        );
+        add_chains(&mem, b"req", 4, 1);
+        plugin.process_queue(0).unwrap();
+        assert_eq!(used(&mem, 0), (1, 0));
+    }
+
+    #[test]
+    fn chain_handles_skip_outstanding_chains() {
+        let mem = guest_memory();
+        let mut plugin = activate_plugin(ECHO_DEVICE, &mem);
+        add_chains(&mem, b"req", 4, 3);
+        let state = plugin.store.data_mut();
+        state.next_chain = i32::MAX;
+        assert_eq!(state.queue_pop(0), i32::MAX);
+        assert_eq!(state.queue_pop(0), 0);
+        state.next_chain = 0;
+        assert_eq!(state.queue_pop(0), 1);
+        assert_eq!(state.chains.len(), 3);
+        assert_eq!(state.queue_pop(0), WASM_ERR_EMPTY);
+        assert_eq!(state.queue_pop(1), WASM_ERR_INVALID);
+    }
+
+    #[test]
+    fn module_buffer_bounds() {
+        let mut memory = [0u8; 16];
+        assert_eq!(module_buffer(&mut memory, 8, 8).map(|b| b.len()), Some(8));
+        assert!(module_buffer(&mut memory, 8, 9).is_none());
+        assert!(module_buffer(&mut memory, -1, 1).is_none());
+        assert!(module_buffer(&mut memory, i32::MAX, i32::MAX).is_none());
+    }
+}
@@ -11,6 +11,7 @@ use devices::BusType;
 use devices::CrosvmDeviceId;
 use devices::DeviceId;
 use devices::IrqChip;
+use devices::IrqChipCap;
 use devices::IrqChipX86_64;
 use devices::IrqEdgeEvent;
 use devices::IrqEventSource;
@@ -20,6 +21,7 @@ use devices::KvmSplitIrqChip;
 use devices::IOAPIC_BASE_ADDRESS;
 use hypervisor::kvm::Kvm;
 use hypervisor::kvm::KvmVm;
+use hypervisor::Config;
 use hypervisor::IoapicRedirectionTableEntry;
 use hypervisor::IrqRoute;
 use hypervisor::IrqSource;
@@ -167,6 +169,35 @@ fn split_irqchip_route_irq() {
     test_route_irq(get_split_chip());
 }
 
+#[test]
+fn kernel_irqchip_no_msi_ext_dest_id() {
+    let kvm = Kvm::new().expect("failed to instantiate Kvm");
+    let mem = GuestMemory::new(&[]).unwrap();
+    let cfg = Config {
+        x2apic_ids: true,
+        ..Default::default()
+    };
+    let vm = KvmVm::new(&kvm, mem, cfg).expect("failed to instantiate vm");
+    let chip = KvmKernelIrqChip::new(vm, 1).expect("failed to instantiate KvmKernelIrqChip");
+    assert!(!chip.check_capability(IrqChipCap::MsiExtDestId));
+}
+
+#[test]
+fn split_irqchip_msi_ext_dest_id() {
+    let kvm = Kvm::new().expect("failed to instantiate Kvm");
+    let mem = GuestMemory::new(&[]).unwrap();
+    let cfg = Config {
+        x2apic_ids: true,
+        ..Default::default()
+    };
+    let vm = KvmVm::new(&kvm, mem, cfg).expect("failed to instantiate vm");
+    let (_, device_tube) = Tube::pair().expect("failed to create irq tube");
+    let chip = KvmSplitIrqChip::new(vm, 1, device_tube, None)
+        .expect("failed to instantiate KvmSplitIrqChip");
+    assert!(chip.check_capability(IrqChipCap::MsiExtDestId));
+    assert!(!get_split_chip().check_capability(IrqChipCap::MsiExtDestId));
+}
+
 #[test]
 fn split_irqchip_pit_uses_speaker_port() {
     let chip = get_split_chip();
@@ -459,3 +490,82 @@ fn broadcast_eoi() {
         EventWaitResult::Signaled
     );
 }
+
+#[test]
+fn split_irqchip_irq_latency_stats() {
+    let mut chip = get_split_chip();
+    chip.enable_irq_latency_stats();
+
+    let source = IrqEventSource {
+        device_id: CrosvmDeviceId::Cmos.into(),
+        device_name: "test".into(),
+        queue_id: 0,
+    };
+    let evt = IrqLevelEvent::new().expect("failed to create event");
+    let index = chip
+        .register_level_irq_event(1, &evt, source)
+        .expect("failed to register_level_irq_event")
+        .expect("split irqchip should return an event index");
+
+    // route ioapic pin 1 to vector 123, level-triggered
+    let mut state = chip.get_ioapic_state().expect("failed to get ioapic state");
+    state.redirect_table[1].set_vector(123);
+    state.redirect_table[1].set_trigger_mode(TriggerMode::Level);
+    chip.set_ioapic_state(&state)
+        .expect("failed to set ioapic state");
+
+    evt.trigger().expect("failed to trigger event");
+    chip.service_irq_event(index)
+        .expect("failed to service irq event");
+    assert!(chip.irq_latency_stats().unwrap().is_empty());
+
+    chip.broadcast_eoi(123).expect("failed to broadcast eoi");
+    let stats = chip.irq_latency_stats().unwrap();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[&1].count, 1);
+}
+
+#[test]
+fn kernel_irqchip_irq_latency_proxy() {
+    let mut chip = get_kernel_chip();
+    assert!(chip.irq_latency_stats().is_none());
+    chip.enable_irq_latency_stats();
+
+    let source = IrqEventSource {
+        device_id: CrosvmDeviceId::Cmos.into(),
+        device_name: "test".into(),
+        queue_id: 0,
+    };
+    let evt = IrqLevelEvent::new().expect("failed to create event");
+    let index = chip
+        .register_level_irq_event(5, &evt, source)
+        .expect("failed to register_level_irq_event")
+        .expect("proxied event should have an event index");
+
+    // the device event and the resample event of KVM are both serviced by crosvm
+    let tokens = chip
+        .irq_event_tokens()
+        .expect("could not get irq_event_tokens");
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0].0, index);
+    assert_eq!(tokens[0].2, *evt.get_trigger());
+
+    evt.trigger().expect("failed to trigger event");
+    chip.service_irq_event(index)
+        .expect("failed to service irq event");
+    // the signal was consumed and forwarded to KVM
+    assert_eq!(
+        evt.get_trigger()
+            .wait_timeout(std::time::Duration::from_millis(10))
+            .expect("failed to read_timeout"),
+        EventWaitResult::TimedOut
+    );
+    assert!(chip.irq_latency_stats().unwrap().is_empty());
+
+    chip.unregister_level_irq_event(5, &evt)
+        .expect("failed to unregister_level_irq_event");
+    let tokens = chip
+        .irq_event_tokens()
+        .expect("could not get irq_event_tokens");
+    assert!(tokens.is_empty());
+}
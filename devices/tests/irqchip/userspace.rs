@@ -39,6 +39,7 @@ use hypervisor::DebugRegs;
 use hypervisor::DeliveryMode;
 use hypervisor::DestinationMode;
 use hypervisor::Fpu;
+use hypervisor::HwWatchpoint;
 use hypervisor::IoParams;
 use hypervisor::IoapicRedirectionTableEntry;
 use hypervisor::IrqRoute;
@@ -764,7 +765,12 @@ impl VcpuX86_64 for FakeVcpu {
     fn handle_cpuid(&mut self, _entry: &CpuIdEntry) -> Result<()> {
         unimplemented!()
     }
-    fn set_guest_debug(&self, _addrs: &[GuestAddress], _enable_singlestep: bool) -> Result<()> {
+    fn set_guest_debug(
+        &self,
+        _addrs: &[GuestAddress],
+        _watchpoints: &[HwWatchpoint],
+        _enable_singlestep: bool,
+    ) -> Result<()> {
         unimplemented!()
     }
     fn snapshot(&self) -> anyhow::Result<VcpuSnapshot> {
@@ -305,11 +305,25 @@ pub struct DiskFileParams {
 
 /// Inspect the image file type and create an appropriate disk file to match it.
 pub fn open_disk_file(params: DiskFileParams) -> Result<Box<dyn DiskFile>> {
+    let raw_image = open_raw_disk_file(&params)?;
+    open_disk_file_from_raw(raw_image, params)
+}
+
+/// Opens the image file described by `params` without inspecting it, so that it can be handed to
+/// [open_disk_file_from_raw] by a process that is not allowed to open it.
+pub fn open_raw_disk_file(params: &DiskFileParams) -> Result<File> {
     if params.depth > MAX_NESTING_DEPTH {
         return Err(Error::MaxNestingDepthExceeded);
     }
+    sys::open_raw_disk_image(params)
+}
 
-    let raw_image = sys::open_raw_disk_image(&params)?;
+/// Like [open_disk_file], but with the image file `raw_image` already opened according to
+/// `params`. Files the image refers to (e.g. qcow backing files) are still opened by path.
+pub fn open_disk_file_from_raw(
+    raw_image: File,
+    params: DiskFileParams,
+) -> Result<Box<dyn DiskFile>> {
     let image_type = detect_image_type(&raw_image, params.is_overlapped)?;
     Ok(match image_type {
         ImageType::Raw => {
@@ -13,6 +13,7 @@ use base::AsRawDescriptor;
 use base::RawDescriptor;
 use base::ReadNotifier;
 use base::StreamChannel;
+use linux_input_sys::constants::*;
 use linux_input_sys::virtio_input_event;
 use linux_input_sys::InputEventDecoder;
 use serde::Deserialize;
@@ -31,6 +32,29 @@ pub enum EventDeviceKind {
     Touchscreen,
     /// Produces key events while the display window has focus.
     Keyboard,
+    /// Produces absolute pointer motion and left button clicks from the display window's touch
+    /// events.
+    Tablet,
+}
+
+/// Translates the single-touch events produced by the display backends into the events of an
+/// absolute pointing device, with contact mapped to the left button.
+pub fn touch_to_tablet_events(events: &[virtio_input_event]) -> Vec<virtio_input_event> {
+    events
+        .iter()
+        .filter_map(|event| {
+            if u16::from(event.type_) != EV_ABS {
+                return None;
+            }
+            let value = i32::from(event.value);
+            match u16::from(event.code) {
+                ABS_MT_TRACKING_ID => Some(virtio_input_event::left_click(value >= 0)),
+                ABS_MT_POSITION_X => Some(virtio_input_event::absolute_x(value)),
+                ABS_MT_POSITION_Y => Some(virtio_input_event::absolute_y(value)),
+                _ => None,
+            }
+        })
+        .collect()
 }
 
 /// Encapsulates a virtual event device, such as a mouse or keyboard
@@ -66,6 +90,11 @@ impl EventDevice {
         Self::new(EventDeviceKind::Keyboard, event_socket)
     }
 
+    #[inline]
+    pub fn tablet(event_socket: StreamChannel) -> EventDevice {
+        Self::new(EventDeviceKind::Tablet, event_socket)
+    }
+
     #[inline]
     pub fn kind(&self) -> EventDeviceKind {
         self.kind
@@ -172,3 +201,37 @@ impl fmt::Debug for EventDevice {
         write!(f, "Event device ({:?})", self.kind)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touch_maps_to_tablet() {
+        let touch_down = [
+            virtio_input_event::multitouch_slot(0),
+            virtio_input_event::multitouch_tracking_id(3),
+            virtio_input_event::multitouch_absolute_x(10),
+            virtio_input_event::multitouch_absolute_y(20),
+            virtio_input_event::touch(true),
+        ];
+        assert_eq!(
+            touch_to_tablet_events(&touch_down),
+            [
+                virtio_input_event::left_click(true),
+                virtio_input_event::absolute_x(10),
+                virtio_input_event::absolute_y(20),
+            ]
+        );
+
+        let touch_up = [
+            virtio_input_event::multitouch_slot(0),
+            virtio_input_event::multitouch_tracking_id(-1),
+            virtio_input_event::touch(false),
+        ];
+        assert_eq!(
+            touch_to_tablet_events(&touch_up),
+            [virtio_input_event::left_click(false)]
+        );
+    }
+}
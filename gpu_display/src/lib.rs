@@ -45,6 +45,7 @@ mod sys;
 #[cfg(feature = "vulkan_display")]
 pub mod vulkan;
 
+use event_device::touch_to_tablet_events;
 pub use event_device::EventDevice;
 pub use event_device::EventDeviceKind;
 #[cfg(windows)]
@@ -506,11 +507,18 @@ impl GpuDisplay {
 
                 if let Some(gpu_display_events) = self.inner.handle_next_event(surface) {
                     for event_device in self.event_devices.values_mut() {
-                        if event_device.kind() != gpu_display_events.device_type {
-                            continue;
+                        match (event_device.kind(), gpu_display_events.device_type) {
+                            (kind, device_type) if kind == device_type => {
+                                event_device
+                                    .send_report(gpu_display_events.events.iter().cloned())?;
+                            }
+                            (EventDeviceKind::Tablet, EventDeviceKind::Touchscreen) => {
+                                event_device.send_report(touch_to_tablet_events(
+                                    &gpu_display_events.events,
+                                ))?;
+                            }
+                            _ => {}
                         }
-
-                        event_device.send_report(gpu_display_events.events.iter().cloned())?;
                     }
                 }
             }
@@ -18,6 +18,7 @@ use serde::Serialize;
 use snapshot::AnySnapshot;
 use vm_memory::GuestAddress;
 
+use crate::HwWatchpoint;
 use crate::Hypervisor;
 use crate::IrqRoute;
 use crate::IrqSource;
@@ -154,11 +155,19 @@ pub trait VcpuAArch64: Vcpu {
     fn get_psci_version(&self) -> Result<PsciVersion>;
 
     /// Sets up debug registers and configure vcpu for handling guest debug events.
-    fn set_guest_debug(&self, addrs: &[GuestAddress], enable_singlestep: bool) -> Result<()>;
+    fn set_guest_debug(
+        &self,
+        addrs: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+        enable_singlestep: bool,
+    ) -> Result<()>;
 
     /// Gets the max number of hardware breakpoints.
     fn get_max_hw_bps(&self) -> Result<usize>;
 
+    /// Gets the max number of hardware watchpoints.
+    fn get_max_hw_wps(&self) -> Result<usize>;
+
     /// Gets the cache architecture information for all cache levels.
     /// The keys of the map are the lower 4 lower significant bits of CSSELR_EL1, which represents
     /// the cache level. cache level is actually located in bits [3:1], but the value saves also
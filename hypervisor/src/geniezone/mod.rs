@@ -61,6 +61,7 @@ use crate::ClockState;
 use crate::Config;
 use crate::Datamatch;
 use crate::DeviceKind;
+use crate::HwWatchpoint;
 use crate::Hypervisor;
 use crate::HypervisorCap;
 use crate::HypervisorKind;
@@ -436,6 +437,12 @@ impl VcpuAArch64 for GeniezoneVcpu {
         Err(Error::new(EINVAL))
     }
 
+    fn get_max_hw_wps(&self) -> Result<usize> {
+        // TODO: Geniezone not support gdb currently
+        error!("Geniezone: not support get_max_hw_wps");
+        Err(Error::new(EINVAL))
+    }
+
     fn get_system_regs(&self) -> Result<BTreeMap<AArch64SysRegId, u64>> {
         error!("Geniezone: not support get_system_regs");
         Err(Error::new(EINVAL))
@@ -465,7 +472,12 @@ impl VcpuAArch64 for GeniezoneVcpu {
         ))
     }
 
-    fn set_guest_debug(&self, _addrs: &[GuestAddress], _enable_singlestep: bool) -> Result<()> {
+    fn set_guest_debug(
+        &self,
+        _addrs: &[GuestAddress],
+        _watchpoints: &[HwWatchpoint],
+        _enable_singlestep: bool,
+    ) -> Result<()> {
         // TODO: Geniezone not support gdb currently
         error!("Geniezone: not support set_guest_debug");
         Err(Error::new(EINVAL))
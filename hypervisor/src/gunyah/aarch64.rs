@@ -20,6 +20,7 @@ use vm_memory::MemoryRegionPurpose;
 
 use super::GunyahVcpu;
 use super::GunyahVm;
+use crate::HwWatchpoint;
 use crate::Hypervisor;
 use crate::PsciVersion;
 use crate::VcpuAArch64;
@@ -263,7 +264,12 @@ impl VcpuAArch64 for GunyahVcpu {
         Ok(PSCI_0_2)
     }
 
-    fn set_guest_debug(&self, _addrs: &[GuestAddress], _enable_singlestep: bool) -> Result<()> {
+    fn set_guest_debug(
+        &self,
+        _addrs: &[GuestAddress],
+        _watchpoints: &[HwWatchpoint],
+        _enable_singlestep: bool,
+    ) -> Result<()> {
         Err(Error::new(ENOTSUP))
     }
 
@@ -271,6 +277,10 @@ impl VcpuAArch64 for GunyahVcpu {
         Err(Error::new(ENOTSUP))
     }
 
+    fn get_max_hw_wps(&self) -> Result<usize> {
+        Err(Error::new(ENOTSUP))
+    }
+
     fn get_system_regs(&self) -> Result<BTreeMap<AArch64SysRegId, u64>> {
         Err(Error::new(ENOTSUP))
     }
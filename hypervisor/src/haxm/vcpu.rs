@@ -33,6 +33,7 @@ use crate::DebugRegs;
 use crate::DescriptorTable;
 use crate::Fpu;
 use crate::FpuReg;
+use crate::HwWatchpoint;
 use crate::IoOperation;
 use crate::IoParams;
 use crate::Regs;
@@ -543,7 +544,12 @@ impl VcpuX86_64 for HaxmVcpu {
         Err(Error::new(ENXIO))
     }
 
-    fn set_guest_debug(&self, _addrs: &[GuestAddress], _enable_singlestep: bool) -> Result<()> {
+    fn set_guest_debug(
+        &self,
+        _addrs: &[GuestAddress],
+        _watchpoints: &[HwWatchpoint],
+        _enable_singlestep: bool,
+    ) -> Result<()> {
         // TODO(b/173807302): Implement this
         Err(Error::new(ENOENT))
     }
@@ -39,6 +39,7 @@ use super::KvmVcpu;
 use super::KvmVm;
 use crate::ClockState;
 use crate::DeviceKind;
+use crate::HwWatchpoint;
 use crate::Hypervisor;
 use crate::IrqSourceChip;
 use crate::ProtectionType;
@@ -49,9 +50,18 @@ use crate::VcpuFeature;
 use crate::VcpuRegAArch64;
 use crate::VmAArch64;
 use crate::VmCap;
+use crate::WatchpointHit;
+use crate::WatchpointKind;
 use crate::AARCH64_MAX_REG_COUNT;
 use crate::PSCI_0_2;
 
+/// Offset of the exception class in the ESR reported by `KVM_EXIT_DEBUG`.
+const ESR_ELX_EC_SHIFT: u32 = 26;
+/// Exception class of a watchpoint hit at a lower exception level.
+const ESR_ELX_EC_WATCHPT_LOW: u32 = 0x34;
+/// Exception class of a watchpoint hit at the current exception level.
+const ESR_ELX_EC_WATCHPT_CUR: u32 = 0x35;
+
 impl Kvm {
     // Compute the machine type, which should be the IPA range for the VM
     // Ideally, this would take a description of the memory map and return
@@ -97,6 +107,12 @@ impl KvmVm {
             // Safe because it does not take pointer arguments.
             unsafe { self.enable_raw_capability(KvmCap::ArmMte, 0, &[0, 0, 0, 0])? }
         }
+        #[cfg(target_arch = "aarch64")]
+        if cfg.system_suspend {
+            // SAFETY:
+            // Safe because it does not take pointer arguments.
+            unsafe { self.enable_raw_capability(KvmCap::ArmSystemSuspend, 0, &[0, 0, 0, 0])? }
+        }
         #[cfg(not(target_arch = "aarch64"))]
         {
             // Suppress warning.
@@ -350,9 +366,23 @@ impl KvmVcpu {
     }
 
     #[inline]
-    pub(crate) fn handle_vm_exit_arch(&self, _run: &mut kvm_run) -> Option<VcpuExit> {
-        // No aarch64-specific exits (for now)
-        None
+    pub(crate) fn handle_vm_exit_arch(&self, run: &mut kvm_run) -> Option<VcpuExit> {
+        match run.exit_reason {
+            KVM_EXIT_DEBUG => {
+                // SAFETY:
+                // Safe because the exit_reason (which comes from the kernel) told us which
+                // union field to use.
+                let debug = unsafe { run.__bindgen_anon_1.debug.arch };
+                // Breakpoints and single steps are reported as `VcpuExit::Debug`.
+                match debug.hsr >> ESR_ELX_EC_SHIFT {
+                    ESR_ELX_EC_WATCHPT_LOW | ESR_ELX_EC_WATCHPT_CUR => {
+                        Some(VcpuExit::Watchpoint(WatchpointHit::Address(debug.far)))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
     }
 
     fn get_reg_list(&self) -> Result<Vec<u64>> {
@@ -767,6 +797,24 @@ impl VcpuAArch64 for KvmVcpu {
         }
     }
 
+    fn get_max_hw_wps(&self) -> Result<usize> {
+        // SAFETY:
+        // Safe because the kernel will only return the result of the ioctl.
+        let max_hw_wps = unsafe {
+            ioctl_with_val(
+                &self.vm,
+                KVM_CHECK_EXTENSION,
+                KVM_CAP_GUEST_DEBUG_HW_WPS.into(),
+            )
+        };
+
+        if max_hw_wps < 0 {
+            errno_result()
+        } else {
+            Ok(max_hw_wps.try_into().expect("can't represent u64 as usize"))
+        }
+    }
+
     fn get_system_regs(&self) -> Result<BTreeMap<AArch64SysRegId, u64>> {
         let reg_list = self.get_reg_list()?;
         let cntvct_el0: u16 = aarch64_sys_reg::CNTVCT_EL0.encoded();
@@ -868,7 +916,12 @@ impl VcpuAArch64 for KvmVcpu {
     }
 
     #[allow(clippy::unusual_byte_groupings)]
-    fn set_guest_debug(&self, addrs: &[GuestAddress], enable_singlestep: bool) -> Result<()> {
+    fn set_guest_debug(
+        &self,
+        addrs: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+        enable_singlestep: bool,
+    ) -> Result<()> {
         let mut dbg = kvm_guest_debug {
             control: KVM_GUESTDBG_ENABLE,
             ..Default::default()
@@ -877,9 +930,12 @@ impl VcpuAArch64 for KvmVcpu {
         if enable_singlestep {
             dbg.control |= KVM_GUESTDBG_SINGLESTEP;
         }
-        if !addrs.is_empty() {
+        if !addrs.is_empty() || !watchpoints.is_empty() {
             dbg.control |= KVM_GUESTDBG_USE_HW;
         }
+        if watchpoints.len() > dbg.arch.dbg_wvr.len() {
+            return Err(Error::new(EINVAL));
+        }
 
         for (i, guest_addr) in addrs.iter().enumerate() {
             // From the ARMv8 Architecture Reference Manual (DDI0487H.a) D31.3.{2,3}:
@@ -903,6 +959,12 @@ impl VcpuAArch64 for KvmVcpu {
             dbg.arch.dbg_bcr[i] = 0b1111_11_1;
         }
 
+        for (i, watchpoint) in watchpoints.iter().enumerate() {
+            let (wvr, wcr) = watchpoint_registers(watchpoint)?;
+            dbg.arch.dbg_wvr[i] = wvr;
+            dbg.arch.dbg_wcr[i] = wcr;
+        }
+
         // SAFETY:
         // Safe because the kernel won't read past the end of the kvm_guest_debug struct.
         let ret = unsafe { ioctl_with_ref(self, KVM_SET_GUEST_DEBUG, &dbg) };
@@ -934,10 +996,66 @@ pub(super) fn chip_to_kvm_chip(chip: IrqSourceChip) -> u32 {
     }
 }
 
+/// Returns the DBGWVR<n>_EL1 and DBGWCR<n>_EL1 values for `watchpoint`.
+fn watchpoint_registers(watchpoint: &HwWatchpoint) -> Result<(u64, u64)> {
+    let addr = watchpoint.addr.0;
+    let len = watchpoint.len;
+    // From the ARMv8 Architecture Reference Manual (DDI0487H.a), DBGWCR<n>_EL1:
+    // BAS, bits [12:5]: Byte address select of the bytes watched in the doubleword at
+    //      DBGWVR<n>_EL1.
+    // MASK, bits [28:24]: Number of low address bits masked, which watches a naturally aligned
+    //      power of two range of at least 8 bytes. BAS must then select all the bytes.
+    let (wvr, bas, mask): (u64, u64, u64) = if len > 0 && (addr & 0b111) + len <= 8 {
+        (addr & !0b111, ((1 << len) - 1) << (addr & 0b111), 0)
+    } else if len.is_power_of_two() && len <= 1 << 31 && addr % len == 0 {
+        (addr, 0xff, u64::from(len.trailing_zeros()))
+    } else {
+        return Err(Error::new(EINVAL));
+    };
+    // LSC, bits [4:3]: Load/store control
+    //      0b01: loads, 0b10: stores, 0b11: loads and stores.
+    let lsc: u64 = match watchpoint.kind {
+        WatchpointKind::Read => 0b01,
+        WatchpointKind::Write => 0b10,
+        WatchpointKind::ReadWrite => 0b11,
+    };
+    // PAC, bits [2:1]: Privilege of access control
+    //      0b11: EL1 & EL0
+    // E, bit [0]: Enable watchpoint
+    let wcr = (mask << 24) | (bas << 5) | (lsc << 3) | (0b11 << 1) | 1;
+    // DBGWVR<n>_EL1.RESS[14:0], bits [63:49]: Reserved, Sign extended
+    let sign_ext = 15;
+    Ok(((((wvr << sign_ext) as i64) >> sign_ext) as u64, wcr))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn watchpoint_register_values() {
+        let watchpoint = |addr, len, kind| HwWatchpoint {
+            addr: GuestAddress(addr),
+            len,
+            kind,
+        };
+        assert_eq!(
+            watchpoint_registers(&watchpoint(0x1004, 4, WatchpointKind::Write)).unwrap(),
+            (0x1000, 0x1e17)
+        );
+        assert_eq!(
+            watchpoint_registers(&watchpoint(0x2000, 0x40, WatchpointKind::ReadWrite)).unwrap(),
+            (0x2000, (6 << 24) | 0x1fff)
+        );
+        assert_eq!(
+            watchpoint_registers(&watchpoint(0xffff_8000_0000_0008, 8, WatchpointKind::Read))
+                .unwrap()
+                .0,
+            0xffff_8000_0000_0008
+        );
+        assert!(watchpoint_registers(&watchpoint(0x1006, 4, WatchpointKind::Read)).is_err());
+    }
+
     #[test]
     fn system_timer_register_mixup() {
         // Per https://docs.kernel.org/virt/kvm/api.html ARM64 system register encoding docs,
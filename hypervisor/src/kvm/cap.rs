@@ -123,11 +123,17 @@ pub enum KvmCap {
     ArmProtectedVm = KVM_CAP_ARM_PROTECTED_VM,
     X86ProtectedVm = KVM_CAP_X86_PROTECTED_VM,
     ArmMte = KVM_CAP_ARM_MTE,
+    ArmSystemSuspend = KVM_CAP_ARM_SYSTEM_SUSPEND,
+    HaltPoll = KVM_CAP_HALT_POLL,
     #[cfg(target_arch = "x86_64")]
     BusLockDetect = KVM_CAP_X86_BUS_LOCK_EXIT,
+    #[cfg(target_arch = "x86_64")]
+    X2ApicApi = KVM_CAP_X2APIC_API,
     // TODO(b/388092267): use upstream cap when available
     MemNoncoherentDma = KVM_CAP_USER_CONFIGURE_NONCOHERENT_DMA_CROS,
     UserMemory2 = KVM_CAP_USER_MEMORY2,
+    DirtyLogRing = KVM_CAP_DIRTY_LOG_RING,
+    DirtyLogRingAcqRel = KVM_CAP_DIRTY_LOG_RING_ACQ_REL,
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     Sve = KVM_CAP_ARM_SVE,
 }
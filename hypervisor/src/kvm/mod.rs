@@ -26,6 +26,8 @@ use std::os::raw::c_ulong;
 use std::os::raw::c_void;
 use std::os::unix::prelude::OsStrExt;
 use std::path::Path;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use base::errno_result;
@@ -35,6 +37,7 @@ use base::ioctl_with_mut_ref;
 use base::ioctl_with_ref;
 use base::ioctl_with_val;
 use base::pagesize;
+use base::warn;
 use base::AsRawDescriptor;
 use base::Error;
 use base::Event;
@@ -241,12 +244,96 @@ impl Hypervisor for Kvm {
     }
 }
 
+/// Number of entries of the dirty ring of each vCPU.
+const DIRTY_RING_ENTRIES: usize = 4096;
+
+// Flags of a `kvm_dirty_gfn`, set by KVM when it fills the entry and by userspace once harvested.
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1 << 0;
+const KVM_DIRTY_GFN_F_RESET: u32 = 1 << 1;
+
+/// The dirty rings of the vCPUs of a VM.
+///
+/// Once the rings are enabled, KVM reports the pages written to the memory slots with dirty
+/// logging enabled through the ring of the vCPU that wrote them, and KVM_GET_DIRTY_LOG is no longer
+/// available. The entries are collected into a bitmap per slot, which `get_dirty_log` hands out.
+#[derive(Default)]
+struct DirtyRings {
+    /// Mapping of the ring of each vCPU, with the index of the next entry to harvest.
+    rings: Vec<(MemoryMapping, usize)>,
+    /// Pages found in the rings and not handed out yet, one bit per page of each slot.
+    bitmaps: BTreeMap<MemSlot, Vec<u64>>,
+}
+
+impl DirtyRings {
+    /// Moves the entries filled by KVM to the bitmaps and resets the rings, so that KVM logs the
+    /// next writes to the harvested pages again.
+    fn harvest(&mut self, vm: &SafeDescriptor) -> Result<()> {
+        let mut harvested = false;
+        for (ring, next) in &mut self.rings {
+            loop {
+                let gfn =
+                    (ring.as_ptr() as *mut kvm_dirty_gfn).wrapping_add(*next % DIRTY_RING_ENTRIES);
+                // SAFETY:
+                // Safe because the mapping holds `DIRTY_RING_ENTRIES` entries and `flags` is the
+                // aligned u32 at the start of each entry, which KVM accesses atomically too.
+                let flags = unsafe { AtomicU32::from_ptr(std::ptr::addr_of_mut!((*gfn).flags)) };
+                // Pairs with the release store with which KVM publishes the entry.
+                if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                    break;
+                }
+                // SAFETY:
+                // Safe because the entry is within the mapping, and KVM does not modify it until it
+                // is harvested.
+                let (slot, offset) = unsafe {
+                    (
+                        std::ptr::addr_of!((*gfn).slot).read_volatile(),
+                        std::ptr::addr_of!((*gfn).offset).read_volatile(),
+                    )
+                };
+                let bitmap = self.bitmaps.entry(slot).or_default();
+                let word = (offset / 64) as usize;
+                if bitmap.len() <= word {
+                    bitmap.resize(word + 1, 0);
+                }
+                bitmap[word] |= 1 << (offset % 64);
+                flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+                *next = next.wrapping_add(1);
+                harvested = true;
+            }
+        }
+        if !harvested {
+            return Ok(());
+        }
+        // SAFETY:
+        // Safe because we know that our file is a VM fd and we verify the return result.
+        let ret = unsafe { ioctl(vm, KVM_RESET_DIRTY_RINGS) };
+        if ret < 0 {
+            return errno_result();
+        }
+        Ok(())
+    }
+
+    /// Moves the pages of `slot` harvested so far to `dirty_log`, in the format of
+    /// KVM_GET_DIRTY_LOG.
+    fn take(&mut self, slot: MemSlot, dirty_log: &mut [u8]) {
+        dirty_log.fill(0);
+        if let Some(bitmap) = self.bitmaps.remove(&slot) {
+            for (bytes, word) in dirty_log.chunks_mut(8).zip(bitmap) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+    }
+}
+
 /// Storage for constant KVM driver caps
 #[derive(Clone, Copy, Default)]
 struct KvmVmCaps {
     kvmclock_ctrl: bool,
     user_noncoherent_dma: bool,
     user_memory_region2: bool,
+    /// MSI routes carry 32-bit APIC IDs, see `KvmVm::init_arch`.
+    #[cfg(target_arch = "x86_64")]
+    x2apic_ids: bool,
 }
 
 /// A wrapper around creating and using a KVM VM.
@@ -258,6 +345,7 @@ pub struct KvmVm {
     /// A min heap of MemSlot numbers that were used and then removed and can now be re-used
     mem_slot_gaps: Arc<Mutex<BinaryHeap<Reverse<MemSlot>>>>,
     caps: KvmVmCaps,
+    dirty_rings: Option<Arc<Mutex<DirtyRings>>>,
 }
 
 impl KvmVm {
@@ -286,10 +374,17 @@ impl KvmVm {
             mem_regions: Arc::new(Mutex::new(BTreeMap::new())),
             mem_slot_gaps: Arc::new(Mutex::new(BinaryHeap::new())),
             caps: Default::default(),
+            dirty_rings: None,
         };
         vm.caps.kvmclock_ctrl = vm.check_raw_capability(KvmCap::KvmclockCtrl);
         vm.caps.user_noncoherent_dma = vm.check_raw_capability(KvmCap::MemNoncoherentDma);
         vm.caps.user_memory_region2 = vm.check_raw_capability(KvmCap::UserMemory2);
+        vm.enable_dirty_rings();
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            vm.caps.x2apic_ids = cfg.x2apic_ids;
+        }
 
         vm.init_arch(&cfg)?;
 
@@ -313,6 +408,55 @@ impl KvmVm {
         Ok(vm)
     }
 
+    /// Enables the dirty rings if KVM supports them, which must happen before any vCPU is created.
+    fn enable_dirty_rings(&mut self) {
+        // Architectures with weakly ordered memory only support the ACQ_REL flavor.
+        let Some(cap) = [KvmCap::DirtyLogRingAcqRel, KvmCap::DirtyLogRing]
+            .into_iter()
+            .find(|cap| self.check_raw_capability(*cap))
+        else {
+            return;
+        };
+        let ring_size = (DIRTY_RING_ENTRIES * std::mem::size_of::<kvm_dirty_gfn>()) as u64;
+        // SAFETY:
+        // Safe because the only argument is the size in bytes of each ring, which are mapped with
+        // that size when the vCPUs are created.
+        match unsafe { self.enable_raw_capability(cap, 0, &[ring_size, 0, 0, 0]) } {
+            Ok(()) => self.dirty_rings = Some(Arc::new(Mutex::new(DirtyRings::default()))),
+            Err(e) => warn!("failed to enable the KVM dirty rings: {}", e),
+        }
+    }
+
+    /// Wrapper around KVM_GET_DIRTY_LOG for the memory slot `slot` of `size` bytes.
+    fn get_slot_dirty_log(&self, slot: MemSlot, size: usize, dirty_log: &mut [u8]) -> Result<()> {
+        // Ensures that there are as many bytes in dirty_log as there are pages in the slot.
+        if dirty_log_bitmap_size(size) > dirty_log.len() {
+            return Err(Error::new(EINVAL));
+        }
+
+        if let Some(dirty_rings) = &self.dirty_rings {
+            let mut dirty_rings = dirty_rings.lock();
+            dirty_rings.harvest(&self.vm)?;
+            dirty_rings.take(slot, dirty_log);
+            return Ok(());
+        }
+
+        let mut dirty_log_kvm = kvm_dirty_log {
+            slot,
+            ..Default::default()
+        };
+        dirty_log_kvm.__bindgen_anon_1.dirty_bitmap = dirty_log.as_ptr() as *mut c_void;
+        // SAFETY:
+        // Safe because the `dirty_bitmap` pointer assigned above is guaranteed to be valid (because
+        // it's from a slice) and we checked that it will be large enough to hold the entire log.
+        let ret = unsafe { ioctl_with_ref(self, KVM_GET_DIRTY_LOG, &dirty_log_kvm) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            errno_result()
+        }
+    }
+
     pub fn create_kvm_vcpu(&self, id: usize) -> Result<KvmVcpu> {
         // SAFETY:
         // Safe because we know that our file is a VM fd and we verify the return result.
@@ -335,6 +479,17 @@ impl KvmVm {
             .build()
             .map_err(|_| Error::new(ENOSPC))?;
 
+        if let Some(dirty_rings) = &self.dirty_rings {
+            let ring = MemoryMappingBuilder::new(
+                DIRTY_RING_ENTRIES * std::mem::size_of::<kvm_dirty_gfn>(),
+            )
+            .from_file(&vcpu)
+            .offset(KVM_DIRTY_LOG_PAGE_OFFSET as u64 * pagesize() as u64)
+            .build()
+            .map_err(|_| Error::new(ENOSPC))?;
+            dirty_rings.lock().rings.push((ring, 0));
+        }
+
         Ok(KvmVcpu {
             kvm: self.kvm.try_clone()?,
             vm: self.vm.try_clone()?,
@@ -342,6 +497,7 @@ impl KvmVm {
             id,
             cap_kvmclock_ctrl: self.caps.kvmclock_ctrl,
             run_mmap: Arc::new(run_mmap),
+            dirty_rings: self.dirty_rings.clone(),
         })
     }
 
@@ -442,6 +598,12 @@ impl KvmVm {
         let irq_routes = unsafe { irq_routing[0].entries.as_mut_slice(routes.len()) };
         for (route, irq_route) in routes.iter().zip(irq_routes.iter_mut()) {
             *irq_route = kvm_irq_routing_entry::from(route);
+            #[cfg(target_arch = "x86_64")]
+            if self.caps.x2apic_ids && irq_route.type_ == KVM_IRQ_ROUTING_MSI {
+                // SAFETY:
+                // Safe because the route was just built as an MSI route.
+                x86_64::msi_to_x2apic_format(unsafe { &mut irq_route.u.msi });
+            }
         }
 
         // TODO(b/315998194): Add safety comment
@@ -527,11 +689,15 @@ impl KvmVm {
                     false
                 }
             }
+            KvmCap::DirtyLogRing | KvmCap::DirtyLogRingAcqRel => {
+                // The result is the maximum size of the rings in bytes.
+                ret as usize >= DIRTY_RING_ENTRIES * std::mem::size_of::<kvm_dirty_gfn>()
+            }
             _ => ret == 1,
         }
     }
 
-    // Currently only used on aarch64, but works on any architecture.
+    // Not used on every architecture, but works on any of them.
     #[allow(dead_code)]
     /// Enables a KVM-specific capability for this VM, with the given arguments.
     ///
@@ -562,6 +728,17 @@ impl KvmVm {
         }
     }
 
+    /// Sets the maximum time in nanoseconds a halted vCPU of this VM polls for a wake-up before
+    /// yielding its host CPU, overriding the `halt_poll_ns` module parameter.
+    pub fn set_halt_poll_ns(&self, ns: u64) -> Result<()> {
+        if !self.check_raw_capability(KvmCap::HaltPoll) {
+            return Err(Error::new(ENOSYS));
+        }
+        // SAFETY:
+        // Safe because KVM_CAP_HALT_POLL only takes the poll time as an integer argument.
+        unsafe { self.enable_raw_capability(KvmCap::HaltPoll, 0, &[ns, 0, 0, 0]) }
+    }
+
     fn handle_inflate(&mut self, guest_address: GuestAddress, size: u64) -> Result<()> {
         match if self.guest_mem.use_dontneed_locked() {
             self.guest_mem.dontneed_locked_range(guest_address, size)
@@ -589,6 +766,7 @@ impl Vm for KvmVm {
             mem_regions: self.mem_regions.clone(),
             mem_slot_gaps: self.mem_slot_gaps.clone(),
             caps: self.caps,
+            dirty_rings: self.dirty_rings.clone(),
         })
     }
 
@@ -809,25 +987,37 @@ impl Vm for KvmVm {
     fn get_dirty_log(&self, slot: MemSlot, dirty_log: &mut [u8]) -> Result<()> {
         let regions = self.mem_regions.lock();
         let mmap = regions.get(&slot).ok_or_else(|| Error::new(ENOENT))?;
-        // Ensures that there are as many bytes in dirty_log as there are pages in the mmap.
-        if dirty_log_bitmap_size(mmap.size()) > dirty_log.len() {
-            return Err(Error::new(EINVAL));
-        }
+        self.get_slot_dirty_log(slot, mmap.size(), dirty_log)
+    }
 
-        let mut dirty_log_kvm = kvm_dirty_log {
-            slot,
-            ..Default::default()
-        };
-        dirty_log_kvm.__bindgen_anon_1.dirty_bitmap = dirty_log.as_ptr() as *mut c_void;
-        // SAFETY:
-        // Safe because the `dirty_bitmap` pointer assigned above is guaranteed to be valid (because
-        // it's from a slice) and we checked that it will be large enough to hold the entire log.
-        let ret = unsafe { ioctl_with_ref(self, KVM_GET_DIRTY_LOG, &dirty_log_kvm) };
-        if ret == 0 {
-            Ok(())
-        } else {
-            errno_result()
+    fn set_guest_memory_dirty_logging(&self, enable: bool) -> Result<()> {
+        for region in self.guest_mem.regions() {
+            // SAFETY:
+            // Safe because this only changes the flags of the slots registered in `KvmVm::new`,
+            // which are guaranteed not to overlap.
+            unsafe {
+                set_user_memory_region(
+                    self,
+                    region.index as MemSlot,
+                    false,
+                    enable,
+                    MemCacheType::CacheCoherent,
+                    region.guest_addr.offset(),
+                    region.size as u64,
+                    region.host_addr as *mut u8,
+                )
+            }?;
         }
+        Ok(())
+    }
+
+    fn get_guest_memory_dirty_log(&self, region_index: usize, dirty_log: &mut [u8]) -> Result<()> {
+        let region = self
+            .guest_mem
+            .regions()
+            .find(|region| region.index == region_index)
+            .ok_or_else(|| Error::new(ENOENT))?;
+        self.get_slot_dirty_log(region.index as MemSlot, region.size, dirty_log)
     }
 
     fn register_ioevent(
@@ -929,6 +1119,7 @@ pub struct KvmVcpu {
     id: usize,
     cap_kvmclock_ctrl: bool,
     run_mmap: Arc<MemoryMapping>,
+    dirty_rings: Option<Arc<Mutex<DirtyRings>>>,
 }
 
 impl Vcpu for KvmVcpu {
@@ -943,6 +1134,7 @@ impl Vcpu for KvmVcpu {
             cap_kvmclock_ctrl: self.cap_kvmclock_ctrl,
             id: self.id,
             run_mmap: self.run_mmap.clone(),
+            dirty_rings: self.dirty_rings.clone(),
         })
     }
 
@@ -1052,6 +1244,13 @@ impl Vcpu for KvmVcpu {
                 })
             }
             KVM_EXIT_INTR => Ok(VcpuExit::Intr),
+            KVM_EXIT_DIRTY_RING_FULL => {
+                // The vCPU can only re-enter the guest once its ring has room again.
+                if let Some(dirty_rings) = &self.dirty_rings {
+                    dirty_rings.lock().harvest(&self.vm)?;
+                }
+                Ok(VcpuExit::Intr)
+            }
             KVM_EXIT_INTERNAL_ERROR => Ok(VcpuExit::InternalError),
             KVM_EXIT_SYSTEM_EVENT => {
                 // SAFETY:
@@ -1067,6 +1266,7 @@ impl Vcpu for KvmVcpu {
                     KVM_SYSTEM_EVENT_SHUTDOWN => Ok(VcpuExit::SystemEventShutdown),
                     KVM_SYSTEM_EVENT_RESET => self.system_event_reset(event_flags),
                     KVM_SYSTEM_EVENT_CRASH => Ok(VcpuExit::SystemEventCrash),
+                    KVM_SYSTEM_EVENT_SUSPEND => Ok(VcpuExit::SystemEventSuspend),
                     _ => {
                         error!(
                             "Unknown KVM system event {} with flags {}",
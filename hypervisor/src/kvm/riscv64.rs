@@ -176,6 +176,24 @@ impl VcpuRiscv64 for KvmVcpu {
             errno_result()
         }
     }
+
+    fn set_guest_debug(&self, enable: bool) -> Result<()> {
+        // KVM can neither single-step a RISC-V vCPU nor use its triggers, so only the trapping of
+        // `ebreak` is enabled.
+        let dbg = kvm_guest_debug {
+            control: if enable { KVM_GUESTDBG_ENABLE } else { 0 },
+            ..Default::default()
+        };
+
+        // SAFETY:
+        // Safe because the kernel won't read past the end of the kvm_guest_debug struct.
+        let ret = unsafe { ioctl_with_ref(self, KVM_SET_GUEST_DEBUG, &dbg) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            errno_result()
+        }
+    }
 }
 
 // Returns the id used for call to `KVM_[GET|SET]_ONE_REG`.
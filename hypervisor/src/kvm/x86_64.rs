@@ -4,6 +4,7 @@
 
 use std::arch::x86_64::CpuidResult;
 use std::collections::BTreeMap;
+use std::sync::Once;
 
 use base::errno_result;
 use base::error;
@@ -13,6 +14,7 @@ use base::ioctl_with_mut_ref;
 use base::ioctl_with_ptr;
 use base::ioctl_with_ref;
 use base::ioctl_with_val;
+use base::warn;
 use base::AsRawDescriptor;
 use base::Error;
 use base::IoctlNr;
@@ -46,6 +48,7 @@ use crate::DescriptorTable;
 use crate::DeviceKind;
 use crate::Fpu;
 use crate::FpuReg;
+use crate::HwWatchpoint;
 use crate::HypervisorX86_64;
 use crate::IoapicRedirectionTableEntry;
 use crate::IoapicState;
@@ -63,6 +66,8 @@ use crate::VcpuExit;
 use crate::VcpuX86_64;
 use crate::VmCap;
 use crate::VmX86_64;
+use crate::WatchpointHit;
+use crate::WatchpointKind;
 use crate::Xsave;
 use crate::NUM_IOAPIC_PINS;
 
@@ -118,6 +123,53 @@ pub struct VcpuTripleFaultState {
     pub pending: Option<bool>,
 }
 
+// arch_prctl() codes of the dynamically enabled XSAVE features.
+const ARCH_GET_XCOMP_SUPP: u64 = 0x1021;
+const ARCH_REQ_XCOMP_GUEST_PERM: u64 = 0x1025;
+
+// XSAVE feature holding the AMX tiles, which is enabled on first use.
+const XFEATURE_XTILEDATA: u64 = 18;
+
+/// Asks for the permission to let guests use the dynamically enabled XSAVE features supported by
+/// the host, i.e. AMX.  Without it, KVM hides them from the supported CPUID and from the size of
+/// the XSAVE area.  The permission is granted to the whole process and can't change once a vCPU
+/// was created, so it is only requested once.
+fn request_guest_xstate_permissions() {
+    static REQUEST: Once = Once::new();
+    REQUEST.call_once(|| {
+        let mut supported: u64 = 0;
+        // SAFETY:
+        // Safe because the kernel only writes a u64 to `supported`, and we verify the return
+        // result.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_arch_prctl,
+                ARCH_GET_XCOMP_SUPP,
+                &mut supported as *mut u64,
+            )
+        };
+        if ret < 0 || supported & (1 << XFEATURE_XTILEDATA) == 0 {
+            return;
+        }
+
+        // SAFETY:
+        // Safe because it does not take pointer arguments, and we verify the return result.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_arch_prctl,
+                ARCH_REQ_XCOMP_GUEST_PERM,
+                XFEATURE_XTILEDATA,
+            )
+        };
+        if ret < 0 {
+            warn!(
+                "failed to get the permission to expose AMX to guests: {}",
+                Error::last()
+            );
+        }
+    });
+}
+
 pub fn get_cpuid_with_initial_capacity<T: AsRawDescriptor>(
     descriptor: &T,
     kind: IoctlNr,
@@ -216,6 +268,23 @@ impl HypervisorX86_64 for Kvm {
 impl KvmVm {
     /// Does platform specific initialization for the KvmVm.
     pub fn init_arch(&self, _cfg: &Config) -> Result<()> {
+        if self.caps.x2apic_ids {
+            // SAFETY:
+            // Safe because it does not take pointer arguments.
+            unsafe {
+                self.enable_raw_capability(
+                    KvmCap::X2ApicApi,
+                    0,
+                    &[
+                        (KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK)
+                            as u64,
+                        0,
+                        0,
+                        0,
+                    ],
+                )?
+            }
+        }
         Ok(())
     }
 
@@ -224,6 +293,11 @@ impl KvmVm {
         false
     }
 
+    /// Whether MSI routes address 32-bit APIC IDs, as requested by `Config::x2apic_ids`.
+    pub fn x2apic_ids(&self) -> bool {
+        self.caps.x2apic_ids
+    }
+
     /// Checks if a particular `VmCap` is available, or returns None if arch-independent
     /// Vm.check_capability() should handle the check.
     pub fn check_capability_arch(&self, c: VmCap) -> Option<bool> {
@@ -432,6 +506,55 @@ impl KvmVm {
         }
     }
 
+    /// Makes the guest accesses to `msrs` raise #GP, as they do for MSRs that don't exist.
+    pub fn hide_msrs(&self, msrs: &[u32]) -> Result<()> {
+        let mut msrs = msrs.to_vec();
+        msrs.sort_unstable();
+        msrs.dedup();
+
+        // Consecutive MSRs share a range, whose cleared bitmap denies reads and writes to all of
+        // them.  Other MSRs are allowed by default.
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for msr in msrs {
+            match ranges.last_mut() {
+                Some((base, nmsrs)) if base.checked_add(*nmsrs) == Some(msr) => *nmsrs += 1,
+                _ => ranges.push((msr, 1)),
+            }
+        }
+        if ranges.len() > KVM_MSR_FILTER_MAX_RANGES as usize {
+            return Err(Error::new(E2BIG));
+        }
+
+        let mut bitmaps: Vec<Vec<u8>> = ranges
+            .iter()
+            .map(|(_, nmsrs)| vec![0; (*nmsrs as usize).div_ceil(8)])
+            .collect();
+        let mut filter = kvm_msr_filter {
+            flags: KVM_MSR_FILTER_DEFAULT_ALLOW,
+            ..Default::default()
+        };
+        for ((range, (base, nmsrs)), bitmap) in
+            filter.ranges.iter_mut().zip(&ranges).zip(&mut bitmaps)
+        {
+            *range = kvm_msr_filter_range {
+                flags: KVM_MSR_FILTER_READ | KVM_MSR_FILTER_WRITE,
+                nmsrs: *nmsrs,
+                base: *base,
+                bitmap: bitmap.as_mut_ptr(),
+            };
+        }
+
+        // SAFETY:
+        // Safe because we know that our file is a VM fd, the kernel only reads the filter and the
+        // bitmaps, which outlive the call, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(self, KVM_X86_SET_MSR_FILTER, &filter) };
+        if ret < 0 {
+            errno_result()
+        } else {
+            Ok(())
+        }
+    }
+
     /// Enable support for split-irqchip.
     pub fn enable_split_irqchip(&self, ioapic_pins: usize) -> Result<()> {
         let mut cap = kvm_enable_cap {
@@ -515,6 +638,9 @@ impl VmX86_64 for KvmVm {
     }
 
     fn create_vcpu(&self, id: usize) -> Result<Box<dyn VcpuX86_64>> {
+        // vCPUs are created on their own threads, before any of them queries the supported CPUID.
+        request_guest_xstate_permissions();
+
         // create_vcpu is declared separately in VmAArch64 and VmX86, so it can return VcpuAArch64
         // or VcpuX86.  But both use the same implementation in KvmVm::create_vcpu.
         Ok(Box::new(KvmVm::create_kvm_vcpu(self, id)?))
@@ -561,7 +687,8 @@ impl KvmVcpu {
     /// Size should always be >=0. If size is negative, an error occurred.
     /// If size <= 4096, XSAVE2 is not supported by the CPU or the kernel. KVM_XSAVE_MAX_SIZE is
     /// returned (4096).
-    /// Otherwise, the size will be returned.
+    /// Otherwise, the size will be returned. It covers the dynamically enabled features like AMX
+    /// tile data when the process has the permission to expose them.
     fn xsave_size(&self) -> Result<usize> {
         let size = {
             // SAFETY:
@@ -591,6 +718,14 @@ impl KvmVcpu {
             KVM_EXIT_SET_TPR => Some(VcpuExit::SetTpr),
             KVM_EXIT_TPR_ACCESS => Some(VcpuExit::TprAccess),
             KVM_EXIT_X86_BUS_LOCK => Some(VcpuExit::BusLock),
+            KVM_EXIT_DEBUG => {
+                // SAFETY:
+                // Safe because the exit_reason (which comes from the kernel) told us which
+                // union field to use.
+                let debug = unsafe { run.__bindgen_anon_1.debug.arch };
+                // Breakpoints and single steps are reported as `VcpuExit::Debug`.
+                debug_exit_watchpoint(debug.dr6, debug.dr7).map(VcpuExit::Watchpoint)
+            }
             _ => None,
         }
     }
@@ -1040,14 +1175,19 @@ impl VcpuX86_64 for KvmVcpu {
         }
     }
 
-    fn set_guest_debug(&self, addrs: &[GuestAddress], enable_singlestep: bool) -> Result<()> {
+    fn set_guest_debug(
+        &self,
+        addrs: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+        enable_singlestep: bool,
+    ) -> Result<()> {
         use kvm_sys::*;
         let mut dbg: kvm_guest_debug = Default::default();
 
-        if addrs.len() > 4 {
+        if addrs.len() + watchpoints.len() > 4 {
             error!(
-                "Support 4 breakpoints at most but {} addresses are passed",
-                addrs.len()
+                "Support 4 breakpoints and watchpoints at most but {} addresses are passed",
+                addrs.len() + watchpoints.len()
             );
             return Err(base::Error::new(libc::EINVAL));
         }
@@ -1068,6 +1208,14 @@ impl VcpuX86_64 for KvmVcpu {
             dbg.arch.debugreg[7] |= 2 << (i * 2);
         }
 
+        // Watchpoints use the debug registers following the breakpoints.
+        for (i, watchpoint) in watchpoints.iter().enumerate() {
+            let i = addrs.len() + i;
+            dbg.arch.debugreg[i] = watchpoint.addr.0;
+            dbg.arch.debugreg[7] |= 2 << (i * 2);
+            dbg.arch.debugreg[7] |= dr7_watchpoint_condition(watchpoint)? << (16 + i * 4);
+        }
+
         let ret = {
             // SAFETY:
             // Here we trust the kernel not to read past the end of the kvm_guest_debug struct.
@@ -1787,10 +1935,97 @@ impl From<&DebugRegs> for kvm_debugregs {
     }
 }
 
+/// Returns the R/W and LEN fields of DR7 for `watchpoint`.
+fn dr7_watchpoint_condition(watchpoint: &HwWatchpoint) -> Result<u64> {
+    // R/W: 0b01 breaks on data writes and 0b11 on data reads or writes. x86 has no condition for
+    // reads only, so read watchpoints also trigger on writes.
+    let rw = match watchpoint.kind {
+        WatchpointKind::Write => 0b01,
+        WatchpointKind::Read | WatchpointKind::ReadWrite => 0b11,
+    };
+    // LEN: the watched range must be 1, 2, 4 or 8 bytes and aligned to its size.
+    let len = match watchpoint.len {
+        1 => 0b00,
+        2 => 0b01,
+        4 => 0b11,
+        8 => 0b10,
+        _ => return Err(Error::new(EINVAL)),
+    };
+    if watchpoint.addr.0 % watchpoint.len != 0 {
+        return Err(Error::new(EINVAL));
+    }
+    Ok(rw | (len << 2))
+}
+
+/// Returns the watchpoint that caused a debug exit with the given DR6 and DR7, if any.
+fn debug_exit_watchpoint(dr6: u64, dr7: u64) -> Option<WatchpointHit> {
+    let is_watchpoint =
+        |i: usize| dr7 & (0b11 << (i * 2)) != 0 && (dr7 >> (16 + i * 4)) & 0b11 != 0;
+    // B0-B3 of DR6 tell which debug registers had their condition met.
+    let slot = (0..4).find(|&i| dr6 & (1 << i) != 0 && is_watchpoint(i))?;
+    // `set_guest_debug` puts the watchpoints after the breakpoints and in order.
+    Some(WatchpointHit::Index(
+        (0..slot).filter(|&i| is_watchpoint(i)).count(),
+    ))
+}
+
+/// Moves the extended destination ID of an MSI, in bits 11:5 of its address, to the upper 32 bits
+/// of the address, where KVM expects bits 31:8 of the destination APIC ID once 32-bit APIC IDs
+/// are enabled.
+pub(super) fn msi_to_x2apic_format(msi: &mut kvm_irq_routing_msi) {
+    const EXT_DEST_ID_SHIFT: u32 = 5;
+    const EXT_DEST_ID_MASK: u32 = 0x7f;
+
+    let ext_dest_id = (msi.address_lo >> EXT_DEST_ID_SHIFT) & EXT_DEST_ID_MASK;
+    msi.address_lo &= !(EXT_DEST_ID_MASK << EXT_DEST_ID_SHIFT);
+    msi.address_hi |= ext_dest_id << 8;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn msi_ext_dest_id() {
+        // APIC ID 0x1234: 0x34 in the destination ID field, 0x12 in the extended one.
+        let mut msi = kvm_irq_routing_msi {
+            address_lo: 0xfee0_0000 | (0x34 << 12) | (0x12 << 5),
+            data: 0x30,
+            ..Default::default()
+        };
+        msi_to_x2apic_format(&mut msi);
+        assert_eq!(msi.address_lo, 0xfee3_4000);
+        assert_eq!(msi.address_hi, 0x1200);
+        assert_eq!(msi.data, 0x30);
+    }
+
+    #[test]
+    fn dr7_watchpoints() {
+        let watchpoint = |addr, len, kind| HwWatchpoint {
+            addr: GuestAddress(addr),
+            len,
+            kind,
+        };
+        assert_eq!(
+            dr7_watchpoint_condition(&watchpoint(0x1000, 4, WatchpointKind::Write)).unwrap(),
+            0b1101
+        );
+        assert_eq!(
+            dr7_watchpoint_condition(&watchpoint(0x1000, 8, WatchpointKind::Read)).unwrap(),
+            0b1011
+        );
+        assert!(dr7_watchpoint_condition(&watchpoint(0x1002, 4, WatchpointKind::Write)).is_err());
+        assert!(dr7_watchpoint_condition(&watchpoint(0x1000, 3, WatchpointKind::Write)).is_err());
+
+        // DR0 is a breakpoint, DR1 and DR2 are watchpoints.
+        let dr7 = 0x0600 | 0b10_1010 | (0b1101 << 20) | (0b0011 << 24);
+        assert_eq!(debug_exit_watchpoint(0b001, dr7), None);
+        assert_eq!(
+            debug_exit_watchpoint(0b100, dr7),
+            Some(WatchpointHit::Index(1))
+        );
+    }
+
     #[test]
     fn vcpu_event_to_from() {
         // All data is random.
@@ -218,6 +218,26 @@ pub trait Vm: Send {
     /// be 2 bytes or greater.
     fn get_dirty_log(&self, slot: MemSlot, dirty_log: &mut [u8]) -> Result<()>;
 
+    /// Enables or disables dirty page logging for the guest memory regions of `get_memory`.
+    ///
+    /// While enabled, `get_guest_memory_dirty_log` returns the pages written by the guest.
+    fn set_guest_memory_dirty_logging(&self, _enable: bool) -> Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+    }
+
+    /// Gets the bitmap of pages written by the guest since the last call for the guest memory
+    /// region with index `region_index`. Dirty logging must have been enabled with
+    /// `set_guest_memory_dirty_logging`.
+    ///
+    /// The size of `dirty_log` must be at least as many bits as there are pages in the region.
+    fn get_guest_memory_dirty_log(
+        &self,
+        _region_index: usize,
+        _dirty_log: &mut [u8],
+    ) -> Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+    }
+
     /// Registers an event to be signaled whenever a certain address is written to.
     ///
     /// The `datamatch` parameter can be used to limit signaling `evt` to only the cases where the
@@ -441,6 +461,31 @@ impl VcpuShutdownError {
     }
 }
 
+/// The guest accesses that trigger a hardware watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchpointKind {
+    Write,
+    Read,
+    ReadWrite,
+}
+
+/// A hardware watchpoint on the `len` bytes at the guest virtual address `addr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HwWatchpoint {
+    pub addr: GuestAddress,
+    pub len: u64,
+    pub kind: WatchpointKind,
+}
+
+/// Identifies the watchpoint that caused a `VcpuExit::Watchpoint`, as reported by the hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchpointHit {
+    /// Index of the watchpoint in the list passed to `set_guest_debug`.
+    Index(usize),
+    /// Guest virtual address of the access that triggered the watchpoint.
+    Address(u64),
+}
+
 // Note that when adding entries to the VcpuExit enum you may want to add corresponding entries in
 // crosvm::stats::exit_to_index and crosvm::stats::exit_index_to_str if you don't want the new
 // exit type to be categorized as "Unknown".
@@ -460,6 +505,8 @@ pub enum VcpuExit {
     Exception,
     Hypercall,
     Debug,
+    /// vcpu stopped after an access matching one of the watchpoints set by `set_guest_debug`.
+    Watchpoint(WatchpointHit),
     Hlt,
     IrqWindowOpen,
     Shutdown(std::result::Result<(), VcpuShutdownError>),
@@ -473,6 +520,8 @@ pub enum VcpuExit {
     SystemEventShutdown,
     SystemEventReset,
     SystemEventCrash,
+    /// The guest requested the VM to be suspended with PSCI SYSTEM_SUSPEND.
+    SystemEventSuspend,
     /// An invalid vcpu register was set while running.
     InvalidVpRegister,
     /// incorrect setup for vcpu requiring an unsupported feature
@@ -628,6 +677,12 @@ pub struct Config {
     #[cfg(target_arch = "aarch64")]
     /// enable the Memory Tagging Extension in the guest
     pub mte: bool,
+    #[cfg(target_arch = "aarch64")]
+    /// let the VMM handle the PSCI SYSTEM_SUSPEND calls of the guest
+    pub system_suspend: bool,
+    #[cfg(target_arch = "x86_64")]
+    /// let the guest address APIC IDs above 255 with the extended destination ID of MSIs
+    pub x2apic_ids: bool,
     pub protection_type: ProtectionType,
 }
 
@@ -636,6 +691,10 @@ impl Default for Config {
         Config {
             #[cfg(target_arch = "aarch64")]
             mte: false,
+            #[cfg(target_arch = "aarch64")]
+            system_suspend: false,
+            #[cfg(target_arch = "x86_64")]
+            x2apic_ids: false,
             protection_type: ProtectionType::Unprotected,
         }
     }
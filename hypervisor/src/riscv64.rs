@@ -33,6 +33,10 @@ pub trait VcpuRiscv64: Vcpu {
     /// Gets the value of a register on this VCPU.
     fn get_one_reg(&self, reg_id: VcpuRegister) -> Result<u64>;
 
+    /// Enables or disables guest debugging. When enabled, `ebreak` instructions executed by the
+    /// guest exit to the VMM with `VcpuExit::Debug`.
+    fn set_guest_debug(&self, enable: bool) -> Result<()>;
+
     /// Snapshot VCPU
     fn snapshot(&self) -> anyhow::Result<VcpuSnapshot> {
         Err(anyhow!("not yet implemented"))
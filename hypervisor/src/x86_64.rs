@@ -2,10 +2,10 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use std::arch::x86_64::CpuidResult;
 #[cfg(any(unix, feature = "haxm", feature = "whpx"))]
 use std::arch::x86_64::__cpuid;
 use std::arch::x86_64::_rdtsc;
+use std::arch::x86_64::CpuidResult;
 use std::collections::BTreeMap;
 use std::collections::HashSet;
 
@@ -23,6 +23,7 @@ use serde::Serialize;
 use snapshot::AnySnapshot;
 use vm_memory::GuestAddress;
 
+use crate::HwWatchpoint;
 use crate::Hypervisor;
 use crate::IrqRoute;
 use crate::IrqSource;
@@ -43,6 +44,8 @@ const MSR_F15H_PERF_CTR3: u32 = 0xc0010207;
 const MSR_F15H_PERF_CTR4: u32 = 0xc0010209;
 const MSR_F15H_PERF_CTR5: u32 = 0xc001020b;
 const MSR_IA32_PERF_CAPABILITIES: u32 = 0x00000345;
+const MSR_KVM_ASYNC_PF_EN: u32 = 0x4b564d02;
+const MSR_KVM_ASYNC_PF_INT: u32 = 0x4b564d06;
 
 /// A trait for managing cpuids for an x86_64 hypervisor and for checking its capabilities.
 pub trait HypervisorX86_64: Hypervisor {
@@ -156,7 +159,15 @@ pub trait VcpuX86_64: Vcpu {
     fn set_cpuid(&self, cpuid: &CpuId) -> Result<()>;
 
     /// Sets up debug registers and configure vcpu for handling guest debug events.
-    fn set_guest_debug(&self, addrs: &[GuestAddress], enable_singlestep: bool) -> Result<()>;
+    ///
+    /// The hardware breakpoints at `addrs` and the `watchpoints` share the 4 debug address
+    /// registers.
+    fn set_guest_debug(
+        &self,
+        addrs: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+        enable_singlestep: bool,
+    ) -> Result<()>;
 
     /// This function should be called after `Vcpu::run` returns `VcpuExit::Cpuid`, and `entry`
     /// should represent the result of emulating the CPUID instruction. The `handle_cpuid` function
@@ -273,7 +284,15 @@ pub trait VcpuX86_64: Vcpu {
             self.set_xcr(*xcr_index, *value)?;
         }
 
-        for (msr_index, value) in snapshot.msrs.iter() {
+        // Enabling asynchronous page faults wakes up all the pending faults through the vector in
+        // MSR_KVM_ASYNC_PF_INT, so it must be restored before MSR_KVM_ASYNC_PF_EN.
+        let async_pf_en = snapshot.msrs.get_key_value(&MSR_KVM_ASYNC_PF_EN);
+        let msrs = snapshot
+            .msrs
+            .iter()
+            .filter(|(msr_index, _)| **msr_index != MSR_KVM_ASYNC_PF_EN)
+            .chain(async_pf_en);
+        for (msr_index, value) in msrs {
             if self.get_msr(*msr_index) == Ok(*value) {
                 continue; // no need to set MSR since the values are the same.
             }
@@ -368,6 +387,16 @@ pub struct CpuConfigX86_64 {
 
     /// whether setting hybrid CPU type
     pub hybrid_type: Option<CpuHybridType>,
+
+    /// whether to expose KVM asynchronous page faults to the guest
+    pub async_pf: bool,
+
+    /// CPUID bits to override once crosvm has adjusted the leaves
+    pub cpuid_overrides: Vec<CpuIdOverride>,
+
+    /// number of vCPUs sharing each host cache of the vCPU, in the order of the cache leaves,
+    /// empty unless the host caches are mirrored
+    pub host_cache_sharing: Vec<u32>,
 }
 
 impl CpuConfigX86_64 {
@@ -378,6 +407,9 @@ impl CpuConfigX86_64 {
         no_smt: bool,
         itmt: bool,
         hybrid_type: Option<CpuHybridType>,
+        async_pf: bool,
+        cpuid_overrides: Vec<CpuIdOverride>,
+        host_cache_sharing: Vec<u32>,
     ) -> Self {
         CpuConfigX86_64 {
             force_calibrated_tsc_leaf,
@@ -386,10 +418,52 @@ impl CpuConfigX86_64 {
             no_smt,
             itmt,
             hybrid_type,
+            async_pf,
+            cpuid_overrides,
+            host_cache_sharing,
         }
     }
 }
 
+/// A register of a CPUID leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuIdRegister {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Replaces the bits of a CPUID register selected by `mask` with the ones of `value`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuIdOverride {
+    pub function: u32,
+    /// The subleaf to override, or None for all the subleaves of `function`.
+    pub index: Option<u32>,
+    pub register: CpuIdRegister,
+    pub mask: u32,
+    pub value: u32,
+}
+
+impl CpuIdOverride {
+    /// Whether the override applies to the leaf `function` and subleaf `index`.
+    pub fn matches(&self, function: u32, index: u32) -> bool {
+        self.function == function && self.index.map_or(true, |i| i == index)
+    }
+
+    /// Overrides the bits of `result`.
+    pub fn apply(&self, result: &mut CpuidResult) {
+        let register = match self.register {
+            CpuIdRegister::Eax => &mut result.eax,
+            CpuIdRegister::Ebx => &mut result.ebx,
+            CpuIdRegister::Ecx => &mut result.ecx,
+            CpuIdRegister::Edx => &mut result.edx,
+        };
+        *register = (*register & !self.mask) | (self.value & self.mask);
+    }
+}
+
 /// A CpuId Entry contains supported feature information for the given processor.
 /// This can be modified by the hypervisor to pass additional information to the guest kernel
 /// about the hypervisor or vm. Information is returned in the eax, ebx, ecx and edx registers
@@ -458,7 +532,10 @@ pub struct MsiAddressMessage {
     #[bits = 1]
     pub destination_mode: DestinationMode,
     pub redirection_hint: BitField1,
-    pub reserved_2: BitField8,
+    pub reserved_2: BitField1,
+    // Bits 14:8 of the destination APIC ID, for hypervisors advertising
+    // KVM_FEATURE_MSI_EXT_DEST_ID.
+    pub ext_destination_id: BitField7,
     pub destination_id: BitField8,
     // According to Intel's implementation of MSI, these bits must always be 0xfee.
     pub always_0xfee: BitField12,
@@ -509,7 +586,10 @@ pub struct IoapicRedirectionTableEntry {
     #[bits = 1]
     trigger_mode: TriggerMode,
     interrupt_mask: bool, // true iff interrupts are masked.
-    reserved: BitField39,
+    reserved: BitField32,
+    // Bits 14:8 of the destination APIC ID, set like in MSIs by guests using extended destination
+    // IDs.
+    ext_dest_id: BitField7,
     dest_id: BitField8,
 }
 
@@ -186,6 +186,7 @@ impl SubmitQueue {
 
 /// Enum to represent all io_uring operations
 #[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum URingOperation {
     Nop = io_uring_op_IORING_OP_NOP,
     Readv = io_uring_op_IORING_OP_READV,
@@ -252,6 +253,32 @@ impl URingAllowlist {
     }
 }
 
+// Maximum number of opcodes reported by `URingContext::probe`.
+const PROBE_OPS_LEN: usize = 256;
+
+// `io_uring_probe` followed by the storage for its flexible `ops` array.
+#[repr(C)]
+struct ProbeBuffer {
+    probe: io_uring_probe,
+    ops: [io_uring_probe_op; PROBE_OPS_LEN],
+}
+
+/// The io_uring operations supported by the running kernel, as reported by `URingContext::probe`.
+#[derive(Clone, Debug)]
+pub struct URingProbe {
+    supported: Vec<bool>,
+}
+
+impl URingProbe {
+    /// Returns true if the kernel supports submitting `operation`.
+    pub fn is_supported(&self, operation: URingOperation) -> bool {
+        self.supported
+            .get(operation as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
 /// Unsafe wrapper for the kernel's io_uring interface. Allows for queueing multiple I/O operations
 /// to the kernel and asynchronously handling the completion of these operations.
 /// Use the various `add_*` functions to configure operations, then call `wait` to start
@@ -503,6 +530,34 @@ impl URingContext {
         Ok(())
     }
 
+    /// Queries the kernel for the operations it supports. Requires Linux 5.6 or newer; older
+    /// kernels fail with `EINVAL`.
+    pub fn probe(&self) -> Result<URingProbe> {
+        let mut buffer = ProbeBuffer {
+            probe: io_uring_probe::default(),
+            ops: [io_uring_probe_op::default(); PROBE_OPS_LEN],
+        };
+        // SAFETY:
+        // Safe because the kernel writes at most `PROBE_OPS_LEN` entries after the probe header,
+        // all of which fit in `buffer`.
+        unsafe {
+            io_uring_register(
+                self.ring_file.as_raw_fd(),
+                io_uring_register_op_IORING_REGISTER_PROBE,
+                &mut buffer as *mut ProbeBuffer as *const c_void,
+                PROBE_OPS_LEN as u32,
+            )
+        }
+        .map_err(Error::RingRegister)?;
+
+        let len = (buffer.probe.ops_len as usize).min(PROBE_OPS_LEN);
+        let supported = buffer.ops[..len]
+            .iter()
+            .map(|op| u32::from(op.flags) & IO_URING_OP_SUPPORTED != 0)
+            .collect();
+        Ok(URingProbe { supported })
+    }
+
     /// Add a no-op operation that doesn't perform any IO. Useful for testing the performance of the
     /// io_uring itself and for waking up a thread that's blocked inside a wait() call.
     pub fn add_nop(&self, user_data: UserData) -> Result<()> {
@@ -34,6 +34,7 @@ use base::WaitContext;
 use io_uring::Error;
 use io_uring::URingAllowlist;
 use io_uring::URingContext;
+use io_uring::URingOperation;
 use io_uring::UserData;
 use libc::EACCES;
 use sync::Condvar;
@@ -417,6 +418,16 @@ fn dev_zero_readable() {
     assert_eq!(res.unwrap(), 1_u32);
 }
 
+#[test]
+fn probe_operations() {
+    let uring = URingContext::new(16, None).unwrap();
+    let probe = uring.probe().unwrap();
+    // Every kernel that supports probing also supports these.
+    assert!(probe.is_supported(URingOperation::Nop));
+    assert!(probe.is_supported(URingOperation::Readv));
+    assert!(probe.is_supported(URingOperation::PollAdd));
+}
+
 #[test]
 fn queue_many_ebusy_retry() {
     let num_entries = 16;
@@ -90,6 +90,22 @@ fn compile_policies(out_dir: &Path, rewrote_policy_folder: &Path, compile_seccom
     fs::write(out_dir.join("bpf_includes.in"), include_all_bytes).unwrap();
 }
 
+fn embed_policies(out_dir: &Path, rewrote_policy_folder: &Path) {
+    let mut include_all_policies = String::from("std::collections::HashMap::from([\n");
+    for entry in fs::read_dir(rewrote_policy_folder).unwrap() {
+        let path = entry.unwrap().path();
+        let extension = path.extension();
+        if extension != Some(OsStr::new("policy")) && extension != Some(OsStr::new("fragment")) {
+            continue;
+        }
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        include_all_policies +=
+            &format!(r#"("{0}", include_str!("policy_input/{0}")),"#, file_name);
+    }
+    include_all_policies += "])";
+    fs::write(out_dir.join("policy_includes.in"), include_all_policies).unwrap();
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=seccomp");
@@ -129,4 +145,5 @@ fn main() {
     fs::create_dir_all(&rewrote_policy_folder).unwrap();
     rewrite_policies(&seccomp_policy_path, &rewrote_policy_folder);
     compile_policies(&out_dir, &rewrote_policy_folder, &compile_seccomp_policy);
+    embed_policies(&out_dir, &rewrote_policy_folder);
 }
@@ -5,6 +5,9 @@
 #![deny(missing_docs)]
 #![allow(dead_code)]
 
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::str;
 use std::sync::LazyLock;
@@ -18,6 +21,9 @@ use base::getegid;
 use base::geteuid;
 #[cfg(feature = "seccomp_trace")]
 use base::warn;
+use base::AsRawDescriptor;
+use base::SafeDescriptor;
+use base::SharedMemory;
 use libc::c_ulong;
 use minijail::Minijail;
 #[cfg(feature = "seccomp_trace")]
@@ -28,6 +34,9 @@ use zerocopy::Immutable;
 use zerocopy::IntoBytes;
 
 use crate::config::JailConfig;
+use crate::seccomp::compose_policy;
+use crate::seccomp::default_seccomp_features;
+use crate::seccomp::SeccompFeature;
 
 static EMBEDDED_BPFS: LazyLock<std::collections::HashMap<&str, Vec<u8>>> =
     LazyLock::new(|| include!(concat!(env!("OUT_DIR"), "/bpf_includes.in")));
@@ -73,6 +82,9 @@ pub struct SandboxConfig<'a> {
     pub bind_mounts: bool,
     /// Specify the user in the jail to run as.
     pub run_as: RunAsUser,
+    /// Optional features whose syscalls are added to the seccomp policy. Defaults to the ones set
+    /// with `set_default_seccomp_features()`.
+    pub seccomp_features: BTreeSet<SeccompFeature>,
 }
 
 impl<'a> SandboxConfig<'a> {
@@ -88,6 +100,7 @@ impl<'a> SandboxConfig<'a> {
             namespace_net: true,
             bind_mounts: false,
             run_as: RunAsUser::Unspecified,
+            seccomp_features: default_seccomp_features(),
         }
     }
 }
@@ -259,6 +272,19 @@ pub fn create_sandbox_minijail(
     // Don't allow the device to gain new privileges.
     jail.no_new_privs();
 
+    apply_seccomp_policy(&mut jail, config)?;
+    // Don't do init setup.
+    jail.run_as_init();
+    // Set up requested remount mode instead of default MS_PRIVATE.
+    if let Some(mode) = config.remount_mode {
+        jail.set_remount_mode(mode);
+    }
+
+    Ok(jail)
+}
+
+/// Applies the seccomp policy of `config` to `jail`.
+fn apply_seccomp_policy(jail: &mut Minijail, config: &SandboxConfig) -> Result<()> {
     #[cfg(feature = "seccomp_trace")]
     {
         #[repr(C)]
@@ -290,14 +316,18 @@ pub fn create_sandbox_minijail(
         debug!(
             "seccomp_trace {{\"event\": \"minijail_create\", \"name\": \"{}\", \"jail_addr\": \"0x{:x}\"}}",
             config.seccomp_policy_name,
-            read_jail_addr(&jail),
+            read_jail_addr(jail),
         );
         jail.parse_seccomp_bytes(FILTER_RET_LOG_BLOCK.as_bytes())
             .unwrap();
     }
 
     #[cfg(not(feature = "seccomp_trace"))]
-    if let Some(seccomp_policy_dir) = config.seccomp_policy_dir {
+    if !config.seccomp_features.is_empty() {
+        // The pre-compiled policies don't include the rules of optional features, so the policy
+        // has to be composed and parsed here.
+        set_composed_seccomp_filter(jail, config)?;
+    } else if let Some(seccomp_policy_dir) = config.seccomp_policy_dir {
         let seccomp_policy_path = seccomp_policy_dir.join(config.seccomp_policy_name);
         // By default we'll prioritize using the pre-compiled .bpf over the .policy file (the .bpf
         // is expected to be compiled using "trap" as the failure behavior instead of the default
@@ -332,17 +362,27 @@ pub fn create_sandbox_minijail(
                 })?;
         }
     } else {
-        set_embedded_bpf_program(&mut jail, config.seccomp_policy_name)?;
+        set_embedded_bpf_program(jail, config.seccomp_policy_name)?;
     }
 
     jail.use_seccomp_filter();
-    // Don't do init setup.
-    jail.run_as_init();
-    // Set up requested remount mode instead of default MS_PRIVATE.
-    if let Some(mode) = config.remount_mode {
-        jail.set_remount_mode(mode);
-    }
+    Ok(())
+}
 
+/// Creates a [Minijail] instance which runs a device in a process of its own with the seccomp
+/// policy of `config`, but otherwise shares the root, namespaces, user and capabilities of crosvm.
+/// Used for devices that are isolated while the sandbox is disabled.
+///
+/// # Arguments
+///
+/// * `max_open_files` - The maximum number of file descriptors to allow a jailed process to open.
+/// * `config` - The [SandboxConfig] whose seccomp policy and features are applied.
+pub fn create_seccomp_minijail(max_open_files: u64, config: &SandboxConfig) -> Result<Minijail> {
+    let mut jail = create_base_minijail(Path::new("/"), max_open_files)?;
+    // Don't allow the device to gain new privileges, which also lets an unprivileged process
+    // install the seccomp filter.
+    jail.no_new_privs();
+    apply_seccomp_policy(&mut jail, config)?;
     Ok(jail)
 }
 
@@ -543,3 +583,40 @@ pub fn set_embedded_bpf_program(jail: &mut Minijail, seccomp_policy_name: &str)
     })?;
     Ok(())
 }
+
+/// Set the seccomp policy for a jail from the policy of `config` combined with the fragments of its
+/// seccomp features.
+fn set_composed_seccomp_filter(jail: &mut Minijail, config: &SandboxConfig) -> Result<()> {
+    let policy = compose_policy(
+        config.seccomp_policy_name,
+        config.seccomp_policy_dir,
+        &config.seccomp_features,
+    )
+    .with_context(|| {
+        format!(
+            "failed to compose seccomp policy {} for {:?}",
+            config.seccomp_policy_name, config.seccomp_features
+        )
+    })?;
+
+    // minijail only parses policies from files, so hand it the composed policy through a memfd.
+    let shm = SharedMemory::new(config.seccomp_policy_name, policy.len() as u64)
+        .context("failed to create the composed seccomp policy file")?;
+    let mut file = File::from(SafeDescriptor::from(shm));
+    file.write_all(policy.as_bytes())
+        .context("failed to write the composed seccomp policy file")?;
+    let policy_path = format!("/proc/self/fd/{}", file.as_raw_descriptor());
+
+    jail.set_seccomp_filter_tsync();
+    if config.log_failures {
+        jail.log_seccomp_filter_failures();
+    }
+    jail.parse_seccomp_filters(Path::new(&policy_path))
+        .with_context(|| {
+            format!(
+                "failed to parse composed seccomp policy {}",
+                config.seccomp_policy_name
+            )
+        })?;
+    Ok(())
+}
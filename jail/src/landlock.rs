@@ -0,0 +1,251 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Filesystem confinement of sandboxed processes with Landlock.
+//!
+//! A [LandlockRuleset] is built in the parent from host paths, before the child pivots into its
+//! jail, and is then enforced by the child with [LandlockRuleset::restrict_self]. Landlock rules
+//! are bound to inodes, so they keep matching the bind-mounted copies of the paths inside the jail,
+//! and also apply to lookups relative to directory descriptors inherited from the parent.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::ptr::null;
+
+use base::AsRawDescriptor;
+use base::FromRawDescriptor;
+use base::RawDescriptor;
+use base::SafeDescriptor;
+
+// Syscall numbers are the same on all architectures.
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+// ABI version 2.
+const LANDLOCK_ACCESS_FS_REFER: u64 = 1 << 13;
+// ABI version 3.
+const LANDLOCK_ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+/// Rights that can be granted on a regular file, as opposed to a directory.
+const ACCESS_FILE: u64 = LANDLOCK_ACCESS_FS_EXECUTE
+    | LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_READ_FILE
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+const ACCESS_READ: u64 = LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR;
+
+const ACCESS_WRITE: u64 = LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_REMOVE_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_FILE
+    | LANDLOCK_ACCESS_FS_MAKE_CHAR
+    | LANDLOCK_ACCESS_FS_MAKE_DIR
+    | LANDLOCK_ACCESS_FS_MAKE_REG
+    | LANDLOCK_ACCESS_FS_MAKE_SOCK
+    | LANDLOCK_ACCESS_FS_MAKE_FIFO
+    | LANDLOCK_ACCESS_FS_MAKE_BLOCK
+    | LANDLOCK_ACCESS_FS_MAKE_SYM
+    | LANDLOCK_ACCESS_FS_REFER
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+#[repr(C)]
+struct landlock_ruleset_attr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct landlock_path_beneath_attr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Access granted to a path and, for directories, everything beneath it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LandlockAccess {
+    /// Read files and list directories.
+    ReadOnly,
+    /// Read, write, create and remove files and directories.
+    ReadWrite,
+}
+
+/// A path a sandboxed process needs to open after it has been confined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LandlockRule {
+    /// Host path of the file or directory.
+    pub path: PathBuf,
+    /// Access granted to `path`.
+    pub access: LandlockAccess,
+}
+
+impl LandlockRule {
+    /// Creates a rule granting `access` to `path`.
+    pub fn new<P: Into<PathBuf>>(path: P, access: LandlockAccess) -> Self {
+        LandlockRule {
+            path: path.into(),
+            access,
+        }
+    }
+}
+
+/// Returns the Landlock ABI version supported by the kernel, or `None` if Landlock is unavailable.
+pub fn landlock_abi_version() -> Option<u32> {
+    // SAFETY:
+    // Safe because a null attribute with a zero size only queries the ABI version.
+    let ret = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            null::<landlock_ruleset_attr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if ret <= 0 {
+        None
+    } else {
+        Some(ret as u32)
+    }
+}
+
+/// A Landlock ruleset handling all the filesystem rights known to the running kernel.
+///
+/// Any access not granted by a rule is denied once the ruleset is enforced.
+pub struct LandlockRuleset {
+    ruleset: SafeDescriptor,
+    handled_access: u64,
+}
+
+impl LandlockRuleset {
+    /// Creates an empty ruleset. Returns `Ok(None)` if the kernel does not support Landlock.
+    pub fn new() -> io::Result<Option<LandlockRuleset>> {
+        let all_access = ACCESS_READ | ACCESS_WRITE | LANDLOCK_ACCESS_FS_EXECUTE;
+        let handled_access = match landlock_abi_version() {
+            None => return Ok(None),
+            Some(1) => all_access & !(LANDLOCK_ACCESS_FS_REFER | LANDLOCK_ACCESS_FS_TRUNCATE),
+            Some(2) => all_access & !LANDLOCK_ACCESS_FS_TRUNCATE,
+            Some(_) => all_access,
+        };
+        let attr = landlock_ruleset_attr {
+            handled_access_fs: handled_access,
+        };
+        // SAFETY:
+        // Safe because the kernel only reads `attr`, whose size is passed along with it.
+        let ret = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const landlock_ruleset_attr,
+                std::mem::size_of::<landlock_ruleset_attr>(),
+                0u32,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(LandlockRuleset {
+            // SAFETY:
+            // Safe because the syscall returned a new descriptor that nothing else owns.
+            ruleset: unsafe { SafeDescriptor::from_raw_descriptor(ret as RawDescriptor) },
+            handled_access,
+        }))
+    }
+
+    /// Creates a ruleset granting exactly `rules`. Returns `Ok(None)` if the kernel does not
+    /// support Landlock.
+    pub fn from_rules(rules: &[LandlockRule]) -> io::Result<Option<LandlockRuleset>> {
+        let Some(mut ruleset) = LandlockRuleset::new()? else {
+            return Ok(None);
+        };
+        for rule in rules {
+            ruleset.add_rule(&rule.path, rule.access)?;
+        }
+        Ok(Some(ruleset))
+    }
+
+    /// Grants `access` to `path` and, if it is a directory, to everything beneath it.
+    pub fn add_rule(&mut self, path: &Path, access: LandlockAccess) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY:
+        // Safe because `c_path` is a valid NUL-terminated string and we check the result.
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY:
+        // Safe because we just opened `fd` and nothing else owns it.
+        let parent = unsafe { SafeDescriptor::from_raw_descriptor(fd) };
+
+        let mut allowed_access = match access {
+            LandlockAccess::ReadOnly => ACCESS_READ,
+            LandlockAccess::ReadWrite => ACCESS_READ | ACCESS_WRITE,
+        } & self.handled_access;
+        if !path.is_dir() {
+            // Directory rights are rejected on rules for anything else.
+            allowed_access &= ACCESS_FILE;
+        }
+
+        let attr = landlock_path_beneath_attr {
+            allowed_access,
+            parent_fd: parent.as_raw_descriptor(),
+        };
+        // SAFETY:
+        // Safe because the kernel only reads `attr` and both descriptors are valid.
+        let ret = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE,
+                self.ruleset.as_raw_descriptor(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const landlock_path_beneath_attr,
+                0u32,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Enforces the ruleset on the calling thread and the threads and processes it creates later.
+    ///
+    /// The caller must already run with `no_new_privs`, which the sandbox jails set.
+    pub fn restrict_self(self) -> io::Result<()> {
+        // SAFETY:
+        // Safe because the syscall does not touch our memory and we check the result.
+        let ret = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_RESTRICT_SELF,
+                self.ruleset.as_raw_descriptor(),
+                0u32,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawDescriptor for LandlockRuleset {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.ruleset.as_raw_descriptor()
+    }
+}
@@ -7,12 +7,20 @@ mod config;
 pub mod fork;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 mod helpers;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub mod landlock;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod seccomp;
 
 pub use crate::config::JailConfig;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 pub use crate::fork::fork_process;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 pub use crate::helpers::*;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use crate::seccomp::set_default_seccomp_features;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use crate::seccomp::SeccompFeature;
 
 // TODO(b/268407006): We define Minijail as an empty struct as a stub for minijail::Minijail on
 // Windows because the concept of jailing is baked into a bunch of places where it isn't easy to
@@ -0,0 +1,316 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Composition of seccomp policies from per-feature fragments.
+//!
+//! Syscalls that are only needed when an optional feature is in use (e.g. the io_uring async
+//! executor) are kept out of the device policies and live in `<feature>.fragment` files next to
+//! them. When a sandbox is created with features enabled, the device policy is expanded, the
+//! fragments of the features are merged into it, and the result is parsed by minijail instead of
+//! the pre-compiled BPF program.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+static EMBEDDED_POLICIES: LazyLock<HashMap<&str, &str>> =
+    LazyLock::new(|| include!(concat!(env!("OUT_DIR"), "/policy_includes.in")));
+
+/// Optional features whose syscalls are added to device seccomp policies on demand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SeccompFeature {
+    /// The io_uring async executor.
+    IoUring,
+}
+
+impl SeccompFeature {
+    /// Name of the policy fragment holding the rules of the feature, without extension.
+    fn fragment_name(self) -> &'static str {
+        match self {
+            SeccompFeature::IoUring => "io_uring",
+        }
+    }
+
+    /// Syscalls that the feature cannot work without.
+    fn required_syscalls(self) -> &'static [&'static str] {
+        match self {
+            SeccompFeature::IoUring => &["io_uring_setup", "io_uring_register", "io_uring_enter"],
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            SeccompFeature::IoUring => "the io_uring async executor",
+        }
+    }
+}
+
+static DEFAULT_SECCOMP_FEATURES: OnceLock<BTreeSet<SeccompFeature>> = OnceLock::new();
+
+/// Sets the features added to the policy of every sandbox created afterwards in this process, such
+/// as the ones needed by the async executor the caller picked. No feature is added by default.
+pub fn set_default_seccomp_features(features: BTreeSet<SeccompFeature>) -> Result<()> {
+    DEFAULT_SECCOMP_FEATURES
+        .set(features)
+        .map_err(|_| anyhow!("the default seccomp features are already set"))
+}
+
+/// Returns the features set by `set_default_seccomp_features()`.
+pub(crate) fn default_seccomp_features() -> BTreeSet<SeccompFeature> {
+    DEFAULT_SECCOMP_FEATURES.get().cloned().unwrap_or_default()
+}
+
+/// Returns the content of the policy file `file_name`, from `policy_dir` if set or from the
+/// policies embedded in the binary otherwise.
+fn load_policy_file(policy_dir: Option<&Path>, file_name: &str) -> Result<String> {
+    match policy_dir {
+        Some(policy_dir) => {
+            let path = policy_dir.join(file_name);
+            fs::read_to_string(&path)
+                .with_context(|| format!("failed to read seccomp policy {}", path.display()))
+        }
+        None => EMBEDDED_POLICIES
+            .get(file_name)
+            .map(|content| content.to_string())
+            .with_context(|| format!("failed to find embedded seccomp policy {}", file_name)),
+    }
+}
+
+/// Returns whether `rule` lets the syscall through, possibly depending on its arguments.
+fn rule_allows(rule: &str) -> bool {
+    !matches!(
+        rule,
+        "0" | "kill" | "kill-process" | "kill-thread" | "trap" | "trace" | "log"
+    ) && !rule.starts_with("return ")
+        && !rule.contains(';')
+}
+
+/// Combines two rules for the same syscall so that the result allows the calls allowed by either.
+///
+/// Returns `None` if the rules cannot be combined, for instance because one of them returns an
+/// error instead of allowing the call.
+fn merge_rules(existing: &str, new: &str) -> Option<String> {
+    if !rule_allows(existing) || !rule_allows(new) {
+        return None;
+    }
+    if existing == "1" || new == "1" {
+        return Some("1".to_string());
+    }
+    if existing == new {
+        return Some(existing.to_string());
+    }
+    Some(format!("{} || {}", existing, new))
+}
+
+/// The rules of a policy, with its `@include` directives expanded.
+#[derive(Default)]
+struct PolicyRules {
+    rules: BTreeMap<String, String>,
+}
+
+impl PolicyRules {
+    /// Parses `content` and merges its rules in, loading included files with `load`.
+    fn add_policy(
+        &mut self,
+        name: &str,
+        content: &str,
+        load: &dyn Fn(&str) -> Result<String>,
+        depth: usize,
+    ) -> Result<()> {
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("@frequency") {
+                continue;
+            }
+            if let Some(include) = line.strip_prefix("@include") {
+                // Minijail only supports a single level of inclusion.
+                if depth > 0 {
+                    bail!(
+                        "{}: nested @include of {} is not supported",
+                        name,
+                        include.trim()
+                    );
+                }
+                let file_name = Path::new(include.trim())
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .with_context(|| format!("{}: invalid @include: {}", name, line))?;
+                let included = load(file_name)?;
+                self.add_policy(file_name, &included, load, depth + 1)?;
+                continue;
+            }
+            let (syscall, rule) = line
+                .split_once(':')
+                .with_context(|| format!("{}: invalid rule: {}", name, line))?;
+            self.add_rule(name, syscall.trim(), rule.trim())?;
+        }
+        Ok(())
+    }
+
+    fn add_rule(&mut self, name: &str, syscall: &str, rule: &str) -> Result<()> {
+        let merged = match self.rules.get(syscall) {
+            Some(existing) => merge_rules(existing, rule).with_context(|| {
+                format!(
+                    "{}: conflicting rules for {}: `{}` and `{}`",
+                    name, syscall, existing, rule
+                )
+            })?,
+            None => rule.to_string(),
+        };
+        self.rules.insert(syscall.to_string(), merged);
+        Ok(())
+    }
+
+    fn to_policy(&self) -> String {
+        self.rules
+            .iter()
+            .map(|(syscall, rule)| format!("{}: {}\n", syscall, rule))
+            .collect()
+    }
+}
+
+fn compose_policy_with(
+    policy_name: &str,
+    features: &BTreeSet<SeccompFeature>,
+    load: &dyn Fn(&str) -> Result<String>,
+    load_fragment: &dyn Fn(&str) -> Result<String>,
+) -> Result<String> {
+    let file_name = format!("{}.policy", policy_name);
+    let mut policy = PolicyRules::default();
+    policy.add_policy(&file_name, &load(&file_name)?, load, 0)?;
+
+    for feature in features {
+        let fragment_name = format!("{}.fragment", feature.fragment_name());
+        let fragment = load_fragment(&fragment_name)?;
+        policy
+            .add_policy(&fragment_name, &fragment, load, 1)
+            .with_context(|| {
+                format!(
+                    "seccomp policy {} cannot be combined with {}",
+                    policy_name,
+                    feature.description()
+                )
+            })?;
+        for syscall in feature.required_syscalls() {
+            if !policy.rules.contains_key(*syscall) {
+                bail!(
+                    "seccomp policy {} does not allow {}, which is needed by {}",
+                    policy_name,
+                    syscall,
+                    feature.description()
+                );
+            }
+        }
+    }
+
+    Ok(policy.to_policy())
+}
+
+/// Builds the text of the seccomp policy `policy_name` extended with the fragments of `features`.
+///
+/// Policies are read from `policy_dir` if set, or from the ones embedded in the binary otherwise.
+/// Fragments missing from `policy_dir` are taken from the embedded ones. Fails if the rules of a
+/// fragment conflict with the policy or if a syscall needed by one of the features is missing.
+pub(crate) fn compose_policy(
+    policy_name: &str,
+    policy_dir: Option<&Path>,
+    features: &BTreeSet<SeccompFeature>,
+) -> Result<String> {
+    let load = |file_name: &str| load_policy_file(policy_dir, file_name);
+    let load_fragment = |file_name: &str| match policy_dir {
+        Some(dir) if dir.join(file_name).exists() => load_policy_file(policy_dir, file_name),
+        _ => load_policy_file(None, file_name),
+    };
+    compose_policy_with(policy_name, features, &load, &load_fragment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loader(files: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Result<String> {
+        move |file_name| {
+            files
+                .iter()
+                .find(|(name, _)| *name == file_name)
+                .map(|(_, content)| content.to_string())
+                .ok_or_else(|| anyhow!("no such file: {}", file_name))
+        }
+    }
+
+    #[test]
+    fn merge() {
+        assert_eq!(merge_rules("1", "arg0 == 1").as_deref(), Some("1"));
+        assert_eq!(merge_rules("arg0 == 1", "1").as_deref(), Some("1"));
+        assert_eq!(
+            merge_rules("arg0 == 1", "arg0 == 2").as_deref(),
+            Some("arg0 == 1 || arg0 == 2")
+        );
+        assert_eq!(merge_rules("return ENOSYS", "1"), None);
+        assert_eq!(merge_rules("arg0 == 1; return EPERM", "1"), None);
+    }
+
+    #[test]
+    fn compose() {
+        let load = loader(&[
+            (
+                "dev.policy",
+                "@include /usr/share/policy/crosvm/common.policy\nioctl: arg1 == 1\n",
+            ),
+            (
+                "common.policy",
+                "@frequency ./common.frequency\n# c\nread: 1\n",
+            ),
+        ]);
+        let load_fragment = loader(&[(
+            "io_uring.fragment",
+            "io_uring_setup: 1\nio_uring_register: 1\nio_uring_enter: 1\nioctl: arg1 == 2\n",
+        )]);
+
+        let policy = compose_policy_with("dev", &BTreeSet::new(), &load, &load_fragment).unwrap();
+        assert_eq!(policy, "ioctl: arg1 == 1\nread: 1\n");
+
+        let features = BTreeSet::from([SeccompFeature::IoUring]);
+        let policy = compose_policy_with("dev", &features, &load, &load_fragment).unwrap();
+        assert_eq!(
+            policy,
+            "io_uring_enter: 1\nio_uring_register: 1\nio_uring_setup: 1\n\
+             ioctl: arg1 == 1 || arg1 == 2\nread: 1\n"
+        );
+    }
+
+    #[test]
+    fn compose_conflict() {
+        let load = loader(&[("dev.policy", "io_uring_setup: return ENOSYS\n")]);
+        let load_fragment = loader(&[("io_uring.fragment", "io_uring_setup: 1\n")]);
+        let features = BTreeSet::from([SeccompFeature::IoUring]);
+        assert!(compose_policy_with("dev", &features, &load, &load_fragment).is_err());
+    }
+
+    #[test]
+    fn compose_missing_syscall() {
+        let load = loader(&[("dev.policy", "read: 1\n")]);
+        let load_fragment = loader(&[("io_uring.fragment", "io_uring_setup: 1\n")]);
+        let features = BTreeSet::from([SeccompFeature::IoUring]);
+        let err = compose_policy_with("dev", &features, &load, &load_fragment).unwrap_err();
+        assert!(err.to_string().contains("io_uring_register"));
+    }
+
+    #[test]
+    fn compose_embedded() {
+        let features = BTreeSet::from([SeccompFeature::IoUring]);
+        let policy = compose_policy("block_device", None, &features).unwrap();
+        assert!(policy.contains("io_uring_enter: 1\n"));
+        assert!(!policy.contains('@'));
+    }
+}
@@ -4,13 +4,22 @@
 
 #[cfg(any(target_os = "android", target_os = "linux"))]
 mod test {
+    use std::fs::OpenOptions;
     use std::thread;
     use std::time::Duration;
 
     use base::getpid;
     use base::AsRawDescriptor;
     use base::Tube;
+    use jail::create_seccomp_minijail;
     use jail::fork::fork_process;
+    use jail::landlock::landlock_abi_version;
+    use jail::landlock::LandlockAccess;
+    use jail::landlock::LandlockRule;
+    use jail::landlock::LandlockRuleset;
+    use jail::JailConfig;
+    use jail::SandboxConfig;
+    use jail::MAX_OPEN_FILES_DEFAULT;
     use minijail::Minijail;
 
     pub fn pid_diff() {
@@ -100,6 +109,60 @@ mod test {
 
         assert_eq!(child.wait().unwrap(), 101);
     }
+
+    pub fn seccomp_minijail_confines_child() {
+        let jail_config = JailConfig::default();
+        let config = SandboxConfig::new(&jail_config, "rng_device");
+        let jail = create_seccomp_minijail(MAX_OPEN_FILES_DEFAULT, &config).unwrap();
+        let (tube, fork_tube) = Tube::pair().expect("failed to create tube");
+        let keep_rds = vec![fork_tube.as_raw_descriptor()];
+
+        let pid = getpid();
+        let child = fork_process(jail, keep_rds, None, || {
+            fork_tube.send(&(getpid() != pid)).unwrap();
+            // chroot(2) is not allowed by the policy of the rng device.
+            // SAFETY: the path is a valid NUL-terminated string.
+            unsafe { libc::chroot(c"/".as_ptr()) };
+        })
+        .expect("failed to fork");
+
+        // The child runs in a process of its own and is killed by its seccomp filter.
+        assert!(tube.recv::<bool>().unwrap());
+        assert_eq!(child.wait().unwrap(), 128 + libc::SIGSYS as u8);
+    }
+
+    pub fn landlock_confines_child() {
+        if landlock_abi_version().is_none() {
+            println!("Landlock is not supported by the kernel, skipping");
+            return;
+        }
+        let ruleset = LandlockRuleset::from_rules(&[LandlockRule::new(
+            "/dev/null",
+            LandlockAccess::ReadWrite,
+        )])
+        .unwrap()
+        .unwrap();
+
+        let (tube, fork_tube) = Tube::pair().expect("failed to create tube");
+        let mut jail = Minijail::new().unwrap();
+        jail.no_new_privs();
+        let keep_rds = vec![fork_tube.as_raw_descriptor(), ruleset.as_raw_descriptor()];
+
+        let child = fork_process(jail, keep_rds, None, || {
+            ruleset.restrict_self().unwrap();
+            let allowed = OpenOptions::new().write(true).open("/dev/null").is_ok();
+            let denied = OpenOptions::new()
+                .read(true)
+                .open("/dev/zero")
+                .map_err(|e| e.raw_os_error())
+                == Err(Some(libc::EACCES));
+            fork_tube.send(&(allowed, denied)).unwrap();
+        })
+        .expect("failed to fork");
+
+        assert_eq!(tube.recv::<(bool, bool)>().unwrap(), (true, true));
+        assert_eq!(child.wait().unwrap(), 0);
+    }
 }
 
 fn main() {
@@ -135,6 +198,16 @@ fn main() {
             test::wait_for_panic();
             Ok(())
         }),
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        libtest_mimic::Trial::test("seccomp_minijail_confines_child", move || {
+            test::seccomp_minijail_confines_child();
+            Ok(())
+        }),
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        libtest_mimic::Trial::test("landlock_confines_child", move || {
+            test::landlock_confines_child();
+            Ok(())
+        }),
     ];
     libtest_mimic::run(&args, tests).exit();
 }
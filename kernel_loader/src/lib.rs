@@ -23,7 +23,7 @@ mod multiboot;
 #[allow(non_snake_case)]
 #[allow(non_upper_case_globals)]
 #[allow(clippy::all)]
-mod elf;
+pub mod elf;
 
 mod arm64;
 
@@ -52,6 +52,53 @@ pub enum MetricEventType {
     SnapshotSaveOverallLatency,
     SnapshotRestoreMemoryLatency,
     SnapshotRestoreOverallLatency,
+    /// A virtio-blk read completed. The value is its latency in microseconds.
+    BlockRead,
+    /// A virtio-blk write completed. The value is its latency in microseconds.
+    BlockWrite,
+    /// Frames were received by a virtio-net device. The value is the number of frames.
+    NetworkRxPackets,
+    /// Frames were transmitted by a virtio-net device. The value is the number of frames.
+    NetworkTxPackets,
     Other(i64),
     Vendor(VendorMetricEventType),
 }
+
+/// Reason of a vCPU exit, for the vCPU exit statistics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum VcpuExitReason {
+    Io,
+    Mmio,
+    IoapicEoi,
+    Hlt,
+    IrqWindowOpen,
+    Hypercall,
+    Exception,
+    Debug,
+    MsrAccess,
+    Cpuid,
+    Intr,
+    SystemEvent,
+    Other,
+}
+
+impl VcpuExitReason {
+    /// Returns the name of the exit reason, suitable as a metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VcpuExitReason::Io => "io",
+            VcpuExitReason::Mmio => "mmio",
+            VcpuExitReason::IoapicEoi => "ioapic_eoi",
+            VcpuExitReason::Hlt => "hlt",
+            VcpuExitReason::IrqWindowOpen => "irq_window_open",
+            VcpuExitReason::Hypercall => "hypercall",
+            VcpuExitReason::Exception => "exception",
+            VcpuExitReason::Debug => "debug",
+            VcpuExitReason::MsrAccess => "msr_access",
+            VcpuExitReason::Cpuid => "cpuid",
+            VcpuExitReason::Intr => "intr",
+            VcpuExitReason::SystemEvent => "system_event",
+            VcpuExitReason::Other => "other",
+        }
+    }
+}
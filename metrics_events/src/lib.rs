@@ -6,5 +6,6 @@ mod event_types;
 pub mod sys;
 
 pub use event_types::MetricEventType;
+pub use event_types::VcpuExitReason;
 pub use metrics_events_product::MetricEventType as VendorMetricEventType;
 pub use metrics_events_product::RecordDetails;
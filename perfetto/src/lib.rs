@@ -17,6 +17,7 @@ use std::slice;
 use std::time::Duration;
 
 pub use bindings::*;
+pub use cros_tracing_types::category_mask::CategoryMask;
 pub use cros_tracing_types::static_strings::StaticString;
 use cros_tracing_types::TraceDuration;
 use protobuf::Message;
@@ -169,6 +170,27 @@ macro_rules! setup_perfetto {
                 )+
         ];
 
+        /// Names of our categories, indexed by `PerfettoCategory`.
+        pub const PERFETTO_CATEGORY_NAMES: [&str; PerfettoCategory::CATEGORY_COUNT as usize] = [
+            $(stringify!($cat),)+
+        ];
+
+        /// Categories enabled at runtime. All categories start enabled, and are then traced
+        /// whenever a trace session selects them.
+        pub static PERFETTO_CATEGORY_MASK: $crate::CategoryMask =
+            $crate::CategoryMask::new(u64::MAX);
+
+        /// Returns the active trace instances of the category at `index`, or 0 if the category
+        /// was disabled at runtime.
+        #[inline]
+        pub fn category_instances(index: usize) -> u32 {
+            if PERFETTO_CATEGORY_MASK.is_enabled(index) {
+                PERFETTO_CATEGORY_INSTANCES[index].load(std::sync::atomic::Ordering::SeqCst)
+            } else {
+                0
+            }
+        }
+
         /// Register the perfetto categories defined by this macro with the perfetto shared
         /// library. This should be called once at process startup.
         pub fn register_categories() {
@@ -218,9 +240,8 @@ macro_rules! setup_perfetto {
         macro_rules! trace_event {
             ($category:ident, $name:literal) => {
                 {
-                    let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                        [$mod::PerfettoCategory::$category as usize]
-                        .load(std::sync::atomic::Ordering::SeqCst);
+                    let instances =
+                        $mod::category_instances($mod::PerfettoCategory::$category as usize);
 
                     if instances != 0 {
                         let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -248,9 +269,8 @@ macro_rules! setup_perfetto {
         #[macro_export]
         macro_rules! trace_event_begin {
             ($category:ident, $name:expr) => {
-                let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                    [$mod::PerfettoCategory::$category as usize]
-                    .load(std::sync::atomic::Ordering::SeqCst);
+                let instances =
+                    $mod::category_instances($mod::PerfettoCategory::$category as usize);
 
                 if instances != 0 {
                     let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -273,9 +293,8 @@ macro_rules! setup_perfetto {
         #[macro_export]
         macro_rules! trace_event_end {
             ($category:ident) => {
-                let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                    [$mod::PerfettoCategory::$category as usize]
-                        .load(std::sync::atomic::Ordering::SeqCst);
+                let instances =
+                    $mod::category_instances($mod::PerfettoCategory::$category as usize);
 
                 if instances != 0 {
                     let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -293,9 +312,7 @@ macro_rules! setup_perfetto {
         macro_rules! trace_create_async {
             ($category:expr, $name:expr) => {
                 {
-                    let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                        [$category as usize]
-                            .load(std::sync::atomic::Ordering::SeqCst);
+                    let instances = $mod::category_instances($category as usize);
 
                     if instances != 0 {
                         let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -323,9 +340,7 @@ macro_rules! setup_perfetto {
         #[macro_export]
         macro_rules! trace_begin_async {
             ($category:expr, $name:expr, $optional_terminating_flow_id:expr) => {
-                let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                    [$category as usize]
-                        .load(std::sync::atomic::Ordering::SeqCst);
+                let instances = $mod::category_instances($category as usize);
 
                 if instances != 0 {
                     let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -356,9 +371,7 @@ macro_rules! setup_perfetto {
         macro_rules! trace_pause_async {
             ($category:expr) => {
                 {
-                    let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                        [$category as usize]
-                            .load(std::sync::atomic::Ordering::SeqCst);
+                    let instances = $mod::category_instances($category as usize);
 
                     if instances != 0 {
                         let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -385,9 +398,7 @@ macro_rules! setup_perfetto {
         #[macro_export]
         macro_rules! trace_end_async {
             ($category:expr) => {
-                let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                    [$category as usize]
-                        .load(std::sync::atomic::Ordering::SeqCst);
+                let instances = $mod::category_instances($category as usize);
 
                 if instances != 0 {
                     let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -416,9 +427,8 @@ macro_rules! setup_perfetto {
         #[macro_export]
         macro_rules! trace_counter {
             ($category:ident, $name:literal, $value:expr) => {
-                let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                    [$mod::PerfettoCategory::$category as usize]
-                        .load(std::sync::atomic::Ordering::SeqCst);
+                let instances =
+                    $mod::category_instances($mod::PerfettoCategory::$category as usize);
 
                 if instances != 0 {
                     let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -441,9 +451,8 @@ macro_rules! setup_perfetto {
                 // Required for safety when calling trace_counter.
                 let trace_point_name: $crate::StaticString = $name;
 
-                let instances = $mod::PERFETTO_CATEGORY_INSTANCES
-                    [$mod::PerfettoCategory::$category as usize]
-                        .load(std::sync::atomic::Ordering::SeqCst);
+                let instances =
+                    $mod::category_instances($mod::PerfettoCategory::$category as usize);
 
                 if instances != 0 {
                     let category_index = $mod::PERFETTO_CATEGORY_BASE
@@ -20,13 +20,13 @@ pub trait PowerClient: Send {
     fn last_request_timestamp(&self) -> Option<SystemTime>;
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PowerData {
     pub ac_online: bool,
     pub battery: Option<BatteryData>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BatteryData {
     pub status: BatteryStatus,
     pub percent: u32,
@@ -40,7 +40,7 @@ pub struct BatteryData {
     pub charge_full: u32,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BatteryStatus {
     Unknown,
     Charging,
@@ -71,6 +71,9 @@ impl<T> CreatePowerClientFn for T where
 #[cfg(feature = "powerd")]
 pub mod powerd;
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub mod sysfs;
+
 #[cfg(feature = "powerd")]
 mod protos {
     include!(concat!(env!("OUT_DIR"), "/protos/generated.rs"));
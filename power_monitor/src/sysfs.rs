@@ -0,0 +1,357 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Power monitor that mirrors the host's `power_supply` class from sysfs.
+//!
+//! <https://www.kernel.org/doc/Documentation/ABI/testing/sysfs-class-power>
+//!
+//! sysfs attributes cannot be waited on reliably, so the monitor polls them on a timer and only
+//! reports a new `PowerData` when something changed since the last report.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use base::AsRawDescriptor;
+use base::RawDescriptor;
+use base::ReadNotifier;
+use base::Timer;
+use base::TimerTrait;
+use remain::sorted;
+use thiserror::Error;
+
+use crate::BatteryData;
+use crate::BatteryStatus;
+use crate::PowerClient;
+use crate::PowerData;
+use crate::PowerMonitor;
+
+/// Directory holding one entry per power supply known to the host kernel.
+pub const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// How often the host power supplies are polled for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[sorted]
+#[derive(Error, Debug)]
+pub enum SysfsMonitorError {
+    #[error("failed to arm the poll timer: {0}")]
+    ArmTimer(base::Error),
+    #[error("failed to create the poll timer: {0}")]
+    CreateTimer(base::Error),
+    #[error("failed to read {0}: {1}")]
+    ReadPowerSupply(PathBuf, io::Error),
+    #[error("failed to wait for the poll timer: {0}")]
+    WaitTimer(base::Error),
+}
+
+type Result<T> = std::result::Result<T, SysfsMonitorError>;
+
+/// Reads an attribute of a power supply, returning `None` if the driver doesn't expose it.
+fn read_attr(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name))
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+fn read_attr_i64(supply: &Path, name: &str) -> Option<i64> {
+    read_attr(supply, name).and_then(|s| s.parse().ok())
+}
+
+/// Clamps a sysfs value to the unsigned 32-bit range used by `BatteryData`.
+fn to_u32(v: i64) -> u32 {
+    v.unsigned_abs().min(u32::MAX as u64) as u32
+}
+
+fn read_battery(supply: &Path) -> BatteryData {
+    let status = match read_attr(supply, "status").as_deref() {
+        Some("Charging") => BatteryStatus::Charging,
+        Some("Discharging") => BatteryStatus::Discharging,
+        Some("Not charging") | Some("Full") => BatteryStatus::NotCharging,
+        _ => BatteryStatus::Unknown,
+    };
+
+    // All values are in micro units. Some drivers only report energy (uWh) and power (uW), which
+    // are converted using the current voltage.
+    let voltage = read_attr_i64(supply, "voltage_now").unwrap_or(0);
+    let from_energy = |v: i64| {
+        if voltage != 0 {
+            v.saturating_mul(1_000_000) / voltage
+        } else {
+            0
+        }
+    };
+    let current = read_attr_i64(supply, "current_now")
+        .or_else(|| read_attr_i64(supply, "power_now").map(from_energy))
+        .unwrap_or(0);
+    let charge_counter = read_attr_i64(supply, "charge_now")
+        .or_else(|| read_attr_i64(supply, "energy_now").map(from_energy))
+        .unwrap_or(0);
+    let charge_full = read_attr_i64(supply, "charge_full")
+        .or_else(|| read_attr_i64(supply, "energy_full").map(from_energy))
+        .unwrap_or(0);
+
+    let percent = match read_attr_i64(supply, "capacity") {
+        Some(capacity) => capacity.clamp(0, 100) as u32,
+        None if charge_full > 0 => (charge_counter * 100 / charge_full).clamp(0, 100) as u32,
+        None => 0,
+    };
+
+    BatteryData {
+        status,
+        percent,
+        voltage: to_u32(voltage),
+        current: to_u32(current),
+        charge_counter: to_u32(charge_counter),
+        charge_full: to_u32(charge_full),
+    }
+}
+
+/// Builds a `PowerData` from the power supplies found under `root`.
+///
+/// The first present system battery in name order is reported, batteries of peripherals (scope
+/// `Device`) are ignored. Without any line power supply, AC is considered online as long as the
+/// battery isn't discharging.
+pub fn read_power_data(root: &Path) -> Result<PowerData> {
+    let mut supplies = fs::read_dir(root)
+        .and_then(|dir| {
+            dir.map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(|e| SysfsMonitorError::ReadPowerSupply(root.to_path_buf(), e))?;
+    supplies.sort();
+
+    let mut ac_online = None;
+    let mut battery = None;
+    for supply in supplies {
+        if read_attr(&supply, "scope").as_deref() == Some("Device") {
+            continue;
+        }
+        match read_attr(&supply, "type").as_deref() {
+            Some("Battery") => {
+                if battery.is_none() && read_attr_i64(&supply, "present").unwrap_or(1) != 0 {
+                    battery = Some(read_battery(&supply));
+                }
+            }
+            Some("Mains") | Some("USB") => {
+                let online = read_attr_i64(&supply, "online").unwrap_or(0) != 0;
+                ac_online = Some(ac_online.unwrap_or(false) || online);
+            }
+            _ => {}
+        }
+    }
+
+    let ac_online = ac_online.unwrap_or_else(|| {
+        !matches!(
+            battery,
+            Some(BatteryData {
+                status: BatteryStatus::Discharging,
+                ..
+            })
+        )
+    });
+
+    Ok(PowerData { ac_online, battery })
+}
+
+/// Polls the host power supplies and reports changes.
+pub struct SysfsMonitor {
+    root: PathBuf,
+    timer: Timer,
+    last_data: Option<PowerData>,
+}
+
+impl SysfsMonitor {
+    /// Creates a monitor of the supplies under `root`, polling them every `interval`.
+    pub fn new(root: PathBuf, interval: Duration) -> Result<Self> {
+        let mut timer = Timer::new().map_err(SysfsMonitorError::CreateTimer)?;
+        timer
+            .reset_repeating(interval)
+            .map_err(SysfsMonitorError::ArmTimer)?;
+        Ok(Self {
+            root,
+            timer,
+            last_data: None,
+        })
+    }
+
+    /// Creates a monitor of the host's power supply class.
+    pub fn connect() -> std::result::Result<Box<dyn PowerMonitor>, Box<dyn Error>> {
+        Ok(Box::new(Self::new(
+            PathBuf::from(POWER_SUPPLY_PATH),
+            POLL_INTERVAL,
+        )?))
+    }
+
+    /// Returns the current state of the power supplies if it differs from the last report.
+    fn poll(&mut self) -> Result<Option<PowerData>> {
+        let data = read_power_data(&self.root)?;
+        if self.last_data.as_ref() == Some(&data) {
+            return Ok(None);
+        }
+        self.last_data = Some(data.clone());
+        Ok(Some(data))
+    }
+}
+
+impl PowerMonitor for SysfsMonitor {
+    fn read_message(&mut self) -> std::result::Result<Option<PowerData>, Box<dyn Error>> {
+        self.timer
+            .mark_waited()
+            .map_err(SysfsMonitorError::WaitTimer)?;
+        Ok(self.poll()?)
+    }
+}
+
+impl AsRawDescriptor for SysfsMonitor {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.timer.as_raw_descriptor()
+    }
+}
+
+impl ReadNotifier for SysfsMonitor {
+    fn get_read_notifier(&self) -> &dyn AsRawDescriptor {
+        self
+    }
+}
+
+/// Reads the host's power supply class on demand, used to initialize the battery state before
+/// the first poll of `SysfsMonitor`.
+pub struct SysfsClient {
+    root: PathBuf,
+    last_request_timestamp: Option<SystemTime>,
+}
+
+impl SysfsClient {
+    pub fn connect() -> std::result::Result<Box<dyn PowerClient>, Box<dyn Error>> {
+        Ok(Box::new(Self {
+            root: PathBuf::from(POWER_SUPPLY_PATH),
+            last_request_timestamp: None,
+        }))
+    }
+}
+
+impl PowerClient for SysfsClient {
+    fn last_request_timestamp(&self) -> Option<SystemTime> {
+        self.last_request_timestamp
+    }
+
+    fn get_power_data(&mut self) -> std::result::Result<PowerData, Box<dyn Error>> {
+        self.last_request_timestamp = Some(SystemTime::now());
+        Ok(read_power_data(&self.root)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn add_supply(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let supply = root.join(name);
+        fs::create_dir(&supply).unwrap();
+        for (attr, value) in attrs {
+            fs::write(supply.join(attr), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn laptop_battery() {
+        let root = TempDir::new().unwrap();
+        add_supply(root.path(), "AC", &[("type", "Mains"), ("online", "0")]);
+        add_supply(
+            root.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("present", "1"),
+                ("status", "Discharging"),
+                ("capacity", "42"),
+                ("voltage_now", "12000000"),
+                ("current_now", "-1500000"),
+                ("charge_now", "2100000"),
+                ("charge_full", "5000000"),
+            ],
+        );
+        add_supply(
+            root.path(),
+            "hid-mouse-battery",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+        );
+
+        let data = read_power_data(root.path()).unwrap();
+        assert!(!data.ac_online);
+        assert_eq!(
+            data.battery,
+            Some(BatteryData {
+                status: BatteryStatus::Discharging,
+                percent: 42,
+                voltage: 12_000_000,
+                current: 1_500_000,
+                charge_counter: 2_100_000,
+                charge_full: 5_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn energy_only_battery() {
+        let root = TempDir::new().unwrap();
+        add_supply(
+            root.path(),
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("status", "Full"),
+                ("voltage_now", "10000000"),
+                ("power_now", "0"),
+                ("energy_now", "40000000"),
+                ("energy_full", "40000000"),
+            ],
+        );
+
+        let data = read_power_data(root.path()).unwrap();
+        // No line power supply and a battery that isn't discharging.
+        assert!(data.ac_online);
+        let battery = data.battery.unwrap();
+        assert_eq!(battery.status, BatteryStatus::NotCharging);
+        assert_eq!(battery.percent, 100);
+        assert_eq!(battery.charge_counter, 4_000_000);
+        assert_eq!(battery.charge_full, 4_000_000);
+    }
+
+    #[test]
+    fn no_battery() {
+        let root = TempDir::new().unwrap();
+        add_supply(root.path(), "ADP1", &[("type", "Mains"), ("online", "1")]);
+        add_supply(
+            root.path(),
+            "BAT0",
+            &[("type", "Battery"), ("present", "0")],
+        );
+
+        let data = read_power_data(root.path()).unwrap();
+        assert!(data.ac_online);
+        assert_eq!(data.battery, None);
+    }
+
+    #[test]
+    fn poll_reports_changes_only() {
+        let root = TempDir::new().unwrap();
+        add_supply(root.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+        let mut monitor =
+            SysfsMonitor::new(root.path().to_path_buf(), Duration::from_secs(60)).unwrap();
+
+        assert!(monitor.poll().unwrap().unwrap().ac_online);
+        assert_eq!(monitor.poll().unwrap(), None);
+
+        fs::write(root.path().join("AC/online"), "0\n").unwrap();
+        assert!(!monitor.poll().unwrap().unwrap().ac_online);
+    }
+}
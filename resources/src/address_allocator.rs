@@ -1026,18 +1026,21 @@ mod tests {
         )
         .unwrap();
         let pci_bar0 = Alloc::PciBar {
+            domain: 0,
             bus: 1,
             dev: 2,
             func: 0,
             bar: 0,
         };
         let pci_bar1 = Alloc::PciBar {
+            domain: 0,
             bus: 1,
             dev: 2,
             func: 0,
             bar: 1,
         };
         let pci_bar2 = Alloc::PciBar {
+            domain: 0,
             bus: 1,
             dev: 2,
             func: 0,
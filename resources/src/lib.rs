@@ -29,12 +29,20 @@ pub enum Alloc {
     /// Should only be instantiated through `SystemAllocator::get_anon_alloc()`.
     /// Avoid using these. Instead, use / create a more descriptive Alloc variant.
     Anon(usize),
-    /// A PCI BAR region with associated bus, device, function and bar numbers.
-    PciBar { bus: u8, dev: u8, func: u8, bar: u8 },
+    /// A PCI BAR region with associated domain, bus, device, function and bar numbers.
+    PciBar {
+        domain: u16,
+        bus: u8,
+        dev: u8,
+        func: u8,
+        bar: u8,
+    },
     /// GPU render node region.
     GpuRenderNode,
     /// Pmem device region with associated device index.
     PmemDevice(usize),
+    /// ivshmem shared memory region with associated device index.
+    IvshmemDevice(usize),
     /// pstore region.
     Pstore,
     /// A PCI bridge window with associated bus, dev, function.
@@ -43,6 +51,8 @@ pub enum Alloc {
     PciBridgePrefetchWindow { bus: u8, dev: u8, func: u8 },
     /// File-backed memory mapping.
     FileBacked(u64),
+    /// The memory window of an additional PCI segment with associated domain number.
+    PciSegment(u16),
 }
 
 #[sorted]
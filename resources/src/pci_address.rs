@@ -46,9 +46,11 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// PCI Device Address, AKA Bus:Device.Function
+/// PCI Device Address, AKA Domain:Bus:Device.Function
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PciAddress {
+    /// Domain (PCI segment group) number, in the range `0..=0xffff`.
+    pub domain: u16,
     /// Bus number, in the range `0..=255`.
     pub bus: u8,
     /// Device number, in the range `0..=31`.
@@ -91,11 +93,10 @@ impl<'de> Deserialize<'de> for PciAddress {
 /// ```
 impl Display for PciAddress {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let domain = 0;
         write!(
             f,
             "{:04x}:{:02x}:{:02x}.{:0x}",
-            domain, self.bus, self.dev, self.func,
+            self.domain, self.bus, self.dev, self.func,
         )
     }
 }
@@ -153,6 +154,8 @@ impl FromStr for PciAddress {
 }
 
 impl PciAddress {
+    #[doc(hidden)]
+    const DOMAIN_MASK: u32 = 0xffff;
     #[doc(hidden)]
     const BUS_MASK: u32 = 0x00ff;
     #[doc(hidden)]
@@ -170,7 +173,7 @@ impl PciAddress {
     ///
     /// # Arguments
     ///
-    /// * `domain` - The PCI domain number. Must be `0` in the current implementation.
+    /// * `domain` - The PCI domain number. Must be in the range `0..=0xffff`.
     /// * `bus` - The PCI bus number. Must be in the range `0..=255`.
     /// * `dev` - The PCI device number. Must be in the range `0..=31`.
     /// * `func` - The PCI function number. Must be in the range `0..=7`.
@@ -192,12 +195,12 @@ impl PciAddress {
             return Err(Error::ComponentOutOfRange(PciAddressComponent::Function));
         }
 
-        // PciAddress does not store domain for now, so disallow anything other than domain 0.
-        if domain > 0 {
+        if domain > Self::DOMAIN_MASK {
             return Err(Error::ComponentOutOfRange(PciAddressComponent::Domain));
         }
 
         Ok(PciAddress {
+            domain: domain as u16,
             bus: bus as u8,
             dev: dev as u8,
             func: func as u8,
@@ -212,7 +215,7 @@ impl PciAddress {
     /// The low two bits of the configuration address, which are technically part of the register
     /// number, are ignored, since PCI configuration space accesses must be DWORD (4-byte) aligned.
     ///
-    /// On success, returns a [`PciAddress`] and the extracted register index in DWORDs.
+    /// On success, returns a [`PciAddress`] in domain 0 and the extracted register index in DWORDs.
     ///
     /// # Arguments
     ///
@@ -239,7 +242,15 @@ impl PciAddress {
         let register_mask: u32 = (1_u32 << (register_bits_num - Self::REGISTER_OFFSET)) - 1;
         let register = ((config_address >> Self::REGISTER_OFFSET) & register_mask) as usize;
 
-        (PciAddress { bus, dev, func }, register)
+        (
+            PciAddress {
+                domain: 0,
+                bus,
+                dev,
+                func,
+            },
+            register,
+        )
     }
 
     /// Construct [`PciAddress`] from a system PCI path
@@ -331,14 +342,15 @@ impl PciAddress {
 
     /// Returns true if the address points to PCI root host-bridge.
     ///
-    /// This is true if and only if this is the all-zero address (`00:0.0`).
+    /// This is true if and only if this is the address `00:0.0` of any domain.
     pub fn is_root(&self) -> bool {
         matches!(
             &self,
             PciAddress {
                 bus: 0,
                 dev: 0,
-                func: 0
+                func: 0,
+                ..
             }
         )
     }
@@ -353,6 +365,7 @@ mod tests {
         assert_eq!(
             PciAddress::from_str("0000:00:00.0").unwrap(),
             PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 0,
                 func: 0
@@ -361,6 +374,7 @@ mod tests {
         assert_eq!(
             PciAddress::from_str("00:00.0").unwrap(),
             PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 0,
                 func: 0
@@ -369,6 +383,7 @@ mod tests {
         assert_eq!(
             PciAddress::from_str("01:02.3").unwrap(),
             PciAddress {
+                domain: 0,
                 bus: 1,
                 dev: 2,
                 func: 3
@@ -377,11 +392,21 @@ mod tests {
         assert_eq!(
             PciAddress::from_str("ff:1f.7").unwrap(),
             PciAddress {
+                domain: 0,
                 bus: 0xff,
                 dev: 0x1f,
                 func: 7,
             }
         );
+        assert_eq!(
+            PciAddress::from_str("0001:02:03.4").unwrap(),
+            PciAddress {
+                domain: 1,
+                bus: 2,
+                dev: 3,
+                func: 4,
+            }
+        );
     }
 
     #[test]
@@ -467,7 +492,7 @@ mod tests {
     #[test]
     fn from_string_invalid_domain_range() {
         assert_eq!(
-            PciAddress::from_str("0001:00:00.0").expect_err("parse should fail"),
+            PciAddress::from_str("10000:00:00.0").expect_err("parse should fail"),
             Error::ComponentOutOfRange(PciAddressComponent::Domain)
         );
     }
@@ -483,8 +508,8 @@ mod tests {
     #[test]
     fn format_max() {
         assert_eq!(
-            PciAddress::new(0, 0xff, 0x1f, 7).unwrap().to_string(),
-            "0000:ff:1f.7"
+            PciAddress::new(0xffff, 0xff, 0x1f, 7).unwrap().to_string(),
+            "ffff:ff:1f.7"
         );
     }
 
@@ -501,6 +526,7 @@ mod tests {
         assert_eq!(
             serde_json::from_str::<PciAddress>("\"0000:a5:1f.3\"").unwrap(),
             PciAddress {
+                domain: 0,
                 bus: 0xa5,
                 dev: 0x1f,
                 func: 3,
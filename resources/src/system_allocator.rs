@@ -109,14 +109,17 @@ pub struct SystemAllocatorConfig {
 pub struct SystemAllocator {
     io_address_space: Option<AddressAllocator>,
 
-    // Indexed by MmioType::Low and MmioType::High.
-    mmio_address_spaces: [AddressAllocator; 2],
+    // Indexed by MmioType::Low and MmioType::High, followed by the low and high memory windows of
+    // the additional PCI segments.
+    mmio_address_spaces: Vec<AddressAllocator>,
+    // Index in `mmio_address_spaces` of the low memory window of each additional PCI segment.
+    pci_segment_mmio: BTreeMap<u16, usize>,
     mmio_platform_address_spaces: Option<AddressAllocator>,
 
     reserved_region: Option<AddressRange>,
 
-    // Each bus number has a AddressAllocator
-    pci_allocator: BTreeMap<u8, AddressAllocator>,
+    // Each (domain, bus) number has a AddressAllocator
+    pci_allocator: BTreeMap<(u16, u8), AddressAllocator>,
     irq_allocator: AddressAllocator,
     gpe_allocator: AddressAllocator,
     next_anon_id: usize,
@@ -185,7 +188,7 @@ impl SystemAllocator {
             } else {
                 None
             },
-            mmio_address_spaces: [
+            mmio_address_spaces: vec![
                 // MmioType::Low
                 AddressAllocator::new_from_list(
                     intersect_mmio_range(config.low_mmio)?,
@@ -199,6 +202,7 @@ impl SystemAllocator {
                     None,
                 )?,
             ],
+            pci_segment_mmio: BTreeMap::new(),
 
             pci_allocator: BTreeMap::new(),
 
@@ -271,8 +275,8 @@ impl SystemAllocator {
             .ok()
     }
 
-    fn get_pci_allocator_mut(&mut self, bus: u8) -> Option<&mut AddressAllocator> {
-        match self.pci_allocator.entry(bus) {
+    fn get_pci_allocator_mut(&mut self, domain: u16, bus: u8) -> Option<&mut AddressAllocator> {
+        match self.pci_allocator.entry((domain, bus)) {
             btree_map::Entry::Occupied(entry) => Some(entry.into_mut()),
             btree_map::Entry::Vacant(entry) => {
                 // pci root is 00:00.0, Bus 0 next device is 00:01.0 with mandatory function number
@@ -297,18 +301,19 @@ impl SystemAllocator {
         }
     }
 
-    // Check whether devices exist or not on the specified bus
+    // Check whether devices exist or not on the specified bus of domain 0
     pub fn pci_bus_empty(&self, bus: u8) -> bool {
-        !self.pci_allocator.contains_key(&bus)
+        !self.pci_allocator.contains_key(&(0, bus))
     }
 
-    /// Allocate PCI slot location.
+    /// Allocate PCI slot location on the specified bus of domain 0.
     pub fn allocate_pci(&mut self, bus: u8, tag: String) -> Option<PciAddress> {
         let id = self.get_anon_alloc();
-        let allocator = self.get_pci_allocator_mut(bus)?;
+        let allocator = self.get_pci_allocator_mut(0, bus)?;
         allocator
             .allocate(1, id, tag)
             .map(|v| PciAddress {
+                domain: 0,
                 bus,
                 dev: (v >> 3) as u8,
                 func: (v & 7) as u8,
@@ -320,7 +325,7 @@ impl SystemAllocator {
     pub fn reserve_pci(&mut self, pci_addr: PciAddress, tag: String) -> bool {
         let id = self.get_anon_alloc();
 
-        let allocator = match self.get_pci_allocator_mut(pci_addr.bus) {
+        let allocator = match self.get_pci_allocator_mut(pci_addr.domain, pci_addr.bus) {
             Some(v) => v,
             None => return false,
         };
@@ -332,7 +337,7 @@ impl SystemAllocator {
 
     /// release PCI slot location.
     pub fn release_pci(&mut self, pci_addr: PciAddress) -> bool {
-        let allocator = match self.get_pci_allocator_mut(pci_addr.bus) {
+        let allocator = match self.get_pci_allocator_mut(pci_addr.domain, pci_addr.bus) {
             Some(v) => v,
             None => return false,
         };
@@ -340,6 +345,38 @@ impl SystemAllocator {
         allocator.release_containing(df).is_ok()
     }
 
+    /// Adds the memory windows of an additional PCI segment.
+    ///
+    /// The BARs of the devices in `domain` are allocated from `low_mmio` and `high_mmio` instead of
+    /// the low and high MMIO regions, which the windows must not overlap with.
+    pub fn add_pci_segment(
+        &mut self,
+        domain: u16,
+        low_mmio: AddressRange,
+        high_mmio: AddressRange,
+    ) -> Result<()> {
+        if domain == 0 || self.pci_segment_mmio.contains_key(&domain) {
+            return Err(Error::ExistingAlloc(Alloc::PciSegment(domain)));
+        }
+        for mmio in [low_mmio, high_mmio] {
+            if self
+                .mmio_address_spaces
+                .iter()
+                .flat_map(|mmio_as| mmio_as.pools())
+                .any(|pool| !pool.intersect(mmio).is_empty())
+            {
+                return Err(Error::RegionOverlap(mmio));
+            }
+        }
+        let page_size = pagesize() as u64;
+        let low = AddressAllocator::new(low_mmio, Some(page_size), None)?;
+        let high = AddressAllocator::new(high_mmio, Some(page_size), None)?;
+        self.pci_segment_mmio
+            .insert(domain, self.mmio_address_spaces.len());
+        self.mmio_address_spaces.extend([low, high]);
+        Ok(())
+    }
+
     /// Allocate a memory-mapped I/O region with properties requested in `opts`.
     pub fn allocate_mmio(
         &mut self,
@@ -359,11 +396,19 @@ impl SystemAllocator {
             mmio_type = MmioType::Low;
         }
 
-        let res = self.allocate_mmio_internal(size, alloc, tag.clone(), opts, mmio_type);
+        // The BARs of the devices in an additional PCI segment are allocated from its windows.
+        let base = match alloc {
+            Alloc::PciBar { domain, .. } => self.pci_segment_mmio.get(&domain).copied(),
+            _ => None,
+        }
+        .unwrap_or(0);
+
+        let res =
+            self.allocate_mmio_internal(size, alloc, tag.clone(), opts, base + mmio_type as usize);
         // If a high allocation failed, retry in low. The reverse is not valid, since the address
         // may be out of range and/or prefetchable memory may not be appropriate.
         if mmio_type == MmioType::High && matches!(res, Err(Error::OutOfSpace)) {
-            self.allocate_mmio_internal(size, alloc, tag, opts, MmioType::Low)
+            self.allocate_mmio_internal(size, alloc, tag, opts, base + MmioType::Low as usize)
         } else {
             res
         }
@@ -375,9 +420,9 @@ impl SystemAllocator {
         alloc: Alloc,
         tag: String,
         opts: &AllocOptions,
-        mmio_type: MmioType,
+        index: usize,
     ) -> Result<u64> {
-        let allocator = &mut self.mmio_address_spaces[mmio_type as usize];
+        let allocator = &mut self.mmio_address_spaces[index];
         match (opts.alignment, opts.top_down) {
             (Some(align), true) => allocator.reverse_allocate_with_align(size, alloc, tag, align),
             (Some(align), false) => allocator.allocate_with_align(size, alloc, tag, align),
@@ -433,14 +478,15 @@ impl SystemAllocator {
     }
 
     /// Gets a set of allocators to be used for MMIO allocation.
-    /// The set of allocators will try the low and high MMIO allocators, in that order.
+    /// The set of allocators will try the low and high MMIO allocators, in that order, followed by
+    /// the memory windows of the additional PCI segments.
     pub fn mmio_allocator_any(&mut self) -> AddressAllocatorSet {
         AddressAllocatorSet::new(&mut self.mmio_address_spaces)
     }
 
-    /// Gets the pools of all mmio allocators.
+    /// Gets the pools of the low and high mmio allocators.
     pub fn mmio_pools(&self) -> Vec<&AddressRange> {
-        self.mmio_address_spaces
+        self.mmio_address_spaces[..=MmioType::High as usize]
             .iter()
             .flat_map(|mmio_as| mmio_as.pools())
             .collect()
@@ -494,6 +540,7 @@ mod tests {
             a.mmio_allocator(MmioType::High).allocate(
                 0x100,
                 Alloc::PciBar {
+                    domain: 0,
                     bus: 0,
                     dev: 0,
                     func: 0,
@@ -505,6 +552,7 @@ mod tests {
         );
         assert_eq!(
             a.mmio_allocator(MmioType::High).get(&Alloc::PciBar {
+                domain: 0,
                 bus: 0,
                 dev: 0,
                 func: 0,
@@ -559,4 +607,96 @@ mod tests {
             true
         );
     }
+    #[test]
+    fn pci_segment() {
+        let mut a = SystemAllocator::new(
+            SystemAllocatorConfig {
+                io: None,
+                low_mmio: AddressRange {
+                    start: 0x3000_0000,
+                    end: 0x3000_ffff,
+                },
+                high_mmio: AddressRange {
+                    start: 0x1000_0000,
+                    end: 0x1fffffff,
+                },
+                platform_mmio: None,
+                first_irq: 5,
+            },
+            None,
+            &[],
+        )
+        .unwrap();
+
+        let low_window = AddressRange {
+            start: 0x3001_0000,
+            end: 0x3001_ffff,
+        };
+        let high_window = AddressRange {
+            start: 0x4000_0000,
+            end: 0x4fff_ffff,
+        };
+        assert_eq!(
+            a.add_pci_segment(
+                1,
+                low_window,
+                AddressRange {
+                    start: 0x1800_0000,
+                    end: 0x27ff_ffff
+                }
+            ),
+            Err(Error::RegionOverlap(AddressRange {
+                start: 0x1800_0000,
+                end: 0x27ff_ffff
+            }))
+        );
+        assert_eq!(a.add_pci_segment(1, low_window, high_window), Ok(()));
+        assert_eq!(
+            a.add_pci_segment(1, low_window, high_window),
+            Err(Error::ExistingAlloc(Alloc::PciSegment(1)))
+        );
+        assert_eq!(a.mmio_pools().len(), 2);
+
+        // Devices in the segment get their address and BARs from the segment.
+        assert!(a.reserve_pci(PciAddress::new(1, 0, 1, 0).unwrap(), "dev".to_string()));
+        assert!(a.reserve_pci(PciAddress::new(0, 0, 1, 0).unwrap(), "dev".to_string()));
+        let bar0 = Alloc::PciBar {
+            domain: 1,
+            bus: 0,
+            dev: 1,
+            func: 0,
+            bar: 0,
+        };
+        assert_eq!(
+            a.allocate_mmio(
+                0x1000,
+                bar0,
+                "bar0".to_string(),
+                AllocOptions::new().prefetchable(true)
+            ),
+            Ok(0x4000_0000)
+        );
+        assert_eq!(
+            a.mmio_allocator_any().get(&bar0).map(|(range, _)| *range),
+            Some(AddressRange {
+                start: 0x4000_0000,
+                end: 0x4000_0fff
+            })
+        );
+        assert_eq!(
+            a.allocate_mmio(
+                0x1000,
+                Alloc::PciBar {
+                    domain: 1,
+                    bus: 0,
+                    dev: 1,
+                    func: 0,
+                    bar: 1,
+                },
+                "bar1".to_string(),
+                AllocOptions::new().max_address(u32::MAX.into())
+            ),
+            Ok(0x3001_0000)
+        );
+    }
 }
@@ -37,9 +37,13 @@ use devices::PciRootCommand;
 #[cfg(feature = "gdb")]
 use gdbstub::arch::Arch;
 #[cfg(feature = "gdb")]
+use gdbstub_arch::riscv::reg::id::RiscvRegId;
+#[cfg(feature = "gdb")]
 use gdbstub_arch::riscv::Riscv64 as GdbArch;
 use hypervisor::CoreRegister;
 use hypervisor::CpuConfigRiscv64;
+#[cfg(feature = "gdb")]
+use hypervisor::HwWatchpoint;
 use hypervisor::Hypervisor;
 use hypervisor::ProtectionType;
 use hypervisor::TimerRegister;
@@ -120,6 +124,8 @@ pub enum Error {
     CreateVcpu(base::Error),
     #[error("vm created wrong kind of vcpu")]
     DowncastVcpu,
+    #[error("failed to enable guest debugging: {0}")]
+    EnableGuestDebug(base::Error),
     #[error("failed to finalize devices: {0}")]
     FinalizeDevices(base::Error),
     #[error("failed to finalize IRQ chip: {0}")]
@@ -140,6 +146,10 @@ pub enum Error {
     ProtectedVmUnsupported,
     #[error("ramoops address is different from high_mmio_base: {0} vs {1}")]
     RamoopsAddress(u64, u64),
+    #[error("error reading guest memory: {0}")]
+    ReadGuestMemory(vm_memory::GuestMemoryError),
+    #[error("error reading CPU register: {0}")]
+    ReadReg(base::Error),
     #[error("failed to register irq fd: {0}")]
     RegisterIrqfd(base::Error),
     #[error("error registering PCI bus: {0}")]
@@ -156,6 +166,10 @@ pub enum Error {
     Unsupported,
     #[error("failed to initialize VCPU: {0}")]
     VcpuInit(base::Error),
+    #[error("error writing guest memory: {0}")]
+    WriteGuestMemory(vm_memory::GuestMemoryError),
+    #[error("error writing CPU register: {0}")]
+    WriteReg(base::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -278,6 +292,7 @@ impl arch::LinuxArch for Riscv64 {
                 &mut vm,
                 devices::IMSIC_MAX_INT_IDS as usize,
                 None,
+                0,
                 #[cfg(feature = "swap")]
                 swap_controller,
             )
@@ -512,54 +527,191 @@ impl arch::LinuxArch for Riscv64 {
     }
 }
 
+/// The core registers holding `x1` to `x31`, in order.
+#[cfg(feature = "gdb")]
+const GDB_GPRS: [CoreRegister; 31] = [
+    CoreRegister::Ra,
+    CoreRegister::Sp,
+    CoreRegister::Gp,
+    CoreRegister::Tp,
+    CoreRegister::T0,
+    CoreRegister::T1,
+    CoreRegister::T2,
+    CoreRegister::S0,
+    CoreRegister::S1,
+    CoreRegister::A0,
+    CoreRegister::A1,
+    CoreRegister::A2,
+    CoreRegister::A3,
+    CoreRegister::A4,
+    CoreRegister::A5,
+    CoreRegister::A6,
+    CoreRegister::A7,
+    CoreRegister::S2,
+    CoreRegister::S3,
+    CoreRegister::S4,
+    CoreRegister::S5,
+    CoreRegister::S6,
+    CoreRegister::S7,
+    CoreRegister::S8,
+    CoreRegister::S9,
+    CoreRegister::S10,
+    CoreRegister::S11,
+    CoreRegister::T3,
+    CoreRegister::T4,
+    CoreRegister::T5,
+    CoreRegister::T6,
+];
+
+/// Returns the core register holding the general purpose register `x<n>`, or `None` for `x0`
+/// which is hardwired to zero.
+#[cfg(feature = "gdb")]
+fn gdb_gpr(n: u8) -> Option<CoreRegister> {
+    GDB_GPRS.get(usize::from(n).checked_sub(1)?).copied()
+}
+
 #[cfg(feature = "gdb")]
 impl<T: VcpuRiscv64> arch::GdbOps<T> for Riscv64 {
     type Error = Error;
 
+    fn init_debug(vcpu: &T) -> Result<()> {
+        // GDB inserts breakpoints by writing `ebreak` to the guest memory, which must exit to the
+        // VMM instead of raising an exception in the guest.
+        vcpu.set_guest_debug(true).map_err(Error::EnableGuestDebug)
+    }
+
     fn read_memory(
         _vcpu: &T,
-        _guest_mem: &GuestMemory,
-        _vaddr: GuestAddress,
-        _len: usize,
+        guest_mem: &GuestMemory,
+        vaddr: GuestAddress,
+        len: usize,
     ) -> Result<Vec<u8>> {
-        unimplemented!();
+        let mut buf = vec![0; len];
+
+        guest_mem
+            .read_exact_at_addr(&mut buf, vaddr)
+            .map_err(Error::ReadGuestMemory)?;
+
+        Ok(buf)
     }
 
     fn write_memory(
         _vcpu: &T,
-        _guest_mem: &GuestMemory,
-        _vaddr: GuestAddress,
-        _buf: &[u8],
+        guest_mem: &GuestMemory,
+        vaddr: GuestAddress,
+        buf: &[u8],
     ) -> Result<()> {
-        unimplemented!();
+        guest_mem
+            .write_all_at_addr(buf, vaddr)
+            .map_err(Error::WriteGuestMemory)
     }
 
-    fn read_registers(_vcpu: &T) -> Result<<GdbArch as Arch>::Registers> {
-        unimplemented!();
+    fn read_registers(vcpu: &T) -> Result<<GdbArch as Arch>::Registers> {
+        let mut regs: <GdbArch as Arch>::Registers = Default::default();
+        for (reg, core_reg) in regs.x.iter_mut().skip(1).zip(GDB_GPRS) {
+            *reg = vcpu
+                .get_one_reg(VcpuRegister::Core(core_reg))
+                .map_err(Error::ReadReg)?;
+        }
+        regs.pc = vcpu
+            .get_one_reg(VcpuRegister::Core(CoreRegister::Pc))
+            .map_err(Error::ReadReg)?;
+
+        Ok(regs)
     }
 
-    fn write_registers(_vcpu: &T, _regs: &<GdbArch as Arch>::Registers) -> Result<()> {
-        unimplemented!();
+    fn write_registers(vcpu: &T, regs: &<GdbArch as Arch>::Registers) -> Result<()> {
+        for (reg, core_reg) in regs.x.iter().skip(1).zip(GDB_GPRS) {
+            vcpu.set_one_reg(VcpuRegister::Core(core_reg), *reg)
+                .map_err(Error::WriteReg)?;
+        }
+        vcpu.set_one_reg(VcpuRegister::Core(CoreRegister::Pc), regs.pc)
+            .map_err(Error::WriteReg)?;
+
+        Ok(())
     }
 
-    fn read_register(_vcpu: &T, _reg_id: <GdbArch as Arch>::RegId) -> Result<Vec<u8>> {
-        unimplemented!();
+    fn read_register(vcpu: &T, reg_id: <GdbArch as Arch>::RegId) -> Result<Vec<u8>> {
+        let result = match reg_id {
+            RiscvRegId::Gpr(n) => match gdb_gpr(n) {
+                Some(core_reg) => vcpu
+                    .get_one_reg(VcpuRegister::Core(core_reg))
+                    .map(|v| v.to_ne_bytes().to_vec()),
+                None => Ok(0u64.to_ne_bytes().to_vec()),
+            },
+            RiscvRegId::Pc => vcpu
+                .get_one_reg(VcpuRegister::Core(CoreRegister::Pc))
+                .map(|v| v.to_ne_bytes().to_vec()),
+            RiscvRegId::Priv => vcpu
+                .get_one_reg(VcpuRegister::Core(CoreRegister::Mode))
+                .map(|v| vec![v as u8]),
+            // The floating point registers and the CSRs are not exposed by the hypervisor.
+            RiscvRegId::Fpr(_) | RiscvRegId::Csr(_) => Ok(Vec::new()),
+            _ => {
+                base::error!("Unexpected RiscvRegId: {:?}", reg_id);
+                Err(base::Error::new(libc::EINVAL))
+            }
+        };
+
+        result.map_err(Error::ReadReg)
     }
 
-    fn write_register(_vcpu: &T, _reg_id: <GdbArch as Arch>::RegId, _data: &[u8]) -> Result<()> {
-        unimplemented!();
+    fn write_register(vcpu: &T, reg_id: <GdbArch as Arch>::RegId, data: &[u8]) -> Result<()> {
+        fn try_into_u64(data: &[u8]) -> Result<u64> {
+            let s = data
+                .get(..8)
+                .ok_or(Error::WriteReg(base::Error::new(libc::EINVAL)))?;
+            let a = s
+                .try_into()
+                .map_err(|_| Error::WriteReg(base::Error::new(libc::EINVAL)))?;
+            Ok(u64::from_ne_bytes(a))
+        }
+
+        match reg_id {
+            RiscvRegId::Gpr(n) => match gdb_gpr(n) {
+                Some(core_reg) => {
+                    vcpu.set_one_reg(VcpuRegister::Core(core_reg), try_into_u64(data)?)
+                }
+                // Writes to x0 are ignored.
+                None => Ok(()),
+            },
+            RiscvRegId::Pc => {
+                vcpu.set_one_reg(VcpuRegister::Core(CoreRegister::Pc), try_into_u64(data)?)
+            }
+            RiscvRegId::Priv => {
+                let mode = data
+                    .first()
+                    .ok_or(Error::WriteReg(base::Error::new(libc::EINVAL)))?;
+                vcpu.set_one_reg(VcpuRegister::Core(CoreRegister::Mode), u64::from(*mode))
+            }
+            _ => {
+                base::error!("Unexpected RiscvRegId: {:?}", reg_id);
+                Err(base::Error::new(libc::EINVAL))
+            }
+        }
+        .map_err(Error::WriteReg)
     }
 
     fn enable_singlestep(_vcpu: &T) -> Result<()> {
-        unimplemented!();
+        // KVM cannot single-step a RISC-V vCPU. GDB steps with temporary breakpoints instead.
+        Err(Error::Unsupported)
     }
 
     fn get_max_hw_breakpoints(_vcpu: &T) -> Result<usize> {
-        unimplemented!();
+        Ok(0)
     }
 
-    fn set_hw_breakpoints(_vcpu: &T, _breakpoints: &[GuestAddress]) -> Result<()> {
-        unimplemented!();
+    fn get_max_hw_watchpoints(_vcpu: &T) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn set_hw_breakpoints(
+        vcpu: &T,
+        _breakpoints: &[GuestAddress],
+        _watchpoints: &[HwWatchpoint],
+    ) -> Result<()> {
+        // There are no hardware breakpoints, keep only `ebreak` trapping.
+        vcpu.set_guest_debug(true).map_err(Error::EnableGuestDebug)
     }
 }
 
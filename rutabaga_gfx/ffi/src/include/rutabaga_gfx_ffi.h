@@ -77,6 +77,7 @@ extern "C" {
 #define RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_WIN32 0x3
 #define RUTABAGA_HANDLE_TYPE_MEM_SHM 0x4
 #define RUTABAGA_HANDLE_TYPE_MEM_ZIRCON 0x5
+#define RUTABAGA_HANDLE_TYPE_MEM_FILE 0x6
 
 #define RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_FD 0x10
 #define RUTABAGA_HANDLE_TYPE_SIGNAL_SYNC_FD 0x20
@@ -244,6 +244,7 @@ pub unsafe extern "C" fn rutabaga_init(builder: &rutabaga_builder, ptr: &mut *mu
                 rutabaga_channels.push(RutabagaChannel {
                     base_channel: path,
                     channel_type: channel.channel_type,
+                    name: None,
                 });
             }
 
@@ -485,6 +485,29 @@ impl CrossDomain {
     }
 }
 
+/// Returns the channels of a context named `context_name`: for every channel type, the channel of
+/// that name if there is one, and the unnamed channel otherwise.
+fn route_channels(
+    channels: &Option<Vec<RutabagaChannel>>,
+    context_name: Option<&str>,
+) -> Option<Vec<RutabagaChannel>> {
+    let channels = channels.as_ref()?;
+    let mut routed: Vec<RutabagaChannel> = channels
+        .iter()
+        .filter(|channel| channel.name.is_some() && channel.name.as_deref() == context_name)
+        .cloned()
+        .collect();
+    for channel in channels.iter().filter(|channel| channel.name.is_none()) {
+        if !routed
+            .iter()
+            .any(|routed_channel| routed_channel.channel_type == channel.channel_type)
+        {
+            routed.push(channel.clone());
+        }
+    }
+    Some(routed)
+}
+
 impl CrossDomainContext {
     fn get_connection(&mut self, cmd_init: &CrossDomainInit) -> RutabagaResult<Tube> {
         let channels = self
@@ -1079,11 +1102,11 @@ impl RutabagaComponent for CrossDomain {
         &self,
         _ctx_id: u32,
         _context_init: u32,
-        _context_name: Option<&str>,
+        context_name: Option<&str>,
         fence_handler: RutabagaFenceHandler,
     ) -> RutabagaResult<Box<dyn RutabagaContext>> {
         Ok(Box::new(CrossDomainContext {
-            channels: self.channels.clone(),
+            channels: route_channels(&self.channels, context_name),
             gralloc: self.gralloc.clone(),
             state: None,
             context_resources: Arc::new(Mutex::new(Default::default())),
@@ -1103,3 +1126,55 @@ impl RutabagaComponent for CrossDomain {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn channel(path: &str, channel_type: u32, name: Option<&str>) -> RutabagaChannel {
+        RutabagaChannel {
+            base_channel: PathBuf::from(path),
+            channel_type,
+            name: name.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn named_contexts_use_their_channels() {
+        let channels = Some(vec![
+            channel("/run/wayland-0", RUTABAGA_CHANNEL_TYPE_WAYLAND, None),
+            channel("/run/camera", RUTABAGA_CHANNEL_TYPE_CAMERA, None),
+            channel(
+                "/run/wayland-1",
+                RUTABAGA_CHANNEL_TYPE_WAYLAND,
+                Some("seat1"),
+            ),
+        ]);
+        let paths = |context_name| -> Vec<PathBuf> {
+            route_channels(&channels, context_name)
+                .unwrap()
+                .into_iter()
+                .map(|channel| channel.base_channel)
+                .collect()
+        };
+
+        assert_eq!(
+            paths(Some("seat1")),
+            vec![
+                PathBuf::from("/run/wayland-1"),
+                PathBuf::from("/run/camera")
+            ]
+        );
+        assert_eq!(
+            paths(Some("seat2")),
+            vec![
+                PathBuf::from("/run/wayland-0"),
+                PathBuf::from("/run/camera")
+            ]
+        );
+        assert_eq!(paths(None), paths(Some("seat2")));
+        assert!(route_channels(&None, Some("seat1")).is_none());
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! fence_stats: Per-ring bookkeeping of the fences created by the guest, used to report fences
+//! that remain unsignaled for too long when diagnosing GPU hangs.
+
+use std::collections::BTreeMap as Map;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::rutabaga_utils::RutabagaFence;
+use crate::rutabaga_utils::RUTABAGA_FLAG_FENCE;
+use crate::rutabaga_utils::RUTABAGA_FLAG_FENCE_ERROR;
+use crate::rutabaga_utils::RUTABAGA_FLAG_INFO_RING_IDX;
+
+/// Timeline a fence is created on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RutabagaFenceRing {
+    /// The global timeline, for fences created without `RUTABAGA_FLAG_INFO_RING_IDX`.
+    Global,
+    /// A timeline of a context.
+    Context { ctx_id: u32, ring_idx: u8 },
+}
+
+impl RutabagaFenceRing {
+    fn of(fence: &RutabagaFence) -> RutabagaFenceRing {
+        if fence.flags & RUTABAGA_FLAG_INFO_RING_IDX != 0 {
+            RutabagaFenceRing::Context {
+                ctx_id: fence.ctx_id,
+                ring_idx: fence.ring_idx,
+            }
+        } else {
+            RutabagaFenceRing::Global
+        }
+    }
+}
+
+/// Fence counters of a ring, returned by `Rutabaga::fence_stats()`.
+#[derive(Copy, Clone, Debug)]
+pub struct RutabagaFenceRingStats {
+    pub ring: RutabagaFenceRing,
+    pub created: u64,
+    pub signaled: u64,
+    pub pending: usize,
+}
+
+/// Fence left unsignaled beyond the watchdog threshold, returned by
+/// `Rutabaga::check_stuck_fences()`.
+#[derive(Copy, Clone, Debug)]
+pub struct RutabagaStuckFence {
+    pub fence_id: u64,
+    pub ring: RutabagaFenceRing,
+    /// Context the guest created the fence for.
+    pub ctx_id: u32,
+    pub pending_for: Duration,
+    /// Size of the last command buffer the context submitted before the fence was created.
+    pub last_command_size: usize,
+}
+
+struct PendingFence {
+    fence_id: u64,
+    ctx_id: u32,
+    created: Instant,
+    last_command_size: usize,
+    reported: bool,
+}
+
+#[derive(Default)]
+struct RingState {
+    created: u64,
+    signaled: u64,
+    // In creation order, which is the order fences on a ring are signaled in.
+    pending: VecDeque<PendingFence>,
+}
+
+/// Fences of every ring, updated both by `Rutabaga` and by the fence handler of the components.
+#[derive(Default)]
+pub(crate) struct FenceTracker {
+    rings: Map<RutabagaFenceRing, RingState>,
+    last_command_sizes: Map<u32, usize>,
+}
+
+impl FenceTracker {
+    /// Records that the context `ctx_id` submitted a command buffer of `size` bytes.
+    pub fn submit(&mut self, ctx_id: u32, size: usize) {
+        self.last_command_sizes.insert(ctx_id, size);
+    }
+
+    /// Records a fence before it is handed to a component, which may signal it right away.
+    pub fn create(&mut self, fence: &RutabagaFence) {
+        let last_command_size = self
+            .last_command_sizes
+            .get(&fence.ctx_id)
+            .copied()
+            .unwrap_or(0);
+        let ring = self.rings.entry(RutabagaFenceRing::of(fence)).or_default();
+        ring.created += 1;
+        ring.pending.push_back(PendingFence {
+            fence_id: fence.fence_id,
+            ctx_id: fence.ctx_id,
+            created: Instant::now(),
+            last_command_size,
+            reported: false,
+        });
+    }
+
+    /// Forgets a fence the component failed to create.
+    pub fn cancel(&mut self, fence: &RutabagaFence) {
+        if let Some(ring) = self.rings.get_mut(&RutabagaFenceRing::of(fence)) {
+            ring.created = ring.created.saturating_sub(1);
+            ring.pending.retain(|f| f.fence_id != fence.fence_id);
+        }
+    }
+
+    /// Records a signaled fence.  Signaling a fence implies that the earlier fences of its ring are
+    /// signaled too.
+    pub fn signal(&mut self, fence: &RutabagaFence) {
+        if let Some(ring) = self.rings.get_mut(&RutabagaFenceRing::of(fence)) {
+            while ring
+                .pending
+                .front()
+                .is_some_and(|f| f.fence_id <= fence.fence_id)
+            {
+                ring.pending.pop_front();
+                ring.signaled += 1;
+            }
+        }
+    }
+
+    /// Returns the last pending fence of every ring of the context `ctx_id`, flagged with
+    /// `RUTABAGA_FLAG_FENCE_ERROR`.  Signaling them retires every pending fence of the context.
+    pub fn error_fences(&self, ctx_id: u32) -> Vec<RutabagaFence> {
+        self.rings
+            .iter()
+            .filter_map(|(ring, state)| match ring {
+                RutabagaFenceRing::Context {
+                    ctx_id: id,
+                    ring_idx,
+                } if *id == ctx_id => state.pending.back().map(|fence| RutabagaFence {
+                    flags: RUTABAGA_FLAG_FENCE
+                        | RUTABAGA_FLAG_INFO_RING_IDX
+                        | RUTABAGA_FLAG_FENCE_ERROR,
+                    fence_id: fence.fence_id,
+                    ctx_id,
+                    ring_idx: *ring_idx,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Forgets the rings and the submissions of a destroyed context.
+    pub fn destroy_context(&mut self, ctx_id: u32) {
+        self.rings.retain(|ring, _| match ring {
+            RutabagaFenceRing::Global => true,
+            RutabagaFenceRing::Context { ctx_id: id, .. } => *id != ctx_id,
+        });
+        self.last_command_sizes.remove(&ctx_id);
+    }
+
+    /// Forgets every fence, for when the state of the components is replaced.
+    pub fn clear(&mut self) {
+        self.rings.clear();
+        self.last_command_sizes.clear();
+    }
+
+    pub fn stats(&self) -> Vec<RutabagaFenceRingStats> {
+        self.rings
+            .iter()
+            .map(|(ring, state)| RutabagaFenceRingStats {
+                ring: *ring,
+                created: state.created,
+                signaled: state.signaled,
+                pending: state.pending.len(),
+            })
+            .collect()
+    }
+
+    /// Returns the fences pending for longer than `threshold`, along with whether each of them is
+    /// returned for the first time.
+    pub fn stuck(&mut self, threshold: Duration) -> Vec<(RutabagaStuckFence, bool)> {
+        let now = Instant::now();
+        let mut stuck = Vec::new();
+        for (ring, state) in self.rings.iter_mut() {
+            for fence in state.pending.iter_mut() {
+                let pending_for = now.saturating_duration_since(fence.created);
+                if pending_for < threshold {
+                    // Fences of a ring are pending in creation order.
+                    break;
+                }
+
+                stuck.push((
+                    RutabagaStuckFence {
+                        fence_id: fence.fence_id,
+                        ring: *ring,
+                        ctx_id: fence.ctx_id,
+                        pending_for,
+                        last_command_size: fence.last_command_size,
+                    },
+                    !fence.reported,
+                ));
+                fence.reported = true;
+            }
+        }
+        stuck
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fence(fence_id: u64, ctx_id: u32, ring_idx: Option<u8>) -> RutabagaFence {
+        RutabagaFence {
+            flags: ring_idx.map_or(0, |_| RUTABAGA_FLAG_INFO_RING_IDX),
+            fence_id,
+            ctx_id,
+            ring_idx: ring_idx.unwrap_or(0),
+        }
+    }
+
+    #[test]
+    fn signal_retires_earlier_fences() {
+        let mut tracker = FenceTracker::default();
+        tracker.submit(1, 64);
+        for fence_id in 1..=3 {
+            tracker.create(&fence(fence_id, 1, Some(0)));
+        }
+        tracker.create(&fence(4, 1, None));
+        tracker.signal(&fence(2, 1, Some(0)));
+
+        let stats = tracker.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].ring, RutabagaFenceRing::Global);
+        assert_eq!(stats[0].pending, 1);
+        assert_eq!(
+            stats[1].ring,
+            RutabagaFenceRing::Context {
+                ctx_id: 1,
+                ring_idx: 0
+            }
+        );
+        assert_eq!((stats[1].created, stats[1].signaled), (3, 2));
+        assert_eq!(stats[1].pending, 1);
+    }
+
+    #[test]
+    fn stuck_fences_are_reported_once() {
+        let mut tracker = FenceTracker::default();
+        tracker.submit(2, 4096);
+        tracker.create(&fence(1, 2, Some(1)));
+
+        let stuck = tracker.stuck(Duration::ZERO);
+        assert_eq!(stuck.len(), 1);
+        assert!(stuck[0].1);
+        assert_eq!(stuck[0].0.ctx_id, 2);
+        assert_eq!(stuck[0].0.last_command_size, 4096);
+        assert!(!tracker.stuck(Duration::ZERO)[0].1);
+        assert!(tracker.stuck(Duration::from_secs(3600)).is_empty());
+
+        tracker.destroy_context(2);
+        assert!(tracker.stats().is_empty());
+    }
+
+    #[test]
+    fn error_fences_retire_context_rings() {
+        let mut tracker = FenceTracker::default();
+        for (fence_id, ring_idx) in [(1, 0), (2, 1), (3, 0)] {
+            tracker.create(&fence(fence_id, 5, Some(ring_idx)));
+        }
+        tracker.create(&fence(4, 6, Some(0)));
+        tracker.create(&fence(5, 5, None));
+
+        let fences = tracker.error_fences(5);
+        assert_eq!(fences.len(), 2);
+        assert_eq!((fences[0].fence_id, fences[0].ring_idx), (3, 0));
+        assert_eq!((fences[1].fence_id, fences[1].ring_idx), (2, 1));
+        assert!(fences
+            .iter()
+            .all(|f| f.flags & RUTABAGA_FLAG_FENCE_ERROR != 0));
+
+        for fence in fences {
+            tracker.signal(&fence);
+        }
+        // The global ring and the ring of the other context are left pending.
+        let pending: Vec<usize> = tracker.stats().iter().map(|stats| stats.pending).collect();
+        assert_eq!(pending, vec![1, 0, 0, 1]);
+    }
+}
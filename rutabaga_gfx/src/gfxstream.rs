@@ -704,9 +704,9 @@ impl RutabagaComponent for Gfxstream {
         ctx_id: u32,
         resource: &mut RutabagaResource,
         transfer: Transfer3D,
-    ) -> RutabagaResult<()> {
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
         if transfer.is_empty() {
-            return Ok(());
+            return Ok(None);
         }
 
         let mut transfer_box = VirglBox {
@@ -733,7 +733,8 @@ impl RutabagaComponent for Gfxstream {
                 0,
             )
         };
-        ret_to_res(ret)
+        ret_to_res(ret)?;
+        Ok(None)
     }
 
     fn transfer_read(
@@ -742,9 +743,9 @@ impl RutabagaComponent for Gfxstream {
         resource: &mut RutabagaResource,
         transfer: Transfer3D,
         buf: Option<IoSliceMut>,
-    ) -> RutabagaResult<()> {
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
         if transfer.is_empty() {
-            return Ok(());
+            return Ok(None);
         }
 
         let mut transfer_box = VirglBox {
@@ -785,7 +786,8 @@ impl RutabagaComponent for Gfxstream {
                 num_iovecs,
             )
         };
-        ret_to_res(ret)
+        ret_to_res(ret)?;
+        Ok(None)
     }
 
     fn resource_flush(&self, resource: &mut RutabagaResource) -> RutabagaResult<()> {
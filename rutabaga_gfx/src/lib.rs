@@ -6,6 +6,7 @@
 //! swapchain allocation and mapping.
 
 mod cross_domain;
+mod fence_stats;
 mod generated;
 mod gfxstream;
 mod gfxstream_stub;
@@ -13,6 +14,7 @@ mod gfxstream_stub;
 mod macros;
 mod bytestream;
 mod ipc;
+mod pixel_convert;
 #[cfg(any(feature = "gfxstream", feature = "virgl_renderer"))]
 mod renderer_utils;
 mod rutabaga_2d;
@@ -23,10 +25,14 @@ mod rutabaga_utils;
 mod snapshot;
 mod virgl_renderer;
 
+pub use crate::fence_stats::RutabagaFenceRing;
+pub use crate::fence_stats::RutabagaFenceRingStats;
+pub use crate::fence_stats::RutabagaStuckFence;
 pub use crate::rutabaga_core::calculate_capset_mask;
 pub use crate::rutabaga_core::calculate_capset_names;
 pub use crate::rutabaga_core::Rutabaga;
 pub use crate::rutabaga_core::RutabagaBuilder;
+pub use crate::rutabaga_core::RutabagaResourceDebugInfo;
 pub use crate::rutabaga_gralloc::DrmFormat;
 pub use crate::rutabaga_gralloc::ImageAllocationInfo;
 pub use crate::rutabaga_gralloc::ImageMemoryRequirements;
@@ -0,0 +1,171 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! pixel_convert: Byte order conversion of the pixels copied by the 2D component, vectorized with
+//! the instructions supported by the host CPU.
+
+// virtio-gpu formats of 2D resources.  The names give the byte order in memory.
+const VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM: u32 = 1;
+const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+const VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM: u32 = 3;
+const VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM: u32 = 4;
+const VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM: u32 = 67;
+const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
+const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
+const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
+
+/// Byte `i` of a converted pixel is byte `shuffle[i]` of the original pixel.
+pub type PixelShuffle = [u8; 4];
+
+/// Returns the shuffle converting pixels of the virtio-gpu `format` to B8G8R8A8, the byte order of
+/// the host memory of 2D resources and of the display.  None is returned if no conversion is
+/// needed, or if the format is unknown.
+pub fn shuffle_to_bgra(format: u32) -> Option<PixelShuffle> {
+    match format {
+        VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM | VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM => Some([3, 2, 1, 0]),
+        VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM | VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => Some([2, 1, 0, 3]),
+        VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM | VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM => Some([1, 2, 3, 0]),
+        VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM | VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM => None,
+        _ => None,
+    }
+}
+
+/// Converts the 4 byte pixels of `pixels` in place.  Trailing bytes that don't make up a whole
+/// pixel are left untouched.
+pub fn shuffle_pixels(pixels: &mut [u8], shuffle: PixelShuffle) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY:
+            // Safe because the CPU supports AVX2.
+            unsafe { x86_64::shuffle_pixels_avx2(pixels, shuffle) };
+            return;
+        }
+        if is_x86_feature_detected!("ssse3") {
+            // SAFETY:
+            // Safe because the CPU supports SSSE3.
+            unsafe { x86_64::shuffle_pixels_ssse3(pixels, shuffle) };
+            return;
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY:
+            // Safe because the CPU supports NEON.
+            unsafe { aarch64::shuffle_pixels_neon(pixels, shuffle) };
+            return;
+        }
+    }
+
+    shuffle_pixels_scalar(pixels, shuffle);
+}
+
+fn shuffle_pixels_scalar(pixels: &mut [u8], shuffle: PixelShuffle) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let original = [pixel[0], pixel[1], pixel[2], pixel[3]];
+        for (byte, index) in pixel.iter_mut().zip(shuffle) {
+            *byte = original[index as usize];
+        }
+    }
+}
+
+// Returns the byte shuffle of `N` bytes applying `shuffle` to every pixel, where indices are
+// relative to the 16 byte lane of the byte, as expected by the shuffle instructions.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn lane_shuffle<const N: usize>(shuffle: PixelShuffle) -> [u8; N] {
+    let mut lane_shuffle = [0u8; N];
+    for (i, index) in lane_shuffle.iter_mut().enumerate() {
+        *index = ((i % 16) - (i % 4)) as u8 + shuffle[i % 4];
+    }
+    lane_shuffle
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use std::arch::x86_64::*;
+
+    use super::*;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn shuffle_pixels_avx2(pixels: &mut [u8], shuffle: PixelShuffle) {
+        let lane_shuffle = lane_shuffle::<32>(shuffle);
+        let mask = _mm256_loadu_si256(lane_shuffle.as_ptr() as *const __m256i);
+        let mut chunks = pixels.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr() as *mut __m256i;
+            _mm256_storeu_si256(ptr, _mm256_shuffle_epi8(_mm256_loadu_si256(ptr), mask));
+        }
+        shuffle_pixels_scalar(chunks.into_remainder(), shuffle);
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn shuffle_pixels_ssse3(pixels: &mut [u8], shuffle: PixelShuffle) {
+        let lane_shuffle = lane_shuffle::<16>(shuffle);
+        let mask = _mm_loadu_si128(lane_shuffle.as_ptr() as *const __m128i);
+        let mut chunks = pixels.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(ptr, _mm_shuffle_epi8(_mm_loadu_si128(ptr), mask));
+        }
+        shuffle_pixels_scalar(chunks.into_remainder(), shuffle);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use std::arch::aarch64::*;
+
+    use super::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn shuffle_pixels_neon(pixels: &mut [u8], shuffle: PixelShuffle) {
+        let lane_shuffle = lane_shuffle::<16>(shuffle);
+        let mask = vld1q_u8(lane_shuffle.as_ptr());
+        let mut chunks = pixels.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr();
+            vst1q_u8(ptr, vqtbl1q_u8(vld1q_u8(ptr), mask));
+        }
+        shuffle_pixels_scalar(chunks.into_remainder(), shuffle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shuffle_matches_scalar() {
+        for format in [
+            VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM,
+            VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM,
+            VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM,
+        ] {
+            let shuffle = shuffle_to_bgra(format).unwrap();
+            // Not a multiple of any vector size, with a trailing partial pixel.
+            let original: Vec<u8> = (0..203u32).map(|i| (i * 7) as u8).collect();
+
+            let mut expected = original.clone();
+            shuffle_pixels_scalar(&mut expected, shuffle);
+            let mut converted = original.clone();
+            shuffle_pixels(&mut converted, shuffle);
+
+            assert!(converted == expected);
+            assert_eq!(converted[200..], original[200..]);
+        }
+    }
+
+    #[test]
+    fn rgba_to_bgra() {
+        let mut pixels = [0x11, 0x22, 0x33, 0x44];
+        shuffle_pixels(
+            &mut pixels,
+            shuffle_to_bgra(VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM).unwrap(),
+        );
+        assert_eq!(pixels, [0x33, 0x22, 0x11, 0x44]);
+        assert!(shuffle_to_bgra(VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM).is_none());
+    }
+}
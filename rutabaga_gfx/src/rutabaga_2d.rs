@@ -7,16 +7,30 @@
 use std::cmp::max;
 use std::cmp::min;
 use std::cmp::Ordering;
+use std::io::Error as IoError;
+use std::io::ErrorKind;
 use std::io::IoSliceMut;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
 
+use log::error;
+
+use crate::pixel_convert::shuffle_pixels;
+use crate::pixel_convert::shuffle_to_bgra;
+use crate::pixel_convert::PixelShuffle;
 use crate::rutabaga_core::Rutabaga2DInfo;
 use crate::rutabaga_core::RutabagaComponent;
 use crate::rutabaga_core::RutabagaResource;
+use crate::rutabaga_os::SharedMemory;
 use crate::rutabaga_utils::*;
 use crate::snapshot::RutabagaSnapshotReader;
 use crate::snapshot::RutabagaSnapshotWriter;
 
-/// Transfers a resource from potentially many chunked src slices to a dst slice.
+/// Transfers a resource from potentially many chunked src slices to a dst slice, converting the
+/// pixels with `shuffle` if given.
 fn transfer_2d(
     resource_w: u32,
     resource_h: u32,
@@ -30,6 +44,7 @@ fn transfer_2d(
     src_stride: u32,
     src_offset: u64,
     srcs: &[&[u8]],
+    shuffle: Option<PixelShuffle>,
 ) -> RutabagaResult<()> {
     if rect_w == 0 || rect_h == 0 {
         return Ok(());
@@ -51,6 +66,9 @@ fn transfer_2d(
     let src_stride = src_stride as u64;
     let src_resource_offset = src_offset + (rect_y * src_stride) + (rect_x * bytes_per_pixel);
 
+    let line_size = rect_w * bytes_per_pixel;
+    let mut line_copied = 0u64;
+
     let mut next_src;
     let mut next_line;
     let mut current_height = 0u64;
@@ -121,6 +139,7 @@ fn transfer_2d(
                 .ok_or(RutabagaErrorKind::InvalidIovec)?;
 
             dst_subslice.copy_from_slice(src_subslice);
+            line_copied += copyable_size;
         } else if src_line_start_offset >= src_start_offset {
             next_src = true;
             next_line = false;
@@ -135,6 +154,19 @@ fn transfer_2d(
         }
 
         if next_line {
+            // Pixels may be split across srcs, so lines are converted once completely copied.
+            if let Some(shuffle) = shuffle.filter(|_| line_copied == line_size) {
+                let dst_line_vertical_offset = checked_arithmetic!(current_height * dst_stride)?;
+                let dst_line_start =
+                    checked_arithmetic!(dst_resource_offset + dst_line_vertical_offset)?;
+                let dst_line_end = checked_arithmetic!(dst_line_start + line_size)?;
+                let dst_line = dst
+                    .get_mut(dst_line_start as usize..dst_line_end as usize)
+                    .ok_or(RutabagaErrorKind::InvalidIovec)?;
+                shuffle_pixels(dst_line, shuffle);
+            }
+
+            line_copied = 0;
             current_height += 1;
         }
     }
@@ -142,19 +174,168 @@ fn transfer_2d(
     Ok(())
 }
 
+/// Copies from the backing iovecs of a resource to its host memory, converting the pixels to
+/// B8G8R8A8.
+fn write_2d(
+    width: u32,
+    height: u32,
+    format: u32,
+    host_mem: &mut [u8],
+    iovecs: &[RutabagaIovec],
+    transfer: Transfer3D,
+) -> RutabagaResult<()> {
+    // All offical virtio_gpu formats are 4 bytes per pixel.
+    let resource_bpp = 4;
+    let mut src_slices = Vec::with_capacity(iovecs.len());
+    for iovec in iovecs {
+        // SAFETY:
+        // Safe because Rutabaga users should have already checked the iovecs.
+        let slice = unsafe { std::slice::from_raw_parts(iovec.base as *mut u8, iovec.len) };
+        src_slices.push(slice);
+    }
+
+    let src_stride = resource_bpp * width;
+    let src_offset = transfer.offset;
+
+    let dst_stride = resource_bpp * width;
+    let dst_offset = 0;
+
+    transfer_2d(
+        width,
+        height,
+        transfer.x,
+        transfer.y,
+        transfer.w,
+        transfer.h,
+        dst_stride,
+        dst_offset,
+        IoSliceMut::new(host_mem),
+        src_stride,
+        src_offset,
+        &src_slices,
+        shuffle_to_bgra(format),
+    )
+}
+
+// Transfer writes at least this large are made by the transfer thread instead of blocking the
+// virtio-gpu queue, e.g. the damaged area of a 1080p or 4K scanout.
+const ASYNC_TRANSFER_MIN_SIZE: u64 = 1 << 20;
+
+// A transfer write made by the transfer thread.
+struct AsyncTransferWrite {
+    width: u32,
+    height: u32,
+    format: u32,
+    host_mem: *mut u8,
+    host_mem_len: usize,
+    iovecs: Vec<RutabagaIovec>,
+    transfer: Transfer3D,
+}
+
+// SAFETY:
+// Safe because `Rutabaga` waits for the completion of the transfer before it otherwise uses or
+// drops the resource, whose host memory is never reallocated.
+unsafe impl Send for AsyncTransferWrite {}
+
+enum TransferJob {
+    Write(AsyncTransferWrite),
+    // Fences created while transfers are in flight are signaled in order with them.
+    Fence(RutabagaFence),
+}
+
+struct TransferThread {
+    sender: Sender<(TransferJob, RutabagaCompletion)>,
+    // Completion of the last job sent to the thread.
+    last: RutabagaCompletion,
+}
+
+impl TransferThread {
+    fn spawn(fence_handler: RutabagaFenceHandler) -> RutabagaResult<TransferThread> {
+        let (sender, receiver) = channel::<(TransferJob, RutabagaCompletion)>();
+        thread::Builder::new()
+            .name("rutabaga 2d transfer".to_string())
+            .spawn(move || {
+                for (job, completion) in receiver {
+                    match job {
+                        TransferJob::Write(write) => {
+                            // SAFETY:
+                            // Safe because the host memory outlives the transfer, see
+                            // `AsyncTransferWrite`.
+                            let host_mem = unsafe {
+                                std::slice::from_raw_parts_mut(write.host_mem, write.host_mem_len)
+                            };
+                            if let Err(e) = write_2d(
+                                write.width,
+                                write.height,
+                                write.format,
+                                host_mem,
+                                &write.iovecs,
+                                write.transfer,
+                            ) {
+                                error!("async transfer write failed: {}", e);
+                            }
+                        }
+                        TransferJob::Fence(fence) => fence_handler.call(fence),
+                    }
+                    completion.complete();
+                }
+            })?;
+
+        Ok(TransferThread {
+            sender,
+            last: RutabagaCompletion::new(),
+        })
+    }
+}
+
 pub struct Rutabaga2D {
     fence_handler: RutabagaFenceHandler,
+    // Started by the first large transfer.
+    transfer_thread: Mutex<Option<TransferThread>>,
 }
 
 impl Rutabaga2D {
     pub fn init(fence_handler: RutabagaFenceHandler) -> RutabagaResult<Box<dyn RutabagaComponent>> {
-        Ok(Box::new(Rutabaga2D { fence_handler }))
+        Ok(Box::new(Rutabaga2D {
+            fence_handler,
+            transfer_thread: Mutex::new(None),
+        }))
+    }
+
+    // Returns whether jobs sent to the transfer thread are still in flight.
+    fn transfers_in_flight(&self) -> bool {
+        self.transfer_thread
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|thread| !thread.last.is_complete())
+    }
+
+    // Sends `job` to the transfer thread, after the jobs in flight.
+    fn send_job(&self, job: TransferJob) -> RutabagaResult<RutabagaCompletion> {
+        let mut transfer_thread = self.transfer_thread.lock().unwrap();
+        if transfer_thread.is_none() {
+            *transfer_thread = Some(TransferThread::spawn(self.fence_handler.clone())?);
+        }
+        let transfer_thread = transfer_thread.as_mut().unwrap();
+
+        let completion = RutabagaCompletion::new();
+        transfer_thread
+            .sender
+            .send((job, completion.clone()))
+            .map_err(|_| IoError::from(ErrorKind::BrokenPipe))?;
+        transfer_thread.last = completion.clone();
+        Ok(completion)
     }
 }
 
 impl RutabagaComponent for Rutabaga2D {
     fn create_fence(&mut self, fence: RutabagaFence) -> RutabagaResult<()> {
-        self.fence_handler.call(fence);
+        if self.transfers_in_flight() {
+            self.send_job(TransferJob::Fence(fence))?;
+        } else {
+            self.fence_handler.call(fence);
+        }
         Ok(())
     }
 
@@ -170,6 +351,7 @@ impl RutabagaComponent for Rutabaga2D {
         let info_2d = Rutabaga2DInfo {
             width: resource_create_3d.width,
             height: resource_create_3d.height,
+            format: resource_create_3d.format,
             host_mem: vec![0; resource_size],
         };
 
@@ -195,55 +377,51 @@ impl RutabagaComponent for Rutabaga2D {
         _ctx_id: u32,
         resource: &mut RutabagaResource,
         transfer: Transfer3D,
-    ) -> RutabagaResult<()> {
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
         if transfer.is_empty() {
-            return Ok(());
+            return Ok(None);
         }
 
-        let mut info_2d = resource
+        let info_2d = resource
             .info_2d
-            .take()
+            .as_mut()
             .ok_or(RutabagaErrorKind::Invalid2DInfo)?;
 
         let iovecs = resource
             .backing_iovecs
-            .take()
+            .as_ref()
             .ok_or(RutabagaErrorKind::InvalidIovec)?;
 
-        // All offical virtio_gpu formats are 4 bytes per pixel.
-        let resource_bpp = 4;
-        let mut src_slices = Vec::with_capacity(iovecs.len());
-        for iovec in &iovecs {
-            // SAFETY:
-            // Safe because Rutabaga users should have already checked the iovecs.
-            let slice = unsafe { std::slice::from_raw_parts(iovec.base as *mut u8, iovec.len) };
-            src_slices.push(slice);
+        // Small transfers are made right away, unless they must be ordered after transfers in
+        // flight.
+        let size = u64::from(transfer.w) * u64::from(transfer.h) * 4;
+        if size < ASYNC_TRANSFER_MIN_SIZE && !self.transfers_in_flight() {
+            write_2d(
+                info_2d.width,
+                info_2d.height,
+                info_2d.format,
+                info_2d.host_mem.as_mut_slice(),
+                iovecs,
+                transfer,
+            )?;
+            return Ok(None);
         }
 
-        let src_stride = resource_bpp * info_2d.width;
-        let src_offset = transfer.offset;
-
-        let dst_stride = resource_bpp * info_2d.width;
-        let dst_offset = 0;
-
-        transfer_2d(
-            info_2d.width,
-            info_2d.height,
-            transfer.x,
-            transfer.y,
-            transfer.w,
-            transfer.h,
-            dst_stride,
-            dst_offset,
-            IoSliceMut::new(info_2d.host_mem.as_mut_slice()),
-            src_stride,
-            src_offset,
-            &src_slices,
-        )?;
-
-        resource.info_2d = Some(info_2d);
-        resource.backing_iovecs = Some(iovecs);
-        Ok(())
+        // Errors of the transfer thread are only logged, so check the box here.
+        let (x, y, w, h) = (transfer.x, transfer.y, transfer.w, transfer.h);
+        checked_range!(checked_arithmetic!(x + w)?; <= info_2d.width)?;
+        checked_range!(checked_arithmetic!(y + h)?; <= info_2d.height)?;
+
+        let completion = self.send_job(TransferJob::Write(AsyncTransferWrite {
+            width: info_2d.width,
+            height: info_2d.height,
+            format: info_2d.format,
+            host_mem: info_2d.host_mem.as_mut_ptr(),
+            host_mem_len: info_2d.host_mem.len(),
+            iovecs: iovecs.clone(),
+            transfer,
+        }))?;
+        Ok(Some(completion))
     }
 
     fn transfer_read(
@@ -252,7 +430,7 @@ impl RutabagaComponent for Rutabaga2D {
         resource: &mut RutabagaResource,
         transfer: Transfer3D,
         buf: Option<IoSliceMut>,
-    ) -> RutabagaResult<()> {
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
         let mut info_2d = resource
             .info_2d
             .take()
@@ -281,10 +459,47 @@ impl RutabagaComponent for Rutabaga2D {
             src_stride,
             src_offset,
             &[info_2d.host_mem.as_mut_slice()],
+            None,
         )?;
 
         resource.info_2d = Some(info_2d);
-        Ok(())
+        Ok(None)
+    }
+
+    fn create_blob(
+        &mut self,
+        _ctx_id: u32,
+        resource_id: u32,
+        resource_create_blob: ResourceCreateBlob,
+        _iovec_opt: Option<Vec<RutabagaIovec>>,
+        _handle_opt: Option<RutabagaHandle>,
+    ) -> RutabagaResult<RutabagaResource> {
+        // Without a renderer, host blobs can only be memory shared with the guest.
+        if resource_create_blob.blob_mem != RUTABAGA_BLOB_MEM_HOST3D {
+            return Err(RutabagaErrorKind::Unsupported.into());
+        }
+
+        let shm = SharedMemory::new("rutabaga_2d_blob", resource_create_blob.size)?;
+        let handle = RutabagaHandle {
+            os_handle: shm.into(),
+            handle_type: RUTABAGA_HANDLE_TYPE_MEM_SHM,
+        };
+
+        Ok(RutabagaResource {
+            resource_id,
+            handle: Some(Arc::new(handle)),
+            blob: true,
+            blob_mem: resource_create_blob.blob_mem,
+            blob_flags: resource_create_blob.blob_flags,
+            map_info: Some(RUTABAGA_MAP_CACHE_CACHED | RUTABAGA_MAP_ACCESS_RW),
+            info_2d: None,
+            info_3d: None,
+            vulkan_info: None,
+            backing_iovecs: None,
+            component_mask: 1 << (RutabagaComponentType::Rutabaga2D as u8),
+            size: resource_create_blob.size,
+            mapping: None,
+        })
     }
 
     fn snapshot(&self, writer: RutabagaSnapshotWriter) -> RutabagaResult<()> {
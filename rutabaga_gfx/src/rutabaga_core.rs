@@ -4,15 +4,23 @@
 
 //! rutabaga_core: Cross-platform, Rust-based, Wayland and Vulkan centric GPU virtualization.
 use std::collections::BTreeMap as Map;
+use std::collections::BTreeSet as Set;
 use std::convert::TryInto;
+use std::ffi::CString;
 use std::io::IoSliceMut;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::cross_domain::CrossDomain;
+use crate::fence_stats::FenceTracker;
+use crate::fence_stats::RutabagaFenceRingStats;
+use crate::fence_stats::RutabagaStuckFence;
 #[cfg(feature = "gfxstream")]
 use crate::gfxstream::Gfxstream;
 use crate::rutabaga_2d::Rutabaga2D;
@@ -32,6 +40,8 @@ const RUTABAGA_DEFAULT_HEIGHT: u32 = 1024;
 pub struct Rutabaga2DInfo {
     pub width: u32,
     pub height: u32,
+    /// virtio-gpu format of the resource.  `host_mem` holds B8G8R8A8 pixels whatever the format.
+    pub format: u32,
     pub host_mem: Vec<u8>,
 }
 
@@ -39,6 +49,8 @@ pub struct Rutabaga2DInfo {
 struct Rutabaga2DSnapshot {
     width: u32,
     height: u32,
+    #[serde(default)]
+    format: u32,
     // NOTE: `host_mem` is not preserved to avoid snapshot bloat.
 }
 
@@ -60,6 +72,21 @@ pub struct RutabagaResource {
     pub mapping: Option<MemoryMapping>,
 }
 
+/// Description of a live resource returned by `Rutabaga::debug_dump()`.
+#[derive(Clone)]
+pub struct RutabagaResourceDebugInfo {
+    pub resource_id: u32,
+    pub size: u64,
+    pub blob: bool,
+    pub blob_mem: u32,
+    pub blob_flags: u32,
+    /// Components that created or imported the resource.
+    pub components: Vec<RutabagaComponentType>,
+    pub has_backing: bool,
+    /// First fence created after the last host access to the resource, if any.
+    pub last_access_fence: Option<u64>,
+}
+
 /// The preserved fields of `RutabagaResource` that are saved and loaded across snapshot and
 /// restore.
 #[derive(Deserialize, Serialize)]
@@ -101,6 +128,7 @@ impl TryFrom<&RutabagaResource> for RutabagaResourceSnapshot {
             info_2d: resource.info_2d.as_ref().map(|info| Rutabaga2DSnapshot {
                 width: info.width,
                 height: info.height,
+                format: info.format,
             }),
             info_3d: resource.info_3d,
             vulkan_info: resource.vulkan_info,
@@ -125,6 +153,7 @@ impl TryFrom<RutabagaResourceSnapshot> for RutabagaResource {
                 Rutabaga2DInfo {
                     width: info.width,
                     height: info.height,
+                    format: info.format,
                     host_mem: vec![0; usize::try_from(size).unwrap()],
                 }
             }),
@@ -222,30 +251,41 @@ pub trait RutabagaComponent {
     /// Implementations must detach `vecs` from the resource.
     fn detach_backing(&self, _resource_id: u32) {}
 
+    /// Implementations must finish any host work that still accesses the attached `vecs` of the
+    /// resource, since the memory backing them is about to go away.
+    fn flush_backing(&self, _resource_id: u32) -> RutabagaResult<()> {
+        Ok(())
+    }
+
     /// Implementations must release the guest kernel reference on the resource.
     fn unref_resource(&self, _resource_id: u32) {}
 
     /// Implementations must perform the transfer write operation.  For 2D rutabaga components, this
     /// done via memcpy().  For 3D components, this is typically done via glTexSubImage(..).
+    ///
+    /// Implementations may instead return the completion of a transfer that continues in the
+    /// background.  Fences created afterwards must not be signaled before the transfer completes.
     fn transfer_write(
         &self,
         _ctx_id: u32,
         _resource: &mut RutabagaResource,
         _transfer: Transfer3D,
-    ) -> RutabagaResult<()> {
-        Ok(())
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
+        Ok(None)
     }
 
     /// Implementations must perform the transfer read operation.  For 2D rutabaga components, this
     /// done via memcpy().  For 3D components, this is typically done via glReadPixels(..).
+    ///
+    /// As with `transfer_write()`, the transfer may complete in the background.
     fn transfer_read(
         &self,
         _ctx_id: u32,
         _resource: &mut RutabagaResource,
         _transfer: Transfer3D,
         _buf: Option<IoSliceMut>,
-    ) -> RutabagaResult<()> {
-        Ok(())
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
+        Ok(None)
     }
 
     /// Implementations must flush the given resource to the display.
@@ -460,6 +500,13 @@ fn calculate_component(component_mask: u8) -> RutabagaResult<RutabagaComponentTy
     }
 }
 
+fn is_file_blob(resource: &RutabagaResource) -> bool {
+    resource
+        .handle
+        .as_ref()
+        .is_some_and(|handle| handle.handle_type == RUTABAGA_HANDLE_TYPE_MEM_FILE)
+}
+
 /// The global libary handle used to query capability sets, create resources and contexts.
 ///
 /// Currently, Rutabaga only supports one default component.  Many components running at the
@@ -477,6 +524,17 @@ pub struct Rutabaga {
     default_component: RutabagaComponentType,
     capset_info: Vec<RutabagaCapsetInfo>,
     fence_handler: RutabagaFenceHandler,
+    debug_handler: Option<RutabagaDebugHandler>,
+    // Fences of every ring, updated by the fence handler when they are signaled.
+    fence_tracker: Arc<Mutex<FenceTracker>>,
+    // Resources accessed since the last fence was created, and the fence that followed the last
+    // access of the other resources, for `debug_dump()`.
+    accessed_resources: Set<u32>,
+    last_access_fences: Map<u32, u64>,
+    blob_files: Map<u64, RutabagaBlobFile>,
+    // Completion of the last transfer still in flight on each resource.  Transfers of a component
+    // complete in order, so waiting for it waits for the earlier ones too.
+    pending_transfers: Map<u32, RutabagaCompletion>,
 }
 
 /// The serialized and deserialized parts of `Rutabaga` that are preserved across
@@ -488,7 +546,21 @@ struct RutabagaSnapshot {
 }
 
 impl Rutabaga {
+    // Waits for the transfers in flight on `resource_id`, before the resource is otherwise used.
+    fn wait_transfers(&mut self, resource_id: u32) {
+        if let Some(completion) = self.pending_transfers.remove(&resource_id) {
+            completion.wait();
+        }
+    }
+
+    fn wait_all_transfers(&self) {
+        for completion in self.pending_transfers.values() {
+            completion.wait();
+        }
+    }
+
     pub fn suspend(&self) -> RutabagaResult<()> {
+        self.wait_all_transfers();
         let component = self
             .components
             .get(&self.default_component)
@@ -500,6 +572,7 @@ impl Rutabaga {
     /// Take a snapshot of Rutabaga's current state. The snapshot is serialized into an opaque byte
     /// stream and written to `w`.
     pub fn snapshot(&self, directory: &Path) -> RutabagaResult<()> {
+        self.wait_all_transfers();
         let snapshot_writer = RutabagaSnapshotWriter::from_existing(directory);
 
         let component = self
@@ -511,6 +584,15 @@ impl Rutabaga {
             snapshot_writer.add_namespace(self.default_component.as_str())?;
         component.snapshot(component_snapshot_writer)?;
 
+        // The contents of 2D resources only live in host memory, the guest copies them to and from
+        // its own.
+        let host_mem_writer = snapshot_writer.add_namespace("host_mem_2d")?;
+        for (resource_id, resource) in &self.resources {
+            if let Some(info_2d) = &resource.info_2d {
+                host_mem_writer.add_raw_fragment(&resource_id.to_string(), &info_2d.host_mem)?;
+            }
+        }
+
         let snapshot = RutabagaSnapshot {
             resources: self
                 .resources
@@ -548,6 +630,7 @@ impl Rutabaga {
     /// * Mode2D
     ///    * The VMM must call `Rutabaga::attach_backing` calls for all resources that had backing
     ///      memory at the time of the snapshot.
+    ///    * The contents of the resources are restored, the VMM may flush them to the display.
     /// * ModeVirglRenderer
     ///    * Not supported.
     /// * ModeGfxstream
@@ -574,11 +657,23 @@ impl Rutabaga {
 
         let snapshot: RutabagaSnapshot = snapshot_reader.get_fragment("rutabaga_snapshot")?;
 
+        self.accessed_resources.clear();
+        self.last_access_fences.clear();
+        self.fence_tracker.lock().unwrap().clear();
         self.resources = snapshot
             .resources
             .into_iter()
             .map(|(i, s)| Ok((i, RutabagaResource::try_from(s)?)))
             .collect::<RutabagaResult<_>>()?;
+        // 2D resources are left blank when restoring snapshots taken without their contents.
+        if let Ok(host_mem_reader) = snapshot_reader.get_namespace("host_mem_2d") {
+            for (resource_id, resource) in self.resources.iter_mut() {
+                if let Some(info_2d) = resource.info_2d.as_mut() {
+                    host_mem_reader
+                        .read_raw_fragment(&resource_id.to_string(), &mut info_2d.host_mem)?;
+                }
+            }
+        }
         self.contexts = snapshot
             .contexts
             .into_iter()
@@ -664,6 +759,20 @@ impl Rutabaga {
     /// If the flags include RUTABAGA_FLAG_INFO_RING_IDX, then the fence is created on a
     /// specific timeline on the specific context.
     pub fn create_fence(&mut self, fence: RutabagaFence) -> RutabagaResult<()> {
+        // Recorded first, since components may signal the fence before returning.
+        self.fence_tracker.lock().unwrap().create(&fence);
+        if let Err(e) = self.create_component_fence(fence) {
+            self.fence_tracker.lock().unwrap().cancel(&fence);
+            return Err(e);
+        }
+
+        for resource_id in std::mem::take(&mut self.accessed_resources) {
+            self.last_access_fences.insert(resource_id, fence.fence_id);
+        }
+        Ok(())
+    }
+
+    fn create_component_fence(&mut self, fence: RutabagaFence) -> RutabagaResult<()> {
         if fence.flags & RUTABAGA_FLAG_INFO_RING_IDX != 0 {
             let ctx = self
                 .contexts
@@ -690,6 +799,40 @@ impl Rutabaga {
         Ok(())
     }
 
+    /// Returns the fence counters of every ring fences were created on.
+    pub fn fence_stats(&self) -> Vec<RutabagaFenceRingStats> {
+        self.fence_tracker.lock().unwrap().stats()
+    }
+
+    /// Returns the fences left unsignaled for longer than `threshold`.  Each fence is also
+    /// reported once through the debug handler, or logged if there is none.
+    pub fn check_stuck_fences(&self, threshold: Duration) -> Vec<RutabagaStuckFence> {
+        let stuck = self.fence_tracker.lock().unwrap().stuck(threshold);
+        for (fence, _) in stuck.iter().filter(|(_, newly_stuck)| *newly_stuck) {
+            let message = format!(
+                "fence {} on {:?} of context {} unsignaled for {:?}, last command size {}",
+                fence.fence_id,
+                fence.ring,
+                fence.ctx_id,
+                fence.pending_for,
+                fence.last_command_size
+            );
+            match &self.debug_handler {
+                Some(debug_handler) => {
+                    // Formatted values have no interior nul byte.
+                    let message = CString::new(message).unwrap();
+                    debug_handler.call(RutabagaDebug {
+                        debug_type: RUTABAGA_DEBUG_WARNING,
+                        message: message.as_ptr(),
+                    });
+                }
+                None => log::warn!("{}", message),
+            }
+        }
+
+        stuck.into_iter().map(|(fence, _)| fence).collect()
+    }
+
     /// Polls the default rutabaga component.
     pub fn event_poll(&self) {
         if let Some(component) = self.components.get(&self.default_component) {
@@ -757,6 +900,7 @@ impl Rutabaga {
         resource_id: u32,
         mut vecs: Vec<RutabagaIovec>,
     ) -> RutabagaResult<()> {
+        self.wait_transfers(resource_id);
         let component = self
             .components
             .get_mut(&self.default_component)
@@ -769,11 +913,13 @@ impl Rutabaga {
 
         component.attach_backing(resource_id, &mut vecs)?;
         resource.backing_iovecs = Some(vecs);
+        self.accessed_resources.insert(resource_id);
         Ok(())
     }
 
     /// Detaches any previously attached iovecs from the resource.
     pub fn detach_backing(&mut self, resource_id: u32) -> RutabagaResult<()> {
+        self.wait_transfers(resource_id);
         let component = self
             .components
             .get_mut(&self.default_component)
@@ -789,18 +935,68 @@ impl Rutabaga {
         Ok(())
     }
 
+    /// Detaches the backing of every resource with an attached iovec overlapping one of the host
+    /// address `ranges`, so that components stop accessing memory that is about to go away (memory
+    /// unplug, snapshot trim).  The default component and every component the resource belongs to
+    /// are flushed before the backing is detached from them.  Returns the ids of the detached
+    /// resources by increasing id: backing must be attached to them again before they are used for
+    /// transfers.
+    pub fn invalidate_backing(&mut self, ranges: &[Range<u64>]) -> RutabagaResult<Vec<u32>> {
+        self.wait_all_transfers();
+        self.pending_transfers.clear();
+        if !self.components.contains_key(&self.default_component) {
+            return Err(RutabagaErrorKind::InvalidComponent.into());
+        }
+
+        let mut invalidated = Vec::new();
+        for resource in self.resources.values_mut() {
+            let overlaps = resource.backing_iovecs.as_ref().is_some_and(|iovecs| {
+                iovecs.iter().any(|iovec| {
+                    let start = iovec.base as u64;
+                    let end = start.saturating_add(iovec.len as u64);
+                    ranges
+                        .iter()
+                        .any(|range| start < range.end && range.start < end)
+                })
+            });
+
+            if overlaps {
+                for (component_type, component) in self.components.iter() {
+                    if *component_type != self.default_component
+                        && resource.component_mask & (1 << (*component_type as u8)) == 0
+                    {
+                        continue;
+                    }
+
+                    component.flush_backing(resource.resource_id)?;
+                    component.detach_backing(resource.resource_id);
+                }
+                resource.backing_iovecs = None;
+                invalidated.push(resource.resource_id);
+            }
+        }
+
+        Ok(invalidated)
+    }
+
     /// Releases guest kernel reference on the resource.
     pub fn unref_resource(&mut self, resource_id: u32) -> RutabagaResult<()> {
+        self.wait_transfers(resource_id);
         let component = self
             .components
             .get_mut(&self.default_component)
             .ok_or(RutabagaErrorKind::InvalidComponent)?;
 
-        self.resources
+        let resource = self
+            .resources
             .remove(&resource_id)
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
+        self.accessed_resources.remove(&resource_id);
+        self.last_access_fences.remove(&resource_id);
 
-        component.unref_resource(resource_id);
+        if !is_file_blob(&resource) {
+            component.unref_resource(resource_id);
+        }
         Ok(())
     }
 
@@ -811,6 +1007,20 @@ impl Rutabaga {
         ctx_id: u32,
         resource_id: u32,
         transfer: Transfer3D,
+    ) -> RutabagaResult<()> {
+        self.transfer_write_async(ctx_id, resource_id, transfer)?;
+        self.wait_transfers(resource_id);
+        Ok(())
+    }
+
+    /// Like `transfer_write()`, but may return while the component still copies in the
+    /// background.  Later operations on the resource wait for the copy, and fences created after
+    /// this call are only signaled once it completes.
+    pub fn transfer_write_async(
+        &mut self,
+        ctx_id: u32,
+        resource_id: u32,
+        transfer: Transfer3D,
     ) -> RutabagaResult<()> {
         let component = self
             .components
@@ -822,7 +1032,11 @@ impl Rutabaga {
             .get_mut(&resource_id)
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
 
-        component.transfer_write(ctx_id, resource, transfer)
+        if let Some(completion) = component.transfer_write(ctx_id, resource, transfer)? {
+            self.pending_transfers.insert(resource_id, completion);
+        }
+        self.accessed_resources.insert(resource_id);
+        Ok(())
     }
 
     /// 1) If specified, copies to `buf` from the host resource.
@@ -836,6 +1050,7 @@ impl Rutabaga {
         transfer: Transfer3D,
         buf: Option<IoSliceMut>,
     ) -> RutabagaResult<()> {
+        self.wait_transfers(resource_id);
         let component = self
             .components
             .get(&self.default_component)
@@ -846,10 +1061,16 @@ impl Rutabaga {
             .get_mut(&resource_id)
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
 
-        component.transfer_read(ctx_id, resource, transfer, buf)
+        // `buf` is only borrowed for the duration of the call.
+        if let Some(completion) = component.transfer_read(ctx_id, resource, transfer, buf)? {
+            completion.wait();
+        }
+        self.accessed_resources.insert(resource_id);
+        Ok(())
     }
 
     pub fn resource_flush(&mut self, resource_id: u32) -> RutabagaResult<()> {
+        self.wait_transfers(resource_id);
         let component = self
             .components
             .get(&self.default_component)
@@ -860,7 +1081,9 @@ impl Rutabaga {
             .get_mut(&resource_id)
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
 
-        component.resource_flush(resource)
+        component.resource_flush(resource)?;
+        self.accessed_resources.insert(resource_id);
+        Ok(())
     }
 
     /// Creates a blob resource with the `ctx_id` and `resource_create_blob` metadata.
@@ -878,6 +1101,39 @@ impl Rutabaga {
             return Err(RutabagaErrorKind::InvalidResourceId.into());
         }
 
+        // Host file blobs are owned by rutabaga rather than by a component.
+        if resource_create_blob.blob_mem == RUTABAGA_BLOB_MEM_HOST3D {
+            if let Some(file) = self.blob_files.get(&resource_create_blob.blob_id) {
+                if resource_create_blob.size > file.size {
+                    return Err(
+                        RutabagaErrorKind::SpecViolation("blob larger than its host file").into(),
+                    );
+                }
+
+                self.resources.insert(
+                    resource_id,
+                    RutabagaResource {
+                        resource_id,
+                        handle: Some(file.handle.clone()),
+                        blob: true,
+                        blob_mem: resource_create_blob.blob_mem,
+                        // The file outlives the resource, so its handle is always shareable.
+                        blob_flags: resource_create_blob.blob_flags
+                            | RUTABAGA_BLOB_FLAG_USE_SHAREABLE,
+                        map_info: Some(RUTABAGA_MAP_CACHE_CACHED | RUTABAGA_MAP_ACCESS_READ),
+                        info_2d: None,
+                        info_3d: None,
+                        vulkan_info: None,
+                        backing_iovecs: None,
+                        component_mask: 0,
+                        size: resource_create_blob.size,
+                        mapping: None,
+                    },
+                );
+                return Ok(());
+            }
+        }
+
         let component = self
             .components
             .get_mut(&self.default_component)
@@ -916,12 +1172,24 @@ impl Rutabaga {
             .get_mut(&resource_id)
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
 
-        let component_type = calculate_component(resource.component_mask)?;
-        if component_type == RutabagaComponentType::CrossDomain {
+        // Host file blobs and the blobs of these components, which are shared memory, are mapped
+        // by rutabaga itself.
+        let file_blob = is_file_blob(resource);
+        let component_type = if file_blob {
+            None
+        } else {
+            Some(calculate_component(resource.component_mask)?)
+        };
+        if file_blob
+            || matches!(
+                component_type,
+                Some(RutabagaComponentType::CrossDomain | RutabagaComponentType::Rutabaga2D)
+            )
+        {
             let handle_opt = resource.handle.take();
             match handle_opt {
                 Some(handle) => {
-                    if handle.handle_type != RUTABAGA_HANDLE_TYPE_MEM_SHM {
+                    if !file_blob && handle.handle_type != RUTABAGA_HANDLE_TYPE_MEM_SHM {
                         return Err(RutabagaErrorKind::SpecViolation(
                             "expected a shared memory handle",
                         )
@@ -952,9 +1220,8 @@ impl Rutabaga {
             }
         }
 
-        let component = self
-            .components
-            .get(&component_type)
+        let component = component_type
+            .and_then(|component_type| self.components.get(&component_type))
             .ok_or(RutabagaErrorKind::InvalidComponent)?;
 
         component.map(resource_id)
@@ -967,8 +1234,16 @@ impl Rutabaga {
             .get_mut(&resource_id)
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
 
+        if is_file_blob(resource) {
+            resource.mapping = None;
+            return Ok(());
+        }
+
         let component_type = calculate_component(resource.component_mask)?;
-        if component_type == RutabagaComponentType::CrossDomain {
+        if matches!(
+            component_type,
+            RutabagaComponentType::CrossDomain | RutabagaComponentType::Rutabaga2D
+        ) {
             resource.mapping = None;
             return Ok(());
         }
@@ -1019,6 +1294,33 @@ impl Rutabaga {
             .ok_or(RutabagaErrorKind::SpecViolation("no 3d info available").into())
     }
 
+    /// Describes the resources the guest still holds a reference on, by increasing id.
+    pub fn debug_dump(&self) -> Vec<RutabagaResourceDebugInfo> {
+        const COMPONENTS: [RutabagaComponentType; 4] = [
+            RutabagaComponentType::Rutabaga2D,
+            RutabagaComponentType::VirglRenderer,
+            RutabagaComponentType::Gfxstream,
+            RutabagaComponentType::CrossDomain,
+        ];
+
+        self.resources
+            .values()
+            .map(|resource| RutabagaResourceDebugInfo {
+                resource_id: resource.resource_id,
+                size: resource.size,
+                blob: resource.blob,
+                blob_mem: resource.blob_mem,
+                blob_flags: resource.blob_flags,
+                components: COMPONENTS
+                    .into_iter()
+                    .filter(|c| resource.component_mask & (1 << (*c as u8)) != 0)
+                    .collect(),
+                has_backing: resource.backing_iovecs.is_some(),
+                last_access_fence: self.last_access_fences.get(&resource.resource_id).copied(),
+            })
+            .collect()
+    }
+
     /// Exports a blob resource.  See virtio-gpu spec for blob flag use flags.
     pub fn export_blob(&mut self, resource_id: u32) -> RutabagaResult<RutabagaHandle> {
         let resource = self
@@ -1102,6 +1404,25 @@ impl Rutabaga {
         self.contexts
             .remove(&ctx_id)
             .ok_or(RutabagaErrorKind::InvalidContextId)?;
+        self.fence_tracker.lock().unwrap().destroy_context(ctx_id);
+        Ok(())
+    }
+
+    /// Destroys the context given by `ctx_id` on behalf of the host, typically because it hung the
+    /// GPU.  The pending fences of its rings are signaled with `RUTABAGA_FLAG_FENCE_ERROR` so that
+    /// the guest stops waiting on them, and its later commands fail with an invalid context id.
+    /// Fences the context created on the global timeline are left alone, since signaling them
+    /// would also signal the fences of the other contexts.
+    pub fn destroy_context_for_misbehavior(&mut self, ctx_id: u32) -> RutabagaResult<()> {
+        self.contexts
+            .remove(&ctx_id)
+            .ok_or(RutabagaErrorKind::InvalidContextId)?;
+
+        let error_fences = self.fence_tracker.lock().unwrap().error_fences(ctx_id);
+        for fence in error_fences {
+            self.fence_handler.call(fence);
+        }
+        self.fence_tracker.lock().unwrap().destroy_context(ctx_id);
         Ok(())
     }
 
@@ -1118,6 +1439,7 @@ impl Rutabaga {
             .ok_or(RutabagaErrorKind::InvalidResourceId)?;
 
         ctx.attach(resource);
+        self.accessed_resources.insert(resource_id);
         Ok(())
     }
 
@@ -1163,6 +1485,10 @@ impl Rutabaga {
             shareable_fences.insert(i, clone);
         }
 
+        self.fence_tracker
+            .lock()
+            .unwrap()
+            .submit(ctx_id, commands.len());
         ctx.submit_cmd(commands, fence_ids, shareable_fences)
     }
 
@@ -1179,6 +1505,13 @@ impl Rutabaga {
     }
 }
 
+impl Drop for Rutabaga {
+    fn drop(&mut self) {
+        // Background transfers write into resources dropped before the components.
+        self.wait_all_transfers();
+    }
+}
+
 /// Rutabaga Builder, following the Rust builder pattern.
 #[derive(Clone)]
 pub struct RutabagaBuilder {
@@ -1191,6 +1524,7 @@ pub struct RutabagaBuilder {
     channels: Option<Vec<RutabagaChannel>>,
     debug_handler: Option<RutabagaDebugHandler>,
     renderer_features: Option<String>,
+    blob_files: Vec<RutabagaBlobFile>,
 }
 
 impl RutabagaBuilder {
@@ -1210,6 +1544,7 @@ impl RutabagaBuilder {
             channels: None,
             debug_handler: None,
             renderer_features: None,
+            blob_files: Vec::new(),
         }
     }
 
@@ -1309,6 +1644,12 @@ impl RutabagaBuilder {
         self
     }
 
+    /// Set the host files the guest can create blob resources from for the RutabagaBuilder
+    pub fn set_blob_files(mut self, blob_files: Vec<RutabagaBlobFile>) -> RutabagaBuilder {
+        self.blob_files = blob_files;
+        self
+    }
+
     /// Builds Rutabaga and returns a handle to it.
     ///
     /// This should be only called once per every virtual machine instance.  Rutabaga tries to
@@ -1319,6 +1660,15 @@ impl RutabagaBuilder {
         fence_handler: RutabagaFenceHandler,
         #[allow(unused_variables)] rutabaga_server_descriptor: Option<OwnedDescriptor>,
     ) -> RutabagaResult<Rutabaga> {
+        let fence_tracker: Arc<Mutex<FenceTracker>> = Default::default();
+        let fence_handler = {
+            let fence_tracker = fence_tracker.clone();
+            RutabagaFenceHandler::new(move |fence| {
+                fence_tracker.lock().unwrap().signal(&fence);
+                fence_handler.call(fence);
+            })
+        };
+
         let mut rutabaga_components: Map<RutabagaComponentType, Box<dyn RutabagaComponent>> =
             Default::default();
 
@@ -1447,12 +1797,25 @@ impl RutabagaBuilder {
             default_component: self.default_component,
             capset_info: rutabaga_capsets,
             fence_handler,
+            debug_handler: self.debug_handler,
+            fence_tracker,
+            accessed_resources: Default::default(),
+            last_access_fences: Default::default(),
+            blob_files: self
+                .blob_files
+                .into_iter()
+                .map(|file| (file.blob_id, file))
+                .collect(),
+            pending_transfers: Default::default(),
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
     use crate::*;
 
     fn new_2d() -> Rutabaga {
@@ -1522,4 +1885,396 @@ mod tests {
         // NOTE: We attached an backing iovec, but it should be gone post-restore.
         assert!(rutabaga_resource.backing_iovecs.is_none());
     }
+
+    #[test]
+    fn snapshot_restore_2d_contents() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+
+        let (width, height) = (64, 32);
+        let resource_create_3d = ResourceCreate3D {
+            target: RUTABAGA_PIPE_TEXTURE_2D,
+            format: 1,
+            bind: RUTABAGA_PIPE_BIND_RENDER_TARGET,
+            width,
+            height,
+            depth: 1,
+            array_size: 1,
+            last_level: 0,
+            nr_samples: 0,
+            flags: 0,
+        };
+
+        let mut backing: Vec<u8> = (0..width * height * 4).map(|i| (i * 3) as u8).collect();
+        let mut rutabaga1 = new_2d();
+        rutabaga1.resource_create_3d(1, resource_create_3d).unwrap();
+        rutabaga1
+            .attach_backing(
+                1,
+                vec![RutabagaIovec {
+                    base: backing.as_mut_ptr() as *mut std::ffi::c_void,
+                    len: backing.len(),
+                }],
+            )
+            .unwrap();
+        rutabaga1
+            .transfer_write(0, 1, Transfer3D::new_2d(0, 0, width, height, 0))
+            .unwrap();
+        rutabaga1.snapshot(snapshot_dir.path()).unwrap();
+
+        // The contents are restored without the backing memory.
+        let mut rutabaga2 = new_2d();
+        rutabaga2.restore(snapshot_dir.path()).unwrap();
+        let mut contents = vec![0u8; backing.len()];
+        let mut transfer = Transfer3D::new_2d(0, 0, width, height, 0);
+        transfer.stride = width * 4;
+        rutabaga2
+            .transfer_read(
+                0,
+                1,
+                transfer,
+                Some(std::io::IoSliceMut::new(&mut contents)),
+            )
+            .unwrap();
+        assert!(contents == backing);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn map_blob_2d() {
+        let resource_create_blob = ResourceCreateBlob {
+            blob_mem: RUTABAGA_BLOB_MEM_HOST3D,
+            blob_flags: RUTABAGA_BLOB_FLAG_USE_MAPPABLE,
+            blob_id: 0,
+            size: 4096,
+        };
+
+        let mut rutabaga = new_2d();
+        rutabaga
+            .resource_create_blob(0, 1, resource_create_blob, None, None)
+            .unwrap();
+        let mapping = rutabaga.map(1).unwrap();
+        assert_eq!(mapping.size, 4096);
+        // SAFETY:
+        // Safe because the mapping is 4096 bytes long and alive until `unmap`.
+        unsafe { *(mapping.ptr as *mut u8) = 1 };
+        rutabaga.unmap(1).unwrap();
+    }
+
+    #[test]
+    fn debug_dump_2d() {
+        let resource_create_3d = ResourceCreate3D {
+            target: RUTABAGA_PIPE_TEXTURE_2D,
+            format: 1,
+            bind: RUTABAGA_PIPE_BIND_RENDER_TARGET,
+            width: 16,
+            height: 16,
+            depth: 1,
+            array_size: 1,
+            last_level: 0,
+            nr_samples: 0,
+            flags: 0,
+        };
+
+        let mut rutabaga = new_2d();
+        rutabaga.resource_create_3d(1, resource_create_3d).unwrap();
+        rutabaga.resource_create_3d(2, resource_create_3d).unwrap();
+        rutabaga
+            .attach_backing(
+                2,
+                vec![RutabagaIovec {
+                    base: std::ptr::null_mut(),
+                    len: 16 * 16 * 4,
+                }],
+            )
+            .unwrap();
+        rutabaga
+            .create_fence(RutabagaFence {
+                flags: 0,
+                fence_id: 7,
+                ctx_id: 0,
+                ring_idx: 0,
+            })
+            .unwrap();
+
+        let dump = rutabaga.debug_dump();
+        assert_eq!(dump.len(), 2);
+        assert_eq!(dump[0].resource_id, 1);
+        assert!(!dump[0].has_backing);
+        assert_eq!(dump[0].last_access_fence, None);
+        assert_eq!(dump[1].resource_id, 2);
+        assert!(dump[1].has_backing);
+        assert_eq!(dump[1].last_access_fence, Some(7));
+        assert!(dump[1].components == [RutabagaComponentType::Rutabaga2D]);
+
+        rutabaga.unref_resource(2).unwrap();
+        assert_eq!(rutabaga.debug_dump().len(), 1);
+    }
+
+    #[test]
+    fn invalidate_backing_2d() {
+        let resource_create_3d = ResourceCreate3D {
+            target: RUTABAGA_PIPE_TEXTURE_2D,
+            format: 1,
+            bind: RUTABAGA_PIPE_BIND_RENDER_TARGET,
+            width: 16,
+            height: 16,
+            depth: 1,
+            array_size: 1,
+            last_level: 0,
+            nr_samples: 0,
+            flags: 0,
+        };
+
+        // The iovecs are never accessed, only compared with the invalidated ranges.
+        let mut rutabaga = new_2d();
+        for (resource_id, base) in [(1, 0x1000), (2, 0x3000), (3, 0x5000)] {
+            rutabaga
+                .resource_create_3d(resource_id, resource_create_3d)
+                .unwrap();
+            rutabaga
+                .attach_backing(
+                    resource_id,
+                    vec![RutabagaIovec {
+                        base: base as *mut std::ffi::c_void,
+                        len: 0x1000,
+                    }],
+                )
+                .unwrap();
+        }
+
+        assert!(rutabaga
+            .invalidate_backing(&[0x2000..0x3000])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            rutabaga
+                .invalidate_backing(&[0x1800..0x1900, 0x3fff..0x5001])
+                .unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(rutabaga.debug_dump().iter().all(|info| !info.has_backing));
+        assert!(rutabaga
+            .invalidate_backing(&[0..u64::MAX])
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Records the backing calls it receives, to check which components are notified.
+    struct RecordingComponent {
+        calls: Arc<Mutex<Vec<(&'static str, u32)>>>,
+    }
+
+    impl super::RutabagaComponent for RecordingComponent {
+        fn detach_backing(&self, resource_id: u32) {
+            self.calls.lock().unwrap().push(("detach", resource_id));
+        }
+
+        fn flush_backing(&self, resource_id: u32) -> RutabagaResult<()> {
+            self.calls.lock().unwrap().push(("flush", resource_id));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn invalidate_backing_notifies_resource_components() {
+        let resource_create_3d = ResourceCreate3D {
+            target: RUTABAGA_PIPE_TEXTURE_2D,
+            format: 1,
+            bind: RUTABAGA_PIPE_BIND_RENDER_TARGET,
+            width: 16,
+            height: 16,
+            depth: 1,
+            array_size: 1,
+            last_level: 0,
+            nr_samples: 0,
+            flags: 0,
+        };
+
+        let mut rutabaga = new_2d();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for component_type in [
+            RutabagaComponentType::VirglRenderer,
+            RutabagaComponentType::CrossDomain,
+        ] {
+            rutabaga.components.insert(
+                component_type,
+                Box::new(RecordingComponent {
+                    calls: calls.clone(),
+                }),
+            );
+        }
+
+        // The iovecs are never accessed, only compared with the invalidated ranges.
+        for resource_id in [1, 2] {
+            rutabaga
+                .resource_create_3d(resource_id, resource_create_3d)
+                .unwrap();
+            rutabaga
+                .attach_backing(
+                    resource_id,
+                    vec![RutabagaIovec {
+                        base: 0x1000 as *mut std::ffi::c_void,
+                        len: 0x1000,
+                    }],
+                )
+                .unwrap();
+        }
+        rutabaga.resources.get_mut(&2).unwrap().component_mask |=
+            1 << (RutabagaComponentType::CrossDomain as u8);
+
+        assert_eq!(
+            rutabaga.invalidate_backing(&[0x1000..0x2000]).unwrap(),
+            vec![1, 2]
+        );
+        // Only the component resource 2 also belongs to is notified, flushed before detaching.
+        assert_eq!(*calls.lock().unwrap(), vec![("flush", 2), ("detach", 2)]);
+    }
+
+    #[test]
+    fn fence_stats_2d() {
+        let mut rutabaga = new_2d();
+        for fence_id in 1..=2 {
+            rutabaga
+                .create_fence(RutabagaFence {
+                    flags: RUTABAGA_FLAG_FENCE,
+                    fence_id,
+                    ctx_id: 0,
+                    ring_idx: 0,
+                })
+                .unwrap();
+        }
+
+        // The 2D component signals fences as soon as they are created.
+        let stats = rutabaga.fence_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].ring, RutabagaFenceRing::Global);
+        assert_eq!((stats[0].created, stats[0].signaled), (2, 2));
+        assert_eq!(stats[0].pending, 0);
+        assert!(rutabaga
+            .check_stuck_fences(std::time::Duration::ZERO)
+            .is_empty());
+    }
+
+    #[test]
+    fn async_transfer_write_2d() {
+        let (width, height) = (1024, 512);
+        let resource_create_3d = ResourceCreate3D {
+            target: RUTABAGA_PIPE_TEXTURE_2D,
+            format: 1,
+            bind: RUTABAGA_PIPE_BIND_RENDER_TARGET,
+            width,
+            height,
+            depth: 1,
+            array_size: 1,
+            last_level: 0,
+            nr_samples: 0,
+            flags: 0,
+        };
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut rutabaga = RutabagaBuilder::new(RutabagaComponentType::Rutabaga2D, 0)
+            .build(
+                RutabagaHandler::new(move |fence: RutabagaFence| {
+                    sender.send(fence.fence_id).unwrap()
+                }),
+                None,
+            )
+            .unwrap();
+
+        let mut backing: Vec<u8> = (0..width * height * 4).map(|i| i as u8).collect();
+        rutabaga.resource_create_3d(1, resource_create_3d).unwrap();
+        rutabaga
+            .attach_backing(
+                1,
+                vec![RutabagaIovec {
+                    base: backing.as_mut_ptr() as *mut std::ffi::c_void,
+                    len: backing.len(),
+                }],
+            )
+            .unwrap();
+
+        // Large enough to be made by the transfer thread, which also signals the fence.
+        rutabaga
+            .transfer_write_async(0, 1, Transfer3D::new_2d(0, 0, width, height, 0))
+            .unwrap();
+        rutabaga
+            .create_fence(RutabagaFence {
+                flags: RUTABAGA_FLAG_FENCE,
+                fence_id: 1,
+                ctx_id: 0,
+                ring_idx: 0,
+            })
+            .unwrap();
+
+        // Reads wait for the transfers in flight.
+        let mut contents = vec![0u8; backing.len()];
+        let mut transfer = Transfer3D::new_2d(0, 0, width, height, 0);
+        transfer.stride = width * 4;
+        rutabaga
+            .transfer_read(
+                0,
+                1,
+                transfer,
+                Some(std::io::IoSliceMut::new(&mut contents)),
+            )
+            .unwrap();
+        assert!(contents == backing);
+        assert_eq!(
+            receiver
+                .recv_timeout(std::time::Duration::from_secs(10))
+                .unwrap(),
+            1
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn blob_file_2d() {
+        use std::io::Write;
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[0xab; 4096]).unwrap();
+        let blob_file = RutabagaBlobFile {
+            blob_id: 7,
+            handle: std::sync::Arc::new(RutabagaHandle {
+                os_handle: file.into(),
+                handle_type: RUTABAGA_HANDLE_TYPE_MEM_FILE,
+            }),
+            size: 4096,
+        };
+        let mut rutabaga = RutabagaBuilder::new(RutabagaComponentType::Rutabaga2D, 0)
+            .set_blob_files(vec![blob_file])
+            .build(RutabagaHandler::new(|_| {}), None)
+            .unwrap();
+
+        let create_blob = |size| ResourceCreateBlob {
+            blob_mem: RUTABAGA_BLOB_MEM_HOST3D,
+            blob_flags: RUTABAGA_BLOB_FLAG_USE_MAPPABLE,
+            blob_id: 7,
+            size,
+        };
+        assert!(rutabaga
+            .resource_create_blob(1, 1, create_blob(8192), None, None)
+            .is_err());
+        rutabaga
+            .resource_create_blob(1, 1, create_blob(4096), None, None)
+            .unwrap();
+
+        assert_eq!(
+            rutabaga.map_info(1).unwrap(),
+            RUTABAGA_MAP_CACHE_CACHED | RUTABAGA_MAP_ACCESS_READ
+        );
+        let mapping = rutabaga.map(1).unwrap();
+        // SAFETY: the mapping is 4096 bytes long and stays mapped until `unmap()`.
+        let contents = unsafe { std::slice::from_raw_parts(mapping.ptr as *const u8, 4096) };
+        assert!(contents.iter().all(|byte| *byte == 0xab));
+        rutabaga.unmap(1).unwrap();
+
+        // The file handle is shared by every export of the blob.
+        for _ in 0..2 {
+            let handle = rutabaga.export_blob(1).unwrap();
+            assert_eq!(handle.handle_type, RUTABAGA_HANDLE_TYPE_MEM_FILE);
+        }
+        rutabaga.unref_resource(1).unwrap();
+    }
 }
@@ -236,6 +236,13 @@ pub trait Gralloc: Send {
     ) -> RutabagaResult<Box<dyn MappedRegion>> {
         Err(RutabagaErrorKind::Unsupported.into())
     }
+
+    /// Implementations must create a timeline semaphore with the given `initial_value` and return
+    /// an exported RutabagaHandle to it upon success.  This is optional and only works with the
+    /// Vulkano backend.
+    fn create_timeline_semaphore(&mut self, _initial_value: u64) -> RutabagaResult<RutabagaHandle> {
+        Err(RutabagaErrorKind::Unsupported.into())
+    }
 }
 
 /// Enumeration of possible allocation backends.
@@ -387,6 +394,20 @@ impl RutabagaGralloc {
 
         gralloc.import_and_map(handle, vulkan_info, size)
     }
+
+    /// Creates a timeline semaphore with the given `initial_value`.  Returns the exported handle
+    /// upon success.  Should not be used with minigbm or system gralloc backends.
+    pub fn create_timeline_semaphore(
+        &mut self,
+        initial_value: u64,
+    ) -> RutabagaResult<RutabagaHandle> {
+        let gralloc = self
+            .grallocs
+            .get_mut(&GrallocBackend::Vulkano)
+            .ok_or(RutabagaErrorKind::InvalidGrallocBackend)?;
+
+        gralloc.create_timeline_semaphore(initial_value)
+    }
 }
 
 #[cfg(test)]
@@ -500,4 +521,23 @@ mod tests {
         assert_eq!(size as u64, reqs.size);
         assert_ne!(addr as *const u8, std::ptr::null());
     }
+
+    #[test]
+    #[cfg_attr(target_os = "windows", ignore)]
+    fn create_timeline_semaphore() {
+        let gralloc_result = RutabagaGralloc::new(RutabagaGrallocBackendFlags::new());
+        if gralloc_result.is_err() {
+            return;
+        }
+
+        let mut gralloc = gralloc_result.unwrap();
+
+        // Only the Vulkano backend creates semaphores, and not every host supports them.
+        let handle = match gralloc.create_timeline_semaphore(1) {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+
+        assert_eq!(handle.handle_type, RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_FD);
+    }
 }
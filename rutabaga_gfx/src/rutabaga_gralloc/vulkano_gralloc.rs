@@ -13,13 +13,17 @@ mod sys;
 
 use std::collections::HashMap as Map;
 use std::convert::TryInto;
+use std::ptr;
 use std::sync::Arc;
 
+use ash::vk;
+use ash::vk::Handle;
 use log::warn;
 use vulkano::device::physical::PhysicalDeviceType;
 use vulkano::device::Device;
 use vulkano::device::DeviceCreateInfo;
 use vulkano::device::DeviceCreationError;
+use vulkano::device::Features;
 use vulkano::device::QueueCreateInfo;
 use vulkano::device::QueueFlags;
 use vulkano::image;
@@ -35,7 +39,6 @@ use vulkano::instance::Version;
 use vulkano::memory::DedicatedAllocation;
 use vulkano::memory::DeviceMemory;
 use vulkano::memory::DeviceMemoryError;
-use vulkano::memory::ExternalMemoryHandleType;
 use vulkano::memory::ExternalMemoryHandleTypes;
 use vulkano::memory::MappedDeviceMemory;
 use vulkano::memory::MemoryAllocateInfo;
@@ -47,6 +50,7 @@ use vulkano::sync::Sharing;
 use vulkano::LoadingError;
 use vulkano::VulkanError;
 use vulkano::VulkanLibrary;
+use vulkano::VulkanObject;
 
 use crate::rutabaga_gralloc::gralloc::Gralloc;
 use crate::rutabaga_gralloc::gralloc::ImageAllocationInfo;
@@ -138,11 +142,15 @@ impl VulkanoGralloc {
         // explanation of VK initialization.
         let library = VulkanLibrary::new()?;
 
-        let instance_extensions = InstanceExtensions {
+        let desired_instance_extensions = InstanceExtensions {
             khr_external_memory_capabilities: true,
+            khr_external_semaphore_capabilities: true,
             khr_get_physical_device_properties2: true,
             ..InstanceExtensions::empty()
         };
+        let instance_extensions = library
+            .supported_extensions()
+            .intersection(&desired_instance_extensions);
         let instance = Instance::new(
             library,
             InstanceCreateInfo {
@@ -177,10 +185,18 @@ impl VulkanoGralloc {
 
             let intersection = supported_extensions.intersection(&desired_extensions);
 
+            // Timeline semaphores are core in Vulkan 1.2, but we only ask for Vulkan 1.1.
+            let enabled_features = Features {
+                timeline_semaphore: intersection.khr_timeline_semaphore
+                    && physical.supported_features().timeline_semaphore,
+                ..Features::empty()
+            };
+
             if let Ok((device, mut _queues)) = Device::new(
                 physical.clone(),
                 DeviceCreateInfo {
                     enabled_extensions: intersection,
+                    enabled_features,
                     queue_create_infos: vec![QueueCreateInfo {
                         queue_family_index: queue_family_index as u32,
                         ..Default::default()
@@ -214,6 +230,19 @@ impl VulkanoGralloc {
         }))
     }
 
+    /// Returns the device allocations are made from: the integrated GPU if there is one, the
+    /// discrete GPU otherwise.
+    fn allocation_device(&self) -> RutabagaResult<&Arc<Device>> {
+        let device_type = match self.has_integrated_gpu {
+            true => PhysicalDeviceType::IntegratedGpu,
+            false => PhysicalDeviceType::DiscreteGpu,
+        };
+
+        self.devices
+            .get(&device_type)
+            .ok_or(RutabagaErrorKind::InvalidGrallocGpuType.into())
+    }
+
     // This function is used safely in this module because gralloc does not:
     //
     //  (1) bind images to any memory.
@@ -226,15 +255,7 @@ impl VulkanoGralloc {
         &mut self,
         info: ImageAllocationInfo,
     ) -> RutabagaResult<(Arc<image::sys::RawImage>, MemoryRequirements)> {
-        let device = if self.has_integrated_gpu {
-            self.devices
-                .get(&PhysicalDeviceType::IntegratedGpu)
-                .ok_or(RutabagaErrorKind::InvalidGrallocGpuType)?
-        } else {
-            self.devices
-                .get(&PhysicalDeviceType::DiscreteGpu)
-                .ok_or(RutabagaErrorKind::InvalidGrallocGpuType)?
-        };
+        let device = self.allocation_device()?;
 
         let usage = match info.flags.uses_rendering() {
             true => ImageUsage::COLOR_ATTACHMENT,
@@ -265,6 +286,8 @@ impl VulkanoGralloc {
                 usage,
                 mip_levels: 1,
                 sharing: Sharing::Exclusive,
+                // The memory types the image can be bound to depend on whether it is exported.
+                external_memory_handle_types: VulkanoGralloc::memory_export_type(device).into(),
                 ..Default::default()
             },
         )?);
@@ -304,16 +327,7 @@ impl Gralloc for VulkanoGralloc {
 
         let (raw_image, memory_requirements) = unsafe { self.create_image(info)? };
 
-        let device_type = if self.has_integrated_gpu {
-            &PhysicalDeviceType::IntegratedGpu
-        } else {
-            &PhysicalDeviceType::DiscreteGpu
-        };
-
-        let device = self
-            .devices
-            .get(device_type)
-            .ok_or(RutabagaErrorKind::InvalidGrallocGpuType)?;
+        let device = self.allocation_device()?;
 
         let planar_layout = info.drm_format.planar_layout()?;
 
@@ -431,39 +445,24 @@ impl Gralloc for VulkanoGralloc {
             .vulkan_info
             .ok_or(RutabagaErrorKind::InvalidVulkanInfo)?;
 
-        let device = if self.has_integrated_gpu {
-            self.devices
-                .get(&PhysicalDeviceType::IntegratedGpu)
-                .ok_or(RutabagaErrorKind::InvalidGrallocGpuType)?
-        } else {
-            self.devices
-                .get(&PhysicalDeviceType::DiscreteGpu)
-                .ok_or(RutabagaErrorKind::InvalidGrallocGpuType)?
-        };
-
-        if vulkan_info.memory_idx as usize
-            >= device
-                .physical_device()
-                .memory_properties()
-                .memory_types
-                .len()
+        let device = self.allocation_device()?;
+
+        // The requirements must come from this device, and the memory type must be one the
+        // image can be bound to.
+        if vulkan_info.device_id != device.get_id()
+            || vulkan_info.memory_idx as usize
+                >= device
+                    .physical_device()
+                    .memory_properties()
+                    .memory_types
+                    .len()
+            || memory_requirements.memory_type_bits & (1 << vulkan_info.memory_idx) == 0
         {
             return Err(RutabagaErrorKind::InvalidVulkanInfo.into());
         }
 
-        let (export_handle_type, export_handle_types, rutabaga_type) =
-            match device.enabled_extensions().ext_external_memory_dma_buf {
-                true => (
-                    ExternalMemoryHandleType::DmaBuf,
-                    ExternalMemoryHandleTypes::DMA_BUF,
-                    RUTABAGA_HANDLE_TYPE_MEM_DMABUF,
-                ),
-                false => (
-                    ExternalMemoryHandleType::OpaqueFd,
-                    ExternalMemoryHandleTypes::OPAQUE_FD,
-                    RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_FD,
-                ),
-            };
+        let export_handle_type = VulkanoGralloc::memory_export_type(device);
+        let export_handle_types = ExternalMemoryHandleTypes::from(export_handle_type);
 
         let dedicated_allocation = match device.enabled_extensions().khr_dedicated_allocation {
             true => {
@@ -487,12 +486,51 @@ impl Gralloc for VulkanoGralloc {
             },
         )?;
 
-        let descriptor = device_memory.export_fd(export_handle_type)?.into();
+        VulkanoGralloc::export_memory(&device_memory, export_handle_type)
+    }
+
+    fn create_timeline_semaphore(&mut self, initial_value: u64) -> RutabagaResult<RutabagaHandle> {
+        let device = self.allocation_device()?;
+
+        if !device.enabled_features().timeline_semaphore
+            || !VulkanoGralloc::supports_semaphore_export(device)
+        {
+            return Err(RutabagaErrorKind::Unsupported.into());
+        }
 
-        Ok(RutabagaHandle {
-            os_handle: descriptor,
-            handle_type: rutabaga_type,
-        })
+        // Vulkano has no support for timeline semaphores, so the raw Vulkan functions are used.
+        let mut export_info = vk::ExportSemaphoreCreateInfo {
+            handle_types: VulkanoGralloc::semaphore_export_type(),
+            ..Default::default()
+        };
+        let type_info = vk::SemaphoreTypeCreateInfo {
+            p_next: &mut export_info as *mut _ as *const _,
+            semaphore_type: vk::SemaphoreType::TIMELINE,
+            initial_value,
+            ..Default::default()
+        };
+        let create_info = vk::SemaphoreCreateInfo {
+            p_next: &type_info as *const _ as *const _,
+            ..Default::default()
+        };
+
+        let fns = device.fns();
+        let mut semaphore = vk::Semaphore::null();
+        // Safe because the create info chain is valid for the duration of the call, and the
+        // timeline semaphore feature and the export extension are enabled on the device.
+        unsafe {
+            (fns.v1_0.create_semaphore)(device.handle(), &create_info, ptr::null(), &mut semaphore)
+        }
+        .result()
+        .map_err(VulkanError::from)?;
+
+        // Safe because the semaphore was created above as exportable, and the exported handle
+        // keeps the payload alive once the semaphore is destroyed.
+        unsafe {
+            let result = VulkanoGralloc::export_semaphore(device, semaphore);
+            (fns.v1_0.destroy_semaphore)(device.handle(), semaphore, ptr::null());
+            result
+        }
     }
 
     /// Implementations must map the memory associated with the `resource_id` upon success.
@@ -5,18 +5,23 @@
 use std::fs::File;
 use std::sync::Arc;
 
+use ash::vk;
 use vulkano::device::Device;
 use vulkano::device::DeviceExtensions;
 use vulkano::memory::DeviceMemory;
 use vulkano::memory::ExternalMemoryHandleType;
 use vulkano::memory::MemoryAllocateInfo;
 use vulkano::memory::MemoryImportInfo;
+use vulkano::VulkanError;
+use vulkano::VulkanObject;
 
 use crate::rutabaga_gralloc::vulkano_gralloc::VulkanoGralloc;
 use crate::rutabaga_os::FromRawDescriptor;
 use crate::rutabaga_os::IntoRawDescriptor;
+use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_utils::RUTABAGA_HANDLE_TYPE_MEM_DMABUF;
 use crate::rutabaga_utils::RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_FD;
+use crate::rutabaga_utils::RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_FD;
 use crate::RutabagaErrorKind;
 use crate::RutabagaHandle;
 use crate::RutabagaResult;
@@ -30,10 +35,80 @@ impl VulkanoGralloc {
             khr_external_memory: true,
             khr_external_memory_fd: true,
             ext_external_memory_dma_buf: true,
+            khr_external_semaphore: true,
+            khr_external_semaphore_fd: true,
+            khr_timeline_semaphore: true,
             ..DeviceExtensions::empty()
         }
     }
 
+    /// Get the handle type allocations on `device` are exported as.  A dma-buf is preferred since
+    /// it can be shared with the display and multimedia subsystems.
+    pub(crate) fn memory_export_type(device: &Device) -> ExternalMemoryHandleType {
+        match device.enabled_extensions().ext_external_memory_dma_buf {
+            true => ExternalMemoryHandleType::DmaBuf,
+            false => ExternalMemoryHandleType::OpaqueFd,
+        }
+    }
+
+    /// Export `device_memory`, which must have been allocated as exportable to `handle_type`.
+    pub(crate) fn export_memory(
+        device_memory: &DeviceMemory,
+        handle_type: ExternalMemoryHandleType,
+    ) -> RutabagaResult<RutabagaHandle> {
+        let rutabaga_type = match handle_type {
+            ExternalMemoryHandleType::DmaBuf => RUTABAGA_HANDLE_TYPE_MEM_DMABUF,
+            ExternalMemoryHandleType::OpaqueFd => RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_FD,
+            _ => return Err(RutabagaErrorKind::InvalidRutabagaHandle.into()),
+        };
+
+        Ok(RutabagaHandle {
+            os_handle: device_memory.export_fd(handle_type)?.into(),
+            handle_type: rutabaga_type,
+        })
+    }
+
+    /// Returns true if semaphores created on `device` can be exported.
+    pub(crate) fn supports_semaphore_export(device: &Device) -> bool {
+        device.enabled_extensions().khr_external_semaphore_fd
+    }
+
+    /// Get the handle type semaphores are exported as.
+    pub(crate) fn semaphore_export_type() -> vk::ExternalSemaphoreHandleTypeFlags {
+        vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD
+    }
+
+    /// Export `semaphore` as an opaque FD.  The FD holds a reference to the semaphore payload, so
+    /// the semaphore may be destroyed afterwards.
+    ///
+    /// # Safety
+    /// Safe if `semaphore` is a valid semaphore of `device`, created as exportable to an opaque FD.
+    pub(crate) unsafe fn export_semaphore(
+        device: &Device,
+        semaphore: vk::Semaphore,
+    ) -> RutabagaResult<RutabagaHandle> {
+        let get_fd_info = vk::SemaphoreGetFdInfoKHR {
+            semaphore,
+            handle_type: vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD,
+            ..Default::default()
+        };
+
+        let mut fd = -1;
+        (device.fns().khr_external_semaphore_fd.get_semaphore_fd_khr)(
+            device.handle(),
+            &get_fd_info,
+            &mut fd,
+        )
+        .result()
+        .map_err(VulkanError::from)?;
+
+        Ok(RutabagaHandle {
+            // Safe because the driver transferred the ownership of `fd` to us.
+            os_handle: OwnedDescriptor::from_raw_descriptor(fd),
+            handle_type: RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_FD,
+        })
+    }
+
     /// Import memory from a handle.
     ///
     /// # Safety
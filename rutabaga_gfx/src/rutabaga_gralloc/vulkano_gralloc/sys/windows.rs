@@ -2,18 +2,27 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::ptr;
 use std::sync::Arc;
 
+use ash::vk;
 use vulkano::device::Device;
 use vulkano::device::DeviceExtensions;
+use vulkano::device::DeviceOwned;
 use vulkano::memory::DeviceMemory;
 use vulkano::memory::ExternalMemoryHandleType;
 use vulkano::memory::MemoryAllocateInfo;
 use vulkano::memory::MemoryImportInfo;
+use vulkano::VulkanError;
+use vulkano::VulkanObject;
 
 use crate::rutabaga_gralloc::vulkano_gralloc::VulkanoGralloc;
 use crate::rutabaga_os::AsRawDescriptor;
+use crate::rutabaga_os::FromRawDescriptor;
+use crate::rutabaga_os::OwnedDescriptor;
+use crate::rutabaga_os::RawDescriptor;
 use crate::rutabaga_utils::RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_WIN32;
+use crate::rutabaga_utils::RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_WIN32;
 use crate::RutabagaErrorKind;
 use crate::RutabagaHandle;
 use crate::RutabagaResult;
@@ -26,10 +35,96 @@ impl VulkanoGralloc {
             khr_get_memory_requirements2: true,
             khr_external_memory: true,
             khr_external_memory_win32: true,
+            khr_external_semaphore: true,
+            khr_external_semaphore_win32: true,
+            khr_timeline_semaphore: true,
             ..DeviceExtensions::empty()
         }
     }
 
+    /// Get the handle type allocations on `device` are exported as.
+    pub(crate) fn memory_export_type(_device: &Device) -> ExternalMemoryHandleType {
+        ExternalMemoryHandleType::OpaqueWin32
+    }
+
+    /// Export `device_memory`, which must have been allocated as exportable to `handle_type`.
+    pub(crate) fn export_memory(
+        device_memory: &DeviceMemory,
+        handle_type: ExternalMemoryHandleType,
+    ) -> RutabagaResult<RutabagaHandle> {
+        if handle_type != ExternalMemoryHandleType::OpaqueWin32 {
+            return Err(RutabagaErrorKind::InvalidRutabagaHandle.into());
+        }
+
+        let device = device_memory.device();
+        let get_handle_info = vk::MemoryGetWin32HandleInfoKHR {
+            memory: device_memory.handle(),
+            handle_type: vk::ExternalMemoryHandleTypeFlags::OPAQUE_WIN32,
+            ..Default::default()
+        };
+
+        let mut handle: vk::HANDLE = ptr::null_mut();
+        // Safe because the memory was allocated on `device` as exportable to an opaque Win32
+        // handle, and `handle` is valid for writes.
+        unsafe {
+            (device
+                .fns()
+                .khr_external_memory_win32
+                .get_memory_win32_handle_khr)(
+                device.handle(), &get_handle_info, &mut handle
+            )
+        }
+        .result()
+        .map_err(VulkanError::from)?;
+
+        Ok(RutabagaHandle {
+            // Safe because the driver created a new NT handle that we now own.
+            os_handle: unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) },
+            handle_type: RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_WIN32,
+        })
+    }
+
+    /// Returns true if semaphores created on `device` can be exported.
+    pub(crate) fn supports_semaphore_export(device: &Device) -> bool {
+        device.enabled_extensions().khr_external_semaphore_win32
+    }
+
+    /// Get the handle type semaphores are exported as.
+    pub(crate) fn semaphore_export_type() -> vk::ExternalSemaphoreHandleTypeFlags {
+        vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_WIN32
+    }
+
+    /// Export `semaphore` as an opaque Win32 handle.  The handle holds a reference to the
+    /// semaphore payload, so the semaphore may be destroyed afterwards.
+    ///
+    /// # Safety
+    /// Safe if `semaphore` is a valid semaphore of `device`, created as exportable to an opaque
+    /// Win32 handle.
+    pub(crate) unsafe fn export_semaphore(
+        device: &Device,
+        semaphore: vk::Semaphore,
+    ) -> RutabagaResult<RutabagaHandle> {
+        let get_handle_info = vk::SemaphoreGetWin32HandleInfoKHR {
+            semaphore,
+            handle_type: vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_WIN32,
+            ..Default::default()
+        };
+
+        let mut handle: vk::HANDLE = ptr::null_mut();
+        (device
+            .fns()
+            .khr_external_semaphore_win32
+            .get_semaphore_win32_handle_khr)(device.handle(), &get_handle_info, &mut handle)
+        .result()
+        .map_err(VulkanError::from)?;
+
+        Ok(RutabagaHandle {
+            // Safe because the driver created a new NT handle that we now own.
+            os_handle: OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor),
+            handle_type: RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_WIN32,
+        })
+    }
+
     /// Import memory from a handle.
     ///
     /// # Safety
@@ -12,6 +12,8 @@ use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_os::RawDescriptor;
 use crate::rutabaga_utils::RutabagaResult;
 
+/// Shared memory object of a fixed size: a sealed memfd on Linux and a section object on
+/// Windows.
 pub struct SharedMemory(pub(crate) SysUtilSharedMemory);
 impl SharedMemory {
     /// Creates a new shared memory object of the given size.
@@ -9,6 +9,9 @@ use std::os::fd::IntoRawFd;
 use std::os::unix::io::OwnedFd;
 
 use libc::off_t;
+use nix::fcntl::fcntl;
+use nix::fcntl::FcntlArg;
+use nix::fcntl::SealFlag;
 use nix::sys::memfd::memfd_create;
 use nix::sys::memfd::MemFdCreateFlag;
 use nix::unistd::ftruncate;
@@ -27,12 +30,14 @@ pub struct SharedMemory {
 }
 
 impl SharedMemory {
-    /// Creates a new shared memory file descriptor with zero size.
+    /// Creates a new shared memory file descriptor of the given size.
     ///
     /// If a name is given, it will appear in `/proc/self/fd/<shm fd>` for the purposes of
     /// debugging. The name does not need to be unique.
     ///
-    /// The file descriptor is opened with the close on exec flag and allows memfd sealing.
+    /// The file descriptor is opened with the close on exec flag, and sealed against shrinking
+    /// and growing so that processes it is shared with can rely on its size, as with sections on
+    /// Windows.
     pub fn new(debug_name: &CStr, size: u64) -> RutabagaResult<SharedMemory> {
         let fd = memfd_create(
             debug_name,
@@ -41,6 +46,10 @@ impl SharedMemory {
 
         let size_off_t: off_t = size.try_into()?;
         ftruncate(&fd, size_off_t)?;
+        fcntl(
+            fd.as_raw_fd(),
+            FcntlArg::F_ADD_SEALS(SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_GROW),
+        )?;
 
         Ok(SharedMemory { fd, size })
     }
@@ -4,6 +4,8 @@
 
 use std::fs::File;
 use std::io::ErrorKind as IoErrorKind;
+use std::mem::size_of;
+use std::mem::MaybeUninit;
 use std::os::windows::io::AsRawHandle;
 use std::os::windows::io::FromRawHandle;
 use std::os::windows::io::IntoRawHandle;
@@ -11,6 +13,14 @@ use std::os::windows::io::OwnedHandle;
 use std::os::windows::io::RawHandle;
 use std::os::windows::raw::HANDLE;
 
+use winapi::um::fileapi::GetFileType;
+use winapi::um::memoryapi::MapViewOfFile;
+use winapi::um::memoryapi::UnmapViewOfFile;
+use winapi::um::memoryapi::VirtualQuery;
+use winapi::um::memoryapi::FILE_MAP_READ;
+use winapi::um::winbase::FILE_TYPE_PIPE;
+use winapi::um::winnt::MEMORY_BASIC_INFORMATION;
+
 use crate::rutabaga_os::descriptor::AsRawDescriptor;
 use crate::rutabaga_os::descriptor::FromRawDescriptor;
 use crate::rutabaga_os::descriptor::IntoRawDescriptor;
@@ -33,8 +43,49 @@ impl OwnedDescriptor {
         Ok(OwnedDescriptor { owned: clone })
     }
 
+    /// Pipes are assumed to be write ends, as only those are received from the other process.
+    /// Sections are memory, whose size is rounded up to the page size.
     pub fn determine_type(&self) -> Result<DescriptorType> {
-        Err(Error::from(IoErrorKind::Unsupported))
+        let handle = self.as_raw_descriptor() as winapi::um::winnt::HANDLE;
+        // SAFETY:
+        // Safe because the handle is owned by `self`.
+        if unsafe { GetFileType(handle) } == FILE_TYPE_PIPE {
+            return Ok(DescriptorType::WritePipe);
+        }
+
+        // SAFETY:
+        // Safe because mapping the whole section read-only has no side effect, and fails if the
+        // handle is not a section.
+        let addr = unsafe { MapViewOfFile(handle, FILE_MAP_READ, 0, 0, 0) };
+        if addr.is_null() {
+            return Err(Error::from(IoErrorKind::Unsupported));
+        }
+
+        let mut info = MaybeUninit::<MEMORY_BASIC_INFORMATION>::uninit();
+        // SAFETY:
+        // Safe because `addr` is the base of the view just mapped, and `info` is large enough.
+        let ret = unsafe {
+            VirtualQuery(
+                addr,
+                info.as_mut_ptr(),
+                size_of::<MEMORY_BASIC_INFORMATION>(),
+            )
+        };
+        // SAFETY:
+        // Safe because the view was mapped above and is not used after this.
+        unsafe { UnmapViewOfFile(addr) };
+        if ret == 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // SAFETY:
+        // Safe because VirtualQuery succeeded, so it filled in `info`.
+        let info = unsafe { info.assume_init() };
+        let size: u32 = info
+            .RegionSize
+            .try_into()
+            .map_err(|_| Error::from(IoErrorKind::Unsupported))?;
+        Ok(DescriptorType::Memory(size))
     }
 }
 
@@ -4,49 +4,110 @@
 
 use std::convert::From;
 use std::convert::TryFrom;
+use std::io::Error as IoError;
+use std::ptr::null_mut;
+
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::minwindef::TRUE;
+use winapi::um::synchapi::CreateEventW;
+use winapi::um::synchapi::ResetEvent;
+use winapi::um::synchapi::SetEvent;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::INFINITE;
+use winapi::um::winbase::WAIT_FAILED;
+use winapi::um::winnt::HANDLE;
 
 use crate::rutabaga_os::AsBorrowedDescriptor;
+use crate::rutabaga_os::AsRawDescriptor;
+use crate::rutabaga_os::FromRawDescriptor;
 use crate::rutabaga_os::OwnedDescriptor;
+use crate::rutabaga_os::RawDescriptor;
 use crate::rutabaga_utils::RutabagaError;
 use crate::rutabaga_utils::RutabagaErrorKind;
 use crate::rutabaga_utils::RutabagaHandle;
 use crate::rutabaga_utils::RutabagaResult;
+use crate::rutabaga_utils::RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_WIN32;
 
-pub struct Event(());
+/// A manual-reset event, which like an eventfd stays signaled until `wait()` consumes it.
+pub struct Event {
+    descriptor: OwnedDescriptor,
+}
 
 impl Event {
     pub fn new() -> RutabagaResult<Event> {
-        Err(RutabagaErrorKind::Unsupported.into())
+        // SAFETY:
+        // Safe because no security attributes or name are given, and the returned handle is
+        // checked before being owned.
+        let handle = unsafe { CreateEventW(null_mut(), TRUE, FALSE, null_mut()) };
+        if handle.is_null() {
+            return Err(IoError::last_os_error().into());
+        }
+
+        Ok(Event {
+            // SAFETY:
+            // Safe because the handle was just created and nothing else owns it.
+            descriptor: unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) },
+        })
+    }
+
+    fn handle(&self) -> HANDLE {
+        self.descriptor.as_raw_descriptor() as HANDLE
     }
 
     pub fn signal(&mut self) -> RutabagaResult<()> {
-        Err(RutabagaErrorKind::Unsupported.into())
+        // SAFETY:
+        // Safe because the event handle is owned by `self`.
+        if unsafe { SetEvent(self.handle()) } == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+        Ok(())
     }
 
     pub fn wait(&self) -> RutabagaResult<()> {
-        Err(RutabagaErrorKind::Unsupported.into())
+        // SAFETY:
+        // Safe because the event handle is owned by `self`.
+        if unsafe { WaitForSingleObject(self.handle(), INFINITE) } == WAIT_FAILED {
+            return Err(IoError::last_os_error().into());
+        }
+
+        // SAFETY:
+        // Safe because the event handle is owned by `self`.
+        if unsafe { ResetEvent(self.handle()) } == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+        Ok(())
     }
 
     pub fn try_clone(&self) -> RutabagaResult<Event> {
-        Err(RutabagaErrorKind::Unsupported.into())
+        let clone = self.descriptor.try_clone()?;
+        Ok(Event { descriptor: clone })
     }
 }
 
 impl TryFrom<RutabagaHandle> for Event {
     type Error = RutabagaError;
-    fn try_from(_handle: RutabagaHandle) -> Result<Self, Self::Error> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    fn try_from(handle: RutabagaHandle) -> Result<Self, Self::Error> {
+        if handle.handle_type != RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_WIN32 {
+            return Err(RutabagaErrorKind::InvalidRutabagaHandle.into());
+        }
+
+        Ok(Event {
+            descriptor: handle.os_handle,
+        })
     }
 }
 
 impl From<Event> for RutabagaHandle {
-    fn from(_evt: Event) -> Self {
-        unimplemented!()
+    fn from(evt: Event) -> Self {
+        RutabagaHandle {
+            os_handle: evt.descriptor,
+            handle_type: RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_WIN32,
+        }
     }
 }
 
 impl AsBorrowedDescriptor for Event {
     fn as_borrowed_descriptor(&self) -> &OwnedDescriptor {
-        unimplemented!()
+        &self.descriptor
     }
 }
@@ -2,28 +2,67 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::io::Error as IoError;
 use std::ptr::NonNull;
 
 use libc::c_void;
+use winapi::um::memoryapi::MapViewOfFile;
+use winapi::um::memoryapi::UnmapViewOfFile;
+use winapi::um::memoryapi::FILE_MAP_READ;
+use winapi::um::memoryapi::FILE_MAP_WRITE;
 
+use crate::rutabaga_os::descriptor::AsRawDescriptor;
 use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_utils::RutabagaErrorKind;
 use crate::rutabaga_utils::RutabagaResult;
+use crate::rutabaga_utils::RUTABAGA_MAP_ACCESS_MASK;
+use crate::rutabaga_utils::RUTABAGA_MAP_ACCESS_READ;
+use crate::rutabaga_utils::RUTABAGA_MAP_ACCESS_RW;
+use crate::rutabaga_utils::RUTABAGA_MAP_ACCESS_WRITE;
 
-/// Wraps an anonymous shared memory mapping in the current process. Provides
-/// RAII semantics including munmap when no longer needed.
+/// Wraps a view of a section object in the current process. Provides
+/// RAII semantics including UnmapViewOfFile when no longer needed.
 #[derive(Debug)]
 pub struct MemoryMapping {
     pub addr: NonNull<c_void>,
     pub size: usize,
 }
 
+impl Drop for MemoryMapping {
+    fn drop(&mut self) {
+        // SAFETY:
+        // This is safe because we mapped the view at addr ourselves, and nobody
+        // else is holding a reference to it.
+        unsafe {
+            UnmapViewOfFile(self.addr.as_ptr() as _);
+        }
+    }
+}
+
 impl MemoryMapping {
     pub fn from_safe_descriptor(
-        _descriptor: OwnedDescriptor,
-        _size: usize,
-        _map_info: u32,
+        descriptor: OwnedDescriptor,
+        size: usize,
+        map_info: u32,
     ) -> RutabagaResult<MemoryMapping> {
-        Err(RutabagaErrorKind::Unsupported.into())
+        // Write access implies read access for views of sections.
+        let access = match map_info & RUTABAGA_MAP_ACCESS_MASK {
+            RUTABAGA_MAP_ACCESS_READ => FILE_MAP_READ,
+            RUTABAGA_MAP_ACCESS_WRITE | RUTABAGA_MAP_ACCESS_RW => FILE_MAP_WRITE,
+            _ => return Err(RutabagaErrorKind::SpecViolation("incorrect access flags").into()),
+        };
+        if size == 0 {
+            return Err(RutabagaErrorKind::SpecViolation("zero size mapping").into());
+        }
+
+        // SAFETY:
+        // Safe because a new view is created, which does not alias any memory of the process. The
+        // view keeps the section alive after `descriptor` is closed.
+        let addr =
+            unsafe { MapViewOfFile(descriptor.as_raw_descriptor() as _, access, 0, 0, size) };
+        match NonNull::new(addr as *mut c_void) {
+            Some(addr) => Ok(MemoryMapping { addr, size }),
+            None => Err(IoError::last_os_error().into()),
+        }
     }
 }
@@ -5,6 +5,7 @@
 pub mod descriptor;
 pub mod event;
 pub mod memory_mapping;
+mod overlapped;
 pub mod pipe;
 pub mod shm;
 pub mod tube;
@@ -0,0 +1,433 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Pipes opened for overlapped I/O, which can be waited on unlike plain pipe handles.
+
+use std::io::Error as IoError;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::minwindef::TRUE;
+use winapi::shared::winerror::ERROR_BROKEN_PIPE;
+use winapi::shared::winerror::ERROR_IO_PENDING;
+use winapi::shared::winerror::ERROR_MORE_DATA;
+use winapi::um::fileapi::ReadFile;
+use winapi::um::fileapi::WriteFile;
+use winapi::um::ioapiset::CancelIoEx;
+use winapi::um::ioapiset::GetOverlappedResult;
+use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::synchapi::CreateEventW;
+use winapi::um::synchapi::ResetEvent;
+use winapi::um::synchapi::SetEvent;
+use winapi::um::winnt::HANDLE;
+
+use crate::rutabaga_os::AsRawDescriptor;
+use crate::rutabaga_os::FromRawDescriptor;
+use crate::rutabaga_os::OwnedDescriptor;
+use crate::rutabaga_os::RawDescriptor;
+use crate::rutabaga_utils::RutabagaResult;
+
+fn create_manual_reset_event() -> RutabagaResult<OwnedDescriptor> {
+    // SAFETY:
+    // Safe because no security attributes or name are given, and the returned handle is checked
+    // before being owned.
+    let handle = unsafe { CreateEventW(null_mut(), TRUE, FALSE, null_mut()) };
+    if handle.is_null() {
+        return Err(IoError::last_os_error().into());
+    }
+
+    // SAFETY:
+    // Safe because the handle was just created and nothing else owns it.
+    Ok(unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) })
+}
+
+enum Completion {
+    Transferred(usize),
+    // Only part of a message fit in the buffer.
+    MoreData,
+    Closed,
+}
+
+struct PendingRead {
+    // Boxed since the kernel writes to it until the pending read completes.
+    overlapped: Box<OVERLAPPED>,
+    pending: bool,
+    closed: bool,
+}
+
+// SAFETY:
+// Safe because the event handle in `overlapped` is valid in every thread of the process.
+unsafe impl Send for PendingRead {}
+
+/// A pipe opened with `FILE_FLAG_OVERLAPPED`.  A zero-byte read is kept pending on it, whose event
+/// is signaled once data can be read or the other end is closed.
+///
+/// Windows can't tell a closed pipe from a readable one until it is read, so `read()` returning
+/// zero bytes is how a closed pipe is reported.
+pub struct OverlappedPipe {
+    pipe: OwnedDescriptor,
+    event: OwnedDescriptor,
+    read_state: Mutex<PendingRead>,
+}
+
+impl OverlappedPipe {
+    pub fn new(pipe: OwnedDescriptor) -> RutabagaResult<OverlappedPipe> {
+        let event = create_manual_reset_event()?;
+        // SAFETY:
+        // Safe because OVERLAPPED is plain data for which all zeroes is a valid value.
+        let mut overlapped: Box<OVERLAPPED> = Box::new(unsafe { std::mem::zeroed() });
+        overlapped.hEvent = event.as_raw_descriptor() as HANDLE;
+
+        let overlapped_pipe = OverlappedPipe {
+            pipe,
+            event,
+            read_state: Mutex::new(PendingRead {
+                overlapped,
+                pending: false,
+                closed: false,
+            }),
+        };
+        overlapped_pipe.arm(&mut overlapped_pipe.read_state.lock().unwrap())?;
+        Ok(overlapped_pipe)
+    }
+
+    /// The event signaled when the pipe is readable or closed.
+    pub fn event(&self) -> &OwnedDescriptor {
+        &self.event
+    }
+
+    // Issues the zero-byte read that signals the event, unless one is pending already.
+    fn arm(&self, state: &mut PendingRead) -> RutabagaResult<()> {
+        if state.pending || state.closed {
+            return Ok(());
+        }
+
+        // SAFETY:
+        // Safe because the pipe and the boxed OVERLAPPED outlive the read, which is completed or
+        // cancelled before they are dropped.
+        let ret = unsafe {
+            ReadFile(
+                self.pipe.as_raw_descriptor() as HANDLE,
+                null_mut(),
+                0,
+                null_mut(),
+                &mut *state.overlapped,
+            )
+        };
+        if ret == FALSE {
+            let e = IoError::last_os_error();
+            match e.raw_os_error().map(|code| code as DWORD) {
+                Some(ERROR_IO_PENDING) | Some(ERROR_MORE_DATA) => (),
+                Some(ERROR_BROKEN_PIPE) => {
+                    state.closed = true;
+                    self.set_event()?;
+                    return Ok(());
+                }
+                _ => return Err(e.into()),
+            }
+        }
+
+        state.pending = true;
+        Ok(())
+    }
+
+    fn set_event(&self) -> RutabagaResult<()> {
+        // SAFETY:
+        // Safe because the event handle is owned by `self`.
+        if unsafe { SetEvent(self.event.as_raw_descriptor() as HANDLE) } == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    // Waits for the overlapped operation using `overlapped` to complete.
+    fn complete(&self, overlapped: &mut OVERLAPPED) -> RutabagaResult<Completion> {
+        let mut transferred: DWORD = 0;
+        // SAFETY:
+        // Safe because `overlapped` belongs to an operation issued on this pipe.
+        let ret = unsafe {
+            GetOverlappedResult(
+                self.pipe.as_raw_descriptor() as HANDLE,
+                overlapped,
+                &mut transferred,
+                TRUE,
+            )
+        };
+        if ret == FALSE {
+            let e = IoError::last_os_error();
+            return match e.raw_os_error().map(|code| code as DWORD) {
+                Some(ERROR_MORE_DATA) => Ok(Completion::MoreData),
+                Some(ERROR_BROKEN_PIPE) => Ok(Completion::Closed),
+                _ => Err(e.into()),
+            };
+        }
+        Ok(Completion::Transferred(transferred as usize))
+    }
+
+    /// Reads from the pipe, returning zero if the other end was closed.  For message pipes, `data`
+    /// must be large enough to hold the whole message.
+    pub fn read(&self, data: &mut [u8]) -> RutabagaResult<usize> {
+        let mut state = self.read_state.lock().unwrap();
+        if state.closed {
+            return Ok(0);
+        }
+
+        if state.pending {
+            state.pending = false;
+            // Zero-byte reads of message pipes complete with ERROR_MORE_DATA.
+            if let Completion::Closed = self.complete(&mut state.overlapped)? {
+                state.closed = true;
+                return Ok(0);
+            }
+        }
+
+        // SAFETY:
+        // Safe because the event handle is owned by `self`.
+        if unsafe { ResetEvent(self.event.as_raw_descriptor() as HANDLE) } == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+
+        let len: DWORD = data.len().try_into()?;
+        // SAFETY:
+        // Safe because `data` and the boxed OVERLAPPED outlive the read, which is waited for below.
+        let ret = unsafe {
+            ReadFile(
+                self.pipe.as_raw_descriptor() as HANDLE,
+                data.as_mut_ptr() as *mut _,
+                len,
+                null_mut(),
+                &mut *state.overlapped,
+            )
+        };
+        if ret == FALSE {
+            let e = IoError::last_os_error();
+            match e.raw_os_error().map(|code| code as DWORD) {
+                Some(ERROR_IO_PENDING) | Some(ERROR_MORE_DATA) => (),
+                Some(ERROR_BROKEN_PIPE) => {
+                    state.closed = true;
+                    self.set_event()?;
+                    return Ok(0);
+                }
+                _ => return Err(e.into()),
+            }
+        }
+
+        let bytes_read = match self.complete(&mut state.overlapped)? {
+            Completion::Transferred(bytes_read) => bytes_read,
+            Completion::MoreData => {
+                return Err(IoError::from_raw_os_error(ERROR_MORE_DATA as i32).into())
+            }
+            Completion::Closed => {
+                state.closed = true;
+                self.set_event()?;
+                return Ok(0);
+            }
+        };
+
+        self.arm(&mut state)?;
+        Ok(bytes_read)
+    }
+
+    /// Writes `data` to the pipe, waiting for the write to complete.
+    pub fn write(&self, data: &[u8]) -> RutabagaResult<usize> {
+        let event = create_manual_reset_event()?;
+        // SAFETY:
+        // Safe because OVERLAPPED is plain data for which all zeroes is a valid value.
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        overlapped.hEvent = event.as_raw_descriptor() as HANDLE;
+
+        let len: DWORD = data.len().try_into()?;
+        // SAFETY:
+        // Safe because `data` and `overlapped` outlive the write, which is waited for below.
+        let ret = unsafe {
+            WriteFile(
+                self.pipe.as_raw_descriptor() as HANDLE,
+                data.as_ptr() as *const _,
+                len,
+                null_mut(),
+                &mut overlapped,
+            )
+        };
+        if ret == FALSE {
+            let e = IoError::last_os_error();
+            if e.raw_os_error().map(|code| code as DWORD) != Some(ERROR_IO_PENDING) {
+                return Err(e.into());
+            }
+        }
+
+        match self.complete(&mut overlapped)? {
+            Completion::Transferred(bytes_written) => Ok(bytes_written),
+            _ => Err(IoError::from_raw_os_error(ERROR_BROKEN_PIPE as i32).into()),
+        }
+    }
+}
+
+impl AsRawDescriptor for OverlappedPipe {
+    fn as_raw_descriptor(&self) -> RawDescriptor {
+        self.pipe.as_raw_descriptor()
+    }
+}
+
+impl Drop for OverlappedPipe {
+    fn drop(&mut self) {
+        let mut state = self.read_state.lock().unwrap();
+        if state.pending {
+            // SAFETY:
+            // Safe because the pending read was issued on this pipe with this OVERLAPPED, which
+            // must not be freed before the cancellation completes.
+            unsafe {
+                CancelIoEx(
+                    self.pipe.as_raw_descriptor() as HANDLE,
+                    &mut *state.overlapped,
+                );
+            }
+            let _ = self.complete(&mut state.overlapped);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::iter::once;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+
+    use winapi::um::fileapi::CreateFileW;
+    use winapi::um::fileapi::OPEN_EXISTING;
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::namedpipeapi::CreateNamedPipeW;
+    use winapi::um::namedpipeapi::SetNamedPipeHandleState;
+    use winapi::um::processthreadsapi::GetCurrentProcessId;
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::winbase::FILE_FLAG_FIRST_PIPE_INSTANCE;
+    use winapi::um::winbase::FILE_FLAG_OVERLAPPED;
+    use winapi::um::winbase::PIPE_ACCESS_DUPLEX;
+    use winapi::um::winbase::PIPE_READMODE_MESSAGE;
+    use winapi::um::winbase::PIPE_REJECT_REMOTE_CLIENTS;
+    use winapi::um::winbase::PIPE_TYPE_MESSAGE;
+    use winapi::um::winbase::PIPE_WAIT;
+    use winapi::um::winbase::WAIT_OBJECT_0;
+    use winapi::um::winnt::GENERIC_READ;
+    use winapi::um::winnt::GENERIC_WRITE;
+
+    use super::*;
+
+    /// Creates the server end of a message-mode named pipe opened for overlapped I/O, and returns
+    /// it with the path of the pipe.
+    pub(crate) fn create_message_pipe() -> (OwnedDescriptor, String) {
+        static PIPE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+        // SAFETY:
+        // Safe because GetCurrentProcessId cannot fail.
+        let pid = unsafe { GetCurrentProcessId() };
+        let path = format!(
+            r"\\.\pipe\rutabaga-test-{}-{}",
+            pid,
+            PIPE_COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let wide_path: Vec<u16> = path.encode_utf16().chain(once(0)).collect();
+
+        // SAFETY:
+        // Safe because `wide_path` is null-terminated, and the returned handle is checked before
+        // being owned.
+        let server = unsafe {
+            CreateNamedPipeW(
+                wide_path.as_ptr(),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                4096,
+                4096,
+                0,
+                null_mut(),
+            )
+        };
+        assert_ne!(server, INVALID_HANDLE_VALUE);
+
+        // SAFETY:
+        // Safe because the handle was just created and nothing else owns it.
+        let server = unsafe { OwnedDescriptor::from_raw_descriptor(server as RawDescriptor) };
+        (server, path)
+    }
+
+    // Opens the client end of the message pipe at `path` for overlapped I/O.
+    fn open_message_pipe(path: &str) -> OwnedDescriptor {
+        let wide_path: Vec<u16> = path.encode_utf16().chain(once(0)).collect();
+        // SAFETY:
+        // Safe because `wide_path` is null-terminated, and the returned handle is checked before
+        // being owned.
+        let client = unsafe {
+            CreateFileW(
+                wide_path.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                0,
+                null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_OVERLAPPED,
+                null_mut(),
+            )
+        };
+        assert_ne!(client, INVALID_HANDLE_VALUE);
+
+        let mut mode: DWORD = PIPE_READMODE_MESSAGE;
+        // SAFETY:
+        // Safe because the pipe handle is valid and only the read mode is changed.
+        let ret = unsafe { SetNamedPipeHandleState(client, &mut mode, null_mut(), null_mut()) };
+        assert_ne!(ret, FALSE);
+
+        // SAFETY:
+        // Safe because the handle was just created and nothing else owns it.
+        unsafe { OwnedDescriptor::from_raw_descriptor(client as RawDescriptor) }
+    }
+
+    /// Waits up to `timeout_ms` for `event` to be signaled.
+    pub(crate) fn wait_signaled(event: &OwnedDescriptor, timeout_ms: DWORD) -> bool {
+        // SAFETY:
+        // Safe because the event handle is valid for the duration of the call.
+        let ret = unsafe { WaitForSingleObject(event.as_raw_descriptor() as HANDLE, timeout_ms) };
+        ret == WAIT_OBJECT_0
+    }
+
+    #[test]
+    fn message_pipe_read_write() {
+        let (server, path) = create_message_pipe();
+        // The server end can only be read once a client is connected.
+        let client = OverlappedPipe::new(open_message_pipe(&path)).unwrap();
+        let server = OverlappedPipe::new(server).unwrap();
+        assert!(!wait_signaled(server.event(), 0));
+
+        assert_eq!(client.write(b"hello").unwrap(), 5);
+        assert!(wait_signaled(server.event(), 5000));
+        let mut buf = [0u8; 16];
+        assert_eq!(server.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert!(!wait_signaled(server.event(), 0));
+
+        // Messages are not merged.
+        assert_eq!(server.write(b"a").unwrap(), 1);
+        assert_eq!(server.write(b"bc").unwrap(), 2);
+        assert!(wait_signaled(client.event(), 5000));
+        assert_eq!(client.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"a");
+        assert_eq!(client.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"bc");
+    }
+
+    #[test]
+    fn closed_pipe_reads_zero() {
+        let (server, path) = create_message_pipe();
+        let client = OverlappedPipe::new(open_message_pipe(&path)).unwrap();
+        let server = OverlappedPipe::new(server).unwrap();
+
+        drop(client);
+        assert!(wait_signaled(server.event(), 5000));
+        let mut buf = [0u8; 16];
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+        // The event stays signaled so that the closed pipe is noticed again.
+        assert!(wait_signaled(server.event(), 0));
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+    }
+}
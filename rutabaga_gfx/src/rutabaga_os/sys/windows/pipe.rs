@@ -2,53 +2,189 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::io::Error as IoError;
+use std::iter::once;
+use std::ptr::null_mut;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::minwindef::FALSE;
+use winapi::um::fileapi::CreateFileW;
+use winapi::um::fileapi::WriteFile;
+use winapi::um::fileapi::OPEN_EXISTING;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::namedpipeapi::CreateNamedPipeW;
+use winapi::um::processthreadsapi::GetCurrentProcessId;
+use winapi::um::winbase::FILE_FLAG_FIRST_PIPE_INSTANCE;
+use winapi::um::winbase::FILE_FLAG_OVERLAPPED;
+use winapi::um::winbase::PIPE_ACCESS_INBOUND;
+use winapi::um::winbase::PIPE_READMODE_BYTE;
+use winapi::um::winbase::PIPE_REJECT_REMOTE_CLIENTS;
+use winapi::um::winbase::PIPE_TYPE_BYTE;
+use winapi::um::winbase::PIPE_WAIT;
+use winapi::um::winnt::GENERIC_WRITE;
+use winapi::um::winnt::HANDLE;
+
+use crate::rutabaga_os::sys::windows::overlapped::OverlappedPipe;
 use crate::rutabaga_os::AsBorrowedDescriptor;
 use crate::rutabaga_os::AsRawDescriptor;
+use crate::rutabaga_os::FromRawDescriptor;
 use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_os::RawDescriptor;
-use crate::rutabaga_utils::RutabagaErrorKind;
 use crate::rutabaga_utils::RutabagaResult;
 
-pub struct ReadPipeStub(());
-pub struct WritePipeStub(());
+const PIPE_BUFFER_SIZE: DWORD = 4096;
 
-pub type ReadPipe = ReadPipeStub;
-pub type WritePipe = WritePipeStub;
+/// Read end of a pipe, which is waitable through the event of its pending read.
+pub struct ReadPipe {
+    pipe: OverlappedPipe,
+}
+
+pub struct WritePipe {
+    descriptor: OwnedDescriptor,
+}
 
+/// Creates a pipe.  Anonymous pipes don't support overlapped I/O, so this is a named pipe with a
+/// single instance whose client end is the write end.
 pub fn create_pipe() -> RutabagaResult<(ReadPipe, WritePipe)> {
-    Err(RutabagaErrorKind::Unsupported.into())
+    static PIPE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+    // SAFETY:
+    // Safe because GetCurrentProcessId cannot fail.
+    let pid = unsafe { GetCurrentProcessId() };
+    let name = format!(
+        r"\\.\pipe\rutabaga-{}-{}",
+        pid,
+        PIPE_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+    let wide_name: Vec<u16> = name.encode_utf16().chain(once(0)).collect();
+
+    // SAFETY:
+    // Safe because `wide_name` is null-terminated, and the returned handle is checked before being
+    // owned.
+    let server = unsafe {
+        CreateNamedPipeW(
+            wide_name.as_ptr(),
+            PIPE_ACCESS_INBOUND | FILE_FLAG_OVERLAPPED | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            0,
+            PIPE_BUFFER_SIZE,
+            0,
+            null_mut(),
+        )
+    };
+    if server == INVALID_HANDLE_VALUE {
+        return Err(IoError::last_os_error().into());
+    }
+
+    // SAFETY:
+    // Safe because the handle was just created and nothing else owns it.
+    let read_end = unsafe { OwnedDescriptor::from_raw_descriptor(server as RawDescriptor) };
+
+    // The write end is handed to other processes, which expect synchronous I/O.
+    // SAFETY:
+    // Safe because `wide_name` is null-terminated, and the returned handle is checked before being
+    // owned.
+    let client = unsafe {
+        CreateFileW(
+            wide_name.as_ptr(),
+            GENERIC_WRITE,
+            0,
+            null_mut(),
+            OPEN_EXISTING,
+            0,
+            null_mut(),
+        )
+    };
+    if client == INVALID_HANDLE_VALUE {
+        return Err(IoError::last_os_error().into());
+    }
+
+    Ok((
+        // The server end is connected once the client end is opened, so there is no need to call
+        // ConnectNamedPipe().
+        ReadPipe {
+            pipe: OverlappedPipe::new(read_end)?,
+        },
+        WritePipe::new(client as RawDescriptor),
+    ))
 }
 
 impl ReadPipe {
-    pub fn read(&self, _data: &mut [u8]) -> RutabagaResult<usize> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    /// Reads the available data, returning zero once the write end is closed.
+    pub fn read(&self, data: &mut [u8]) -> RutabagaResult<usize> {
+        self.pipe.read(data)
     }
 }
 
 impl AsBorrowedDescriptor for ReadPipe {
     fn as_borrowed_descriptor(&self) -> &OwnedDescriptor {
-        unimplemented!()
+        self.pipe.event()
     }
 }
 
 impl WritePipe {
-    pub fn new(_descriptor: RawDescriptor) -> WritePipe {
-        unimplemented!()
+    pub fn new(descriptor: RawDescriptor) -> WritePipe {
+        // SAFETY: Safe because we know the underlying OS descriptor is valid and
+        // owned by us.
+        let owned = unsafe { OwnedDescriptor::from_raw_descriptor(descriptor) };
+        WritePipe { descriptor: owned }
     }
 
-    pub fn write(&self, _data: &[u8]) -> RutabagaResult<usize> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    pub fn write(&self, data: &[u8]) -> RutabagaResult<usize> {
+        let len: DWORD = data.len().try_into()?;
+        let mut bytes_written: DWORD = 0;
+        // SAFETY:
+        // Safe because the pipe was opened for synchronous I/O and `data` outlives the write.
+        let ret = unsafe {
+            WriteFile(
+                self.descriptor.as_raw_descriptor() as HANDLE,
+                data.as_ptr() as *const _,
+                len,
+                &mut bytes_written,
+                null_mut(),
+            )
+        };
+        if ret == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+        Ok(bytes_written as usize)
     }
 }
 
 impl AsBorrowedDescriptor for WritePipe {
     fn as_borrowed_descriptor(&self) -> &OwnedDescriptor {
-        unimplemented!()
+        &self.descriptor
     }
 }
 
 impl AsRawDescriptor for WritePipe {
     fn as_raw_descriptor(&self) -> RawDescriptor {
-        unimplemented!()
+        self.descriptor.as_raw_descriptor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rutabaga_os::sys::windows::overlapped::tests::wait_signaled;
+
+    #[test]
+    fn pipe_read_write() {
+        let (read_pipe, write_pipe) = create_pipe().unwrap();
+        assert!(!wait_signaled(read_pipe.as_borrowed_descriptor(), 0));
+
+        assert_eq!(write_pipe.write(b"data").unwrap(), 4);
+        assert!(wait_signaled(read_pipe.as_borrowed_descriptor(), 5000));
+        let mut buf = [0u8; 16];
+        assert_eq!(read_pipe.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"data");
+        assert!(!wait_signaled(read_pipe.as_borrowed_descriptor(), 0));
+
+        drop(write_pipe);
+        assert!(wait_signaled(read_pipe.as_borrowed_descriptor(), 5000));
+        assert_eq!(read_pipe.read(&mut buf).unwrap(), 0);
     }
 }
@@ -3,12 +3,22 @@
 // found in the LICENSE file.
 
 use std::ffi::CStr;
+use std::io::Error as IoError;
+use std::mem::MaybeUninit;
+use std::ptr::null;
+use std::ptr::null_mut;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::memoryapi::CreateFileMappingW;
+use winapi::um::sysinfoapi::GetSystemInfo;
+use winapi::um::winnt::PAGE_READWRITE;
 
 use crate::rutabaga_os::descriptor::AsRawDescriptor;
+use crate::rutabaga_os::descriptor::FromRawDescriptor;
 use crate::rutabaga_os::descriptor::IntoRawDescriptor;
 use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_os::RawDescriptor;
-use crate::rutabaga_utils::RutabagaErrorKind;
 use crate::rutabaga_utils::RutabagaResult;
 
 /// A shared memory file descriptor and its size.
@@ -18,9 +28,32 @@ pub struct SharedMemory {
 }
 
 impl SharedMemory {
-    /// Creates a new shared memory file mapping with zero size.
-    pub fn new(_debug_name: &CStr, _size: u64) -> RutabagaResult<Self> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    /// Creates a new section object backed by the paging file, of a fixed size.
+    ///
+    /// The section is unnamed since named sections live in a namespace shared with other
+    /// processes, so `debug_name` is unused.
+    pub fn new(_debug_name: &CStr, size: u64) -> RutabagaResult<Self> {
+        // SAFETY:
+        // Safe because no security attributes or name are given, and the returned handle is
+        // checked before being owned.
+        let handle = unsafe {
+            CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                null_mut(),
+                PAGE_READWRITE,
+                (size >> 32) as DWORD,
+                size as DWORD,
+                null(),
+            )
+        };
+        if handle.is_null() {
+            return Err(IoError::last_os_error().into());
+        }
+
+        // SAFETY:
+        // Safe because the handle was just created and nothing else owns it.
+        let descriptor = unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) };
+        Ok(SharedMemory { descriptor, size })
     }
 
     /// Gets the size in bytes of the shared memory.
@@ -47,6 +80,15 @@ impl IntoRawDescriptor for SharedMemory {
     }
 }
 
-pub fn round_up_to_page_size(_v: u64) -> RutabagaResult<u64> {
-    Err(RutabagaErrorKind::Unsupported.into())
+/// Uses the system's page size in bytes to round the given value up to the nearest page boundary.
+pub fn round_up_to_page_size(v: u64) -> RutabagaResult<u64> {
+    let mut system_info = MaybeUninit::uninit();
+    // SAFETY:
+    // Safe because GetSystemInfo only writes to the given structure and cannot fail.
+    let system_info = unsafe {
+        GetSystemInfo(system_info.as_mut_ptr());
+        system_info.assume_init()
+    };
+    let page_mask = (system_info.dwPageSize - 1) as u64;
+    Ok((v + page_mask) & !page_mask)
 }
@@ -2,43 +2,242 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::io::Error as IoError;
+use std::iter::once;
+use std::mem::size_of;
+use std::os::windows::ffi::OsStrExt;
 use std::path::Path;
+use std::ptr::null_mut;
 
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::minwindef::ULONG;
+use winapi::um::fileapi::CreateFileW;
+use winapi::um::fileapi::OPEN_EXISTING;
+use winapi::um::handleapi::DuplicateHandle;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::namedpipeapi::SetNamedPipeHandleState;
+use winapi::um::processthreadsapi::GetCurrentProcess;
+use winapi::um::processthreadsapi::OpenProcess;
+use winapi::um::winbase::GetNamedPipeServerProcessId;
+use winapi::um::winbase::FILE_FLAG_OVERLAPPED;
+use winapi::um::winbase::PIPE_READMODE_MESSAGE;
+use winapi::um::winnt::DUPLICATE_CLOSE_SOURCE;
+use winapi::um::winnt::DUPLICATE_SAME_ACCESS;
+use winapi::um::winnt::GENERIC_READ;
+use winapi::um::winnt::GENERIC_WRITE;
+use winapi::um::winnt::HANDLE;
+use winapi::um::winnt::PROCESS_DUP_HANDLE;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+
+use crate::rutabaga_os::sys::windows::overlapped::OverlappedPipe;
 use crate::rutabaga_os::AsBorrowedDescriptor;
+use crate::rutabaga_os::AsRawDescriptor;
+use crate::rutabaga_os::FromRawDescriptor;
 use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_os::RawDescriptor;
 use crate::rutabaga_os::TubeType;
 use crate::rutabaga_utils::RutabagaErrorKind;
 use crate::rutabaga_utils::RutabagaResult;
 
+const MAX_IDENTIFIERS: usize = 28;
+
+/// Header of every message, followed by `num_handles` handle values valid in the receiving
+/// process and `data_size` bytes of opaque data.
+#[repr(C)]
+#[derive(Copy, Clone, Default, FromBytes, IntoBytes, Immutable)]
+struct TubeHeader {
+    num_handles: u32,
+    data_size: u32,
+}
+
+/// Client end of a message-mode named pipe.  Handles are passed by duplicating them into the
+/// process of the server end, which owns them once the message is received.
+pub struct Tube {
+    pipe: OverlappedPipe,
+    peer_process: OwnedDescriptor,
+}
+
 pub struct Stub(());
-pub type Tube = Stub;
 pub type Listener = Stub;
 
 impl Tube {
-    pub fn new<P: AsRef<Path>>(_path: P, _kind: TubeType) -> RutabagaResult<Tube> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    /// Connects to the named pipe at `path`, e.g. `\\.\pipe\compositor`.  Messages are framed
+    /// since they carry handles, so both kinds of tubes are message pipes.
+    pub fn new<P: AsRef<Path>>(path: P, _kind: TubeType) -> RutabagaResult<Tube> {
+        let wide_path: Vec<u16> = path
+            .as_ref()
+            .as_os_str()
+            .encode_wide()
+            .chain(once(0))
+            .collect();
+
+        // SAFETY:
+        // Safe because `wide_path` is null-terminated, and the returned handle is checked before
+        // being owned.
+        let handle = unsafe {
+            CreateFileW(
+                wide_path.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                0,
+                null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_OVERLAPPED,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(IoError::last_os_error().into());
+        }
+
+        // SAFETY:
+        // Safe because the handle was just created and nothing else owns it.
+        let pipe = unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) };
+
+        let mut mode: DWORD = PIPE_READMODE_MESSAGE;
+        // SAFETY:
+        // Safe because the pipe handle is valid and only the read mode is changed.
+        if unsafe { SetNamedPipeHandleState(handle, &mut mode, null_mut(), null_mut()) } == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+
+        let mut server_pid: ULONG = 0;
+        // SAFETY:
+        // Safe because the pipe handle is valid and `server_pid` outlives the call.
+        if unsafe { GetNamedPipeServerProcessId(handle, &mut server_pid) } == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+
+        // SAFETY:
+        // Safe because the returned handle is checked before being owned.
+        let process = unsafe { OpenProcess(PROCESS_DUP_HANDLE, FALSE, server_pid) };
+        if process.is_null() {
+            return Err(IoError::last_os_error().into());
+        }
+
+        Ok(Tube {
+            pipe: OverlappedPipe::new(pipe)?,
+            // SAFETY:
+            // Safe because the handle was just opened and nothing else owns it.
+            peer_process: unsafe { OwnedDescriptor::from_raw_descriptor(process as RawDescriptor) },
+        })
     }
 
-    pub fn send(
-        &self,
-        _opaque_data: &[u8],
-        _descriptors: &[RawDescriptor],
-    ) -> RutabagaResult<usize> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    // Duplicates `descriptor` into the peer process, returning its value there.
+    fn duplicate_to_peer(&self, descriptor: RawDescriptor) -> RutabagaResult<u64> {
+        let mut target: HANDLE = null_mut();
+        // SAFETY:
+        // Safe because both process handles are valid and `target` outlives the call.
+        let ret = unsafe {
+            DuplicateHandle(
+                GetCurrentProcess(),
+                descriptor as HANDLE,
+                self.peer_process.as_raw_descriptor() as HANDLE,
+                &mut target,
+                0,
+                FALSE,
+                DUPLICATE_SAME_ACCESS,
+            )
+        };
+        if ret == FALSE {
+            return Err(IoError::last_os_error().into());
+        }
+        Ok(target as u64)
     }
 
-    pub fn receive(
-        &self,
-        _opaque_data: &mut [u8],
-    ) -> RutabagaResult<(usize, Vec<OwnedDescriptor>)> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    // Closes handles duplicated into the peer process by a message that failed to be sent.
+    fn close_in_peer(&self, handles: &[u64]) {
+        for handle in handles {
+            // SAFETY:
+            // Safe because the handle was duplicated into the peer process, which never saw it.
+            unsafe {
+                DuplicateHandle(
+                    self.peer_process.as_raw_descriptor() as HANDLE,
+                    *handle as HANDLE,
+                    null_mut(),
+                    null_mut(),
+                    0,
+                    FALSE,
+                    DUPLICATE_CLOSE_SOURCE,
+                );
+            }
+        }
+    }
+
+    pub fn send(&self, opaque_data: &[u8], descriptors: &[RawDescriptor]) -> RutabagaResult<usize> {
+        if descriptors.len() > MAX_IDENTIFIERS {
+            return Err(RutabagaErrorKind::InvalidCrossDomainItemId.into());
+        }
+
+        let mut handles: Vec<u64> = Vec::with_capacity(descriptors.len());
+        for descriptor in descriptors {
+            match self.duplicate_to_peer(*descriptor) {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    self.close_in_peer(&handles);
+                    return Err(e);
+                }
+            }
+        }
+
+        let header = TubeHeader {
+            num_handles: handles.len().try_into()?,
+            data_size: opaque_data.len().try_into()?,
+        };
+        let mut message = header.as_bytes().to_vec();
+        message.extend_from_slice(handles.as_bytes());
+        message.extend_from_slice(opaque_data);
+
+        if let Err(e) = self.pipe.write(&message) {
+            self.close_in_peer(&handles);
+            return Err(e);
+        }
+
+        Ok(opaque_data.len())
+    }
+
+    pub fn receive(&self, opaque_data: &mut [u8]) -> RutabagaResult<(usize, Vec<OwnedDescriptor>)> {
+        let handles_offset = size_of::<TubeHeader>();
+        let mut message =
+            vec![0u8; handles_offset + MAX_IDENTIFIERS * size_of::<u64>() + opaque_data.len()];
+        let len = self.pipe.read(&mut message)?;
+        if len == 0 {
+            // The server end was closed.
+            return Ok((0, Vec::new()));
+        }
+
+        let (header, _) = TubeHeader::read_from_prefix(&message[..len])
+            .map_err(|_| RutabagaErrorKind::InvalidCommandBuffer)?;
+        let num_handles = header.num_handles as usize;
+        let data_size = header.data_size as usize;
+        let data_offset = handles_offset + num_handles * size_of::<u64>();
+        if num_handles > MAX_IDENTIFIERS
+            || data_size > opaque_data.len()
+            || data_offset + data_size != len
+        {
+            return Err(RutabagaErrorKind::InvalidCommandBuffer.into());
+        }
+
+        let descriptors = message[handles_offset..data_offset]
+            .chunks_exact(size_of::<u64>())
+            .map(|bytes| {
+                let handle = u64::from_ne_bytes(bytes.try_into().unwrap());
+                // SAFETY:
+                // Safe since the sender duplicated the handles into this process for us to own.
+                unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) }
+            })
+            .collect();
+        opaque_data[..data_size].copy_from_slice(&message[data_offset..len]);
+
+        Ok((data_size, descriptors))
     }
 }
 
 impl AsBorrowedDescriptor for Tube {
     fn as_borrowed_descriptor(&self) -> &OwnedDescriptor {
-        unimplemented!()
+        self.pipe.event()
     }
 }
 
@@ -52,3 +251,100 @@ impl Listener {
         Err(RutabagaErrorKind::Unsupported.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rutabaga_os::sys::windows::overlapped::tests::create_message_pipe;
+    use crate::rutabaga_os::sys::windows::overlapped::tests::wait_signaled;
+    use crate::rutabaga_os::Event;
+    use crate::rutabaga_os::IntoRawDescriptor;
+
+    fn message(handles: &[u64], data: &[u8]) -> Vec<u8> {
+        let header = TubeHeader {
+            num_handles: handles.len() as u32,
+            data_size: data.len() as u32,
+        };
+        let mut message = header.as_bytes().to_vec();
+        message.extend_from_slice(handles.as_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    #[test]
+    fn send_duplicates_handles_to_server() {
+        let (server, path) = create_message_pipe();
+        let tube = Tube::new(&path, TubeType::Packet).unwrap();
+        let server = OverlappedPipe::new(server).unwrap();
+        let mut event = Event::new().unwrap();
+
+        let raw_event = event.as_borrowed_descriptor().as_raw_descriptor();
+        assert_eq!(tube.send(b"ping", &[raw_event]).unwrap(), 4);
+
+        let mut buf = [0u8; 64];
+        let len = server.read(&mut buf).unwrap();
+        let (header, rest) = TubeHeader::read_from_prefix(&buf[..len]).unwrap();
+        assert_eq!(header.num_handles, 1);
+        assert_eq!(header.data_size, 4);
+        assert_eq!(&rest[size_of::<u64>()..], b"ping");
+
+        // The server is this process, so the duplicate is a new handle to the same event.
+        let handle = u64::from_ne_bytes(rest[..size_of::<u64>()].try_into().unwrap());
+        assert_ne!(handle, raw_event as u64);
+        // SAFETY:
+        // Safe because the handle was duplicated into this process for the server to own.
+        let duplicate = unsafe { OwnedDescriptor::from_raw_descriptor(handle as RawDescriptor) };
+        event.signal().unwrap();
+        assert!(wait_signaled(&duplicate, 0));
+    }
+
+    #[test]
+    fn receive_takes_ownership_of_handles() {
+        let (server, path) = create_message_pipe();
+        let tube = Tube::new(&path, TubeType::Packet).unwrap();
+        let server = OverlappedPipe::new(server).unwrap();
+
+        // Handles sent to crosvm must be valid in its process, which the test process is.
+        let event = Event::new().unwrap();
+        let handle = event
+            .as_borrowed_descriptor()
+            .try_clone()
+            .unwrap()
+            .into_raw_descriptor() as u64;
+        server.write(&message(&[handle], b"pong")).unwrap();
+
+        assert!(wait_signaled(tube.as_borrowed_descriptor(), 5000));
+        let mut data = [0u8; 16];
+        let (len, descriptors) = tube.receive(&mut data).unwrap();
+        assert_eq!(&data[..len], b"pong");
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].as_raw_descriptor() as u64, handle);
+    }
+
+    #[test]
+    fn receive_rejects_malformed_message() {
+        let (server, path) = create_message_pipe();
+        let tube = Tube::new(&path, TubeType::Packet).unwrap();
+        let server = OverlappedPipe::new(server).unwrap();
+
+        // The header announces more data than the message holds.
+        let mut bad = message(&[], b"abc");
+        bad.truncate(bad.len() - 1);
+        server.write(&bad).unwrap();
+
+        let mut data = [0u8; 16];
+        assert!(tube.receive(&mut data).is_err());
+    }
+
+    #[test]
+    fn closed_server_receives_nothing() {
+        let (server, path) = create_message_pipe();
+        let tube = Tube::new(&path, TubeType::Packet).unwrap();
+        drop(server);
+
+        let mut data = [0u8; 16];
+        let (len, descriptors) = tube.receive(&mut data).unwrap();
+        assert_eq!(len, 0);
+        assert!(descriptors.is_empty());
+    }
+}
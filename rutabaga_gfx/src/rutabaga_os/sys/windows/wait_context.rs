@@ -2,33 +2,91 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::io::Error as IoError;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::winerror::WAIT_TIMEOUT;
+use winapi::um::synchapi::WaitForMultipleObjects;
+use winapi::um::winbase::INFINITE;
+use winapi::um::winbase::WAIT_OBJECT_0;
+use winapi::um::winnt::HANDLE;
+use winapi::um::winnt::MAXIMUM_WAIT_OBJECTS;
+
+use crate::rutabaga_os::AsRawDescriptor;
 use crate::rutabaga_os::OwnedDescriptor;
 use crate::rutabaga_os::WaitEvent;
 use crate::rutabaga_os::WaitTimeout;
 use crate::rutabaga_utils::RutabagaErrorKind;
 use crate::rutabaga_utils::RutabagaResult;
 
-pub struct Stub(());
-pub type WaitContext = Stub;
+/// Waits on events, including the events of the pending reads of pipes and tubes.
+pub struct WaitContext {
+    connection_ids: Vec<u64>,
+    // Stored as integers to keep the context `Send`, the descriptors are owned by the caller.
+    handles: Vec<usize>,
+}
 
 impl WaitContext {
     pub fn new() -> RutabagaResult<WaitContext> {
-        Err(RutabagaErrorKind::Unsupported.into())
+        Ok(WaitContext {
+            connection_ids: Vec::new(),
+            handles: Vec::new(),
+        })
     }
 
-    pub fn add(
-        &mut self,
-        _connection_id: u64,
-        _descriptor: &OwnedDescriptor,
-    ) -> RutabagaResult<()> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    pub fn add(&mut self, connection_id: u64, descriptor: &OwnedDescriptor) -> RutabagaResult<()> {
+        if self.handles.len() >= MAXIMUM_WAIT_OBJECTS as usize {
+            return Err(RutabagaErrorKind::Unsupported.into());
+        }
+
+        self.connection_ids.push(connection_id);
+        self.handles.push(descriptor.as_raw_descriptor() as usize);
+        Ok(())
     }
 
-    pub fn wait(&mut self, _timeout: WaitTimeout) -> RutabagaResult<Vec<WaitEvent>> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    /// Returns the first signaled descriptor.  Windows can't tell a closed pipe from a readable
+    /// one until it is read, so it is reported as both readable and hung up, and reading zero
+    /// bytes from it means it is closed.
+    pub fn wait(&mut self, timeout: WaitTimeout) -> RutabagaResult<Vec<WaitEvent>> {
+        let timeout_ms: DWORD = match timeout {
+            WaitTimeout::Finite(duration) => {
+                duration.as_millis().try_into().unwrap_or(INFINITE - 1)
+            }
+            WaitTimeout::NoTimeout => INFINITE,
+        };
+
+        let handles: Vec<HANDLE> = self.handles.iter().map(|h| *h as HANDLE).collect();
+        // SAFETY:
+        // Safe because the caller keeps the added descriptors open while they are in the context.
+        let ret = unsafe {
+            WaitForMultipleObjects(handles.len() as DWORD, handles.as_ptr(), FALSE, timeout_ms)
+        };
+
+        if ret == WAIT_TIMEOUT {
+            return Ok(Vec::new());
+        }
+
+        let index = ret.wrapping_sub(WAIT_OBJECT_0) as usize;
+        match self.connection_ids.get(index) {
+            Some(connection_id) => Ok(vec![WaitEvent {
+                connection_id: *connection_id,
+                readable: true,
+                hung_up: true,
+            }]),
+            None => Err(IoError::last_os_error().into()),
+        }
     }
 
-    pub fn delete(&mut self, _descriptor: &OwnedDescriptor) -> RutabagaResult<()> {
-        Err(RutabagaErrorKind::Unsupported.into())
+    pub fn delete(&mut self, descriptor: &OwnedDescriptor) -> RutabagaResult<()> {
+        let handle = descriptor.as_raw_descriptor() as usize;
+        let index = self
+            .handles
+            .iter()
+            .position(|h| *h == handle)
+            .ok_or(RutabagaErrorKind::InvalidCrossDomainItemId)?;
+        self.connection_ids.remove(index);
+        self.handles.remove(index);
+        Ok(())
     }
 }
@@ -13,6 +13,8 @@ use std::os::raw::c_void;
 use std::path::PathBuf;
 use std::str::Utf8Error;
 use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
 
 #[cfg(any(target_os = "android", target_os = "linux"))]
 use nix::Error as NixError;
@@ -163,6 +165,10 @@ pub const RUTABAGA_FLAG_FENCE: u32 = 1 << 0;
 pub const RUTABAGA_FLAG_INFO_RING_IDX: u32 = 1 << 1;
 pub const RUTABAGA_FLAG_FENCE_HOST_SHAREABLE: u32 = 1 << 2;
 
+/// Set on the fences that rutabaga signals because their context was destroyed for misbehavior,
+/// rather than because their work completed.
+pub const RUTABAGA_FLAG_FENCE_ERROR: u32 = 1 << 31;
+
 /// Convenience struct for Rutabaga fences
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -766,6 +772,9 @@ pub const RUTABAGA_CHANNEL_TYPE_CAMERA: u32 = 0x0002;
 pub struct RutabagaChannel {
     pub base_channel: PathBuf,
     pub channel_type: u32,
+    /// Cross-domain contexts named `name` connect to this channel instead of the unnamed channel
+    /// of the same type, such as the compositor of their seat.
+    pub name: Option<String>,
 }
 
 /// Enumeration of possible rutabaga components.
@@ -795,6 +804,7 @@ pub const RUTABAGA_HANDLE_TYPE_MEM_DMABUF: u32 = 0x0002;
 pub const RUTABAGA_HANDLE_TYPE_MEM_OPAQUE_WIN32: u32 = 0x0003;
 pub const RUTABAGA_HANDLE_TYPE_MEM_SHM: u32 = 0x0004;
 pub const RUTABAGA_HANDLE_TYPE_MEM_ZIRCON: u32 = 0x0005;
+pub const RUTABAGA_HANDLE_TYPE_MEM_FILE: u32 = 0x0006;
 
 pub const RUTABAGA_HANDLE_TYPE_SIGNAL_OPAQUE_FD: u32 = 0x0010;
 pub const RUTABAGA_HANDLE_TYPE_SIGNAL_SYNC_FD: u32 = 0x0020;
@@ -831,6 +841,48 @@ impl RutabagaHandle {
     }
 }
 
+/// Host file the guest can create read-only blob resources from, by passing `blob_id` with
+/// `RUTABAGA_BLOB_MEM_HOST3D`.  Mapping such a blob maps the file itself, so large assets are
+/// neither copied into guest memory nor duplicated in the host page cache.
+#[derive(Clone)]
+pub struct RutabagaBlobFile {
+    pub blob_id: u64,
+    /// Handle of type `RUTABAGA_HANDLE_TYPE_MEM_FILE`, shared by every blob created from the file.
+    pub handle: Arc<RutabagaHandle>,
+    pub size: u64,
+}
+
+/// Completion of work a component continues in the background, such as a large transfer.
+#[derive(Clone, Default)]
+pub struct RutabagaCompletion {
+    done: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl RutabagaCompletion {
+    pub fn new() -> RutabagaCompletion {
+        Default::default()
+    }
+
+    /// Marks the work as completed and wakes up the waiters.
+    pub fn complete(&self) {
+        let (done, condvar) = &*self.done;
+        *done.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_complete(&self) -> bool {
+        *self.done.0.lock().unwrap()
+    }
+
+    /// Blocks until the work is completed.
+    pub fn wait(&self) {
+        let (done, condvar) = &*self.done;
+        let _done = condvar
+            .wait_while(done.lock().unwrap(), |done| !*done)
+            .unwrap();
+    }
+}
+
 #[derive(Clone)]
 pub struct RutabagaHandler<S> {
     closure: Arc<dyn Fn(S) + Send + Sync>,
@@ -8,6 +8,7 @@
 use std::fs::File;
 use std::io::BufReader;
 use std::io::BufWriter;
+use std::io::Read;
 use std::io::Write;
 use std::path::PathBuf;
 
@@ -60,6 +61,22 @@ impl RutabagaSnapshotWriter {
         })?;
         Ok(())
     }
+
+    /// Adds a fragment holding `data` as is, for buffers too large to be serialized.
+    pub fn add_raw_fragment(&self, name: &str, data: &[u8]) -> RutabagaResult<()> {
+        let fragment_path = self.dir.join(name);
+        let mut fragment_file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(fragment_path)
+            .map_err(|e| {
+                RutabagaErrorKind::SnapshotError(format!("failed to add fragment {}: {}", name, e))
+            })?;
+        fragment_file.write_all(data).map_err(|e| {
+            RutabagaErrorKind::SnapshotError(format!("failed to write fragment {}: {}", name, e))
+        })?;
+        Ok(())
+    }
 }
 
 pub struct RutabagaSnapshotReader {
@@ -101,4 +118,32 @@ impl RutabagaSnapshotReader {
                 .into()
         })
     }
+
+    /// Reads a fragment added by `RutabagaSnapshotWriter::add_raw_fragment` into `data`, which
+    /// must be the size of the fragment.
+    pub fn read_raw_fragment(&self, name: &str, data: &mut [u8]) -> RutabagaResult<()> {
+        let fragment_path = self.dir.join(name);
+        let mut fragment_file = File::open(fragment_path).map_err(|e| {
+            RutabagaErrorKind::SnapshotError(format!("failed to get fragment {}: {}", name, e))
+        })?;
+        let size = fragment_file
+            .metadata()
+            .map_err(|e| {
+                RutabagaErrorKind::SnapshotError(format!("failed to stat fragment {}: {}", name, e))
+            })?
+            .len();
+        if size != data.len() as u64 {
+            return Err(RutabagaErrorKind::SnapshotError(format!(
+                "fragment {} has {} bytes instead of {}",
+                name,
+                size,
+                data.len()
+            ))
+            .into());
+        }
+        fragment_file.read_exact(data).map_err(|e| {
+            RutabagaErrorKind::SnapshotError(format!("failed to read fragment {}: {}", name, e))
+                .into()
+        })
+    }
 }
@@ -603,9 +603,9 @@ impl RutabagaComponent for VirglRenderer {
         ctx_id: u32,
         resource: &mut RutabagaResource,
         transfer: Transfer3D,
-    ) -> RutabagaResult<()> {
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
         if transfer.is_empty() {
-            return Ok(());
+            return Ok(None);
         }
 
         let mut transfer_box = VirglBox {
@@ -632,7 +632,8 @@ impl RutabagaComponent for VirglRenderer {
                 0,
             )
         };
-        ret_to_res(ret)
+        ret_to_res(ret)?;
+        Ok(None)
     }
 
     fn transfer_read(
@@ -641,9 +642,9 @@ impl RutabagaComponent for VirglRenderer {
         resource: &mut RutabagaResource,
         transfer: Transfer3D,
         buf: Option<IoSliceMut>,
-    ) -> RutabagaResult<()> {
+    ) -> RutabagaResult<Option<RutabagaCompletion>> {
         if transfer.is_empty() {
-            return Ok(());
+            return Ok(None);
         }
 
         let mut transfer_box = VirglBox {
@@ -684,7 +685,8 @@ impl RutabagaComponent for VirglRenderer {
                 num_iovecs,
             )
         };
-        ret_to_res(ret)
+        ret_to_res(ret)?;
+        Ok(None)
     }
 
     #[allow(unused_variables)]
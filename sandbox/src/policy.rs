@@ -27,6 +27,10 @@ pub struct Policy {
     pub alternate_winstation: bool,
     pub exceptions: Vec<Rule>,
     pub dll_blocklist: Vec<String>,
+    /// Name of the AppContainer profile to run the process in, if any. The profile's package SID
+    /// identifies the process type, so objects can be shared with one type of device process
+    /// only. Processes in an AppContainer can only use the handles passed to them by the broker.
+    pub app_container_profile: Option<String>,
 }
 
 /// Rule struct describing a sandbox rule that should be added to the
@@ -53,6 +57,7 @@ pub const MAIN: Policy = Policy {
     alternate_winstation: false,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
 
 /// Policy for the metrics process.
@@ -68,6 +73,7 @@ pub const METRICS: Policy = Policy {
     alternate_winstation: true,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
 
 /// Policy for a block device process.
@@ -83,6 +89,7 @@ pub const BLOCK: Policy = Policy {
     alternate_winstation: true,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
 
 /// Policy for the network process.
@@ -99,6 +106,7 @@ pub const NET: Policy = Policy {
     alternate_winstation: true,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
 
 /// Policy for the slirp process.
@@ -116,6 +124,7 @@ pub const SLIRP: Policy = Policy {
     alternate_winstation: true,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
 
 /// Policy for the GPU process.
@@ -138,6 +147,7 @@ pub const GPU: Policy = Policy {
     alternate_winstation: false,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
 
 /// Policy for the sound process.
@@ -155,4 +165,5 @@ pub const SND: Policy = Policy {
     alternate_winstation: true,
     exceptions: vec![],
     dll_blocklist: vec![],
+    app_container_profile: None,
 };
@@ -0,0 +1,137 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Parallel zstd compression of snapshot fragments.
+//!
+//! The data is split in fixed size chunks that are compressed on several threads into separate
+//! zstd frames. Concatenated frames are a valid zstd stream, so any zstd decoder can read the
+//! result.
+
+use std::io::Write;
+use std::thread;
+
+use base::error;
+
+/// Amount of uncompressed data in each zstd frame.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Compresses everything written to it into `W`, using one thread per CPU.
+///
+/// Buffered data is compressed when the writer is flushed or finished. If the writer is dropped
+/// without being finished, it is compressed then but failures are only logged.
+pub struct ZstdChunkWriter<W: Write> {
+    inner: W,
+    level: i32,
+    threads: usize,
+    /// Chunks waiting to be compressed. All but the last one are full.
+    chunks: Vec<Vec<u8>>,
+    finished: bool,
+}
+
+impl<W: Write> ZstdChunkWriter<W> {
+    pub fn new(inner: W, level: i32) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        ZstdChunkWriter {
+            inner,
+            level,
+            threads,
+            chunks: Vec::with_capacity(threads),
+            finished: false,
+        }
+    }
+
+    /// Compresses and writes out the buffered data, and flushes `W`.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.finished = true;
+        self.flush()
+    }
+
+    /// Returns the writer of the compressed data.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Compresses the buffered chunks in parallel and writes the frames in order.
+    fn write_chunks(&mut self) -> std::io::Result<()> {
+        let level = self.level;
+        let frames = thread::scope(|s| {
+            let workers: Vec<_> = self
+                .chunks
+                .iter()
+                .filter(|chunk| !chunk.is_empty())
+                .map(|chunk| s.spawn(move || zstd::bulk::compress(chunk, level)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("zstd compression thread panicked"))
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        for frame in frames {
+            self.inner.write_all(&frame)?;
+        }
+        self.chunks.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ZstdChunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !matches!(self.chunks.last(), Some(chunk) if chunk.len() < CHUNK_SIZE) {
+            if self.chunks.len() == self.threads {
+                self.write_chunks()?;
+            }
+            self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+        }
+        let chunk = self.chunks.last_mut().unwrap();
+        let len = buf.len().min(CHUNK_SIZE - chunk.len());
+        chunk.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_chunks()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for ZstdChunkWriter<W> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(e) = self.flush() {
+            error!("failed to write compressed snapshot data: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| (i / 1000) as u8).collect();
+        let mut compressed = Vec::new();
+        {
+            let mut writer = ZstdChunkWriter::new(&mut compressed, 3);
+            // Force more chunks than threads to exercise the batching.
+            writer.threads = 2;
+            writer.write_all(&data[..100]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&data[100..]).unwrap();
+            writer.finish().unwrap();
+        }
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = Vec::new();
+        zstd::stream::read::Decoder::new(compressed.as_slice())
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -2,6 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::fs::File;
@@ -12,15 +13,139 @@ use std::path::PathBuf;
 
 use anyhow::Context;
 use anyhow::Result;
-use crypto::CryptKey;
+pub use crypto::CryptKey;
 
 mod any_snapshot;
+mod compress;
+mod stream;
 
 pub use any_snapshot::AnySnapshot;
+pub use compress::ZstdChunkWriter;
+pub use stream::is_snapshot_stream;
+use stream::SectionLocation;
+use stream::SectionReader;
+pub use stream::SectionWriter;
+pub use stream::StreamWriter;
 
 // Use 4kB encrypted chunks by default (if encryption is used).
 const DEFAULT_ENCRYPTED_CHUNK_SIZE_BYTES: usize = 1024 * 4;
 
+/// Name of the root fragment describing how the other fragments are stored. It is only present
+/// if they are compressed or encrypted with a user provided key.
+const FORMAT_FRAGMENT: &str = "format";
+
+/// How the fragments of a snapshot are stored.
+#[derive(Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct SnapshotFormat {
+    /// Fragments are encrypted with a key that is not stored in the snapshot.
+    user_key: bool,
+    /// Fragments are zstd compressed (before encryption).
+    zstd: bool,
+}
+
+/// Size of the keys used to encrypt snapshots with a user provided key.
+pub const KEY_SIZE: usize = 32;
+
+/// Reads a snapshot encryption key from the file at `path`, which holds either the raw key bytes
+/// or the key as hexadecimal digits.
+pub fn read_key_file(path: &Path) -> Result<CryptKey> {
+    let data: crypto::SecureByteVec = std::fs::read(path)
+        .with_context(|| format!("failed to read key file {}", path.display()))?
+        .into();
+    if data.as_slice().len() == KEY_SIZE {
+        return Ok(CryptKey::from_bytes(data.as_slice()));
+    }
+    let hex = data.as_slice().trim_ascii();
+    if hex.len() != KEY_SIZE * 2 {
+        return Err(anyhow::anyhow!(
+            "key file {} must hold {KEY_SIZE} bytes or {} hexadecimal digits",
+            path.display(),
+            KEY_SIZE * 2
+        ));
+    }
+    let mut key: crypto::SecureByteVec = vec![0u8; KEY_SIZE].into();
+    for (byte, digits) in key.as_mut_slice().iter_mut().zip(hex.chunks(2)) {
+        *byte = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .with_context(|| format!("key file {} is not valid hexadecimal", path.display()))?;
+    }
+    Ok(CryptKey::from_bytes(key.as_slice()))
+}
+
+/// Where the data of a fragment is written, after compression.
+enum FragmentSink {
+    File(File),
+    Encrypted(Box<crypto::CryptWriter<File>>),
+}
+
+impl Write for FragmentSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            FragmentSink::File(file) => file.write(buf),
+            FragmentSink::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FragmentSink::File(file) => file.flush(),
+            FragmentSink::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+impl FragmentSink {
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            FragmentSink::File(file) => file.flush(),
+            FragmentSink::Encrypted(writer) => writer.finish(),
+        }
+    }
+}
+
+enum FragmentWriterInner {
+    Plain(FragmentSink),
+    Zstd(ZstdChunkWriter<FragmentSink>),
+}
+
+/// Writer of a snapshot fragment.
+///
+/// `finish` must be called once all the data is written: compressed and encrypted fragments are
+/// only complete once the data buffered by the writer is written out.
+pub struct FragmentWriter {
+    inner: FragmentWriterInner,
+}
+
+impl Write for FragmentWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.inner {
+            FragmentWriterInner::Plain(sink) => sink.write(buf),
+            FragmentWriterInner::Zstd(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.inner {
+            FragmentWriterInner::Plain(sink) => sink.flush(),
+            FragmentWriterInner::Zstd(writer) => writer.flush(),
+        }
+    }
+}
+
+impl FragmentWriter {
+    /// Writes out the buffered data and completes the fragment.
+    pub fn finish(mut self) -> Result<()> {
+        match &mut self.inner {
+            FragmentWriterInner::Plain(sink) => sink.finish(),
+            FragmentWriterInner::Zstd(writer) => {
+                writer.finish().and_then(|()| writer.get_mut().finish())
+            }
+        }
+        .context("failed to write snapshot fragment")
+    }
+}
+
 /// Writer of serialized VM snapshots.
 ///
 /// Each fragment is an opaque byte blob. Namespaces can be used to avoid fragment naming
@@ -34,6 +159,8 @@ pub struct SnapshotWriter {
     dir: PathBuf,
     /// If encryption is used, the plaintext key will be stored here.
     key: Option<CryptKey>,
+    /// If compression is used, the zstd compression level.
+    zstd_level: Option<i32>,
 }
 
 impl Debug for SnapshotWriter {
@@ -41,6 +168,7 @@ impl Debug for SnapshotWriter {
         f.debug_struct("SnapshotWriter")
             .field("dir", &format!("{:?}", self.dir))
             .field("key", if self.key.is_some() { &"Some" } else { &"None" })
+            .field("zstd_level", &self.zstd_level)
             .finish()
     }
 }
@@ -48,49 +176,86 @@ impl Debug for SnapshotWriter {
 impl SnapshotWriter {
     /// Creates a new `SnapshotWriter` that will writes its data to a dir at `root`. The path must
     /// not exist yet. If encryption is desired, set encrypt (Note: only supported downstream on
-    /// Windows).
+    /// Windows, the key would not be stored with the `rustcrypto` feature).
     // TODO(b/268094487): If the snapshot fails, we leave incomplete snapshot files at the
     // requested path. Consider building up the snapshot dir somewhere else and moving it into
     // place at the end.
     pub fn new(root: PathBuf, encrypt: bool) -> Result<Self> {
+        if encrypt && cfg!(feature = "rustcrypto") {
+            return Err(anyhow::anyhow!(
+                "snapshots encrypted with a random key could not be restored; provide a key"
+            ));
+        }
+        let key = encrypt.then(crypto::generate_random_key);
+        Self::create(root, key, false, None)
+    }
+
+    /// Creates a new `SnapshotWriter` like `new`, but encrypts the fragments with the user
+    /// provided `key`, which is not stored in the snapshot, and compresses them with zstd at
+    /// `zstd_level`. Both are optional.
+    pub fn new_with_options(
+        root: PathBuf,
+        key: Option<CryptKey>,
+        zstd_level: Option<i32>,
+    ) -> Result<Self> {
+        let user_key = key.is_some();
+        Self::create(root, key, user_key, zstd_level)
+    }
+
+    fn create(
+        root: PathBuf,
+        key: Option<CryptKey>,
+        user_key: bool,
+        zstd_level: Option<i32>,
+    ) -> Result<Self> {
         std::fs::create_dir(&root)
             .with_context(|| format!("failed to create snapshot root dir: {}", root.display()))?;
 
-        if encrypt {
-            let key = crypto::generate_random_key();
+        let format = SnapshotFormat {
+            user_key,
+            zstd: zstd_level.is_some(),
+        };
+        if format != SnapshotFormat::default() {
+            let file = File::create(root.join(FORMAT_FRAGMENT))
+                .context("failed to create snapshot format fragment")?;
+            ciborium::into_writer(&format, file).context("failed to write snapshot format")?;
+        }
+
+        if let Some(key) = &key {
             // Creating an empty CryptWriter will still write header information
             // to the file, and that header information is what we need. This
-            // ensures we use a single key for *all* snapshot files.
+            // ensures we use a single key for *all* snapshot files. With a user
+            // provided key, it also lets readers check the key before restoring.
             let mut writer = crypto::CryptWriter::new_from_key(
                 File::create(root.join("enc_metadata")).context("failed to create enc_metadata")?,
                 1024,
-                &key,
+                key,
             )
             .context("failed to create enc_metadata writer")?;
-            writer.flush().context("flush of enc_metadata failed")?;
-            return Ok(Self {
-                dir: root,
-                key: Some(key),
-            });
+            writer.finish().context("flush of enc_metadata failed")?;
         }
 
         Ok(Self {
             dir: root,
-            key: None,
+            key,
+            zstd_level,
         })
     }
 
     /// Creates a snapshot fragment and get access to the `Write` impl representing it.
-    pub fn raw_fragment(&self, name: &str) -> Result<Box<dyn Write>> {
+    pub fn raw_fragment(&self, name: &str) -> Result<FragmentWriter> {
         self.raw_fragment_with_chunk_size(name, DEFAULT_ENCRYPTED_CHUNK_SIZE_BYTES)
     }
 
     /// When encryption is used, allows direct control of the encrypted chunk size.
+    ///
+    /// When compression is used, the data is only compressed and written out once the writer is
+    /// flushed or finished.
     pub fn raw_fragment_with_chunk_size(
         &self,
         name: &str,
         chunk_size_bytes: usize,
-    ) -> Result<Box<dyn Write>> {
+    ) -> Result<FragmentWriter> {
         let path = self.dir.join(name);
         let file = File::options()
             .write(true)
@@ -103,23 +268,26 @@ impl SnapshotWriter {
                 )
             })?;
 
-        if let Some(key) = self.key.as_ref() {
-            return Ok(Box::new(crypto::CryptWriter::new_from_key(
+        let sink = match self.key.as_ref() {
+            Some(key) => FragmentSink::Encrypted(crypto::CryptWriter::new_from_key(
                 file,
                 chunk_size_bytes,
                 key,
-            )?));
-        }
-
-        Ok(Box::new(file))
+            )?),
+            None => FragmentSink::File(file),
+        };
+        let inner = match self.zstd_level {
+            Some(level) => FragmentWriterInner::Zstd(ZstdChunkWriter::new(sink, level)),
+            None => FragmentWriterInner::Plain(sink),
+        };
+        Ok(FragmentWriter { inner })
     }
 
     /// Creates a snapshot fragment from a serialized representation of `v`.
     pub fn write_fragment<T: serde::Serialize>(&self, name: &str, v: &T) -> Result<()> {
         let mut w = std::io::BufWriter::new(self.raw_fragment(name)?);
         ciborium::into_writer(v, &mut w)?;
-        w.flush()?;
-        Ok(())
+        w.into_inner().map_err(|e| e.into_error())?.finish()
     }
 
     /// Creates new namespace and returns a `SnapshotWriter` that writes to it. Namespaces can be
@@ -135,16 +303,28 @@ impl SnapshotWriter {
         Ok(Self {
             dir,
             key: self.key.clone(),
+            zstd_level: self.zstd_level,
         })
     }
 }
 
-/// Reads snapshots created by `SnapshotWriter`.
+/// Reads snapshots created by `SnapshotWriter`, or packed in a single stream by `StreamWriter`.
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct SnapshotReader {
+    /// For a single-stream snapshot, the path of the namespace inside the stream.
     dir: PathBuf,
     /// If encryption is used, the plaintext key will be stored here.
     key: Option<CryptKey>,
+    /// Whether fragments are zstd compressed.
+    zstd: bool,
+    stream: Option<SnapshotStream>,
+}
+
+/// A single-stream snapshot file and its index.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct SnapshotStream {
+    path: PathBuf,
+    index: BTreeMap<String, SectionLocation>,
 }
 
 impl Debug for SnapshotReader {
@@ -152,49 +332,160 @@ impl Debug for SnapshotReader {
         f.debug_struct("SnapshotReader")
             .field("dir", &format!("{:?}", self.dir))
             .field("key", if self.key.is_some() { &"Some" } else { &"None" })
+            .field("zstd", &self.zstd)
+            .field(
+                "stream",
+                &self.stream.as_ref().map(|s| format!("{:?}", s.path)),
+            )
             .finish()
     }
 }
 
 impl SnapshotReader {
-    /// Reads a snapshot at `root`. Set require_encrypted to require an encrypted snapshot.
+    /// Reads a snapshot at `root`, which is either a snapshot directory or a single-stream
+    /// snapshot file. Set require_encrypted to require an encrypted snapshot.
     pub fn new(root: &Path, require_encrypted: bool) -> Result<Self> {
+        Self::new_with_key(root, require_encrypted, None)
+    }
+
+    /// Reads a snapshot like `new`. `key` must be given if and only if the snapshot was encrypted
+    /// with a user provided key.
+    pub fn new_with_key(
+        root: &Path,
+        require_encrypted: bool,
+        key: Option<CryptKey>,
+    ) -> Result<Self> {
+        if is_snapshot_stream(root) {
+            if require_encrypted || key.is_some() {
+                return Err(anyhow::anyhow!(
+                    "single-stream snapshots cannot be encrypted"
+                ));
+            }
+            let mut file = File::open(root)
+                .with_context(|| format!("failed to open snapshot {}", root.display()))?;
+            let index = stream::read_index(&mut file).context("failed to read snapshot index")?;
+            let mut reader = Self {
+                dir: PathBuf::new(),
+                key: None,
+                zstd: false,
+                stream: Some(SnapshotStream {
+                    path: root.to_path_buf(),
+                    index,
+                }),
+            };
+            let format = reader.read_format()?;
+            if format.user_key {
+                return Err(anyhow::anyhow!(
+                    "single-stream snapshots cannot be encrypted"
+                ));
+            }
+            reader.zstd = format.zstd;
+            return Ok(reader);
+        }
+
+        let mut reader = Self {
+            dir: root.to_path_buf(),
+            key: None,
+            zstd: false,
+            stream: None,
+        };
+        let format = reader.read_format()?;
         let enc_metadata_path = root.join("enc_metadata");
-        if Path::exists(&enc_metadata_path) {
-            let key = Some(
+        if format.user_key {
+            let key = key.context("snapshot is encrypted with a user provided key")?;
+            // The data is authenticated as it is read, so reading the metadata to the end checks
+            // the key before anything is restored.
+            let mut metadata = crypto::CryptReader::from_file_and_key(
+                File::open(&enc_metadata_path).context("failed to open encryption metadata")?,
+                &key,
+            )
+            .context("failed to read encryption metadata")?;
+            std::io::copy(&mut metadata, &mut std::io::sink())
+                .context("snapshot key does not match")?;
+            reader.key = Some(key);
+        } else if key.is_some() {
+            return Err(anyhow::anyhow!(
+                "snapshot is not encrypted with a user provided key"
+            ));
+        } else if Path::exists(&enc_metadata_path) {
+            reader.key = Some(
                 crypto::CryptReader::extract_key(
                     File::open(&enc_metadata_path).context("failed to open encryption metadata")?,
                 )
                 .context("failed to load snapshot key")?,
             );
-            return Ok(Self {
-                dir: root.to_path_buf(),
-                key,
-            });
         } else if require_encrypted {
             return Err(anyhow::anyhow!("snapshot was not encrypted"));
         }
+        reader.zstd = format.zstd;
+        Ok(reader)
+    }
 
-        Ok(Self {
-            dir: root.to_path_buf(),
-            key: None,
-        })
+    /// Reads the format fragment at the root of the snapshot, if any.
+    fn read_format(&self) -> Result<SnapshotFormat> {
+        let exists = match &self.stream {
+            Some(stream) => stream.index.contains_key(FORMAT_FRAGMENT),
+            None => self.dir.join(FORMAT_FRAGMENT).exists(),
+        };
+        if !exists {
+            return Ok(SnapshotFormat::default());
+        }
+        ciborium::from_reader(self.open_fragment(FORMAT_FRAGMENT)?)
+            .context("failed to read snapshot format")
     }
 
-    /// Gets access to a `Read` impl that represents a fragment.
-    pub fn raw_fragment(&self, name: &str) -> Result<Box<dyn Read>> {
+    /// Opens a fragment without decrypting or decompressing it.
+    fn open_fragment(&self, name: &str) -> Result<Box<dyn Read>> {
+        let path = self.dir.join(name);
+        if let Some(stream) = &self.stream {
+            let location = path
+                .to_str()
+                .and_then(|section| stream.index.get(section))
+                .with_context(|| {
+                    format!("snapshot fragment {} not found in stream", path.display())
+                })?;
+            let file = File::open(&stream.path)
+                .with_context(|| format!("failed to open snapshot {}", stream.path.display()))?;
+            return Ok(Box::new(SectionReader::new(file, *location)?));
+        }
+        Ok(Box::new(self.open_file(name)?))
+    }
+
+    fn open_file(&self, name: &str) -> Result<File> {
         let path = self.dir.join(name);
-        let file = File::open(&path).with_context(|| {
+        File::open(&path).with_context(|| {
             format!(
                 "failed to open snapshot fragment {name:?} at {}",
                 path.display()
             )
-        })?;
-        if let Some(key) = self.key.as_ref() {
-            return Ok(Box::new(crypto::CryptReader::from_file_and_key(file, key)?));
+        })
+    }
+
+    /// Gets access to a `Read` impl that represents a fragment.
+    pub fn raw_fragment(&self, name: &str) -> Result<Box<dyn Read>> {
+        let mut reader: Box<dyn Read> = match self.key.as_ref() {
+            // Encrypted snapshots are never single-stream.
+            Some(key) => crypto::CryptReader::from_file_and_key(self.open_file(name)?, key)?,
+            None => self.open_fragment(name)?,
+        };
+        if self.zstd {
+            reader = Box::new(
+                zstd::stream::read::Decoder::new(reader)
+                    .context("failed to create zstd decoder")?,
+            );
         }
+        Ok(reader)
+    }
 
-        Ok(Box::new(file))
+    /// Opens the file holding a fragment, e.g. to map it. The fragment must be stored as is, in a
+    /// snapshot directory without compression or encryption.
+    pub fn fragment_file(&self, name: &str) -> Result<File> {
+        if self.stream.is_some() || self.key.is_some() || self.zstd {
+            return Err(anyhow::anyhow!(
+                "snapshot fragment {name:?} is not stored in a plain file"
+            ));
+        }
+        self.open_file(name)
     }
 
     /// Reads a fragment.
@@ -205,6 +496,16 @@ impl SnapshotReader {
 
     /// Reads the names of all fragments in this namespace.
     pub fn list_fragments(&self) -> Result<Vec<String>> {
+        if let Some(stream) = &self.stream {
+            return Ok(stream
+                .index
+                .keys()
+                .map(Path::new)
+                .filter(|path| path.parent() == Some(self.dir.as_path()))
+                .filter_map(|path| path.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect());
+        }
         let mut result = Vec::new();
         for entry in std::fs::read_dir(&self.dir)? {
             let entry = entry?;
@@ -221,11 +522,25 @@ impl SnapshotReader {
         Ok(Self {
             dir,
             key: self.key.clone(),
+            zstd: self.zstd,
+            stream: self.stream.clone(),
         })
     }
 
     /// Reads the names of all child namespaces
     pub fn list_namespaces(&self) -> Result<Vec<String>> {
+        if let Some(stream) = &self.stream {
+            let mut result: Vec<String> = stream
+                .index
+                .keys()
+                .filter_map(|name| Path::new(name).strip_prefix(&self.dir).ok())
+                .filter(|path| path.components().count() > 1)
+                .filter_map(|path| path.components().next())
+                .map(|name| name.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            result.dedup();
+            return Ok(result);
+        }
         let mut result = Vec::new();
         for entry in std::fs::read_dir(&self.dir)? {
             let entry = entry?;
@@ -238,3 +553,79 @@ impl SnapshotReader {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_snapshot(writer: &SnapshotWriter) {
+        writer.write_fragment("a", &"top level").unwrap();
+        let mut w = writer
+            .add_namespace("ns")
+            .unwrap()
+            .raw_fragment("b")
+            .unwrap();
+        w.write_all(&[0x55; 100000]).unwrap();
+        w.finish().unwrap();
+    }
+
+    fn check_snapshot(reader: &SnapshotReader) {
+        assert_eq!(reader.read_fragment::<String>("a").unwrap(), "top level");
+        let mut data = Vec::new();
+        reader
+            .namespace("ns")
+            .unwrap()
+            .raw_fragment("b")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, [0x55; 100000]);
+    }
+
+    #[test]
+    fn key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        std::fs::write(&path, [0xab; KEY_SIZE]).unwrap();
+        assert!(read_key_file(&path).is_ok());
+        std::fs::write(&path, format!("{}\n", "0f".repeat(KEY_SIZE))).unwrap();
+        assert!(read_key_file(&path).is_ok());
+        std::fs::write(&path, "0g".repeat(KEY_SIZE)).unwrap();
+        assert!(read_key_file(&path).is_err());
+        std::fs::write(&path, "0f").unwrap();
+        assert!(read_key_file(&path).is_err());
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("snapshot");
+        write_snapshot(&SnapshotWriter::new_with_options(root.clone(), None, Some(3)).unwrap());
+        assert!(std::fs::metadata(root.join("ns/b")).unwrap().len() < 1000);
+        check_snapshot(&SnapshotReader::new(&root, false).unwrap());
+    }
+
+    #[cfg(feature = "rustcrypto")]
+    #[test]
+    fn random_key_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(SnapshotWriter::new(dir.path().join("snapshot"), true).is_err());
+    }
+
+    #[cfg(feature = "rustcrypto")]
+    #[test]
+    fn user_key_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("snapshot");
+        let key = CryptKey::from_bytes(&[7; 32]);
+        write_snapshot(
+            &SnapshotWriter::new_with_options(root.clone(), Some(key.clone()), Some(3)).unwrap(),
+        );
+        assert!(SnapshotReader::new(&root, false).is_err());
+        assert!(
+            SnapshotReader::new_with_key(&root, true, Some(CryptKey::from_bytes(&[8; 32])))
+                .is_err()
+        );
+        check_snapshot(&SnapshotReader::new_with_key(&root, true, Some(key)).unwrap());
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Single-stream snapshot container.
+//!
+//! A snapshot directory can be packed into a single stream that is written strictly sequentially,
+//! so it can go to a pipe, a socket or object storage. Sections are split in length-prefixed
+//! chunks so their size need not be known when they start, and the index comes last so a reader
+//! with random access can find any section without scanning the whole stream:
+//!
+//! ```text
+//! header:  magic: [u8; 8]
+//! section: name_len: u32, name: [u8; name_len], chunk..., end: u32 = 0
+//! chunk:   len: u32, data: [u8; len]
+//! index:   count: u32, [name_len: u32, name: [u8; name_len], offset: u64, size: u64; count]
+//! footer:  index_offset: u64, magic: [u8; 8]
+//! ```
+//!
+//! Section names are fragment paths relative to the snapshot root, with `/` separating
+//! namespaces. `offset` is the position of the first chunk of a section and `size` the number of
+//! data bytes in it. All integers are little endian.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+const STREAM_MAGIC: [u8; 8] = *b"CVMSNAP1";
+const FOOTER_SIZE: u64 = 16;
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Location of a section's data in a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct SectionLocation {
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Returns whether the file at `path` is a single-stream snapshot.
+pub fn is_snapshot_stream(path: &Path) -> bool {
+    let mut magic = [0u8; 8];
+    path.is_file()
+        && File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .is_ok()
+        && magic == STREAM_MAGIC
+}
+
+/// Writes a single-stream snapshot to `W`.
+pub struct StreamWriter<W: Write> {
+    inner: W,
+    offset: u64,
+    index: BTreeMap<String, SectionLocation>,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Starts a stream on `inner`.
+    pub fn new(mut inner: W) -> Result<Self> {
+        inner
+            .write_all(&STREAM_MAGIC)
+            .context("failed to write snapshot stream header")?;
+        Ok(StreamWriter {
+            inner,
+            offset: STREAM_MAGIC.len() as u64,
+            index: BTreeMap::new(),
+        })
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.inner
+            .write_all(data)
+            .context("failed to write snapshot stream")?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    fn write_name(&mut self, name: &str) -> Result<()> {
+        self.write_all(&(name.len() as u32).to_le_bytes())?;
+        self.write_all(name.as_bytes())
+    }
+
+    /// Starts the section `name`. Its data must be written to the returned writer, which must
+    /// then be finished with `SectionWriter::finish`.
+    pub fn section(&mut self, name: &str) -> Result<SectionWriter<'_, W>> {
+        if self.index.contains_key(name) {
+            bail!("duplicate snapshot section {name:?}");
+        }
+        self.write_name(name)?;
+        let offset = self.offset;
+        Ok(SectionWriter {
+            stream: self,
+            name: name.to_owned(),
+            location: SectionLocation { offset, size: 0 },
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Writes the section `name` with a serialized representation of `v`, like
+    /// `SnapshotWriter::write_fragment`.
+    pub fn write_fragment<T: serde::Serialize>(&mut self, name: &str, v: &T) -> Result<()> {
+        let mut section = self.section(name)?;
+        ciborium::into_writer(v, &mut section)?;
+        section.finish()
+    }
+
+    /// Adds every file below the snapshot directory `root` as a section.
+    pub fn add_dir(&mut self, root: &Path) -> Result<()> {
+        self.add_dir_at(root, root)
+    }
+
+    fn add_dir_at(&mut self, root: &Path, dir: &Path) -> Result<()> {
+        let mut entries = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to list {}", dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        // Sort so that the same snapshot always produces the same stream.
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                self.add_dir_at(root, &path)?;
+                continue;
+            }
+            let name = path.strip_prefix(root)?;
+            let name = name
+                .to_str()
+                .with_context(|| format!("fragment path {} is not UTF-8", name.display()))?;
+            let mut file =
+                File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+            let mut section = self.section(name)?;
+            std::io::copy(&mut file, &mut section)?;
+            section.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let index_offset = self.offset;
+        let index = std::mem::take(&mut self.index);
+        self.write_all(&(index.len() as u32).to_le_bytes())?;
+        for (name, location) in &index {
+            self.write_name(name)?;
+            self.write_all(&location.offset.to_le_bytes())?;
+            self.write_all(&location.size.to_le_bytes())?;
+        }
+        self.write_all(&index_offset.to_le_bytes())?;
+        self.write_all(&STREAM_MAGIC)?;
+        self.inner
+            .flush()
+            .context("failed to flush snapshot stream")?;
+        Ok(self.inner)
+    }
+}
+
+/// Writer of one section of a `StreamWriter`.
+pub struct SectionWriter<'a, W: Write> {
+    stream: &'a mut StreamWriter<W>,
+    name: String,
+    location: SectionLocation,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> SectionWriter<'_, W> {
+    fn write_chunk(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.stream
+            .write_all(&(self.buf.len() as u32).to_le_bytes())?;
+        self.stream.write_all(&self.buf)?;
+        self.location.size += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Ends the section and adds it to the index.
+    pub fn finish(mut self) -> Result<()> {
+        self.write_chunk()?;
+        self.stream.write_all(&0u32.to_le_bytes())?;
+        self.stream
+            .index
+            .insert(std::mem::take(&mut self.name), self.location);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SectionWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let len = data.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.write_chunk().map_err(std::io::Error::other)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Chunks are only written when full or when the section ends.
+        Ok(())
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads the index of the single-stream snapshot `file`.
+pub(crate) fn read_index(file: &mut File) -> Result<BTreeMap<String, SectionLocation>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < STREAM_MAGIC.len() as u64 + FOOTER_SIZE {
+        bail!("snapshot stream is truncated");
+    }
+    file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+    let index_offset = read_u64(file)?;
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic)?;
+    if magic != STREAM_MAGIC {
+        bail!("snapshot stream has no index; it may be incomplete");
+    }
+    if index_offset > len - FOOTER_SIZE {
+        bail!("snapshot stream index offset {index_offset} is out of range");
+    }
+
+    file.seek(SeekFrom::Start(index_offset))?;
+    let mut r = BufReader::new(file);
+    let count = read_u32(&mut r)?;
+    let mut index = BTreeMap::new();
+    for _ in 0..count {
+        let name_len = read_u32(&mut r)?;
+        let mut name = vec![0u8; name_len as usize];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8(name).context("snapshot section name is not UTF-8")?;
+        let offset = read_u64(&mut r)?;
+        let size = read_u64(&mut r)?;
+        index.insert(name, SectionLocation { offset, size });
+    }
+    Ok(index)
+}
+
+/// Reader of one section of a single-stream snapshot.
+pub(crate) struct SectionReader {
+    inner: BufReader<File>,
+    chunk_remaining: usize,
+    done: bool,
+}
+
+impl SectionReader {
+    pub fn new(mut file: File, location: SectionLocation) -> Result<Self> {
+        file.seek(SeekFrom::Start(location.offset))?;
+        Ok(SectionReader {
+            inner: BufReader::new(file),
+            chunk_remaining: 0,
+            done: false,
+        })
+    }
+}
+
+impl Read for SectionReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.chunk_remaining == 0 {
+            let mut len = [0u8; 4];
+            self.inner.read_exact(&mut len)?;
+            self.chunk_remaining = u32::from_le_bytes(len) as usize;
+            if self.chunk_remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.chunk_remaining);
+        let read = self.inner.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.chunk_remaining -= read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("snapshot");
+        std::fs::create_dir_all(root.join("vcpu")).unwrap();
+        std::fs::write(root.join("irqchip"), b"irq").unwrap();
+        std::fs::write(root.join("vcpu").join("0"), b"cpu0").unwrap();
+
+        let path = dir.path().join("snapshot.bin");
+        let mut stream = StreamWriter::new(File::create(&path).unwrap()).unwrap();
+        stream.add_dir(&root).unwrap();
+        let mut section = stream.section("mem").unwrap();
+        let big = vec![0xa5u8; CHUNK_SIZE * 2 + 3];
+        section.write_all(&big).unwrap();
+        section.finish().unwrap();
+        stream.finish().unwrap();
+
+        assert!(is_snapshot_stream(&path));
+        assert!(!is_snapshot_stream(&root));
+        let mut file = File::open(&path).unwrap();
+        let index = read_index(&mut file).unwrap();
+        assert_eq!(
+            index.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["irqchip", "mem", "vcpu/0"]
+        );
+        assert_eq!(index["mem"].size, big.len() as u64);
+
+        let read_section = |name: &str| {
+            let mut data = Vec::new();
+            SectionReader::new(file.try_clone().unwrap(), index[name])
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+        assert_eq!(read_section("vcpu/0"), b"cpu0");
+        assert_eq!(read_section("irqchip"), b"irq");
+        assert_eq!(read_section("mem"), big);
+    }
+
+    #[test]
+    fn truncated_stream_has_no_index() {
+        let mut buf = Vec::new();
+        let mut stream = StreamWriter::new(&mut buf).unwrap();
+        stream.write_fragment("pvclock", &1u32).unwrap();
+        drop(stream);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.bin");
+        std::fs::write(&path, &buf).unwrap();
+        assert!(read_index(&mut File::open(&path).unwrap()).is_err());
+    }
+}
@@ -10,17 +10,21 @@ cfg_if::cfg_if! {
         use crate::crosvm::sys::config::parse_pmem_ext2_option;
         use crate::crosvm::sys::config::VfioOption;
         use crate::crosvm::sys::config::SharedDir;
+        use crate::crosvm::sys::config::VcpuBoostOptions;
         use crate::crosvm::sys::config::PmemExt2Option;
     }
 }
 
 use std::collections::BTreeMap;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::net::SocketAddr;
 #[cfg(feature = "config-file")]
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use arch::CpuSet;
 use arch::FdtPosition;
@@ -42,9 +46,13 @@ use devices::virtio::scsi::ScsiOption;
 use devices::virtio::snd::parameters::Parameters as SndParameters;
 use devices::virtio::vhost::user::device;
 use devices::virtio::vsock::VsockConfig;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use devices::virtio::CanParameters;
 #[cfg(feature = "gpu")]
 use devices::virtio::GpuDisplayParameters;
 #[cfg(feature = "gpu")]
+use devices::virtio::GpuDisplayRotation;
+#[cfg(feature = "gpu")]
 use devices::virtio::GpuMouseMode;
 #[cfg(feature = "gpu")]
 use devices::virtio::GpuParameters;
@@ -52,11 +60,23 @@ use devices::virtio::GpuParameters;
 use devices::virtio::NetParameters;
 #[cfg(all(unix, feature = "net"))]
 use devices::virtio::NetParametersMode;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use devices::virtio::SpiParameters;
+#[cfg(all(
+    any(target_os = "android", target_os = "linux"),
+    feature = "wasm-devices"
+))]
+use devices::virtio::WasmDeviceParameters;
 use devices::FwCfgParameters;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use devices::IvshmemParameters;
 use devices::PflashParameters;
+use devices::SecureBootParameters;
 use devices::SerialHardware;
 use devices::SerialParameters;
 use devices::StubPciParameters;
+#[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+use devices::SwtpmParameters;
 #[cfg(target_arch = "x86_64")]
 use hypervisor::CpuHybridType;
 use hypervisor::ProtectionType;
@@ -70,6 +90,10 @@ use serde::Deserializer;
 use serde::Serialize;
 #[cfg(feature = "gpu")]
 use serde_keyvalue::FromKeyValues;
+#[cfg(feature = "net")]
+use vm_control::NetGuestOffloads;
+use vm_control::PstoreRecordKind;
+use vm_control::UsbHidGadgetKind;
 use vm_memory::FileBackedMappingParameters;
 
 use super::config::PmemOption;
@@ -80,6 +104,8 @@ use super::gpu_config::fixup_gpu_options;
 #[cfg(all(feature = "gpu", feature = "virgl_renderer"))]
 use super::sys::GpuRenderServerParameters;
 use crate::crosvm::config::from_key_values;
+#[cfg(all(target_arch = "x86_64", unix))]
+use crate::crosvm::config::load_cpuid_overrides;
 use crate::crosvm::config::parse_bus_id_addr;
 use crate::crosvm::config::parse_cpu_affinity;
 use crate::crosvm::config::parse_cpu_btreemap_u32;
@@ -88,18 +114,26 @@ use crate::crosvm::config::parse_cpu_btreemap_u32;
     any(target_os = "android", target_os = "linux")
 ))]
 use crate::crosvm::config::parse_cpu_frequencies;
+use crate::crosvm::config::parse_duration;
+use crate::crosvm::config::parse_hex_bytes;
 use crate::crosvm::config::parse_mmio_address_range;
 use crate::crosvm::config::parse_pflash_parameters;
+use crate::crosvm::config::parse_secure_boot_parameters;
 use crate::crosvm::config::parse_serial_options;
 use crate::crosvm::config::parse_touch_device_option;
+use crate::crosvm::config::read_params_file;
 use crate::crosvm::config::BatteryConfig;
 use crate::crosvm::config::CpuOptions;
 use crate::crosvm::config::DtboOption;
 use crate::crosvm::config::Executable;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use crate::crosvm::config::HostNumaBinding;
 use crate::crosvm::config::HypervisorKind;
 use crate::crosvm::config::InputDeviceOption;
 use crate::crosvm::config::IrqChipKind;
 use crate::crosvm::config::MemOptions;
+#[cfg(target_arch = "x86_64")]
+use crate::crosvm::config::RtcOptions;
 use crate::crosvm::config::TouchDeviceOption;
 use crate::crosvm::config::VhostUserFrontendOption;
 #[cfg(feature = "plugin")]
@@ -139,29 +173,46 @@ pub enum CrossPlatformCommands {
     #[cfg(feature = "balloon")]
     BalloonWs(BalloonWsCommand),
     Battery(BatteryCommand),
+    Console(ConsoleCommand),
     #[cfg(feature = "composite-disk")]
     CreateComposite(CreateCompositeCommand),
     #[cfg(feature = "qcow")]
     CreateQcow2(CreateQcow2Command),
     Device(DeviceCommand),
     Disk(DiskCommand),
+    DumpCore(DumpCoreCommand),
+    Fs(FsCommand),
     #[cfg(feature = "gpu")]
     Gpu(GpuCommand),
     #[cfg(feature = "audio")]
     Snd(SndCommand),
     MakeRT(MakeRTCommand),
+    Migrate(MigrateCommand),
+    #[cfg(feature = "net")]
+    Net(NetCommand),
+    Params(ParamsCommand),
+    Query(QueryCommand),
+    #[cfg(feature = "config-file")]
+    ReloadConfig(ReloadConfigCommand),
     Resume(ResumeCommand),
+    Rtc(RtcCommand),
     Run(RunCommand),
+    Stats(StatsCommand),
     Stop(StopCommand),
     Suspend(SuspendCommand),
     Swap(SwapCommand),
+    Trace(TraceCommand),
     Powerbtn(PowerbtnCommand),
+    Powerdown(PowerdownCommand),
     Sleepbtn(SleepCommand),
     Gpe(GpeCommand),
     Usb(UsbCommand),
+    Vcpu(VcpuCommand),
     Version(VersionCommand),
     Vfio(VfioCrosvmCommand),
     #[cfg(feature = "pci-hotplug")]
+    Virtio(VirtioCommand),
+    #[cfg(feature = "pci-hotplug")]
     VirtioNet(VirtioNetCommand),
     Snapshot(SnapshotCommand),
 }
@@ -204,6 +255,11 @@ pub struct BalloonWsCommand {
     #[argh(positional, arg_name = "VM_SOCKET")]
     /// VM control socket path.
     pub socket_path: String,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "PATH")]
+    /// instead of requesting a report, listen on a socket created at PATH and print every working
+    /// set report of the guest as it arrives
+    pub watch: Option<PathBuf>,
 }
 
 #[derive(FromArgs)]
@@ -260,6 +316,7 @@ pub struct CreateQcow2Command {
 #[argh(subcommand)]
 pub enum DiskSubcommand {
     Resize(ResizeDiskSubcommand),
+    SetReadOnly(SetReadOnlyDiskSubcommand),
 }
 
 #[derive(FromArgs)]
@@ -277,6 +334,21 @@ pub struct ResizeDiskSubcommand {
     pub socket_path: String,
 }
 
+#[derive(FromArgs)]
+/// start or stop rejecting writes to a disk
+#[argh(subcommand, name = "set-read-only")]
+pub struct SetReadOnlyDiskSubcommand {
+    #[argh(positional, arg_name = "DISK_INDEX")]
+    /// disk index
+    pub disk_index: usize,
+    #[argh(positional, arg_name = "READ_ONLY")]
+    /// true to reject writes, false to accept them again
+    pub read_only: bool,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "disk")]
 /// Manage attached virtual disk devices
@@ -285,6 +357,127 @@ pub struct DiskCommand {
     pub command: DiskSubcommand,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "dump-core")]
+/// Pause the crosvm instance and write its vCPU registers and guest memory to an ELF core file
+pub struct DumpCoreCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+    #[argh(positional, arg_name = "OUTPUT")]
+    /// path of the core file to write
+    pub output: PathBuf,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum ConsoleSubcommand {
+    AddPort(AddPortConsoleSubcommand),
+    RemovePort(RemovePortConsoleSubcommand),
+}
+
+#[derive(FromArgs)]
+/// add a port backed by a Unix socket, pipe or character device
+#[argh(subcommand, name = "add-port")]
+pub struct AddPortConsoleSubcommand {
+    #[argh(positional, arg_name = "CONSOLE_INDEX")]
+    /// index of the virtio-console among those created with max-ports
+    pub console_index: usize,
+    #[argh(positional, arg_name = "NAME")]
+    /// port name, visible to the guest
+    pub name: String,
+    #[argh(positional, arg_name = "PATH")]
+    /// host path backing the port
+    pub path: PathBuf,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// remove a previously added port
+#[argh(subcommand, name = "remove-port")]
+pub struct RemovePortConsoleSubcommand {
+    #[argh(positional, arg_name = "CONSOLE_INDEX")]
+    /// index of the virtio-console among those created with max-ports
+    pub console_index: usize,
+    #[argh(positional, arg_name = "NAME")]
+    /// port name
+    pub name: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "console")]
+/// Manage ports of virtio-console devices
+pub struct ConsoleCommand {
+    #[argh(subcommand)]
+    pub command: ConsoleSubcommand,
+}
+
+#[cfg(feature = "net")]
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum NetSubcommand {
+    SetGuestOffloads(SetGuestOffloadsNetSubcommand),
+}
+
+#[cfg(feature = "net")]
+#[derive(FromArgs)]
+/// limit the offloads of the packets passed to the guest
+#[argh(subcommand, name = "set-guest-offloads")]
+pub struct SetGuestOffloadsNetSubcommand {
+    #[argh(positional, arg_name = "NET_INDEX")]
+    /// index of the virtio-net device among the --net options not using vhost-net
+    pub net_index: usize,
+    #[argh(positional, arg_name = "OFFLOADS")]
+    /// comma separated offloads among csum, tso4, tso6, ecn and ufo, or none
+    pub offloads: NetGuestOffloads,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "net")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "net")]
+/// Reconfigure running virtio-net devices
+pub struct NetCommand {
+    #[argh(subcommand)]
+    pub command: NetSubcommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum FsSubcommand {
+    SetReadOnly(SetReadOnlyFsSubcommand),
+}
+
+#[derive(FromArgs)]
+/// start or stop rejecting modifications of a shared directory
+#[argh(subcommand, name = "set-read-only")]
+pub struct SetReadOnlyFsSubcommand {
+    #[argh(positional, arg_name = "FS_INDEX")]
+    /// index of the virtio-fs device among the --shared-dir options of type fs
+    pub fs_index: usize,
+    #[argh(positional, arg_name = "READ_ONLY")]
+    /// true to reject modifications, false to accept them again
+    pub read_only: bool,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "fs")]
+/// Reconfigure running virtio-fs devices
+pub struct FsCommand {
+    #[argh(subcommand)]
+    pub command: FsSubcommand,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "make_rt")]
 /// Enables real-time vcpu priority for crosvm instances started with `--delay-rt`
@@ -379,6 +572,209 @@ pub struct SwapStatusCommand {
     pub socket_path: String,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "devices")]
+/// Print a JSON description of the devices of a VM
+pub struct QueryDevicesCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "pstore")]
+/// Print the pstore records of a VM, those of the most recent boot first
+pub struct QueryPstoreCommand {
+    #[argh(option, arg_name = "KIND")]
+    /// only print the records of this kind: dmesg, console, ftrace or pmsg
+    pub kind: Option<PstoreRecordKind>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+/// Query the configuration of a VM
+#[derive(FromArgs)]
+#[argh(subcommand, name = "query")]
+pub struct QueryCommand {
+    #[argh(subcommand)]
+    pub nested: QuerySubcommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum QuerySubcommands {
+    Devices(QueryDevicesCommand),
+    Pstore(QueryPstoreCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "vcpu")]
+/// Print the exit counts and handling time of each vCPU by exit reason
+pub struct StatsVcpuCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "numa")]
+/// Print the host NUMA policy of each range of guest memory and the host nodes its resident pages
+/// are on
+pub struct StatsNumaCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "irq")]
+/// Print the latency histogram of each GSI, see `crosvm run --irq-latency-stats`
+pub struct StatsIrqCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "ksm")]
+/// Print the guest memory merged with identical pages by KSM, see `crosvm run --ksm`
+pub struct StatsKsmCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+/// Print runtime statistics of a VM
+#[derive(FromArgs)]
+#[argh(subcommand, name = "stats")]
+pub struct StatsCommand {
+    #[argh(subcommand)]
+    pub nested: StatsSubcommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum StatsSubcommands {
+    Vcpu(StatsVcpuCommand),
+    Numa(StatsNumaCommand),
+    Ksm(StatsKsmCommand),
+    Irq(StatsIrqCommand),
+}
+
+#[cfg(feature = "config-file")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "reload-config")]
+/// Apply the changes of a configuration file to a running VM, and print the changes that could not
+/// be applied without restarting it
+pub struct ReloadConfigCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+    #[argh(positional, arg_name = "CONFIG")]
+    /// path to the new configuration file, in the format of `crosvm run --cfg`
+    pub config_path: PathBuf,
+}
+
+/// RTC commands
+#[derive(FromArgs)]
+#[argh(subcommand, name = "rtc")]
+pub struct RtcCommand {
+    #[argh(subcommand)]
+    pub nested: RtcSubcommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum RtcSubcommands {
+    GetOffset(RtcGetOffsetCommand),
+    SetOffset(RtcSetOffsetCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "get-offset")]
+/// Print the offset in seconds of the RTC of a VM from the host time
+pub struct RtcGetOffsetCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "set-offset")]
+/// Set the offset in seconds of the RTC of a VM from the host time
+pub struct RtcSetOffsetCommand {
+    #[argh(positional, arg_name = "SECONDS")]
+    /// offset from the host time, may be negative
+    pub offset: i64,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+/// vCPU commands
+#[derive(FromArgs)]
+#[argh(subcommand, name = "vcpu")]
+pub struct VcpuCommand {
+    #[argh(subcommand)]
+    pub nested: VcpuSubcommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum VcpuSubcommands {
+    Online(VcpuOnlineCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "online")]
+/// Let the guest bring up a vCPU parked at boot with `--cpus max-cores` (aarch64 only)
+pub struct VcpuOnlineCommand {
+    #[argh(positional, arg_name = "INDEX")]
+    /// index of the vCPU
+    pub index: usize,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+/// Kernel command line commands
+#[derive(FromArgs)]
+#[argh(subcommand, name = "params")]
+pub struct ParamsCommand {
+    #[argh(subcommand)]
+    pub nested: ParamsSubcommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum ParamsSubcommands {
+    SetNextBoot(ParamsSetNextBootCommand),
+    ClearNextBoot(ParamsClearNextBootCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "set-next-boot")]
+/// Replace the kernel command line for the next boot of a VM started with
+/// `--next-boot-params-file`
+pub struct ParamsSetNextBootCommand {
+    #[argh(option, short = 'p', arg_name = "PARAMS")]
+    /// kernel command line arguments. Can be given more than once
+    pub params: Vec<String>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "clear-next-boot")]
+/// Cancel a replacement of the kernel command line for the next boot of a VM
+pub struct ParamsClearNextBootCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 /// Vmm-swap commands
 #[derive(FromArgs)]
 #[argh(subcommand, name = "swap")]
@@ -397,6 +793,55 @@ pub enum SwapSubcommands {
     Status(SwapStatusCommand),
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "enable")]
+/// Enable tracing categories of a VM
+pub struct TraceEnableCommand {
+    #[argh(positional, arg_name = "CATEGORIES")]
+    /// comma separated list of tracing categories
+    pub categories: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "disable")]
+/// Disable tracing categories of a VM
+pub struct TraceDisableCommand {
+    #[argh(positional, arg_name = "CATEGORIES")]
+    /// comma separated list of tracing categories
+    pub categories: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "status")]
+/// List the tracing categories of a VM and whether they are enabled
+pub struct TraceStatusCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+/// Tracing commands
+#[derive(FromArgs)]
+#[argh(subcommand, name = "trace")]
+pub struct TraceCommand {
+    #[argh(subcommand)]
+    pub nested: TraceSubcommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum TraceSubcommands {
+    Enable(TraceEnableCommand),
+    Disable(TraceDisableCommand),
+    Status(TraceStatusCommand),
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "powerbtn")]
 /// Triggers a power button event in the crosvm instance
@@ -406,6 +851,24 @@ pub struct PowerbtnCommand {
     pub socket_path: String,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "powerdown")]
+/// Presses the power button and waits for the guest to shut down, stopping the VM after the
+/// timeout if it has not
+pub struct PowerdownCommand {
+    #[argh(
+        option,
+        arg_name = "DURATION",
+        default = "Duration::from_secs(30)",
+        from_str_fn(parse_duration)
+    )]
+    /// time given to the guest to shut down, in seconds or with a ms, s or m suffix (default: 30s)
+    pub timeout: Duration,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "sleepbtn")]
 /// Triggers a sleep button event in the crosvm instance
@@ -485,6 +948,10 @@ pub struct VfioAddSubCommand {
     #[argh(positional)]
     /// path to host's vfio sysfs
     pub vfio_path: PathBuf,
+    #[argh(switch)]
+    /// export the BARs of the device to the virtio-iommu, so that devices behind it can DMA to
+    /// them
+    pub p2p: bool,
     #[argh(positional, arg_name = "VM_SOCKET")]
     /// VM Socket path
     pub socket_path: String,
@@ -560,12 +1027,84 @@ pub struct VirtioNetCommand {
     pub command: VirtioNetSubCommand,
 }
 
+#[cfg(feature = "pci-hotplug")]
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum VirtioSubCommand {
+    AddNet(VirtioAddNetSubCommand),
+    AddBlock(VirtioAddBlockSubCommand),
+    RemoveNet(VirtioRemoveNetSubCommand),
+    RemoveBlock(VirtioRemoveBlockSubCommand),
+}
+
+#[cfg(feature = "pci-hotplug")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "add-net")]
+/// Add a virtio-net device backed by a tap device.
+pub struct VirtioAddNetSubCommand {
+    #[argh(positional)]
+    /// tap name
+    pub tap_name: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "pci-hotplug")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "add-block")]
+/// Add a virtio-blk device.
+pub struct VirtioAddBlockSubCommand {
+    #[argh(positional, arg_name = "DISK")]
+    /// disk image path, optionally followed by the options of `--block`, e.g. `disk.img,ro`
+    pub disk: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "pci-hotplug")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "remove-net")]
+/// Remove a virtio-net device by bus number.
+pub struct VirtioRemoveNetSubCommand {
+    #[argh(positional)]
+    /// bus number for device to remove
+    pub bus: u8,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "pci-hotplug")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "remove-block")]
+/// Remove a virtio-blk device by bus number.
+pub struct VirtioRemoveBlockSubCommand {
+    #[argh(positional)]
+    /// bus number for device to remove
+    pub bus: u8,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "pci-hotplug")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "virtio")]
+/// add/remove virtio devices on a running VM through PCI hotplug.
+pub struct VirtioCommand {
+    #[argh(subcommand)]
+    pub command: VirtioSubCommand,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "device")]
 /// Start a device process
 pub struct DeviceCommand {
     /// configure async executor backend; "uring" or "epoll" on Linux, "handle" or "overlapped" on
-    /// Windows. If this option is omitted on Linux, "epoll" is used by default.
+    /// Windows. If this option is omitted on Linux, "uring" is used by default when the kernel
+    /// supports it and "epoll" otherwise.
     #[argh(option, arg_name = "EXECUTOR")]
     pub async_executor: Option<ExecutorKind>,
 
@@ -600,6 +1139,12 @@ pub enum GpuSubCommand {
     ListDisplays(GpuListDisplaysCommand),
     RemoveDisplays(GpuRemoveDisplaysCommand),
     SetDisplayMouseMode(GpuSetDisplayMouseModeCommand),
+    ConfigureDisplay(GpuConfigureDisplayCommand),
+    SetEdid(GpuSetEdidCommand),
+    Dump(GpuDumpCommand),
+    FenceStats(GpuFenceStatsCommand),
+    KillContext(GpuKillContextCommand),
+    InvalidateBacking(GpuInvalidateBackingCommand),
 }
 
 #[cfg(feature = "gpu")]
@@ -655,11 +1200,132 @@ pub struct GpuSetDisplayMouseModeCommand {
     pub socket_path: String,
 }
 
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Changes the resolution, scale or rotation of a display attached to the GPU device.
+#[argh(subcommand, name = "configure-display")]
+pub struct GpuConfigureDisplayCommand {
+    #[argh(option)]
+    /// display id
+    pub display_id: u32,
+    #[argh(option, arg_name = "WIDTHxHEIGHT", from_str_fn(parse_display_size))]
+    /// resolution of the display before rotation
+    pub size: Option<(u32, u32)>,
+    #[argh(option, arg_name = "PERCENT")]
+    /// scale factor in percent, applied to the DPI of the display
+    pub scale: Option<u32>,
+    #[argh(option, arg_name = "DEGREES")]
+    /// clockwise rotation from the initial orientation: 0, 90, 180 or 270
+    pub rotation: Option<GpuDisplayRotation>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Replace the EDID of a display attached to the GPU device, so that the guest probes its modes
+/// again.
+#[argh(subcommand, name = "set-edid")]
+pub struct GpuSetEdidCommand {
+    #[argh(option)]
+    /// display id
+    pub display_id: u32,
+    #[argh(option, arg_name = "PATH")]
+    /// file holding the EDID blob, a base block and its extension blocks. The EDID generated
+    /// from the display parameters is restored if omitted
+    pub edid: Option<PathBuf>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// List the resources of the GPU device that the guest still holds a reference on.
+#[argh(subcommand, name = "dump")]
+pub struct GpuDumpCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// List the fence counters of every ring of the GPU device, and the fences left unsignaled for too
+/// long.
+#[argh(subcommand, name = "fence-stats")]
+pub struct GpuFenceStatsCommand {
+    #[argh(
+        option,
+        arg_name = "DURATION",
+        default = "Duration::from_secs(5)",
+        from_str_fn(parse_duration)
+    )]
+    /// time after which an unsignaled fence is listed as stuck, in seconds or with a ms, s or m
+    /// suffix (default: 5s)
+    pub stuck_after: Duration,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Destroy a guest context that hung the GPU, without resetting the device.
+#[argh(subcommand, name = "kill-context")]
+pub struct GpuKillContextCommand {
+    #[argh(option)]
+    /// id of the context to destroy, as listed by fence-stats
+    pub ctx_id: u32,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Detach the GPU resources backed by guest memory that is about to be removed from the guest.
+#[argh(subcommand, name = "invalidate-backing")]
+pub struct GpuInvalidateBackingCommand {
+    #[argh(option, arg_name = "START-END", from_str_fn(parse_address_range))]
+    /// guest physical address range, inclusive of its end address (may be given more than once)
+    pub range: Vec<AddressRange>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+fn parse_address_range(s: &str) -> Result<AddressRange, String> {
+    match parse_mmio_address_range(s)?.as_slice() {
+        [range] => Ok(*range),
+        _ => Err(super::config::invalid_value_err(
+            s,
+            "expected a single range such as 0x100000000-0x13fffffff",
+        )),
+    }
+}
+
+#[cfg(feature = "gpu")]
+fn parse_display_size(s: &str) -> Result<(u32, u32), String> {
+    s.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|(width, height)| *width > 0 && *height > 0)
+        .ok_or_else(|| super::config::invalid_value_err(s, "expected a size such as 1920x1080"))
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand)]
 pub enum UsbSubCommand {
     Attach(UsbAttachCommand),
     SecurityKeyAttach(UsbAttachKeyCommand),
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    UsbipAttach(UsbAttachUsbipCommand),
+    HidAttach(UsbAttachHidCommand),
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    HidAttachSocket(UsbAttachHidSocketCommand),
+    HidReport(UsbHidReportCommand),
     Detach(UsbDetachCommand),
     List(UsbListCommand),
 }
@@ -695,6 +1361,66 @@ pub struct UsbAttachKeyCommand {
     pub socket_path: String,
 }
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(FromArgs)]
+/// Attach a device exported by a usbip server
+#[argh(subcommand, name = "attach_usbip")]
+pub struct UsbAttachUsbipCommand {
+    #[argh(positional, arg_name = "HOST[:PORT]")]
+    /// usbip server, on port 3240 unless specified
+    pub server: String,
+    #[argh(positional, arg_name = "BUS_ID")]
+    /// bus id of the exported device on the server, e.g. 1-1.2
+    pub busid: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// Attach an emulated HID keyboard, mouse or tablet
+#[argh(subcommand, name = "attach_hid")]
+pub struct UsbAttachHidCommand {
+    #[argh(positional, arg_name = "KIND")]
+    /// kind of HID device: keyboard, mouse or tablet
+    pub kind: UsbHidGadgetKind,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(FromArgs)]
+/// Attach an emulated HID keyboard, mouse or tablet that reads its input reports from a unix
+/// stream socket, on which the reports are written back to back
+#[argh(subcommand, name = "attach_hid_socket")]
+pub struct UsbAttachHidSocketCommand {
+    #[argh(positional, arg_name = "KIND")]
+    /// kind of HID device: keyboard, mouse or tablet
+    pub kind: UsbHidGadgetKind,
+    #[argh(positional, arg_name = "REPORT_SOCKET")]
+    /// path of the unix stream socket the reports are read from, which must be listening
+    pub report_socket: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// Send an input report to an emulated HID device
+#[argh(subcommand, name = "hid_report")]
+pub struct UsbHidReportCommand {
+    #[argh(positional, arg_name = "PORT")]
+    /// usb port of the HID device
+    pub port: u8,
+    #[argh(positional, arg_name = "REPORT", from_str_fn(parse_hex_bytes))]
+    /// input report as hex digits, e.g. 0000040000000000 to press 'a' on a keyboard
+    pub report: Vec<u8>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 /// Detach usb device
 #[argh(subcommand, name = "detach")]
@@ -778,8 +1504,33 @@ pub struct SnapshotTakeCommand {
     /// compress the ram snapshot.
     pub compress_memory: bool,
     #[argh(switch, arg_name = "encrypt")]
-    /// whether the snapshot should be encrypted
+    /// whether the snapshot should be encrypted with a random key. Not supported with the
+    /// snapshot-encryption feature, which requires --key-file instead.
     pub encrypt: bool,
+    #[argh(option, arg_name = "LEVEL")]
+    /// compress all snapshot files with zstd at the given level, using all host CPUs.
+    /// Cannot be combined with --compress-memory.
+    pub zstd_level: Option<i32>,
+    #[argh(option, arg_name = "PATH")]
+    /// encrypt all snapshot files with AES-256-GCM using the 32-byte key in the file at PATH,
+    /// stored as raw bytes or hexadecimal digits. The key is not stored in the snapshot and must
+    /// be given to `crosvm run --restore-key-file`. Requires the snapshot-encryption feature.
+    pub key_file: Option<PathBuf>,
+    #[argh(switch)]
+    /// write the snapshot as a single stream to the file at snapshot_path, which may be a pipe
+    /// such as /dev/stdout, instead of creating a directory.
+    pub stream: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "shared-memory-stats")]
+/// Show the memory usage of guest memory mapped from a snapshot with `--restore-shared-memory`.
+/// `shared_bytes` are resident and shared with other VMs restored from the same snapshot, while
+/// `private_bytes` were written by this VM.
+pub struct SnapshotSharedMemoryStatsCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
 }
 
 #[derive(FromArgs)]
@@ -787,6 +1538,35 @@ pub struct SnapshotTakeCommand {
 /// Snapshot commands
 pub enum SnapshotSubCommands {
     Take(SnapshotTakeCommand),
+    SharedMemoryStats(SnapshotSharedMemoryStatsCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "migrate", description = "Live migration commands")]
+/// Live migration commands
+pub struct MigrateCommand {
+    #[argh(subcommand)]
+    pub migrate_command: MigrateSubCommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "send")]
+/// Migrate the VM to a crosvm instance started with `--migrate-receive`. The VM exits once the
+/// destination took over.
+pub struct MigrateSendCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+    #[argh(positional, arg_name = "DESTINATION")]
+    /// address of the destination, as tcp://HOST:PORT
+    pub destination: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+/// Live migration commands
+pub enum MigrateSubCommands {
+    Send(MigrateSendCommand),
 }
 
 /// Container for GpuParameters that have been fixed after parsing using serde.
@@ -832,6 +1612,12 @@ fn load_config_file<P: AsRef<Path>>(config_file: P) -> Result<RunCommand, String
     serde_json::from_str(&config).map_err(|e| e.to_string())
 }
 
+/// Load the configuration of a VM from `config_file`, as `crosvm run --cfg` does.
+#[cfg(feature = "config-file")]
+pub fn load_config<P: AsRef<Path>>(config_file: P) -> Result<super::config::Config, String> {
+    load_config_file(config_file)?.try_into()
+}
+
 /// Return a vector configuration loaded from the files pointed by strings in a sequence.
 ///
 /// Used for including configuration files from another one.
@@ -977,14 +1763,31 @@ pub struct RunCommand {
     /// name that the Android display backend will be registered to the service manager.
     pub android_display_service: Option<String>,
 
-    #[argh(option)]
+    #[argh(option)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// path to Android fstab
+    pub android_fstab: Option<PathBuf>,
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// path to put an HTTP management API socket, with endpoints
+    /// similar to the Cloud Hypervisor API (e.g. PUT /api/v1/vm.pause).
+    pub api_socket: Option<PathBuf>,
+
+    #[cfg(windows)]
+    #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
-    /// path to Android fstab
-    pub android_fstab: Option<PathBuf>,
+    /// run the sandboxed block and network device processes in
+    /// AppContainers, with their disk images opened by the broker.
+    pub app_container: Option<bool>,
 
     /// configure async executor backend; "uring" or "epoll" on Linux, "handle" or "overlapped" on
-    /// Windows. If this option is omitted on Linux, "epoll" is used by default.
+    /// Windows. If this option is omitted on Linux, "uring" is used by default when the kernel
+    /// supports it and "epoll" otherwise.
     #[argh(option, arg_name = "EXECUTOR")]
     #[serde(skip)] // TODO(b/255223604)
     pub async_executor: Option<ExecutorKind>,
@@ -1030,6 +1833,8 @@ pub struct RunCommand {
     /// Possible key values:
     ///     type=goldfish - type of battery emulation, defaults to
     ///     goldfish
+    ///     host=BOOL - mirror the host batteries and AC adapters
+    ///     from /sys/class/power_supply (linux only)
     #[merge(strategy = overwrite_option)]
     pub battery: Option<BatteryConfig>,
 
@@ -1077,6 +1882,12 @@ pub struct RunCommand {
     ///         after failing to boot from the device with
     ///         bootindex=1.
     ///     pci-address=ADDR - Preferred PCI address, e.g. "00:01.0".
+    ///     affinity=CPUSET - Host CPUs the device worker threads
+    ///         may run on, e.g. "2-3" or "[0,2-3]".
+    ///         (default: unrestricted)
+    ///     cgroup=PATH - (Linux only) Host cgroup the device worker
+    ///         threads are moved into, e.g. "/sys/fs/cgroup/vm-io".
+    ///         With cgroup v2, this must be a threaded cgroup.
     block: Vec<DiskOptionWithId>,
 
     #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -1101,6 +1912,25 @@ pub struct RunCommand {
     #[argh(option)]
     pub bus_lock_ratelimit: Option<u64>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(
+        option,
+        arg_name = "iface=NAME[,fd=BOOL,filters=[[id=ID,mask=MASK],...]]"
+    )]
+    #[serde(default)]
+    #[merge(strategy = append)]
+    /// add a virtio-can device bridged to a host SocketCAN
+    /// interface. Can be given more than once.
+    /// Possible key values:
+    ///     iface=NAME - name of the host CAN interface, e.g. can0.
+    ///     fd=BOOL - offer CAN FD frames to the guest.
+    ///         (default: false)
+    ///     filters=[[id=ID,mask=MASK],...] - only pass received
+    ///         frames matching one of the filters to the guest.
+    ///         Identifiers use the SocketCAN encoding, with bit 31
+    ///         set for extended frames. (default: no filtering)
+    pub can: Vec<CanParameters>,
+
     #[cfg(feature = "config-file")]
     #[argh(option, arg_name = "CONFIG_FILE", from_str_fn(load_config_file))]
     #[serde(default, deserialize_with = "include_config_file")]
@@ -1203,11 +2033,24 @@ pub struct RunCommand {
     /// with vCPU frequencies * vCPU IPC > pCPU@FMax * 1024 will not be properly supported.
     pub cpu_ipc_ratio: Option<BTreeMap<usize, u32>>, // CPU index -> ipc_ratio
 
+    #[cfg(all(target_arch = "x86_64", unix))]
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// path to a JSON file overriding CPUID bits and hiding MSRs
+    ///   from the guest, applied after crosvm adjusted the CPUID
+    ///   leaves. See the crosvm book for the file format.
+    pub cpuid_overrides: Option<PathBuf>,
+
     #[argh(option, short = 'c')]
     #[merge(strategy = overwrite_option)]
     /// cpu parameters.
     /// Possible key values:
     ///     num-cores=NUM - number of VCPUs. (default: 1)
+    ///     max-cores=NUM - maximum number of VCPUs, the ones
+    ///       beyond num-cores are parked until brought online
+    ///       with `crosvm vcpu online`. (default: num-cores)
+    ///       (aarch64 only)
     ///     clusters=[[CLUSTER],...] - CPU clusters (default: None)
     ///       Each CLUSTER is a set containing a list of CPUs
     ///       that should belong to the same cluster. Individual
@@ -1308,6 +2151,13 @@ pub struct RunCommand {
     /// capture keyboard input from the display window
     pub display_window_mouse: Option<bool>,
 
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// forward pointer input from the display window to a virtio
+    /// tablet (absolute pointing device)
+    pub display_window_tablet: Option<bool>,
+
     #[cfg(feature = "config-file")]
     #[argh(option, arg_name = "CONFIG_FILE")]
     #[serde(skip)]
@@ -1360,6 +2210,14 @@ pub struct RunCommand {
     /// gather and display statistics on Vm Exits and Bus Reads/Writes.
     pub exit_stats: Option<bool>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "PATH")]
+    #[serde(default)]
+    #[merge(strategy = append)]
+    /// connect to the device extension listening on the control socket at PATH and add the
+    /// devices it provides. Can be given more than once.
+    pub extension: Vec<PathBuf>,
+
     #[argh(option)]
     #[serde(skip)]
     #[merge(strategy = overwrite)]
@@ -1414,6 +2272,16 @@ pub struct RunCommand {
     ///      included in fw_cfg under name
     pub fw_cfg: Vec<FwCfgParameters>,
 
+    #[cfg(target_arch = "x86_64")]
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// kernel image for the firmware to boot directly, exposed
+    /// through fw_cfg along with the --initrd image and the
+    /// kernel command line from --params. Requires --bios.
+    /// x86_64 only
+    pub fw_cfg_kernel: Option<PathBuf>,
+
     #[cfg(feature = "gdb")]
     #[argh(option, arg_name = "PORT")]
     #[merge(strategy = overwrite_option)]
@@ -1473,6 +2341,20 @@ pub struct RunCommand {
     ///        (ignored when sandboxing is enabled)
     ///     fixed-blob-mapping[=true|=false] - if gpu memory blobs
     ///        should use fixed address mapping.
+    ///     fence-watchdog-ms=INT - Period of the check for fences
+    ///        left unsignaled for longer than that, which are
+    ///        logged (default: no check)
+    ///     blob-files=[[id=INT,path=PATH]] - Host files the guest
+    ///        maps read-only as blob resources by creating a
+    ///        host3d blob with the given blob id. The ids must not
+    ///        collide with the blob ids used by the contexts.
+    ///     memory-budget-mib=INT - Estimated host memory in MiB
+    ///        used by the resources of the guest above which GPU
+    ///        memory pressure is reported (default: only failed
+    ///        allocations are reported)
+    ///     compositor-pipe=PATH - (Windows only) Named pipe of
+    ///        the host compositor that cross-domain contexts
+    ///        forward the Wayland protocol to.
     ///
     /// Possible key values for GpuDisplayParameters:
     ///     mode=(borderless_full_screen|windowed[width,height]) -
@@ -1533,6 +2415,27 @@ pub struct RunCommand {
     /// move all vGPU server threads to this Cgroup (default: nothing moves)
     pub gpu_server_cgroup_path: Option<PathBuf>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "NS")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// maximum time in nanoseconds a halted vCPU polls for a wake-up
+    /// before yielding its host CPU. Longer polls lower the wake-up
+    /// latency of idle vCPUs at the cost of host CPU time.
+    /// (default: the host's KVM halt_poll_ns module parameter)
+    pub halt_poll_ns: Option<u64>,
+
+    #[cfg(all(
+        any(target_os = "android", target_os = "linux"),
+        any(target_arch = "aarch64", target_arch = "x86_64")
+    ))]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// describe the caches of the host CPUs the vCPUs are pinned to
+    /// to the guest, instead of the synthetic cache topology
+    pub host_cache_topology: Option<bool>,
+
     #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -1554,6 +2457,25 @@ pub struct RunCommand {
     /// IP address to assign to host tap interface
     pub host_ip: Option<std::net::Ipv4Addr>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(
+        option,
+        arg_name = "addr=NUM,size=NUM,nodes=[NODE,...][,policy=POLICY]"
+    )]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = append)]
+    /// set the host NUMA memory policy of a range of guest
+    /// memory, e.g. to keep the memory of pinned vCPUs local.
+    /// Can be given multiple times.
+    /// Parameters (addr, size, nodes are required):
+    ///     addr=NUM - guest physical address of the range
+    ///     size=NUM - size in bytes of the range
+    ///     nodes=[NODE,...] - host NUMA nodes
+    ///     policy=(bind|interleave|preferred) - allocate only
+    ///        from the nodes, interleave across them or prefer
+    ///        the first one. (default: bind)
+    pub host_numa_bind: Vec<HostNumaBinding>,
+
     #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -1584,12 +2506,14 @@ pub struct RunCommand {
     /// TYPE is an input device type, and OPTIONS are key=value
     /// pairs specific to the device type:
     ///     evdev[path=PATH]
+    ///     evdev-hotplug[name=N,vendor=V,product=P]
     ///     keyboard[path=PATH]
     ///     mouse[path=PATH]
-    ///     multi-touch[path=PATH,width=W,height=H,name=N]
+    ///     multi-touch[path=PATH,width=W,height=H,slots=S,name=N]
     ///     rotary[path=PATH]
     ///     single-touch[path=PATH,width=W,height=H,name=N]
     ///     switches[path=PATH]
+    ///     tablet[path=PATH,width=W,height=H,name=N]
     ///     trackpad[path=PATH,width=W,height=H,name=N]
     ///     multi-touch-trackpad[path=PATH,width=W,height=H,name=N]
     /// See <https://crosvm.dev/book/devices/input.html> for more
@@ -1601,6 +2525,25 @@ pub struct RunCommand {
     /// type of interrupt controller emulation. "split" is only available for x86 KVM.
     pub irqchip: Option<IrqChipKind>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// time level-triggered interrupts from their signal by the device to their EOI by the guest.
+    /// See `crosvm stats irq` for the histograms
+    pub irq_latency_stats: Option<bool>,
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// run each virtio device that would otherwise run in the main
+    /// process (e.g. with --disable-sandbox) in a child process of
+    /// its own, connected through a proxy device and confined by the
+    /// seccomp policy of the device. Only block, net, rng, console,
+    /// input, balloon, pmem, 9p and fs devices support this.
+    pub isolate_virtio_devices: Option<bool>,
+
     #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -1608,6 +2551,23 @@ pub struct RunCommand {
     /// ACPI CPPC support on hardware
     pub itmt: Option<bool>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "path=PATH,size=NUM[,doorbell=PATH,id=NUM]")]
+    #[serde(default)]
+    #[merge(strategy = append)]
+    /// add an ivshmem device exposing a host file as shared
+    /// memory. Can be given more than once.
+    /// Possible key values:
+    ///     path=PATH - file backing the shared memory, e.g. under
+    ///         /dev/shm. It is created if it does not exist.
+    ///     size=NUM - size of the shared memory in bytes. Must be
+    ///         a power of two.
+    ///     doorbell=PATH - path prefix of the doorbell sockets
+    ///         shared by the two VMs. Peer N listens on PATH.N.
+    ///         (default: no doorbell)
+    ///     id=NUM - peer ID of this VM, 0 or 1. (default: 0)
+    pub ivshmem: Vec<IvshmemParameters>,
+
     #[argh(positional, arg_name = "KERNEL")]
     #[merge(strategy = overwrite_option)]
     /// bzImage of kernel to run
@@ -1626,6 +2586,15 @@ pub struct RunCommand {
     /// path to a socket from where to read keyboard input events and write status updates to
     pub keyboard: Vec<PathBuf>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// let KSM merge identical pages of guest memory. The kernel only merges privately mapped
+    /// pages, which are the ones written after a restore with `--restore-shared-memory`, so this
+    /// requires `--restore-shared-memory`. See `crosvm stats ksm` for the savings
+    pub ksm: Option<bool>,
+
     #[cfg(any(target_os = "android", target_os = "linux"))]
     #[argh(option, arg_name = "PATH")]
     #[serde(skip)] // Deprecated - use `hypervisor` instead.
@@ -1683,8 +2652,30 @@ pub struct RunCommand {
     /// memory parameters.
     /// Possible key values:
     ///     size=NUM - amount of guest memory in MiB. (default: 256)
+    ///     hugetlb[=BOOL] - back guest RAM with sealed memfds
+    ///         allocated from huge pages. (Linux only, default:
+    ///         false)
+    ///     hugetlb-page-size=NUM - size in KiB of the huge pages
+    ///         used with `hugetlb`. (default: the host's default
+    ///         huge page size)
     pub mem: Option<MemOptions>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "ADDR:PORT")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// serve metrics in the Prometheus text format on
+    /// http://ADDR:PORT/metrics.
+    pub metrics_address: Option<SocketAddr>,
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "tcp://ADDR:PORT")]
+    #[serde(skip)]
+    #[merge(strategy = overwrite_option)]
+    /// wait for a VM to be migrated from another crosvm instance with `crosvm migrate send`
+    /// instead of booting. The VM configuration must match the one of the source.
+    pub migrate_receive: Option<String>,
+
     #[argh(option, from_str_fn(parse_mmio_address_range))]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -1787,6 +2778,23 @@ pub struct RunCommand {
     /// netmask for VM subnet
     pub netmask: Option<std::net::Ipv4Addr>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// file in which `crosvm params set-next-boot` saves the kernel command line to use
+    /// instead of `--params` and `--params-file` the next time the VM boots with this option.
+    /// The file is removed once used, so the override only applies to a single boot.
+    pub next_boot_params_file: Option<PathBuf>,
+
+    #[cfg(target_arch = "x86_64")]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// don't let the guest use KVM asynchronous page faults, which keep vCPUs running other tasks
+    /// while the host faults in guest memory, e.g. from vmm-swap or after a restore
+    pub no_async_pf: Option<bool>,
+
     #[cfg(feature = "balloon")]
     #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
@@ -1846,6 +2854,13 @@ pub struct RunCommand {
     /// extra kernel or plugin command line arguments. Can be given more than once
     pub params: Vec<String>,
 
+    #[argh(option, arg_name = "PATH")]
+    #[serde(default)]
+    #[merge(strategy = append)]
+    /// file with extra kernel command line arguments, appended after `--params`. Blank lines
+    /// and lines starting with `#` are ignored. Can be given more than once
+    pub params_file: Vec<PathBuf>,
+
     #[argh(option)]
     #[serde(default)]
     #[merge(strategy = overwrite_option)]
@@ -1862,6 +2877,9 @@ pub struct RunCommand {
     /// Possible key values (x86_64 only):
     ///     ecam=[start=INT,size=INT] - region for PCIe Enhanced
     ///         Configuration Access Mechanism
+    ///     segments=NUM - number of PCI segments (domains), each
+    ///         with its own host bridge and bus number space
+    ///         (default: 1)
     pub pci: Option<PciConfig>,
 
     #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -1888,14 +2906,16 @@ pub struct RunCommand {
 
     #[argh(
         option,
-        arg_name = "path=PATH,[block_size=SIZE]",
+        arg_name = "path=PATH,[block_size=SIZE],[template=PATH]",
         from_str_fn(parse_pflash_parameters)
     )]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
     /// comma-seperated key-value pair for setting up the pflash device, which provides space to
-    /// store UEFI variables. block_size defaults to 4K.
-    /// [--pflash <path=PATH,[block_size=SIZE]>]
+    /// store UEFI variables. block_size defaults to 4K. If `template` (e.g. OVMF_VARS.fd) is
+    /// given and `path` doesn't exist, `path` is created as a copy of it. The image is locked
+    /// while the VM runs and is included in snapshots.
+    /// [--pflash <path=PATH,[block_size=SIZE],[template=PATH]>]
     pub pflash: Option<PflashParameters>,
 
     #[argh(option, arg_name = "PATH")]
@@ -2012,6 +3032,16 @@ pub struct RunCommand {
     ///       (default: "0 <current egid> 1")
     pub pmem_ext2: Vec<PmemExt2Option>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = append)]
+    /// program to run once the VM is stopped by `crosvm powerdown`,
+    /// with CROSVM_POWERDOWN set to "graceful" if the guest shut
+    /// down or "forced" if it was stopped after the timeout. Can be
+    /// given more than once, the programs run in order.
+    pub powerdown_hook: Vec<PathBuf>,
+
     #[cfg(feature = "process-invariants")]
     #[argh(option, arg_name = "PATH")]
     #[serde(skip)] // TODO(b/255223604)
@@ -2066,13 +3096,27 @@ pub struct RunCommand {
     /// (EXPERIMENTAL) prevent host access to guest memory, but don't use protected VM firmware
     protected_vm_without_firmware: Option<bool>,
 
-    #[argh(option, arg_name = "path=PATH,size=SIZE")]
+    #[argh(option, arg_name = "path=PATH,size=SIZE[,rotate=N]")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
     /// path to pstore buffer backend file followed by size
-    ///     [--pstore <path=PATH,size=SIZE>]
+    ///     [--pstore <path=PATH,size=SIZE[,rotate=N]>]
+    /// Possible key values:
+    ///     path=PATH - path of the file backing the buffer
+    ///     size=SIZE - size of the buffer in bytes
+    ///     rotate=N - (default: 0) number of copies of the
+    ///        records left by previous boots to keep, as
+    ///        PATH.1 (most recent) to PATH.N
     pub pstore: Option<Pstore>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, long = "pvpanic-core-dump", arg_name = "DIR")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// write an ELF core of the guest into DIR when it reports a panic through the pvpanic
+    /// device
+    pub pvpanic_core_dump_dir: Option<PathBuf>,
+
     #[cfg(feature = "pvclock")]
     #[argh(switch)]
     #[serde(skip)] // TODO(b/255223604)
@@ -2081,12 +3125,38 @@ pub struct RunCommand {
     /// Only available when crosvm is built with feature 'pvclock'.
     pub pvclock: Option<bool>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, long = "qmp", arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// path to put a QMP (QEMU Machine Protocol) control socket.
+    /// Only a subset of the QMP commands is supported.
+    pub qmp_socket_path: Option<PathBuf>,
+
     #[argh(option, long = "restore", arg_name = "PATH")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
-    /// path of the snapshot that is used to restore the VM on startup.
+    /// path of the snapshot that is used to restore the VM on startup, either a snapshot
+    /// directory or a file written by `crosvm snapshot take --stream`.
     pub restore: Option<PathBuf>,
 
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)]
+    #[merge(strategy = overwrite_option)]
+    /// file holding the key of a snapshot taken with `crosvm snapshot take --key-file`, as raw
+    /// bytes or hexadecimal digits.
+    pub restore_key_file: Option<PathBuf>,
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(switch)]
+    #[serde(skip)]
+    #[merge(strategy = overwrite_option)]
+    /// map guest memory from the snapshot given to `--restore` instead of copying it, so that VMs
+    /// restored from the same snapshot share the memory they don't write. The snapshot must be a
+    /// directory with uncompressed, unencrypted memory, and must not be modified while VMs use
+    /// it. Requires `--disable-sandbox`.
+    pub restore_shared_memory: Option<bool>,
+
     #[argh(option, arg_name = "PATH[,key=value[,key=value[,...]]]", short = 'r')]
     #[serde(skip)] // Deprecated - use `block` instead.
     #[merge(strategy = overwrite_option)]
@@ -2115,6 +3185,19 @@ pub struct RunCommand {
     /// comma-separated list of CPUs or CPU ranges to run VCPUs on. (e.g. 0,1-3,5) (default: none)
     pub rt_cpus: Option<CpuSet>,
 
+    #[cfg(target_arch = "x86_64")]
+    #[argh(option, arg_name = "[base=DATE][,offset=SECONDS]")]
+    #[serde(default)]
+    #[merge(strategy = overwrite_option)]
+    /// initial time of the emulated RTC (default: the host time).
+    /// Possible key values:
+    ///     base=DATE - RFC 3339 date the RTC starts at when the VM
+    ///       boots (e.g. 2000-01-01T00:00:00Z).
+    ///     offset=SECONDS - offset of the RTC from the host time.
+    /// The offset is kept across snapshot/restore and can be
+    /// changed with `crosvm rtc set-offset`. x86_64 only
+    pub rtc: Option<RtcOptions>,
+
     #[argh(option, arg_name = "PATH")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = append)]
@@ -2162,6 +3245,20 @@ pub struct RunCommand {
     /// routines to perform full guest suspension/resumption
     pub s2idle: Option<bool>,
 
+    #[argh(switch)]
+    #[merge(strategy = overwrite_option)]
+    /// advertise the ACPI S3 (suspend-to-RAM) sleep state to the guest.
+    /// Wake events (power button, RTC alarm, GPEs) resume the guest
+    /// through its firmware waking vector. x86_64 only
+    pub s3: Option<bool>,
+
+    #[argh(switch)]
+    #[merge(strategy = overwrite_option)]
+    /// put all devices to sleep while the guest is in S3, releasing the
+    /// host resources they hold (e.g. audio streams). RTC
+    /// alarms can't wake the guest in this mode. Implies --s3
+    pub s3_release_devices: Option<bool>,
+
     #[argh(option, arg_name = "PATH[,key=value[,key=value[,...]]]")]
     #[serde(default)]
     #[merge(strategy = append)]
@@ -2194,6 +3291,19 @@ pub struct RunCommand {
     /// path to seccomp .policy files
     pub seccomp_policy_dir: Option<PathBuf>,
 
+    #[argh(
+        option,
+        arg_name = "pk=PATH,[kek=[PATH,...]],[db=[PATH,...]]",
+        from_str_fn(parse_secure_boot_parameters)
+    )]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// DER-encoded X.509 certificates to enroll as the Secure Boot platform key, key exchange
+    /// keys and signature database in the `--pflash` varstore. The keys are only enrolled if
+    /// the varstore has no platform key yet, i.e. on the first boot.
+    /// [--secure-boot <pk=PATH,[kek=[PATH,...]],[db=[PATH,...]]>]
+    pub secure_boot: Option<SecureBootParameters>,
+
     #[argh(
         option,
         arg_name = "type=TYPE,[hardware=HW,name=NAME,num=NUM,path=PATH,input=PATH,console,earlycon,stdin,pci-address=ADDR]",
@@ -2207,7 +3317,8 @@ pub struct RunCommand {
     ///     type=(stdout,syslog,sink,file) - Where to route the
     ///        serial device.
     ///        Platform-specific options:
-    ///        On Unix: 'unix' (datagram) and 'unix-stream' (stream)
+    ///        On Unix: 'unix' (datagram), 'unix-stream' (stream),
+    ///        'tcp' and 'log-file'
     ///        On Windows: 'namedpipe'
     ///     hardware=(serial,virtio-console,debugcon) - Which type of
     ///        serial hardware to emulate. Defaults to 8250 UART
@@ -2220,7 +3331,9 @@ pub struct RunCommand {
     ///        listen to. Defaults to 0x402, which is what OVMF
     ///        expects.
     ///     path=PATH - The path to the file to write to when
-    ///        type=file
+    ///        type=file or type=log-file. With type=log-file, each
+    ///        line is prefixed with the wall-clock time and the
+    ///        seconds since the VM started.
     ///     input=PATH - The path to the file to read from when not
     ///        stdin
     ///     input-unix-stream - (Unix-only) Whether to use the given
@@ -2228,6 +3341,20 @@ pub struct RunCommand {
     ///        This flag is only valid when type=unix-stream and
     ///        the socket path is specified with path=.
     ///        Can't be passed when input is specified.
+    ///     addr=HOST:PORT - (Unix-only) Address to connect to when
+    ///        type=tcp. Input and output are relayed over the
+    ///        connection, which is retried until it succeeds and
+    ///        re-established if it drops.
+    ///     listen - (Unix-only) With type=tcp, listen on addr and
+    ///        accept one client at a time instead of connecting.
+    ///     telnet - (Unix-only) With type=tcp, speak the telnet
+    ///        protocol on the connection.
+    ///     rotate-size=BYTES - (Unix-only) With type=log-file,
+    ///        rotate the log once its size reaches BYTES bytes.
+    ///        Rotated logs are named PATH.1, PATH.2, etc. By
+    ///        default the log is never rotated.
+    ///     rotate-count=NUM - (Unix-only) Number of rotated logs
+    ///        to keep when type=log-file. Defaults to 5.
     ///     console - Use this serial device as the guest console.
     ///        Will default to first serial port if not provided.
     ///     earlycon - Use this serial device as the early console.
@@ -2238,6 +3365,10 @@ pub struct RunCommand {
     ///     pci-address - Preferred PCI address, e.g. "00:01.0".
     ///     max-queue-sizes=[uint,uint] - Max size of each virtio
     ///        queue. Only applicable when hardware=virtio-console.
+    ///     max-ports=NUM - (Unix-only) Enable multiport with room
+    ///        for NUM ports, so that ports can be added at runtime
+    ///        with `crosvm console add-port`. Only applicable when
+    ///        hardware=virtio-console.
     pub serial: Vec<SerialParameters>,
 
     #[cfg(windows)]
@@ -2381,6 +3512,18 @@ pub struct RunCommand {
     ///     oem-strings=[...] - Free-form OEM strings (SMBIOS type 11).
     pub smbios: Option<SmbiosOptions>,
 
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// emulate an Arm SMMUv3 instead of a virtio-iommu for the virtio devices translated by an
+    /// IOMMU. The SMMU is not backed by the host IOMMU, so VFIO devices with iommu=viommu and
+    /// --vfio-isolate-hotplug are not supported
+    pub smmuv3: Option<bool>,
+
     #[argh(option, short = 's', arg_name = "PATH")]
     #[merge(strategy = overwrite_option)]
     /// path to put the control socket. If PATH is a directory, a name will be generated
@@ -2400,6 +3543,19 @@ pub struct RunCommand {
     /// (EXPERIMENTAL) enable split-irqchip support
     pub split_irqchip: Option<bool>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "devices=[PATH,...][,max-freq-hz=NUM]")]
+    #[serde(default)]
+    #[merge(strategy = append)]
+    /// add a virtio-spi controller proxying transfers to host
+    /// spidev nodes. Can be given more than once.
+    /// Possible key values:
+    ///     devices=[PATH,...] - spidev nodes, one per chip select,
+    ///         e.g. [/dev/spidev0.0,/dev/spidev0.1].
+    ///     max-freq-hz=NUM - maximum clock frequency reported to
+    ///         the guest. (default: no limit)
+    pub spi: Vec<SpiParameters>,
+
     #[argh(
         option,
         arg_name = "DOMAIN:BUS:DEVICE.FUNCTION[,vendor=NUM][,device=NUM][,class=NUM][,subsystem_vendor=NUM][,subsystem_device=NUM][,revision=NUM]"
@@ -2425,6 +3581,13 @@ pub struct RunCommand {
     /// start a VM with vCPUs and devices suspended
     pub suspended: Option<bool>,
 
+    #[argh(option, arg_name = "LEVEL")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// compress the pages in the vmm-swap file with zstd at the
+    /// given level. requires `--swap`.
+    pub swap_compression_level: Option<i32>,
+
     #[argh(option, long = "swap", arg_name = "PATH")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -2432,6 +3595,15 @@ pub struct RunCommand {
     /// specified directory.
     pub swap_dir: Option<PathBuf>,
 
+    #[argh(option, arg_name = "PATH")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// enable, trim, swap out and disable vmm-swap automatically
+    /// following the JSON policy file at PATH, based on the host
+    /// memory pressure (PSI) and the guest idleness. requires
+    /// `--swap`.
+    pub swap_policy: Option<PathBuf>,
+
     #[argh(option, arg_name = "N")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -2445,12 +3617,33 @@ pub struct RunCommand {
     /// path to a socket from where to read switch input events and write status updates to
     pub switches: Vec<PathBuf>,
 
+    #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+    #[argh(option, arg_name = "socket=PATH[,ctrl=PATH][,interface=virtio|crb]")]
+    #[serde(default)]
+    #[merge(strategy = overwrite_option)]
+    /// add a TPM backed by an swtpm instance.
+    ///     socket=PATH - path to the swtpm data channel socket.
+    ///     ctrl=PATH - path to the swtpm control channel socket.
+    ///         Required to include the TPM state in snapshots.
+    ///     interface=virtio|crb - expose the TPM as a virtio-tpm
+    ///         device (default) or as a TPM 2.0 CRB interface
+    ///         described in ACPI (x86_64 only).
+    pub swtpm: Option<SwtpmParameters>,
+
     #[argh(option, arg_name = "TAG")]
     #[serde(skip)] // Deprecated - use `CrosvmCmdlineArgs::syslog_tag` instead.
     #[merge(strategy = overwrite_option)]
     /// when logging to syslog, use the provided tag
     pub syslog_tag: Option<String>,
 
+    #[cfg(target_arch = "aarch64")]
+    #[argh(switch)]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// let the guest suspend itself with PSCI SYSTEM_SUSPEND. The suspended VM is resumed by
+    /// `crosvm resume` or activity on a virtio-input device
+    pub system_suspend: Option<bool>,
+
     #[cfg(any(target_os = "android", target_os = "linux"))]
     #[argh(option)]
     #[serde(skip)] // Deprecated - use `net` instead.
@@ -2515,6 +3708,21 @@ pub struct RunCommand {
     /// path to a V4L2 device to expose to the guest using the virtio-media protocol.
     pub v4l2_proxy: Vec<PathBuf>,
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[argh(option, arg_name = "[util=NUM][,decay-ms=NUM]")]
+    #[serde(skip)] // TODO(b/255223604)
+    #[merge(strategy = overwrite_option)]
+    /// raise the minimum utilization clamp of the vCPU threads whenever
+    /// an interrupt is delivered to the guest, then decay it. This
+    /// only covers interrupts routed through crosvm, e.g. with
+    /// --irqchip=split.
+    ///     util=NUM - utilization clamp while boosted, out of 1024.
+    ///         (default: 1024)
+    ///     decay-ms=NUM - how long the full boost lasts after the
+    ///         last interrupt; the clamp is then halved every
+    ///         period. (default: 4)
+    pub vcpu_boost: Option<VcpuBoostOptions>,
+
     #[argh(option, arg_name = "PATH")]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = overwrite_option)]
@@ -2524,7 +3732,7 @@ pub struct RunCommand {
     #[cfg(any(target_os = "android", target_os = "linux"))]
     #[argh(
         option,
-        arg_name = "PATH[,guest-address=<BUS:DEVICE.FUNCTION>][,iommu=viommu|coiommu|pkvm-iommu|off][,dt-symbol=<SYMBOL>]"
+        arg_name = "PATH[,guest-address=<BUS:DEVICE.FUNCTION>][,iommu=viommu|coiommu|pkvm-iommu|off][,dt-symbol=<SYMBOL>][,dt-from-host=BOOL][,p2p=BOOL]"
     )]
     #[serde(default)]
     #[merge(strategy = append)]
@@ -2538,6 +3746,13 @@ pub struct RunCommand {
     ///        to use for this device.
     ///     dt-symbol=<SYMBOL> - the symbol that labels the device tree
     ///        node in the device tree overlay file.
+    ///     dt-from-host=BOOL - generate the device tree node of a
+    ///        platform device from its node in the host device
+    ///        tree. Overlays can amend it through the dt-symbol
+    ///        label (default: false).
+    ///     p2p=BOOL - export the BARs of the device to the
+    ///        virtio-iommu, so that devices with iommu=viommu can
+    ///        DMA to them (default: false).
     pub vfio: Vec<VfioOption>,
 
     #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -2577,17 +3792,25 @@ pub struct RunCommand {
 
     #[argh(
         option,
-        arg_name = "[type=]TYPE,socket=SOCKET_PATH[,max-queue-size=NUM][,pci-address=ADDR]"
+        arg_name = "[type=]TYPE,socket=SOCKET_PATH[,max-queue-size=NUM][,pci-address=ADDR]\
+        [,protocol-features=MASK][,reconnect]"
     )]
     #[serde(default)]
     #[merge(strategy = append)]
     /// comma separated key=value pairs for connecting to a
     /// vhost-user backend.
     /// Possible key values:
-    ///     type=TYPE - Virtio device type (net, block, etc.)
+    ///     type=TYPE - Virtio device type (net, block, etc.) or
+    ///         numeric virtio device ID (1 to 63) for device types
+    ///         crosvm does not implement.
     ///     socket=SOCKET_PATH - Path to vhost-user socket.
     ///     max-queue-size=NUM - Limit maximum queue size (must be a power of two).
     ///     pci-address=ADDR - Preferred PCI address, e.g. "00:01.0".
+    ///     protocol-features=MASK - Mask of vhost-user protocol
+    ///         features that may be negotiated with the backend
+    ///         (default: those supported for TYPE).
+    ///     reconnect - Wait for the backend to be restarted if it
+    ///         disconnects instead of stopping the VM.
     pub vhost_user: Vec<VhostUserFrontendOption>,
 
     #[argh(option)]
@@ -2699,12 +3922,28 @@ pub struct RunCommand {
     /// enable the virtio-tpm connection to vtpm daemon
     pub vtpm_proxy: Option<bool>,
 
+    #[cfg(all(
+        any(target_os = "android", target_os = "linux"),
+        feature = "wasm-devices"
+    ))]
+    #[argh(option, arg_name = "path=PATH")]
+    #[serde(default)]
+    #[merge(strategy = append)]
+    /// add a virtio device implemented by a WebAssembly module.
+    /// Can be given more than once.
+    /// Possible key values:
+    ///     path=PATH - path to the WebAssembly module.
+    /// Only available when crosvm is built with feature
+    /// 'wasm-devices'.
+    pub wasm_device: Vec<WasmDeviceParameters>,
+
     #[cfg(any(target_os = "android", target_os = "linux"))]
     #[argh(option, arg_name = "PATH[,name=NAME]", from_str_fn(parse_wayland_sock))]
     #[serde(skip)] // TODO(b/255223604)
     #[merge(strategy = append)]
     /// path to the Wayland socket to use. The unnamed one is used for displaying virtual screens.
-    /// Named ones are only for IPC
+    /// Named ones are for IPC, and are used instead of the unnamed one by the cross-domain
+    /// contexts of the same name, e.g. to connect each seat to its own compositor
     pub wayland_sock: Vec<(String, PathBuf)>,
 
     #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -2776,6 +4015,10 @@ impl TryFrom<RunCommand> for super::config::Config {
         }
 
         cfg.android_fstab = cmd.android_fstab;
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.api_socket_path = cmd.api_socket;
+        }
 
         cfg.async_executor = cmd.async_executor;
 
@@ -2785,6 +4028,13 @@ impl TryFrom<RunCommand> for super::config::Config {
         }
 
         cfg.params.extend(cmd.params);
+        for path in &cmd.params_file {
+            cfg.params.push(read_params_file(path)?);
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.next_boot_params_file = cmd.next_boot_params_file;
+        }
 
         cfg.core_scheduling = cmd.core_scheduling;
         cfg.per_vm_core_scheduling = cmd.per_vm_core_scheduling.unwrap_or_default();
@@ -2793,6 +4043,11 @@ impl TryFrom<RunCommand> for super::config::Config {
         {
             let cpus = cmd.cpus.unwrap_or_default();
             cfg.vcpu_count = cpus.num_cores;
+            #[cfg(target_arch = "aarch64")]
+            if let Some(max_cores) = cpus.max_cores {
+                cfg.vcpu_count = Some(max_cores);
+                cfg.boot_vcpu_count = Some(cpus.num_cores.unwrap_or(1));
+            }
             cfg.boot_cpu = cpus.boot_cpu.unwrap_or_default();
             cfg.cpu_freq_domains = cpus.freq_domains;
 
@@ -2864,6 +4119,11 @@ impl TryFrom<RunCommand> for super::config::Config {
         }
 
         cfg.vcpu_cgroup_path = cmd.vcpu_cgroup_path;
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.vcpu_boost = cmd.vcpu_boost;
+            cfg.halt_poll_ns = cmd.halt_poll_ns;
+        }
 
         cfg.no_smt = cmd.no_smt.unwrap_or_default();
 
@@ -2875,6 +4135,21 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         let mem = cmd.mem.unwrap_or_default();
         cfg.memory = mem.size;
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            if let Some(page_size) = mem.hugetlb_page_size {
+                if !mem.hugetlb {
+                    return Err("`hugetlb-page-size` requires `hugetlb`".to_string());
+                }
+                if !page_size.is_power_of_two() {
+                    return Err(format!(
+                        "invalid huge page size {page_size}KiB: must be a power of two"
+                    ));
+                }
+            }
+            cfg.hugetlb = mem.hugetlb;
+            cfg.hugetlb_page_size = mem.hugetlb_page_size.map(|kib| kib * 1024);
+        }
 
         #[cfg(target_arch = "aarch64")]
         {
@@ -2891,10 +4166,24 @@ impl TryFrom<RunCommand> for super::config::Config {
             cfg.mte = cmd.mte.unwrap_or_default();
             cfg.no_pmu = cmd.no_pmu.unwrap_or_default();
             cfg.swiotlb = cmd.swiotlb;
+            cfg.system_suspend = cmd.system_suspend.unwrap_or_default();
         }
 
         cfg.hugepages = cmd.hugepages.unwrap_or_default();
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            for binding in &cmd.host_numa_bind {
+                if binding.nodes.is_empty() {
+                    return Err("`host-numa-bind` requires at least one node".to_string());
+                }
+                if binding.size == 0 {
+                    return Err("`host-numa-bind` requires a non-zero size".to_string());
+                }
+            }
+            cfg.host_numa_bindings = cmd.host_numa_bind;
+        }
+
         // `cfg.hypervisor` may have been set by the deprecated `--kvm-device` option above.
         // TODO(b/274817652): remove this workaround when `--kvm-device` is removed.
         if cfg.hypervisor.is_none() {
@@ -2903,6 +4192,7 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         #[cfg(any(target_os = "android", target_os = "linux"))]
         {
+            cfg.ksm = cmd.ksm.unwrap_or_default();
             cfg.lock_guest_memory = cmd.lock_guest_memory.unwrap_or_default();
             cfg.lock_guest_memory_dontneed = cmd.lock_guest_memory_dontneed.unwrap_or_default();
             cfg.boost_uclamp = cmd.boost_uclamp.unwrap_or_default();
@@ -3080,6 +4370,7 @@ impl TryFrom<RunCommand> for super::config::Config {
                 cfg.crash_pipe_name = cmd.crash_pipe_name;
             }
             cfg.product_name = cmd.product_name;
+            cfg.app_container = cmd.app_container.unwrap_or_default();
             cfg.exit_stats = cmd.exit_stats.unwrap_or_default();
             cfg.host_guid = cmd.host_guid;
             cfg.kernel_log_file = cmd.kernel_log_file;
@@ -3104,8 +4395,18 @@ impl TryFrom<RunCommand> for super::config::Config {
         }
         cfg.pstore = cmd.pstore;
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.powerdown_hooks = cmd.powerdown_hook;
+            cfg.pvpanic_core_dump_dir = cmd.pvpanic_core_dump_dir;
+        }
+
         cfg.enable_fw_cfg = cmd.enable_fw_cfg.unwrap_or_default();
         cfg.fw_cfg_parameters = cmd.fw_cfg;
+        #[cfg(target_arch = "x86_64")]
+        {
+            cfg.fw_cfg_kernel = cmd.fw_cfg_kernel;
+        }
 
         #[cfg(any(target_os = "android", target_os = "linux"))]
         for (name, params) in cmd.wayland_sock {
@@ -3122,9 +4423,46 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         cfg.display_window_keyboard = cmd.display_window_keyboard.unwrap_or_default();
         cfg.display_window_mouse = cmd.display_window_mouse.unwrap_or_default();
+        cfg.display_window_tablet = cmd.display_window_tablet.unwrap_or_default();
 
+        cfg.swap_compression_level = cmd.swap_compression_level;
         cfg.swap_dir = cmd.swap_dir;
+        cfg.swap_policy = cmd.swap_policy;
+        #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+        {
+            cfg.swtpm = cmd.swtpm;
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.qmp_socket_path = cmd.qmp_socket_path;
+        }
         cfg.restore_path = cmd.restore;
+        cfg.restore_key_file = cmd.restore_key_file;
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.restore_shared_memory = cmd.restore_shared_memory.unwrap_or_default();
+        }
+        if cfg.restore_key_file.is_some() {
+            if cfg.restore_path.is_none() {
+                return Err("`--restore-key-file` requires `--restore`".to_string());
+            }
+            if !cfg!(feature = "snapshot-encryption") {
+                return Err(
+                    "`--restore-key-file` requires the `snapshot-encryption` feature".to_string(),
+                );
+            }
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.metrics_address = cmd.metrics_address;
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.migrate_receive = cmd.migrate_receive;
+            if cfg.restore_path.is_some() && cfg.migrate_receive.is_some() {
+                return Err("cannot use `--restore` and `--migrate-receive` together".to_string());
+            }
+        }
         cfg.suspended = cmd.suspended.unwrap_or_default();
 
         if let Some(mut socket_path) = cmd.socket {
@@ -3250,6 +4588,7 @@ impl TryFrom<RunCommand> for super::config::Config {
                             path: touch.path,
                             width: touch.width,
                             height: touch.height,
+                            slots: None,
                             name: touch.name,
                         }),
                 );
@@ -3316,6 +4655,10 @@ impl TryFrom<RunCommand> for super::config::Config {
         }
 
         cfg.irq_chip = cmd.irqchip;
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.irq_latency_stats = cmd.irq_latency_stats.unwrap_or_default();
+        }
 
         #[cfg(target_arch = "x86_64")]
         if cmd.split_irqchip.unwrap_or_default() {
@@ -3339,6 +4682,7 @@ impl TryFrom<RunCommand> for super::config::Config {
             cfg.executable_path = Some(Executable::Bios(p));
         }
         cfg.pflash_parameters = cmd.pflash;
+        cfg.secure_boot = cmd.secure_boot;
 
         #[cfg(feature = "video-decoder")]
         {
@@ -3530,6 +4874,19 @@ impl TryFrom<RunCommand> for super::config::Config {
         {
             cfg.shared_dirs = cmd.shared_dir;
 
+            cfg.can = cmd.can;
+
+            cfg.extensions = cmd.extension;
+
+            cfg.spi = cmd.spi;
+
+            #[cfg(feature = "wasm-devices")]
+            {
+                cfg.wasm_devices = cmd.wasm_device;
+            }
+
+            cfg.ivshmem = cmd.ivshmem;
+
             cfg.coiommu_param = cmd.coiommu;
 
             #[cfg(all(feature = "gpu", feature = "virgl_renderer"))]
@@ -3612,6 +4969,13 @@ impl TryFrom<RunCommand> for super::config::Config {
             cfg.gdb = cmd.gdb;
         }
 
+        #[cfg(all(
+            any(target_os = "android", target_os = "linux"),
+            any(target_arch = "aarch64", target_arch = "x86_64")
+        ))]
+        {
+            cfg.host_cache_topology = cmd.host_cache_topology.unwrap_or_default();
+        }
         cfg.host_cpu_topology = cmd.host_cpu_topology.unwrap_or_default();
 
         cfg.pci_config = cmd.pci.unwrap_or_default();
@@ -3621,8 +4985,15 @@ impl TryFrom<RunCommand> for super::config::Config {
             cfg.break_linux_pci_config_io = cmd.break_linux_pci_config_io.unwrap_or_default();
             cfg.enable_hwp = cmd.enable_hwp.unwrap_or_default();
             cfg.force_s2idle = cmd.s2idle.unwrap_or_default();
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            {
+                cfg.s3_release_devices = cmd.s3_release_devices.unwrap_or_default();
+                cfg.s3 = cmd.s3.unwrap_or_default() || cfg.s3_release_devices;
+            }
+            cfg.no_async_pf = cmd.no_async_pf.unwrap_or_default();
             cfg.no_i8042 = cmd.no_i8042.unwrap_or_default();
             cfg.no_rtc = cmd.no_rtc.unwrap_or_default();
+            cfg.rtc = cmd.rtc.unwrap_or_default();
             cfg.smbios = cmd.smbios.unwrap_or_default();
 
             if let Some(pci_start) = cmd.pci_start {
@@ -3657,6 +5028,11 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         cfg.dump_device_tree_blob = cmd.dump_device_tree_blob;
 
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            cfg.isolate_virtio_devices = cmd.isolate_virtio_devices.unwrap_or_default();
+        }
+
         cfg.itmt = cmd.itmt.unwrap_or_default();
 
         #[cfg(target_arch = "x86_64")]
@@ -3664,6 +5040,11 @@ impl TryFrom<RunCommand> for super::config::Config {
             cfg.force_calibrated_tsc_leaf = cmd.force_calibrated_tsc_leaf.unwrap_or_default();
         }
 
+        #[cfg(all(target_arch = "x86_64", unix))]
+        if let Some(path) = &cmd.cpuid_overrides {
+            (cfg.cpuid_overrides, cfg.hidden_msrs) = load_cpuid_overrides(path)?;
+        }
+
         cfg.stub_pci_devices = cmd.stub_pci_device;
 
         cfg.fdt_position = cmd.fdt_position;
@@ -3705,6 +5086,14 @@ impl TryFrom<RunCommand> for super::config::Config {
             cfg.vfio_isolate_hotplug = cmd.vfio_isolate_hotplug.unwrap_or_default();
         }
 
+        #[cfg(all(
+            target_arch = "aarch64",
+            any(target_os = "android", target_os = "linux")
+        ))]
+        {
+            cfg.smmuv3 = cmd.smmuv3.unwrap_or_default();
+        }
+
         cfg.device_tree_overlay = cmd.device_tree_overlay;
         #[cfg(any(target_os = "android", target_os = "linux"))]
         {
@@ -3757,20 +5146,29 @@ mod tests {
     #[cfg(feature = "config-file")]
     fn merge_runcommands() {
         let cmd2 = RunCommand {
-            mem: Some(MemOptions { size: Some(4096) }),
+            mem: Some(MemOptions {
+                size: Some(4096),
+                ..Default::default()
+            }),
             kernel: Some("/path/to/kernel".into()),
             params: vec!["firstparam".into()],
             ..Default::default()
         };
 
         let cmd3 = RunCommand {
-            mem: Some(MemOptions { size: Some(8192) }),
+            mem: Some(MemOptions {
+                size: Some(8192),
+                ..Default::default()
+            }),
             params: vec!["secondparam".into()],
             ..Default::default()
         };
 
         let cmd1 = RunCommand {
-            mem: Some(MemOptions { size: Some(2048) }),
+            mem: Some(MemOptions {
+                size: Some(2048),
+                ..Default::default()
+            }),
             params: vec!["thirdparam".into(), "fourthparam".into()],
             cfg: vec![cmd2, cmd3],
             ..Default::default()
@@ -3778,7 +5176,13 @@ mod tests {
 
         let merged_cmd = cmd1.squash();
 
-        assert_eq!(merged_cmd.mem, Some(MemOptions { size: Some(2048) }));
+        assert_eq!(
+            merged_cmd.mem,
+            Some(MemOptions {
+                size: Some(2048),
+                ..Default::default()
+            })
+        );
         assert_eq!(merged_cmd.kernel, Some("/path/to/kernel".into()));
         assert_eq!(
             merged_cmd.params,
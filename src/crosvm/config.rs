@@ -7,6 +7,11 @@ use std::arch::x86_64::__cpuid;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__cpuid_count;
 use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::net::SocketAddr;
+use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
@@ -23,9 +28,13 @@ use arch::SveConfig;
 use arch::VcpuAffinity;
 use base::debug;
 use base::pagesize;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use base::NumaPolicy;
 use cros_async::ExecutorKind;
 use devices::serial_device::SerialHardware;
 use devices::serial_device::SerialParameters;
+#[cfg(unix)]
+use devices::serial_device::SerialType;
 use devices::virtio::block::DiskOption;
 #[cfg(any(feature = "video-decoder", feature = "video-encoder"))]
 use devices::virtio::device_constants::video::VideoDeviceConfig;
@@ -45,15 +54,33 @@ use devices::virtio::vhost::user::device::gpu::sys::windows::WindowProcedureThre
 #[cfg(all(windows, feature = "audio"))]
 use devices::virtio::vhost::user::device::snd::sys::windows::SndSplitConfig;
 use devices::virtio::vsock::VsockConfig;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use devices::virtio::CanParameters;
 use devices::virtio::DeviceType;
 #[cfg(feature = "net")]
 use devices::virtio::NetParameters;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use devices::virtio::SpiParameters;
+#[cfg(all(
+    any(target_os = "android", target_os = "linux"),
+    feature = "wasm-devices"
+))]
+use devices::virtio::WasmDeviceParameters;
 use devices::FwCfgParameters;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use devices::IvshmemParameters;
 use devices::PciAddress;
 use devices::PflashParameters;
+use devices::SecureBootParameters;
 use devices::StubPciParameters;
+#[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+use devices::SwtpmParameters;
 #[cfg(target_arch = "x86_64")]
 use hypervisor::CpuHybridType;
+#[cfg(all(target_arch = "x86_64", unix))]
+use hypervisor::CpuIdOverride;
+#[cfg(all(target_arch = "x86_64", unix))]
+use hypervisor::CpuIdRegister;
 use hypervisor::ProtectionType;
 use jail::JailConfig;
 use resources::AddressRange;
@@ -128,6 +155,11 @@ pub struct CpuOptions {
     /// Number of CPU cores.
     #[serde(default)]
     pub num_cores: Option<usize>,
+    /// Maximum number of CPU cores. The cores beyond `num_cores` are parked at boot and can be
+    /// brought online at runtime.
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub max_cores: Option<usize>,
     /// Vector of CPU ids to be grouped into the same cluster.
     #[serde(default)]
     pub clusters: Vec<CpuSet>,
@@ -162,6 +194,62 @@ pub struct MemOptions {
     /// Amount of guest memory in MiB.
     #[serde(default)]
     pub size: Option<u64>,
+    /// Back guest RAM with sealed memfds allocated from huge pages (`MFD_HUGETLB`).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[serde(default)]
+    pub hugetlb: bool,
+    /// Size in KiB of the huge pages backing guest RAM. Defaults to the host's default huge page
+    /// size.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[serde(default)]
+    pub hugetlb_page_size: Option<u64>,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn default_host_numa_policy() -> NumaPolicy {
+    NumaPolicy::Bind
+}
+
+/// Host NUMA memory policy of a range of guest memory.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Clone, Debug, Deserialize, Serialize, FromKeyValues, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HostNumaBinding {
+    /// Guest physical address of the start of the range.
+    #[serde(rename = "addr")]
+    pub address: u64,
+    /// Size of the range in bytes.
+    pub size: u64,
+    /// Host NUMA nodes the policy applies to.
+    pub nodes: Vec<u32>,
+    /// Memory policy of the range.
+    #[serde(default = "default_host_numa_policy")]
+    pub policy: NumaPolicy,
+}
+
+/// Initial time of the emulated RTC. By default it follows the host time.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, FromKeyValues, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RtcOptions {
+    /// RFC 3339 date the RTC starts at when the VM boots.
+    #[serde(default)]
+    pub base: Option<String>,
+    /// Offset in seconds of the RTC from the host time.
+    #[serde(default)]
+    pub offset: Option<i64>,
+}
+
+impl RtcOptions {
+    /// Returns the offset of the RTC from the host time at boot.
+    pub fn initial_offset(&self) -> Result<i64, String> {
+        match (&self.base, self.offset) {
+            (Some(_), Some(_)) => Err("`base` and `offset` are mutually exclusive".to_string()),
+            (Some(base), None) => {
+                devices::cmos::rtc_offset_from_base(base).map_err(|e| format!("{:#}", e))
+            }
+            (None, offset) => Ok(offset.unwrap_or_default()),
+        }
+    }
 }
 
 fn deserialize_swap_interval<'de, D: Deserializer<'de>>(
@@ -203,7 +291,7 @@ pub struct PmemOption {
 #[derive(Serialize, Deserialize, FromKeyValues)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct VhostUserFrontendOption {
-    /// Device type
+    /// Device type, either by name or as a numeric virtio device ID
     #[serde(rename = "type")]
     pub type_: devices::virtio::DeviceType,
 
@@ -215,6 +303,15 @@ pub struct VhostUserFrontendOption {
 
     /// Preferred PCI address
     pub pci_address: Option<PciAddress>,
+
+    /// Mask of vhost-user protocol features that may be negotiated with the backend (default:
+    /// the features supported for the device type)
+    pub protocol_features: Option<u64>,
+
+    /// Wait for the backend to come back and restore its state if it disconnects, instead of
+    /// failing
+    #[serde(default)]
+    pub reconnect: bool,
 }
 
 pub const DEFAULT_TOUCH_DEVICE_HEIGHT: u32 = 1024;
@@ -284,6 +381,11 @@ pub enum InputDeviceOption {
     Evdev {
         path: PathBuf,
     },
+    EvdevHotplug {
+        name: Option<String>,
+        vendor: Option<u16>,
+        product: Option<u16>,
+    },
     Keyboard {
         path: PathBuf,
     },
@@ -294,6 +396,7 @@ pub enum InputDeviceOption {
         path: PathBuf,
         width: Option<u32>,
         height: Option<u32>,
+        slots: Option<u32>,
         name: Option<String>,
     },
     Rotary {
@@ -308,6 +411,12 @@ pub enum InputDeviceOption {
     Switches {
         path: PathBuf,
     },
+    Tablet {
+        path: PathBuf,
+        width: Option<u32>,
+        height: Option<u32>,
+        name: Option<String>,
+    },
     Trackpad {
         path: PathBuf,
         width: Option<u32>,
@@ -385,6 +494,40 @@ pub fn validate_serial_parameters(params: &SerialParameters) -> Result<(), Strin
         ));
     }
 
+    if let Some(max_ports) = params.max_ports {
+        if params.hardware != SerialHardware::VirtioConsole {
+            return Err(invalid_value_err(
+                max_ports.to_string(),
+                "max-ports is only supported for virtio-console hardware type",
+            ));
+        }
+        if max_ports < 1 {
+            return Err(invalid_value_err(
+                max_ports.to_string(),
+                "max-ports must be at least 1",
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    if params.type_ == SerialType::Tcp {
+        if params.addr.is_none() {
+            return Err("type=tcp requires addr".to_string());
+        }
+        if params.stdin || params.input.is_some() {
+            return Err("type=tcp can't be used with stdin or input".to_string());
+        }
+    } else if params.addr.is_some() || params.listen || params.telnet {
+        return Err("addr, listen and telnet are only valid with type=tcp".to_string());
+    }
+
+    #[cfg(unix)]
+    if params.type_ != SerialType::LogFile
+        && (params.rotate_size.is_some() || params.rotate_count.is_some())
+    {
+        return Err("rotate-size and rotate-count are only valid with type=log-file".to_string());
+    }
+
     Ok(())
 }
 
@@ -412,6 +555,40 @@ pub fn parse_bus_id_addr(v: &str) -> Result<(u8, u8, u16, u16), String> {
     }
 }
 
+/// Parses a string of hex digit pairs, e.g. `0200040000000000`, into bytes.
+pub fn parse_hex_bytes(v: &str) -> Result<Vec<u8>, String> {
+    if v.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in {}", v));
+    }
+    (0..v.len())
+        .step_by(2)
+        .map(|i| {
+            v.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| format!("invalid hex byte at offset {} in {}", i, v))
+        })
+        .collect()
+}
+
+/// Parses a duration given in seconds, optionally with an `ms`, `s` or `m` unit suffix.
+pub fn parse_duration(v: &str) -> Result<Duration, String> {
+    let (number, unit_ms) = if let Some(ms) = v.strip_suffix("ms") {
+        (ms, 1)
+    } else if let Some(s) = v.strip_suffix('s') {
+        (s, 1000)
+    } else if let Some(m) = v.strip_suffix('m') {
+        (m, 60 * 1000)
+    } else {
+        (v, 1000)
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit_ms))
+        .map(Duration::from_millis)
+        .ok_or_else(|| invalid_value_err(v, "expected a duration such as 30s"))
+}
+
 pub fn invalid_value_err<T: AsRef<str>, S: ToString>(value: T, expected: S) -> String {
     format!("invalid value {}: {}", value.as_ref(), expected.to_string())
 }
@@ -421,6 +598,9 @@ pub fn invalid_value_err<T: AsRef<str>, S: ToString>(value: T, expected: S) -> S
 pub struct BatteryConfig {
     #[serde(rename = "type", default)]
     pub type_: BatteryType,
+    /// Mirror the host's batteries and AC adapters from `/sys/class/power_supply`.
+    #[serde(default)]
+    pub host: bool,
 }
 
 pub fn parse_cpu_btreemap_u32(s: &str) -> Result<BTreeMap<usize, u32>, String> {
@@ -522,6 +702,78 @@ pub fn parse_pflash_parameters(s: &str) -> Result<PflashParameters, String> {
     Ok(pflash_parameters)
 }
 
+pub fn parse_secure_boot_parameters(s: &str) -> Result<SecureBootParameters, String> {
+    let secure_boot_parameters: SecureBootParameters = from_key_values(s)?;
+
+    Ok(secure_boot_parameters)
+}
+
+/// Joins the lines of a kernel command line file, skipping blank lines and `#` comments.
+fn params_from_file_contents(contents: &str) -> String {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reads kernel command line parameters from a file given to `--params-file`.
+pub fn read_params_file(path: &Path) -> Result<String, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read params file {}: {}", path.display(), e))?;
+    Ok(params_from_file_contents(&contents))
+}
+
+/// Returns the kernel command line parameters for this boot of the VM.
+///
+/// Parameters saved with `save_next_boot_params` by a previous run replace those of the
+/// configuration, and are removed so that they only apply to a single boot.
+pub fn take_next_boot_params(cfg: &Config) -> Result<Vec<String>, String> {
+    let Some(path) = &cfg.next_boot_params_file else {
+        return Ok(cfg.params.clone());
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cfg.params.clone()),
+        Err(e) => {
+            return Err(format!(
+                "failed to read next boot params {}: {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    fs::remove_file(path).map_err(|e| {
+        format!(
+            "failed to remove next boot params {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    Ok(vec![params_from_file_contents(&contents)])
+}
+
+/// Saves the kernel command line parameters to use instead of the configured ones on the next
+/// boot of the VM, or discards previously saved ones if `params` is `None`.
+pub fn save_next_boot_params(path: &Path, params: Option<&[String]>) -> io::Result<()> {
+    match params {
+        Some(params) => {
+            // Write then rename, so that a crash never leaves half the parameters behind.
+            let mut tmp_path = path.as_os_str().to_owned();
+            tmp_path.push(".tmp");
+            let mut contents = params.join("\n");
+            contents.push('\n');
+            fs::write(&tmp_path, contents)?;
+            fs::rename(&tmp_path, path)
+        }
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
 // BTreeMaps serialize fine, as long as their keys are trivial types. A tuple does not
 // work, hence the need to convert to/from a vector form.
 mod serde_serial_params {
@@ -555,6 +807,108 @@ mod serde_serial_params {
     }
 }
 
+/// A number of a CPUID overrides file, either a JSON number or a string holding a decimal or
+/// "0x" prefixed hexadecimal number.
+#[cfg(all(target_arch = "x86_64", unix))]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CpuIdOverridesNumber {
+    Number(u32),
+    String(String),
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "CpuIdOverridesNumber")]
+struct CpuIdOverridesU32(u32);
+
+#[cfg(all(target_arch = "x86_64", unix))]
+impl TryFrom<CpuIdOverridesNumber> for CpuIdOverridesU32 {
+    type Error = String;
+
+    fn try_from(number: CpuIdOverridesNumber) -> Result<Self, Self::Error> {
+        match number {
+            CpuIdOverridesNumber::Number(n) => Ok(CpuIdOverridesU32(n)),
+            CpuIdOverridesNumber::String(s) => parse_hex_or_decimal(&s)?
+                .try_into()
+                .map(CpuIdOverridesU32)
+                .map_err(|_| format!("{} doesn't fit in 32 bits", s)),
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CpuIdLeafOverride {
+    function: CpuIdOverridesU32,
+    index: Option<CpuIdOverridesU32>,
+    register: CpuIdRegister,
+    mask: Option<CpuIdOverridesU32>,
+    value: CpuIdOverridesU32,
+}
+
+/// Contents of the file given to `--cpuid-overrides`.
+#[cfg(all(target_arch = "x86_64", unix))]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CpuIdOverridesFile {
+    vendor: Option<String>,
+    #[serde(default)]
+    leaves: Vec<CpuIdLeafOverride>,
+    #[serde(default)]
+    hidden_msrs: Vec<CpuIdOverridesU32>,
+}
+
+/// Parses a CPUID overrides file, returning the CPUID overrides and the MSRs to hide from the
+/// guest.
+#[cfg(all(target_arch = "x86_64", unix))]
+fn parse_cpuid_overrides(contents: &str) -> Result<(Vec<CpuIdOverride>, Vec<u32>), String> {
+    let file: CpuIdOverridesFile = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+
+    let mut overrides = Vec::new();
+    if let Some(vendor) = file.vendor {
+        let vendor: [u8; 12] = vendor
+            .as_bytes()
+            .try_into()
+            .map_err(|_| format!("vendor {:?} must be 12 bytes long", vendor))?;
+        // The vendor string is spread over EBX, EDX and ECX, in that order.
+        for (register, bytes) in [CpuIdRegister::Ebx, CpuIdRegister::Edx, CpuIdRegister::Ecx]
+            .into_iter()
+            .zip(vendor.chunks_exact(4))
+        {
+            overrides.push(CpuIdOverride {
+                function: 0,
+                index: None,
+                register,
+                mask: u32::MAX,
+                value: u32::from_le_bytes(bytes.try_into().unwrap()),
+            });
+        }
+    }
+    overrides.extend(file.leaves.into_iter().map(|leaf| CpuIdOverride {
+        function: leaf.function.0,
+        index: leaf.index.map(|index| index.0),
+        register: leaf.register,
+        mask: leaf.mask.map_or(u32::MAX, |mask| mask.0),
+        value: leaf.value.0,
+    }));
+
+    Ok((
+        overrides,
+        file.hidden_msrs.into_iter().map(|msr| msr.0).collect(),
+    ))
+}
+
+/// Reads the CPUID overrides file given to `--cpuid-overrides`.
+#[cfg(all(target_arch = "x86_64", unix))]
+pub fn load_cpuid_overrides(path: &Path) -> Result<(Vec<CpuIdOverride>, Vec<u32>), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read CPUID overrides {}: {}", path.display(), e))?;
+    parse_cpuid_overrides(&contents)
+        .map_err(|e| format!("invalid CPUID overrides {}: {}", path.display(), e))
+}
+
 /// Aggregate of all configurable options for a running VM.
 #[derive(Serialize, Deserialize)]
 #[remain::sorted]
@@ -565,6 +919,10 @@ pub struct Config {
     #[cfg(feature = "android_display")]
     pub android_display_service: Option<String>,
     pub android_fstab: Option<PathBuf>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub api_socket_path: Option<PathBuf>,
+    #[cfg(windows)]
+    pub app_container: bool,
     pub async_executor: Option<ExecutorKind>,
     #[cfg(feature = "balloon")]
     pub balloon: bool,
@@ -586,6 +944,9 @@ pub struct Config {
     #[cfg(any(target_os = "android", target_os = "linux"))]
     pub boost_uclamp: bool,
     pub boot_cpu: usize,
+    /// Number of vCPUs online at boot when `vcpu_count` includes parked ones.
+    #[cfg(target_arch = "aarch64")]
+    pub boot_vcpu_count: Option<usize>,
     #[cfg(target_arch = "x86_64")]
     pub break_linux_pci_config_io: bool,
     #[cfg(windows)]
@@ -593,6 +954,8 @@ pub struct Config {
     #[cfg(target_arch = "x86_64")]
     pub bus_lock_ratelimit: u64,
     #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub can: Vec<CanParameters>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
     pub coiommu_param: Option<devices::CoIommuParameters>,
     pub core_scheduling: bool,
     pub cpu_capacity: BTreeMap<usize, u32>, // CPU index -> capacity
@@ -608,6 +971,8 @@ pub struct Config {
         any(target_os = "android", target_os = "linux")
     ))]
     pub cpu_ipc_ratio: BTreeMap<usize, u32>, // CPU index -> IPC Ratio
+    #[cfg(all(target_arch = "x86_64", unix))]
+    pub cpuid_overrides: Vec<CpuIdOverride>,
     #[cfg(feature = "crash-report")]
     pub crash_pipe_name: Option<String>,
     #[cfg(feature = "crash-report")]
@@ -620,6 +985,7 @@ pub struct Config {
     pub display_input_width: Option<u32>,
     pub display_window_keyboard: bool,
     pub display_window_mouse: bool,
+    pub display_window_tablet: bool,
     pub dump_device_tree_blob: Option<PathBuf>,
     pub dynamic_power_coefficient: BTreeMap<usize, u32>,
     pub enable_fw_cfg: bool,
@@ -627,11 +993,14 @@ pub struct Config {
     pub executable_path: Option<Executable>,
     #[cfg(windows)]
     pub exit_stats: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub extensions: Vec<PathBuf>,
     pub fdt_position: Option<FdtPosition>,
     pub file_backed_mappings_mmio: Vec<FileBackedMappingParameters>,
     pub file_backed_mappings_ram: Vec<FileBackedMappingParameters>,
     pub force_calibrated_tsc_leaf: bool,
     pub force_s2idle: bool,
+    pub fw_cfg_kernel: Option<PathBuf>,
     pub fw_cfg_parameters: Vec<FwCfgParameters>,
     #[cfg(feature = "gdb")]
     pub gdb: Option<u32>,
@@ -647,10 +1016,25 @@ pub struct Config {
     pub gpu_server_cgroup_path: Option<PathBuf>,
     #[cfg(all(windows, feature = "gpu"))]
     pub gpu_vmm_config: Option<GpuVmmConfig>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub halt_poll_ns: Option<u64>,
+    #[cfg(all(target_arch = "x86_64", unix))]
+    pub hidden_msrs: Vec<u32>,
+    #[cfg(all(
+        any(target_os = "android", target_os = "linux"),
+        any(target_arch = "aarch64", target_arch = "x86_64")
+    ))]
+    pub host_cache_topology: bool,
     pub host_cpu_topology: bool,
     #[cfg(windows)]
     pub host_guid: Option<String>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub host_numa_bindings: Vec<HostNumaBinding>,
     pub hugepages: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub hugetlb: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub hugetlb_page_size: Option<u64>,
     pub hypervisor: Option<HypervisorKind>,
     #[cfg(feature = "balloon")]
     pub init_memory: Option<u64>,
@@ -658,11 +1042,19 @@ pub struct Config {
     #[cfg(all(windows, feature = "gpu"))]
     pub input_event_split_config: Option<InputEventSplitConfig>,
     pub irq_chip: Option<IrqChipKind>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub irq_latency_stats: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub isolate_virtio_devices: bool,
     pub itmt: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub ivshmem: Vec<IvshmemParameters>,
     pub jail_config: Option<JailConfig>,
     #[cfg(windows)]
     pub kernel_log_file: Option<String>,
     #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub ksm: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
     pub lock_guest_memory: bool,
     #[cfg(any(target_os = "android", target_os = "linux"))]
     pub lock_guest_memory_dontneed: bool,
@@ -674,6 +1066,10 @@ pub struct Config {
     pub media_decoder: Vec<VideoDeviceConfig>,
     pub memory: Option<u64>,
     pub memory_file: Option<PathBuf>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub metrics_address: Option<SocketAddr>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub migrate_receive: Option<String>,
     pub mmio_address_ranges: Vec<AddressRange>,
     #[cfg(target_arch = "aarch64")]
     pub mte: bool,
@@ -682,6 +1078,8 @@ pub struct Config {
     pub net: Vec<NetParameters>,
     #[cfg(windows)]
     pub net_vhost_user_tube: Option<Tube>,
+    pub next_boot_params_file: Option<PathBuf>,
+    pub no_async_pf: bool,
     pub no_i8042: bool,
     pub no_pmu: bool,
     pub no_rtc: bool,
@@ -700,6 +1098,8 @@ pub struct Config {
     #[cfg(any(target_os = "android", target_os = "linux"))]
     pub pmem_ext2: Vec<crate::crosvm::sys::config::PmemExt2Option>,
     pub pmems: Vec<PmemOption>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub powerdown_hooks: Vec<PathBuf>,
     #[cfg(feature = "process-invariants")]
     pub process_invariants_data_handle: Option<u64>,
     #[cfg(feature = "process-invariants")]
@@ -714,12 +1114,23 @@ pub struct Config {
     pub pstore: Option<Pstore>,
     #[cfg(feature = "pvclock")]
     pub pvclock: bool,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub pvpanic_core_dump_dir: Option<PathBuf>,
     /// Must be `Some` iff `protection_type == ProtectionType::UnprotectedWithFirmware`.
     pub pvm_fw: Option<PathBuf>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub qmp_socket_path: Option<PathBuf>,
+    pub restore_key_file: Option<PathBuf>,
     pub restore_path: Option<PathBuf>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub restore_shared_memory: bool,
     pub rng: bool,
     pub rt_cpus: CpuSet,
+    pub rtc: RtcOptions,
+    pub s3: bool,
+    pub s3_release_devices: bool,
     pub scsis: Vec<ScsiOption>,
+    pub secure_boot: Option<SecureBootParameters>,
     #[serde(with = "serde_serial_params")]
     pub serial_parameters: BTreeMap<(SerialHardware, u8), SerialParameters>,
     #[cfg(windows)]
@@ -733,17 +1144,30 @@ pub struct Config {
     pub slirp_capture_file: Option<String>,
     #[cfg(target_arch = "x86_64")]
     pub smbios: SmbiosOptions,
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    pub smmuv3: bool,
     #[cfg(all(windows, feature = "audio"))]
     pub snd_split_configs: Vec<SndSplitConfig>,
     pub socket_path: Option<PathBuf>,
     #[cfg(feature = "audio")]
     pub sound: Option<PathBuf>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub spi: Vec<SpiParameters>,
     pub stub_pci_devices: Vec<StubPciParameters>,
     pub suspended: bool,
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     pub sve: Option<SveConfig>,
+    pub swap_compression_level: Option<i32>,
     pub swap_dir: Option<PathBuf>,
+    pub swap_policy: Option<PathBuf>,
     pub swiotlb: Option<u64>,
+    #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+    pub swtpm: Option<SwtpmParameters>,
+    #[cfg(target_arch = "aarch64")]
+    pub system_suspend: bool,
     #[cfg(target_os = "android")]
     pub task_profiles: Vec<String>,
     #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -753,6 +1177,8 @@ pub struct Config {
     #[cfg(feature = "media")]
     pub v4l2_proxy: Vec<PathBuf>,
     pub vcpu_affinity: Option<VcpuAffinity>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub vcpu_boost: Option<crate::crosvm::sys::config::VcpuBoostOptions>,
     pub vcpu_cgroup_path: Option<PathBuf>,
     pub vcpu_count: Option<usize>,
     #[cfg(target_arch = "x86_64")]
@@ -786,6 +1212,11 @@ pub struct Config {
     pub vsock: Option<VsockConfig>,
     #[cfg(feature = "vtpm")]
     pub vtpm_proxy: bool,
+    #[cfg(all(
+        any(target_os = "android", target_os = "linux"),
+        feature = "wasm-devices"
+    ))]
+    pub wasm_devices: Vec<WasmDeviceParameters>,
     pub wayland_socket_paths: BTreeMap<String, PathBuf>,
     #[cfg(all(windows, feature = "gpu"))]
     pub window_procedure_thread_split_config: Option<WindowProcedureThreadSplitConfig>,
@@ -801,6 +1232,10 @@ impl Default for Config {
             #[cfg(feature = "android_display")]
             android_display_service: None,
             android_fstab: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            api_socket_path: None,
+            #[cfg(windows)]
+            app_container: false,
             async_executor: None,
             #[cfg(feature = "balloon")]
             balloon: true,
@@ -816,6 +1251,8 @@ impl Default for Config {
             balloon_ws_reporting: false,
             battery_config: None,
             boot_cpu: 0,
+            #[cfg(target_arch = "aarch64")]
+            boot_vcpu_count: None,
             #[cfg(windows)]
             block_control_tube: Vec::new(),
             #[cfg(windows)]
@@ -827,6 +1264,8 @@ impl Default for Config {
             #[cfg(target_arch = "x86_64")]
             bus_lock_ratelimit: 0,
             #[cfg(any(target_os = "android", target_os = "linux"))]
+            can: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
             coiommu_param: None,
             core_scheduling: true,
             #[cfg(feature = "crash-report")]
@@ -846,6 +1285,8 @@ impl Default for Config {
                 any(target_os = "android", target_os = "linux")
             ))]
             cpu_ipc_ratio: BTreeMap::new(),
+            #[cfg(all(target_arch = "x86_64", unix))]
+            cpuid_overrides: Vec::new(),
             delay_rt: false,
             device_tree_overlay: Vec::new(),
             disks: Vec::new(),
@@ -854,6 +1295,7 @@ impl Default for Config {
             display_input_width: None,
             display_window_keyboard: false,
             display_window_mouse: false,
+            display_window_tablet: false,
             dump_device_tree_blob: None,
             dynamic_power_coefficient: BTreeMap::new(),
             enable_fw_cfg: false,
@@ -861,11 +1303,14 @@ impl Default for Config {
             executable_path: None,
             #[cfg(windows)]
             exit_stats: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            extensions: Vec::new(),
             fdt_position: None,
             file_backed_mappings_mmio: Vec::new(),
             file_backed_mappings_ram: Vec::new(),
             force_calibrated_tsc_leaf: false,
             force_s2idle: false,
+            fw_cfg_kernel: None,
             fw_cfg_parameters: Vec::new(),
             #[cfg(feature = "gdb")]
             gdb: None,
@@ -881,6 +1326,15 @@ impl Default for Config {
             gpu_server_cgroup_path: None,
             #[cfg(all(windows, feature = "gpu"))]
             gpu_vmm_config: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            halt_poll_ns: None,
+            #[cfg(all(target_arch = "x86_64", unix))]
+            hidden_msrs: Vec::new(),
+            #[cfg(all(
+                any(target_os = "android", target_os = "linux"),
+                any(target_arch = "aarch64", target_arch = "x86_64")
+            ))]
+            host_cache_topology: false,
             host_cpu_topology: false,
             #[cfg(windows)]
             host_guid: None,
@@ -888,7 +1342,13 @@ impl Default for Config {
             product_version: None,
             #[cfg(windows)]
             product_channel: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            host_numa_bindings: Vec::new(),
             hugepages: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            hugetlb: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            hugetlb_page_size: None,
             hypervisor: None,
             #[cfg(feature = "balloon")]
             init_memory: None,
@@ -896,7 +1356,13 @@ impl Default for Config {
             #[cfg(all(windows, feature = "gpu"))]
             input_event_split_config: None,
             irq_chip: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            irq_latency_stats: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            isolate_virtio_devices: false,
             itmt: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            ivshmem: Vec::new(),
             jail_config: if !cfg!(feature = "default-no-sandbox") {
                 Some(Default::default())
             } else {
@@ -905,6 +1371,8 @@ impl Default for Config {
             #[cfg(windows)]
             kernel_log_file: None,
             #[cfg(any(target_os = "android", target_os = "linux"))]
+            ksm: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
             lock_guest_memory: false,
             #[cfg(any(target_os = "android", target_os = "linux"))]
             lock_guest_memory_dontneed: false,
@@ -918,6 +1386,10 @@ impl Default for Config {
             media_decoder: Default::default(),
             memory: None,
             memory_file: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            metrics_address: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            migrate_receive: None,
             mmio_address_ranges: Vec::new(),
             #[cfg(target_arch = "aarch64")]
             mte: false,
@@ -926,6 +1398,8 @@ impl Default for Config {
             net: Vec::new(),
             #[cfg(windows)]
             net_vhost_user_tube: None,
+            next_boot_params_file: None,
+            no_async_pf: false,
             no_i8042: false,
             no_pmu: false,
             no_rtc: false,
@@ -944,6 +1418,8 @@ impl Default for Config {
             #[cfg(any(target_os = "android", target_os = "linux"))]
             pmem_ext2: Vec::new(),
             pmems: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            powerdown_hooks: Vec::new(),
             #[cfg(feature = "process-invariants")]
             process_invariants_data_handle: None,
             #[cfg(feature = "process-invariants")]
@@ -954,12 +1430,23 @@ impl Default for Config {
             pstore: None,
             #[cfg(feature = "pvclock")]
             pvclock: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            pvpanic_core_dump_dir: None,
             pvm_fw: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            qmp_socket_path: None,
+            restore_key_file: None,
             restore_path: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            restore_shared_memory: false,
             rng: true,
             rt_cpus: Default::default(),
+            rtc: Default::default(),
+            s3: false,
+            s3_release_devices: false,
             serial_parameters: BTreeMap::new(),
             scsis: Vec::new(),
+            secure_boot: None,
             #[cfg(windows)]
             service_pipe_name: None,
             #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -970,23 +1457,38 @@ impl Default for Config {
             slirp_capture_file: None,
             #[cfg(target_arch = "x86_64")]
             smbios: SmbiosOptions::default(),
+            #[cfg(all(
+                target_arch = "aarch64",
+                any(target_os = "android", target_os = "linux")
+            ))]
+            smmuv3: false,
             #[cfg(all(windows, feature = "audio"))]
             snd_split_configs: Vec::new(),
             socket_path: None,
             #[cfg(feature = "audio")]
             sound: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            spi: Vec::new(),
             stub_pci_devices: Vec::new(),
             suspended: false,
             #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
             sve: None,
+            swap_compression_level: None,
             swap_dir: None,
+            swap_policy: None,
             swiotlb: None,
+            #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+            swtpm: None,
+            #[cfg(target_arch = "aarch64")]
+            system_suspend: false,
             #[cfg(target_os = "android")]
             task_profiles: Vec::new(),
             #[cfg(any(target_os = "android", target_os = "linux"))]
             unmap_guest_memory_on_fork: false,
             usb: true,
             vcpu_affinity: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            vcpu_boost: None,
             vcpu_cgroup_path: None,
             vcpu_count: None,
             #[cfg(target_arch = "x86_64")]
@@ -1022,6 +1524,11 @@ impl Default for Config {
             v4l2_proxy: Vec::new(),
             #[cfg(feature = "vtpm")]
             vtpm_proxy: false,
+            #[cfg(all(
+                any(target_os = "android", target_os = "linux"),
+                feature = "wasm-devices"
+            ))]
+            wasm_devices: Vec::new(),
             wayland_socket_paths: BTreeMap::new(),
             #[cfg(windows)]
             window_procedure_thread_split_config: None,
@@ -1043,10 +1550,17 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
     {
         crate::crosvm::gpu_config::validate_gpu_config(cfg)?;
     }
-    #[cfg(feature = "gdb")]
-    if cfg.gdb.is_some() && cfg.vcpu_count.unwrap_or(1) != 1 {
-        return Err("`gdb` requires the number of vCPU to be 1".to_string());
+    if cfg.fw_cfg_kernel.is_some() && !matches!(cfg.executable_path, Some(Executable::Bios(_))) {
+        return Err("`fw-cfg-kernel` requires `bios`".to_string());
+    }
+    if cfg.secure_boot.is_some() && cfg.pflash_parameters.is_none() {
+        return Err("`secure-boot` requires `pflash`".to_string());
     }
+
+    cfg.rtc
+        .initial_offset()
+        .map_err(|e| format!("invalid `rtc`: {}", e))?;
+
     if cfg.host_cpu_topology {
         if cfg.no_smt {
             return Err(
@@ -1102,6 +1616,38 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
         }
     }
 
+    #[cfg(all(
+        any(target_os = "android", target_os = "linux"),
+        any(target_arch = "aarch64", target_arch = "x86_64")
+    ))]
+    if cfg.host_cache_topology {
+        // `host-cpu-topology` pins the vCPUs above.
+        let pinned = match &cfg.vcpu_affinity {
+            Some(VcpuAffinity::PerVcpu(affinity)) => (0..cfg.vcpu_count.unwrap_or(1)).all(|vcpu| {
+                affinity
+                    .get(&vcpu)
+                    .is_some_and(|cpus| cpus.iter().count() == 1)
+            }),
+            _ => false,
+        };
+        if !pinned {
+            return Err(
+                "`host-cache-topology` requires every vCPU to be pinned to a single host CPU"
+                    .to_string(),
+            );
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if let Some(boot_vcpu_count) = cfg.boot_vcpu_count {
+        if boot_vcpu_count == 0 || boot_vcpu_count > cfg.vcpu_count.unwrap_or(1) {
+            return Err("`max-cores` must be at least `num-cores`, which can't be 0".to_string());
+        }
+        if cfg.boot_cpu >= boot_vcpu_count {
+            return Err("`boot-cpu` must be one of the vCPUs online at boot".to_string());
+        }
+    }
+
     if cfg.boot_cpu >= cfg.vcpu_count.unwrap_or(1) {
         log::warn!("boot_cpu selection cannot be higher than vCPUs available, defaulting to 0");
         cfg.boot_cpu = 0;
@@ -1135,6 +1681,35 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
                 .to_string());
         }
     }
+
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    if cfg.smmuv3 {
+        if cfg
+            .vfio
+            .iter()
+            .any(|o| o.iommu == devices::IommuDevType::VirtioIommu)
+        {
+            return Err("`smmuv3` cannot translate VFIO devices with `iommu=viommu`".to_string());
+        }
+        if cfg.vfio_isolate_hotplug {
+            return Err("`smmuv3` cannot be used with `vfio-isolate-hotplug`".to_string());
+        }
+    }
+    #[cfg(all(
+        not(target_arch = "x86_64"),
+        any(target_os = "android", target_os = "linux"),
+        feature = "vtpm"
+    ))]
+    if cfg
+        .swtpm
+        .as_ref()
+        .is_some_and(|p| p.interface == devices::TpmInterface::Crb)
+    {
+        return Err("`swtpm` `interface=crb` is only supported on x86_64".to_string());
+    }
     #[cfg(target_arch = "x86_64")]
     if !cfg.vcpu_hybrid_type.is_empty() {
         if cfg.host_cpu_topology {
@@ -1219,12 +1794,44 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
         );
     }
 
+    // Only the main process sees guest memory mapped from the snapshot, so no other process may
+    // access guest memory.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if cfg.restore_shared_memory {
+        if cfg.restore_path.is_none() {
+            return Err("'restore-shared-memory' requires 'restore'".to_string());
+        }
+        if cfg.jail_config.is_some() {
+            return Err("'restore-shared-memory' requires 'disable-sandbox'".to_string());
+        }
+        if !cfg.vhost_user.is_empty() {
+            return Err(
+                "'restore-shared-memory' cannot be used with vhost-user devices".to_string(),
+            );
+        }
+    }
+
+    // KSM only merges anonymous pages, which guest memory only has once restored with
+    // 'restore-shared-memory'. Otherwise all of it is a shared mapping of its memfd.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if cfg.ksm && !cfg.restore_shared_memory {
+        return Err("'ksm' requires 'restore-shared-memory'".to_string());
+    }
+
     // TODO(b/253386409): Vmm-swap only support sandboxed devices until vmm-swap use
     // `devices::Suspendable` to suspend devices.
     #[cfg(feature = "swap")]
     if cfg.swap_dir.is_some() && cfg.jail_config.is_none() {
         return Err("'swap' and 'disable-sandbox' are mutually exclusive".to_string());
     }
+    #[cfg(feature = "swap")]
+    if cfg.swap_compression_level.is_some() && cfg.swap_dir.is_none() {
+        return Err("'swap-compression-level' requires 'swap'".to_string());
+    }
+    #[cfg(feature = "swap")]
+    if cfg.swap_policy.is_some() && cfg.swap_dir.is_none() {
+        return Err("'swap-policy' requires 'swap'".to_string());
+    }
 
     set_default_serial_parameters(
         &mut cfg.serial_parameters,
@@ -1245,6 +1852,29 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
         validate_pmem(pmem)?;
     }
 
+    for input in cfg.virtio_input.iter() {
+        if let InputDeviceOption::EvdevHotplug {
+            name,
+            vendor,
+            product,
+        } = input
+        {
+            if name.is_none() && vendor.is_none() && product.is_none() {
+                return Err(
+                    "`evdev-hotplug` requires at least one of `name`, `vendor` or `product`"
+                        .to_string(),
+                );
+            }
+            #[cfg(feature = "pci-hotplug")]
+            let hotplug_enabled = cfg.pci_hotplug_slots.is_some();
+            #[cfg(not(feature = "pci-hotplug"))]
+            let hotplug_enabled = false;
+            if !hotplug_enabled {
+                return Err("`evdev-hotplug` requires `pci-hotplug-slots`".to_string());
+            }
+        }
+    }
+
     // Validate platform specific things
     super::sys::config::validate_config(cfg)
 }
@@ -1379,6 +2009,19 @@ mod tests {
             );
         }
 
+        #[cfg(target_arch = "aarch64")]
+        {
+            let res: CpuOptions = from_key_values("num-cores=2,max-cores=8").unwrap();
+            assert_eq!(
+                res,
+                CpuOptions {
+                    num_cores: Some(2),
+                    max_cores: Some(8),
+                    ..Default::default()
+                }
+            );
+        }
+
         // All together
         let res: CpuOptions = from_key_values("16,clusters=[[0],[4-6],[7]]").unwrap();
         assert_eq!(
@@ -1401,6 +2044,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_hex_bytes_valid() {
+        assert_eq!(parse_hex_bytes("00ff7f").unwrap(), vec![0x00, 0xff, 0x7f]);
+        assert!(parse_hex_bytes("0").is_err());
+        assert!(parse_hex_bytes("0g").is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1h").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
     #[test]
     fn parse_cpu_set_single() {
         assert_eq!(
@@ -1510,6 +2171,173 @@ mod tests {
         assert_eq!(res.size, Some(16384));
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn parse_host_numa_binding() {
+        let res: HostNumaBinding =
+            from_key_values("addr=0x100000,size=0x40000000,nodes=[1]").unwrap();
+        assert_eq!(
+            res,
+            HostNumaBinding {
+                address: 0x100000,
+                size: 0x40000000,
+                nodes: vec![1],
+                policy: NumaPolicy::Bind,
+            }
+        );
+
+        let res: HostNumaBinding =
+            from_key_values("addr=0,size=0x1000,nodes=[0,1],policy=interleave").unwrap();
+        assert_eq!(res.nodes, vec![0, 1]);
+        assert_eq!(res.policy, NumaPolicy::Interleave);
+
+        assert!(from_key_values::<HostNumaBinding>("addr=0,size=0x1000").is_err());
+        assert!(
+            from_key_values::<HostNumaBinding>("addr=0,size=0x1000,nodes=[0],policy=local")
+                .is_err()
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn parse_mem_opts_hugetlb() {
+        let res: MemOptions = from_key_values("size=1024,hugetlb").unwrap();
+        assert_eq!(res.size, Some(1024));
+        assert!(res.hugetlb);
+        assert_eq!(res.hugetlb_page_size, None);
+
+        let res: MemOptions = from_key_values("hugetlb=true,hugetlb-page-size=1048576").unwrap();
+        assert!(res.hugetlb);
+        assert_eq!(res.hugetlb_page_size, Some(1048576));
+    }
+
+    #[test]
+    fn parse_rtc_opts() {
+        let res: RtcOptions = from_key_values("").unwrap();
+        assert_eq!(res.initial_offset(), Ok(0));
+
+        let res: RtcOptions = from_key_values("offset=-3600").unwrap();
+        assert_eq!(res.initial_offset(), Ok(-3600));
+
+        let res: RtcOptions = from_key_values("base=2000-01-01T00:00:00Z").unwrap();
+        assert!(res.initial_offset().unwrap() < 0);
+
+        let res: RtcOptions = from_key_values("base=2000-01-01T00:00:00Z,offset=1").unwrap();
+        assert!(res.initial_offset().is_err());
+
+        let res: RtcOptions = from_key_values("base=yesterday").unwrap();
+        assert!(res.initial_offset().is_err());
+    }
+
+    #[test]
+    fn parse_secure_boot_opts() {
+        let res = parse_secure_boot_parameters("pk=/pk.der,kek=[/kek.der],db=[/db1.der,/db2.der]")
+            .unwrap();
+        assert_eq!(
+            res,
+            SecureBootParameters {
+                pk: PathBuf::from("/pk.der"),
+                kek: vec![PathBuf::from("/kek.der")],
+                db: vec![PathBuf::from("/db1.der"), PathBuf::from("/db2.der")],
+            }
+        );
+
+        let res = parse_secure_boot_parameters("pk=/pk.der").unwrap();
+        assert!(res.kek.is_empty());
+        assert!(res.db.is_empty());
+
+        assert!(parse_secure_boot_parameters("kek=[/kek.der]").is_err());
+        assert!(parse_secure_boot_parameters("pk=/pk.der,dbx=[/dbx.der]").is_err());
+    }
+
+    #[test]
+    fn params_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cmdline");
+        fs::write(
+            &path,
+            "# test params\nconsole=ttyS0\n\n  quiet loglevel=3  \n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_params_file(&path).unwrap(),
+            "console=ttyS0 quiet loglevel=3"
+        );
+        assert!(read_params_file(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn next_boot_params() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("next_boot");
+        let cfg = Config {
+            params: vec!["quiet".to_string()],
+            next_boot_params_file: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(take_next_boot_params(&cfg).unwrap(), vec!["quiet"]);
+
+        let params = vec!["console=ttyS0".to_string(), "debug".to_string()];
+        save_next_boot_params(&path, Some(&params[..])).unwrap();
+        assert_eq!(
+            take_next_boot_params(&cfg).unwrap(),
+            vec!["console=ttyS0 debug"]
+        );
+        // The override only applies to a single boot.
+        assert_eq!(take_next_boot_params(&cfg).unwrap(), vec!["quiet"]);
+
+        save_next_boot_params(&path, Some(&params[..])).unwrap();
+        save_next_boot_params(&path, None).unwrap();
+        assert_eq!(take_next_boot_params(&cfg).unwrap(), vec!["quiet"]);
+        save_next_boot_params(&path, None).unwrap();
+    }
+
+    #[cfg(all(target_arch = "x86_64", unix))]
+    #[test]
+    fn cpuid_overrides() {
+        let (overrides, hidden_msrs) = parse_cpuid_overrides(
+            r#"{
+                "vendor": "GenuineIntel",
+                "leaves": [
+                    {"function": 7, "index": 0, "register": "ebx", "mask": "0x10000", "value": 0},
+                    {"function": "0x40000000", "register": "eax", "value": "0x40000001"}
+                ],
+                "hidden_msrs": ["0x1a0", 416]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(overrides.len(), 5);
+        assert_eq!(
+            overrides[0],
+            CpuIdOverride {
+                function: 0,
+                index: None,
+                register: CpuIdRegister::Ebx,
+                mask: u32::MAX,
+                value: u32::from_le_bytes(*b"Genu"),
+            }
+        );
+        assert_eq!(overrides[2].register, CpuIdRegister::Ecx);
+        assert_eq!(overrides[2].value, u32::from_le_bytes(*b"ntel"));
+        assert_eq!(
+            overrides[3],
+            CpuIdOverride {
+                function: 7,
+                index: Some(0),
+                register: CpuIdRegister::Ebx,
+                mask: 0x10000,
+                value: 0,
+            }
+        );
+        assert_eq!(overrides[4].index, None);
+        assert_eq!(overrides[4].mask, u32::MAX);
+        assert_eq!(hidden_msrs, vec![0x1a0, 0x1a0]);
+
+        assert!(parse_cpuid_overrides(r#"{"vendor": "Intel"}"#).is_err());
+        assert!(parse_cpuid_overrides(r#"{"hidden_msrs": ["0x100000000"]}"#).is_err());
+        assert!(parse_cpuid_overrides(r#"{"msrs": []}"#).is_err());
+    }
+
     #[test]
     fn parse_serial_vaild() {
         parse_serial_options("type=syslog,num=1,console=true,stdin=true")
@@ -1590,6 +2418,7 @@ mod tests {
         assert_eq!(
             parsed.pci_address,
             Some(PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 14,
                 func: 0
@@ -1605,6 +2434,7 @@ mod tests {
         assert_eq!(
             parsed.pci_address,
             Some(PciAddress {
+                domain: 0,
                 bus: 0,
                 dev: 14,
                 func: 0
@@ -1624,6 +2454,72 @@ mod tests {
             .expect_err("expected pci-address error for debugcon hardware");
     }
 
+    #[test]
+    fn parse_serial_max_ports_valid_for_virtio() {
+        let parsed = parse_serial_options("type=syslog,hardware=virtio-console,max-ports=4")
+            .expect("parse should have succeded");
+        assert_eq!(parsed.max_ports, Some(4));
+    }
+
+    #[test]
+    fn parse_serial_max_ports_failed_for_serial() {
+        parse_serial_options("type=syslog,hardware=serial,max-ports=4")
+            .expect_err("expected max-ports error for serial hardware");
+    }
+
+    #[test]
+    fn parse_serial_max_ports_failed_for_zero() {
+        parse_serial_options("type=syslog,hardware=virtio-console,max-ports=0")
+            .expect_err("expected max-ports error for zero ports");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_serial_tcp_valid() {
+        let parsed = parse_serial_options("type=tcp,addr=127.0.0.1:4555,listen,telnet")
+            .expect("parse should have succeded");
+        assert_eq!(parsed.addr.as_deref(), Some("127.0.0.1:4555"));
+        assert!(parsed.listen);
+        assert!(parsed.telnet);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_serial_tcp_requires_addr() {
+        parse_serial_options("type=tcp,listen").expect_err("expected error for missing addr");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_serial_tcp_failed_with_stdin() {
+        parse_serial_options("type=tcp,addr=127.0.0.1:4555,stdin")
+            .expect_err("expected error for tcp with stdin");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_serial_addr_failed_for_file() {
+        parse_serial_options("type=file,path=/tmp/out,addr=127.0.0.1:4555")
+            .expect_err("expected addr error for non-tcp type");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_serial_log_file_valid() {
+        let parsed =
+            parse_serial_options("type=log-file,path=/tmp/log,rotate-size=4096,rotate-count=2")
+                .expect("parse should have succeded");
+        assert_eq!(parsed.rotate_size, Some(4096));
+        assert_eq!(parsed.rotate_count, Some(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_serial_rotate_size_failed_for_file() {
+        parse_serial_options("type=file,path=/tmp/log,rotate-size=4096")
+            .expect_err("expected rotate-size error for non-log-file type");
+    }
+
     #[test]
     fn parse_battery_valid() {
         let bat_config: BatteryConfig = from_key_values("type=goldfish").unwrap();
@@ -1634,6 +2530,14 @@ mod tests {
     fn parse_battery_valid_no_type() {
         let bat_config: BatteryConfig = from_key_values("").unwrap();
         assert_eq!(bat_config.type_, BatteryType::Goldfish);
+        assert!(!bat_config.host);
+    }
+
+    #[test]
+    fn parse_battery_host() {
+        let bat_config: BatteryConfig = from_key_values("type=goldfish,host").unwrap();
+        assert_eq!(bat_config.type_, BatteryType::Goldfish);
+        assert!(bat_config.host);
     }
 
     #[test]
@@ -1871,6 +2775,38 @@ mod tests {
         .is_err());
     }
 
+    #[test]
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    fn parse_smmuv3() {
+        assert!(config_from_args(&["--smmuv3", "/dev/null"]).smmuv3);
+
+        assert!(TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &[
+                    "--smmuv3",
+                    "--vfio",
+                    "/path/to/dev,iommu=viommu",
+                    "/dev/null"
+                ],
+            )
+            .unwrap(),
+        )
+        .is_err());
+
+        assert!(TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &["--smmuv3", "--vfio-isolate-hotplug", "/dev/null"],
+            )
+            .unwrap(),
+        )
+        .is_err());
+    }
+
     #[test]
     fn parse_fw_cfg_invalid_no_name() {
         assert!(
@@ -1946,6 +2882,39 @@ mod tests {
         test_device_type("wl", DeviceType::Wl);
         test_device_type("tpm", DeviceType::Tpm);
         test_device_type("pvclock", DeviceType::Pvclock);
+        test_device_type("can", DeviceType::Can);
+        test_device_type("spi", DeviceType::Spi);
+        // Numeric virtio IDs map to known types when possible.
+        test_device_type("26", DeviceType::Fs);
+        test_device_type("0x2a", DeviceType::Custom(42));
+        test_device_type("63", DeviceType::Wl);
+    }
+
+    #[test]
+    fn parse_vhost_user_option_invalid_device_id() {
+        // 0 is reserved and virtio-pci device IDs stop at 0x107f, i.e. virtio device ID 0x3f.
+        for type_ in ["0", "64", "0x1000", "4294967296", "-1"] {
+            assert!(
+                from_key_values::<VhostUserFrontendOption>(&format!("{},socket=sock", type_))
+                    .is_err(),
+                "{type_}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_vhost_user_option_protocol_features() {
+        let opt: VhostUserFrontendOption =
+            from_key_values("55,socket=sock,protocol-features=0x80000201").unwrap();
+        assert_eq!(opt.type_, DeviceType::Custom(55));
+        assert_eq!(opt.protocol_features, Some(0x8000_0201));
+
+        let opt: VhostUserFrontendOption = from_key_values("gpu,socket=sock").unwrap();
+        assert_eq!(opt.protocol_features, None);
+        assert!(!opt.reconnect);
+
+        let opt: VhostUserFrontendOption = from_key_values("block,socket=sock,reconnect").unwrap();
+        assert!(opt.reconnect);
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -1984,6 +2953,7 @@ mod tests {
                 path: PathBuf::from("my_socket"),
                 width: Some(867),
                 height: Some(5309),
+                slots: None,
                 name: None
             }
         );
@@ -2012,11 +2982,115 @@ mod tests {
                 path: PathBuf::from(r"C:\path"),
                 width: Some(867),
                 height: Some(5309),
+                slots: None,
                 name: None
             }
         );
     }
 
+    #[cfg(feature = "pci-hotplug")]
+    #[test]
+    fn parse_input_evdev_hotplug() {
+        let cfg = TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &[
+                    "--pci-hotplug-slots",
+                    "2",
+                    "--input",
+                    "evdev-hotplug[vendor=0x045e,product=0x028e]",
+                    "bzImage",
+                ],
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.virtio_input,
+            vec![InputDeviceOption::EvdevHotplug {
+                name: None,
+                vendor: Some(0x045e),
+                product: Some(0x028e),
+            }]
+        );
+
+        // At least one criterion is required.
+        assert!(TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &[
+                    "--pci-hotplug-slots",
+                    "2",
+                    "--input",
+                    "evdev-hotplug",
+                    "bzImage"
+                ],
+            )
+            .unwrap(),
+        )
+        .is_err());
+
+        // Hotplug slots are required.
+        assert!(TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &["--input", "evdev-hotplug[name=Gamepad]", "bzImage"],
+            )
+            .unwrap(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn ksm_requires_restore_shared_memory() {
+        assert_eq!(
+            TryInto::<Config>::try_into(
+                crate::crosvm::cmdline::RunCommand::from_args(&[], &["--ksm", "/dev/null"])
+                    .unwrap(),
+            )
+            .err(),
+            Some("'ksm' requires 'restore-shared-memory'".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_input_multi_touch_slots_and_tablet() {
+        let cfg = TryInto::<Config>::try_into(
+            crate::crosvm::cmdline::RunCommand::from_args(
+                &[],
+                &[
+                    "--input",
+                    "multi-touch[path=/tmp/touch,width=1920,height=1080,slots=5]",
+                    "--input",
+                    "tablet[path=/tmp/tablet,width=1920,height=1080,name=pen]",
+                    "bzImage",
+                ],
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cfg.virtio_input,
+            vec![
+                InputDeviceOption::MultiTouch {
+                    path: PathBuf::from("/tmp/touch"),
+                    width: Some(1920),
+                    height: Some(1080),
+                    slots: Some(5),
+                    name: None,
+                },
+                InputDeviceOption::Tablet {
+                    path: PathBuf::from("/tmp/tablet"),
+                    width: Some(1920),
+                    height: Some(1080),
+                    name: Some("pen".to_string()),
+                },
+            ]
+        );
+    }
+
     #[test]
     fn single_touch_spec_and_track_pad_spec_default_size() {
         let config: Config = crate::crosvm::cmdline::RunCommand::from_args(
@@ -2282,6 +3356,18 @@ mod tests {
         );
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn parse_pci_segments() {
+        assert_eq!(
+            config_from_args(&["--pci", "segments=4", "/dev/null"]).pci_config,
+            PciConfig {
+                segments: Some(4),
+                ..PciConfig::default()
+            }
+        );
+    }
+
     #[test]
     fn parse_pci_mem() {
         assert_eq!(
@@ -2,7 +2,14 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+//! GDB remote stub of the guest.
+//!
+//! Each vCPU is reported to GDB as a thread whose id is the vCPU index plus one. The stub runs in
+//! all-stop mode: when a vCPU stops, the other vCPUs are suspended before the stop is reported.
+
+use std::collections::VecDeque;
 use std::net::TcpListener;
+use std::num::NonZeroUsize;
 use std::sync::mpsc;
 use std::time::Duration;
 
@@ -17,27 +24,34 @@ use base::Tube;
 use base::TubeError;
 use gdbstub::arch::Arch;
 use gdbstub::common::Signal;
+use gdbstub::common::Tid;
 use gdbstub::conn::Connection;
 use gdbstub::conn::ConnectionExt;
 use gdbstub::stub::run_blocking;
 use gdbstub::stub::run_blocking::BlockingEventLoop;
-use gdbstub::stub::SingleThreadStopReason;
+use gdbstub::stub::MultiThreadStopReason;
+use gdbstub::target::ext::base::multithread::MultiThreadBase;
+use gdbstub::target::ext::base::multithread::MultiThreadResume;
+use gdbstub::target::ext::base::multithread::MultiThreadResumeOps;
+use gdbstub::target::ext::base::multithread::MultiThreadSingleStep;
+use gdbstub::target::ext::base::multithread::MultiThreadSingleStepOps;
 use gdbstub::target::ext::base::single_register_access::SingleRegisterAccess;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use gdbstub::target::ext::base::single_register_access::SingleRegisterAccessOps;
-use gdbstub::target::ext::base::singlethread::SingleThreadBase;
-use gdbstub::target::ext::base::singlethread::SingleThreadResume;
-use gdbstub::target::ext::base::singlethread::SingleThreadResumeOps;
-use gdbstub::target::ext::base::singlethread::SingleThreadSingleStep;
-use gdbstub::target::ext::base::singlethread::SingleThreadSingleStepOps;
 use gdbstub::target::ext::base::BaseOps;
 use gdbstub::target::ext::breakpoints::Breakpoints;
 use gdbstub::target::ext::breakpoints::BreakpointsOps;
 use gdbstub::target::ext::breakpoints::HwBreakpoint;
 use gdbstub::target::ext::breakpoints::HwBreakpointOps;
+use gdbstub::target::ext::breakpoints::HwWatchpoint as GdbHwWatchpoint;
+use gdbstub::target::ext::breakpoints::HwWatchpointOps;
+use gdbstub::target::ext::breakpoints::WatchKind;
 use gdbstub::target::Target;
 use gdbstub::target::TargetError::NonFatal;
 use gdbstub::target::TargetResult;
+use hypervisor::HwWatchpoint;
+use hypervisor::WatchpointHit;
+use hypervisor::WatchpointKind;
 use remain::sorted;
 #[cfg(target_arch = "riscv64")]
 use riscv64::Riscv64 as CrosvmArch;
@@ -49,6 +63,7 @@ use vm_control::VcpuDebugStatus;
 use vm_control::VcpuDebugStatusMessage;
 use vm_control::VmRequest;
 use vm_control::VmResponse;
+use vm_control::VmRunMode;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 #[cfg(target_arch = "x86_64")]
@@ -113,14 +128,32 @@ enum Error {
 }
 type GdbResult<T> = std::result::Result<T, Error>;
 
+/// How a vCPU runs on the next resume.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResumeAction {
+    Continue,
+    Step,
+}
+
+/// Returns the GDB thread id of a vCPU.
+fn vcpu_tid(cpu: usize) -> Tid {
+    NonZeroUsize::new(cpu + 1).expect("thread id overflow")
+}
+
 pub struct GdbStub {
     vm_tube: Mutex<Tube>,
     vcpu_com: Vec<mpsc::Sender<VcpuControl>>,
     from_vcpu: mpsc::Receiver<VcpuDebugStatusMessage>,
+    /// Stops of vCPUs received while waiting for the response to a request, reported to GDB by
+    /// the next `wait_for_stop_reason`.
+    pending_stops: Mutex<VecDeque<VcpuDebugStatusMessage>>,
 
-    single_step: bool,
+    resume_actions: Vec<Option<ResumeAction>>,
+    single_step: Vec<bool>,
     max_hw_breakpoints: Option<usize>,
     hw_breakpoints: Vec<GuestAddress>,
+    max_hw_watchpoints: Option<usize>,
+    hw_watchpoints: Vec<HwWatchpoint>,
 }
 
 impl GdbStub {
@@ -129,23 +162,50 @@ impl GdbStub {
         vcpu_com: Vec<mpsc::Sender<VcpuControl>>,
         from_vcpu: mpsc::Receiver<VcpuDebugStatusMessage>,
     ) -> Self {
+        let vcpu_count = vcpu_com.len();
         GdbStub {
             vm_tube: Mutex::new(vm_tube),
             vcpu_com,
             from_vcpu,
-            single_step: false,
+            pending_stops: Default::default(),
+            resume_actions: vec![None; vcpu_count],
+            single_step: vec![false; vcpu_count],
             max_hw_breakpoints: None,
             hw_breakpoints: Default::default(),
+            max_hw_watchpoints: None,
+            hw_watchpoints: Default::default(),
         }
     }
 
-    fn vcpu_request(&self, request: VcpuControl) -> GdbResult<VcpuDebugStatus> {
-        // We use the only one vCPU when GDB is enabled.
-        self.vcpu_com[0].send(request).map_err(Error::VcpuRequest)?;
+    /// Returns the vCPU of the GDB thread `tid`.
+    fn tid_vcpu(&self, tid: Tid) -> Option<usize> {
+        let cpu = tid.get() - 1;
+        (cpu < self.vcpu_com.len()).then_some(cpu)
+    }
 
-        match self.from_vcpu.recv_timeout(Duration::from_millis(500)) {
-            Ok(msg) => Ok(msg.msg),
-            Err(e) => Err(Error::VcpuResponse(e)),
+    fn vcpu_request(&self, cpu: usize, request: VcpuControl) -> GdbResult<VcpuDebugStatus> {
+        self.vcpu_com[cpu]
+            .send(request)
+            .map_err(Error::VcpuRequest)?;
+
+        loop {
+            let msg = self
+                .from_vcpu
+                .recv_timeout(Duration::from_millis(500))
+                .map_err(Error::VcpuResponse)?;
+            match msg.msg {
+                // Another vCPU stopped before being suspended.
+                VcpuDebugStatus::HitBreakPoint | VcpuDebugStatus::HitWatchPoint(_) => {
+                    self.pending_stops.lock().push_back(msg);
+                }
+                _ if msg.cpu == cpu => return Ok(msg.msg),
+                status => {
+                    error!(
+                        "Ignoring a late response from vCPU {}: {:?}",
+                        msg.cpu, status
+                    );
+                }
+            }
         }
     }
 
@@ -160,7 +220,7 @@ impl GdbStub {
     }
 
     fn max_hw_breakpoints_request(&self) -> TargetResult<usize, Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::GetHwBreakPointCount)) {
+        match self.vcpu_request(0, VcpuControl::Debug(VcpuDebug::GetHwBreakPointCount)) {
             Ok(VcpuDebugStatus::HwBreakPointCount(n)) => Ok(n),
             Ok(s) => {
                 error!("Unexpected vCPU response for GetHwBreakPointCount: {:?}", s);
@@ -172,6 +232,111 @@ impl GdbStub {
             }
         }
     }
+
+    fn max_hw_watchpoints_request(&self) -> TargetResult<usize, Self> {
+        match self.vcpu_request(0, VcpuControl::Debug(VcpuDebug::GetHwWatchPointCount)) {
+            Ok(VcpuDebugStatus::HwWatchPointCount(n)) => Ok(n),
+            Ok(s) => {
+                error!("Unexpected vCPU response for GetHwWatchPointCount: {:?}", s);
+                Err(NonFatal)
+            }
+            Err(e) => {
+                error!("Failed to request GetHwWatchPointCount: {}", e);
+                Err(NonFatal)
+            }
+        }
+    }
+
+    /// Sends the current hardware breakpoints and watchpoints to `cpu`.
+    ///
+    /// This also stops the single-stepping of the vCPU.
+    fn set_vcpu_hw_breakpoints(&self, cpu: usize) -> TargetResult<bool, Self> {
+        match self.vcpu_request(
+            cpu,
+            VcpuControl::Debug(VcpuDebug::SetHwBreakPoint(
+                self.hw_breakpoints.clone(),
+                self.hw_watchpoints.clone(),
+            )),
+        ) {
+            Ok(VcpuDebugStatus::CommandComplete) => Ok(true),
+            Ok(s) => {
+                error!("Unexpected vCPU response for SetHwBreakPoint: {:?}", s);
+                Err(NonFatal)
+            }
+            Err(e) => {
+                error!("Failed to request SetHwBreakPoint: {}", e);
+                Err(NonFatal)
+            }
+        }
+    }
+
+    /// Sends the current hardware breakpoints and watchpoints to all the vCPUs.
+    fn set_hw_breakpoints_request(&mut self) -> TargetResult<bool, Self> {
+        for cpu in 0..self.vcpu_com.len() {
+            self.set_vcpu_hw_breakpoints(cpu)?;
+            self.single_step[cpu] = false;
+        }
+        Ok(true)
+    }
+
+    /// Returns the stop reason reported to GDB for a watchpoint `hit` on the thread `tid`.
+    fn watchpoint_stop_reason(
+        &self,
+        tid: Tid,
+        hit: WatchpointHit,
+    ) -> MultiThreadStopReason<<GdbArch as Arch>::Usize> {
+        let watchpoint = match hit {
+            WatchpointHit::Index(i) => self.hw_watchpoints.get(i),
+            WatchpointHit::Address(addr) => self
+                .hw_watchpoints
+                .iter()
+                .find(|w| (w.addr.0..w.addr.0.saturating_add(w.len)).contains(&addr)),
+        };
+        match watchpoint {
+            Some(w) => MultiThreadStopReason::Watch {
+                tid,
+                kind: match w.kind {
+                    WatchpointKind::Write => WatchKind::Write,
+                    WatchpointKind::Read => WatchKind::Read,
+                    WatchpointKind::ReadWrite => WatchKind::ReadWrite,
+                },
+                addr: w.addr.0,
+            },
+            None => {
+                error!("Hit an unknown watchpoint: {:?}", hit);
+                MultiThreadStopReason::HwBreak(tid)
+            }
+        }
+    }
+
+    /// Returns the stop reason reported to GDB for a stop `msg` of a vCPU.
+    fn stop_reason(
+        &mut self,
+        msg: VcpuDebugStatusMessage,
+    ) -> Option<MultiThreadStopReason<<GdbArch as Arch>::Usize>> {
+        let tid = vcpu_tid(msg.cpu);
+        match msg.msg {
+            VcpuDebugStatus::HitBreakPoint => {
+                if std::mem::take(&mut self.single_step[msg.cpu]) {
+                    Some(MultiThreadStopReason::SignalWithThread {
+                        tid,
+                        signal: Signal::SIGTRAP,
+                    })
+                } else if cfg!(target_arch = "riscv64") {
+                    // There are no hardware breakpoints, so the vCPU hit an `ebreak` written by
+                    // GDB.
+                    Some(MultiThreadStopReason::SwBreak(tid))
+                } else {
+                    Some(MultiThreadStopReason::HwBreak(tid))
+                }
+            }
+            VcpuDebugStatus::HitWatchPoint(hit) => Some(self.watchpoint_stop_reason(tid, hit)),
+            status => {
+                error!("Unexpected VcpuDebugStatus: {:?}", status);
+                None
+            }
+        }
+    }
 }
 
 impl Target for GdbStub {
@@ -179,10 +344,10 @@ impl Target for GdbStub {
     type Error = &'static str;
 
     fn base_ops(&mut self) -> BaseOps<Self::Arch, Self::Error> {
-        BaseOps::SingleThread(self)
+        BaseOps::MultiThread(self)
     }
 
-    // TODO(keiichiw): sw_breakpoint, hw_watchpoint, extended_mode, monitor_cmd, section_offsets
+    // TODO(keiichiw): sw_breakpoint, extended_mode, monitor_cmd, section_offsets
     fn support_breakpoints(&mut self) -> Option<BreakpointsOps<Self>> {
         Some(self)
     }
@@ -200,12 +365,14 @@ impl Target for GdbStub {
     }
 }
 
-impl SingleThreadBase for GdbStub {
+impl MultiThreadBase for GdbStub {
     fn read_registers(
         &mut self,
         regs: &mut <Self::Arch as Arch>::Registers,
+        tid: Tid,
     ) -> TargetResult<(), Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::ReadRegs)) {
+        let cpu = self.tid_vcpu(tid).ok_or(NonFatal)?;
+        match self.vcpu_request(cpu, VcpuControl::Debug(VcpuDebug::ReadRegs)) {
             Ok(VcpuDebugStatus::RegValues(r)) => {
                 *regs = r;
                 Ok(())
@@ -224,10 +391,13 @@ impl SingleThreadBase for GdbStub {
     fn write_registers(
         &mut self,
         regs: &<Self::Arch as Arch>::Registers,
+        tid: Tid,
     ) -> TargetResult<(), Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::WriteRegs(Box::new(
-            regs.clone(),
-        )))) {
+        let cpu = self.tid_vcpu(tid).ok_or(NonFatal)?;
+        match self.vcpu_request(
+            cpu,
+            VcpuControl::Debug(VcpuDebug::WriteRegs(Box::new(regs.clone()))),
+        ) {
             Ok(VcpuDebugStatus::CommandComplete) => Ok(()),
             Ok(s) => {
                 error!("Unexpected vCPU response for WriteRegs: {:?}", s);
@@ -244,11 +414,14 @@ impl SingleThreadBase for GdbStub {
         &mut self,
         start_addr: <Self::Arch as Arch>::Usize,
         data: &mut [u8],
+        tid: Tid,
     ) -> TargetResult<usize, Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::ReadMem(
-            GuestAddress(start_addr),
-            data.len(),
-        ))) {
+        // The virtual address is translated with the page tables of the vCPU.
+        let cpu = self.tid_vcpu(tid).ok_or(NonFatal)?;
+        match self.vcpu_request(
+            cpu,
+            VcpuControl::Debug(VcpuDebug::ReadMem(GuestAddress(start_addr), data.len())),
+        ) {
             Ok(VcpuDebugStatus::MemoryRegion(r)) => {
                 for (dst, v) in data.iter_mut().zip(r.iter()) {
                     *dst = *v;
@@ -270,11 +443,16 @@ impl SingleThreadBase for GdbStub {
         &mut self,
         start_addr: <Self::Arch as Arch>::Usize,
         data: &[u8],
+        tid: Tid,
     ) -> TargetResult<(), Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::WriteMem(
-            GuestAddress(start_addr),
-            data.to_owned(),
-        ))) {
+        let cpu = self.tid_vcpu(tid).ok_or(NonFatal)?;
+        match self.vcpu_request(
+            cpu,
+            VcpuControl::Debug(VcpuDebug::WriteMem(
+                GuestAddress(start_addr),
+                data.to_owned(),
+            )),
+        ) {
             Ok(VcpuDebugStatus::CommandComplete) => Ok(()),
             Ok(s) => {
                 error!("Unexpected vCPU response for WriteMem: {:?}", s);
@@ -287,57 +465,122 @@ impl SingleThreadBase for GdbStub {
         }
     }
 
+    fn list_active_threads(
+        &mut self,
+        thread_is_active: &mut dyn FnMut(Tid),
+    ) -> Result<(), Self::Error> {
+        for cpu in 0..self.vcpu_com.len() {
+            thread_is_active(vcpu_tid(cpu));
+        }
+        Ok(())
+    }
+
     #[inline(always)]
-    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<Self>> {
+    fn support_resume(&mut self) -> Option<MultiThreadResumeOps<Self>> {
         Some(self)
     }
 
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     #[inline(always)]
-    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<(), Self>> {
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<Tid, Self>> {
         Some(self)
     }
 }
 
-impl SingleThreadResume for GdbStub {
-    fn resume(&mut self, _signal: Option<Signal>) -> Result<(), Self::Error> {
+impl MultiThreadResume for GdbStub {
+    fn resume(&mut self) -> Result<(), Self::Error> {
         // TODO: Handle any incoming signal.
 
-        self.vm_request(VmRequest::ResumeVcpus).map_err(|e| {
-            error!("Failed to resume the target: {}", e);
-            "Failed to resume the target"
-        })
-    }
+        // GDB gives no action to any thread when all of them continue. Otherwise, only the
+        // vCPUs given an action run and the others stay stopped, which is what GDB expects from
+        // `set scheduler-locking on`.
+        let all_resumed = self.resume_actions.iter().all(Option::is_none);
+        for cpu in 0..self.vcpu_com.len() {
+            let action = match self.resume_actions[cpu] {
+                Some(action) => action,
+                None if all_resumed => ResumeAction::Continue,
+                None => continue,
+            };
+            match action {
+                ResumeAction::Step => {
+                    match self.vcpu_request(cpu, VcpuControl::Debug(VcpuDebug::EnableSinglestep)) {
+                        Ok(VcpuDebugStatus::CommandComplete) => {
+                            self.single_step[cpu] = true;
+                        }
+                        Ok(s) => {
+                            error!("Unexpected vCPU response for EnableSinglestep: {:?}", s);
+                            return Err("Unexpected vCPU response for EnableSinglestep");
+                        }
+                        Err(e) => {
+                            error!("Failed to request EnableSinglestep: {}", e);
+                            return Err("Failed to request EnableSinglestep");
+                        }
+                    }
+                }
+                ResumeAction::Continue if self.single_step[cpu] => {
+                    // The vCPU was stepping but another vCPU stopped first.
+                    self.set_vcpu_hw_breakpoints(cpu)
+                        .map_err(|_| "Failed to stop single-stepping")?;
+                    self.single_step[cpu] = false;
+                }
+                ResumeAction::Continue => {}
+            }
+        }
 
-    #[inline(always)]
-    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
-        Some(self)
+        if all_resumed || self.resume_actions.iter().all(Option::is_some) {
+            return self.vm_request(VmRequest::ResumeVcpus).map_err(|e| {
+                error!("Failed to resume the target: {}", e);
+                "Failed to resume the target"
+            });
+        }
+        // The stopped vCPUs wait for a message, so they can be resumed without being kicked.
+        for (cpu, action) in self.resume_actions.iter().enumerate() {
+            if action.is_some() {
+                self.vcpu_com[cpu]
+                    .send(VcpuControl::RunState(VmRunMode::Running))
+                    .map_err(|e| {
+                        error!("Failed to resume vCPU {}: {}", cpu, e);
+                        "Failed to resume the target"
+                    })?;
+            }
+        }
+        Ok(())
     }
-}
 
-impl SingleThreadSingleStep for GdbStub {
-    fn step(&mut self, _signal: Option<Signal>) -> Result<(), Self::Error> {
-        // TODO: Handle any incoming signal.
+    fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.resume_actions.fill(None);
+        Ok(())
+    }
 
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::EnableSinglestep)) {
-            Ok(VcpuDebugStatus::CommandComplete) => {
-                self.single_step = true;
-            }
-            Ok(s) => {
-                error!("Unexpected vCPU response for EnableSinglestep: {:?}", s);
-                return Err("Unexpected vCPU response for EnableSinglestep");
-            }
-            Err(e) => {
-                error!("Failed to request EnableSinglestep: {}", e);
-                return Err("Failed to request EnableSinglestep");
-            }
-        };
+    fn set_resume_action_continue(
+        &mut self,
+        tid: Tid,
+        _signal: Option<Signal>,
+    ) -> Result<(), Self::Error> {
+        let cpu = self.tid_vcpu(tid).ok_or("Invalid thread id")?;
+        self.resume_actions[cpu] = Some(ResumeAction::Continue);
+        Ok(())
+    }
 
-        self.vm_request(VmRequest::ResumeVcpus).map_err(|e| {
-            error!("Failed to resume the target: {}", e);
-            "Failed to resume the target"
-        })?;
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<MultiThreadSingleStepOps<'_, Self>> {
+        // RISC-V vCPUs cannot be single-stepped, GDB steps with temporary breakpoints instead.
+        if cfg!(target_arch = "riscv64") {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
 
+impl MultiThreadSingleStep for GdbStub {
+    fn set_resume_action_step(
+        &mut self,
+        tid: Tid,
+        _signal: Option<Signal>,
+    ) -> Result<(), Self::Error> {
+        let cpu = self.tid_vcpu(tid).ok_or("Invalid thread id")?;
+        self.resume_actions[cpu] = Some(ResumeAction::Step);
         Ok(())
     }
 }
@@ -346,6 +589,10 @@ impl Breakpoints for GdbStub {
     fn support_hw_breakpoint(&mut self) -> Option<HwBreakpointOps<Self>> {
         Some(self)
     }
+
+    fn support_hw_watchpoint(&mut self) -> Option<HwWatchpointOps<Self>> {
+        Some(self)
+    }
 }
 
 impl HwBreakpoint for GdbStub {
@@ -368,19 +615,7 @@ impl HwBreakpoint for GdbStub {
         }
         self.hw_breakpoints.push(GuestAddress(addr));
 
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::SetHwBreakPoint(
-            self.hw_breakpoints.clone(),
-        ))) {
-            Ok(VcpuDebugStatus::CommandComplete) => Ok(true),
-            Ok(s) => {
-                error!("Unexpected vCPU response for SetHwBreakPoint: {:?}", s);
-                Err(NonFatal)
-            }
-            Err(e) => {
-                error!("Failed to request SetHwBreakPoint: {}", e);
-                Err(NonFatal)
-            }
-        }
+        self.set_hw_breakpoints_request()
     }
 
     /// Remove an existing hardware breakpoint.
@@ -392,30 +627,72 @@ impl HwBreakpoint for GdbStub {
     ) -> TargetResult<bool, Self> {
         self.hw_breakpoints.retain(|&b| b.0 != addr);
 
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::SetHwBreakPoint(
-            self.hw_breakpoints.clone(),
-        ))) {
-            Ok(VcpuDebugStatus::CommandComplete) => Ok(true),
-            Ok(s) => {
-                error!("Unexpected vCPU response for SetHwBreakPoint: {:?}", s);
-                Err(NonFatal)
-            }
-            Err(e) => {
-                error!("Failed to request SetHwBreakPoint: {}", e);
-                Err(NonFatal)
-            }
+        self.set_hw_breakpoints_request()
+    }
+}
+
+impl GdbHwWatchpoint for GdbStub {
+    /// Add a new hardware watchpoint.
+    /// Return `Ok(false)` if the operation could not be completed.
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: <Self::Arch as Arch>::Usize,
+        len: <Self::Arch as Arch>::Usize,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        let max_count = *(match &mut self.max_hw_watchpoints {
+            None => self
+                .max_hw_watchpoints
+                .insert(self.max_hw_watchpoints_request()?),
+            Some(c) => c,
+        });
+        if self.hw_watchpoints.len() >= max_count {
+            error!("Not allowed to set more than {} HW watchpoints", max_count);
+            return Err(NonFatal);
+        }
+        let kind = match kind {
+            WatchKind::Write => WatchpointKind::Write,
+            WatchKind::Read => WatchpointKind::Read,
+            WatchKind::ReadWrite => WatchpointKind::ReadWrite,
+        };
+        self.hw_watchpoints.push(HwWatchpoint {
+            addr: GuestAddress(addr),
+            len,
+            kind,
+        });
+
+        let result = self.set_hw_breakpoints_request();
+        if result.is_err() {
+            // The watchpoint may be unsupported by the hardware, e.g. because of its size.
+            self.hw_watchpoints.pop();
         }
+        result
+    }
+
+    /// Remove an existing hardware watchpoint.
+    /// Return `Ok(false)` if the operation could not be completed.
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: <Self::Arch as Arch>::Usize,
+        len: <Self::Arch as Arch>::Usize,
+        _kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        self.hw_watchpoints
+            .retain(|w| w.addr.0 != addr || w.len != len);
+
+        self.set_hw_breakpoints_request()
     }
 }
 
-impl SingleRegisterAccess<()> for GdbStub {
+impl SingleRegisterAccess<Tid> for GdbStub {
     fn read_register(
         &mut self,
-        _tid: (),
+        tid: Tid,
         reg_id: <Self::Arch as Arch>::RegId,
         buf: &mut [u8],
     ) -> TargetResult<usize, Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::ReadReg(reg_id))) {
+        let cpu = self.tid_vcpu(tid).ok_or(NonFatal)?;
+        match self.vcpu_request(cpu, VcpuControl::Debug(VcpuDebug::ReadReg(reg_id))) {
             Ok(VcpuDebugStatus::RegValue(r)) => {
                 if !r.is_empty() && buf.len() != r.len() {
                     error!(
@@ -443,14 +720,15 @@ impl SingleRegisterAccess<()> for GdbStub {
 
     fn write_register(
         &mut self,
-        _tid: (),
+        tid: Tid,
         reg_id: <Self::Arch as Arch>::RegId,
         val: &[u8],
     ) -> TargetResult<(), Self> {
-        match self.vcpu_request(VcpuControl::Debug(VcpuDebug::WriteReg(
-            reg_id,
-            val.to_owned(),
-        ))) {
+        let cpu = self.tid_vcpu(tid).ok_or(NonFatal)?;
+        match self.vcpu_request(
+            cpu,
+            VcpuControl::Debug(VcpuDebug::WriteReg(reg_id, val.to_owned())),
+        ) {
             Ok(VcpuDebugStatus::CommandComplete) => Ok(()),
             Ok(s) => {
                 error!("Unexpected vCPU response for WriteReg: {:?}", s);
@@ -469,7 +747,7 @@ struct GdbStubEventLoop;
 impl BlockingEventLoop for GdbStubEventLoop {
     type Target = GdbStub;
     type Connection = Box<dyn ConnectionExt<Error = std::io::Error>>;
-    type StopReason = SingleThreadStopReason<<GdbArch as Arch>::Usize>;
+    type StopReason = MultiThreadStopReason<<GdbArch as Arch>::Usize>;
 
     fn wait_for_stop_reason(
         target: &mut Self::Target,
@@ -483,27 +761,20 @@ impl BlockingEventLoop for GdbStubEventLoop {
     > {
         loop {
             // TODO(keiichiw): handle error?
-            if let Ok(msg) = target
-                .from_vcpu
-                .recv_timeout(std::time::Duration::from_millis(100))
-            {
-                match msg.msg {
-                    VcpuDebugStatus::HitBreakPoint => {
-                        if target.single_step {
-                            target.single_step = false;
-                            return Ok(run_blocking::Event::TargetStopped(
-                                SingleThreadStopReason::DoneStep,
-                            ));
-                        } else {
-                            return Ok(run_blocking::Event::TargetStopped(
-                                SingleThreadStopReason::HwBreak(()),
-                            ));
-                        }
-                    }
-                    status => {
-                        error!("Unexpected VcpuDebugStatus: {:?}", status);
-                    }
-                }
+            let pending_stop = target.pending_stops.lock().pop_front();
+            let msg = pending_stop.or_else(|| {
+                target
+                    .from_vcpu
+                    .recv_timeout(std::time::Duration::from_millis(100))
+                    .ok()
+            });
+            if let Some(reason) = msg.and_then(|msg| target.stop_reason(msg)) {
+                // Stop all the other vCPUs before reporting the stop.
+                target.vm_request(VmRequest::SuspendVcpus).map_err(|e| {
+                    error!("Failed to suspend the target: {}", e);
+                    run_blocking::WaitForStopReasonError::Target("Failed to suspend the target")
+                })?;
+                return Ok(run_blocking::Event::TargetStopped(reason));
             }
 
             // If no message was received within the timeout check for incoming data from
@@ -529,10 +800,28 @@ impl BlockingEventLoop for GdbStubEventLoop {
             "Failed to suspend the target"
         })?;
 
-        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+        Ok(Some(MultiThreadStopReason::SignalWithThread {
+            tid: vcpu_tid(0),
+            signal: Signal::SIGINT,
+        }))
     }
 }
 
+/// Prepare a VCPU to be debugged if a GDB thread is running.
+pub fn vcpu_init_debug<V>(
+    vcpu: &V,
+    to_gdb_tube: Option<&mpsc::Sender<VcpuDebugStatusMessage>>,
+) -> anyhow::Result<()>
+where
+    V: VcpuArch + 'static,
+{
+    if to_gdb_tube.is_some() {
+        <CrosvmArch as arch::GdbOps<V>>::init_debug(vcpu)
+            .context("failed to prepare the vcpu for debugging")?;
+    }
+    Ok(())
+}
+
 /// Notify the GDB thread that a VCPU has stopped because of a breakpoint.
 pub fn vcpu_exit_debug(
     cpu: usize,
@@ -548,6 +837,22 @@ pub fn vcpu_exit_debug(
     Ok(())
 }
 
+/// Notify the GDB thread that a VCPU has stopped because of a watchpoint.
+pub fn vcpu_exit_watchpoint(
+    cpu: usize,
+    to_gdb_tube: Option<&mpsc::Sender<VcpuDebugStatusMessage>>,
+    hit: WatchpointHit,
+) -> anyhow::Result<()> {
+    if let Some(ch) = to_gdb_tube.as_ref() {
+        ch.send(VcpuDebugStatusMessage {
+            cpu,
+            msg: VcpuDebugStatus::HitWatchPoint(hit),
+        })
+        .context("failed to send watchpoint status to gdb thread")?;
+    }
+    Ok(())
+}
+
 /// Handle a `VcpuDebug` request for a given `vcpu`.
 pub fn vcpu_control_debug<V>(
     cpu_id: usize,
@@ -600,8 +905,12 @@ where
             <CrosvmArch as arch::GdbOps<V>>::get_max_hw_breakpoints(vcpu as &V)
                 .context("failed to get max number of HW breakpoints")?,
         ),
-        VcpuDebug::SetHwBreakPoint(addrs) => {
-            <CrosvmArch as arch::GdbOps<V>>::set_hw_breakpoints(vcpu as &V, &addrs)
+        VcpuDebug::GetHwWatchPointCount => VcpuDebugStatus::HwWatchPointCount(
+            <CrosvmArch as arch::GdbOps<V>>::get_max_hw_watchpoints(vcpu as &V)
+                .context("failed to get max number of HW watchpoints")?,
+        ),
+        VcpuDebug::SetHwBreakPoint(addrs, watchpoints) => {
+            <CrosvmArch as arch::GdbOps<V>>::set_hw_breakpoints(vcpu as &V, &addrs, &watchpoints)
                 .context("failed to handle a gdb SetHwBreakPoint command")?;
             VcpuDebugStatus::CommandComplete
         }
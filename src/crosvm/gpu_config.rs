@@ -124,6 +124,18 @@ pub(crate) fn validate_gpu_config(cfg: &mut Config) -> Result<(), String> {
             ));
         }
 
+        let mut blob_ids = std::collections::BTreeSet::new();
+        if let Some(blob_file) = gpu_parameters
+            .blob_files
+            .iter()
+            .find(|blob_file| !blob_ids.insert(blob_file.id))
+        {
+            return Err(format!(
+                "`blob-files` has more than one file with id {}",
+                blob_file.id
+            ));
+        }
+
         // Add a default display if no display is specified.
         if gpu_parameters.display_params.is_empty() {
             gpu_parameters.display_params.push(Default::default());
@@ -141,6 +153,7 @@ pub(crate) fn validate_gpu_config(cfg: &mut Config) -> Result<(), String> {
 #[cfg(test)]
 mod tests {
     use argh::FromArgs;
+    use devices::virtio::gpu::GpuBlobFile;
     #[cfg(feature = "gfxstream")]
     use devices::virtio::GpuWsi;
 
@@ -414,6 +427,32 @@ mod tests {
         assert_eq!(gpu_params.cache_size, Some("16384".into()));
     }
 
+    #[test]
+    fn parse_gpu_options_blob_files() {
+        let gpu_params =
+            parse_gpu_options("blob-files=[[id=1,path=/a.pak],[id=2,path=/b.pak]]").unwrap();
+        assert_eq!(
+            gpu_params.blob_files,
+            vec![
+                GpuBlobFile {
+                    id: 1,
+                    path: "/a.pak".into(),
+                },
+                GpuBlobFile {
+                    id: 2,
+                    path: "/b.pak".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_gpu_options_memory_budget() {
+        assert_eq!(parse_gpu_options("").unwrap().memory_budget_mib, None);
+        let gpu_params = parse_gpu_options("memory-budget-mib=2048").unwrap();
+        assert_eq!(gpu_params.memory_budget_mib, Some(2048));
+    }
+
     #[test]
     fn parse_gpu_options_pci_bar() {
         let gpu_params = parse_gpu_options("pci-bar-size=0x100000").unwrap();
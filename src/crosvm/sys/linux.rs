@@ -4,19 +4,29 @@
 
 #[cfg(target_os = "android")]
 mod android;
+mod api_server;
 pub mod cmdline;
 pub mod config;
+mod config_reload;
+mod core_dump;
 mod device_helpers;
+#[cfg(feature = "pci-hotplug")]
+mod evdev_hotplug;
 pub(crate) mod ext2;
 #[cfg(feature = "gpu")]
 pub(crate) mod gpu;
 #[cfg(feature = "pci-hotplug")]
 pub(crate) mod jail_warden;
+mod metrics_server;
 #[cfg(feature = "pci-hotplug")]
 pub(crate) mod pci_hotplug_helpers;
 #[cfg(feature = "pci-hotplug")]
 pub(crate) mod pci_hotplug_manager;
+mod qmp;
+#[cfg(feature = "swap")]
+mod swap_policy;
 mod vcpu;
+mod vcpu_boost;
 
 #[cfg(all(feature = "pvclock", target_arch = "aarch64"))]
 use std::arch::asm;
@@ -36,13 +46,17 @@ use std::fs::OpenOptions;
 #[cfg(feature = "registered_events")]
 use std::hash::Hash;
 use std::io::stdin;
+use std::io::BufWriter;
 use std::iter;
 use std::mem;
 #[cfg(target_arch = "x86_64")]
 use std::ops::RangeInclusive;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
 use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
-#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use std::path::PathBuf;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use std::process;
@@ -52,6 +66,8 @@ use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Barrier;
 use std::thread::JoinHandle;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use aarch64::AArch64 as Arch;
@@ -66,6 +82,8 @@ use arch::LinuxArch;
 use arch::RunnableLinuxVm;
 use arch::VcpuAffinity;
 use arch::VcpuArch;
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+use arch::VcpuCache;
 use arch::VirtioDeviceStub;
 use arch::VmArch;
 use arch::VmComponents;
@@ -75,10 +93,13 @@ use base::ReadNotifier;
 #[cfg(feature = "balloon")]
 use base::UnixSeqpacket;
 use base::UnixSeqpacketListener;
+use base::UnlinkUnixListener;
 use base::UnlinkUnixSeqpacketListener;
 use base::*;
 use cros_async::Executor;
 use device_helpers::*;
+#[cfg(target_arch = "x86_64")]
+use devices::cmos::RtcOffset;
 use devices::create_devices_worker_thread;
 use devices::serial_device::SerialHardware;
 #[cfg(all(feature = "pvclock", target_arch = "x86_64"))]
@@ -86,6 +107,8 @@ use devices::tsc::get_tsc_sync_mitigations;
 use devices::vfio::VfioContainerManager;
 #[cfg(feature = "gpu")]
 use devices::virtio;
+#[cfg(feature = "pci-hotplug")]
+use devices::virtio::block::DiskOption;
 #[cfg(any(feature = "video-decoder", feature = "video-encoder"))]
 use devices::virtio::device_constants::video::VideoDeviceType;
 #[cfg(feature = "gpu")]
@@ -103,22 +126,26 @@ use devices::virtio::NetParameters;
 use devices::virtio::NetParametersMode;
 use devices::virtio::VirtioDevice;
 use devices::virtio::VirtioDeviceType;
+#[cfg(feature = "pci-hotplug")]
+use devices::BlockResourceCarrier;
 use devices::Bus;
 use devices::BusDeviceObj;
 use devices::BusType;
 use devices::CoIommuDev;
 #[cfg(feature = "usb")]
 use devices::DeviceProvider;
+#[cfg(feature = "pci-hotplug")]
+use devices::EvdevResourceCarrier;
 #[cfg(target_arch = "x86_64")]
 use devices::HotPlugBus;
-#[cfg(target_arch = "x86_64")]
+#[cfg(any(target_arch = "x86_64", feature = "pci-hotplug"))]
 use devices::HotPlugKey;
 use devices::IommuDevType;
 use devices::IrqEventIndex;
 use devices::IrqEventSource;
 #[cfg(feature = "pci-hotplug")]
 use devices::NetResourceCarrier;
-#[cfg(target_arch = "x86_64")]
+#[cfg(any(target_arch = "x86_64", feature = "pci-hotplug"))]
 use devices::PciAddress;
 #[cfg(target_arch = "x86_64")]
 use devices::PciBridge;
@@ -142,6 +169,8 @@ use devices::PvPanicPciDevice;
 #[cfg(feature = "pci-hotplug")]
 use devices::ResourceCarrier;
 use devices::StubPciDevice;
+#[cfg(feature = "vtpm")]
+use devices::TpmInterface;
 use devices::VirtioPciDevice;
 #[cfg(feature = "usb")]
 use devices::XhciController;
@@ -176,21 +205,29 @@ use resources::SystemAllocator;
 use riscv64::Riscv64 as Arch;
 use rutabaga_gfx::RutabagaGralloc;
 use rutabaga_gfx::RutabagaGrallocBackendFlags;
+#[cfg(feature = "pci-hotplug")]
+use serde_keyvalue::from_key_values;
 use smallvec::SmallVec;
 #[cfg(feature = "swap")]
 use swap::SwapController;
 use sync::Condvar;
 use sync::Mutex;
 use vm_control::api::VmMemoryClient;
+use vm_control::extension::ExtensionRequest;
+use vm_control::extension::VmEvent;
 use vm_control::*;
 use vm_memory::FileBackedMappingParameters;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
+use vm_memory::MemoryBacking;
 use vm_memory::MemoryPolicy;
 use vm_memory::MemoryRegionOptions;
+use vm_memory::MemoryRegionPurpose;
 #[cfg(target_arch = "x86_64")]
 use x86_64::X8664arch as Arch;
 
+use crate::crosvm::config::save_next_boot_params;
+use crate::crosvm::config::take_next_boot_params;
 use crate::crosvm::config::Config;
 use crate::crosvm::config::Executable;
 use crate::crosvm::config::HypervisorKind;
@@ -208,6 +245,8 @@ use crate::crosvm::sys::cmdline::DevicesCommand;
 use crate::crosvm::sys::config::SharedDir;
 use crate::crosvm::sys::config::SharedDirKind;
 use crate::crosvm::sys::platform::vcpu::VcpuPidTid;
+use crate::crosvm::sys::platform::vcpu_boost;
+use crate::crosvm::sys::platform::vcpu_boost::VcpuBoost;
 
 const KVM_PATH: &str = "/dev/kvm";
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
@@ -227,8 +266,16 @@ fn create_virtio_devices(
     #[cfg(feature = "gpu")] render_server_fd: Option<SafeDescriptor>,
     #[cfg(feature = "gpu")] has_vfio_gfx_device: bool,
     #[cfg(feature = "registered_events")] registered_evt_q: &SendTube,
+    input_wakeup_evt: Option<&Event>,
 ) -> DeviceResult<Vec<VirtioDeviceStub>> {
     let mut devs = Vec::new();
+    // Every virtio-input device signals its own handle of the wakeup event.
+    let clone_input_wakeup_evt = || {
+        input_wakeup_evt
+            .map(Event::try_clone)
+            .transpose()
+            .context("failed to clone input wakeup event")
+    };
 
     #[cfg(any(feature = "gpu", feature = "video-decoder", feature = "video-encoder"))]
     let mut resource_bridges = Vec::<Tube>::new();
@@ -307,11 +354,13 @@ fn create_virtio_devices(
                         .context("failed to create socket")?;
                 let mut multi_touch_width = gpu_display_w;
                 let mut multi_touch_height = gpu_display_h;
+                let mut multi_touch_slots = None;
                 let mut multi_touch_name = None;
                 for input in &cfg.virtio_input {
                     if let InputDeviceOption::MultiTouch {
                         width,
                         height,
+                        slots,
                         name,
                         ..
                     } = input
@@ -322,34 +371,89 @@ fn create_virtio_devices(
                         if let Some(height) = height {
                             multi_touch_height = *height;
                         }
+                        multi_touch_slots = *slots;
                         if let Some(name) = name {
                             multi_touch_name = Some(name.as_str());
                         }
                         break;
                     }
                 }
-                let dev = virtio::input::new_multi_touch(
+                let mut dev = virtio::input::new_multi_touch(
                     // u32::MAX is the least likely to collide with the indices generated above for
                     // the multi_touch options, which begin at 0.
                     u32::MAX,
                     virtio_dev_socket,
                     multi_touch_width,
                     multi_touch_height,
+                    multi_touch_slots,
                     multi_touch_name,
                     virtio::base_features(cfg.protection_type),
                 )
                 .context("failed to set up mouse device")?;
+                dev.set_wakeup_event(clone_input_wakeup_evt()?);
                 devs.push(VirtioDeviceStub {
                     dev: Box::new(dev),
                     jail: simple_jail(cfg.jail_config.as_ref(), "input_device")?,
                 });
                 event_devices.push(EventDevice::touchscreen(event_device_socket));
             }
+            if cfg.display_window_tablet {
+                let display_param = if gpu_parameters.display_params.is_empty() {
+                    Default::default()
+                } else {
+                    gpu_parameters.display_params[0].clone()
+                };
+                let (gpu_display_w, gpu_display_h) = display_param.get_virtual_display_size();
+
+                let (event_device_socket, virtio_dev_socket) =
+                    StreamChannel::pair(BlockingMode::Nonblocking, FramingMode::Byte)
+                        .context("failed to create socket")?;
+                let mut tablet_width = gpu_display_w;
+                let mut tablet_height = gpu_display_h;
+                let mut tablet_name = None;
+                for input in &cfg.virtio_input {
+                    if let InputDeviceOption::Tablet {
+                        width,
+                        height,
+                        name,
+                        ..
+                    } = input
+                    {
+                        if let Some(width) = width {
+                            tablet_width = *width;
+                        }
+                        if let Some(height) = height {
+                            tablet_height = *height;
+                        }
+                        if let Some(name) = name {
+                            tablet_name = Some(name.as_str());
+                        }
+                        break;
+                    }
+                }
+                let mut dev = virtio::input::new_tablet(
+                    // u32::MAX is the least likely to collide with the indices generated above for
+                    // the tablet options, which begin at 0.
+                    u32::MAX,
+                    virtio_dev_socket,
+                    tablet_width,
+                    tablet_height,
+                    tablet_name,
+                    virtio::base_features(cfg.protection_type),
+                )
+                .context("failed to set up tablet device")?;
+                dev.set_wakeup_event(clone_input_wakeup_evt()?);
+                devs.push(VirtioDeviceStub {
+                    dev: Box::new(dev),
+                    jail: simple_jail(cfg.jail_config.as_ref(), "input_device")?,
+                });
+                event_devices.push(EventDevice::tablet(event_device_socket));
+            }
             if cfg.display_window_keyboard {
                 let (event_device_socket, virtio_dev_socket) =
                     StreamChannel::pair(BlockingMode::Nonblocking, FramingMode::Byte)
                         .context("failed to create socket")?;
-                let dev = virtio::input::new_keyboard(
+                let mut dev = virtio::input::new_keyboard(
                     // u32::MAX is the least likely to collide with the indices generated above for
                     // the multi_touch options, which begin at 0.
                     u32::MAX,
@@ -357,6 +461,7 @@ fn create_virtio_devices(
                     virtio::base_features(cfg.protection_type),
                 )
                 .context("failed to set up keyboard device")?;
+                dev.set_wakeup_event(clone_input_wakeup_evt()?);
                 devs.push(VirtioDeviceStub {
                     dev: Box::new(dev),
                     jail: simple_jail(cfg.jail_config.as_ref(), "input_device")?,
@@ -375,6 +480,12 @@ fn create_virtio_devices(
                 render_server_fd,
                 has_vfio_gfx_device,
                 event_devices,
+                #[cfg(feature = "registered_events")]
+                Some(
+                    registered_evt_q
+                        .try_clone()
+                        .context("failed to clone registered_evt_q tube")?,
+                ),
             )?);
         }
     }
@@ -384,9 +495,19 @@ fn create_virtio_devices(
         .iter()
         .filter(|(_k, v)| v.hardware == SerialHardware::VirtioConsole)
     {
-        let dev =
-            param.create_virtio_device_and_jail(cfg.protection_type, cfg.jail_config.as_ref())?;
-        devs.push(dev);
+        let console_device_tube = if param.max_ports.is_some() {
+            let (console_host_tube, console_device_tube) =
+                Tube::pair().context("failed to create tube")?;
+            add_control_tube(DeviceControlTube::Console(console_host_tube).into());
+            Some(console_device_tube)
+        } else {
+            None
+        };
+        let console_config = ConsoleConfig::new(param, console_device_tube);
+        devs.push(
+            console_config
+                .create_virtio_device_and_jail(cfg.protection_type, cfg.jail_config.as_ref())?,
+        );
     }
 
     for disk in &cfg.disks {
@@ -514,10 +635,49 @@ fn create_virtio_devices(
         }
     }
 
+    #[cfg(feature = "vtpm")]
+    if let Some(swtpm_params) = cfg
+        .swtpm
+        .as_ref()
+        .filter(|p| p.interface == TpmInterface::Virtio)
+    {
+        devs.push(create_swtpm_device(
+            cfg.protection_type,
+            cfg.jail_config.as_ref(),
+            swtpm_params,
+        )?);
+    }
+
+    for can_params in &cfg.can {
+        devs.push(create_can_device(
+            cfg.protection_type,
+            cfg.jail_config.as_ref(),
+            can_params,
+        )?);
+    }
+
+    for spi_params in &cfg.spi {
+        devs.push(create_spi_device(
+            cfg.protection_type,
+            cfg.jail_config.as_ref(),
+            spi_params,
+        )?);
+    }
+
+    #[cfg(feature = "wasm-devices")]
+    for wasm_params in &cfg.wasm_devices {
+        devs.push(create_wasm_device(
+            cfg.protection_type,
+            cfg.jail_config.as_ref(),
+            wasm_params,
+        )?);
+    }
+
     let mut keyboard_idx = 0;
     let mut mouse_idx = 0;
     let mut rotary_idx = 0;
     let mut switches_idx = 0;
+    let mut tablet_idx = 0;
     let mut multi_touch_idx = 0;
     let mut single_touch_idx = 0;
     let mut trackpad_idx = 0;
@@ -529,13 +689,17 @@ fn create_virtio_devices(
                 cfg.protection_type,
                 cfg.jail_config.as_ref(),
                 path.as_path(),
+                clone_input_wakeup_evt()?,
             )?,
+            // Attached once the VM is running, see `evdev_hotplug`.
+            InputDeviceOption::EvdevHotplug { .. } => continue,
             InputDeviceOption::Keyboard { path } => {
                 let dev = create_keyboard_device(
                     cfg.protection_type,
                     cfg.jail_config.as_ref(),
                     path.as_path(),
                     keyboard_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 keyboard_idx += 1;
                 dev
@@ -546,6 +710,7 @@ fn create_virtio_devices(
                     cfg.jail_config.as_ref(),
                     path.as_path(),
                     mouse_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 mouse_idx += 1;
                 dev
@@ -554,6 +719,7 @@ fn create_virtio_devices(
                 path,
                 width,
                 height,
+                slots,
                 name,
             } => {
                 let mut width = *width;
@@ -572,8 +738,10 @@ fn create_virtio_devices(
                     path.as_path(),
                     width.unwrap_or(DEFAULT_TOUCH_DEVICE_WIDTH),
                     height.unwrap_or(DEFAULT_TOUCH_DEVICE_HEIGHT),
+                    *slots,
                     name.as_deref(),
                     multi_touch_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 multi_touch_idx += 1;
                 dev
@@ -584,6 +752,7 @@ fn create_virtio_devices(
                     cfg.jail_config.as_ref(),
                     path.as_path(),
                     rotary_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 rotary_idx += 1;
                 dev
@@ -612,6 +781,7 @@ fn create_virtio_devices(
                     height.unwrap_or(DEFAULT_TOUCH_DEVICE_HEIGHT),
                     name.as_deref(),
                     single_touch_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 single_touch_idx += 1;
                 dev
@@ -622,10 +792,30 @@ fn create_virtio_devices(
                     cfg.jail_config.as_ref(),
                     path.as_path(),
                     switches_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 switches_idx += 1;
                 dev
             }
+            InputDeviceOption::Tablet {
+                path,
+                width,
+                height,
+                name,
+            } => {
+                let dev = create_tablet_device(
+                    cfg.protection_type,
+                    cfg.jail_config.as_ref(),
+                    path.as_path(),
+                    width.unwrap_or(DEFAULT_TOUCH_DEVICE_WIDTH),
+                    height.unwrap_or(DEFAULT_TOUCH_DEVICE_HEIGHT),
+                    name.as_deref(),
+                    tablet_idx,
+                    clone_input_wakeup_evt()?,
+                )?;
+                tablet_idx += 1;
+                dev
+            }
             InputDeviceOption::Trackpad {
                 path,
                 width,
@@ -640,6 +830,7 @@ fn create_virtio_devices(
                     height.unwrap_or(DEFAULT_TOUCH_DEVICE_HEIGHT),
                     name.as_deref(),
                     trackpad_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 trackpad_idx += 1;
                 dev
@@ -658,6 +849,7 @@ fn create_virtio_devices(
                     height.unwrap_or(DEFAULT_TOUCH_DEVICE_HEIGHT),
                     name.as_deref(),
                     multi_touch_trackpad_idx,
+                    clone_input_wakeup_evt()?,
                 )?;
                 multi_touch_trackpad_idx += 1;
                 dev
@@ -669,6 +861,7 @@ fn create_virtio_devices(
                     path.as_path(),
                     custom_idx,
                     config_path.clone(),
+                    clone_input_wakeup_evt()?,
                 )?;
                 custom_idx += 1;
                 dev
@@ -749,8 +942,15 @@ fn create_virtio_devices(
 
     #[cfg(feature = "net")]
     for opt in &cfg.net {
-        let dev =
-            opt.create_virtio_device_and_jail(cfg.protection_type, cfg.jail_config.as_ref())?;
+        let net_device_tube = if opt.vhost_net.is_none() {
+            let (net_host_tube, net_device_tube) = Tube::pair().context("failed to create tube")?;
+            add_control_tube(DeviceControlTube::Net(net_host_tube).into());
+            Some(net_device_tube)
+        } else {
+            None
+        };
+        let dev = NetConfig::new(opt, net_device_tube)
+            .create_virtio_device_and_jail(cfg.protection_type, cfg.jail_config.as_ref())?;
         devs.push(dev);
     }
 
@@ -858,6 +1058,9 @@ fn create_virtio_devices(
             SharedDirKind::FS => {
                 let (host_tube, device_tube) = Tube::pair().context("failed to create tube")?;
                 add_control_tube(TaggedControlTube::Fs(host_tube).into());
+                let (fs_host_tube, fs_device_tube) =
+                    Tube::pair().context("failed to create tube")?;
+                add_control_tube(DeviceControlTube::Fs(fs_host_tube).into());
 
                 create_fs_device(
                     cfg.protection_type,
@@ -869,6 +1072,7 @@ fn create_virtio_devices(
                     tag,
                     fs_cfg.clone(),
                     device_tube,
+                    fs_device_tube,
                 )?
             }
             SharedDirKind::P9 => create_9p_device(
@@ -902,6 +1106,12 @@ fn create_virtio_devices(
         )?);
     }
 
+    for path in &cfg.extensions {
+        let (extension_devs, extension_tube) = create_extension_devices(cfg.protection_type, path)?;
+        devs.extend(extension_devs);
+        add_control_tube(DeviceControlTube::Extension(extension_tube).into());
+    }
+
     Ok(devs)
 }
 
@@ -919,6 +1129,7 @@ fn create_devices(
     vfio_container_manager: &mut VfioContainerManager,
     // Stores a set of PID of child processes that are suppose to exit cleanly.
     worker_process_pids: &mut BTreeSet<Pid>,
+    input_wakeup_evt: Option<&Event>,
 ) -> DeviceResult<Vec<(Box<dyn BusDeviceObj>, Option<Minijail>)>> {
     let mut devices: Vec<(Box<dyn BusDeviceObj>, Option<Minijail>)> = Vec::new();
     #[cfg(feature = "balloon")]
@@ -941,6 +1152,8 @@ fn create_devices(
                 Some(&mut coiommu_attached_endpoints),
                 vfio_dev.iommu,
                 vfio_dev.dt_symbol.clone(),
+                vfio_dev.dt_from_host,
+                vfio_dev.p2p,
                 vfio_container_manager,
             )?;
             match dev {
@@ -1056,13 +1269,25 @@ fn create_devices(
         has_vfio_gfx_device,
         #[cfg(feature = "registered_events")]
         registered_evt_q,
+        input_wakeup_evt,
     )?;
 
     for stub in stubs {
+        let jail = match stub.jail {
+            // Devices without a sandbox of their own still get a process of their own, confined
+            // by their seccomp policy.
+            None if cfg.isolate_virtio_devices && stub.dev.supports_child_process() => Some(
+                create_isolated_device_jail(stub.dev.as_ref()).with_context(|| {
+                    format!("failed to create jail for {}", stub.dev.debug_label())
+                })?,
+            ),
+            jail => jail,
+        };
+
         let (msi_host_tube, msi_device_tube) = Tube::pair().context("failed to create tube")?;
         add_control_tube(AnyControlTube::IrqTube(msi_host_tube));
 
-        let shared_memory_tube = if stub.dev.get_shared_memory_region().is_some() {
+        let shared_memory_tube = if !stub.dev.get_shared_memory_regions().is_empty() {
             let (host_tube, device_tube) =
                 Tube::pair().context("failed to create shared memory tube")?;
             add_control_tube(
@@ -1102,7 +1327,7 @@ fn create_devices(
         )
         .context("failed to create virtio pci dev")?;
 
-        devices.push((Box::new(dev) as Box<dyn BusDeviceObj>, stub.jail));
+        devices.push((Box::new(dev) as Box<dyn BusDeviceObj>, jail));
     }
 
     #[cfg(feature = "usb")]
@@ -1118,6 +1343,16 @@ fn create_devices(
         ));
     }
 
+    for (index, params) in cfg.ivshmem.iter().enumerate() {
+        devices.push(create_ivshmem_device(
+            cfg.jail_config.as_ref(),
+            vm,
+            resources,
+            params,
+            index,
+        )?);
+    }
+
     for params in &cfg.stub_pci_devices {
         // Stub devices don't need jailing since they don't do anything.
         devices.push((Box::new(StubPciDevice::new(params)), None));
@@ -1260,12 +1495,14 @@ fn create_pure_virtual_pcie_root_port(
 
         hp_stub.iommu_bus_ranges.push(RangeInclusive::new(
             PciAddress {
+                domain: 0,
                 bus: pci_bridge.get_secondary_num(),
                 dev: 0,
                 func: 0,
             }
             .to_u32(),
             PciAddress {
+                domain: 0,
                 bus: pci_bridge.get_subordinate_num(),
                 dev: 32,
                 func: 8,
@@ -1281,6 +1518,27 @@ fn create_pure_virtual_pcie_root_port(
     Ok(hp_stub)
 }
 
+// Returns the caches of the host CPU each vCPU is pinned to, or nothing unless the host cache
+// topology is mirrored.
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+fn host_vcpu_caches(cfg: &Config) -> Result<Vec<Vec<VcpuCache>>> {
+    if !cfg.host_cache_topology {
+        return Ok(Vec::new());
+    }
+    let Some(VcpuAffinity::PerVcpu(affinity)) = &cfg.vcpu_affinity else {
+        bail!("`host-cache-topology` requires a host CPU for each vCPU");
+    };
+    let host_cpus = (0..cfg.vcpu_count.unwrap_or(1))
+        .map(|vcpu| {
+            affinity
+                .get(&vcpu)
+                .and_then(|cpus| cpus.iter().next().copied())
+                .with_context(|| format!("no host CPU for vCPU {}", vcpu))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    arch::host_vcpu_caches(&host_cpus).context("failed to read the host cache topology")
+}
+
 fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
     let initrd_image = if let Some(initrd_path) = &cfg.initrd_path {
         Some(
@@ -1325,18 +1583,28 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
 
     let (pflash_image, pflash_block_size) = if let Some(pflash_parameters) = &cfg.pflash_parameters
     {
-        (
-            Some(
-                open_file_or_duplicate(
-                    &pflash_parameters.path,
-                    OpenOptions::new().read(true).write(true),
-                )
-                .with_context(|| {
-                    format!("failed to open pflash {}", pflash_parameters.path.display())
-                })?,
-            ),
-            pflash_parameters.block_size,
+        pflash_parameters.create_from_template()?;
+        let pflash_image = open_file_or_duplicate(
+            &pflash_parameters.path,
+            OpenOptions::new().read(true).write(true),
         )
+        .with_context(|| format!("failed to open pflash {}", pflash_parameters.path.display()))?;
+        // Two VMs sharing a variable store would corrupt it.
+        flock(&pflash_image, FlockOperation::LockExclusive, true).with_context(|| {
+            format!(
+                "pflash {} is in use by another VM",
+                pflash_parameters.path.display()
+            )
+        })?;
+        if let Some(secure_boot) = &cfg.secure_boot {
+            if secure_boot.provision(&pflash_parameters.path)? {
+                info!(
+                    "enrolled Secure Boot keys in {}",
+                    pflash_parameters.path.display()
+                );
+            }
+        }
+        (Some(pflash_image), pflash_parameters.block_size)
     } else {
         (None, 0)
     };
@@ -1346,8 +1614,20 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     let mut normalized_cpu_ipc_ratios = BTreeMap::new();
 
-    // if --enable-fw-cfg or --fw-cfg was given, we want to enable fw_cfg
-    let fw_cfg_enable = cfg.enable_fw_cfg || !cfg.fw_cfg_parameters.is_empty();
+    #[cfg(target_arch = "x86_64")]
+    let fw_cfg_kernel = if let Some(kernel_path) = &cfg.fw_cfg_kernel {
+        Some(
+            open_file_or_duplicate(kernel_path, OpenOptions::new().read(true)).with_context(
+                || format!("failed to open fw_cfg kernel {}", kernel_path.display()),
+            )?,
+        )
+    } else {
+        None
+    };
+
+    // if --enable-fw-cfg, --fw-cfg or --fw-cfg-kernel was given, we want to enable fw_cfg
+    let fw_cfg_enable =
+        cfg.enable_fw_cfg || !cfg.fw_cfg_parameters.is_empty() || cfg.fw_cfg_kernel.is_some();
     let (cpu_clusters, cpu_capacity) = if cfg.host_cpu_topology {
         (
             Arch::get_host_cpu_clusters()?,
@@ -1488,12 +1768,16 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         bootorder_fw_cfg_blob: Vec::new(),
         vcpu_count: cfg.vcpu_count.unwrap_or(1),
         vcpu_affinity: cfg.vcpu_affinity.clone(),
+        #[cfg(target_arch = "aarch64")]
+        vcpu_caches: host_vcpu_caches(cfg)?,
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
         vcpu_domains,
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
         vcpu_domain_paths,
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
         cpu_frequencies,
+        #[cfg(target_arch = "x86_64")]
+        fw_cfg_kernel,
         fw_cfg_parameters: cfg.fw_cfg_parameters.clone(),
         cpu_clusters,
         cpu_capacity,
@@ -1504,6 +1788,10 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         hv_cfg: hypervisor::Config {
             #[cfg(target_arch = "aarch64")]
             mte: cfg.mte,
+            #[cfg(target_arch = "aarch64")]
+            system_suspend: cfg.system_suspend,
+            #[cfg(target_arch = "x86_64")]
+            x2apic_ids: cfg.vcpu_count.unwrap_or(1) > x86_64::MAX_XAPIC_VCPUS,
             protection_type: cfg.protection_type,
         },
         vm_image,
@@ -1519,7 +1807,7 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         pflash_block_size,
         pflash_image,
         initrd_image,
-        extra_kernel_params: cfg.params.clone(),
+        extra_kernel_params: take_next_boot_params(cfg).map_err(|e| anyhow!(e))?,
         acpi_sdts: cfg
             .acpi_tables
             .iter()
@@ -1534,14 +1822,27 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         no_rtc: cfg.no_rtc,
         #[cfg(target_arch = "x86_64")]
         smbios: cfg.smbios.clone(),
+        #[cfg(target_arch = "aarch64")]
+        smmuv3: None,
+        #[cfg(all(target_arch = "x86_64", feature = "vtpm"))]
+        tpm_crb: None,
+        host_battery: cfg.battery_config.as_ref().is_some_and(|c| c.host),
         host_cpu_topology: cfg.host_cpu_topology,
         itmt: cfg.itmt,
         #[cfg(target_arch = "x86_64")]
         force_s2idle: cfg.force_s2idle,
+        #[cfg(target_arch = "x86_64")]
+        s3: cfg.s3,
+        #[cfg(target_arch = "x86_64")]
+        rtc_offset: RtcOffset::new(cfg.rtc.initial_offset().map_err(|e| anyhow!(e))?),
         pvm_fw: pvm_fw_image,
         pci_config: cfg.pci_config,
         dynamic_power_coefficient: cfg.dynamic_power_coefficient.clone(),
         boot_cpu: cfg.boot_cpu,
+        #[cfg(target_arch = "aarch64")]
+        boot_vcpu_count: cfg
+            .boot_vcpu_count
+            .unwrap_or_else(|| cfg.vcpu_count.unwrap_or(1)),
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
         virt_cpufreq_v2: cfg.virt_cpufreq_v2,
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
@@ -1657,6 +1958,48 @@ fn punch_holes_in_guest_mem_layout_for_mappings(
         .collect())
 }
 
+/// Returns the host's default huge page size in bytes, as reported by `/proc/meminfo`.
+fn default_hugetlb_page_size() -> Result<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").context("failed to read meminfo")?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))
+        .and_then(|size| size.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .map(|kib| kib * 1024)
+        .context("failed to find the default huge page size in meminfo")
+}
+
+/// Moves the RAM regions of `guest_mem_layout` aligned to the configured huge page size to their
+/// own huge page backed memfds. Other regions stay in the shared guest memory memfd.
+fn apply_hugetlb_backing(
+    cfg: &Config,
+    guest_mem_layout: Vec<(GuestAddress, u64, MemoryRegionOptions)>,
+) -> Result<Vec<(GuestAddress, u64, MemoryRegionOptions)>> {
+    if !cfg.hugetlb {
+        return Ok(guest_mem_layout);
+    }
+    let page_size = match cfg.hugetlb_page_size {
+        Some(page_size) => page_size,
+        None => default_hugetlb_page_size()?,
+    };
+    Ok(guest_mem_layout
+        .into_iter()
+        .map(|(addr, size, options)| {
+            let options = if options.purpose == MemoryRegionPurpose::GuestMemoryRegion
+                && options.file_backed.is_none()
+                && addr.offset() % page_size == 0
+                && size % page_size == 0
+            {
+                options.backing(MemoryBacking::Hugetlb(cfg.hugetlb_page_size))
+            } else {
+                options
+            };
+            (addr, size, options)
+        })
+        .collect())
+}
+
 fn create_guest_memory(
     cfg: &Config,
     components: &VmComponents,
@@ -1670,6 +2013,7 @@ fn create_guest_memory(
         guest_mem_layout,
         &cfg.file_backed_mappings_ram,
     )?;
+    let guest_mem_layout = apply_hugetlb_backing(cfg, guest_mem_layout)?;
 
     let mut guest_mem = GuestMemory::new_with_options(&guest_mem_layout)
         .context("failed to create guest memory")?;
@@ -1684,8 +2028,27 @@ fn create_guest_memory(
     if cfg.lock_guest_memory_dontneed {
         mem_policy |= MemoryPolicy::USE_DONTNEED_LOCKED;
     }
+    if cfg.ksm {
+        mem_policy |= MemoryPolicy::MERGEABLE;
+    }
     guest_mem.set_memory_policy(mem_policy);
 
+    for binding in &cfg.host_numa_bindings {
+        guest_mem
+            .set_numa_policy(
+                GuestAddress(binding.address),
+                binding.size,
+                binding.policy,
+                &binding.nodes,
+            )
+            .with_context(|| {
+                format!(
+                    "failed to bind guest memory {:#x}+{:#x} to host NUMA nodes {:?}",
+                    binding.address, binding.size, binding.nodes
+                )
+            })?;
+    }
+
     if cfg.unmap_guest_memory_on_fork {
         // Note that this isn't compatible with sandboxing. We could potentially fix that by
         // delaying the call until after the sandboxed devices are forked. However, the main use
@@ -1717,8 +2080,13 @@ fn run_gz(device_path: Option<&Path>, cfg: Config, components: VmComponents) ->
     #[cfg(feature = "swap")]
     let swap_controller = if let Some(swap_dir) = cfg.swap_dir.as_ref() {
         Some(
-            SwapController::launch(guest_mem.clone(), swap_dir, cfg.jail_config.as_ref())
-                .context("launch vmm-swap monitor process")?,
+            SwapController::launch(
+                guest_mem.clone(),
+                swap_dir,
+                cfg.swap_compression_level,
+                cfg.jail_config.as_ref(),
+            )
+            .context("launch vmm-swap monitor process")?,
         )
     } else {
         None
@@ -1775,8 +2143,13 @@ fn run_kvm(device_path: Option<&Path>, cfg: Config, components: VmComponents) ->
     #[cfg(feature = "swap")]
     let swap_controller = if let Some(swap_dir) = cfg.swap_dir.as_ref() {
         Some(
-            SwapController::launch(guest_mem.clone(), swap_dir, cfg.jail_config.as_ref())
-                .context("launch vmm-swap monitor process")?,
+            SwapController::launch(
+                guest_mem.clone(),
+                swap_dir,
+                cfg.swap_compression_level,
+                cfg.jail_config.as_ref(),
+            )
+            .context("launch vmm-swap monitor process")?,
         )
     } else {
         None
@@ -1790,6 +2163,25 @@ fn run_kvm(device_path: Option<&Path>, cfg: Config, components: VmComponents) ->
             .context("failed to disable MSR_PLATFORM_INFO read access")?;
     }
 
+    #[cfg(target_arch = "x86_64")]
+    if !cfg.hidden_msrs.is_empty() {
+        vm.hide_msrs(&cfg.hidden_msrs)
+            .context("failed to hide MSRs from the guest")?;
+    }
+
+    if let Some(halt_poll_ns) = cfg.halt_poll_ns {
+        vm.set_halt_poll_ns(halt_poll_ns)
+            .context("failed to set the halt polling time")?;
+    }
+
+    if cfg.vcpu_boost.is_some()
+        && cfg.irq_chip.unwrap_or(IrqChipKind::Kernel) == IrqChipKind::Kernel
+    {
+        warn!(
+            "--vcpu-boost has no effect with the kernel irqchip, which delivers interrupts itself"
+        );
+    }
+
     // Check that the VM was actually created in protected mode as expected.
     // This check is only needed on aarch64. On x86_64, protected VM creation will fail
     // if protected mode is not supported.
@@ -1828,23 +2220,27 @@ fn run_kvm(device_path: Option<&Path>, cfg: Config, components: VmComponents) ->
                 let (host_tube, ioapic_device_tube) =
                     Tube::pair().context("failed to create tube")?;
                 ioapic_host_tube = Some(host_tube);
-                KvmIrqChip::Split(
-                    KvmSplitIrqChip::new(
-                        vm_clone,
-                        components.vcpu_count,
-                        ioapic_device_tube,
-                        Some(24),
-                    )
-                    .context("failed to create IRQ chip")?,
+                let mut chip = KvmSplitIrqChip::new(
+                    vm_clone,
+                    components.vcpu_count,
+                    ioapic_device_tube,
+                    Some(24),
                 )
+                .context("failed to create IRQ chip")?;
+                if cfg.irq_latency_stats {
+                    chip.enable_irq_latency_stats();
+                }
+                KvmIrqChip::Split(chip)
             }
         }
         IrqChipKind::Kernel => {
             ioapic_host_tube = None;
-            KvmIrqChip::Kernel(
-                KvmKernelIrqChip::new(vm_clone, components.vcpu_count)
-                    .context("failed to create IRQ chip")?,
-            )
+            let mut chip = KvmKernelIrqChip::new(vm_clone, components.vcpu_count)
+                .context("failed to create IRQ chip")?;
+            if cfg.irq_latency_stats {
+                chip.enable_irq_latency_stats();
+            }
+            KvmIrqChip::Kernel(chip)
         }
     };
 
@@ -1884,8 +2280,13 @@ fn run_gunyah(
     #[cfg(feature = "swap")]
     let swap_controller = if let Some(swap_dir) = cfg.swap_dir.as_ref() {
         Some(
-            SwapController::launch(guest_mem.clone(), swap_dir, cfg.jail_config.as_ref())
-                .context("launch vmm-swap monitor process")?,
+            SwapController::launch(
+                guest_mem.clone(),
+                swap_dir,
+                cfg.swap_compression_level,
+                cfg.jail_config.as_ref(),
+            )
+            .context("launch vmm-swap monitor process")?,
         )
     } else {
         None
@@ -1959,6 +2360,7 @@ fn get_default_hypervisor() -> Option<HypervisorKind> {
 }
 
 pub fn run_config(cfg: Config) -> Result<ExitState> {
+    set_executor_seccomp_features()?;
     let components = setup_vm_components(&cfg)?;
 
     let hypervisor = cfg
@@ -2014,7 +2416,10 @@ where
     }
 
     let (metrics_send, metrics_recv) = Tube::directional_pair().context("metrics tube")?;
-    metrics::initialize(metrics_send);
+    // Metrics are only collected when something is there to export them.
+    if cfg.metrics_address.is_some() {
+        metrics::initialize(metrics_send);
+    }
 
     #[cfg(all(feature = "pci-hotplug", feature = "swap"))]
     let swap_device_helper = match &swap_controller {
@@ -2065,25 +2470,35 @@ where
         add_control_tube(AnyControlTube::IrqTube(ioapic_host_tube));
     }
 
-    let battery = if cfg.battery_config.is_some() {
-        #[cfg_attr(
-            not(feature = "power-monitor-powerd"),
-            allow(clippy::manual_map, clippy::needless_match, unused_mut)
-        )]
+    #[cfg(feature = "pci-hotplug")]
+    {
+        let matchers = evdev_hotplug::evdev_hotplug_matchers(&cfg.virtio_input);
+        if !matchers.is_empty() {
+            let (evdev_hotplug_host_tube, evdev_hotplug_tube) =
+                Tube::pair().context("failed to create tube")?;
+            add_control_tube(TaggedControlTube::Vm(evdev_hotplug_host_tube).into());
+            evdev_hotplug::start_evdev_hotplug(matchers, evdev_hotplug_tube)?;
+        }
+    }
+
+    let battery = if let Some(battery_config) = cfg.battery_config.as_ref() {
         let jail = if let Some(jail_config) = cfg.jail_config.as_ref() {
             let mut config = SandboxConfig::new(jail_config, "battery");
-            #[cfg(feature = "power-monitor-powerd")]
-            {
-                config.bind_mounts = true;
-            }
+            config.bind_mounts = battery_config.host || cfg!(feature = "power-monitor-powerd");
             let mut jail =
                 create_sandbox_minijail(&jail_config.pivot_root, MAX_OPEN_FILES_DEFAULT, &config)?;
 
-            // Setup a bind mount to the system D-Bus socket if the powerd monitor is used.
-            #[cfg(feature = "power-monitor-powerd")]
-            {
-                let system_bus_socket_path = Path::new("/run/dbus/system_bus_socket");
-                jail.mount_bind(system_bus_socket_path, system_bus_socket_path, true)?;
+            if battery_config.host {
+                // The power_supply class entries are symlinks into the sysfs device tree.
+                let sys_path = Path::new("/sys");
+                jail.mount_bind(sys_path, sys_path, false)?;
+            } else {
+                // Setup a bind mount to the system D-Bus socket if the powerd monitor is used.
+                #[cfg(feature = "power-monitor-powerd")]
+                {
+                    let system_bus_socket_path = Path::new("/run/dbus/system_bus_socket");
+                    jail.mount_bind(system_bus_socket_path, system_bus_socket_path, true)?;
+                }
             }
             Some(jail)
         } else {
@@ -2141,6 +2556,16 @@ where
 
     let mut worker_process_pids = BTreeSet::new();
 
+    // Signaled on virtio-input activity to wake up a guest suspended with PSCI SYSTEM_SUSPEND.
+    #[cfg(target_arch = "aarch64")]
+    let input_wakeup_evt = cfg
+        .system_suspend
+        .then(Event::new)
+        .transpose()
+        .context("failed to create input wakeup event")?;
+    #[cfg(not(target_arch = "aarch64"))]
+    let input_wakeup_evt: Option<Event> = None;
+
     let mut devices = create_devices(
         &cfg,
         &mut vm,
@@ -2157,6 +2582,7 @@ where
         &reg_evt_wrtube,
         &mut vfio_container_manager,
         &mut worker_process_pids,
+        input_wakeup_evt.as_ref(),
     )?;
 
     #[cfg(feature = "pci-hotplug")]
@@ -2231,6 +2657,31 @@ where
         &mut devices,
     )?;
 
+    // The endpoints are translated by the SMMUv3 instead of a virtio-iommu.
+    #[cfg(target_arch = "aarch64")]
+    let (translate_response_senders, request_rx) =
+        if cfg.smmuv3 && !iommu_attached_endpoints.is_empty() {
+            components.smmuv3 = Some(create_smmuv3(
+                cfg.jail_config.as_ref(),
+                vm.get_memory(),
+                mem::take(&mut iommu_attached_endpoints),
+                translate_response_senders,
+                request_rx,
+            )?);
+            (None, None)
+        } else {
+            (translate_response_senders, request_rx)
+        };
+
+    #[cfg(all(target_arch = "x86_64", feature = "vtpm"))]
+    if let Some(swtpm_params) = cfg
+        .swtpm
+        .as_ref()
+        .filter(|p| p.interface == TpmInterface::Crb)
+    {
+        components.tpm_crb = Some(create_swtpm_crb(cfg.jail_config.as_ref(), swtpm_params)?);
+    }
+
     #[cfg(target_arch = "x86_64")]
     let iommu_bus_ranges = hp_stub.iommu_bus_ranges;
     #[cfg(not(target_arch = "x86_64"))]
@@ -2320,6 +2771,8 @@ where
 
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     let vcpu_domain_paths = components.vcpu_domain_paths.clone();
+    #[cfg(target_arch = "x86_64")]
+    let rtc_offset = components.rtc_offset.clone();
 
     let mut linux = Arch::build_vm::<V, Vcpu>(
         components,
@@ -2438,6 +2891,9 @@ where
         worker_process_pids,
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
         vcpu_domain_paths,
+        input_wakeup_evt,
+        #[cfg(target_arch = "x86_64")]
+        rtc_offset,
     )
 }
 
@@ -2622,6 +3078,8 @@ fn add_hotplug_device<V: VmArch, Vcpu: VcpuArch>(
                     IommuDevType::NoIommu
                 },
                 None,
+                false,
+                device.p2p,
                 vfio_container_manager,
             )?;
             let vfio_pci_device = match vfio_device {
@@ -2669,14 +3127,12 @@ fn add_hotplug_device<V: VmArch, Vcpu: VcpuArch>(
     Ok(())
 }
 
+/// Creates the control tubes a hotplugged virtio PCI device needs and registers their host ends.
+/// Returns the msi tube, ioevent client and vm control tube for the device side.
 #[cfg(feature = "pci-hotplug")]
-fn add_hotplug_net<V: VmArch, Vcpu: VcpuArch>(
-    linux: &mut RunnableLinuxVm<V, Vcpu>,
-    sys_allocator: &mut SystemAllocator,
+fn create_hotplug_device_tubes(
     add_control_tube: &mut impl FnMut(AnyControlTube),
-    hotplug_manager: &mut PciHotPlugManager,
-    net_param: NetParameters,
-) -> Result<u8> {
+) -> Result<(Tube, VmMemoryClient, Tube)> {
     let (msi_host_tube, msi_device_tube) = Tube::pair().context("create tube")?;
     add_control_tube(AnyControlTube::IrqTube(msi_host_tube));
     let (ioevent_host_tube, ioevent_device_tube) = Tube::pair().context("create tube")?;
@@ -2690,6 +3146,23 @@ fn add_hotplug_net<V: VmArch, Vcpu: VcpuArch>(
     );
     let (vm_control_host_tube, vm_control_device_tube) = Tube::pair().context("create tube")?;
     add_control_tube(TaggedControlTube::Vm(vm_control_host_tube).into());
+    Ok((
+        msi_device_tube,
+        ioevent_vm_memory_client,
+        vm_control_device_tube,
+    ))
+}
+
+#[cfg(feature = "pci-hotplug")]
+fn add_hotplug_net<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    hotplug_manager: &mut PciHotPlugManager,
+    net_param: NetParameters,
+) -> Result<u8> {
+    let (msi_device_tube, ioevent_vm_memory_client, vm_control_device_tube) =
+        create_hotplug_device_tubes(add_control_tube)?;
     let net_carrier_device = NetResourceCarrier::new(
         net_param,
         msi_device_tube,
@@ -2720,7 +3193,7 @@ fn handle_hotplug_net_command<V: VmArch, Vcpu: VcpuArch>(
             &tap_name,
         ),
         NetControlCommand::RemoveTap(bus) => {
-            handle_hotplug_net_remove(linux, sys_allocator, hotplug_manager, bus)
+            handle_hotplug_device_remove(linux, sys_allocator, hotplug_manager, bus)
         }
     }
 }
@@ -2759,25 +3232,133 @@ fn handle_hotplug_net_add<V: VmArch, Vcpu: VcpuArch>(
 }
 
 #[cfg(feature = "pci-hotplug")]
-fn handle_hotplug_net_remove<V: VmArch, Vcpu: VcpuArch>(
+fn add_hotplug_evdev<V: VmArch, Vcpu: VcpuArch>(
     linux: &mut RunnableLinuxVm<V, Vcpu>,
     sys_allocator: &mut SystemAllocator,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
     hotplug_manager: &mut PciHotPlugManager,
-    bus: u8,
-) -> VmResponse {
-    match hotplug_manager.remove_hotplug_device(bus, linux, sys_allocator) {
-        Ok(_) => VmResponse::Ok,
-        Err(e) => VmResponse::ErrString(format!("{:?}", e)),
-    }
+    path: &Path,
+) -> Result<u8> {
+    let evdev = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open evdev device {}", path.display()))?;
+    let (msi_device_tube, ioevent_vm_memory_client, vm_control_device_tube) =
+        create_hotplug_device_tubes(add_control_tube)?;
+    let evdev_carrier_device = EvdevResourceCarrier::new(
+        evdev,
+        msi_device_tube,
+        ioevent_vm_memory_client,
+        vm_control_device_tube,
+    );
+    hotplug_manager.hotplug_device(
+        vec![ResourceCarrier::VirtioInputEvdev(evdev_carrier_device)],
+        linux,
+        sys_allocator,
+    )
 }
 
-#[cfg(target_arch = "x86_64")]
-fn remove_hotplug_bridge<V: VmArch, Vcpu: VcpuArch>(
-    linux: &RunnableLinuxVm<V, Vcpu>,
+#[cfg(feature = "pci-hotplug")]
+fn handle_hotplug_evdev_command<V: VmArch, Vcpu: VcpuArch>(
+    evdev_cmd: EvdevControlCommand,
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
     sys_allocator: &mut SystemAllocator,
-    buses_to_remove: &mut Vec<u8>,
-    hotplug_key: HotPlugKey,
-    child_bus: u8,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    hotplug_manager: &mut PciHotPlugManager,
+) -> VmResponse {
+    match evdev_cmd {
+        EvdevControlCommand::Add(path) => match add_hotplug_evdev(
+            linux,
+            sys_allocator,
+            add_control_tube,
+            hotplug_manager,
+            &path,
+        ) {
+            Ok(pci_bus) => VmResponse::PciHotPlugResponse { bus: pci_bus },
+            Err(e) => VmResponse::ErrString(format!("{:?}", e)),
+        },
+        EvdevControlCommand::Remove(bus) => {
+            handle_hotplug_device_remove(linux, sys_allocator, hotplug_manager, bus)
+        }
+    }
+}
+
+#[cfg(feature = "pci-hotplug")]
+fn add_hotplug_block<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    hotplug_manager: &mut PciHotPlugManager,
+    disk_option: DiskOption,
+) -> Result<u8> {
+    if disk_option.bootindex.is_some() {
+        bail!("bootindex cannot be set on a hotplugged disk");
+    }
+    let (msi_device_tube, ioevent_vm_memory_client, vm_control_device_tube) =
+        create_hotplug_device_tubes(add_control_tube)?;
+    let block_carrier_device = BlockResourceCarrier::new(
+        disk_option,
+        msi_device_tube,
+        ioevent_vm_memory_client,
+        vm_control_device_tube,
+    );
+    hotplug_manager.hotplug_device(
+        vec![ResourceCarrier::VirtioBlock(block_carrier_device)],
+        linux,
+        sys_allocator,
+    )
+}
+
+#[cfg(feature = "pci-hotplug")]
+fn handle_hotplug_block_command<V: VmArch, Vcpu: VcpuArch>(
+    block_cmd: BlockControlCommand,
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    hotplug_manager: &mut PciHotPlugManager,
+) -> VmResponse {
+    match block_cmd {
+        BlockControlCommand::Add(disk) => match from_key_values(&disk)
+            .map_err(|e| anyhow!("invalid disk {}: {}", disk, e))
+            .and_then(|disk_option| {
+                add_hotplug_block(
+                    linux,
+                    sys_allocator,
+                    add_control_tube,
+                    hotplug_manager,
+                    disk_option,
+                )
+            }) {
+            Ok(pci_bus) => VmResponse::PciHotPlugResponse { bus: pci_bus },
+            Err(e) => VmResponse::ErrString(format!("{:?}", e)),
+        },
+        BlockControlCommand::Remove(bus) => {
+            handle_hotplug_device_remove(linux, sys_allocator, hotplug_manager, bus)
+        }
+    }
+}
+
+#[cfg(feature = "pci-hotplug")]
+fn handle_hotplug_device_remove<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    hotplug_manager: &mut PciHotPlugManager,
+    bus: u8,
+) -> VmResponse {
+    match hotplug_manager.remove_hotplug_device(bus, linux, sys_allocator) {
+        Ok(_) => VmResponse::Ok,
+        Err(e) => VmResponse::ErrString(format!("{:?}", e)),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn remove_hotplug_bridge<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    buses_to_remove: &mut Vec<u8>,
+    hotplug_key: HotPlugKey,
+    child_bus: u8,
 ) -> Result<()> {
     for (bus_num, hp_bus) in linux.hotplug_bus.iter() {
         let mut hp_bus_lock = hp_bus.lock();
@@ -2963,6 +3544,79 @@ pub fn trigger_vm_suspend_and_wait_for_entry(
     }
 }
 
+/// Puts the devices to sleep once the vCPUs of a guest that entered ACPI S3 are parked, so they
+/// release the host resources they hold.
+#[cfg(target_arch = "x86_64")]
+fn sleep_devices_for_s3(
+    pm: &Option<Arc<Mutex<dyn PmResource + Send>>>,
+    vcpu_handles: &[(JoinHandle<()>, mpsc::Sender<VcpuControl>)],
+    irq_chip: &dyn devices::IrqChip,
+    device_ctrl_tube: &Tube,
+) -> anyhow::Result<()> {
+    if !pm.as_ref().is_some_and(|pm| pm.lock().is_s3_suspended()) {
+        return Ok(());
+    }
+    let kick_all_vcpus = |msg| vcpu::kick_all_vcpus(vcpu_handles, irq_chip, msg);
+    if get_vcpu_state(kick_all_vcpus, vcpu_handles.len())? != VmRunMode::Suspending {
+        bail!("vCPUs failed to all suspend");
+    }
+    device_ctrl_tube
+        .send(&DeviceControlCommand::SleepDevices)
+        .context("send command to devices control socket")?;
+    match device_ctrl_tube
+        .recv()
+        .context("receive from devices control socket")?
+    {
+        VmResponse::Ok => Ok(()),
+        resp => bail!("device sleep failed: {}", resp),
+    }
+}
+
+/// Sends the vCPUs of a guest that entered ACPI S3 to its firmware waking vector before they are
+/// resumed. If `device_ctrl_tube` is set, the devices put to sleep by `sleep_devices_for_s3` are
+/// woken up first.
+#[cfg(target_arch = "x86_64")]
+fn prepare_s3_resume(
+    pm: &Option<Arc<Mutex<dyn PmResource + Send>>>,
+    guest_mem: &GuestMemory,
+    vcpu_handles: &[(JoinHandle<()>, mpsc::Sender<VcpuControl>)],
+    irq_chip: &dyn devices::IrqChip,
+    device_ctrl_tube: Option<&Tube>,
+) -> anyhow::Result<()> {
+    let Some(pm) = pm else {
+        return Ok(());
+    };
+    // The PM device lock must not be held while the devices are woken up.
+    {
+        let mut pm = pm.lock();
+        if !pm.is_s3_suspended() {
+            return Ok(());
+        }
+        pm.s3_resume();
+    }
+    if let Some(device_ctrl_tube) = device_ctrl_tube {
+        device_ctrl_tube
+            .send(&DeviceControlCommand::WakeDevices)
+            .context("send command to devices control socket")?;
+        match device_ctrl_tube
+            .recv()
+            .context("receive from devices control socket")?
+        {
+            VmResponse::Ok => (),
+            resp => bail!("device wake failed: {}", resp),
+        }
+    }
+    let waking_vector = x86_64::acpi::read_waking_vector(guest_mem)
+        .context("the guest did not set a firmware waking vector")?;
+    info!("Resuming from S3 at waking vector {:#x}", waking_vector);
+    vcpu::kick_all_vcpus(
+        vcpu_handles,
+        irq_chip,
+        VcpuControl::ResumeFromS3 { waking_vector },
+    );
+    Ok(())
+}
+
 #[cfg(feature = "pvclock")]
 #[derive(Debug)]
 /// The action requested by the pvclock device to perform on the main thread.
@@ -3053,14 +3707,226 @@ fn handle_hotplug_command<V: VmArch, Vcpu: VcpuArch>(
     }
 }
 
+/// Hotplugs the VFIO PCI device at `path` into an empty port of `hotplug_manager`.
+///
+/// The device is created by the main process, as it shares the VFIO container with the other
+/// VFIO devices. Returns the bus the device is plugged into.
+#[cfg(feature = "pci-hotplug")]
+fn add_hotplug_vfio<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    cfg: &Config,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    hotplug_manager: &mut PciHotPlugManager,
+    iommu_host_tube: Option<&Tube>,
+    path: &Path,
+    p2p: bool,
+    #[cfg(feature = "swap")] swap_controller: &mut Option<SwapController>,
+    vfio_container_manager: &mut VfioContainerManager,
+) -> Result<u8> {
+    let host_addr =
+        PciAddress::from_path(path).context("failed to parse hotplug device's PCI address")?;
+    let hotplug_key = HotPlugKey::HostVfio { host_addr };
+    if hotplug_manager.get_hotplug_bus(hotplug_key).is_some() {
+        bail!("{} is already hotplugged", path.display());
+    }
+    let bus = hotplug_manager.get_empty_bus()?;
+    let guest_address = PciAddress::new(0, bus.into(), 0, 0)?;
+    let (vfio_device, jail, viommu_mapper) = create_vfio_device(
+        cfg.jail_config.as_ref(),
+        &linux.vm,
+        sys_allocator,
+        add_control_tube,
+        path,
+        true,
+        None,
+        Some(guest_address),
+        None,
+        if iommu_host_tube.is_some() {
+            IommuDevType::VirtioIommu
+        } else {
+            IommuDevType::NoIommu
+        },
+        None,
+        false,
+        p2p,
+        vfio_container_manager,
+    )?;
+    let vfio_pci_device = match vfio_device {
+        VfioDeviceVariant::Pci(pci) => Box::new(pci),
+        VfioDeviceVariant::Platform(_) => bail!("vfio platform hotplug not supported"),
+    };
+    if let Some(iommu_host_tube) = iommu_host_tube {
+        let vfio_wrapper = viommu_mapper.context("expected mapper")?;
+        let descriptor = vfio_wrapper.clone_as_raw_descriptor()?;
+        let request = VirtioIOMMURequest::VfioCommand(VirtioIOMMUVfioCommand::VfioDeviceAdd {
+            endpoint_addr: guest_address.to_u32(),
+            wrapper_id: vfio_wrapper.id(),
+            container: {
+                // SAFETY:
+                // Safe because the descriptor is uniquely owned by `descriptor`.
+                unsafe { File::from_raw_descriptor(descriptor) }
+            },
+        });
+        match virtio_iommu_request(iommu_host_tube, &request)
+            .map_err(|_| VirtioIOMMUVfioError::SocketFailed)?
+        {
+            VirtioIOMMUResponse::VfioResponse(VirtioIOMMUVfioResult::Ok) => (),
+            resp => bail!("Unexpected message response: {:?}", resp),
+        }
+    }
+    hotplug_manager.hotplug_local_device(
+        vfio_pci_device,
+        jail,
+        hotplug_key,
+        bus,
+        linux,
+        sys_allocator,
+        #[cfg(feature = "swap")]
+        swap_controller,
+    )
+}
+
+/// Hot unplugs the VFIO PCI device at `path` from the port of `hotplug_manager` it is plugged
+/// into.
+#[cfg(feature = "pci-hotplug")]
+fn remove_hotplug_vfio<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    hotplug_manager: &mut PciHotPlugManager,
+    iommu_host_tube: Option<&Tube>,
+    path: &Path,
+) -> Result<()> {
+    let host_addr = PciAddress::from_path(path)?;
+    let bus = hotplug_manager
+        .get_hotplug_bus(HotPlugKey::HostVfio { host_addr })
+        .with_context(|| format!("{} is not hotplugged", path.display()))?;
+    if let Some(iommu_host_tube) = iommu_host_tube {
+        let request = VirtioIOMMURequest::VfioCommand(VirtioIOMMUVfioCommand::VfioDeviceDel {
+            endpoint_addr: PciAddress::new(0, bus.into(), 0, 0)?.to_u32(),
+        });
+        match virtio_iommu_request(iommu_host_tube, &request)
+            .map_err(|_| VirtioIOMMUVfioError::SocketFailed)?
+        {
+            VirtioIOMMUResponse::VfioResponse(VirtioIOMMUVfioResult::Ok) => (),
+            resp => bail!("Unexpected message response: {:?}", resp),
+        }
+    }
+    hotplug_manager.remove_hotplug_device(bus, linux, sys_allocator)
+}
+
+#[cfg(feature = "pci-hotplug")]
+fn handle_hotplug_vfio_command<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    sys_allocator: &mut SystemAllocator,
+    cfg: &Config,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    hotplug_manager: &mut PciHotPlugManager,
+    iommu_host_tube: Option<&Arc<Mutex<Tube>>>,
+    path: &Path,
+    p2p: bool,
+    add: bool,
+    #[cfg(feature = "swap")] swap_controller: &mut Option<SwapController>,
+    vfio_container_manager: &mut VfioContainerManager,
+) -> VmResponse {
+    let iommu_host_tube = if cfg.vfio_isolate_hotplug {
+        iommu_host_tube.map(|t| t.lock())
+    } else {
+        None
+    };
+
+    if add {
+        match add_hotplug_vfio(
+            linux,
+            sys_allocator,
+            cfg,
+            add_control_tube,
+            hotplug_manager,
+            iommu_host_tube.as_deref(),
+            path,
+            p2p,
+            #[cfg(feature = "swap")]
+            swap_controller,
+            vfio_container_manager,
+        ) {
+            Ok(bus) => {
+                info!("hotplugged {} on bus {}", path.display(), bus);
+                VmResponse::Ok
+            }
+            Err(e) => VmResponse::ErrString(format!("{:?}", e)),
+        }
+    } else {
+        match remove_hotplug_vfio(
+            linux,
+            sys_allocator,
+            hotplug_manager,
+            iommu_host_tube.as_deref(),
+            path,
+        ) {
+            Ok(()) => VmResponse::Ok,
+            Err(e) => VmResponse::ErrString(format!("{:?}", e)),
+        }
+    }
+}
+
+/// Opens the host end of a hotplugged console port. Unix sockets are connected to; anything else
+/// (e.g. a FIFO or character device) is opened for reading and writing.
+fn open_console_port_stream(path: &Path) -> Result<SafeDescriptor> {
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("failed to stat console port path {}", path.display()))?;
+    let fd = if metadata.file_type().is_socket() {
+        OwnedFd::from(
+            UnixStream::connect(path)
+                .with_context(|| format!("failed to connect to {}", path.display()))?,
+        )
+    } else {
+        OwnedFd::from(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .with_context(|| format!("failed to open {}", path.display()))?,
+        )
+    };
+    Ok(SafeDescriptor::from(fd))
+}
+
+fn handle_console_command(command: ConsoleControlCommand, console_host_tube: &Tube) -> VmResponse {
+    let request = match command {
+        ConsoleControlCommand::AddPort { name, path } => match open_console_port_stream(&path) {
+            Ok(stream) => ConsolePortRequest::Add { name, stream },
+            Err(e) => return VmResponse::ErrString(format!("{:#}", e)),
+        },
+        ConsoleControlCommand::RemovePort { name } => ConsolePortRequest::Remove { name },
+    };
+
+    // Forward the request to the console device process via its control tube.
+    if let Err(e) = console_host_tube.send(&request) {
+        error!("console tube send failed: {}", e);
+        return VmResponse::Err(base::Error::new(libc::EINVAL));
+    }
+    match console_host_tube.recv() {
+        Ok(ConsoleControlResult::Ok) => VmResponse::Ok,
+        Ok(ConsoleControlResult::Err(e)) => VmResponse::Err(e),
+        Err(e) => {
+            error!("console tube recv failed: {}", e);
+            VmResponse::Err(base::Error::new(libc::EINVAL))
+        }
+    }
+}
+
 struct ControlLoopState<'a, V: VmArch, Vcpu: VcpuArch> {
     linux: &'a mut RunnableLinuxVm<V, Vcpu>,
     cfg: &'a Config,
     sys_allocator: &'a Arc<Mutex<SystemAllocator>>,
     control_tubes: &'a BTreeMap<usize, TaggedControlTube>,
     disk_host_tubes: &'a [Tube],
+    console_host_tubes: &'a [Tube],
+    net_host_tubes: &'a [Tube],
+    fs_host_tubes: &'a [Tube],
     #[cfg(feature = "audio")]
     snd_host_tubes: &'a [Tube],
+    extension_tubes: &'a [Tube],
     #[cfg(feature = "gpu")]
     gpu_control_tube: Option<&'a Tube>,
     #[cfg(feature = "usb")]
@@ -3088,6 +3954,21 @@ struct ControlLoopState<'a, V: VmArch, Vcpu: VcpuArch> {
     vfio_container_manager: &'a mut VfioContainerManager,
     suspended_pvclock_state: &'a mut Option<hypervisor::ClockState>,
     vcpus_pid_tid: &'a BTreeMap<usize, (u32, u32)>,
+    #[cfg(target_arch = "x86_64")]
+    rtc_offset: &'a RtcOffset,
+    #[cfg(target_arch = "x86_64")]
+    bus_lock_ratelimit_ctrl: &'a Arc<Mutex<Ratelimit>>,
+    running_config: &'a mut Option<config_reload::RunningConfig>,
+    powerdown: &'a mut Option<PendingPowerdown>,
+    powerdown_timer: &'a mut Timer,
+}
+
+/// Shutdown requested by `VmRequest::Powerdown`, waiting for the guest to power off.
+struct PendingPowerdown {
+    /// Ids of the control tubes that wait for the VM to stop.
+    waiting_ids: Vec<usize>,
+    /// Whether the VM was stopped without the guest shutting down.
+    forced: bool,
 }
 
 struct VmRequestResult {
@@ -3101,6 +3982,59 @@ impl VmRequestResult {
     }
 }
 
+/// Suspends the vCPUs and writes an ELF core of the guest to `output`.
+fn dump_core(
+    kick_all_vcpus: &impl Fn(VcpuControl),
+    vcpu_num: usize,
+    mem: &GuestMemory,
+    output: File,
+) -> anyhow::Result<()> {
+    let _vcpu_guard = VcpuSuspendGuard::new(kick_all_vcpus, vcpu_num)?;
+    let (send_chan, recv_chan) = mpsc::channel();
+    kick_all_vcpus(VcpuControl::GetCoreNote(send_chan));
+    let mut notes = Vec::with_capacity(vcpu_num);
+    for _ in 0..vcpu_num {
+        let (cpu_id, note) = recv_chan
+            .recv_timeout(std::time::Duration::from_secs(1))
+            .context("failed to get vcpu core note")?;
+        notes.push((
+            cpu_id,
+            note.with_context(|| format!("failed to get registers of vcpu {}", cpu_id))?,
+        ));
+    }
+    notes.sort_by_key(|(cpu_id, _)| *cpu_id);
+    let prstatus: Vec<Vec<u8>> = notes.into_iter().map(|(_, note)| note).collect();
+    core_dump::write_core_dump(&mut BufWriter::new(output), mem, &prstatus)
+}
+
+/// Writes an ELF core of the guest that reported a panic into `dir` and returns its path.
+fn dump_panic_core(
+    kick_all_vcpus: &impl Fn(VcpuControl),
+    vcpu_num: usize,
+    mem: &GuestMemory,
+    dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!("guest-panic-{}.core", timestamp));
+    let output =
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    dump_core(kick_all_vcpus, vcpu_num, mem, output)?;
+    Ok(path)
+}
+
+/// Notifies the extensions of `event`. Failures are only logged, as an extension going away must
+/// not take the VM down with it.
+fn notify_extensions(extension_tubes: &[Tube], event: VmEvent) {
+    for tube in extension_tubes {
+        if let Err(e) = tube.send(&ExtensionRequest::Event(event)) {
+            warn!("failed to notify extension of {:?}: {}", event, e);
+        }
+    }
+}
+
 fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     state: &mut ControlLoopState<V, Vcpu>,
     id: usize,
@@ -3127,11 +4061,43 @@ fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         AnyControlTube::VmMemoryTube(t) => add_vm_memory_control_tubes.push(t),
     };
 
+    let extension_event = match request {
+        VmRequest::SuspendVcpus => Some(VmEvent::Suspended),
+        VmRequest::ResumeVcpus => Some(VmEvent::Resumed),
+        _ => None,
+    };
+
     let response = match request {
         VmRequest::Exit => {
             return Ok(VmRequestResult::new(Some(VmResponse::Ok), true));
         }
         VmRequest::HotPlugVfioCommand { device, add } => {
+            // Endpoints are plugged into the ports of the hotplug manager when it is enabled.
+            #[cfg(feature = "pci-hotplug")]
+            if let Some(hotplug_manager) = state.hotplug_manager.as_mut() {
+                if matches!(device.device_type, HotPlugDeviceType::EndPoint) {
+                    #[cfg(target_arch = "x86_64")]
+                    let iommu_host_tube = state.iommu_host_tube.as_ref();
+                    #[cfg(not(target_arch = "x86_64"))]
+                    let iommu_host_tube = None;
+                    let response = handle_hotplug_vfio_command(
+                        state.linux,
+                        &mut state.sys_allocator.lock(),
+                        state.cfg,
+                        &mut add_control_tube,
+                        hotplug_manager,
+                        iommu_host_tube,
+                        &device.path,
+                        device.p2p,
+                        add,
+                        #[cfg(feature = "swap")]
+                        state.swap_controller,
+                        state.vfio_container_manager,
+                    );
+                    return Ok(VmRequestResult::new(Some(response), false));
+                }
+            }
+
             #[cfg(target_arch = "x86_64")]
             {
                 handle_hotplug_command(
@@ -3171,6 +4137,34 @@ fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 VmResponse::ErrString("PCI hotplug is not enabled.".to_owned())
             }
         }
+        #[cfg(feature = "pci-hotplug")]
+        VmRequest::HotPlugEvdevCommand(evdev_cmd) => {
+            if let Some(hotplug_manager) = state.hotplug_manager.as_mut() {
+                handle_hotplug_evdev_command(
+                    evdev_cmd,
+                    state.linux,
+                    &mut state.sys_allocator.lock(),
+                    &mut add_control_tube,
+                    hotplug_manager,
+                )
+            } else {
+                VmResponse::ErrString("PCI hotplug is not enabled.".to_owned())
+            }
+        }
+        #[cfg(feature = "pci-hotplug")]
+        VmRequest::HotPlugBlockCommand(block_cmd) => {
+            if let Some(hotplug_manager) = state.hotplug_manager.as_mut() {
+                handle_hotplug_block_command(
+                    block_cmd,
+                    state.linux,
+                    &mut state.sys_allocator.lock(),
+                    &mut add_control_tube,
+                    hotplug_manager,
+                )
+            } else {
+                VmResponse::ErrString("PCI hotplug is not enabled.".to_owned())
+            }
+        }
         #[cfg(feature = "registered_events")]
         VmRequest::RegisterListener { socket_addr, event } => {
             let (registered_tube, already_registered) =
@@ -3229,9 +4223,148 @@ fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 VmResponse::Err(base::Error::new(libc::ENOTSUP))
             }
         }
+        VmRequest::ConsoleCommand {
+            console_index,
+            command,
+        } => match state.console_host_tubes.get(console_index) {
+            Some(tube) => handle_console_command(command, tube),
+            None => VmResponse::Err(base::Error::new(libc::ENODEV)),
+        },
+        VmRequest::NetCommand { net_index, command } => match state.net_host_tubes.get(net_index) {
+            Some(tube) => handle_net_command(&command, tube),
+            None => VmResponse::Err(base::Error::new(libc::ENODEV)),
+        },
+        VmRequest::FsCommand { fs_index, command } => match state.fs_host_tubes.get(fs_index) {
+            Some(tube) => handle_fs_command(&command, tube),
+            None => VmResponse::Err(base::Error::new(libc::ENODEV)),
+        },
         VmRequest::VcpuPidTid => VmResponse::VcpuPidTidResponse {
             pid_tid_map: state.vcpus_pid_tid.clone(),
         },
+        VmRequest::QueryDevices => VmResponse::Devices(devices::query_devices(&[
+            &state.linux.mmio_bus,
+            &state.linux.io_bus,
+        ])),
+        VmRequest::VcpuStats => {
+            let (send_chan, recv_chan) = mpsc::channel();
+            vcpu::kick_all_vcpus(
+                state.vcpu_handles,
+                state.linux.irq_chip.as_irq_chip(),
+                VcpuControl::GetExitStats(send_chan),
+            );
+            let mut stats = Vec::with_capacity(state.vcpu_handles.len());
+            for _ in 0..state.vcpu_handles.len() {
+                match recv_chan.recv_timeout(std::time::Duration::from_secs(1)) {
+                    Ok(vcpu_stats) => stats.push(vcpu_stats),
+                    Err(e) => {
+                        error!("failed to get vcpu exit stats: {}", e);
+                        break;
+                    }
+                }
+            }
+            stats.sort_by_key(|s| s.cpu_id);
+            VmResponse::VcpuStats(stats)
+        }
+        VmRequest::IrqLatencyStats => {
+            match state.linux.irq_chip.as_irq_chip().irq_latency_stats() {
+                Some(stats) => VmResponse::IrqLatencyStats(stats),
+                None => VmResponse::ErrString(
+                    "IRQ latency statistics require `--irq-latency-stats` with a KVM irqchip"
+                        .to_owned(),
+                ),
+            }
+        }
+        VmRequest::DumpCore { output } => {
+            let kick_all_vcpus = |msg| {
+                vcpu::kick_all_vcpus(state.vcpu_handles, state.linux.irq_chip.as_irq_chip(), msg)
+            };
+            match dump_core(
+                &kick_all_vcpus,
+                state.vcpu_handles.len(),
+                state.linux.vm.get_memory(),
+                output,
+            ) {
+                Ok(()) => VmResponse::Ok,
+                Err(e) => {
+                    error!("failed to dump core: {:#}", e);
+                    VmResponse::ErrString(format!("failed to dump core: {:#}", e))
+                }
+            }
+        }
+        #[cfg(target_arch = "x86_64")]
+        VmRequest::RtcCommand(command) => {
+            if state.cfg.no_rtc {
+                VmResponse::ErrString("the RTC is disabled".to_owned())
+            } else {
+                if let RtcCommand::SetOffset(secs) = command {
+                    state.rtc_offset.set(secs);
+                }
+                VmResponse::RtcOffset {
+                    offset_secs: state.rtc_offset.get(),
+                }
+            }
+        }
+        VmRequest::Powerdown { timeout } => {
+            // A powerdown already in progress keeps its deadline.
+            if let Some(powerdown) = state.powerdown.as_mut() {
+                powerdown.waiting_ids.push(id);
+                return Ok(VmRequestResult::new(None, false));
+            }
+            let Some(pm) = state.linux.pm.as_ref() else {
+                warn!("the VM has no power button, stopping it");
+                *state.powerdown = Some(PendingPowerdown {
+                    waiting_ids: vec![id],
+                    forced: true,
+                });
+                return Ok(VmRequestResult::new(None, true));
+            };
+            match state.powerdown_timer.reset_oneshot(timeout) {
+                Ok(()) => {
+                    info!("power button pressed, waiting {:?} for the guest", timeout);
+                    pm.lock().pwrbtn_evt();
+                    *state.powerdown = Some(PendingPowerdown {
+                        waiting_ids: vec![id],
+                        forced: false,
+                    });
+                    return Ok(VmRequestResult::new(None, false));
+                }
+                Err(e) => {
+                    error!("failed to arm the powerdown timer: {}", e);
+                    VmResponse::ErrString(format!("failed to arm the powerdown timer: {}", e))
+                }
+            }
+        }
+        VmRequest::ReloadConfig { config } => config_reload::reload_config(
+            state,
+            #[cfg(feature = "pci-hotplug")]
+            &mut add_control_tube,
+            &config,
+        ),
+        VmRequest::NextBootParams(params) => match &state.cfg.next_boot_params_file {
+            Some(path) => match save_next_boot_params(path, params.as_deref()) {
+                Ok(()) => VmResponse::Ok,
+                Err(e) => {
+                    error!("failed to save next boot params: {:#}", e);
+                    VmResponse::ErrString(format!("failed to save next boot params: {:#}", e))
+                }
+            },
+            None => VmResponse::ErrString("`next-boot-params-file` is not set".to_owned()),
+        },
+        VmRequest::PstoreRecords { kind } => match &state.cfg.pstore {
+            Some(pstore) => match arch::pstore::read_records(pstore) {
+                Ok(mut records) => {
+                    if let Some(kind) = kind {
+                        records.retain(|r| r.kind == kind);
+                    }
+                    VmResponse::PstoreRecords(records)
+                }
+                Err(e) => {
+                    error!("failed to read pstore records: {:#}", e);
+                    VmResponse::ErrString(format!("failed to read pstore records: {:#}", e))
+                }
+            },
+            None => VmResponse::ErrString("pstore is not enabled".to_owned()),
+        },
         VmRequest::Throttle(vcpu, cycles) => {
             vcpu::kick_vcpu(
                 &state.vcpu_handles.get(vcpu),
@@ -3240,6 +4373,28 @@ fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             );
             return Ok(VmRequestResult::new(None, false));
         }
+        #[cfg(target_arch = "aarch64")]
+        VmRequest::OnlineVcpu(vcpu) => {
+            let vcpu_handle = state.vcpu_handles.get(vcpu);
+            if vcpu_handle.is_none() {
+                VmResponse::ErrString(format!("vcpu {} does not exist", vcpu))
+            } else {
+                let (send_chan, recv_chan) = mpsc::channel();
+                vcpu::kick_vcpu(
+                    &vcpu_handle,
+                    state.linux.irq_chip.as_irq_chip(),
+                    VcpuControl::Online(send_chan),
+                );
+                match recv_chan.recv_timeout(std::time::Duration::from_secs(1)) {
+                    Ok(true) => VmResponse::Ok,
+                    Ok(false) => VmResponse::ErrString(format!("vcpu {} is not parked", vcpu)),
+                    Err(e) => {
+                        error!("failed to online vcpu {}: {}", vcpu, e);
+                        VmResponse::Err(base::Error::new(libc::EIO))
+                    }
+                }
+            }
+        }
         _ => {
             if !state.cfg.force_s2idle {
                 #[cfg(feature = "pvclock")]
@@ -3264,8 +4419,24 @@ fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                     }
                 }
             }
+            // `state.linux.pm` is mutably borrowed by `execute` below.
+            #[cfg(target_arch = "x86_64")]
+            let pm = state.linux.pm.clone();
             let kick_all_vcpus = |msg| {
                 if let VcpuControl::RunState(VmRunMode::Running) = msg {
+                    #[cfg(target_arch = "x86_64")]
+                    if let Err(e) = prepare_s3_resume(
+                        &pm,
+                        state.linux.vm.get_memory(),
+                        state.vcpu_handles,
+                        state.linux.irq_chip.as_irq_chip(),
+                        state
+                            .cfg
+                            .s3_release_devices
+                            .then_some(state.device_ctrl_tube),
+                    ) {
+                        error!("failed to resume from S3: {:#}", e);
+                    }
                     for dev in &state.linux.resume_notify_devices {
                         dev.lock().resume_imminent();
                     }
@@ -3353,6 +4524,10 @@ fn process_vm_request<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         }
     };
 
+    if let (Some(event), VmResponse::Ok) = (extension_event, &response) {
+        notify_extensions(state.extension_tubes, event);
+    }
+
     cfg_if::cfg_if! {
         if #[cfg(any(target_arch = "x86_64", feature = "pci-hotplug"))] {
             if !add_irq_control_tubes.is_empty() {
@@ -3471,6 +4646,33 @@ impl AddressedProtoTube {
     }
 }
 
+/// Sends `reg_evt` to the listeners registered for it, dropping the ones that cannot be reached.
+#[cfg(feature = "registered_events")]
+fn send_registered_event(
+    registered_evt_tubes: &mut HashMap<RegisteredEvent, HashSet<AddressedProtoTube>>,
+    reg_evt: &RegisteredEventWithData,
+) {
+    let evt = reg_evt.into_event();
+    let mut tubes_to_remove: Vec<String> = Vec::new();
+    if let Some(tubes) = registered_evt_tubes.get_mut(&evt) {
+        for tube in tubes.iter() {
+            if let Err(e) = tube.send(&reg_evt.into_proto()) {
+                warn!(
+                    "failed to send registered event {:?} to {}, removing from registrations: {}",
+                    reg_evt, tube.socket_addr, e
+                );
+                tubes_to_remove.push(tube.socket_addr.clone());
+            }
+        }
+    }
+    for tube_addr in tubes_to_remove {
+        for tubes in registered_evt_tubes.values_mut() {
+            tubes.retain(|t| t.socket_addr != tube_addr);
+        }
+    }
+    registered_evt_tubes.retain(|_, tubes| !tubes.is_empty());
+}
+
 #[cfg(feature = "registered_events")]
 fn find_registered_tube<'a>(
     registered_tubes: &'a HashMap<RegisteredEvent, HashSet<AddressedProtoTube>>,
@@ -3549,17 +4751,24 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         usize,
         PathBuf,
     >,
+    input_wakeup_evt: Option<Event>,
+    #[cfg(target_arch = "x86_64")] rtc_offset: RtcOffset,
 ) -> Result<ExitState> {
     // Split up `all_control_tubes`.
     #[cfg(feature = "balloon")]
     let mut balloon_host_tube = None;
     let mut disk_host_tubes = Vec::new();
+    let mut console_host_tubes = Vec::new();
+    #[cfg_attr(not(feature = "net"), allow(unused_mut))]
+    let mut net_host_tubes = Vec::new();
+    let mut fs_host_tubes = Vec::new();
     #[cfg(feature = "gpu")]
     let mut gpu_control_tube = None;
     #[cfg(feature = "pvclock")]
     let mut pvclock_host_tube = None;
     #[cfg(feature = "audio")]
     let mut snd_host_tubes = Vec::new();
+    let mut extension_tubes = Vec::new();
     let mut irq_control_tubes = Vec::new();
     let mut vm_memory_control_tubes = Vec::new();
     let mut control_tubes = Vec::new();
@@ -3573,6 +4782,12 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             AnyControlTube::DeviceControlTube(DeviceControlTube::Disk(t)) => {
                 disk_host_tubes.push(t)
             }
+            AnyControlTube::DeviceControlTube(DeviceControlTube::Console(t)) => {
+                console_host_tubes.push(t)
+            }
+            #[cfg(feature = "net")]
+            AnyControlTube::DeviceControlTube(DeviceControlTube::Net(t)) => net_host_tubes.push(t),
+            AnyControlTube::DeviceControlTube(DeviceControlTube::Fs(t)) => fs_host_tubes.push(t),
             #[cfg(feature = "gpu")]
             AnyControlTube::DeviceControlTube(DeviceControlTube::Gpu(t)) => {
                 assert!(gpu_control_tube.is_none());
@@ -3587,12 +4802,57 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             AnyControlTube::DeviceControlTube(DeviceControlTube::Snd(t)) => {
                 snd_host_tubes.push(t);
             }
+            AnyControlTube::DeviceControlTube(DeviceControlTube::Extension(t)) => {
+                extension_tubes.push(t);
+            }
             AnyControlTube::IrqTube(t) => irq_control_tubes.push(t),
             AnyControlTube::TaggedControlTube(t) => control_tubes.push(t),
             AnyControlTube::VmMemoryTube(t) => vm_memory_control_tubes.push(t),
         }
     }
 
+    // The API server translates HTTP requests into requests sent through a regular control tube.
+    let _api_listener = if let Some(path) = cfg.api_socket_path.as_ref() {
+        let listener =
+            UnlinkUnixListener(UnixListener::bind(path).context("failed to create API server")?);
+        let (api_host_tube, api_tube) = Tube::pair().context("failed to create tube")?;
+        control_tubes.push(TaggedControlTube::Vm(api_host_tube));
+        api_server::start_api_server(&listener, api_tube)?;
+        Some(listener)
+    } else {
+        None
+    };
+
+    // The QMP server translates QMP commands into requests sent through a regular control tube.
+    let _qmp_listener = if let Some(path) = cfg.qmp_socket_path.as_ref() {
+        let listener =
+            UnlinkUnixListener(UnixListener::bind(path).context("failed to create QMP server")?);
+        let (qmp_host_tube, qmp_tube) = Tube::pair().context("failed to create tube")?;
+        control_tubes.push(TaggedControlTube::Vm(qmp_host_tube));
+        qmp::start_qmp_server(&listener, qmp_tube)?;
+        Some(listener)
+    } else {
+        None
+    };
+
+    // The metrics server reads the balloon and vmm-swap gauges through a regular control tube.
+    if let Some(address) = cfg.metrics_address {
+        let (metrics_host_tube, metrics_tube) = Tube::pair().context("failed to create tube")?;
+        control_tubes.push(TaggedControlTube::Vm(metrics_host_tube));
+        metrics_server::start_metrics_server(address, metrics_tube)?;
+    }
+
+    // The swap policy thread sends its swap commands through a regular control tube.
+    #[cfg(feature = "swap")]
+    let swap_policy = if let Some(path) = cfg.swap_policy.as_ref() {
+        let policy = swap::policy::SwapPolicy::from_file(path)?;
+        let (policy_host_tube, policy_tube) = Tube::pair().context("failed to create tube")?;
+        control_tubes.push(TaggedControlTube::Vm(policy_host_tube));
+        Some((policy, policy_tube))
+    } else {
+        None
+    };
+
     #[cfg(feature = "gdb")]
     let (to_gdb_channel, gdb) = if let Some(port) = cfg.gdb {
         // GDB needs a control socket to interrupt vcpus.
@@ -3621,6 +4881,8 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         RegisteredEvent,
         #[cfg(feature = "balloon")]
         BalloonTube,
+        InputWakeup,
+        PowerdownTimeout,
     }
     stdin()
         .set_raw_mode()
@@ -3651,6 +4913,11 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             .context("failed to add descriptor to wait context")?;
     }
 
+    let mut powerdown_timer = Timer::new().context("failed to create powerdown timer")?;
+    wait_ctx
+        .add(&powerdown_timer, Token::PowerdownTimeout)
+        .context("failed to add descriptor to wait context")?;
+
     #[cfg(feature = "balloon")]
     let mut balloon_tube = balloon_host_tube
         .map(|tube| -> Result<BalloonTube> {
@@ -3786,16 +5053,29 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         // Wait until a GDB client attaches
         run_mode = VmRunMode::Breakpoint;
     }
-    // If we are restoring from a snapshot, then start suspended.
-    let (run_mode, post_restore_run_mode) = if cfg.restore_path.is_some() {
-        (VmRunMode::Suspending, run_mode)
-    } else {
-        (run_mode, run_mode)
-    };
+    // If we are restoring from a snapshot or a migration, then start suspended.
+    let (run_mode, post_restore_run_mode) =
+        if cfg.restore_path.is_some() || cfg.migrate_receive.is_some() {
+            (VmRunMode::Suspending, run_mode)
+        } else {
+            (run_mode, run_mode)
+        };
 
     // Architecture-specific code must supply a vcpu_init element for each VCPU.
     assert_eq!(vcpus.len(), linux.vcpu_init.len());
 
+    // CPUID only tells how many vCPUs share each cache, the guest infers which from APIC IDs.
+    #[cfg(target_arch = "x86_64")]
+    let host_cache_sharing: Vec<Vec<u32>> = host_vcpu_caches(&cfg)?
+        .iter()
+        .map(|caches| {
+            caches
+                .iter()
+                .map(|cache| cache.shared_vcpus.iter().count() as u32)
+                .collect()
+        })
+        .collect();
+
     let (vcpu_pid_tid_sender, vcpu_pid_tid_receiver) = mpsc::channel();
     for ((cpu_id, vcpu), vcpu_init) in vcpus.into_iter().enumerate().zip(linux.vcpu_init.drain(..))
     {
@@ -3834,6 +5114,9 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             cfg.no_smt,
             cfg.itmt,
             vcpu_hybrid_type,
+            !cfg.no_async_pf,
+            cfg.cpuid_overrides.clone(),
+            host_cache_sharing.get(cpu_id).cloned().unwrap_or_default(),
         ));
         #[cfg(target_arch = "x86_64")]
         let bus_lock_ratelimit_ctrl = Arc::clone(&bus_lock_ratelimit_ctrl);
@@ -3879,6 +5162,10 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             },
             #[cfg(target_arch = "x86_64")]
             bus_lock_ratelimit_ctrl,
+            #[cfg(target_arch = "aarch64")]
+            linux.suspend_tube.0.clone(),
+            #[cfg(target_arch = "aarch64")]
+            cfg.boot_vcpu_count.is_some_and(|count| cpu_id >= count),
             run_mode,
             cfg.boost_uclamp,
             vcpu_pid_tid_sender.clone(),
@@ -3905,6 +5192,21 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         }
     }
 
+    let vcpu_boost = match &cfg.vcpu_boost {
+        Some(options) => {
+            let vcpu_tids = vcpus_pid_tid.values().map(|(_pid, tid)| *tid).collect();
+            Some(Arc::new(VcpuBoost::new(options, vcpu_tids)?))
+        }
+        None => None,
+    };
+    let _vcpu_boost_thread = vcpu_boost.clone().map(vcpu_boost::start_vcpu_boost_thread);
+
+    #[cfg(feature = "swap")]
+    if let Some((policy, policy_tube)) = swap_policy {
+        let vcpu_tids = vcpus_pid_tid.values().map(|(_pid, tid)| *tid).collect();
+        swap_policy::start_swap_policy_thread(policy, vcpu_tids, policy_tube)?;
+    }
+
     #[cfg(feature = "gdb")]
     // Spawn GDB thread.
     if let Some((gdb_port_num, gdb_control_tube, from_vcpu_channel)) = gdb {
@@ -3930,6 +5232,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 irq_chip_for_thread,
                 sys_allocator_for_thread,
                 irq_handler_control_for_thread,
+                vcpu_boost,
             )
         })
         .unwrap();
@@ -3961,6 +5264,12 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     // See comment on `VmRequest::execute`.
     let mut suspended_pvclock_state: Option<hypervisor::ClockState> = None;
 
+    // Configuration as changed by `crosvm reload-config`, set on the first reload.
+    let mut running_config = None;
+
+    // Shutdown requested by `crosvm powerdown`, if any.
+    let mut powerdown = None;
+
     // Restore VM (if applicable).
     // Must happen after the vCPU barrier to avoid deadlock.
     if let Some(path) = &cfg.restore_path {
@@ -3980,6 +5289,11 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                     .restore(image, linux.vcpu_count)
             },
             /* require_encrypted= */ false,
+            cfg.restore_key_file
+                .as_deref()
+                .map(snapshot::read_key_file)
+                .transpose()?,
+            cfg.restore_shared_memory,
             &mut suspended_pvclock_state,
             &linux.vm,
         )?;
@@ -3991,6 +5305,33 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         )
     }
 
+    // Receive the VM from a live migration source (if applicable).
+    if let Some(listen_address) = &cfg.migrate_receive {
+        vm_control::migration::receive(
+            listen_address,
+            |msg| vcpu::kick_all_vcpus(&vcpu_handles, linux.irq_chip.as_irq_chip(), msg),
+            |msg, index| {
+                vcpu::kick_vcpu(&vcpu_handles.get(index), linux.irq_chip.as_irq_chip(), msg)
+            },
+            &irq_handler_control,
+            &device_ctrl_tube,
+            linux.vcpu_count,
+            |image| {
+                linux
+                    .irq_chip
+                    .try_box_clone()?
+                    .restore(image, linux.vcpu_count)
+            },
+            &mut suspended_pvclock_state,
+            &linux.vm,
+        )?;
+        vcpu::kick_all_vcpus(
+            &vcpu_handles,
+            linux.irq_chip.as_irq_chip(),
+            VcpuControl::RunState(post_restore_run_mode),
+        )
+    }
+
     #[cfg(feature = "swap")]
     if let Some(swap_controller) = &swap_controller {
         swap_controller
@@ -4019,6 +5360,8 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     let mut registered_evt_tubes: HashMap<RegisteredEvent, HashSet<AddressedProtoTube>> =
         HashMap::new();
 
+    notify_extensions(&extension_tubes, VmEvent::Running);
+
     'wait: loop {
         let events = {
             match wait_ctx.wait() {
@@ -4035,28 +5378,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             match event.token {
                 #[cfg(feature = "registered_events")]
                 Token::RegisteredEvent => match reg_evt_rdtube.recv::<RegisteredEventWithData>() {
-                    Ok(reg_evt) => {
-                        let evt = reg_evt.into_event();
-                        let mut tubes_to_remove: Vec<String> = Vec::new();
-                        if let Some(tubes) = registered_evt_tubes.get_mut(&evt) {
-                            for tube in tubes.iter() {
-                                if let Err(e) = tube.send(&reg_evt.into_proto()) {
-                                    warn!(
-                                        "failed to send registered event {:?} to {}, removing from \
-                                         registrations: {}",
-                                        reg_evt, tube.socket_addr, e
-                                    );
-                                    tubes_to_remove.push(tube.socket_addr.clone());
-                                }
-                            }
-                        }
-                        for tube_addr in tubes_to_remove {
-                            for tubes in registered_evt_tubes.values_mut() {
-                                tubes.retain(|t| t.socket_addr != tube_addr);
-                            }
-                        }
-                        registered_evt_tubes.retain(|_, tubes| !tubes.is_empty());
-                    }
+                    Ok(reg_evt) => send_registered_event(&mut registered_evt_tubes, &reg_evt),
                     Err(e) => {
                         warn!("failed to recv RegisteredEvent: {}", e);
                     }
@@ -4080,6 +5402,45 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             VmEventType::Panic(panic_code) => {
                                 pvpanic_code = PvPanicCode::from_u8(panic_code);
                                 info!("Guest reported panic [Code: {}]", pvpanic_code);
+                                #[cfg_attr(
+                                    not(feature = "registered_events"),
+                                    allow(unused_variables)
+                                )]
+                                let core_dump = match &cfg.pvpanic_core_dump_dir {
+                                    Some(dir) if pvpanic_code == PvPanicCode::Panicked => {
+                                        let kick_all_vcpus = |msg| {
+                                            vcpu::kick_all_vcpus(
+                                                &vcpu_handles,
+                                                linux.irq_chip.as_irq_chip(),
+                                                msg,
+                                            )
+                                        };
+                                        match dump_panic_core(
+                                            &kick_all_vcpus,
+                                            vcpu_handles.len(),
+                                            linux.vm.get_memory(),
+                                            dir,
+                                        ) {
+                                            Ok(path) => {
+                                                info!("wrote guest core to {}", path.display());
+                                                Some(path)
+                                            }
+                                            Err(e) => {
+                                                error!("failed to dump guest core: {:#}", e);
+                                                None
+                                            }
+                                        }
+                                    }
+                                    _ => None,
+                                };
+                                #[cfg(feature = "registered_events")]
+                                send_registered_event(
+                                    &mut registered_evt_tubes,
+                                    &RegisteredEventWithData::GuestPanic {
+                                        code: panic_code,
+                                        core_dump,
+                                    },
+                                );
                                 break_to_wait = false;
                             }
                             VmEventType::WatchdogReset => {
@@ -4103,6 +5464,16 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                         let mode = if is_suspend_request {
                             VmRunMode::Suspending
                         } else {
+                            #[cfg(target_arch = "x86_64")]
+                            if let Err(e) = prepare_s3_resume(
+                                &linux.pm,
+                                linux.vm.get_memory(),
+                                &vcpu_handles,
+                                linux.irq_chip.as_irq_chip(),
+                                cfg.s3_release_devices.then_some(&device_ctrl_tube),
+                            ) {
+                                error!("failed to resume from S3: {:#}", e);
+                            }
                             for dev in &linux.resume_notify_devices {
                                 dev.lock().resume_imminent();
                             }
@@ -4114,11 +5485,63 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             linux.irq_chip.as_irq_chip(),
                             VcpuControl::RunState(mode),
                         );
+                        // Input activity only matters while the guest is suspended.
+                        if let Some(evt) = input_wakeup_evt.as_ref().filter(|_| is_suspend_request)
+                        {
+                            if let Err(e) = evt
+                                .reset()
+                                .and_then(|_| wait_ctx.add(evt, Token::InputWakeup))
+                            {
+                                error!("failed to watch for input wakeup: {}", e);
+                            }
+                        }
+                        #[cfg(target_arch = "x86_64")]
+                        if is_suspend_request && cfg.s3_release_devices {
+                            if let Err(e) = sleep_devices_for_s3(
+                                &linux.pm,
+                                &vcpu_handles,
+                                linux.irq_chip.as_irq_chip(),
+                                &device_ctrl_tube,
+                            ) {
+                                error!("failed to put devices to sleep for S3: {:#}", e);
+                            }
+                        }
                     }
                     Err(err) => {
                         warn!("Failed to read suspend tube {:?}", err);
                     }
                 },
+                Token::InputWakeup => {
+                    if let Some(evt) = &input_wakeup_evt {
+                        let _ = wait_ctx.delete(evt);
+                    }
+                    // The guest may already have been resumed through the control socket.
+                    let kick_all_vcpus = |msg| {
+                        vcpu::kick_all_vcpus(&vcpu_handles, linux.irq_chip.as_irq_chip(), msg)
+                    };
+                    match get_vcpu_state(kick_all_vcpus, vcpu_handles.len()) {
+                        Ok(VmRunMode::Suspending) => {
+                            info!("Input activity, resuming the VM");
+                            for dev in &linux.resume_notify_devices {
+                                dev.lock().resume_imminent();
+                            }
+                            kick_all_vcpus(VcpuControl::RunState(VmRunMode::Running));
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("failed to get vcpu state: {:#}", e),
+                    }
+                }
+                Token::PowerdownTimeout => {
+                    if let Err(e) = powerdown_timer.mark_waited() {
+                        error!("failed to read the powerdown timer: {}", e);
+                    }
+                    if let Some(powerdown) = powerdown.as_mut() {
+                        warn!("guest did not shut down in time, stopping the VM");
+                        powerdown.forced = true;
+                        exit_state = ExitState::Stop;
+                        break 'wait;
+                    }
+                }
                 Token::ChildSignal => {
                     // Print all available siginfo structs, then exit the loop if child process has
                     // been exited except CLD_STOPPED and CLD_CONTINUED. the two should be ignored
@@ -4201,8 +5624,12 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             sys_allocator: &sys_allocator_mutex,
                             control_tubes: &control_tubes,
                             disk_host_tubes: &disk_host_tubes[..],
+                            console_host_tubes: &console_host_tubes[..],
+                            net_host_tubes: &net_host_tubes[..],
+                            fs_host_tubes: &fs_host_tubes[..],
                             #[cfg(feature = "audio")]
                             snd_host_tubes: &snd_host_tubes[..],
+                            extension_tubes: &extension_tubes[..],
                             #[cfg(feature = "gpu")]
                             gpu_control_tube: gpu_control_tube.as_ref(),
                             #[cfg(feature = "usb")]
@@ -4230,6 +5657,13 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             vfio_container_manager: &mut vfio_container_manager,
                             suspended_pvclock_state: &mut suspended_pvclock_state,
                             vcpus_pid_tid: &vcpus_pid_tid,
+                            #[cfg(target_arch = "x86_64")]
+                            rtc_offset: &rtc_offset,
+                            #[cfg(target_arch = "x86_64")]
+                            bus_lock_ratelimit_ctrl: &bus_lock_ratelimit_ctrl,
+                            running_config: &mut running_config,
+                            powerdown: &mut powerdown,
+                            powerdown_timer: &mut powerdown_timer,
                         };
                         let (exit_requested, mut ids_to_remove, add_tubes) =
                             process_vm_control_event(&mut state, id, socket)?;
@@ -4285,6 +5719,8 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         )?;
     }
 
+    notify_extensions(&extension_tubes, VmEvent::Exiting);
+
     vcpu::kick_all_vcpus(
         &vcpu_handles,
         linux.irq_chip.as_irq_chip(),
@@ -4381,6 +5817,21 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         }
     }
 
+    // The VM is fully stopped, run the cleanup hooks before telling `crosvm powerdown`.
+    if let Some(powerdown) = powerdown {
+        run_powerdown_hooks(&cfg.powerdown_hooks, powerdown.forced);
+        let response = VmResponse::PoweredDown {
+            forced: powerdown.forced,
+        };
+        for id in powerdown.waiting_ids {
+            if let Some(TaggedControlTube::Vm(tube)) = control_tubes.get(&id) {
+                if let Err(e) = tube.send(&response) {
+                    error!("failed to send VmResponse: {}", e);
+                }
+            }
+        }
+    }
+
     stdin()
         .set_canon_mode()
         .expect("failed to restore canonical mode for terminal");
@@ -4388,6 +5839,22 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     Ok(exit_state)
 }
 
+/// Runs the `--powerdown-hook` programs in order, telling them through `CROSVM_POWERDOWN` whether
+/// the guest shut down by itself (`graceful`) or the VM was stopped after the timeout (`forced`).
+fn run_powerdown_hooks(hooks: &[PathBuf], forced: bool) {
+    let mode = if forced { "forced" } else { "graceful" };
+    for hook in hooks {
+        match process::Command::new(hook)
+            .env("CROSVM_POWERDOWN", mode)
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!("powerdown hook {} failed: {}", hook.display(), status),
+            Err(e) => error!("failed to run powerdown hook {}: {}", hook.display(), e),
+        }
+    }
+}
+
 #[derive(EventToken)]
 enum IrqHandlerToken {
     IrqFd { index: IrqEventIndex },
@@ -4402,6 +5869,7 @@ fn irq_handler_thread(
     mut irq_chip: Box<dyn IrqChipArch + 'static>,
     sys_allocator_mutex: Arc<Mutex<SystemAllocator>>,
     handler_control: Tube,
+    vcpu_boost: Option<Arc<VcpuBoost>>,
 ) -> anyhow::Result<()> {
     let wait_ctx = WaitContext::build_with(&[(
         handler_control.get_read_notifier(),
@@ -4530,6 +5998,9 @@ fn irq_handler_thread(
                     if let Err(e) = irq_chip.service_irq_event(index) {
                         error!("failed to signal irq {}: {}", index, e);
                     }
+                    if let Some(vcpu_boost) = &vcpu_boost {
+                        vcpu_boost.boost();
+                    }
                 }
                 IrqHandlerToken::DelayedIrqFd => {
                     if let Err(e) = irq_chip.process_delayed_irq_events() {
@@ -4605,6 +6076,7 @@ fn handle_irq_tube_request(
                             Ok(())
                         }
                         IrqSetup::Route(route) => irq_chip.route_irq(route),
+                        IrqSetup::Routes(routes) => irq_chip.route_irqs(&routes),
                         IrqSetup::UnRegister(irq, ev) => {
                             let irq_evt = devices::IrqEdgeEvent::from_event(ev.try_clone()?);
                             irq_chip.unregister_edge_irq_event(irq, &irq_evt)
@@ -4970,6 +6442,7 @@ pub fn start_devices(opts: DevicesCommand) -> anyhow::Result<()> {
         Executor::set_default_executor_kind(async_executor)
             .context("Failed to set the default async executor")?;
     }
+    set_executor_seccomp_features()?;
 
     struct DeviceJailInfo {
         // Unique name for the device, in the form `foomatic-0`.
@@ -5115,8 +6588,6 @@ pub fn setup_emulator_crash_reporting(_cfg: &Config) -> anyhow::Result<String> {
 mod tests {
     use std::path::PathBuf;
 
-    use vm_memory::MemoryRegionPurpose;
-
     use super::*;
 
     // Create a file-backed mapping parameters struct with the given `address` and `size` and other
@@ -5367,6 +6838,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn guest_mem_hugetlb_backing_aligned_ram_only() {
+        let cfg = Config {
+            hugetlb: true,
+            hugetlb_page_size: Some(0x20_0000),
+            ..Default::default()
+        };
+        let layout = apply_hugetlb_backing(
+            &cfg,
+            vec![
+                (GuestAddress(0), 0xa_0000, Default::default()),
+                (
+                    GuestAddress(0x10_0000),
+                    0x10_0000,
+                    MemoryRegionOptions::new().purpose(MemoryRegionPurpose::Bios),
+                ),
+                (GuestAddress(0x20_0000), 0x40_0000, Default::default()),
+            ],
+        )
+        .unwrap();
+        let backings: Vec<MemoryBacking> = layout.iter().map(|(_, _, o)| o.backing).collect();
+        assert_eq!(
+            backings,
+            vec![
+                MemoryBacking::Shared,
+                MemoryBacking::Shared,
+                MemoryBacking::Hugetlb(Some(0x20_0000)),
+            ]
+        );
+    }
+
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     #[test]
     fn normalized_cpu_ipc_ratios_simple() {
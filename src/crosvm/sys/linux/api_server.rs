@@ -0,0 +1,453 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! An HTTP management API served on a unix socket, modeled after the Cloud Hypervisor API.
+//!
+//! Each endpoint is translated into [VmRequest]s sent to the main control loop. Requests and
+//! responses bodies are JSON.
+//!
+//! | Endpoint                      | Body                        | Action                        |
+//! |-------------------------------|-----------------------------|-------------------------------|
+//! | `GET /api/v1/vmm.ping`        |                             | crosvm version                |
+//! | `GET /api/v1/vm.info`         |                             | run state of the VM           |
+//! | `GET /api/v1/vm.counters`     |                             | balloon and memory statistics |
+//! | `PUT /api/v1/vm.pause`        |                             | suspend the VM                |
+//! | `PUT /api/v1/vm.resume`       |                             | resume the VM                 |
+//! | `PUT /api/v1/vm.power-button` |                             | press the power button        |
+//! | `PUT /api/v1/vm.shutdown`     |                             | stop crosvm                   |
+//! | `PUT /api/v1/vm.snapshot`     | `{"destination_url": URL}`  | snapshot into a `file://` URL |
+//! | `PUT /api/v1/vm.resize`       | `{"desired_balloon": SIZE}` | set the balloon size in bytes |
+//! | `PUT /api/v1/vm.add-net`      | `{"tap": NAME}`             | hotplug a virtio-net device   |
+//! | `PUT /api/v1/vm.add-device`   | `{"path": PATH}`            | hotplug a VFIO device         |
+//!
+//! Actions reply `204 No Content` on success. Failures reply with an error status and a
+//! `{"error": MESSAGE}` body.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::Tube;
+use base::UnlinkUnixListener;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+#[cfg(feature = "balloon")]
+use vm_control::BalloonControlCommand;
+use vm_control::HotPlugDeviceInfo;
+use vm_control::HotPlugDeviceType;
+#[cfg(feature = "pci-hotplug")]
+use vm_control::NetControlCommand;
+use vm_control::SnapshotCommand;
+use vm_control::VmRequest;
+use vm_control::VmResponse;
+use vm_control::VmRunMode;
+
+const API_PREFIX: &str = "/api/v1/";
+/// Bounds the memory allocated for a request body.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct HttpResponse {
+    status: u16,
+    body: Option<Value>,
+}
+
+impl HttpResponse {
+    fn ok(body: Value) -> Self {
+        HttpResponse {
+            status: 200,
+            body: Some(body),
+        }
+    }
+
+    fn no_content() -> Self {
+        HttpResponse {
+            status: 204,
+            body: None,
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        HttpResponse {
+            status,
+            body: Some(json!({ "error": message.into() })),
+        }
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotConfig {
+    destination_url: String,
+}
+
+#[cfg_attr(not(feature = "balloon"), allow(dead_code))]
+#[derive(Deserialize)]
+struct ResizeConfig {
+    desired_balloon: u64,
+}
+
+#[cfg_attr(not(feature = "pci-hotplug"), allow(dead_code))]
+#[derive(Deserialize)]
+struct NetConfig {
+    tap: String,
+}
+
+#[derive(Deserialize)]
+struct DeviceConfig {
+    path: PathBuf,
+}
+
+/// What the server does for an endpoint.
+enum Endpoint {
+    Ping,
+    Info,
+    Counters,
+    Action(VmRequest),
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, HttpResponse> {
+    serde_json::from_slice(body)
+        .map_err(|e| HttpResponse::error(400, format!("invalid request body: {}", e)))
+}
+
+fn route(method: &str, path: &str, body: &[u8]) -> Result<Endpoint, HttpResponse> {
+    let Some(name) = path.strip_prefix(API_PREFIX) else {
+        return Err(HttpResponse::error(404, format!("unknown path {}", path)));
+    };
+    let expected_method = match name {
+        "vmm.ping" | "vm.info" | "vm.counters" => "GET",
+        "vm.pause" | "vm.resume" | "vm.power-button" | "vm.shutdown" | "vm.snapshot"
+        | "vm.resize" | "vm.add-net" | "vm.add-device" => "PUT",
+        _ => {
+            return Err(HttpResponse::error(
+                404,
+                format!("unknown endpoint {}", name),
+            ))
+        }
+    };
+    if method != expected_method {
+        return Err(HttpResponse::error(
+            405,
+            format!("{} expects {}", name, expected_method),
+        ));
+    }
+    let endpoint = match name {
+        "vmm.ping" => Endpoint::Ping,
+        "vm.info" => Endpoint::Info,
+        "vm.counters" => Endpoint::Counters,
+        "vm.pause" => Endpoint::Action(VmRequest::SuspendVm),
+        "vm.resume" => Endpoint::Action(VmRequest::ResumeVm),
+        "vm.power-button" => Endpoint::Action(VmRequest::Powerbtn),
+        "vm.shutdown" => Endpoint::Action(VmRequest::Exit),
+        "vm.snapshot" => {
+            let config: SnapshotConfig = parse_body(body)?;
+            let Some(path) = config.destination_url.strip_prefix("file://") else {
+                return Err(HttpResponse::error(
+                    400,
+                    "destination_url must be a file:// URL",
+                ));
+            };
+            Endpoint::Action(VmRequest::Snapshot(SnapshotCommand::Take {
+                snapshot_path: PathBuf::from(path),
+                compress_memory: false,
+                encrypt: false,
+                zstd_level: None,
+                key: None,
+            }))
+        }
+        #[cfg(feature = "balloon")]
+        "vm.resize" => {
+            let config: ResizeConfig = parse_body(body)?;
+            Endpoint::Action(VmRequest::BalloonCommand(BalloonControlCommand::Adjust {
+                num_bytes: config.desired_balloon,
+                wait_for_success: false,
+            }))
+        }
+        #[cfg(feature = "pci-hotplug")]
+        "vm.add-net" => {
+            let config: NetConfig = parse_body(body)?;
+            Endpoint::Action(VmRequest::HotPlugNetCommand(NetControlCommand::AddTap(
+                config.tap,
+            )))
+        }
+        "vm.add-device" => {
+            let config: DeviceConfig = parse_body(body)?;
+            Endpoint::Action(VmRequest::HotPlugVfioCommand {
+                device: HotPlugDeviceInfo {
+                    device_type: HotPlugDeviceType::EndPoint,
+                    path: config.path,
+                    hp_interrupt: true,
+                    p2p: false,
+                },
+                add: true,
+            })
+        }
+        _ => {
+            return Err(HttpResponse::error(
+                404,
+                format!("{} is not supported by this build", name),
+            ))
+        }
+    };
+    Ok(endpoint)
+}
+
+fn run_state(mode: VmRunMode) -> &'static str {
+    match mode {
+        VmRunMode::Running => "Running",
+        VmRunMode::Suspending => "Paused",
+        VmRunMode::Exiting => "Shutdown",
+        VmRunMode::Breakpoint => "Breakpoint",
+    }
+}
+
+fn send_request(tube: &Tube, request: &VmRequest) -> Result<VmResponse, HttpResponse> {
+    tube.send(request)
+        .and_then(|()| tube.recv())
+        .map_err(|e| HttpResponse::error(500, format!("failed to send the request: {}", e)))
+}
+
+fn counters(tube: &Tube) -> Value {
+    let mut counters = json!({});
+    if let Ok(VmResponse::SharedMemoryStats(stats)) =
+        send_request(tube, &VmRequest::SharedMemoryStats)
+    {
+        counters["shared_memory"] = json!(stats);
+    }
+    #[cfg(feature = "balloon")]
+    if let Ok(VmResponse::BalloonStats {
+        stats,
+        balloon_actual,
+    }) = send_request(
+        tube,
+        &VmRequest::BalloonCommand(BalloonControlCommand::Stats),
+    ) {
+        counters["balloon"] = json!({ "stats": stats, "actual_bytes": balloon_actual });
+    }
+    counters
+}
+
+fn handle_endpoint(tube: &Tube, endpoint: Endpoint) -> Result<HttpResponse, HttpResponse> {
+    match endpoint {
+        Endpoint::Ping => Ok(HttpResponse::ok(json!({
+            "version": env!("CARGO_PKG_VERSION"),
+        }))),
+        Endpoint::Info => match send_request(tube, &VmRequest::GetRunMode)? {
+            VmResponse::RunMode(mode) => Ok(HttpResponse::ok(json!({ "state": run_state(mode) }))),
+            response => Err(HttpResponse::error(500, response.to_string())),
+        },
+        Endpoint::Counters => Ok(HttpResponse::ok(counters(tube))),
+        Endpoint::Action(request) => match send_request(tube, &request)? {
+            VmResponse::Ok => Ok(HttpResponse::no_content()),
+            #[cfg(feature = "pci-hotplug")]
+            VmResponse::PciHotPlugResponse { .. } => Ok(HttpResponse::no_content()),
+            response => Err(HttpResponse::error(500, response.to_string())),
+        },
+    }
+}
+
+/// Reads the next request of the connection, or returns `None` once the client closed it.
+fn read_request(reader: &mut impl BufRead) -> anyhow::Result<Option<HttpRequest>> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("invalid request line {:?}", request_line.trim_end());
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            bail!("connection closed in the request headers");
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().context("invalid Content-Length")?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        bail!("request body is too large: {} bytes", content_length);
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(HttpRequest {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        body,
+    }))
+}
+
+fn write_response(writer: &mut impl Write, response: &HttpResponse) -> std::io::Result<()> {
+    let body = match &response.body {
+        Some(body) => body.to_string(),
+        None => String::new(),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason_phrase(response.status)
+    )?;
+    if response.body.is_some() {
+        write!(writer, "Content-Type: application/json\r\n")?;
+    }
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn serve_client(stream: UnixStream, tube: &Tube) -> anyhow::Result<()> {
+    let mut writer = stream.try_clone().context("failed to clone API stream")?;
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader)? {
+        let response = route(&request.method, &request.path, &request.body)
+            .and_then(|endpoint| handle_endpoint(tube, endpoint))
+            .unwrap_or_else(|response| response);
+        write_response(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+/// Starts a thread which serves API clients connecting to `listener`.
+///
+/// The requests are sent to the main control loop through `tube`. Clients are served one at a
+/// time.
+pub(crate) fn start_api_server(listener: &UnlinkUnixListener, tube: Tube) -> anyhow::Result<()> {
+    let listener = listener
+        .try_clone()
+        .context("failed to clone API listener")?;
+    thread::Builder::new()
+        .name("api_server".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = serve_client(stream, &tube) {
+                            error!("API client error: {:#}", e);
+                        }
+                    }
+                    Err(e) => error!("failed to accept API client: {}", e),
+                }
+            }
+        })
+        .context("failed to spawn API server thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_requests() {
+        let mut input: &[u8] = b"PUT /api/v1/vm.snapshot HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            content-length: 2\r\n\
+            \r\n\
+            {}\
+            GET /api/v1/vm.info HTTP/1.1\r\n\
+            \r\n";
+        let request = read_request(&mut input).unwrap().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/api/v1/vm.snapshot");
+        assert_eq!(request.body, b"{}");
+        let request = read_request(&mut input).unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert!(request.body.is_empty());
+        assert!(read_request(&mut input).unwrap().is_none());
+
+        let mut truncated: &[u8] = b"GET /api/v1/vm.info HTTP/1.1\r\nHost: localhost\r\n";
+        assert!(read_request(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn route_endpoints() {
+        assert!(matches!(
+            route("GET", "/api/v1/vm.info", b""),
+            Ok(Endpoint::Info)
+        ));
+        assert!(matches!(
+            route("PUT", "/api/v1/vm.pause", b""),
+            Ok(Endpoint::Action(VmRequest::SuspendVm))
+        ));
+        match route(
+            "PUT",
+            "/api/v1/vm.snapshot",
+            br#"{"destination_url": "file:///tmp/snapshot"}"#,
+        ) {
+            Ok(Endpoint::Action(VmRequest::Snapshot(SnapshotCommand::Take {
+                snapshot_path,
+                ..
+            }))) => assert_eq!(snapshot_path, PathBuf::from("/tmp/snapshot")),
+            _ => panic!("unexpected endpoint"),
+        }
+    }
+
+    #[test]
+    fn route_errors() {
+        fn status(method: &str, path: &str, body: &[u8]) -> u16 {
+            route(method, path, body).err().unwrap().status
+        }
+        assert_eq!(status("GET", "/api/v2/vm.info", b""), 404);
+        assert_eq!(status("GET", "/api/v1/vm.boot", b""), 404);
+        assert_eq!(status("PUT", "/api/v1/vm.info", b""), 405);
+        assert_eq!(status("PUT", "/api/v1/vm.snapshot", b"{"), 400);
+        assert_eq!(
+            status(
+                "PUT",
+                "/api/v1/vm.snapshot",
+                br#"{"destination_url": "tcp://host:1234"}"#
+            ),
+            400
+        );
+    }
+
+    #[test]
+    fn write_responses() {
+        let mut out = Vec::new();
+        write_response(&mut out, &HttpResponse::no_content()).unwrap();
+        assert_eq!(out, b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n");
+
+        let mut out = Vec::new();
+        write_response(&mut out, &HttpResponse::ok(json!({ "state": "Running" }))).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 19\r\n\r\n\
+             {\"state\":\"Running\"}"
+        );
+    }
+}
@@ -15,6 +15,8 @@ use devices::virtio::vsock::VsockConfig;
 use devices::virtio::NetParameters;
 use devices::SerialParameters;
 use jail::JailConfig;
+use vm_control::guest_agent::GuestAgentAddress;
+use vm_control::guest_agent::GUEST_AGENT_VSOCK_PORT;
 
 use crate::crosvm::config::validate_serial_parameters;
 
@@ -41,7 +43,8 @@ fn parse_vu_serial_options(s: &str) -> Result<VhostUserParams<SerialParameters>,
 #[argh(subcommand, name = "devices")]
 /// Start one or several jailed device processes.
 pub struct DevicesCommand {
-    /// configure async executor backend to "uring" or "epoll" (default).
+    /// configure async executor backend to "uring" or "epoll". Defaults to "uring" when the
+    /// kernel supports it and "epoll" otherwise.
     #[argh(option, arg_name = "EXECUTOR")]
     pub async_executor: Option<ExecutorKind>,
 
@@ -128,10 +131,122 @@ pub struct DevicesCommand {
     pub control_socket: Option<PathBuf>,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "guest-agent")]
+/// Talk to the guest agent of a VM, or run the agent inside a guest
+pub struct GuestAgentCommand {
+    #[argh(subcommand)]
+    pub nested: GuestAgentSubcommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum GuestAgentSubcommand {
+    Exec(GuestAgentExecCommand),
+    CopyTo(GuestAgentCopyToCommand),
+    CopyFrom(GuestAgentCopyFromCommand),
+    SyncTime(GuestAgentSyncTimeCommand),
+    Shutdown(GuestAgentShutdownCommand),
+    Serve(GuestAgentServeCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "exec")]
+/// Run a program in the guest and print its output
+pub struct GuestAgentExecCommand {
+    #[argh(option, arg_name = "KEY=VALUE")]
+    /// environment variable to set for the program. Can be given more than once
+    pub env: Vec<String>,
+    #[argh(switch)]
+    /// send the standard input of this command to the program
+    pub stdin: bool,
+    #[argh(positional, arg_name = "AGENT")]
+    /// address of the agent: vsock:CID[:PORT] or the path of the Unix socket of its
+    /// virtio-console port
+    pub agent: GuestAgentAddress,
+    #[argh(positional, arg_name = "PROGRAM")]
+    /// program to run
+    pub program: String,
+    #[argh(positional, arg_name = "ARGS")]
+    /// arguments of the program
+    pub args: Vec<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "copy-to")]
+/// Copy a host file to the guest
+pub struct GuestAgentCopyToCommand {
+    #[argh(option, arg_name = "MODE", default = "String::from(\"644\")")]
+    /// permissions of the guest file if it is created, in octal (default: 644)
+    pub mode: String,
+    #[argh(positional, arg_name = "AGENT")]
+    /// address of the agent: vsock:CID[:PORT] or the path of the Unix socket of its
+    /// virtio-console port
+    pub agent: GuestAgentAddress,
+    #[argh(positional, arg_name = "SOURCE")]
+    /// path of the host file
+    pub source: PathBuf,
+    #[argh(positional, arg_name = "DESTINATION")]
+    /// path of the guest file
+    pub destination: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "copy-from")]
+/// Copy a guest file to the host
+pub struct GuestAgentCopyFromCommand {
+    #[argh(positional, arg_name = "AGENT")]
+    /// address of the agent: vsock:CID[:PORT] or the path of the Unix socket of its
+    /// virtio-console port
+    pub agent: GuestAgentAddress,
+    #[argh(positional, arg_name = "SOURCE")]
+    /// path of the guest file
+    pub source: String,
+    #[argh(positional, arg_name = "DESTINATION")]
+    /// path of the host file
+    pub destination: PathBuf,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "sync-time")]
+/// Set the clock of the guest to the time of the host
+pub struct GuestAgentSyncTimeCommand {
+    #[argh(positional, arg_name = "AGENT")]
+    /// address of the agent: vsock:CID[:PORT] or the path of the Unix socket of its
+    /// virtio-console port
+    pub agent: GuestAgentAddress,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "shutdown")]
+/// Power the guest off
+pub struct GuestAgentShutdownCommand {
+    #[argh(switch)]
+    /// reboot the guest instead
+    pub reboot: bool,
+    #[argh(positional, arg_name = "AGENT")]
+    /// address of the agent: vsock:CID[:PORT] or the path of the Unix socket of its
+    /// virtio-console port
+    pub agent: GuestAgentAddress,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "serve")]
+/// Run the guest agent. To be run inside the guest
+pub struct GuestAgentServeCommand {
+    #[argh(option, arg_name = "PORT", default = "GUEST_AGENT_VSOCK_PORT")]
+    /// vsock port to listen on (default: 9000)
+    pub vsock_port: u32,
+    #[argh(option, arg_name = "NAME")]
+    /// serve on the virtio-console port with this name instead of vsock
+    pub console_port: Option<String>,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand)]
 /// Unix Commands
 pub enum Commands {
     #[cfg(any(target_os = "android", target_os = "linux"))]
     Devices(DevicesCommand),
+    GuestAgent(GuestAgentCommand),
 }
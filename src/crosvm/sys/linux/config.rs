@@ -44,7 +44,47 @@ pub fn check_serial_params(_serial_params: &SerialParameters) -> Result<(), Stri
     Ok(())
 }
 
-pub fn validate_config(_cfg: &mut Config) -> std::result::Result<(), String> {
+fn default_vcpu_boost_util() -> u32 {
+    1024
+}
+
+fn default_vcpu_boost_decay_ms() -> u64 {
+    4
+}
+
+/// Scheduler boost given to the vCPU threads when crosvm delivers an interrupt to the guest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct VcpuBoostOptions {
+    /// Minimum utilization clamp, out of 1024, applied to the vCPU threads while boosted.
+    #[serde(default = "default_vcpu_boost_util")]
+    pub util: u32,
+    /// How long in milliseconds the boost lasts after the last interrupt. The clamp is then
+    /// halved after each further period until it reaches zero.
+    #[serde(default = "default_vcpu_boost_decay_ms")]
+    pub decay_ms: u64,
+}
+
+pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
+    if let Some(vcpu_boost) = &cfg.vcpu_boost {
+        if vcpu_boost.util == 0 || vcpu_boost.util > 1024 {
+            return Err("'vcpu-boost' util must be between 1 and 1024".to_string());
+        }
+        if vcpu_boost.decay_ms == 0 {
+            return Err("'vcpu-boost' decay-ms must be greater than 0".to_string());
+        }
+        if cfg.boost_uclamp {
+            return Err("'vcpu-boost' and 'boost-uclamp' are mutually exclusive".to_string());
+        }
+    }
+    if cfg.vfio.iter().any(|vfio| vfio.p2p)
+        && !cfg
+            .vfio
+            .iter()
+            .any(|vfio| vfio.iommu == IommuDevType::VirtioIommu)
+    {
+        return Err("'p2p' VFIO devices require a VFIO device with 'iommu=viommu'".to_string());
+    }
     Ok(())
 }
 
@@ -66,6 +106,16 @@ pub struct VfioOption {
     /// The symbol that labels the overlay device tree node which corresponds to this
     /// VFIO device.
     pub dt_symbol: Option<String>,
+
+    /// Generates the device tree node of a VFIO platform device from its node in the host device
+    /// tree, instead of taking it from an overlay file.
+    #[serde(default)]
+    pub dt_from_host: bool,
+
+    /// Exports the BARs of the device to the virtio-iommu, so that they can be mapped into the
+    /// IOMMU domains of other passthrough devices for peer-to-peer DMA.
+    #[serde(default)]
+    pub p2p: bool,
 }
 
 #[derive(Default, Eq, PartialEq, Serialize, Deserialize)]
@@ -505,6 +555,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn vfio_pci_p2p() {
+        let config: Config = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &[
+                "--vfio",
+                "/path/to/gpu,p2p=true",
+                "--vfio",
+                "/path/to/nic,iommu=viommu",
+                "/dev/null",
+            ],
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        assert!(config.vfio[0].p2p);
+        assert!(!config.vfio[1].p2p);
+    }
+
+    #[test]
+    fn vfio_pci_p2p_requires_viommu() {
+        let config: Result<Config, String> = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &["--vfio", "/path/to/gpu,p2p=true", "/dev/null"],
+        )
+        .unwrap()
+        .try_into();
+
+        assert!(config.is_err());
+    }
+
     #[test]
     fn vfio_platform() {
         let config: Config = crate::crosvm::cmdline::RunCommand::from_args(
@@ -518,6 +600,27 @@ mod tests {
         let vfio = config.vfio.first().unwrap();
 
         assert_eq!(vfio.path, PathBuf::from("/path/to/dev"));
+        assert!(!vfio.dt_from_host);
+    }
+
+    #[test]
+    fn vfio_platform_dt_from_host() {
+        let config: Config = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &[
+                "--vfio",
+                "/path/to/dev,dt-symbol=uart,dt-from-host=true",
+                "/dev/null",
+            ],
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        let vfio = config.vfio.first().unwrap();
+
+        assert_eq!(vfio.dt_symbol.as_deref(), Some("uart"));
+        assert!(vfio.dt_from_host);
     }
 
     #[test]
@@ -962,4 +1065,41 @@ mod tests {
         assert_eq!(opt.inodes_per_group, inodes_per_group);
         assert_eq!(opt.size, size);
     }
+
+    #[test]
+    fn parse_vcpu_boost() {
+        let config: Config = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &["--vcpu-boost", "util=512,decay-ms=8", "/dev/null"],
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            config.vcpu_boost,
+            Some(VcpuBoostOptions {
+                util: 512,
+                decay_ms: 8,
+            })
+        );
+
+        let opt: VcpuBoostOptions = from_key_values("util=1024").unwrap();
+        assert_eq!(opt.decay_ms, 4);
+
+        let config: Result<Config, String> = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &["--vcpu-boost", "util=2048", "/dev/null"],
+        )
+        .unwrap()
+        .try_into();
+        assert!(config.is_err());
+
+        let config: Result<Config, String> = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &["--vcpu-boost", "util=512", "--boost-uclamp", "/dev/null"],
+        )
+        .unwrap()
+        .try_into();
+        assert!(config.is_err());
+    }
 }
@@ -0,0 +1,336 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Applies the changes of a configuration file to a running VM, for `crosvm reload-config`.
+//!
+//! The new configuration is compared option by option with the one the VM runs with, both in
+//! their serialized form. Changes that map to a runtime mechanism (balloon, PCI hotplug, bus lock
+//! rate limit) are applied, the other ones are rejected and leave the VM untouched. The running
+//! configuration is updated with the applied changes only, so that a later reload is compared
+//! with the actual state of the VM.
+
+use std::collections::BTreeSet;
+
+#[cfg(feature = "pci-hotplug")]
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use arch::VcpuArch;
+use arch::VmArch;
+#[cfg(feature = "pci-hotplug")]
+use devices::virtio::block::DiskOption;
+#[cfg(feature = "pci-hotplug")]
+use devices::virtio::NetParameters;
+use serde_json::Map;
+use serde_json::Value;
+#[cfg(feature = "balloon")]
+use vm_control::BalloonControlCommand;
+use vm_control::ConfigChange;
+use vm_control::ConfigReloadReport;
+use vm_control::VmResponse;
+
+#[cfg(feature = "pci-hotplug")]
+use super::add_hotplug_block;
+#[cfg(feature = "pci-hotplug")]
+use super::add_hotplug_net;
+#[cfg(feature = "pci-hotplug")]
+use super::AnyControlTube;
+use super::ControlLoopState;
+use crate::crosvm::config::Config;
+
+/// Serialized configuration of the running VM, indexed by option name.
+pub(super) type RunningConfig = Map<String, Value>;
+
+/// Serializes `cfg` into the form in which configurations are compared.
+fn running_config(cfg: &Config) -> Result<RunningConfig> {
+    match serde_json::to_value(cfg).context("failed to serialize the configuration")? {
+        Value::Object(options) => Ok(options),
+        _ => bail!("the configuration is not serialized as an object"),
+    }
+}
+
+/// Returns the options whose value differs between `running` and `new`, in alphabetical order.
+fn changed_options(running: &RunningConfig, new: &RunningConfig) -> Vec<String> {
+    let options: BTreeSet<&String> = running.keys().chain(new.keys()).collect();
+    options
+        .into_iter()
+        .filter(|option| running.get(*option) != new.get(*option))
+        .cloned()
+        .collect()
+}
+
+/// Returns the entries that `new` adds at the end of the list `old`, or `None` if `new` also
+/// modifies or removes entries of `old`.
+#[cfg_attr(not(feature = "pci-hotplug"), allow(dead_code))]
+fn appended_entries<'a>(old: Option<&Value>, new: Option<&'a Value>) -> Option<&'a [Value]> {
+    let old = match old {
+        Some(Value::Array(old)) => &old[..],
+        None | Some(Value::Null) => &[],
+        _ => return None,
+    };
+    match new {
+        Some(Value::Array(new)) if new.starts_with(old) => Some(&new[old.len()..]),
+        _ => None,
+    }
+}
+
+/// Applies to the VM the changes between its running configuration and `config`, the JSON
+/// serialization of a `Config`.
+pub(super) fn reload_config<V: VmArch, Vcpu: VcpuArch>(
+    state: &mut ControlLoopState<V, Vcpu>,
+    #[cfg(feature = "pci-hotplug")] add_control_tube: &mut impl FnMut(AnyControlTube),
+    config: &str,
+) -> VmResponse {
+    let new = match serde_json::from_str::<Config>(config)
+        .context("failed to parse the configuration")
+        .and_then(|cfg| running_config(&cfg))
+    {
+        Ok(new) => new,
+        Err(e) => return VmResponse::ErrString(format!("{:#}", e)),
+    };
+    // The configuration the VM was started with is only serialized on the first reload.
+    let mut running = match state.running_config.take() {
+        Some(running) => running,
+        None => match running_config(state.cfg) {
+            Ok(running) => running,
+            Err(e) => return VmResponse::ErrString(format!("{:#}", e)),
+        },
+    };
+
+    let mut report = ConfigReloadReport::default();
+    for option in changed_options(&running, &new) {
+        let new_value = new.get(&option);
+        let result = match option.as_str() {
+            #[cfg(feature = "balloon")]
+            "init_memory" => set_init_memory(state, new_value),
+            #[cfg(target_arch = "x86_64")]
+            "bus_lock_ratelimit" => set_bus_lock_ratelimit(state, new_value),
+            #[cfg(feature = "pci-hotplug")]
+            "disks" | "net" => {
+                hotplug_entries(
+                    state,
+                    add_control_tube,
+                    &mut running,
+                    &option,
+                    new_value,
+                    &mut report,
+                );
+                continue;
+            }
+            _ => Err("cannot be changed on a running VM".to_string()),
+        };
+        match result {
+            Ok(detail) => {
+                match new_value {
+                    Some(value) => running.insert(option.clone(), value.clone()),
+                    None => running.remove(&option),
+                };
+                report.applied.push(ConfigChange { option, detail });
+            }
+            Err(detail) => report.rejected.push(ConfigChange { option, detail }),
+        }
+    }
+    *state.running_config = Some(running);
+    VmResponse::ConfigReloadReport(report)
+}
+
+/// Resizes the balloon so that the guest is left with the new `init_memory`.
+///
+/// The balloon adjusts asynchronously, so the change is applied once the request is sent.
+#[cfg(feature = "balloon")]
+fn set_init_memory<V: VmArch, Vcpu: VcpuArch>(
+    state: &mut ControlLoopState<V, Vcpu>,
+    new_value: Option<&Value>,
+) -> std::result::Result<String, String> {
+    let init_memory: Option<u64> = serde_json::from_value(new_value.cloned().unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    let Some(balloon_tube) = state.balloon_tube.as_mut() else {
+        return Err("the VM has no balloon device".to_string());
+    };
+    let total_memory_bytes = state.linux.vm.get_memory().memory_size();
+    let init_memory_bytes = match init_memory {
+        Some(init_memory) => init_memory.saturating_mul(1024 * 1024),
+        None => total_memory_bytes,
+    };
+    if init_memory_bytes > total_memory_bytes {
+        return Err(format!(
+            "initial memory cannot be greater than total memory {}",
+            total_memory_bytes / (1024 * 1024)
+        ));
+    }
+    let num_bytes = total_memory_bytes - init_memory_bytes;
+    balloon_tube.send_cmd(
+        BalloonControlCommand::Adjust {
+            num_bytes,
+            wait_for_success: false,
+        },
+        None,
+    );
+    Ok(format!("balloon size set to {} bytes", num_bytes))
+}
+
+/// Changes the rate at which bus locks of the vCPUs are allowed.
+///
+/// Bus lock detection can only be enabled when the VM starts, so the option can only be changed if
+/// it was set then.
+#[cfg(target_arch = "x86_64")]
+fn set_bus_lock_ratelimit<V: VmArch, Vcpu: VcpuArch>(
+    state: &mut ControlLoopState<V, Vcpu>,
+    new_value: Option<&Value>,
+) -> std::result::Result<String, String> {
+    if state.cfg.bus_lock_ratelimit == 0 {
+        return Err("bus lock detection is not enabled on the running VM".to_string());
+    }
+    let speed: u64 = serde_json::from_value(new_value.cloned().unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    state
+        .bus_lock_ratelimit_ctrl
+        .lock()
+        .ratelimit_set_speed(speed);
+    if speed == 0 {
+        Ok("bus lock rate limit removed".to_string())
+    } else {
+        Ok(format!("bus lock rate limit set to {} per second", speed))
+    }
+}
+
+/// Hotplugs the disks or network devices that the new configuration adds to the list `option`,
+/// and records them in `running`.
+///
+/// Every device added is reported as applied. The first failure is reported as rejected along
+/// with the devices that follow it, which are not added.
+#[cfg(feature = "pci-hotplug")]
+fn hotplug_entries<V: VmArch, Vcpu: VcpuArch>(
+    state: &mut ControlLoopState<V, Vcpu>,
+    add_control_tube: &mut impl FnMut(AnyControlTube),
+    running: &mut RunningConfig,
+    option: &str,
+    new_value: Option<&Value>,
+    report: &mut ConfigReloadReport,
+) {
+    let Some(entries) = appended_entries(running.get(option), new_value) else {
+        report.rejected.push(ConfigChange {
+            option: option.to_string(),
+            detail: "devices can only be added at the end of the list".to_string(),
+        });
+        return;
+    };
+    let Some(hotplug_manager) = state.hotplug_manager.as_mut() else {
+        report.rejected.push(ConfigChange {
+            option: option.to_string(),
+            detail: "PCI hotplug is not enabled".to_string(),
+        });
+        return;
+    };
+    let first_index = running
+        .get(option)
+        .and_then(Value::as_array)
+        .map_or(0, Vec::len);
+
+    for (i, entry) in entries.iter().enumerate() {
+        let index = first_index + i;
+        let result = if option == "disks" {
+            match serde_json::from_value::<DiskOption>(entry.clone()) {
+                Ok(disk) => add_hotplug_block(
+                    state.linux,
+                    &mut state.sys_allocator.lock(),
+                    add_control_tube,
+                    hotplug_manager,
+                    disk,
+                ),
+                Err(e) => Err(anyhow!("invalid disk: {}", e)),
+            }
+        } else {
+            match serde_json::from_value::<NetParameters>(entry.clone()) {
+                Ok(net) => add_hotplug_net(
+                    state.linux,
+                    &mut state.sys_allocator.lock(),
+                    add_control_tube,
+                    hotplug_manager,
+                    net,
+                ),
+                Err(e) => Err(anyhow!("invalid network device: {}", e)),
+            }
+        };
+        let bus = match result {
+            Ok(bus) => bus,
+            Err(e) => {
+                let skipped = entries.len() - i - 1;
+                let detail = if skipped > 0 {
+                    format!(
+                        "failed to add device {}: {:#}; the {} following devices were not added",
+                        index, e, skipped
+                    )
+                } else {
+                    format!("failed to add device {}: {:#}", index, e)
+                };
+                report.rejected.push(ConfigChange {
+                    option: option.to_string(),
+                    detail,
+                });
+                return;
+            }
+        };
+        if let Value::Array(list) = running
+            .entry(option)
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            list.push(entry.clone());
+        }
+        report.applied.push(ConfigChange {
+            option: option.to_string(),
+            detail: format!("device {} added on PCI bus {}", index, bus),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn options(value: Value) -> RunningConfig {
+        match value {
+            Value::Object(options) => options,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn changed() {
+        let running = options(json!({"cpus": 2, "disks": [], "init_memory": null}));
+        let new = options(json!({"cpus": 2, "disks": [{"path": "a"}], "name": "vm"}));
+        assert_eq!(
+            changed_options(&running, &new),
+            vec![
+                "disks".to_string(),
+                "init_memory".to_string(),
+                "name".to_string()
+            ]
+        );
+        assert!(changed_options(&running, &running).is_empty());
+    }
+
+    #[test]
+    fn appended() {
+        let old = json!([{"path": "a"}]);
+        let new = json!([{"path": "a"}, {"path": "b"}]);
+        assert_eq!(
+            appended_entries(Some(&old), Some(&new)),
+            Some(&[json!({"path": "b"})][..])
+        );
+        assert_eq!(
+            appended_entries(None, Some(&old)),
+            Some(&[json!({"path": "a"})][..])
+        );
+        assert_eq!(appended_entries(Some(&new), Some(&old)), None);
+        assert_eq!(
+            appended_entries(Some(&old), Some(&json!([{"path": "b"}, {"path": "a"}]))),
+            None
+        );
+        assert_eq!(appended_entries(Some(&old), None), None);
+    }
+}
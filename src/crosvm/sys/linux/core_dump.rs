@@ -0,0 +1,296 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Writes the state of a paused VM as an ELF core file, for `crosvm dump-core`.
+//!
+//! The core has a `PT_NOTE` segment holding one `NT_PRSTATUS` note per vCPU, followed by one
+//! `PT_LOAD` segment per guest memory region. The segments are placed at their guest physical
+//! address, which is what tools such as `crash` expect from a VM core.
+
+use std::io::Write;
+use std::mem::size_of;
+
+use anyhow::Context;
+use anyhow::Result;
+use arch::VcpuArch;
+use kernel_loader::elf;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u32 = elf::EM_X86_64;
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u32 = elf::EM_AARCH64;
+#[cfg(target_arch = "riscv64")]
+const ELF_MACHINE: u32 = elf::EM_RISCV;
+
+/// Number of registers in the `elf_gregset_t` of the architecture.
+#[cfg(target_arch = "x86_64")]
+const NUM_GREGS: usize = 27;
+#[cfg(target_arch = "aarch64")]
+const NUM_GREGS: usize = 34;
+#[cfg(target_arch = "riscv64")]
+const NUM_GREGS: usize = 32;
+
+/// Name of the notes describing the state of a thread.
+const NOTE_NAME: &[u8] = b"CORE\0";
+
+/// Alignment of the memory segments in the file.
+const SEGMENT_ALIGN: u64 = 0x1000;
+
+/// Size of the chunks guest memory is copied in.
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+/// The `struct elf_prstatus` of Linux, holding the registers of a vCPU.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct ElfPrstatus {
+    si_signo: i32,
+    si_code: i32,
+    si_errno: i32,
+    pr_cursig: i16,
+    _pad0: [u8; 2],
+    pr_sigpend: u64,
+    pr_sighold: u64,
+    pr_pid: i32,
+    pr_ppid: i32,
+    pr_pgrp: i32,
+    pr_sid: i32,
+    /// `pr_utime`, `pr_stime`, `pr_cutime` and `pr_cstime`.
+    pr_times: [u64; 8],
+    pr_reg: [u64; NUM_GREGS],
+    pr_fpvalid: i32,
+    _pad1: [u8; 4],
+}
+
+/// The `Elf64_Nhdr` of a note.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct ElfNoteHeader {
+    n_namesz: u32,
+    n_descsz: u32,
+    n_type: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+fn read_gregs<V: VcpuArch>(vcpu: &V) -> Result<[u64; NUM_GREGS]> {
+    let regs = vcpu.get_regs().context("failed to get registers")?;
+    let sregs = vcpu
+        .get_sregs()
+        .context("failed to get special registers")?;
+    // The order of `struct user_regs_struct`.
+    Ok([
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        // orig_rax
+        u64::MAX,
+        regs.rip,
+        sregs.cs.selector.into(),
+        regs.rflags,
+        regs.rsp,
+        sregs.ss.selector.into(),
+        sregs.fs.base,
+        sregs.gs.base,
+        sregs.ds.selector.into(),
+        sregs.es.selector.into(),
+        sregs.fs.selector.into(),
+        sregs.gs.selector.into(),
+    ])
+}
+
+#[cfg(target_arch = "aarch64")]
+fn read_gregs<V: VcpuArch>(vcpu: &V) -> Result<[u64; NUM_GREGS]> {
+    use hypervisor::VcpuRegAArch64;
+
+    // The order of `struct user_pt_regs`.
+    let mut gregs = [0u64; NUM_GREGS];
+    for (i, reg) in gregs.iter_mut().take(31).enumerate() {
+        *reg = vcpu
+            .get_one_reg(VcpuRegAArch64::X(i as u8))
+            .with_context(|| format!("failed to get register x{}", i))?;
+    }
+    gregs[31] = vcpu
+        .get_one_reg(VcpuRegAArch64::Sp)
+        .context("failed to get sp")?;
+    gregs[32] = vcpu
+        .get_one_reg(VcpuRegAArch64::Pc)
+        .context("failed to get pc")?;
+    gregs[33] = vcpu
+        .get_one_reg(VcpuRegAArch64::Pstate)
+        .context("failed to get pstate")?;
+    Ok(gregs)
+}
+
+#[cfg(target_arch = "riscv64")]
+fn read_gregs<V: VcpuArch>(_vcpu: &V) -> Result<[u64; NUM_GREGS]> {
+    anyhow::bail!("core dumps are not supported on riscv64")
+}
+
+/// Returns the descriptor of the `NT_PRSTATUS` note of `vcpu`.
+pub fn vcpu_prstatus<V: VcpuArch>(vcpu: &V, cpu_id: usize) -> Result<Vec<u8>> {
+    let mut prstatus = ElfPrstatus::new_zeroed();
+    // Debuggers show each vCPU as a thread identified by its pid, which must not be 0.
+    prstatus.pr_pid = cpu_id as i32 + 1;
+    prstatus.pr_reg = read_gregs(vcpu)?;
+    Ok(prstatus.as_bytes().to_vec())
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+/// Writes a core of the guest with the guest memory `mem` and the `NT_PRSTATUS` descriptors of
+/// the vCPUs in `prstatus`.
+pub fn write_core_dump<W: Write>(
+    output: &mut W,
+    mem: &GuestMemory,
+    prstatus: &[Vec<u8>],
+) -> Result<()> {
+    let regions: Vec<(GuestAddress, u64)> = mem
+        .regions()
+        .map(|region| (region.guest_addr, region.size as u64))
+        .collect();
+
+    let mut notes = Vec::new();
+    for desc in prstatus {
+        let header = ElfNoteHeader {
+            n_namesz: NOTE_NAME.len() as u32,
+            n_descsz: desc.len() as u32,
+            n_type: elf::NT_PRSTATUS,
+        };
+        notes.extend_from_slice(header.as_bytes());
+        notes.extend_from_slice(NOTE_NAME);
+        notes.resize(align_up(notes.len() as u64, 4) as usize, 0);
+        notes.extend_from_slice(desc);
+        notes.resize(align_up(notes.len() as u64, 4) as usize, 0);
+    }
+
+    let phnum = regions.len() + 1;
+    let headers_size = size_of::<elf::Elf64_Ehdr>() + phnum * size_of::<elf::Elf64_Phdr>();
+    let notes_offset = headers_size as u64;
+    let notes_end = notes_offset + notes.len() as u64;
+    let mut offset = align_up(notes_end, SEGMENT_ALIGN);
+
+    let mut ehdr = elf::Elf64_Ehdr::default();
+    ehdr.e_ident[..4].copy_from_slice(&elf::ELFMAG[..4]);
+    ehdr.e_ident[elf::EI_CLASS as usize] = elf::ELFCLASS64 as u8;
+    ehdr.e_ident[elf::EI_DATA as usize] = elf::ELFDATA2LSB as u8;
+    ehdr.e_ident[elf::EI_VERSION as usize] = elf::EV_CURRENT as u8;
+    ehdr.e_type = elf::ET_CORE as u16;
+    ehdr.e_machine = ELF_MACHINE as u16;
+    ehdr.e_version = elf::EV_CURRENT;
+    ehdr.e_phoff = size_of::<elf::Elf64_Ehdr>() as u64;
+    ehdr.e_ehsize = size_of::<elf::Elf64_Ehdr>() as u16;
+    ehdr.e_phentsize = size_of::<elf::Elf64_Phdr>() as u16;
+    ehdr.e_phnum = phnum.try_into().context("too many memory regions")?;
+
+    let mut phdrs = vec![elf::Elf64_Phdr {
+        p_type: elf::PT_NOTE,
+        p_offset: notes_offset,
+        p_filesz: notes.len() as u64,
+        ..Default::default()
+    }];
+    for (addr, size) in &regions {
+        phdrs.push(elf::Elf64_Phdr {
+            p_type: elf::PT_LOAD,
+            p_flags: elf::PF_R | elf::PF_W | elf::PF_X,
+            p_offset: offset,
+            p_vaddr: addr.offset(),
+            p_paddr: addr.offset(),
+            p_filesz: *size,
+            p_memsz: *size,
+            p_align: SEGMENT_ALIGN,
+        });
+        offset += size;
+    }
+
+    output.write_all(ehdr.as_bytes())?;
+    for phdr in &phdrs {
+        output.write_all(phdr.as_bytes())?;
+    }
+    output.write_all(&notes)?;
+    output.write_all(&vec![
+        0u8;
+        (align_up(notes_end, SEGMENT_ALIGN) - notes_end)
+            as usize
+    ])?;
+
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    for (addr, size) in regions {
+        let mut copied = 0;
+        while copied < size {
+            let len = (size - copied).min(COPY_CHUNK_SIZE as u64) as usize;
+            let chunk_addr = addr.unchecked_add(copied);
+            mem.read_exact_at_addr(&mut buf[..len], chunk_addr)
+                .with_context(|| format!("failed to read guest memory at {}", chunk_addr))?;
+            output.write_all(&buf[..len])?;
+            copied += len as u64;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_dump_layout() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x2000), (GuestAddress(0x10000), 0x1000)])
+            .unwrap();
+        mem.write_all_at_addr(b"crosvm", GuestAddress(0x10010))
+            .unwrap();
+        let prstatus = vec![vec![1u8; size_of::<ElfPrstatus>()]; 2];
+
+        let mut core = Vec::new();
+        write_core_dump(&mut core, &mem, &prstatus).unwrap();
+
+        let (ehdr, _) = elf::Elf64_Ehdr::read_from_prefix(&core).unwrap();
+        assert_eq!(&ehdr.e_ident[..4], &elf::ELFMAG[..4]);
+        assert_eq!(ehdr.e_type, elf::ET_CORE as u16);
+        assert_eq!(ehdr.e_phnum, 3);
+
+        let phdrs: Vec<elf::Elf64_Phdr> = (0..3)
+            .map(|i| {
+                let start = ehdr.e_phoff as usize + i * size_of::<elf::Elf64_Phdr>();
+                elf::Elf64_Phdr::read_from_prefix(&core[start..]).unwrap().0
+            })
+            .collect();
+        assert_eq!(phdrs[0].p_type, elf::PT_NOTE);
+        let note_size = size_of::<ElfNoteHeader>() + 8 + size_of::<ElfPrstatus>();
+        assert_eq!(phdrs[0].p_filesz as usize, 2 * note_size);
+        let (note, _) =
+            ElfNoteHeader::read_from_prefix(&core[phdrs[0].p_offset as usize..]).unwrap();
+        assert_eq!(note.n_type, elf::NT_PRSTATUS);
+        assert_eq!(note.n_descsz as usize, size_of::<ElfPrstatus>());
+
+        assert_eq!(phdrs[1].p_type, elf::PT_LOAD);
+        assert_eq!(phdrs[1].p_offset % SEGMENT_ALIGN, 0);
+        assert_eq!(phdrs[1].p_filesz, 0x2000);
+        assert_eq!(phdrs[2].p_paddr, 0x10000);
+        assert_eq!(phdrs[2].p_offset, phdrs[1].p_offset + 0x2000);
+        let data = phdrs[2].p_offset as usize + 0x10;
+        assert_eq!(&core[data..data + 6], b"crosvm");
+        assert_eq!(core.len() as u64, phdrs[2].p_offset + 0x1000);
+    }
+}
@@ -26,6 +26,8 @@ use base::linux::MemfdSeals;
 use base::sys::SharedMemoryLinux;
 use base::ReadNotifier;
 use base::*;
+use cros_async::sys::ExecutorKindSys;
+use cros_async::ExecutorKind;
 use devices::serial_device::SerialParameters;
 use devices::serial_device::SerialType;
 use devices::vfio::VfioContainerManager;
@@ -50,7 +52,9 @@ use devices::virtio::vhost::user::NetBackend;
 use devices::virtio::vhost::user::VhostUserDeviceBuilder;
 use devices::virtio::vhost::user::VhostUserVsockDevice;
 use devices::virtio::vsock::VsockConfig;
+use devices::virtio::CanParameters;
 use devices::virtio::Console;
+use devices::virtio::DeviceType;
 use devices::virtio::MemSlotConfig;
 #[cfg(feature = "net")]
 use devices::virtio::NetError;
@@ -59,13 +63,24 @@ use devices::virtio::NetParameters;
 #[cfg(feature = "net")]
 use devices::virtio::NetParametersMode;
 use devices::virtio::PmemConfig;
+use devices::virtio::SpiParameters;
 use devices::virtio::VhostUserFrontend;
 use devices::virtio::VirtioDevice;
 use devices::virtio::VirtioDeviceType;
+#[cfg(feature = "wasm-devices")]
+use devices::virtio::WasmDeviceParameters;
 use devices::BusDeviceObj;
 use devices::IommuDevType;
+use devices::IvshmemParameters;
+use devices::IvshmemPciDevice;
 use devices::PciAddress;
 use devices::PciDevice;
+#[cfg(target_arch = "aarch64")]
+use devices::SmmuV3;
+#[cfg(feature = "vtpm")]
+use devices::Swtpm;
+#[cfg(feature = "vtpm")]
+use devices::SwtpmParameters;
 use devices::VfioDevice;
 use devices::VfioDeviceType;
 use devices::VfioPciDevice;
@@ -88,7 +103,12 @@ use resources::AllocOptions;
 use resources::SystemAllocator;
 use sync::Mutex;
 use vm_control::api::VmMemoryClient;
+use vm_control::extension::ExtensionRequest;
+use vm_control::extension::ExtensionResponse;
+use vm_control::extension::EXTENSION_PROTOCOL_VERSION;
 use vm_memory::GuestAddress;
+#[cfg(target_arch = "aarch64")]
+use vm_memory::GuestMemory;
 
 use crate::crosvm::config::PmemOption;
 use crate::crosvm::config::VhostUserFrontendOption;
@@ -132,11 +152,20 @@ pub enum DeviceControlTube {
     // See `BalloonTube`.
     #[cfg(feature = "balloon")]
     Balloon(Tube),
+    // Sends `ConsolePortRequest`.
+    Console(Tube),
     // Sends `DiskControlCommand`.
     Disk(Tube),
+    // Sends `ExtensionRequest::Event`.
+    Extension(Tube),
+    // Sends `FsControlCommand`.
+    Fs(Tube),
     // Sends `GpuControlCommand`.
     #[cfg(feature = "gpu")]
     Gpu(Tube),
+    // Sends `NetConfigCommand`.
+    #[cfg(feature = "net")]
+    Net(Tube),
     // Sends `PvClockCommand`.
     #[cfg(feature = "pvclock")]
     PvClock(Tube),
@@ -331,6 +360,27 @@ impl VirtioDeviceBuilder for DiskConfig<'_> {
         ))
     }
 
+    fn create_jail(
+        &self,
+        jail_config: Option<&JailConfig>,
+        virtio_transport: VirtioDeviceType,
+    ) -> anyhow::Result<Option<Minijail>> {
+        let Some(jail_config) = jail_config else {
+            return Ok(None);
+        };
+        let policy = virtio_transport.seccomp_policy_file(Self::NAME);
+        let mut config = SandboxConfig::new(jail_config, &policy);
+        // The disk may use io_uring even if it isn't the default executor.
+        if self.disk.async_executor == Some(ExecutorKindSys::Uring.into()) {
+            config.seccomp_features.insert(SeccompFeature::IoUring);
+        }
+        Ok(Some(create_sandbox_minijail(
+            &jail_config.pivot_root,
+            MAX_OPEN_FILES_DEFAULT,
+            &config,
+        )?))
+    }
+
     fn create_vhost_user_device(
         self,
         keep_rds: &mut Vec<RawDescriptor>,
@@ -385,6 +435,49 @@ impl<'a> VirtioDeviceBuilder for &'a ScsiConfig<'a> {
     }
 }
 
+/// Adds the syscalls of the default async executor to the policy of every sandbox created from now
+/// on. Must be called once the default executor is chosen.
+pub fn set_executor_seccomp_features() -> Result<()> {
+    let mut features = BTreeSet::new();
+    if ExecutorKind::default() == ExecutorKindSys::Uring.into() {
+        features.insert(SeccompFeature::IoUring);
+    }
+    set_default_seccomp_features(features)
+}
+
+/// Returns the base name of the seccomp policy of a device that can run in a process of its own,
+/// as used for its jail when the sandbox is enabled.
+pub fn isolated_device_policy(device_type: DeviceType) -> Option<&'static str> {
+    match device_type {
+        DeviceType::Balloon => Some("balloon"),
+        DeviceType::Block => Some("block"),
+        DeviceType::Console => Some("serial"),
+        DeviceType::Fs => Some("fs"),
+        DeviceType::Input => Some("input"),
+        DeviceType::Net => Some("net"),
+        DeviceType::P9 => Some("9p"),
+        DeviceType::Pmem => Some("pmem"),
+        DeviceType::Rng => Some("rng"),
+        _ => None,
+    }
+}
+
+/// Creates the jail of a device that runs in a process of its own with `--isolate-virtio-devices`
+/// while the sandbox is disabled. The process keeps the root, namespaces and user of crosvm but
+/// runs under the seccomp policy of the device.
+pub fn create_isolated_device_jail(dev: &dyn VirtioDevice) -> Result<Minijail> {
+    let policy = isolated_device_policy(dev.device_type())
+        .with_context(|| format!("{} cannot run in a process of its own", dev.debug_label()))?;
+    let policy = VirtioDeviceType::Regular.seccomp_policy_file(policy);
+    let jail_config = JailConfig::default();
+    let mut config = SandboxConfig::new(&jail_config, &policy);
+    // Disks may use io_uring even if it isn't the default executor.
+    if dev.device_type() == DeviceType::Block {
+        config.seccomp_features.insert(SeccompFeature::IoUring);
+    }
+    create_seccomp_minijail(MAX_OPEN_FILES_DEFAULT, &config)
+}
+
 fn vhost_user_connection(
     path: &Path,
     connect_timeout_ms: Option<u64>,
@@ -439,21 +532,31 @@ pub fn create_vhost_user_frontend(
     connect_timeout_ms: Option<u64>,
 ) -> DeviceResult {
     let connection = if let Some(socket_fd) = safe_descriptor_from_path(&opt.socket)? {
+        if opt.reconnect {
+            bail!("vhost-user reconnect requires a socket path, not an inherited fd");
+        }
         socket_fd
             .try_into()
             .context("failed to create vhost-user connection from fd")?
     } else {
         vhost_user_connection(&opt.socket, connect_timeout_ms)?
     };
-    let dev = VhostUserFrontend::new(
+    let mut dev = VhostUserFrontend::new(
         opt.type_,
         virtio::base_features(protection_type),
         connection,
         opt.max_queue_size,
         opt.pci_address,
+        opt.protocol_features,
     )
     .context("failed to set up vhost-user frontend")?;
 
+    if opt.reconnect {
+        let socket = opt.socket.clone();
+        dev.set_reconnect(Arc::new(move || vhost_user_connection(&socket, None)))
+            .context("failed to enable vhost-user reconnect")?;
+    }
+
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
         // no sandbox here because virtqueue handling is exported to a different process.
@@ -461,6 +564,59 @@ pub fn create_vhost_user_frontend(
     })
 }
 
+/// Connects to the extension listening on `path` and creates a vhost-user frontend for each of the
+/// devices it provides. Returns them with the tube used to notify the extension of VM events.
+pub fn create_extension_devices(
+    protection_type: ProtectionType,
+    path: &Path,
+) -> DeviceResult<(Vec<VirtioDeviceStub>, Tube)> {
+    let socket = UnixSeqpacket::connect(path)
+        .with_context(|| format!("failed to connect to extension {}", path.display()))?;
+    let tube = Tube::try_from(socket).context("failed to create extension tube")?;
+    tube.send(&ExtensionRequest::Hello {
+        version: EXTENSION_PROTOCOL_VERSION,
+    })
+    .context("failed to send hello to extension")?;
+    let devices = match tube
+        .recv::<ExtensionResponse>()
+        .context("failed to receive extension devices")?
+    {
+        ExtensionResponse::Devices(devices) => devices,
+        ExtensionResponse::Err(e) => bail!("extension {} failed: {}", path.display(), e),
+    };
+
+    let mut devs = Vec::new();
+    for info in devices {
+        let device_type =
+            virtio::DeviceType::from_virtio_id(info.virtio_type).with_context(|| {
+                format!(
+                    "extension {} has a device with invalid virtio device ID {}",
+                    path.display(),
+                    info.virtio_type
+                )
+            })?;
+        let connection = info
+            .socket
+            .try_into()
+            .context("failed to construct Connection from UnixStream")?;
+        let dev = VhostUserFrontend::new(
+            device_type,
+            virtio::base_features(protection_type),
+            connection,
+            info.max_queue_size,
+            None, // pci_address
+            None, // allow_protocol_features
+        )
+        .with_context(|| format!("failed to set up {} device of extension", device_type))?;
+        devs.push(VirtioDeviceStub {
+            dev: Box::new(dev),
+            // no sandbox here because virtqueue handling is done by the extension.
+            jail: None,
+        });
+    }
+    Ok((devs, tube))
+}
+
 pub fn create_rng_device(
     protection_type: ProtectionType,
     jail_config: Option<&JailConfig>,
@@ -554,6 +710,74 @@ pub fn create_vtpm_proxy_device(
     })
 }
 
+#[cfg(feature = "vtpm")]
+pub fn create_swtpm_device(
+    protection_type: ProtectionType,
+    jail_config: Option<&JailConfig>,
+    params: &SwtpmParameters,
+) -> DeviceResult {
+    let backend = Swtpm::new(params).context("failed to set up swtpm backend")?;
+    let dev = virtio::Tpm::new(Box::new(backend), virtio::base_features(protection_type));
+
+    Ok(VirtioDeviceStub {
+        dev: Box::new(dev),
+        jail: simple_jail(jail_config, "swtpm_device")?,
+    })
+}
+
+/// Connects to swtpm for a TPM exposed through a CRB interface, which the arch sets up.
+#[cfg(all(target_arch = "x86_64", feature = "vtpm"))]
+pub fn create_swtpm_crb(
+    jail_config: Option<&JailConfig>,
+    params: &SwtpmParameters,
+) -> Result<(Box<dyn virtio::TpmBackend>, Option<Minijail>)> {
+    let backend = Swtpm::new(params).context("failed to set up swtpm backend")?;
+    Ok((Box::new(backend), simple_jail(jail_config, "swtpm_device")?))
+}
+
+pub fn create_can_device(
+    protection_type: ProtectionType,
+    jail_config: Option<&JailConfig>,
+    params: &CanParameters,
+) -> DeviceResult {
+    let dev = virtio::Can::new(params, virtio::base_features(protection_type))
+        .context("failed to set up virtio-can device")?;
+
+    Ok(VirtioDeviceStub {
+        dev: Box::new(dev),
+        jail: simple_jail(jail_config, "can_device")?,
+    })
+}
+
+pub fn create_spi_device(
+    protection_type: ProtectionType,
+    jail_config: Option<&JailConfig>,
+    params: &SpiParameters,
+) -> DeviceResult {
+    let dev = virtio::Spi::new(params, virtio::base_features(protection_type))
+        .context("failed to set up virtio-spi device")?;
+
+    Ok(VirtioDeviceStub {
+        dev: Box::new(dev),
+        jail: simple_jail(jail_config, "spi_device")?,
+    })
+}
+
+#[cfg(feature = "wasm-devices")]
+pub fn create_wasm_device(
+    protection_type: ProtectionType,
+    jail_config: Option<&JailConfig>,
+    params: &WasmDeviceParameters,
+) -> DeviceResult {
+    let dev = virtio::WasmDevice::new(params, virtio::base_features(protection_type))
+        .context("failed to set up wasm device")?;
+
+    Ok(VirtioDeviceStub {
+        dev: Box::new(dev),
+        jail: simple_jail(jail_config, "wasm_device")?,
+    })
+}
+
 pub fn create_single_touch_device<T: IntoUnixStream>(
     protection_type: ProtectionType,
     jail_config: Option<&JailConfig>,
@@ -562,12 +786,13 @@ pub fn create_single_touch_device<T: IntoUnixStream>(
     height: u32,
     name: Option<&str>,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = single_touch_socket
         .into_unix_stream()
         .context("failed configuring virtio single touch")?;
 
-    let dev = virtio::input::new_single_touch(
+    let mut dev = virtio::input::new_single_touch(
         idx,
         socket,
         width,
@@ -576,6 +801,7 @@ pub fn create_single_touch_device<T: IntoUnixStream>(
         virtio::base_features(protection_type),
     )
     .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
         jail: simple_jail(jail_config, "input_device")?,
@@ -588,14 +814,48 @@ pub fn create_multi_touch_device<T: IntoUnixStream>(
     multi_touch_socket: T,
     width: u32,
     height: u32,
+    slots: Option<u32>,
     name: Option<&str>,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = multi_touch_socket
         .into_unix_stream()
         .context("failed configuring virtio multi touch")?;
 
-    let dev = virtio::input::new_multi_touch(
+    let mut dev = virtio::input::new_multi_touch(
+        idx,
+        socket,
+        width,
+        height,
+        slots,
+        name,
+        virtio::base_features(protection_type),
+    )
+    .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
+
+    Ok(VirtioDeviceStub {
+        dev: Box::new(dev),
+        jail: simple_jail(jail_config, "input_device")?,
+    })
+}
+
+pub fn create_tablet_device<T: IntoUnixStream>(
+    protection_type: ProtectionType,
+    jail_config: Option<&JailConfig>,
+    tablet_socket: T,
+    width: u32,
+    height: u32,
+    name: Option<&str>,
+    idx: u32,
+    wakeup_evt: Option<Event>,
+) -> DeviceResult {
+    let socket = tablet_socket
+        .into_unix_stream()
+        .context("failed configuring virtio tablet")?;
+
+    let mut dev = virtio::input::new_tablet(
         idx,
         socket,
         width,
@@ -604,6 +864,7 @@ pub fn create_multi_touch_device<T: IntoUnixStream>(
         virtio::base_features(protection_type),
     )
     .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -619,12 +880,13 @@ pub fn create_trackpad_device<T: IntoUnixStream>(
     height: u32,
     name: Option<&str>,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = trackpad_socket
         .into_unix_stream()
         .context("failed configuring virtio trackpad")?;
 
-    let dev = virtio::input::new_trackpad(
+    let mut dev = virtio::input::new_trackpad(
         idx,
         socket,
         width,
@@ -633,6 +895,7 @@ pub fn create_trackpad_device<T: IntoUnixStream>(
         virtio::base_features(protection_type),
     )
     .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -648,12 +911,13 @@ pub fn create_multitouch_trackpad_device<T: IntoUnixStream>(
     height: u32,
     name: Option<&str>,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = trackpad_socket
         .into_unix_stream()
         .context("failed configuring virtio trackpad")?;
 
-    let dev = virtio::input::new_multitouch_trackpad(
+    let mut dev = virtio::input::new_multitouch_trackpad(
         idx,
         socket,
         width,
@@ -662,6 +926,7 @@ pub fn create_multitouch_trackpad_device<T: IntoUnixStream>(
         virtio::base_features(protection_type),
     )
     .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -674,13 +939,15 @@ pub fn create_mouse_device<T: IntoUnixStream>(
     jail_config: Option<&JailConfig>,
     mouse_socket: T,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = mouse_socket
         .into_unix_stream()
         .context("failed configuring virtio mouse")?;
 
-    let dev = virtio::input::new_mouse(idx, socket, virtio::base_features(protection_type))
+    let mut dev = virtio::input::new_mouse(idx, socket, virtio::base_features(protection_type))
         .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -693,13 +960,15 @@ pub fn create_keyboard_device<T: IntoUnixStream>(
     jail_config: Option<&JailConfig>,
     keyboard_socket: T,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = keyboard_socket
         .into_unix_stream()
         .context("failed configuring virtio keyboard")?;
 
-    let dev = virtio::input::new_keyboard(idx, socket, virtio::base_features(protection_type))
+    let mut dev = virtio::input::new_keyboard(idx, socket, virtio::base_features(protection_type))
         .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -712,13 +981,15 @@ pub fn create_switches_device<T: IntoUnixStream>(
     jail_config: Option<&JailConfig>,
     switches_socket: T,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = switches_socket
         .into_unix_stream()
         .context("failed configuring virtio switches")?;
 
-    let dev = virtio::input::new_switches(idx, socket, virtio::base_features(protection_type))
+    let mut dev = virtio::input::new_switches(idx, socket, virtio::base_features(protection_type))
         .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -731,13 +1002,15 @@ pub fn create_rotary_device<T: IntoUnixStream>(
     jail_config: Option<&JailConfig>,
     rotary_socket: T,
     idx: u32,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = rotary_socket
         .into_unix_stream()
         .context("failed configuring virtio rotary")?;
 
-    let dev = virtio::input::new_rotary(idx, socket, virtio::base_features(protection_type))
+    let mut dev = virtio::input::new_rotary(idx, socket, virtio::base_features(protection_type))
         .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -749,6 +1022,7 @@ pub fn create_vinput_device(
     protection_type: ProtectionType,
     jail_config: Option<&JailConfig>,
     dev_path: &Path,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let dev_file = OpenOptions::new()
         .read(true)
@@ -756,8 +1030,9 @@ pub fn create_vinput_device(
         .open(dev_path)
         .with_context(|| format!("failed to open vinput device {}", dev_path.display()))?;
 
-    let dev = virtio::input::new_evdev(dev_file, virtio::base_features(protection_type))
+    let mut dev = virtio::input::new_evdev(dev_file, virtio::base_features(protection_type))
         .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -771,18 +1046,20 @@ pub fn create_custom_device<T: IntoUnixStream>(
     custom_device_socket: T,
     idx: u32,
     input_config_path: PathBuf,
+    wakeup_evt: Option<Event>,
 ) -> DeviceResult {
     let socket = custom_device_socket
         .into_unix_stream()
         .context("failed configuring custom virtio input device")?;
 
-    let dev = virtio::input::new_custom(
+    let mut dev = virtio::input::new_custom(
         idx,
         socket,
         input_config_path,
         virtio::base_features(protection_type),
     )
     .context("failed to set up input device")?;
+    dev.set_wakeup_event(wakeup_evt);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -840,45 +1117,52 @@ pub fn create_pvclock_device(
     })
 }
 
+/// A one-shot configuration structure for implementing `VirtioDeviceBuilder`, like `DiskConfig`,
+/// for net devices that can be passed an optional control tube.
 #[cfg(feature = "net")]
-impl VirtioDeviceBuilder for &NetParameters {
+pub struct NetConfig<'a> {
+    /// Options for net device creation.
+    net: &'a NetParameters,
+    /// Optional control tube for the device. Unused with vhost-net.
+    device_tube: Option<Tube>,
+}
+
+#[cfg(feature = "net")]
+impl<'a> NetConfig<'a> {
+    pub fn new(net: &'a NetParameters, device_tube: Option<Tube>) -> Self {
+        Self { net, device_tube }
+    }
+}
+
+#[cfg(feature = "net")]
+impl VirtioDeviceBuilder for NetConfig<'_> {
     const NAME: &'static str = "net";
 
     fn create_virtio_device(
         self,
         protection_type: ProtectionType,
     ) -> anyhow::Result<Box<dyn VirtioDevice>> {
-        let vq_pairs = self.vq_pairs.unwrap_or(1);
-        let multi_vq = vq_pairs > 1 && self.vhost_net.is_none();
+        create_net_device(self.net, protection_type, self.device_tube)
+    }
 
-        let features = virtio::base_features(protection_type);
-        let (tap, mac) = create_tap_for_net_device(&self.mode, multi_vq)?;
-
-        Ok(if let Some(vhost_net) = &self.vhost_net {
-            Box::new(
-                virtio::vhost::Net::<_, vhost::Net<_>>::new(
-                    &vhost_net.device,
-                    features,
-                    tap,
-                    mac,
-                    self.packed_queue,
-                    self.pci_address,
-                )
-                .context("failed to set up virtio-vhost networking")?,
-            ) as Box<dyn VirtioDevice>
-        } else {
-            Box::new(
-                virtio::Net::new(
-                    features,
-                    tap,
-                    vq_pairs,
-                    mac,
-                    self.packed_queue,
-                    self.pci_address,
-                )
-                .context("failed to set up virtio networking")?,
-            ) as Box<dyn VirtioDevice>
-        })
+    fn create_jail(
+        &self,
+        jail_config: Option<&JailConfig>,
+        virtio_transport: VirtioDeviceType,
+    ) -> anyhow::Result<Option<Minijail>> {
+        self.net.create_jail(jail_config, virtio_transport)
+    }
+}
+
+#[cfg(feature = "net")]
+impl VirtioDeviceBuilder for &NetParameters {
+    const NAME: &'static str = "net";
+
+    fn create_virtio_device(
+        self,
+        protection_type: ProtectionType,
+    ) -> anyhow::Result<Box<dyn VirtioDevice>> {
+        create_net_device(self, protection_type, None)
     }
 
     fn create_jail(
@@ -911,6 +1195,47 @@ impl VirtioDeviceBuilder for &NetParameters {
     }
 }
 
+#[cfg(feature = "net")]
+fn create_net_device(
+    params: &NetParameters,
+    protection_type: ProtectionType,
+    control_tube: Option<Tube>,
+) -> anyhow::Result<Box<dyn VirtioDevice>> {
+    let vq_pairs = params.vq_pairs.unwrap_or(1);
+    let multi_vq = vq_pairs > 1 && params.vhost_net.is_none();
+
+    let features = virtio::base_features(protection_type);
+    let (tap, mac) = create_tap_for_net_device(&params.mode, multi_vq)?;
+
+    Ok(if let Some(vhost_net) = &params.vhost_net {
+        Box::new(
+            virtio::vhost::Net::<_, vhost::Net<_>>::new(
+                &vhost_net.device,
+                features,
+                tap,
+                mac,
+                params.packed_queue,
+                params.pci_address,
+            )
+            .context("failed to set up virtio-vhost networking")?,
+        ) as Box<dyn VirtioDevice>
+    } else {
+        let mut dev = virtio::Net::new(
+            features,
+            tap,
+            vq_pairs,
+            mac,
+            params.packed_queue,
+            params.pci_address,
+        )
+        .context("failed to set up virtio networking")?;
+        if let Some(control_tube) = control_tube {
+            dev.set_control_tube(control_tube);
+        }
+        Box::new(dev) as Box<dyn VirtioDevice>
+    })
+}
+
 /// Create a new tap interface based on NetParametersMode.
 #[cfg(feature = "net")]
 fn create_tap_for_net_device(
@@ -1202,6 +1527,7 @@ pub fn create_fs_device(
     tag: &str,
     fs_cfg: virtio::fs::Config,
     device_tube: Tube,
+    control_tube: Tube,
 ) -> DeviceResult {
     let max_open_files = base::linux::max_open_files()
         .context("failed to get max number of open files")?
@@ -1226,8 +1552,9 @@ pub fn create_fs_device(
     let features = virtio::base_features(protection_type);
     // TODO(chirantan): Use more than one worker once the kernel driver has been fixed to not panic
     // when num_queues > 1.
-    let dev = virtio::fs::Fs::new(features, tag, 1, fs_cfg, device_tube)
+    let mut dev = virtio::fs::Fs::new(features, tag, 1, fs_cfg, device_tube)
         .context("failed to create fs device")?;
+    dev.set_control_tube(control_tube);
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -1500,6 +1827,89 @@ pub fn create_anonymous_file<P: AsRef<Path>>(path: P, size: u64) -> Result<File>
     Ok(shm.descriptor.into())
 }
 
+pub fn create_ivshmem_device(
+    jail_config: Option<&JailConfig>,
+    vm: &mut impl Vm,
+    resources: &mut SystemAllocator,
+    params: &IvshmemParameters,
+    index: usize,
+) -> DeviceResult<(Box<dyn BusDeviceObj>, Option<Minijail>)> {
+    if !params.size.is_power_of_two() || params.size < pagesize() as u64 {
+        bail!(
+            "ivshmem size {:#x} must be a power of two of at least a page",
+            params.size
+        );
+    }
+    let size = usize::try_from(params.size).context("ivshmem size too big")?;
+
+    let shm = open_file_or_duplicate(
+        &params.path,
+        OpenOptions::new().read(true).write(true).create(true),
+    )
+    .with_context(|| format!("failed to open ivshmem file {}", params.path.display()))?;
+    let file_size = shm
+        .metadata()
+        .context("failed to get ivshmem file metadata")?
+        .len();
+    if file_size < params.size {
+        shm.set_len(params.size)
+            .context("failed to resize ivshmem file")?;
+    } else if file_size > params.size {
+        bail!(
+            "ivshmem file {} is larger than the requested size",
+            params.path.display()
+        );
+    }
+
+    let mapping = MemoryMappingBuilder::new(size)
+        .from_file(&shm)
+        .build()
+        .context("failed to map ivshmem file")?;
+    let shm_address = resources
+        .allocate_mmio(
+            params.size,
+            Alloc::IvshmemDevice(index),
+            format!("ivshmem_{}", index),
+            AllocOptions::new()
+                .prefetchable(true)
+                // BARs are naturally aligned.
+                .align(params.size),
+        )
+        .context("failed to allocate memory for ivshmem device")?;
+    vm.add_memory_region(
+        GuestAddress(shm_address),
+        Box::new(mapping),
+        /* read_only = */ false,
+        /* log_dirty_pages = */ false,
+        MemCacheType::CacheCoherent,
+    )
+    .context("failed to add ivshmem device memory")?;
+
+    let dev =
+        IvshmemPciDevice::new(params, shm_address).context("failed to create ivshmem device")?;
+
+    let jail = if let Some(jail_config) = jail_config {
+        let mut config = SandboxConfig::new(jail_config, "ivshmem_device");
+        config.bind_mounts = params.doorbell.is_some();
+        let mut jail =
+            create_sandbox_minijail(&jail_config.pivot_root, MAX_OPEN_FILES_DEFAULT, &config)?;
+        // Doorbells are sent to the peer's socket by path.
+        if let Some(dir) = params
+            .doorbell
+            .as_ref()
+            .and_then(|path| path.parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            jail.mount_bind(dir, dir, true)?;
+        }
+        Some(jail)
+    } else {
+        None
+    };
+
+    Ok((Box::new(dev), jail))
+}
+
 pub fn create_iommu_device(
     protection_type: ProtectionType,
     jail_config: Option<&JailConfig>,
@@ -1527,6 +1937,25 @@ pub fn create_iommu_device(
     })
 }
 
+/// Creates an emulated SMMUv3 translating the DMA of `endpoints`, for aarch64 guests.
+#[cfg(target_arch = "aarch64")]
+pub fn create_smmuv3(
+    jail_config: Option<&JailConfig>,
+    mem: &GuestMemory,
+    endpoints: BTreeMap<u32, Arc<Mutex<Box<dyn MemoryMapperTrait>>>>,
+    translate_response_senders: Option<BTreeMap<u32, Tube>>,
+    translate_request_rx: Option<Tube>,
+) -> DeviceResult<(SmmuV3, Option<Minijail>)> {
+    let smmu = SmmuV3::new(
+        mem.clone(),
+        endpoints,
+        translate_response_senders,
+        translate_request_rx,
+    )
+    .context("failed to create SMMUv3")?;
+    Ok((smmu, simple_jail(jail_config, "iommu_device")?))
+}
+
 fn add_bind_mounts(param: &SerialParameters, jail: &mut Minijail) -> Result<(), minijail::Error> {
     if let Some(path) = &param.path {
         if let SerialType::SystemSerialType = param.type_ {
@@ -1587,6 +2016,54 @@ impl VirtioDeviceBuilder for &SerialParameters {
     }
 }
 
+/// A one-shot configuration structure for creating a virtio-console device with an optional
+/// control tube for port hotplug. See `DiskConfig` for why this is separate from
+/// `SerialParameters`.
+pub struct ConsoleConfig<'a> {
+    /// Options for console creation.
+    params: &'a SerialParameters,
+    /// Optional control tube for adding and removing ports.
+    device_tube: Option<Tube>,
+}
+
+impl<'a> ConsoleConfig<'a> {
+    pub fn new(params: &'a SerialParameters, device_tube: Option<Tube>) -> Self {
+        Self {
+            params,
+            device_tube,
+        }
+    }
+}
+
+impl VirtioDeviceBuilder for ConsoleConfig<'_> {
+    const NAME: &'static str = "serial";
+
+    fn create_virtio_device(
+        self,
+        protection_type: ProtectionType,
+    ) -> anyhow::Result<Box<dyn VirtioDevice>> {
+        let mut keep_rds = Vec::new();
+        let evt = Event::new().context("failed to create event")?;
+
+        let mut console = self
+            .params
+            .create_serial_device::<Console>(protection_type, &evt, &mut keep_rds)
+            .context("failed to create console device")?;
+        if let Some(device_tube) = self.device_tube {
+            console.enable_port_hotplug(device_tube);
+        }
+        Ok(Box::new(console))
+    }
+
+    fn create_jail(
+        &self,
+        jail_config: Option<&JailConfig>,
+        virtio_transport: VirtioDeviceType,
+    ) -> anyhow::Result<Option<Minijail>> {
+        self.params.create_jail(jail_config, virtio_transport)
+    }
+}
+
 #[cfg(feature = "audio")]
 pub fn create_sound_device(
     path: &Path,
@@ -1620,6 +2097,8 @@ pub fn create_vfio_device(
     coiommu_endpoints: Option<&mut Vec<u16>>,
     iommu_dev: IommuDevType,
     dt_symbol: Option<String>,
+    dt_from_host: bool,
+    p2p: bool,
     vfio_container_manager: &mut VfioContainerManager,
 ) -> DeviceResult<(VfioDeviceVariant, Option<Minijail>, Option<VfioWrapper>)> {
     let vfio_container = vfio_container_manager
@@ -1631,7 +2110,9 @@ pub fn create_vfio_device(
     add_control_tube(
         VmMemoryTube {
             tube: vfio_host_tube_mem,
-            expose_with_viommu: false,
+            // The mmapped BARs are registered through this tube, which makes them available to
+            // the IOMMU domains of the other devices.
+            expose_with_viommu: p2p,
         }
         .into(),
     );
@@ -1645,6 +2126,10 @@ pub fn create_vfio_device(
 
     match vfio_device.device_type() {
         VfioDeviceType::Pci => {
+            if dt_from_host {
+                bail!("dt-from-host is only supported for VFIO platform devices");
+            }
+
             let (vfio_host_tube_msi, vfio_device_tube_msi) =
                 Tube::pair().context("failed to create tube")?;
             add_control_tube(AnyControlTube::IrqTube(vfio_host_tube_msi));
@@ -1703,8 +2188,22 @@ pub fn create_vfio_device(
                 bail!("hotplug is not supported for VFIO platform devices");
             }
 
-            let vfio_plat_dev =
-                VfioPlatformDevice::new(vfio_device, VmMemoryClient::new(vfio_device_tube_mem));
+            // The node of the device in the host device tree, which the guest node is generated
+            // from.
+            let host_dt_node = if dt_from_host {
+                let of_node = vfio_path.join("of_node");
+                Some(std::fs::canonicalize(&of_node).with_context(|| {
+                    format!("failed to find host device tree node {}", of_node.display())
+                })?)
+            } else {
+                None
+            };
+
+            let vfio_plat_dev = VfioPlatformDevice::new(
+                vfio_device,
+                VmMemoryClient::new(vfio_device_tube_mem),
+                host_dt_node,
+            );
 
             Ok((
                 VfioDeviceVariant::Platform(vfio_plat_dev),
@@ -0,0 +1,382 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Attaches host evdev devices to the guest as they appear and detaches them when they go away.
+//!
+//! A thread listens for udev events on a `NETLINK_KOBJECT_UEVENT` socket. When an input event
+//! node matching one of the configured criteria is added, it asks the main loop to hotplug a
+//! virtio-input device passing it through, and unplugs that device once the node is removed.
+//! Devices that already exist when the VM starts are attached the same way.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::Context;
+use anyhow::Result;
+use base::error;
+use base::info;
+use base::FromRawDescriptor;
+use base::SafeDescriptor;
+use base::Tube;
+use vm_control::EvdevControlCommand;
+use vm_control::VmRequest;
+use vm_control::VmResponse;
+
+use crate::crosvm::config::InputDeviceOption;
+
+const SYSFS_INPUT_CLASS: &str = "/sys/class/input";
+const DEV_INPUT: &str = "/dev/input";
+
+// Multicast group udevd forwards processed events on. Unlike the kernel group, events arrive
+// here only after udevd has created the device node and applied its permissions.
+const UDEV_MONITOR_GROUP: u32 = 2;
+
+// Header udevd prepends to the events it forwards, from libudev's `monitor_netlink_header`.
+const UDEV_HEADER_PREFIX: &[u8] = b"libudev\0";
+const UDEV_HEADER_PROPERTIES_OFF: usize = 16;
+
+const BUF_SIZE: usize = 8192;
+
+/// Criteria a host input device must meet to be passed through. Unset fields match anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvdevMatcher {
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+}
+
+impl EvdevMatcher {
+    fn matches(&self, info: &EvdevInfo) -> bool {
+        self.name.as_ref().map_or(true, |name| *name == info.name)
+            && self.vendor.map_or(true, |vendor| vendor == info.vendor)
+            && self.product.map_or(true, |product| product == info.product)
+    }
+}
+
+/// Identification of a host input device, read from sysfs.
+#[derive(Debug, PartialEq, Eq)]
+struct EvdevInfo {
+    name: String,
+    vendor: u16,
+    product: u16,
+}
+
+impl EvdevInfo {
+    fn read(node: &str) -> io::Result<Self> {
+        let device = Path::new(SYSFS_INPUT_CLASS).join(node).join("device");
+        let read_attr = |attr: &str| -> io::Result<String> {
+            Ok(fs::read_to_string(device.join(attr))?.trim_end().to_owned())
+        };
+        let read_id = |attr: &str| -> io::Result<u16> {
+            let value = read_attr(attr)?;
+            u16::from_str_radix(&value, 16)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        Ok(EvdevInfo {
+            name: read_attr("name")?,
+            vendor: read_id("id/vendor")?,
+            product: read_id("id/product")?,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Uevent {
+    /// An input event node, e.g. `event3`, was added.
+    Add(String),
+    /// An input event node was removed.
+    Remove(String),
+}
+
+/// Parses a uevent message, returning the event node it refers to if it is for an evdev device.
+///
+/// Both the kernel format (`action@devpath` followed by properties) and the format forwarded by
+/// udevd (a binary header followed by properties) are accepted.
+fn parse_uevent(buf: &[u8]) -> Option<Uevent> {
+    let properties = if buf.starts_with(UDEV_HEADER_PREFIX) {
+        let off = buf.get(UDEV_HEADER_PROPERTIES_OFF..UDEV_HEADER_PROPERTIES_OFF + 4)?;
+        let off = u32::from_ne_bytes(off.try_into().unwrap()) as usize;
+        buf.get(off..)?
+    } else {
+        let pos = buf.iter().position(|&b| b == 0)?;
+        &buf[pos + 1..]
+    };
+
+    let mut action = None;
+    let mut subsystem = None;
+    let mut devname = None;
+    for property in properties.split(|&b| b == 0) {
+        let Ok(property) = std::str::from_utf8(property) else {
+            continue;
+        };
+        match property.split_once('=') {
+            Some(("ACTION", value)) => action = Some(value),
+            Some(("SUBSYSTEM", value)) => subsystem = Some(value),
+            Some(("DEVNAME", value)) => devname = Some(value),
+            _ => {}
+        }
+    }
+
+    if subsystem != Some("input") {
+        return None;
+    }
+    let node = Path::new(devname?).file_name()?.to_str()?;
+    if !node.starts_with("event") {
+        return None;
+    }
+    match action? {
+        "add" => Some(Uevent::Add(node.to_owned())),
+        "remove" => Some(Uevent::Remove(node.to_owned())),
+        _ => None,
+    }
+}
+
+fn open_uevent_socket() -> io::Result<SafeDescriptor> {
+    // SAFETY:
+    // Safe because we check the return value and convert the raw fd into a SafeDescriptor.
+    let sock = unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        SafeDescriptor::from_raw_descriptor(fd)
+    };
+
+    // SAFETY:
+    // Safe because all 0s is valid data for sockaddr_nl.
+    let mut sa = unsafe { MaybeUninit::<libc::sockaddr_nl>::zeroed().assume_init() };
+    sa.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    sa.nl_groups = UDEV_MONITOR_GROUP;
+
+    // SAFETY:
+    // Safe because we pass a descriptor that we own and valid pointer/size for sockaddr.
+    let res = unsafe {
+        libc::bind(
+            sock.as_raw_fd(),
+            &sa as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of_val(&sa) as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sock)
+}
+
+struct EvdevHotplugMonitor {
+    matchers: Vec<EvdevMatcher>,
+    control_tube: Tube,
+    // Bus number of the device attached for each event node.
+    attached: BTreeMap<String, u8>,
+}
+
+impl EvdevHotplugMonitor {
+    fn request(&self, command: EvdevControlCommand) -> Result<VmResponse> {
+        self.control_tube
+            .send(&VmRequest::HotPlugEvdevCommand(command))
+            .context("failed to send hotplug request")?;
+        self.control_tube
+            .recv()
+            .context("failed to receive hotplug response")
+    }
+
+    fn attach(&mut self, node: &str) -> Result<()> {
+        if self.attached.contains_key(node) {
+            return Ok(());
+        }
+        let info = match EvdevInfo::read(node) {
+            Ok(info) => info,
+            // The device may already be gone, or not be backed by a physical device.
+            Err(_) => return Ok(()),
+        };
+        if !self.matchers.iter().any(|m| m.matches(&info)) {
+            return Ok(());
+        }
+
+        let path = PathBuf::from(DEV_INPUT).join(node);
+        match self.request(EvdevControlCommand::Add(path))? {
+            VmResponse::PciHotPlugResponse { bus } => {
+                info!(
+                    "attached evdev device {} ({}) on bus {}",
+                    node, info.name, bus
+                );
+                self.attached.insert(node.to_owned(), bus);
+            }
+            VmResponse::ErrString(e) => error!("failed to attach evdev device {}: {}", node, e),
+            r => error!("unexpected response attaching evdev device {}: {}", node, r),
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self, node: &str) -> Result<()> {
+        let Some(bus) = self.attached.remove(node) else {
+            return Ok(());
+        };
+        match self.request(EvdevControlCommand::Remove(bus))? {
+            VmResponse::Ok => info!("detached evdev device {} from bus {}", node, bus),
+            VmResponse::ErrString(e) => error!("failed to detach evdev device {}: {}", node, e),
+            r => error!("unexpected response detaching evdev device {}: {}", node, r),
+        }
+        Ok(())
+    }
+
+    fn run(mut self, sock: SafeDescriptor) -> Result<()> {
+        // The socket is already listening, so devices added while enumerating are not missed;
+        // attaching the same node twice is ignored.
+        for entry in fs::read_dir(SYSFS_INPUT_CLASS).context("failed to enumerate input devices")? {
+            let name = entry?.file_name();
+            if let Some(node) = name.to_str().filter(|n| n.starts_with("event")) {
+                self.attach(node)?;
+            }
+        }
+
+        let mut buf = [0u8; BUF_SIZE];
+        loop {
+            // SAFETY:
+            // Safe because we pass a valid, owned socket fd and a valid pointer/size for the
+            // buffer.
+            let len = unsafe {
+                libc::recv(
+                    sock.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if len < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e).context("failed to receive uevent");
+            }
+            match parse_uevent(&buf[..len as usize]) {
+                Some(Uevent::Add(node)) => self.attach(&node)?,
+                Some(Uevent::Remove(node)) => self.detach(&node)?,
+                None => {}
+            }
+        }
+    }
+}
+
+/// Returns the hotplug criteria given by `evdev-hotplug` input options.
+pub fn evdev_hotplug_matchers(inputs: &[InputDeviceOption]) -> Vec<EvdevMatcher> {
+    inputs
+        .iter()
+        .filter_map(|input| match input {
+            InputDeviceOption::EvdevHotplug {
+                name,
+                vendor,
+                product,
+            } => Some(EvdevMatcher {
+                name: name.clone(),
+                vendor: *vendor,
+                product: *product,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Starts a thread that attaches host evdev devices matching `matchers` through `control_tube`,
+/// which must be connected to the VM control loop.
+pub fn start_evdev_hotplug(matchers: Vec<EvdevMatcher>, control_tube: Tube) -> Result<()> {
+    let sock = open_uevent_socket().context("failed to open uevent socket")?;
+    let monitor = EvdevHotplugMonitor {
+        matchers,
+        control_tube,
+        attached: BTreeMap::new(),
+    };
+    thread::Builder::new()
+        .name("evdev_hotplug".to_string())
+        .spawn(move || {
+            if let Err(e) = monitor.run(sock) {
+                error!("evdev hotplug monitor stopped: {:#}", e);
+            }
+        })
+        .context("failed to spawn evdev hotplug thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(props: &[&str]) -> Vec<u8> {
+        props.iter().flat_map(|p| p.bytes().chain([0])).collect()
+    }
+
+    #[test]
+    fn parse_kernel_uevent() {
+        let mut buf = b"add@/devices/virtual/input/input7/event3\0".to_vec();
+        buf.extend(properties(&[
+            "ACTION=add",
+            "DEVPATH=/devices/virtual/input/input7/event3",
+            "SUBSYSTEM=input",
+            "DEVNAME=input/event3",
+        ]));
+        assert_eq!(parse_uevent(&buf), Some(Uevent::Add("event3".to_owned())));
+
+        // The parent input device has no event node.
+        let mut buf = b"add@/devices/virtual/input/input7\0".to_vec();
+        buf.extend(properties(&["ACTION=add", "SUBSYSTEM=input"]));
+        assert_eq!(parse_uevent(&buf), None);
+    }
+
+    #[test]
+    fn parse_udev_uevent() {
+        let props = properties(&[
+            "ACTION=remove",
+            "SUBSYSTEM=input",
+            "DEVNAME=/dev/input/event5",
+        ]);
+        let mut buf = UDEV_HEADER_PREFIX.to_vec();
+        buf.extend(0xfeedcafeu32.to_be_bytes());
+        buf.extend(40u32.to_ne_bytes()); // header_size
+        buf.extend(40u32.to_ne_bytes()); // properties_off
+        buf.extend((props.len() as u32).to_ne_bytes());
+        buf.resize(40, 0);
+        buf.extend(props);
+        assert_eq!(
+            parse_uevent(&buf),
+            Some(Uevent::Remove("event5".to_owned()))
+        );
+    }
+
+    #[test]
+    fn matcher_checks_all_criteria() {
+        let info = EvdevInfo {
+            name: "Gamepad".to_owned(),
+            vendor: 0x045e,
+            product: 0x028e,
+        };
+        assert!(EvdevMatcher {
+            vendor: Some(0x045e),
+            ..Default::default()
+        }
+        .matches(&info));
+        assert!(EvdevMatcher {
+            name: Some("Gamepad".to_owned()),
+            vendor: Some(0x045e),
+            product: Some(0x028e),
+        }
+        .matches(&info));
+        assert!(!EvdevMatcher {
+            vendor: Some(0x045e),
+            product: Some(0x02ea),
+            ..Default::default()
+        }
+        .matches(&info));
+    }
+}
@@ -90,6 +90,7 @@ pub fn create_gpu_device(
     render_server_fd: Option<SafeDescriptor>,
     has_vfio_gfx_device: bool,
     event_devices: Vec<EventDevice>,
+    #[cfg(feature = "registered_events")] registered_evt_q: Option<SendTube>,
 ) -> DeviceResult {
     let is_sandboxed = cfg.jail_config.is_some();
     let mut gpu_params = cfg.gpu_parameters.clone().unwrap();
@@ -155,6 +156,8 @@ pub fn create_gpu_device(
         virtio::base_features(cfg.protection_type),
         &cfg.wayland_socket_paths,
         cfg.gpu_cgroup_path.as_ref(),
+        #[cfg(feature = "registered_events")]
+        registered_evt_q,
     );
 
     let jail = if let Some(jail_config) = cfg.jail_config.as_ref() {
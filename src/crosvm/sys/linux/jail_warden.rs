@@ -30,6 +30,7 @@ use jail::create_base_minijail;
 use jail::create_sandbox_minijail;
 use jail::fork::fork_process;
 use jail::fork::Child;
+use jail::simple_jail;
 use jail::RunAsUser;
 use jail::SandboxConfig;
 use jail::MAX_OPEN_FILES_FOR_JAIL_WARDEN;
@@ -40,8 +41,13 @@ use swap::SwapDeviceHelper;
 use sync::Mutex;
 use vm_memory::GuestMemory;
 
+use crate::crosvm::sys::linux::pci_hotplug_helpers::build_hotplug_block_device;
+use crate::crosvm::sys::linux::pci_hotplug_helpers::build_hotplug_evdev_device;
 use crate::crosvm::sys::linux::pci_hotplug_helpers::build_hotplug_net_device;
+use crate::crosvm::sys::linux::pci_hotplug_helpers::BlockLocalParameters;
+use crate::crosvm::sys::linux::pci_hotplug_helpers::EvdevLocalParameters;
 use crate::crosvm::sys::linux::pci_hotplug_helpers::NetLocalParameters;
+use crate::crosvm::sys::linux::DiskConfig;
 use crate::crosvm::sys::linux::VirtioDeviceBuilder;
 use crate::Config;
 
@@ -198,6 +204,29 @@ fn jail_worker_process(
                             build_hotplug_net_device(net_resource_carrier, net_local_parameters)?;
                         (pci_device, jail)
                     }
+                    ResourceCarrier::VirtioInputEvdev(evdev_resource_carrier) => {
+                        let jail = simple_jail(config.jail_config.as_ref(), "input_device")?
+                            .ok_or(anyhow!("no jail created"))?;
+                        let evdev_local_parameters =
+                            EvdevLocalParameters::new(guest_memory.clone(), config.protection_type);
+                        let pci_device = build_hotplug_evdev_device(
+                            evdev_resource_carrier,
+                            evdev_local_parameters,
+                        )?;
+                        (pci_device, jail)
+                    }
+                    ResourceCarrier::VirtioBlock(block_resource_carrier) => {
+                        let jail = DiskConfig::new(&block_resource_carrier.disk_option, None)
+                            .create_jail(config.jail_config.as_ref(), VirtioDeviceType::Regular)?
+                            .ok_or(anyhow!("no jail created"))?;
+                        let block_local_parameters =
+                            BlockLocalParameters::new(guest_memory.clone(), config.protection_type);
+                        let pci_device = build_hotplug_block_device(
+                            block_resource_carrier,
+                            block_local_parameters,
+                        )?;
+                        (pci_device, jail)
+                    }
                 };
                 let mut keep_rds = vec![];
                 syslog::push_descriptors(&mut keep_rds);
@@ -256,6 +285,20 @@ impl JailWarden for PermissiveJailWarden {
                     NetLocalParameters::new(self.guest_memory.clone(), self.config.protection_type);
                 build_hotplug_net_device(net_resource_carrier, net_local_parameters)?
             }
+            ResourceCarrier::VirtioInputEvdev(evdev_resource_carrier) => {
+                let evdev_local_parameters = EvdevLocalParameters::new(
+                    self.guest_memory.clone(),
+                    self.config.protection_type,
+                );
+                build_hotplug_evdev_device(evdev_resource_carrier, evdev_local_parameters)?
+            }
+            ResourceCarrier::VirtioBlock(block_resource_carrier) => {
+                let block_local_parameters = BlockLocalParameters::new(
+                    self.guest_memory.clone(),
+                    self.config.protection_type,
+                );
+                build_hotplug_block_device(block_resource_carrier, block_local_parameters)?
+            }
         };
         Ok((Arc::new(Mutex::new(pci_device)), 0))
     }
@@ -0,0 +1,161 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Serves the VM metrics in the Prometheus text format on `--metrics-address`.
+//!
+//! The block and network counters are aggregated from the metrics events of all crosvm processes by
+//! the metrics controller. The vCPU exit counters and the balloon and vmm-swap gauges are read
+//! through a regular control tube when the metrics are scraped.
+
+use std::fmt::Write as _;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::thread;
+
+use anyhow::Context;
+use base::error;
+use base::Tube;
+#[cfg(feature = "swap")]
+use swap::SwapState;
+#[cfg(feature = "balloon")]
+use vm_control::BalloonControlCommand;
+#[cfg(feature = "swap")]
+use vm_control::SwapCommand;
+use vm_control::VmRequest;
+use vm_control::VmResponse;
+
+#[cfg(feature = "swap")]
+const SWAP_STATES: [SwapState; 7] = [
+    SwapState::Ready,
+    SwapState::Failed,
+    SwapState::Pending,
+    SwapState::TrimInProgress,
+    SwapState::SwapOutInProgress,
+    SwapState::Active,
+    SwapState::SwapInInProgress,
+];
+
+fn send_request(tube: &Tube, request: &VmRequest) -> Option<VmResponse> {
+    tube.send(request).and_then(|()| tube.recv()).ok()
+}
+
+fn render_metrics(tube: &Tube) -> String {
+    let mut out = metrics::render_prometheus();
+
+    // The vCPUs count their exits locally; the counts are only collected on scrape.
+    if let Some(VmResponse::VcpuStats(stats)) = send_request(tube, &VmRequest::VcpuStats) {
+        let _ = writeln!(out, "# HELP crosvm_vcpu_exits_total vCPU exits by reason.");
+        let _ = writeln!(out, "# TYPE crosvm_vcpu_exits_total counter");
+        for vcpu in stats {
+            for (reason, stat) in vcpu.exits {
+                let _ = writeln!(
+                    out,
+                    "crosvm_vcpu_exits_total{{vcpu=\"{}\",reason=\"{}\"}} {}",
+                    vcpu.cpu_id,
+                    reason.as_str(),
+                    stat.count
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "balloon")]
+    if let Some(VmResponse::BalloonStats { balloon_actual, .. }) = send_request(
+        tube,
+        &VmRequest::BalloonCommand(BalloonControlCommand::Stats),
+    ) {
+        let _ = writeln!(
+            out,
+            "# HELP crosvm_balloon_actual_bytes Size of the balloon."
+        );
+        let _ = writeln!(out, "# TYPE crosvm_balloon_actual_bytes gauge");
+        let _ = writeln!(out, "crosvm_balloon_actual_bytes {}", balloon_actual);
+    }
+
+    #[cfg(feature = "swap")]
+    if let Some(VmResponse::SwapStatus(status)) =
+        send_request(tube, &VmRequest::Swap(SwapCommand::Status))
+    {
+        let _ = writeln!(out, "# HELP crosvm_swap_state Current vmm-swap state.");
+        let _ = writeln!(out, "# TYPE crosvm_swap_state gauge");
+        for state in SWAP_STATES {
+            let _ = writeln!(
+                out,
+                "crosvm_swap_state{{state=\"{:?}\"}} {}",
+                state,
+                (state == status.state) as u8
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP crosvm_swap_file_pages Pages in the vmm-swap file."
+        );
+        let _ = writeln!(out, "# TYPE crosvm_swap_file_pages gauge");
+        let _ = writeln!(out, "crosvm_swap_file_pages {}", status.metrics.swap_pages);
+    }
+
+    out
+}
+
+fn serve_client(stream: TcpStream, tube: &Tube) -> anyhow::Result<()> {
+    let mut writer = stream
+        .try_clone()
+        .context("failed to clone metrics stream")?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are not needed to answer.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render_metrics(tube)),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Starts a thread serving the metrics on `address`.
+///
+/// The gauges are queried from the main control loop through `tube`.
+pub(crate) fn start_metrics_server(address: SocketAddr, tube: Tube) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("failed to bind metrics server to {}", address))?;
+    thread::Builder::new()
+        .name("metrics_server".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = serve_client(stream, &tube) {
+                            error!("metrics client error: {:#}", e);
+                        }
+                    }
+                    Err(e) => error!("failed to accept metrics client: {}", e),
+                }
+            }
+        })
+        .context("failed to spawn metrics server thread")?;
+    Ok(())
+}
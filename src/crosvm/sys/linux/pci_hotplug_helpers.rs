@@ -8,14 +8,19 @@
 
 use anyhow::Context;
 use anyhow::Result;
+use devices::virtio;
+use devices::BlockResourceCarrier;
+use devices::EvdevResourceCarrier;
 use devices::HotPluggable;
 use devices::IntxParameter;
 use devices::NetResourceCarrier;
+use devices::PciAddress;
 use devices::PciDevice;
 use devices::VirtioPciDevice;
 use hypervisor::ProtectionType;
 use vm_memory::GuestMemory;
 
+use crate::crosvm::sys::linux::DiskConfig;
 use crate::crosvm::sys::linux::VirtioDeviceBuilder;
 
 /// Builds HotPlugPci from NetResourceCarrier and NetLocalParameters.
@@ -30,7 +35,7 @@ pub fn build_hotplug_net_device(
         .net_param
         .create_virtio_device(net_local_parameters.protection_type)
         .context("create virtio device")?;
-    let mut virtio_pci_device = VirtioPciDevice::new(
+    let virtio_pci_device = VirtioPciDevice::new(
         net_local_parameters.guest_memory,
         virtio_device,
         net_carrier_device.msi_device_tube,
@@ -40,6 +45,77 @@ pub fn build_hotplug_net_device(
         net_carrier_device.vm_control_tube,
     )
     .context("create virtio PCI device")?;
+    configure_hotplug_device(
+        virtio_pci_device,
+        pci_address,
+        net_carrier_device.intx_parameter,
+    )
+}
+
+/// Builds HotPlugPci from EvdevResourceCarrier and EvdevLocalParameters.
+pub fn build_hotplug_evdev_device(
+    evdev_carrier_device: EvdevResourceCarrier,
+    evdev_local_parameters: EvdevLocalParameters,
+) -> Result<Box<dyn HotPluggable>> {
+    let pci_address = evdev_carrier_device
+        .pci_address
+        .context("PCI address not allocated")?;
+    let virtio_device = virtio::input::new_evdev(
+        evdev_carrier_device.evdev,
+        virtio::base_features(evdev_local_parameters.protection_type),
+    )
+    .context("create virtio device")?;
+    let virtio_pci_device = VirtioPciDevice::new(
+        evdev_local_parameters.guest_memory,
+        Box::new(virtio_device),
+        evdev_carrier_device.msi_device_tube,
+        true,
+        None,
+        evdev_carrier_device.ioevent_vm_memory_client,
+        evdev_carrier_device.vm_control_tube,
+    )
+    .context("create virtio PCI device")?;
+    configure_hotplug_device(
+        virtio_pci_device,
+        pci_address,
+        evdev_carrier_device.intx_parameter,
+    )
+}
+
+/// Builds HotPlugPci from BlockResourceCarrier and BlockLocalParameters.
+pub fn build_hotplug_block_device(
+    block_carrier_device: BlockResourceCarrier,
+    block_local_parameters: BlockLocalParameters,
+) -> Result<Box<dyn HotPluggable>> {
+    let pci_address = block_carrier_device
+        .pci_address
+        .context("PCI address not allocated")?;
+    let virtio_device = DiskConfig::new(&block_carrier_device.disk_option, None)
+        .create_virtio_device(block_local_parameters.protection_type)
+        .context("create virtio device")?;
+    let virtio_pci_device = VirtioPciDevice::new(
+        block_local_parameters.guest_memory,
+        virtio_device,
+        block_carrier_device.msi_device_tube,
+        true,
+        None,
+        block_carrier_device.ioevent_vm_memory_client,
+        block_carrier_device.vm_control_tube,
+    )
+    .context("create virtio PCI device")?;
+    configure_hotplug_device(
+        virtio_pci_device,
+        pci_address,
+        block_carrier_device.intx_parameter,
+    )
+}
+
+/// Lays out the BARs and interrupt of a VirtioPciDevice without access to the SystemAllocator.
+fn configure_hotplug_device(
+    mut virtio_pci_device: VirtioPciDevice,
+    pci_address: PciAddress,
+    intx_parameter: Option<IntxParameter>,
+) -> Result<Box<dyn HotPluggable>> {
     virtio_pci_device
         .set_pci_address(pci_address)
         .context("set PCI address")?;
@@ -53,9 +129,7 @@ pub fn build_hotplug_net_device(
         irq_evt,
         irq_num,
         pin,
-    } = net_carrier_device
-        .intx_parameter
-        .context("Missing INTx parameter.")?;
+    } = intx_parameter.context("Missing INTx parameter.")?;
     virtio_pci_device.assign_irq(irq_evt, pin, irq_num);
     Ok(Box::new(virtio_pci_device))
 }
@@ -75,3 +149,35 @@ impl NetLocalParameters {
         }
     }
 }
+
+/// Additional parameters required on the destination process to configure evdev VirtioPciDevice.
+pub struct EvdevLocalParameters {
+    guest_memory: GuestMemory,
+    protection_type: ProtectionType,
+}
+
+impl EvdevLocalParameters {
+    /// Constructs EvdevLocalParameters.
+    pub fn new(guest_memory: GuestMemory, protection_type: ProtectionType) -> Self {
+        Self {
+            guest_memory,
+            protection_type,
+        }
+    }
+}
+
+/// Additional parameters required on the destination process to configure block VirtioPciDevice.
+pub struct BlockLocalParameters {
+    guest_memory: GuestMemory,
+    protection_type: ProtectionType,
+}
+
+impl BlockLocalParameters {
+    /// Constructs BlockLocalParameters.
+    pub fn new(guest_memory: GuestMemory, protection_type: ProtectionType) -> Self {
+        Self {
+            guest_memory,
+            protection_type,
+        }
+    }
+}
@@ -28,15 +28,21 @@ use base::WorkerThread;
 use devices::BusDevice;
 use devices::HotPlugBus;
 use devices::HotPlugKey;
+use devices::HotPluggable;
 use devices::IrqEventSource;
 use devices::IrqLevelEvent;
 use devices::PciAddress;
+use devices::PciDevice;
 use devices::PciInterruptPin;
 use devices::PciRootCommand;
+use devices::ProxyDevice;
 use devices::ResourceCarrier;
 use log::error;
+use minijail::Minijail;
 use resources::SystemAllocator;
 #[cfg(feature = "swap")]
+use swap::SwapController;
+#[cfg(feature = "swap")]
 use swap::SwapDeviceHelper;
 use sync::Mutex;
 use vm_memory::GuestMemory;
@@ -119,6 +125,10 @@ struct PortManagerStub {
 struct RecoverableResource {
     irq_num: u32,
     irq_evt: IrqLevelEvent,
+    /// key identifying the device to the hotplug commands
+    hotplug_key: HotPlugKey,
+    /// pid of the process running the device, if sandboxed
+    pid: Option<u32>,
 }
 
 /// Control commands to worker.
@@ -822,12 +832,7 @@ impl PciHotPlugManager {
             };
             resource_carrier.allocate_address(device_address, resources)?;
             let irq_evt = IrqLevelEvent::new()?;
-            let (pin, irq_num) = match downstream_bus % 4 {
-                0 => (PciInterruptPin::IntA, 0),
-                1 => (PciInterruptPin::IntB, 1),
-                2 => (PciInterruptPin::IntC, 2),
-                _ => (PciInterruptPin::IntD, 3),
-            };
+            let (pin, irq_num) = port_intx(downstream_bus);
             resource_carrier.assign_irq(irq_evt.try_clone()?, pin, irq_num);
             let (proxy_device, pid) = self
                 .jail_warden
@@ -845,7 +850,8 @@ impl PciHotPlugManager {
                 },
             )?;
             let pid: u32 = pid.try_into().context("fork fail")?;
-            if pid > 0 {
+            let pid = (pid > 0).then_some(pid);
+            if let Some(pid) = pid {
                 linux.pid_debug_label_map.insert(pid, device_name);
             }
             devices.push(GuestDeviceStub {
@@ -853,9 +859,15 @@ impl PciHotPlugManager {
                 key: hotplug_key,
                 device: proxy_device,
             });
-            port_stub
-                .devices
-                .insert(device_address, RecoverableResource { irq_num, irq_evt });
+            port_stub.devices.insert(
+                device_address,
+                RecoverableResource {
+                    irq_num,
+                    irq_evt,
+                    hotplug_key,
+                    pid,
+                },
+            );
         }
         // Ask worker to schedule hotplug signal.
         match worker_client.send_worker_command(WorkerCommand::SignalHotPlug(
@@ -867,6 +879,146 @@ impl PciHotPlugManager {
         }
     }
 
+    /// Returns the downstream bus of the port the next device would be hotplugged into.
+    pub fn get_empty_bus(&self) -> Result<u8> {
+        let worker_client = self
+            .worker_client
+            .as_ref()
+            .context("No worker thread. Is set_rootbus_controller not called?")?;
+        let pci_address = match worker_client.send_worker_command(WorkerCommand::GetEmptyPort)? {
+            WorkerResponse::GetEmptyPortOk(p) => Ok(p),
+            WorkerResponse::InvalidCommand(e) => Err(e),
+            r => bail!("Unexpected response from worker: {:?}", &r),
+        }?;
+        let port_stub = self
+            .port_stubs
+            .get(&pci_address)
+            .context("Cannot find port")?;
+        Ok(port_stub.downstream_bus)
+    }
+
+    /// Hotplugs a PCI device built by the main process into the empty port of `bus`.
+    ///
+    /// Unlike `hotplug_device`, the device is not created by the jail warden. This is required for
+    /// VFIO devices, which share the VFIO container of the main process. The device is placed at
+    /// function 0 of `bus`, which should be obtained from `get_empty_bus`.
+    ///
+    /// returns the bus number of the bus on success.
+    pub fn hotplug_local_device<V: VmArch, Vcpu: VcpuArch>(
+        &mut self,
+        mut device: Box<dyn HotPluggable>,
+        jail: Option<Minijail>,
+        hotplug_key: HotPlugKey,
+        bus: u8,
+        linux: &mut RunnableLinuxVm<V, Vcpu>,
+        resources: &mut SystemAllocator,
+        #[cfg(feature = "swap")] swap_controller: &mut Option<SwapController>,
+    ) -> Result<u8> {
+        let worker_client = self
+            .worker_client
+            .as_ref()
+            .context("No worker thread. Is set_rootbus_controller not called?")?;
+        let pci_address = *self
+            .bus_address_map
+            .get(&bus)
+            .context(format!("Port {} is not known", &bus))?;
+        match worker_client.send_worker_command(WorkerCommand::GetPortState(pci_address))? {
+            WorkerResponse::GetPortStateOk(PortState::Empty(_) | PortState::EmptyNotReady) => {}
+            WorkerResponse::GetPortStateOk(_) => bail!("Port {} is occupied", &bus),
+            WorkerResponse::InvalidCommand(e) => return Err(e),
+            r => bail!("Unexpected response from worker: {:?}", &r),
+        }
+        let port_stub = self
+            .port_stubs
+            .get_mut(&pci_address)
+            .context("Cannot find port")?;
+        let device_address = PciAddress::new(0, bus as u32, 0, 0)?;
+        if !resources.reserve_pci(device_address, PciDevice::debug_label(&device)) {
+            bail!("PCI address {} is in use", device_address);
+        }
+        device
+            .set_pci_address(device_address)
+            .context("set PCI address")?;
+        device.configure_io_bars().context("configure IO BAR")?;
+        device
+            .configure_device_bars()
+            .context("configure device BAR")?;
+        let irq_evt = IrqLevelEvent::new()?;
+        let (pin, irq_num) = port_intx(bus);
+        device.assign_irq(irq_evt.try_clone()?, pin, irq_num);
+        linux.irq_chip.as_irq_chip_mut().register_level_irq_event(
+            irq_num,
+            &irq_evt,
+            IrqEventSource::from_device(&device),
+        )?;
+        device
+            .register_device_capabilities()
+            .context("register device capabilities")?;
+        let mut pid = None;
+        let device: Arc<Mutex<dyn BusDevice>> = match jail {
+            Some(jail) => {
+                let mut keep_rds = device.keep_rds();
+                base::syslog::push_descriptors(&mut keep_rds);
+                cros_tracing::push_descriptors!(&mut keep_rds);
+                metrics::push_descriptors(&mut keep_rds);
+                let proxy = ProxyDevice::new(
+                    device,
+                    jail,
+                    keep_rds,
+                    #[cfg(feature = "swap")]
+                    swap_controller,
+                )
+                .context("make proxy device")?;
+                pid = Some(proxy.pid() as u32);
+                linux
+                    .pid_debug_label_map
+                    .insert(proxy.pid() as u32, proxy.debug_label());
+                Arc::new(Mutex::new(proxy))
+            }
+            None => {
+                device.on_sandboxed();
+                Arc::new(Mutex::new(device))
+            }
+        };
+        port_stub.devices.insert(
+            device_address,
+            RecoverableResource {
+                irq_num,
+                irq_evt,
+                hotplug_key,
+                pid,
+            },
+        );
+        // Ask worker to schedule hotplug signal.
+        match worker_client.send_worker_command(WorkerCommand::SignalHotPlug(
+            SignalHotPlugCommand::new(
+                pci_address,
+                vec![GuestDeviceStub {
+                    pci_addr: device_address,
+                    key: hotplug_key,
+                    device,
+                }],
+            )?,
+        ))? {
+            WorkerResponse::SignalOk => Ok(bus),
+            WorkerResponse::InvalidCommand(e) => Err(e),
+            r => bail!("Unexpected response from worker: {:?}", &r),
+        }
+    }
+
+    /// Returns the downstream bus of the port holding the device identified by `hotplug_key`.
+    pub fn get_hotplug_bus(&self, hotplug_key: HotPlugKey) -> Option<u8> {
+        self.port_stubs
+            .values()
+            .find(|port_stub| {
+                port_stub
+                    .devices
+                    .values()
+                    .any(|resource| resource.hotplug_key == hotplug_key)
+            })
+            .map(|port_stub| port_stub.downstream_bus)
+    }
+
     /// Removes all hotplugged devices on the hotplug bus.
     pub fn remove_hotplug_device<V: VmArch, Vcpu: VcpuArch>(
         &mut self,
@@ -914,11 +1066,25 @@ impl PciHotPlugManager {
                 recoverable_resource.irq_num,
                 &recoverable_resource.irq_evt,
             )?;
+            // The device process exits once its proxy is dropped by hot_unplug.
+            if let Some(pid) = recoverable_resource.pid {
+                linux.pid_debug_label_map.remove(&pid);
+            }
         }
         Ok(())
     }
 }
 
+/// Returns the INTx pin and IRQ number of the devices behind the port of `downstream_bus`.
+fn port_intx(downstream_bus: u8) -> (PciInterruptPin, u32) {
+    match downstream_bus % 4 {
+        0 => (PciInterruptPin::IntA, 0),
+        1 => (PciInterruptPin::IntB, 1),
+        2 => (PciInterruptPin::IntC, 2),
+        _ => (PciInterruptPin::IntD, 3),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread;
@@ -1060,12 +1226,14 @@ mod tests {
         let client = WorkerClient::new(rootbus_controller).unwrap();
         // Port A: upstream 00:01.1, downstream 2.
         let upstream_addr_a = PciAddress {
+            domain: 0,
             bus: 0,
             dev: 1,
             func: 1,
         };
         let bus_a = 2;
         let downstream_addr_a = PciAddress {
+            domain: 0,
             bus: bus_a,
             dev: 0,
             func: 0,
@@ -1083,12 +1251,14 @@ mod tests {
         let port_a = new_port(bus_a);
         // Port B: upstream 00:01.0, downstream 3.
         let upstream_addr_b = PciAddress {
+            domain: 0,
             bus: 0,
             dev: 1,
             func: 0,
         };
         let bus_b = 3;
         let downstream_addr_b = PciAddress {
+            domain: 0,
             bus: bus_b,
             dev: 0,
             func: 0,
@@ -1106,12 +1276,14 @@ mod tests {
         let port_b = new_port(bus_b);
         // Port C: upstream 00:02.0, downstream 4.
         let upstream_addr_c = PciAddress {
+            domain: 0,
             bus: 0,
             dev: 2,
             func: 0,
         };
         let bus_c = 4;
         let downstream_addr_c = PciAddress {
+            domain: 0,
             bus: bus_c,
             dev: 0,
             func: 0,
@@ -1253,6 +1425,7 @@ mod tests {
         let (rootbus_controller, _rootbus_recvr) = mpsc::channel();
         let client = WorkerClient::new(rootbus_controller).unwrap();
         let upstream_addr = PciAddress {
+            domain: 0,
             bus: 0,
             dev: 1,
             func: 1,
@@ -1367,6 +1540,7 @@ mod tests {
         let (rootbus_controller, _rootbus_recvr) = mpsc::channel();
         let client = WorkerClient::new(rootbus_controller).unwrap();
         let upstream_addr = PciAddress {
+            domain: 0,
             bus: 0,
             dev: 1,
             func: 1,
@@ -0,0 +1,380 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A QMP (QEMU Machine Protocol) server, so tools written for QEMU can manage crosvm.
+//!
+//! Only a subset of QMP is implemented. Each command is translated into a [VmRequest] sent to the
+//! main control loop:
+//!
+//! | QMP command             | crosvm request                                                   |
+//! |-------------------------|------------------------------------------------------------------|
+//! | `query-status`          | `GetRunMode`                                                     |
+//! | `stop`, `cont`          | `SuspendVm`, `ResumeVm`                                          |
+//! | `system_powerdown`      | `Powerbtn`                                                       |
+//! | `quit`                  | `Exit`                                                           |
+//! | `__dev.crosvm_snapshot` | `Snapshot` of the whole VM into the `path` directory             |
+//! | `device_add`            | `HotPlugNetCommand`, `HotPlugEvdevCommand`, `HotPlugVfioCommand` |
+//!
+//! crosvm has no block layer overlays, so `blockdev-snapshot` is not supported. Snapshots of the
+//! whole VM are taken with `__dev.crosvm_snapshot`, named as a QMP downstream extension.
+//! `device_add` supports the `virtio-net-pci` (`netdev` names the host TAP interface),
+//! `virtio-input-host-pci` (`evdev`) and `vfio-pci` (`sysfsdev`) drivers.
+//!
+//! Clients are served one at a time.
+
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::Context;
+use base::error;
+use base::info;
+use base::Tube;
+use base::UnlinkUnixListener;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+#[cfg(feature = "pci-hotplug")]
+use vm_control::EvdevControlCommand;
+use vm_control::HotPlugDeviceInfo;
+use vm_control::HotPlugDeviceType;
+#[cfg(feature = "pci-hotplug")]
+use vm_control::NetControlCommand;
+use vm_control::SnapshotCommand;
+use vm_control::VmRequest;
+use vm_control::VmResponse;
+use vm_control::VmRunMode;
+
+const COMMANDS: &[&str] = &[
+    "qmp_capabilities",
+    "query-commands",
+    "query-status",
+    "stop",
+    "cont",
+    "system_powerdown",
+    "quit",
+    "__dev.crosvm_snapshot",
+    "device_add",
+];
+
+#[derive(Deserialize)]
+struct QmpRequest {
+    execute: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+/// Arguments of `__dev.crosvm_snapshot`.
+#[derive(Deserialize)]
+struct SnapshotArgs {
+    path: PathBuf,
+}
+
+/// Arguments of `device_add`. Properties other than these are ignored.
+#[derive(Deserialize)]
+struct DeviceAddArgs {
+    driver: String,
+    #[cfg_attr(not(feature = "pci-hotplug"), allow(dead_code))]
+    netdev: Option<String>,
+    #[cfg_attr(not(feature = "pci-hotplug"), allow(dead_code))]
+    evdev: Option<PathBuf>,
+    sysfsdev: Option<PathBuf>,
+}
+
+/// An error reported to the client, with its QMP error class.
+#[derive(Debug, PartialEq)]
+struct QmpError {
+    class: &'static str,
+    desc: String,
+}
+
+impl QmpError {
+    fn generic(desc: impl Into<String>) -> Self {
+        QmpError {
+            class: "GenericError",
+            desc: desc.into(),
+        }
+    }
+
+    fn command_not_found(command: &str) -> Self {
+        QmpError {
+            class: "CommandNotFound",
+            desc: format!("The command {} has not been found", command),
+        }
+    }
+}
+
+/// What the server does for a command.
+enum QmpCommand {
+    Capabilities,
+    QueryCommands,
+    QueryStatus,
+    Request(VmRequest),
+}
+
+fn parse_arguments<'de, T: Deserialize<'de>>(arguments: &'de Value) -> Result<T, QmpError> {
+    T::deserialize(arguments).map_err(|e| QmpError::generic(format!("invalid arguments: {}", e)))
+}
+
+fn parse_device_add(arguments: &Value) -> Result<VmRequest, QmpError> {
+    let args: DeviceAddArgs = parse_arguments(arguments)?;
+    let missing = |property| {
+        QmpError::generic(format!(
+            "Parameter '{}' is missing for driver '{}'",
+            property, args.driver
+        ))
+    };
+    match args.driver.as_str() {
+        #[cfg(feature = "pci-hotplug")]
+        "virtio-net-pci" => Ok(VmRequest::HotPlugNetCommand(NetControlCommand::AddTap(
+            args.netdev.clone().ok_or_else(|| missing("netdev"))?,
+        ))),
+        #[cfg(feature = "pci-hotplug")]
+        "virtio-input-host-pci" => Ok(VmRequest::HotPlugEvdevCommand(EvdevControlCommand::Add(
+            args.evdev.clone().ok_or_else(|| missing("evdev"))?,
+        ))),
+        "vfio-pci" => Ok(VmRequest::HotPlugVfioCommand {
+            device: HotPlugDeviceInfo {
+                device_type: HotPlugDeviceType::EndPoint,
+                path: args.sysfsdev.clone().ok_or_else(|| missing("sysfsdev"))?,
+                hp_interrupt: true,
+                p2p: false,
+            },
+            add: true,
+        }),
+        driver => Err(QmpError::generic(format!(
+            "'{}' is not a valid device model name",
+            driver
+        ))),
+    }
+}
+
+fn parse_command(execute: &str, arguments: &Value) -> Result<QmpCommand, QmpError> {
+    Ok(match execute {
+        "qmp_capabilities" => QmpCommand::Capabilities,
+        "query-commands" => QmpCommand::QueryCommands,
+        "query-status" => QmpCommand::QueryStatus,
+        "stop" => QmpCommand::Request(VmRequest::SuspendVm),
+        "cont" => QmpCommand::Request(VmRequest::ResumeVm),
+        "system_powerdown" => QmpCommand::Request(VmRequest::Powerbtn),
+        "quit" => QmpCommand::Request(VmRequest::Exit),
+        "__dev.crosvm_snapshot" => {
+            let args: SnapshotArgs = parse_arguments(arguments)?;
+            QmpCommand::Request(VmRequest::Snapshot(SnapshotCommand::Take {
+                snapshot_path: args.path,
+                compress_memory: false,
+                encrypt: false,
+                zstd_level: None,
+                key: None,
+            }))
+        }
+        "device_add" => QmpCommand::Request(parse_device_add(arguments)?),
+        _ => return Err(QmpError::command_not_found(execute)),
+    })
+}
+
+fn run_mode_status(mode: VmRunMode) -> Value {
+    let status = match mode {
+        VmRunMode::Running => "running",
+        VmRunMode::Suspending => "paused",
+        VmRunMode::Exiting => "shutdown",
+        VmRunMode::Breakpoint => "debug",
+    };
+    json!({
+        "status": status,
+        "singlestep": false,
+        "running": mode == VmRunMode::Running,
+    })
+}
+
+fn send_request(tube: &Tube, request: &VmRequest) -> Result<VmResponse, QmpError> {
+    tube.send(request)
+        .and_then(|()| tube.recv())
+        .map_err(|e| QmpError::generic(format!("failed to send the request to crosvm: {}", e)))
+}
+
+fn execute_command(tube: &Tube, command: QmpCommand) -> Result<Value, QmpError> {
+    match command {
+        QmpCommand::Capabilities => Ok(json!({})),
+        QmpCommand::QueryCommands => Ok(Value::Array(
+            COMMANDS
+                .iter()
+                .map(|name| json!({ "name": name }))
+                .collect(),
+        )),
+        QmpCommand::QueryStatus => match send_request(tube, &VmRequest::GetRunMode)? {
+            VmResponse::RunMode(mode) => Ok(run_mode_status(mode)),
+            response => Err(QmpError::generic(response.to_string())),
+        },
+        QmpCommand::Request(request) => match send_request(tube, &request)? {
+            VmResponse::Ok => Ok(json!({})),
+            #[cfg(feature = "pci-hotplug")]
+            VmResponse::PciHotPlugResponse { .. } => Ok(json!({})),
+            response => Err(QmpError::generic(response.to_string())),
+        },
+    }
+}
+
+fn write_message(stream: &mut UnixStream, message: &Value) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *stream, message)?;
+    stream.write_all(b"\r\n")
+}
+
+fn serve_client(mut stream: UnixStream, tube: &Tube) -> anyhow::Result<()> {
+    write_message(
+        &mut stream,
+        &json!({
+            "QMP": {
+                "version": {
+                    "qemu": { "major": 0, "minor": 0, "micro": 0 },
+                    "package": format!("crosvm {}", env!("CARGO_PKG_VERSION")),
+                },
+                "capabilities": [],
+            }
+        }),
+    )?;
+
+    let mut negotiated = false;
+    let reader = stream.try_clone().context("failed to clone QMP stream")?;
+    for message in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        let message = message.context("failed to parse QMP message")?;
+        let id = message.get("id").cloned();
+        let result = QmpRequest::deserialize(&message)
+            .map_err(|e| QmpError::generic(format!("invalid QMP request: {}", e)))
+            .and_then(|request| {
+                let command = parse_command(&request.execute, &request.arguments)?;
+                match command {
+                    QmpCommand::Capabilities if negotiated => Err(QmpError {
+                        class: "CommandNotFound",
+                        desc: "Capabilities negotiation is already complete".to_owned(),
+                    }),
+                    QmpCommand::Capabilities => {
+                        negotiated = true;
+                        Ok(json!({}))
+                    }
+                    _ if !negotiated => Err(QmpError {
+                        class: "CommandNotFound",
+                        desc: "Expecting capabilities negotiation with 'qmp_capabilities'"
+                            .to_owned(),
+                    }),
+                    command => execute_command(tube, command),
+                }
+            });
+        let mut response = match result {
+            Ok(value) => json!({ "return": value }),
+            Err(e) => json!({ "error": { "class": e.class, "desc": e.desc } }),
+        };
+        if let Some(id) = id {
+            response["id"] = id;
+        }
+        write_message(&mut stream, &response)?;
+    }
+    Ok(())
+}
+
+/// Starts a thread which serves QMP clients connecting to `listener`.
+///
+/// The commands are sent to the main control loop through `tube`.
+pub(crate) fn start_qmp_server(listener: &UnlinkUnixListener, tube: Tube) -> anyhow::Result<()> {
+    let listener = listener
+        .try_clone()
+        .context("failed to clone QMP listener")?;
+    thread::Builder::new()
+        .name("qmp_server".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        info!("QMP client connected");
+                        if let Err(e) = serve_client(stream, &tube) {
+                            error!("QMP client error: {:#}", e);
+                        }
+                    }
+                    Err(e) => error!("failed to accept QMP client: {}", e),
+                }
+            }
+        })
+        .context("failed to spawn QMP server thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_basic_commands() {
+        let none = Value::Null;
+        assert!(matches!(
+            parse_command("qmp_capabilities", &none),
+            Ok(QmpCommand::Capabilities)
+        ));
+        assert!(matches!(
+            parse_command("query-status", &none),
+            Ok(QmpCommand::QueryStatus)
+        ));
+        assert!(matches!(
+            parse_command("system_powerdown", &none),
+            Ok(QmpCommand::Request(VmRequest::Powerbtn))
+        ));
+        assert!(matches!(
+            parse_command("stop", &none),
+            Ok(QmpCommand::Request(VmRequest::SuspendVm))
+        ));
+        assert_eq!(
+            parse_command("human-monitor-command", &none).err(),
+            Some(QmpError::command_not_found("human-monitor-command"))
+        );
+    }
+
+    #[test]
+    fn parse_snapshot() {
+        let arguments = json!({ "path": "/tmp/snapshot" });
+        match parse_command("__dev.crosvm_snapshot", &arguments) {
+            Ok(QmpCommand::Request(VmRequest::Snapshot(SnapshotCommand::Take {
+                snapshot_path,
+                ..
+            }))) => assert_eq!(snapshot_path, PathBuf::from("/tmp/snapshot")),
+            _ => panic!("unexpected command"),
+        }
+        assert!(parse_command("__dev.crosvm_snapshot", &json!({})).is_err());
+        // crosvm has no block layer overlays to snapshot.
+        let arguments = json!({ "node": "disk0", "overlay": "/tmp/snapshot" });
+        assert_eq!(
+            parse_command("blockdev-snapshot", &arguments).err(),
+            Some(QmpError::command_not_found("blockdev-snapshot"))
+        );
+    }
+
+    #[test]
+    fn parse_device_add_vfio() {
+        let arguments = json!({
+            "driver": "vfio-pci",
+            "id": "hostdev0",
+            "sysfsdev": "/sys/bus/pci/devices/0000:01:00.0",
+        });
+        match parse_command("device_add", &arguments) {
+            Ok(QmpCommand::Request(VmRequest::HotPlugVfioCommand { device, add })) => {
+                assert!(add);
+                assert_eq!(
+                    device.path,
+                    PathBuf::from("/sys/bus/pci/devices/0000:01:00.0")
+                );
+            }
+            _ => panic!("unexpected command"),
+        }
+        assert!(parse_command("device_add", &json!({ "driver": "vfio-pci" })).is_err());
+        assert!(parse_command("device_add", &json!({ "driver": "e1000" })).is_err());
+    }
+
+    #[test]
+    fn status_of_run_mode() {
+        assert_eq!(
+            run_mode_status(VmRunMode::Running),
+            json!({ "status": "running", "singlestep": false, "running": true })
+        );
+        assert_eq!(run_mode_status(VmRunMode::Suspending)["status"], "paused");
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Issues vmm-swap commands on behalf of a `--swap-policy`.
+
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use base::error;
+use base::info;
+use base::Tube;
+use base::TubeError;
+use swap::policy::read_memory_pressure;
+use swap::policy::PolicyAction;
+use swap::policy::PolicyEngine;
+use swap::policy::SwapPolicy;
+use vm_control::SwapCommand;
+use vm_control::VmRequest;
+use vm_control::VmResponse;
+
+/// Total CPU time spent by the vCPU threads, read from their schedstat.
+fn vcpu_cpu_time(vcpu_tids: &[u32]) -> Duration {
+    vcpu_tids
+        .iter()
+        .filter_map(|tid| {
+            let schedstat =
+                std::fs::read_to_string(format!("/proc/self/task/{}/schedstat", tid)).ok()?;
+            schedstat.split_whitespace().next()?.parse().ok()
+        })
+        .map(Duration::from_nanos)
+        .sum()
+}
+
+fn send_request(tube: &Tube, request: VmRequest) -> base::TubeResult<VmResponse> {
+    tube.send(&request)?;
+    tube.recv()
+}
+
+fn run_swap_policy(policy: SwapPolicy, vcpu_tids: Vec<u32>, tube: Tube) -> base::TubeResult<()> {
+    let interval = policy.interval();
+    let mut engine = PolicyEngine::new(policy);
+    let mut last_sample = Instant::now();
+    let mut last_cpu_time = vcpu_cpu_time(&vcpu_tids);
+    loop {
+        thread::sleep(interval);
+
+        let now = Instant::now();
+        let cpu_time = vcpu_cpu_time(&vcpu_tids);
+        let vcpu_usage = 100.0 * cpu_time.saturating_sub(last_cpu_time).as_secs_f64()
+            / now.duration_since(last_sample).as_secs_f64()
+            / vcpu_tids.len().max(1) as f64;
+        last_sample = now;
+        last_cpu_time = cpu_time;
+
+        let pressure = match read_memory_pressure() {
+            Ok(pressure) => pressure,
+            Err(e) => {
+                error!("swap policy: {:#}", e);
+                continue;
+            }
+        };
+        let state = match send_request(&tube, VmRequest::Swap(SwapCommand::Status))? {
+            VmResponse::SwapStatus(status) => status.state,
+            response => {
+                error!("swap policy: unexpected status response: {}", response);
+                continue;
+            }
+        };
+        let Some(action) = engine.next_action(now, state, pressure, vcpu_usage) else {
+            continue;
+        };
+        info!(
+            "swap policy: {:?} (pressure: {:.2}%, vcpu usage: {:.2}%)",
+            action, pressure, vcpu_usage
+        );
+        let command = match action {
+            PolicyAction::Enable => SwapCommand::Enable,
+            PolicyAction::Trim => SwapCommand::Trim,
+            PolicyAction::SwapOut => SwapCommand::SwapOut,
+            PolicyAction::Disable => SwapCommand::Disable {
+                slow_file_cleanup: true,
+            },
+        };
+        match send_request(&tube, VmRequest::Swap(command))? {
+            VmResponse::Ok => {}
+            response => error!("swap policy: {:?} failed: {}", action, response),
+        }
+    }
+}
+
+/// Starts a thread which drives vmm-swap according to `policy`.
+///
+/// The commands are sent to the main control loop through `tube`. The thread exits when the
+/// other end of `tube` is closed.
+pub(crate) fn start_swap_policy_thread(
+    policy: SwapPolicy,
+    vcpu_tids: Vec<u32>,
+    tube: Tube,
+) -> Result<()> {
+    thread::Builder::new()
+        .name("swap_policy".to_owned())
+        .spawn(move || match run_swap_policy(policy, vcpu_tids, tube) {
+            Ok(()) | Err(TubeError::Disconnected) => {}
+            Err(e) => error!("swap policy stopped: {}", e),
+        })
+        .context("failed to spawn swap policy thread")?;
+    Ok(())
+}
@@ -13,6 +13,7 @@ use std::thread;
 use std::thread::JoinHandle;
 #[cfg(target_arch = "x86_64")]
 use std::time::Duration;
+use std::time::Instant;
 
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use aarch64::AArch64 as Arch;
@@ -40,11 +41,12 @@ use hypervisor::VcpuExit;
 use hypervisor::VcpuSignalHandle;
 use libc::c_int;
 use metrics_events::MetricEventType;
+use metrics_events::VcpuExitReason;
 #[cfg(target_arch = "riscv64")]
 use riscv64::Riscv64 as Arch;
 use serde::Deserialize;
 use serde::Serialize;
-#[cfg(target_arch = "x86_64")]
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
 use sync::Mutex;
 use vm_control::*;
 #[cfg(feature = "gdb")]
@@ -52,6 +54,7 @@ use vm_memory::GuestMemory;
 #[cfg(target_arch = "x86_64")]
 use x86_64::X8664arch as Arch;
 
+use super::core_dump;
 use super::ExitState;
 #[cfg(target_arch = "x86_64")]
 use crate::crosvm::ratelimit::Ratelimit;
@@ -60,9 +63,9 @@ use crate::crosvm::ratelimit::Ratelimit;
 const SCHED_FLAG_RESET_ON_FORK: u64 = 0x1;
 const SCHED_FLAG_KEEP_POLICY: u64 = 0x08;
 const SCHED_FLAG_KEEP_PARAMS: u64 = 0x10;
-const SCHED_FLAG_UTIL_CLAMP_MIN: u64 = 0x20;
-const SCHED_SCALE_CAPACITY: u32 = 1024;
-const SCHED_FLAG_KEEP_ALL: u64 = SCHED_FLAG_KEEP_POLICY | SCHED_FLAG_KEEP_PARAMS;
+pub(super) const SCHED_FLAG_UTIL_CLAMP_MIN: u64 = 0x20;
+pub(super) const SCHED_SCALE_CAPACITY: u32 = 1024;
+pub(super) const SCHED_FLAG_KEEP_ALL: u64 = SCHED_FLAG_KEEP_POLICY | SCHED_FLAG_KEEP_PARAMS;
 
 /// Set the VCPU thread affinity and other per-thread scheduler properties.
 /// This function will be called from each VCPU thread at startup.
@@ -218,6 +221,76 @@ pub fn remove_vcpu_signal_handler() -> Result<()> {
     clear_signal_handler(SIGRTMIN() + 0).context("error unregistering signal handler")
 }
 
+/// Categorizes a vCPU exit for its exit statistics.
+fn vcpu_exit_reason(exit: &VcpuExit) -> VcpuExitReason {
+    match exit {
+        VcpuExit::Io => VcpuExitReason::Io,
+        VcpuExit::Mmio => VcpuExitReason::Mmio,
+        VcpuExit::IoapicEoi { .. } => VcpuExitReason::IoapicEoi,
+        VcpuExit::Hlt => VcpuExitReason::Hlt,
+        VcpuExit::IrqWindowOpen => VcpuExitReason::IrqWindowOpen,
+        VcpuExit::Hypercall | VcpuExit::Sbi { .. } => VcpuExitReason::Hypercall,
+        VcpuExit::Exception => VcpuExitReason::Exception,
+        VcpuExit::Debug | VcpuExit::Watchpoint(_) => VcpuExitReason::Debug,
+        VcpuExit::MsrAccess => VcpuExitReason::MsrAccess,
+        #[cfg(target_arch = "x86_64")]
+        VcpuExit::Cpuid { .. } => VcpuExitReason::Cpuid,
+        VcpuExit::Intr => VcpuExitReason::Intr,
+        VcpuExit::SystemEventShutdown
+        | VcpuExit::SystemEventReset
+        | VcpuExit::SystemEventCrash
+        | VcpuExit::SystemEventSuspend => VcpuExitReason::SystemEvent,
+        _ => VcpuExitReason::Other,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn resume_from_s3(
+    vcpu: &dyn hypervisor::VcpuX86_64,
+    irq_chip: &mut dyn IrqChipArch,
+    cpu_id: usize,
+    waking_vector: u32,
+) -> Result<()> {
+    if cpu_id != 0 {
+        return irq_chip
+            .set_mp_state(cpu_id, &hypervisor::MPState::Uninitialized)
+            .context("failed to set mp state");
+    }
+    let (regs, sregs) = x86_64::regs::s3_resume_regs(waking_vector);
+    vcpu.set_sregs(&sregs).context("failed to set sregs")?;
+    vcpu.set_regs(&regs).context("failed to set regs")?;
+    irq_chip
+        .set_mp_state(cpu_id, &hypervisor::MPState::Runnable)
+        .context("failed to set mp state")
+}
+
+/// Handles a PSCI SYSTEM_SUSPEND call by asking the main loop to suspend the VM. Returns the entry
+/// point and context ID to resume the vCPU with.
+#[cfg(target_arch = "aarch64")]
+fn system_suspend(
+    vcpu: &dyn hypervisor::VcpuAArch64,
+    suspend_tube: &Mutex<SendTube>,
+) -> Result<(u64, u64)> {
+    // PSCI return code of a call that failed for an unspecified reason.
+    const PSCI_RET_INTERNAL_FAILURE: i64 = -6;
+
+    let entry = vcpu
+        .get_one_reg(hypervisor::VcpuRegAArch64::X(1))
+        .context("failed to read the entry point")?;
+    let context_id = vcpu
+        .get_one_reg(hypervisor::VcpuRegAArch64::X(2))
+        .context("failed to read the context ID")?;
+    if let Err(e) = suspend_tube.lock().send(&true) {
+        vcpu.set_one_reg(
+            hypervisor::VcpuRegAArch64::X(0),
+            PSCI_RET_INTERNAL_FAILURE as u64,
+        )
+        .context("failed to set the return value")?;
+        return Err(e).context("failed to request the VM suspend");
+    }
+    Ok((entry, context_id))
+}
+
 fn vcpu_loop<V>(
     mut run_mode: VmRunMode,
     cpu_id: usize,
@@ -231,22 +304,37 @@ fn vcpu_loop<V>(
     #[cfg(feature = "gdb")] to_gdb_tube: Option<mpsc::Sender<VcpuDebugStatusMessage>>,
     #[cfg(feature = "gdb")] guest_mem: GuestMemory,
     #[cfg(target_arch = "x86_64")] bus_lock_ratelimit_ctrl: Arc<Mutex<Ratelimit>>,
+    #[cfg(target_arch = "aarch64")] suspend_tube: Arc<Mutex<SendTube>>,
+    #[cfg(target_arch = "aarch64")] parked: bool,
 ) -> ExitState
 where
     V: VcpuArch,
 {
     let mut interrupted_by_signal = false;
+    let mut exit_stats = VcpuExitStats::new(cpu_id);
+    // Where the vCPU resumes after a PSCI SYSTEM_SUSPEND call, and with which context ID.
+    #[cfg(target_arch = "aarch64")]
+    let mut system_resume_point = None;
+    // A vCPU parked at boot doesn't enter the guest until it is onlined, even if the guest tries to
+    // bring it up.
+    #[cfg(target_arch = "aarch64")]
+    let mut parked = parked;
+    #[cfg(not(target_arch = "aarch64"))]
+    let parked = false;
+    // Resuming from S3 resets the multiprocessing state of the vCPU.
+    #[cfg(target_arch = "x86_64")]
+    let mut irq_chip = irq_chip;
 
     loop {
         // Start by checking for messages to process and the run state of the CPU.
         // An extra check here for Running so there isn't a need to call recv unless a
         // message is likely to be ready because a signal was sent.
-        if interrupted_by_signal || run_mode != VmRunMode::Running {
+        if interrupted_by_signal || run_mode != VmRunMode::Running || parked {
             'state_loop: loop {
                 // Tries to get a pending message without blocking first.
                 let msg = match from_main_tube.try_recv() {
                     Ok(m) => m,
-                    Err(mpsc::TryRecvError::Empty) if run_mode == VmRunMode::Running => {
+                    Err(mpsc::TryRecvError::Empty) if run_mode == VmRunMode::Running && !parked => {
                         // If the VM is running and no message is pending, the state won't
                         // change.
                         break 'state_loop;
@@ -276,7 +364,19 @@ where
                         VcpuControl::RunState(new_mode) => {
                             run_mode = new_mode;
                             match run_mode {
-                                VmRunMode::Running => {}
+                                VmRunMode::Running => {
+                                    #[cfg(target_arch = "aarch64")]
+                                    if let Some((entry, context_id)) = system_resume_point.take() {
+                                        if let Err(e) = aarch64::resume_from_system_suspend(
+                                            &vcpu, entry, context_id,
+                                        ) {
+                                            error!(
+                                                "failed to resume vcpu {} from system suspend: {}",
+                                                cpu_id, e
+                                            );
+                                        }
+                                    }
+                                }
                                 VmRunMode::Suspending => {
                                     if let Err(e) = vcpu.on_suspend() {
                                         error!(
@@ -343,10 +443,42 @@ where
                                     )
                                 })
                                 .with_context(|| format!("Failed to restore Vcpu #{}", vcpu.id()));
+                            // The guest may have brought the vCPU up before the snapshot, and
+                            // the restored state keeps it powered off otherwise.
+                            #[cfg(target_arch = "aarch64")]
+                            if resp.is_ok() {
+                                parked = false;
+                            }
                             if let Err(e) = req.result_sender.send(resp) {
                                 error!("Failed to send restore response: {}", e);
                             }
                         }
+                        VcpuControl::GetCoreNote(response_chan) => {
+                            let note = core_dump::vcpu_prstatus(&vcpu, cpu_id);
+                            if let Err(e) = response_chan.send((cpu_id, note)) {
+                                error!("Failed to send core note: {}", e);
+                            }
+                        }
+                        #[cfg(target_arch = "x86_64")]
+                        VcpuControl::ResumeFromS3 { waking_vector } => {
+                            if let Err(e) =
+                                resume_from_s3(&vcpu, irq_chip.as_mut(), cpu_id, waking_vector)
+                            {
+                                error!("failed to resume vcpu {} from S3: {:#}", cpu_id, e);
+                            }
+                        }
+                        #[cfg(target_arch = "aarch64")]
+                        VcpuControl::Online(response_chan) => {
+                            if let Err(e) = response_chan.send(parked) {
+                                error!("Failed to send online response: {}", e);
+                            }
+                            parked = false;
+                        }
+                        VcpuControl::GetExitStats(response_chan) => {
+                            if let Err(e) = response_chan.send(exit_stats.clone()) {
+                                error!("Failed to send exit stats: {}", e);
+                            }
+                        }
                         VcpuControl::Throttle(target_us) => {
                             let start_time = std::time::Instant::now();
 
@@ -359,7 +491,7 @@ where
                         }
                     }
                 }
-                if run_mode == VmRunMode::Running {
+                if run_mode == VmRunMode::Running && !parked {
                     break 'state_loop;
                 }
             }
@@ -383,7 +515,11 @@ where
         }
 
         if !interrupted_by_signal {
-            match vcpu.run() {
+            let exit = vcpu.run();
+            let exit_start = Instant::now();
+            let exit_reason = exit.as_ref().ok().map(vcpu_exit_reason);
+            let _trace = cros_tracing::trace_event!(vcpu, "vcpu exit", exit_reason);
+            match exit {
                 Ok(VcpuExit::Io) => {
                     if let Err(e) =
                         vcpu.handle_io(&mut |IoParams { address, operation }| match operation {
@@ -451,6 +587,16 @@ where
                     info!("system crash event on vcpu {}", cpu_id);
                     return ExitState::Stop;
                 }
+                #[cfg(target_arch = "aarch64")]
+                Ok(VcpuExit::SystemEventSuspend) => match system_suspend(&vcpu, &suspend_tube) {
+                    Ok(resume_point) => {
+                        info!("system suspend event on vcpu {}", cpu_id);
+                        system_resume_point = Some(resume_point);
+                        // Don't run the guest until the main loop resumes the VM.
+                        run_mode = VmRunMode::Suspending;
+                    }
+                    Err(e) => error!("failed to suspend the VM from vcpu {}: {:#}", cpu_id, e),
+                },
                 Ok(VcpuExit::Debug) => {
                     #[cfg(feature = "gdb")]
                     if let Err(e) =
@@ -462,6 +608,19 @@ where
 
                     run_mode = VmRunMode::Breakpoint;
                 }
+                Ok(VcpuExit::Watchpoint(hit)) => {
+                    #[cfg(feature = "gdb")]
+                    if let Err(e) =
+                        crate::crosvm::gdb::vcpu_exit_watchpoint(cpu_id, to_gdb_tube.as_ref(), hit)
+                    {
+                        error!("Failed to handle VcpuExit::Watchpoint: {:#}", e);
+                        return ExitState::Crash;
+                    }
+                    #[cfg(not(feature = "gdb"))]
+                    let _ = hit;
+
+                    run_mode = VmRunMode::Breakpoint;
+                }
                 #[cfg(target_arch = "x86_64")]
                 Ok(VcpuExit::BusLock) => {
                     let delay_ns: u64 = bus_lock_ratelimit_ctrl.lock().ratelimit_calculate_delay(1);
@@ -498,6 +657,9 @@ where
                     }
                 },
             }
+            if let Some(reason) = exit_reason {
+                exit_stats.record(reason, exit_start.elapsed());
+            }
         }
 
         if interrupted_by_signal {
@@ -539,6 +701,8 @@ pub fn run_vcpu<V>(
     cpu_config: Option<CpuConfigArch>,
     vcpu_cgroup_tasks_file: Option<File>,
     #[cfg(target_arch = "x86_64")] bus_lock_ratelimit_ctrl: Arc<Mutex<Ratelimit>>,
+    #[cfg(target_arch = "aarch64")] suspend_tube: Arc<Mutex<SendTube>>,
+    #[cfg(target_arch = "aarch64")] parked: bool,
     run_mode: VmRunMode,
     boost_uclamp: bool,
     vcpu_pid_tid_tube: mpsc::Sender<VcpuPidTid>,
@@ -598,6 +762,12 @@ where
                     }
                 };
 
+                #[cfg(feature = "gdb")]
+                if let Err(e) = crate::crosvm::gdb::vcpu_init_debug(&vcpu, to_gdb_tube.as_ref()) {
+                    error!("failed to start vcpu {}: {:#}", cpu_id, e);
+                    return ExitState::Stop;
+                }
+
                 set_vcpu_thread_local(Some(&vcpu), SIGRTMIN() + 0);
 
                 mmio_bus.set_access_id(cpu_id);
@@ -619,6 +789,10 @@ where
                     guest_mem,
                     #[cfg(target_arch = "x86_64")]
                     bus_lock_ratelimit_ctrl,
+                    #[cfg(target_arch = "aarch64")]
+                    suspend_tube,
+                    #[cfg(target_arch = "aarch64")]
+                    parked,
                 );
 
                 // We don't want any more VCPU signals from now until the thread exits.
@@ -0,0 +1,169 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Temporarily raises the utilization clamp of the vCPU threads when crosvm delivers an interrupt
+//! to the guest, so that a vCPU woken up under host contention is placed on a fast core right
+//! away, and decays the boost once interrupts stop arriving.
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use base::error;
+use base::sched_attr;
+use base::sched_setattr;
+use base::warn;
+use base::Event;
+use base::EventToken;
+use base::Timer;
+use base::TimerTrait;
+use base::WaitContext;
+use base::WorkerThread;
+use sync::Mutex;
+
+use super::config::VcpuBoostOptions;
+use super::vcpu::SCHED_FLAG_KEEP_ALL;
+use super::vcpu::SCHED_FLAG_UTIL_CLAMP_MIN;
+
+/// Boosts below this utilization are not worth a `sched_setattr` per vCPU; the clamp is dropped
+/// to zero instead.
+const MIN_BOOST_UTIL: u32 = 64;
+
+struct BoostState {
+    vcpu_tids: Vec<u32>,
+    // Utilization clamp currently applied to the vCPU threads.
+    util: u32,
+    // When the current boost level starts decaying.
+    deadline: Instant,
+}
+
+/// Shared between the threads delivering interrupts, which call `boost`, and the decay thread.
+pub struct VcpuBoost {
+    util: u32,
+    decay_period: Duration,
+    state: Mutex<BoostState>,
+    // Signaled when the vCPUs go from a decayed to a fully boosted state.
+    boost_evt: Event,
+}
+
+fn set_util_min(vcpu_tids: &[u32], util: u32) {
+    for tid in vcpu_tids {
+        let mut attr = sched_attr {
+            sched_flags: SCHED_FLAG_KEEP_ALL | SCHED_FLAG_UTIL_CLAMP_MIN,
+            sched_util_min: util,
+            ..Default::default()
+        };
+        if let Err(e) = sched_setattr(*tid as base::Pid, &mut attr, 0) {
+            warn!(
+                "failed to set utilization clamp of vcpu thread {}: {}",
+                tid, e
+            );
+        }
+    }
+}
+
+impl VcpuBoost {
+    pub fn new(options: &VcpuBoostOptions, vcpu_tids: Vec<u32>) -> Result<VcpuBoost> {
+        Ok(VcpuBoost {
+            util: options.util,
+            decay_period: Duration::from_millis(options.decay_ms),
+            state: Mutex::new(BoostState {
+                vcpu_tids,
+                util: 0,
+                deadline: Instant::now(),
+            }),
+            boost_evt: Event::new().context("failed to create vcpu boost event")?,
+        })
+    }
+
+    /// Raises the vCPU threads to the full boost, or extends the current one.
+    ///
+    /// Only the first interrupt of a burst issues syscalls; later ones just push the deadline.
+    pub fn boost(&self) {
+        let mut state = self.state.lock();
+        state.deadline = Instant::now() + self.decay_period;
+        if state.util < self.util {
+            state.util = self.util;
+            set_util_min(&state.vcpu_tids, self.util);
+            if let Err(e) = self.boost_evt.signal() {
+                error!("failed to signal vcpu boost: {}", e);
+            }
+        }
+    }
+
+    /// Halves the boost once its deadline has passed. Returns when to check again, or `None` if
+    /// the boost is fully decayed.
+    fn decay(&self) -> Option<Duration> {
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        if now < state.deadline {
+            return Some(state.deadline - now);
+        }
+
+        let util = state.util / 2;
+        state.util = if util < MIN_BOOST_UTIL { 0 } else { util };
+        set_util_min(&state.vcpu_tids, state.util);
+        if state.util == 0 {
+            return None;
+        }
+        state.deadline = now + self.decay_period;
+        Some(self.decay_period)
+    }
+}
+
+#[derive(EventToken)]
+enum Token {
+    Boost,
+    Decay,
+    Kill,
+}
+
+fn run_decay(boost: &VcpuBoost, kill_evt: Event) -> Result<()> {
+    let mut timer = Timer::new().context("failed to create vcpu boost timer")?;
+    let wait_ctx = WaitContext::build_with(&[
+        (&boost.boost_evt, Token::Boost),
+        (&timer, Token::Decay),
+        (&kill_evt, Token::Kill),
+    ])
+    .context("failed to create vcpu boost wait context")?;
+
+    loop {
+        let events = wait_ctx
+            .wait()
+            .context("failed to wait for vcpu boost events")?;
+        for event in events.iter().filter(|e| e.is_readable) {
+            let next = match event.token {
+                Token::Boost => {
+                    boost
+                        .boost_evt
+                        .wait()
+                        .context("failed to read boost event")?;
+                    Some(boost.decay_period)
+                }
+                Token::Decay => {
+                    timer.mark_waited().context("failed to read boost timer")?;
+                    boost.decay()
+                }
+                Token::Kill => return Ok(()),
+            };
+            match next {
+                Some(dur) => timer.reset_oneshot(dur),
+                None => timer.clear(),
+            }
+            .context("failed to arm vcpu boost timer")?;
+        }
+    }
+}
+
+/// Starts the thread decaying the boosts requested through `boost`. The thread stops when the
+/// returned `WorkerThread` is dropped.
+pub(crate) fn start_vcpu_boost_thread(boost: Arc<VcpuBoost>) -> WorkerThread<()> {
+    WorkerThread::start("vcpu_boost", move |kill_evt| {
+        if let Err(e) = run_decay(&boost, kill_evt) {
+            error!("vcpu boost stopped: {:#}", e);
+        }
+    })
+}
@@ -41,6 +41,7 @@ use base::DuplicateHandleRequest;
 use base::DuplicateHandleResponse;
 use base::Event;
 use base::EventToken;
+use base::FileSerdeWrapper;
 use base::FramingMode;
 use base::RawDescriptor;
 use base::ReadNotifier;
@@ -193,6 +194,10 @@ fn process_policy(process_type: ProcessType, cfg: &Config) -> sandbox::policy::P
         policy.dll_blocklist.push(dll.to_string());
     }
 
+    if uses_app_container(process_type, cfg) {
+        policy.app_container_profile = Some(format!("crosvm.{:?}", process_type).to_lowercase());
+    }
+
     #[cfg(feature = "asan")]
     adjust_asan_policy(&mut policy);
     #[cfg(feature = "cperfetto")]
@@ -200,6 +205,14 @@ fn process_policy(process_type: ProcessType, cfg: &Config) -> sandbox::policy::P
     policy
 }
 
+/// Returns whether processes of type `process_type` run in an AppContainer. Only device processes
+/// that get all their handles from the broker support it.
+fn uses_app_container(process_type: ProcessType, cfg: &Config) -> bool {
+    cfg.app_container
+        && cfg.jail_config.is_some()
+        && matches!(process_type, ProcessType::Block | ProcessType::Net)
+}
+
 /// Dynamically appends rules to the main process's policy.
 #[cfg(feature = "sandbox")]
 fn main_process_policy(cfg: &Config) -> sandbox::policy::Policy {
@@ -1234,6 +1247,18 @@ fn start_up_block_backends(
 
         block_child.bootstrap_tube.send(&disk_option).unwrap();
 
+        // A block process in an AppContainer cannot open the disk image, so it is opened here and
+        // the handle passed along.
+        let raw_image = if uses_app_container(ProcessType::Block, cfg) {
+            Some(FileSerdeWrapper(disk_option.open_raw().exit_context(
+                Exit::OpenDiskImage,
+                "failed to open disk image",
+            )?))
+        } else {
+            None
+        };
+        block_child.bootstrap_tube.send(&raw_image).unwrap();
+
         let exit_event = Event::new().exit_context(Exit::CreateEvent, "failed to create event")?;
         block_child.bootstrap_tube.send(&exit_event).unwrap();
         exit_events.push(exit_event);
@@ -1367,6 +1392,12 @@ where
             .exit_context(Exit::SandboxError, "sandbox operation failed")?;
     }
 
+    if let Some(profile) = process_policy.app_container_profile.as_ref() {
+        policy
+            .add_app_container_profile(profile, /* create_profile= */ true)
+            .exit_context(Exit::SandboxError, "sandbox operation failed")?;
+    }
+
     // spawn_target uses CreateProcessW to create a new process, which will pass
     // the command line arguments verbatim to the new process. Most processes
     // expect that argv[0] will be the program name, so provide that before the
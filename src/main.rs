@@ -57,6 +57,12 @@ use crosvm::cmdline::CrossPlatformDevicesCommands;
 use sys::windows::setup_metrics_reporting;
 #[cfg(feature = "composite-disk")]
 use uuid::Uuid;
+#[cfg(feature = "pci-hotplug")]
+use vm_control::client::do_block_add;
+#[cfg(feature = "pci-hotplug")]
+use vm_control::client::do_block_remove;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_configure_display;
 #[cfg(feature = "gpu")]
 use vm_control::client::do_gpu_display_add;
 #[cfg(feature = "gpu")]
@@ -64,36 +70,79 @@ use vm_control::client::do_gpu_display_list;
 #[cfg(feature = "gpu")]
 use vm_control::client::do_gpu_display_remove;
 #[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_dump_resources;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_fence_stats;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_invalidate_backing;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_kill_context;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_set_display_edid;
+#[cfg(feature = "gpu")]
 use vm_control::client::do_gpu_set_display_mouse_mode;
+use vm_control::client::do_irq_latency_stats;
+use vm_control::client::do_ksm_stats;
 use vm_control::client::do_modify_battery;
 #[cfg(feature = "pci-hotplug")]
 use vm_control::client::do_net_add;
 #[cfg(feature = "pci-hotplug")]
 use vm_control::client::do_net_remove;
+use vm_control::client::do_numa_binding_stats;
+use vm_control::client::do_powerdown;
+use vm_control::client::do_query_devices;
+use vm_control::client::do_query_pstore;
+#[cfg(feature = "config-file")]
+use vm_control::client::do_reload_config;
+use vm_control::client::do_rtc;
 use vm_control::client::do_security_key_attach;
+use vm_control::client::do_shared_memory_stats;
 #[cfg(feature = "audio")]
 use vm_control::client::do_snd_mute_all;
 use vm_control::client::do_swap_status;
+use vm_control::client::do_tracing;
 use vm_control::client::do_usb_attach;
 use vm_control::client::do_usb_detach;
+use vm_control::client::do_usb_hid_attach;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use vm_control::client::do_usb_hid_attach_socket;
+use vm_control::client::do_usb_hid_report;
 use vm_control::client::do_usb_list;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use vm_control::client::do_usbip_attach;
+use vm_control::client::do_vcpu_stats;
 #[cfg(feature = "balloon")]
 use vm_control::client::handle_request;
 use vm_control::client::vms_request;
 #[cfg(feature = "gpu")]
 use vm_control::client::ModifyGpuResult;
 use vm_control::client::ModifyUsbResult;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::DisplayConfig;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::GpuControlResult;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::ModifyGpuError;
 #[cfg(feature = "balloon")]
 use vm_control::BalloonControlCommand;
+use vm_control::ConsoleControlCommand;
 use vm_control::DiskControlCommand;
+use vm_control::FsControlCommand;
 use vm_control::HotPlugDeviceInfo;
 use vm_control::HotPlugDeviceType;
+use vm_control::MigrateCommand;
+#[cfg(feature = "net")]
+use vm_control::NetConfigCommand;
+use vm_control::RtcCommand;
 use vm_control::SnapshotCommand;
 use vm_control::SwapCommand;
+use vm_control::TracingCommand;
 use vm_control::UsbControlResult;
 use vm_control::VmRequest;
 #[cfg(feature = "balloon")]
 use vm_control::VmResponse;
+#[cfg(feature = "gpu")]
+use vm_memory::GuestAddress;
 
 use crate::sys::error_to_exit_code;
 use crate::sys::init_log;
@@ -185,6 +234,13 @@ fn run_vm(cmd: RunCommand, log_config: LogConfig) -> Result<CommandStatus> {
     if let Some(async_executor) = cfg.async_executor {
         cros_async::Executor::set_default_executor_kind(async_executor)
             .context("Failed to set the default async executor")?;
+    } else {
+        // Probe for io_uring support before sandboxed device processes are forked, so they inherit
+        // the choice instead of probing on their own.
+        info!(
+            "Using the {:?} async executor",
+            cros_async::ExecutorKind::default()
+        );
     }
 
     let exit_state = crate::sys::run_config(cfg)?;
@@ -224,6 +280,74 @@ fn swap_vms(cmd: cmdline::SwapCommand) -> std::result::Result<(), ()> {
     }
 }
 
+fn trace_vm(cmd: cmdline::TraceCommand) -> std::result::Result<(), ()> {
+    use cmdline::TraceSubcommands::*;
+    let split = |categories: &str| categories.split(',').map(str::to_owned).collect();
+    let (command, path) = match cmd.nested {
+        Enable(params) => (
+            TracingCommand::Enable(split(&params.categories)),
+            params.socket_path,
+        ),
+        Disable(params) => (
+            TracingCommand::Disable(split(&params.categories)),
+            params.socket_path,
+        ),
+        Status(params) => (TracingCommand::Status, params.socket_path),
+    };
+    do_tracing(command, path)
+}
+
+fn rtc_vm(cmd: cmdline::RtcCommand) -> std::result::Result<(), ()> {
+    use cmdline::RtcSubcommands::*;
+    match cmd.nested {
+        GetOffset(params) => do_rtc(RtcCommand::GetOffset, params.socket_path),
+        SetOffset(params) => do_rtc(RtcCommand::SetOffset(params.offset), params.socket_path),
+    }
+}
+
+fn params_vm(cmd: cmdline::ParamsCommand) -> std::result::Result<(), ()> {
+    use cmdline::ParamsSubcommands::*;
+    let (params, socket_path) = match cmd.nested {
+        SetNextBoot(params) => (Some(params.params), params.socket_path),
+        ClearNextBoot(params) => (None, params.socket_path),
+    };
+    vms_request(&VmRequest::NextBootParams(params), socket_path)
+}
+
+#[cfg(feature = "config-file")]
+fn reload_config(cmd: cmdline::ReloadConfigCommand) -> std::result::Result<(), ()> {
+    let cfg = cmdline::load_config(&cmd.config_path)
+        .map_err(|e| error!("invalid configuration {}: {}", cmd.config_path.display(), e))?;
+    let config = serde_json::to_string(&cfg)
+        .map_err(|e| error!("failed to serialize the configuration: {}", e))?;
+    do_reload_config(config, cmd.socket_path)
+}
+
+fn query_vm(cmd: cmdline::QueryCommand) -> std::result::Result<(), ()> {
+    use cmdline::QuerySubcommands::*;
+    match cmd.nested {
+        Devices(params) => do_query_devices(params.socket_path),
+        Pstore(params) => do_query_pstore(params.socket_path, params.kind),
+    }
+}
+
+fn stats_vm(cmd: cmdline::StatsCommand) -> std::result::Result<(), ()> {
+    use cmdline::StatsSubcommands::*;
+    match cmd.nested {
+        Vcpu(params) => do_vcpu_stats(params.socket_path),
+        Numa(params) => do_numa_binding_stats(params.socket_path),
+        Ksm(params) => do_ksm_stats(params.socket_path),
+        Irq(params) => do_irq_latency_stats(params.socket_path),
+    }
+}
+
+fn vcpu_vm(cmd: cmdline::VcpuCommand) -> std::result::Result<(), ()> {
+    use cmdline::VcpuSubcommands::*;
+    match cmd.nested {
+        Online(params) => vms_request(&VmRequest::OnlineVcpu(params.index), params.socket_path),
+    }
+}
+
 fn resume_vms(cmd: cmdline::ResumeCommand) -> std::result::Result<(), ()> {
     if cmd.full {
         vms_request(&VmRequest::ResumeVm, cmd.socket_path)
@@ -236,6 +360,10 @@ fn powerbtn_vms(cmd: cmdline::PowerbtnCommand) -> std::result::Result<(), ()> {
     vms_request(&VmRequest::Powerbtn, cmd.socket_path)
 }
 
+fn powerdown_vm(cmd: cmdline::PowerdownCommand) -> std::result::Result<(), ()> {
+    do_powerdown(cmd.timeout, cmd.socket_path)
+}
+
 fn sleepbtn_vms(cmd: cmdline::SleepCommand) -> std::result::Result<(), ()> {
     vms_request(&VmRequest::Sleepbtn, cmd.socket_path)
 }
@@ -277,8 +405,48 @@ fn balloon_stats(cmd: cmdline::BalloonStatsCommand) -> std::result::Result<(), (
     }
 }
 
+/// Subscribes a socket created at `listen_path` to the working set reports of the VM, and prints
+/// them until the VM goes away.
+#[cfg(all(feature = "balloon", any(target_os = "android", target_os = "linux")))]
+fn balloon_ws_watch(socket_path: String, listen_path: &Path) -> std::result::Result<(), ()> {
+    let listener = base::UnixSeqpacketListener::bind(listen_path)
+        .map_err(|e| error!("Failed to listen on {}: {}", listen_path.display(), e))?;
+    let socket_addr = listen_path.to_string_lossy().into_owned();
+    let result = (|| {
+        let command = BalloonControlCommand::WorkingSetSubscribe {
+            socket_addr: socket_addr.clone(),
+        };
+        vms_request(&VmRequest::BalloonCommand(command), &socket_path)?;
+        let tube = listener
+            .accept()
+            .map_err(|e| error!("Failed to accept crosvm: {}", e))
+            .and_then(|socket| {
+                base::Tube::try_from(socket).map_err(|e| error!("Failed to create tube: {}", e))
+            })?;
+        loop {
+            match tube.recv::<vm_control::WorkingSetReport>() {
+                Ok(report) => match serde_json::to_string(&report) {
+                    Ok(report_json) => println!("{report_json}"),
+                    Err(e) => error!("Failed to serialize into JSON: {e}"),
+                },
+                Err(base::TubeError::Disconnected) => return Ok(()),
+                Err(e) => {
+                    error!("Failed to receive working set report: {e}");
+                    return Err(());
+                }
+            }
+        }
+    })();
+    let _ = std::fs::remove_file(listen_path);
+    result
+}
+
 #[cfg(feature = "balloon")]
 fn balloon_ws(cmd: cmdline::BalloonWsCommand) -> std::result::Result<(), ()> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if let Some(listen_path) = &cmd.watch {
+        return balloon_ws_watch(cmd.socket_path, listen_path);
+    }
     let command = BalloonControlCommand::WorkingSet {};
     let request = &VmRequest::BalloonCommand(command);
     let response = handle_request(request, cmd.socket_path)?;
@@ -312,6 +480,7 @@ fn modify_vfio(cmd: cmdline::VfioCrosvmCommand) -> std::result::Result<(), ()> {
                     device_type: HotPlugDeviceType::EndPoint,
                     path: c.vfio_path.clone(),
                     hp_interrupt: true,
+                    p2p: c.p2p,
                 },
                 add: true,
             };
@@ -323,6 +492,7 @@ fn modify_vfio(cmd: cmdline::VfioCrosvmCommand) -> std::result::Result<(), ()> {
                     device_type: HotPlugDeviceType::EndPoint,
                     path: c.vfio_path.clone(),
                     hp_interrupt: false,
+                    p2p: false,
                 },
                 add: false,
             };
@@ -358,6 +528,38 @@ fn modify_virtio_net(cmd: cmdline::VirtioNetCommand) -> std::result::Result<(),
     Ok(())
 }
 
+#[cfg(feature = "pci-hotplug")]
+fn modify_virtio(cmd: cmdline::VirtioCommand) -> std::result::Result<(), ()> {
+    match cmd.command {
+        cmdline::VirtioSubCommand::AddNet(c) => {
+            let bus_num = do_net_add(&c.tap_name, c.socket_path).map_err(|e| {
+                error!("{}", &e);
+            })?;
+            info!("Tap device {} plugged to PCI bus {}", &c.tap_name, bus_num);
+        }
+        cmdline::VirtioSubCommand::AddBlock(c) => {
+            let bus_num = do_block_add(&c.disk, c.socket_path).map_err(|e| {
+                error!("{}", &e);
+            })?;
+            info!("Disk {} plugged to PCI bus {}", &c.disk, bus_num);
+        }
+        cmdline::VirtioSubCommand::RemoveNet(c) => {
+            do_net_remove(c.bus, &c.socket_path).map_err(|e| {
+                error!("Tap device remove failed: {:?}", &e);
+            })?;
+            info!("Tap device removed from PCI bus {}", &c.bus);
+        }
+        cmdline::VirtioSubCommand::RemoveBlock(c) => {
+            do_block_remove(c.bus, &c.socket_path).map_err(|e| {
+                error!("Disk remove failed: {:?}", &e);
+            })?;
+            info!("Disk removed from PCI bus {}", &c.bus);
+        }
+    };
+
+    Ok(())
+}
+
 #[cfg(feature = "composite-disk")]
 fn parse_composite_partition_arg(
     partition_arg: &str,
@@ -581,6 +783,81 @@ fn disk_cmd(cmd: cmdline::DiskCommand) -> std::result::Result<(), ()> {
             };
             vms_request(&request, cmd.socket_path)
         }
+        cmdline::DiskSubcommand::SetReadOnly(cmd) => {
+            let request = VmRequest::DiskCommand {
+                disk_index: cmd.disk_index,
+                command: DiskControlCommand::SetReadOnly {
+                    read_only: cmd.read_only,
+                },
+            };
+            vms_request(&request, cmd.socket_path)
+        }
+    }
+}
+
+fn dump_core(cmd: cmdline::DumpCoreCommand) -> std::result::Result<(), ()> {
+    // The output is opened here so that the core is written with the permissions of the caller.
+    let output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&cmd.output)
+        .map_err(|e| error!("failed to open {}: {}", cmd.output.display(), e))?;
+    vms_request(&VmRequest::DumpCore { output }, cmd.socket_path)
+}
+
+fn console_cmd(cmd: cmdline::ConsoleCommand) -> std::result::Result<(), ()> {
+    match cmd.command {
+        cmdline::ConsoleSubcommand::AddPort(cmd) => {
+            // The path is opened by the VM process, which may have a different working directory.
+            let path = std::fs::canonicalize(&cmd.path).map_err(|e| {
+                error!("invalid console port path {}: {}", cmd.path.display(), e);
+            })?;
+            let request = VmRequest::ConsoleCommand {
+                console_index: cmd.console_index,
+                command: ConsoleControlCommand::AddPort {
+                    name: cmd.name,
+                    path,
+                },
+            };
+            vms_request(&request, cmd.socket_path)
+        }
+        cmdline::ConsoleSubcommand::RemovePort(cmd) => {
+            let request = VmRequest::ConsoleCommand {
+                console_index: cmd.console_index,
+                command: ConsoleControlCommand::RemovePort { name: cmd.name },
+            };
+            vms_request(&request, cmd.socket_path)
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+fn net_cmd(cmd: cmdline::NetCommand) -> std::result::Result<(), ()> {
+    match cmd.command {
+        cmdline::NetSubcommand::SetGuestOffloads(cmd) => {
+            let request = VmRequest::NetCommand {
+                net_index: cmd.net_index,
+                command: NetConfigCommand::SetGuestOffloads {
+                    offloads: cmd.offloads,
+                },
+            };
+            vms_request(&request, cmd.socket_path)
+        }
+    }
+}
+
+fn fs_cmd(cmd: cmdline::FsCommand) -> std::result::Result<(), ()> {
+    match cmd.command {
+        cmdline::FsSubcommand::SetReadOnly(cmd) => {
+            let request = VmRequest::FsCommand {
+                fs_index: cmd.fs_index,
+                command: FsControlCommand::SetReadOnly {
+                    read_only: cmd.read_only,
+                },
+            };
+            vms_request(&request, cmd.socket_path)
+        }
     }
 }
 
@@ -608,6 +885,57 @@ fn gpu_set_display_mouse_mode(cmd: cmdline::GpuSetDisplayMouseModeCommand) -> Mo
     do_gpu_set_display_mouse_mode(cmd.socket_path, cmd.display_id, cmd.mouse_mode)
 }
 
+#[cfg(feature = "gpu")]
+fn gpu_configure_display(cmd: cmdline::GpuConfigureDisplayCommand) -> ModifyGpuResult {
+    let config = DisplayConfig {
+        size: cmd.size,
+        scale_percent: cmd.scale,
+        rotation: cmd.rotation,
+    };
+    do_gpu_configure_display(cmd.socket_path, cmd.display_id, config)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_set_edid(cmd: cmdline::GpuSetEdidCommand) -> ModifyGpuResult {
+    let edid = match cmd.edid {
+        Some(path) => match std::fs::read(&path) {
+            Ok(edid) => Some(edid),
+            Err(e) => {
+                return Err(ModifyGpuError::GpuControl(GpuControlResult::ErrString(
+                    format!("failed to read {}: {}", path.display(), e),
+                )))
+            }
+        },
+        None => None,
+    };
+    do_gpu_set_display_edid(cmd.socket_path, cmd.display_id, edid)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_dump(cmd: cmdline::GpuDumpCommand) -> ModifyGpuResult {
+    do_gpu_dump_resources(cmd.socket_path)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_fence_stats(cmd: cmdline::GpuFenceStatsCommand) -> ModifyGpuResult {
+    do_gpu_fence_stats(cmd.socket_path, cmd.stuck_after)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_kill_context(cmd: cmdline::GpuKillContextCommand) -> ModifyGpuResult {
+    do_gpu_kill_context(cmd.socket_path, cmd.ctx_id)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_invalidate_backing(cmd: cmdline::GpuInvalidateBackingCommand) -> ModifyGpuResult {
+    let ranges = cmd
+        .range
+        .into_iter()
+        .map(|range| (GuestAddress(range.start), range.len().unwrap_or(u64::MAX)))
+        .collect();
+    do_gpu_invalidate_backing(cmd.socket_path, ranges)
+}
+
 #[cfg(feature = "gpu")]
 fn modify_gpu(cmd: cmdline::GpuCommand) -> std::result::Result<(), ()> {
     let result = match cmd.command {
@@ -615,6 +943,12 @@ fn modify_gpu(cmd: cmdline::GpuCommand) -> std::result::Result<(), ()> {
         cmdline::GpuSubCommand::ListDisplays(cmd) => gpu_display_list(cmd),
         cmdline::GpuSubCommand::RemoveDisplays(cmd) => gpu_display_remove(cmd),
         cmdline::GpuSubCommand::SetDisplayMouseMode(cmd) => gpu_set_display_mouse_mode(cmd),
+        cmdline::GpuSubCommand::ConfigureDisplay(cmd) => gpu_configure_display(cmd),
+        cmdline::GpuSubCommand::SetEdid(cmd) => gpu_set_edid(cmd),
+        cmdline::GpuSubCommand::Dump(cmd) => gpu_dump(cmd),
+        cmdline::GpuSubCommand::FenceStats(cmd) => gpu_fence_stats(cmd),
+        cmdline::GpuSubCommand::KillContext(cmd) => gpu_kill_context(cmd),
+        cmdline::GpuSubCommand::InvalidateBacking(cmd) => gpu_invalidate_backing(cmd),
     };
     match result {
         Ok(response) => {
@@ -647,6 +981,28 @@ fn security_key_attach(cmd: cmdline::UsbAttachKeyCommand) -> ModifyUsbResult<Usb
     do_security_key_attach(cmd.socket_path, dev_path)
 }
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn usbip_attach(cmd: cmdline::UsbAttachUsbipCommand) -> ModifyUsbResult<UsbControlResult> {
+    do_usbip_attach(cmd.socket_path, &cmd.server, &cmd.busid)
+}
+
+fn usb_hid_attach(cmd: cmdline::UsbAttachHidCommand) -> ModifyUsbResult<UsbControlResult> {
+    do_usb_hid_attach(cmd.socket_path, cmd.kind)
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn usb_hid_attach_socket(
+    cmd: cmdline::UsbAttachHidSocketCommand,
+) -> ModifyUsbResult<UsbControlResult> {
+    let report_socket = Path::new(&cmd.report_socket);
+
+    do_usb_hid_attach_socket(cmd.socket_path, cmd.kind, report_socket)
+}
+
+fn usb_hid_report(cmd: cmdline::UsbHidReportCommand) -> ModifyUsbResult<UsbControlResult> {
+    do_usb_hid_report(cmd.socket_path, cmd.port, cmd.report)
+}
+
 fn usb_detach(cmd: cmdline::UsbDetachCommand) -> ModifyUsbResult<UsbControlResult> {
     do_usb_detach(cmd.socket_path, cmd.port)
 }
@@ -659,6 +1015,12 @@ fn modify_usb(cmd: cmdline::UsbCommand) -> std::result::Result<(), ()> {
     let result = match cmd.command {
         cmdline::UsbSubCommand::Attach(cmd) => usb_attach(cmd),
         cmdline::UsbSubCommand::SecurityKeyAttach(cmd) => security_key_attach(cmd),
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        cmdline::UsbSubCommand::UsbipAttach(cmd) => usbip_attach(cmd),
+        cmdline::UsbSubCommand::HidAttach(cmd) => usb_hid_attach(cmd),
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        cmdline::UsbSubCommand::HidAttachSocket(cmd) => usb_hid_attach_socket(cmd),
+        cmdline::UsbSubCommand::HidReport(cmd) => usb_hid_report(cmd),
         cmdline::UsbSubCommand::Detach(cmd) => usb_detach(cmd),
         cmdline::UsbSubCommand::List(cmd) => usb_list(cmd),
     };
@@ -677,11 +1039,59 @@ fn modify_usb(cmd: cmdline::UsbCommand) -> std::result::Result<(), ()> {
 fn snapshot_vm(cmd: cmdline::SnapshotCommand) -> std::result::Result<(), ()> {
     use cmdline::SnapshotSubCommands::*;
     let (socket_path, request) = match cmd.snapshot_command {
+        SharedMemoryStats(stats_cmd) => return do_shared_memory_stats(stats_cmd.socket_path),
+        Take(take_cmd) if take_cmd.compress_memory && take_cmd.zstd_level.is_some() => {
+            error!("--compress-memory cannot be combined with --zstd-level");
+            return Err(());
+        }
+        Take(take_cmd)
+            if take_cmd.encrypt
+                && (take_cmd.key_file.is_some() || take_cmd.zstd_level.is_some()) =>
+        {
+            error!("--encrypt cannot be combined with --key-file or --zstd-level");
+            return Err(());
+        }
+        // The random key of --encrypt is not stored with the snapshot by this implementation.
+        Take(take_cmd) if take_cmd.encrypt && cfg!(feature = "snapshot-encryption") => {
+            error!("--encrypt is not supported by this build, use --key-file");
+            return Err(());
+        }
+        Take(take_cmd) if take_cmd.stream => {
+            if take_cmd.encrypt || take_cmd.key_file.is_some() {
+                error!("encryption is not supported with --stream");
+                return Err(());
+            }
+            // The output is opened here so that the snapshot can go to a pipe of this process.
+            let output = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&take_cmd.snapshot_path)
+                .map_err(|e| {
+                    error!("failed to open {}: {}", take_cmd.snapshot_path.display(), e)
+                })?;
+            let req = VmRequest::Snapshot(SnapshotCommand::TakeStream {
+                output,
+                compress_memory: take_cmd.compress_memory,
+                zstd_level: take_cmd.zstd_level,
+            });
+            (take_cmd.socket_path, req)
+        }
         Take(take_cmd) => {
+            let key = match &take_cmd.key_file {
+                Some(_) if !cfg!(feature = "snapshot-encryption") => {
+                    error!("--key-file requires crosvm built with the snapshot-encryption feature");
+                    return Err(());
+                }
+                Some(path) => Some(snapshot::read_key_file(path).map_err(|e| error!("{:#}", e))?),
+                None => None,
+            };
             let req = VmRequest::Snapshot(SnapshotCommand::Take {
                 snapshot_path: take_cmd.snapshot_path,
                 compress_memory: take_cmd.compress_memory,
                 encrypt: take_cmd.encrypt,
+                zstd_level: take_cmd.zstd_level,
+                key,
             });
             (take_cmd.socket_path, req)
         }
@@ -690,6 +1100,21 @@ fn snapshot_vm(cmd: cmdline::SnapshotCommand) -> std::result::Result<(), ()> {
     vms_request(&request, socket_path)
 }
 
+fn migrate_vm(cmd: cmdline::MigrateCommand) -> std::result::Result<(), ()> {
+    use cmdline::MigrateSubCommands::*;
+    match cmd.migrate_command {
+        Send(send_cmd) => {
+            let socket_path = Path::new(&send_cmd.socket_path);
+            let req = VmRequest::Migrate(MigrateCommand::Send {
+                destination: send_cmd.destination,
+            });
+            vms_request(&req, socket_path)?;
+            // The VM is now running at the destination.
+            vms_request(&VmRequest::Exit, socket_path)
+        }
+    }
+}
+
 #[allow(clippy::unnecessary_wraps)]
 fn pkg_version() -> std::result::Result<(), ()> {
     const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
@@ -838,6 +1263,9 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                     CrossPlatformCommands::Battery(cmd) => {
                         modify_battery(cmd).map_err(|_| anyhow!("battery subcommand failed"))
                     }
+                    CrossPlatformCommands::Console(cmd) => {
+                        console_cmd(cmd).map_err(|_| anyhow!("console subcommand failed"))
+                    }
                     #[cfg(feature = "composite-disk")]
                     CrossPlatformCommands::CreateComposite(cmd) => create_composite(cmd)
                         .map_err(|_| anyhow!("create_composite subcommand failed")),
@@ -849,6 +1277,16 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                     CrossPlatformCommands::Disk(cmd) => {
                         disk_cmd(cmd).map_err(|_| anyhow!("disk subcommand failed"))
                     }
+                    CrossPlatformCommands::Fs(cmd) => {
+                        fs_cmd(cmd).map_err(|_| anyhow!("fs subcommand failed"))
+                    }
+                    #[cfg(feature = "net")]
+                    CrossPlatformCommands::Net(cmd) => {
+                        net_cmd(cmd).map_err(|_| anyhow!("net subcommand failed"))
+                    }
+                    CrossPlatformCommands::DumpCore(cmd) => {
+                        dump_core(cmd).map_err(|_| anyhow!("dump-core subcommand failed"))
+                    }
                     #[cfg(feature = "gpu")]
                     CrossPlatformCommands::Gpu(cmd) => {
                         modify_gpu(cmd).map_err(|_| anyhow!("gpu subcommand failed"))
@@ -860,10 +1298,29 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                     CrossPlatformCommands::MakeRT(cmd) => {
                         make_rt(cmd).map_err(|_| anyhow!("make_rt subcommand failed"))
                     }
+                    CrossPlatformCommands::Migrate(cmd) => {
+                        migrate_vm(cmd).map_err(|_| anyhow!("migrate subcommand failed"))
+                    }
+                    CrossPlatformCommands::Params(cmd) => {
+                        params_vm(cmd).map_err(|_| anyhow!("params subcommand failed"))
+                    }
+                    CrossPlatformCommands::Query(cmd) => {
+                        query_vm(cmd).map_err(|_| anyhow!("query subcommand failed"))
+                    }
+                    #[cfg(feature = "config-file")]
+                    CrossPlatformCommands::ReloadConfig(cmd) => {
+                        reload_config(cmd).map_err(|_| anyhow!("reload-config command failed"))
+                    }
                     CrossPlatformCommands::Resume(cmd) => {
                         resume_vms(cmd).map_err(|_| anyhow!("resume subcommand failed"))
                     }
+                    CrossPlatformCommands::Rtc(cmd) => {
+                        rtc_vm(cmd).map_err(|_| anyhow!("rtc subcommand failed"))
+                    }
                     CrossPlatformCommands::Run(_) => unreachable!(),
+                    CrossPlatformCommands::Stats(cmd) => {
+                        stats_vm(cmd).map_err(|_| anyhow!("stats subcommand failed"))
+                    }
                     CrossPlatformCommands::Stop(cmd) => {
                         stop_vms(cmd).map_err(|_| anyhow!("stop subcommand failed"))
                     }
@@ -873,9 +1330,15 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                     CrossPlatformCommands::Swap(cmd) => {
                         swap_vms(cmd).map_err(|_| anyhow!("swap subcommand failed"))
                     }
+                    CrossPlatformCommands::Trace(cmd) => {
+                        trace_vm(cmd).map_err(|_| anyhow!("trace subcommand failed"))
+                    }
                     CrossPlatformCommands::Powerbtn(cmd) => {
                         powerbtn_vms(cmd).map_err(|_| anyhow!("powerbtn subcommand failed"))
                     }
+                    CrossPlatformCommands::Powerdown(cmd) => {
+                        powerdown_vm(cmd).map_err(|_| anyhow!("powerdown subcommand failed"))
+                    }
                     CrossPlatformCommands::Sleepbtn(cmd) => {
                         sleepbtn_vms(cmd).map_err(|_| anyhow!("sleepbtn subcommand failed"))
                     }
@@ -885,6 +1348,9 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                     CrossPlatformCommands::Usb(cmd) => {
                         modify_usb(cmd).map_err(|_| anyhow!("usb subcommand failed"))
                     }
+                    CrossPlatformCommands::Vcpu(cmd) => {
+                        vcpu_vm(cmd).map_err(|_| anyhow!("vcpu subcommand failed"))
+                    }
                     CrossPlatformCommands::Version(_) => {
                         pkg_version().map_err(|_| anyhow!("version subcommand failed"))
                     }
@@ -892,6 +1358,10 @@ fn crosvm_main<I: IntoIterator<Item = String>>(args: I) -> Result<CommandStatus>
                         modify_vfio(cmd).map_err(|_| anyhow!("vfio subcommand failed"))
                     }
                     #[cfg(feature = "pci-hotplug")]
+                    CrossPlatformCommands::Virtio(cmd) => {
+                        modify_virtio(cmd).map_err(|_| anyhow!("virtio subcommand failed"))
+                    }
+                    #[cfg(feature = "pci-hotplug")]
                     CrossPlatformCommands::VirtioNet(cmd) => {
                         modify_virtio_net(cmd).map_err(|_| anyhow!("virtio subcommand failed"))
                     }
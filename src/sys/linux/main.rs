@@ -2,10 +2,14 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
 use std::thread::sleep;
 use std::time::Duration;
 
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use base::kill_process_group;
 use base::reap_child;
@@ -17,9 +21,15 @@ use devices::virtio::vhost::user::device::run_console_device;
 use devices::virtio::vhost::user::device::run_fs_device;
 use devices::virtio::vhost::user::device::run_vsock_device;
 use devices::virtio::vhost::user::device::run_wl_device;
+use vm_control::guest_agent::serve_console_port;
+use vm_control::guest_agent::serve_vsock;
+use vm_control::guest_agent::ExecRequest;
+use vm_control::guest_agent::GuestAgentClient;
 
 use crate::crosvm::sys::cmdline::Commands;
 use crate::crosvm::sys::cmdline::DeviceSubcommand;
+use crate::crosvm::sys::cmdline::GuestAgentCommand;
+use crate::crosvm::sys::cmdline::GuestAgentSubcommand;
 use crate::crosvm::sys::linux::start_devices;
 use crate::CommandStatus;
 use crate::Config;
@@ -79,9 +89,72 @@ pub fn get_library_watcher() -> std::io::Result<()> {
     Ok(())
 }
 
+fn guest_agent(command: GuestAgentCommand) -> anyhow::Result<()> {
+    match command.nested {
+        GuestAgentSubcommand::Exec(cmd) => {
+            let env = cmd
+                .env
+                .iter()
+                .map(|var| {
+                    var.split_once('=')
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .with_context(|| format!("invalid environment variable {}", var))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let mut stdin = Vec::new();
+            if cmd.stdin {
+                std::io::stdin()
+                    .read_to_end(&mut stdin)
+                    .context("failed to read standard input")?;
+            }
+            let output = GuestAgentClient::connect(&cmd.agent)?.exec(ExecRequest {
+                program: cmd.program,
+                args: cmd.args,
+                env,
+                stdin,
+            })?;
+            std::io::stdout().write_all(&output.stdout)?;
+            std::io::stderr().write_all(&output.stderr)?;
+            match (output.exit_code, output.signal) {
+                (Some(0), _) => Ok(()),
+                (Some(code), _) => bail!("program exited with status {}", code),
+                (None, Some(signal)) => bail!("program was killed by signal {}", signal),
+                (None, None) => bail!("program exited abnormally"),
+            }
+        }
+        GuestAgentSubcommand::CopyTo(cmd) => {
+            let mode = u32::from_str_radix(&cmd.mode, 8)
+                .with_context(|| format!("invalid mode {}", cmd.mode))?;
+            let mut source = File::open(&cmd.source)
+                .with_context(|| format!("failed to open {}", cmd.source.display()))?;
+            GuestAgentClient::connect(&cmd.agent)?.write_file(
+                &cmd.destination,
+                &mut source,
+                mode,
+            )?;
+            Ok(())
+        }
+        GuestAgentSubcommand::CopyFrom(cmd) => {
+            let mut destination = File::create(&cmd.destination)
+                .with_context(|| format!("failed to create {}", cmd.destination.display()))?;
+            GuestAgentClient::connect(&cmd.agent)?.read_file(&cmd.source, &mut destination)?;
+            Ok(())
+        }
+        GuestAgentSubcommand::SyncTime(cmd) => GuestAgentClient::connect(&cmd.agent)?.sync_time(),
+        GuestAgentSubcommand::Shutdown(cmd) => {
+            GuestAgentClient::connect(&cmd.agent)?.shutdown(cmd.reboot)
+        }
+        GuestAgentSubcommand::Serve(cmd) => match cmd.console_port {
+            Some(name) => serve_console_port(&name),
+            None => serve_vsock(cmd.vsock_port),
+        },
+    }
+}
+
 pub(crate) fn run_command(command: Commands, _log_args: LogArgs) -> anyhow::Result<()> {
     match command {
         Commands::Devices(cmd) => start_devices(cmd).context("start_devices subcommand failed"),
+        Commands::GuestAgent(cmd) => guest_agent(cmd).context("guest-agent subcommand failed"),
     }
 }
 
@@ -298,6 +298,7 @@ fn create_vhost_user_block_device(
         connection,
         None,
         None,
+        None,
     )
     .exit_context(
         Exit::VhostUserBlockDeviceNew,
@@ -339,6 +340,7 @@ fn create_vhost_user_gpu_device(
         connection,
         None,
         None,
+        None,
     )
     .exit_context(
         Exit::VhostUserGpuDeviceNew,
@@ -362,6 +364,7 @@ fn create_vhost_user_snd_device(
         connection,
         None,
         None,
+        None,
     )
     .exit_context(
         Exit::VhostUserSndDeviceNew,
@@ -380,6 +383,7 @@ fn create_multi_touch_device(
     event_pipe: StreamChannel,
     width: u32,
     height: u32,
+    slots: Option<u32>,
     name: Option<&str>,
     idx: u32,
 ) -> DeviceResult {
@@ -388,6 +392,7 @@ fn create_multi_touch_device(
         event_pipe,
         width,
         height,
+        slots,
         name,
         virtio::base_features(cfg.protection_type),
     )
@@ -411,12 +416,18 @@ fn create_mouse_device(cfg: &Config, event_pipe: StreamChannel, idx: u32) -> Dev
 #[cfg(feature = "slirp")]
 fn create_vhost_user_net_device(cfg: &Config, connection: Connection<FrontendReq>) -> DeviceResult {
     let features = virtio::base_features(cfg.protection_type);
-    let dev =
-        virtio::VhostUserFrontend::new(virtio::DeviceType::Net, features, connection, None, None)
-            .exit_context(
-            Exit::VhostUserNetDeviceNew,
-            "failed to set up vhost-user net device",
-        )?;
+    let dev = virtio::VhostUserFrontend::new(
+        virtio::DeviceType::Net,
+        features,
+        connection,
+        None,
+        None,
+        None,
+    )
+    .exit_context(
+        Exit::VhostUserNetDeviceNew,
+        "failed to set up vhost-user net device",
+    )?;
 
     Ok(VirtioDeviceStub {
         dev: Box::new(dev),
@@ -657,6 +668,7 @@ fn create_virtio_input_event_devices(
             InputDeviceOption::MultiTouch {
                 width,
                 height,
+                slots,
                 name,
                 ..
             } => {
@@ -678,6 +690,7 @@ fn create_virtio_input_event_devices(
                     pipe,
                     width.unwrap_or(DEFAULT_TOUCH_DEVICE_WIDTH),
                     height.unwrap_or(DEFAULT_TOUCH_DEVICE_HEIGHT),
+                    *slots,
                     name.as_deref(),
                     idx as u32,
                 )?);
@@ -819,7 +832,7 @@ fn create_devices(
             Tube::pair().exit_context(Exit::CreateTube, "failed to create tube")?;
         irq_control_tubes.push(msi_host_tube);
 
-        let shared_memory_tube = if stub.dev.get_shared_memory_region().is_some() {
+        let shared_memory_tube = if !stub.dev.get_shared_memory_regions().is_empty() {
             let (host_tube, device_tube) =
                 Tube::pair().context("failed to create VVU proxy tube")?;
             vm_memory_control_tubes.push(host_tube);
@@ -1483,6 +1496,8 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                     .restore(image, guest_os.vcpu_count)
             },
             /* require_encrypted= */ false,
+            /* key= */ None,
+            /* share_memory= */ false,
             &mut suspended_pvclock_state,
             &guest_os.vm,
         )?;
@@ -1911,8 +1926,11 @@ fn create_whpx_vm(
         false, /* host_cpu_topology */
         false, /* enable_hwp */
         no_smt,
-        false, /* itmt */
-        None,  /* hybrid_type */
+        false,      /* itmt */
+        None,       /* hybrid_type */
+        false,      /* async_pf */
+        Vec::new(), /* cpuid_overrides */
+        Vec::new(), /* host_cache_sharing */
     );
 
     // context for non-cpu-specific cpuid results
@@ -2043,6 +2061,15 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
 
     let (pflash_image, pflash_block_size) = if let Some(pflash_parameters) = &cfg.pflash_parameters
     {
+        pflash_parameters.create_from_template()?;
+        if let Some(secure_boot) = &cfg.secure_boot {
+            if secure_boot.provision(&pflash_parameters.path)? {
+                info!(
+                    "enrolled Secure Boot keys in {}",
+                    pflash_parameters.path.display()
+                );
+            }
+        }
         (
             Some(
                 open_file_or_duplicate(
@@ -2075,6 +2102,8 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         no_smt: cfg.no_smt,
         hugepages: cfg.hugepages,
         hv_cfg: hypervisor::Config {
+            #[cfg(target_arch = "x86_64")]
+            x2apic_ids: false,
             protection_type: cfg.protection_type,
         },
         vm_image,
@@ -2108,6 +2137,12 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         host_cpu_topology: cfg.host_cpu_topology,
         #[cfg(target_arch = "x86_64")]
         force_s2idle: cfg.force_s2idle,
+        #[cfg(target_arch = "x86_64")]
+        s3: false,
+        #[cfg(target_arch = "x86_64")]
+        rtc_offset: Default::default(),
+        #[cfg(target_arch = "x86_64")]
+        fw_cfg_kernel: None,
         fw_cfg_parameters: cfg.fw_cfg_parameters.clone(),
         itmt: false,
         pvm_fw: None,
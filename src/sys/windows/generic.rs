@@ -245,6 +245,8 @@ pub(super) fn create_gpu(
         features,
         &BTreeMap::new(),
         wndproc_thread,
+        #[cfg(feature = "registered_events")]
+        None,
     ))
 }
 
@@ -281,6 +281,9 @@ impl IrqWaitWorker {
                                                     }
                                                 }
                                                 IrqSetup::Route(route) => irq_chip.route_irq(route),
+                                                IrqSetup::Routes(routes) => {
+                                                    irq_chip.route_irqs(&routes)
+                                                }
                                                 IrqSetup::UnRegister(irq, ev) => irq_chip
                                                     .unregister_edge_irq_event(
                                                         irq,
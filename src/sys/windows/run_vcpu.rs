@@ -205,8 +205,11 @@ impl VcpuRunThread {
             host_cpu_topology,
             false, /* enable_hwp */
             no_smt,
-            false, /* itmt */
-            None,  /* hybrid_type */
+            false,      /* itmt */
+            None,       /* hybrid_type */
+            false,      /* async_pf */
+            Vec::new(), /* cpuid_overrides */
+            Vec::new(), /* host_cache_sharing */
         ));
 
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
@@ -302,8 +305,11 @@ impl VcpuRunThread {
                         host_cpu_topology,
                         false, /* enable_hwp */
                         no_smt,
-                        false, /* itmt */
-                        None,  /* hybrid_type */
+                        false,      /* itmt */
+                        None,       /* hybrid_type */
+                        false,      /* async_pf */
+                        Vec::new(), /* cpuid_overrides */
+                        Vec::new(), /* host_cache_sharing */
                     );
 
                     #[cfg(target_arch = "x86_64")]
@@ -152,9 +152,12 @@ impl SwapController {
     /// * `guest_memory` - fresh new [GuestMemory]. Any pages on the [GuestMemory] must not be
     ///   touched.
     /// * `swap_dir` - directory to store swap files.
+    /// * `compression_level` - zstd level to compress the pages in swap files with. The pages are
+    ///   stored uncompressed if this is `None`.
     pub fn launch(
         guest_memory: GuestMemory,
         swap_dir: &Path,
+        compression_level: Option<i32>,
         jail_config: Option<&JailConfig>,
     ) -> anyhow::Result<Self> {
         info!("vmm-swap is enabled. launch monitor process.");
@@ -229,6 +232,7 @@ impl SwapController {
                     guest_memory,
                     uffd,
                     swap_file,
+                    compression_level,
                     bg_job_control,
                     &dead_uffd_checker,
                 ) {
@@ -555,6 +559,7 @@ fn monitor_process(
     guest_memory: GuestMemory,
     uffd: Userfaultfd,
     swap_file: File,
+    compression_level: Option<i32>,
     bg_job_control: BackgroundJobControl,
     dead_uffd_checker: &DeadUffdCheckerImpl,
 ) -> anyhow::Result<()> {
@@ -655,6 +660,7 @@ fn monitor_process(
                             &staging_shmem,
                             &regions,
                             worker.channel.clone(),
+                            compression_level,
                         ) {
                             Ok(page_handler) => page_handler,
                             Err(e) => {
@@ -5,6 +5,7 @@
 #![deny(missing_docs)]
 
 use std::fs::File;
+use std::io::ErrorKind;
 use std::ops::Range;
 use std::os::unix::fs::FileExt;
 
@@ -14,6 +15,7 @@ use base::MemoryMapping;
 use base::MemoryMappingBuilder;
 use base::MmapError;
 use base::Protection;
+use base::PunchHole;
 use base::VolatileMemory;
 use base::VolatileMemoryError;
 use base::VolatileSlice;
@@ -28,6 +30,10 @@ pub type Result<T> = std::result::Result<T, Error>;
 // On 4KB page size system, guest memory must be less than 8 TiB which is reasonable assumption.
 const MAX_PAGE_IDX: usize = (1 << 31) - 2;
 
+/// The maximum size of the pages returned by [SwapFile::get_slice()] when the swap file is
+/// compressed.
+const MAX_DECOMPRESSED_SIZE: usize = 2 * 1024 * 1024;
+
 #[derive(ThisError, Debug)]
 pub enum Error {
     #[error("failed to io: {0}")]
@@ -42,6 +48,10 @@ pub enum Error {
     InvalidSize,
     #[error("index is invalid")]
     InvalidIndex,
+    #[error("failed to compress a page: {0}")]
+    Compress(std::io::Error),
+    #[error("failed to decompress a page: {0}")]
+    Decompress(std::io::Error),
 }
 
 /// u32 to pack the state of a page on the file.
@@ -268,12 +278,186 @@ impl PageState {
     }
 }
 
+/// The location of the compressed content of a file page in the swap file.
+///
+/// A page which zstd can't shrink is stored as is, with `len` equal to the page size.
+#[derive(Clone, Copy, Debug)]
+struct CompressedPage {
+    offset: u64,
+    len: u32,
+    /// The size of the space at `offset` reserved for the page. Bigger than `len` if the page was
+    /// rewritten with content which compresses better.
+    space: u32,
+}
+
+/// The state of a compressed swap file.
+///
+/// Each file page is compressed on its own so that a page fault only decompresses the faulting
+/// page. Compressed pages are appended to the file, but a rewritten page reuses its previous
+/// location if the new content fits. The space of freed pages is punched out of the file.
+#[derive(Debug)]
+struct Compression {
+    level: i32,
+    /// The compressed content of each file page, indexed by the file page index.
+    pages: Vec<Option<CompressedPage>>,
+    /// The number of file pages with content.
+    num_pages: usize,
+    /// The offset to append the next compressed page at.
+    tail: u64,
+    /// Holds the pages decompressed by [SwapFile::page_content()] and [SwapFile::get_slice()].
+    decompressed: MemoryMapping,
+}
+
+impl Compression {
+    fn new(level: i32) -> Result<Self> {
+        let decompressed = MemoryMappingBuilder::new(MAX_DECOMPRESSED_SIZE)
+            .build()
+            .map_err(|e| Error::Mmap("create decompression buffer", e))?;
+        Ok(Self {
+            level,
+            pages: Vec::new(),
+            num_pages: 0,
+            tail: 0,
+            decompressed,
+        })
+    }
+
+    /// Writes the content of the consecutive file pages starting at `idx_file`.
+    fn write(&mut self, file: &File, idx_file: usize, mem_slice: &[u8]) -> Result<()> {
+        let page_size = pages_to_bytes(1);
+        let end_idx_file = idx_file + bytes_to_pages(mem_slice.len());
+        if self.pages.len() < end_idx_file {
+            self.pages.resize(end_idx_file, None);
+        }
+        // Pages which don't fit in their previous location are appended with a single write.
+        let append_offset = self.tail;
+        let mut appended = Vec::new();
+        let mut abandoned = Vec::new();
+        for (page, slot) in mem_slice
+            .chunks_exact(page_size)
+            .zip(&mut self.pages[idx_file..end_idx_file])
+        {
+            let compressed = zstd::bulk::compress(page, self.level).map_err(Error::Compress)?;
+            let data = if compressed.len() < page_size {
+                compressed.as_slice()
+            } else {
+                page
+            };
+            match slot {
+                Some(prev) if data.len() <= prev.space as usize => {
+                    file.write_all_at(data, prev.offset)?;
+                    prev.len = data.len() as u32;
+                }
+                _ => {
+                    if let Some(prev) = slot {
+                        abandoned.push(prev.offset..prev.offset + prev.space as u64);
+                    } else {
+                        self.num_pages += 1;
+                    }
+                    *slot = Some(CompressedPage {
+                        offset: append_offset + appended.len() as u64,
+                        len: data.len() as u32,
+                        space: data.len() as u32,
+                    });
+                    appended.extend_from_slice(data);
+                }
+            }
+        }
+        if !appended.is_empty() {
+            file.write_all_at(&appended, append_offset)?;
+            self.tail += appended.len() as u64;
+        }
+        for range in abandoned {
+            punch_hole(file, range)?;
+        }
+        Ok(())
+    }
+
+    /// Decompresses the consecutive file pages in `idx_file_range` into the decompression buffer.
+    fn read(&self, file: &File, idx_file_range: Range<usize>) -> Result<VolatileSlice> {
+        let page_size = pages_to_bytes(1);
+        let size = pages_to_bytes(idx_file_range.end - idx_file_range.start);
+        let slice = self.decompressed.get_slice(0, size)?;
+        // SAFETY:
+        // Safe because the slice is within the decompression buffer, which is only accessed by
+        // the owner of the [SwapFile].
+        let buf = unsafe { std::slice::from_raw_parts_mut(slice.as_mut_ptr(), size) };
+        let mut compressed = vec![0; page_size];
+        for (idx_file, page) in idx_file_range.zip(buf.chunks_exact_mut(page_size)) {
+            let Some(Some(compressed_page)) = self.pages.get(idx_file) else {
+                return Err(Error::InvalidIndex);
+            };
+            let data = &mut compressed[..compressed_page.len as usize];
+            file.read_exact_at(data, compressed_page.offset)?;
+            if data.len() == page_size {
+                page.copy_from_slice(data);
+            } else if zstd::bulk::decompress_to_buffer(data, page).map_err(Error::Decompress)?
+                != page_size
+            {
+                return Err(Error::Decompress(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "decompressed page is too short",
+                )));
+            }
+        }
+        Ok(slice)
+    }
+
+    /// Forgets the content of the file pages and punches their space out of the file.
+    fn free(&mut self, file: &File, idx_files: impl Iterator<Item = usize>) -> Result<()> {
+        // Compressed pages which were written together are next to each other in the file.
+        let mut hole: Option<Range<u64>> = None;
+        for idx_file in idx_files {
+            let Some(page) = self.pages.get_mut(idx_file).and_then(Option::take) else {
+                continue;
+            };
+            self.num_pages -= 1;
+            let range = page.offset..page.offset + page.space as u64;
+            match hole.as_mut() {
+                Some(hole) if hole.end == range.start => hole.end = range.end,
+                _ => {
+                    if let Some(hole) = hole.replace(range) {
+                        punch_hole(file, hole)?;
+                    }
+                }
+            }
+        }
+        if let Some(hole) = hole {
+            punch_hole(file, hole)?;
+        }
+        if self.num_pages == 0 && self.tail > 0 {
+            // Also punch out the partial blocks left by the holes punched before.
+            punch_hole(file, 0..self.tail)?;
+            self.tail = 0;
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the compressed content of the file pages.
+    fn compressed_bytes(&self, idx_files: impl Iterator<Item = usize>) -> u64 {
+        idx_files
+            .filter_map(|idx_file| self.pages.get(idx_file).copied().flatten())
+            .map(|page| page.len as u64)
+            .sum()
+    }
+}
+
+fn punch_hole(file: &File, range: Range<u64>) -> Result<()> {
+    match file.punch_hole(range.start, range.end - range.start) {
+        // The space is only reclaimed when the swap file is truncated on filesystems which can't
+        // punch holes.
+        Err(e) if e.kind() == ErrorKind::Unsupported => Ok(()),
+        result => Ok(result?),
+    }
+}
+
 /// [SwapFile] stores active pages in a memory region.
 ///
 /// This shares the swap file with other regions and creates mmap corresponding range in the file.
 ///
 /// TODO(kawasin): The file structure is straightforward and is not optimized yet.
-/// Each page in the file corresponds to the page in the memory region.
+/// Each page in the file corresponds to the page in the memory region, unless the file is
+/// compressed.
 #[derive(Debug)]
 pub struct SwapFile<'a> {
     file: &'a File,
@@ -283,6 +467,7 @@ pub struct SwapFile<'a> {
     // All the data pages before this index are mlock(2)ed.
     cursor_mlock: usize,
     min_possible_present_idx_file: usize,
+    compression: Option<Compression>,
 }
 
 impl<'a> SwapFile<'a> {
@@ -294,7 +479,13 @@ impl<'a> SwapFile<'a> {
     ///
     /// * `file` - The swap file.
     /// * `num_of_pages` - The number of pages in the region.
-    pub fn new(file: &'a File, num_of_pages: usize) -> Result<Self> {
+    /// * `compression_level` - The zstd compression level of the pages in the file. The pages are
+    ///   stored uncompressed if this is `None`.
+    pub fn new(
+        file: &'a File,
+        num_of_pages: usize,
+        compression_level: Option<i32>,
+    ) -> Result<Self> {
         if num_of_pages > MAX_PAGE_IDX {
             return Err(Error::InvalidSize);
         }
@@ -310,6 +501,7 @@ impl<'a> SwapFile<'a> {
             file_states: FilePageStates::new(num_of_pages),
             cursor_mlock: 0,
             min_possible_present_idx_file: 0,
+            compression: compression_level.map(Compression::new).transpose()?,
         })
     }
 
@@ -319,6 +511,9 @@ impl<'a> SwapFile<'a> {
     ///
     /// Returns [Error::OutOfRange] if the `idx` is out of range.
     ///
+    /// If the file is compressed, the content is decompressed into a buffer which is overwritten
+    /// by the next call to this or [Self::get_slice()].
+    ///
     /// # Arguments
     ///
     /// * `idx_page` - the index of the page from the head of the pages.
@@ -332,6 +527,11 @@ impl<'a> SwapFile<'a> {
             let Some(idx_file) = state.idx_file() else {
                 unreachable!("the page is not none");
             };
+            if let Some(compression) = &self.compression {
+                return compression
+                    .read(self.file, idx_file..idx_file + 1)
+                    .map(Some);
+            }
             return match self
                 .file_mmap
                 .get_slice(pages_to_bytes(idx_file), pages_to_bytes(1))
@@ -364,6 +564,10 @@ impl<'a> SwapFile<'a> {
     ///
     /// * `max_pages` - The maximum number of pages to be mlock(2)ed at once.
     pub fn lock_and_async_prefetch(&mut self, max_pages: usize) -> Result<usize> {
+        if self.compression.is_some() {
+            // Compressed pages are read with pread(2) instead of the mmap.
+            return Ok(0);
+        }
         if let Some((idx_file_range, _)) = self.file_states.find_present_pages_range(
             self.cursor_mlock,
             &self.page_states,
@@ -405,6 +609,10 @@ impl<'a> SwapFile<'a> {
         for state in &mut self.page_states[idx_page_range] {
             state.clear();
         }
+        if self.compression.is_some() {
+            // Compressed pages are never mlock(2)ed nor read through the mmap.
+            return Ok(0);
+        }
 
         let offset = pages_to_bytes(idx_file_range.start);
         let munlocked_size = if idx_file_range.start < self.cursor_mlock {
@@ -441,6 +649,14 @@ impl<'a> SwapFile<'a> {
         if idx_page_range.end > self.page_states.len() {
             return Err(Error::OutOfRange);
         }
+        if let Some(compression) = &mut self.compression {
+            compression.free(
+                self.file,
+                self.page_states[idx_page_range.clone()]
+                    .iter()
+                    .filter_map(PageState::idx_file),
+            )?;
+        }
         let mut mlocked_pages = 0;
         let mut mlock_range: Option<Range<usize>> = None;
         for state in &mut self.page_states[idx_page_range] {
@@ -569,11 +785,17 @@ impl<'a> SwapFile<'a> {
                     continue;
                 }
                 let size = pages_to_bytes(pending_pages);
-                // Write with pwrite(2) syscall instead of copying contents to mmap because write
-                // syscall is more explicit for kernel how many pages are going to be written while
-                // mmap only knows each page to be written on a page fault basis.
-                self.file
-                    .write_all_at(&mem_slice[..size], pages_to_bytes(pending_idx_file) as u64)?;
+                if let Some(compression) = self.compression.as_mut() {
+                    compression.write(self.file, pending_idx_file, &mem_slice[..size])?;
+                } else {
+                    // Write with pwrite(2) syscall instead of copying contents to mmap because
+                    // write syscall is more explicit for kernel how many pages are going to be
+                    // written while mmap only knows each page to be written on a page fault basis.
+                    self.file.write_all_at(
+                        &mem_slice[..size],
+                        pages_to_bytes(pending_idx_file) as u64,
+                    )?;
+                }
                 mem_slice = &mem_slice[size..];
             }
             pending_idx_file = Some(idx_file);
@@ -581,8 +803,12 @@ impl<'a> SwapFile<'a> {
         }
         if let Some(pending_idx_file) = pending_idx_file {
             let size = pages_to_bytes(pending_pages);
-            self.file
-                .write_all_at(&mem_slice[..size], pages_to_bytes(pending_idx_file) as u64)?;
+            if let Some(compression) = self.compression.as_mut() {
+                compression.write(self.file, pending_idx_file, &mem_slice[..size])?;
+            } else {
+                self.file
+                    .write_all_at(&mem_slice[..size], pages_to_bytes(pending_idx_file) as u64)?;
+            }
             mem_slice = &mem_slice[size..];
         }
         if !mem_slice.is_empty() {
@@ -599,6 +825,11 @@ impl<'a> SwapFile<'a> {
     /// * `max_pages` - the max size of the returned chunk even if the chunk of consecutive present
     ///   pages is longer than this.
     pub fn first_data_range(&mut self, max_pages: usize) -> Option<Range<usize>> {
+        let max_pages = if self.compression.is_some() {
+            max_pages.min(bytes_to_pages(MAX_DECOMPRESSED_SIZE))
+        } else {
+            max_pages
+        };
         if let Some((idx_file_range, head_idx_page)) = self.file_states.find_present_pages_range(
             self.min_possible_present_idx_file,
             &self.page_states,
@@ -626,6 +857,12 @@ impl<'a> SwapFile<'a> {
     ///   in the compacted file.
     pub fn get_slice(&self, idx_page_range: Range<usize>) -> Result<VolatileSlice> {
         let idx_file_range = self.convert_idx_page_range_to_idx_file(idx_page_range)?;
+        if let Some(compression) = &self.compression {
+            if pages_to_bytes(idx_file_range.end - idx_file_range.start) > MAX_DECOMPRESSED_SIZE {
+                return Err(Error::InvalidSize);
+            }
+            return compression.read(self.file, idx_file_range);
+        }
         match self.file_mmap.get_slice(
             pages_to_bytes(idx_file_range.start),
             pages_to_bytes(idx_file_range.end - idx_file_range.start),
@@ -644,6 +881,20 @@ impl<'a> SwapFile<'a> {
             .sum()
     }
 
+    /// Returns the size of the compressed content of the present pages in the swap file.
+    ///
+    /// Returns `0` if the file is not compressed.
+    pub fn compressed_bytes(&self) -> u64 {
+        self.compression.as_ref().map_or(0, |compression| {
+            compression.compressed_bytes(
+                self.page_states
+                    .iter()
+                    .filter(|state| state.is_present())
+                    .filter_map(PageState::idx_file),
+            )
+        })
+    }
+
     /// Convert the index range to corresponding index range of compacted file.
     ///
     /// This validates that the `idx_page_range` satisfy:
@@ -698,13 +949,13 @@ mod tests {
     fn new_success() {
         let file = tempfile::tempfile().unwrap();
 
-        assert_eq!(SwapFile::new(&file, 200).is_ok(), true);
+        assert_eq!(SwapFile::new(&file, 200, None).is_ok(), true);
     }
 
     #[test]
     fn len() {
         let file = tempfile::tempfile().unwrap();
-        let swap_file = SwapFile::new(&file, 200).unwrap();
+        let swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         assert_eq!(swap_file.page_states.len(), 200);
     }
@@ -712,7 +963,7 @@ mod tests {
     #[test]
     fn page_content_default_is_none() {
         let file = tempfile::tempfile().unwrap();
-        let swap_file = SwapFile::new(&file, 200).unwrap();
+        let swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         assert_eq!(swap_file.page_content(0, false).unwrap().is_none(), true);
     }
@@ -720,7 +971,7 @@ mod tests {
     #[test]
     fn page_content_returns_content() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let data = &vec![1; pagesize()];
         swap_file.write_to_file(0, data).unwrap();
@@ -735,7 +986,7 @@ mod tests {
     #[test]
     fn page_content_out_of_range() {
         let file = tempfile::tempfile().unwrap();
-        let swap_file = SwapFile::new(&file, 200).unwrap();
+        let swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         assert_eq!(swap_file.page_content(199, false).is_ok(), true);
         match swap_file.page_content(200, false) {
@@ -755,7 +1006,7 @@ mod tests {
     #[test]
     fn write_to_file_swap_file() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let buf1 = &vec![1; pagesize()];
         let buf2 = &vec![2; 2 * pagesize()];
@@ -771,7 +1022,7 @@ mod tests {
     #[test]
     fn write_to_file_invalid_size() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let buf = &vec![1; pagesize() + 1];
         match swap_file.write_to_file(0, buf) {
@@ -783,7 +1034,7 @@ mod tests {
     #[test]
     fn write_to_file_out_of_range() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let buf1 = &vec![1; pagesize()];
         let buf2 = &vec![2; 2 * pagesize()];
@@ -800,7 +1051,7 @@ mod tests {
     #[test]
     fn write_to_file_overwrite() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file.write_to_file(0, &vec![1; pagesize()]).unwrap();
         swap_file
@@ -843,7 +1094,7 @@ mod tests {
     #[cfg(target_arch = "x86_64")] // TODO(b/272612118): unit test infra (qemu-user) support
     fn lock_and_start_populate() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file.write_to_file(1, &vec![1; pagesize()]).unwrap();
         swap_file
@@ -866,7 +1117,7 @@ mod tests {
     #[test]
     fn clear_range() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let data = &vec![1; pagesize()];
         swap_file.write_to_file(0, data).unwrap();
@@ -879,7 +1130,7 @@ mod tests {
     #[cfg(target_arch = "x86_64")] // TODO(b/272612118): unit test infra (qemu-user) support
     fn clear_range_unlocked_pages() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file
             .write_to_file(1, &vec![1; 10 * pagesize()])
@@ -898,7 +1149,7 @@ mod tests {
     #[test]
     fn clear_range_keep_on_disk() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let data = &vec![1; pagesize()];
         swap_file.write_to_file(0, data).unwrap();
@@ -914,7 +1165,7 @@ mod tests {
     #[test]
     fn clear_range_out_of_range() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
         swap_file.write_to_file(199, &vec![0; pagesize()]).unwrap();
 
         match swap_file.clear_range(199..201) {
@@ -931,7 +1182,7 @@ mod tests {
     #[test]
     fn free_range() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let data = &vec![1; pagesize()];
         swap_file.write_to_file(0, data).unwrap();
@@ -945,7 +1196,7 @@ mod tests {
     #[cfg(target_arch = "x86_64")] // TODO(b/272612118): unit test infra (qemu-user) support
     fn free_range_unlocked_pages() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file
             .write_to_file(1, &vec![1; 10 * pagesize()])
@@ -968,7 +1219,7 @@ mod tests {
     #[test]
     fn free_range_out_of_range() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         assert_eq!(swap_file.free_range(199..200).is_ok(), true);
         match swap_file.free_range(200..201) {
@@ -984,7 +1235,7 @@ mod tests {
     #[test]
     fn free_range_and_write() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         let data = &vec![1; 5 * pagesize()];
         swap_file.write_to_file(0, data).unwrap();
@@ -1017,7 +1268,7 @@ mod tests {
     #[cfg(target_arch = "x86_64")] // TODO(b/272612118): unit test infra (qemu-user) support
     fn clear_mlock() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file
             .write_to_file(1, &vec![1; 10 * pagesize()])
@@ -1036,7 +1287,7 @@ mod tests {
     #[test]
     fn first_data_range() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file
             .write_to_file(1, &vec![1; 2 * pagesize()])
@@ -1055,7 +1306,7 @@ mod tests {
     #[test]
     fn get_slice() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file.write_to_file(1, &vec![1; pagesize()]).unwrap();
         swap_file.write_to_file(2, &vec![2; pagesize()]).unwrap();
@@ -1077,7 +1328,7 @@ mod tests {
     #[test]
     fn get_slice_out_of_range() {
         let file = tempfile::tempfile().unwrap();
-        let swap_file = SwapFile::new(&file, 200).unwrap();
+        let swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         match swap_file.get_slice(200..201) {
             Err(Error::OutOfRange) => {}
@@ -1090,11 +1341,80 @@ mod tests {
     #[test]
     fn present_pages() {
         let file = tempfile::tempfile().unwrap();
-        let mut swap_file = SwapFile::new(&file, 200).unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, None).unwrap();
 
         swap_file.write_to_file(1, &vec![1; pagesize()]).unwrap();
         swap_file.write_to_file(2, &vec![2; pagesize()]).unwrap();
 
         assert_eq!(swap_file.present_pages(), 2);
     }
+
+    /// Returns data which zstd can't compress.
+    fn random_page(seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..pagesize())
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compressed_write_and_read() {
+        let file = tempfile::tempfile().unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, Some(3)).unwrap();
+
+        let random = random_page(1);
+        swap_file
+            .write_to_file(1, &vec![1; pagesize() * 2])
+            .unwrap();
+        swap_file.write_to_file(3, &random).unwrap();
+
+        assert_page_content(&swap_file, 1, &vec![1; pagesize()]);
+        assert_page_content(&swap_file, 3, &random);
+        let slice = swap_file.get_slice(1..4).unwrap();
+        let mut buf = vec![0; pagesize() * 3];
+        slice.copy_to(&mut buf);
+        assert_eq!(&buf[..pagesize() * 2], vec![1; pagesize() * 2]);
+        assert_eq!(&buf[pagesize() * 2..], random);
+
+        // The random page is stored as is.
+        let compressed_bytes = swap_file.compressed_bytes();
+        assert!(compressed_bytes > pagesize() as u64);
+        assert!(compressed_bytes < pages_to_bytes(2) as u64);
+    }
+
+    #[test]
+    fn compressed_overwrite_and_free() {
+        let file = tempfile::tempfile().unwrap();
+        let mut swap_file = SwapFile::new(&file, 200, Some(3)).unwrap();
+        let tail = |swap_file: &SwapFile| swap_file.compression.as_ref().unwrap().tail;
+
+        swap_file.write_to_file(0, &vec![1; pagesize()]).unwrap();
+        let compressed_size = tail(&swap_file);
+        // A page which does not fit in its previous location is appended.
+        swap_file.write_to_file(0, &random_page(1)).unwrap();
+        assert_eq!(tail(&swap_file), compressed_size + pagesize() as u64);
+        assert_page_content(&swap_file, 0, &random_page(1));
+        // A smaller page reuses the location of the previous content.
+        swap_file.write_to_file(0, &vec![2; pagesize()]).unwrap();
+        assert_eq!(tail(&swap_file), compressed_size + pagesize() as u64);
+        assert_page_content(&swap_file, 0, &vec![2; pagesize()]);
+
+        swap_file.free_range(0..1).unwrap();
+        assert!(swap_file.page_content(0, true).unwrap().is_none());
+        assert_eq!(swap_file.compressed_bytes(), 0);
+        assert_eq!(tail(&swap_file), 0);
+        // The space of the pages is punched out of the file.
+        assert_eq!(
+            FileDataIterator::new(&file, 0, file.metadata().unwrap().len()).count(),
+            0
+        );
+
+        swap_file.write_to_file(0, &vec![3; pagesize()]).unwrap();
+        assert_page_content(&swap_file, 0, &vec![3; pagesize()]);
+    }
 }
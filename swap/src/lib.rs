@@ -15,6 +15,7 @@ cfg_if::cfg_if! {
         mod present_list;
         // this is public only for integration tests.
         pub mod page_handler;
+        pub mod policy;
         mod processes;
         mod staging;
         mod uffd_list;
@@ -98,6 +99,9 @@ pub struct SwapMetrics {
     pub staging_pages: u64,
     /// count of pages in swap files.
     pub swap_pages: u64,
+    /// bytes taken by the compressed content of the pages in swap files. zero if the swap file is
+    /// not compressed.
+    pub compressed_swap_bytes: u64,
 }
 
 /// The response to `crosvm swap status` command.
@@ -186,11 +186,14 @@ impl<'a> PageHandler<'a> {
     ///   Otherwise monitor process crashes on creating a mmap.
     /// * `address_ranges` - The list of address range of the regions. the start address must align
     ///   with page. the size must be multiple of pagesize.
+    /// * `compression_level` - The zstd compression level of the pages in the swap file. The pages
+    ///   are stored uncompressed if this is `None`.
     pub fn create(
         swap_file: &'a File,
         staging_shmem: &'a SharedMemory,
         address_ranges: &[Range<usize>],
         stating_move_context: Arc<Channel<MoveToStaging>>,
+        compression_level: Option<i32>,
     ) -> Result<Self> {
         // Truncate the file into the size to hold all regions, otherwise access beyond the end of
         // file may cause SIGBUS.
@@ -259,7 +262,7 @@ impl<'a> PageHandler<'a> {
             }
         }
 
-        let file = SwapFile::new(swap_file, offset_pages)?;
+        let file = SwapFile::new(swap_file, offset_pages, compression_level)?;
 
         Ok(Self {
             ctx: Mutex::new(PageHandleContext {
@@ -642,6 +645,7 @@ impl<'a> PageHandler<'a> {
         metrics.redundant_pages = self.compute_redundant_pages() as u64;
         metrics.staging_pages = self.compute_staging_pages() as u64;
         metrics.swap_pages = self.compute_swap_pages() as u64;
+        metrics.compressed_swap_bytes = self.ctx.lock().file.compressed_bytes();
     }
 }
 
@@ -0,0 +1,325 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Decides when to enable, trim, swap out and disable vmm-swap.
+//!
+//! The policy is loaded from a JSON file given with `--swap-policy`:
+//!
+//! ```json
+//! {
+//!     "interval_secs": 10,
+//!     "enable_pressure": 10.0,
+//!     "disable_pressure": 1.0,
+//!     "idle_secs": 300,
+//!     "idle_cpu_percent": 5.0
+//! }
+//! ```
+//!
+//! On each evaluation, [PolicyEngine] looks at the host memory pressure reported by PSI and at how
+//! busy the vCPUs were since the previous evaluation:
+//!
+//! * `Ready`: vmm-swap is enabled once the guest has been idle for `idle_secs` and the memory
+//!   pressure is at least `enable_pressure`.
+//! * `Pending`: the staging memory is trimmed once, then swapped out. vmm-swap is disabled instead
+//!   if the guest became busy in the meantime.
+//! * `Active`: vmm-swap is disabled when the guest is busy and the memory pressure is lower than
+//!   `disable_pressure`, or as soon as the guest is busy if `disable_pressure` is not set.
+//! * `Failed`: vmm-swap is disabled to be able to try again later.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::SwapState;
+
+const PSI_MEMORY_PATH: &str = "/proc/pressure/memory";
+
+fn default_interval_secs() -> u64 {
+    10
+}
+
+fn default_idle_cpu_percent() -> f64 {
+    5.0
+}
+
+/// Thresholds of a vmm-swap policy.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SwapPolicy {
+    /// Seconds between two evaluations of the policy.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Minimum host memory pressure to enable vmm-swap. This is the `some avg10` value of
+    /// `/proc/pressure/memory`, in percent.
+    pub enable_pressure: f64,
+    /// Host memory pressure under which vmm-swap is disabled once the guest is busy again.
+    #[serde(default)]
+    pub disable_pressure: Option<f64>,
+    /// Seconds the guest has to stay idle before vmm-swap is enabled.
+    pub idle_secs: u64,
+    /// The guest is idle while its vCPUs use less than this percentage of their CPU time.
+    #[serde(default = "default_idle_cpu_percent")]
+    pub idle_cpu_percent: f64,
+}
+
+impl SwapPolicy {
+    /// Loads a policy from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open swap policy {}", path.display()))?;
+        let policy: SwapPolicy = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("failed to parse swap policy {}", path.display()))?;
+        if policy.interval_secs == 0 {
+            bail!("swap policy interval_secs must be positive");
+        }
+        Ok(policy)
+    }
+
+    /// Interval between two evaluations of the policy.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
+
+/// A swap command chosen by [PolicyEngine].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Move the guest memory to the staging memory.
+    Enable,
+    /// Drop the clean pages from the staging memory.
+    Trim,
+    /// Write the staging memory to the swap file.
+    SwapOut,
+    /// Swap the guest memory back in.
+    Disable,
+}
+
+/// Tracks the guest idleness and chooses the next swap command according to a [SwapPolicy].
+pub struct PolicyEngine {
+    policy: SwapPolicy,
+    idle_since: Option<Instant>,
+    trimmed: bool,
+}
+
+impl PolicyEngine {
+    /// Creates an engine following `policy`.
+    pub fn new(policy: SwapPolicy) -> Self {
+        PolicyEngine {
+            policy,
+            idle_since: None,
+            trimmed: false,
+        }
+    }
+
+    /// Returns the swap command to issue, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - time of the evaluation.
+    /// * `state` - current vmm-swap state.
+    /// * `pressure` - host memory pressure, as returned by [read_memory_pressure].
+    /// * `vcpu_usage` - CPU time used by the vCPUs since the previous evaluation, in percent of the
+    ///   time available to them.
+    pub fn next_action(
+        &mut self,
+        now: Instant,
+        state: SwapState,
+        pressure: f64,
+        vcpu_usage: f64,
+    ) -> Option<PolicyAction> {
+        let idle = vcpu_usage < self.policy.idle_cpu_percent;
+        if !idle {
+            self.idle_since = None;
+        } else if self.idle_since.is_none() {
+            self.idle_since = Some(now);
+        }
+
+        match state {
+            SwapState::Ready => {
+                self.trimmed = false;
+                let idle_long_enough = self.idle_since.is_some_and(|since| {
+                    now.duration_since(since) >= Duration::from_secs(self.policy.idle_secs)
+                });
+                (idle_long_enough && pressure >= self.policy.enable_pressure)
+                    .then_some(PolicyAction::Enable)
+            }
+            SwapState::Pending if !idle => Some(PolicyAction::Disable),
+            SwapState::Pending if !self.trimmed => {
+                self.trimmed = true;
+                Some(PolicyAction::Trim)
+            }
+            SwapState::Pending => Some(PolicyAction::SwapOut),
+            SwapState::Active => {
+                let low_pressure = self
+                    .policy
+                    .disable_pressure
+                    .map_or(true, |threshold| pressure < threshold);
+                (!idle && low_pressure).then_some(PolicyAction::Disable)
+            }
+            SwapState::Failed => Some(PolicyAction::Disable),
+            SwapState::TrimInProgress
+            | SwapState::SwapOutInProgress
+            | SwapState::SwapInInProgress => None,
+        }
+    }
+}
+
+/// Reads the host memory pressure from PSI.
+///
+/// This is the share of time in the last 10 seconds during which some tasks were stalled on
+/// memory, in percent.
+pub fn read_memory_pressure() -> Result<f64> {
+    let content = std::fs::read_to_string(PSI_MEMORY_PATH)
+        .with_context(|| format!("failed to read {}", PSI_MEMORY_PATH))?;
+    parse_memory_pressure(&content)
+}
+
+fn parse_memory_pressure(content: &str) -> Result<f64> {
+    let line = content
+        .lines()
+        .find(|line| line.starts_with("some "))
+        .context("no \"some\" line in PSI memory pressure")?;
+    let avg10 = line
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))
+        .context("no avg10 in PSI memory pressure")?;
+    avg10
+        .parse()
+        .context("invalid avg10 in PSI memory pressure")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> SwapPolicy {
+        SwapPolicy {
+            interval_secs: 10,
+            enable_pressure: 10.0,
+            disable_pressure: Some(1.0),
+            idle_secs: 60,
+            idle_cpu_percent: 5.0,
+        }
+    }
+
+    #[test]
+    fn parse_policy() {
+        let policy: SwapPolicy =
+            serde_json::from_str(r#"{"enable_pressure": 10.0, "idle_secs": 60}"#).unwrap();
+        assert_eq!(
+            policy,
+            SwapPolicy {
+                disable_pressure: None,
+                ..self::policy()
+            }
+        );
+        assert!(serde_json::from_str::<SwapPolicy>(
+            r#"{"enable_pressure": 10.0, "idle_secs": 60, "idle": 1}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_psi() {
+        let content = "some avg10=12.50 avg60=3.00 avg300=0.50 total=123456\n\
+                       full avg10=1.00 avg60=0.00 avg300=0.00 total=1234\n";
+        assert_eq!(parse_memory_pressure(content).unwrap(), 12.5);
+        assert!(parse_memory_pressure("full avg10=1.00\n").is_err());
+        assert!(parse_memory_pressure("some avg60=1.00\n").is_err());
+    }
+
+    #[test]
+    fn enable_after_idle() {
+        let mut engine = PolicyEngine::new(policy());
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(engine.next_action(at(0), SwapState::Ready, 20.0, 1.0), None);
+        assert_eq!(
+            engine.next_action(at(30), SwapState::Ready, 20.0, 1.0),
+            None
+        );
+        // Busy guest restarts the idle period.
+        assert_eq!(
+            engine.next_action(at(40), SwapState::Ready, 20.0, 50.0),
+            None
+        );
+        assert_eq!(
+            engine.next_action(at(70), SwapState::Ready, 20.0, 1.0),
+            None
+        );
+        // Idle long enough but no memory pressure.
+        assert_eq!(
+            engine.next_action(at(130), SwapState::Ready, 5.0, 1.0),
+            None
+        );
+        assert_eq!(
+            engine.next_action(at(140), SwapState::Ready, 20.0, 1.0),
+            Some(PolicyAction::Enable)
+        );
+    }
+
+    #[test]
+    fn trim_then_swap_out() {
+        let mut engine = PolicyEngine::new(policy());
+        let now = Instant::now();
+
+        assert_eq!(
+            engine.next_action(now, SwapState::Pending, 20.0, 1.0),
+            Some(PolicyAction::Trim)
+        );
+        assert_eq!(
+            engine.next_action(now, SwapState::TrimInProgress, 20.0, 1.0),
+            None
+        );
+        assert_eq!(
+            engine.next_action(now, SwapState::Pending, 20.0, 1.0),
+            Some(PolicyAction::SwapOut)
+        );
+        // The next enable trims again.
+        assert_eq!(engine.next_action(now, SwapState::Ready, 0.0, 1.0), None);
+        assert_eq!(
+            engine.next_action(now, SwapState::Pending, 20.0, 1.0),
+            Some(PolicyAction::Trim)
+        );
+        // A busy guest cancels the swap out.
+        assert_eq!(
+            engine.next_action(now, SwapState::Pending, 20.0, 50.0),
+            Some(PolicyAction::Disable)
+        );
+    }
+
+    #[test]
+    fn disable_when_busy() {
+        let mut engine = PolicyEngine::new(policy());
+        let now = Instant::now();
+
+        assert_eq!(engine.next_action(now, SwapState::Active, 0.0, 1.0), None);
+        assert_eq!(engine.next_action(now, SwapState::Active, 20.0, 50.0), None);
+        assert_eq!(
+            engine.next_action(now, SwapState::Active, 0.5, 50.0),
+            Some(PolicyAction::Disable)
+        );
+        assert_eq!(
+            engine.next_action(now, SwapState::Failed, 20.0, 1.0),
+            Some(PolicyAction::Disable)
+        );
+
+        let mut engine = PolicyEngine::new(SwapPolicy {
+            disable_pressure: None,
+            ..policy()
+        });
+        assert_eq!(
+            engine.next_action(now, SwapState::Active, 20.0, 50.0),
+            Some(PolicyAction::Disable)
+        );
+    }
+}
@@ -137,7 +137,8 @@ mod test {
         let dir = tempfile::tempdir().unwrap();
         let guest_memory = create_guest_memory();
 
-        let controller = SwapController::launch(guest_memory.clone(), dir.path(), None).unwrap();
+        let controller =
+            SwapController::launch(guest_memory.clone(), dir.path(), None, None).unwrap();
 
         guest_memory
             .write_all_at_addr(&[1u8; 4096], GuestAddress(0x0000000000000000))
@@ -326,7 +327,8 @@ mod test {
         let dir = tempfile::tempdir().unwrap();
         let guest_memory = create_guest_memory();
 
-        let controller = SwapController::launch(guest_memory.clone(), dir.path(), None).unwrap();
+        let controller =
+            SwapController::launch(guest_memory.clone(), dir.path(), None, None).unwrap();
 
         guest_memory
             .write_all_at_addr(&[1u8; 4096], GuestAddress(0x0000000000000000))
@@ -520,7 +522,8 @@ mod test {
         let dir = tempfile::tempdir().unwrap();
         let guest_memory = create_guest_memory();
 
-        let controller = SwapController::launch(guest_memory.clone(), dir.path(), None).unwrap();
+        let controller =
+            SwapController::launch(guest_memory.clone(), dir.path(), None, None).unwrap();
 
         guest_memory
             .write_all_at_addr(&[1u8; 4096], GuestAddress(0x0000000000000000))
@@ -687,7 +690,8 @@ mod test {
         let dir = tempfile::tempdir().unwrap();
         let guest_memory = create_guest_memory();
 
-        let controller = SwapController::launch(guest_memory.clone(), dir.path(), None).unwrap();
+        let controller =
+            SwapController::launch(guest_memory.clone(), dir.path(), None, None).unwrap();
 
         guest_memory
             .write_all_at_addr(&[1u8; 4096], GuestAddress(0x0000000000000000))
@@ -25,6 +25,7 @@ use swap::page_handler::PageHandler;
 use swap::userfaultfd::register_regions;
 use swap::userfaultfd::unregister_regions;
 use swap::worker::Worker;
+use swap::SwapMetrics;
 
 const HUGEPAGE_SIZE: usize = 2 * 1024 * 1024; // 2MB
 
@@ -50,6 +51,7 @@ fn create_success_impl() {
             (base_addr + 3 * pagesize())..(base_addr + 6 * pagesize()),
         ],
         worker.channel.clone(),
+        None,
     );
 
     assert!(result.is_ok());
@@ -87,6 +89,7 @@ fn create_partially_overlap_impl() {
             &staging_shmem,
             &[base_addr..(base_addr + 3 * pagesize()), range],
             worker.channel.clone(),
+            None,
         );
         assert_eq!(result.is_err(), true);
         match result {
@@ -114,7 +117,13 @@ fn create_invalid_range_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr - pagesize());
 
-    let result = PageHandler::create(&file, &staging_shmem, &[region], worker.channel.clone());
+    let result = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &[region],
+        worker.channel.clone(),
+        None,
+    );
 
     assert!(result.is_err());
     worker.close();
@@ -146,8 +155,14 @@ fn handle_page_fault_zero_success_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
@@ -195,8 +210,14 @@ fn handle_page_fault_invalid_address_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
@@ -232,8 +253,14 @@ fn handle_page_fault_duplicated_page_fault_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
@@ -265,8 +292,14 @@ fn handle_page_remove_success_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
@@ -324,8 +357,14 @@ fn handle_page_remove_invalid_address_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
@@ -388,8 +427,14 @@ fn move_to_staging_data_written_before_enabling_impl() {
         base_addr1..(base_addr1 + 3 * pagesize()),
         base_addr2..(base_addr2 + 3 * pagesize()),
     ];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // write data before registering to userfaultfd
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
@@ -498,8 +543,14 @@ fn move_to_staging_hugepage_chunks_impl() {
         base_addr1..(base_addr1 + 5 * HUGEPAGE_SIZE),
         base_addr2..(base_addr2 + 5 * HUGEPAGE_SIZE),
     ];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // write data before registering to userfaultfd
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
@@ -627,8 +678,14 @@ fn move_to_staging_invalid_base_addr_impl() {
     let base_addr = shm.base_addr();
     let region = base_addr..(base_addr + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
@@ -694,8 +751,14 @@ fn swap_out_success_impl() {
         base_addr1..(base_addr1 + 3 * pagesize()),
         base_addr2..(base_addr2 + 3 * pagesize()),
     ];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // write data before registering to userfaultfd
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
@@ -765,6 +828,75 @@ fn swap_out_success_impl() {
     worker.close();
 }
 
+#[test]
+fn swap_out_compressed() {
+    call_test_with_sudo("swap_out_compressed_impl")
+}
+
+#[ignore = "Only to be called by swap_out_compressed"]
+#[test]
+fn swap_out_compressed_impl() {
+    let worker = Worker::new(2, 2);
+    let uffd = create_uffd_for_test();
+    let file = tempfile::tempfile().unwrap();
+    let staging_shmem = SharedMemory::new("test staging memory", 3 * pagesize() as u64).unwrap();
+    let shm = SharedMemory::new("shm", 3 * pagesize() as u64).unwrap();
+    let mmap = MemoryMappingBuilder::new(3 * pagesize())
+        .from_shared_memory(&shm)
+        .build()
+        .unwrap();
+    let base_addr = mmap.as_ptr() as usize;
+    let regions = [base_addr..(base_addr + 3 * pagesize())];
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        Some(3),
+    )
+    .unwrap();
+    // write data before registering to userfaultfd
+    // SAFETY: the pages are in the mmap.
+    unsafe {
+        for i in 0..2 * pagesize() {
+            *((base_addr + i) as *mut u8) = (i / pagesize() + 1) as u8;
+        }
+    }
+    // SAFETY: the region is in the mmap which is not used by other processes.
+    unsafe { register_regions(&regions, array::from_ref(&uffd)) }.unwrap();
+
+    // SAFETY: the region is registered to the uffd whose page faults are handled below.
+    unsafe {
+        page_handler.move_to_staging(base_addr, &shm, 0).unwrap();
+    }
+    worker.channel.wait_complete();
+    swap_out_all(&page_handler);
+    let mut metrics = SwapMetrics::default();
+    page_handler.load_metrics(&mut metrics);
+    assert_eq!(metrics.swap_pages, 2);
+    assert!(metrics.compressed_swap_bytes > 0);
+    assert!(metrics.compressed_swap_bytes < pagesize() as u64);
+
+    for i in 0..3 {
+        page_handler
+            .handle_page_fault(&uffd, base_addr + i * pagesize())
+            .unwrap();
+    }
+
+    // read values on another thread to avoid blocking forever
+    let join_handle = thread::spawn(move || {
+        (0..3 * pagesize())
+            // SAFETY: the pages are in the mmap and their page faults were handled.
+            .map(|i| unsafe { *((base_addr + i) as *const u8) })
+            .collect::<Vec<_>>()
+    });
+    let result = wait_thread_with_timeout(join_handle, 100);
+    for (i, v) in result.iter().enumerate() {
+        assert_eq!(*v, [1, 2, 0][i / pagesize()]);
+    }
+    worker.close();
+}
+
 #[test]
 fn swap_out_handled_page() {
     call_test_with_sudo("swap_out_handled_page_impl")
@@ -786,8 +918,14 @@ fn swap_out_handled_page_impl() {
 
     let region = base_addr1..(base_addr1 + 3 * pagesize());
     let regions = [region];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // write data before registering to userfaultfd
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
@@ -860,8 +998,14 @@ fn swap_out_twice_impl() {
         base_addr1..(base_addr1 + 3 * pagesize()),
         base_addr2..(base_addr2 + 3 * pagesize()),
     ];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe {
@@ -991,8 +1135,14 @@ fn swap_in_success_impl() {
         base_addr1..(base_addr1 + 3 * pagesize()),
         base_addr2..(base_addr2 + 3 * pagesize()),
     ];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe {
@@ -1107,8 +1257,14 @@ fn trim_success_impl() {
         base_addr1..(base_addr1 + 3 * pagesize()),
         base_addr2..(base_addr2 + 3 * pagesize()),
     ];
-    let page_handler =
-        PageHandler::create(&file, &staging_shmem, &regions, worker.channel.clone()).unwrap();
+    let page_handler = PageHandler::create(
+        &file,
+        &staging_shmem,
+        &regions,
+        worker.channel.clone(),
+        None,
+    )
+    .unwrap();
     // TODO(b/315998194): Add safety comment
     #[allow(clippy::undocumented_unsafe_blocks)]
     unsafe {
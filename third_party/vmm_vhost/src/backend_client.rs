@@ -14,6 +14,7 @@ use base::INVALID_DESCRIPTOR;
 use zerocopy::FromBytes;
 use zerocopy::Immutable;
 use zerocopy::IntoBytes;
+use zerocopy::Ref;
 
 use crate::backend::VhostUserMemoryRegionInfo;
 use crate::backend::VringConfigData;
@@ -81,6 +82,46 @@ impl BackendClient {
     /// Set the memory map regions on the backend so it can translate the vring
     /// addresses. In the ancillary data there is an array of file descriptors
     pub fn set_mem_table(&self, regions: &[VhostUserMemoryRegionInfo]) -> Result<()> {
+        let hdr = self.send_mem_table(regions)?;
+        self.wait_for_ack(&hdr)
+    }
+
+    /// Like `set_mem_table`, but for use between `postcopy_listen` and `postcopy_end`. Returns
+    /// the addresses at which the backend mapped each region, which are the addresses its
+    /// userfaultfd reports faults at.
+    pub fn set_mem_table_postcopy(
+        &self,
+        regions: &[VhostUserMemoryRegionInfo],
+    ) -> Result<Vec<u64>> {
+        let hdr = self.send_mem_table(regions)?;
+
+        let (body, buf, _files) = self.recv_reply_with_payload::<VhostUserMemory>(&hdr)?;
+        let (mapped, excess) = Ref::<_, [VhostUserMemoryRegion]>::from_prefix_with_elems(
+            buf.as_slice(),
+            body.num_regions as usize,
+        )
+        .map_err(|_| VhostUserError::InvalidMessage)?;
+        if !excess.is_empty() || mapped.len() != regions.len() {
+            return Err(VhostUserError::InvalidMessage);
+        }
+        let addrs = mapped.iter().map(|region| region.user_addr).collect();
+
+        // Let the backend know that we are ready to handle faults on the regions.
+        let ack_hdr = self.new_request_header(
+            FrontendReq::SET_MEM_TABLE,
+            mem::size_of::<VhostUserU64>() as u32,
+        );
+        self.connection
+            .send_message(&ack_hdr, &VhostUserU64::new(0), None)?;
+
+        self.wait_for_ack(&hdr)?;
+        Ok(addrs)
+    }
+
+    fn send_mem_table(
+        &self,
+        regions: &[VhostUserMemoryRegionInfo],
+    ) -> Result<VhostUserMsgHeader<FrontendReq>> {
         if regions.is_empty() || regions.len() > MAX_ATTACHED_FD_ENTRIES {
             return Err(VhostUserError::InvalidParam(
                 "set_mem_table: regions empty or exceed max allowed regions per req.",
@@ -105,13 +146,12 @@ impl BackendClient {
         }
 
         let body = VhostUserMemory::new(ctx.regions.len() as u32);
-        let hdr = self.send_request_with_payload(
+        self.send_request_with_payload(
             FrontendReq::SET_MEM_TABLE,
             &body,
             ctx.regions.as_bytes(),
             Some(ctx.fds.as_slice()),
-        )?;
-        self.wait_for_ack(&hdr)
+        )
     }
 
     /// Set base address for page modification logging.
@@ -506,6 +546,38 @@ impl BackendClient {
         self.wait_for_ack(&hdr)
     }
 
+    /// Tells the backend that a postcopy migration is about to start. Returns the userfaultfd the
+    /// backend opened for it.
+    pub fn postcopy_advise(&self) -> Result<File> {
+        let hdr = self.send_request_header(FrontendReq::POSTCOPY_ADVISE, None)?;
+        let (body, files) = self.recv_reply_with_files::<VhostUserU64>(&hdr)?;
+        if body.value != 0 {
+            return Err(VhostUserError::BackendInternalError);
+        }
+        into_single_file(files).ok_or(VhostUserError::IncorrectFds)
+    }
+
+    /// Tells the backend to register guest memory with its userfaultfd. Memory tables sent after
+    /// this must use `set_mem_table_postcopy`.
+    pub fn postcopy_listen(&self) -> Result<()> {
+        let hdr = self.send_request_header(FrontendReq::POSTCOPY_LISTEN, None)?;
+        let reply = self.recv_reply::<VhostUserU64>(&hdr)?;
+        if reply.value != 0 {
+            return Err(VhostUserError::BackendInternalError);
+        }
+        Ok(())
+    }
+
+    /// Tells the backend that all of guest memory has arrived and the userfaultfd can be closed.
+    pub fn postcopy_end(&self) -> Result<()> {
+        let hdr = self.send_request_header(FrontendReq::POSTCOPY_END, None)?;
+        let reply = self.recv_reply::<VhostUserU64>(&hdr)?;
+        if reply.value != 0 {
+            return Err(VhostUserError::BackendInternalError);
+        }
+        Ok(())
+    }
+
     /// Gets the shared memory regions used by the device.
     pub fn get_shared_memory_regions(&self) -> Result<Vec<VhostSharedMemoryRegion>> {
         let hdr = self.send_request_header(FrontendReq::GET_SHARED_MEMORY_REGIONS, None)?;
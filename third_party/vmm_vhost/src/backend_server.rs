@@ -76,6 +76,16 @@ pub trait Backend {
     ) -> Result<Option<File>>;
     fn check_device_state(&mut self) -> Result<()>;
     fn get_shared_memory_regions(&mut self) -> Result<Vec<VhostSharedMemoryRegion>>;
+    /// Opens a userfaultfd for a postcopy migration and returns it so it can be sent to the
+    /// frontend.
+    fn postcopy_advise(&mut self) -> Result<File>;
+    /// Starts registering guest memory with the userfaultfd from `postcopy_advise`.
+    fn postcopy_listen(&mut self) -> Result<()>;
+    /// Ends the postcopy migration and closes the userfaultfd.
+    fn postcopy_end(&mut self) -> Result<()>;
+    /// Returns the host addresses at which the regions of the last `set_mem_table` call were
+    /// mapped, in the order they were given. Only used while postcopy is listening.
+    fn postcopy_region_addrs(&mut self) -> Result<Vec<u64>>;
 }
 
 impl<T> Backend for T
@@ -212,6 +222,22 @@ where
     fn get_shared_memory_regions(&mut self) -> Result<Vec<VhostSharedMemoryRegion>> {
         self.as_mut().get_shared_memory_regions()
     }
+
+    fn postcopy_advise(&mut self) -> Result<File> {
+        self.as_mut().postcopy_advise()
+    }
+
+    fn postcopy_listen(&mut self) -> Result<()> {
+        self.as_mut().postcopy_listen()
+    }
+
+    fn postcopy_end(&mut self) -> Result<()> {
+        self.as_mut().postcopy_end()
+    }
+
+    fn postcopy_region_addrs(&mut self) -> Result<Vec<u64>> {
+        self.as_mut().postcopy_region_addrs()
+    }
 }
 
 /// Handles requests from a vhost-user connection by dispatching them to [[Backend]] methods.
@@ -228,6 +254,8 @@ pub struct BackendServer<S: Backend> {
 
     /// Sending ack for messages without payload.
     reply_ack_enabled: bool,
+    /// Set between POSTCOPY_LISTEN and POSTCOPY_END.
+    postcopy_listening: bool,
 }
 
 impl<S: Backend> AsRef<S> for BackendServer<S> {
@@ -246,6 +274,7 @@ impl<S: Backend> BackendServer<S> {
             protocol_features: VhostUserProtocolFeatures::empty(),
             acked_protocol_features: 0,
             reply_ack_enabled: false,
+            postcopy_listening: false,
         }
     }
 
@@ -399,6 +428,9 @@ impl<S: Backend> BackendServer<S> {
             }
             Ok(FrontendReq::SET_MEM_TABLE) => {
                 let res = self.set_mem_table(&hdr, size, &buf, files);
+                if self.postcopy_listening && res.is_ok() {
+                    self.send_postcopy_mem_table_reply(&hdr, &buf)?;
+                }
                 self.send_ack_message(&hdr, res.is_ok())?;
                 res?;
             }
@@ -648,6 +680,45 @@ impl<S: Backend> BackendServer<S> {
                 self.send_reply_message(&hdr, &msg)?;
                 res?;
             }
+            Ok(FrontendReq::POSTCOPY_ADVISE) => {
+                if self.acked_protocol_features & VhostUserProtocolFeatures::PAGEFAULT.bits() == 0 {
+                    return Err(Error::InvalidOperation);
+                }
+                self.check_request_size(&hdr, size, 0)?;
+                let res = self.backend.postcopy_advise();
+                let msg = VhostUserU64::new(if res.is_ok() { 0 } else { 1 });
+                let reply_hdr: VhostUserMsgHeader<FrontendReq> =
+                    self.new_reply_header::<VhostUserU64>(&hdr, 0)?;
+                let ufd = res.as_ref().ok().map(|f| f.as_raw_descriptor());
+                self.connection.send_message(
+                    &reply_hdr,
+                    &msg,
+                    ufd.as_ref().map(std::slice::from_ref),
+                )?;
+                res?;
+            }
+            Ok(FrontendReq::POSTCOPY_LISTEN) => {
+                if self.acked_protocol_features & VhostUserProtocolFeatures::PAGEFAULT.bits() == 0 {
+                    return Err(Error::InvalidOperation);
+                }
+                self.check_request_size(&hdr, size, 0)?;
+                let res = self.backend.postcopy_listen();
+                self.postcopy_listening = res.is_ok();
+                let msg = VhostUserU64::new(if res.is_ok() { 0 } else { 1 });
+                self.send_reply_message(&hdr, &msg)?;
+                res?;
+            }
+            Ok(FrontendReq::POSTCOPY_END) => {
+                if self.acked_protocol_features & VhostUserProtocolFeatures::PAGEFAULT.bits() == 0 {
+                    return Err(Error::InvalidOperation);
+                }
+                self.check_request_size(&hdr, size, 0)?;
+                self.postcopy_listening = false;
+                let res = self.backend.postcopy_end();
+                let msg = VhostUserU64::new(if res.is_ok() { 0 } else { 1 });
+                self.send_reply_message(&hdr, &msg)?;
+                res?;
+            }
             Ok(FrontendReq::GET_SHARED_MEMORY_REGIONS) => {
                 let regions = self.backend.get_shared_memory_regions()?;
                 let mut buf = Vec::new();
@@ -757,6 +828,43 @@ impl<S: Backend> BackendServer<S> {
         self.backend.set_mem_table(&regions, files)
     }
 
+    /// In postcopy mode, the backend tells the frontend where it mapped each memory region so the
+    /// frontend can resolve faults on them, then waits for the frontend to acknowledge before it
+    /// touches guest memory.
+    fn send_postcopy_mem_table_reply(
+        &mut self,
+        hdr: &VhostUserMsgHeader<FrontendReq>,
+        buf: &[u8],
+    ) -> Result<()> {
+        let (msg, regions) =
+            Ref::<_, VhostUserMemory>::from_prefix(buf).map_err(|_| Error::InvalidMessage)?;
+        let (regions, _) = Ref::<_, [VhostUserMemoryRegion]>::from_prefix_with_elems(
+            regions,
+            msg.num_regions as usize,
+        )
+        .map_err(|_| Error::InvalidMessage)?;
+
+        let addrs = self.backend.postcopy_region_addrs()?;
+        if addrs.len() != regions.len() {
+            return Err(Error::InvalidOperation);
+        }
+        let mapped: VhostUserMemoryPayload = regions
+            .iter()
+            .zip(addrs)
+            .map(|(region, addr)| VhostUserMemoryRegion {
+                user_addr: addr,
+                ..*region
+            })
+            .collect();
+        self.send_reply_with_payload(hdr, &*msg, mapped.as_bytes())?;
+
+        let (ack_hdr, ack, _files) = self.connection.recv_message::<VhostUserU64>()?;
+        if !matches!(ack_hdr.get_code(), Ok(FrontendReq::SET_MEM_TABLE)) || ack.value != 0 {
+            return Err(Error::InvalidMessage);
+        }
+        Ok(())
+    }
+
     fn get_config(&mut self, hdr: &VhostUserMsgHeader<FrontendReq>, buf: &[u8]) -> Result<()> {
         let (msg, payload) =
             Ref::<_, VhostUserConfig>::from_prefix(buf).map_err(|_| Error::InvalidMessage)?;
@@ -513,6 +513,100 @@ mod tests {
         mbar.wait();
     }
 
+    #[test]
+    fn test_postcopy() {
+        let mbar = Arc::new(Barrier::new(2));
+        let sbar = mbar.clone();
+        let test_backend = TestBackend::new();
+        let (mut backend_client, mut backend_server) = create_client_server_pair(test_backend);
+
+        thread::spawn(move || {
+            // set_owner(), get/set_features(), get/set_protocol_features()
+            for _ in 0..5 {
+                handle_request(&mut backend_server).unwrap();
+            }
+
+            // postcopy_advise()
+            handle_request(&mut backend_server).unwrap();
+            assert!(backend_server.as_ref().postcopy_advised);
+
+            // set_mem_table() before listening gets a plain ack.
+            handle_request(&mut backend_server).unwrap();
+
+            // postcopy_listen()
+            handle_request(&mut backend_server).unwrap();
+            assert!(backend_server.as_ref().postcopy_listening);
+
+            // set_mem_table() while listening replies with the mapped addresses.
+            handle_request(&mut backend_server).unwrap();
+            assert_eq!(backend_server.as_ref().mem_table.len(), 2);
+
+            // postcopy_end()
+            handle_request(&mut backend_server).unwrap();
+            assert!(!backend_server.as_ref().postcopy_advised);
+            assert!(!backend_server.as_ref().postcopy_listening);
+
+            sbar.wait();
+        });
+
+        backend_client.set_owner().unwrap();
+        let features = backend_client.get_features().unwrap();
+        backend_client.set_features(features).unwrap();
+        let features = backend_client.get_protocol_features().unwrap();
+        assert!(features.contains(VhostUserProtocolFeatures::PAGEFAULT));
+        backend_client.set_protocol_features(features).unwrap();
+
+        backend_client.postcopy_advise().unwrap();
+
+        let event = base::Event::new().unwrap();
+        let mem = [
+            VhostUserMemoryRegionInfo {
+                guest_phys_addr: 0,
+                memory_size: 0x10_0000,
+                userspace_addr: 0x1000_0000,
+                mmap_offset: 0,
+                mmap_handle: event.as_raw_descriptor(),
+            },
+            VhostUserMemoryRegionInfo {
+                guest_phys_addr: 0x10_0000,
+                memory_size: 0x10_0000,
+                userspace_addr: 0x2000_0000,
+                mmap_offset: 0x10_0000,
+                mmap_handle: event.as_raw_descriptor(),
+            },
+        ];
+        backend_client.set_mem_table(&mem).unwrap();
+
+        backend_client.postcopy_listen().unwrap();
+        let addrs = backend_client.set_mem_table_postcopy(&mem).unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                crate::test_backend::MAP_BASE,
+                crate::test_backend::MAP_BASE + 0x10_0000
+            ]
+        );
+
+        backend_client.postcopy_end().unwrap();
+
+        mbar.wait();
+    }
+
+    #[test]
+    fn test_postcopy_requires_pagefault() {
+        let test_backend = TestBackend::new();
+        let (backend_client, mut backend_server) = create_client_server_pair(test_backend);
+
+        thread::spawn(move || {
+            // POSTCOPY_ADVISE without VHOST_USER_PROTOCOL_F_PAGEFAULT is rejected without a
+            // reply, and the connection is then dropped.
+            handle_request(&mut backend_server).unwrap_err();
+            assert!(!backend_server.as_ref().postcopy_advised);
+        });
+
+        backend_client.postcopy_advise().unwrap_err();
+    }
+
     #[test]
     fn test_error_display() {
         assert_eq!(
@@ -12,6 +12,8 @@ pub const MAX_QUEUE_NUM: usize = 2;
 pub const MAX_VRING_NUM: usize = 256;
 pub const MAX_MEM_SLOTS: usize = 32;
 pub const VIRTIO_FEATURES: u64 = 0x40000003;
+/// Host address at which the test backend pretends to map guest address 0.
+pub const MAP_BASE: u64 = 0x7f00_0000_0000;
 
 #[derive(Default)]
 pub struct TestBackend {
@@ -28,6 +30,9 @@ pub struct TestBackend {
     pub vring_started: [bool; MAX_QUEUE_NUM],
     pub vring_enabled: [bool; MAX_QUEUE_NUM],
     pub inflight_file: Option<File>,
+    pub mem_table: Vec<VhostUserMemoryRegion>,
+    pub postcopy_advised: bool,
+    pub postcopy_listening: bool,
 }
 
 impl TestBackend {
@@ -85,7 +90,8 @@ impl Backend for TestBackend {
         Ok(())
     }
 
-    fn set_mem_table(&mut self, _ctx: &[VhostUserMemoryRegion], _files: Vec<File>) -> Result<()> {
+    fn set_mem_table(&mut self, ctx: &[VhostUserMemoryRegion], _files: Vec<File>) -> Result<()> {
+        self.mem_table = ctx.to_vec();
         Ok(())
     }
 
@@ -280,4 +286,33 @@ impl Backend for TestBackend {
     fn get_shared_memory_regions(&mut self) -> Result<Vec<VhostSharedMemoryRegion>> {
         Ok(Vec::new())
     }
+
+    fn postcopy_advise(&mut self) -> Result<File> {
+        // Any file will do in place of a userfaultfd.
+        let file = tempfile::tempfile().map_err(|_| Error::InvalidOperation)?;
+        self.postcopy_advised = true;
+        Ok(file)
+    }
+
+    fn postcopy_listen(&mut self) -> Result<()> {
+        if !self.postcopy_advised {
+            return Err(Error::InvalidOperation);
+        }
+        self.postcopy_listening = true;
+        Ok(())
+    }
+
+    fn postcopy_end(&mut self) -> Result<()> {
+        self.postcopy_advised = false;
+        self.postcopy_listening = false;
+        Ok(())
+    }
+
+    fn postcopy_region_addrs(&mut self) -> Result<Vec<u64>> {
+        Ok(self
+            .mem_table
+            .iter()
+            .map(|region| MAP_BASE + region.guest_phys_addr)
+            .collect())
+    }
 }
@@ -643,16 +643,53 @@ impl Transfer {
         Ok(transfer)
     }
 
-    /// Create an isochronous transfer.
-    pub fn new_isochronous(endpoint: u8, buffer: TransferBuffer) -> Result<Transfer> {
-        // TODO(dverkamp): allow user to specify iso descriptors
-        Self::new(usb_sys::USBDEVFS_URB_TYPE_ISO, endpoint, buffer, &[])
+    /// Create an isochronous transfer with one packet of each length in `packet_lengths`.
+    ///
+    /// The packets are laid out back to back in `buffer` and scheduled as soon as possible.
+    pub fn new_isochronous(
+        endpoint: u8,
+        buffer: TransferBuffer,
+        packet_lengths: &[u32],
+    ) -> Result<Transfer> {
+        let iso_packets: Vec<usb_sys::usbdevfs_iso_packet_desc> = packet_lengths
+            .iter()
+            .map(|&length| usb_sys::usbdevfs_iso_packet_desc {
+                length,
+                actual_length: 0,
+                status: 0,
+            })
+            .collect();
+        let mut transfer = Self::new(
+            usb_sys::USBDEVFS_URB_TYPE_ISO,
+            endpoint,
+            buffer,
+            &iso_packets,
+        )?;
+        transfer.urb_mut().flags = usb_sys::USBDEVFS_URB_ISO_ASAP;
+        transfer.urb_mut().number_of_packets_or_stream_id = iso_packets.len() as u32;
+        Ok(transfer)
+    }
+
+    fn iso_packets(&self) -> &[usb_sys::usbdevfs_iso_packet_desc] {
+        if self.urb().urb_type != usb_sys::USBDEVFS_URB_TYPE_ISO {
+            return &[];
+        }
+        let num_packets = self.urb().number_of_packets_or_stream_id as usize;
+        // SAFETY:
+        // Safe because isochronous transfers are created with room for `num_packets`
+        // descriptors in transfer.urb.
+        unsafe { self.urb().iso_frame_desc.as_slice(num_packets) }
     }
 
     /// Get the status of a completed transfer.
     pub fn status(&self) -> TransferStatus {
         let status = self.urb().status;
         if status == 0 {
+            // The status of an isochronous transfer only reports whether it was submitted; errors
+            // on the bus are reported per packet.
+            if self.iso_packets().iter().any(|packet| packet.status != 0) {
+                return TransferStatus::Error;
+            }
             TransferStatus::Completed
         } else if status == -ENODEV {
             TransferStatus::NoDevice
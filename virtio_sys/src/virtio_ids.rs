@@ -18,6 +18,8 @@ pub const VIRTIO_ID_TPM: u32 = 62;
 pub const VIRTIO_ID_PVCLOCK: u32 = 61;
 // TODO: Remove this once the ID is included in the Linux headers.
 pub const VIRTIO_ID_MEDIA: u32 = 48;
+// TODO: Remove this once the ID is included in the Linux headers.
+pub const VIRTIO_ID_SPI: u32 = 45;
 
 pub const VIRTIO_ID_NET: u32 = 1;
 pub const VIRTIO_ID_BLOCK: u32 = 2;
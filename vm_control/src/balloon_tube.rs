@@ -11,7 +11,9 @@ use anyhow::Context;
 use anyhow::Result;
 use balloon_control::BalloonTubeCommand;
 use balloon_control::BalloonTubeResult;
+use balloon_control::BalloonWS;
 use base::error;
+use base::warn;
 use base::Error as SysError;
 use base::Tube;
 use serde::Deserialize;
@@ -29,11 +31,75 @@ pub enum BalloonControlCommand {
     },
     Stats,
     WorkingSet,
+    /// Like `WorkingSet`, but replies with a `WorkingSetReport`.
+    WorkingSetReport,
     WorkingSetConfig {
         bins: Vec<u32>,
         refresh_threshold: u32,
         report_threshold: u32,
     },
+    /// Connect to the socket listening at `socket_addr` and send it a `WorkingSetReport` for
+    /// every working set report of the guest, requested or not.
+    WorkingSetSubscribe {
+        socket_addr: String,
+    },
+    /// Stop sending reports to a socket given to `WorkingSetSubscribe`.
+    WorkingSetUnsubscribe {
+        socket_addr: String,
+    },
+}
+
+/// One bin of a working set histogram.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkingSetBin {
+    /// Upper bound of the time since the memory of the bin was last accessed, in milliseconds.
+    pub idle_age_ms: u64,
+    pub file_bytes: u64,
+    pub anon_bytes: u64,
+}
+
+/// A working set report of the guest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkingSetReport {
+    /// Bins of the histogram, by increasing idle age.
+    pub bins: Vec<WorkingSetBin>,
+    /// Size of the balloon in bytes when the report was received.
+    pub balloon_actual: u64,
+    /// Time the report was received, in milliseconds since the UNIX epoch.
+    pub timestamp_ms: u64,
+    /// Whether the report was requested by the host, rather than sent by the guest on its own.
+    pub requested: bool,
+}
+
+impl WorkingSetReport {
+    fn new(ws: &BalloonWS, balloon_actual: u64, timestamp_ms: u64, requested: bool) -> Self {
+        WorkingSetReport {
+            bins: ws
+                .ws
+                .iter()
+                .map(|bucket| WorkingSetBin {
+                    idle_age_ms: bucket.age,
+                    file_bytes: bucket.bytes[0],
+                    anon_bytes: bucket.bytes[1],
+                })
+                .collect(),
+            balloon_actual,
+            timestamp_ms,
+            requested,
+        }
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn connect_subscriber(socket_addr: &str) -> Result<Tube> {
+    let socket = base::UnixSeqpacket::connect(socket_addr)
+        .with_context(|| format!("failed to connect to {}", socket_addr))?;
+    Tube::try_from(socket).context("failed to create subscriber tube")
+}
+
+#[cfg(windows)]
+fn connect_subscriber(_socket_addr: &str) -> Result<Tube> {
+    bail!("working set subscriptions are not supported on Windows")
 }
 
 fn do_send(tube: &Tube, cmd: &BalloonControlCommand) -> Option<VmResponse> {
@@ -74,19 +140,32 @@ fn do_send(tube: &Tube, cmd: &BalloonControlCommand) -> Option<VmResponse> {
             Ok(_) => None,
             Err(_) => Some(VmResponse::Err(SysError::last())),
         },
-        BalloonControlCommand::WorkingSet => match tube.send(&BalloonTubeCommand::WorkingSet) {
-            Ok(_) => None,
-            Err(_) => Some(VmResponse::Err(SysError::last())),
-        },
+        BalloonControlCommand::WorkingSet | BalloonControlCommand::WorkingSetReport => {
+            match tube.send(&BalloonTubeCommand::WorkingSet) {
+                Ok(_) => None,
+                Err(_) => Some(VmResponse::Err(SysError::last())),
+            }
+        }
+        BalloonControlCommand::WorkingSetSubscribe { .. }
+        | BalloonControlCommand::WorkingSetUnsubscribe { .. } => {
+            unreachable!("subscriptions are handled by BalloonTube")
+        }
     }
 }
 
+fn set_ws_forward(tube: &Tube, enabled: bool) -> Result<()> {
+    tube.send(&BalloonTubeCommand::WorkingSetForward { enabled })
+        .context("failed to send ws forward command")
+}
+
 /// Utility for multiplexing a balloon tube between multiple control tubes. Commands
 /// are sent and processed serially.
 pub struct BalloonTube {
     tube: Tube,
     pending_queue: VecDeque<(BalloonControlCommand, Option<usize>)>,
     pending_adjust_with_completion: Option<(u64, usize)>,
+    // Sockets given to `WorkingSetSubscribe`, with their tubes.
+    ws_subscribers: Vec<(String, Tube)>,
 }
 
 impl BalloonTube {
@@ -95,6 +174,48 @@ impl BalloonTube {
             tube,
             pending_queue: VecDeque::new(),
             pending_adjust_with_completion: None,
+            ws_subscribers: Vec::new(),
+        }
+    }
+
+    fn subscribe_ws(&mut self, socket_addr: String) -> Result<()> {
+        if self
+            .ws_subscribers
+            .iter()
+            .any(|(addr, _)| *addr == socket_addr)
+        {
+            return Ok(());
+        }
+        let subscriber = connect_subscriber(&socket_addr)?;
+        if self.ws_subscribers.is_empty() {
+            set_ws_forward(&self.tube, true)?;
+        }
+        self.ws_subscribers.push((socket_addr, subscriber));
+        Ok(())
+    }
+
+    fn unsubscribe_ws(&mut self, socket_addr: &str) -> Result<()> {
+        let had_subscribers = !self.ws_subscribers.is_empty();
+        self.ws_subscribers.retain(|(addr, _)| addr != socket_addr);
+        if had_subscribers && self.ws_subscribers.is_empty() {
+            set_ws_forward(&self.tube, false)?;
+        }
+        Ok(())
+    }
+
+    /// Sends `report` to the subscribers, dropping the ones that cannot be reached.
+    fn push_ws_report(&mut self, report: &WorkingSetReport) {
+        let mut unreachable = Vec::new();
+        for (addr, subscriber) in &self.ws_subscribers {
+            if let Err(e) = subscriber.send(report) {
+                warn!("failed to send ws report to {}, unsubscribing: {}", addr, e);
+                unreachable.push(addr.clone());
+            }
+        }
+        for addr in unreachable {
+            if let Err(e) = self.unsubscribe_ws(&addr) {
+                error!("{:#}", e);
+            }
         }
     }
 
@@ -124,6 +245,20 @@ impl BalloonTube {
                 self.pending_adjust_with_completion = Some((num_bytes, key));
                 resp
             }
+            BalloonControlCommand::WorkingSetSubscribe { socket_addr } => {
+                let resp = match self.subscribe_ws(socket_addr) {
+                    Ok(()) => VmResponse::Ok,
+                    Err(e) => VmResponse::ErrString(format!("{:#}", e)),
+                };
+                key.map(|key| (resp, key))
+            }
+            BalloonControlCommand::WorkingSetUnsubscribe { socket_addr } => {
+                let resp = match self.unsubscribe_ws(&socket_addr) {
+                    Ok(()) => VmResponse::Ok,
+                    Err(e) => VmResponse::ErrString(format!("{:#}", e)),
+                };
+                key.map(|key| (resp, key))
+            }
             _ => {
                 if !self.pending_queue.is_empty() {
                     self.pending_queue.push_back((cmd, key));
@@ -158,7 +293,22 @@ impl BalloonTube {
             self.pending_adjust_with_completion.take();
             return Ok(vec![(VmResponse::Ok, key)]);
         }
+        if let BalloonTubeResult::WorkingSetPushed {
+            ws,
+            balloon_actual,
+            timestamp_ms,
+        } = res
+        {
+            self.push_ws_report(&WorkingSetReport::new(
+                &ws,
+                balloon_actual,
+                timestamp_ms,
+                false,
+            ));
+            return Ok(vec![]);
+        }
         let mut responses = vec![];
+        let mut ws_report = None;
         if self.pending_queue.is_empty() {
             bail!("Unexpected balloon tube result {:?}", res)
         }
@@ -178,12 +328,39 @@ impl BalloonTube {
             },
             (
                 BalloonControlCommand::WorkingSet,
-                BalloonTubeResult::WorkingSet { ws, balloon_actual },
-            ) => VmResponse::BalloonWS { ws, balloon_actual },
+                BalloonTubeResult::WorkingSet {
+                    ws,
+                    balloon_actual,
+                    timestamp_ms,
+                },
+            ) => {
+                ws_report = Some(WorkingSetReport::new(
+                    &ws,
+                    balloon_actual,
+                    timestamp_ms,
+                    true,
+                ));
+                VmResponse::BalloonWS { ws, balloon_actual }
+            }
+            (
+                BalloonControlCommand::WorkingSetReport,
+                BalloonTubeResult::WorkingSet {
+                    ws,
+                    balloon_actual,
+                    timestamp_ms,
+                },
+            ) => {
+                let report = WorkingSetReport::new(&ws, balloon_actual, timestamp_ms, true);
+                ws_report = Some(report.clone());
+                VmResponse::BalloonWorkingSetReport(report)
+            }
             (_, resp) => {
                 bail!("Unexpected balloon tube result {:?}", resp);
             }
         };
+        if let Some(report) = ws_report {
+            self.push_ws_report(&report);
+        }
         let key = self.pending_queue.pop_front().expect("entry disappeared").1;
         if let Some(key) = key {
             responses.push((resp, key))
@@ -206,6 +383,7 @@ impl BalloonTube {
 #[cfg(test)]
 mod tests {
     use balloon_control::BalloonStats;
+    use balloon_control::WSBucket;
 
     use super::*;
 
@@ -381,4 +559,93 @@ mod tests {
         assert_eq!(resp[0].1, 0xc0ffee);
         assert!(matches!(resp[0].0, VmResponse::BalloonStats { .. }));
     }
+
+    #[test]
+    fn test_ws_report_command() {
+        let (host, device) = Tube::pair().unwrap();
+        let mut balloon_tube = BalloonTube::new(host);
+
+        let resp = balloon_tube.send_cmd(BalloonControlCommand::WorkingSetReport, Some(0xc0ffee));
+        assert!(resp.is_none());
+        let cmd = device.recv::<BalloonTubeCommand>().unwrap();
+        assert!(matches!(cmd, BalloonTubeCommand::WorkingSet));
+
+        device
+            .send(&BalloonTubeResult::WorkingSet {
+                ws: BalloonWS {
+                    ws: vec![
+                        WSBucket {
+                            age: 1000,
+                            bytes: [1, 2],
+                        },
+                        WSBucket {
+                            age: 5000,
+                            bytes: [3, 4],
+                        },
+                    ],
+                },
+                balloon_actual: 0x1000,
+                timestamp_ms: 42,
+            })
+            .unwrap();
+        let resp = balloon_tube.recv().unwrap();
+        assert_eq!(resp.len(), 1);
+        assert_eq!(resp[0].1, 0xc0ffee);
+        let VmResponse::BalloonWorkingSetReport(report) = &resp[0].0 else {
+            panic!("unexpected response {:?}", resp[0].0);
+        };
+        assert_eq!(
+            *report,
+            WorkingSetReport {
+                bins: vec![
+                    WorkingSetBin {
+                        idle_age_ms: 1000,
+                        file_bytes: 1,
+                        anon_bytes: 2,
+                    },
+                    WorkingSetBin {
+                        idle_age_ms: 5000,
+                        file_bytes: 3,
+                        anon_bytes: 4,
+                    },
+                ],
+                balloon_actual: 0x1000,
+                timestamp_ms: 42,
+                requested: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_ws_pushed_between_requests() {
+        let (host, device) = Tube::pair().unwrap();
+        let mut balloon_tube = BalloonTube::new(host);
+
+        let resp = balloon_tube.send_cmd(BalloonControlCommand::Stats, Some(0xc0ffee));
+        assert!(resp.is_none());
+        let cmd = device.recv::<BalloonTubeCommand>().unwrap();
+        assert!(matches!(cmd, BalloonTubeCommand::Stats));
+
+        // A report pushed by the guest does not complete the pending request.
+        device
+            .send(&BalloonTubeResult::WorkingSetPushed {
+                ws: BalloonWS::new(),
+                balloon_actual: 0,
+                timestamp_ms: 0,
+            })
+            .unwrap();
+        let resp = balloon_tube.recv().unwrap();
+        assert_eq!(resp.len(), 0);
+
+        device
+            .send(&BalloonTubeResult::Stats {
+                stats: BalloonStats::default(),
+                balloon_actual: 0,
+            })
+            .unwrap();
+        let resp = balloon_tube.recv().unwrap();
+        assert_eq!(resp.len(), 1);
+        assert_eq!(resp[0].1, 0xc0ffee);
+        assert!(matches!(resp[0].0, VmResponse::BalloonStats { .. }));
+    }
 }
@@ -3,16 +3,29 @@
 // found in the LICENSE file.
 
 use std::fs::OpenOptions;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::net::TcpStream;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::net::ToSocketAddrs;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::os::fd::OwnedFd;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[cfg(feature = "pci-hotplug")]
 use anyhow::anyhow;
 use anyhow::Result as AnyHowResult;
 use base::open_file_or_duplicate;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use base::SafeDescriptor;
 use remain::sorted;
 use thiserror::Error;
 
+#[cfg(feature = "gpu")]
+pub use crate::gpu::do_gpu_configure_display;
 #[cfg(feature = "gpu")]
 pub use crate::gpu::do_gpu_display_add;
 #[cfg(feature = "gpu")]
@@ -20,6 +33,16 @@ pub use crate::gpu::do_gpu_display_list;
 #[cfg(feature = "gpu")]
 pub use crate::gpu::do_gpu_display_remove;
 #[cfg(feature = "gpu")]
+pub use crate::gpu::do_gpu_dump_resources;
+#[cfg(feature = "gpu")]
+pub use crate::gpu::do_gpu_fence_stats;
+#[cfg(feature = "gpu")]
+pub use crate::gpu::do_gpu_invalidate_backing;
+#[cfg(feature = "gpu")]
+pub use crate::gpu::do_gpu_kill_context;
+#[cfg(feature = "gpu")]
+pub use crate::gpu::do_gpu_set_display_edid;
+#[cfg(feature = "gpu")]
 pub use crate::gpu::do_gpu_set_display_mouse_mode;
 #[cfg(feature = "gpu")]
 pub use crate::gpu::ModifyGpuResult;
@@ -28,11 +51,15 @@ pub use crate::sys::handle_request_with_timeout;
 use crate::BatControlCommand;
 use crate::BatControlResult;
 use crate::BatteryType;
+use crate::PstoreRecordKind;
+use crate::RtcCommand;
 #[cfg(feature = "audio")]
 use crate::SndControlCommand;
 use crate::SwapCommand;
+use crate::TracingCommand;
 use crate::UsbControlCommand;
 use crate::UsbControlResult;
+use crate::UsbHidGadgetKind;
 use crate::VmRequest;
 use crate::VmResponse;
 use crate::USB_CONTROL_MAX_PORTS;
@@ -47,6 +74,8 @@ enum ModifyBatError {
 #[sorted]
 #[derive(Error, Debug)]
 pub enum ModifyUsbError {
+    #[error("failed to connect to {0}: {1}")]
+    FailedToConnect(String, std::io::Error),
     #[error("failed to open device {0}: {1}")]
     FailedToOpenDevice(PathBuf, base::Error),
     #[error("socket failed")]
@@ -122,6 +151,54 @@ pub fn do_net_remove<T: AsRef<Path> + std::fmt::Debug>(
     anyhow::bail!("Unsupported: pci-hotplug feature disabled");
 }
 
+#[cfg(feature = "pci-hotplug")]
+/// Send a `VmRequest` for hotplugging the disk described by `disk`, in the syntax of `--block`,
+/// that expects `VmResponse::PciHotPlugResponse`
+pub fn do_block_add<T: AsRef<Path> + std::fmt::Debug>(
+    disk: &str,
+    socket_path: T,
+) -> AnyHowResult<u8> {
+    let request = VmRequest::HotPlugBlockCommand(crate::BlockControlCommand::Add(disk.to_owned()));
+    let response = handle_request(&request, socket_path).map_err(|()| anyhow!("socket error: "))?;
+    match response {
+        VmResponse::PciHotPlugResponse { bus } => Ok(bus),
+        e => Err(anyhow!("Unexpected response: {:#}", e)),
+    }
+}
+
+#[cfg(not(feature = "pci-hotplug"))]
+/// Send a `VmRequest` for hotplugging the disk described by `disk`, in the syntax of `--block`,
+/// that expects `VmResponse::PciHotPlugResponse`
+pub fn do_block_add<T: AsRef<Path> + std::fmt::Debug>(
+    _disk: &str,
+    _socket_path: T,
+) -> AnyHowResult<u8> {
+    anyhow::bail!("Unsupported: pci-hotplug feature disabled");
+}
+
+#[cfg(feature = "pci-hotplug")]
+/// Send a `VmRequest` for removing a hotplugged disk that expects `VmResponse::Ok`
+pub fn do_block_remove<T: AsRef<Path> + std::fmt::Debug>(
+    bus_num: u8,
+    socket_path: T,
+) -> AnyHowResult<()> {
+    let request = VmRequest::HotPlugBlockCommand(crate::BlockControlCommand::Remove(bus_num));
+    let response = handle_request(&request, socket_path).map_err(|()| anyhow!("socket error: "))?;
+    match response {
+        VmResponse::Ok => Ok(()),
+        e => Err(anyhow!("Unexpected response: {:#}", e)),
+    }
+}
+
+#[cfg(not(feature = "pci-hotplug"))]
+/// Send a `VmRequest` for removing a hotplugged disk that expects `VmResponse::Ok`
+pub fn do_block_remove<T: AsRef<Path> + std::fmt::Debug>(
+    _bus_num: u8,
+    _socket_path: T,
+) -> AnyHowResult<()> {
+    anyhow::bail!("Unsupported: pci-hotplug feature disabled");
+}
+
 pub fn do_usb_attach<T: AsRef<Path> + std::fmt::Debug>(
     socket_path: T,
     dev_path: &Path,
@@ -154,6 +231,86 @@ pub fn do_security_key_attach<T: AsRef<Path> + std::fmt::Debug>(
     }
 }
 
+/// TCP port usbipd listens on by default.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const USBIP_DEFAULT_PORT: u16 = 3240;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn do_usbip_attach<T: AsRef<Path> + std::fmt::Debug>(
+    socket_path: T,
+    server: &str,
+    busid: &str,
+) -> ModifyUsbResult<UsbControlResult> {
+    let stream = match server.to_socket_addrs() {
+        Ok(_) => TcpStream::connect(server),
+        // `server` does not name a port, use the default one.
+        Err(_) => TcpStream::connect((server, USBIP_DEFAULT_PORT)),
+    }
+    .map_err(|e| ModifyUsbError::FailedToConnect(server.to_string(), e))?;
+
+    let request = VmRequest::UsbCommand(UsbControlCommand::AttachUsbipDevice {
+        socket: SafeDescriptor::from(OwnedFd::from(stream)),
+        busid: busid.to_string(),
+    });
+    let response =
+        handle_request(&request, socket_path).map_err(|_| ModifyUsbError::SocketFailed)?;
+    match response {
+        VmResponse::UsbResponse(usb_resp) => Ok(usb_resp),
+        r => Err(ModifyUsbError::UnexpectedResponse(r)),
+    }
+}
+
+pub fn do_usb_hid_attach<T: AsRef<Path> + std::fmt::Debug>(
+    socket_path: T,
+    kind: UsbHidGadgetKind,
+) -> ModifyUsbResult<UsbControlResult> {
+    let request = VmRequest::UsbCommand(UsbControlCommand::AttachHidGadget { kind });
+    let response =
+        handle_request(&request, socket_path).map_err(|_| ModifyUsbError::SocketFailed)?;
+    match response {
+        VmResponse::UsbResponse(usb_resp) => Ok(usb_resp),
+        r => Err(ModifyUsbError::UnexpectedResponse(r)),
+    }
+}
+
+/// Attaches an emulated HID device that reads its input reports from the stream socket listening
+/// on `report_socket_path`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn do_usb_hid_attach_socket<T: AsRef<Path> + std::fmt::Debug>(
+    socket_path: T,
+    kind: UsbHidGadgetKind,
+    report_socket_path: &Path,
+) -> ModifyUsbResult<UsbControlResult> {
+    let stream = UnixStream::connect(report_socket_path).map_err(|e| {
+        ModifyUsbError::FailedToConnect(report_socket_path.display().to_string(), e)
+    })?;
+
+    let request = VmRequest::UsbCommand(UsbControlCommand::AttachHidGadgetWithSocket {
+        kind,
+        socket: SafeDescriptor::from(OwnedFd::from(stream)),
+    });
+    let response =
+        handle_request(&request, socket_path).map_err(|_| ModifyUsbError::SocketFailed)?;
+    match response {
+        VmResponse::UsbResponse(usb_resp) => Ok(usb_resp),
+        r => Err(ModifyUsbError::UnexpectedResponse(r)),
+    }
+}
+
+pub fn do_usb_hid_report<T: AsRef<Path> + std::fmt::Debug>(
+    socket_path: T,
+    port: u8,
+    report: Vec<u8>,
+) -> ModifyUsbResult<UsbControlResult> {
+    let request = VmRequest::UsbCommand(UsbControlCommand::SendHidReport { port, report });
+    let response =
+        handle_request(&request, socket_path).map_err(|_| ModifyUsbError::SocketFailed)?;
+    match response {
+        VmResponse::UsbResponse(usb_resp) => Ok(usb_resp),
+        r => Err(ModifyUsbError::UnexpectedResponse(r)),
+    }
+}
+
 pub fn do_usb_detach<T: AsRef<Path> + std::fmt::Debug>(
     socket_path: T,
     port: u8,
@@ -255,4 +412,182 @@ pub fn do_swap_status<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRe
     }
 }
 
+pub fn do_shared_memory_stats<T: AsRef<Path> + std::fmt::Debug>(
+    socket_path: T,
+) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::SharedMemoryStats, socket_path)?;
+    match &response {
+        VmResponse::SharedMemoryStats(_) => {
+            println!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r:?}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_ksm_stats<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::KsmStats, socket_path)?;
+    match &response {
+        VmResponse::KsmStats(_) => {
+            println!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r:?}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_numa_binding_stats<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::NumaBindingStats, socket_path)?;
+    match &response {
+        VmResponse::NumaBindingStats(_) => {
+            println!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r:?}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_query_devices<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::QueryDevices, socket_path)?;
+    match &response {
+        VmResponse::Devices(_) => {
+            println!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r:?}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_query_pstore<T: AsRef<Path> + std::fmt::Debug>(
+    socket_path: T,
+    kind: Option<PstoreRecordKind>,
+) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::PstoreRecords { kind }, socket_path)?;
+    match &response {
+        VmResponse::PstoreRecords(_) => {
+            print!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r:?}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_vcpu_stats<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::VcpuStats, socket_path)?;
+    match &response {
+        VmResponse::VcpuStats(_) => {
+            print!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r:?}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_irq_latency_stats<T: AsRef<Path> + std::fmt::Debug>(socket_path: T) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::IrqLatencyStats, socket_path)?;
+    match &response {
+        VmResponse::IrqLatencyStats(_) => {
+            print!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_tracing<T: AsRef<Path> + std::fmt::Debug>(
+    command: TracingCommand,
+    socket_path: T,
+) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::Tracing(command), socket_path)?;
+    match &response {
+        VmResponse::TracingCategories(_) => {
+            print!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r}");
+            Err(())
+        }
+    }
+}
+
+pub fn do_rtc<T: AsRef<Path> + std::fmt::Debug>(
+    command: RtcCommand,
+    socket_path: T,
+) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::RtcCommand(command), socket_path)?;
+    match &response {
+        VmResponse::RtcOffset { .. } => {
+            println!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r}");
+            Err(())
+        }
+    }
+}
+
+/// Asks the VM to apply the changes of `config`, the JSON serialization of its new configuration,
+/// and prints the changes that were applied and rejected. Fails if any change was rejected.
+pub fn do_reload_config<T: AsRef<Path> + std::fmt::Debug>(
+    config: String,
+    socket_path: T,
+) -> VmsRequestResult {
+    let response = handle_request(&VmRequest::ReloadConfig { config }, socket_path)?;
+    match &response {
+        VmResponse::ConfigReloadReport(report) => {
+            println!("{}", response);
+            if report.rejected.is_empty() {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+        r => {
+            println!("unexpected response: {r}");
+            Err(())
+        }
+    }
+}
+
+/// Presses the power button of the VM and waits until it is stopped, which is forced after
+/// `timeout` if the guest has not shut down by then.
+pub fn do_powerdown<T: AsRef<Path> + std::fmt::Debug>(
+    timeout: Duration,
+    socket_path: T,
+) -> VmsRequestResult {
+    match handle_request(&VmRequest::Powerdown { timeout }, socket_path)? {
+        response @ VmResponse::PoweredDown { .. } => {
+            println!("{}", response);
+            Ok(())
+        }
+        r => {
+            println!("unexpected response: {r}");
+            Err(())
+        }
+    }
+}
+
 pub type HandleRequestResult = std::result::Result<VmResponse, ()>;
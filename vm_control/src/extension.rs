@@ -0,0 +1,146 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Control channel between crosvm and an out-of-process device extension.
+//!
+//! An extension listens on a `SOCK_SEQPACKET` Unix socket given to crosvm with `--extension`. Each
+//! message is a single packet holding a JSON encoded `ExtensionRequest` or `ExtensionResponse`,
+//! with file descriptors attached as `SCM_RIGHTS` ancillary data, i.e. the `Tube` wire format.
+//!
+//! After connecting, crosvm sends `ExtensionRequest::Hello` and the extension answers with the
+//! devices it provides. Each device comes with one end of a connected vhost-user socket, over which
+//! crosvm shares guest memory and the queue notification eventfds as with any vhost-user backend.
+//! crosvm then only sends `ExtensionRequest::Event` notifications, which are not answered.
+
+use std::os::unix::net::UnixStream;
+
+use base::with_as_descriptor;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Version of the extension protocol implemented by this crosvm.
+pub const EXTENSION_PROTOCOL_VERSION: u32 = 1;
+
+/// A message sent by crosvm to an extension.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ExtensionRequest {
+    /// First message on the channel. Answered with `ExtensionResponse::Devices`.
+    Hello { version: u32 },
+    /// A change in the state of the VM.
+    Event(VmEvent),
+}
+
+/// A message sent by an extension to crosvm.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ExtensionResponse {
+    /// The devices provided by the extension, to be added to the VM.
+    Devices(Vec<ExtensionDeviceInfo>),
+    /// The extension cannot be used, for instance because of a protocol version mismatch.
+    Err(String),
+}
+
+/// A device provided by an extension.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExtensionDeviceInfo {
+    /// Virtio device ID.
+    pub virtio_type: u32,
+    /// Maximum size of the device's queues, `Queue::MAX_SIZE` if `None`.
+    pub max_queue_size: Option<u16>,
+    /// Connected vhost-user socket, with the extension acting as the backend.
+    #[serde(with = "with_as_descriptor")]
+    pub socket: UnixStream,
+}
+
+/// Changes in the state of the VM that extensions are notified of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VmEvent {
+    /// The vCPUs started running.
+    Running,
+    /// The vCPUs were suspended, e.g. by `crosvm suspend`.
+    Suspended,
+    /// The vCPUs resumed after being suspended.
+    Resumed,
+    /// The VM is shutting down. This is the last message on the channel.
+    Exiting,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+
+    use base::Tube;
+
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let (crosvm, extension) = Tube::pair().unwrap();
+
+        crosvm
+            .send(&ExtensionRequest::Hello {
+                version: EXTENSION_PROTOCOL_VERSION,
+            })
+            .unwrap();
+        assert!(matches!(
+            extension.recv::<ExtensionRequest>().unwrap(),
+            ExtensionRequest::Hello {
+                version: EXTENSION_PROTOCOL_VERSION
+            }
+        ));
+
+        for event in [
+            VmEvent::Running,
+            VmEvent::Suspended,
+            VmEvent::Resumed,
+            VmEvent::Exiting,
+        ] {
+            crosvm.send(&ExtensionRequest::Event(event)).unwrap();
+            match extension.recv::<ExtensionRequest>().unwrap() {
+                ExtensionRequest::Event(received) => assert_eq!(received, event),
+                request => panic!("unexpected request {:?}", request),
+            }
+        }
+    }
+
+    #[test]
+    fn devices_round_trip() {
+        let (crosvm, extension) = Tube::pair().unwrap();
+        let (frontend, mut backend) = UnixStream::pair().unwrap();
+
+        extension
+            .send(&ExtensionResponse::Devices(vec![ExtensionDeviceInfo {
+                virtio_type: 4,
+                max_queue_size: Some(64),
+                socket: frontend,
+            }]))
+            .unwrap();
+        let mut devices = match crosvm.recv::<ExtensionResponse>().unwrap() {
+            ExtensionResponse::Devices(devices) => devices,
+            response => panic!("unexpected response {:?}", response),
+        };
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].virtio_type, 4);
+        assert_eq!(devices[0].max_queue_size, Some(64));
+
+        // The received socket is still connected to the extension's end.
+        devices[0].socket.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        backend.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn error_round_trip() {
+        let (crosvm, extension) = Tube::pair().unwrap();
+
+        extension
+            .send(&ExtensionResponse::Err("unsupported".to_string()))
+            .unwrap();
+        match crosvm.recv::<ExtensionResponse>().unwrap() {
+            ExtensionResponse::Err(msg) => assert_eq!(msg, "unsupported"),
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+}
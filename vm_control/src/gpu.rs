@@ -6,10 +6,13 @@ use std::collections::BTreeMap as Map;
 use std::fmt;
 use std::fmt::Display;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
 use serde::Deserialize;
 use serde::Serialize;
 use serde_keyvalue::FromKeyValues;
+use vm_memory::GuestAddress;
 
 pub use crate::sys::handle_request;
 pub use crate::sys::DisplayMode;
@@ -121,6 +124,91 @@ impl Default for DisplayParameters {
     }
 }
 
+/// Clockwise rotation of a display from the orientation it was connected with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisplayRotation {
+    #[default]
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl DisplayRotation {
+    /// Returns whether the width and height of the display are exchanged by the rotation.
+    pub fn swaps_axes(&self) -> bool {
+        matches!(self, DisplayRotation::Rotate90 | DisplayRotation::Rotate270)
+    }
+}
+
+impl FromStr for DisplayRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(DisplayRotation::Rotate0),
+            "90" => Ok(DisplayRotation::Rotate90),
+            "180" => Ok(DisplayRotation::Rotate180),
+            "270" => Ok(DisplayRotation::Rotate270),
+            _ => Err(format!(
+                "invalid rotation {}, expected 0, 90, 180 or 270",
+                s
+            )),
+        }
+    }
+}
+
+/// Changes made to a connected display by `GpuControlCommand::ConfigureDisplay`. The settings
+/// left to `None` are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisplayConfig {
+    /// Resolution of the display before rotation.
+    pub size: Option<(u32, u32)>,
+    /// Scale factor in percent, applied to the DPI the display was connected with.
+    pub scale_percent: Option<u32>,
+    pub rotation: Option<DisplayRotation>,
+}
+
+/// Resource of the GPU device still referenced by the guest, listed by
+/// `GpuControlCommand::DumpResources`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GpuResourceInfo {
+    pub resource_id: u32,
+    pub size: u64,
+    pub blob: bool,
+    pub blob_mem: u32,
+    pub blob_flags: u32,
+    /// Names of the rutabaga components that created or imported the resource.
+    pub components: Vec<String>,
+    pub has_backing: bool,
+    /// First fence created after the last host access to the resource.
+    pub last_access_fence: Option<u64>,
+}
+
+/// Fence counters of a ring of the GPU device, listed by `GpuControlCommand::FenceStats`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GpuFenceRingInfo {
+    /// Context owning the ring, or `None` for the global timeline.
+    pub ctx_id: Option<u32>,
+    pub ring_idx: Option<u8>,
+    pub created: u64,
+    pub signaled: u64,
+    pub pending: usize,
+}
+
+/// Fence of the GPU device left unsignaled beyond the stuck threshold, listed by
+/// `GpuControlCommand::FenceStats`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GpuStuckFenceInfo {
+    pub fence_id: u64,
+    pub ctx_id: u32,
+    /// Ring of the context the fence was created on, or `None` for the global timeline.
+    pub ring_idx: Option<u8>,
+    pub pending_ms: u128,
+    /// Size of the last command buffer the context submitted before the fence was created.
+    pub last_command_size: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum GpuControlCommand {
     AddDisplays {
@@ -134,6 +222,35 @@ pub enum GpuControlCommand {
         display_id: u32,
         mouse_mode: MouseMode,
     },
+    /// Changes the resolution, scale or rotation of a connected display, which the guest is
+    /// notified of.
+    ConfigureDisplay {
+        display_id: u32,
+        config: DisplayConfig,
+    },
+    /// Replaces the EDID of a connected display, or reverts to the EDID generated from its
+    /// parameters if `edid` is None. The guest is notified so that it probes the modes again.
+    SetDisplayEdid {
+        display_id: u32,
+        edid: Option<Vec<u8>>,
+    },
+    /// Lists the resources that the guest still holds a reference on.
+    DumpResources,
+    /// Lists the fence counters of every ring, and the fences left unsignaled for longer than
+    /// `stuck_threshold`.
+    FenceStats {
+        stuck_threshold: Duration,
+    },
+    /// Destroys a guest context that hung the GPU: its pending fences are signaled and its later
+    /// commands fail.
+    KillContext {
+        ctx_id: u32,
+    },
+    /// Detaches the backing of the resources attached to guest memory overlapping one of the
+    /// `(address, size)` ranges, before that memory is removed from the guest.
+    InvalidateBacking {
+        ranges: Vec<(GuestAddress, u64)>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -150,6 +267,20 @@ pub enum GpuControlResult {
         display_id: u32,
     },
     DisplayMouseModeSet,
+    DisplayConfigured,
+    DisplayEdidSet,
+    ResourceList {
+        resources: Vec<GpuResourceInfo>,
+    },
+    FenceStats {
+        rings: Vec<GpuFenceRingInfo>,
+        stuck: Vec<GpuStuckFenceInfo>,
+    },
+    ContextKilled,
+    /// Resources whose backing was detached by `GpuControlCommand::InvalidateBacking`.
+    BackingInvalidated {
+        resource_ids: Vec<u32>,
+    },
     ErrString(String),
 }
 
@@ -174,6 +305,26 @@ impl Display for GpuControlResult {
             ),
             NoSuchDisplay { display_id } => write!(f, "no_such_display {}", display_id),
             DisplayMouseModeSet => write!(f, "display_mouse_mode_set"),
+            DisplayConfigured => write!(f, "display_configured"),
+            DisplayEdidSet => write!(f, "display_edid_set"),
+            ResourceList { resources } => {
+                let json_pretty =
+                    serde_json::to_string_pretty(resources).map_err(|_| std::fmt::Error)?;
+                write!(f, "{}", json_pretty)
+            }
+            FenceStats { rings, stuck } => {
+                let json: serde_json::Value = serde_json::json!({
+                    "rings": rings,
+                    "stuck": stuck,
+                });
+                let json_pretty =
+                    serde_json::to_string_pretty(&json).map_err(|_| std::fmt::Error)?;
+                write!(f, "{}", json_pretty)
+            }
+            ContextKilled => write!(f, "context_killed"),
+            BackingInvalidated { resource_ids } => {
+                write!(f, "backing_invalidated {:?}", resource_ids)
+            }
             ErrString(reason) => write!(f, "err_string {}", reason),
         }
     }
@@ -252,3 +403,64 @@ pub fn do_gpu_set_display_mouse_mode<T: AsRef<Path> + std::fmt::Debug>(
         .map_err(|_| ModifyGpuError::SocketFailed)?
         .into()
 }
+
+pub fn do_gpu_configure_display<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    display_id: u32,
+    config: DisplayConfig,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::ConfigureDisplay { display_id, config });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_set_display_edid<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    display_id: u32,
+    edid: Option<Vec<u8>>,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::SetDisplayEdid { display_id, edid });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_dump_resources<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::DumpResources);
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_fence_stats<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    stuck_threshold: Duration,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::FenceStats { stuck_threshold });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_kill_context<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    ctx_id: u32,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::KillContext { ctx_id });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_invalidate_backing<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    ranges: Vec<(GuestAddress, u64)>,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::InvalidateBacking { ranges });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
@@ -0,0 +1,642 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Protocol of the crosvm guest agent, a process running in the guest that lets the host run
+//! commands, copy files, set the clock and shut the guest down.
+//!
+//! The host reaches the agent either over vsock, on port `GUEST_AGENT_VSOCK_PORT` of the guest, or
+//! through a virtio-console port named `GUEST_AGENT_PORT_NAME` whose host end is a Unix stream
+//! socket (`--serial type=unix-stream,hardware=virtio-console,name=...`).
+//!
+//! Each message is a JSON encoded `GuestAgentRequest` or `GuestAgentResponse` preceded by its size
+//! as a little-endian `u32`. The host sends a request and waits for its response before sending
+//! the next one. The first request on a connection is `GuestAgentRequest::Hello`.
+
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use base::error;
+use base::linux::vsock::VsockCid;
+use base::linux::vsock::VsockListener;
+use base::linux::vsock::VsockStream;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Version of the guest agent protocol implemented by this crate.
+pub const GUEST_AGENT_PROTOCOL_VERSION: u32 = 1;
+
+/// Vsock port the guest agent listens on by default.
+pub const GUEST_AGENT_VSOCK_PORT: u32 = 9000;
+
+/// Name of the virtio-console port the guest agent uses by default.
+pub const GUEST_AGENT_PORT_NAME: &str = "org.chromium.crosvm.guest_agent";
+
+/// Maximum size of an encoded message.
+pub const MAX_MESSAGE_SIZE: usize = 8 << 20;
+
+/// Size of the chunks in which files are copied.
+const FILE_CHUNK_SIZE: u32 = 256 << 10;
+
+/// A request sent by the host to the guest agent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GuestAgentRequest {
+    /// First request on a connection. Answered with `GuestAgentResponse::Hello`.
+    Hello { version: u32 },
+    /// Runs a program to completion. Answered with `GuestAgentResponse::Exec`.
+    Exec(ExecRequest),
+    /// Reads up to `len` bytes of a file from `offset`. Answered with `GuestAgentResponse::Data`,
+    /// which is shorter than `len` only at the end of the file.
+    ReadFile { path: String, offset: u64, len: u32 },
+    /// Writes `data` to a file at `offset`, creating it with `mode` if it does not exist and
+    /// truncating it first if `truncate` is set.
+    WriteFile {
+        path: String,
+        offset: u64,
+        data: Vec<u8>,
+        truncate: bool,
+        mode: u32,
+    },
+    /// Sets the realtime clock of the guest to `since_epoch` after the Unix epoch.
+    SetTime { since_epoch: Duration },
+    /// Powers the guest off, or reboots it if `reboot` is set. The agent answers before shutting
+    /// down.
+    Shutdown { reboot: bool },
+}
+
+/// A program to run in the guest.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecRequest {
+    /// Program to run, looked up in the `PATH` of the agent if it has no slash.
+    pub program: String,
+    pub args: Vec<String>,
+    /// Variables added to the environment of the agent.
+    pub env: Vec<(String, String)>,
+    /// Data written to the standard input of the program.
+    pub stdin: Vec<u8>,
+}
+
+/// Outcome of an `ExecRequest`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecOutput {
+    /// Exit code of the program, `None` if it was killed by a signal.
+    pub exit_code: Option<i32>,
+    /// Signal that killed the program.
+    pub signal: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// A response sent by the guest agent to the host.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GuestAgentResponse {
+    /// Protocol version implemented by the agent.
+    Hello {
+        version: u32,
+    },
+    Exec(ExecOutput),
+    Data(Vec<u8>),
+    Ok,
+    /// The request failed.
+    Err(String),
+}
+
+/// Writes `message` to `writer` with its size.
+pub fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    let data = serde_json::to_vec(message).context("failed to serialize message")?;
+    if data.len() > MAX_MESSAGE_SIZE {
+        bail!("message of {} bytes is too large", data.len());
+    }
+    writer
+        .write_all(&(data.len() as u32).to_le_bytes())
+        .and_then(|_| writer.write_all(&data))
+        .and_then(|_| writer.flush())
+        .context("failed to write message")
+}
+
+/// Reads a message written by `write_message` from `reader`. Returns `None` if the connection was
+/// closed before the start of a message.
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut size = [0u8; 4];
+    match reader.read_exact(&mut size) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("failed to read message size"),
+    }
+    let size = u32::from_le_bytes(size) as usize;
+    if size > MAX_MESSAGE_SIZE {
+        bail!("message of {} bytes is too large", size);
+    }
+    let mut data = vec![0u8; size];
+    reader
+        .read_exact(&mut data)
+        .context("failed to read message")?;
+    serde_json::from_slice(&data)
+        .map(Some)
+        .context("failed to deserialize message")
+}
+
+/// Where the host reaches a guest agent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GuestAgentAddress {
+    /// Vsock port of a guest, written `vsock:CID[:PORT]`.
+    Vsock { cid: u32, port: u32 },
+    /// Host end of a virtio-console port, written as the path of the socket.
+    Unix(PathBuf),
+}
+
+impl FromStr for GuestAgentAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some(vsock) = s.strip_prefix("vsock:") else {
+            return Ok(GuestAgentAddress::Unix(PathBuf::from(s)));
+        };
+        let (cid, port) = match vsock.split_once(':') {
+            Some((cid, port)) => (cid, Some(port)),
+            None => (vsock, None),
+        };
+        let cid = cid
+            .parse()
+            .map_err(|_| format!("invalid vsock CID: {}", cid))?;
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("invalid vsock port: {}", port))?,
+            None => GUEST_AGENT_VSOCK_PORT,
+        };
+        Ok(GuestAgentAddress::Vsock { cid, port })
+    }
+}
+
+/// Stream over which a host talks to a guest agent.
+pub trait GuestAgentStream: Read + Write + Send {}
+
+impl GuestAgentStream for UnixStream {}
+impl GuestAgentStream for VsockStream {}
+
+/// Host end of a connection to a guest agent.
+pub struct GuestAgentClient {
+    stream: Box<dyn GuestAgentStream>,
+}
+
+impl GuestAgentClient {
+    /// Connects to the agent at `address`.
+    pub fn connect(address: &GuestAgentAddress) -> Result<Self> {
+        let stream: Box<dyn GuestAgentStream> = match address {
+            GuestAgentAddress::Vsock { cid, port } => Box::new(
+                VsockStream::connect((VsockCid::from(*cid), *port))
+                    .with_context(|| format!("failed to connect to vsock:{}:{}", cid, port))?,
+            ),
+            GuestAgentAddress::Unix(path) => Box::new(
+                UnixStream::connect(path)
+                    .with_context(|| format!("failed to connect to {}", path.display()))?,
+            ),
+        };
+        Self::new(stream)
+    }
+
+    /// Starts a session with the agent on the other end of `stream`.
+    pub fn new(stream: Box<dyn GuestAgentStream>) -> Result<Self> {
+        let mut client = GuestAgentClient { stream };
+        match client.call(GuestAgentRequest::Hello {
+            version: GUEST_AGENT_PROTOCOL_VERSION,
+        })? {
+            GuestAgentResponse::Hello { version } if version == GUEST_AGENT_PROTOCOL_VERSION => {
+                Ok(client)
+            }
+            GuestAgentResponse::Hello { version } => {
+                bail!("unsupported guest agent protocol version {}", version)
+            }
+            r => bail!("unexpected response: {:?}", r),
+        }
+    }
+
+    /// Sends `request` and returns the response, failing if the agent reports an error.
+    fn call(&mut self, request: GuestAgentRequest) -> Result<GuestAgentResponse> {
+        write_message(&mut self.stream, &request)?;
+        match read_message(&mut self.stream)? {
+            Some(GuestAgentResponse::Err(e)) => Err(anyhow!(e)),
+            Some(response) => Ok(response),
+            None => bail!("the guest agent closed the connection"),
+        }
+    }
+
+    fn call_ok(&mut self, request: GuestAgentRequest) -> Result<()> {
+        match self.call(request)? {
+            GuestAgentResponse::Ok => Ok(()),
+            r => bail!("unexpected response: {:?}", r),
+        }
+    }
+
+    /// Runs a program in the guest and waits for it to exit.
+    pub fn exec(&mut self, request: ExecRequest) -> Result<ExecOutput> {
+        match self.call(GuestAgentRequest::Exec(request))? {
+            GuestAgentResponse::Exec(output) => Ok(output),
+            r => bail!("unexpected response: {:?}", r),
+        }
+    }
+
+    /// Copies the guest file `path` to `output` and returns its size.
+    pub fn read_file(&mut self, path: &str, output: &mut impl Write) -> Result<u64> {
+        let mut offset = 0;
+        loop {
+            let data = match self.call(GuestAgentRequest::ReadFile {
+                path: path.to_string(),
+                offset,
+                len: FILE_CHUNK_SIZE,
+            })? {
+                GuestAgentResponse::Data(data) => data,
+                r => bail!("unexpected response: {:?}", r),
+            };
+            output.write_all(&data).context("failed to write file")?;
+            offset += data.len() as u64;
+            if data.len() < FILE_CHUNK_SIZE as usize {
+                return Ok(offset);
+            }
+        }
+    }
+
+    /// Copies `input` to the guest file `path`, replacing its content, and returns the number of
+    /// bytes copied. The file is created with `mode` if it does not exist.
+    pub fn write_file(&mut self, path: &str, input: &mut impl Read, mode: u32) -> Result<u64> {
+        let mut offset = 0;
+        loop {
+            let mut data = Vec::new();
+            input
+                .take(FILE_CHUNK_SIZE as u64)
+                .read_to_end(&mut data)
+                .context("failed to read file")?;
+            // The first chunk is always sent, so that empty files are created.
+            if data.is_empty() && offset > 0 {
+                return Ok(offset);
+            }
+            let len = data.len();
+            self.call_ok(GuestAgentRequest::WriteFile {
+                path: path.to_string(),
+                offset,
+                data,
+                truncate: offset == 0,
+                mode,
+            })?;
+            offset += len as u64;
+            if len < FILE_CHUNK_SIZE as usize {
+                return Ok(offset);
+            }
+        }
+    }
+
+    /// Sets the clock of the guest to the time of the host.
+    pub fn sync_time(&mut self) -> Result<()> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("host time is before the epoch")?;
+        self.call_ok(GuestAgentRequest::SetTime { since_epoch })
+    }
+
+    /// Powers the guest off or reboots it.
+    pub fn shutdown(&mut self, reboot: bool) -> Result<()> {
+        self.call_ok(GuestAgentRequest::Shutdown { reboot })
+    }
+}
+
+/// Implementation of the requests in the guest.
+pub trait GuestAgentHandler {
+    fn exec(&mut self, request: ExecRequest) -> io::Result<ExecOutput>;
+    fn read_file(&mut self, path: &str, offset: u64, len: u32) -> io::Result<Vec<u8>>;
+    fn write_file(
+        &mut self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        truncate: bool,
+        mode: u32,
+    ) -> io::Result<()>;
+    fn set_time(&mut self, since_epoch: Duration) -> io::Result<()>;
+    /// Only returns on failure.
+    fn shutdown(&mut self, reboot: bool) -> io::Result<()>;
+}
+
+/// Answers the requests received on `stream` with `handler` until the host disconnects.
+pub fn serve(stream: &mut (impl Read + Write), handler: &mut impl GuestAgentHandler) -> Result<()> {
+    while let Some(request) = read_message::<GuestAgentRequest>(stream)? {
+        let (response, shutdown) = match request {
+            GuestAgentRequest::Hello { .. } => (
+                Ok(GuestAgentResponse::Hello {
+                    version: GUEST_AGENT_PROTOCOL_VERSION,
+                }),
+                None,
+            ),
+            GuestAgentRequest::Exec(request) => {
+                (handler.exec(request).map(GuestAgentResponse::Exec), None)
+            }
+            GuestAgentRequest::ReadFile { path, offset, len } => (
+                handler
+                    .read_file(&path, offset, len)
+                    .map(GuestAgentResponse::Data),
+                None,
+            ),
+            GuestAgentRequest::WriteFile {
+                path,
+                offset,
+                data,
+                truncate,
+                mode,
+            } => (
+                handler
+                    .write_file(&path, offset, &data, truncate, mode)
+                    .map(|_| GuestAgentResponse::Ok),
+                None,
+            ),
+            GuestAgentRequest::SetTime { since_epoch } => (
+                handler
+                    .set_time(since_epoch)
+                    .map(|_| GuestAgentResponse::Ok),
+                None,
+            ),
+            GuestAgentRequest::Shutdown { reboot } => (Ok(GuestAgentResponse::Ok), Some(reboot)),
+        };
+        let response = response.unwrap_or_else(|e| GuestAgentResponse::Err(e.to_string()));
+        if let Err(e) = write_message(stream, &response) {
+            // The response may be too large, e.g. the output of a command. Report it to the host
+            // rather than leaving it waiting.
+            write_message(stream, &GuestAgentResponse::Err(format!("{:#}", e)))?;
+        }
+        if let Some(reboot) = shutdown {
+            handler.shutdown(reboot).context("failed to shut down")?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the agent in the guest, answering the connections to vsock port `port` with
+/// `SystemGuestAgent`. Never returns unless the port cannot be listened on.
+pub fn serve_vsock(port: u32) -> Result<()> {
+    let listener = VsockListener::bind((VsockCid::Any, port))
+        .with_context(|| format!("failed to listen on vsock port {}", port))?;
+    loop {
+        let (mut stream, addr) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("failed to accept guest agent connection: {}", e);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            if let Err(e) = serve(&mut stream, &mut SystemGuestAgent) {
+                error!("guest agent connection from {} failed: {:#}", addr, e);
+            }
+        });
+    }
+}
+
+/// Runs the agent in the guest on the virtio-console port named `name`, with `SystemGuestAgent`.
+/// Reopens the port whenever the host disconnects.
+pub fn serve_console_port(name: &str) -> ! {
+    let path = PathBuf::from("/dev/virtio-ports").join(name);
+    loop {
+        let result = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))
+            .and_then(|mut port| serve(&mut port, &mut SystemGuestAgent));
+        if let Err(e) = result {
+            error!("guest agent on {} failed: {:#}", path.display(), e);
+        }
+        // The port reads as closed while the host end is not connected.
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// `GuestAgentHandler` acting on the system the agent runs on.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemGuestAgent;
+
+impl GuestAgentHandler for SystemGuestAgent {
+    fn exec(&mut self, request: ExecRequest) -> io::Result<ExecOutput> {
+        let mut child = Command::new(&request.program)
+            .args(&request.args)
+            .envs(request.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Feed stdin from another thread, as the program may fill its output pipes before reading
+        // all its input.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let data = request.stdin;
+        let writer = thread::spawn(move || {
+            if let Err(e) = stdin.write_all(&data) {
+                error!("failed to write the standard input of the program: {}", e);
+            }
+        });
+        let output = child.wait_with_output()?;
+        let _ = writer.join();
+        Ok(ExecOutput {
+            exit_code: output.status.code(),
+            signal: output.status.signal(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn read_file(&mut self, path: &str, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.take(len as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_file(
+        &mut self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        truncate: bool,
+        mode: u32,
+    ) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .mode(mode)
+            .open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+
+    fn set_time(&mut self, since_epoch: Duration) -> io::Result<()> {
+        let time = libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+        };
+        // SAFETY: `time` is a valid timespec that outlives the call.
+        if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &time) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self, reboot: bool) -> io::Result<()> {
+        // SAFETY: trivially safe, and reboot() only returns on failure.
+        unsafe {
+            libc::sync();
+            libc::reboot(if reboot {
+                libc::RB_AUTOBOOT
+            } else {
+                libc::RB_POWER_OFF
+            });
+        }
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// Handler keeping files in memory.
+    #[derive(Default)]
+    struct FakeHandler {
+        files: BTreeMap<String, Vec<u8>>,
+    }
+
+    impl GuestAgentHandler for FakeHandler {
+        fn exec(&mut self, request: ExecRequest) -> io::Result<ExecOutput> {
+            Ok(ExecOutput {
+                exit_code: Some(0),
+                signal: None,
+                stdout: request.args.join(" ").into_bytes(),
+                stderr: request.stdin,
+            })
+        }
+
+        fn read_file(&mut self, path: &str, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+            let file = self
+                .files
+                .get(path)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            let start = (offset as usize).min(file.len());
+            let end = (start + len as usize).min(file.len());
+            Ok(file[start..end].to_vec())
+        }
+
+        fn write_file(
+            &mut self,
+            path: &str,
+            offset: u64,
+            data: &[u8],
+            truncate: bool,
+            _mode: u32,
+        ) -> io::Result<()> {
+            let file = self.files.entry(path.to_string()).or_default();
+            if truncate {
+                file.clear();
+            }
+            file.truncate(offset as usize);
+            file.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn set_time(&mut self, _since_epoch: Duration) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+
+        fn shutdown(&mut self, _reboot: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_address() {
+        assert_eq!(
+            "vsock:3".parse(),
+            Ok(GuestAgentAddress::Vsock {
+                cid: 3,
+                port: GUEST_AGENT_VSOCK_PORT
+            })
+        );
+        assert_eq!(
+            "vsock:3:1234".parse(),
+            Ok(GuestAgentAddress::Vsock { cid: 3, port: 1234 })
+        );
+        assert_eq!(
+            "/run/agent.sock".parse(),
+            Ok(GuestAgentAddress::Unix(PathBuf::from("/run/agent.sock")))
+        );
+        assert!("vsock:x".parse::<GuestAgentAddress>().is_err());
+    }
+
+    #[test]
+    fn session() {
+        let (host, mut guest) = UnixStream::pair().unwrap();
+        let agent = thread::spawn(move || {
+            let mut handler = FakeHandler::default();
+            serve(&mut guest, &mut handler).unwrap();
+            handler
+        });
+
+        let mut client = GuestAgentClient::new(Box::new(host)).unwrap();
+        let output = client
+            .exec(ExecRequest {
+                program: "echo".to_string(),
+                args: vec!["a".to_string(), "b".to_string()],
+                stdin: b"in".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(output.stdout, b"a b");
+        assert_eq!(output.stderr, b"in");
+
+        let content: Vec<u8> = (0..FILE_CHUNK_SIZE * 2 + 7).map(|i| i as u8).collect();
+        assert_eq!(
+            client.write_file("/f", &mut &content[..], 0o644).unwrap(),
+            content.len() as u64
+        );
+        let mut copy = Vec::new();
+        assert_eq!(
+            client.read_file("/f", &mut copy).unwrap(),
+            content.len() as u64
+        );
+        assert_eq!(copy, content);
+
+        assert_eq!(
+            client
+                .write_file("/empty", &mut io::empty(), 0o644)
+                .unwrap(),
+            0
+        );
+        assert!(client.read_file("/missing", &mut Vec::new()).is_err());
+        assert!(client.sync_time().is_err());
+        client.shutdown(false).unwrap();
+
+        drop(client);
+        let handler = agent.join().unwrap();
+        assert_eq!(handler.files["/f"], content);
+        assert!(handler.files["/empty"].is_empty());
+    }
+}
@@ -11,10 +11,15 @@
 //! if the request type expects one.
 
 pub mod api;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub mod extension;
 #[cfg(feature = "gdb")]
 pub mod gdb;
 #[cfg(feature = "gpu")]
 pub mod gpu;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub mod guest_agent;
+pub mod migration;
 
 use base::debug;
 #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -44,13 +49,18 @@ use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Display;
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
 use anyhow::bail;
 use anyhow::Context;
@@ -84,10 +94,12 @@ use libc::EIO;
 use libc::ENODEV;
 use libc::ENOTSUP;
 use libc::ERANGE;
+use metrics_events::VcpuExitReason;
 #[cfg(feature = "registered_events")]
 use protos::registered_events;
 use remain::sorted;
 use resources::Alloc;
+use resources::PciAddress;
 use resources::SystemAllocator;
 use rutabaga_gfx::DeviceId;
 use rutabaga_gfx::RutabagaDescriptor;
@@ -99,8 +111,11 @@ use rutabaga_gfx::VulkanInfo;
 use serde::de::Error;
 use serde::Deserialize;
 use serde::Serialize;
+use snapshot::CryptKey;
 use snapshot::SnapshotReader;
 use snapshot::SnapshotWriter;
+use snapshot::StreamWriter;
+use snapshot::ZstdChunkWriter;
 use swap::SwapStatus;
 use sync::Mutex;
 #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -116,11 +131,18 @@ pub use vm_control_product::GpuSendToMain;
 pub use vm_control_product::GpuSendToService;
 pub use vm_control_product::ServiceSendToGpu;
 use vm_memory::GuestAddress;
+use vm_memory::KsmStats;
+use vm_memory::NumaBindingStats;
+use vm_memory::SharedMemoryStats;
 
 #[cfg(feature = "balloon")]
 pub use crate::balloon_tube::BalloonControlCommand;
 #[cfg(feature = "balloon")]
 pub use crate::balloon_tube::BalloonTube;
+#[cfg(feature = "balloon")]
+pub use crate::balloon_tube::WorkingSetBin;
+#[cfg(feature = "balloon")]
+pub use crate::balloon_tube::WorkingSetReport;
 #[cfg(feature = "gdb")]
 pub use crate::gdb::VcpuDebug;
 #[cfg(feature = "gdb")]
@@ -147,6 +169,151 @@ pub enum VcpuControl {
     Restore(VcpuRestoreRequest),
     #[cfg(any(target_os = "android", target_os = "linux"))]
     Throttle(u32),
+    // Request the exit statistics of the vCPU. The result is sent back over the included channel.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    GetExitStats(mpsc::Sender<VcpuExitStats>),
+    // Request the `NT_PRSTATUS` core note describing the registers of the vCPU. The vCPU id and
+    // the note are sent back over the included channel.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    GetCoreNote(mpsc::Sender<(usize, anyhow::Result<Vec<u8>>)>),
+    // Reset the vCPU as the platform does when waking up from ACPI S3: the boot vCPU restarts in
+    // real mode at the firmware waking vector, the others wait for an INIT-SIPI sequence.
+    #[cfg(all(
+        target_arch = "x86_64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    ResumeFromS3 {
+        waking_vector: u32,
+    },
+    // Let a vCPU parked at boot enter the guest, which can then bring it up with PSCI CPU_ON.
+    // Whether the vCPU was parked is sent back over the included channel.
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    Online(mpsc::Sender<bool>),
+}
+
+/// Number of exits of a vCPU for a given reason and time spent handling them in crosvm.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VcpuExitStat {
+    pub count: u64,
+    pub duration: Duration,
+}
+
+/// Exit statistics of a vCPU, for `crosvm stats vcpu` and the metrics server.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VcpuExitStats {
+    pub cpu_id: usize,
+    pub exits: BTreeMap<VcpuExitReason, VcpuExitStat>,
+}
+
+impl VcpuExitStats {
+    pub fn new(cpu_id: usize) -> Self {
+        VcpuExitStats {
+            cpu_id,
+            exits: BTreeMap::new(),
+        }
+    }
+
+    /// Records an exit for `reason` which took `duration` to handle.
+    ///
+    /// The counters saturate rather than overflow so they never disrupt the vCPU.
+    pub fn record(&mut self, reason: VcpuExitReason, duration: Duration) {
+        let stat = self.exits.entry(reason).or_default();
+        stat.count = stat.count.saturating_add(1);
+        stat.duration = stat.duration.saturating_add(duration);
+    }
+}
+
+impl Display for VcpuExitStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "vcpu {}:", self.cpu_id)?;
+        writeln!(
+            f,
+            "  {:<16}{:>16}{:>16}",
+            "exit reason", "count", "duration"
+        )?;
+        let mut exits: Vec<_> = self.exits.iter().collect();
+        // The most expensive exits come first.
+        exits.sort_by_key(|(_, stat)| std::cmp::Reverse(stat.duration));
+        for (reason, stat) in exits {
+            writeln!(
+                f,
+                "  {:<16}{:>16}{:>16}",
+                reason.as_str(),
+                stat.count,
+                format!("{:?}", stat.duration)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of buckets of an `IrqLatencyHistogram`.
+pub const IRQ_LATENCY_BUCKETS: usize = 20;
+
+/// Time between a device signaling an interrupt and the guest ending it with an EOI, for
+/// `crosvm stats irq`.
+///
+/// Bucket 0 counts the latencies under 1us, and bucket `i` those from `2^(i-1)` up to `2^i`
+/// microseconds. The last bucket also counts all the longer latencies.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct IrqLatencyHistogram {
+    pub count: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub buckets: [u64; IRQ_LATENCY_BUCKETS],
+}
+
+impl IrqLatencyHistogram {
+    /// Records an interrupt which was ended `latency` after being signaled.
+    pub fn record(&mut self, latency: Duration) {
+        if self.count == 0 || latency < self.min {
+            self.min = latency;
+        }
+        self.max = self.max.max(latency);
+        self.count = self.count.saturating_add(1);
+        self.total = self.total.saturating_add(latency);
+
+        let micros = latency.as_micros();
+        let bucket = ((u128::BITS - micros.leading_zeros()) as usize).min(IRQ_LATENCY_BUCKETS - 1);
+        self.buckets[bucket] = self.buckets[bucket].saturating_add(1);
+    }
+
+    /// Returns the average latency, zero if no interrupt was recorded.
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+}
+
+impl Display for IrqLatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "count {}, min {:?}, avg {:?}, max {:?}",
+            self.count,
+            self.min,
+            self.average(),
+            self.max
+        )?;
+        for (i, count) in self.buckets.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let bound = if i == IRQ_LATENCY_BUCKETS - 1 {
+                format!(">= {}us", 1u64 << (i - 1))
+            } else {
+                format!("< {}us", 1u64 << i)
+            };
+            writeln!(f, "  {:<16}{:>16}", bound, count)?;
+        }
+        Ok(())
+    }
 }
 
 /// Request to restore a Vcpu from a given snapshot, and report the results
@@ -160,7 +327,7 @@ pub struct VcpuRestoreRequest {
 }
 
 /// Mode of execution for the VM.
-#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum VmRunMode {
     /// The default run mode indicating the VCPUs are running.
     #[default]
@@ -202,6 +369,12 @@ pub trait PmResource {
     fn rtc_evt(&mut self, _clear_evt: Event) {}
     fn gpe_evt(&mut self, _gpe: u32, _clear_evt: Option<Event>) {}
     fn pme_evt(&mut self, _requester_id: u16) {}
+    /// Returns whether the guest entered ACPI S3 and must resume through its waking vector.
+    fn is_s3_suspended(&self) -> bool {
+        false
+    }
+    /// Called once the vCPUs have been sent back to the waking vector after S3.
+    fn s3_resume(&mut self) {}
     fn register_gpe_notify_dev(&mut self, _gpe: u32, _notify_dev: Arc<Mutex<dyn GpeNotify>>) {}
     fn register_pme_notify_dev(&mut self, _bus: u8, _notify_dev: Arc<Mutex<dyn PmeNotify>>) {}
 }
@@ -217,6 +390,8 @@ pub const USB_CONTROL_MAX_PORTS: usize = 16;
 pub enum DiskControlCommand {
     /// Resize a disk to `new_size` in bytes.
     Resize { new_size: u64 },
+    /// Start or stop rejecting writes. A disk opened read-only cannot be made writable.
+    SetReadOnly { read_only: bool },
 }
 
 impl Display for DiskControlCommand {
@@ -225,6 +400,7 @@ impl Display for DiskControlCommand {
 
         match self {
             Resize { new_size } => write!(f, "disk_resize {}", new_size),
+            SetReadOnly { read_only } => write!(f, "disk_set_read_only {}", read_only),
         }
     }
 }
@@ -235,6 +411,159 @@ pub enum DiskControlResult {
     Err(SysError),
 }
 
+/// Console control commands for adding and removing virtio-console ports at runtime.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ConsoleControlCommand {
+    /// Add a port named `name`, backed by the Unix socket, pipe or character device at `path`.
+    AddPort { name: String, path: PathBuf },
+    /// Remove the previously added port named `name`.
+    RemovePort { name: String },
+}
+
+impl Display for ConsoleControlCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConsoleControlCommand::*;
+
+        match self {
+            AddPort { name, path } => write!(f, "console_add_port {} {}", name, path.display()),
+            RemovePort { name } => write!(f, "console_remove_port {}", name),
+        }
+    }
+}
+
+/// Requests sent from the main process to a virtio-console device, answered with a
+/// `ConsoleControlResult`.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ConsolePortRequest {
+    /// Add a port named `name` that reads from and writes to `stream`.
+    Add {
+        name: String,
+        stream: SafeDescriptor,
+    },
+    /// Remove the port named `name`.
+    Remove { name: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleControlResult {
+    Ok,
+    Err(SysError),
+}
+
+/// Commands changing the configuration of a running virtio-net device, answered with a
+/// `NetConfigResult`.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum NetConfigCommand {
+    /// Limit the offloads of the packets passed to the guest to `offloads`. Offloads the guest did
+    /// not negotiate stay disabled, and the guest may change them again through the
+    /// `VIRTIO_NET_CTRL_GUEST_OFFLOADS` control queue command.
+    SetGuestOffloads { offloads: NetGuestOffloads },
+}
+
+impl Display for NetConfigCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::NetConfigCommand::*;
+
+        match self {
+            SetGuestOffloads { offloads } => write!(f, "net_set_guest_offloads {}", offloads),
+        }
+    }
+}
+
+/// Offloads of the packets a virtio-net device passes to the guest, matching the
+/// `VIRTIO_NET_F_GUEST_*` features.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetGuestOffloads {
+    /// Packets with a partial checksum.
+    pub csum: bool,
+    /// TCPv4 segmentation offload.
+    pub tso4: bool,
+    /// TCPv6 segmentation offload.
+    pub tso6: bool,
+    /// TCP segmentation offload with ECN.
+    pub ecn: bool,
+    /// UDP fragmentation offload.
+    pub ufo: bool,
+}
+
+impl FromStr for NetGuestOffloads {
+    type Err = String;
+
+    /// Parses a comma separated list of offloads, e.g. `csum,tso4,tso6`, or `none`.
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let mut offloads = NetGuestOffloads::default();
+        if s == "none" {
+            return Ok(offloads);
+        }
+        for offload in s.split(',') {
+            match offload {
+                "csum" => offloads.csum = true,
+                "tso4" => offloads.tso4 = true,
+                "tso6" => offloads.tso6 = true,
+                "ecn" => offloads.ecn = true,
+                "ufo" => offloads.ufo = true,
+                _ => {
+                    return Err(format!(
+                        "invalid offload {}, expected csum, tso4, tso6, ecn or ufo",
+                        offload
+                    ))
+                }
+            }
+        }
+        Ok(offloads)
+    }
+}
+
+impl Display for NetGuestOffloads {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offloads: Vec<&str> = [
+            (self.csum, "csum"),
+            (self.tso4, "tso4"),
+            (self.tso6, "tso6"),
+            (self.ecn, "ecn"),
+            (self.ufo, "ufo"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        if offloads.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", offloads.join(","))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum NetConfigResult {
+    Ok,
+    Err(SysError),
+}
+
+/// Commands changing the configuration of a running virtio-fs device, answered with an
+/// `FsControlResult`.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum FsControlCommand {
+    /// Start or stop rejecting requests that modify the shared directory.
+    SetReadOnly { read_only: bool },
+}
+
+impl Display for FsControlCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::FsControlCommand::*;
+
+        match self {
+            SetReadOnly { read_only } => write!(f, "fs_set_read_only {}", read_only),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum FsControlResult {
+    Ok,
+    Err(SysError),
+}
+
 /// Net control commands for adding and removing tap devices.
 #[cfg(feature = "pci-hotplug")]
 #[derive(Serialize, Deserialize, Debug)]
@@ -243,6 +572,26 @@ pub enum NetControlCommand {
     RemoveTap(u8),
 }
 
+/// Evdev control commands for adding and removing host input devices passed through to the guest.
+#[cfg(feature = "pci-hotplug")]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum EvdevControlCommand {
+    /// Attaches the evdev device at the given path, e.g. `/dev/input/event3`.
+    Add(PathBuf),
+    /// Detaches the device on the given bus.
+    Remove(u8),
+}
+
+/// Block control commands for adding and removing virtio-blk devices.
+#[cfg(feature = "pci-hotplug")]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum BlockControlCommand {
+    /// Attaches a disk described with the syntax of `--block`, e.g. `path=disk.img,ro`.
+    Add(String),
+    /// Detaches the device on the given bus.
+    Remove(u8),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum UsbControlCommand {
     AttachDevice {
@@ -253,6 +602,28 @@ pub enum UsbControlCommand {
         #[serde(with = "with_as_descriptor")]
         file: File,
     },
+    /// Imports the device `busid` from the usbip server at the other end of `socket`.
+    AttachUsbipDevice {
+        #[serde(with = "with_as_descriptor")]
+        socket: SafeDescriptor,
+        busid: String,
+    },
+    /// Attaches an emulated HID device whose input reports are sent with `SendHidReport`.
+    AttachHidGadget {
+        kind: UsbHidGadgetKind,
+    },
+    /// Attaches an emulated HID device that also reads input reports from `socket`, a stream
+    /// socket on which reports are written back to back, each the size of a `kind` report.
+    AttachHidGadgetWithSocket {
+        kind: UsbHidGadgetKind,
+        #[serde(with = "with_as_descriptor")]
+        socket: SafeDescriptor,
+    },
+    /// Queues an input report for the emulated HID device on `port`.
+    SendHidReport {
+        port: u8,
+        report: Vec<u8>,
+    },
     DetachDevice {
         port: u8,
     },
@@ -261,6 +632,36 @@ pub enum UsbControlCommand {
     },
 }
 
+/// Kind of HID device emulated by a USB HID gadget. The layout of the input reports follows the
+/// HID boot protocol for keyboards and mice, tablets report absolute coordinates.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UsbHidGadgetKind {
+    /// 8 byte reports: modifier bits, reserved byte, up to 6 pressed key usages.
+    Keyboard,
+    /// 4 byte reports: button bits, relative x, relative y and wheel as signed bytes.
+    Mouse,
+    /// 6 byte reports: button bits, absolute x and y as little endian u16 in the range
+    /// [0, 32767], wheel as a signed byte.
+    Tablet,
+}
+
+impl FromStr for UsbHidGadgetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "keyboard" => Ok(UsbHidGadgetKind::Keyboard),
+            "mouse" => Ok(UsbHidGadgetKind::Mouse),
+            "tablet" => Ok(UsbHidGadgetKind::Tablet),
+            _ => Err(format!(
+                "invalid HID gadget kind {}, expected keyboard, mouse or tablet",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
 pub struct UsbControlAttachedDevice {
     pub port: u8,
@@ -306,6 +707,7 @@ pub enum UsbControlResult {
     FailedToOpenDevice,
     Devices([UsbControlAttachedDevice; USB_CONTROL_MAX_PORTS]),
     FailedToInitHostDevice,
+    InvalidReport,
 }
 
 impl Display for UsbControlResult {
@@ -326,6 +728,7 @@ impl Display for UsbControlResult {
                 std::result::Result::Ok(())
             }
             FailedToInitHostDevice => write!(f, "failed_to_init_host_device"),
+            InvalidReport => write!(f, "invalid_report"),
         }
     }
 }
@@ -337,9 +740,27 @@ pub enum SnapshotCommand {
         snapshot_path: PathBuf,
         compress_memory: bool,
         encrypt: bool,
+        /// Compress all snapshot files with zstd at this level.
+        zstd_level: Option<i32>,
+        /// Encrypt all snapshot files with this key, which is not stored in the snapshot.
+        key: Option<CryptKey>,
+    },
+    /// Take a snapshot as a single stream written to `output`, e.g. a pipe.
+    TakeStream {
+        #[serde(with = "with_as_descriptor")]
+        output: File,
+        compress_memory: bool,
+        zstd_level: Option<i32>,
     },
 }
 
+/// Commands for live migration
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MigrateCommand {
+    /// Migrate the VM to the crosvm process listening at `destination` (`tcp://HOST:PORT`).
+    Send { destination: String },
+}
+
 /// Commands for actions on devices and the devices control thread.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DeviceControlCommand {
@@ -454,12 +875,13 @@ impl Display for VmMemorySource {
 }
 
 impl VmMemorySource {
-    /// Map the resource and return its mapping and size in bytes.
+    /// Map the resource and return its mapping and size in bytes, along with the descriptor and
+    /// offset it was mapped from, if any.
     fn map(
         self,
         gralloc: &mut RutabagaGralloc,
         prot: Protection,
-    ) -> anyhow::Result<(Box<dyn MappedRegion>, u64, Option<SafeDescriptor>)> {
+    ) -> anyhow::Result<(Box<dyn MappedRegion>, u64, Option<(SafeDescriptor, u64)>)> {
         let (mem_region, size, descriptor) = match self {
             VmMemorySource::Descriptor {
                 descriptor,
@@ -468,7 +890,7 @@ impl VmMemorySource {
             } => (
                 map_descriptor(&descriptor, offset, size, prot)?,
                 size,
-                Some(descriptor),
+                Some((descriptor, offset)),
             ),
 
             VmMemorySource::SharedMemory(shm) => {
@@ -805,13 +1227,15 @@ impl VmMemoryRequest {
                 };
 
                 let region_id = VmMemoryRegionId(guest_addr);
-                if let (Some(descriptor), Some(iommu_client)) = (descriptor, iommu_client) {
+                if let (Some((descriptor, offset)), Some(iommu_client)) = (descriptor, iommu_client)
+                {
                     let request =
                         VirtioIOMMURequest::VfioCommand(VirtioIOMMUVfioCommand::VfioDmabufMap {
                             region_id,
                             gpa: guest_addr.0,
                             size,
                             dma_buf: descriptor,
+                            offset,
                         });
 
                     match virtio_iommu_request(&iommu_client.tube.lock(), &request) {
@@ -1176,6 +1600,10 @@ pub enum VmIrqRequest {
         msi_address: u64,
         msi_data: u32,
     },
+    /// Add several msi route entries into the IRQ chip with a single update of its routing table.
+    AddMsiRoutes {
+        routes: Vec<MsiRoute>,
+    },
     // unregister_irqfs() and release gsi
     ReleaseOneIrq {
         gsi: u32,
@@ -1183,12 +1611,33 @@ pub enum VmIrqRequest {
     },
 }
 
+/// An msi route entry of `VmIrqRequest::AddMsiRoutes`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiRoute {
+    pub gsi: u32,
+    pub msi_address: u64,
+    pub msi_data: u32,
+}
+
+impl From<MsiRoute> for IrqRoute {
+    fn from(route: MsiRoute) -> Self {
+        IrqRoute {
+            gsi: route.gsi,
+            source: IrqSource::Msi {
+                address: route.msi_address,
+                data: route.msi_data,
+            },
+        }
+    }
+}
+
 /// Data to set up an IRQ event or IRQ route on the IRQ chip.
 /// VmIrqRequest::execute can't take an `IrqChip` argument, because of a dependency cycle between
 /// devices and vm_control, so it takes a Fn that processes an `IrqSetup`.
 pub enum IrqSetup<'a> {
     Event(u32, &'a Event, u32, usize, String),
     Route(IrqRoute),
+    Routes(Vec<IrqRoute>),
     UnRegister(u32, &'a Event),
 }
 
@@ -1251,14 +1700,19 @@ impl VmIrqRequest {
                 msi_address,
                 msi_data,
             } => {
-                let route = IrqRoute {
+                let route = MsiRoute {
                     gsi,
-                    source: IrqSource::Msi {
-                        address: msi_address,
-                        data: msi_data,
-                    },
+                    msi_address,
+                    msi_data,
                 };
-                match set_up_irq(IrqSetup::Route(route)) {
+                match set_up_irq(IrqSetup::Route(route.into())) {
+                    Ok(_) => VmIrqResponse::Ok,
+                    Err(e) => VmIrqResponse::Err(e),
+                }
+            }
+            AddMsiRoutes { ref routes } => {
+                let routes = routes.iter().map(|route| (*route).into()).collect();
+                match set_up_irq(IrqSetup::Routes(routes)) {
                     Ok(_) => VmIrqResponse::Ok,
                     Err(e) => VmIrqResponse::Err(e),
                 }
@@ -1279,6 +1733,43 @@ pub enum VmIrqResponse {
     Err(SysError),
 }
 
+/// Runtime details a device reports about itself, for `crosvm query devices`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceDetails {
+    /// The PCI address of the device, if it is a PCI device.
+    pub pci_address: Option<PciAddress>,
+    /// The size of each virtqueue of the device.
+    pub queue_sizes: Vec<u16>,
+    /// Host resources backing the device, such as disk images or tap interfaces.
+    pub resources: Vec<String>,
+    /// Number of irqfds used to inject interrupts into the guest.
+    pub irqfds: usize,
+    /// Number of ioeventfds notified of guest writes.
+    pub ioeventfds: usize,
+}
+
+/// An address range occupied by a device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceRange {
+    /// The bus the range is on, either "mmio" or "io".
+    pub bus: String,
+    pub base: u64,
+    pub len: u64,
+}
+
+/// Description of a device attached to the VM.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// The debug label of the device.
+    pub label: String,
+    /// The type of the device.
+    pub device_type: String,
+    /// The address ranges the device occupies on the MMIO and I/O buses.
+    pub ranges: Vec<DeviceRange>,
+    #[serde(flatten)]
+    pub details: DeviceDetails,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DevicesState {
     Sleep,
@@ -1525,6 +2016,8 @@ pub struct HotPlugDeviceInfo {
     pub device_type: HotPlugDeviceType,
     pub path: PathBuf,
     pub hp_interrupt: bool,
+    /// Export the BARs of an endpoint to the virtio-iommu for peer-to-peer DMA.
+    pub p2p: bool,
 }
 
 /// Message for communicating a suspend or resume to the virtio-pvclock device.
@@ -1553,6 +2046,23 @@ pub enum SwapCommand {
     Status,
 }
 
+/// Commands to toggle the tracing categories at runtime.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum TracingCommand {
+    Enable(Vec<String>),
+    Disable(Vec<String>),
+    Status,
+}
+
+/// Commands to read or change the offset of the guest RTC from the host time.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RtcCommand {
+    GetOffset,
+    /// Sets the offset, in seconds. The guest sees its RTC jump by the difference with the
+    /// previous offset.
+    SetOffset(i64),
+}
+
 ///
 /// A request to the main process to perform some operation on the VM.
 ///
@@ -1563,6 +2073,9 @@ pub enum VmRequest {
     Exit,
     /// Trigger a power button event in the guest.
     Powerbtn,
+    /// Trigger a power button event in the guest and stop the VM once the guest has shut down, or
+    /// after `timeout` if it has not. Responds once the VM is stopped.
+    Powerdown { timeout: Duration },
     /// Trigger a sleep button event in the guest.
     Sleepbtn,
     /// Trigger a RTC interrupt in the guest. When the irq associated with the RTC is
@@ -1591,6 +2104,25 @@ pub enum VmRequest {
         disk_index: usize,
         command: DiskControlCommand,
     },
+    /// Send a command to a virtio-console chosen by `console_index`.
+    /// `console_index` is a 0-based count of the `--serial hardware=virtio-console` options that
+    /// set `max-ports`.
+    ConsoleCommand {
+        console_index: usize,
+        command: ConsoleControlCommand,
+    },
+    /// Send a command to a virtio-net device chosen by `net_index`.
+    /// `net_index` is a 0-based count of the `--net` options that do not use vhost-net.
+    NetCommand {
+        net_index: usize,
+        command: NetConfigCommand,
+    },
+    /// Send a command to a virtio-fs device chosen by `fs_index`.
+    /// `fs_index` is a 0-based count of the `--shared-dir` options of type `fs`.
+    FsCommand {
+        fs_index: usize,
+        command: FsControlCommand,
+    },
     /// Command to use controller.
     UsbCommand(UsbControlCommand),
     /// Command to modify the gpu.
@@ -1609,8 +2141,16 @@ pub enum VmRequest {
     /// Command to add/remove network tap device as virtio-pci device
     #[cfg(feature = "pci-hotplug")]
     HotPlugNetCommand(NetControlCommand),
+    /// Command to add/remove host evdev device as virtio-input PCI device
+    #[cfg(feature = "pci-hotplug")]
+    HotPlugEvdevCommand(EvdevControlCommand),
+    /// Command to add/remove disk as virtio-blk PCI device
+    #[cfg(feature = "pci-hotplug")]
+    HotPlugBlockCommand(BlockControlCommand),
     /// Command to Snapshot devices
     Snapshot(SnapshotCommand),
+    /// Command to live migrate the VM
+    Migrate(MigrateCommand),
     /// Register for event notification
     RegisterListener {
         socket_addr: String,
@@ -1631,8 +2171,44 @@ pub enum VmRequest {
     VcpuPidTid,
     /// Throttles the requested vCPU for microseconds
     Throttle(usize, u32),
+    /// Lets the guest bring up the requested vCPU, which was parked at boot.
+    OnlineVcpu(usize),
     /// Returns unique descriptor of this VM.
     GetVmDescriptor,
+    /// Returns the run mode of the VCPUs.
+    GetRunMode,
+    /// Returns a description of all devices attached to the VM.
+    QueryDevices,
+    /// Returns the exit statistics of each vCPU.
+    VcpuStats,
+    /// Returns the interrupt latency histogram of each GSI, see `crosvm run --irq-latency-stats`.
+    IrqLatencyStats,
+    /// Toggles tracing categories and returns the state of all of them.
+    Tracing(TracingCommand),
+    /// Pause the vCPUs and write an ELF core of the guest to `output`.
+    DumpCore {
+        #[serde(with = "with_as_descriptor")]
+        output: File,
+    },
+    /// Returns the memory usage of guest memory shared with other VMs restored from the same
+    /// snapshot.
+    SharedMemoryStats,
+    /// Returns the host NUMA policy and placement of the resident pages of guest memory.
+    NumaBindingStats,
+    /// Returns the guest memory merged by KSM.
+    KsmStats,
+    /// Returns the records of the pstore buffer and of its copies from previous boots, optionally
+    /// only those of one `kind`.
+    PstoreRecords { kind: Option<PstoreRecordKind> },
+    /// Reads or changes the offset of the RTC from the host time and returns the resulting
+    /// offset.
+    RtcCommand(RtcCommand),
+    /// Replaces the kernel command line parameters for the next boot of the VM, or cancels a
+    /// previous replacement if `None`.
+    NextBootParams(Option<Vec<String>>),
+    /// Applies the changes between the running configuration and `config`, a JSON serialized
+    /// configuration, that can be made without restarting the VM.
+    ReloadConfig { config: String },
 }
 
 /// NOTE: when making any changes to this enum please also update
@@ -1642,6 +2218,8 @@ pub enum RegisteredEvent {
     VirtioBalloonWsReport,
     VirtioBalloonResize,
     VirtioBalloonOOMDeflation,
+    GuestPanic,
+    GpuMemoryPressure,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -1652,6 +2230,20 @@ pub enum RegisteredEventWithData {
     },
     VirtioBalloonResize,
     VirtioBalloonOOMDeflation,
+    /// The guest reported a panic through the pvpanic device. `code` holds the pvpanic event
+    /// bits and `core_dump` the path of the ELF core written for it, if any.
+    GuestPanic {
+        code: u8,
+        core_dump: Option<PathBuf>,
+    },
+    /// The host is short of memory for the GPU resources of the guest. `usage` is the estimated
+    /// host memory used by the resources and `budget` the limit it exceeded, if any, in bytes.
+    /// `allocation_failed` is set when the renderer failed to allocate a resource.
+    GpuMemoryPressure {
+        usage: u64,
+        budget: Option<u64>,
+        allocation_failed: bool,
+    },
 }
 
 impl RegisteredEventWithData {
@@ -1660,6 +2252,8 @@ impl RegisteredEventWithData {
             Self::VirtioBalloonWsReport { .. } => RegisteredEvent::VirtioBalloonWsReport,
             Self::VirtioBalloonResize => RegisteredEvent::VirtioBalloonResize,
             Self::VirtioBalloonOOMDeflation => RegisteredEvent::VirtioBalloonOOMDeflation,
+            Self::GuestPanic { .. } => RegisteredEvent::GuestPanic,
+            Self::GpuMemoryPressure { .. } => RegisteredEvent::GpuMemoryPressure,
         }
     }
 
@@ -1696,6 +2290,34 @@ impl RegisteredEventWithData {
                 event.set_oom_deflation(registered_events::VirtioBalloonOOMDeflation::new());
                 event
             }
+            Self::GuestPanic { code, core_dump } => {
+                let panic = registered_events::GuestPanic {
+                    code: (*code).into(),
+                    core_dump_path: core_dump
+                        .as_ref()
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    ..registered_events::GuestPanic::new()
+                };
+                let mut event = registered_events::RegisteredEvent::new();
+                event.set_guest_panic(panic);
+                event
+            }
+            Self::GpuMemoryPressure {
+                usage,
+                budget,
+                allocation_failed,
+            } => {
+                let pressure = registered_events::GpuMemoryPressure {
+                    usage: *usage,
+                    budget: budget.unwrap_or_default(),
+                    allocation_failed: *allocation_failed,
+                    ..registered_events::GpuMemoryPressure::new()
+                };
+                let mut event = registered_events::RegisteredEvent::new();
+                event.set_gpu_memory_pressure(pressure);
+                event
+            }
         }
     }
 
@@ -1725,6 +2347,40 @@ pub fn handle_disk_command(command: &DiskControlCommand, disk_host_tube: &Tube)
     }
 }
 
+pub fn handle_net_command(command: &NetConfigCommand, net_host_tube: &Tube) -> VmResponse {
+    // Forward the request to the net device process via its control tube.
+    if let Err(e) = net_host_tube.send(command) {
+        error!("net tube send failed: {}", e);
+        return VmResponse::Err(SysError::new(EINVAL));
+    }
+
+    match net_host_tube.recv() {
+        Ok(NetConfigResult::Ok) => VmResponse::Ok,
+        Ok(NetConfigResult::Err(e)) => VmResponse::Err(e),
+        Err(e) => {
+            error!("net tube recv failed: {}", e);
+            VmResponse::Err(SysError::new(EINVAL))
+        }
+    }
+}
+
+pub fn handle_fs_command(command: &FsControlCommand, fs_host_tube: &Tube) -> VmResponse {
+    // Forward the request to the fs device process via its control tube.
+    if let Err(e) = fs_host_tube.send(command) {
+        error!("fs tube send failed: {}", e);
+        return VmResponse::Err(SysError::new(EINVAL));
+    }
+
+    match fs_host_tube.recv() {
+        Ok(FsControlResult::Ok) => VmResponse::Ok,
+        Ok(FsControlResult::Err(e)) => VmResponse::Err(e),
+        Err(e) => {
+            error!("fs tube recv failed: {}", e);
+            VmResponse::Err(SysError::new(EINVAL))
+        }
+    }
+}
+
 /// WARNING: descriptor must be a mapping handle on Windows.
 fn map_descriptor(
     descriptor: &dyn AsRawDescriptor,
@@ -1749,7 +2405,10 @@ fn map_descriptor(
 // In this function, there may be a time where vCPUs are not holding the same state
 // as they transition from one state to the other. This is expected, and the final result
 // should be all vCPUs holding the same state.
-fn get_vcpu_state(kick_vcpus: impl Fn(VcpuControl), vcpu_num: usize) -> anyhow::Result<VmRunMode> {
+pub fn get_vcpu_state(
+    kick_vcpus: impl Fn(VcpuControl),
+    vcpu_num: usize,
+) -> anyhow::Result<VmRunMode> {
     let (send_chan, recv_chan) = mpsc::channel();
     kick_vcpus(VcpuControl::GetStates(send_chan));
     if vcpu_num == 0 {
@@ -2181,8 +2840,8 @@ impl VmRequest {
                 // Resume the pvclock as late as possible before starting vCPUs.
                 if vm.check_capability(VmCap::PvClock) {
                     // If None, then we aren't suspended, which is a valid case.
-                    if let Some(x) = suspended_pvclock_state {
-                        if let Err(e) = vm.set_pvclock(x) {
+                    if let Some(x) = suspended_pvclock_state.take() {
+                        if let Err(e) = vm.set_pvclock(&x) {
                             error!("resume_pvclock failed: {e:?}");
                             return VmResponse::Err(SysError::new(EIO));
                         }
@@ -2230,6 +2889,12 @@ impl VmRequest {
                 Some(tube) => handle_disk_command(command, tube),
                 None => VmResponse::Err(SysError::new(ENODEV)),
             },
+            VmRequest::ConsoleCommand { .. }
+            | VmRequest::NetCommand { .. }
+            | VmRequest::FsCommand { .. } => {
+                error!("{:#?} not supported", *self);
+                VmResponse::Err(SysError::new(ENOTSUP))
+            }
             #[cfg(feature = "gpu")]
             VmRequest::GpuCommand(ref cmd) => match gpu_control_tube {
                 Some(gpu_control) => {
@@ -2326,21 +2991,53 @@ impl VmRequest {
             VmRequest::HotPlugNetCommand(ref _net_cmd) => {
                 VmResponse::ErrString("hot plug not supported".to_owned())
             }
-            VmRequest::Snapshot(SnapshotCommand::Take {
-                ref snapshot_path,
-                compress_memory,
-                encrypt,
-            }) => {
+            #[cfg(feature = "pci-hotplug")]
+            VmRequest::HotPlugEvdevCommand(ref _evdev_cmd) => {
+                VmResponse::ErrString("hot plug not supported".to_owned())
+            }
+            #[cfg(feature = "pci-hotplug")]
+            VmRequest::HotPlugBlockCommand(ref _block_cmd) => {
+                VmResponse::ErrString("hot plug not supported".to_owned())
+            }
+            VmRequest::Snapshot(ref command) => {
                 info!("Starting crosvm snapshot");
+                let (output, compress_memory, zstd_level) = match command {
+                    SnapshotCommand::Take {
+                        snapshot_path,
+                        compress_memory,
+                        encrypt,
+                        zstd_level,
+                        key,
+                    } => (
+                        SnapshotOutput::Dir {
+                            path: snapshot_path.to_path_buf(),
+                            encrypt: *encrypt,
+                            key: key.clone(),
+                        },
+                        *compress_memory,
+                        *zstd_level,
+                    ),
+                    SnapshotCommand::TakeStream {
+                        output,
+                        compress_memory,
+                        zstd_level,
+                    } => match output.try_clone() {
+                        Ok(file) => (SnapshotOutput::Stream(file), *compress_memory, *zstd_level),
+                        Err(e) => {
+                            error!("failed to clone snapshot output: {}", e);
+                            return VmResponse::Err(e.into());
+                        }
+                    },
+                };
                 match do_snapshot(
-                    snapshot_path.to_path_buf(),
+                    output,
                     kick_vcpus,
                     irq_handler_control,
                     device_control_tube,
                     vcpu_size,
                     snapshot_irqchip,
-                    *compress_memory,
-                    *encrypt,
+                    compress_memory,
+                    zstd_level,
                     suspended_pvclock_state,
                     vm,
                 ) {
@@ -2354,6 +3051,28 @@ impl VmRequest {
                     }
                 }
             }
+            VmRequest::Migrate(MigrateCommand::Send { ref destination }) => {
+                info!("Starting live migration to {}", destination);
+                match migration::send(
+                    destination,
+                    kick_vcpus,
+                    irq_handler_control,
+                    device_control_tube,
+                    vcpu_size,
+                    snapshot_irqchip,
+                    suspended_pvclock_state,
+                    vm,
+                ) {
+                    Ok(()) => {
+                        info!("Finished live migration successfully");
+                        VmResponse::Ok
+                    }
+                    Err(e) => {
+                        error!("failed to migrate: {:?}", e);
+                        VmResponse::Err(SysError::new(EIO))
+                    }
+                }
+            }
             VmRequest::RegisterListener {
                 socket_addr: _,
                 event: _,
@@ -2364,6 +3083,40 @@ impl VmRequest {
             } => VmResponse::Ok,
             VmRequest::Unregister { socket_addr: _ } => VmResponse::Ok,
             VmRequest::VcpuPidTid => unreachable!(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            VmRequest::SharedMemoryStats => match vm.get_memory().shared_memory_stats() {
+                Ok(stats) => VmResponse::SharedMemoryStats(stats),
+                Err(e) => {
+                    error!("failed to get shared memory stats: {:#}", e);
+                    VmResponse::Err(SysError::new(EIO))
+                }
+            },
+            #[cfg(windows)]
+            VmRequest::SharedMemoryStats => {
+                VmResponse::ErrString("shared memory is not supported".to_owned())
+            }
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            VmRequest::NumaBindingStats => match vm.get_memory().numa_binding_stats() {
+                Ok(stats) => VmResponse::NumaBindingStats(stats),
+                Err(e) => {
+                    error!("failed to get NUMA binding stats: {:#}", e);
+                    VmResponse::Err(SysError::new(EIO))
+                }
+            },
+            #[cfg(windows)]
+            VmRequest::NumaBindingStats => {
+                VmResponse::ErrString("NUMA binding is not supported".to_owned())
+            }
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            VmRequest::KsmStats => match vm.get_memory().ksm_stats() {
+                Ok(stats) => VmResponse::KsmStats(stats),
+                Err(e) => {
+                    error!("failed to get KSM stats: {:#}", e);
+                    VmResponse::Err(SysError::new(EIO))
+                }
+            },
+            #[cfg(windows)]
+            VmRequest::KsmStats => VmResponse::ErrString("KSM is not supported".to_owned()),
             VmRequest::Throttle(_, _) => unreachable!(),
             VmRequest::GetVmDescriptor => {
                 let vm_fd = match vm.try_clone_descriptor() {
@@ -2378,28 +3131,245 @@ impl VmRequest {
                     vm_fd,
                 }
             }
-        }
-    }
-}
-
-/// Snapshot the VM to file at `snapshot_path`
+            VmRequest::GetRunMode => match get_vcpu_state(kick_vcpus, vcpu_size) {
+                Ok(mode) => VmResponse::RunMode(mode),
+                Err(e) => {
+                    error!("failed to get run mode: {:#}", e);
+                    VmResponse::Err(SysError::new(EIO))
+                }
+            },
+            VmRequest::QueryDevices => {
+                VmResponse::ErrString("querying devices is not supported".to_owned())
+            }
+            VmRequest::VcpuStats => {
+                VmResponse::ErrString("vcpu statistics are not supported".to_owned())
+            }
+            VmRequest::IrqLatencyStats => {
+                VmResponse::ErrString("IRQ latency statistics are not supported".to_owned())
+            }
+            VmRequest::OnlineVcpu(_) => {
+                VmResponse::ErrString("onlining vcpus is not supported".to_owned())
+            }
+            VmRequest::DumpCore { .. } => {
+                VmResponse::ErrString("core dumps are not supported".to_owned())
+            }
+            VmRequest::PstoreRecords { .. } => {
+                VmResponse::ErrString("reading pstore records is not supported".to_owned())
+            }
+            VmRequest::RtcCommand(_) => {
+                VmResponse::ErrString("changing the RTC offset is not supported".to_owned())
+            }
+            VmRequest::NextBootParams(_) => VmResponse::ErrString(
+                "changing the kernel command line of the next boot is not supported".to_owned(),
+            ),
+            VmRequest::ReloadConfig { .. } => {
+                VmResponse::ErrString("reloading the configuration is not supported".to_owned())
+            }
+            VmRequest::Powerdown { .. } => {
+                VmResponse::ErrString("powering down the VM is not supported".to_owned())
+            }
+            VmRequest::Tracing(command) => {
+                let result = match command {
+                    TracingCommand::Enable(names) => names
+                        .iter()
+                        .try_for_each(|name| cros_tracing::set_category_enabled(name, true)),
+                    TracingCommand::Disable(names) => names
+                        .iter()
+                        .try_for_each(|name| cros_tracing::set_category_enabled(name, false)),
+                    TracingCommand::Status => Ok(()),
+                };
+                match result {
+                    Ok(()) => VmResponse::TracingCategories(
+                        cros_tracing::categories()
+                            .into_iter()
+                            .map(|(name, enabled)| (name.to_owned(), enabled))
+                            .collect(),
+                    ),
+                    Err(e) => VmResponse::ErrString(format!("{:#}", e)),
+                }
+            }
+        }
+    }
+}
+
+/// A private directory under the system temporary directory that is removed with its contents
+/// when dropped. Holds the VM state files that are sent or streamed somewhere else.
+pub(crate) struct StateDir(PathBuf);
+
+impl StateDir {
+    pub(crate) fn new() -> anyhow::Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "crosvm-state-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&path)
+            .with_context(|| format!("failed to create state directory {}", path.display()))?;
+        Ok(StateDir(path))
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for StateDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            warn!("failed to remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Destination of a snapshot taken by `do_snapshot`.
+enum SnapshotOutput {
+    /// A snapshot directory created at `path`, encrypted with a random key if `encrypt` is set
+    /// or with `key` if given.
+    Dir {
+        path: PathBuf,
+        encrypt: bool,
+        key: Option<CryptKey>,
+    },
+    /// A single-stream snapshot written to the file.
+    Stream(File),
+}
+
+/// Snapshot the VM to `output`, compressing all of it with zstd if `zstd_level` is set.
 fn do_snapshot(
-    snapshot_path: PathBuf,
+    output: SnapshotOutput,
     kick_vcpus: impl Fn(VcpuControl),
     irq_handler_control: &Tube,
     device_control_tube: &Tube,
     vcpu_size: usize,
     snapshot_irqchip: impl Fn() -> anyhow::Result<AnySnapshot>,
     compress_memory: bool,
-    encrypt: bool,
+    zstd_level: Option<i32>,
     suspended_pvclock_state: &mut Option<hypervisor::ClockState>,
     vm: &impl Vm,
 ) -> anyhow::Result<()> {
     let snapshot_start = Instant::now();
 
+    if compress_memory && zstd_level.is_some() {
+        bail!("memory compression and zstd compression are mutually exclusive");
+    }
+
     let _vcpu_guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size)?;
     let _device_guard = DeviceSleepGuard::new(device_control_tube)?;
 
+    flush_irqs(irq_handler_control)?;
+
+    match output {
+        SnapshotOutput::Dir { path, encrypt, key } => {
+            let snapshot_writer = if encrypt {
+                if key.is_some() || zstd_level.is_some() {
+                    bail!("random key encryption cannot be combined with a key or compression");
+                }
+                SnapshotWriter::new(path, true)?
+            } else {
+                SnapshotWriter::new_with_options(path, key, zstd_level)?
+            };
+            snapshot_cpu_state(
+                &snapshot_writer,
+                &kick_vcpus,
+                vcpu_size,
+                &snapshot_irqchip,
+                suspended_pvclock_state,
+                vm,
+            )?;
+            // Use 64MB chunks when writing the memory snapshot (if encryption is used).
+            const MEMORY_SNAP_ENCRYPTED_CHUNK_SIZE_BYTES: usize = 1024 * 1024 * 64;
+            let mut mem_writer = snapshot_writer
+                .raw_fragment_with_chunk_size("mem", MEMORY_SNAP_ENCRYPTED_CHUNK_SIZE_BYTES)?;
+            let guest_memory_metadata = snapshot_memory(vm, &mut mem_writer, compress_memory)?;
+            mem_writer.finish()?;
+            snapshot_writer.write_fragment("mem_metadata", &guest_memory_metadata)?;
+            snapshot_devices(device_control_tube, snapshot_writer)?;
+        }
+        SnapshotOutput::Stream(file) => {
+            // Devices write their state as separate fragments, possibly from other processes, so
+            // collect everything but guest memory in a directory first. Memory goes straight to
+            // the stream.
+            let state_dir = StateDir::new()?;
+            let state_root = state_dir.path().join("snapshot");
+            let snapshot_writer =
+                SnapshotWriter::new_with_options(state_root.clone(), None, zstd_level)?;
+            snapshot_cpu_state(
+                &snapshot_writer,
+                &kick_vcpus,
+                vcpu_size,
+                &snapshot_irqchip,
+                suspended_pvclock_state,
+                vm,
+            )?;
+            snapshot_devices(device_control_tube, snapshot_writer.clone())?;
+
+            let mut stream = StreamWriter::new(std::io::BufWriter::new(file))?;
+            let mut mem_section = stream.section("mem")?;
+            let guest_memory_metadata = match zstd_level {
+                Some(level) => {
+                    let mut mem_writer = ZstdChunkWriter::new(&mut mem_section, level);
+                    let metadata = snapshot_memory(vm, &mut mem_writer, false)?;
+                    mem_writer.finish()?;
+                    metadata
+                }
+                None => snapshot_memory(vm, &mut mem_section, compress_memory)?,
+            };
+            mem_section.finish()?;
+            // Written to the directory rather than the stream so that it is compressed like the
+            // other fragments.
+            snapshot_writer.write_fragment("mem_metadata", &guest_memory_metadata)?;
+            stream.add_dir(&state_root)?;
+            stream.finish()?;
+        }
+    }
+
+    let snap_duration_ms = snapshot_start.elapsed().as_millis();
+    info!(
+        "snapshot: completed snapshot in {}ms; VM mem size: {}MB",
+        snap_duration_ms,
+        vm.get_memory().memory_size() / 1024 / 1024,
+    );
+    metrics::log_metric_with_details(
+        metrics::MetricEventType::SnapshotSaveOverallLatency,
+        snap_duration_ms as i64,
+        &metrics_events::RecordDetails {},
+    );
+    Ok(())
+}
+
+/// Writes guest memory to `w` and returns its metadata. The vCPUs and devices must be stopped.
+fn snapshot_memory(
+    vm: &impl Vm,
+    w: &mut impl Write,
+    compress_memory: bool,
+) -> anyhow::Result<AnySnapshot> {
+    let mem_snap_start = Instant::now();
+    // SAFETY:
+    // VM & devices are stopped.
+    let guest_memory_metadata = unsafe {
+        vm.get_memory()
+            .snapshot(w, compress_memory)
+            .context("failed to snapshot memory")?
+    };
+    w.flush().context("failed to write memory snapshot")?;
+
+    let mem_snap_duration_ms = mem_snap_start.elapsed().as_millis();
+    info!(
+        "snapshot: memory snapshotted {}MB in {}ms",
+        vm.get_memory().memory_size() / 1024 / 1024,
+        mem_snap_duration_ms
+    );
+    metrics::log_metric_with_details(
+        metrics::MetricEventType::SnapshotSaveMemoryLatency,
+        mem_snap_duration_ms as i64,
+        &metrics_events::RecordDetails {},
+    );
+    Ok(guest_memory_metadata)
+}
+
+/// Flushes all pending IRQs to the interrupt controller so that its state can be saved.
+fn flush_irqs(irq_handler_control: &Tube) -> anyhow::Result<()> {
     // We want to flush all pending IRQs to the interrupt controller. There are two cases:
     //
     // MSIs: these are directly delivered to the interrupt controller.
@@ -2420,38 +3390,84 @@ fn do_snapshot(
     // Note: within CrosVM, *all* interrupts are eventually converted into the
     // same mechanicism that MSIs use. This is why we say "underlying" MSI for
     // a legacy IRQ.
-    {
-        let mut flush_attempts = 0;
-        loop {
-            irq_handler_control
-                .send(&IrqHandlerRequest::WakeAndNotifyIteration)
-                .context("failed to send flush command to IRQ handler thread")?;
-            let resp = irq_handler_control
-                .recv()
-                .context("failed to recv flush response from IRQ handler thread")?;
-            match resp {
-                IrqHandlerResponse::HandlerIterationComplete(tokens_serviced) => {
-                    if tokens_serviced == 0 {
-                        break;
-                    }
+    let mut flush_attempts = 0;
+    loop {
+        irq_handler_control
+            .send(&IrqHandlerRequest::WakeAndNotifyIteration)
+            .context("failed to send flush command to IRQ handler thread")?;
+        let resp = irq_handler_control
+            .recv()
+            .context("failed to recv flush response from IRQ handler thread")?;
+        match resp {
+            IrqHandlerResponse::HandlerIterationComplete(tokens_serviced) => {
+                if tokens_serviced == 0 {
+                    break;
                 }
-                _ => bail!("received unexpected reply from IRQ handler: {:?}", resp),
-            }
-            flush_attempts += 1;
-            if flush_attempts > EXPECTED_MAX_IRQ_FLUSH_ITERATIONS {
-                warn!(
-                    "flushing IRQs for snapshot may be stalled after iteration {}, expected <= {}
-                      iterations",
-                    flush_attempts, EXPECTED_MAX_IRQ_FLUSH_ITERATIONS
-                );
             }
+            _ => bail!("received unexpected reply from IRQ handler: {:?}", resp),
+        }
+        flush_attempts += 1;
+        if flush_attempts > EXPECTED_MAX_IRQ_FLUSH_ITERATIONS {
+            warn!(
+                "flushing IRQs for snapshot may be stalled after iteration {}, expected <= {}
+                  iterations",
+                flush_attempts, EXPECTED_MAX_IRQ_FLUSH_ITERATIONS
+            );
         }
-        info!("flushed IRQs in {} iterations", flush_attempts);
     }
-    let snapshot_writer = SnapshotWriter::new(snapshot_path, encrypt)?;
+    info!("flushed IRQs in {} iterations", flush_attempts);
+    Ok(())
+}
 
-    // Snapshot hypervisor's paravirtualized clock.
-    snapshot_writer.write_fragment("pvclock", &AnySnapshot::to_any(suspended_pvclock_state)?)?;
+/// Host wall clock time at which a snapshot was taken.
+///
+/// Used on restore to move the guest clock forward by the time the VM spent in the snapshot.
+#[derive(Serialize, Deserialize)]
+struct SnapshotClock {
+    /// Nanoseconds since the UNIX epoch.
+    realtime_ns: u64,
+}
+
+impl SnapshotClock {
+    fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        SnapshotClock {
+            realtime_ns: since_epoch.as_nanos().try_into().unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Returns how long ago the snapshot was taken.
+    fn elapsed(&self) -> Duration {
+        let taken = SystemTime::UNIX_EPOCH + Duration::from_nanos(self.realtime_ns);
+        SystemTime::now().duration_since(taken).unwrap_or_else(|_| {
+            warn!("snapshot was taken in the future (was the host clock adjusted?)");
+            Duration::ZERO
+        })
+    }
+}
+
+/// Writes the paravirtualized clock, vCPU and irqchip state to `snapshot_writer`.
+fn snapshot_cpu_state(
+    snapshot_writer: &SnapshotWriter,
+    kick_vcpus: &impl Fn(VcpuControl),
+    vcpu_size: usize,
+    snapshot_irqchip: &impl Fn() -> anyhow::Result<AnySnapshot>,
+    suspended_pvclock_state: &Option<hypervisor::ClockState>,
+    vm: &impl Vm,
+) -> anyhow::Result<()> {
+    // Snapshot hypervisor's paravirtualized clock. It keeps ticking unless the VM was suspended
+    // with `VmRequest::SuspendVm`, in which case the value saved at that point is used.
+    let pvclock_state = match suspended_pvclock_state {
+        Some(state) => Some(*state),
+        None if vm.check_capability(VmCap::PvClock) => {
+            Some(vm.get_pvclock().context("failed to read pvclock")?)
+        }
+        None => None,
+    };
+    snapshot_writer.write_fragment("pvclock", &AnySnapshot::to_any(pvclock_state)?)?;
+    snapshot_writer.write_fragment("clock", &SnapshotClock::now())?;
 
     // Snapshot Vcpus
     info!("VCPUs snapshotting...");
@@ -2476,40 +3492,14 @@ fn do_snapshot(
         .write_fragment("irqchip", &irqchip_snap)
         .context("Failed to write irqchip state")?;
     info!("Snapshotted irqchip.");
+    Ok(())
+}
 
-    // Snapshot memory
-    {
-        let mem_snap_start = Instant::now();
-        // Use 64MB chunks when writing the memory snapshot (if encryption is used).
-        const MEMORY_SNAP_ENCRYPTED_CHUNK_SIZE_BYTES: usize = 1024 * 1024 * 64;
-        // SAFETY:
-        // VM & devices are stopped.
-        let guest_memory_metadata = unsafe {
-            vm.get_memory()
-                .snapshot(
-                    &mut snapshot_writer.raw_fragment_with_chunk_size(
-                        "mem",
-                        MEMORY_SNAP_ENCRYPTED_CHUNK_SIZE_BYTES,
-                    )?,
-                    compress_memory,
-                )
-                .context("failed to snapshot memory")?
-        };
-        snapshot_writer.write_fragment("mem_metadata", &guest_memory_metadata)?;
-
-        let mem_snap_duration_ms = mem_snap_start.elapsed().as_millis();
-        info!(
-            "snapshot: memory snapshotted {}MB in {}ms",
-            vm.get_memory().memory_size() / 1024 / 1024,
-            mem_snap_duration_ms
-        );
-        metrics::log_metric_with_details(
-            metrics::MetricEventType::SnapshotSaveMemoryLatency,
-            mem_snap_duration_ms as i64,
-            &metrics_events::RecordDetails {},
-        );
-    }
-    // Snapshot devices
+/// Asks the devices to write their state to `snapshot_writer`.
+fn snapshot_devices(
+    device_control_tube: &Tube,
+    snapshot_writer: SnapshotWriter,
+) -> anyhow::Result<()> {
     info!("Devices snapshotting...");
     device_control_tube
         .send(&DeviceControlCommand::SnapshotDevices { snapshot_writer })
@@ -2521,18 +3511,6 @@ fn do_snapshot(
         bail!("unexpected SnapshotDevices response: {resp}");
     }
     info!("Devices snapshotted.");
-
-    let snap_duration_ms = snapshot_start.elapsed().as_millis();
-    info!(
-        "snapshot: completed snapshot in {}ms; VM mem size: {}MB",
-        snap_duration_ms,
-        vm.get_memory().memory_size() / 1024 / 1024,
-    );
-    metrics::log_metric_with_details(
-        metrics::MetricEventType::SnapshotSaveOverallLatency,
-        snap_duration_ms as i64,
-        &metrics_events::RecordDetails {},
-    );
     Ok(())
 }
 
@@ -2540,6 +3518,10 @@ fn do_snapshot(
 ///
 /// Same as `VmRequest::execute` with a `VmRequest::Restore`. Exposed as a separate function
 /// because not all the `VmRequest::execute` arguments are available in the "cold restore" flow.
+///
+/// `key` must be given if the snapshot was encrypted with a user provided key. If `share_memory`
+/// is set, guest memory is mapped from the snapshot rather than copied (see
+/// `GuestMemory::restore_shared`).
 pub fn do_restore(
     restore_path: &Path,
     kick_vcpus: impl Fn(VcpuControl),
@@ -2549,6 +3531,8 @@ pub fn do_restore(
     vcpu_size: usize,
     mut restore_irqchip: impl FnMut(AnySnapshot) -> anyhow::Result<()>,
     require_encrypted: bool,
+    key: Option<CryptKey>,
+    share_memory: bool,
     suspended_pvclock_state: &mut Option<hypervisor::ClockState>,
     vm: &impl Vm,
 ) -> anyhow::Result<()> {
@@ -2556,10 +3540,74 @@ pub fn do_restore(
     let _guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size);
     let _devices_guard = DeviceSleepGuard::new(device_control_tube)?;
 
-    let snapshot_reader = SnapshotReader::new(restore_path, require_encrypted)?;
+    let snapshot_reader = SnapshotReader::new_with_key(restore_path, require_encrypted, key)?;
+    restore_from_reader(
+        &snapshot_reader,
+        if share_memory {
+            RestoreMemory::Shared
+        } else {
+            RestoreMemory::Copy
+        },
+        kick_vcpu,
+        irq_handler_control,
+        device_control_tube,
+        vcpu_size,
+        &mut restore_irqchip,
+        suspended_pvclock_state,
+        vm,
+    )?;
 
-    // Restore hypervisor's paravirtualized clock.
-    *suspended_pvclock_state = snapshot_reader.read_fragment("pvclock")?;
+    let restore_duration_ms = restore_start.elapsed().as_millis();
+    info!(
+        "snapshot: completed restore in {}ms; mem size: {}",
+        restore_duration_ms,
+        vm.get_memory().memory_size(),
+    );
+
+    metrics::log_metric_with_details(
+        metrics::MetricEventType::SnapshotRestoreOverallLatency,
+        restore_duration_ms as i64,
+        &metrics_events::RecordDetails {},
+    );
+    Ok(())
+}
+
+/// How `restore_from_reader` restores guest memory.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RestoreMemory {
+    /// Guest memory is not part of the snapshot, e.g. live migration transfers it separately.
+    Skip,
+    /// Copy the snapshot into guest memory.
+    Copy,
+    /// Map guest memory from the snapshot, sharing the pages nobody writes with other VMs.
+    Shared,
+}
+
+/// Restores the state in `snapshot_reader` to a VM whose vCPUs and devices are suspended.
+fn restore_from_reader(
+    snapshot_reader: &SnapshotReader,
+    memory: RestoreMemory,
+    kick_vcpu: impl Fn(VcpuControl, usize),
+    irq_handler_control: &Tube,
+    device_control_tube: &Tube,
+    vcpu_size: usize,
+    restore_irqchip: &mut impl FnMut(AnySnapshot) -> anyhow::Result<()>,
+    suspended_pvclock_state: &mut Option<hypervisor::ClockState>,
+    vm: &impl Vm,
+) -> anyhow::Result<()> {
+    let pvclock_state: Option<hypervisor::ClockState> = snapshot_reader.read_fragment("pvclock")?;
+    // Snapshots taken before the host time was recorded don't get their clock moved forward.
+    let time_in_snapshot = if snapshot_reader
+        .list_fragments()?
+        .iter()
+        .any(|name| name == "clock")
+    {
+        snapshot_reader
+            .read_fragment::<SnapshotClock>("clock")?
+            .elapsed()
+    } else {
+        Duration::ZERO
+    };
 
     // Restore IrqChip
     let irq_snapshot: AnySnapshot = snapshot_reader.read_fragment("irqchip")?;
@@ -2600,17 +3648,25 @@ pub fn do_restore(
     }
 
     // Restore Memory
-    {
+    if memory != RestoreMemory::Skip {
         let mem_restore_start = Instant::now();
         let guest_memory_metadata = snapshot_reader.read_fragment("mem_metadata")?;
-        // SAFETY:
-        // VM & devices are stopped.
-        unsafe {
-            vm.get_memory().restore(
+        if memory == RestoreMemory::Shared {
+            restore_memory_shared(
+                vm,
                 guest_memory_metadata,
-                &mut snapshot_reader.raw_fragment("mem")?,
-            )?
-        };
+                &snapshot_reader.fragment_file("mem")?,
+            )?;
+        } else {
+            // SAFETY:
+            // VM & devices are stopped.
+            unsafe {
+                vm.get_memory().restore(
+                    guest_memory_metadata,
+                    &mut snapshot_reader.raw_fragment("mem")?,
+                )?
+            };
+        }
         let mem_restore_duration_ms = mem_restore_start.elapsed().as_millis();
         info!(
             "snapshot: memory restored {}MB in {}ms",
@@ -2652,23 +3708,126 @@ pub fn do_restore(
         }
     }
 
-    let restore_duration_ms = restore_start.elapsed().as_millis();
-    info!(
-        "snapshot: completed restore in {}ms; mem size: {}",
-        restore_duration_ms,
-        vm.get_memory().memory_size(),
-    );
-
-    metrics::log_metric_with_details(
-        metrics::MetricEventType::SnapshotRestoreOverallLatency,
-        restore_duration_ms as i64,
-        &metrics_events::RecordDetails {},
-    );
+    // Restore hypervisor's paravirtualized clock. The vCPUs run right after a restore, without a
+    // `VmRequest::ResumeVm`, so set it now. It is moved forward by the time the VM spent in the
+    // snapshot, which updates the guest's wall clock.
+    *suspended_pvclock_state = None;
+    if let Some(mut state) = pvclock_state {
+        if vm.check_capability(VmCap::PvClock) {
+            state.clock = state
+                .clock
+                .saturating_add(time_in_snapshot.as_nanos().try_into().unwrap_or(u64::MAX));
+            vm.set_pvclock(&state)
+                .context("failed to restore pvclock")?;
+            info!(
+                "snapshot: moved pvclock forward by {}ms",
+                time_in_snapshot.as_millis()
+            );
+        }
+    }
     Ok(())
 }
 
+/// Maps guest memory from the uncompressed memory snapshot `file`. The vCPUs and devices must be
+/// stopped.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn restore_memory_shared(
+    vm: &impl Vm,
+    guest_memory_metadata: AnySnapshot,
+    file: &File,
+) -> anyhow::Result<()> {
+    // SAFETY:
+    // VM & devices are stopped.
+    unsafe {
+        vm.get_memory()
+            .restore_shared(guest_memory_metadata, file)
+            .context("failed to map memory snapshot")
+    }
+}
+
+#[cfg(windows)]
+fn restore_memory_shared(
+    _vm: &impl Vm,
+    _guest_memory_metadata: AnySnapshot,
+    _file: &File,
+) -> anyhow::Result<()> {
+    bail!("sharing snapshot memory is not supported on this platform")
+}
+
 pub type HypervisorKind = hypervisor::HypervisorKind;
 
+/// Ramoops zone a pstore record was read from.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PstoreRecordKind {
+    /// Kernel log dumped on panic or oops.
+    Dmesg,
+    /// Kernel console output.
+    Console,
+    /// Function trace.
+    Ftrace,
+    /// Messages written by userspace to /dev/pmsg0.
+    Pmsg,
+}
+
+impl Display for PstoreRecordKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PstoreRecordKind::Dmesg => write!(f, "dmesg"),
+            PstoreRecordKind::Console => write!(f, "console"),
+            PstoreRecordKind::Ftrace => write!(f, "ftrace"),
+            PstoreRecordKind::Pmsg => write!(f, "pmsg"),
+        }
+    }
+}
+
+impl FromStr for PstoreRecordKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "dmesg" => Ok(PstoreRecordKind::Dmesg),
+            "console" => Ok(PstoreRecordKind::Console),
+            "ftrace" => Ok(PstoreRecordKind::Ftrace),
+            "pmsg" => Ok(PstoreRecordKind::Pmsg),
+            _ => Err(format!(
+                "invalid pstore record kind {}, expected dmesg, console, ftrace or pmsg",
+                s
+            )),
+        }
+    }
+}
+
+/// Record read from a pstore buffer.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PstoreRecord {
+    /// 0 for the buffer of the running VM, n for the copy taken n boots ago.
+    pub boot: u32,
+    pub kind: PstoreRecordKind,
+    /// Content of the record. Dmesg records start with a `====<seconds>.<nanoseconds>-<C|D>`
+    /// header line, `C` meaning that the rest of the record is compressed by the guest.
+    pub data: Vec<u8>,
+}
+
+/// Change of a configuration option requested by `VmRequest::ReloadConfig`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConfigChange {
+    /// Name of the option, as in the configuration file.
+    pub option: String,
+    /// What was done to apply the change, or why it could not be applied.
+    pub detail: String,
+}
+
+/// Outcome of `VmRequest::ReloadConfig`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigReloadReport {
+    /// Changes applied to the running VM.
+    pub applied: Vec<ConfigChange>,
+    /// Changes that cannot be made without restarting the VM. The running VM keeps the previous
+    /// value of these options.
+    pub rejected: Vec<ConfigChange>,
+}
+
 /// Indication of success or failure of a `VmRequest`.
 ///
 /// Success is usually indicated `VmResponse::Ok` unless there is data associated with the response.
@@ -2695,6 +3854,9 @@ pub enum VmResponse {
         ws: balloon_control::BalloonWS,
         balloon_actual: u64,
     },
+    /// Result of the balloon `WorkingSetReport` command.
+    #[cfg(feature = "balloon")]
+    BalloonWorkingSetReport(WorkingSetReport),
     /// Results of PCI hot plug
     #[cfg(feature = "pci-hotplug")]
     PciHotPlugResponse { bus: u8 },
@@ -2707,6 +3869,12 @@ pub enum VmResponse {
     BatResponse(BatControlResult),
     /// Results of swap status command.
     SwapStatus(SwapStatus),
+    /// Results of shared memory stats command.
+    SharedMemoryStats(SharedMemoryStats),
+    /// Results of NUMA binding stats command.
+    NumaBindingStats(Vec<NumaBindingStats>),
+    /// Results of KSM stats command.
+    KsmStats(KsmStats),
     /// Gets the state of Devices (sleep/wake)
     DevicesState(DevicesState),
     /// Map of the Vcpu PID/TIDs
@@ -2717,6 +3885,25 @@ pub enum VmResponse {
         hypervisor: HypervisorKind,
         vm_fd: SafeDescriptor,
     },
+    /// Run mode of the VCPUs.
+    RunMode(VmRunMode),
+    /// Description of the devices attached to the VM.
+    Devices(Vec<DeviceInfo>),
+    /// Exit statistics of each vCPU.
+    VcpuStats(Vec<VcpuExitStats>),
+    /// Interrupt latency histogram of each GSI.
+    IrqLatencyStats(BTreeMap<u32, IrqLatencyHistogram>),
+    /// Tracing categories and whether they are enabled.
+    TracingCategories(BTreeMap<String, bool>),
+    /// Records read from the pstore buffers, most recent boot first.
+    PstoreRecords(Vec<PstoreRecord>),
+    /// Offset in seconds of the RTC from the host time.
+    RtcOffset { offset_secs: i64 },
+    /// Changes applied and rejected by a configuration reload.
+    ConfigReloadReport(ConfigReloadReport),
+    /// The VM was stopped by `VmRequest::Powerdown`, `forced` if the guest did not shut down
+    /// before the timeout.
+    PoweredDown { forced: bool },
 }
 
 impl Display for VmResponse {
@@ -2751,6 +3938,15 @@ impl Display for VmResponse {
                     balloon_actual,
                 )
             }
+            #[cfg(feature = "balloon")]
+            VmResponse::BalloonWorkingSetReport(report) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string_pretty(&report)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
             UsbResponse(result) => write!(f, "usb control request get result {:?}", result),
             #[cfg(feature = "pci-hotplug")]
             PciHotPlugResponse { bus } => write!(f, "pci hotplug bus {:?}", bus),
@@ -2765,11 +3961,85 @@ impl Display for VmResponse {
                         .unwrap_or_else(|_| "invalid_response".to_string()),
                 )
             }
+            SharedMemoryStats(stats) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string(&stats)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
+            KsmStats(stats) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string(&stats)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
+            NumaBindingStats(stats) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string_pretty(&stats)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
             DevicesState(status) => write!(f, "devices status: {:?}", status),
             VcpuPidTidResponse { pid_tid_map } => write!(f, "vcpu pid tid map: {:?}", pid_tid_map),
             VmDescriptor { hypervisor, vm_fd } => {
                 write!(f, "hypervisor: {:?}, vm_fd: {:?}", hypervisor, vm_fd)
             }
+            RunMode(mode) => write!(f, "run mode: {}", mode),
+            Devices(devices) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string_pretty(&devices)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
+            VcpuStats(stats) => {
+                for vcpu in stats {
+                    write!(f, "{}", vcpu)?;
+                }
+                Ok(())
+            }
+            IrqLatencyStats(stats) => {
+                for (gsi, histogram) in stats {
+                    write!(f, "gsi {}: {}", gsi, histogram)?;
+                }
+                Ok(())
+            }
+            TracingCategories(categories) => {
+                for (name, enabled) in categories {
+                    writeln!(
+                        f,
+                        "{}: {}",
+                        name,
+                        if *enabled { "enabled" } else { "disabled" }
+                    )?;
+                }
+                Ok(())
+            }
+            PstoreRecords(records) => {
+                for record in records {
+                    writeln!(f, "--- {} record, boot {} ---", record.kind, record.boot)?;
+                    writeln!(f, "{}", String::from_utf8_lossy(&record.data))?;
+                }
+                Ok(())
+            }
+            RtcOffset { offset_secs } => write!(f, "rtc offset: {} seconds", offset_secs),
+            ConfigReloadReport(report) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string_pretty(&report)
+                        .unwrap_or_else(|_| "invalid_response".to_string()),
+                )
+            }
+            PoweredDown { forced: false } => write!(f, "guest shut down"),
+            PoweredDown { forced: true } => write!(f, "guest did not shut down, VM stopped"),
         }
     }
 }
@@ -2807,12 +4077,14 @@ pub enum VirtioIOMMUVfioCommand {
     VfioDeviceDel {
         endpoint_addr: u32,
     },
-    // Map a dma-buf into vfio iommu table
+    // Map a dma-buf, or another region such as the BAR of a VFIO device, into vfio iommu table
     VfioDmabufMap {
         region_id: VmMemoryRegionId,
         gpa: u64,
         size: u64,
         dma_buf: SafeDescriptor,
+        // offset of the region in `dma_buf`
+        offset: u64,
     },
     // Unmap a dma-buf from vfio iommu table
     VfioDmabufUnmap(VmMemoryRegionId),
@@ -2938,6 +4210,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn state_dir_is_removed_on_drop() {
+        let state_dir = StateDir::new().unwrap();
+        let other = StateDir::new().unwrap();
+        assert_ne!(state_dir.path(), other.path());
+        let path = state_dir.path().to_path_buf();
+        std::fs::create_dir(path.join("snapshot")).unwrap();
+        std::fs::write(path.join("snapshot").join("cpu"), b"state").unwrap();
+        drop(state_dir);
+        assert!(!path.exists());
+        assert!(other.path().exists());
+    }
+
     #[test]
     fn vm_memory_response_error_deserialization_should_handle_malformat_correctly() {
         let flat_source = FlatVmMemoryResponseError(vec![]);
@@ -2946,4 +4231,72 @@ mod tests {
         serde_json::from_slice::<VmMemoryResponseError>(&serialized_bytes)
             .expect_err("deserialize with 0 error messages should fail");
     }
+
+    #[test]
+    fn net_guest_offloads_parse_and_display() {
+        let offloads: NetGuestOffloads = "csum,tso6".parse().unwrap();
+        assert_eq!(
+            offloads,
+            NetGuestOffloads {
+                csum: true,
+                tso6: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(offloads.to_string(), "csum,tso6");
+
+        let none: NetGuestOffloads = "none".parse().unwrap();
+        assert_eq!(none, NetGuestOffloads::default());
+        assert_eq!(none.to_string(), "none");
+
+        assert!("csum,gso".parse::<NetGuestOffloads>().is_err());
+        assert!("".parse::<NetGuestOffloads>().is_err());
+    }
+
+    #[test]
+    fn vcpu_exit_stats_should_serialize_and_deserialize_correctly() {
+        let mut stats = VcpuExitStats::new(1);
+        stats.record(VcpuExitReason::Mmio, Duration::from_micros(3));
+        stats.record(VcpuExitReason::Mmio, Duration::from_micros(4));
+        stats.record(VcpuExitReason::Io, Duration::from_micros(1));
+        assert_eq!(
+            stats.exits[&VcpuExitReason::Mmio],
+            VcpuExitStat {
+                count: 2,
+                duration: Duration::from_micros(7),
+            }
+        );
+
+        let serialized_bytes =
+            serde_json::to_vec(&stats).expect("should serialize to json successfully");
+        let deserialized = serde_json::from_slice::<VcpuExitStats>(&serialized_bytes)
+            .expect("should deserialize from json successfully");
+        assert_eq!(stats, deserialized);
+    }
+
+    #[test]
+    fn irq_latency_histogram_buckets() {
+        let mut histogram = IrqLatencyHistogram::default();
+        histogram.record(Duration::from_nanos(500));
+        histogram.record(Duration::from_micros(1));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_micros(4));
+        histogram.record(Duration::from_secs(10));
+
+        assert_eq!(histogram.count, 5);
+        assert_eq!(histogram.min, Duration::from_nanos(500));
+        assert_eq!(histogram.max, Duration::from_secs(10));
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[1], 1);
+        assert_eq!(histogram.buckets[2], 1);
+        assert_eq!(histogram.buckets[3], 1);
+        assert_eq!(histogram.buckets[IRQ_LATENCY_BUCKETS - 1], 1);
+        assert_eq!(histogram.buckets.iter().sum::<u64>(), histogram.count);
+
+        let serialized_bytes =
+            serde_json::to_vec(&histogram).expect("should serialize to json successfully");
+        let deserialized = serde_json::from_slice::<IrqLatencyHistogram>(&serialized_bytes)
+            .expect("should deserialize from json successfully");
+        assert_eq!(histogram, deserialized);
+    }
 }
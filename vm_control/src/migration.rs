@@ -0,0 +1,756 @@
+// Copyright 2025 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Live migration of a VM to another crosvm process over a TCP connection.
+//!
+//! The source copies all of guest memory while the VM keeps running, then repeatedly copies the
+//! pages the guest wrote in the meantime, as reported by the hypervisor's dirty page log. Once a
+//! round leaves little enough to copy, the VM is suspended, the remaining pages and the vCPU,
+//! irqchip and device state are sent, and the VM stays suspended until the destination confirms
+//! that it took over.
+//!
+//! The hypervisor only logs the writes of the vCPUs. The pages written by devices are logged by
+//! `GuestMemory` and sent once the devices are asleep.
+//!
+//! After a magic number, the stream is a sequence of messages, each starting with a one byte tag.
+//! All integers are little endian:
+//!
+//! ```text
+//! MEMORY: guest_addr: u64, len: u64, data: [u8; len]
+//! STATE:  path_len: u32, path: [u8; path_len], len: u64, data: [u8; len]
+//! DONE
+//! ```
+//!
+//! `STATE` messages carry the files of a snapshot without guest memory, with paths relative to
+//! the snapshot root. The destination answers `DONE` with a single status byte once the state was
+//! restored.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::bail;
+use anyhow::Context;
+use base::error;
+use base::info;
+use base::pagesize;
+use base::Tube;
+use hypervisor::Vm;
+use snapshot::AnySnapshot;
+use snapshot::SnapshotReader;
+use snapshot::SnapshotWriter;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+
+use crate::flush_irqs;
+use crate::restore_from_reader;
+use crate::snapshot_cpu_state;
+use crate::snapshot_devices;
+use crate::DeviceSleepGuard;
+use crate::RestoreMemory;
+use crate::StateDir;
+use crate::VcpuControl;
+use crate::VcpuSuspendGuard;
+
+const MAGIC: [u8; 8] = *b"CVMMIG01";
+
+const MSG_MEMORY: u8 = 1;
+const MSG_STATE: u8 = 2;
+const MSG_DONE: u8 = 3;
+
+const STATUS_OK: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+
+/// Largest amount of guest memory read and sent at once.
+const MEMORY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Number of dirty page copy rounds after which the migration is abandoned.
+const MAX_DIRTY_ROUNDS: usize = 30;
+
+/// The VM is stopped once a round finds at most this many dirty bytes, which bounds the amount
+/// of memory copied while it is suspended.
+const STOP_AND_COPY_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Snapshot root directory inside the temporary directory holding the VM state.
+const STATE_DIR: &str = "state";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    Memory { guest_addr: u64, len: u64 },
+    State { path: PathBuf, len: u64 },
+    Done,
+}
+
+/// Strips the `tcp://` scheme from a migration address.
+fn parse_address(address: &str) -> anyhow::Result<&str> {
+    match address.strip_prefix("tcp://") {
+        Some(addr) if !addr.is_empty() => Ok(addr),
+        _ => bail!("migration address {address:?} must be of the form tcp://HOST:PORT"),
+    }
+}
+
+fn read_u8(r: &mut impl Read) -> anyhow::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> anyhow::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> anyhow::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn write_memory(w: &mut impl Write, guest_addr: GuestAddress, data: &[u8]) -> anyhow::Result<()> {
+    w.write_all(&[MSG_MEMORY])?;
+    w.write_all(&guest_addr.offset().to_le_bytes())?;
+    w.write_all(&(data.len() as u64).to_le_bytes())?;
+    w.write_all(data)?;
+    Ok(())
+}
+
+fn write_state_header(w: &mut impl Write, path: &Path, len: u64) -> anyhow::Result<()> {
+    let path = path
+        .to_str()
+        .with_context(|| format!("state file path {} is not UTF-8", path.display()))?;
+    w.write_all(&[MSG_STATE])?;
+    w.write_all(&(path.len() as u32).to_le_bytes())?;
+    w.write_all(path.as_bytes())?;
+    w.write_all(&len.to_le_bytes())?;
+    Ok(())
+}
+
+/// Reads the header of the next message. The payload of `Memory` and `State` messages follows.
+fn read_message(r: &mut impl Read) -> anyhow::Result<Message> {
+    match read_u8(r)? {
+        MSG_MEMORY => {
+            let guest_addr = read_u64(r)?;
+            let len = read_u64(r)?;
+            Ok(Message::Memory { guest_addr, len })
+        }
+        MSG_STATE => {
+            let path_len = read_u32(r)?;
+            let mut path = vec![0u8; path_len as usize];
+            r.read_exact(&mut path)?;
+            let path = PathBuf::from(String::from_utf8(path).context("state path is not UTF-8")?);
+            // The path is joined to a local directory, so it must not be able to escape it.
+            if path.as_os_str().is_empty()
+                || !path.components().all(|c| matches!(c, Component::Normal(_)))
+            {
+                bail!("invalid state file path {}", path.display());
+            }
+            let len = read_u64(r)?;
+            Ok(Message::State { path, len })
+        }
+        MSG_DONE => Ok(Message::Done),
+        tag => bail!("unknown migration message {tag}"),
+    }
+}
+
+/// Guest memory region being migrated.
+struct RegionState {
+    index: usize,
+    guest_addr: GuestAddress,
+    num_pages: usize,
+    /// Pages written since they were last sent, in the format of a KVM dirty log bitmap.
+    dirty_log: Vec<u8>,
+}
+
+impl RegionState {
+    fn is_dirty(&self, page: usize) -> bool {
+        self.dirty_log[page / 8] & (1 << (page % 8)) != 0
+    }
+}
+
+/// Keeps track of which guest memory must be sent again.
+struct MemorySender<'a, W: Write> {
+    stream: &'a mut W,
+    mem: &'a GuestMemory,
+    page_size: usize,
+    regions: Vec<RegionState>,
+    buf: Vec<u8>,
+    /// Receives the dirty log of a region before it is merged into the pending pages.
+    log_buf: Vec<u8>,
+}
+
+impl<'a, W: Write> MemorySender<'a, W> {
+    fn new(stream: &'a mut W, mem: &'a GuestMemory) -> Self {
+        let page_size = pagesize();
+        let regions = mem
+            .regions()
+            .map(|region| {
+                let num_pages = region.size.div_ceil(page_size);
+                RegionState {
+                    index: region.index,
+                    guest_addr: region.guest_addr,
+                    num_pages,
+                    // KVM fills the bitmap in units of 64 bits.
+                    dirty_log: vec![0; num_pages.div_ceil(64) * 8],
+                }
+            })
+            .collect();
+        let log_len = regions
+            .iter()
+            .map(|r: &RegionState| r.dirty_log.len())
+            .max()
+            .unwrap_or(0);
+        MemorySender {
+            stream,
+            mem,
+            page_size,
+            regions,
+            buf: vec![0; MEMORY_CHUNK_SIZE],
+            log_buf: vec![0; log_len],
+        }
+    }
+
+    /// Sends all of guest memory.
+    fn send_all(&mut self) -> anyhow::Result<u64> {
+        let mut sent = 0;
+        for region in 0..self.regions.len() {
+            sent += self.send_pages(region, 0, self.regions[region].num_pages)?;
+            self.regions[region].dirty_log.fill(0);
+        }
+        Ok(sent)
+    }
+
+    /// Adds the pages logged by `get_dirty_log` for each region to the pages to send, and returns
+    /// the number of bytes to send.
+    fn fetch_dirty_log(
+        &mut self,
+        mut get_dirty_log: impl FnMut(usize, &mut [u8]) -> base::Result<()>,
+    ) -> anyhow::Result<u64> {
+        for region in &mut self.regions {
+            let log = &mut self.log_buf[..region.dirty_log.len()];
+            get_dirty_log(region.index, log).context("failed to get dirty page log")?;
+            for (pending, dirty) in region.dirty_log.iter_mut().zip(log.iter()) {
+                *pending |= dirty;
+            }
+        }
+        Ok(self.dirty_bytes())
+    }
+
+    /// Adds the pages written by devices since the last call to the pages to send, and returns the
+    /// number of bytes to send.
+    fn fetch_write_log(&mut self) -> u64 {
+        for region in &mut self.regions {
+            self.mem.take_write_log(region.index, &mut region.dirty_log);
+        }
+        self.dirty_bytes()
+    }
+
+    fn dirty_bytes(&self) -> u64 {
+        let dirty_pages = self
+            .regions
+            .iter()
+            .flat_map(|region| &region.dirty_log)
+            .map(|b| b.count_ones() as u64)
+            .sum::<u64>();
+        dirty_pages * self.page_size as u64
+    }
+
+    /// Sends the pages to send, which are then considered clean.
+    fn send_dirty(&mut self) -> anyhow::Result<u64> {
+        let mut sent = 0;
+        for region in 0..self.regions.len() {
+            let num_pages = self.regions[region].num_pages;
+            let mut page = 0;
+            while page < num_pages {
+                if !self.regions[region].is_dirty(page) {
+                    page += 1;
+                    continue;
+                }
+                let first = page;
+                while page < num_pages && self.regions[region].is_dirty(page) {
+                    page += 1;
+                }
+                sent += self.send_pages(region, first, page - first)?;
+            }
+            self.regions[region].dirty_log.fill(0);
+        }
+        Ok(sent)
+    }
+
+    /// Sends `count` pages of `region` starting at page `first`. Returns the number of bytes sent.
+    fn send_pages(&mut self, region: usize, first: usize, count: usize) -> anyhow::Result<u64> {
+        let pages_per_chunk = MEMORY_CHUNK_SIZE / self.page_size;
+        let region_addr = self.regions[region].guest_addr;
+        let mut sent = 0;
+        let mut page = first;
+        while page < first + count {
+            let chunk_pages = pages_per_chunk.min(first + count - page);
+            let chunk_addr = region_addr.unchecked_add((page * self.page_size) as u64);
+            let chunk = &mut self.buf[..chunk_pages * self.page_size];
+            self.mem
+                .read_exact_at_addr(chunk, chunk_addr)
+                .context("failed to read guest memory")?;
+            write_memory(self.stream, chunk_addr, chunk)?;
+            sent += chunk.len() as u64;
+            page += chunk_pages;
+        }
+        Ok(sent)
+    }
+}
+
+/// Sends all of guest memory, then the pages dirtied in the meantime, until a round finds at most
+/// `stop_threshold` dirty bytes. These pages are left to send once the VM is stopped. Returns the
+/// number of bytes sent.
+fn precopy<W: Write>(
+    memory: &mut MemorySender<W>,
+    mut get_dirty_log: impl FnMut(usize, &mut [u8]) -> base::Result<()>,
+    stop_threshold: u64,
+) -> anyhow::Result<u64> {
+    let mut sent = memory.send_all()?;
+    info!("migration: sent {}MB of guest memory", sent / 1024 / 1024);
+
+    let mut round = 0;
+    loop {
+        let dirty = memory.fetch_dirty_log(&mut get_dirty_log)?;
+        if dirty <= stop_threshold {
+            return Ok(sent);
+        }
+        round += 1;
+        if round > MAX_DIRTY_ROUNDS {
+            bail!(
+                "migration did not converge: {}MB still dirty after {} rounds",
+                dirty / 1024 / 1024,
+                MAX_DIRTY_ROUNDS
+            );
+        }
+        sent += memory.send_dirty()?;
+        info!(
+            "migration: round {}: sent {}MB of dirty guest memory",
+            round,
+            dirty / 1024 / 1024
+        );
+    }
+}
+
+/// Logs the pages written by vCPUs and devices, and stops logging them when dropped.
+struct DirtyLogGuard<'a, V: Vm> {
+    vm: &'a V,
+}
+
+impl<'a, V: Vm> DirtyLogGuard<'a, V> {
+    fn new(vm: &'a V) -> anyhow::Result<Self> {
+        vm.set_guest_memory_dirty_logging(true)
+            .context("failed to enable dirty page logging")?;
+        vm.get_memory().set_write_logging(true);
+        Ok(DirtyLogGuard { vm })
+    }
+}
+
+impl<V: Vm> Drop for DirtyLogGuard<'_, V> {
+    fn drop(&mut self) {
+        self.vm.get_memory().set_write_logging(false);
+        if let Err(e) = self.vm.set_guest_memory_dirty_logging(false) {
+            error!("failed to disable dirty page logging: {}", e);
+        }
+    }
+}
+
+/// Sends every file below `dir` as a `STATE` message with a path relative to `root`.
+fn send_state_dir(w: &mut impl Write, root: &Path, dir: &Path) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir).context("failed to list state directory")? {
+        let path = entry?.path();
+        if path.is_dir() {
+            send_state_dir(w, root, &path)?;
+            continue;
+        }
+        let mut file = File::open(&path)
+            .with_context(|| format!("failed to open state file {}", path.display()))?;
+        let len = file.metadata()?.len();
+        write_state_header(w, path.strip_prefix(root)?, len)?;
+        let copied = std::io::copy(&mut file, w)?;
+        if copied != len {
+            bail!("state file {} changed while being sent", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Migrates the VM to the crosvm process listening at `destination` (`tcp://HOST:PORT`).
+///
+/// On success, the vCPUs and devices are left suspended: the VM now runs at the destination and
+/// this one must not be resumed. On failure, the VM keeps running here.
+pub(crate) fn send(
+    destination: &str,
+    kick_vcpus: impl Fn(VcpuControl),
+    irq_handler_control: &Tube,
+    device_control_tube: &Tube,
+    vcpu_size: usize,
+    snapshot_irqchip: impl Fn() -> anyhow::Result<AnySnapshot>,
+    suspended_pvclock_state: &Option<hypervisor::ClockState>,
+    vm: &impl Vm,
+) -> anyhow::Result<()> {
+    let migration_start = Instant::now();
+    let address = parse_address(destination)?;
+    let stream = TcpStream::connect(address)
+        .with_context(|| format!("failed to connect to migration destination {address}"))?;
+    stream.set_nodelay(true)?;
+    let mut reader = stream.try_clone()?;
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(&MAGIC)?;
+
+    let _dirty_log_guard = DirtyLogGuard::new(vm)?;
+    let mut memory = MemorySender::new(&mut writer, vm.get_memory());
+    let get_dirty_log = |index: usize, log: &mut [u8]| vm.get_guest_memory_dirty_log(index, log);
+    let mut sent = precopy(&mut memory, get_dirty_log, STOP_AND_COPY_THRESHOLD_BYTES)?;
+
+    let vcpu_guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size)?;
+    let device_guard = DeviceSleepGuard::new(device_control_tube)?;
+    let stop_start = Instant::now();
+    flush_irqs(irq_handler_control)?;
+
+    // The vCPUs and devices are stopped, so the logs now hold every page written since it was
+    // sent. Pages written by devices are only sent here, as a device may still write to memory it
+    // obtained before the log was taken.
+    memory.fetch_dirty_log(get_dirty_log)?;
+    memory.fetch_write_log();
+    let stop_and_copy = memory.send_dirty()?;
+    sent += stop_and_copy;
+
+    let state_dir = StateDir::new()?;
+    let state_root = state_dir.path().join(STATE_DIR);
+    let snapshot_writer = SnapshotWriter::new(state_root.clone(), false)?;
+    snapshot_cpu_state(
+        &snapshot_writer,
+        &kick_vcpus,
+        vcpu_size,
+        &snapshot_irqchip,
+        suspended_pvclock_state,
+        vm,
+    )?;
+    snapshot_devices(device_control_tube, snapshot_writer)?;
+    send_state_dir(&mut writer, &state_root, &state_root)?;
+    writer.write_all(&[MSG_DONE])?;
+    writer.flush()?;
+
+    match read_u8(&mut reader).context("failed to read migration status")? {
+        STATUS_OK => {}
+        status => bail!("migration destination failed to restore the VM (status {status})"),
+    }
+    info!(
+        "migration: completed in {}ms, {}MB sent, VM stopped for {}ms with {}MB sent",
+        migration_start.elapsed().as_millis(),
+        sent / 1024 / 1024,
+        stop_start.elapsed().as_millis(),
+        stop_and_copy / 1024 / 1024,
+    );
+
+    // The VM now runs at the destination, so it must stay suspended here.
+    std::mem::forget(device_guard);
+    std::mem::forget(vcpu_guard);
+    Ok(())
+}
+
+fn receive_stream(
+    reader: &mut impl Read,
+    mem: &GuestMemory,
+    state_root: &Path,
+) -> anyhow::Result<()> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        bail!("not a crosvm migration stream");
+    }
+    let mut buf = vec![0u8; MEMORY_CHUNK_SIZE];
+    loop {
+        match read_message(reader)? {
+            Message::Memory { guest_addr, len } => {
+                let guest_addr = GuestAddress(guest_addr);
+                if len > MEMORY_CHUNK_SIZE as u64 || !mem.is_valid_range(guest_addr, len) {
+                    bail!("invalid guest memory range {guest_addr} + {len:#x}");
+                }
+                let data = &mut buf[..len as usize];
+                reader.read_exact(data)?;
+                mem.write_all_at_addr(data, guest_addr)
+                    .context("failed to write guest memory")?;
+            }
+            Message::State { path, len } => {
+                let path = state_root.join(path);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(&path)
+                    .with_context(|| format!("failed to create {}", path.display()))?;
+                if std::io::copy(&mut reader.by_ref().take(len), &mut file)? != len {
+                    bail!("migration stream ended within {}", path.display());
+                }
+            }
+            Message::Done => return Ok(()),
+        }
+    }
+}
+
+/// Waits for a VM to be migrated from another crosvm process to `listen_address`
+/// (`tcp://HOST:PORT`) and restores it.
+///
+/// Same as `do_restore`, but the state comes from the migration source instead of a snapshot.
+pub fn receive(
+    listen_address: &str,
+    kick_vcpus: impl Fn(VcpuControl),
+    kick_vcpu: impl Fn(VcpuControl, usize),
+    irq_handler_control: &Tube,
+    device_control_tube: &Tube,
+    vcpu_size: usize,
+    mut restore_irqchip: impl FnMut(AnySnapshot) -> anyhow::Result<()>,
+    suspended_pvclock_state: &mut Option<hypervisor::ClockState>,
+    vm: &impl Vm,
+) -> anyhow::Result<()> {
+    let _guard = VcpuSuspendGuard::new(&kick_vcpus, vcpu_size)?;
+    let _devices_guard = DeviceSleepGuard::new(device_control_tube)?;
+
+    let address = parse_address(listen_address)?;
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("failed to listen for migration on {address}"))?;
+    info!("migration: waiting for incoming VM on {}", address);
+    let (stream, peer) = listener.accept()?;
+    info!("migration: receiving VM from {}", peer);
+    let receive_start = Instant::now();
+
+    let mut writer = stream.try_clone()?;
+    let state_dir = StateDir::new()?;
+    let state_root = state_dir.path().join(STATE_DIR);
+    let result = receive_stream(&mut BufReader::new(stream), vm.get_memory(), &state_root)
+        .and_then(|()| {
+            restore_from_reader(
+                &SnapshotReader::new(&state_root, false)?,
+                RestoreMemory::Skip,
+                kick_vcpu,
+                irq_handler_control,
+                device_control_tube,
+                vcpu_size,
+                &mut restore_irqchip,
+                suspended_pvclock_state,
+                vm,
+            )
+        });
+    let status = if result.is_ok() {
+        STATUS_OK
+    } else {
+        STATUS_FAILED
+    };
+    // The source may be gone if the stream broke, and the error that matters is `result`.
+    let _ = writer.write_all(&[status]);
+    result?;
+
+    info!(
+        "migration: received VM in {}ms",
+        receive_start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let mut stream = Vec::new();
+        write_memory(&mut stream, GuestAddress(0x1000), &[1, 2, 3]).unwrap();
+        write_state_header(&mut stream, Path::new("vcpu/0"), 2).unwrap();
+        stream.extend_from_slice(&[4, 5]);
+        stream.push(MSG_DONE);
+
+        let mut r = stream.as_slice();
+        assert_eq!(
+            read_message(&mut r).unwrap(),
+            Message::Memory {
+                guest_addr: 0x1000,
+                len: 3
+            }
+        );
+        let mut data = [0u8; 3];
+        r.read_exact(&mut data).unwrap();
+        assert_eq!(data, [1, 2, 3]);
+        assert_eq!(
+            read_message(&mut r).unwrap(),
+            Message::State {
+                path: PathBuf::from("vcpu/0"),
+                len: 2
+            }
+        );
+        let mut data = [0u8; 2];
+        r.read_exact(&mut data).unwrap();
+        assert_eq!(data, [4, 5]);
+        assert_eq!(read_message(&mut r).unwrap(), Message::Done);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn state_path_must_stay_in_root() {
+        for path in ["../etc/passwd", "/etc/passwd", "a/../../b", ""] {
+            let mut stream = Vec::new();
+            write_state_header(&mut stream, Path::new(path), 0).unwrap();
+            assert!(read_message(&mut stream.as_slice()).is_err(), "{path}");
+        }
+    }
+
+    #[test]
+    fn parse_tcp_address() {
+        assert_eq!(
+            parse_address("tcp://10.0.0.1:1234").unwrap(),
+            "10.0.0.1:1234"
+        );
+        assert!(parse_address("10.0.0.1:1234").is_err());
+        assert!(parse_address("tcp://").is_err());
+    }
+
+    const NUM_PAGES: usize = 8;
+
+    fn source_memory() -> GuestMemory {
+        let mem = GuestMemory::new(&[(GuestAddress(0), (NUM_PAGES * pagesize()) as u64)]).unwrap();
+        for page in 0..NUM_PAGES {
+            mem.write_obj_at_addr(page as u8 + 1, page_addr(page))
+                .unwrap();
+        }
+        mem
+    }
+
+    fn page_addr(page: usize) -> GuestAddress {
+        GuestAddress((page * pagesize()) as u64)
+    }
+
+    /// Fills `log` as a dirty log with `pages` marked.
+    fn mark_dirty(log: &mut [u8], pages: &[usize]) -> base::Result<()> {
+        log.fill(0);
+        for page in pages {
+            log[page / 8] |= 1 << (page % 8);
+        }
+        Ok(())
+    }
+
+    /// Returns the guest addresses of the memory messages in `stream`, checking their contents
+    /// against `mem`.
+    fn sent_pages(stream: &[u8], mem: &GuestMemory) -> Vec<usize> {
+        let mut r = stream;
+        let mut pages = Vec::new();
+        while !r.is_empty() {
+            let Message::Memory { guest_addr, len } = read_message(&mut r).unwrap() else {
+                panic!("unexpected message");
+            };
+            let mut data = vec![0u8; len as usize];
+            r.read_exact(&mut data).unwrap();
+            let mut expected = vec![0u8; len as usize];
+            mem.read_exact_at_addr(&mut expected, GuestAddress(guest_addr))
+                .unwrap();
+            assert_eq!(data, expected);
+            let first = guest_addr as usize / pagesize();
+            pages.extend(first..first + len as usize / pagesize());
+        }
+        pages
+    }
+
+    #[test]
+    fn send_all_copies_memory() {
+        let mem = source_memory();
+        let mut stream = MAGIC.to_vec();
+        let sent = MemorySender::new(&mut stream, &mem).send_all().unwrap();
+        assert_eq!(sent, (NUM_PAGES * pagesize()) as u64);
+        stream.push(MSG_DONE);
+
+        let dest = GuestMemory::new(&[(GuestAddress(0), (NUM_PAGES * pagesize()) as u64)]).unwrap();
+        let state_dir = StateDir::new().unwrap();
+        receive_stream(&mut stream.as_slice(), &dest, state_dir.path()).unwrap();
+        for page in 0..NUM_PAGES {
+            assert_eq!(
+                dest.read_obj_from_addr::<u8>(page_addr(page)).unwrap(),
+                page as u8 + 1
+            );
+        }
+    }
+
+    #[test]
+    fn send_dirty_sends_dirty_pages() {
+        let mem = source_memory();
+        let mut stream = Vec::new();
+        let mut memory = MemorySender::new(&mut stream, &mem);
+        let dirty = memory
+            .fetch_dirty_log(|_, log| mark_dirty(log, &[1, 2, 5]))
+            .unwrap();
+        assert_eq!(dirty, 3 * pagesize() as u64);
+        assert_eq!(memory.send_dirty().unwrap(), 3 * pagesize() as u64);
+        // The pages are clean once sent.
+        assert_eq!(memory.send_dirty().unwrap(), 0);
+        assert_eq!(sent_pages(&stream, &mem), [1, 2, 5]);
+    }
+
+    #[test]
+    fn fetch_write_log_adds_device_writes() {
+        let mem = source_memory();
+        mem.set_write_logging(true);
+        mem.write_obj_at_addr(0xffu8, page_addr(6)).unwrap();
+        let mut stream = Vec::new();
+        let mut memory = MemorySender::new(&mut stream, &mem);
+        memory
+            .fetch_dirty_log(|_, log| mark_dirty(log, &[3]))
+            .unwrap();
+        assert_eq!(memory.fetch_write_log(), 2 * pagesize() as u64);
+        memory.send_dirty().unwrap();
+        mem.set_write_logging(false);
+        assert_eq!(sent_pages(&stream, &mem), [3, 6]);
+    }
+
+    #[test]
+    fn precopy_converges() {
+        let mem = source_memory();
+        let mut stream = Vec::new();
+        let mut memory = MemorySender::new(&mut stream, &mem);
+        let rounds: [&[usize]; 3] = [&[0, 1, 2, 3], &[0, 1, 2], &[4, 5]];
+        let mut round = 0;
+        let sent = precopy(
+            &mut memory,
+            |_, log| {
+                round += 1;
+                mark_dirty(log, rounds[round - 1])
+            },
+            2 * pagesize() as u64,
+        )
+        .unwrap();
+        assert_eq!(round, 3);
+        assert_eq!(sent, ((NUM_PAGES + 4 + 3) * pagesize()) as u64);
+
+        // The pages dirtied in the last round are sent with those dirtied while stopping.
+        let dirty = memory
+            .fetch_dirty_log(|_, log| mark_dirty(log, &[7]))
+            .unwrap();
+        assert_eq!(dirty, 3 * pagesize() as u64);
+        assert_eq!(memory.send_dirty().unwrap(), 3 * pagesize() as u64);
+    }
+
+    #[test]
+    fn precopy_does_not_converge() {
+        let mem = source_memory();
+        let mut stream = Vec::new();
+        let mut memory = MemorySender::new(&mut stream, &mem);
+        let mut rounds = 0;
+        let err = precopy(
+            &mut memory,
+            |_, log| {
+                rounds += 1;
+                mark_dirty(log, &[0, 1, 2, 3])
+            },
+            2 * pagesize() as u64,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("did not converge"), "{err:#}");
+        assert_eq!(rounds, MAX_DIRTY_ROUNDS + 1);
+    }
+}
@@ -4,6 +4,7 @@
 
 //! Track memory regions that are mapped to the guest VM.
 
+use std::collections::BTreeMap;
 use std::convert::AsRef;
 use std::convert::TryFrom;
 use std::fs::File;
@@ -12,7 +13,11 @@ use std::io::Write;
 use std::marker::Send;
 use std::marker::Sync;
 use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::OnceLock;
 
 use anyhow::bail;
 use anyhow::Context;
@@ -24,6 +29,7 @@ use base::MappedRegion;
 use base::MemoryMapping;
 use base::MemoryMappingBuilder;
 use base::MmapError;
+use base::NumaPolicy;
 use base::RawDescriptor;
 use base::SharedMemory;
 use base::VolatileMemory;
@@ -53,6 +59,8 @@ pub enum Error {
     FiledBackedMemoryMappingFailed(#[source] MmapError),
     #[error("failed to open file for file backed mapping: {0}")]
     FiledBackedOpenFailed(#[source] std::io::Error),
+    #[error("huge page backed guest memory is not supported on this platform")]
+    HugetlbUnsupported,
     #[error("invalid guest address {0}")]
     InvalidGuestAddress(GuestAddress),
     #[error("invalid offset {0}")]
@@ -142,6 +150,19 @@ pub enum MemoryRegionPurpose {
     StaticSwiotlbRegion,
 }
 
+/// How the host memory behind a guest memory region is allocated.
+#[derive(Clone, Copy, Debug, Default, PartialOrd, PartialEq, Eq, Ord)]
+pub enum MemoryBacking {
+    /// Part of the sealed memfd shared by all regions of the `GuestMemory`.
+    #[default]
+    Shared,
+
+    /// A dedicated sealed memfd backed by huge pages (`MFD_HUGETLB`). The optional value is the
+    /// huge page size in bytes; the host's default huge page size is used if it is `None`. The
+    /// region size must be a multiple of the huge page size.
+    Hugetlb(Option<u64>),
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, FromKeyValues, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(deny_unknown_fields)]
 pub struct FileBackedMappingParameters {
@@ -174,6 +195,8 @@ pub struct MemoryRegionOptions {
     pub align: u64,
     /// Backing file params.
     pub file_backed: Option<FileBackedMappingParameters>,
+    /// Host memory backing for the region. Ignored if `file_backed` is set.
+    pub backing: MemoryBacking,
 }
 
 impl MemoryRegionOptions {
@@ -195,6 +218,11 @@ impl MemoryRegionOptions {
         self.file_backed = Some(params);
         self
     }
+
+    pub fn backing(mut self, backing: MemoryBacking) -> Self {
+        self.backing = backing;
+        self
+    }
 }
 
 /// A regions of memory mapped memory.
@@ -270,6 +298,14 @@ impl MemoryRegion {
     }
 }
 
+/// Pages written through a `GuestMemory` and its clones, see `GuestMemory::set_write_logging`.
+#[derive(Debug, Default)]
+struct WriteLog {
+    enabled: AtomicBool,
+    /// One bit per page of each region, allocated when logging is first enabled.
+    bitmaps: OnceLock<Vec<Vec<AtomicU64>>>,
+}
+
 /// Tracks memory regions and where they are mapped in the guest, along with shm
 /// descriptors of the underlying memory regions.
 #[derive(Clone, Debug)]
@@ -277,6 +313,8 @@ pub struct GuestMemory {
     regions: Arc<[MemoryRegion]>,
     locked: bool,
     use_dontneed_locked: bool,
+    mergeable: bool,
+    write_log: Arc<WriteLog>,
 }
 
 impl AsRawDescriptors for GuestMemory {
@@ -296,8 +334,9 @@ impl GuestMemory {
         let mut aligned_size = 0;
         let pg_size = pagesize();
         for range in ranges {
-            if range.2.file_backed.is_some() {
-                // Regions with a backing file don't use part of the `SharedMemory`.
+            if range.2.file_backed.is_some() || range.2.backing != MemoryBacking::Shared {
+                // Regions with a backing file or their own memfd don't use part of the
+                // `SharedMemory`.
                 continue;
             }
             if range.1 % pg_size as u64 != 0 {
@@ -364,6 +403,20 @@ impl GuestMemory {
                     obj_offset: file_backed.offset,
                     options: range.2.clone(),
                 });
+            } else if let MemoryBacking::Hugetlb(page_size) = range.2.backing {
+                let region_shm = Arc::new(sys::create_hugetlb_shm(range.1, page_size)?);
+                let mapping = MemoryMappingBuilder::new(size)
+                    .from_shared_memory(region_shm.as_ref())
+                    .align(range.2.align)
+                    .build()
+                    .map_err(Error::MemoryMappingFailed)?;
+                regions.push(MemoryRegion {
+                    mapping,
+                    guest_base: range.0,
+                    shared_obj: BackingObject::Shm(region_shm),
+                    obj_offset: 0,
+                    options: range.2.clone(),
+                });
             } else {
                 let mapping = MemoryMappingBuilder::new(size)
                     .from_shared_memory(shm.as_ref())
@@ -386,6 +439,8 @@ impl GuestMemory {
             regions: Arc::from(regions),
             locked: false,
             use_dontneed_locked: false,
+            mergeable: false,
+            write_log: Default::default(),
         })
     }
 
@@ -428,6 +483,8 @@ impl GuestMemory {
             regions: Arc::from(regions),
             locked: false,
             use_dontneed_locked: false,
+            mergeable: false,
+            write_log: Default::default(),
         })
     }
 
@@ -441,6 +498,11 @@ impl GuestMemory {
         self.use_dontneed_locked
     }
 
+    // Whether `MemoryPolicy::MERGEABLE` was set.
+    pub fn mergeable(&self) -> bool {
+        self.mergeable
+    }
+
     /// Returns the end address of memory.
     ///
     /// # Examples
@@ -535,6 +597,75 @@ impl GuestMemory {
             })
     }
 
+    /// Enables or disables the logging of the pages written through this `GuestMemory` and its
+    /// clones. Enabling the log clears it.
+    ///
+    /// Memory written through host addresses, e.g. by vhost backends, is not logged. The slices
+    /// returned by `get_slice_at_addr` are logged when they are created, as they may be written.
+    pub fn set_write_logging(&self, enable: bool) {
+        if enable {
+            let bitmaps = self.write_log.bitmaps.get_or_init(|| {
+                self.regions
+                    .iter()
+                    .map(|region| {
+                        let num_pages = region.mapping.size().div_ceil(pagesize());
+                        (0..num_pages.div_ceil(64))
+                            .map(|_| AtomicU64::new(0))
+                            .collect()
+                    })
+                    .collect()
+            });
+            for word in bitmaps.iter().flatten() {
+                word.store(0, Ordering::SeqCst);
+            }
+        }
+        self.write_log.enabled.store(enable, Ordering::SeqCst);
+    }
+
+    /// Sets the bit of each page of region `index` written since logging was enabled or the log
+    /// of the region was last taken in `dirty_log`, in the format of a KVM dirty log bitmap, and
+    /// clears the log of the region. The other bits of `dirty_log` are left untouched.
+    pub fn take_write_log(&self, index: usize, dirty_log: &mut [u8]) {
+        let Some(bitmap) = self
+            .write_log
+            .bitmaps
+            .get()
+            .and_then(|bitmaps| bitmaps.get(index))
+        else {
+            return;
+        };
+        for (bytes, word) in dirty_log.chunks_mut(8).zip(bitmap) {
+            let word = word.swap(0, Ordering::SeqCst).to_le_bytes();
+            for (byte, logged) in bytes.iter_mut().zip(word) {
+                *byte |= logged;
+            }
+        }
+    }
+
+    /// Logs a write of `len` bytes at `guest_addr` if write logging is enabled. Called once the
+    /// memory was written, or when it is handed out to be written.
+    pub(crate) fn log_write(&self, guest_addr: GuestAddress, len: usize) {
+        if len == 0 || !self.write_log.enabled.load(Ordering::SeqCst) {
+            return;
+        }
+        let Some(bitmaps) = self.write_log.bitmaps.get() else {
+            return;
+        };
+        let Some((index, region)) = self
+            .regions
+            .iter()
+            .enumerate()
+            .find(|(_, region)| region.contains(guest_addr))
+        else {
+            return;
+        };
+        let offset = guest_addr.offset_from(region.start()) as usize;
+        let end = offset.saturating_add(len).min(region.mapping.size());
+        for page in offset / pagesize()..end.div_ceil(pagesize()) {
+            bitmaps[index][page / 64].fetch_or(1 << (page % 64), Ordering::SeqCst);
+        }
+    }
+
     /// Writes a slice to guest memory at the specified guest address.
     /// Returns the number of bytes written.  The number of bytes written can
     /// be less than the length of the slice if there isn't enough room in the
@@ -556,9 +687,11 @@ impl GuestMemory {
     /// ```
     pub fn write_at_addr(&self, buf: &[u8], guest_addr: GuestAddress) -> Result<usize> {
         let (mapping, offset, _) = self.find_region(guest_addr)?;
-        mapping
+        let written = mapping
             .write_slice(buf, offset)
-            .map_err(|e| Error::MemoryAccess(guest_addr, e))
+            .map_err(|e| Error::MemoryAccess(guest_addr, e))?;
+        self.log_write(guest_addr, written);
+        Ok(written)
     }
 
     /// Writes the entire contents of a slice to guest memory at the specified
@@ -728,7 +861,9 @@ impl GuestMemory {
         let (mapping, offset, _) = self.find_region(guest_addr)?;
         mapping
             .write_obj(val, offset)
-            .map_err(|e| Error::MemoryAccess(guest_addr, e))
+            .map_err(|e| Error::MemoryAccess(guest_addr, e))?;
+        self.log_write(guest_addr, std::mem::size_of::<T>());
+        Ok(())
     }
 
     /// Writes an object to the memory region at the specified guest address.
@@ -757,7 +892,9 @@ impl GuestMemory {
         let (mapping, offset, _) = self.find_region(guest_addr)?;
         mapping
             .write_obj_volatile(val, offset)
-            .map_err(|e| Error::MemoryAccess(guest_addr, e))
+            .map_err(|e| Error::MemoryAccess(guest_addr, e))?;
+        self.log_write(guest_addr, std::mem::size_of::<T>());
+        Ok(())
     }
 
     /// Returns a `VolatileSlice` of `len` bytes starting at `addr`. Returns an error if the slice
@@ -778,7 +915,8 @@ impl GuestMemory {
     /// # }
     /// ```
     pub fn get_slice_at_addr(&self, addr: GuestAddress, len: usize) -> Result<VolatileSlice> {
-        self.regions
+        let slice = self
+            .regions
             .iter()
             .find(|region| region.contains(addr))
             .ok_or(Error::InvalidGuestAddress(addr))
@@ -789,7 +927,9 @@ impl GuestMemory {
                     .mapping
                     .get_slice(addr.offset_from(region.start()) as usize, len)
                     .map_err(Error::VolatileMemoryAccess)
-            })
+            })?;
+        self.log_write(addr, len);
+        Ok(slice)
     }
     /// Convert a GuestAddress into a pointer in the address space of this
     /// process. This should only be necessary for giving addresses to the
@@ -1039,23 +1179,8 @@ impl GuestMemory {
             Box::new(r)
         };
 
-        if self.regions.len() != metadata.regions.len() {
-            bail!(
-                "snapshot expected {} memory regions but VM has {}",
-                metadata.regions.len(),
-                self.regions.len()
-            );
-        }
-        for (region, metadata) in self.regions.iter().zip(metadata.regions.iter()) {
-            let MemoryRegionSnapshotMetadata {
-                guest_base,
-                size,
-                data_ranges,
-            } = metadata;
-            if region.guest_base.0 != *guest_base || region.mapping.size() != *size {
-                bail!("snapshot memory regions don't match VM memory regions");
-            }
-
+        for (region, metadata) in self.snapshot_regions(&metadata)? {
+            let data_ranges = &metadata.data_ranges;
             let mut prev_end = 0;
             for range in data_ranges {
                 let hole_size = range
@@ -1096,6 +1221,65 @@ impl GuestMemory {
 
         Ok(())
     }
+
+    /// Pairs the regions of this `GuestMemory` with the ones in a snapshot, checking they match.
+    fn snapshot_regions<'a>(
+        &'a self,
+        metadata: &'a MemorySnapshotMetadata,
+    ) -> anyhow::Result<impl Iterator<Item = (&'a MemoryRegion, &'a MemoryRegionSnapshotMetadata)>>
+    {
+        if self.regions.len() != metadata.regions.len() {
+            bail!(
+                "snapshot expected {} memory regions but VM has {}",
+                metadata.regions.len(),
+                self.regions.len()
+            );
+        }
+        for (region, metadata) in self.regions.iter().zip(metadata.regions.iter()) {
+            if region.guest_base.0 != metadata.guest_base || region.mapping.size() != metadata.size
+            {
+                bail!("snapshot memory regions don't match VM memory regions");
+            }
+        }
+        Ok(self.regions.iter().zip(metadata.regions.iter()))
+    }
+}
+
+/// Memory usage of guest memory mapped from a snapshot shared with other VMs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedMemoryStats {
+    /// Bytes of guest memory mapped from the snapshot.
+    pub mapped_bytes: u64,
+    /// Bytes of the mapped memory that are resident and still shared with the snapshot file.
+    pub shared_bytes: u64,
+    /// Bytes of the mapped memory that the guest wrote since the restore, and so are private to
+    /// this VM.
+    pub private_bytes: u64,
+}
+
+/// Host NUMA placement of a range of guest memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NumaBindingStats {
+    /// Guest physical address of the start of the range.
+    pub guest_address: u64,
+    /// Size of the range in bytes.
+    pub size: u64,
+    /// Host memory policy of the range.
+    pub policy: NumaPolicy,
+    /// Host nodes the policy applies to.
+    pub nodes: Vec<u32>,
+    /// Bytes of the range resident on each host node.
+    pub resident_bytes: BTreeMap<u32, u64>,
+}
+
+/// Memory saved by merging identical guest pages with KSM.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KsmStats {
+    /// Bytes of guest memory merged with identical pages.
+    pub merged_bytes: u64,
+    /// Bytes saved by merging minus the memory used by KSM to track the pages, if reported by the
+    /// host kernel (6.4 and newer).
+    pub profit_bytes: Option<i64>,
 }
 
 #[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -1138,6 +1322,42 @@ mod tests {
         assert!(GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]).is_ok());
     }
 
+    #[test]
+    fn write_log() {
+        let page = pagesize() as u64;
+        let gm = GuestMemory::new(&[(GuestAddress(0), 8 * page), (GuestAddress(16 * page), page)])
+            .unwrap();
+        let mut log = [0u8; 8];
+
+        // Nothing is logged while logging is disabled.
+        gm.write_obj_at_addr(1u8, GuestAddress(0)).unwrap();
+        gm.set_write_logging(true);
+        gm.take_write_log(0, &mut log);
+        assert_eq!(log, [0; 8]);
+
+        gm.write_obj_at_addr(1u8, GuestAddress(page)).unwrap();
+        gm.write_at_addr(&[1, 2], GuestAddress(4 * page - 1))
+            .unwrap();
+        gm.get_slice_at_addr(GuestAddress(6 * page), 1).unwrap();
+        gm.write_obj_at_addr(1u8, GuestAddress(16 * page)).unwrap();
+        log[0] = 0x80;
+        gm.take_write_log(0, &mut log);
+        assert_eq!(log, [0b1101_1010, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Taking the log clears it.
+        let mut log = [0u8; 8];
+        gm.take_write_log(0, &mut log);
+        assert_eq!(log, [0; 8]);
+        gm.take_write_log(1, &mut log);
+        assert_eq!(log, [1, 0, 0, 0, 0, 0, 0, 0]);
+
+        gm.set_write_logging(false);
+        gm.write_obj_at_addr(1u8, GuestAddress(page)).unwrap();
+        let mut log = [0u8; 8];
+        gm.take_write_log(0, &mut log);
+        assert_eq!(log, [0; 8]);
+    }
+
     #[test]
     fn two_regions() {
         let start_addr1 = GuestAddress(0x0);
@@ -1426,11 +1646,86 @@ mod tests {
         data.seek(std::io::SeekFrom::Start(0)).unwrap();
         // SAFETY:
         // no vm is running
-        unsafe { gm2.restore(metadata_json, &mut data).unwrap() };
+        unsafe { gm2.restore(metadata_json.clone(), &mut data).unwrap() };
 
         assert_eq!(gm2.read_obj_from_addr::<u64>(hole_addr).unwrap(), 0);
         for &(addr, value) in writes {
             assert_eq!(gm2.read_obj_from_addr::<u64>(addr).unwrap(), value);
         }
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            let gm3 = GuestMemory::new(regions).unwrap();
+            gm3.write_obj_at_addr(8u64, hole_addr).unwrap();
+            // SAFETY:
+            // no vm is running
+            unsafe { gm3.restore_shared(metadata_json, &data).unwrap() };
+
+            assert_eq!(gm3.read_obj_from_addr::<u64>(hole_addr).unwrap(), 0);
+            for &(addr, value) in writes {
+                assert_eq!(gm3.read_obj_from_addr::<u64>(addr).unwrap(), value);
+            }
+            let stats = gm3.shared_memory_stats().unwrap();
+            assert_eq!(stats.mapped_bytes, 0x4000);
+            assert_eq!(stats.private_bytes, 0);
+
+            // Writes stay private to the VM.
+            gm3.write_obj_at_addr(5u64, writes[0].0).unwrap();
+            assert_eq!(gm3.shared_memory_stats().unwrap().private_bytes, 0x1000);
+            assert_eq!(gm3.read_obj_from_addr::<u64>(writes[0].0).unwrap(), 5);
+            // The first data range starts the file, at guest address 0xF000.
+            let mut value = [0u8; 8];
+            std::os::unix::fs::FileExt::read_exact_at(&data, &mut value, 0xFFF0 - 0xF000).unwrap();
+            assert_eq!(u64::from_ne_bytes(value), 1);
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn numa_binding() {
+        let gm = GuestMemory::new(&[(GuestAddress(0), 0x10000), (GuestAddress(0x20000), 0x10000)])
+            .unwrap();
+        gm.write_obj_at_addr(1u64, GuestAddress(0)).unwrap();
+        match gm.set_numa_policy(GuestAddress(0x8000), 0x28000, NumaPolicy::Bind, &[0]) {
+            // The host kernel was built without NUMA support.
+            Err(Error::MemoryAccess(_, MmapError::SystemCallFailed(e)))
+                if e.errno() == libc::ENOSYS =>
+            {
+                return
+            }
+            r => r.unwrap(),
+        }
+        gm.write_obj_at_addr(2u64, GuestAddress(0x20000)).unwrap();
+
+        let stats = gm.numa_binding_stats().unwrap();
+        let bound: Vec<_> = stats
+            .iter()
+            .filter(|s| s.policy == NumaPolicy::Bind)
+            .map(|s| (s.guest_address, s.size, s.nodes.clone()))
+            .collect();
+        assert_eq!(
+            bound,
+            vec![(0x8000, 0x8000, vec![0]), (0x20000, 0x10000, vec![0])]
+        );
+        let second = stats.iter().find(|s| s.guest_address == 0x20000).unwrap();
+        assert!(second.resident_bytes.get(&0).copied().unwrap_or(0) >= pagesize() as u64);
+
+        // The range must overlap guest memory.
+        assert!(gm
+            .set_numa_policy(GuestAddress(0x10000), 0x10000, NumaPolicy::Bind, &[0])
+            .is_err());
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn mergeable() {
+        let mut gm = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        assert!(!gm.mergeable());
+        gm.set_memory_policy(MemoryPolicy::MERGEABLE);
+        assert!(gm.mergeable());
+        // The host kernel may be built without KSM.
+        if let Ok(stats) = gm.ksm_stats() {
+            assert_eq!(stats.merged_bytes % pagesize() as u64, 0);
+        }
     }
 }
@@ -12,5 +12,6 @@ cfg_if::cfg_if! {
     }
 }
 
+pub(crate) use platform::create_hugetlb_shm;
 pub(crate) use platform::finalize_shm;
 pub use platform::MemoryPolicy;
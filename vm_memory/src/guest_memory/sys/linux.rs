@@ -2,20 +2,35 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+
+use anyhow::bail;
+use anyhow::Context;
 use base::linux::FileDataIterator;
 use base::linux::MemfdSeals;
 use base::linux::MemoryMappingUnix;
 use base::linux::SharedMemoryLinux;
+use base::pagesize;
+use base::warn;
 use base::MappedRegion;
+use base::NumaPolicy;
 use base::SharedMemory;
 use bitflags::bitflags;
+use snapshot::AnySnapshot;
 
+use crate::guest_memory::MemorySnapshotMetadata;
+use crate::BackingObject;
 use crate::Error;
 use crate::FileBackedMappingParameters;
 use crate::GuestAddress;
 use crate::GuestMemory;
+use crate::KsmStats;
 use crate::MemoryRegion;
+use crate::NumaBindingStats;
 use crate::Result;
+use crate::SharedMemoryStats;
 
 bitflags! {
     #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -24,6 +39,7 @@ bitflags! {
         const USE_HUGEPAGES = 1;
         const LOCK_GUEST_MEMORY = (1 << 1);
         const USE_DONTNEED_LOCKED = (1 << 2);
+        const MERGEABLE = (1 << 3);
     }
 }
 
@@ -40,6 +56,15 @@ pub(crate) fn finalize_shm(shm: &mut SharedMemory) -> Result<()> {
     shm.add_seals(seals).map_err(Error::MemoryAddSealsFailed)
 }
 
+/// Creates a sealed memfd of `size` bytes backed by huge pages of `page_size` bytes, or of the
+/// system's default huge page size if `page_size` is `None`.
+pub(crate) fn create_hugetlb_shm(size: u64, page_size: Option<u64>) -> Result<SharedMemory> {
+    let mut shm = SharedMemory::new_hugetlb(c"crosvm_guest_hugetlb", size, page_size)
+        .map_err(Error::MemoryCreationFailed)?;
+    finalize_shm(&mut shm)?;
+    Ok(shm)
+}
+
 impl GuestMemory {
     /// Madvise away the address range in the host that is associated with the given guest range.
     ///
@@ -48,7 +73,10 @@ impl GuestMemory {
         let (mapping, offset, _) = self.find_region(addr)?;
         mapping
             .remove_range(offset, count as usize)
-            .map_err(|e| Error::MemoryAccess(addr, e))
+            .map_err(|e| Error::MemoryAccess(addr, e))?;
+        // The range now reads as zeros.
+        self.log_write(addr, count as usize);
+        Ok(())
     }
 
     /// Madvise away the address range in the host that is associated with the given guest range.
@@ -60,7 +88,9 @@ impl GuestMemory {
         let (mapping, offset, _) = self.find_region(addr)?;
         mapping
             .dontneed_locked_range(offset, count as usize)
-            .map_err(|e| Error::MemoryAccess(addr, e))
+            .map_err(|e| Error::MemoryAccess(addr, e))?;
+        self.log_write(addr, count as usize);
+        Ok(())
     }
 
     /// Handles guest memory policy hints/advices.
@@ -96,7 +126,149 @@ impl GuestMemory {
             if mem_policy.contains(MemoryPolicy::USE_DONTNEED_LOCKED) {
                 self.use_dontneed_locked = true;
             }
+
+            if mem_policy.contains(MemoryPolicy::MERGEABLE) {
+                self.mergeable = true;
+
+                // The kernel only merges anonymous pages. Guest memory is a shared mapping of its
+                // memfd, so only the pages written to private mappings, such as the ones created
+                // by `restore_shared`, can be merged.
+                if let Err(err) = region.mapping.use_mergeable() {
+                    warn!("Failed to enable KSM for mapping {}", err);
+                }
+            }
+        }
+    }
+
+    /// Reports the guest memory merged by KSM.
+    pub fn ksm_stats(&self) -> anyhow::Result<KsmStats> {
+        let merging_pages = std::fs::read_to_string("/proc/self/ksm_merging_pages")
+            .context("failed to read ksm_merging_pages")?;
+        let merging_pages: u64 = merging_pages
+            .trim()
+            .parse()
+            .context("invalid ksm_merging_pages")?;
+        // Older kernels don't report the profit, or the whole file.
+        let profit_bytes = std::fs::read_to_string("/proc/self/ksm_stat")
+            .ok()
+            .and_then(|ksm_stat| {
+                ksm_stat.lines().find_map(|line| {
+                    line.strip_prefix("ksm_process_profit ")
+                        .and_then(|profit| profit.trim().parse().ok())
+                })
+            });
+        Ok(KsmStats {
+            merged_bytes: merging_pages * pagesize() as u64,
+            profit_bytes,
+        })
+    }
+
+    /// Sets the host NUMA memory policy of the guest memory in the range `addr..addr + count` and
+    /// migrates the pages already allocated in it. The range may span several regions and the
+    /// holes between them, but must overlap guest memory.
+    pub fn set_numa_policy(
+        &self,
+        addr: GuestAddress,
+        count: u64,
+        policy: NumaPolicy,
+        nodes: &[u32],
+    ) -> Result<()> {
+        let end = addr
+            .checked_add(count)
+            .ok_or(Error::InvalidGuestAddress(addr))?;
+        let mut bound = false;
+        for region in self.regions.iter() {
+            let start = addr.max(region.start());
+            let region_end = end.min(region.end());
+            if start >= region_end {
+                continue;
+            }
+            region
+                .mapping
+                .set_numa_policy(
+                    start.offset_from(region.start()) as usize,
+                    region_end.offset_from(start) as usize,
+                    policy,
+                    nodes,
+                )
+                .map_err(|e| Error::MemoryAccess(start, e))?;
+            bound = true;
+        }
+        if !bound {
+            return Err(Error::InvalidGuestAddress(addr));
+        }
+        Ok(())
+    }
+
+    /// Reports the host NUMA policy and the host nodes of the resident pages of each range of
+    /// guest memory with a distinct policy.
+    pub fn numa_binding_stats(&self) -> anyhow::Result<Vec<NumaBindingStats>> {
+        let numa_maps = File::open("/proc/self/numa_maps").context("failed to open numa_maps")?;
+        // Start address, policy, nodes and resident bytes per node of each host mapping.
+        let mut mappings = Vec::new();
+        for line in BufReader::new(numa_maps).lines() {
+            let line = line.context("failed to read numa_maps")?;
+            // "start policy[:nodes] [key=value]..."
+            let mut fields = line.split_whitespace();
+            let (Some(start), Some(policy)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let Ok(start) = u64::from_str_radix(start, 16) else {
+                continue;
+            };
+            let mut page_size = pagesize() as u64;
+            let mut pages = Vec::new();
+            for field in fields {
+                let Some((key, value)) = field.split_once('=') else {
+                    continue;
+                };
+                let Ok(value) = value.parse::<u64>() else {
+                    continue;
+                };
+                if key == "kernelpagesize_kB" {
+                    page_size = value * 1024;
+                } else if let Some(Ok(node)) = key.strip_prefix('N').map(str::parse::<u32>) {
+                    pages.push((node, value));
+                }
+            }
+            let resident_bytes = pages
+                .into_iter()
+                .map(|(node, count)| (node, count * page_size))
+                .collect();
+            mappings.push((start, policy.to_owned(), resident_bytes));
+        }
+        mappings.sort_by_key(|(start, _, _)| *start);
+
+        let mut stats = Vec::new();
+        for region in self.regions.iter() {
+            let host_start = region.mapping.as_ptr() as u64;
+            let host_end = host_start + region.mapping.size() as u64;
+            // A mapping extends to the start of the next one, and the first one in the region may
+            // start before it.
+            let first = mappings.partition_point(|(start, _, _)| *start <= host_start);
+            let Some(first) = first.checked_sub(1) else {
+                continue;
+            };
+            let region_mappings = mappings[first..]
+                .iter()
+                .take_while(|(start, _, _)| *start < host_end);
+            let mut region_mappings = region_mappings.peekable();
+            while let Some((start, policy, resident_bytes)) = region_mappings.next() {
+                let start = (*start).max(host_start);
+                let end = region_mappings
+                    .peek()
+                    .map_or(host_end, |(next, _, _)| *next);
+                let (policy, nodes) = parse_numa_policy(policy)?;
+                stats.push(NumaBindingStats {
+                    guest_address: region.start().offset() + (start - host_start),
+                    size: end - start,
+                    policy,
+                    nodes,
+                    resident_bytes: resident_bytes.clone(),
+                });
+            }
         }
+        Ok(stats)
     }
 
     pub fn use_dontfork(&self) -> anyhow::Result<()> {
@@ -105,6 +277,158 @@ impl GuestMemory {
         }
         Ok(())
     }
+
+    /// Restores the guest memory by mapping `file`, an uncompressed memory snapshot written by
+    /// `GuestMemory::snapshot`, instead of copying it.
+    ///
+    /// The mappings are private, so pages the guest never writes stay shared in the page cache
+    /// with every VM restored this way from the same file. Only this process sees the restored
+    /// memory, so no other process may access guest memory, and `file` must not be modified while
+    /// it is mapped.
+    ///
+    /// # Safety
+    /// Must have exclusive access to the guest memory for the duration of the
+    /// call (e.g. all vCPUs and devices must be stopped).
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn restore_shared(&self, metadata: AnySnapshot, file: &File) -> anyhow::Result<()> {
+        let metadata: MemorySnapshotMetadata = AnySnapshot::from_any(metadata)?;
+        if metadata.compressed {
+            bail!("compressed memory snapshots cannot be mapped");
+        }
+        let page_size = pagesize();
+        let mut file_offset = 0;
+        for (region, metadata) in self.snapshot_regions(&metadata)? {
+            if !matches!(region.shared_obj, BackingObject::Shm(_)) {
+                bail!("file-backed memory regions cannot be mapped from a snapshot");
+            }
+            let mut prev_end = 0;
+            for range in &metadata.data_ranges {
+                let hole_size = range
+                    .start
+                    .checked_sub(prev_end)
+                    .context("invalid data range")?;
+                if hole_size > 0 {
+                    region.zero_range(prev_end, hole_size)?;
+                }
+                let size = range
+                    .end
+                    .checked_sub(range.start)
+                    .context("invalid data range")?;
+                if range.start % page_size != 0 || size % page_size != 0 {
+                    bail!("memory snapshot data range {:?} is not page aligned", range);
+                }
+                region
+                    .mapping
+                    .remap_private(range.start, size, file, file_offset)
+                    .context("failed to map memory snapshot")?;
+                file_offset += size as u64;
+                prev_end = range.end;
+            }
+            let hole_size = region
+                .mapping
+                .size()
+                .checked_sub(prev_end)
+                .context("invalid data range")?;
+            if hole_size > 0 {
+                region.zero_range(prev_end, hole_size)?;
+            }
+            // The private mappings replaced the ones KSM was enabled on.
+            if self.mergeable {
+                region
+                    .mapping
+                    .use_mergeable()
+                    .context("failed to enable KSM for memory snapshot")?;
+            }
+        }
+
+        let file_size = file.metadata()?.len();
+        if file_size != file_offset {
+            bail!(
+                "memory snapshot has {} bytes, expected {}",
+                file_size,
+                file_offset
+            );
+        }
+        Ok(())
+    }
+
+    /// Reports the memory usage of guest memory mapped by `restore_shared`.
+    pub fn shared_memory_stats(&self) -> anyhow::Result<SharedMemoryStats> {
+        let ranges: Vec<_> = self
+            .regions
+            .iter()
+            .map(|region| {
+                let start = region.mapping.as_ptr() as u64;
+                start..start + region.mapping.size() as u64
+            })
+            .collect();
+        let smaps = File::open("/proc/self/smaps").context("failed to open smaps")?;
+        let mut stats = SharedMemoryStats::default();
+        // Whether the fields being parsed belong to a private file mapping of guest memory.
+        let mut in_mapping = false;
+        for line in BufReader::new(smaps).lines() {
+            let line = line.context("failed to read smaps")?;
+            let mut fields = line.split_whitespace();
+            let (Some(first), Some(second)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            if let Some((start, end)) = first.split_once('-') {
+                // Mapping header: "start-end perms offset dev inode [path]".
+                let (Ok(start), Ok(end)) =
+                    (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16))
+                else {
+                    continue;
+                };
+                let inode = fields.nth(2).unwrap_or("0");
+                // Adjacent mappings of the file may be merged across regions.
+                let overlap: u64 = ranges
+                    .iter()
+                    .map(|range| end.min(range.end).saturating_sub(start.max(range.start)))
+                    .sum();
+                in_mapping = second.ends_with('p') && inode != "0" && overlap > 0;
+                if in_mapping {
+                    stats.mapped_bytes += overlap;
+                }
+            } else if in_mapping {
+                let bytes = second.parse::<u64>().unwrap_or(0) * 1024;
+                match first {
+                    "Rss:" => stats.shared_bytes += bytes,
+                    "Anonymous:" => stats.private_bytes += bytes,
+                    _ => {}
+                }
+            }
+        }
+        // Anonymous pages are counted in both.
+        stats.shared_bytes = stats.shared_bytes.saturating_sub(stats.private_bytes);
+        Ok(stats)
+    }
+}
+
+/// Parses a memory policy of `/proc/<pid>/numa_maps`, such as "bind:0-1,3", into its mode and
+/// nodes.
+fn parse_numa_policy(text: &str) -> anyhow::Result<(NumaPolicy, Vec<u32>)> {
+    let (mode, node_list) = text.split_once(':').unwrap_or((text, ""));
+    // Mode flags such as "=static" are not reported.
+    let mode = mode.split('=').next().unwrap_or(mode);
+    let policy = match mode {
+        "default" => NumaPolicy::Default,
+        "bind" => NumaPolicy::Bind,
+        "interleave" => NumaPolicy::Interleave,
+        "prefer" | "preferred" => NumaPolicy::Preferred,
+        _ => bail!("unsupported memory policy {text:?}"),
+    };
+    let mut nodes = Vec::new();
+    for range in node_list.split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: u32 = first
+            .parse()
+            .with_context(|| format!("invalid node list in memory policy {text:?}"))?;
+        let last: u32 = last
+            .parse()
+            .with_context(|| format!("invalid node list in memory policy {text:?}"))?;
+        nodes.extend(first..=last);
+    }
+    Ok((policy, nodes))
 }
 
 impl FileBackedMappingParameters {
@@ -7,6 +7,7 @@ use base::SharedMemory;
 use base::VolatileMemory;
 use bitflags::bitflags;
 
+use crate::Error;
 use crate::FileBackedMappingParameters;
 use crate::GuestMemory;
 use crate::MemoryRegion;
@@ -25,6 +26,10 @@ pub(crate) fn finalize_shm(_shm: &mut SharedMemory) -> Result<()> {
     Ok(())
 }
 
+pub(crate) fn create_hugetlb_shm(_size: u64, _page_size: Option<u64>) -> Result<SharedMemory> {
+    Err(Error::HugetlbUnsupported)
+}
+
 impl GuestMemory {
     /// Handles guest memory policy hints/advices.
     pub fn set_memory_policy(&self, _mem_policy: MemoryPolicy) {
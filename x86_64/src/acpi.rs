@@ -2,9 +2,9 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use std::arch::x86_64::CpuidResult;
 use std::arch::x86_64::__cpuid;
 use std::arch::x86_64::__cpuid_count;
+use std::arch::x86_64::CpuidResult;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
@@ -170,11 +170,13 @@ const XSDT_REVISION: u8 = 1;
 const CPUID_LEAF0_EBX_CPUID_SHIFT: u32 = 24; // Offset of initial apic id.
 
 // MCFG
-const MCFG_LEN: u32 = 60;
+const MCFG_LEN: u32 = 44;
 const MCFG_REVISION: u8 = 1;
-const MCFG_FIELD_BASE_ADDRESS: usize = 44;
-const MCFG_FIELD_START_BUS_NUMBER: usize = 54;
-const MCFG_FIELD_END_BUS_NUMBER: usize = 55;
+const MCFG_ENTRY_LEN: u32 = 16;
+const MCFG_ENTRY_FIELD_BASE_ADDRESS: usize = 0;
+const MCFG_ENTRY_FIELD_SEGMENT: usize = 8;
+const MCFG_ENTRY_FIELD_START_BUS_NUMBER: usize = 10;
+const MCFG_ENTRY_FIELD_END_BUS_NUMBER: usize = 11;
 
 const SSDT_REVISION: u8 = 2;
 pub fn create_customize_ssdt(
@@ -225,6 +227,25 @@ fn create_dsdt_table(amls: &[u8]) -> SDT {
     dsdt
 }
 
+fn create_mcfg_table(pcie_cfg_mmio: &[(u64, u16, u8)]) -> SDT {
+    let mut mcfg = SDT::new(
+        *b"MCFG",
+        MCFG_LEN + MCFG_ENTRY_LEN * pcie_cfg_mmio.len() as u32,
+        MCFG_REVISION,
+        *b"CROSVM",
+        *b"CROSVMDT",
+        OEM_REVISION,
+    );
+    for (i, (base_address, segment, max_bus)) in pcie_cfg_mmio.iter().enumerate() {
+        let entry = (MCFG_LEN + MCFG_ENTRY_LEN * i as u32) as usize;
+        mcfg.write(entry + MCFG_ENTRY_FIELD_BASE_ADDRESS, *base_address);
+        mcfg.write(entry + MCFG_ENTRY_FIELD_SEGMENT, *segment);
+        mcfg.write(entry + MCFG_ENTRY_FIELD_START_BUS_NUMBER, 0_u8);
+        mcfg.write(entry + MCFG_ENTRY_FIELD_END_BUS_NUMBER, *max_bus);
+    }
+    mcfg
+}
+
 fn create_facp_table(sci_irq: u16, force_s2idle: bool) -> SDT {
     let mut facp = SDT::new(
         *b"FACP",
@@ -416,6 +437,23 @@ fn write_facp_overrides(
     facp.write(FADT_FIELD_RESET_VALUE, reset_value);
 }
 
+/// Returns the address of the FACS, which immediately follows the RSDP.
+fn facs_address() -> Option<GuestAddress> {
+    next_offset(
+        GuestAddress(super::ACPI_HI_RSDP_WINDOW_BASE),
+        RSDP::len() as u64,
+    )
+}
+
+/// Reads the firmware waking vector the OSPM stored in the FACS before entering S3.
+///
+/// Returns `None` if the FACS can't be read or the guest didn't set a waking vector.
+pub fn read_waking_vector(guest_mem: &GuestMemory) -> Option<u32> {
+    let facs: FACS = guest_mem.read_obj_from_addr(facs_address()?).ok()?;
+    let waking = facs.waking;
+    (waking != 0).then_some(waking)
+}
+
 fn next_offset(offset: GuestAddress, len: u64) -> Option<GuestAddress> {
     // Enforce 64-byte allocation alignment.
     match len % 64 {
@@ -523,7 +561,8 @@ fn sync_acpi_id_from_cpuid(
 /// # Arguments
 ///
 /// * `guest_mem` - The guest memory where the tables will be stored.
-/// * `num_cpus` - Used to construct the MADT.
+/// * `num_cpus` - Used to construct the MADT. vCPUs beyond the 255th are described with Local
+///   x2APIC structures.
 /// * `sci_irq` - Used to fill the FACP SCI_INTERRUPT field, which is going to be used by the ACPI
 ///   drivers to register sci handler.
 /// * `acpi_dev_resource` - resouces needed by the ACPI devices for creating tables.
@@ -532,11 +571,11 @@ fn sync_acpi_id_from_cpuid(
 /// * `apic_ids` - The apic id for vCPU will be sent to KVM by KVM_CREATE_VCPU ioctl.
 /// * `pci_rqs` - PCI device to IRQ number assignments as returned by `arch::generate_pci_root()`
 ///   (device address, IRQ number, and PCI interrupt pin assignment).
-/// * `pcie_cfg_mmio` - Base address for the pcie enhanced configuration access mechanism
-/// * `max_bus` - Max bus number in MCFG table
+/// * `pcie_cfg_mmio` - Base address for the pcie enhanced configuration access mechanism, PCI
+///   segment and max bus number of each PCI segment, in MCFG table
 pub fn create_acpi_tables(
     guest_mem: &GuestMemory,
-    num_cpus: u8,
+    num_cpus: usize,
     sci_irq: u32,
     reset_port: u32,
     reset_value: u8,
@@ -544,13 +583,12 @@ pub fn create_acpi_tables(
     host_cpus: Option<VcpuAffinity>,
     apic_ids: &mut Vec<usize>,
     pci_irqs: &[(PciAddress, u32, PciInterruptPin)],
-    pcie_cfg_mmio: u64,
-    max_bus: u8,
+    pcie_cfg_mmio: &[(u64, u16, u8)],
     force_s2idle: bool,
 ) -> Option<GuestAddress> {
     // RSDP is at the HI RSDP WINDOW
     let rsdp_offset = GuestAddress(super::ACPI_HI_RSDP_WINDOW_BASE);
-    let facs_offset = next_offset(rsdp_offset, RSDP::len() as u64)?;
+    let facs_offset = facs_address()?;
     let mut offset = next_offset(facs_offset, FACS::len() as u64)?;
     let mut dsdt_offset: Option<GuestAddress> = None;
     let mut tables: Vec<u64> = Vec::new();
@@ -643,15 +681,27 @@ pub fn create_acpi_tables(
         }
         _ => {
             for cpu in 0..num_cpus {
-                let apic = LocalApic {
-                    _type: MADT_TYPE_LOCAL_APIC,
-                    _length: std::mem::size_of::<LocalApic>() as u8,
-                    _processor_id: cpu,
-                    _apic_id: cpu,
-                    _flags: MADT_ENABLED,
-                };
-                madt.append(apic);
-                apic_ids.push(cpu as usize);
+                // (ACPI Spec v6.4) Logical processors with APIC ID values less than 255 must use
+                // the Processor Local APIC structure.
+                if cpu < MADT_MIN_LOCAL_APIC_ID as usize {
+                    madt.append(LocalApic {
+                        _type: MADT_TYPE_LOCAL_APIC,
+                        _length: std::mem::size_of::<LocalApic>() as u8,
+                        _processor_id: cpu as u8,
+                        _apic_id: cpu as u8,
+                        _flags: MADT_ENABLED,
+                    });
+                } else {
+                    madt.append(Localx2Apic {
+                        _type: MADT_TYPE_LOCAL_X2APIC,
+                        _length: std::mem::size_of::<Localx2Apic>() as u8,
+                        _x2apic_id: cpu as u32,
+                        _flags: MADT_ENABLED,
+                        _processor_id: cpu as u32,
+                        ..Default::default()
+                    });
+                }
+                apic_ids.push(cpu);
             }
         }
     }
@@ -701,21 +751,10 @@ pub fn create_acpi_tables(
     offset = next_offset(offset, madt.len() as u64)?;
 
     // MCFG
-    let mut mcfg = SDT::new(
-        *b"MCFG",
-        MCFG_LEN,
-        MCFG_REVISION,
-        *b"CROSVM",
-        *b"CROSVMDT",
-        OEM_REVISION,
-    );
-    mcfg.write(MCFG_FIELD_BASE_ADDRESS, pcie_cfg_mmio);
-    mcfg.write(MCFG_FIELD_START_BUS_NUMBER, 0_u8);
-    mcfg.write(MCFG_FIELD_END_BUS_NUMBER, max_bus);
-
+    let mcfg = create_mcfg_table(pcie_cfg_mmio);
     guest_mem.write_at_addr(mcfg.as_slice(), offset).ok()?;
     tables.push(offset.0);
-    offset = next_offset(offset, madt.len() as u64)?;
+    offset = next_offset(offset, mcfg.len() as u64)?;
 
     // XSDT
     let mut xsdt = SDT::new(
@@ -743,6 +782,20 @@ pub fn create_acpi_tables(
 mod tests {
     use crate::acpi::*;
 
+    #[test]
+    fn mcfg_table_creation() {
+        let mcfg = create_mcfg_table(&[(0xe000_0000, 0, 0x3f), (0x10_0000_0000, 1, 0xff)]);
+
+        assert_eq!(mcfg.len(), 76);
+        assert_eq!(mcfg.read::<u64>(44), 0xe000_0000);
+        assert_eq!(mcfg.read::<u16>(52), 0);
+        assert_eq!(mcfg.read::<u8>(55), 0x3f);
+        assert_eq!(mcfg.read::<u64>(60), 0x10_0000_0000);
+        assert_eq!(mcfg.read::<u16>(68), 1);
+        assert_eq!(mcfg.read::<u8>(70), 0);
+        assert_eq!(mcfg.read::<u8>(71), 0xff);
+    }
+
     #[test]
     fn facp_table_creation() {
         let sci_irq: u16 = 5;
@@ -48,7 +48,14 @@ pub const ECX_TOPO_SMT_TYPE: u32 = 1; // SMT type.
 pub const ECX_TOPO_CORE_TYPE: u32 = 2; // CORE type.
 pub const ECX_HCFC_PERF_SHIFT: u32 = 0; // Presence of IA32_MPERF and IA32_APERF.
 pub const EAX_CPU_CORES_SHIFT: u32 = 26; // Index of cpu cores in the same physical package.
+pub const EAX_CACHE_SHARING_SHIFT: u32 = 14; // Logical processor IDs sharing the cache.
+pub const EAX_CACHE_SHARING_MASK: u32 = 0xfff;
 pub const EDX_HYBRID_CPU_SHIFT: u32 = 15; // Hybrid. The processor is identified as a hybrid part.
+pub const EDX_AMX_BF16_SHIFT: u32 = 22; // AMX tile computations on bfloat16 numbers.
+pub const EDX_AMX_TILE_SHIFT: u32 = 24; // AMX tile architecture.
+pub const EDX_AMX_INT8_SHIFT: u32 = 25; // AMX tile computations on 8-bit integers.
+pub const EAX_AMX_FP16_SHIFT: u32 = 21; // AMX tile computations on FP16 numbers.
+pub const EAX_XTILEDATA_SHIFT: u32 = 18; // XSAVE state component of the AMX tiles.
 pub const EAX_HWP_SHIFT: u32 = 7; // Intel Hardware P-states.
 pub const EAX_HWP_NOTIFICATION_SHIFT: u32 = 8; // IA32_HWP_INTERRUPT MSR is supported
 pub const EAX_HWP_EPP_SHIFT: u32 = 10; // HWP Energy Perf. Preference.
@@ -56,6 +63,16 @@ pub const EAX_ITMT_SHIFT: u32 = 14; // Intel Turbo Boost Max Technology 3.0 avai
 pub const EAX_CORE_TEMP: u32 = 0; // Core Temperature
 pub const EAX_PKG_TEMP: u32 = 6; // Package Temperature
 pub const EAX_CORE_TYPE_SHIFT: u32 = 24; // Hybrid information. Hybrid core type.
+pub const EAX_KVM_ASYNC_PF_SHIFT: u32 = 4; // KVM asynchronous page faults.
+pub const EAX_KVM_ASYNC_PF_VMEXIT_SHIFT: u32 = 10; // KVM async PF delivered as a nested VM exit.
+pub const EAX_KVM_ASYNC_PF_INT_SHIFT: u32 = 14; // KVM async PF completion delivered as interrupt.
+pub const EAX_KVM_MSI_EXT_DEST_ID_SHIFT: u32 = 15; // MSIs carry 15-bit APIC IDs.
+
+const KVM_CPUID_FEATURES: u32 = 0x40000001; // KVM paravirtual features.
+
+const XSAVE_FUNCTION: u32 = 0xd; // Processor extended state enumeration.
+const TILE_INFO_FUNCTION: u32 = 0x1d; // AMX tile information.
+const TMUL_INFO_FUNCTION: u32 = 0x1e; // AMX tile multiply information.
 
 const EAX_CORE_TYPE_ATOM: u32 = 0x20; // Hybrid Atom CPU.
 const EAX_CORE_TYPE_CORE: u32 = 0x40; // Hybrid Core CPU.
@@ -75,6 +92,10 @@ pub struct CpuIdContext {
     apic_frequency: u32,
     /// The TSC frequency in Hz, if it could be determined.
     tsc_frequency: Option<u64>,
+    /// Whether or not the IrqChip can deliver KVM asynchronous page faults.
+    async_pf: bool,
+    /// Whether or not the IrqChip's MSIs can address APIC IDs above 255.
+    msi_ext_dest_id: bool,
     /// CPU feature configurations.
     cpu_config: CpuConfigX86_64,
     /// __cpuid_count or a fake function for test.
@@ -100,6 +121,9 @@ impl CpuIdContext {
             tsc_deadline_timer: irq_chip
                 .is_some_and(|chip| chip.check_capability(IrqChipCap::TscDeadlineTimer)),
             apic_frequency: irq_chip.map_or(Apic::frequency(), |chip| chip.lapic_frequency()),
+            async_pf: irq_chip.is_some_and(|chip| chip.check_capability(IrqChipCap::AsyncPf)),
+            msi_ext_dest_id: irq_chip
+                .is_some_and(|chip| chip.check_capability(IrqChipCap::MsiExtDestId)),
             tsc_frequency: if calibrated_tsc_leaf_required || cpu_config.force_calibrated_tsc_leaf {
                 devices::tsc::tsc_frequency().ok()
             } else {
@@ -110,6 +134,23 @@ impl CpuIdContext {
             cpuid,
         }
     }
+
+    // Number of vCPUs sharing the host cache described by subleaf `index` of the cache leaves, if
+    // the host caches are mirrored.
+    fn cache_sharing(&self, index: u32) -> Option<u32> {
+        self.cpu_config
+            .host_cache_sharing
+            .get(index as usize)
+            .copied()
+    }
+}
+
+// Makes the cache of `entry` shared by the vCPUs whose APIC IDs only differ in their low bits,
+// the guest grouping vCPUs by the power of two above `sharing`.
+fn set_cache_sharing(entry: &mut CpuIdEntry, sharing: u32) {
+    entry.cpuid.eax &= !(EAX_CACHE_SHARING_MASK << EAX_CACHE_SHARING_SHIFT);
+    entry.cpuid.eax |= ((sharing.max(1).next_power_of_two() - 1) & EAX_CACHE_SHARING_MASK)
+        << EAX_CACHE_SHARING_SHIFT;
 }
 
 /// Adjust a CPUID instruction result to return values that work with crosvm.
@@ -153,8 +194,9 @@ pub fn adjust_cpuid(entry: &mut CpuIdEntry, ctx: &CpuIdContext) {
             entry.cpuid.ebx = (ctx.vcpu_id << EBX_CPUID_SHIFT) as u32
                 | (EBX_CLFLUSH_CACHELINE << EBX_CLFLUSH_SIZE_SHIFT);
             if ctx.cpu_count > 1 {
-                // This field is only valid if CPUID.1.EDX.HTT[bit 28]= 1.
-                entry.cpuid.ebx |= (ctx.cpu_count as u32) << EBX_CPU_COUNT_SHIFT;
+                // This field is only valid if CPUID.1.EDX.HTT[bit 28]= 1. It is 8 bits wide, leaf
+                // 0xB gives the actual count of larger VMs.
+                entry.cpuid.ebx |= (ctx.cpu_count.min(0xff) as u32) << EBX_CPU_COUNT_SHIFT;
                 // A value of 0 for HTT indicates there is only a single logical
                 // processor in the package and software should assume only a
                 // single APIC ID is reserved.
@@ -172,6 +214,9 @@ pub fn adjust_cpuid(entry: &mut CpuIdEntry, ctx: &CpuIdContext) {
                 // SAFETY: trivially safe
                 unsafe { (ctx.cpuid_count)(entry.function, entry.index) }};
 
+            if let Some(sharing) = ctx.cache_sharing(entry.index) {
+                set_cache_sharing(entry, sharing);
+            }
             if ctx.cpu_config.host_cpu_topology {
                 return;
             }
@@ -185,7 +230,8 @@ pub fn adjust_cpuid(entry: &mut CpuIdEntry, ctx: &CpuIdContext) {
                 } else {
                     1
                 };
-                entry.cpuid.eax |= (cpu_cores - 1) << EAX_CPU_CORES_SHIFT;
+                // The field is 6 bits wide.
+                entry.cpuid.eax |= (cpu_cores - 1).min(0x3f) << EAX_CPU_CORES_SHIFT;
             }
         }
         6 => {
@@ -218,6 +264,21 @@ pub fn adjust_cpuid(entry: &mut CpuIdEntry, ctx: &CpuIdContext) {
                 entry.cpuid.edx |= 1 << EDX_HYBRID_CPU_SHIFT;
             }
         }
+        KVM_CPUID_FEATURES => {
+            // The guest can't enable asynchronous page faults without an APIC emulated by KVM.
+            if !ctx.cpu_config.async_pf || !ctx.async_pf {
+                entry.cpuid.eax &= !((1 << EAX_KVM_ASYNC_PF_SHIFT)
+                    | (1 << EAX_KVM_ASYNC_PF_VMEXIT_SHIFT)
+                    | (1 << EAX_KVM_ASYNC_PF_INT_SHIFT));
+            }
+            // The guest can only address vCPUs beyond APIC ID 255 without interrupt remapping if
+            // the extended destination ID of its MSIs is understood.
+            if ctx.msi_ext_dest_id {
+                entry.cpuid.eax |= 1 << EAX_KVM_MSI_EXT_DEST_ID_SHIFT;
+            } else {
+                entry.cpuid.eax &= !(1 << EAX_KVM_MSI_EXT_DEST_ID_SHIFT);
+            }
+        }
         0x15 => {
             if let Some(tsc_freq) = ctx.tsc_frequency {
                 // A calibrated TSC is required by the hypervisor or was forced by the user.
@@ -281,12 +342,23 @@ pub fn adjust_cpuid(entry: &mut CpuIdEntry, ctx: &CpuIdContext) {
                 entry.cpuid.ecx = 0;
             }
         }
+        0x8000001D => {
+            // AMD cache topology.
+            if let Some(sharing) = ctx.cache_sharing(entry.index) {
+                // SAFETY: trivially safe
+                entry.cpuid = unsafe { (ctx.cpuid_count)(entry.function, entry.index) };
+                set_cache_sharing(entry, sharing);
+            }
+        }
         _ => (),
     }
 }
 
 /// Adjust all the entries in `cpuid` based on crosvm's cpuid logic and `ctx`. Calls `adjust_cpuid`
 /// on each entry in `cpuid`, and adds any entries that should exist and are missing from `cpuid`.
+/// AMX is hidden unless the XSAVE area holds its tiles. The CPUID overrides of the CPU
+/// configuration are applied last, adding the leaves they target if the hypervisor doesn't report
+/// them.
 pub fn filter_cpuid(cpuid: &mut hypervisor::CpuId, ctx: &CpuIdContext) {
     // Add an empty leaf 0x15 if we have a tsc_frequency and it's not in the current set of leaves.
     // It will be filled with the appropriate frequency information by `adjust_cpuid`.
@@ -313,6 +385,61 @@ pub fn filter_cpuid(cpuid: &mut hypervisor::CpuId, ctx: &CpuIdContext) {
     for entry in entries.iter_mut() {
         adjust_cpuid(entry, ctx);
     }
+
+    // The tiles are a dynamically enabled XSAVE feature, which is only part of the XSAVE area if
+    // the host let crosvm expose it.
+    let xtiledata = entries.iter().any(|entry| {
+        entry.function == XSAVE_FUNCTION
+            && entry.index == 0
+            && entry.cpuid.eax & (1 << EAX_XTILEDATA_SHIFT) != 0
+    });
+    if !xtiledata {
+        entries.retain(|entry| {
+            entry.function != TILE_INFO_FUNCTION && entry.function != TMUL_INFO_FUNCTION
+        });
+        for entry in entries.iter_mut().filter(|entry| entry.function == 7) {
+            match entry.index {
+                0 => {
+                    entry.cpuid.edx &= !((1 << EDX_AMX_BF16_SHIFT)
+                        | (1 << EDX_AMX_TILE_SHIFT)
+                        | (1 << EDX_AMX_INT8_SHIFT))
+                }
+                1 => entry.cpuid.eax &= !(1 << EAX_AMX_FP16_SHIFT),
+                _ => (),
+            }
+        }
+    }
+
+    for cpuid_override in &ctx.cpu_config.cpuid_overrides {
+        let mut matched = false;
+        for entry in entries
+            .iter_mut()
+            .filter(|entry| cpuid_override.matches(entry.function, entry.index))
+        {
+            cpuid_override.apply(&mut entry.cpuid);
+            matched = true;
+        }
+        if !matched {
+            // Reuse the flags of the other subleaves, which tell KVM whether the index matters.
+            let flags = entries
+                .iter()
+                .find(|entry| entry.function == cpuid_override.function)
+                .map_or(0, |entry| entry.flags);
+            let mut entry = CpuIdEntry {
+                function: cpuid_override.function,
+                index: cpuid_override.index.unwrap_or(0),
+                flags,
+                cpuid: CpuidResult {
+                    eax: 0,
+                    ebx: 0,
+                    ecx: 0,
+                    edx: 0,
+                },
+            };
+            cpuid_override.apply(&mut entry.cpuid);
+            entries.push(entry);
+        }
+    }
 }
 
 /// Sets up the cpuid entries for the given vcpu.  Can fail if there are too many CPUs specified or
@@ -377,6 +504,9 @@ pub fn cpu_manufacturer() -> CpuManufacturer {
 
 #[cfg(test)]
 mod tests {
+    use hypervisor::CpuIdOverride;
+    use hypervisor::CpuIdRegister;
+
     use super::*;
 
     #[test]
@@ -407,6 +537,9 @@ mod tests {
             no_smt: false,
             itmt: false,
             hybrid_type: None,
+            async_pf: false,
+            cpuid_overrides: Vec::new(),
+            host_cache_sharing: Vec::new(),
         };
         let ctx = CpuIdContext {
             vcpu_id: 0,
@@ -415,6 +548,8 @@ mod tests {
             tsc_deadline_timer: false,
             apic_frequency: 0,
             tsc_frequency: None,
+            async_pf: false,
+            msi_ext_dest_id: false,
             cpu_config,
             cpuid_count: fake_cpuid_count,
             cpuid: fake_cpuid,
@@ -433,4 +568,297 @@ mod tests {
         adjust_cpuid(&mut cpu_id_entry, &ctx);
         assert_eq!(cpu_id_entry.cpuid.eax, 27)
     }
+
+    #[test]
+    fn cpuid_async_pf() {
+        let fake_cpuid = |_function: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        let fake_cpuid_count = |_function: u32, _index: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        let mut ctx = CpuIdContext {
+            vcpu_id: 0,
+            cpu_count: 1,
+            x2apic: true,
+            tsc_deadline_timer: false,
+            apic_frequency: 0,
+            tsc_frequency: None,
+            async_pf: true,
+            msi_ext_dest_id: false,
+            cpu_config: CpuConfigX86_64::new(
+                false,
+                false,
+                false,
+                false,
+                false,
+                None,
+                true,
+                Vec::new(),
+                Vec::new(),
+            ),
+            cpuid_count: fake_cpuid_count,
+            cpuid: fake_cpuid,
+        };
+        let kvm_features = (1 << EAX_KVM_ASYNC_PF_SHIFT)
+            | (1 << EAX_KVM_ASYNC_PF_VMEXIT_SHIFT)
+            | (1 << EAX_KVM_ASYNC_PF_INT_SHIFT)
+            | 1;
+        let mut entry = CpuIdEntry {
+            function: KVM_CPUID_FEATURES,
+            index: 0,
+            flags: 0,
+            cpuid: CpuidResult {
+                eax: kvm_features,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            },
+        };
+
+        let mut adjusted = entry;
+        adjust_cpuid(&mut adjusted, &ctx);
+        assert_eq!(adjusted.cpuid.eax, kvm_features);
+
+        // Without an APIC emulated by KVM only the other features are left.
+        ctx.async_pf = false;
+        adjust_cpuid(&mut entry, &ctx);
+        assert_eq!(entry.cpuid.eax, 1);
+
+        ctx.msi_ext_dest_id = true;
+        adjust_cpuid(&mut entry, &ctx);
+        assert_eq!(entry.cpuid.eax, 1 | (1 << EAX_KVM_MSI_EXT_DEST_ID_SHIFT));
+    }
+
+    #[test]
+    fn cpuid_overrides() {
+        let fake_cpuid = |_function: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        let fake_cpuid_count = |_function: u32, _index: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        // Hide AVX-512F from every subleaf of leaf 7, and add a hypervisor leaf.
+        let cpuid_overrides = vec![
+            CpuIdOverride {
+                function: 0x7,
+                index: None,
+                register: CpuIdRegister::Ebx,
+                mask: 1 << 16,
+                value: 0,
+            },
+            CpuIdOverride {
+                function: 0x40000010,
+                index: Some(0),
+                register: CpuIdRegister::Eax,
+                mask: 0xffff,
+                value: 0x1234,
+            },
+        ];
+        let cpu_config = CpuConfigX86_64::new(
+            false,
+            false,
+            false,
+            false,
+            false,
+            None,
+            false,
+            cpuid_overrides,
+            Vec::new(),
+        );
+        let ctx = CpuIdContext {
+            vcpu_id: 0,
+            cpu_count: 1,
+            x2apic: false,
+            tsc_deadline_timer: false,
+            apic_frequency: 0,
+            tsc_frequency: None,
+            async_pf: false,
+            msi_ext_dest_id: false,
+            cpu_config,
+            cpuid_count: fake_cpuid_count,
+            cpuid: fake_cpuid,
+        };
+        let leaf_7 = |index| CpuIdEntry {
+            function: 0x7,
+            index,
+            flags: 1,
+            cpuid: CpuidResult {
+                eax: 0,
+                ebx: 0x1_0001,
+                ecx: 0,
+                edx: 0,
+            },
+        };
+        let mut cpuid = hypervisor::CpuId {
+            cpu_id_entries: vec![leaf_7(0), leaf_7(1)],
+        };
+
+        filter_cpuid(&mut cpuid, &ctx);
+        assert_eq!(cpuid.cpu_id_entries.len(), 3);
+        assert_eq!(cpuid.cpu_id_entries[0].cpuid.ebx, 1);
+        assert_eq!(cpuid.cpu_id_entries[1].cpuid.ebx, 1);
+        assert_eq!(cpuid.cpu_id_entries[2].function, 0x40000010);
+        assert_eq!(cpuid.cpu_id_entries[2].flags, 0);
+        assert_eq!(cpuid.cpu_id_entries[2].cpuid.eax, 0x1234);
+    }
+
+    #[test]
+    fn cpuid_amx() {
+        let fake_cpuid = |_function: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        let fake_cpuid_count = |_function: u32, _index: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        let ctx = CpuIdContext {
+            vcpu_id: 0,
+            cpu_count: 1,
+            x2apic: false,
+            tsc_deadline_timer: false,
+            apic_frequency: 0,
+            tsc_frequency: None,
+            async_pf: false,
+            msi_ext_dest_id: false,
+            cpu_config: CpuConfigX86_64::new(
+                false,
+                false,
+                false,
+                false,
+                false,
+                None,
+                false,
+                Vec::new(),
+                Vec::new(),
+            ),
+            cpuid_count: fake_cpuid_count,
+            cpuid: fake_cpuid,
+        };
+        let amx = (1 << EDX_AMX_BF16_SHIFT) | (1 << EDX_AMX_TILE_SHIFT) | (1 << EDX_AMX_INT8_SHIFT);
+        let entry = |function, eax, edx| CpuIdEntry {
+            function,
+            index: 0,
+            flags: 0,
+            cpuid: CpuidResult {
+                eax,
+                ebx: 0,
+                ecx: 0,
+                edx,
+            },
+        };
+
+        let mut cpuid = hypervisor::CpuId {
+            cpu_id_entries: vec![
+                entry(7, 0, amx | 1),
+                entry(XSAVE_FUNCTION, 0x7, 0),
+                entry(TILE_INFO_FUNCTION, 1, 0),
+            ],
+        };
+        filter_cpuid(&mut cpuid, &ctx);
+        assert_eq!(cpuid.cpu_id_entries.len(), 2);
+        assert_eq!(cpuid.cpu_id_entries[0].cpuid.edx, 1);
+
+        let xsave_features = 0x7 | (0x3 << 17);
+        let mut cpuid = hypervisor::CpuId {
+            cpu_id_entries: vec![
+                entry(7, 0, amx | 1),
+                entry(XSAVE_FUNCTION, xsave_features, 0),
+                entry(TILE_INFO_FUNCTION, 1, 0),
+            ],
+        };
+        filter_cpuid(&mut cpuid, &ctx);
+        assert_eq!(cpuid.cpu_id_entries.len(), 3);
+        assert_eq!(cpuid.cpu_id_entries[0].cpuid.edx, amx | 1);
+    }
+
+    #[test]
+    fn cpuid_cache_sharing() {
+        let fake_cpuid = |_function: u32| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        // A cache shared by 16 logical processors of the host.
+        let fake_cpuid_count = |_function: u32, _index: u32| CpuidResult {
+            eax: (15 << EAX_CACHE_SHARING_SHIFT) | (7 << EAX_CPU_CORES_SHIFT) | 0x63,
+            ebx: 0x3f,
+            ecx: 0,
+            edx: 0,
+        };
+        // The L1 cache of vCPU 1 is private, its L2 cache is shared with 2 other vCPUs.
+        let cpu_config = CpuConfigX86_64::new(
+            false,
+            false,
+            false,
+            true,
+            false,
+            None,
+            false,
+            Vec::new(),
+            vec![1, 3],
+        );
+        let ctx = CpuIdContext {
+            vcpu_id: 1,
+            cpu_count: 4,
+            x2apic: false,
+            tsc_deadline_timer: false,
+            apic_frequency: 0,
+            tsc_frequency: None,
+            async_pf: false,
+            msi_ext_dest_id: false,
+            cpu_config,
+            cpuid_count: fake_cpuid_count,
+            cpuid: fake_cpuid,
+        };
+        let entry = |function, index| CpuIdEntry {
+            function,
+            index,
+            flags: 0,
+            cpuid: CpuidResult {
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            },
+        };
+
+        let mut l1 = entry(4, 0);
+        adjust_cpuid(&mut l1, &ctx);
+        assert_eq!(l1.cpuid.eax, (3 << EAX_CPU_CORES_SHIFT) | 0x63);
+        assert_eq!(l1.cpuid.ebx, 0x3f);
+
+        let mut l2 = entry(0x8000001D, 1);
+        adjust_cpuid(&mut l2, &ctx);
+        assert_eq!(
+            l2.cpuid.eax,
+            (3 << EAX_CACHE_SHARING_SHIFT) | (7 << EAX_CPU_CORES_SHIFT) | 0x63
+        );
+
+        // Subleaves without a host cache are left alone.
+        let mut l3 = entry(4, 2);
+        adjust_cpuid(&mut l3, &ctx);
+        assert_eq!(
+            l3.cpuid.eax,
+            (15 << EAX_CACHE_SHARING_SHIFT) | (3 << EAX_CPU_CORES_SHIFT) | 0x63
+        );
+    }
 }
@@ -10,6 +10,7 @@ use gdbstub_arch::x86::reg::X86_64CoreRegs;
 use gdbstub_arch::x86::reg::X87FpuInternalRegs;
 use hypervisor::x86_64::Regs;
 use hypervisor::x86_64::Sregs;
+use hypervisor::HwWatchpoint;
 use hypervisor::VcpuX86_64;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
@@ -202,7 +203,7 @@ impl<T: VcpuX86_64> arch::GdbOps<T> for X8664arch {
     }
 
     fn enable_singlestep(vcpu: &T) -> Result<()> {
-        vcpu.set_guest_debug(&[], true /* enable_singlestep */)
+        vcpu.set_guest_debug(&[], &[], true /* enable_singlestep */)
             .map_err(Error::EnableSinglestep)
     }
 
@@ -210,8 +211,17 @@ impl<T: VcpuX86_64> arch::GdbOps<T> for X8664arch {
         Ok(4usize)
     }
 
-    fn set_hw_breakpoints(vcpu: &T, breakpoints: &[GuestAddress]) -> Result<()> {
-        vcpu.set_guest_debug(breakpoints, false /* enable_singlestep */)
+    fn get_max_hw_watchpoints(_vcpu: &T) -> Result<usize> {
+        // The 4 debug address registers are shared with the breakpoints.
+        Ok(4usize)
+    }
+
+    fn set_hw_breakpoints(
+        vcpu: &T,
+        breakpoints: &[GuestAddress],
+        watchpoints: &[HwWatchpoint],
+    ) -> Result<()> {
+        vcpu.set_guest_debug(breakpoints, watchpoints, false /* enable_singlestep */)
             .map_err(Error::SetHwBreakpoint)
     }
 }
@@ -46,6 +46,7 @@ use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::File;
 use std::io;
+use std::io::Read;
 use std::io::Write;
 use std::mem;
 use std::path::PathBuf;
@@ -90,6 +91,7 @@ use devices::BusType;
 use devices::Debugcon;
 use devices::FwCfgParameters;
 use devices::IrqChip;
+use devices::IrqChipCap;
 use devices::IrqChipX86_64;
 use devices::IrqEventSource;
 use devices::PciAddress;
@@ -185,6 +187,8 @@ pub enum Error {
     ConfigurePciEcam(String),
     #[error("bad PCI mem configuration: {0}")]
     ConfigurePciMem(String),
+    #[error("bad PCI segments configuration: {0}")]
+    ConfigurePciSegments(String),
     #[error("failed to configure segment registers: {0}")]
     ConfigureSegments(regs::Error),
     #[error("error configuring the system")]
@@ -256,6 +260,8 @@ pub enum Error {
     PciMmioOverlapPvmFw,
     #[error("pVM firmware not supported when bios is used on x86_64")]
     PvmFwBiosUnsupported,
+    #[error("failed to read the fw_cfg kernel or initrd: {0}")]
+    ReadFwCfgKernel(io::Error),
     #[error("error reading guest memory {0}")]
     ReadingGuestMemory(vm_memory::GuestMemoryError),
     #[error("single register read not supported on x86_64")]
@@ -377,6 +383,11 @@ const DEFAULT_PCIE_CFG_MMIO_END: u64 = FIRST_ADDR_PAST_32BITS - RESERVED_MEM_SIZ
 const DEFAULT_PCIE_CFG_MMIO_START: u64 = DEFAULT_PCIE_CFG_MMIO_END - DEFAULT_PCIE_CFG_MMIO_SIZE + 1;
 // Linux (with 4-level paging) has a physical memory limit of 46 bits (64 TiB).
 const HIGH_MMIO_MAX_END: u64 = (1u64 << 46) - 1;
+// Each additional pci segment has 256 buses, its own pcie cfg mmio and pci mmio windows.
+const MAX_PCI_SEGMENTS: u16 = 8;
+const PCI_SEGMENT_ECAM_SIZE: u64 = 256 * MB;
+const PCI_SEGMENT_LOW_MEM_SIZE: u64 = 32 * MB;
+const PCI_SEGMENT_HIGH_MEM_SIZE: u64 = 32 * GB;
 pub const KERNEL_32BIT_ENTRY_OFFSET: u64 = 0x0;
 pub const KERNEL_64BIT_ENTRY_OFFSET: u64 = 0x200;
 pub const MULTIBOOT_INFO_OFFSET: u64 = 0x6000;
@@ -402,6 +413,10 @@ pub const X86_64_SCI_IRQ: u32 = 5;
 // The CMOS RTC uses IRQ 8; start allocating IRQs at 9.
 pub const X86_64_IRQ_BASE: u32 = 9;
 const ACPI_HI_RSDP_WINDOW_BASE: u64 = 0x000E_0000;
+// vCPUs get APIC IDs from 0, and 255 is the xAPIC broadcast ID.
+pub const MAX_XAPIC_VCPUS: usize = 255;
+// The 8-bit destination ID of MSIs plus their 7-bit extended destination ID.
+const MAX_VCPUS: usize = 1 << 15;
 
 // pVM firmware memory. Should be within the low 4GB, so that it is identity-mapped
 // by setup_page_tables() when a protected VM boots in long mode, since the pVM firmware is
@@ -428,6 +443,108 @@ pub struct ArchMemoryLayout {
     pcie_cfg_mmio: AddressRange,
     // the pVM firmware memory (if running a protected VM)
     pvmfw_mem: Option<AddressRange>,
+    // the number of pci segments, the first being described by the fields above
+    pci_segments: u16,
+}
+
+impl ArchMemoryLayout {
+    // The pci mmio range below 4G of the additional pci segments is taken from the start of
+    // `pci_mmio_before_32bit`, this returns what is left to the first segment.
+    fn pci_mmio_low(&self) -> AddressRange {
+        AddressRange {
+            start: self.pci_mmio_before_32bit.start
+                + (self.pci_segments - 1) as u64 * PCI_SEGMENT_LOW_MEM_SIZE,
+            end: self.pci_mmio_before_32bit.end,
+        }
+    }
+}
+
+/// Resources of an additional PCI segment (domain).
+struct PciSegment {
+    segment: u16,
+    // the pcie cfg mmio range
+    ecam: AddressRange,
+    // the pci mmio range below 4G
+    mem_low: AddressRange,
+    // the pci mmio range above 4G
+    mem_high: AddressRange,
+}
+
+impl PciSegment {
+    /// Appends the AML of the root bridge `name` of the segment and of the motherboard resource
+    /// reserving its pcie cfg mmio.
+    fn generate_aml(
+        &self,
+        name: &str,
+        pci_irqs: &[(PciAddress, u32, PciInterruptPin)],
+        amls: &mut Vec<u8>,
+    ) {
+        let prt_entries: Vec<aml::Package> = pci_irqs
+            .iter()
+            .map(|(pci_address, gsi, pci_intr_pin)| {
+                aml::Package::new(vec![
+                    &pci_address.acpi_adr(),
+                    &pci_intr_pin.to_mask(),
+                    &aml::ZERO,
+                    gsi,
+                ])
+            })
+            .collect();
+
+        aml::Device::new(
+            format!("_SB_.{}", name).as_str().into(),
+            vec![
+                &aml::Name::new("_HID".into(), &aml::EISAName::new("PNP0A08")),
+                &aml::Name::new("_CID".into(), &aml::EISAName::new("PNP0A03")),
+                &aml::Name::new("_ADR".into(), &aml::ZERO),
+                &aml::Name::new("_SEG".into(), &self.segment),
+                &aml::Name::new("_UID".into(), &self.segment),
+                &aml::Name::new("SUPP".into(), &aml::ZERO),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![
+                        &aml::AddressSpace::new_bus_number(0x0u16, u8::MAX as u16),
+                        &aml::AddressSpace::new_memory(
+                            aml::AddressSpaceCachable::NotCacheable,
+                            true,
+                            self.mem_low.start as u32,
+                            self.mem_low.end as u32,
+                        ),
+                        &aml::AddressSpace::new_memory(
+                            aml::AddressSpaceCachable::NotCacheable,
+                            true,
+                            self.mem_high.start,
+                            self.mem_high.end,
+                        ),
+                    ]),
+                ),
+                &PciRootOSC {},
+                &aml::Name::new(
+                    "_PRT".into(),
+                    &aml::Package::new(prt_entries.iter().map(|p| p as &dyn Aml).collect()),
+                ),
+            ],
+        )
+        .to_aml_bytes(amls);
+
+        aml::Device::new(
+            format!("_SB_.MB{:02X}", self.segment).as_str().into(),
+            vec![
+                &aml::Name::new("_HID".into(), &aml::EISAName::new("PNP0C02")),
+                &aml::Name::new("_UID".into(), &self.segment),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![&aml::AddressSpace::new_memory(
+                        aml::AddressSpaceCachable::NotCacheable,
+                        true,
+                        self.ecam.start,
+                        self.ecam.end,
+                    )]),
+                ),
+            ],
+        )
+        .to_aml_bytes(amls);
+    }
 }
 
 pub fn create_arch_memory_layout(
@@ -479,6 +596,28 @@ pub fn create_arch_memory_layout(
         ),
     };
 
+    let pci_segments = pci_config.segments.unwrap_or(1);
+    if pci_segments == 0 || pci_segments > MAX_PCI_SEGMENTS {
+        return Err(Error::ConfigurePciSegments(format!(
+            "the number of segments must be between 1 and {}",
+            MAX_PCI_SEGMENTS
+        )));
+    }
+    if pci_segments > 1 {
+        let segments_mem = AddressRange::from_start_and_size(
+            pci_mmio_before_32bit.start,
+            (pci_segments - 1) as u64 * PCI_SEGMENT_LOW_MEM_SIZE,
+        )
+        .ok_or(Error::ConfigurePciSegments("region overflowed".to_string()))?;
+        if segments_mem.end >= pci_mmio_before_32bit.end
+            || !segments_mem.intersect(pcie_cfg_mmio).is_empty()
+        {
+            return Err(Error::ConfigurePciSegments(
+                "not enough PCI memory below 4G".to_string(),
+            ));
+        }
+    }
+
     let pvmfw_mem = if has_protected_vm_firmware {
         let range = AddressRange {
             start: PROTECTED_VM_FW_START,
@@ -497,6 +636,7 @@ pub fn create_arch_memory_layout(
         pci_mmio_before_32bit,
         pcie_cfg_mmio,
         pvmfw_mem,
+        pci_segments,
     })
 }
 
@@ -980,13 +1120,20 @@ impl arch::LinuxArch for X8664arch {
         vm: &V,
         arch_memory_layout: &Self::ArchMemoryLayout,
     ) -> SystemAllocatorConfig {
+        let mut high_mmio = Self::get_high_mmio_range(vm, arch_memory_layout);
+        if let Some(segment) = Self::get_pci_segments(vm, arch_memory_layout)
+            .as_ref()
+            .and_then(|segments| segments.last())
+        {
+            high_mmio.end = segment.ecam.start - 1;
+        }
         SystemAllocatorConfig {
             io: Some(AddressRange {
                 start: 0xc000,
                 end: 0xffff,
             }),
-            low_mmio: arch_memory_layout.pci_mmio_before_32bit,
-            high_mmio: Self::get_high_mmio_range(vm, arch_memory_layout),
+            low_mmio: arch_memory_layout.pci_mmio_low(),
+            high_mmio,
             platform_mmio: None,
             first_irq: X86_64_IRQ_BASE,
         }
@@ -1040,6 +1187,15 @@ impl arch::LinuxArch for X8664arch {
             .reserve_mmio(pcie_cfg_mmio_range)
             .map_err(Error::ReservePcieCfgMmio)?;
 
+        let pci_segments = Self::get_pci_segments(&vm, arch_memory_layout).ok_or_else(|| {
+            Error::ConfigurePciSegments("not enough MMIO space above 4G".to_string())
+        })?;
+        for segment in &pci_segments {
+            system_allocator
+                .add_pci_segment(segment.segment, segment.mem_low, segment.mem_high)
+                .map_err(|e| Error::ConfigurePciSegments(e.to_string()))?;
+        }
+
         for sdt in components.acpi_sdts.iter() {
             if sdt.is_signature(b"FACP") {
                 mptable = false;
@@ -1059,26 +1215,47 @@ impl arch::LinuxArch for X8664arch {
             .into_iter()
             .partition(|(dev, _)| dev.as_pci_device().is_some());
 
-        let pci_devices = pci_devices
-            .into_iter()
-            .map(|(dev, jail_orig)| (dev.into_pci_device().unwrap(), jail_orig))
-            .collect();
-
-        let (pci, pci_irqs, pid_debug_label_map, amls, gpe_scope_amls) = arch::generate_pci_root(
-            pci_devices,
-            irq_chip.as_irq_chip_mut(),
-            mmio_bus.clone(),
-            GuestAddress(pcie_cfg_mmio_range.start),
-            12,
-            io_bus.clone(),
-            system_allocator,
-            &mut vm,
-            4, // Share the four pin interrupts (INTx#)
-            Some(pcie_vcfg_range.start),
-            #[cfg(feature = "swap")]
-            swap_controller,
-        )
-        .map_err(Error::CreatePciRoot)?;
+        // Each device is attached to the root bridge of the segment of its address.
+        let mut segment_devices: BTreeMap<u16, Vec<_>> = BTreeMap::new();
+        for (dev, jail_orig) in pci_devices {
+            let mut dev = dev.into_pci_device().unwrap();
+            let address = dev
+                .allocate_address(system_allocator)
+                .map_err(arch::DeviceRegistrationError::AllocateDeviceAddrs)
+                .map_err(Error::CreatePciRoot)?;
+            segment_devices
+                .entry(address.domain)
+                .or_default()
+                .push((dev, jail_orig));
+        }
+        if let Some(segment) = segment_devices
+            .keys()
+            .find(|segment| **segment >= arch_memory_layout.pci_segments)
+        {
+            return Err(Error::ConfigurePciSegments(format!(
+                "PCI segment {:04x} does not exist",
+                segment
+            )));
+        }
+        let pci_devices = segment_devices.remove(&0).unwrap_or_default();
+
+        let (pci, pci_irqs, mut pid_debug_label_map, amls, gpe_scope_amls) =
+            arch::generate_pci_root(
+                pci_devices,
+                irq_chip.as_irq_chip_mut(),
+                mmio_bus.clone(),
+                GuestAddress(pcie_cfg_mmio_range.start),
+                12,
+                io_bus.clone(),
+                system_allocator,
+                &mut vm,
+                4, // Share the four pin interrupts (INTx#)
+                Some(pcie_vcfg_range.start),
+                0,
+                #[cfg(feature = "swap")]
+                swap_controller,
+            )
+            .map_err(Error::CreatePciRoot)?;
 
         let pci = Arc::new(Mutex::new(pci));
         pci.lock().enable_pcie_cfg_mmio(pcie_cfg_mmio_range.start);
@@ -1105,16 +1282,66 @@ impl arch::LinuxArch for X8664arch {
             )
             .unwrap();
 
+        // The additional segments are only reachable through their own pcie cfg mmio.
+        let mut segment_roots = Vec::new();
+        for segment in &pci_segments {
+            let (root, irqs, pid_labels, amls, gpe_scope_amls) = arch::generate_pci_root(
+                segment_devices.remove(&segment.segment).unwrap_or_default(),
+                irq_chip.as_irq_chip_mut(),
+                mmio_bus.clone(),
+                GuestAddress(segment.ecam.start),
+                12,
+                io_bus.clone(),
+                system_allocator,
+                &mut vm,
+                4, // Share the four pin interrupts (INTx#)
+                None,
+                segment.segment,
+                #[cfg(feature = "swap")]
+                swap_controller,
+            )
+            .map_err(Error::CreatePciRoot)?;
+            pid_debug_label_map.extend(pid_labels);
+
+            let root = Arc::new(Mutex::new(root));
+            let segment_cfg_mmio = Arc::new(Mutex::new(PciConfigMmio::new(root.clone(), 12)));
+            mmio_bus
+                .insert(segment_cfg_mmio, segment.ecam.start, PCI_SEGMENT_ECAM_SIZE)
+                .unwrap();
+            segment_roots.push((root, irqs, amls, gpe_scope_amls));
+        }
+
         // Event used to notify crosvm that guest OS is trying to suspend.
         let (suspend_tube_send, suspend_tube_recv) =
             Tube::directional_pair().map_err(Error::CreateTube)?;
         let suspend_tube_send = Arc::new(Mutex::new(suspend_tube_send));
 
         if components.fw_cfg_enable {
+            let fw_cfg_kernel = match components.fw_cfg_kernel.take() {
+                Some(mut kernel_image) => {
+                    let mut kernel = Vec::new();
+                    kernel_image
+                        .read_to_end(&mut kernel)
+                        .map_err(Error::ReadFwCfgKernel)?;
+                    let mut initrd = Vec::new();
+                    if let Some(mut initrd_image) = components.initrd_image.take() {
+                        initrd_image
+                            .read_to_end(&mut initrd)
+                            .map_err(Error::ReadFwCfgKernel)?;
+                    }
+                    Some(devices::FwCfgKernel {
+                        kernel,
+                        initrd,
+                        cmdline: components.extra_kernel_params.join(" "),
+                    })
+                }
+                None => None,
+            };
             Self::setup_fw_cfg_device(
                 &io_bus,
                 components.fw_cfg_parameters.clone(),
                 components.bootorder_fw_cfg_blob.clone(),
+                fw_cfg_kernel,
                 fw_cfg_jail,
                 #[cfg(feature = "swap")]
                 swap_controller,
@@ -1138,6 +1365,7 @@ impl arch::LinuxArch for X8664arch {
                 irq_chip,
                 device_tube,
                 components.memory_size,
+                components.rtc_offset.clone(),
             )
             .map_err(Error::SetupCmos)?;
             Some(host_tube)
@@ -1179,6 +1407,19 @@ impl arch::LinuxArch for X8664arch {
             )?;
         }
 
+        #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+        let tpm_crb_base = match components.tpm_crb.take() {
+            Some((backend, jail)) => Some(Self::setup_tpm_crb(
+                backend,
+                &mmio_bus,
+                system_allocator,
+                jail,
+                #[cfg(feature = "swap")]
+                swap_controller,
+            )?),
+            None => None,
+        };
+
         // Functions that use/create jails MUST be used before the call to
         // setup_acpi_devices below, as this move us into a multiprocessing state
         // from which we can no longer fork.
@@ -1206,16 +1447,37 @@ impl arch::LinuxArch for X8664arch {
             swap_controller,
             #[cfg(any(target_os = "android", target_os = "linux"))]
             components.ac_adapter,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            components.host_battery,
+            components.s3,
             guest_suspended_cvar,
             &pci_irqs,
         )?;
 
+        #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+        if let Some(mmio_base) = tpm_crb_base {
+            devices::tpm_crb::generate_aml(mmio_base, &mut acpi_dev_resource.amls);
+            acpi_dev_resource
+                .sdts
+                .push(devices::tpm_crb::create_tpm2_table(mmio_base));
+        }
+
         // Create customized SSDT table
         let sdt = acpi::create_customize_ssdt(pci.clone(), amls, gpe_scope_amls);
         if let Some(sdt) = sdt {
             acpi_dev_resource.sdts.push(sdt);
         }
 
+        let mut mcfg_entries = vec![(pcie_cfg_mmio_range.start, 0, max_bus)];
+        for (segment, (root, irqs, amls, gpe_scope_amls)) in pci_segments.iter().zip(segment_roots)
+        {
+            segment.generate_aml(&root.lock().acpi_name(), &irqs, &mut acpi_dev_resource.amls);
+            if let Some(sdt) = acpi::create_customize_ssdt(root, amls, gpe_scope_amls) {
+                acpi_dev_resource.sdts.push(sdt);
+            }
+            mcfg_entries.push((segment.ecam.start, segment.segment, u8::MAX));
+        }
+
         irq_chip
             .finalize_devices(system_allocator, &io_bus, &mmio_bus)
             .map_err(Error::RegisterIrqfd)?;
@@ -1228,13 +1490,18 @@ impl arch::LinuxArch for X8664arch {
         // If another guest does need a way to pass these tables down to it's BIOS, this approach
         // should be rethought.
 
-        // Make sure the `vcpu_count` casts below and the arithmetic in `setup_mptable` are well
-        // defined.
-        if vcpu_count >= u8::MAX.into() {
+        // APIC ID 255 and above are only usable in x2APIC mode, and without interrupt remapping
+        // the guest can only send them interrupts if MSIs carry their extended destination ID.
+        if vcpu_count > MAX_VCPUS
+            || (vcpu_count > MAX_XAPIC_VCPUS
+                && !irq_chip.check_capability(IrqChipCap::MsiExtDestId))
+        {
             return Err(Error::TooManyVcpus);
         }
 
-        if mptable {
+        // The MP table has 8-bit APIC IDs and places the IOAPIC after the vCPUs, larger VMs are
+        // only described by the MADT.
+        if mptable && vcpu_count < MAX_XAPIC_VCPUS {
             // Note that this puts the mptable at 0x9FC00 in guest physical memory.
             mptable::setup_mptable(&mem, vcpu_count as u8, &pci_irqs)
                 .map_err(Error::SetupMptable)?;
@@ -1250,7 +1517,7 @@ impl arch::LinuxArch for X8664arch {
         // TODO (tjeznach) Write RSDP to bootconfig before writing to memory
         acpi::create_acpi_tables(
             &mem,
-            vcpu_count as u8,
+            vcpu_count,
             sci_irq,
             0xcf9,
             6, // RST_CPU|SYS_RST
@@ -1258,8 +1525,7 @@ impl arch::LinuxArch for X8664arch {
             host_cpus,
             vcpu_ids,
             &pci_irqs,
-            pcie_cfg_mmio_range.start,
-            max_bus,
+            &mcfg_entries,
             components.force_s2idle,
         )
         .ok_or(Error::CreateAcpi)?;
@@ -1717,6 +1983,50 @@ impl X8664arch {
         Ok(())
     }
 
+    /// Adds a TPM CRB interface forwarding the guest's commands to `backend`, and returns its mmio
+    /// base address.
+    #[cfg(all(any(target_os = "android", target_os = "linux"), feature = "vtpm"))]
+    fn setup_tpm_crb(
+        backend: Box<dyn devices::virtio::TpmBackend>,
+        mmio_bus: &Bus,
+        resources: &mut SystemAllocator,
+        jail: Option<Minijail>,
+        #[cfg(feature = "swap")] swap_controller: &mut Option<swap::SwapController>,
+    ) -> Result<u64> {
+        let alloc = resources.get_anon_alloc();
+        let mmio_base = resources
+            .allocate_mmio(
+                devices::tpm_crb::TPM_CRB_MMIO_SIZE,
+                alloc,
+                "TpmCrb".to_string(),
+                resources::AllocOptions::new().align(devices::tpm_crb::TPM_CRB_MMIO_SIZE),
+            )
+            .map_err(Error::AllocateIOResouce)?;
+
+        let tpm_crb = devices::TpmCrb::new(backend, mmio_base);
+        let tpm_crb: Arc<Mutex<dyn BusDevice>> = match jail {
+            Some(jail) => {
+                let keep_rds = tpm_crb.keep_rds();
+                Arc::new(Mutex::new(
+                    ProxyDevice::new(
+                        tpm_crb,
+                        jail,
+                        keep_rds,
+                        #[cfg(feature = "swap")]
+                        swap_controller,
+                    )
+                    .map_err(Error::CreateProxyDevice)?,
+                ))
+            }
+            None => Arc::new(Mutex::new(tpm_crb)),
+        };
+        mmio_bus
+            .insert(tpm_crb, mmio_base, devices::tpm_crb::TPM_CRB_MMIO_SIZE)
+            .map_err(Error::InsertBus)?;
+
+        Ok(mmio_base)
+    }
+
     /// Writes the command line string to the given memory slice.
     ///
     /// # Arguments
@@ -1932,6 +2242,43 @@ impl X8664arch {
         Ok(())
     }
 
+    /// Returns the resources of the additional PCI segments, or `None` if they do not fit.
+    ///
+    /// Their pcie cfg mmio and pci mmio above 4G are carved out from the end of the high mmio
+    /// range.
+    fn get_pci_segments<V: Vm>(
+        vm: &V,
+        arch_memory_layout: &ArchMemoryLayout,
+    ) -> Option<Vec<PciSegment>> {
+        let high_mmio = Self::get_high_mmio_range(vm, arch_memory_layout);
+        let mut end = high_mmio.end.checked_add(1)?;
+        let mut segments = Vec::new();
+        for segment in 1..arch_memory_layout.pci_segments {
+            let mem_high_start =
+                end.checked_sub(PCI_SEGMENT_HIGH_MEM_SIZE)? & !(PCI_SEGMENT_HIGH_MEM_SIZE - 1);
+            let ecam_start = mem_high_start.checked_sub(PCI_SEGMENT_ECAM_SIZE)?;
+            if ecam_start < high_mmio.start {
+                return None;
+            }
+            let mem_low_start = arch_memory_layout.pci_mmio_before_32bit.start
+                + (segment - 1) as u64 * PCI_SEGMENT_LOW_MEM_SIZE;
+            segments.push(PciSegment {
+                segment,
+                ecam: AddressRange::from_start_and_size(ecam_start, PCI_SEGMENT_ECAM_SIZE)?,
+                mem_low: AddressRange::from_start_and_size(
+                    mem_low_start,
+                    PCI_SEGMENT_LOW_MEM_SIZE,
+                )?,
+                mem_high: AddressRange::from_start_and_size(
+                    mem_high_start,
+                    PCI_SEGMENT_HIGH_MEM_SIZE,
+                )?,
+            });
+            end = ecam_start;
+        }
+        Some(segments)
+    }
+
     fn get_pcie_vcfg_mmio_range(mem: &GuestMemory, pcie_cfg_mmio: &AddressRange) -> AddressRange {
         // Put PCIe VCFG region at a 2MB boundary after physical memory or 4gb, whichever is
         // greater.
@@ -1970,10 +2317,12 @@ impl X8664arch {
     /// * `io_bus` - the IO bus object
     /// * `fw_cfg_parameters` - command-line specified data to add to device. May contain all None
     ///   fields if user did not specify data to add to the device
+    /// * `fw_cfg_kernel` - kernel for the firmware to boot directly
     fn setup_fw_cfg_device(
         io_bus: &Bus,
         fw_cfg_parameters: Vec<FwCfgParameters>,
         bootorder_fw_cfg_blob: Vec<u8>,
+        fw_cfg_kernel: Option<devices::FwCfgKernel>,
         fw_cfg_jail: Option<Minijail>,
         #[cfg(feature = "swap")] swap_controller: &mut Option<swap::SwapController>,
     ) -> Result<()> {
@@ -1993,6 +2342,11 @@ impl X8664arch {
                         return Err(Error::CreateFwCfgDevice(err));
                     }
                 }
+                if let Some(kernel) = fw_cfg_kernel {
+                    device
+                        .add_kernel(kernel)
+                        .map_err(Error::CreateFwCfgDevice)?;
+                }
                 device
             }
             Err(err) => {
@@ -2063,12 +2417,14 @@ impl X8664arch {
     ///
     /// * - `io_bus` - the IO bus object
     /// * - `mem_size` - the size in bytes of physical ram for the guest
+    /// * - `rtc_offset` - the offset of the RTC time from the host time
     pub fn setup_legacy_cmos_device(
         arch_memory_layout: &ArchMemoryLayout,
         io_bus: &Bus,
         irq_chip: &mut dyn IrqChipX86_64,
         vm_control: Tube,
         mem_size: u64,
+        rtc_offset: devices::cmos::RtcOffset,
     ) -> anyhow::Result<()> {
         let mem_regions = arch_memory_regions(arch_memory_layout, mem_size, None);
 
@@ -2089,6 +2445,7 @@ impl X8664arch {
             mem_below_4g,
             mem_above_4g,
             Utc::now,
+            rtc_offset,
             vm_control,
             irq_evt.try_clone().context("cmos irq clone")?,
         )
@@ -2120,6 +2477,8 @@ impl X8664arch {
     /// * `irq_chip` the IrqChip object for registering irq events
     /// * `battery` indicate whether to create the battery
     /// * `mmio_bus` the MMIO bus to add the devices to
+    /// * `host_battery` whether the battery mirrors the host's power supplies
+    /// * `s3` whether to advertise the S3 sleep state to the guest
     /// * `pci_irqs` IRQ assignment of PCI devices. Tuples of (PCI address, gsi, PCI interrupt pin).
     ///   Note that this matches one of the return values of generate_pci_root.
     pub fn setup_acpi_devices(
@@ -2139,6 +2498,8 @@ impl X8664arch {
         resume_notify_devices: &mut Vec<Arc<Mutex<dyn BusResumeDevice>>>,
         #[cfg(feature = "swap")] swap_controller: &mut Option<swap::SwapController>,
         #[cfg(any(target_os = "android", target_os = "linux"))] ac_adapter: bool,
+        #[cfg(any(target_os = "android", target_os = "linux"))] host_battery: bool,
+        s3: bool,
         guest_suspended_cvar: Option<Arc<(Mutex<bool>, Condvar)>>,
         pci_irqs: &[(PciAddress, u32, PciInterruptPin)],
     ) -> Result<(acpi::AcpiDevResource, Option<BatControl>)> {
@@ -2155,6 +2516,7 @@ impl X8664arch {
                     let (control_tube, _mmio_base) = arch::sys::linux::add_goldfish_battery(
                         &mut amls,
                         battery.1,
+                        host_battery,
                         mmio_bus,
                         irq_chip,
                         irq_num,
@@ -2257,6 +2619,7 @@ impl X8664arch {
             suspend_tube,
             vm_evt_wrtube,
             acdc,
+            s3,
         );
         pmresource.to_aml_bytes(&mut amls);
         irq_chip
@@ -2566,6 +2929,8 @@ pub fn check_host_hybrid_support(cpuid: &CpuIdCall) -> std::result::Result<(), H
 mod tests {
     use std::mem::size_of;
 
+    use vm_memory::MemoryBacking;
+
     use super::*;
 
     fn setup() -> ArchMemoryLayout {
@@ -2578,10 +2943,36 @@ mod tests {
                 start: 2 * GB,
                 size: None,
             }),
+            segments: None,
         };
         create_arch_memory_layout(&pci_config, false).unwrap()
     }
 
+    #[test]
+    fn pci_segments_layout() {
+        let pci_config = PciConfig {
+            segments: Some(3),
+            ..Default::default()
+        };
+        let arch_memory_layout = create_arch_memory_layout(&pci_config, false).unwrap();
+        assert_eq!(
+            arch_memory_layout.pci_mmio_low(),
+            AddressRange {
+                start: arch_memory_layout.pci_mmio_before_32bit.start
+                    + 2 * PCI_SEGMENT_LOW_MEM_SIZE,
+                end: arch_memory_layout.pci_mmio_before_32bit.end,
+            }
+        );
+
+        for segments in [0, MAX_PCI_SEGMENTS + 1] {
+            let pci_config = PciConfig {
+                segments: Some(segments),
+                ..Default::default()
+            };
+            assert!(create_arch_memory_layout(&pci_config, false).is_err());
+        }
+    }
+
     #[test]
     fn regions_lt_4gb_nobios() {
         let arch_memory_layout = setup();
@@ -2596,6 +2987,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2605,6 +2997,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::ReservedMemory,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2614,6 +3007,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 )
             ]
@@ -2635,6 +3029,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2644,6 +3039,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::ReservedMemory,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2653,6 +3049,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2662,6 +3059,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
             ]
@@ -2683,6 +3081,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2692,6 +3091,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::ReservedMemory,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2701,6 +3101,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2710,6 +3111,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::Bios,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
             ]
@@ -2731,6 +3133,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2740,6 +3143,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::ReservedMemory,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2749,6 +3153,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2758,6 +3163,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::Bios,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2767,6 +3173,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
             ]
@@ -2788,6 +3195,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2797,6 +3205,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::ReservedMemory,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2806,6 +3215,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 )
             ]
@@ -2828,6 +3238,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2837,6 +3248,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::ReservedMemory,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2846,6 +3258,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::GuestMemoryRegion,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
                 (
@@ -2855,6 +3268,7 @@ mod tests {
                         align: 0,
                         purpose: MemoryRegionPurpose::Bios,
                         file_backed: None,
+                        backing: MemoryBacking::Shared,
                     },
                 ),
             ]
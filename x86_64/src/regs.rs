@@ -7,6 +7,7 @@ use std::mem;
 use std::result;
 
 use base::warn;
+use hypervisor::Regs;
 use hypervisor::Sregs;
 use hypervisor::VcpuX86_64;
 use hypervisor::Vm;
@@ -336,6 +337,21 @@ pub fn setup_page_tables(mem: &GuestMemory, sregs: &mut Sregs) -> Result<()> {
     Ok(())
 }
 
+/// Returns the registers of a CPU resuming from ACPI S3 through the firmware waking vector.
+///
+/// The CPU starts in real mode as after a reset, with CS:IP pointing at the waking vector as
+/// described in section 4.8.3.6 of the ACPI spec (Version 6.4).
+pub fn s3_resume_regs(waking_vector: u32) -> (Regs, Sregs) {
+    let regs = Regs {
+        rip: (waking_vector & 0xf).into(),
+        ..Default::default()
+    };
+    let mut sregs = Sregs::default();
+    sregs.cs.selector = (waking_vector >> 4) as u16;
+    sregs.cs.base = (waking_vector & !0xf).into();
+    (regs, sregs)
+}
+
 #[cfg(test)]
 mod tests {
     use vm_memory::GuestAddress;
@@ -396,4 +412,16 @@ mod tests {
         assert_eq!(X86_CR4_PAE, sregs.cr4);
         assert_eq!(X86_CR0_PG, sregs.cr0 & X86_CR0_PG);
     }
+
+    #[test]
+    fn s3_resume() {
+        let (regs, sregs) = s3_resume_regs(0x9a01f);
+
+        assert_eq!(0xf, regs.rip);
+        assert_eq!(0x9a01, sregs.cs.selector);
+        assert_eq!(0x9a010, sregs.cs.base);
+        assert_eq!(0xffff, sregs.cs.limit_bytes);
+        assert_eq!(0, sregs.cr0 & X86_CR0_PE);
+        assert_eq!(0, sregs.efer);
+    }
 }